@@ -0,0 +1,124 @@
+//! 断言失败时的处理策略
+//!
+//! 各章 utils 中的驱动原本用 assert!() 检查参数，条件不满足就 panic，调试时这样最直接，
+//! 但对于装好了就不再连接调试器的设备，一个越界的坐标、一个写错的通道号就让整个程序停下来，代价太大
+//!
+//! 这里把“可以恢复”的检查换成两个宏：
+//!
+//! - ensure!(条件, 错误)：用在返回 Result 的函数中，条件不满足时返回 Err(错误)
+//! - check!(条件)：用在没有办法返回错误的函数中，返回条件是否满足，由调用者决定如何退回到安全的行为（比如截断到范围之内）
+//!
+//! 条件不满足时，先按照 POLICY 决定是否 panic，不 panic 的话，调用 set_hook 设置的函数记录这次失败，再按上面的方式继续
+//! 策略在编译期决定：
+//!
+//! | 特性      | debug 构建 | release 构建 |
+//! | --------- | ---------- | ------------ |
+//! | 默认      | panic      | 记录后继续   |
+//! | panic     | panic      | panic        |
+//! | recover   | 记录后继续 | 记录后继续   |
+//!
+//! 各章的 Cargo.toml 把这两个特性转发为 assert-panic 与 assert-recover
+//!
+//! 不属于这里的检查：
+//!
+//! - 编译期就能确定的条件，依旧用 const _: () = assert!(...)
+//! - 出错之后已经没有安全的退路的条件（比如内存布局、外设的重复初始化），依旧直接 panic
+//!
+//! 本 crate 不依赖任何外设与输出方式，MCU 端与 Host 端都可以使用
+
+#![no_std]
+
+use core::{
+    panic::Location,
+    sync::atomic::{AtomicPtr, AtomicU32, Ordering},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Panic,
+    Recover,
+}
+
+pub const POLICY: Policy = if cfg!(feature = "panic") {
+    Policy::Panic
+} else if cfg!(feature = "recover") {
+    Policy::Recover
+} else if cfg!(debug_assertions) {
+    Policy::Panic
+} else {
+    Policy::Recover
+};
+
+// 一次失败的检查
+#[derive(Debug, Clone, Copy)]
+pub struct Failure {
+    // 条件的源码
+    pub condition: &'static str,
+    pub location: &'static Location<'static>,
+}
+
+// 保存的是 fn(&Failure) 的指针，为空时表示没有设置
+static HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+static FAILURES: AtomicU32 = AtomicU32::new(0);
+
+// 设置记录失败的函数，通常在 main 的开头设置为输出到 RTT 或者 defmt
+// 它可能在中断中被调用，因此不能阻塞
+pub fn set_hook(hook: fn(&Failure)) {
+    HOOK.store(hook as *mut (), Ordering::Release);
+}
+
+// 开机以来失败的次数，没有设置 hook 时，至少还可以通过它知道发生过失败
+pub fn failures() -> u32 {
+    FAILURES.load(Ordering::Relaxed)
+}
+
+// 由 ensure! 与 check! 调用，不要直接使用
+#[doc(hidden)]
+#[track_caller]
+#[cold]
+pub fn fail(condition: &'static str) {
+    let failure = Failure {
+        condition,
+        location: Location::caller(),
+    };
+
+    if POLICY == Policy::Panic {
+        panic!(
+            "check failed: {} at {}:{}",
+            condition,
+            failure.location.file(),
+            failure.location.line()
+        );
+    }
+
+    FAILURES.fetch_add(1, Ordering::Relaxed);
+    let hook = HOOK.load(Ordering::Acquire);
+    if !hook.is_null() {
+        // 安全性：HOOK 中只会存入 set_hook 的参数，也就是一个 fn(&Failure)
+        let hook: fn(&Failure) = unsafe { core::mem::transmute(hook) };
+        hook(&failure);
+    }
+}
+
+// 条件不满足时返回 Err，错误会经过 Into 转换为函数的错误类型
+#[macro_export]
+macro_rules! ensure {
+    ($cond:expr, $err:expr $(,)?) => {
+        if !($cond) {
+            $crate::fail(::core::stringify!($cond));
+            return ::core::result::Result::Err(::core::convert::Into::into($err));
+        }
+    };
+}
+
+// 返回条件是否满足
+#[macro_export]
+macro_rules! check {
+    ($cond:expr $(,)?) => {{
+        let ok: bool = $cond;
+        if !ok {
+            $crate::fail(::core::stringify!($cond));
+        }
+        ok
+    }};
+}
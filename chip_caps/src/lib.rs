@@ -0,0 +1,271 @@
+//! 所选芯片有哪些片上外设
+//!
+//! 笔记是在 STM32F413 上写的，但手边更常见的往往是 F401、F411 的 Black Pill，或者 F412 的 Discovery，
+//! 这几颗芯片的内核与大部分外设相同，差别在于“有没有”：
+//!
+//! | 外设        | F401 | F411 | F412 | F413 |
+//! | ----------- | ---- | ---- | ---- | ---- |
+//! | QUADSPI     |      |      | o    | o    |
+//! | DAC         |      |      |      | o    |
+//! | RNG         |      |      | o    | o    |
+//! | FSMC        |      |      | o    | o    |
+//! | TIM6/TIM7   |      |      | o    | o    |
+//! | TIM8        |      |      | o    | o    |
+//! | TIM12       |      |      | o    | o    |
+//! | USART3      |      |      | o    | o    |
+//! | CAN         |      |      | o    | o    |
+//! | SPI5        |      | o    | o    | o    |
+//!
+//! 另外，同一个信号能使用的引脚也不完全相同（比如 I2C3_SDA），这部分见 pinmap
+//!
+//! 芯片由各章 Cargo.toml 中的 stm32f401 / stm32f411 / stm32f412 / stm32f413 特性选择（默认 stm32f413），
+//! 这些特性同时转发给 stm32f4xx-hal 与本 crate，比如在 F411 上编译 s11：
+//!
+//! cargo build -p s11_lcd1602_pac --no-default-features --features stm32f411
+//!
+//! 用到上表中外设的程序，在文件开头写上 chip_caps::require!(QUADSPI);
+//! 各章 utils 中的驱动由同一章的所有程序共用，不能在驱动中写 require!，否则没有用到该外设的程序也会编译失败，
+//! 而是在 utils/mod.rs 中用所在 crate 的芯片特性 #[cfg] 掉对应的模块，再由用到它的程序写 require!
+//! 所选的芯片没有该外设时，编译直接失败，并给出一条说明，而不是一堆“Peripherals 中没有 QUADSPI”的错误，
+//! 或者编译通过之后，在板子上才发现对着一个不存在的外设读写
+//!
+//! 不过 compile_error! 并不会让 rustc 停下来，它依旧会检查程序中其余的代码，
+//! 所以程序中用到该外设的 use、函数与中断处理函数，也要用同样的芯片特性 #[cfg] 掉，错误才会只剩 require! 给出的这一条
+//!
+//! 只是可有可无的功能（比如开机自检中的 QSPI Flash 检查），用 has 中的常量判断，
+//! 由于 pac 中连类型都不存在，这部分代码还需要用所在 crate 的同名特性 #[cfg] 掉
+//!
+//! 本 crate 不依赖任何外设，MCU 端与 Host 端都可以使用
+
+#![no_std]
+
+pub mod pinmap;
+
+#[cfg(not(any(
+    feature = "stm32f401",
+    feature = "stm32f411",
+    feature = "stm32f412",
+    feature = "stm32f413"
+)))]
+compile_error!(
+    "no chip selected, enable one of the features: stm32f401, stm32f411, stm32f412, stm32f413"
+);
+
+#[cfg(any(
+    all(feature = "stm32f401", feature = "stm32f411"),
+    all(feature = "stm32f401", feature = "stm32f412"),
+    all(feature = "stm32f401", feature = "stm32f413"),
+    all(feature = "stm32f411", feature = "stm32f412"),
+    all(feature = "stm32f411", feature = "stm32f413"),
+    all(feature = "stm32f412", feature = "stm32f413"),
+))]
+compile_error!("more than one chip selected, use --no-default-features when selecting a chip other than stm32f413");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    F401,
+    F411,
+    F412,
+    F413,
+}
+
+pub const CHIP: Chip = if cfg!(feature = "stm32f401") {
+    Chip::F401
+} else if cfg!(feature = "stm32f411") {
+    Chip::F411
+} else if cfg!(feature = "stm32f412") {
+    Chip::F412
+} else {
+    Chip::F413
+};
+
+impl Chip {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Chip::F401 => "STM32F401",
+            Chip::F411 => "STM32F411",
+            Chip::F412 => "STM32F412",
+            Chip::F413 => "STM32F413",
+        }
+    }
+
+    pub const fn sysclk_max_hz(self) -> u32 {
+        match self {
+            Chip::F401 => 84_000_000,
+            Chip::F411 | Chip::F412 | Chip::F413 => 100_000_000,
+        }
+    }
+
+    // 该系列中最大的型号，具体的容量以 Flash size 寄存器（0x1FFF_7A22）为准
+    pub const fn flash_kb(self) -> u32 {
+        match self {
+            Chip::F401 | Chip::F411 => 512,
+            Chip::F412 => 1024,
+            Chip::F413 => 1536,
+        }
+    }
+
+    // Flash 的扇区数，同样以该系列中最大的型号为准
+    // 扇区 0 ~ 3 为 16 KB，扇区 4 为 64 KB，之后每个扇区 128 KB
+    pub const fn flash_sectors(self) -> u8 {
+        match self {
+            Chip::F401 | Chip::F411 => 8,
+            Chip::F412 => 12,
+            Chip::F413 => 16,
+        }
+    }
+
+    pub const fn sram_kb(self) -> u32 {
+        match self {
+            Chip::F401 => 96,
+            Chip::F411 => 128,
+            Chip::F412 => 256,
+            Chip::F413 => 320,
+        }
+    }
+}
+
+// 运行时（或者 const 中）判断外设是否存在，与模块文档中的表一致，修改时两处一起改
+pub mod has {
+    const F412_UP: bool = cfg!(any(feature = "stm32f412", feature = "stm32f413"));
+
+    pub const QUADSPI: bool = F412_UP;
+    pub const DAC: bool = cfg!(feature = "stm32f413");
+    pub const RNG: bool = F412_UP;
+    pub const FSMC: bool = F412_UP;
+    pub const TIM6_TIM7: bool = F412_UP;
+    pub const TIM8: bool = F412_UP;
+    pub const TIM12: bool = F412_UP;
+    pub const USART3: bool = F412_UP;
+    pub const CAN: bool = F412_UP;
+    pub const SPI5: bool = !cfg!(feature = "stm32f401");
+}
+
+// 所选芯片缺少列出的任意一个外设时，编译失败
+// 可以一次列出多个：chip_caps::require!(DAC, TIM6_TIM7);
+#[macro_export]
+macro_rules! require {
+    ($($cap:ident),+ $(,)?) => {
+        $( $crate::__require_one!($cap); )+
+    };
+}
+
+// 每个外设存在于哪些芯片上，与 has 一致
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __require_one {
+    (QUADSPI) => {
+        $crate::__chip_in!(QUADSPI, "stm32f412", "stm32f413");
+    };
+    (DAC) => {
+        $crate::__chip_in!(DAC, "stm32f413");
+    };
+    (RNG) => {
+        $crate::__chip_in!(RNG, "stm32f412", "stm32f413");
+    };
+    (FSMC) => {
+        $crate::__chip_in!(FSMC, "stm32f412", "stm32f413");
+    };
+    (TIM6_TIM7) => {
+        $crate::__chip_in!(TIM6_TIM7, "stm32f412", "stm32f413");
+    };
+    (TIM8) => {
+        $crate::__chip_in!(TIM8, "stm32f412", "stm32f413");
+    };
+    (TIM12) => {
+        $crate::__chip_in!(TIM12, "stm32f412", "stm32f413");
+    };
+    (USART3) => {
+        $crate::__chip_in!(USART3, "stm32f412", "stm32f413");
+    };
+    (CAN) => {
+        $crate::__chip_in!(CAN, "stm32f412", "stm32f413");
+    };
+    (SPI5) => {
+        $crate::__chip_in!(SPI5, "stm32f411", "stm32f412", "stm32f413");
+    };
+    ($other:ident) => {
+        ::core::compile_error!(::core::concat!(
+            "chip_caps: unknown peripheral ",
+            ::core::stringify!($other)
+        ));
+    };
+}
+
+// 逐个比较列表中的芯片与所选的芯片，每种芯片一个版本，由本 crate 的特性决定使用哪一个
+// 宏在使用者的 crate 中展开，因此不能在宏的内容里写 #[cfg(feature = ...)]，那样检查的是使用者的特性
+#[cfg(feature = "stm32f401")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __chip_in {
+    (@ $cap:ident, "stm32f401" $(, $rest:tt)*) => {};
+    (@ $cap:ident, $first:tt $(, $rest:tt)*) => {
+        $crate::__chip_in!(@ $cap, $($rest),*);
+    };
+    (@ $cap:ident,) => {
+        ::core::compile_error!(::core::concat!(
+            ::core::stringify!($cap),
+            " is not available on STM32F401, see the table in chip_caps"
+        ));
+    };
+    ($cap:ident $(, $chip:tt)*) => {
+        $crate::__chip_in!(@ $cap, $($chip),*);
+    };
+}
+
+#[cfg(feature = "stm32f411")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __chip_in {
+    (@ $cap:ident, "stm32f411" $(, $rest:tt)*) => {};
+    (@ $cap:ident, $first:tt $(, $rest:tt)*) => {
+        $crate::__chip_in!(@ $cap, $($rest),*);
+    };
+    (@ $cap:ident,) => {
+        ::core::compile_error!(::core::concat!(
+            ::core::stringify!($cap),
+            " is not available on STM32F411, see the table in chip_caps"
+        ));
+    };
+    ($cap:ident $(, $chip:tt)*) => {
+        $crate::__chip_in!(@ $cap, $($chip),*);
+    };
+}
+
+#[cfg(feature = "stm32f412")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __chip_in {
+    (@ $cap:ident, "stm32f412" $(, $rest:tt)*) => {};
+    (@ $cap:ident, $first:tt $(, $rest:tt)*) => {
+        $crate::__chip_in!(@ $cap, $($rest),*);
+    };
+    (@ $cap:ident,) => {
+        ::core::compile_error!(::core::concat!(
+            ::core::stringify!($cap),
+            " is not available on STM32F412, see the table in chip_caps"
+        ));
+    };
+    ($cap:ident $(, $chip:tt)*) => {
+        $crate::__chip_in!(@ $cap, $($chip),*);
+    };
+}
+
+#[cfg(feature = "stm32f413")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __chip_in {
+    (@ $cap:ident, "stm32f413" $(, $rest:tt)*) => {};
+    (@ $cap:ident, $first:tt $(, $rest:tt)*) => {
+        $crate::__chip_in!(@ $cap, $($rest),*);
+    };
+    (@ $cap:ident,) => {
+        ::core::compile_error!(::core::concat!(
+            ::core::stringify!($cap),
+            " is not available on STM32F413, see the table in chip_caps"
+        ));
+    };
+    ($cap:ident $(, $chip:tt)*) => {
+        $crate::__chip_in!(@ $cap, $($chip),*);
+    };
+}
@@ -0,0 +1,359 @@
+//! 信号与引脚的对应关系，以及编译期检查的 bind!
+//!
+//! 之前的例子里，每次用到一个外设的引脚，都是先翻 Datasheet 的 Alternate function mapping 表，
+//! 再把结果写在注释里，比如“可以作为 USART1 Tx 引脚的有 AF07 模式下的 PA9 PA15 PB6”，
+//! 注释和代码不会互相检查，AF 写错一位，或者换了一颗没有这个映射的芯片，程序照样能编译，只是引脚上没有信号
+//!
+//! 这里把表格写成常量：
+//!
+//! - pin 中是所有的引脚，PA0 ~ PE15，以及 PH0、PH1（100 脚的封装没有 PF、PG）
+//! - signal 中是外设的信号，每个信号列出可以使用的引脚、AF 编号，以及哪些芯片上有这个映射
+//!
+//! bind!(USART1_TX => PA9) 在编译期查表，得到一个 Binding（引脚与 AF 编号）：
+//!
+//! - 信号或者引脚的名字写错了，找不到对应的常量
+//! - 这个引脚不能承载这个信号，或者所选的芯片上没有这个映射（比如 F401 的 PB8 不能作为 I2C3_SDA，F411 没有 QUADSPI），
+//!   const 求值失败，编译报错
+//!
+//! 用法：
+//!
+//! const TX: Binding = chip_caps::bind!(USART1_TX => PA9);
+//! gpioa.afrh.modify(|_, w| w.afrh9().bits(TX.af));
+//!
+//! 寄存器的字段（afrh9）依旧要自己对应到引脚上，Binding 中的 port 与 num 可以用来核对
+//!
+//! 只收录了笔记中用到的外设，AF 编号取自各芯片 Datasheet 的 Alternate function mapping 表；
+//! 引脚按 100 脚的封装收录，更小的封装有没有这个引脚，还要再看一眼 pin definitions
+
+use crate::{Chip, CHIP};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pin {
+    pub port: Port,
+    pub num: u8,
+}
+
+impl Pin {
+    pub const fn new(port: Port, num: u8) -> Self {
+        Self { port, num }
+    }
+
+    // AF 编号位于 AFRL（0 ~ 7）还是 AFRH（8 ~ 15）
+    pub const fn in_afrh(self) -> bool {
+        self.num >= 8
+    }
+}
+
+// 哪些芯片上有某个映射，每种芯片一位
+type Chips = u8;
+
+const fn chip_bit(chip: Chip) -> Chips {
+    match chip {
+        Chip::F401 => 0b0001,
+        Chip::F411 => 0b0010,
+        Chip::F412 => 0b0100,
+        Chip::F413 => 0b1000,
+    }
+}
+
+const ALL: Chips = 0b1111;
+const F411_UP: Chips = 0b1110;
+const F412_UP: Chips = 0b1100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alternative {
+    pub pin: Pin,
+    pub af: u8,
+    chips: Chips,
+}
+
+impl Alternative {
+    // 所选的芯片上有没有这个映射
+    pub const fn available(&self) -> bool {
+        self.chips & chip_bit(CHIP) != 0
+    }
+}
+
+const fn alt(pin: Pin, af: u8, chips: Chips) -> Alternative {
+    Alternative { pin, af, chips }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Signal {
+    pub name: &'static str,
+    pub alternatives: &'static [Alternative],
+}
+
+impl Signal {
+    const fn new(name: &'static str, alternatives: &'static [Alternative]) -> Self {
+        Self { name, alternatives }
+    }
+}
+
+// 一个确定下来的映射，由 bind! 得到
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding {
+    pub signal: &'static str,
+    pub port: Port,
+    pub num: u8,
+    pub af: u8,
+}
+
+// 查表，找不到时 panic，在 const 中调用时就成了编译错误，通常通过 bind! 使用
+// const 中的 panic 只能带一段固定的文字，具体是哪个信号与引脚，看报错位置的 bind! 即可
+pub const fn bind(signal: Signal, pin: Pin) -> Binding {
+    let mut i = 0;
+    let mut other_chip = false;
+    while i < signal.alternatives.len() {
+        let alt = signal.alternatives[i];
+        if alt.pin.port as u8 == pin.port as u8 && alt.pin.num == pin.num {
+            if alt.available() {
+                return Binding {
+                    signal: signal.name,
+                    port: pin.port,
+                    num: pin.num,
+                    af: alt.af,
+                };
+            }
+            other_chip = true;
+        }
+        i += 1;
+    }
+    if other_chip {
+        panic!("this pin can carry the signal on other chips, but not on the selected one");
+    }
+    panic!("this pin can not carry the signal, see chip_caps::pinmap::signal for the allowed pins");
+}
+
+// 在编译期得到 Binding，写法为 bind!(信号 => 引脚)
+#[macro_export]
+macro_rules! bind {
+    ($signal:ident => $pin:ident) => {{
+        const BINDING: $crate::pinmap::Binding =
+            $crate::pinmap::bind($crate::pinmap::signal::$signal, $crate::pinmap::pin::$pin);
+        BINDING
+    }};
+}
+
+pub mod pin {
+    use super::{Pin, Port};
+
+    pub const PA0: Pin = Pin::new(Port::A, 0);
+    pub const PA1: Pin = Pin::new(Port::A, 1);
+    pub const PA2: Pin = Pin::new(Port::A, 2);
+    pub const PA3: Pin = Pin::new(Port::A, 3);
+    pub const PA4: Pin = Pin::new(Port::A, 4);
+    pub const PA5: Pin = Pin::new(Port::A, 5);
+    pub const PA6: Pin = Pin::new(Port::A, 6);
+    pub const PA7: Pin = Pin::new(Port::A, 7);
+    pub const PA8: Pin = Pin::new(Port::A, 8);
+    pub const PA9: Pin = Pin::new(Port::A, 9);
+    pub const PA10: Pin = Pin::new(Port::A, 10);
+    pub const PA11: Pin = Pin::new(Port::A, 11);
+    pub const PA12: Pin = Pin::new(Port::A, 12);
+    pub const PA13: Pin = Pin::new(Port::A, 13);
+    pub const PA14: Pin = Pin::new(Port::A, 14);
+    pub const PA15: Pin = Pin::new(Port::A, 15);
+    pub const PB0: Pin = Pin::new(Port::B, 0);
+    pub const PB1: Pin = Pin::new(Port::B, 1);
+    pub const PB2: Pin = Pin::new(Port::B, 2);
+    pub const PB3: Pin = Pin::new(Port::B, 3);
+    pub const PB4: Pin = Pin::new(Port::B, 4);
+    pub const PB5: Pin = Pin::new(Port::B, 5);
+    pub const PB6: Pin = Pin::new(Port::B, 6);
+    pub const PB7: Pin = Pin::new(Port::B, 7);
+    pub const PB8: Pin = Pin::new(Port::B, 8);
+    pub const PB9: Pin = Pin::new(Port::B, 9);
+    pub const PB10: Pin = Pin::new(Port::B, 10);
+    pub const PB11: Pin = Pin::new(Port::B, 11);
+    pub const PB12: Pin = Pin::new(Port::B, 12);
+    pub const PB13: Pin = Pin::new(Port::B, 13);
+    pub const PB14: Pin = Pin::new(Port::B, 14);
+    pub const PB15: Pin = Pin::new(Port::B, 15);
+    pub const PC0: Pin = Pin::new(Port::C, 0);
+    pub const PC1: Pin = Pin::new(Port::C, 1);
+    pub const PC2: Pin = Pin::new(Port::C, 2);
+    pub const PC3: Pin = Pin::new(Port::C, 3);
+    pub const PC4: Pin = Pin::new(Port::C, 4);
+    pub const PC5: Pin = Pin::new(Port::C, 5);
+    pub const PC6: Pin = Pin::new(Port::C, 6);
+    pub const PC7: Pin = Pin::new(Port::C, 7);
+    pub const PC8: Pin = Pin::new(Port::C, 8);
+    pub const PC9: Pin = Pin::new(Port::C, 9);
+    pub const PC10: Pin = Pin::new(Port::C, 10);
+    pub const PC11: Pin = Pin::new(Port::C, 11);
+    pub const PC12: Pin = Pin::new(Port::C, 12);
+    pub const PC13: Pin = Pin::new(Port::C, 13);
+    pub const PC14: Pin = Pin::new(Port::C, 14);
+    pub const PC15: Pin = Pin::new(Port::C, 15);
+    pub const PD0: Pin = Pin::new(Port::D, 0);
+    pub const PD1: Pin = Pin::new(Port::D, 1);
+    pub const PD2: Pin = Pin::new(Port::D, 2);
+    pub const PD3: Pin = Pin::new(Port::D, 3);
+    pub const PD4: Pin = Pin::new(Port::D, 4);
+    pub const PD5: Pin = Pin::new(Port::D, 5);
+    pub const PD6: Pin = Pin::new(Port::D, 6);
+    pub const PD7: Pin = Pin::new(Port::D, 7);
+    pub const PD8: Pin = Pin::new(Port::D, 8);
+    pub const PD9: Pin = Pin::new(Port::D, 9);
+    pub const PD10: Pin = Pin::new(Port::D, 10);
+    pub const PD11: Pin = Pin::new(Port::D, 11);
+    pub const PD12: Pin = Pin::new(Port::D, 12);
+    pub const PD13: Pin = Pin::new(Port::D, 13);
+    pub const PD14: Pin = Pin::new(Port::D, 14);
+    pub const PD15: Pin = Pin::new(Port::D, 15);
+    pub const PE0: Pin = Pin::new(Port::E, 0);
+    pub const PE1: Pin = Pin::new(Port::E, 1);
+    pub const PE2: Pin = Pin::new(Port::E, 2);
+    pub const PE3: Pin = Pin::new(Port::E, 3);
+    pub const PE4: Pin = Pin::new(Port::E, 4);
+    pub const PE5: Pin = Pin::new(Port::E, 5);
+    pub const PE6: Pin = Pin::new(Port::E, 6);
+    pub const PE7: Pin = Pin::new(Port::E, 7);
+    pub const PE8: Pin = Pin::new(Port::E, 8);
+    pub const PE9: Pin = Pin::new(Port::E, 9);
+    pub const PE10: Pin = Pin::new(Port::E, 10);
+    pub const PE11: Pin = Pin::new(Port::E, 11);
+    pub const PE12: Pin = Pin::new(Port::E, 12);
+    pub const PE13: Pin = Pin::new(Port::E, 13);
+    pub const PE14: Pin = Pin::new(Port::E, 14);
+    pub const PE15: Pin = Pin::new(Port::E, 15);
+    pub const PH0: Pin = Pin::new(Port::H, 0);
+    pub const PH1: Pin = Pin::new(Port::H, 1);
+}
+
+// 信号名与 Datasheet 中的写法一致
+pub mod signal {
+    use super::{alt, pin::*, Signal, ALL, F411_UP, F412_UP};
+
+    pub const USART1_TX: Signal = Signal::new(
+        "USART1_TX",
+        &[alt(PA9, 7, ALL), alt(PB6, 7, ALL), alt(PA15, 7, F411_UP)],
+    );
+    pub const USART1_RX: Signal = Signal::new(
+        "USART1_RX",
+        &[alt(PA10, 7, ALL), alt(PB7, 7, ALL), alt(PB3, 7, F411_UP)],
+    );
+    pub const USART2_TX: Signal = Signal::new("USART2_TX", &[alt(PA2, 7, ALL), alt(PD5, 7, ALL)]);
+    pub const USART2_RX: Signal = Signal::new("USART2_RX", &[alt(PA3, 7, ALL), alt(PD6, 7, ALL)]);
+    pub const USART3_TX: Signal = Signal::new(
+        "USART3_TX",
+        &[
+            alt(PB10, 7, F412_UP),
+            alt(PC10, 7, F412_UP),
+            alt(PD8, 7, F412_UP),
+        ],
+    );
+    pub const USART3_RX: Signal = Signal::new(
+        "USART3_RX",
+        &[
+            alt(PB11, 7, F412_UP),
+            alt(PC11, 7, F412_UP),
+            alt(PD9, 7, F412_UP),
+        ],
+    );
+    pub const USART6_TX: Signal =
+        Signal::new("USART6_TX", &[alt(PC6, 8, ALL), alt(PA11, 8, F411_UP)]);
+    pub const USART6_RX: Signal =
+        Signal::new("USART6_RX", &[alt(PC7, 8, ALL), alt(PA12, 8, F411_UP)]);
+    pub const I2C1_SCL: Signal = Signal::new("I2C1_SCL", &[alt(PB6, 4, ALL), alt(PB8, 4, ALL)]);
+    pub const I2C1_SDA: Signal = Signal::new("I2C1_SDA", &[alt(PB7, 4, ALL), alt(PB9, 4, ALL)]);
+    pub const I2C2_SCL: Signal = Signal::new("I2C2_SCL", &[alt(PB10, 4, ALL)]);
+    pub const I2C2_SDA: Signal = Signal::new(
+        "I2C2_SDA",
+        &[
+            alt(PB11, 4, ALL),
+            alt(PB3, 9, F411_UP),
+            alt(PB9, 9, F411_UP),
+        ],
+    );
+    pub const I2C3_SCL: Signal = Signal::new("I2C3_SCL", &[alt(PA8, 4, ALL)]);
+    pub const I2C3_SDA: Signal = Signal::new(
+        "I2C3_SDA",
+        &[alt(PC9, 4, ALL), alt(PB4, 9, ALL), alt(PB8, 9, F411_UP)],
+    );
+    pub const SPI1_NSS: Signal = Signal::new("SPI1_NSS", &[alt(PA4, 5, ALL), alt(PA15, 5, ALL)]);
+    pub const SPI1_SCK: Signal = Signal::new("SPI1_SCK", &[alt(PA5, 5, ALL), alt(PB3, 5, ALL)]);
+    pub const SPI1_MISO: Signal = Signal::new("SPI1_MISO", &[alt(PA6, 5, ALL), alt(PB4, 5, ALL)]);
+    pub const SPI1_MOSI: Signal = Signal::new("SPI1_MOSI", &[alt(PA7, 5, ALL), alt(PB5, 5, ALL)]);
+    pub const SPI2_NSS: Signal = Signal::new("SPI2_NSS", &[alt(PB12, 5, ALL), alt(PB9, 5, ALL)]);
+    pub const SPI2_SCK: Signal = Signal::new("SPI2_SCK", &[alt(PB13, 5, ALL), alt(PB10, 5, ALL)]);
+    pub const SPI2_MISO: Signal = Signal::new("SPI2_MISO", &[alt(PB14, 5, ALL), alt(PC2, 5, ALL)]);
+    pub const SPI2_MOSI: Signal = Signal::new("SPI2_MOSI", &[alt(PB15, 5, ALL), alt(PC3, 5, ALL)]);
+    pub const SPI3_SCK: Signal = Signal::new("SPI3_SCK", &[alt(PC10, 6, ALL), alt(PB3, 6, ALL)]);
+    pub const SPI3_MISO: Signal = Signal::new("SPI3_MISO", &[alt(PC11, 6, ALL), alt(PB4, 6, ALL)]);
+    pub const SPI3_MOSI: Signal = Signal::new("SPI3_MOSI", &[alt(PC12, 6, ALL), alt(PB5, 6, ALL)]);
+    pub const TIM1_CH1: Signal = Signal::new("TIM1_CH1", &[alt(PA8, 1, ALL), alt(PE9, 1, ALL)]);
+    pub const TIM1_CH2: Signal = Signal::new("TIM1_CH2", &[alt(PA9, 1, ALL), alt(PE11, 1, ALL)]);
+    pub const TIM1_CH3: Signal = Signal::new("TIM1_CH3", &[alt(PA10, 1, ALL), alt(PE13, 1, ALL)]);
+    pub const TIM1_CH4: Signal = Signal::new("TIM1_CH4", &[alt(PA11, 1, ALL), alt(PE14, 1, ALL)]);
+    pub const TIM1_CH1N: Signal = Signal::new("TIM1_CH1N", &[alt(PA7, 1, ALL), alt(PB13, 1, ALL)]);
+    pub const TIM2_CH1: Signal = Signal::new(
+        "TIM2_CH1",
+        &[alt(PA0, 1, ALL), alt(PA5, 1, ALL), alt(PA15, 1, ALL)],
+    );
+    pub const TIM2_CH2: Signal = Signal::new("TIM2_CH2", &[alt(PA1, 1, ALL), alt(PB3, 1, ALL)]);
+    pub const TIM2_CH3: Signal = Signal::new("TIM2_CH3", &[alt(PA2, 1, ALL), alt(PB10, 1, ALL)]);
+    pub const TIM2_CH4: Signal = Signal::new("TIM2_CH4", &[alt(PA3, 1, ALL), alt(PB11, 1, ALL)]);
+    pub const TIM3_CH1: Signal = Signal::new(
+        "TIM3_CH1",
+        &[alt(PA6, 2, ALL), alt(PB4, 2, ALL), alt(PC6, 2, ALL)],
+    );
+    pub const TIM3_CH2: Signal = Signal::new(
+        "TIM3_CH2",
+        &[alt(PA7, 2, ALL), alt(PB5, 2, ALL), alt(PC7, 2, ALL)],
+    );
+    pub const TIM3_CH3: Signal = Signal::new("TIM3_CH3", &[alt(PB0, 2, ALL), alt(PC8, 2, ALL)]);
+    pub const TIM3_CH4: Signal = Signal::new("TIM3_CH4", &[alt(PB1, 2, ALL), alt(PC9, 2, ALL)]);
+    pub const TIM4_CH1: Signal = Signal::new("TIM4_CH1", &[alt(PB6, 2, ALL), alt(PD12, 2, ALL)]);
+    pub const TIM4_CH2: Signal = Signal::new("TIM4_CH2", &[alt(PB7, 2, ALL), alt(PD13, 2, ALL)]);
+    pub const TIM4_CH3: Signal = Signal::new("TIM4_CH3", &[alt(PB8, 2, ALL), alt(PD14, 2, ALL)]);
+    pub const TIM4_CH4: Signal = Signal::new("TIM4_CH4", &[alt(PB9, 2, ALL), alt(PD15, 2, ALL)]);
+    pub const TIM5_CH1: Signal = Signal::new("TIM5_CH1", &[alt(PA0, 2, ALL)]);
+    pub const TIM5_CH2: Signal = Signal::new("TIM5_CH2", &[alt(PA1, 2, ALL)]);
+    pub const TIM5_CH3: Signal = Signal::new("TIM5_CH3", &[alt(PA2, 2, ALL)]);
+    pub const TIM5_CH4: Signal = Signal::new("TIM5_CH4", &[alt(PA3, 2, ALL)]);
+    pub const TIM8_CH1: Signal = Signal::new("TIM8_CH1", &[alt(PC6, 3, F412_UP)]);
+    pub const TIM8_CH2: Signal = Signal::new("TIM8_CH2", &[alt(PC7, 3, F412_UP)]);
+    pub const TIM8_CH3: Signal = Signal::new("TIM8_CH3", &[alt(PC8, 3, F412_UP)]);
+    pub const TIM8_CH4: Signal = Signal::new("TIM8_CH4", &[alt(PC9, 3, F412_UP)]);
+    pub const QUADSPI_CLK: Signal = Signal::new(
+        "QUADSPI_CLK",
+        &[
+            alt(PB1, 9, F412_UP),
+            alt(PB2, 9, F412_UP),
+            alt(PD3, 9, F412_UP),
+        ],
+    );
+    pub const QUADSPI_BK1_NCS: Signal = Signal::new("QUADSPI_BK1_NCS", &[alt(PB6, 10, F412_UP)]);
+    pub const QUADSPI_BK1_IO0: Signal = Signal::new(
+        "QUADSPI_BK1_IO0",
+        &[alt(PC9, 9, F412_UP), alt(PD11, 9, F412_UP)],
+    );
+    pub const QUADSPI_BK1_IO1: Signal = Signal::new(
+        "QUADSPI_BK1_IO1",
+        &[alt(PC10, 9, F412_UP), alt(PD12, 9, F412_UP)],
+    );
+    pub const QUADSPI_BK1_IO2: Signal = Signal::new(
+        "QUADSPI_BK1_IO2",
+        &[alt(PC8, 9, F412_UP), alt(PE2, 9, F412_UP)],
+    );
+    pub const QUADSPI_BK1_IO3: Signal = Signal::new(
+        "QUADSPI_BK1_IO3",
+        &[alt(PA1, 9, F412_UP), alt(PD13, 9, F412_UP)],
+    );
+    pub const OTG_FS_DM: Signal = Signal::new("OTG_FS_DM", &[alt(PA11, 10, ALL)]);
+    pub const OTG_FS_DP: Signal = Signal::new("OTG_FS_DP", &[alt(PA12, 10, ALL)]);
+    pub const MCO1: Signal = Signal::new("MCO1", &[alt(PA8, 0, ALL)]);
+    pub const MCO2: Signal = Signal::new("MCO2", &[alt(PC9, 0, ALL)]);
+}
@@ -0,0 +1,174 @@
+//! AES-128 与 CTR 模式
+//!
+//! 按照 FIPS 197 实现分组加密，按照 NIST SP 800-38A 实现 CTR 模式
+//!
+//! CTR 模式把一个计数器逐块加密得到密钥流，再与数据异或，因此：
+//!
+//! 1. 加密与解密是同一个操作，只需要实现 AES 的加密方向
+//! 2. 数据不需要是 16 字节的整数倍，最后一块只使用密钥流的前一部分
+//! 3. 同一个密钥下，计数器绝对不能重复使用：两段数据用了相同的密钥流，把两段密文异或就得到了两段明文的异或
+//! 4. CTR 只提供保密性，不能发现密文被篡改（翻转密文的某一位，解密后明文的同一位也会翻转），需要完整性时另外加上 HMAC
+//!
+//! 关于耗时：这里的 S 盒是查表实现的，查表的地址与数据有关
+//! F413 的 SRAM 没有数据缓存，访问任何地址的耗时都相同；但 Flash 前面有 ART 加速器的缓存，
+//! S 盒位于 Flash 时，命中与不命中缓存的耗时不同，理论上存在时间侧信道
+//! 对于遥测数据的加密来说，攻击者很难精确测量每次加密的耗时，这里就不做 bitslice 之类的常数时间实现了
+
+pub const KEY_LEN: usize = 16;
+pub const BLOCK_LEN: usize = 16;
+
+const ROUNDS: usize = 10;
+
+pub type Block = [u8; BLOCK_LEN];
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7C, 0x77, 0x7B, 0xF2, 0x6B, 0x6F, 0xC5, 0x30, 0x01, 0x67, 0x2B, 0xFE, 0xD7, 0xAB, 0x76,
+    0xCA, 0x82, 0xC9, 0x7D, 0xFA, 0x59, 0x47, 0xF0, 0xAD, 0xD4, 0xA2, 0xAF, 0x9C, 0xA4, 0x72, 0xC0,
+    0xB7, 0xFD, 0x93, 0x26, 0x36, 0x3F, 0xF7, 0xCC, 0x34, 0xA5, 0xE5, 0xF1, 0x71, 0xD8, 0x31, 0x15,
+    0x04, 0xC7, 0x23, 0xC3, 0x18, 0x96, 0x05, 0x9A, 0x07, 0x12, 0x80, 0xE2, 0xEB, 0x27, 0xB2, 0x75,
+    0x09, 0x83, 0x2C, 0x1A, 0x1B, 0x6E, 0x5A, 0xA0, 0x52, 0x3B, 0xD6, 0xB3, 0x29, 0xE3, 0x2F, 0x84,
+    0x53, 0xD1, 0x00, 0xED, 0x20, 0xFC, 0xB1, 0x5B, 0x6A, 0xCB, 0xBE, 0x39, 0x4A, 0x4C, 0x58, 0xCF,
+    0xD0, 0xEF, 0xAA, 0xFB, 0x43, 0x4D, 0x33, 0x85, 0x45, 0xF9, 0x02, 0x7F, 0x50, 0x3C, 0x9F, 0xA8,
+    0x51, 0xA3, 0x40, 0x8F, 0x92, 0x9D, 0x38, 0xF5, 0xBC, 0xB6, 0xDA, 0x21, 0x10, 0xFF, 0xF3, 0xD2,
+    0xCD, 0x0C, 0x13, 0xEC, 0x5F, 0x97, 0x44, 0x17, 0xC4, 0xA7, 0x7E, 0x3D, 0x64, 0x5D, 0x19, 0x73,
+    0x60, 0x81, 0x4F, 0xDC, 0x22, 0x2A, 0x90, 0x88, 0x46, 0xEE, 0xB8, 0x14, 0xDE, 0x5E, 0x0B, 0xDB,
+    0xE0, 0x32, 0x3A, 0x0A, 0x49, 0x06, 0x24, 0x5C, 0xC2, 0xD3, 0xAC, 0x62, 0x91, 0x95, 0xE4, 0x79,
+    0xE7, 0xC8, 0x37, 0x6D, 0x8D, 0xD5, 0x4E, 0xA9, 0x6C, 0x56, 0xF4, 0xEA, 0x65, 0x7A, 0xAE, 0x08,
+    0xBA, 0x78, 0x25, 0x2E, 0x1C, 0xA6, 0xB4, 0xC6, 0xE8, 0xDD, 0x74, 0x1F, 0x4B, 0xBD, 0x8B, 0x8A,
+    0x70, 0x3E, 0xB5, 0x66, 0x48, 0x03, 0xF6, 0x0E, 0x61, 0x35, 0x57, 0xB9, 0x86, 0xC1, 0x1D, 0x9E,
+    0xE1, 0xF8, 0x98, 0x11, 0x69, 0xD9, 0x8E, 0x94, 0x9B, 0x1E, 0x87, 0xE9, 0xCE, 0x55, 0x28, 0xDF,
+    0x8C, 0xA1, 0x89, 0x0D, 0xBF, 0xE6, 0x42, 0x68, 0x41, 0x99, 0x2D, 0x0F, 0xB0, 0x54, 0xBB, 0x16,
+];
+
+// 每一轮的轮常数，x^(i-1) in GF(2^8)
+const RCON: [u8; ROUNDS] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1B, 0x36];
+
+// 展开后的 11 个轮密钥
+#[derive(Clone)]
+pub struct Aes128 {
+    round_keys: [Block; ROUNDS + 1],
+}
+
+impl Aes128 {
+    // 密钥扩展，可以在编译期完成，比如 static CIPHER: Aes128 = Aes128::new(&KEY)
+    pub const fn new(key: &[u8; KEY_LEN]) -> Self {
+        let mut round_keys = [[0u8; BLOCK_LEN]; ROUNDS + 1];
+        round_keys[0] = *key;
+
+        let mut round = 1;
+        while round <= ROUNDS {
+            let prev = round_keys[round - 1];
+            // RotWord + SubWord + Rcon
+            let mut word = [
+                SBOX[prev[13] as usize] ^ RCON[round - 1],
+                SBOX[prev[14] as usize],
+                SBOX[prev[15] as usize],
+                SBOX[prev[12] as usize],
+            ];
+
+            let mut index = 0;
+            while index < BLOCK_LEN {
+                word[index % 4] ^= prev[index];
+                round_keys[round][index] = word[index % 4];
+                index += 1;
+            }
+            round += 1;
+        }
+
+        Self { round_keys }
+    }
+
+    // 加密一个分组
+    pub fn encrypt_block(&self, block: &mut Block) {
+        add_round_key(block, &self.round_keys[0]);
+        for round_key in &self.round_keys[1..ROUNDS] {
+            sub_bytes(block);
+            shift_rows(block);
+            mix_columns(block);
+            add_round_key(block, round_key);
+        }
+        sub_bytes(block);
+        shift_rows(block);
+        add_round_key(block, &self.round_keys[ROUNDS]);
+    }
+
+    // CTR 模式加密或解密 data
+    // counter 是第一块的计数器，每处理一块按 128 位的大端序整数加 1，返回处理完之后的计数器，
+    // 一段数据分多次处理时，下一次从返回的计数器继续；但分开处理时，除了最后一次，每次的长度都必须是 16 的整数倍
+    pub fn apply_ctr(&self, counter: &Block, data: &mut [u8]) -> Block {
+        let mut counter = *counter;
+        for chunk in data.chunks_mut(BLOCK_LEN) {
+            let mut keystream = counter;
+            self.encrypt_block(&mut keystream);
+            for (byte, key) in chunk.iter_mut().zip(keystream) {
+                *byte ^= key;
+            }
+            increment(&mut counter);
+        }
+        counter
+    }
+}
+
+// 轮密钥也是秘密，不再使用时清零，防止之后从 RAM 中被读出来
+impl Drop for Aes128 {
+    fn drop(&mut self) {
+        for round_key in self.round_keys.iter_mut() {
+            round_key.fill(0);
+        }
+        core::hint::black_box(&mut self.round_keys);
+    }
+}
+
+// 8 字节的 nonce 与 8 字节的块序号组成计数器的常见做法：nonce || 0
+pub fn counter_block(nonce: u64) -> Block {
+    let mut counter = [0u8; BLOCK_LEN];
+    counter[..8].copy_from_slice(&nonce.to_be_bytes());
+    counter
+}
+
+fn increment(counter: &mut Block) {
+    for byte in counter.iter_mut().rev() {
+        *byte = byte.wrapping_add(1);
+        if *byte != 0 {
+            break;
+        }
+    }
+}
+
+fn add_round_key(block: &mut Block, round_key: &Block) {
+    for (byte, key) in block.iter_mut().zip(round_key) {
+        *byte ^= key;
+    }
+}
+
+fn sub_bytes(block: &mut Block) {
+    for byte in block.iter_mut() {
+        *byte = SBOX[*byte as usize];
+    }
+}
+
+// 分组按列存放：block[4 * c + r] 是第 r 行第 c 列，第 r 行循环左移 r 个字节
+fn shift_rows(block: &mut Block) {
+    let state = *block;
+    for c in 0..4 {
+        for r in 1..4 {
+            block[4 * c + r] = state[4 * ((c + r) % 4) + r];
+        }
+    }
+}
+
+// GF(2^8) 中乘以 x，不使用分支，耗时与数据无关
+fn xtime(value: u8) -> u8 {
+    (value << 1) ^ (0x1B & 0u8.wrapping_sub(value >> 7))
+}
+
+fn mix_columns(block: &mut Block) {
+    for column in block.chunks_exact_mut(4) {
+        let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+        let all = a0 ^ a1 ^ a2 ^ a3;
+        column[0] ^= all ^ xtime(a0 ^ a1);
+        column[1] ^= all ^ xtime(a1 ^ a2);
+        column[2] ^= all ^ xtime(a2 ^ a3);
+        column[3] ^= all ^ xtime(a3 ^ a0);
+    }
+}
@@ -0,0 +1,15 @@
+//! 与数据无关的耗时
+//!
+//! 比较 MAC、签名、密码之类的秘密数据时，如果在第一个不同的字节处就返回，
+//! 攻击者可以通过响应时间逐字节猜出正确的值，因此这类比较要把所有字节都看一遍，只在最后给出结果
+
+// 比较两段数据是否相同，耗时只与长度有关，与第一个不同的字节在哪里无关
+// 长度本身不是秘密，长度不同时直接返回
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y));
+    // 防止编译器把上面的循环优化成提前退出的比较
+    core::hint::black_box(diff) == 0
+}
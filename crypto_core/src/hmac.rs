@@ -0,0 +1,76 @@
+//! HMAC-SHA256
+//!
+//! 按照 RFC 2104：H((K ^ opad) || H((K ^ ipad) || m))，超过一个块的密钥先做一次哈希
+//!
+//! 与 Sha256 一样可以分多次 update，bootloader 可以一边读取 Flash 中的镜像一边计算
+//! 检查对方给出的 MAC 时应该使用 verify，而不是直接用 == 比较，原因见 ct::ct_eq
+
+use crate::{
+    ct::ct_eq,
+    sha256::{sha256, Sha256, BLOCK_LEN, DIGEST_LEN},
+};
+
+// verify 接受的最短的截短 MAC，RFC 2104 第 5 节建议不短于哈希长度的一半
+// 更短的 MAC 被猜中的概率太高，比如只有 1 个字节时，随便给一个值就有 1/256 的机会通过
+pub const MIN_MAC_LEN: usize = DIGEST_LEN / 2;
+
+#[derive(Clone)]
+pub struct HmacSha256 {
+    inner: Sha256,
+    // 外层的哈希在 new 时就吸收了 K ^ opad，finalize 时只需要再输入内层的结果
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    pub fn new(key: &[u8]) -> Self {
+        let mut padded = [0u8; BLOCK_LEN];
+        if key.len() > BLOCK_LEN {
+            padded[..DIGEST_LEN].copy_from_slice(&sha256(key));
+        } else {
+            padded[..key.len()].copy_from_slice(key);
+        }
+
+        let mut inner_pad = [0u8; BLOCK_LEN];
+        let mut outer_pad = [0u8; BLOCK_LEN];
+        for ((inner, outer), byte) in inner_pad.iter_mut().zip(&mut outer_pad).zip(padded) {
+            *inner = byte ^ 0x36;
+            *outer = byte ^ 0x5C;
+        }
+
+        let mut inner = Sha256::new();
+        inner.update(&inner_pad);
+        let mut outer = Sha256::new();
+        outer.update(&outer_pad);
+
+        Self { inner, outer }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finalize(self) -> [u8; DIGEST_LEN] {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finalize());
+        outer.finalize()
+    }
+
+    // 与 mac 比较，耗时与 mac 的内容无关
+    // mac 可以是截短的 MAC（比如只取前 16 个字节），此时只比较前 mac.len() 个字节，但长度不能短于 MIN_MAC_LEN
+    pub fn verify(self, mac: &[u8]) -> bool {
+        let expected = self.finalize();
+        (MIN_MAC_LEN..=DIGEST_LEN).contains(&mac.len()) && ct_eq(&expected[..mac.len()], mac)
+    }
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut mac = HmacSha256::new(key);
+    mac.update(data);
+    mac.finalize()
+}
+
+pub fn verify(key: &[u8], data: &[u8], mac: &[u8]) -> bool {
+    let mut hmac = HmacSha256::new(key);
+    hmac.update(data);
+    hmac.verify(mac)
+}
@@ -0,0 +1,145 @@
+//! 带签名的固件镜像格式，由 s14 的 bootloader 检查，由 Host 端的 image_tool 生成
+//!
+//! 一个镜像槽（slot）的开头是 HEADER_LEN 字节的头部，之后紧跟着 app 本身（从向量表开始）：
+//!
+//! | 偏移 | 长度 | 内容                                                   |
+//! | 0    | 4    | MAGIC，"FWIM"                                          |
+//! | 4    | 4    | 头部的长度，固定为 HEADER_LEN                          |
+//! | 8    | 4    | app 的长度 image_len                                   |
+//! | 12   | 4    | 版本号 version，两个槽都有效时启动版本号大的那一个     |
+//! | 16   | 4    | 安全版本号 security_version，见下方说明                |
+//! | 20   | 12   | 保留，为 0                                             |
+//! | 32   | 32   | MAC：HMAC-SHA256(key, 头部的前 32 字节 || app)          |
+//! | 64   | 448  | 填充，为 0xFF                                          |
+//!
+//! 所有多字节的字段均为小端序
+//! 头部占用 512 字节，是为了让 app 的向量表满足 VTOR 的对齐要求（见 s14 的 utils::boot）
+//!
+//! MAC 同时覆盖了头部与 app，改动其中任何一个字节（包括版本号）都会让检查失败
+//! 这里用的是 HMAC 而不是 Ed25519 之类的签名：bootloader 中必须保存同一个密钥，能读出 bootloader 的人也就能生成合法的镜像，
+//! 因此 bootloader 所在的扇区需要写保护，芯片需要开启 RDP Level 1（见 s14 的 utils::option_bytes）
+//!
+//! 安全版本号用来防止回滚：发现旧版本有漏洞时，新版本提高 security_version，
+//! bootloader 启动它之后会记住这个值，之后安全版本号更低的镜像即使 MAC 正确也不会再被启动
+//! 只修复普通 bug 的版本不需要提高 security_version，这样出问题时还可以退回上一个版本
+
+use crate::hmac::HmacSha256;
+
+pub const HEADER_LEN: usize = 0x200;
+// 头部中参与 MAC 计算的部分
+pub const SIGNED_LEN: usize = 32;
+pub const MAC_LEN: usize = 32;
+
+// "FWIM"
+pub const MAGIC: u32 = 0x4D49_5746;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub image_len: u32,
+    pub version: u32,
+    pub security_version: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    // MAGIC 不对，通常说明这个槽是空的（擦除之后为 0xFF）
+    NoImage,
+    // 头部的长度或保留字段不对
+    BadHeader,
+    // app 的长度为 0，或者超出了槽的大小
+    BadLength,
+    // MAC 不对，镜像被改动过，或者使用了别的密钥
+    BadMac,
+}
+
+impl ImageHeader {
+    // 解析头部，只检查格式，不检查 MAC
+    pub fn parse(header: &[u8]) -> Result<Self, ImageError> {
+        if header.len() < SIGNED_LEN {
+            return Err(ImageError::BadHeader);
+        }
+        let word = |offset: usize| {
+            u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap())
+        };
+
+        if word(0) != MAGIC {
+            return Err(ImageError::NoImage);
+        }
+        if word(4) != HEADER_LEN as u32 || header[20..SIGNED_LEN].iter().any(|&b| b != 0) {
+            return Err(ImageError::BadHeader);
+        }
+
+        Ok(Self {
+            image_len: word(8),
+            version: word(12),
+            security_version: word(16),
+        })
+    }
+
+    fn signed_part(&self) -> [u8; SIGNED_LEN] {
+        let mut bytes = [0u8; SIGNED_LEN];
+        let fields = [
+            MAGIC,
+            HEADER_LEN as u32,
+            self.image_len,
+            self.version,
+            self.security_version,
+        ];
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn mac(&self, key: &[u8], image: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new(key);
+        mac.update(&self.signed_part());
+        mac.update(image);
+        mac
+    }
+}
+
+// 为 image 生成头部，把头部与 image 依次写入 Flash 即可
+pub fn sign(
+    key: &[u8],
+    version: u32,
+    security_version: u32,
+    image: &[u8],
+) -> Result<[u8; HEADER_LEN], ImageError> {
+    let image_len = u32::try_from(image.len()).map_err(|_| ImageError::BadLength)?;
+    if image_len == 0 {
+        return Err(ImageError::BadLength);
+    }
+    let header = ImageHeader {
+        image_len,
+        version,
+        security_version,
+    };
+
+    let mut bytes = [0xFFu8; HEADER_LEN];
+    bytes[..SIGNED_LEN].copy_from_slice(&header.signed_part());
+    bytes[SIGNED_LEN..SIGNED_LEN + MAC_LEN].copy_from_slice(&header.mac(key, image).finalize());
+    Ok(bytes)
+}
+
+// 检查一个槽中的镜像，slot 是整个槽（头部 + app + 之后没有用到的部分）
+// MAC 的比较是常数时间的，见 ct
+pub fn verify(key: &[u8], slot: &[u8]) -> Result<ImageHeader, ImageError> {
+    if slot.len() < HEADER_LEN {
+        return Err(ImageError::BadLength);
+    }
+    let header = ImageHeader::parse(&slot[..HEADER_LEN])?;
+
+    let image_len = header.image_len as usize;
+    if image_len == 0 || image_len > slot.len() - HEADER_LEN {
+        return Err(ImageError::BadLength);
+    }
+
+    let image = &slot[HEADER_LEN..HEADER_LEN + image_len];
+    let mac = &slot[SIGNED_LEN..SIGNED_LEN + MAC_LEN];
+    if header.mac(key, image).verify(mac) {
+        Ok(header)
+    } else {
+        Err(ImageError::BadMac)
+    }
+}
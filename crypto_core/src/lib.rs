@@ -0,0 +1,30 @@
+//! MCU 与 Host 共用的密码学基础算法
+//!
+//! 需要用到它们的地方有：
+//!
+//! - s14 的 bootloader 与 Host 端的 image_tool：检查与生成带有 HMAC 的 app 镜像（image）
+//! - s21 的命令行认证（utils::shell_auth）：HMAC-SHA256 的挑战与应答
+//! - telemetry_core 的帧：可选的 payload 加密（AES-128-CTR）
+//!
+//! 这些算法都不依赖外设，单独放在一个 no_std 的 crate 中，MCU 端与 Host 端运行的是同一份代码，
+//! 测试向量（vectors）也可以在两端分别运行
+//!
+//! 模块：
+//!
+//! - sha256：SHA-256，可以分多次输入
+//! - hmac：HMAC-SHA256，以及常数时间的 MAC 检查
+//! - aes：AES-128 的加密方向，以及 CTR 模式
+//! - ct：耗时与数据无关的比较
+//! - vectors：标准文档中的测试向量
+//! - image：s14 的 bootloader 检查的固件镜像头部，带有 HMAC 与防回滚用的安全版本号
+//!
+//! 注意：这些实现以容易读懂为主，没有针对侧信道做全面的防护，各模块的说明中写明了哪些操作的耗时与数据无关
+
+#![no_std]
+
+pub mod aes;
+pub mod ct;
+pub mod hmac;
+pub mod image;
+pub mod sha256;
+pub mod vectors;
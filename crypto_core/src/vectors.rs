@@ -0,0 +1,240 @@
+//! 已知答案测试（Known Answer Test）
+//!
+//! 用标准文档中给出的测试向量检查各个算法的实现，MCU 与 Host 都可以运行：
+//!
+//! - MCU 端：s21c06 的自检中有一项 crypto，失败时的错误码是 CHECKS 中失败项的下标
+//! - Host 端：cargo run -p telemetry_host --target x86_64-unknown-linux-gnu -- selftest
+//! - 单元测试：cargo test -p crypto_core --target x86_64-unknown-linux-gnu，每种算法一个 #[test]，失败时能直接看到是哪一组
+//!
+//! 两端运行的是同一份代码，Host 端通过而 MCU 端不通过时，多半是编译器优化或者栈溢出之类的问题，而不是算法本身写错了
+
+use crate::{
+    aes::{Aes128, Block},
+    hmac::{self, hmac_sha256},
+    sha256::{sha256, Sha256},
+};
+
+pub struct Check {
+    pub name: &'static str,
+    pub run: fn() -> bool,
+}
+
+pub const CHECKS: [Check; 9] = [
+    Check {
+        name: "sha256 empty",
+        run: sha256_empty,
+    },
+    Check {
+        name: "sha256 abc",
+        run: sha256_abc,
+    },
+    Check {
+        name: "sha256 two blocks",
+        run: sha256_two_blocks,
+    },
+    Check {
+        name: "sha256 split update",
+        run: sha256_split_update,
+    },
+    Check {
+        name: "hmac rfc4231 #1",
+        run: hmac_case_1,
+    },
+    Check {
+        name: "hmac rfc4231 #2",
+        run: hmac_case_2,
+    },
+    Check {
+        name: "hmac rfc4231 #6",
+        run: hmac_case_6,
+    },
+    Check {
+        name: "aes128 fips197",
+        run: aes128_block,
+    },
+    Check {
+        name: "aes128 ctr sp800-38a",
+        run: aes128_ctr,
+    },
+];
+
+// 依次运行所有的检查，返回第一个失败项的下标
+pub fn run_all() -> Result<(), usize> {
+    match CHECKS.iter().position(|check| !(check.run)()) {
+        Some(index) => Err(index),
+        None => Ok(()),
+    }
+}
+
+// 在编译期把十六进制字符串转换为字节数组，长度或字符不对时编译失败
+const fn hex<const N: usize>(text: &str) -> [u8; N] {
+    let text = text.as_bytes();
+    assert!(text.len() == N * 2, "hex string length mismatch");
+
+    const fn digit(c: u8) -> u8 {
+        match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            b'A'..=b'F' => c - b'A' + 10,
+            _ => panic!("invalid hex digit"),
+        }
+    }
+
+    let mut out = [0u8; N];
+    let mut index = 0;
+    while index < N {
+        out[index] = digit(text[2 * index]) << 4 | digit(text[2 * index + 1]);
+        index += 1;
+    }
+    out
+}
+
+// FIPS 180-4 的示例，以及空字符串
+fn sha256_empty() -> bool {
+    const DIGEST: [u8; 32] =
+        hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    sha256(b"") == DIGEST
+}
+
+fn sha256_abc() -> bool {
+    const DIGEST: [u8; 32] =
+        hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    sha256(b"abc") == DIGEST
+}
+
+// 56 字节，补位之后需要两个块
+const TWO_BLOCKS: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+const TWO_BLOCKS_DIGEST: [u8; 32] =
+    hex("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+
+fn sha256_two_blocks() -> bool {
+    sha256(TWO_BLOCKS) == TWO_BLOCKS_DIGEST
+}
+
+// 同样的数据按不规则的长度分多次输入，结果应该不变
+fn sha256_split_update() -> bool {
+    let mut hasher = Sha256::new();
+    for chunk in TWO_BLOCKS.chunks(7) {
+        hasher.update(chunk);
+    }
+    hasher.finalize() == TWO_BLOCKS_DIGEST
+}
+
+// RFC 4231 的第 1、2、6 组，第 6 组的密钥超过一个块
+fn hmac_case_1() -> bool {
+    const MAC: [u8; 32] = hex("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7");
+    hmac_sha256(&[0x0B; 20], b"Hi There") == MAC
+}
+
+fn hmac_case_2() -> bool {
+    const MAC: [u8; 32] = hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    let data = b"what do ya want for nothing?";
+    // 正确的 MAC 与截短的 MAC 都应该通过，改动一位之后应该不通过
+    let mut wrong = MAC;
+    wrong[31] ^= 0x01;
+    hmac_sha256(b"Jefe", data) == MAC
+        && hmac::verify(b"Jefe", data, &MAC)
+        && hmac::verify(b"Jefe", data, &MAC[..16])
+        && !hmac::verify(b"Jefe", data, &wrong)
+}
+
+fn hmac_case_6() -> bool {
+    const MAC: [u8; 32] = hex("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+    let data = b"Test Using Larger Than Block-Size Key - Hash Key First";
+    hmac_sha256(&[0xAA; 131], data) == MAC
+}
+
+// FIPS 197 附录 C.1
+fn aes128_block() -> bool {
+    const KEY: [u8; 16] = hex("000102030405060708090a0b0c0d0e0f");
+    const PLAIN: Block = hex("00112233445566778899aabbccddeeff");
+    const CIPHER: Block = hex("69c4e0d86a7b0430d8cdb78070b4c55a");
+    let mut block = PLAIN;
+    Aes128::new(&KEY).encrypt_block(&mut block);
+    block == CIPHER
+}
+
+// NIST SP 800-38A F.5.1，加密 4 个块；再把其中的 37 个字节分两次解密，检查计数器的衔接与不满一块的情况
+fn aes128_ctr() -> bool {
+    const KEY: [u8; 16] = hex("2b7e151628aed2a6abf7158809cf4f3c");
+    const COUNTER: Block = hex("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff");
+    const PLAIN: [u8; 64] = hex(concat!(
+        "6bc1bee22e409f96e93d7e117393172a",
+        "ae2d8a571e03ac9c9eb76fac45af8e51",
+        "30c81c46a35ce411e5fbc1191a0a52ef",
+        "f69f2445df4f9b17ad2b417be66c3710",
+    ));
+    const CIPHER: [u8; 64] = hex(concat!(
+        "874d6191b620e3261bef6864990db6ce",
+        "9806f66b7970fdff8617187bb9fffdff",
+        "5ae4df3edbd5d35e5b4f09020db03eab",
+        "1e031dda2fbe03d1792170a0f3009cee",
+    ));
+
+    let cipher = Aes128::new(&KEY);
+
+    let mut data = PLAIN;
+    cipher.apply_ctr(&COUNTER, &mut data);
+    if data != CIPHER {
+        return false;
+    }
+
+    let mut data = [0u8; 37];
+    data.copy_from_slice(&CIPHER[..37]);
+    let (first, second) = data.split_at_mut(16);
+    let next = cipher.apply_ctr(&COUNTER, first);
+    cipher.apply_ctr(&next, second);
+    data == PLAIN[..37]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_matches_fips_180_4() {
+        assert!(sha256_empty());
+        assert!(sha256_abc());
+        assert!(sha256_two_blocks());
+        assert!(sha256_split_update());
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        assert!(hmac_case_1());
+        assert!(hmac_case_2());
+        assert!(hmac_case_6());
+    }
+
+    #[test]
+    fn aes128_matches_fips_197_and_sp800_38a() {
+        assert!(aes128_block());
+        assert!(aes128_ctr());
+    }
+
+    #[test]
+    fn run_all_passes() {
+        assert_eq!(run_all(), Ok(()));
+    }
+
+    // 反过来确认这些比较真的能发现错误：输入改动一个字节，结果就应该对不上
+    #[test]
+    fn altered_input_does_not_match() {
+        assert_ne!(sha256(b"abd"), sha256(b"abc"));
+        assert_ne!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing!"),
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?")
+        );
+    }
+
+    // 截短的 MAC 即使内容正确，短于 MIN_MAC_LEN 时也不能通过，否则 1 个字节的 MAC 有 1/256 的机会被猜中
+    #[test]
+    fn short_mac_is_rejected() {
+        let data = b"what do ya want for nothing?";
+        let mac = hmac_sha256(b"Jefe", data);
+        assert!(hmac::verify(b"Jefe", data, &mac[..hmac::MIN_MAC_LEN]));
+        assert!(!hmac::verify(b"Jefe", data, &mac[..hmac::MIN_MAC_LEN - 1]));
+        assert!(!hmac::verify(b"Jefe", data, &mac[..1]));
+        assert!(!hmac::verify(b"Jefe", data, &[]));
+    }
+}
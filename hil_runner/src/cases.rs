@@ -0,0 +1,78 @@
+//! 要在开发板上运行的程序，以及判断它们通过与否的标记
+//!
+//! 每个程序的输出按行检查（RTT 的文本，或者 probe-rs 解码之后的 defmt 日志）：
+//! expect 中的每一个标记都在某一行中出现过，就算通过，不要求顺序；
+//! 任意一行包含 fail 中的标记，或者超时之前没有等到全部 expect，就算失败
+//!
+//! 标记只做子串匹配，改动这些程序的输出时，记得同步修改这里
+
+use std::time::Duration;
+
+pub struct Case {
+    // 所在的 package 与 bin 的名字，也就是 cargo build -p <package> --bin <bin>
+    pub package: &'static str,
+    pub bin: &'static str,
+    pub expect: &'static [&'static str],
+    pub fail: &'static [&'static str],
+    // 从开始烧写算起
+    pub timeout: Duration,
+    // 运行前需要的接线，只用于 --list 的输出
+    pub wiring: &'static str,
+}
+
+// panic-rtt-target 输出的 panic 信息以此开头
+const PANICKED: &str = "panicked at";
+
+pub const CASES: &[Case] = &[
+    Case {
+        package: "s08_dma",
+        bin: "s08c01_mem2mem_01polling",
+        // 注意程序中的拼写就是 Tranfer
+        expect: &["DMA2 STREAM0 Tranfer Complete", "dst_list end value"],
+        fail: &[PANICKED],
+        timeout: Duration::from_secs(20),
+        wiring: "none",
+    },
+    Case {
+        package: "s03_spi",
+        bin: "s03c04_spi_self_test",
+        expect: &["0 failed: PASS"],
+        fail: &["[FAIL]", PANICKED],
+        timeout: Duration::from_secs(30),
+        wiring: "SPI1 <-> SPI2 loopback, see s03c04",
+    },
+    Case {
+        package: "s04_i2c",
+        bin: "s04c05_i2c_self_test",
+        expect: &["0 failed: PASS"],
+        fail: &["[FAIL]", PANICKED],
+        timeout: Duration::from_secs(30),
+        wiring: "I2C1 <-> I2C3 loopback, see s04c05",
+    },
+    Case {
+        package: "s21_sensor",
+        bin: "s21c06_selftest",
+        expect: &["selftest:", "passed, PASS"],
+        fail: &["[FAIL]", PANICKED],
+        timeout: Duration::from_secs(30),
+        wiring: "sensor board, see s21c06",
+    },
+    Case {
+        package: "s21_sensor",
+        bin: "s21c21_async_drivers",
+        // 每 5 秒输出一次的统计
+        expect: &["async drivers", "i2c ok:"],
+        fail: &["i2c: ", "dma: ", PANICKED],
+        timeout: Duration::from_secs(20),
+        wiring: "BME280 on I2C1, see s21c21",
+    },
+    Case {
+        package: "s12_defmt",
+        bin: "s12c01_defmt_test",
+        // 这个程序最后会主动 panic，panic 信息本身就是预期的输出
+        expect: &["Hello world!", "info", "error", "panicking!"],
+        fail: &[],
+        timeout: Duration::from_secs(20),
+        wiring: "none",
+    },
+];
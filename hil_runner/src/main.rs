@@ -0,0 +1,126 @@
+//! 在开发板上自动运行示例程序，并根据输出判断是否正常
+//!
+//! 重构驱动之后，逐个烧写示例程序、盯着 RTT 的输出看，既费时又容易漏掉问题，
+//! 这个工具把 cases.rs 中列出的程序依次编译、用 probe-rs 烧写，检查输出中是否出现了预期的标记，最后给出汇总
+//!
+//! 用法：
+//!
+//! hil_runner [--chip CHIP] [--probe VID:PID] [--no-build] [名字...]
+//!     运行名字中包含任意一个给定字符串的程序，省略时运行全部，比如 hil_runner s08c01 selftest
+//! hil_runner --list
+//!     列出所有程序，以及它们需要的接线
+//!
+//! 芯片默认为 STM32F413VGTx，可以用 probe-rs chip list 查看其它型号的名字
+//! 每个程序的完整输出保存在 target/hil/<bin>.log 中，失败时还会打印最后几行
+//!
+//! 注意：一块开发板一次只能接一种线，需要不同接线的程序请分批运行
+
+use std::{env, process::ExitCode};
+
+mod cases;
+mod probe;
+
+use cases::CASES;
+use probe::{Options, Outcome};
+
+const USAGE: &str =
+    "usage: hil_runner [--chip CHIP] [--probe VID:PID] [--no-build] [NAME...]\n       hil_runner --list";
+
+const DEFAULT_CHIP: &str = "STM32F413VGTx";
+
+// 失败时打印的输出的行数
+const TAIL_LINES: usize = 10;
+
+fn main() -> ExitCode {
+    let mut options = Options {
+        chip: DEFAULT_CHIP.to_string(),
+        probe: None,
+        build: true,
+    };
+    let mut filters = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--list" => {
+                list();
+                return ExitCode::SUCCESS;
+            }
+            "--chip" | "--probe" => {
+                let Some(value) = args.next() else {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                };
+                if arg == "--chip" {
+                    options.chip = value;
+                } else {
+                    options.probe = Some(value);
+                }
+            }
+            "--no-build" => options.build = false,
+            _ if arg.starts_with("--") => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+            _ => filters.push(arg),
+        }
+    }
+
+    let selected: Vec<_> = CASES
+        .iter()
+        .filter(|case| filters.is_empty() || filters.iter().any(|f| case.bin.contains(f.as_str())))
+        .collect();
+    if selected.is_empty() {
+        eprintln!("no matching case, see hil_runner --list");
+        return ExitCode::FAILURE;
+    }
+
+    let mut failed = Vec::new();
+    for case in &selected {
+        eprintln!("==> {}", case.bin);
+        match probe::run(case, &options) {
+            Ok(report) => {
+                println!(
+                    "{:<32} {} ({:.1} s)",
+                    case.bin,
+                    report.outcome,
+                    report.elapsed.as_secs_f32()
+                );
+                if !matches!(report.outcome, Outcome::Pass) {
+                    for line in report.log.iter().rev().take(TAIL_LINES).rev() {
+                        println!("    | {}", line);
+                    }
+                    failed.push(case.bin);
+                }
+            }
+            Err(e) => {
+                println!("{:<32} ERROR: {}", case.bin, e);
+                failed.push(case.bin);
+            }
+        }
+    }
+
+    println!(
+        "\n{} passed, {} failed",
+        selected.len() - failed.len(),
+        failed.len()
+    );
+    if failed.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        println!("failed: {}", failed.join(", "));
+        ExitCode::FAILURE
+    }
+}
+
+fn list() {
+    for case in CASES {
+        println!(
+            "{:<32} {:<12} {}s  wiring: {}",
+            case.bin,
+            case.package,
+            case.timeout.as_secs(),
+            case.wiring
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! 编译、烧写一个程序，并检查它的输出
+//!
+//! 烧写与读取输出都由 probe-rs run 完成：它烧写 ELF、复位 MCU，之后一直把 RTT 的内容输出到 stdout，
+//! 如果 ELF 中带有 defmt 的信息，输出的是已经解码的日志；它自己的进度信息则输出到 stderr
+//! probe-rs run 不会自己退出（除非 MCU 停在了断点上，比如 panic-probe），因此判定出结果之后，由这里结束它
+//!
+//! stdout 与 stderr 各由一个线程按行读取，统一送到 channel 中，这样主线程可以用 recv_timeout 处理超时
+
+use std::{
+    env,
+    fmt::{self, Display},
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::cases::Case;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// MCU 程序的编译目标，与 .cargo/config.toml 中的相同
+const TARGET: &str = "thumbv7em-none-eabihf";
+
+pub struct Options {
+    pub chip: String,
+    // 接了多个调试器时，用 VID:PID 或 VID:PID:序列号 指定其中一个
+    pub probe: Option<String>,
+    pub build: bool,
+}
+
+pub enum Outcome {
+    Pass,
+    // 包含 fail 标记的那一行
+    FailMarker(String),
+    // 超时时还没有出现的 expect 标记
+    Timeout(Vec<&'static str>),
+    // probe-rs 提前退出，通常是没有连接调试器，或者烧写失败
+    Exited(Vec<&'static str>),
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Pass => write!(f, "PASS"),
+            Outcome::FailMarker(line) => write!(f, "FAIL: {}", line),
+            Outcome::Timeout(missing) => write!(f, "FAIL: timeout, missing {:?}", missing),
+            Outcome::Exited(missing) => write!(f, "FAIL: probe-rs exited, missing {:?}", missing),
+        }
+    }
+}
+
+pub struct Report {
+    pub outcome: Outcome,
+    pub elapsed: Duration,
+    // 完整的输出，也会保存到 target/hil/<bin>.log
+    pub log: Vec<String>,
+}
+
+// workspace 的根目录，也就是本 crate 的上一级
+fn workspace_root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap()
+}
+
+fn target_dir() -> PathBuf {
+    env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| workspace_root().join("target"))
+}
+
+// 在 workspace 的根目录下编译，这样 .cargo/config.toml 中的默认目标与链接参数都会生效
+fn build(case: &Case) -> Result<PathBuf> {
+    let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".into()))
+        .current_dir(workspace_root())
+        .args([
+            "build",
+            "-p",
+            case.package,
+            "--bin",
+            case.bin,
+            "--target",
+            TARGET,
+        ])
+        .status()?;
+    if !status.success() {
+        return Err(format!("cargo build failed for {}", case.bin).into());
+    }
+    Ok(target_dir().join(TARGET).join("debug").join(case.bin))
+}
+
+// 按行读取，不是 UTF-8 的部分替换为 U+FFFD
+fn forward_lines(input: impl Read + Send + 'static, tx: mpsc::Sender<String>) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(input);
+        let mut buf = Vec::new();
+        while let Ok(len) = reader.read_until(b'\n', &mut buf) {
+            if len == 0 {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buf);
+            if tx.send(line.trim_end().to_string()).is_err() {
+                break;
+            }
+            buf.clear();
+        }
+    });
+}
+
+pub fn run(case: &Case, options: &Options) -> Result<Report> {
+    let elf = if options.build {
+        build(case)?
+    } else {
+        target_dir().join(TARGET).join("debug").join(case.bin)
+    };
+    if !elf.exists() {
+        return Err(format!("{} not found, build it first", elf.display()).into());
+    }
+
+    let mut command = Command::new("probe-rs");
+    command.args(["run", "--chip", &options.chip]);
+    if let Some(probe) = &options.probe {
+        command.args(["--probe", probe]);
+    }
+    let mut child = command
+        .arg(&elf)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to start probe-rs: {}", e))?;
+
+    let (tx, rx) = mpsc::channel();
+    forward_lines(child.stdout.take().unwrap(), tx.clone());
+    forward_lines(child.stderr.take().unwrap(), tx);
+
+    let start = Instant::now();
+    let mut seen = vec![false; case.expect.len()];
+    let mut log = Vec::new();
+    let missing = |seen: &[bool]| {
+        case.expect
+            .iter()
+            .zip(seen)
+            .filter(|(_, &seen)| !seen)
+            .map(|(marker, _)| *marker)
+            .collect::<Vec<_>>()
+    };
+
+    let outcome = loop {
+        let remaining = case.timeout.saturating_sub(start.elapsed());
+        let line = match rx.recv_timeout(remaining) {
+            Ok(line) => line,
+            Err(mpsc::RecvTimeoutError::Timeout) => break Outcome::Timeout(missing(&seen)),
+            // 两个线程都结束了，说明 probe-rs 已经退出
+            Err(mpsc::RecvTimeoutError::Disconnected) => break Outcome::Exited(missing(&seen)),
+        };
+
+        if case.fail.iter().any(|marker| line.contains(marker)) {
+            log.push(line.clone());
+            break Outcome::FailMarker(line);
+        }
+        for (marker, seen) in case.expect.iter().zip(seen.iter_mut()) {
+            *seen |= line.contains(marker);
+        }
+        log.push(line);
+
+        if seen.iter().all(|&seen| seen) {
+            break Outcome::Pass;
+        }
+    };
+    let elapsed = start.elapsed();
+
+    // probe-rs 可能已经退出了，此时 kill 会返回错误，忽略即可
+    child.kill().ok();
+    child.wait()?;
+
+    save_log(case, &log)?;
+    Ok(Report {
+        outcome,
+        elapsed,
+        log,
+    })
+}
+
+fn save_log(case: &Case, log: &[String]) -> Result<()> {
+    let dir = target_dir().join("hil");
+    fs::create_dir_all(&dir)?;
+    let mut file = fs::File::create(dir.join(format!("{}.log", case.bin)))?;
+    for line in log {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! I2C 的 7 位/10 位地址，以及从机的双地址
+//!
+//! 7 位地址：SB 之后，主机只需要发送一个字节，高 7 位是地址，最低位是读写位
+//!
+//! 10 位地址：SB 之后，主机首先发送一个“头字节”（header），格式为 11110 A9 A8 R/W，
+//! 这就是为什么 7 位地址不能使用 11110XX 的原因，之后主机再发送地址的低 8 位 A7~A0
+//! 在 STM32 上，头字节发送完成后 SR1 的 ADD10 会被挂起，此时写入低 8 位，才会进入常规的 ADDR 流程
+//!
+//! 10 位地址下，主机想要读取的话，必须先以写的方式发送完整的 10 位地址，
+//! 然后产生一个 Repeated START，再发送一个读方向的头字节（此时不需要再发送低 8 位）
+//!
+//! 双地址：从机除了 OAR1 中的地址，还可以在 OAR2 中设置第二个 7 位地址，这样一个 I2C 外设就可以同时响应两个地址
+//! 需要注意的是，双地址模式仅在 OAR1 为 7 位地址时有效
+//! 匹配之后，SR2 中的 DUALF 会指示，匹配的是 OAR1 还是 OAR2
+
+use stm32f4xx_hal::pac::i2c1::RegisterBlock;
+
+// 10 位地址的头字节的固定部分 11110XX0
+const TEN_BIT_HEADER: u8 = 0b1111_0000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub enum I2cAddress {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+impl I2cAddress {
+    // SB 之后要写入 DR 的第一个字节
+    //
+    // 7 位地址下，就是地址加上读写位
+    // 10 位地址下，就是头字节，其中包含了地址的最高 2 位以及读写位
+    pub fn first_byte(self, read: bool) -> u8 {
+        match self {
+            Self::SevenBit(addr) => addr << 1 | read as u8,
+            Self::TenBit(addr) => TEN_BIT_HEADER | ((addr >> 8) as u8 & 0b11) << 1 | read as u8,
+        }
+    }
+
+    // ADD10 挂起之后要写入 DR 的第二个字节，仅 10 位地址有
+    pub fn second_byte(self) -> Option<u8> {
+        match self {
+            Self::SevenBit(_) => None,
+            Self::TenBit(addr) => Some(addr as u8),
+        }
+    }
+
+    // 地址本身是否合法
+    //
+    // 7 位地址中 0000XXX 与 1111XXX 为保留地址，其中 11110XX 留给了 10 位地址的头字节
+    pub fn is_valid(self) -> bool {
+        match self {
+            Self::SevenBit(addr) => (0b000_1000..=0b111_0111).contains(&addr),
+            Self::TenBit(addr) => addr < (1 << 10),
+        }
+    }
+
+    // 把地址写入 OAR1，作为从机自己的主地址
+    //
+    // 注意，修改 OAR1 时 I2C 外设最好处于关闭（PE = 0）的状态
+    pub fn set_as_own_address(self, i2c: &RegisterBlock) {
+        i2c.oar1.modify(|_, w| {
+            match self {
+                Self::SevenBit(addr) => {
+                    w.addmode().add7();
+                    // 7 位模式下，地址位于 ADD 的 第 7 位到 第 1 位
+                    w.add().bits((addr as u16) << 1);
+                }
+                Self::TenBit(addr) => {
+                    w.addmode().add10();
+                    // 10 位模式下，地址占满 ADD 的 第 9 位到 第 0 位
+                    w.add().bits(addr);
+                }
+            }
+            w
+        });
+    }
+}
+
+// 设置从机的第二地址，传入 None 则关闭双地址模式
+//
+// OAR2 只能设置 7 位地址，且 OAR1 也必须是 7 位地址
+pub fn set_dual_address(i2c: &RegisterBlock, addr2: Option<u8>) {
+    match addr2 {
+        Some(addr) => i2c.oar2.write(|w| {
+            // ADD2 字段本身就是从第 1 位开始的，因此这里不需要左移
+            w.add2().bits(addr);
+            // ENDUAL: ENable DUAL addressing mode
+            w.endual().set_bit();
+            w
+        }),
+        None => i2c.oar2.write(|w| w.endual().clear_bit()),
+    }
+}
+
+// 从机被哪个地址匹配上了
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub enum MatchedAddress {
+    // OAR1
+    Primary,
+    // OAR2
+    Secondary,
+}
+
+// 必须在清理 ADDR 之前（或者与清理 ADDR 时读取的 SR2 一起）判断，
+// 因为 DUALF 会在 STOP condition 或 Repeated START 之后被硬件清除
+pub fn matched_address(sr2_bits: u32) -> MatchedAddress {
+    // DUALF 位于 SR2 的第 7 位
+    if sr2_bits & (1 << 7) != 0 {
+        MatchedAddress::Secondary
+    } else {
+        MatchedAddress::Primary
+    }
+}
@@ -0,0 +1,216 @@
+//! 以轮询的方式，让 I2C 作为主机收发数据
+//!
+//! 与 s04c01 中基于中断的流程相同，只不过这里是在主循环中等待各个标识位，
+//! 同时额外处理了 10 位地址需要的 ADD10 标识位
+
+use stm32f4xx_hal::pac::i2c1::RegisterBlock;
+
+use super::addressing::I2cAddress;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub enum MasterError {
+    // 地址或数据没有被 ACK
+    Nack,
+    // 多主机竞争时，失去了总线
+    ArbitrationLost,
+    // 总线上出现了错位的 START/STOP condition
+    Bus,
+    // SMBus 模式下，SCL 被拉低的时间超过了 25 ms，见 s04 的 utils::smbus
+    Timeout,
+}
+
+// 检查 SR1 中的错误位，出错时清理错误位，并释放总线
+pub fn check_error(i2c: &RegisterBlock) -> Result<(), MasterError> {
+    let sr1 = i2c.sr1.read();
+
+    let error = if sr1.af().bit_is_set() {
+        // AF: Acknowledge Failure
+        // 被 NACK 之后，需要主机自己产生 STOP condition
+        i2c.cr1.modify(|_, w| w.stop().stop());
+        Some(MasterError::Nack)
+    } else if sr1.arlo().bit_is_set() {
+        // ARLO: ARbitration LOst
+        // 失去总线之后，硬件会自动退回到从机模式，不需要产生 STOP condition
+        Some(MasterError::ArbitrationLost)
+    } else if sr1.berr().bit_is_set() {
+        Some(MasterError::Bus)
+    } else if sr1.timeout().bit_is_set() {
+        // TIMEOUT 仅在 SMBus 模式下会被挂起，硬件在挂起它的同时会自动释放总线
+        Some(MasterError::Timeout)
+    } else {
+        None
+    };
+
+    match error {
+        Some(e) => {
+            // 错误位是通过向其写 0 来清理的
+            i2c.sr1.modify(|_, w| {
+                w.af().clear_bit();
+                w.arlo().clear_bit();
+                w.berr().clear_bit();
+                w.timeout().clear_bit();
+                w
+            });
+            Err(e)
+        }
+        None => Ok(()),
+    }
+}
+
+// 产生 START（或 Repeated START），并发送地址
+//
+// 返回时 ADDR 已经被清理，read 为 true 且只接收 1 个字节时，需要在清理 ADDR 之前关闭 ACK，
+// 因此这里把清理 ADDR 之前要做的事情交给调用者
+fn start_and_address(
+    i2c: &RegisterBlock,
+    addr: I2cAddress,
+    read: bool,
+    before_clear_addr: impl FnOnce(),
+) -> Result<(), MasterError> {
+    i2c.cr1.modify(|_, w| w.start().start());
+    while i2c.sr1.read().sb().is_no_start() {}
+
+    // 读 SR1 之后写 DR，以清理 SB
+    // 10 位地址下，总是先以写的方向发送头字节
+    i2c.dr.write(|w| {
+        w.dr()
+            .bits(addr.first_byte(read && addr.second_byte().is_none()))
+    });
+
+    if let Some(second_byte) = addr.second_byte() {
+        // 等待头字节发送完成
+        loop {
+            check_error(i2c)?;
+            if i2c.sr1.read().add10().bit_is_set() {
+                break;
+            }
+        }
+
+        // ADD10 的清理方法为，读 SR1 之后写 DR
+        i2c.dr.write(|w| w.dr().bits(second_byte));
+
+        if read {
+            // 10 位地址下的读取，需要先以写的方向完成整个地址的发送
+            loop {
+                check_error(i2c)?;
+                if i2c.sr1.read().addr().is_match() {
+                    break;
+                }
+            }
+            i2c.sr1.read();
+            i2c.sr2.read();
+
+            // 然后再产生一个 Repeated START，并发送读方向的头字节，此时不需要再发送低 8 位地址
+            i2c.cr1.modify(|_, w| w.start().start());
+            while i2c.sr1.read().sb().is_no_start() {}
+            i2c.dr.write(|w| w.dr().bits(addr.first_byte(true)));
+        }
+    }
+
+    loop {
+        check_error(i2c)?;
+        if i2c.sr1.read().addr().is_match() {
+            break;
+        }
+    }
+
+    before_clear_addr();
+
+    // 读 SR1 之后读 SR2，以清理 ADDR
+    i2c.sr1.read();
+    i2c.sr2.read();
+
+    Ok(())
+}
+
+pub fn write(i2c: &RegisterBlock, addr: I2cAddress, data: &[u8]) -> Result<(), MasterError> {
+    write_without_stop(i2c, addr, data)?;
+    i2c.cr1.modify(|_, w| w.stop().stop());
+    Ok(())
+}
+
+// 先写后读，两者之间使用 Repeated START 而非 STOP condition 衔接
+// 常见于“先写寄存器地址，再读寄存器内容”的场景
+pub fn write_read(
+    i2c: &RegisterBlock,
+    addr: I2cAddress,
+    data: &[u8],
+    buf: &mut [u8],
+) -> Result<(), MasterError> {
+    write_without_stop(i2c, addr, data)?;
+    read(i2c, addr, buf)
+}
+
+fn write_without_stop(
+    i2c: &RegisterBlock,
+    addr: I2cAddress,
+    data: &[u8],
+) -> Result<(), MasterError> {
+    start_and_address(i2c, addr, false, || {})?;
+
+    for &byte in data {
+        loop {
+            check_error(i2c)?;
+            if i2c.sr1.read().tx_e().is_empty() {
+                break;
+            }
+        }
+        i2c.dr.write(|w| w.dr().bits(byte));
+    }
+
+    // 等待最后一个字节真正发送完成
+    // BTF: Byte Transfer Finished
+    loop {
+        check_error(i2c)?;
+        if i2c.sr1.read().btf().bit_is_set() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read(
+    i2c: &RegisterBlock,
+    addr: I2cAddress,
+    buf: &mut [u8],
+) -> Result<(), MasterError> {
+    let len = buf.len();
+    if len == 0 {
+        return Ok(());
+    }
+
+    // 只接收 1 个字节时，必须在清理 ADDR 之前就关闭 ACK，
+    // 否则清理 ADDR 之后硬件立刻开始接收，我们就来不及 NACK 这唯一的字节了
+    start_and_address(i2c, addr, true, || {
+        if len == 1 {
+            i2c.cr1.modify(|_, w| w.ack().nak());
+        } else {
+            i2c.cr1.modify(|_, w| w.ack().ack());
+        }
+    })?;
+
+    for (index, byte) in buf.iter_mut().enumerate() {
+        // 最后一个字节要以 NACK 回复，并在其后产生 STOP condition
+        // 这里是在读取倒数第二个字节之后立刻设置的，此时最后一个字节还在接收中，
+        // 只要不被更高优先级的中断打断太久，ACK 位的修改就会在最后一个字节的第 9 个时钟之前生效
+        if index == len - 1 {
+            i2c.cr1.modify(|_, w| {
+                w.ack().nak();
+                w.stop().stop();
+                w
+            });
+        }
+
+        loop {
+            check_error(i2c)?;
+            if i2c.sr1.read().rx_ne().is_not_empty() {
+                break;
+            }
+        }
+        *byte = i2c.dr.read().dr().bits();
+    }
+
+    Ok(())
+}
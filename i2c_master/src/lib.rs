@@ -0,0 +1,12 @@
+//! 以轮询的方式使用 I2C 主机，以及 7 位/10 位地址
+//!
+//! 这两个模块最早写在 s04 中，之后 s07 的 DS1307、s13 的 I2C 扫描、s21 的各个传感器驱动都要用到，
+//! 原来是每章各复制一份，现在只保留这里的一份，各章在 utils/mod.rs 中用 pub(crate) use 引入，
+//! 原来的 utils::blocking_master 这样的路径保持不变
+//!
+//! 与 chip_caps 一样，芯片由各章转发过来的 stm32f401 / stm32f411 / stm32f412 / stm32f413 特性选择
+
+#![no_std]
+
+pub mod addressing;
+pub mod blocking_master;
@@ -0,0 +1,103 @@
+//! 为 s14c04 的 bootloader 生成与检查带签名的固件镜像
+//!
+//! 用法：
+//!
+//! image_tool sign --key <HEX> --version <N> [--security-version <N>] <app.bin> <app.img>
+//!     为 app.bin（objcopy -O binary 得到的纯二进制文件）加上头部，写入 app.img，安全版本号默认为 0
+//! image_tool verify --key <HEX> <app.img>
+//!     检查 app.img 的头部与 MAC，打印版本号
+//!
+//! 密钥是 64 个十六进制字符（32 字节），必须与 bootloader 中的 IMAGE_KEY 相同
+//! 头部的格式见 crypto_core::image
+
+use std::{env, fs, process::ExitCode};
+
+use crypto_core::image::{self, ImageHeader, HEADER_LEN};
+
+const KEY_LEN: usize = 32;
+
+const USAGE: &str = "usage: image_tool sign --key HEX --version N [--security-version N] IN OUT\n       image_tool verify --key HEX FILE";
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args[..] {
+        ["sign", "--key", key, "--version", version, ref rest @ ..] => match rest {
+            [input, output] => sign(key, version, "0", input, output),
+            ["--security-version", security_version, input, output] => {
+                sign(key, version, security_version, input, output)
+            }
+            _ => usage(),
+        },
+        ["verify", "--key", key, path] => verify(key, path),
+        _ => usage(),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn usage() -> Result<()> {
+    Err(USAGE.into())
+}
+
+fn parse_key(text: &str) -> Result<[u8; KEY_LEN]> {
+    if text.len() != KEY_LEN * 2 {
+        return Err(format!("key must be {} hex digits", KEY_LEN * 2).into());
+    }
+    let mut key = [0u8; KEY_LEN];
+    for (byte, index) in key.iter_mut().zip((0..text.len()).step_by(2)) {
+        *byte = u8::from_str_radix(&text[index..index + 2], 16)
+            .map_err(|_| format!("invalid hex in key: {}", text))?;
+    }
+    Ok(key)
+}
+
+fn sign(key: &str, version: &str, security_version: &str, input: &str, output: &str) -> Result<()> {
+    let key = parse_key(key)?;
+    let version: u32 = version.parse()?;
+    let security_version: u32 = security_version.parse()?;
+
+    let app = fs::read(input)?;
+    let header = image::sign(&key, version, security_version, &app)
+        .map_err(|e| format!("cannot sign {}: {:?}", input, e))?;
+
+    let mut out = Vec::with_capacity(HEADER_LEN + app.len());
+    out.extend_from_slice(&header);
+    out.extend_from_slice(&app);
+    fs::write(output, &out)?;
+
+    eprintln!(
+        "{}: {} bytes, version {}, security version {}",
+        output,
+        app.len(),
+        version,
+        security_version
+    );
+    Ok(())
+}
+
+fn verify(key: &str, path: &str) -> Result<()> {
+    let key = parse_key(key)?;
+    let slot = fs::read(path)?;
+
+    let ImageHeader {
+        image_len,
+        version,
+        security_version,
+    } = image::verify(&key, &slot).map_err(|e| format!("{}: {:?}", path, e))?;
+
+    println!(
+        "{}: OK, {} bytes, version {}, security version {}",
+        path, image_len, version, security_version
+    );
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use super::pins::Core;
+
+#[cfg(not(feature = "mock"))]
+pub fn delay(cp: &Core, micro_sec: u32) {
+    unsafe {
+        cp.SYST.rvr.write(micro_sec);
+        cp.SYST.csr.modify(|_data| 1);
+
+        while cp.SYST.csr.read().checked_shr(16).unwrap() & 1 == 0 {}
+    };
+}
+
+// 模拟的 LCD1602 没有 SysTick，延时就是推进模拟的时间，见 mock
+#[cfg(feature = "mock")]
+pub fn delay(cp: &Core, micro_sec: u32) {
+    cp.advance_us(micro_sec as u64);
+}
+
+// pins::init_with 中用到的延时
+//
+// s11 用的是上面的 delay，也就是 SysTick；SysTick 另有用途的程序（比如 s21c14 把它用作 1 ms 的中断），可以换成别的计时方式
+pub trait DelayUs {
+    fn delay_us(&self, micro_sec: u32);
+}
+
+impl DelayUs for Core {
+    fn delay_us(&self, micro_sec: u32) {
+        delay(self, micro_sec);
+    }
+}
@@ -0,0 +1,25 @@
+//! 以 PAC 驱动 LCD1602
+//!
+//! 这些模块最早写在 s11 中，之后 s21 的传感器程序也要用 LCD1602 显示读数，原来 s21 另写了一份只写不读的精简驱动，
+//! 现在只保留这里的一份，s11 在 utils/mod.rs 中用 pub(crate) use 引入，原来的 utils::pins 这样的路径保持不变
+//!
+//! 收发函数只通过 pins::PinIo 操作引脚，s11 的接法是 pac::Peripherals 上的 GPIOA/GPIOB，
+//! s21 还有一种经过 74HC595 背板的接法，背板只能输出，读不回 busy flag，见 PinIo::READABLE
+//!
+//! 与 chip_caps 一样，芯片由各章转发过来的 stm32f401 / stm32f411 / stm32f412 / stm32f413 特性选择
+//!
+//! 启用 mock 特性时，pins::Port 与 pins::Core 换成了 mock 中模拟的 LCD1602，
+//! pins、mode_4pin、mode_8pin、readback、widgets 中的代码不需要任何修改就能在 PC 上运行，测试见 src/mock/tests.rs：
+//!
+//! cargo test -p lcd1602 --features stm32f413,mock --target x86_64-unknown-linux-gnu
+
+#![cfg_attr(not(feature = "mock"), no_std)]
+
+pub mod common;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod mode_4pin;
+pub mod mode_8pin;
+pub mod pins;
+pub mod readback;
+pub mod widgets;
@@ -0,0 +1,157 @@
+//! 在 PC 上代替 GPIO 与 SysTick 的 LCD1602，只在启用 mock 特性时编译，用法见 src/lib.rs
+//!
+//! Lcd 实现了 pins::PinIo，记录 RS、RW、E 与数据线的电平，并在 E 的边沿驱动 st7066u 中模拟的控制器：
+//!
+//! - E 上升沿且 RW = 1：控制器把数据输出到数据线上，这时 MCU 一侧接到 LCD 的数据线必须已经切换为输入，否则记为一次总线冲突
+//! - E 下降沿且 RW = 0：控制器锁存数据线上的电平，这时接到 LCD 的数据线必须是输出，否则数据线是悬空的，同样记为一次总线冲突
+//!
+//! 每个 E 脉冲推进 1 us 的模拟时间，common::delay 推进指定的时间，这样 busy flag 的轮询与初始化中的延时都与实际的时序一致
+//!
+//! 接法与 MCU 上相同：8 线接法时 D0~D7 接在 PB0~PB7 上，4 线接法时 D4~D7 接在 PB4~PB7 上，
+//! 此时 PB0~PB3 没有接到 LCD，读出的总是 1，收发函数没有把它们屏蔽掉时，读回的数据就会出错
+//!
+//! 另外可以用 break_lines 模拟虚接的数据线，检查 readback 能否发现问题
+
+use core::cell::{Ref, RefCell};
+
+use super::pins::PinIo;
+
+mod st7066u;
+#[cfg(test)]
+mod tests;
+
+use st7066u::St7066u;
+
+// 两种接法下接到 LCD 的数据线，对应 Pins<4> 与 Pins<8>
+pub const FOUR_PIN: u8 = 0b1111_0000;
+pub const EIGHT_PIN: u8 = 0b1111_1111;
+
+// 每个 E 脉冲大约耗费的时间，GPIO 翻转几次也就是 1 us 左右
+const STROBE_US: u64 = 1;
+
+struct State {
+    chip: St7066u,
+    rs: bool,
+    rw: bool,
+    e: bool,
+    // MCU 在数据线上输出的电平
+    odr: u8,
+    // 为 1 的数据线是输入
+    input: u8,
+    // 最近一次读取时，LCD 输出到数据线上的电平
+    lcd_out: u8,
+    // 虚接的数据线，两端看到的都是低电平（setup_gpio 为数据线开启了下拉）
+    broken: u8,
+    bus_conflicts: u32,
+}
+
+pub struct Lcd {
+    // 接到 LCD 的数据线
+    wired: u8,
+    // PinIo 的方法都只拿到 &self，与 pac::Peripherals 一样，因此内部的状态放在 RefCell 中
+    state: RefCell<State>,
+}
+
+impl Lcd {
+    // 刚上电的 LCD，GPIO 已经按照 setup_gpio 配置好了：接到 LCD 的数据线为输出，其余的 PB 引脚为输入
+    pub fn new(wired: u8) -> Self {
+        Self {
+            wired,
+            state: RefCell::new(State {
+                chip: St7066u::power_on(),
+                rs: false,
+                rw: false,
+                e: false,
+                odr: 0,
+                input: !wired,
+                lcd_out: 0,
+                broken: 0,
+                bus_conflicts: 0,
+            }),
+        }
+    }
+
+    // 控制器的内部状态：DDRAM、CGRAM、AC、各个模式位，以及在忙碌时收到的指令
+    pub fn chip(&self) -> Ref<'_, St7066u> {
+        Ref::map(self.state.borrow(), |state| &state.chip)
+    }
+
+    pub fn bus_conflicts(&self) -> u32 {
+        self.state.borrow().bus_conflicts
+    }
+
+    // 让 mask 中的数据线虚接
+    pub fn break_lines(&self, mask: u8) {
+        self.state.borrow_mut().broken = mask & self.wired;
+    }
+
+    pub fn advance_us(&self, us: u64) {
+        self.state.borrow_mut().chip.advance_us(us);
+    }
+}
+
+impl PinIo for Lcd {
+    fn set_rs(&self, high: bool) {
+        self.state.borrow_mut().rs = high;
+    }
+
+    fn set_rw(&self, high: bool) {
+        self.state.borrow_mut().rw = high;
+    }
+
+    fn set_e(&self, high: bool) {
+        let mut state = self.state.borrow_mut();
+        let driven_by_mcu = self.wired & !state.input;
+        let rs = state.rs;
+
+        match (state.e, high, state.rw) {
+            (false, true, true) => {
+                state.chip.advance_us(STROBE_US);
+                if driven_by_mcu != 0 {
+                    state.bus_conflicts += 1;
+                }
+                state.lcd_out = state.chip.strobe_read(rs);
+            }
+            (false, true, false) => state.chip.advance_us(STROBE_US),
+            (true, false, false) => {
+                if driven_by_mcu != self.wired {
+                    state.bus_conflicts += 1;
+                }
+                let bus = state.odr & self.wired & !state.broken;
+                state.chip.strobe_write(rs, bus);
+            }
+            _ => {}
+        }
+
+        state.e = high;
+    }
+
+    fn write_dbus(&self, mask: u8, data: u8) {
+        let mut state = self.state.borrow_mut();
+        state.odr = (state.odr & !mask) | (data & mask);
+    }
+
+    fn read_dbus(&self) -> u8 {
+        let state = self.state.borrow();
+        // 输出的引脚读到的是自己输出的电平
+        let output = state.odr & !state.input;
+        // 接到 LCD 的输入引脚，只有 E 为高、RW 为 1 时才由 LCD 驱动，其余时候被下拉为低电平
+        let from_lcd = if state.e && state.rw {
+            state.lcd_out & self.wired & state.input & !state.broken
+        } else {
+            0
+        };
+        // 没有接到 LCD 的输入引脚是悬空的，这里总是读出 1
+        let floating = !self.wired & state.input;
+        output | from_lcd | floating
+    }
+
+    fn set_dbus_input(&self, mask: u8, input: bool) {
+        let mut state = self.state.borrow_mut();
+        if input {
+            state.input |= mask;
+        } else {
+            state.input &= !mask;
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! ST7066U 控制器的模拟
+//!
+//! 这里以 E 引脚的一次脉冲为单位：每次调用 strobe_write/strobe_read，相当于 MCU 拉高再拉低一次 E 引脚
+//! 4 bit 模式下，一次脉冲只传输 D4~D7 这 4 位，两次脉冲才组成一个完整的字节，先高后低
+//!
+//! 时间由调用者通过 advance_us 推进，每条指令执行期间 busy flag 为 1，
+//! 这期间写入的指令会被真实的芯片忽略，模拟器也会忽略它，并把它记录到 violations 中
+//!
+//! 引脚的电平如何变成一次脉冲，见 mock 的 Lcd
+
+// 2 行模式下，每行 40 个字节的 DDRAM
+const ROW_LEN: usize = 40;
+
+// 执行时间，见 ST7066U datasheet 的 Instruction Table
+const EXEC_US_CLEAR_HOME: u64 = 1520;
+const EXEC_US_NORMAL: u64 = 37;
+// 写入/读取 RAM 之后，还需要额外的 4 us 来更新地址计数器
+const EXEC_US_RAM: u64 = 37 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddrTarget {
+    Ddram,
+    Cgram,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Violation {
+    pub at_us: u64,
+    pub rs: bool,
+    pub data: u8,
+}
+
+pub struct St7066u {
+    pub ddram: [u8; ROW_LEN * 2],
+    pub cgram: [u8; 64],
+
+    pub ac: u8,
+    pub target: AddrTarget,
+
+    // Entry Mode Set
+    pub increment: bool,
+    pub shift_on_write: bool,
+
+    // Display ON/OFF Control
+    pub display_on: bool,
+    pub cursor_on: bool,
+    pub blink_on: bool,
+
+    // Function Set
+    pub eight_bit: bool,
+    pub two_line: bool,
+    pub font_5x10: bool,
+
+    // 显示窗口相对于 DDRAM 的偏移，每次左移显示时加 1
+    pub display_offset: u8,
+
+    // 4 bit 模式下，已经收到的高半字节
+    pending_nibble: Option<u8>,
+    // 4 bit 模式下读取时，已经返回了高半字节的那个字节，下一次脉冲返回它的低半字节
+    pending_read: Option<u8>,
+
+    now_us: u64,
+    busy_until_us: u64,
+
+    pub violations: Vec<Violation>,
+}
+
+impl St7066u {
+    // 上电后的状态，见 datasheet 的 Initializing by Internal Reset Circuit
+    pub fn power_on() -> Self {
+        Self {
+            ddram: [b' '; ROW_LEN * 2],
+            cgram: [0; 64],
+            ac: 0,
+            target: AddrTarget::Ddram,
+            increment: true,
+            shift_on_write: false,
+            display_on: false,
+            cursor_on: false,
+            blink_on: false,
+            eight_bit: true,
+            two_line: false,
+            font_5x10: false,
+            display_offset: 0,
+            pending_nibble: None,
+            pending_read: None,
+            now_us: 0,
+            // 上电后内部复位需要大约 40 ms，期间是忙碌的
+            busy_until_us: 40_000,
+            violations: Vec::new(),
+        }
+    }
+
+    pub fn advance_us(&mut self, us: u64) {
+        self.now_us += us;
+    }
+
+    pub fn now_us(&self) -> u64 {
+        self.now_us
+    }
+
+    pub fn busy(&self) -> bool {
+        self.now_us < self.busy_until_us
+    }
+
+    // 一次 RW = 0 的 E 脉冲，bus 的第 n 位对应 Dn 引脚上的电平
+    // 4 bit 模式下只有 D4~D7 会被读取，D0~D3 可以悬空
+    pub fn strobe_write(&mut self, rs: bool, bus: u8) {
+        let byte = if self.eight_bit {
+            bus
+        } else {
+            match self.pending_nibble.take() {
+                None => {
+                    self.pending_nibble = Some(bus >> 4);
+                    return;
+                }
+                Some(high) => (high << 4) | (bus >> 4),
+            }
+        };
+
+        if self.busy() {
+            self.violations.push(Violation {
+                at_us: self.now_us,
+                rs,
+                data: byte,
+            });
+            return;
+        }
+
+        if rs {
+            self.write_ram(byte);
+        } else {
+            self.instruction(byte);
+        }
+    }
+
+    // 一次 RW = 1 的 E 脉冲，返回 D0~D7 上的电平，4 bit 模式下只有 D4~D7 有效
+    pub fn strobe_read(&mut self, rs: bool) -> u8 {
+        if !self.eight_bit {
+            if let Some(byte) = self.pending_read.take() {
+                return byte << 4;
+            }
+        }
+
+        let byte = if rs {
+            self.read_ram()
+        } else {
+            ((self.busy() as u8) << 7) | (self.ac & 0x7F)
+        };
+
+        if self.eight_bit {
+            byte
+        } else {
+            self.pending_read = Some(byte);
+            byte & 0xF0
+        }
+    }
+
+    // 把 DDRAM 地址换算为数组下标
+    fn ddram_index(addr: u8) -> Option<usize> {
+        match addr {
+            0x00..=0x27 => Some(addr as usize),
+            0x40..=0x67 => Some(addr as usize - 0x40 + ROW_LEN),
+            _ => None,
+        }
+    }
+
+    fn move_ac(&mut self) {
+        self.ac = match (self.target, self.increment) {
+            (AddrTarget::Cgram, true) => (self.ac + 1) & 0x3F,
+            (AddrTarget::Cgram, false) => self.ac.wrapping_sub(1) & 0x3F,
+            // 2 行模式下，第一行的末尾会跳到第二行的开头，第二行的末尾会跳回第一行的开头
+            (AddrTarget::Ddram, true) => match self.ac {
+                0x27 if self.two_line => 0x40,
+                0x67 => 0x00,
+                0x4F if !self.two_line => 0x00,
+                ac => ac + 1,
+            },
+            (AddrTarget::Ddram, false) => match self.ac {
+                0x40 if self.two_line => 0x27,
+                0x00 if self.two_line => 0x67,
+                0x00 => 0x4F,
+                ac => ac - 1,
+            },
+        };
+    }
+
+    fn shift_display(&mut self, left: bool) {
+        self.display_offset = if left {
+            (self.display_offset + 1) % ROW_LEN as u8
+        } else {
+            (self.display_offset + ROW_LEN as u8 - 1) % ROW_LEN as u8
+        };
+    }
+
+    fn write_ram(&mut self, byte: u8) {
+        match self.target {
+            AddrTarget::Ddram => {
+                if let Some(index) = Self::ddram_index(self.ac) {
+                    self.ddram[index] = byte;
+                }
+                if self.shift_on_write {
+                    self.shift_display(self.increment);
+                }
+            }
+            // CGRAM 每个字节只有低 5 位有效
+            AddrTarget::Cgram => self.cgram[self.ac as usize] = byte & 0x1F,
+        }
+        self.move_ac();
+        self.busy_until_us = self.now_us + EXEC_US_RAM;
+    }
+
+    fn read_ram(&mut self) -> u8 {
+        let byte = match self.target {
+            AddrTarget::Ddram => Self::ddram_index(self.ac).map_or(b' ', |index| self.ddram[index]),
+            AddrTarget::Cgram => self.cgram[self.ac as usize],
+        };
+        self.move_ac();
+        self.busy_until_us = self.now_us + EXEC_US_RAM;
+        byte
+    }
+
+    fn instruction(&mut self, cmd: u8) {
+        let mut exec_us = EXEC_US_NORMAL;
+
+        match cmd.leading_zeros() {
+            // Set DDRAM Address
+            0 => {
+                self.target = AddrTarget::Ddram;
+                self.ac = cmd & 0x7F;
+            }
+            // Set CGRAM Address
+            1 => {
+                self.target = AddrTarget::Cgram;
+                self.ac = cmd & 0x3F;
+            }
+            // Function Set
+            2 => {
+                let eight_bit = cmd & 0b1_0000 != 0;
+                // 从 8 bit 切换到 4 bit 时，这条指令本身是以 8 bit 的方式接收的
+                // 之后的数据才开始以半字节的方式接收
+                self.eight_bit = eight_bit;
+                self.two_line = cmd & 0b1000 != 0;
+                self.font_5x10 = cmd & 0b100 != 0;
+            }
+            // Cursor or Display Shift
+            3 => {
+                let shift_display = cmd & 0b1000 != 0;
+                let right = cmd & 0b100 != 0;
+                if shift_display {
+                    self.shift_display(!right);
+                } else {
+                    let saved = self.increment;
+                    self.increment = right;
+                    self.move_ac();
+                    self.increment = saved;
+                }
+            }
+            // Display ON/OFF Control
+            4 => {
+                self.display_on = cmd & 0b100 != 0;
+                self.cursor_on = cmd & 0b10 != 0;
+                self.blink_on = cmd & 0b1 != 0;
+            }
+            // Entry Mode Set
+            5 => {
+                self.increment = cmd & 0b10 != 0;
+                self.shift_on_write = cmd & 0b1 != 0;
+            }
+            // Return Home
+            6 => {
+                self.target = AddrTarget::Ddram;
+                self.ac = 0;
+                self.display_offset = 0;
+                exec_us = EXEC_US_CLEAR_HOME;
+            }
+            // Clear Display
+            7 => {
+                self.ddram = [b' '; ROW_LEN * 2];
+                self.target = AddrTarget::Ddram;
+                self.ac = 0;
+                self.display_offset = 0;
+                self.increment = true;
+                exec_us = EXEC_US_CLEAR_HOME;
+            }
+            // 0x00 不是合法的指令
+            _ => {}
+        }
+
+        self.busy_until_us = self.now_us + exec_us;
+    }
+
+    // 屏幕上实际显示的 16 列内容，显示关闭时返回 None
+    pub fn visible_row(&self, row: usize) -> Option<String> {
+        if !self.display_on {
+            return None;
+        }
+        let base = row * ROW_LEN;
+        Some(
+            (0..16)
+                .map(|col| {
+                    let byte = self.ddram[base + (col + self.display_offset as usize) % ROW_LEN];
+                    match byte {
+                        // 前 8 个（以及它们的镜像 0x08~0x0F）是 CGRAM 中的自定义字符
+                        0x00..=0x0F => char::from_digit((byte & 0x07) as u32, 10).unwrap(),
+                        0x20..=0x7E => byte as char,
+                        _ => '?',
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    pub fn render(&self) -> String {
+        let rows = if self.two_line { 2 } else { 1 };
+        let mut out = String::new();
+        out.push_str("+----------------+\n");
+        for row in 0..rows {
+            let line = self.visible_row(row).unwrap_or_else(|| " ".repeat(16));
+            out.push_str(&format!("|{}|\n", line));
+        }
+        out.push_str("+----------------+");
+        out
+    }
+}
@@ -0,0 +1,297 @@
+//! 用模拟的 LCD1602 检查 pins、mode_4pin、mode_8pin、readback 与 widgets
+//!
+//! 每个测试都从上电状态开始，调用与 MCU 上完全相同的函数，再检查模拟的控制器的内部状态
+
+use super::{
+    super::{
+        pins::{function_set, init, init_with, Bus, DataWidth, Font, LineMode, PinIo, Pins},
+        readback::{dump_cgram, dump_ddram, DdramWriter, CGRAM_LEN, DDRAM_LEN},
+        widgets::{ProgressBar, Spinner},
+    },
+    st7066u::AddrTarget,
+    Lcd, EIGHT_PIN, FOUR_PIN,
+};
+
+const BUS_4: Bus = <Pins<4> as DataWidth>::BUS;
+const BUS_8: Bus = <Pins<8> as DataWidth>::BUS;
+
+// 上电，并用 pins::init 初始化
+fn power_on<P: DataWidth>(wired: u8, line: LineMode, font: Font) -> Lcd {
+    let lcd = Lcd::new(wired);
+    init::<P>(&lcd, &lcd, line, font);
+    lcd
+}
+
+// 任何一条指令都不应该在芯片忙碌时发出，MCU 与 LCD 也不应该同时驱动数据线
+fn assert_clean(lcd: &Lcd) {
+    assert_eq!(lcd.chip().violations.len(), 0, "{}", lcd.chip().render());
+    assert_eq!(lcd.bus_conflicts(), 0);
+}
+
+#[test]
+fn init_4pin() {
+    let lcd = power_on::<Pins<4>>(FOUR_PIN, LineMode::TwoLine, Font::Font5x8);
+
+    let chip = lcd.chip();
+    assert!(!chip.eight_bit);
+    assert!(chip.two_line);
+    assert!(chip.display_on);
+    assert!(!chip.cursor_on);
+    assert!(chip.increment);
+    assert_eq!(chip.ac, 0);
+    drop(chip);
+    assert_clean(&lcd);
+}
+
+#[test]
+fn init_8pin() {
+    let lcd = power_on::<Pins<8>>(EIGHT_PIN, LineMode::TwoLine, Font::Font5x8);
+
+    let chip = lcd.chip();
+    assert!(chip.eight_bit);
+    assert!(chip.two_line);
+    assert!(chip.display_on);
+    drop(chip);
+    assert_clean(&lcd);
+}
+
+#[test]
+fn init_one_line_large_font() {
+    let lcd = power_on::<Pins<4>>(FOUR_PIN, LineMode::OneLine, Font::Font5x10);
+
+    assert!(!lcd.chip().two_line);
+    assert!(lcd.chip().font_5x10);
+    assert_clean(&lcd);
+}
+
+#[test]
+#[should_panic(expected = "5x10 font is for one line mode only")]
+fn large_font_rejected_in_two_line_mode() {
+    function_set::<Pins<4>>(LineMode::TwoLine, Font::Font5x10);
+}
+
+// 4 线接法下 PB0~PB3 读出的总是 1，读回的 AC 正确，说明 read_8bit 把它们屏蔽掉了
+#[test]
+fn write_and_read_ac() {
+    let lcd = power_on::<Pins<4>>(FOUR_PIN, LineMode::TwoLine, Font::Font5x8);
+    let mut writer = DdramWriter::new(&BUS_4, &lcd, false);
+
+    writer.write_bytes(b"Hello");
+    assert_eq!(&lcd.chip().ddram[..5], b"Hello");
+    assert_eq!(lcd.chip().ac, 5);
+
+    writer.set_pos(1, 3);
+    writer.write_bytes(b"abc");
+    assert_eq!(lcd.chip().visible_row(1).unwrap(), "   abc          ");
+
+    assert_eq!((BUS_4.read)(&lcd, 0) & 0x7F, 0x46);
+    assert_clean(&lcd);
+}
+
+// DdramWriter 自己记录的地址，在两行之间跳转时要与控制器的 AC 保持一致，否则写后校验会读错位置
+#[test]
+fn writer_wraps_between_lines() {
+    let lcd = power_on::<Pins<4>>(FOUR_PIN, LineMode::TwoLine, Font::Font5x8);
+    let mut writer = DdramWriter::new(&BUS_4, &lcd, true);
+
+    // 第一行的最后一个地址是 0x27，再写一个字节就应该跳到第二行的 0x40
+    writer.set_pos(0, 39);
+    assert_eq!(writer.write_bytes(b"XY"), 0);
+    assert_eq!(lcd.chip().ddram[39], b'X');
+    assert_eq!(lcd.chip().ddram[40], b'Y');
+    assert_eq!(lcd.chip().ac, 0x41);
+
+    // 第二行的最后一个地址是 0x67，再写一个字节就应该跳回第一行的 0x00
+    writer.set_pos(1, 39);
+    assert_eq!(writer.write_bytes(b"Z!"), 0);
+    assert_eq!(lcd.chip().ddram[0], b'!');
+    assert_eq!(lcd.chip().ac, 0x01);
+    assert_clean(&lcd);
+}
+
+#[test]
+fn verify_after_write() {
+    for (bus, lcd) in [
+        (
+            BUS_4,
+            power_on::<Pins<4>>(FOUR_PIN, LineMode::TwoLine, Font::Font5x8),
+        ),
+        (
+            BUS_8,
+            power_on::<Pins<8>>(EIGHT_PIN, LineMode::TwoLine, Font::Font5x8),
+        ),
+    ] {
+        let mut writer = DdramWriter::new(&bus, &lcd, true);
+        assert_eq!(writer.write_bytes(b"verify"), 0);
+        assert!(writer.last_mismatch().is_none());
+        // 读取会让 AC 自增，所以读回之后 AC 正好指向下一个位置
+        assert_eq!(lcd.chip().ac, 6);
+
+        let mut ddram = [0; DDRAM_LEN];
+        dump_ddram(&bus, &lcd, &mut ddram);
+        assert_eq!(&ddram[..6], b"verify");
+        assert_eq!(ddram[40], b' ');
+        assert_clean(&lcd);
+    }
+}
+
+// D1 虚接时，'C'（0x43）会被写成 'A'（0x41），写后校验应该指出是 bit1 出了问题
+// 只写前两个地址，再往后的 Set DDRAM Address 指令本身的 bit1 也会丢失
+#[test]
+fn broken_data_line_is_reported() {
+    let lcd = power_on::<Pins<8>>(EIGHT_PIN, LineMode::TwoLine, Font::Font5x8);
+    lcd.break_lines(0b0000_0010);
+    let mut writer = DdramWriter::new(&BUS_8, &lcd, true);
+
+    assert_eq!(writer.write_bytes(b"AC"), 1);
+    assert_eq!(writer.mismatch_cnt(), 1);
+    let mismatch = writer.last_mismatch().unwrap();
+    assert_eq!(
+        (mismatch.addr, mismatch.wrote, mismatch.read),
+        (1, b'C', b'A')
+    );
+    assert_eq!(mismatch.bad_bits(), 0b0000_0010);
+}
+
+#[test]
+fn display_shift() {
+    let lcd = power_on::<Pins<4>>(FOUR_PIN, LineMode::TwoLine, Font::Font5x8);
+    DdramWriter::new(&BUS_4, &lcd, false).write_bytes(b"0123456789abcdefXYZ");
+
+    // Cursor or Display Shift，S/C = 1，R/L = 0，显示整体左移
+    for _ in 0..3 {
+        BUS_4.command(&lcd, 0b0001_1000);
+    }
+    assert_eq!(lcd.chip().visible_row(0).unwrap(), "3456789abcdefXYZ");
+    // 移动显示不会修改 AC
+    assert_eq!(lcd.chip().ac, 19);
+
+    // 右移回来，再多移一次，窗口回绕到 DDRAM 的末尾
+    for _ in 0..4 {
+        BUS_4.command(&lcd, 0b0001_1100);
+    }
+    assert_eq!(lcd.chip().display_offset, 39);
+
+    // Return Home 会清除偏移
+    BUS_4.command(&lcd, 0b0000_0010);
+    assert_eq!(lcd.chip().display_offset, 0);
+    assert_eq!(lcd.chip().ac, 0);
+    assert_clean(&lcd);
+}
+
+// Spinner 把字形写入 CGRAM，dump_cgram 读回的应该与写入的一致
+#[test]
+fn spinner_glyph_round_trip() {
+    let lcd = power_on::<Pins<4>>(FOUR_PIN, LineMode::TwoLine, Font::Font5x8);
+    let mut spinner = Spinner::new(1);
+
+    // 第 2 帧是一条横线
+    spinner.draw(&BUS_4, &lcd, 0, 15, 2);
+    assert_eq!(&lcd.chip().cgram[8..16], &[0, 0, 0, 0b11111, 0, 0, 0, 0]);
+
+    // 写完 CGRAM 之后，Spinner 要重新设置 DDRAM 地址，字符才会写到屏幕上
+    assert_eq!(lcd.chip().target, AddrTarget::Ddram);
+    assert_eq!(lcd.chip().visible_row(0).unwrap(), "               1");
+
+    let mut cgram = [0; CGRAM_LEN];
+    dump_cgram(&BUS_4, &lcd, &mut cgram);
+    assert_eq!(cgram, lcd.chip().cgram);
+    assert_clean(&lcd);
+}
+
+#[test]
+fn progress_bar() {
+    let lcd = power_on::<Pins<8>>(EIGHT_PIN, LineMode::TwoLine, Font::Font5x8);
+    let mut bar = ProgressBar::new(0, 10);
+
+    // 10 个字符共 50 级，50% 正好是 5 个满格
+    bar.draw(&BUS_8, &lcd, 1, 0, 50);
+    assert_eq!(&lcd.chip().visible_row(1).unwrap()[..10], "00000     ");
+    assert_eq!(lcd.chip().cgram[0], 0b11111);
+
+    // 55% 是 27 级，5 个满格之后是一个点亮了左边 2 列的字符
+    bar.draw(&BUS_8, &lcd, 1, 0, 55);
+    assert_eq!(&lcd.chip().visible_row(1).unwrap()[..10], "000001    ");
+    assert_eq!(lcd.chip().cgram[8], 0b11000);
+    assert_clean(&lcd);
+}
+
+#[test]
+fn busy_flag_timing() {
+    let lcd = power_on::<Pins<4>>(FOUR_PIN, LineMode::TwoLine, Font::Font5x8);
+
+    // Clear Display 需要 1.52 ms，这期间直接发送的数据会被芯片丢弃
+    BUS_4.command(&lcd, 0b0000_0001);
+    (BUS_4.send)(&lcd, 1, 0, b'A');
+    let violation = lcd.chip().violations[0];
+    assert_eq!((violation.rs, violation.data), (true, b'A'));
+    assert_eq!(lcd.chip().ddram[0], b' ');
+
+    // 通过 busy flag 等待之后再发送就没有问题了
+    let before = lcd.chip().now_us();
+    BUS_4.write_data(&lcd, b'A');
+    assert_eq!(lcd.chip().ddram[0], b'A');
+    assert_eq!(lcd.chip().violations.len(), 1);
+    assert!(lcd.chip().now_us() - before >= 1000);
+}
+
+// 只能写入的接法，比如 s21 中经过 74HC595 的背板：RW 始终为低，每条指令之后只能等待足够长的时间
+struct WriteOnly<'a>(&'a Lcd);
+
+impl PinIo for WriteOnly<'_> {
+    const READABLE: bool = false;
+
+    fn set_rs(&self, high: bool) {
+        self.0.set_rs(high);
+    }
+
+    fn set_rw(&self, high: bool) {
+        assert!(!high, "RW must stay low on a write only bus");
+        self.0.set_rw(high);
+    }
+
+    fn set_e(&self, high: bool) {
+        self.0.set_e(high);
+    }
+
+    fn write_dbus(&self, mask: u8, data: u8) {
+        self.0.write_dbus(mask, data);
+    }
+
+    fn read_dbus(&self) -> u8 {
+        unreachable!("write only bus");
+    }
+
+    fn set_dbus_input(&self, _mask: u8, _input: bool) {
+        unreachable!("write only bus");
+    }
+
+    fn wait_us(&self, micro_sec: u32) {
+        self.0.advance_us(micro_sec as u64);
+    }
+}
+
+// 不读 busy flag，只靠等待，也不应该在芯片忙碌时发出任何指令，Clear Display 之后的等待尤其要足够长
+#[test]
+fn write_only_bus() {
+    let lcd = Lcd::new(FOUR_PIN);
+    let io = WriteOnly(&lcd);
+    init_with::<Pins<4>, _>(&io, &lcd, LineMode::TwoLine, Font::Font5x8);
+    let bus = <Pins<4> as DataWidth>::bus::<WriteOnly>();
+
+    assert!(lcd.chip().two_line);
+    assert!(lcd.chip().display_on);
+
+    // 第二行的开头
+    bus.command(&io, 0b1100_0000);
+    for &byte in b"Hi" {
+        bus.write_data(&io, byte);
+    }
+    assert_eq!(&lcd.chip().visible_row(1).unwrap()[..2], "Hi");
+
+    bus.command(&io, 0b0000_0001);
+    bus.write_data(&io, b'A');
+    assert_eq!(lcd.chip().visible_row(0).unwrap(), "A               ");
+    assert_eq!(lcd.chip().visible_row(1).unwrap(), "                ");
+    assert_clean(&lcd);
+}
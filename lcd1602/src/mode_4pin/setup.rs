@@ -1,5 +1,3 @@
-#![allow(dead_code)]
-
 use stm32f4xx_hal::pac;
 
 pub fn setup_gpioa(dp: &pac::Peripherals) {
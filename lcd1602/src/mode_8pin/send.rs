@@ -0,0 +1,69 @@
+use crate::{
+    common::delay,
+    pins::{Core, PinIo},
+};
+
+// D0~D7 接在 PB0~PB7 上
+const DBUS_MASK: u8 = 0b1111_1111;
+
+pub fn send<P: PinIo>(dp: &P, rs: u8, rw: u8, data: u8) {
+    dp.set_e(false);
+
+    match rs {
+        0 => dp.set_rs(false),
+        1 => dp.set_rs(true),
+        _ => panic!("RS value Error"),
+    }
+
+    match rw {
+        0 => dp.set_rw(false),
+        1 => dp.set_rw(true),
+        _ => panic!("RW value Error"),
+    }
+
+    dp.write_dbus(DBUS_MASK, data);
+
+    dp.set_e(true);
+    dp.set_e(false);
+}
+
+// 读取 LCD1602 的一个字节
+// rs 为 0 时，读取 busy flag 与地址计数器（AC），rs 为 1 时，读取 AC 指向的 DDRAM/CGRAM 中的数据，并使 AC 自增
+pub fn read<P: PinIo>(dp: &P, rs: u8) -> u8 {
+    dp.set_e(false);
+
+    // 由于是输入，这里需要将 PB0~PB7 切换到输入模式
+    dp.set_dbus_input(DBUS_MASK, true);
+
+    match rs {
+        0 => dp.set_rs(false),
+        1 => dp.set_rs(true),
+        _ => panic!("RS value Error"),
+    }
+    dp.set_rw(true);
+
+    dp.set_e(true);
+
+    let state = dp.read_dbus();
+
+    dp.set_e(false);
+
+    dp.set_dbus_input(DBUS_MASK, false);
+
+    state
+}
+
+pub fn read_busy_flag<P: PinIo>(dp: &P) -> u8 {
+    read(dp, 0)
+}
+
+pub fn wait_for_idle<P: PinIo>(dp: &P, cp: &Core, poll_interval_ms: u32) {
+    while read_busy_flag(dp).checked_shr(7).unwrap() & 1 == 1 {
+        delay(cp, poll_interval_ms);
+    }
+}
+
+pub fn wait_and_send<P: PinIo>(dp: &P, cp: &Core, rs: u8, rw: u8, data: u8, poll_interval_ms: u32) {
+    wait_for_idle(dp, cp, poll_interval_ms);
+    send(dp, rs, rw, data);
+}
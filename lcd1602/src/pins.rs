@@ -0,0 +1,274 @@
+//! 在编译期确定 LCD1602 的数据线数量
+//!
+//! LCD1602 只支持 4 根或 8 根数据线两种接法，两种接法的收发函数不同，Function Set 指令中的 DL 位也不同
+//! 如果用一个普通的数字参数来表示数据线的数量，那么写错成 5 或者 6 时，只有在运行时才会发现
+//!
+//! 因此这里用 Pins<const PIN_CNT: usize> 来表示接法，但只为 Pins<4> 和 Pins<8> 实现了 DataWidth trait，
+//! 且 DataWidth 依赖一个外部无法访问的 Sealed trait，外部也无法为 Pins<5> 补上实现
+//! 这样 init::<Pins<5>>() 这样的代码会直接编译失败，而 Function Set 中的 DL 位也会随着 PIN_CNT 自动确定
+//!
+//! 收发函数不直接读写 GPIO 寄存器，而是通过 PinIo 操作引脚，延时也只通过 common::DelayUs
+//! 在 MCU 上 Port 与 Core 就是 pac::Peripherals 与 pac::CorePeripherals，
+//! 启用 mock 特性时，两者都换成 mock 中模拟的 LCD1602，这样这里以及 readback、widgets 的代码可以原样在 PC 上测试
+
+use stm32f4xx_hal::pac;
+
+use super::{common::DelayUs, mode_4pin, mode_8pin};
+
+#[cfg(not(feature = "mock"))]
+pub type Port = pac::Peripherals;
+#[cfg(not(feature = "mock"))]
+pub type Core = pac::CorePeripherals;
+
+#[cfg(feature = "mock")]
+pub type Port = super::mock::Lcd;
+#[cfg(feature = "mock")]
+pub type Core = super::mock::Lcd;
+
+// LCD1602 引脚的最底层操作，mode_4pin 与 mode_8pin 的收发函数只通过它访问引脚
+// RS、RW、E 接在 PA0、PA1、PA2 上，D0~D7 接在 PB0~PB7 上（4 线接法时只有 D4~D7，接在 PB4~PB7 上）
+pub trait PinIo {
+    fn set_rs(&self, high: bool);
+    fn set_rw(&self, high: bool);
+    fn set_e(&self, high: bool);
+    // 只修改 mask 中为 1 的数据线，其它的 PB 引脚可能另有用途
+    fn write_dbus(&self, mask: u8, data: u8);
+    // 读取 PB0~PB7 的电平
+    fn read_dbus(&self) -> u8;
+    // 将 mask 中为 1 的数据线切换为输入（input 为 true）或者输出
+    fn set_dbus_input(&self, mask: u8, input: bool);
+
+    // 能否从数据线读回数据，经过 74HC595 这类只能输出的背板时（见 s21 的 utils::lcd1602），RW 始终为低，读不回 busy flag
+    // 此时不会调用 read_dbus 与 set_dbus_input，Bus 改为在每条指令之后用 wait_us 等待它执行完成
+    const READABLE: bool = true;
+
+    fn wait_us(&self, _micro_sec: u32) {
+        unreachable!("wait_us is only used when READABLE is false")
+    }
+}
+
+impl PinIo for pac::Peripherals {
+    fn set_rs(&self, high: bool) {
+        self.GPIOA.odr.modify(|_, w| w.odr0().bit(high));
+    }
+
+    fn set_rw(&self, high: bool) {
+        self.GPIOA.odr.modify(|_, w| w.odr1().bit(high));
+    }
+
+    fn set_e(&self, high: bool) {
+        self.GPIOA.odr.modify(|_, w| w.odr2().bit(high));
+    }
+
+    fn write_dbus(&self, mask: u8, data: u8) {
+        // BSRR 的低 16 位写 1 输出高电平，高 16 位写 1 输出低电平，写 0 的引脚不受影响
+        let set = (data & mask) as u32;
+        let reset = (!data & mask) as u32;
+        self.GPIOB
+            .bsrr
+            .write(|w| unsafe { w.bits(set | reset << 16) });
+    }
+
+    fn read_dbus(&self) -> u8 {
+        // PB8 之后的引脚与这里无关，直接截掉
+        self.GPIOB.idr.read().bits() as u8
+    }
+
+    fn set_dbus_input(&self, mask: u8, input: bool) {
+        // MODER 中每个引脚占 2 位，0b00 为输入，0b01 为输出
+        self.GPIOB.moder.modify(|r, w| {
+            let mut bits = r.bits();
+            for pin in (0..8).filter(|pin| mask >> pin & 1 == 1) {
+                bits &= !(0b11 << (pin * 2));
+                if !input {
+                    bits |= 0b01 << (pin * 2);
+                }
+            }
+            unsafe { w.bits(bits) }
+        });
+    }
+}
+
+// 4 bit 模式与 8 bit 模式的收发函数的签名是一样的，这里把它们包装起来，上层的函数就可以同时用于两种模式了
+pub struct Bus<P = Port> {
+    // 参数依次为 dp, rs, rw, data
+    pub send: fn(&P, u8, u8, u8),
+    // 参数依次为 dp, rs
+    pub read: fn(&P, u8) -> u8,
+}
+
+impl<P: PinIo> Bus<P> {
+    pub fn wait_for_idle(&self, dp: &P) {
+        while (self.read)(dp, 0) & 0b1000_0000 != 0 {}
+    }
+
+    pub fn command(&self, dp: &P, cmd: u8) {
+        if !P::READABLE {
+            (self.send)(dp, 0, 0, cmd);
+            // Clear Display 与 Return Home 最长要 1.52 ms，其它指令都在 40 us 以内
+            dp.wait_us(if cmd < 0b0000_0100 { 2_000 } else { 50 });
+            return;
+        }
+        self.wait_for_idle(dp);
+        (self.send)(dp, 0, 0, cmd);
+    }
+
+    pub fn write_data(&self, dp: &P, data: u8) {
+        if !P::READABLE {
+            (self.send)(dp, 1, 0, data);
+            dp.wait_us(50);
+            return;
+        }
+        self.wait_for_idle(dp);
+        (self.send)(dp, 1, 0, data);
+    }
+
+    pub fn read_data(&self, dp: &P) -> u8 {
+        assert!(P::READABLE, "LCD1602 bus is write only");
+        self.wait_for_idle(dp);
+        (self.read)(dp, 1)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LineMode {
+    OneLine,
+    TwoLine,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Font {
+    Font5x8,
+    // 只有单行模式下才能使用 5x10 的字体
+    Font5x10,
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+pub struct Pins<const PIN_CNT: usize>;
+
+impl sealed::Sealed for Pins<4> {}
+impl sealed::Sealed for Pins<8> {}
+
+pub trait DataWidth: sealed::Sealed {
+    // Function Set 指令中的 DL（Data Length）位
+    const DATA_LENGTH_BIT: u8;
+    // 接在 Port 上时的 Bus，与 bus::<Port>() 相同，可以用在 const 中
+    const BUS: Bus;
+
+    // 接在任意一种 PinIo 上时的 Bus
+    fn bus<P: PinIo>() -> Bus<P>;
+
+    // 配置 GPIO，只在 MCU 上使用，模拟的 LCD1602 没有 GPIO 需要配置
+    fn setup_gpio(dp: &pac::Peripherals);
+
+    // 上电后，LCD1602 处于 8 bit 模式，这里发送第一条 Function Set 指令，让它切换到需要的模式
+    fn wake_up<P: PinIo>(dp: &P);
+}
+
+impl DataWidth for Pins<4> {
+    const DATA_LENGTH_BIT: u8 = 0;
+    const BUS: Bus = Bus {
+        send: mode_4pin::send::send_8bit,
+        read: mode_4pin::send::read_8bit,
+    };
+
+    fn bus<P: PinIo>() -> Bus<P> {
+        Bus {
+            send: mode_4pin::send::send_8bit,
+            read: mode_4pin::send::read_8bit,
+        }
+    }
+
+    fn setup_gpio(dp: &pac::Peripherals) {
+        mode_4pin::setup::setup_gpioa(dp);
+        mode_4pin::setup::setup_gpiob(dp);
+    }
+
+    fn wake_up<P: PinIo>(dp: &P) {
+        // 此时 LCD1602 还在 8 bit 模式下，只会读取 D4~D7，因此只发送高 4 位
+        mode_4pin::send::send_4bit(dp, 0, 0, 0b0010);
+    }
+}
+
+impl DataWidth for Pins<8> {
+    const DATA_LENGTH_BIT: u8 = 0b0001_0000;
+    const BUS: Bus = Bus {
+        send: mode_8pin::send::send,
+        read: mode_8pin::send::read,
+    };
+
+    fn bus<P: PinIo>() -> Bus<P> {
+        Bus {
+            send: mode_8pin::send::send,
+            read: mode_8pin::send::read,
+        }
+    }
+
+    fn setup_gpio(dp: &pac::Peripherals) {
+        mode_8pin::setup::setup_gpioa(dp);
+        mode_8pin::setup::setup_gpiob(dp);
+    }
+
+    fn wake_up<P: PinIo>(dp: &P) {
+        mode_8pin::send::send(dp, 0, 0, 0b0011_0000);
+    }
+}
+
+// 依照接法、行数、字体，生成 Function Set 指令
+pub fn function_set<P: DataWidth>(line: LineMode, font: Font) -> u8 {
+    let line_bit = match line {
+        LineMode::OneLine => 0,
+        LineMode::TwoLine => 0b0000_1000,
+    };
+    let font_bit = match (line, font) {
+        (LineMode::OneLine, Font::Font5x10) => 0b0000_0100,
+        (LineMode::TwoLine, Font::Font5x10) => panic!("5x10 font is for one line mode only"),
+        (_, Font::Font5x8) => 0,
+    };
+    0b0010_0000 | P::DATA_LENGTH_BIT | line_bit | font_bit
+}
+
+// 完整的初始化流程，与 s11c01 和 s11c02 中的流程相同，结束后显示开启、光标关闭、屏幕清空
+pub fn init<P: DataWidth>(dp: &Port, cp: &Core, line: LineMode, font: Font) {
+    #[cfg(not(feature = "mock"))]
+    P::setup_gpio(dp);
+
+    init_with::<P, _>(dp, cp, line, font);
+}
+
+// 与 init 相同，但引脚与延时都由调用者提供，GPIO 或者背板需要事先配置好
+pub fn init_with<P: DataWidth, IO: PinIo>(
+    io: &IO,
+    delay: &impl DelayUs,
+    line: LineMode,
+    font: Font,
+) {
+    let bus = P::bus::<IO>();
+
+    delay.delay_us(100_000);
+    P::wake_up(io);
+
+    let function_set = function_set::<P>(line, font);
+
+    delay.delay_us(40);
+    (bus.send)(io, 0, 0, function_set);
+
+    delay.delay_us(40);
+    (bus.send)(io, 0, 0, function_set);
+
+    // 之后的指令都通过 Bus 发送，读不回 busy flag 的接法，要先等这一条 Function Set 执行完
+    if !IO::READABLE {
+        delay.delay_us(40);
+    }
+
+    bus.command(io, 0b0000_1100);
+    bus.command(io, 0b0000_0001);
+    bus.command(io, 0b0000_0110);
+}
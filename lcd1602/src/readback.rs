@@ -0,0 +1,156 @@
+//! 从 LCD1602 读回数据
+//!
+//! ST7066U 的 DDRAM 和 CGRAM 都是可以读的：
+//! 先用 Set DDRAM Address / Set CGRAM Address 指令设置地址计数器（AC），之后每次以 RS=1、RW=1 读取，
+//! 都会得到 AC 指向的数据，并且 AC 会自动加 1（方向由 Entry Mode Set 决定）
+//!
+//! 需要注意的是，写入数据之后，AC 已经指向下一个位置了，想要读回刚写入的数据，必须重新设置一次地址
+//!
+//! 读回的能力在排查接线问题的时候非常有用：
+//! 如果某根数据线虚接，写入的字符会变成另一个字符，屏幕上看起来只是“乱码”，
+//! 而把写入的值和读回的值逐位比较，就可以直接看出是哪一位出了问题
+
+use assert_policy::check;
+
+use super::pins::{Bus, Port};
+
+// 2 行模式下，第一行的 DDRAM 地址为 0x00~0x27，第二行为 0x40~0x67，每行 40 个字节
+pub const DDRAM_ROW_LEN: usize = 40;
+pub const DDRAM_LEN: usize = DDRAM_ROW_LEN * 2;
+// 8 个自定义字符，每个 8 行，每行 1 个字节
+pub const CGRAM_LEN: usize = 64;
+
+pub const CMD_SET_CGRAM_ADDR: u8 = 0b0100_0000;
+pub const CMD_SET_DDRAM_ADDR: u8 = 0b1000_0000;
+
+// 读回时，不一致的那个字节
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Mismatch {
+    pub addr: u8,
+    pub wrote: u8,
+    pub read: u8,
+}
+
+impl Mismatch {
+    // 有问题的数据位，对应位为 1 表示该数据线可能有问题
+    pub fn bad_bits(&self) -> u8 {
+        self.wrote ^ self.read
+    }
+}
+
+// 行列坐标转换为 DDRAM 地址
+// 超出范围时截断到最后一行、最后一列，写错位置总比让整个程序停下来好，策略见 assert_policy
+pub fn ddram_addr(row: u8, col: u8) -> u8 {
+    if !check!(row < 2 && (col as usize) < DDRAM_ROW_LEN) {
+        return row.min(1) * 0x40 + col.min(DDRAM_ROW_LEN as u8 - 1);
+    }
+    row * 0x40 + col
+}
+
+// 写入或读取一次之后 AC 的下一个值：第一行的末尾（0x27）之后是第二行的开头（0x40），
+// 第二行的末尾（0x67）之后回到 0x00，与 HD44780 自己的行为一致
+pub fn next_ddram_addr(addr: u8) -> u8 {
+    match addr {
+        0x27 => 0x40,
+        0x67 => 0x00,
+        _ => addr.wrapping_add(1),
+    }
+}
+
+// 读取屏幕上指定位置的字符
+pub fn read_u8_from_pos(bus: &Bus, dp: &Port, row: u8, col: u8) -> u8 {
+    bus.command(dp, CMD_SET_DDRAM_ADDR | ddram_addr(row, col));
+    bus.read_data(dp)
+}
+
+// 读取整个 DDRAM，buf 的前 40 个字节为第一行，后 40 个字节为第二行
+pub fn dump_ddram(bus: &Bus, dp: &Port, buf: &mut [u8; DDRAM_LEN]) {
+    for (row, row_buf) in buf.chunks_exact_mut(DDRAM_ROW_LEN).enumerate() {
+        // 两行的地址是不连续的，因此每行都要重新设置一次地址
+        bus.command(dp, CMD_SET_DDRAM_ADDR | ddram_addr(row as u8, 0));
+        for byte in row_buf.iter_mut() {
+            *byte = bus.read_data(dp);
+        }
+    }
+}
+
+// 读取整个 CGRAM
+pub fn dump_cgram(bus: &Bus, dp: &Port, buf: &mut [u8; CGRAM_LEN]) {
+    bus.command(dp, CMD_SET_CGRAM_ADDR);
+    for byte in buf.iter_mut() {
+        // CGRAM 每个字节只有低 5 位是有效的
+        *byte = bus.read_data(dp) & 0b1_1111;
+    }
+}
+
+// 向 DDRAM 写入数据的工具，可以选择在每次写入之后读回并比较
+pub struct DdramWriter<'a> {
+    bus: &'a Bus,
+    dp: &'a Port,
+    verify: bool,
+    addr: u8,
+    mismatch_cnt: u32,
+    last_mismatch: Option<Mismatch>,
+}
+
+impl<'a> DdramWriter<'a> {
+    pub fn new(bus: &'a Bus, dp: &'a Port, verify: bool) -> Self {
+        Self {
+            bus,
+            dp,
+            verify,
+            addr: 0,
+            mismatch_cnt: 0,
+            last_mismatch: None,
+        }
+    }
+
+    pub fn set_pos(&mut self, row: u8, col: u8) {
+        self.addr = ddram_addr(row, col);
+        self.bus.command(self.dp, CMD_SET_DDRAM_ADDR | self.addr);
+    }
+
+    pub fn write_u8(&mut self, data: u8) -> Result<(), Mismatch> {
+        let addr = self.addr;
+        self.bus.write_data(self.dp, data);
+        self.addr = next_ddram_addr(self.addr);
+
+        if !self.verify {
+            return Ok(());
+        }
+
+        // 回到刚写入的位置读一次，读取会让 AC 自增，因此读完之后 AC 正好指向下一个位置，不需要再次设置
+        self.bus.command(self.dp, CMD_SET_DDRAM_ADDR | addr);
+        let read = self.bus.read_data(self.dp);
+
+        if read != data {
+            let mismatch = Mismatch {
+                addr,
+                wrote: data,
+                read,
+            };
+            self.mismatch_cnt += 1;
+            self.last_mismatch = Some(mismatch);
+            return Err(mismatch);
+        }
+
+        Ok(())
+    }
+
+    // 写入一串字节，遇到不一致的字节不会停下，而是继续写完，返回不一致的字节数
+    pub fn write_bytes(&mut self, data: &[u8]) -> u32 {
+        data.iter()
+            .filter(|&&byte| self.write_u8(byte).is_err())
+            .count() as u32
+    }
+
+    pub fn mismatch_cnt(&self) -> u32 {
+        self.mismatch_cnt
+    }
+
+    pub fn last_mismatch(&self) -> Option<Mismatch> {
+        self.last_mismatch
+    }
+}
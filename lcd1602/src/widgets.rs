@@ -0,0 +1,277 @@
+//! 用 CGRAM 自定义字符实现的几个小部件：进度条、两行高的大数字、旋转指示
+//!
+//! CGRAM 只有 8 个字符（0~7），DDRAM 中写入 0~7 就会显示对应的自定义字符
+//! 修改 CGRAM 中某个字符的点阵之后，屏幕上所有显示这个字符的位置都会立刻跟着变化，
+//! 因此一个会变化的字形只能在屏幕上出现一次，这也是进度条只用一个字符表示“不满一格”的那一格的原因
+//!
+//! 每个部件在创建时指定自己从哪个字符开始使用 CGRAM，占用的个数见各自的 SLOTS，
+//! 几个部件同时使用时，各自的范围不能重叠，加起来不能超过 8 个
+//! 部件会记住自己已经写入的字形，只有字形需要变化时才会重写 CGRAM
+//!
+//! 字形不使用字符 ROM 中的 0xFF（实心方块），不同 ROM 版本（A00/A02）的字符表不完全相同，全部自己生成更稳妥
+
+use assert_policy::check;
+
+use super::{
+    pins::{Bus, Port},
+    readback::{ddram_addr, CMD_SET_CGRAM_ADDR, CMD_SET_DDRAM_ADDR},
+};
+
+pub const CGRAM_SLOTS: u8 = 8;
+
+// 5x8 字体，每个字符 5 列
+pub const CELL_COLUMNS: u8 = 5;
+
+// 一个字符的点阵，每个字节是一行，低 5 位有效，最高的一列对应 bit4
+type Glyph = [u8; 8];
+
+// 把点阵写入 CGRAM 的第 slot 个字符
+// 写入 CGRAM 之后 AC 指向 CGRAM，写 DDRAM 之前必须重新设置一次 DDRAM 地址
+// slot 超出范围时什么都不写，否则会写进 DDRAM 的地址空间，策略见 assert_policy
+fn load_glyph(bus: &Bus, dp: &Port, slot: u8, glyph: &Glyph) {
+    if !check!(slot < CGRAM_SLOTS) {
+        return;
+    }
+    bus.command(dp, CMD_SET_CGRAM_ADDR | slot << 3);
+    for &row in glyph {
+        bus.write_data(dp, row);
+    }
+}
+
+fn set_pos(bus: &Bus, dp: &Port, row: u8, col: u8) {
+    bus.command(dp, CMD_SET_DDRAM_ADDR | ddram_addr(row, col));
+}
+
+// 各部件使用的 CGRAM 范围在编写程序时就确定了，超出时没有合理的退路（会覆盖别的部件的字形），因此依旧直接 panic
+fn check_slots(first_slot: u8, count: u8) {
+    assert!(
+        first_slot + count <= CGRAM_SLOTS,
+        "Widget needs more CGRAM slots than available"
+    );
+}
+
+// 左边 columns 列点亮的方块，最后一行留给光标，不点亮
+fn partial_block(columns: u8) -> Glyph {
+    let row = !(0b1_1111 >> columns) & 0b1_1111;
+    [row, row, row, row, row, row, row, 0]
+}
+
+// 平滑的进度条，每个字符分为 5 列，因此 width 个字符一共有 width * 5 级
+//
+// 占用两个字符：first_slot 为满格，first_slot + 1 为不满一格的那一格，按需生成 1~4 列的字形
+pub struct ProgressBar {
+    first_slot: u8,
+    width: u8,
+    full_loaded: bool,
+    // 当前 CGRAM 中不满一格的字形是几列
+    partial_loaded: Option<u8>,
+}
+
+impl ProgressBar {
+    pub const SLOTS: u8 = 2;
+
+    pub fn new(first_slot: u8, width: u8) -> Self {
+        check_slots(first_slot, Self::SLOTS);
+        Self {
+            first_slot,
+            width,
+            full_loaded: false,
+            partial_loaded: None,
+        }
+    }
+
+    // 在 row 行 col 列开始画出 percent% 的进度，percent 超过 100 时按 100 处理
+    pub fn draw(&mut self, bus: &Bus, dp: &Port, row: u8, col: u8, percent: u8) {
+        let steps = self.width as u32 * CELL_COLUMNS as u32;
+        let lit = steps * percent.min(100) as u32 / 100;
+        let full_cells = (lit / CELL_COLUMNS as u32) as u8;
+        let partial = (lit % CELL_COLUMNS as u32) as u8;
+
+        if full_cells > 0 && !self.full_loaded {
+            load_glyph(bus, dp, self.first_slot, &partial_block(CELL_COLUMNS));
+            self.full_loaded = true;
+        }
+        if partial > 0 && self.partial_loaded != Some(partial) {
+            load_glyph(bus, dp, self.first_slot + 1, &partial_block(partial));
+            self.partial_loaded = Some(partial);
+        }
+
+        set_pos(bus, dp, row, col);
+        for cell in 0..self.width {
+            let c = if cell < full_cells {
+                self.first_slot
+            } else if cell == full_cells && partial > 0 {
+                self.first_slot + 1
+            } else {
+                b' '
+            };
+            bus.write_data(dp, c);
+        }
+    }
+}
+
+// 大数字的笔画，每个数字占 3 列 2 行
+//
+// 上半行与下半行各由 4 种笔画拼出：满格、上横、下横、上下横，
+// 比如 2 的上半行是“上下横 上下横 满格”，上横是 2 的顶部，下横是 2 的中间那一横
+#[derive(Clone, Copy)]
+enum Stroke {
+    Blank,
+    Full,
+    Upper,
+    Lower,
+    UpperLower,
+}
+
+use Stroke::{Blank as __, Full as FF, Lower as LO, Upper as UP, UpperLower as UL};
+
+// 下标为数字，每个数字依次为上半行 3 个、下半行 3 个
+const BIG_DIGITS: [[Stroke; 6]; 10] = [
+    [FF, UP, FF, FF, LO, FF],
+    [UP, FF, __, LO, FF, LO],
+    [UL, UL, FF, FF, LO, LO],
+    [UL, UL, FF, LO, LO, FF],
+    [FF, LO, FF, __, __, FF],
+    [FF, UL, UL, LO, LO, FF],
+    [FF, UL, UL, FF, LO, FF],
+    [UP, UP, FF, __, __, FF],
+    [FF, UL, FF, FF, LO, FF],
+    [FF, UL, FF, LO, LO, FF],
+];
+
+const STROKE_GLYPHS: [Glyph; 4] = [
+    // Full
+    [0b11111; 8],
+    // Upper
+    [0b11111, 0b11111, 0b11111, 0, 0, 0, 0, 0],
+    // Lower
+    [0, 0, 0, 0, 0, 0b11111, 0b11111, 0b11111],
+    // UpperLower
+    [0b11111, 0b11111, 0b11111, 0, 0, 0b11111, 0b11111, 0b11111],
+];
+
+// 两行高的数字，每个数字 3 列，数字之间空 1 列
+//
+// 占用 4 个字符，笔画的字形不会变化，第一次 draw 时写入
+pub struct BigNumber {
+    first_slot: u8,
+    digits: u8,
+    loaded: bool,
+}
+
+impl BigNumber {
+    pub const SLOTS: u8 = 4;
+    pub const DIGIT_WIDTH: u8 = 3;
+
+    // digits 位数字，16 列的屏幕上最多放得下 4 位
+    pub fn new(first_slot: u8, digits: u8) -> Self {
+        check_slots(first_slot, Self::SLOTS);
+        // 位数超出范围时截断到 1 ~ 9 位
+        let digits = if check!((1..=9).contains(&digits)) {
+            digits
+        } else {
+            digits.clamp(1, 9)
+        };
+        Self {
+            first_slot,
+            digits,
+            loaded: false,
+        }
+    }
+
+    // 占用的列数
+    pub fn width(&self) -> u8 {
+        self.digits * (Self::DIGIT_WIDTH + 1) - 1
+    }
+
+    // 从 col 列开始，在两行中右对齐地画出 value，去掉开头的 0
+    // value 超过 digits 位能表示的最大值时，显示最大值
+    pub fn draw(&mut self, bus: &Bus, dp: &Port, col: u8, value: u32) {
+        if !self.loaded {
+            for (index, glyph) in STROKE_GLYPHS.iter().enumerate() {
+                load_glyph(bus, dp, self.first_slot + index as u8, glyph);
+            }
+            self.loaded = true;
+        }
+
+        let value = value.min(10u32.pow(self.digits as u32) - 1);
+
+        for row in 0..2u8 {
+            set_pos(bus, dp, row, col);
+            for position in 0..self.digits {
+                let place = 10u32.pow((self.digits - 1 - position) as u32);
+                // 最低位的 0 总是要显示的
+                let strokes = if value < place && place > 1 {
+                    [Stroke::Blank; 6]
+                } else {
+                    BIG_DIGITS[(value / place % 10) as usize]
+                };
+
+                let start = row as usize * 3;
+                for &stroke in &strokes[start..start + 3] {
+                    bus.write_data(dp, self.stroke_char(stroke));
+                }
+                if position + 1 < self.digits {
+                    bus.write_data(dp, b' ');
+                }
+            }
+        }
+    }
+
+    fn stroke_char(&self, stroke: Stroke) -> u8 {
+        match stroke {
+            Stroke::Blank => b' ',
+            Stroke::Full => self.first_slot,
+            Stroke::Upper => self.first_slot + 1,
+            Stroke::Lower => self.first_slot + 2,
+            Stroke::UpperLower => self.first_slot + 3,
+        }
+    }
+}
+
+// | / - \ 四帧，字符 ROM 中 0x5C 是日元符号，没有反斜杠，所以全部自己画
+const SPINNER_FRAMES: [Glyph; 4] = [
+    [
+        0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0,
+    ],
+    [
+        0b00001, 0b00010, 0b00010, 0b00100, 0b01000, 0b01000, 0b10000, 0,
+    ],
+    [0, 0, 0, 0b11111, 0, 0, 0, 0],
+    [
+        0b10000, 0b01000, 0b01000, 0b00100, 0b00010, 0b00010, 0b00001, 0,
+    ],
+];
+
+// 旋转指示，用来表示“还在运行”
+//
+// 占用 1 个字符，屏幕上的字符不需要改动，换帧只需要重写 CGRAM
+pub struct Spinner {
+    slot: u8,
+    // 当前 CGRAM 中是第几帧
+    frame_loaded: Option<usize>,
+}
+
+impl Spinner {
+    pub const SLOTS: u8 = 1;
+
+    pub fn new(slot: u8) -> Self {
+        check_slots(slot, Self::SLOTS);
+        Self {
+            slot,
+            frame_loaded: None,
+        }
+    }
+
+    // 在 row 行 col 列显示第 frame 帧，frame 可以一直递增，超出帧数时循环
+    pub fn draw(&mut self, bus: &Bus, dp: &Port, row: u8, col: u8, frame: u32) {
+        let frame = frame as usize % SPINNER_FRAMES.len();
+        if self.frame_loaded != Some(frame) {
+            load_glyph(bus, dp, self.slot, &SPINNER_FRAMES[frame]);
+            self.frame_loaded = Some(frame);
+        }
+
+        set_pos(bus, dp, row, col);
+        bus.write_data(dp, self.slot);
+    }
+}
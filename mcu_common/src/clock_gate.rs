@@ -0,0 +1,385 @@
+//! 外设时钟的开关记录与审计
+//!
+//! 外设不工作时关掉它的时钟，是最简单的省电方法，但每个示例都在各处直接改写 RCC 的 xxxENR：
+//! 哪个驱动打开了哪个时钟、关掉某个时钟会不会影响别人、程序跑起来之后到底还有哪些外设在耗电，都很难说清楚
+//!
+//! 因此这里要求驱动通过 claim/release 开关时钟：
+//!
+//! - claim：引用计数加 1，并打开时钟
+//! - release：引用计数减 1，减到 0 时才真正关闭时钟，所以两个驱动共用 GPIOB 时，一个驱动 release 不会影响另一个
+//! - disable_unused：关闭所有已经打开、但没有被 claim 的时钟，比如 HAL 顺手打开之后就忘了的那些
+//! - apply_sleep_gating：把 xxxLPENR 设置为只包含被 claim 的外设，这样 WFI 进入 Sleep 模式之后，
+//!   只有真正在用的外设还有时钟（xxxLPENR 复位后几乎全为 1，即 Sleep 模式下所有外设照常有时钟）
+//! - report：打印当前打开了时钟的外设，以及它们的引用计数与 Sleep 模式下的状态
+//!
+//! 注意：
+//! 在 Sleep 模式下使用 RTT 的话，需要保持 DMA1 的时钟（见 s17c01），否则调试器无法在 CPU 睡眠时访问 SRAM，
+//! 因此 disable_unused 与 apply_sleep_gating 都接受一个 keep 列表，KEEP_FOR_RTT 即为此准备
+//! xxxLPENR 中 FLITF、SRAM1、SRAM2 这几个不属于外设的位，apply_sleep_gating 不会修改
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use rtt_target::rprintln;
+use stm32f4xx_hal::pac::{rcc::RegisterBlock, RCC};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    Ahb1 = 0,
+    Ahb2 = 1,
+    Ahb3 = 2,
+    Apb1 = 3,
+    Apb2 = 4,
+}
+
+const BUSES: [Bus; 5] = [Bus::Ahb1, Bus::Ahb2, Bus::Ahb3, Bus::Apb1, Bus::Apb2];
+
+impl Bus {
+    fn name(self) -> &'static str {
+        match self {
+            Bus::Ahb1 => "AHB1",
+            Bus::Ahb2 => "AHB2",
+            Bus::Ahb3 => "AHB3",
+            Bus::Apb1 => "APB1",
+            Bus::Apb2 => "APB2",
+        }
+    }
+}
+
+// 一个外设的时钟开关，也就是 xxxENR/xxxLPENR/xxxRSTR 中的同一个位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gate {
+    bus: Bus,
+    bit: u8,
+}
+
+impl Gate {
+    pub const fn new(bus: Bus, bit: u8) -> Self {
+        Self { bus, bit }
+    }
+
+    fn mask(self) -> u32 {
+        1 << self.bit
+    }
+
+    pub fn name(self) -> &'static str {
+        gates::TABLE
+            .iter()
+            .find(|(gate, _)| *gate == self)
+            .map(|(_, name)| *name)
+            .unwrap_or("?")
+    }
+}
+
+macro_rules! declare_gates {
+    ($($name:ident: $bus:ident, $bit:literal;)*) => {
+        $(pub const $name: Gate = Gate::new(Bus::$bus, $bit);)*
+
+        pub(super) const TABLE: &[(Gate, &str)] = &[$(($name, stringify!($name)),)*];
+    };
+}
+
+// STM32F413 上所有的外设时钟，见 Reference Manual 的 RCC register map
+pub mod gates {
+    use super::{Bus, Gate};
+
+    declare_gates! {
+        GPIOA: Ahb1, 0;
+        GPIOB: Ahb1, 1;
+        GPIOC: Ahb1, 2;
+        GPIOD: Ahb1, 3;
+        GPIOE: Ahb1, 4;
+        GPIOF: Ahb1, 5;
+        GPIOG: Ahb1, 6;
+        GPIOH: Ahb1, 7;
+        CRC: Ahb1, 12;
+        DMA1: Ahb1, 21;
+        DMA2: Ahb1, 22;
+
+        RNG: Ahb2, 6;
+        OTGFS: Ahb2, 7;
+
+        FSMC: Ahb3, 0;
+        QSPI: Ahb3, 1;
+
+        TIM2: Apb1, 0;
+        TIM3: Apb1, 1;
+        TIM4: Apb1, 2;
+        TIM5: Apb1, 3;
+        TIM6: Apb1, 4;
+        TIM7: Apb1, 5;
+        TIM12: Apb1, 6;
+        TIM13: Apb1, 7;
+        TIM14: Apb1, 8;
+        LPTIM1: Apb1, 9;
+        WWDG: Apb1, 11;
+        SPI2: Apb1, 14;
+        SPI3: Apb1, 15;
+        USART2: Apb1, 17;
+        USART3: Apb1, 18;
+        UART4: Apb1, 19;
+        UART5: Apb1, 20;
+        I2C1: Apb1, 21;
+        I2C2: Apb1, 22;
+        I2C3: Apb1, 23;
+        FMPI2C1: Apb1, 24;
+        CAN1: Apb1, 25;
+        CAN2: Apb1, 26;
+        CAN3: Apb1, 27;
+        PWR: Apb1, 28;
+        DAC: Apb1, 29;
+        UART7: Apb1, 30;
+        UART8: Apb1, 31;
+
+        TIM1: Apb2, 0;
+        TIM8: Apb2, 1;
+        USART1: Apb2, 4;
+        USART6: Apb2, 5;
+        UART9: Apb2, 6;
+        UART10: Apb2, 7;
+        ADC1: Apb2, 8;
+        SDIO: Apb2, 11;
+        SPI1: Apb2, 12;
+        SPI4: Apb2, 13;
+        SYSCFG: Apb2, 14;
+        EXTIT: Apb2, 15;
+        TIM9: Apb2, 16;
+        TIM10: Apb2, 17;
+        TIM11: Apb2, 18;
+        SPI5: Apb2, 20;
+        SAI1: Apb2, 22;
+        DFSDM1: Apb2, 24;
+        DFSDM2: Apb2, 25;
+    }
+}
+
+// 在 Sleep 模式下使用 RTT 时需要保留的时钟
+pub const KEEP_FOR_RTT: &[Gate] = &[gates::DMA1];
+
+// 每个总线上，表中列出的所有位
+fn known_mask(bus: Bus) -> u32 {
+    gates::TABLE
+        .iter()
+        .filter(|(gate, _)| gate.bus == bus)
+        .fold(0, |mask, (gate, _)| mask | gate.mask())
+}
+
+// 每个位的引用计数
+static G_CLAIMS: Mutex<RefCell<[[u8; 32]; 5]>> = Mutex::new(RefCell::new([[0; 32]; 5]));
+
+// 下面三组函数分别读写 xxxENR、xxxLPENR、xxxRSTR
+// F401/F411 没有 AHB3（FSMC 与 QUADSPI 都在 AHB3 上），也就没有对应的寄存器，读到的总是 0，写入被忽略
+// F412 的 pac 中只有 AHB3ENR 与 AHB3RSTR，没有 AHB3LPENR，因此 AHB3 在 Sleep 模式下的状态只在 F413 上读写
+// 只在 interrupt::free 中调用，因此读-改-写不会被打断
+fn rcc() -> &'static RegisterBlock {
+    unsafe { &*RCC::ptr() }
+}
+
+fn read_enr(bus: Bus) -> u32 {
+    let rcc = rcc();
+    match bus {
+        Bus::Ahb1 => rcc.ahb1enr.read().bits(),
+        Bus::Ahb2 => rcc.ahb2enr.read().bits(),
+        #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+        Bus::Ahb3 => rcc.ahb3enr.read().bits(),
+        #[cfg(not(any(feature = "stm32f412", feature = "stm32f413")))]
+        Bus::Ahb3 => 0,
+        Bus::Apb1 => rcc.apb1enr.read().bits(),
+        Bus::Apb2 => rcc.apb2enr.read().bits(),
+    }
+}
+
+fn write_enr(bus: Bus, bits: u32) {
+    let rcc = rcc();
+    unsafe {
+        match bus {
+            Bus::Ahb1 => rcc.ahb1enr.write(|w| w.bits(bits)),
+            Bus::Ahb2 => rcc.ahb2enr.write(|w| w.bits(bits)),
+            #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+            Bus::Ahb3 => rcc.ahb3enr.write(|w| w.bits(bits)),
+            #[cfg(not(any(feature = "stm32f412", feature = "stm32f413")))]
+            Bus::Ahb3 => {}
+            Bus::Apb1 => rcc.apb1enr.write(|w| w.bits(bits)),
+            Bus::Apb2 => rcc.apb2enr.write(|w| w.bits(bits)),
+        }
+    }
+    // 打开时钟之后，要等 2 个总线周期才能访问外设，读回一次即可（见 errata 与 Reference Manual 的 RCC 一节）
+    read_enr(bus);
+}
+
+fn read_lpenr(bus: Bus) -> u32 {
+    let rcc = rcc();
+    match bus {
+        Bus::Ahb1 => rcc.ahb1lpenr.read().bits(),
+        Bus::Ahb2 => rcc.ahb2lpenr.read().bits(),
+        #[cfg(feature = "stm32f413")]
+        Bus::Ahb3 => rcc.ahb3lpenr.read().bits(),
+        #[cfg(not(feature = "stm32f413"))]
+        Bus::Ahb3 => 0,
+        Bus::Apb1 => rcc.apb1lpenr.read().bits(),
+        Bus::Apb2 => rcc.apb2lpenr.read().bits(),
+    }
+}
+
+fn write_lpenr(bus: Bus, bits: u32) {
+    let rcc = rcc();
+    unsafe {
+        match bus {
+            Bus::Ahb1 => rcc.ahb1lpenr.write(|w| w.bits(bits)),
+            Bus::Ahb2 => rcc.ahb2lpenr.write(|w| w.bits(bits)),
+            #[cfg(feature = "stm32f413")]
+            Bus::Ahb3 => rcc.ahb3lpenr.write(|w| w.bits(bits)),
+            #[cfg(not(feature = "stm32f413"))]
+            Bus::Ahb3 => {}
+            Bus::Apb1 => rcc.apb1lpenr.write(|w| w.bits(bits)),
+            Bus::Apb2 => rcc.apb2lpenr.write(|w| w.bits(bits)),
+        }
+    }
+}
+
+fn modify_rstr(bus: Bus, f: impl Fn(u32) -> u32) {
+    let rcc = rcc();
+    unsafe {
+        match bus {
+            Bus::Ahb1 => rcc.ahb1rstr.modify(|r, w| w.bits(f(r.bits()))),
+            Bus::Ahb2 => rcc.ahb2rstr.modify(|r, w| w.bits(f(r.bits()))),
+            #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+            Bus::Ahb3 => rcc.ahb3rstr.modify(|r, w| w.bits(f(r.bits()))),
+            #[cfg(not(any(feature = "stm32f412", feature = "stm32f413")))]
+            Bus::Ahb3 => {}
+            Bus::Apb1 => rcc.apb1rstr.modify(|r, w| w.bits(f(r.bits()))),
+            Bus::Apb2 => rcc.apb2rstr.modify(|r, w| w.bits(f(r.bits()))),
+        }
+    }
+}
+
+// 申请使用一个外设，打开它的时钟
+pub fn claim(gate: Gate) {
+    cortex_m::interrupt::free(|cs| {
+        let mut claims = G_CLAIMS.borrow(cs).borrow_mut();
+        let count = &mut claims[gate.bus as usize][gate.bit as usize];
+        *count = count.saturating_add(1);
+        write_enr(gate.bus, read_enr(gate.bus) | gate.mask());
+    });
+}
+
+// 不再使用一个外设，没有其他使用者时关闭它的时钟，返回时钟是否真的被关闭了
+pub fn release(gate: Gate) -> bool {
+    cortex_m::interrupt::free(|cs| {
+        let mut claims = G_CLAIMS.borrow(cs).borrow_mut();
+        let count = &mut claims[gate.bus as usize][gate.bit as usize];
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            write_enr(gate.bus, read_enr(gate.bus) & !gate.mask());
+            true
+        } else {
+            false
+        }
+    })
+}
+
+// 通过 xxxRSTR 把外设的寄存器恢复为复位值，不影响时钟与引用计数
+pub fn reset(gate: Gate) {
+    cortex_m::interrupt::free(|_| {
+        modify_rstr(gate.bus, |bits| bits | gate.mask());
+        modify_rstr(gate.bus, |bits| bits & !gate.mask());
+    });
+}
+
+pub fn claim_count(gate: Gate) -> u8 {
+    cortex_m::interrupt::free(|cs| {
+        G_CLAIMS.borrow(cs).borrow()[gate.bus as usize][gate.bit as usize]
+    })
+}
+
+fn claimed_mask(claims: &[[u8; 32]; 5], bus: Bus) -> u32 {
+    claims[bus as usize]
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count > 0)
+        .fold(0, |mask, (bit, _)| mask | (1 << bit))
+}
+
+fn keep_mask(keep: &[Gate], bus: Bus) -> u32 {
+    keep.iter()
+        .filter(|gate| gate.bus == bus)
+        .fold(0, |mask, gate| mask | gate.mask())
+}
+
+// 关闭所有没有被 claim、也不在 keep 中的时钟，返回关闭了几个
+pub fn disable_unused(keep: &[Gate]) -> u32 {
+    cortex_m::interrupt::free(|cs| {
+        let claims = G_CLAIMS.borrow(cs).borrow();
+        let mut count = 0;
+        for bus in BUSES {
+            let enr = read_enr(bus);
+            let unused =
+                enr & known_mask(bus) & !claimed_mask(&claims, bus) & !keep_mask(keep, bus);
+            if unused != 0 {
+                for (gate, name) in gates::TABLE.iter().filter(|(gate, _)| gate.bus == bus) {
+                    if unused & gate.mask() != 0 {
+                        rprintln!("clock gate: disable unused {}\r", name);
+                    }
+                }
+                write_enr(bus, enr & !unused);
+                count += unused.count_ones();
+            }
+        }
+        count
+    })
+}
+
+// 让 Sleep 模式下只有被 claim 或在 keep 中的外设保持时钟
+//
+// 引用计数之后再有变化的话，需要重新调用一次
+pub fn apply_sleep_gating(keep: &[Gate]) {
+    cortex_m::interrupt::free(|cs| {
+        let claims = G_CLAIMS.borrow(cs).borrow();
+        for bus in BUSES {
+            let known = known_mask(bus);
+            let wanted = (claimed_mask(&claims, bus) | keep_mask(keep, bus)) & known;
+            write_lpenr(bus, (read_lpenr(bus) & !known) | wanted);
+        }
+    });
+}
+
+// 打印所有打开了时钟的外设
+//
+// 每一行的格式为：总线 名称 引用计数 [sleep]，其中 sleep 表示 Sleep 模式下依旧有时钟
+// 引用计数为 0 的外设，就是 disable_unused 会关闭的那些
+pub fn report() {
+    cortex_m::interrupt::free(|cs| {
+        let claims = G_CLAIMS.borrow(cs).borrow();
+        rprintln!("clocked peripherals:\r");
+        for bus in BUSES {
+            let enr = read_enr(bus) & known_mask(bus);
+            let lpenr = read_lpenr(bus);
+            for (gate, name) in gates::TABLE.iter().filter(|(gate, _)| gate.bus == bus) {
+                if enr & gate.mask() == 0 {
+                    continue;
+                }
+                let count = claims[bus as usize][gate.bit as usize];
+                rprintln!(
+                    "  {} {:<8} claims {}{}{}\r",
+                    bus.name(),
+                    name,
+                    count,
+                    if lpenr & gate.mask() != 0 {
+                        " sleep"
+                    } else {
+                        ""
+                    },
+                    if count == 0 { " (unclaimed)" } else { "" },
+                );
+            }
+        }
+    });
+}
+
+// 打开了时钟的外设个数
+pub fn clocked_count() -> u32 {
+    BUSES
+        .iter()
+        .map(|&bus| (read_enr(bus) & known_mask(bus)).count_ones())
+        .sum()
+}
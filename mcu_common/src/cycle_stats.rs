@@ -0,0 +1,102 @@
+//! 用 DWT 的周期计数器（CYCCNT）统计一段代码花费的 CPU 周期，主要用来测量中断处理函数
+//!
+//! 用法：
+//!
+//! - 初始化时调用 enable 打开 CYCCNT
+//! - 在要测量的代码之前记下 DWT::cycle_count()，结束时调用 record，或者直接用 measure 包住这段代码
+//! - 在主循环或者不那么频繁的地方调用 report，打印次数与最小、最大、平均周期数
+//!
+//! CycleStats 可以放在 static 中，在中断里记录，在别处打印
+//! record 本身（进出临界区、读 CYCCNT）也要花一些周期，比较改动前后的数字时这部分是相同的，可以不管
+//!
+//! CYCCNT 只有 32 位，单次测量的时间远小于它回绕一次的时间，直接 wrapping_sub 即可
+
+use core::cell::Cell;
+
+use cortex_m::{
+    interrupt::Mutex,
+    peripheral::{DCB, DWT},
+};
+use rtt_target::rprintln;
+
+#[derive(Debug, Clone, Copy)]
+struct Totals {
+    count: u32,
+    min: u32,
+    max: u32,
+    sum: u64,
+}
+
+impl Totals {
+    const EMPTY: Self = Self {
+        count: 0,
+        min: u32::MAX,
+        max: 0,
+        sum: 0,
+    };
+}
+
+pub struct CycleStats {
+    name: &'static str,
+    totals: Mutex<Cell<Totals>>,
+}
+
+impl CycleStats {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            totals: Mutex::new(Cell::new(Totals::EMPTY)),
+        }
+    }
+
+    // since 为之前读到的 DWT::cycle_count()
+    pub fn record(&self, since: u32) {
+        let cycles = DWT::cycle_count().wrapping_sub(since);
+        cortex_m::interrupt::free(|cs| {
+            let cell = self.totals.borrow(cs);
+            let mut totals = cell.get();
+            totals.count = totals.count.saturating_add(1);
+            totals.min = totals.min.min(cycles);
+            totals.max = totals.max.max(cycles);
+            totals.sum += cycles as u64;
+            cell.set(totals);
+        });
+    }
+
+    pub fn measure<R>(&self, f: impl FnOnce() -> R) -> R {
+        let since = DWT::cycle_count();
+        let result = f();
+        self.record(since);
+        result
+    }
+
+    pub fn count(&self) -> u32 {
+        cortex_m::interrupt::free(|cs| self.totals.borrow(cs).get().count)
+    }
+
+    pub fn reset(&self) {
+        cortex_m::interrupt::free(|cs| self.totals.borrow(cs).set(Totals::EMPTY));
+    }
+
+    pub fn report(&self) {
+        let totals = cortex_m::interrupt::free(|cs| self.totals.borrow(cs).get());
+        if totals.count == 0 {
+            rprintln!("{}: no samples\r", self.name);
+            return;
+        }
+        rprintln!(
+            "{}: n={} min={} max={} avg={} cycles\r",
+            self.name,
+            totals.count,
+            totals.min,
+            totals.max,
+            totals.sum / totals.count as u64
+        );
+    }
+}
+
+// 打开 DWT 的周期计数器，之后 DWT::cycle_count() 才会计数
+pub fn enable(dcb: &mut DCB, dwt: &mut DWT) {
+    dcb.enable_trace();
+    dwt.enable_cycle_counter();
+}
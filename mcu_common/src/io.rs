@@ -0,0 +1,232 @@
+//! 以字节流的形式使用 USART 与 RTT
+//!
+//! 命令行、帧的收发这些代码，只关心“读几个字节、写几个字节”，并不关心字节是从哪里来的，
+//! 这里为 USART 与 RTT 实现 embedded-io 的 Read、Write，以及 ReadReady、WriteReady，
+//! 上层代码写成对 embedded-io 的泛型之后，同一份代码就可以在任意一种传输方式上运行（见 s21c20、s22c01）
+//!
+//! 语义与 embedded-io 的约定相同：
+//!
+//! - read 至少读到 1 个字节才返回，能读多少就读多少，不会为了凑满 buf 而等待
+//! - write 至少写出 1 个字节才返回，返回实际写出的字节数，需要全部写出时使用 write_all
+//! - read_ready、write_ready 不会阻塞，可以在主循环中先检查，再决定是否读写，这就是非阻塞的用法
+//!
+//! USB CDC 的适配见 s13 的 utils::usb_io，它需要 usb-device，因此没有放在这里
+
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
+use rtt_target::{DownChannel, UpChannel};
+use stm32f4xx_hal::pac::usart1::RegisterBlock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsartError {
+    // 上一个字节还没有被读走，下一个字节就到了，至少丢失了一个字节
+    Overrun,
+    // 采样时发现了噪声
+    Noise,
+    // 没有在预期的位置检测到停止位，通常是波特率不对
+    Framing,
+    // 奇偶校验错误，只有打开了校验时才会出现
+    Parity,
+}
+
+impl core::fmt::Display for UsartError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let text = match self {
+            UsartError::Overrun => "overrun",
+            UsartError::Noise => "noise",
+            UsartError::Framing => "framing error",
+            UsartError::Parity => "parity error",
+        };
+        f.write_str(text)
+    }
+}
+
+// embedded-io 要求错误类型实现 core::error::Error
+impl core::error::Error for UsartError {}
+
+impl embedded_io::Error for UsartError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            UsartError::Overrun => ErrorKind::Other,
+            _ => ErrorKind::InvalidData,
+        }
+    }
+}
+
+// 以轮询的方式使用一个已经配置好的 USART（见各程序中的 setup_usart1），USART1 ~ USART10 的寄存器结构相同
+//
+// 不要与 USART 的接收中断同时使用：中断里读走 DR 之后，这里就永远等不到 RXNE 了
+pub struct Usart<'a> {
+    usart: &'a RegisterBlock,
+}
+
+impl<'a> Usart<'a> {
+    pub fn new(usart: &'a RegisterBlock) -> Self {
+        Self { usart }
+    }
+
+    // RXNE 置位时取出一个字节，先读 SR 再读 DR，可以同时清除 RXNE 与各个错误标志
+    fn try_read_byte(&mut self) -> Option<Result<u8, UsartError>> {
+        let sr = self.usart.sr.read();
+        if sr.rxne().bit_is_clear() && sr.ore().bit_is_clear() {
+            return None;
+        }
+        let byte = self.usart.dr.read().dr().bits() as u8;
+
+        Some(if sr.ore().bit_is_set() {
+            Err(UsartError::Overrun)
+        } else if sr.nf().bit_is_set() {
+            Err(UsartError::Noise)
+        } else if sr.fe().bit_is_set() {
+            Err(UsartError::Framing)
+        } else if sr.pe().bit_is_set() {
+            Err(UsartError::Parity)
+        } else {
+            Ok(byte)
+        })
+    }
+}
+
+impl ErrorType for Usart<'_> {
+    type Error = UsartError;
+}
+
+impl Read for Usart<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // 第一个字节阻塞等待，之后只取已经到达的字节
+        let first = loop {
+            if let Some(result) = self.try_read_byte() {
+                break result?;
+            }
+        };
+        buf[0] = first;
+
+        let mut len = 1;
+        while len < buf.len() {
+            match self.try_read_byte() {
+                Some(Ok(byte)) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                // 错误标志在读 DR 时就已经被清除了，没有办法留到下一次再报告，
+                // 因此先把已经读到的字节交给调用者，出错的那个字节被丢弃
+                Some(Err(_)) | None => break,
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl ReadReady for Usart<'_> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        let sr = self.usart.sr.read();
+        Ok(sr.rxne().bit_is_set() || sr.ore().bit_is_set())
+    }
+}
+
+impl Write for Usart<'_> {
+    // 一次写出所有字节，USART 只有一个字节的发送缓冲，等待的时间就是发送这些字节的时间
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for &byte in buf {
+            while self.usart.sr.read().txe().bit_is_clear() {}
+            self.usart.dr.write(|w| w.dr().bits(byte as u16));
+        }
+        Ok(buf.len())
+    }
+
+    // 等待最后一个字节的停止位也发送完成，之后才可以关闭 USART 或者进入低功耗模式
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        while self.usart.sr.read().tc().bit_is_clear() {}
+        Ok(())
+    }
+}
+
+impl WriteReady for Usart<'_> {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.usart.sr.read().txe().bit_is_set())
+    }
+}
+
+// 一对 RTT 通道，up 通道发往 Host，down 通道来自 Host，由 rtt_init! 创建
+//
+// Host 端可以用 probe-rs 的 RTT 终端，或者 OpenOCD 的 rtt server（把通道映射为一个 TCP 端口）来收发
+// 不要与 rprintln 使用同一个 up 通道，否则日志会与数据混在一起
+pub struct Rtt {
+    up: UpChannel,
+    down: DownChannel,
+    // read_ready 时预先读出的一个字节，RTT 没有“查看有没有数据”的接口，只能先读出来
+    peeked: Option<u8>,
+}
+
+impl Rtt {
+    pub fn new(up: UpChannel, down: DownChannel) -> Self {
+        Self {
+            up,
+            down,
+            peeked: None,
+        }
+    }
+}
+
+impl ErrorType for Rtt {
+    type Error = core::convert::Infallible;
+}
+
+impl Read for Rtt {
+    // 没有连接 Host 时会一直等待
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(byte) = self.peeked.take() {
+            buf[0] = byte;
+            return Ok(1 + self.down.read(&mut buf[1..]));
+        }
+        loop {
+            let len = self.down.read(buf);
+            if len > 0 {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+impl ReadReady for Rtt {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        if self.peeked.is_none() {
+            let mut byte = [0u8];
+            if self.down.read(&mut byte) == 1 {
+                self.peeked = Some(byte[0]);
+            }
+        }
+        Ok(self.peeked.is_some())
+    }
+}
+
+impl Write for Rtt {
+    // 通道的缓冲满了时，取决于通道的模式：默认的 NoBlockSkip 下，放不下的数据会被整个丢弃，
+    // 数据不能丢失时，应该在 rtt_init! 中把这个通道设置为 BlockIfFull
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let written = self.up.write(buf);
+        // NoBlockSkip 丢弃数据时返回 0，embedded-io 不允许返回 0，就当作已经写出了
+        Ok(if written == 0 { buf.len() } else { written })
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl WriteReady for Rtt {
+    // RTT 不提供剩余空间的查询，write 总是会立刻返回
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
@@ -0,0 +1,185 @@
+//! NVIC 的优先级与分组
+//!
+//! Cortex-M4 的每个中断有一个 8 bit 的优先级寄存器（IPR），但 STM32F4 只实现了其中的高 4 位（NVIC_PRIO_BITS），
+//! 低 4 位写入什么都会被忽略，因此 NVIC::set_priority 的参数必须是 16 的倍数才有意义：
+//! 直接写 2、4、8 的话，它们全都会变成 0，也就是同一个优先级
+//!
+//! 这 4 位又由 SCB 的 AIRCR 寄存器中的 PRIGROUP 字段划分为两部分：
+//!
+//! - 抢占优先级（preempt）：数值小的中断可以打断数值大的中断的处理函数
+//! - 子优先级（sub）：抢占优先级相同时，两个中断同时挂起，先处理子优先级数值小的那个，但不会互相打断
+//!
+//! 复位之后 PRIGROUP 为 0，对于 4 位的优先级来说，等同于 Grouping::Preempt16Sub1，即全部 4 位都是抢占优先级
+//!
+//! 这里的 Priority 只能通过 const fn 创建，写成 const 时，超出范围的优先级会直接导致编译失败
+
+use cortex_m::peripheral::{NVIC, SCB};
+use rtt_target::rprintln;
+use stm32f4xx_hal::pac::{Interrupt, NVIC_PRIO_BITS};
+
+// AIRCR 的写入必须带上这个 key，否则写入无效
+const AIRCR_VECTKEY: u32 = 0x05FA << 16;
+const AIRCR_PRIGROUP_SHIFT: u32 = 8;
+const AIRCR_PRIGROUP_MASK: u32 = 0b111 << AIRCR_PRIGROUP_SHIFT;
+
+// F413 的中断个数，见 Reference Manual 的 Vector table
+const IRQ_COUNT: usize = 102;
+
+// 抢占优先级与子优先级的划分，后面的数字为各自的级数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    Preempt16Sub1 = 3,
+    Preempt8Sub2 = 4,
+    Preempt4Sub4 = 5,
+    Preempt2Sub8 = 6,
+    Preempt1Sub16 = 7,
+}
+
+impl Grouping {
+    // 抢占优先级所占的位数
+    pub const fn preempt_bits(self) -> u8 {
+        7 - self as u8
+    }
+
+    pub const fn sub_bits(self) -> u8 {
+        NVIC_PRIO_BITS - self.preempt_bits()
+    }
+
+    pub const fn preempt_levels(self) -> u8 {
+        1 << self.preempt_bits()
+    }
+
+    pub const fn sub_levels(self) -> u8 {
+        1 << self.sub_bits()
+    }
+
+    fn from_prigroup(prigroup: u8) -> Self {
+        match prigroup {
+            // 0 ~ 2 时，低于 bit 4 的部分本来就没有实现，与 3 的效果相同
+            0..=3 => Grouping::Preempt16Sub1,
+            4 => Grouping::Preempt8Sub2,
+            5 => Grouping::Preempt4Sub4,
+            6 => Grouping::Preempt2Sub8,
+            _ => Grouping::Preempt1Sub16,
+        }
+    }
+}
+
+// 已经编码好的优先级，可以直接写入 IPR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority(u8);
+
+impl Priority {
+    // 在默认的分组（Preempt16Sub1）下，常用的几个级别
+    pub const HIGHEST: Priority = Priority::preempt(0);
+    pub const HIGH: Priority = Priority::preempt(4);
+    pub const NORMAL: Priority = Priority::preempt(8);
+    pub const LOW: Priority = Priority::preempt(12);
+    pub const LOWEST: Priority = Priority::preempt(15);
+
+    // 默认分组下的优先级，0 ~ 15
+    pub const fn preempt(level: u8) -> Self {
+        Self::new(Grouping::Preempt16Sub1, level, 0)
+    }
+
+    // 指定分组下的优先级
+    //
+    // 注意，这里的 grouping 必须与 set_grouping 设置的一致，否则编码出来的值会被 NVIC 按另一种方式解读
+    pub const fn new(grouping: Grouping, preempt: u8, sub: u8) -> Self {
+        assert!(
+            preempt < grouping.preempt_levels(),
+            "preempt priority out of range for this grouping"
+        );
+        assert!(
+            sub < grouping.sub_levels(),
+            "sub priority out of range for this grouping"
+        );
+        let value = (preempt << grouping.sub_bits()) | sub;
+        Self(value << (8 - NVIC_PRIO_BITS))
+    }
+
+    pub const fn raw(self) -> u8 {
+        self.0
+    }
+
+    pub fn preempt_level(self, grouping: Grouping) -> u8 {
+        (self.0 >> (8 - NVIC_PRIO_BITS)) >> grouping.sub_bits()
+    }
+
+    pub fn sub_level(self, grouping: Grouping) -> u8 {
+        (self.0 >> (8 - NVIC_PRIO_BITS)) & (grouping.sub_levels() - 1)
+    }
+}
+
+pub fn set_grouping(grouping: Grouping) {
+    // 修改分组会改变所有已设置优先级的含义，应该在设置任何优先级之前调用
+    let scb = unsafe { &*SCB::PTR };
+    unsafe {
+        scb.aircr.modify(|v| {
+            (v & !(AIRCR_PRIGROUP_MASK | 0xFFFF << 16))
+                | AIRCR_VECTKEY
+                | ((grouping as u32) << AIRCR_PRIGROUP_SHIFT)
+        })
+    };
+}
+
+pub fn grouping() -> Grouping {
+    let scb = unsafe { &*SCB::PTR };
+    Grouping::from_prigroup(
+        ((scb.aircr.read() & AIRCR_PRIGROUP_MASK) >> AIRCR_PRIGROUP_SHIFT) as u8,
+    )
+}
+
+pub fn set_priority(nvic: &mut NVIC, irq: Interrupt, priority: Priority) {
+    // 在中断已经启用的情况下修改优先级，可能会打破其它代码对中断顺序的假设，因此 cortex-m 将其标记为 unsafe
+    // 这里的封装只保证写入的值是合法的，调用的时机依旧需要自己把握
+    unsafe { nvic.set_priority(irq, priority.raw()) };
+}
+
+pub fn priority(irq: Interrupt) -> Priority {
+    Priority(NVIC::get_priority(irq))
+}
+
+// 设置优先级，清除挂起状态，然后启用中断
+pub fn enable(nvic: &mut NVIC, irq: Interrupt, priority: Priority) {
+    set_priority(nvic, irq, priority);
+    NVIC::unpend(irq);
+    unsafe { NVIC::unmask(irq) };
+}
+
+pub fn disable(irq: Interrupt) {
+    NVIC::mask(irq);
+}
+
+// 打印当前的分组，以及所有已启用、挂起或正在处理的中断
+pub fn dump() {
+    let grouping = grouping();
+    rprintln!(
+        "NVIC grouping: {:?} ({} preempt bits, {} sub bits)",
+        grouping,
+        grouping.preempt_bits(),
+        grouping.sub_bits()
+    );
+
+    let nvic = unsafe { &*NVIC::PTR };
+    for n in 0..IRQ_COUNT {
+        let (reg, bit) = (n / 32, 1 << (n % 32));
+        let enabled = nvic.iser[reg].read() & bit != 0;
+        let pending = nvic.ispr[reg].read() & bit != 0;
+        let active = nvic.iabr[reg].read() & bit != 0;
+        if !(enabled || pending || active) {
+            continue;
+        }
+
+        let priority = Priority(nvic.ipr[n].read());
+        rprintln!(
+            "IRQ {:3}: preempt {:2}, sub {:2}{}{}{}",
+            n,
+            priority.preempt_level(grouping),
+            priority.sub_level(grouping),
+            if enabled { ", enabled" } else { "" },
+            if pending { ", pending" } else { "" },
+            if active { ", active" } else { "" },
+        );
+    }
+}
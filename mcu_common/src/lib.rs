@@ -0,0 +1,23 @@
+//! 几个章节都用到的 utils 模块
+//!
+//! 各章的程序都放在 src/bin 下，共用的代码放在 src/bin/utils 中，由每个程序 mod utils 引入，
+//! 这对只在一章里用到的驱动很方便，但像 resources 这样与具体外设无关的模块，被几章各复制了一份，
+//! 修了其中一份的问题，其它几份还留着，时间一长就对不上了
+//!
+//! 这些模块现在只保留这里的一份，各章在 utils/mod.rs 中用 pub(crate) use 引入，
+//! 原来的 utils::resources 这样的路径保持不变，程序与 utils 中的其它模块都不需要修改
+//!
+//! 与 chip_caps 一样，芯片由各章转发过来的 stm32f401 / stm32f411 / stm32f412 / stm32f413 特性选择
+
+#![no_std]
+
+pub mod clock_gate;
+pub mod cycle_stats;
+pub mod io;
+pub mod irq;
+pub mod loopback;
+pub mod optcr;
+pub mod pid;
+pub mod reg_batch;
+pub mod resources;
+pub mod ws2812;
@@ -0,0 +1,343 @@
+//! 同一块芯片上两个外设之间的回环自检
+//!
+//! s03c02（SPI1 -> SPI2）和 s04c01（I2C1 -> I2C3）都是靠调整 NVIC 的优先级，让接收方的中断总是先于发送方被处理，
+//! 这样能跑通一次演示，但结果只能靠肉眼看 RTT 的输出，而且换一个时钟频率、多加一行打印，时序就可能变了
+//!
+//! 这里把两个外设分为发起方（initiator，SPI/I2C 的主机）与响应方（responder，从机），两者都实现 Side，
+//! 由 Harness 在同一个上下文中轮流轮询，不使用中断，也就不需要依赖中断优先级：
+//!
+//! 1. 响应方先 begin，准备好要发出的第一个字节后设置 RESPONDER_READY
+//! 2. Harness 看到 RESPONDER_READY 之后，才让发起方 begin，并设置 INITIATOR_STARTED
+//! 3. 之后每一轮都先轮询响应方、再轮询发起方，完成的一方设置各自的 DONE
+//!    发起方若要求响应方在每个字节之前都准备好（比如 SPI 从机必须先把数据写进 DR），可以在发送前等待并清除 RESPONDER_READY
+//! 4. 双方都完成后，检查响应方收到的是否为发起方发出的数据，发起方收到的是否为响应方发出的数据
+//!
+//! 超过 max_polls 轮还没有完成，就判定为超时，并打印当时的事件标识，以便判断卡在了哪一步
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use rtt_target::rprintln;
+
+// 单次传输中，每个方向最多的字节数
+pub const FRAME_MAX: usize = 16;
+
+// 响应方已经准备好发出下一个字节
+pub const RESPONDER_READY: u32 = 1 << 0;
+pub const INITIATOR_STARTED: u32 = 1 << 1;
+pub const RESPONDER_DONE: u32 = 1 << 2;
+pub const INITIATOR_DONE: u32 = 1 << 3;
+// 任意一方出错
+pub const FAILED: u32 = 1 << 4;
+
+// 两方共享的事件标识
+// 目前两方都在主循环中轮询，不过用原子量实现之后，Side 也可以在中断中设置标识
+pub struct EventFlags(AtomicU32);
+
+impl EventFlags {
+    pub const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    pub fn set(&self, flags: u32) {
+        self.0.fetch_or(flags, Ordering::Release);
+    }
+
+    pub fn clear(&self, flags: u32) {
+        self.0.fetch_and(!flags, Ordering::Release);
+    }
+
+    // flags 中的每一位都被设置时才返回 true
+    pub fn contains(&self, flags: u32) -> bool {
+        self.bits() & flags == flags
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    fn reset(&self) {
+        self.0.store(0, Ordering::Release);
+    }
+}
+
+impl Default for EventFlags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Pending,
+    Done,
+    Failed(&'static str),
+}
+
+// 一方的收发缓冲，各个 Side 用它记录发到了哪里、收到了哪里
+pub struct Frame {
+    tx: [u8; FRAME_MAX],
+    tx_len: usize,
+    tx_index: usize,
+    rx: [u8; FRAME_MAX],
+    rx_len: usize,
+    rx_index: usize,
+}
+
+impl Frame {
+    pub const fn new() -> Self {
+        Self {
+            tx: [0; FRAME_MAX],
+            tx_len: 0,
+            tx_index: 0,
+            rx: [0; FRAME_MAX],
+            rx_len: 0,
+            rx_index: 0,
+        }
+    }
+
+    // 超过 FRAME_MAX 的部分会被截断，Harness 会提前拒绝这样的 Case
+    pub fn load(&mut self, tx: &[u8], rx_len: usize) {
+        self.tx_len = tx.len().min(FRAME_MAX);
+        self.tx[..self.tx_len].copy_from_slice(&tx[..self.tx_len]);
+        self.tx_index = 0;
+        self.rx_len = rx_len.min(FRAME_MAX);
+        self.rx_index = 0;
+    }
+
+    pub fn next_tx(&mut self) -> Option<u8> {
+        let byte = self.tx[..self.tx_len].get(self.tx_index).copied()?;
+        self.tx_index += 1;
+        Some(byte)
+    }
+
+    // 超出 rx_len 的字节会被丢弃，返回 false
+    pub fn push_rx(&mut self, byte: u8) -> bool {
+        if self.rx_index == self.rx_len {
+            return false;
+        }
+        self.rx[self.rx_index] = byte;
+        self.rx_index += 1;
+        true
+    }
+
+    pub fn tx_remaining(&self) -> usize {
+        self.tx_len - self.tx_index
+    }
+
+    pub fn rx_remaining(&self) -> usize {
+        self.rx_len - self.rx_index
+    }
+
+    pub fn received(&self) -> &[u8] {
+        &self.rx[..self.rx_index]
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 参与回环的一方
+pub trait Side {
+    fn name(&self) -> &'static str;
+
+    // 准备一次传输：要发出的数据，以及期望收到的字节数
+    fn begin(&mut self, flags: &EventFlags, tx: &[u8], rx_len: usize);
+
+    // 推进传输，不能阻塞
+    fn poll(&mut self, flags: &EventFlags) -> Step;
+
+    // 超时或出错时，让外设回到空闲状态，以便继续下一个 Case
+    fn abort(&mut self);
+
+    fn received(&self) -> &[u8];
+}
+
+// 一组测试数据
+pub struct Case {
+    pub name: &'static str,
+    // 由发起方发给响应方
+    pub initiator_tx: &'static [u8],
+    // 由响应方发给发起方
+    pub responder_tx: &'static [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    // side 在第 index 个字节收到了 actual，应为 expected，None 表示长度不一致
+    Mismatch {
+        side: &'static str,
+        index: usize,
+        expected: Option<u8>,
+        actual: Option<u8>,
+    },
+    // 超时时的事件标识
+    Timeout {
+        flags: u32,
+    },
+    Error {
+        side: &'static str,
+        reason: &'static str,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Summary {
+    pub passed: u32,
+    pub failed: u32,
+}
+
+impl Summary {
+    pub fn all_passed(&self) -> bool {
+        self.failed == 0
+    }
+
+    pub fn report(&self) {
+        rprintln!(
+            "{} passed, {} failed: {}",
+            self.passed,
+            self.failed,
+            if self.all_passed() { "PASS" } else { "FAIL" }
+        );
+    }
+}
+
+pub struct Harness<I: Side, R: Side> {
+    initiator: I,
+    responder: R,
+    flags: EventFlags,
+    max_polls: u32,
+}
+
+impl<I: Side, R: Side> Harness<I, R> {
+    pub fn new(initiator: I, responder: R, max_polls: u32) -> Self {
+        Self {
+            initiator,
+            responder,
+            flags: EventFlags::new(),
+            max_polls,
+        }
+    }
+
+    pub fn run_case(&mut self, case: &Case) -> Outcome {
+        if case.initiator_tx.len() > FRAME_MAX || case.responder_tx.len() > FRAME_MAX {
+            return Outcome::Error {
+                side: "harness",
+                reason: "case longer than FRAME_MAX",
+            };
+        }
+
+        let flags = &self.flags;
+        flags.reset();
+
+        self.responder
+            .begin(flags, case.responder_tx, case.initiator_tx.len());
+
+        let mut polls = 0;
+        while !flags.contains(RESPONDER_DONE | INITIATOR_DONE) {
+            if polls == self.max_polls {
+                let bits = flags.bits();
+                self.initiator.abort();
+                self.responder.abort();
+                return Outcome::Timeout { flags: bits };
+            }
+            polls += 1;
+
+            // 响应方总是先于发起方被轮询
+            if !flags.contains(RESPONDER_DONE) {
+                match self.responder.poll(flags) {
+                    Step::Pending => {}
+                    Step::Done => flags.set(RESPONDER_DONE),
+                    Step::Failed(reason) => {
+                        flags.set(FAILED);
+                        self.initiator.abort();
+                        self.responder.abort();
+                        return Outcome::Error {
+                            side: self.responder.name(),
+                            reason,
+                        };
+                    }
+                }
+            }
+
+            if !flags.contains(INITIATOR_STARTED) {
+                if flags.contains(RESPONDER_READY) {
+                    self.initiator
+                        .begin(flags, case.initiator_tx, case.responder_tx.len());
+                    flags.set(INITIATOR_STARTED);
+                }
+                continue;
+            }
+
+            if !flags.contains(INITIATOR_DONE) {
+                match self.initiator.poll(flags) {
+                    Step::Pending => {}
+                    Step::Done => flags.set(INITIATOR_DONE),
+                    Step::Failed(reason) => {
+                        flags.set(FAILED);
+                        self.initiator.abort();
+                        self.responder.abort();
+                        return Outcome::Error {
+                            side: self.initiator.name(),
+                            reason,
+                        };
+                    }
+                }
+            }
+        }
+
+        let checks = [
+            (
+                self.responder.name(),
+                case.initiator_tx,
+                self.responder.received(),
+            ),
+            (
+                self.initiator.name(),
+                case.responder_tx,
+                self.initiator.received(),
+            ),
+        ];
+        for (side, expected, actual) in checks {
+            if let Some((index, expected, actual)) = first_mismatch(expected, actual) {
+                return Outcome::Mismatch {
+                    side,
+                    index,
+                    expected,
+                    actual,
+                };
+            }
+        }
+
+        Outcome::Pass
+    }
+
+    // 依次运行所有的 Case，并打印每一项的结果
+    pub fn run_all(&mut self, cases: &[Case]) -> Summary {
+        let mut summary = Summary::default();
+        for case in cases {
+            match self.run_case(case) {
+                Outcome::Pass => {
+                    summary.passed += 1;
+                    rprintln!("[PASS] {}", case.name);
+                }
+                outcome => {
+                    summary.failed += 1;
+                    rprintln!("[FAIL] {}: {:?}", case.name, outcome);
+                }
+            }
+        }
+        summary
+    }
+}
+
+// 找到第一个不一致的字节，长度不同时，较短一方的位置为 None
+fn first_mismatch(expected: &[u8], actual: &[u8]) -> Option<(usize, Option<u8>, Option<u8>)> {
+    (0..expected.len().max(actual.len())).find_map(|index| {
+        let pair = (expected.get(index).copied(), actual.get(index).copied());
+        (pair.0 != pair.1).then_some((index, pair.0, pair.1))
+    })
+}
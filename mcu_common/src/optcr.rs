@@ -0,0 +1,100 @@
+//! 修改 FLASH_OPTCR 中的 Option Bytes
+//!
+//! s14 的 utils::option_bytes 修改 RDP、nWRP 与 BOR_LEV，s17 的 utils::supervisor 只修改 BOR_LEV，
+//! 两者的 BorLevel 与解锁、写入的流程原来各写了一份，现在都放在这里
+//!
+//! 修改的流程为：
+//!
+//! 1. 向 FLASH_OPTKEYR 依次写入 0x08192A3B 与 0x4C5D6E7F，解锁 FLASH_OPTCR（OPTLOCK 变为 0）
+//! 2. 等待 FLASH_SR 的 BSY 为 0
+//! 3. 修改 FLASH_OPTCR 中的值
+//! 4. 置位 OPTSTRT，等待 BSY 为 0
+//! 5. 置位 OPTLOCK，重新上锁
+//!
+//! 哪些修改是安全的（比如 BOR 阈值不能高于供电电压，RDP 降级会擦除整个 FLASH），由调用者自己检查
+
+use stm32f4xx_hal::pac::{flash::optcr, FLASH};
+
+const OPTKEY1: u32 = 0x0819_2A3B;
+const OPTKEY2: u32 = 0x4C5D_6E7F;
+
+// BOR 的阈值，Off 时只剩下 POR/PDR（约 1.8 V）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BorLevel {
+    Off,
+    // 约 2.1 V
+    Level1,
+    // 约 2.4 V
+    Level2,
+    // 约 2.7 V
+    Level3,
+}
+
+impl BorLevel {
+    // BOR_LEV 的编码与等级的高低是反过来的：11 为关闭，00 为最高的 Level 3
+    pub fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0b11 => Self::Off,
+            0b10 => Self::Level1,
+            0b01 => Self::Level2,
+            _ => Self::Level3,
+        }
+    }
+
+    pub fn bits(self) -> u8 {
+        match self {
+            Self::Off => 0b11,
+            Self::Level1 => 0b10,
+            Self::Level2 => 0b01,
+            Self::Level3 => 0b00,
+        }
+    }
+
+    // VDD 下降时的阈值
+    pub fn millivolts(self) -> u16 {
+        match self {
+            Self::Off => 1800,
+            Self::Level1 => 2100,
+            Self::Level2 => 2400,
+            Self::Level3 => 2700,
+        }
+    }
+}
+
+// FLASH_OPTCR 没能解锁
+//
+// 钥匙写错一次之后，OPTKEYR 会一直锁住，直到下一次复位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locked;
+
+pub fn bor_level(flash: &FLASH) -> BorLevel {
+    BorLevel::from_bits(flash.optcr.read().bor_lev().bits())
+}
+
+// 按照上面的流程修改 FLASH_OPTCR，update 与寄存器的 modify 一样，只需要写入要修改的字段
+//
+// 返回之后 OPTCR 已经重新上锁，FLASH_SR 中的错误标志由调用者检查
+pub fn modify<F>(flash: &FLASH, update: F) -> Result<(), Locked>
+where
+    F: for<'w> FnOnce(&optcr::R, &'w mut optcr::W) -> &'w mut optcr::W,
+{
+    if flash.optcr.read().optlock().bit_is_set() {
+        flash.optkeyr.write(|w| w.optkey().bits(OPTKEY1));
+        flash.optkeyr.write(|w| w.optkey().bits(OPTKEY2));
+        if flash.optcr.read().optlock().bit_is_set() {
+            return Err(Locked);
+        }
+    }
+
+    while flash.sr.read().bsy().bit_is_set() {}
+    flash.optcr.modify(|r, w| {
+        update(r, w);
+        w
+    });
+    flash.optcr.modify(|_, w| w.optstrt().set_bit());
+    while flash.sr.read().bsy().bit_is_set() {}
+
+    flash.optcr.modify(|_, w| w.optlock().set_bit());
+
+    Ok(())
+}
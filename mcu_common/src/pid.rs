@@ -0,0 +1,73 @@
+//! PID 控制器，s06 的循迹小车与 s21 的热电偶温控都在用
+//!
+//! output = kp * e + ki * ∫e dt - kd * d(measurement)/dt，其中 e = setpoint - measurement
+//!
+//! 几个常见的处理：
+//! - 微分项使用测量值而不是误差的变化率，修改设定值时输出不会突然跳一下（derivative kick）
+//! - 输出被限制在 out_min ~ out_max 之间，输出已经饱和、且误差还在把输出往外推时，积分项停止累加，避免积分饱和（windup）
+//! - 积分项本身也被限制在输出范围之内
+//!
+//! 时间间隔由调用者给出，单位为秒，因此参数与采样间隔无关，修改采样间隔之后不需要重新整定
+
+#[derive(Debug, Clone, Copy)]
+pub struct PidConfig {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    pub out_min: f32,
+    pub out_max: f32,
+}
+
+pub struct Pid {
+    config: PidConfig,
+    integral: f32,
+    last_measurement: Option<f32>,
+}
+
+impl Pid {
+    pub const fn new(config: PidConfig) -> Self {
+        Self {
+            config,
+            integral: 0.0,
+            last_measurement: None,
+        }
+    }
+
+    pub fn config(&self) -> &PidConfig {
+        &self.config
+    }
+
+    // 清空积分项与微分项的历史，比如控制对象出现故障、重新开始控制时
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.last_measurement = None;
+    }
+
+    // dt_s 为距离上一次 update 的时间
+    pub fn update(&mut self, setpoint: f32, measurement: f32, dt_s: f32) -> f32 {
+        let PidConfig {
+            kp,
+            ki,
+            kd,
+            out_min,
+            out_max,
+        } = self.config;
+
+        let error = setpoint - measurement;
+
+        let derivative = match self.last_measurement {
+            Some(last) if dt_s > 0.0 => (measurement - last) / dt_s,
+            _ => 0.0,
+        };
+        self.last_measurement = Some(measurement);
+
+        let unclamped = kp * error + self.integral + ki * error * dt_s - kd * derivative;
+        let saturated_high = unclamped > out_max && error > 0.0;
+        let saturated_low = unclamped < out_min && error < 0.0;
+        if !saturated_high && !saturated_low {
+            self.integral = (self.integral + ki * error * dt_s).clamp(out_min, out_max);
+        }
+
+        (kp * error + self.integral - kd * derivative).clamp(out_min, out_max)
+    }
+}
@@ -0,0 +1,127 @@
+//! 把对同一个寄存器的多处字段修改合并为一次读-改-写，或者一次直接写入
+//!
+//! svd2rust 的 modify 每调用一次，就是一次完整的读-改-写：从外设总线读出寄存器，在闭包里修改，再写回去
+//! APB 上的一次读写要好几个总线周期，中断处理函数中对同一个寄存器连续 modify 两三次，这部分开销就翻了几倍，
+//! 而且两次 modify 之间，寄存器处于“只改了一半”的状态
+//!
+//! Batch 把若干字段的修改合并为一个 mask 与一个 value，二者都在编译期算好：
+//!
+//! - apply：new = (old & !mask) | value，放在一次 modify 中，一次读-改-写完成所有修改
+//! - value：如果寄存器中真正用到的位都在 mask 里（比如 TIM 的 DIER 只开了一个 DMA 请求），
+//!   可以直接 write(value)，连读都省了
+//!
+//! 状态寄存器中的标识位大多不能用读-改-写清除：
+//!
+//! - rc_w0（TIMx_SR 等）：写 0 清除，写 1 不变，读-改-写会把“读之后、写之前”新置位的标识一并清除掉，
+//!   用 clear_rc_w0 算出要写入的值，直接 write，只清除指定的标识
+//! - rc_w1 或者专门的清除寄存器（DMA 的 LIFCR/HIFCR 等）：本来就是直接 write，多个标识合并到同一次 write 即可
+//!
+//! Batch 应当声明为 const，这样字段的值超出宽度时编译就会失败，而不是在中断里悄悄写错别的位
+//!
+//! 用法：
+//!
+//! const RUN: Batch = Batch::new().set(CR1_DIR, 0).set(CR1_CEN, 1);
+//! tim.cr1.modify(|r, w| unsafe { w.bits(RUN.apply(r.bits())) });
+//! tim.cr1.write(|w| unsafe { w.bits(RUN.value()) });
+
+// 寄存器中的一个字段，shift 为最低位的位置，width 为位数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    shift: u8,
+    width: u8,
+}
+
+impl Field {
+    pub const fn new(shift: u8, width: u8) -> Self {
+        assert!(
+            width > 0 && shift as u32 + width as u32 <= 32,
+            "field does not fit in a 32-bit register"
+        );
+        Self { shift, width }
+    }
+
+    // 只有一位的字段
+    pub const fn bit(shift: u8) -> Self {
+        Self::new(shift, 1)
+    }
+
+    pub const fn mask(self) -> u32 {
+        (u32::MAX >> (32 - self.width as u32)) << self.shift
+    }
+
+    // 从寄存器的值中取出这个字段
+    pub const fn get(self, bits: u32) -> u32 {
+        (bits & self.mask()) >> self.shift
+    }
+}
+
+// 若干个字段的修改，mask 中的位被改写为 value 中对应的位，其余的位保持不变
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batch {
+    mask: u32,
+    value: u32,
+}
+
+impl Batch {
+    pub const fn new() -> Self {
+        Self { mask: 0, value: 0 }
+    }
+
+    // 同一个字段设置多次时，以最后一次为准
+    pub const fn set(self, field: Field, value: u32) -> Self {
+        let mask = field.mask();
+        assert!(
+            value <= mask >> field.shift,
+            "value does not fit in the field"
+        );
+        Self {
+            mask: self.mask | mask,
+            value: (self.value & !mask) | (value << field.shift),
+        }
+    }
+
+    pub const fn enable(self, field: Field) -> Self {
+        self.set(field, 1)
+    }
+
+    pub const fn disable(self, field: Field) -> Self {
+        self.set(field, 0)
+    }
+
+    // 合并两个 Batch，重叠的位以 other 为准
+    pub const fn merge(self, other: Batch) -> Self {
+        Self {
+            mask: self.mask | other.mask,
+            value: (self.value & !other.mask) | other.value,
+        }
+    }
+
+    pub const fn mask(self) -> u32 {
+        self.mask
+    }
+
+    pub const fn value(self) -> u32 {
+        self.value
+    }
+
+    // mask 是否覆盖了 used 中的所有位，覆盖时可以用 value 直接 write，而不必读-改-写
+    pub const fn covers(self, used: u32) -> bool {
+        used & !self.mask == 0
+    }
+
+    // 读-改-写中“改”的那一步
+    pub const fn apply(self, bits: u32) -> u32 {
+        (bits & !self.mask) | self.value
+    }
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 清除 rc_w0 类型的标识位时应当直接写入的值：要清除的位为 0，其余位为 1（写 1 没有效果）
+pub const fn clear_rc_w0(flags: u32) -> u32 {
+    !flags
+}
@@ -0,0 +1,186 @@
+//! 在 main 与中断处理函数之间共享的全局资源
+//!
+//! 前面的例子里，几乎每个需要在中断里访问外设的程序，都要写一遍
+//!
+//! static G_X: Mutex<RefCell<Option<T>>> = Mutex::new(RefCell::new(None));
+//!
+//! 然后在每次访问的时候，都要 interrupt::free、borrow、borrow_mut、as_ref、unwrap 走一遍，
+//! 而且若中断在 main 把值放进去之前就触发了，unwrap 会直接 panic，且 panic 信息看不出是哪一个全局量的问题
+//!
+//! 这里把这套写法包装成了两个类型：
+//!
+//! - LateResource<T>：在 main 中初始化（init），之后通过 with/try_with 访问，
+//!   未初始化时 with 会给出明确的 panic 信息，try_with 则返回 None，中断处理函数可以据此直接返回
+//! - StaticCell<T>：对应 Mutex<Cell<T>>，用于计数器、标志位这类 Copy 的值
+//!
+//! 关于加锁的方式：
+//!
+//! 默认（new）使用 interrupt::free，也就是关闭所有的中断，与之前的写法完全一样
+//! 若某个资源只在 main 与某一个中断里使用，可以用 masked 创建，此时：
+//! - 在线程模式（main）中访问时，只在 NVIC 中屏蔽那一个中断，其它中断照常响应
+//! - 在那个中断自己的处理函数中访问时，不需要加锁，因为它既不会被自己打断，main 也不可能在此时运行
+//! - 若意外地在其它中断中访问，则退回到 interrupt::free，依旧是安全的，只是失去了屏蔽单个中断的好处
+
+use core::cell::{Cell, RefCell, RefMut};
+
+use cortex_m::{
+    interrupt::{CriticalSection, InterruptNumber, Mutex},
+    peripheral::{scb::VectActive, NVIC, SCB},
+};
+use stm32f4xx_hal::pac::Interrupt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lock {
+    // 关闭所有中断
+    Global,
+    // 只屏蔽一个中断
+    Masked(Interrupt),
+}
+
+impl Lock {
+    fn run<R>(self, f: impl FnOnce(&CriticalSection) -> R) -> R {
+        let irq = match self {
+            Lock::Global => return cortex_m::interrupt::free(f),
+            Lock::Masked(irq) => irq,
+        };
+
+        match SCB::vect_active() {
+            // 在它自己的中断处理函数中，不会有其它使用者同时访问
+            VectActive::Interrupt { irqn } if irqn as u16 == irq.number() => {
+                f(unsafe { &CriticalSection::new() })
+            }
+            VectActive::ThreadMode => {
+                let was_enabled = NVIC::is_enabled(irq);
+                NVIC::mask(irq);
+                // 确保屏蔽生效之后，才开始访问
+                cortex_m::asm::dsb();
+                cortex_m::asm::isb();
+
+                // 唯一可能同时访问的中断已经被屏蔽了，此时可以当作处于临界区中
+                let result = f(unsafe { &CriticalSection::new() });
+
+                if was_enabled {
+                    unsafe { NVIC::unmask(irq) };
+                }
+                result
+            }
+            _ => cortex_m::interrupt::free(f),
+        }
+    }
+}
+
+pub struct LateResource<T> {
+    inner: Mutex<RefCell<Option<T>>>,
+    lock: Lock,
+    // 出现在 panic 信息中
+    name: &'static str,
+}
+
+impl<T> LateResource<T> {
+    // 使用 interrupt::free 加锁
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(None)),
+            lock: Lock::Global,
+            name,
+        }
+    }
+
+    // 只在 main 与 irq 的处理函数中使用，访问时只屏蔽 irq
+    pub const fn masked(name: &'static str, irq: Interrupt) -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(None)),
+            lock: Lock::Masked(irq),
+            name,
+        }
+    }
+
+    // 放入初始值，每个资源只能初始化一次
+    pub fn init(&self, value: T) {
+        self.lock.run(|cs| {
+            let mut slot = self.inner.borrow(cs).borrow_mut();
+            if slot.is_some() {
+                panic!("resource `{}` initialized twice", self.name);
+            }
+            slot.replace(value);
+        })
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.lock.run(|cs| self.inner.borrow(cs).borrow().is_some())
+    }
+
+    // 访问资源，资源尚未初始化时 panic
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        match self.try_with(f) {
+            Some(result) => result,
+            None => panic!("resource `{}` used before init", self.name),
+        }
+    }
+
+    // 访问资源，资源尚未初始化（或者已经被 take 走了）时返回 None
+    pub fn try_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.lock
+            .run(|cs| self.inner.borrow(cs).borrow_mut().as_mut().map(f))
+    }
+
+    // 在已有的 interrupt::free 中访问资源，适合同时要访问多个全局量的场合
+    //
+    // 资源尚未初始化时 panic
+    pub fn get<'cs>(&'cs self, cs: &'cs CriticalSection) -> RefMut<'cs, T> {
+        match self.try_get(cs) {
+            Some(value) => value,
+            None => panic!("resource `{}` used before init", self.name),
+        }
+    }
+
+    // 与 get 相同，资源尚未初始化时返回 None
+    pub fn try_get<'cs>(&'cs self, cs: &'cs CriticalSection) -> Option<RefMut<'cs, T>> {
+        RefMut::filter_map(self.inner.borrow(cs).borrow_mut(), |slot| slot.as_mut()).ok()
+    }
+
+    // 把资源取出来，之后的 try_with 都会返回 None
+    pub fn take(&self) -> Option<T> {
+        self.lock
+            .run(|cs| self.inner.borrow(cs).borrow_mut().take())
+    }
+}
+
+pub struct StaticCell<T: Copy> {
+    inner: Mutex<Cell<T>>,
+    lock: Lock,
+}
+
+impl<T: Copy> StaticCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(Cell::new(value)),
+            lock: Lock::Global,
+        }
+    }
+
+    pub const fn masked(value: T, irq: Interrupt) -> Self {
+        Self {
+            inner: Mutex::new(Cell::new(value)),
+            lock: Lock::Masked(irq),
+        }
+    }
+
+    pub fn get(&self) -> T {
+        self.lock.run(|cs| self.inner.borrow(cs).get())
+    }
+
+    pub fn set(&self, value: T) {
+        self.lock.run(|cs| self.inner.borrow(cs).set(value))
+    }
+
+    // 读取、修改、写回在同一次加锁中完成，返回新的值
+    pub fn update(&self, f: impl FnOnce(T) -> T) -> T {
+        self.lock.run(|cs| {
+            let cell = self.inner.borrow(cs);
+            let value = f(cell.get());
+            cell.set(value);
+            value
+        })
+    }
+}
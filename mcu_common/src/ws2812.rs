@@ -0,0 +1,151 @@
+//! ws2812 的帧缓冲，与具体的发送方式无关
+//!
+//! 程序只管在 FrameBuffer 里修改每颗灯的颜色，然后交给某个后端发送：
+//!
+//! - TIM + DMA（s06c100、s06c06）：用 encode_pwm 把帧缓冲展开为每个 bit 一个 CCR 值，再由 DMA 写入 TIM
+//! - GPIO 翻转（s06 的 utils::ws2812_bitbang）：没有合适的 TIM 通道或 DMA Stream 的引脚，由 CPU 按周期计数直接输出
+//! - SPI 的 MOSI（s21 的 utils::ws2812_spi）：用 SPI 的几个 bit 拼出一个 ws2812 的 bit
+//!
+//! 后端都实现 Ws2812Out，换一种发送方式，填充帧缓冲的代码不需要改动
+//!
+//! ws2812 的数据顺序为 G、R、B，每个字节高位先发送，每颗灯 24 bit，这里的 grb 就是按发送顺序排好的 24 bit
+
+pub const BITS_PER_LED: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const OFF: Self = Self::new(0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    // brightness 为 255 时保持不变，为 0 时全灭
+    pub const fn scale(self, brightness: u8) -> Self {
+        let k = brightness as u16 + 1;
+        Self {
+            r: ((self.r as u16 * k) >> 8) as u8,
+            g: ((self.g as u16 * k) >> 8) as u8,
+            b: ((self.b as u16 * k) >> 8) as u8,
+        }
+    }
+
+    // 低 24 bit 依次为 G、R、B，bit 23 最先发送
+    pub const fn grb(self) -> u32 {
+        (self.g as u32) << 16 | (self.r as u32) << 8 | self.b as u32
+    }
+}
+
+pub struct FrameBuffer<const N: usize> {
+    pixels: [Rgb; N],
+    brightness: u8,
+}
+
+impl<const N: usize> FrameBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            pixels: [Rgb::OFF; N],
+            brightness: u8::MAX,
+        }
+    }
+
+    pub const fn len(&self) -> usize {
+        N
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    // 超出范围的 index 直接忽略，方便做“跑马灯”之类的效果时不必处处检查边界
+    pub fn set(&mut self, index: usize, color: Rgb) {
+        if let Some(pixel) = self.pixels.get_mut(index) {
+            *pixel = color;
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<Rgb> {
+        self.pixels.get(index).copied()
+    }
+
+    pub fn fill(&mut self, color: Rgb) {
+        self.pixels.fill(color);
+    }
+
+    pub fn clear(&mut self) {
+        self.fill(Rgb::OFF);
+    }
+
+    pub fn pixels(&self) -> &[Rgb] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Rgb] {
+        &mut self.pixels
+    }
+
+    // 全局亮度，在发送时才乘上去，帧缓冲里保存的颜色不受影响
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    // 按发送顺序依次给出每颗灯已经乘上亮度的 24 bit 数据
+    pub fn grb_words(&self) -> impl Iterator<Item = u32> + '_ {
+        self.pixels
+            .iter()
+            .map(move |pixel| pixel.scale(self.brightness).grb())
+    }
+
+    // 把帧缓冲展开为 TIM 的 CCR 值，每个 bit 一个，bit 0 写 zero，bit 1 写 one
+    //
+    // 第 n 个 bit 写入 out[n * stride + offset]，一个 TIM 驱动一条灯带时 stride 为 1、offset 为 0，
+    // 用 DMA burst 同时驱动多条灯带时（s06c06），stride 为一帧的长度，offset 为灯带对应的通道
+    //
+    // 只写入这 N * 24 个位置，复位所需的低电平部分由调用者自行保留，返回写入的个数
+    pub fn encode_pwm(
+        &self,
+        out: &mut [u16],
+        stride: usize,
+        offset: usize,
+        zero: u16,
+        one: u16,
+    ) -> usize {
+        assert!(
+            (N * BITS_PER_LED).saturating_sub(1) * stride + offset < out.len(),
+            "ws2812 PWM buffer too short"
+        );
+
+        let mut n = 0;
+        for word in self.grb_words() {
+            for bit in (0..BITS_PER_LED).rev() {
+                out[n * stride + offset] = if word & (1 << bit) != 0 { one } else { zero };
+                n += 1;
+            }
+        }
+        n
+    }
+}
+
+impl<const N: usize> Default for FrameBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 发送后端
+pub trait Ws2812Out {
+    type Error;
+
+    // 发送一整帧，返回时数据已经锁存（复位的低电平已经保持够了）
+    fn show<const N: usize>(&mut self, frame: &FrameBuffer<N>) -> Result<(), Self::Error>;
+}
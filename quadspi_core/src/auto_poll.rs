@@ -0,0 +1,160 @@
+//! QUADSPI 的状态标志轮询模式（FMODE = 0b10）
+//!
+//! c02 中等待 W25Q32 完成写入/擦除的方法，是由 CPU 不停地发送 0x05 并检查 BUSY 位，
+//! 而一次扇区擦除要几十毫秒，块擦除甚至要上百毫秒，这段时间 CPU 都被占着
+//!
+//! 状态标志轮询模式下，QUADSPI 会自己每隔一段时间发送一次命令，读回 1 ~ 4 个字节的状态，
+//! 与 PSMKR（掩码）和 PSMAR（匹配值）比较，匹配时置位 SMF，开启了 SMIE 的话还会触发中断
+//!
+//! PSMKR 中为 1 的位才会参与比较
+//! PMM = 0 时为 AND 模式，所有参与比较的位都要匹配；PMM = 1 时为 OR 模式，任意一位匹配即可
+//! PIR 为两次轮询之间间隔的 CLK 周期数
+//! APMS = 1 时，匹配之后自动停止轮询；否则会一直轮询下去，直到软件 Abort 或关闭 QUADSPI
+//!
+//! 匹配之后，最后一次读到的状态可以从 DR 中读出
+//!
+//! 在 QUADSPI 的中断里调用 on_interrupt 即可，它与 engine::on_quadspi_interrupt 可以放在同一个中断中
+
+use core::cell::Cell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{Peripherals, QUADSPI};
+
+use crate::command::{w25q, write_ccr, Command, FunctionalMode};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub enum MatchMode {
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub struct PollConfig {
+    pub mask: u32,
+    pub match_value: u32,
+    pub match_mode: MatchMode,
+    // 状态的字节数，1 ~ 4
+    pub status_len: u8,
+    pub interval: u16,
+}
+
+// W25Q 的 BUSY 位变为 0，也就是写入/擦除完成
+pub const W25Q_NOT_BUSY: PollConfig = PollConfig {
+    mask: w25q::STATUS_BUSY as u32,
+    match_value: 0,
+    match_mode: MatchMode::And,
+    status_len: 1,
+    interval: 0x10,
+};
+
+// W25Q 的 WEL 位变为 1，也就是 Write Enable 已经生效
+pub const W25Q_WRITE_ENABLED: PollConfig = PollConfig {
+    mask: 0b10,
+    match_value: 0b10,
+    match_mode: MatchMode::And,
+    status_len: 1,
+    interval: 0x10,
+};
+
+// None 表示还没有匹配，Some 中为匹配时的状态
+static G_MATCHED: Mutex<Cell<Option<u32>>> = Mutex::new(Cell::new(None));
+
+// 一次轮询的凭证，只能通过 start 获得
+pub struct AutoPoll {
+    _private: (),
+}
+
+impl AutoPoll {
+    // 还没有匹配时返回 None，匹配后返回最后一次读到的状态
+    pub fn poll(&self) -> Option<u32> {
+        cortex_m::interrupt::free(|cs| G_MATCHED.borrow(cs).take())
+    }
+
+    // 等待匹配，等待期间 CPU 处于睡眠状态
+    pub fn wait(self) -> u32 {
+        loop {
+            let matched = cortex_m::interrupt::free(|cs| {
+                let matched = G_MATCHED.borrow(cs).take();
+                if matched.is_none() {
+                    cortex_m::asm::wfi();
+                }
+                matched
+            });
+            if let Some(status) = matched {
+                return status;
+            }
+        }
+    }
+}
+
+// PSMKR、PSMAR、PIR 只能在 QUADSPI 空闲时写入，写入 CCR 之后轮询就开始了
+fn configure(qspi: &QUADSPI, command: &Command, config: &PollConfig, interrupt: bool) {
+    while qspi.sr.read().busy().bit_is_set() {}
+
+    qspi.psmkr.write(|w| unsafe { w.mask().bits(config.mask) });
+    qspi.psmar
+        .write(|w| unsafe { w.match_().bits(config.match_value) });
+    qspi.pir
+        .write(|w| unsafe { w.interval().bits(config.interval) });
+
+    qspi.cr.modify(|_, w| {
+        w.pmm().bit(config.match_mode == MatchMode::Or);
+        // 匹配后自动停止
+        w.apms().set_bit();
+        w.smie().bit(interrupt);
+        w
+    });
+
+    // 轮询模式下，DLR 为每次读取的状态字节数
+    write_ccr(
+        qspi,
+        command,
+        FunctionalMode::AutoPolling,
+        0,
+        config.status_len as usize,
+    );
+}
+
+// 启动状态标志轮询，匹配时触发中断
+//
+// QUADSPI 的 NVIC 中断需要提前开启
+pub fn start(qspi: &QUADSPI, command: &Command, config: &PollConfig) -> AutoPoll {
+    cortex_m::interrupt::free(|cs| G_MATCHED.borrow(cs).set(None));
+    configure(qspi, command, config, true);
+    AutoPoll { _private: () }
+}
+
+// 不使用中断，直接等待 SMF
+//
+// 与 c02 的方法不同，这里 CPU 只是在读 SR，并不参与轮询命令的发送
+pub fn run_blocking(qspi: &QUADSPI, command: &Command, config: &PollConfig) -> u32 {
+    configure(qspi, command, config, false);
+
+    while qspi.sr.read().smf().bit_is_clear() {}
+    let status = qspi.dr.read().data().bits();
+    qspi.fcr.write(|w| w.csmf().set_bit());
+    // 匹配后 QUADSPI 会自己停止，等它回到空闲状态
+    while qspi.sr.read().busy().bit_is_set() {}
+
+    status
+}
+
+// 在 QUADSPI 的中断里调用，若本次中断由 SMF 引起，则返回 true
+pub fn on_interrupt() -> bool {
+    let dp = unsafe { Peripherals::steal() };
+    let qspi = &dp.QUADSPI;
+
+    if qspi.cr.read().smie().bit_is_clear() || qspi.sr.read().smf().bit_is_clear() {
+        return false;
+    }
+
+    let status = qspi.dr.read().data().bits();
+    qspi.cr.modify(|_, w| w.smie().clear_bit());
+    qspi.fcr.write(|w| w.csmf().set_bit());
+
+    cortex_m::interrupt::free(|cs| G_MATCHED.borrow(cs).set(Some(status)));
+
+    true
+}
@@ -0,0 +1,197 @@
+//! QUADSPI 命令的描述
+//!
+//! 一个命令由指令、地址、交替字节、空指令、数据 5 个阶段组成，每个阶段可以使用不同的线数，详细说明见 s19c01
+//! 这里把一个命令的各个阶段写成一个结构体，由 write_ccr 统一写入 CCR，避免每次手写一长串寄存器操作
+
+use stm32f4xx_hal::pac::{interrupt, QUADSPI};
+
+// QUADSPI 的全局中断，F412 的 pac 中叫 QUAD_SPI
+#[cfg(feature = "stm32f413")]
+pub const IRQ: interrupt = interrupt::QUADSPI;
+#[cfg(feature = "stm32f412")]
+pub const IRQ: interrupt = interrupt::QUAD_SPI;
+
+// 某个阶段使用几根数据线，None 表示跳过这个阶段
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub enum Lines {
+    None = 0b00,
+    Single = 0b01,
+    Dual = 0b10,
+    Quad = 0b11,
+}
+
+// CCR 的 FMODE 字段
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub enum FunctionalMode {
+    IndirectWrite = 0b00,
+    IndirectRead = 0b01,
+    AutoPolling = 0b10,
+    MemoryMapped = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub struct Command {
+    pub instruction: u8,
+    pub instruction_lines: Lines,
+    pub address_lines: Lines,
+    // ADSIZE 字段，0b10 为 24 位地址
+    pub address_size: u8,
+    pub alternate_lines: Lines,
+    pub alternate: u8,
+    pub dummy_cycles: u8,
+    pub data_lines: Lines,
+}
+
+impl Command {
+    // 只有单线指令阶段的命令，比如 Write Enable
+    pub const fn instruction_only(instruction: u8) -> Self {
+        Self {
+            instruction,
+            instruction_lines: Lines::Single,
+            address_lines: Lines::None,
+            address_size: 0b10,
+            alternate_lines: Lines::None,
+            alternate: 0,
+            dummy_cycles: 0,
+            data_lines: Lines::None,
+        }
+    }
+
+    pub const fn with_address(mut self, lines: Lines) -> Self {
+        self.address_lines = lines;
+        self
+    }
+
+    pub const fn with_alternate(mut self, lines: Lines, alternate: u8) -> Self {
+        self.alternate_lines = lines;
+        self.alternate = alternate;
+        self
+    }
+
+    pub const fn with_dummy(mut self, cycles: u8) -> Self {
+        self.dummy_cycles = cycles;
+        self
+    }
+
+    pub const fn with_data(mut self, lines: Lines) -> Self {
+        self.data_lines = lines;
+        self
+    }
+}
+
+// 写入命令的各个寄存器
+//
+// 写入顺序很重要，QUADSPI 会在条件满足时自动开始命令（见 s19c01），因此这里的顺序是：
+// DLR -> ABR -> CCR -> AR
+// 对于没有地址阶段的读命令，写入 CCR 时命令就开始了；有地址阶段的读命令，写入 AR 时命令才开始；
+// 写命令则要等到 DR 中有数据时才开始
+pub fn write_ccr(
+    qspi: &QUADSPI,
+    command: &Command,
+    mode: FunctionalMode,
+    address: u32,
+    data_len: usize,
+) {
+    while qspi.sr.read().busy().bit_is_set() {}
+    qspi.fcr.write(|w| {
+        w.ctcf().set_bit();
+        w.ctef().set_bit();
+        w.csmf().set_bit();
+        w
+    });
+
+    if command.data_lines != Lines::None && data_len > 0 {
+        qspi.dlr
+            .write(|w| unsafe { w.dl().bits(data_len as u32 - 1) });
+    }
+
+    if command.alternate_lines != Lines::None {
+        qspi.abr
+            .write(|w| unsafe { w.alternate().bits(command.alternate as u32) });
+    }
+
+    qspi.ccr.write(|w| unsafe {
+        w.fmode().bits(mode as u8);
+        w.imode().bits(command.instruction_lines as u8);
+        w.admode().bits(command.address_lines as u8);
+        w.adsize().bits(command.address_size);
+        w.abmode().bits(command.alternate_lines as u8);
+        // 交替字节只用 1 个字节
+        w.absize().bits(0b00);
+        w.dcyc().bits(command.dummy_cycles);
+        w.dmode().bits(command.data_lines as u8);
+        w.instruction().bits(command.instruction);
+        w
+    });
+
+    if command.address_lines != Lines::None {
+        qspi.ar.write(|w| unsafe { w.address().bits(address) });
+    }
+}
+
+// 以字节为单位访问 DR
+//
+// DR 是 32 位的寄存器，但以 8 位的宽度访问时，QUADSPI 每次只会从 FIFO 中取出/放入 1 个字节
+pub fn read_dr_u8(qspi: &QUADSPI) -> u8 {
+    unsafe { core::ptr::read_volatile(qspi.dr.as_ptr() as *const u8) }
+}
+
+pub fn write_dr_u8(qspi: &QUADSPI, byte: u8) {
+    unsafe { core::ptr::write_volatile(qspi.dr.as_ptr() as *mut u8, byte) }
+}
+
+// 阻塞地执行一个命令，适合很短的命令（比如 Write Enable 或读取状态寄存器）
+pub fn run_blocking(
+    qspi: &QUADSPI,
+    command: &Command,
+    address: u32,
+    read: Option<&mut [u8]>,
+) {
+    match read {
+        Some(buf) => {
+            write_ccr(
+                qspi,
+                command,
+                FunctionalMode::IndirectRead,
+                address,
+                buf.len(),
+            );
+            for byte in buf.iter_mut() {
+                // 有数据（FLEVEL > 0）时才能读取
+                while qspi.sr.read().flevel().bits() == 0 {}
+                *byte = read_dr_u8(qspi);
+            }
+        }
+        None => write_ccr(qspi, command, FunctionalMode::IndirectWrite, address, 0),
+    }
+
+    while qspi.sr.read().busy().bit_is_set() {}
+    qspi.fcr.write(|w| w.ctcf().set_bit());
+}
+
+// W25Q 系列常用的几个命令
+pub mod w25q {
+    use super::{Command, Lines};
+
+    pub const WRITE_ENABLE: Command = Command::instruction_only(0x06);
+    pub const READ_STATUS_1: Command =
+        Command::instruction_only(0x05).with_data(Lines::Single);
+    pub const SECTOR_ERASE_4K: Command =
+        Command::instruction_only(0x20).with_address(Lines::Single);
+    // Quad Input Page Program，一次最多写入 256 字节，且不能跨页
+    pub const QUAD_PAGE_PROGRAM: Command = Command::instruction_only(0x32)
+        .with_address(Lines::Single)
+        .with_data(Lines::Quad);
+    // Fast Read Quad Output，需要 8 个空指令周期
+    pub const FAST_READ_QUAD_OUTPUT: Command = Command::instruction_only(0x6B)
+        .with_address(Lines::Single)
+        .with_dummy(8)
+        .with_data(Lines::Quad);
+
+    pub const PAGE_SIZE: usize = 256;
+    // 状态寄存器 1 的 BUSY 位
+    pub const STATUS_BUSY: u8 = 0b1;
+}
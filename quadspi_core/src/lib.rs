@@ -0,0 +1,15 @@
+//! QUADSPI 命令的描述，以及状态标志轮询模式
+//!
+//! 这两个模块最早写在 s19 中，之后 s21 的 QSPI Flash 驱动也要用到，
+//! 原来是两章各复制一份，现在只保留这里的一份，各章在 utils/mod.rs 中用 pub(crate) use 引入，
+//! 原来的 utils::command 这样的路径保持不变
+//!
+//! 与 chip_caps 一样，芯片由各章转发过来的 stm32f401 / stm32f411 / stm32f412 / stm32f413 特性选择，
+//! F401/F411 没有 QUADSPI，用到这些模块的程序在开头写有 chip_caps::require!(QUADSPI)
+
+#![no_std]
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub mod auto_poll;
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub mod command;
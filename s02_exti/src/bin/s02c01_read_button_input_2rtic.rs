@@ -112,7 +112,7 @@ mod app {
     }
 
     // rtic 特有的属性，idle task 是运行在启用了外部中断的环境下的，而且该函数永远不能返回
-    #[idle(local = [], shared = [])]
+    #[idle]
     fn idle(_ctx: idle::Context) -> ! {
         #[allow(clippy::empty_loop)]
         loop {
@@ -161,7 +161,7 @@ mod app {
 
     // 当倒计时结束，TIM2 中断会被触发，
     // 此时我们清除 TIM2 的 Pending bit，并 toggle LED 的亮灭
-    #[task(binds = TIM2, local = [], shared = [timer, led, led_state])]
+    #[task(binds = TIM2, shared = [timer, led, led_state])]
     fn blink_led(mut ctx: blink_led::Context) {
         ctx.shared
             .timer
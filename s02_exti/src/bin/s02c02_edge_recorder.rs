@@ -0,0 +1,69 @@
+//! 用 EXTI 记录引脚的边沿，在没有逻辑分析仪的时候检查协议的时序
+//!
+//! 程序会监视 PA1 与 PA2 两个引脚（可以按需修改 CHANNELS），开始记录之后：
+//! 1. 收到第一个边沿后，若 500 ms 内不再有新的边沿，或者缓冲区已满，就认为一段信号结束了
+//! 2. 打印这段信号的时序报告：每个边沿的时间与间隔，以及每个引脚高低电平脉宽的最小值、最大值与平均值
+//! 3. 清空缓冲区，等待下一段信号
+//!
+//! 精度的限制见 utils::edge_recorder
+//!
+//! 接线图：
+//!
+//! 待测信号（比如 1-Wire 的 DQ、红外接收头的输出）接 PA1 或 PA2，并与开发板共地
+//! 信号为 5V 时，需要确认引脚是否耐 5V（FT），或者加上分压/电平转换
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::{pac, prelude::*};
+
+mod utils;
+
+use utils::edge_recorder::{self, Channel, Port, Pull};
+
+// 1-Wire 与红外接收头的输出在空闲时都是高电平，因此使用上拉
+const CHANNELS: [Channel; 2] = [
+    Channel::new(Port::A, 1, Pull::Up),
+    Channel::new(Port::A, 2, Pull::Up),
+];
+
+// 一段信号结束之后的空闲时间
+const IDLE_MS: u32 = 500;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut cp = pac::CorePeripherals::take().unwrap();
+
+    // setup 需要访问 RCC 的寄存器，要在 constrain 之前调用
+    edge_recorder::setup(&dp, &mut cp, &CHANNELS).unwrap();
+
+    // 较高的 SYSCLK 可以缩短中断的响应时间，也就提高了能分辨的最小脉宽
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.use_hse(12.MHz()).sysclk(96.MHz()).freeze();
+    let sysclk_hz = clocks.sysclk().raw();
+    let idle_cycles = sysclk_hz / 1000 * IDLE_MS;
+
+    rprintln!("edge recorder ready, sysclk {} Hz\r", sysclk_hz);
+
+    loop {
+        edge_recorder::arm();
+
+        loop {
+            if edge_recorder::is_full() {
+                break;
+            }
+            if let Some(idle) = edge_recorder::idle_cycles() {
+                if idle > idle_cycles {
+                    break;
+                }
+            }
+        }
+
+        edge_recorder::report(sysclk_hz);
+    }
+}
@@ -0,0 +1,484 @@
+//! GPIO 边沿记录器
+//!
+//! 手边没有逻辑分析仪时，用来检查 WS2812、1-Wire、红外遥控这类协议的时序是否正确：
+//! 最多监视 4 个引脚，每个引脚的上升沿与下降沿都会触发 EXTI 中断，
+//! 中断里用 DWT 的 CYCCNT 记下时间戳，连同引脚的新电平一起放入环形缓冲区，之后再统一打印为时序报告
+//!
+//! 时间戳的精度为 1 个 SYSCLK 周期，但真正的限制在于中断的响应速度：
+//! 从边沿到读取 CYCCNT 之间有十几个周期的中断延迟，处理函数本身也要几十个周期，
+//! 因此两个边沿之间至少要相隔 1 ~ 2 µs（以 96 MHz 计）才能被分别记录下来
+//! 对于 1-Wire（时隙为几十 µs）与红外遥控（NEC 协议的最短脉冲约 560 µs）来说足够了；
+//! WS2812 的单个比特只有 1.25 µs，无法逐个比特记录，但可以检查复位的低电平，以及每一帧的总长度
+//!
+//! 若两个边沿挨得太近，中断只会触发一次，记录下来的电平与上一次相同，这种情况会被计为 missed
+//!
+//! 注意：
+//! 同一个 EXTI 线只能连接到一个 GPIO 端口，因此不同通道的引脚编号不能相同（比如 PA1 与 PB1）
+
+#![allow(dead_code)]
+
+use core::{
+    cell::RefCell,
+    ptr::{read_volatile, write_volatile},
+};
+
+use cortex_m::{interrupt::Mutex, peripheral::DWT};
+use rtt_target::rprintln;
+use stm32f4xx_hal::pac::{self, interrupt, Interrupt, NVIC};
+
+pub(crate) const MAX_CHANNELS: usize = 4;
+pub(crate) const CAPACITY: usize = 512;
+
+// GPIO 端口寄存器的地址，见 Reference Manual 的 Memory map
+const GPIO_BASE: u32 = 0x4002_0000;
+const GPIO_STRIDE: u32 = 0x400;
+const GPIO_MODER: u32 = 0x00;
+const GPIO_PUPDR: u32 = 0x0C;
+const GPIO_IDR: u32 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Port {
+    A = 0,
+    B = 1,
+    C = 2,
+    D = 3,
+    E = 4,
+    F = 5,
+    G = 6,
+    H = 7,
+}
+
+impl Port {
+    fn name(self) -> char {
+        (b'A' + self as u8) as char
+    }
+
+    fn reg(self, offset: u32) -> *mut u32 {
+        (GPIO_BASE + GPIO_STRIDE * self as u32 + offset) as *mut u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Pull {
+    None = 0b00,
+    Up = 0b01,
+    Down = 0b10,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Channel {
+    pub(crate) port: Port,
+    pub(crate) pin: u8,
+    pub(crate) pull: Pull,
+}
+
+impl Channel {
+    pub(crate) const fn new(port: Port, pin: u8, pull: Pull) -> Self {
+        Self { port, pin, pull }
+    }
+
+    fn level(&self) -> bool {
+        unsafe { read_volatile(self.port.reg(GPIO_IDR)) & (1 << self.pin) != 0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RecorderError {
+    TooManyChannels,
+    InvalidPin,
+    // 两个通道使用了同一个 EXTI 线
+    LineConflict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Edge {
+    pub(crate) channel: u8,
+    // 边沿之后的电平，true 表示上升沿
+    pub(crate) rising: bool,
+    // DWT CYCCNT
+    pub(crate) cycles: u32,
+}
+
+struct State {
+    channels: [Option<Channel>; MAX_CHANNELS],
+    armed: bool,
+    edges: [Edge; CAPACITY],
+    len: usize,
+    // 缓冲区满了之后丢弃的边沿个数
+    dropped: u32,
+    // 每个通道最后记录的电平，用来发现被合并掉的边沿
+    last_level: [bool; MAX_CHANNELS],
+    missed: [u32; MAX_CHANNELS],
+}
+
+static G_STATE: Mutex<RefCell<State>> = Mutex::new(RefCell::new(State {
+    channels: [None; MAX_CHANNELS],
+    armed: false,
+    edges: [Edge {
+        channel: 0,
+        rising: false,
+        cycles: 0,
+    }; CAPACITY],
+    len: 0,
+    dropped: 0,
+    last_level: [false; MAX_CHANNELS],
+    missed: [0; MAX_CHANNELS],
+}));
+
+// 设置引脚、EXTI 与 NVIC，并启动 DWT 的周期计数器
+//
+// 设置完成之后并不开始记录，需要调用 arm
+pub(crate) fn setup(
+    dp: &pac::Peripherals,
+    cp: &mut pac::CorePeripherals,
+    channels: &[Channel],
+) -> Result<(), RecorderError> {
+    if channels.len() > MAX_CHANNELS {
+        return Err(RecorderError::TooManyChannels);
+    }
+    let mut lines = 0u32;
+    for ch in channels {
+        if ch.pin > 15 {
+            return Err(RecorderError::InvalidPin);
+        }
+        if lines & (1 << ch.pin) != 0 {
+            return Err(RecorderError::LineConflict);
+        }
+        lines |= 1 << ch.pin;
+    }
+
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    dp.RCC.apb2enr.modify(|_, w| w.syscfgen().enabled());
+
+    for ch in channels {
+        let pin = ch.pin as u32;
+
+        dp.RCC
+            .ahb1enr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << ch.port as u32)) });
+
+        unsafe {
+            let pupdr = ch.port.reg(GPIO_PUPDR);
+            let v = read_volatile(pupdr) & !(0b11 << (pin * 2));
+            write_volatile(pupdr, v | ((ch.pull as u32) << (pin * 2)));
+
+            // 输入模式为 0b00
+            let moder = ch.port.reg(GPIO_MODER);
+            write_volatile(moder, read_volatile(moder) & !(0b11 << (pin * 2)));
+        }
+
+        // 每个 EXTICR 寄存器管理 4 个 EXTI 线，每个线 4 bit，值为端口编号
+        let shift = (pin % 4) * 4;
+        let set = |bits: u32| (bits & !(0xF << shift)) | ((ch.port as u32) << shift);
+        match pin / 4 {
+            0 => dp
+                .SYSCFG
+                .exticr1
+                .modify(|r, w| unsafe { w.bits(set(r.bits())) }),
+            1 => dp
+                .SYSCFG
+                .exticr2
+                .modify(|r, w| unsafe { w.bits(set(r.bits())) }),
+            2 => dp
+                .SYSCFG
+                .exticr3
+                .modify(|r, w| unsafe { w.bits(set(r.bits())) }),
+            _ => dp
+                .SYSCFG
+                .exticr4
+                .modify(|r, w| unsafe { w.bits(set(r.bits())) }),
+        }
+    }
+
+    // 上升沿与下降沿都触发
+    dp.EXTI
+        .rtsr
+        .modify(|r, w| unsafe { w.bits(r.bits() | lines) });
+    dp.EXTI
+        .ftsr
+        .modify(|r, w| unsafe { w.bits(r.bits() | lines) });
+    dp.EXTI.pr.write(|w| unsafe { w.bits(lines) });
+    dp.EXTI
+        .imr
+        .modify(|r, w| unsafe { w.bits(r.bits() | lines) });
+
+    cortex_m::interrupt::free(|cs| {
+        let mut state = G_STATE.borrow(cs).borrow_mut();
+        state.channels = [None; MAX_CHANNELS];
+        for (slot, ch) in state.channels.iter_mut().zip(channels) {
+            slot.replace(*ch);
+        }
+    });
+
+    for ch in channels {
+        unsafe { NVIC::unmask(exti_interrupt(ch.pin)) };
+    }
+
+    Ok(())
+}
+
+fn exti_interrupt(line: u8) -> Interrupt {
+    match line {
+        0 => Interrupt::EXTI0,
+        1 => Interrupt::EXTI1,
+        2 => Interrupt::EXTI2,
+        3 => Interrupt::EXTI3,
+        4 => Interrupt::EXTI4,
+        5..=9 => Interrupt::EXTI9_5,
+        _ => Interrupt::EXTI15_10,
+    }
+}
+
+// 清空缓冲区，开始记录
+pub(crate) fn arm() {
+    cortex_m::interrupt::free(|cs| {
+        let mut state = G_STATE.borrow(cs).borrow_mut();
+        state.len = 0;
+        state.dropped = 0;
+        state.missed = [0; MAX_CHANNELS];
+        for i in 0..MAX_CHANNELS {
+            if let Some(ch) = state.channels[i] {
+                state.last_level[i] = ch.level();
+            }
+        }
+        state.armed = true;
+    });
+}
+
+pub(crate) fn disarm() {
+    cortex_m::interrupt::free(|cs| G_STATE.borrow(cs).borrow_mut().armed = false);
+}
+
+// 已经记录的边沿个数
+pub(crate) fn len() -> usize {
+    cortex_m::interrupt::free(|cs| G_STATE.borrow(cs).borrow().len)
+}
+
+pub(crate) fn is_full() -> bool {
+    len() == CAPACITY
+}
+
+// 距离最后一个边沿经过的周期数，还没有边沿时返回 None
+pub(crate) fn idle_cycles() -> Option<u32> {
+    let now = DWT::cycle_count();
+    cortex_m::interrupt::free(|cs| {
+        let state = G_STATE.borrow(cs).borrow();
+        state.edges[..state.len]
+            .last()
+            .map(|edge| now.wrapping_sub(edge.cycles))
+    })
+}
+
+// 在 EXTI0 ~ EXTI4、EXTI9_5、EXTI15_10 的中断处理函数中调用
+pub(crate) fn on_exti() {
+    // 越早读取越准确
+    let cycles = DWT::cycle_count();
+
+    let dp = unsafe { pac::Peripherals::steal() };
+    let pending = dp.EXTI.pr.read().bits();
+
+    cortex_m::interrupt::free(|cs| {
+        let mut state = G_STATE.borrow(cs).borrow_mut();
+        let mut handled = 0u32;
+
+        for i in 0..MAX_CHANNELS {
+            let Some(ch) = state.channels[i] else {
+                continue;
+            };
+            let mask = 1 << ch.pin;
+            if pending & mask == 0 {
+                continue;
+            }
+            handled |= mask;
+
+            let level = ch.level();
+            if !state.armed {
+                continue;
+            }
+            if level == state.last_level[i] {
+                // 电平没有变化，说明中间有一对边沿被合并了
+                state.missed[i] += 1;
+            }
+            state.last_level[i] = level;
+
+            if state.len < CAPACITY {
+                let len = state.len;
+                state.edges[len] = Edge {
+                    channel: i as u8,
+                    rising: level,
+                    cycles,
+                };
+                state.len += 1;
+            } else {
+                state.dropped += 1;
+            }
+        }
+
+        dp.EXTI.pr.write(|w| unsafe { w.bits(handled) });
+    });
+}
+
+// 把周期数换算为 ns
+fn cycles_to_ns(cycles: u32, sysclk_hz: u32) -> u64 {
+    cycles as u64 * 1_000_000_000 / sysclk_hz as u64
+}
+
+// 以 µs 为单位打印，保留 3 位小数
+struct Micros(u64);
+
+impl core::fmt::Display for Micros {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{:03}", self.0 / 1000, self.0 % 1000)
+    }
+}
+
+#[derive(Default)]
+struct WidthStats {
+    count: u32,
+    min: u32,
+    max: u32,
+    sum: u64,
+}
+
+impl WidthStats {
+    fn add(&mut self, cycles: u32) {
+        if self.count == 0 || cycles < self.min {
+            self.min = cycles;
+        }
+        if cycles > self.max {
+            self.max = cycles;
+        }
+        self.sum += cycles as u64;
+        self.count += 1;
+    }
+
+    fn print(&self, name: &str, sysclk_hz: u32) {
+        if self.count == 0 {
+            rprintln!("    {} pulses: none\r", name);
+            return;
+        }
+        rprintln!(
+            "    {} pulses: {}, min {} us, max {} us, avg {} us\r",
+            name,
+            self.count,
+            Micros(cycles_to_ns(self.min, sysclk_hz)),
+            Micros(cycles_to_ns(self.max, sysclk_hz)),
+            Micros(cycles_to_ns(
+                (self.sum / self.count as u64) as u32,
+                sysclk_hz
+            )),
+        );
+    }
+}
+
+// 打印时序报告
+//
+// 先按时间顺序打印所有边沿，以及与前一个边沿（不论通道）的间隔；
+// 然后按通道统计高电平与低电平的脉宽
+//
+// 打印期间停止记录，打印之后需要重新 arm
+pub(crate) fn report(sysclk_hz: u32) {
+    disarm();
+
+    cortex_m::interrupt::free(|cs| {
+        let state = G_STATE.borrow(cs).borrow();
+        let edges = &state.edges[..state.len];
+
+        rprintln!(
+            "---- {} edges, {} dropped ----\r",
+            edges.len(),
+            state.dropped
+        );
+        let Some(first) = edges.first() else {
+            return;
+        };
+
+        let mut prev_cycles = first.cycles;
+        for edge in edges {
+            let ch = state.channels[edge.channel as usize].unwrap();
+            rprintln!(
+                "{:>12} us  P{}{:<2} {}  gap {:>10} us\r",
+                Micros(cycles_to_ns(
+                    edge.cycles.wrapping_sub(first.cycles),
+                    sysclk_hz
+                )),
+                ch.port.name(),
+                ch.pin,
+                if edge.rising { "rise" } else { "fall" },
+                Micros(cycles_to_ns(
+                    edge.cycles.wrapping_sub(prev_cycles),
+                    sysclk_hz
+                )),
+            );
+            prev_cycles = edge.cycles;
+        }
+
+        for (i, ch) in state.channels.iter().enumerate() {
+            let Some(ch) = ch else {
+                continue;
+            };
+            let mut high = WidthStats::default();
+            let mut low = WidthStats::default();
+            let mut prev: Option<&Edge> = None;
+            for edge in edges.iter().filter(|e| e.channel as usize == i) {
+                if let Some(p) = prev {
+                    // 两个边沿之间的电平，就是前一个边沿之后的电平
+                    let width = edge.cycles.wrapping_sub(p.cycles);
+                    if p.rising {
+                        high.add(width);
+                    } else {
+                        low.add(width);
+                    }
+                }
+                prev = Some(edge);
+            }
+
+            rprintln!(
+                "P{}{}: missed {}\r",
+                ch.port.name(),
+                ch.pin,
+                state.missed[i]
+            );
+            high.print("high", sysclk_hz);
+            low.print("low ", sysclk_hz);
+        }
+    });
+}
+
+// 把 EXTI 中断转发给 on_exti，没有用到的通道也没有关系，中断不会被启用
+#[interrupt]
+fn EXTI0() {
+    on_exti();
+}
+
+#[interrupt]
+fn EXTI1() {
+    on_exti();
+}
+
+#[interrupt]
+fn EXTI2() {
+    on_exti();
+}
+
+#[interrupt]
+fn EXTI3() {
+    on_exti();
+}
+
+#[interrupt]
+fn EXTI4() {
+    on_exti();
+}
+
+#[interrupt]
+fn EXTI9_5() {
+    on_exti();
+}
+
+#[interrupt]
+fn EXTI15_10() {
+    on_exti();
+}
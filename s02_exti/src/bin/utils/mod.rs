@@ -0,0 +1 @@
+pub(crate) mod edge_recorder;
@@ -12,9 +12,7 @@
 #![no_std]
 #![no_main]
 
-use core::cell::{Cell, RefCell};
-
-use cortex_m::{interrupt::Mutex, prelude::*};
+use cortex_m::prelude::*;
 use panic_rtt_target as _;
 use rtt_target::{rprintln, rtt_init_print};
 
@@ -27,19 +25,27 @@ use stm32f4xx_hal::{
     spi::{self, Spi1, SpiSlave2},
 };
 
+mod utils;
+
+use utils::{
+    irq::{self, Priority},
+    resources::{LateResource, StaticCell},
+};
+
+// 全局静态量的包装见 utils::resources
+
 // SPI1 的全局静态量，SPI1 作为主控端，并发出数据
-static G_SPI_MASTER: Mutex<RefCell<Option<Spi1<false, u16>>>> = Mutex::new(RefCell::new(None));
+static G_SPI_MASTER: LateResource<Spi1<false, u16>> = LateResource::new("G_SPI_MASTER");
 
 // SPI1 片选从机的引脚 1
 // 这里使用了 GPIO PA04，这个引脚是我们任选的
-static G_SPI_MASTER_CS: Mutex<RefCell<Option<Pin<'A', 4, Output>>>> =
-    Mutex::new(RefCell::new(None));
+static G_SPI_MASTER_CS: LateResource<Pin<'A', 4, Output>> = LateResource::new("G_SPI_MASTER_CS");
 
 // SPI2 的全局静态量，SPI2 作为从机端，并接收数据
-static G_SPI_SLAVE: Mutex<RefCell<Option<SpiSlave2<false, u16>>>> = Mutex::new(RefCell::new(None));
+static G_SPI_SLAVE: LateResource<SpiSlave2<false, u16>> = LateResource::new("G_SPI_SLAVE");
 
 // 记录一下发送是否完成
-static G_SENT: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+static G_SENT: StaticCell<bool> = StaticCell::new(false);
 
 #[cortex_m_rt::entry]
 fn main() -> ! {
@@ -101,23 +107,20 @@ fn main() -> ! {
     // 该中断表示可以接收数据
     spi_slave.listen(spi::Event::RxNotEmpty);
 
-    cortex_m::interrupt::free(|cs| {
+    cortex_m::interrupt::free(|_| {
         rprintln!("setup NVIC\r\n");
 
         // 将本地变量注入到全局静态量中
-        G_SPI_MASTER.borrow(cs).borrow_mut().replace(spi_master);
-        G_SPI_MASTER_CS.borrow(cs).borrow_mut().replace(cs_pin);
-        G_SPI_SLAVE.borrow(cs).borrow_mut().replace(spi_slave);
-
-        unsafe {
-            // 让 SPI1 的优先级低于 SPI2
-            // 首先保证接收端可以接收，再让发送端可以发送
-            cp.NVIC.set_priority(interrupt::SPI1, 20);
-            cp.NVIC.set_priority(interrupt::SPI2, 10);
-
-            NVIC::unmask(interrupt::SPI1);
-            NVIC::unmask(interrupt::SPI2);
-        }
+        G_SPI_MASTER.init(spi_master);
+        G_SPI_MASTER_CS.init(cs_pin);
+        G_SPI_SLAVE.init(spi_slave);
+
+        // 让 SPI1 的优先级低于 SPI2
+        // 首先保证接收端可以接收，再让发送端可以发送
+        irq::enable(&mut cp.NVIC, interrupt::SPI2, Priority::HIGH);
+        irq::enable(&mut cp.NVIC, interrupt::SPI1, Priority::NORMAL);
+
+        irq::dump();
     });
 
     #[allow(clippy::empty_loop)]
@@ -128,13 +131,11 @@ fn main() -> ! {
 fn SPI1() {
     cortex_m::interrupt::free(|cs| {
         rprintln!("SPI1 interrupt triggered\r");
-        let master_refcell = G_SPI_MASTER.borrow(cs);
-        // 这里我们另起了一个作用域，这样对 master_refcell 的借用会限制在这个作用域里，
-        // 在这个作用域之外，我们会尝试释放 master_refcell 中包含的对象
+        // 这里我们另起了一个作用域，这样对 G_SPI_MASTER 的借用会限制在这个作用域里，
+        // 在这个作用域之外，我们会尝试释放 G_SPI_MASTER 中包含的对象
         {
-            let mut master_mut = master_refcell.borrow_mut();
-            match master_mut.as_mut() {
-                Some(master) => {
+            match G_SPI_MASTER.try_get(cs) {
+                Some(mut master) => {
                     // 若 SPI1 处于繁忙状态，则立刻返回
                     if master.is_busy() {
                         rprintln!("SPI1 is busy\r\n");
@@ -143,8 +144,7 @@ fn SPI1() {
 
                     if master.is_tx_empty() {
                         rprintln!("SPI1 TX is Empty\r");
-                        let mut cs_pin_mut = G_SPI_MASTER_CS.borrow(cs).borrow_mut();
-                        let cs_pin = cs_pin_mut.as_mut().unwrap();
+                        let mut cs_pin = G_SPI_MASTER_CS.get(cs);
                         if cs_pin.is_set_high() {
                             rprintln!("will pull down SPI2 NSS...\r");
                             cs_pin.set_low();
@@ -154,7 +154,7 @@ fn SPI1() {
                         master
                             .send(0xFFAA)
                             .map(|_| {
-                                G_SENT.borrow(cs).set(true);
+                                G_SENT.set(true);
                             })
                             .unwrap();
                     }
@@ -168,22 +168,21 @@ fn SPI1() {
         }
 
         // 若已经是发送完成的状态，则掩蔽 SPI1 中断，并关闭 SPI1
-        if G_SENT.borrow(cs).get() {
+        if G_SENT.get() {
             rprintln!("Data sending completed, will mask out SPI1 from NVIC, then shutdown SPI1\r");
             // 这个花括号必不可少，它标识了 spi1_ref 的作用域
             // 在离开作用域之后，spi1_ref、spi1 就都被丢弃了
-            // 防止与后面的 master_refcell.replace() 冲突
+            // 防止与后面的 G_SPI_MASTER.take() 冲突
             {
                 // 等待 SPI1 处于非繁忙的状态，再关闭 SPI1
-                let master_ref = master_refcell.borrow();
-                let master = master_ref.as_ref().unwrap();
+                let master = G_SPI_MASTER.get(cs);
                 rprintln!("Waiting for BSY bit clean\r");
                 while master.is_busy() {}
             }
             // 第一步，关闭 NVIC 中对应的中断
             NVIC::mask(interrupt::SPI1);
-            // 第二步，将存储在 RefCell 中的 SPI 对象移动出来，并重新向全部变量中灌注 None
-            let mut master = master_refcell.replace(None).unwrap();
+            // 第二步，将存储在全局静态量中的 SPI 对象移动出来，之后全局静态量中就不再有 SPI 对象了
+            let mut master = G_SPI_MASTER.take().unwrap();
             // 第三步，关闭 SPI1 模块
             master.enable(false);
             rprintln!("SPI1 disabled\r");
@@ -197,15 +196,12 @@ fn SPI1() {
 #[interrupt]
 fn SPI2() {
     cortex_m::interrupt::free(|cs| {
-        let send_state = G_SENT.borrow(cs).get();
-
-        let slave_refcell = G_SPI_SLAVE.borrow(cs);
+        let send_state = G_SENT.get();
 
         // 与 SPI1 中断处理函数类似，这里也要另开一个作用域，方便后面的
         {
             rprintln!("SPI2 interrupt triggered\r");
-            let mut slave_mut = slave_refcell.borrow_mut();
-            let slave = slave_mut.as_mut().unwrap();
+            let mut slave = G_SPI_SLAVE.get(cs);
 
             // 中断触发，检查 Rx 是否为空，
             // 为空读一下数据，不为空说明产生了错误，这里我们直接 panic
@@ -221,12 +217,11 @@ fn SPI2() {
         // 检测发送状态，若发送被标记为完成，则逐步关闭 SPI2
         if send_state {
             {
-                // 在这个作用域中，我们还是需要 .borrow_mut() 的，因此，
+                // 在这个作用域中，我们还借用着 G_SPI_SLAVE，因此，
                 // 我们尽量检查 SPI2 的各种状态，保证 SPI2 处于可以解构的状态
-                // 这样，在脱离这个作用域之后，我们就可以通过 .replace() 和 .release() 解构 slave 了
+                // 这样，在脱离这个作用域之后，我们就可以通过 .take() 和 .release() 解构 slave 了
 
-                let mut slave_mut = slave_refcell.borrow_mut();
-                let slave = slave_mut.as_mut().unwrap();
+                let mut slave = G_SPI_SLAVE.get(cs);
                 // 等待 Slave 的 Busy Flag 置空
                 rprintln!("Waiting for BSY bit clean\r");
                 while slave.is_busy() {}
@@ -238,7 +233,7 @@ fn SPI2() {
             }
 
             // 此处我们正式释放 slave 控制的引脚
-            let slave = slave_refcell.replace(None).unwrap();
+            let slave = G_SPI_SLAVE.take().unwrap();
             slave.release();
             rprintln!("SPI2 pins released\r\n");
         }
@@ -0,0 +1,200 @@
+//! 多个从机共享 SPI1
+//!
+//! SPI1 上同时挂了三个从机，它们的 SPI 配置各不相同：
+//!
+//! W25Q 系列 SPI Flash：Mode 0，可以跑得很快，这里取 8 MHz
+//! SD 卡：Mode 0，初始化阶段 SCK 不能超过 400 kHz，这里取 250 kHz
+//! ST7789 TFT 屏幕：Mode 3，这里取 4 MHz
+//!
+//! SharedSpi 负责在切换从机时重新设置 CR1，并管理 CS 的时序，见 utils::chip_select
+//!
+//! 接线图：
+//!
+//! PA5 SPI1_SCK  -> 所有从机的 SCK
+//! PA6 SPI1_MISO <- W25Q DO，SD 卡 DO（需要上拉）
+//! PA7 SPI1_MOSI -> 所有从机的 DI/SDA
+//!
+//! PA4 -> W25Q /CS
+//! PB0 -> SD 卡 CS
+//! PB1 -> ST7789 CS
+//! PB2 -> ST7789 DC
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, Peripherals};
+
+mod utils;
+
+use utils::chip_select::{Device, DeviceConfig, DeviceId, Mode, SharedSpi};
+
+const W25Q: DeviceId = DeviceId(0);
+const SD_CARD: DeviceId = DeviceId(1);
+const TFT: DeviceId = DeviceId(2);
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_gpio(&dp);
+    dp.RCC.apb2enr.modify(|_, w| w.spi1en().enabled());
+
+    // 使用默认的 16 MHz HSI，SPI1 位于 APB2 上，也是 16 MHz
+    let mut spi = SharedSpi::new(
+        &dp,
+        &dp.SPI1,
+        [
+            Device {
+                name: "W25Q",
+                config: DeviceConfig {
+                    mode: Mode::Mode0,
+                    // 16 MHz / 2 = 8 MHz
+                    br: 0,
+                    lsb_first: false,
+                    // W25Q 的 t_SLCH、t_CHSH 均只有 5 ns 左右，t_SHSL（CS 高电平时间）为 10 ~ 50 ns，
+                    // 在 16 MHz 下，一个 CPU 周期就有 62.5 ns 了
+                    setup_cycles: 1,
+                    hold_cycles: 1,
+                    cs_high_cycles: 1,
+                },
+                select: |dp, on| {
+                    dp.GPIOA
+                        .bsrr
+                        .write(|w| if on { w.br4().reset() } else { w.bs4().set() });
+                },
+            },
+            Device {
+                name: "SD card",
+                config: DeviceConfig {
+                    mode: Mode::Mode0,
+                    // 16 MHz / 64 = 250 kHz
+                    br: 5,
+                    lsb_first: false,
+                    // SD 卡在 CS 前后最好各留 8 个时钟，这里直接留出对应的时间
+                    setup_cycles: 8 * 64,
+                    hold_cycles: 8 * 64,
+                    cs_high_cycles: 8 * 64,
+                },
+                select: |dp, on| {
+                    dp.GPIOB
+                        .bsrr
+                        .write(|w| if on { w.br0().reset() } else { w.bs0().set() });
+                },
+            },
+            Device {
+                name: "ST7789",
+                config: DeviceConfig {
+                    mode: Mode::Mode3,
+                    // 16 MHz / 4 = 4 MHz
+                    br: 1,
+                    lsb_first: false,
+                    // ST7789 的 t_CSS 为 15 ns，t_CSH 为 15 ns，t_CHW（CS 高电平时间）为 40 ns
+                    setup_cycles: 1,
+                    hold_cycles: 1,
+                    cs_high_cycles: 1,
+                },
+                select: |dp, on| {
+                    dp.GPIOB
+                        .bsrr
+                        .write(|w| if on { w.br1().reset() } else { w.bs1().set() });
+                },
+            },
+        ],
+    );
+
+    // W25Q：读取 JEDEC ID
+    let mut jedec_id = [0u8; 3];
+    spi.transaction(W25Q, |t| {
+        t.write(&[0x9F]);
+        t.read(&mut jedec_id);
+    });
+    rprintln!("{}: JEDEC ID {:02X?}", spi.device(W25Q).name, jedec_id);
+
+    // SD 卡：CS 为高时先发送至少 74 个时钟，然后发送 CMD0，让 SD 卡进入 SPI 模式
+    spi.idle_clocks(SD_CARD, 10);
+    let r1 = spi.transaction(SD_CARD, |t| {
+        // CMD0，参数为 0，CRC 为 0x95（SPI 模式下只有 CMD0 和 CMD8 需要正确的 CRC）
+        t.write(&[0x40, 0x00, 0x00, 0x00, 0x00, 0x95]);
+        // R1 响应会在 0 ~ 8 个字节之后出现，最高位为 0 的字节就是响应
+        let mut r1 = 0xFF;
+        for _ in 0..8 {
+            r1 = t.transfer_byte(0xFF);
+            if r1 & 0x80 == 0 {
+                break;
+            }
+        }
+        r1
+    });
+    // 0x01 表示 SD 卡处于 idle 状态，也就是成功进入了 SPI 模式
+    rprintln!("{}: CMD0 R1 0x{:02X}", spi.device(SD_CARD).name, r1);
+
+    // ST7789：发送 SWRESET 命令，DC 为低表示命令
+    dp.GPIOB.bsrr.write(|w| w.br2().reset());
+    spi.transaction(TFT, |t| t.write(&[0x01]));
+    rprintln!("{}: SWRESET sent", spi.device(TFT).name);
+
+    // 回到 W25Q，此时 SharedSpi 会把 Mode 3 切回 Mode 0
+    let mut status = [0u8; 1];
+    spi.transaction(W25Q, |t| {
+        t.write(&[0x05]);
+        t.read(&mut status);
+    });
+    rprintln!(
+        "{}: status register 1 0x{:02X}",
+        spi.device(W25Q).name,
+        status[0]
+    );
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+fn setup_gpio(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+    // PA5 ~ PA7 为 SPI1，AF5
+    gpioa.afrl.modify(|_, w| {
+        w.afrl5().af5();
+        w.afrl6().af5();
+        w.afrl7().af5();
+        w
+    });
+    // SD 卡的 DO 是开漏输出，需要上拉
+    gpioa.pupdr.modify(|_, w| w.pupdr6().pull_up());
+    gpioa.ospeedr.modify(|_, w| {
+        w.ospeedr5().high_speed();
+        w.ospeedr7().high_speed();
+        w
+    });
+    // 片选在切换为输出之前先设置为高电平，防止切换的瞬间选中从机
+    gpioa.bsrr.write(|w| w.bs4().set());
+    gpioa.moder.modify(|_, w| {
+        w.moder4().output();
+        w.moder5().alternate();
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w
+    });
+
+    let gpiob = &dp.GPIOB;
+    gpiob.bsrr.write(|w| {
+        w.bs0().set();
+        w.bs1().set();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder0().output();
+        w.moder1().output();
+        w.moder2().output();
+        w
+    });
+}
@@ -0,0 +1,352 @@
+//! SPI1 <-> SPI2 回环自检
+//!
+//! 与 s03c02 的接线相同，不过这里不再依赖中断优先级，而是由 utils::loopback 在主循环中轮流推进两个 SPI，
+//! 并检查两边收到的数据，最后打印通过与失败的数量，可以当作板子出厂时的自检程序
+//!
+//! SPI 是全双工的，主机每发出一个字节，同时也会收到从机发出的一个字节，
+//! 因此从机必须在主机产生时钟之前，就把要发出的字节写进 DR，否则主机收到的是上一次残留的数据
+//! 这里由从机在写好 DR 之后设置 RESPONDER_READY，主机等到这个标识才发送下一个字节
+//!
+//! 两边要发出的数据长度不同时，较短的一方补 0xFF，补上的字节不计入接收结果
+//!
+//! 接线图：
+//!
+//!           SPI1 <-> SPI2
+//! CS        PA04 >-> PB12  SPI2_NSS
+//! SPI1_SCK  PA05 >-> PB13  SPI2_SCK
+//! SPI1_MISO PA06 <-< PB14 SPI2_MISO
+//! SPI1_MOSI PA07 >-> PB15 SPI2_MOSI
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, Peripherals};
+
+mod utils;
+
+use utils::loopback::{Case, EventFlags, Frame, Harness, Side, Step, Summary, RESPONDER_READY};
+
+// 较短的一方在末尾补上的字节
+const PADDING: u8 = 0xFF;
+
+// 1 MHz 的 SCK 下，16 个字节也只需要几千次轮询
+const MAX_POLLS: u32 = 100_000;
+
+const ROUNDS: u32 = 10;
+
+const CASES: [Case; 5] = [
+    Case {
+        name: "single byte",
+        initiator_tx: &[0xA5],
+        responder_tx: &[0x5A],
+    },
+    Case {
+        name: "counting",
+        initiator_tx: &[0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07],
+        responder_tx: &[0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7],
+    },
+    Case {
+        name: "all zeros and all ones",
+        initiator_tx: &[0x00; 8],
+        responder_tx: &[0xFF; 8],
+    },
+    Case {
+        name: "initiator longer",
+        initiator_tx: &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66],
+        responder_tx: &[0x42, 0x43],
+    },
+    Case {
+        name: "full frame",
+        initiator_tx: &[
+            0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01, 0x7F, 0xBF, 0xDF, 0xEF, 0xF7, 0xFB,
+            0xFD, 0xFE,
+        ],
+        responder_tx: &[
+            0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA,
+            0x55, 0xAA,
+        ],
+    },
+];
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    // 使用默认的 16 MHz HSI
+    setup_gpio(&dp);
+    setup_spi1(&dp);
+    setup_spi2(&dp);
+
+    let mut harness = Harness::new(SpiMaster::new(&dp), SpiSlave::new(&dp), MAX_POLLS);
+
+    let mut total = Summary::default();
+    for round in 1..=ROUNDS {
+        rprintln!("round {}", round);
+        let summary = harness.run_all(&CASES);
+        total.passed += summary.passed;
+        total.failed += summary.failed;
+    }
+    total.report();
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+// SPI1，主机，同时也是发起方
+struct SpiMaster<'a> {
+    dp: &'a Peripherals,
+    frame: Frame,
+    // 本次要产生的字节数，为两个方向中较长的一个
+    total: usize,
+    sent: usize,
+    clocked: usize,
+}
+
+impl<'a> SpiMaster<'a> {
+    fn new(dp: &'a Peripherals) -> Self {
+        Self {
+            dp,
+            frame: Frame::new(),
+            total: 0,
+            sent: 0,
+            clocked: 0,
+        }
+    }
+
+    fn select(&self, on: bool) {
+        self.dp
+            .GPIOA
+            .bsrr
+            .write(|w| if on { w.br4().reset() } else { w.bs4().set() });
+    }
+}
+
+impl Side for SpiMaster<'_> {
+    fn name(&self) -> &'static str {
+        "SPI1"
+    }
+
+    fn begin(&mut self, _flags: &EventFlags, tx: &[u8], rx_len: usize) {
+        self.frame.load(tx, rx_len);
+        self.total = tx.len().max(rx_len);
+        self.sent = 0;
+        self.clocked = 0;
+
+        let spi = &self.dp.SPI1;
+        if spi.sr.read().rxne().is_not_empty() {
+            spi.dr.read();
+        }
+        self.select(true);
+    }
+
+    fn poll(&mut self, flags: &EventFlags) -> Step {
+        let spi = &self.dp.SPI1;
+        let sr = spi.sr.read();
+        if sr.ovr().bit_is_set() {
+            return Step::Failed("overrun");
+        }
+        if sr.modf().bit_is_set() {
+            return Step::Failed("mode fault");
+        }
+
+        // 上一个字节收完，且从机已经准备好之后，才发送下一个字节
+        if self.sent == self.clocked
+            && self.sent < self.total
+            && sr.txe().is_empty()
+            && flags.contains(RESPONDER_READY)
+        {
+            flags.clear(RESPONDER_READY);
+            let byte = self.frame.next_tx().unwrap_or(PADDING);
+            spi.dr.write(|w| w.dr().bits(byte as u16));
+            self.sent += 1;
+        }
+
+        if spi.sr.read().rxne().is_not_empty() {
+            self.frame.push_rx(spi.dr.read().dr().bits() as u8);
+            self.clocked += 1;
+        }
+
+        if self.clocked == self.total && spi.sr.read().bsy().is_not_busy() {
+            self.select(false);
+            return Step::Done;
+        }
+        Step::Pending
+    }
+
+    fn abort(&mut self) {
+        self.select(false);
+        // 依次读 DR 与 SR，以清理 OVR
+        let spi = &self.dp.SPI1;
+        spi.dr.read();
+        spi.sr.read();
+    }
+
+    fn received(&self) -> &[u8] {
+        self.frame.received()
+    }
+}
+
+// SPI2，从机，同时也是响应方
+struct SpiSlave<'a> {
+    dp: &'a Peripherals,
+    frame: Frame,
+    total: usize,
+    clocked: usize,
+    // 收到一个字节之后，还需要写入下一个要发出的字节
+    tx_due: bool,
+}
+
+impl<'a> SpiSlave<'a> {
+    fn new(dp: &'a Peripherals) -> Self {
+        Self {
+            dp,
+            frame: Frame::new(),
+            total: 0,
+            clocked: 0,
+            tx_due: false,
+        }
+    }
+
+    fn write_next(&mut self, flags: &EventFlags) {
+        let byte = self.frame.next_tx().unwrap_or(PADDING);
+        self.dp.SPI2.dr.write(|w| w.dr().bits(byte as u16));
+        self.tx_due = false;
+        flags.set(RESPONDER_READY);
+    }
+}
+
+impl Side for SpiSlave<'_> {
+    fn name(&self) -> &'static str {
+        "SPI2"
+    }
+
+    fn begin(&mut self, flags: &EventFlags, tx: &[u8], rx_len: usize) {
+        self.frame.load(tx, rx_len);
+        self.total = tx.len().max(rx_len);
+        self.clocked = 0;
+
+        let spi = &self.dp.SPI2;
+        if spi.sr.read().rxne().is_not_empty() {
+            spi.dr.read();
+        }
+        // 第一个字节要在主机拉低 CS 之前写好
+        self.write_next(flags);
+    }
+
+    fn poll(&mut self, flags: &EventFlags) -> Step {
+        let spi = &self.dp.SPI2;
+        if spi.sr.read().ovr().bit_is_set() {
+            return Step::Failed("overrun");
+        }
+
+        if spi.sr.read().rxne().is_not_empty() {
+            self.frame.push_rx(spi.dr.read().dr().bits() as u8);
+            self.clocked += 1;
+            self.tx_due = self.clocked < self.total;
+        }
+
+        if self.tx_due && spi.sr.read().txe().is_empty() {
+            self.write_next(flags);
+        }
+
+        if self.clocked == self.total {
+            return Step::Done;
+        }
+        Step::Pending
+    }
+
+    fn abort(&mut self) {
+        // 关闭 SPE 并不会清空从机的发送缓冲，只能复位整个 SPI2，再重新配置一遍
+        self.dp.RCC.apb1rstr.modify(|_, w| w.spi2rst().reset());
+        self.dp.RCC.apb1rstr.modify(|_, w| w.spi2rst().clear_bit());
+        setup_spi2(self.dp);
+        self.tx_due = false;
+    }
+
+    fn received(&self) -> &[u8] {
+        self.frame.received()
+    }
+}
+
+fn setup_gpio(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+    // PA5 ~ PA7 为 SPI1，AF5
+    gpioa.afrl.modify(|_, w| {
+        w.afrl5().af5();
+        w.afrl6().af5();
+        w.afrl7().af5();
+        w
+    });
+    // SCK 空闲时为低电平，下拉可以让 SPI1 关闭期间 SPI2 不会看到错误的时钟
+    gpioa.pupdr.modify(|_, w| w.pupdr5().pull_down());
+    // 片选在切换为输出之前先设置为高电平，防止切换的瞬间选中从机
+    gpioa.bsrr.write(|w| w.bs4().set());
+    gpioa.moder.modify(|_, w| {
+        w.moder4().output();
+        w.moder5().alternate();
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w
+    });
+
+    let gpiob = &dp.GPIOB;
+    // PB12 ~ PB15 为 SPI2，AF5
+    gpiob.afrh.modify(|_, w| {
+        w.afrh12().af5();
+        w.afrh13().af5();
+        w.afrh14().af5();
+        w.afrh15().af5();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| w.pupdr12().pull_up());
+    gpiob.moder.modify(|_, w| {
+        w.moder12().alternate();
+        w.moder13().alternate();
+        w.moder14().alternate();
+        w.moder15().alternate();
+        w
+    });
+}
+
+// 主机，Mode 0，8 位，16 MHz / 16 = 1 MHz
+fn setup_spi1(dp: &Peripherals) {
+    dp.RCC.apb2enr.modify(|_, w| w.spi1en().enabled());
+
+    dp.SPI1.cr1.write(|w| {
+        w.mstr().master();
+        // 片选由 PA4 负责，内部的 NSS 保持高电平，防止 SPI1 掉回从机模式
+        w.ssm().enabled();
+        w.ssi().slave_not_selected();
+        w.dff().eight_bit();
+        w.cpol().idle_low();
+        w.cpha().first_edge();
+        w.br().div16();
+        w
+    });
+    dp.SPI1.cr1.modify(|_, w| w.spe().enabled());
+}
+
+// 从机，Mode 0，8 位，使用 PB12 作为硬件 NSS
+fn setup_spi2(dp: &Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.spi2en().enabled());
+
+    dp.SPI2.cr1.write(|w| {
+        w.mstr().slave();
+        w.ssm().disabled();
+        w.dff().eight_bit();
+        w.cpol().idle_low();
+        w.cpha().first_edge();
+        w
+    });
+    dp.SPI2.cr1.modify(|_, w| w.spe().enabled());
+}
@@ -0,0 +1,267 @@
+//! 软件绘制与脏矩形刷新
+//!
+//! 在 SRAM 中放一块 240x240 的 RGB565 帧缓冲（utils::framebuffer），所有的绘制都在帧缓冲中完成，
+//! 再由 utils::st7789 只把改动过的区域发送给屏幕
+//!
+//! 程序先用 DWT 的周期计数器（utils::cycle_stats）测量各个操作，结果输出到 RTT：
+//!
+//! - 用 set_pixel 逐个像素填充 64x64 的区域，与 fill_rect 对比
+//! - 填充整个屏幕、贴一个 32x32 的图像、不透明与半透明的文字
+//! - 发送整个屏幕，与只发送一个改动过的数字对比
+//!
+//! 之后是一个简单的动画：一个方块在屏幕中弹跳，左上角显示帧数，每 100 帧输出一次 flush 的统计，
+//! 每一帧只需要发送方块的新旧位置与帧数这几个小区域
+//!
+//! 240x240 的帧缓冲占用 115200 字节，F413 有 320 KiB 的 SRAM，没有问题；
+//! F411 只有 128 KiB，如果放不下，可以换成 240x135 之类更小的屏幕
+//!
+//! USE_FSMC 为 true 时改为使用 FSMC 的 16 bit 并口（接线见 utils::st7789 的 setup_fsmc），
+//! 只有 F412/F413 有 FSMC，其它芯片上总是使用 SPI
+//!
+//! 接线图（SPI）：
+//!
+//! PA5 SPI1_SCK  -> ST7789 SCL
+//! PA7 SPI1_MOSI -> ST7789 SDA
+//! PB1           -> ST7789 CS
+//! PB2           -> ST7789 DC
+//! 3.3V          -> ST7789 RES、BLK（或者接到 GPIO 上控制）
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+
+use cortex_m::peripheral::DWT;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, Peripherals};
+
+mod utils;
+
+use utils::{
+    cycle_stats::{self, CycleStats},
+    framebuffer::{self, Color, FrameBuffer, Rect, BLACK, BLUE, GREEN, RED, WHITE},
+    st7789::{PanelBus, SpiPanelBus, St7789},
+};
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+const USE_FSMC: bool = false;
+
+// 使用默认的 16 MHz HSI
+const SYSCLK_HZ: u32 = 16_000_000;
+
+const WIDTH: u16 = 240;
+const HEIGHT: u16 = 240;
+const PIXELS: usize = WIDTH as usize * HEIGHT as usize;
+
+const BACKGROUND: Color = framebuffer::rgb565(0, 0, 48);
+const SPRITE_SIZE: u16 = 32;
+const BOX_SIZE: u16 = 24;
+
+// 测量用的统计，每一项只测量几次，看平均值即可
+static FILL_BY_PIXEL: CycleStats = CycleStats::new("set_pixel 64x64");
+static FILL_RECT_SMALL: CycleStats = CycleStats::new("fill_rect 64x64");
+static FILL_SCREEN: CycleStats = CycleStats::new("fill_rect 240x240");
+static BLIT: CycleStats = CycleStats::new("blit 32x32");
+static TEXT_OPAQUE: CycleStats = CycleStats::new("text x2 opaque");
+static TEXT_ALPHA: CycleStats = CycleStats::new("text x2 alpha 50%");
+static FLUSH_FULL: CycleStats = CycleStats::new("flush full screen");
+static FLUSH_DIGIT: CycleStats = CycleStats::new("flush one digit");
+static FLUSH_FRAME: CycleStats = CycleStats::new("flush animation frame");
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    static mut FRAME: [Color; PIXELS] = [0; PIXELS];
+
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut cp = pac::CorePeripherals::take().unwrap();
+    cycle_stats::enable(&mut cp.DCB, &mut cp.DWT);
+
+    let fb = FrameBuffer::new(FRAME, WIDTH, HEIGHT);
+
+    #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+    if USE_FSMC {
+        utils::st7789::setup_fsmc(&dp);
+        run(St7789::new(utils::st7789::FsmcPanelBus::new(), 0, 0), fb);
+    }
+
+    setup_spi1(&dp);
+    // 16 MHz / 2 = 8 MHz
+    run(St7789::new(SpiPanelBus::new(&dp, &dp.SPI1, 0), 0, 0), fb)
+}
+
+fn run<B: PanelBus>(mut panel: St7789<B>, mut fb: FrameBuffer) -> ! {
+    panel.init(SYSCLK_HZ);
+
+    benchmark(&mut panel, &mut fb);
+
+    fb.clear(BACKGROUND);
+    panel.flush(&mut fb);
+
+    let mut x = 0u16;
+    let mut y = 40u16;
+    let mut dx = 3i16;
+    let mut dy = 2i16;
+    let mut frame = 0u32;
+    let mut pixels_sent = 0u32;
+    let mut text = Text::new();
+
+    loop {
+        // 擦掉旧的方块，画出新的方块
+        fb.fill_rect(Rect::new(x, y, BOX_SIZE, BOX_SIZE), BACKGROUND);
+        let (nx, ndx) = bounce(x, dx, WIDTH - BOX_SIZE);
+        let (ny, ndy) = bounce(y, dy, HEIGHT - BOX_SIZE);
+        (x, dx, y, dy) = (nx, ndx, ny, ndy);
+        fb.fill_rect(Rect::new(x, y, BOX_SIZE, BOX_SIZE), GREEN);
+
+        // 帧数，先用背景色盖住上一次的数字
+        text.clear();
+        write!(text, "FRAME {}", frame).ok();
+        fb.fill_rect(Rect::new(4, 4, 160, 14), BACKGROUND);
+        fb.draw_text(4, 4, text.as_str(), WHITE, u8::MAX, 2);
+
+        let since = DWT::cycle_count();
+        let stats = panel.flush(&mut fb);
+        FLUSH_FRAME.record(since);
+        pixels_sent += stats.pixels;
+
+        frame += 1;
+        if frame.is_multiple_of(100) {
+            FLUSH_FRAME.report();
+            rprintln!(
+                "average {} pixels per frame ({}% of the screen)",
+                pixels_sent / 100,
+                pixels_sent / PIXELS as u32
+            );
+            FLUSH_FRAME.reset();
+            pixels_sent = 0;
+        }
+    }
+}
+
+fn benchmark<B: PanelBus>(panel: &mut St7789<B>, fb: &mut FrameBuffer) {
+    // 一个 32x32 的渐变图像，用来测试 blit
+    let mut sprite = [0 as Color; (SPRITE_SIZE * SPRITE_SIZE) as usize];
+    for (index, pixel) in sprite.iter_mut().enumerate() {
+        let (col, row) = (index as u16 % SPRITE_SIZE, index as u16 / SPRITE_SIZE);
+        *pixel = framebuffer::rgb565((col * 8) as u8, (row * 8) as u8, 128);
+    }
+
+    for _ in 0..4 {
+        FILL_BY_PIXEL.measure(|| {
+            for y in 0..64 {
+                for x in 0..64 {
+                    fb.set_pixel(x, y, RED);
+                }
+            }
+        });
+        FILL_RECT_SMALL.measure(|| fb.fill_rect(Rect::new(0, 0, 64, 64), RED));
+        FILL_SCREEN.measure(|| fb.clear(BLACK));
+        BLIT.measure(|| fb.blit(100, 100, SPRITE_SIZE, SPRITE_SIZE, &sprite));
+        TEXT_OPAQUE.measure(|| fb.draw_text(10, 150, "HELLO 0123", WHITE, u8::MAX, 2));
+        TEXT_ALPHA.measure(|| fb.draw_text(10, 150, "HELLO 0123", BLUE, 128, 2));
+
+        fb.mark_all_dirty();
+        FLUSH_FULL.measure(|| panel.flush(fb));
+
+        // 只改动一个数字
+        fb.draw_text(200, 200, "7", WHITE, u8::MAX, 2);
+        FLUSH_DIGIT.measure(|| panel.flush(fb));
+    }
+
+    for stats in [
+        &FILL_BY_PIXEL,
+        &FILL_RECT_SMALL,
+        &FILL_SCREEN,
+        &BLIT,
+        &TEXT_OPAQUE,
+        &TEXT_ALPHA,
+        &FLUSH_FULL,
+        &FLUSH_DIGIT,
+    ] {
+        stats.report();
+    }
+}
+
+// 在 0 ~ max 之间移动，碰到边缘时反向
+fn bounce(pos: u16, speed: i16, max: u16) -> (u16, i16) {
+    let next = pos as i16 + speed;
+    if next < 0 || next > max as i16 {
+        (pos, -speed)
+    } else {
+        (next as u16, speed)
+    }
+}
+
+// 格式化帧数用的小缓冲
+struct Text {
+    buf: [u8; 24],
+    len: usize,
+}
+
+impl Text {
+    fn new() -> Self {
+        Self {
+            buf: [0; 24],
+            len: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Text {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buf.len() {
+            return Err(core::fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+fn setup_spi1(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w
+    });
+    dp.RCC.apb2enr.modify(|_, w| w.spi1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    // PA5 SCK、PA7 MOSI 为 SPI1，AF5，屏幕不需要 MISO
+    gpioa.afrl.modify(|_, w| {
+        w.afrl5().af5();
+        w.afrl7().af5();
+        w
+    });
+    gpioa.ospeedr.modify(|_, w| {
+        w.ospeedr5().very_high_speed();
+        w.ospeedr7().very_high_speed();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder5().alternate();
+        w.moder7().alternate();
+        w
+    });
+
+    let gpiob = &dp.GPIOB;
+    // CS 在切换为输出之前先设置为高电平
+    gpiob.bsrr.write(|w| w.bs1().set());
+    gpiob.moder.modify(|_, w| {
+        w.moder1().output();
+        w.moder2().output();
+        w
+    });
+}
@@ -0,0 +1,194 @@
+//! 多个从机共享一个 SPI 总线
+//!
+//! 一条 SPI 总线上可以挂多个从机，它们共用 SCK/MISO/MOSI，每个从机有一根独立的 CS（片选）线，CS 为低时从机才会响应
+//! 不同的从机往往要求不同的 SPI 模式（CPOL/CPHA）、速度以及位序，因此每次切换从机时，都需要重新设置 CR1
+//!
+//! 这里的 SharedSpi 持有总线上所有从机的片选与配置，保证：
+//!
+//! 1. 同一时刻只有一个 CS 为低
+//! 2. 修改 CR1 时 SPI 处于空闲且关闭的状态（Reference Manual 要求修改 CPOL/CPHA/BR 时 SPE 必须为 0）
+//! 3. CS 拉低到第一个 SCK 边沿之间（setup），以及最后一个 SCK 边沿到 CS 拉高之间（hold），
+//!    以及 CS 两次拉低之间（最小高电平时间），都有足够的延时
+//!
+//! 注意 CPOL 必须在 CS 拉低之前设置好，否则 SCK 空闲电平的变化会被从机当作一个时钟边沿
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::{spi1::RegisterBlock, Peripherals};
+
+// SPI 的 4 种模式
+//
+// CPOL：SCK 空闲时的电平
+// CPHA：0 表示在第一个边沿采样，1 表示在第二个边沿采样
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+    // CPOL = 0，CPHA = 0
+    Mode0,
+    // CPOL = 0，CPHA = 1
+    Mode1,
+    // CPOL = 1，CPHA = 0
+    Mode2,
+    // CPOL = 1，CPHA = 1
+    Mode3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DeviceConfig {
+    pub(crate) mode: Mode,
+    // CR1 的 BR 位，SCK = f_PCLK / 2^(br + 1)，取值 0 ~ 7
+    pub(crate) br: u8,
+    pub(crate) lsb_first: bool,
+    // 以下三个延时，单位均为 CPU 周期
+    pub(crate) setup_cycles: u32,
+    pub(crate) hold_cycles: u32,
+    pub(crate) cs_high_cycles: u32,
+}
+
+pub(crate) struct Device {
+    pub(crate) name: &'static str,
+    pub(crate) config: DeviceConfig,
+    // 设置 CS 引脚的电平，传入 true 表示选中（拉低）
+    pub(crate) select: fn(&Peripherals, bool),
+}
+
+// 在 SharedSpi 中，通过下标来指代从机
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DeviceId(pub(crate) usize);
+
+pub(crate) struct SharedSpi<'a, const N: usize> {
+    dp: &'a Peripherals,
+    spi: &'a RegisterBlock,
+    devices: [Device; N],
+    // 当前 CR1 所对应的从机配置
+    current: Option<DeviceConfig>,
+}
+
+impl<'a, const N: usize> SharedSpi<'a, N> {
+    // SPI 的时钟与 GPIO 需要提前设置好
+    pub(crate) fn new(dp: &'a Peripherals, spi: &'a RegisterBlock, devices: [Device; N]) -> Self {
+        // 上电后，先释放所有的 CS
+        for device in devices.iter() {
+            (device.select)(dp, false);
+        }
+
+        spi.cr1.modify(|_, w| {
+            w.mstr().master();
+            // 由软件控制 NSS，并让内部的 NSS 保持高电平，防止 SPI 掉回从机模式，片选由各个从机的 GPIO 负责
+            w.ssm().enabled();
+            w.ssi().slave_not_selected();
+            w.dff().eight_bit();
+            w
+        });
+
+        Self {
+            dp,
+            spi,
+            devices,
+            current: None,
+        }
+    }
+
+    pub(crate) fn device(&self, id: DeviceId) -> &Device {
+        &self.devices[id.0]
+    }
+
+    // 等待 SPI 完全空闲：最后一个字节已经写入移位寄存器，且移位寄存器也已经发送完成
+    fn wait_idle(&self) {
+        while self.spi.sr.read().txe().is_not_empty() {}
+        while self.spi.sr.read().bsy().is_busy() {}
+    }
+
+    // 按照从机的配置修改 CR1，配置相同时不做任何事情
+    fn apply_config(&mut self, config: DeviceConfig) {
+        let same_bus_setting = self.current.is_some_and(|c| {
+            c.mode == config.mode && c.br == config.br && c.lsb_first == config.lsb_first
+        });
+        if same_bus_setting {
+            self.current = Some(config);
+            return;
+        }
+
+        let (cpol, cpha) = match config.mode {
+            Mode::Mode0 => (false, false),
+            Mode::Mode1 => (false, true),
+            Mode::Mode2 => (true, false),
+            Mode::Mode3 => (true, true),
+        };
+
+        self.spi.cr1.modify(|_, w| w.spe().disabled());
+        self.spi.cr1.modify(|_, w| {
+            w.cpol().bit(cpol);
+            w.cpha().bit(cpha);
+            w.br().bits(config.br);
+            w.lsbfirst().bit(config.lsb_first);
+            w
+        });
+        self.spi.cr1.modify(|_, w| w.spe().enabled());
+
+        self.current = Some(config);
+    }
+
+    // 以某个从机的身份执行一次传输，在闭包返回之后释放 CS
+    pub(crate) fn transaction<R>(&mut self, id: DeviceId, f: impl FnOnce(&Transfer) -> R) -> R {
+        let config = self.devices[id.0].config;
+        let select = self.devices[id.0].select;
+
+        // 上一次传输可能还没有完全结束，此时不能修改 CR1
+        self.wait_idle();
+        self.apply_config(config);
+
+        select(self.dp, true);
+        cortex_m::asm::delay(config.setup_cycles);
+
+        let result = f(&Transfer { spi: self.spi });
+
+        self.wait_idle();
+        cortex_m::asm::delay(config.hold_cycles);
+        select(self.dp, false);
+
+        // CS 的最小高电平时间，保证下一次传输（不论是哪个从机）不会来得太早
+        cortex_m::asm::delay(config.cs_high_cycles);
+
+        result
+    }
+
+    // 在所有 CS 均为高电平的情况下，以某个从机的配置发送时钟
+    // SD 卡在进入 SPI 模式之前，需要在 CS 为高的状态下收到至少 74 个时钟
+    pub(crate) fn idle_clocks(&mut self, id: DeviceId, bytes: usize) {
+        self.wait_idle();
+        self.apply_config(self.devices[id.0].config);
+        let transfer = Transfer { spi: self.spi };
+        for _ in 0..bytes {
+            transfer.transfer_byte(0xFF);
+        }
+        self.wait_idle();
+    }
+}
+
+// 一次传输中的收发操作
+pub(crate) struct Transfer<'a> {
+    spi: &'a RegisterBlock,
+}
+
+impl Transfer<'_> {
+    // SPI 是全双工的，每发送一个字节，就一定会收到一个字节
+    pub(crate) fn transfer_byte(&self, byte: u8) -> u8 {
+        while self.spi.sr.read().txe().is_not_empty() {}
+        self.spi.dr.write(|w| w.dr().bits(byte as u16));
+        while self.spi.sr.read().rxne().is_empty() {}
+        self.spi.dr.read().dr().bits() as u8
+    }
+
+    pub(crate) fn write(&self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.transfer_byte(byte);
+        }
+    }
+
+    // 读取时发送 0xFF，对于 SD 卡来说这是必须的，对于其它从机来说也没有坏处
+    pub(crate) fn read(&self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.transfer_byte(0xFF);
+        }
+    }
+}
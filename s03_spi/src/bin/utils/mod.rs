@@ -0,0 +1,8 @@
+pub(crate) mod chip_select;
+
+// 与其它章节共用的模块，代码在工作区中单独的 crate 里
+// 与上面的模块一样，不是每个程序都会用到，因此允许未使用
+#[allow(unused_imports)]
+pub(crate) use mcu_common::{cycle_stats, irq, loopback, resources};
+#[allow(unused_imports)]
+pub(crate) use tft_display::{font5x7, framebuffer, st7789};
@@ -46,7 +46,7 @@
 //!
 //! 在确认地址的流程中，由于 I2C 是通过一条 SDA 总线完成的收发操作的半双工模式，因此 I2C 协议还需要确认主从设备双方应该怎么协商，以确认后面的数据的发送方向（主发从收，还是从发主收）
 //! 因此地址字节的最后一位，起始是本段传输的传输方向，该位置 0 表示主发从收（主机拉低 SDA，表示主机主动），1 则表示主收从发（主机未拉低 SDA，表示主机被动），
-//! 因此，实际上 I2C 的有效 I2C 地址只有 7 位（某些配置下有 10 位，此处不讨论），且 I2C 从设备在收到地址的同时，也就知道了主机的意图（主机是读还是写），也同时会做好准备
+//! 因此，实际上 I2C 的有效 I2C 地址只有 7 位（某些配置下有 10 位，见 s04c03），且 I2C 从设备在收到地址的同时，也就知道了主机的意图（主机是读还是写），也同时会做好准备
 //!
 //! 然后我们还要解决一个问题，那就是主机怎么知道，被自己通过地址“叫到的”设备，的确存在于当前的总线上，而且的确处于可应答的状态的？
 //! 这就涉及到 I2C 的响应机制，当主设备发送完地址后，会产生一个时钟周期，在该周期中，主设备不会操作 SDA 线，
@@ -72,7 +72,7 @@
 //! 设计这个 stretch 功能，主要是用来处理下面两个情况：
 //! 1. 主设备的 I2C 总线信号过快，从设备无法正确区分信号，从设备延长 SCL 低电平时间就等价于降低了 I2C 的传输速率
 //! 2. 从设备需要一段时间处理主设备发来的请求，此时从设备可以一直保持 SCL 低电平，直到自身处理完成，再释放 SCL，让 I2C 总线继续运转
-
+//!
 //! 好了，上面说了这么多关于 I2C 通信协议的事情，现在让我们看一看，在这个案例中我们要实现的效果
 //!
 //! I2C1 作为主机，向作为从机的 I2C3 发送一组数据
@@ -88,9 +88,13 @@
 #![no_main]
 
 use core::cell::{Cell, RefCell};
-use cortex_m::{interrupt::Mutex, peripheral::NVIC};
+use cortex_m::{
+    interrupt::Mutex,
+    peripheral::{DWT, NVIC},
+};
 use rtt_target::ChannelMode;
 
+use chip_caps::pinmap::Binding;
 use panic_rtt_target as _;
 use rtt_target::rtt_init_print;
 
@@ -101,17 +105,47 @@ use stm32f4xx_hal::{
 
 mod utils;
 use utils::{
+    cycle_stats::{self, CycleStats},
+    irq::{self, Priority},
     printing::{master_rprintln, slave_rprintln},
+    reg_batch::{Batch, Field},
+    resources::LateResource,
     setup_pll,
 };
 
-static G_DP: Mutex<RefCell<Option<Peripherals>>> = Mutex::new(RefCell::new(None));
+// 见 utils::resources，省去了 Mutex<RefCell<Option<_>>> 的层层 borrow 与 unwrap
+static G_DP: LateResource<Peripherals> = LateResource::new("G_DP");
 
 // 我们胡乱定义的 7 位 I2C 地址位
 // 虽然是胡乱定义的，但绝对不可以将这 7 位设置为如下模式 11110XX
 // 这个地址是留给 10 bit 地址模式使用的，7 位下绝对不可以设置
 const I2C_SLAVE_ADDRESS: u8 = 0b1010101;
 
+// 为 true 时，I2C1 的事件中断（i2c1_event）只读一次 CR1，并用 utils::reg_batch 算好的值产生 STOP condition，
+// 为 false 时则是每次都重新读 CR1、用 modify 设置 STOP 的写法，可以用 I2C1_EVT_CYCLES 对比两者的耗时
+const BATCHED: bool = true;
+
+// I2C_CR1 的 STOP 位
+const CR1_STOP: Field = Field::bit(9);
+const REQUEST_STOP: Batch = Batch::new().enable(CR1_STOP);
+
+// 整个 i2c1_event 的耗时，其中大部分是 RTT 打印，两种写法的差别体现在 min 与 avg 的差值上
+static I2C1_EVT_CYCLES: CycleStats = CycleStats::new("I2C1_EVT");
+
+// SYSCLK 为 64 MHz，传输开始之后等待 1 s 再打印中断的耗时
+const REPORT_DELAY_CYCLES: u32 = 64_000_000;
+
+// I2C1 的事件与错误中断在 F413 的 pac 中叫 I2C1_EVT、I2C1_ERR，在 F401/F411/F412 的 pac 中叫 I2C1_EV、I2C1_ER
+// 中断处理函数的名字也随之不同，见文件末尾
+#[cfg(feature = "stm32f413")]
+const I2C1_EV_IRQ: interrupt = interrupt::I2C1_EVT;
+#[cfg(feature = "stm32f413")]
+const I2C1_ER_IRQ: interrupt = interrupt::I2C1_ERR;
+#[cfg(not(feature = "stm32f413"))]
+const I2C1_EV_IRQ: interrupt = interrupt::I2C1_EV;
+#[cfg(not(feature = "stm32f413"))]
+const I2C1_ER_IRQ: interrupt = interrupt::I2C1_ER;
+
 #[cortex_m_rt::entry]
 fn main() -> ! {
     // 由于 I2C 的通信速度相较于 RTT 来说还是比较快的，因此我们需要扩大 RTT 的缓存容量，
@@ -131,26 +165,26 @@ fn main() -> ! {
 
     setup_pll::setup(&dp);
 
-    cortex_m::interrupt::free(|cs| {
-        G_DP.borrow(cs).borrow_mut().replace(dp);
-    });
+    G_DP.init(dp);
 
     // 由于 I2C 对于时序的要求较高，而我们为了实验，两个 I2C 又都是在同一块芯片里面
     // 因此这里有必要设置一下 I2C 的中断顺序
 
     let mut cp = CorePeripherals::take().expect("Cannot Get Core Peripherals");
 
+    cycle_stats::enable(&mut cp.DCB, &mut cp.DWT);
+
     // 修改了优先级，I2C3 作为接收方，它的优先级需要高于 I2C1
     // 这样我们就保证了如果有输入输入，则优先处理接收操作，同时也阻止了发送的产生
     //
     // 优先级关系：
     // Slave_Error > Slave_Int > Master_Error > Master_Int
-    unsafe {
-        cp.NVIC.set_priority(interrupt::I2C3_ER, 2);
-        cp.NVIC.set_priority(interrupt::I2C3_EV, 4);
-        cp.NVIC.set_priority(interrupt::I2C1_ERR, 8);
-        cp.NVIC.set_priority(interrupt::I2C1_EVT, 16);
-    }
+    //
+    // 注意 STM32F4 只实现了优先级的高 4 位，直接写入 2、4、8 的话它们都会变成 0，见 utils::irq
+    irq::set_priority(&mut cp.NVIC, interrupt::I2C3_ER, Priority::HIGHEST);
+    irq::set_priority(&mut cp.NVIC, interrupt::I2C3_EV, Priority::HIGH);
+    irq::set_priority(&mut cp.NVIC, I2C1_ER_IRQ, Priority::NORMAL);
+    irq::set_priority(&mut cp.NVIC, I2C1_EV_IRQ, Priority::LOW);
 
     // 为两个 I2C 设置 GPIO 引脚
     setup_gpio_for_i2c1();
@@ -162,32 +196,36 @@ fn main() -> ! {
 
     // 在我们完成了全部的初始化配置之后，我们需要手动触发一下 I2C1，让其产生 START condition
     // 以开始本流程的传输
-    cortex_m::interrupt::free(|cs| {
-        let dp_ref = G_DP.borrow(cs).borrow();
-        let dp = dp_ref.as_ref().unwrap();
-
+    G_DP.with(|dp| {
         let master = &dp.I2C1;
 
         master_rprintln!("Main\ttrigger START condition");
         master.cr1.modify(|_, w| w.start().start());
     });
 
+    // 9 个字节的传输早就完成了
+    cortex_m::asm::delay(REPORT_DELAY_CYCLES);
+    I2C1_EVT_CYCLES.report();
+
     #[allow(clippy::empty_loop)]
     loop {}
 }
 
-fn setup_gpio_for_i2c1() {
-    cortex_m::interrupt::free(|cs| {
-        let dp_ref = G_DP.borrow(cs).borrow();
-        let dp = dp_ref.as_ref().unwrap();
+// 两个 I2C 使用的引脚，bind! 在编译时核对引脚与 AF，见 chip_caps::pinmap
+const I2C1_SCL: Binding = chip_caps::bind!(I2C1_SCL => PB6);
+const I2C1_SDA: Binding = chip_caps::bind!(I2C1_SDA => PB7);
+const I2C3_SCL: Binding = chip_caps::bind!(I2C3_SCL => PA8);
+const I2C3_SDA: Binding = chip_caps::bind!(I2C3_SDA => PC9);
 
+fn setup_gpio_for_i2c1() {
+    G_DP.with(|dp| {
         dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
 
         let gpiob = &dp.GPIOB;
 
         gpiob.afrl.modify(|_, w| {
-            w.afrl6().af4();
-            w.afrl7().af4();
+            w.afrl6().bits(I2C1_SCL.af);
+            w.afrl7().bits(I2C1_SDA.af);
             w
         });
 
@@ -227,10 +265,7 @@ fn setup_gpio_for_i2c1() {
 }
 
 fn setup_gpio_for_i2c3() {
-    cortex_m::interrupt::free(|cs| {
-        let dp_ref = G_DP.borrow(cs).borrow();
-        let dp = dp_ref.as_ref().unwrap();
-
+    G_DP.with(|dp| {
         // 依照 I2C 的说明，所有的输出状态必须处于开漏状态
         // 依照 I2C 的说明，SCL 线路和 SDA 线路必须处于弱上拉状态
         // 虽然 SCL 和 SDA 分别只需要一个上拉电阻就好了，这里我们还是启用了所有引脚的上拉电阻
@@ -243,14 +278,14 @@ fn setup_gpio_for_i2c3() {
 
         dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
         let gpioa = &dp.GPIOA;
-        gpioa.afrh.modify(|_, w| w.afrh8().af4());
+        gpioa.afrh.modify(|_, w| w.afrh8().bits(I2C3_SCL.af));
         gpioa.otyper.modify(|_, w| w.ot8().open_drain());
         gpioa.pupdr.modify(|_, w| w.pupdr8().pull_up());
         gpioa.moder.modify(|_, w| w.moder8().alternate());
 
         dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
         let gpioc = &dp.GPIOC;
-        gpioc.afrh.modify(|_, w| w.afrh9().af4());
+        gpioc.afrh.modify(|_, w| w.afrh9().bits(I2C3_SDA.af));
         gpioc.otyper.modify(|_, w| w.ot9().open_drain());
         gpioc.pupdr.modify(|_, w| w.pupdr9().pull_up());
         gpioc.moder.modify(|_, w| w.moder9().alternate());
@@ -258,10 +293,7 @@ fn setup_gpio_for_i2c3() {
 }
 
 fn setup_i2c_master() {
-    cortex_m::interrupt::free(|cs| {
-        let dp_ref = G_DP.borrow(cs).borrow();
-        let dp = dp_ref.as_ref().unwrap();
-
+    G_DP.with(|dp| {
         dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
 
         let master = &dp.I2C1;
@@ -304,8 +336,8 @@ fn setup_i2c_master() {
         master.trise.write(|w| w.trise().bits(33));
 
         unsafe {
-            NVIC::unmask(interrupt::I2C1_EVT);
-            NVIC::unmask(interrupt::I2C1_ERR)
+            NVIC::unmask(I2C1_EV_IRQ);
+            NVIC::unmask(I2C1_ER_IRQ)
         };
 
         // 由于 I2C 是半双工运行的，导致了 I2C 的两个特性
@@ -334,10 +366,7 @@ fn setup_i2c_master() {
 }
 
 fn setup_i2c_slave() {
-    cortex_m::interrupt::free(|cs| {
-        let dp_ref = G_DP.borrow(cs).borrow();
-        let dp = dp_ref.as_ref().unwrap();
-
+    G_DP.with(|dp| {
         dp.RCC.apb1enr.modify(|_, w| w.i2c3en().enabled());
 
         let slave = &dp.I2C3;
@@ -394,8 +423,9 @@ static G_SENDING_INT_CNT: Mutex<Cell<usize>> = Mutex::new(Cell::new(1));
 static G_RECEIVING_INT_CNT: Mutex<Cell<usize>> = Mutex::new(Cell::new(1));
 
 // 主设备一直连续发送 hello 这 5 个字母
-#[interrupt]
-fn I2C1_EVT() {
+fn i2c1_event() {
+    let since = DWT::cycle_count();
+
     cortex_m::interrupt::free(|cs| {
         // 记录中断次数用
         let interrupt_counter = G_SENDING_INT_CNT.borrow(cs);
@@ -405,19 +435,26 @@ fn I2C1_EVT() {
         let sending_indexer = G_SENDING_INDEX.borrow(cs);
         let sending_idx = sending_indexer.get();
 
-        let dp_cellref = G_DP.borrow(cs).borrow();
-        let dp = dp_cellref.as_ref().unwrap();
+        let dp = G_DP.get(cs);
 
         let master = &dp.I2C1;
 
         // 中断产生，第一步必然是获取一下当前状态寄存器 SR1 的数据
         let master_sr1 = master.sr1.read();
 
+        // CR1 只读这一次，下面判断 STOP 与设置 STOP 都用这个值，省掉一次 APB1 上的读取
+        // 可以这么做是因为 CR1 中会被硬件改变的只有 START、STOP 与 PEC 这几位：
+        // 执行到 TX_E 的分支时，START 在 SB 出现时就已经被硬件清除了，STOP 则刚刚判断过为 0，
+        // 因此这个值与 CR1 实际的值是相同的，把它与 STOP 合并之后直接写回即可
+        let master_cr1 = master.cr1.read().bits();
+
         // 【特别注意】
         // 我们并不可以假定在一个中断中，仅出现了一个标识位
         // 准确来说对于 I2C 而言，在一次中断中，出现了多少标识位，就要处理多少标识位
         // 因此我们并不能随便将多个判定标识位的 if 块用 else if 串联在一起
         // 一开始我在这里吃了大亏，触发了很多不应该触发的错误
+        //
+        // 标识位一多，这种 if 链就很难看出哪些组合被漏掉了，s04c05 用 utils::fsm 的转移表重写了主机一侧，可以对照着看
 
         // 由于一个中断中要判定 I2C 外设的多个状态，因此我们并不能直接在流程的末尾确定，触发该中断的状态是否被处理了
         // 因此我们这里设置一个变量，只要下方任何的处理流程执行了处理，handled 就会被改写为 true
@@ -476,7 +513,12 @@ fn I2C1_EVT() {
 
         // 注意，这里检查的是 CR1 里的 STOP bit，表示的是我们有没有让 I2C 准备好产生 STOP condition
         // 不是检查 SR1 里的 STOPF bit
-        if master.cr1.read().stop().bit_is_set() {
+        let stop_requested = if BATCHED {
+            master_cr1 & CR1_STOP.mask() != 0
+        } else {
+            master.cr1.read().stop().bit_is_set()
+        };
+        if stop_requested {
             // 这里必须要等待 STOP condition 实际建立，从而让 TX_E 的清空，
             // 不能直接关闭 ITBUFEN，否则 STOP condition 建立后，会额外触发一个中断，而这个中断触发时 SR1 和 SR2 均为 0
             // 导致我们无法处理最后那个中断
@@ -515,7 +557,13 @@ fn I2C1_EVT() {
                     "Int {}\tData sending finish, trigger STOP condition",
                     interrupt_cnt
                 );
-                master.cr1.modify(|_, w| w.stop().stop());
+                if BATCHED {
+                    master
+                        .cr1
+                        .write(|w| unsafe { w.bits(REQUEST_STOP.apply(master_cr1)) });
+                } else {
+                    master.cr1.modify(|_, w| w.stop().stop());
+                }
             }
 
             sending_indexer.set(sending_idx + 1);
@@ -534,13 +582,13 @@ fn I2C1_EVT() {
 
         interrupt_counter.set(interrupt_cnt + 1);
     });
+
+    I2C1_EVT_CYCLES.record(since);
 }
 
-#[interrupt]
-fn I2C1_ERR() {
+fn i2c1_error() {
     cortex_m::interrupt::free(|cs| {
-        let dp_cellref = G_DP.borrow(cs).borrow();
-        let dp = dp_cellref.as_ref().unwrap();
+        let dp = G_DP.get(cs);
 
         let master = &dp.I2C1;
         slave_rprintln!(
@@ -563,8 +611,7 @@ fn I2C3_EV() {
         let receiving_indexer = G_RECEIVING_INDEX.borrow(cs);
         let mut receiving_idx = receiving_indexer.get();
 
-        let dp_cellref = G_DP.borrow(cs).borrow();
-        let dp = dp_cellref.as_ref().unwrap();
+        let dp = G_DP.get(cs);
 
         let slave = &dp.I2C3;
 
@@ -642,8 +689,7 @@ fn I2C3_EV() {
 #[interrupt]
 fn I2C3_ER() {
     cortex_m::interrupt::free(|cs| {
-        let dp_cellref = G_DP.borrow(cs).borrow();
-        let dp = dp_cellref.as_ref().unwrap();
+        let dp = G_DP.get(cs);
 
         let slave = &dp.I2C3;
         slave_rprintln!(
@@ -653,3 +699,27 @@ fn I2C3_ER() {
         );
     });
 }
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn I2C1_EVT() {
+    i2c1_event();
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn I2C1_ERR() {
+    i2c1_error();
+}
+
+#[cfg(not(feature = "stm32f413"))]
+#[interrupt]
+fn I2C1_EV() {
+    i2c1_event();
+}
+
+#[cfg(not(feature = "stm32f413"))]
+#[interrupt]
+fn I2C1_ER() {
+    i2c1_error();
+}
@@ -0,0 +1,347 @@
+//! I2C 的 10 位地址与双地址
+//!
+//! 接线与 s04c01 一致，I2C1 作为主机，I2C3 作为从机
+//!
+//! 接线图：
+//!
+//! I2C1 SCL PB6 <-> I2C3 SCL PA8
+//! I2C1 SDA PB7 <-> I2C3 SDA PC9
+//!
+//! 与 s04c01 不同的是，这里主机使用 utils::blocking_master 在主循环中轮询，从机依旧在中断中处理
+//!
+//! 流程分为两个阶段：
+//! 1. 从机使用 10 位地址，主机向其写入数据，然后再读回几个字节
+//! 2. 从机切换为 7 位地址，并在 OAR2 中设置第二个地址，主机分别向两个地址写入数据，从机打印匹配上的是哪个地址
+//!
+//! 地址的编码方式，见 utils::addressing
+
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+use cortex_m::{interrupt::Mutex, peripheral::NVIC};
+use rtt_target::ChannelMode;
+
+use panic_rtt_target as _;
+use rtt_target::rtt_init_print;
+
+use stm32f4xx_hal::{
+    interrupt,
+    pac::{CorePeripherals, Peripherals},
+};
+
+mod utils;
+use utils::{
+    addressing::{matched_address, set_dual_address, I2cAddress},
+    blocking_master,
+    irq::{self, Priority},
+    printing::{master_rprintln, slave_rprintln},
+    setup_pll,
+};
+
+const SLAVE_10BIT_ADDRESS: I2cAddress = I2cAddress::TenBit(0b10_1010_0101);
+const SLAVE_PRIMARY_ADDRESS: I2cAddress = I2cAddress::SevenBit(0b1010101);
+const SLAVE_SECONDARY_ADDRESS: u8 = 0b1010110;
+
+// 从机被读取时，依次发出的数据
+const SLAVE_REPLY: [u8; 4] = [0xA0, 0xA1, 0xA2, 0xA3];
+
+struct SlaveState {
+    receive_buf: [u8; 16],
+    receive_idx: usize,
+    reply_idx: usize,
+}
+
+static G_SLAVE: Mutex<RefCell<SlaveState>> = Mutex::new(RefCell::new(SlaveState {
+    receive_buf: [0; 16],
+    receive_idx: 0,
+    reply_idx: 0,
+}));
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    // 原因见 s04c01
+    rtt_init_print!(ChannelMode::NoBlockTrim, 4096);
+
+    let dp = Peripherals::take().expect("Cannot Get Peripherals");
+    let mut cp = CorePeripherals::take().expect("Cannot Get Core Peripherals");
+
+    setup_pll::setup(&dp);
+
+    // 主机在主循环中轮询，不使用中断，这里只需要设置从机的优先级
+    irq::set_priority(&mut cp.NVIC, interrupt::I2C3_ER, Priority::HIGHEST);
+    irq::set_priority(&mut cp.NVIC, interrupt::I2C3_EV, Priority::HIGH);
+
+    setup_gpio(&dp);
+    setup_i2c_master(&dp);
+    setup_i2c_slave(&dp);
+
+    let master = &dp.I2C1;
+    let slave = &dp.I2C3;
+
+    // 阶段 1：10 位地址
+    set_slave_address(&dp, SLAVE_10BIT_ADDRESS, None);
+
+    master_rprintln!(
+        "write to 10 bit address, header: 0b{:08b}, second byte: 0x{:02X}",
+        SLAVE_10BIT_ADDRESS.first_byte(false),
+        SLAVE_10BIT_ADDRESS.second_byte().unwrap()
+    );
+    master_rprintln!(
+        "result: {:?}",
+        blocking_master::write(master, SLAVE_10BIT_ADDRESS, b"ten")
+    );
+    wait_for_stop(&dp);
+
+    let mut buf = [0u8; SLAVE_REPLY.len()];
+    let result = blocking_master::read(master, SLAVE_10BIT_ADDRESS, &mut buf);
+    master_rprintln!(
+        "read from 10 bit address, result: {:?}, data: {:02X?}",
+        result,
+        buf
+    );
+    wait_for_stop(&dp);
+
+    // 阶段 2：双地址
+    set_slave_address(&dp, SLAVE_PRIMARY_ADDRESS, Some(SLAVE_SECONDARY_ADDRESS));
+
+    master_rprintln!(
+        "write to primary address, result: {:?}",
+        blocking_master::write(master, SLAVE_PRIMARY_ADDRESS, b"one")
+    );
+    wait_for_stop(&dp);
+
+    master_rprintln!(
+        "write to secondary address, result: {:?}",
+        blocking_master::write(
+            master,
+            I2cAddress::SevenBit(SLAVE_SECONDARY_ADDRESS),
+            b"two"
+        )
+    );
+    wait_for_stop(&dp);
+
+    // 关闭双地址之后，第二地址就不会被 ACK 了
+    set_slave_address(&dp, SLAVE_PRIMARY_ADDRESS, None);
+    master_rprintln!(
+        "write to secondary address after ENDUAL cleared, result: {:?}",
+        blocking_master::write(
+            master,
+            I2cAddress::SevenBit(SLAVE_SECONDARY_ADDRESS),
+            b"none"
+        )
+    );
+    wait_for_stop(&dp);
+
+    slave_rprintln!(
+        "OAR1: 0x{:04X}, OAR2: 0x{:02X}",
+        slave.oar1.read().bits(),
+        slave.oar2.read().bits()
+    );
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+// 等待主机产生的 STOP condition 真正出现在总线上，再开始下一次传输
+fn wait_for_stop(dp: &Peripherals) {
+    while dp.I2C1.cr1.read().stop().bit_is_set() {}
+    while dp.I2C1.sr2.read().busy().bit_is_set() {}
+}
+
+// 修改从机地址时要关闭 PE，修改完成后再重新打开，并重新设置 ACK（原因见 s04c01）
+fn set_slave_address(dp: &Peripherals, addr: I2cAddress, addr2: Option<u8>) {
+    assert!(addr.is_valid());
+
+    let slave = &dp.I2C3;
+
+    slave.cr1.modify(|_, w| w.pe().disabled());
+    addr.set_as_own_address(slave);
+    set_dual_address(slave, addr2);
+    slave.cr1.modify(|_, w| w.pe().enabled());
+    slave.cr1.modify(|_, w| w.ack().ack());
+
+    slave_rprintln!("own address: {:?}, dual address: {:?}", addr, addr2);
+}
+
+fn setup_gpio(dp: &Peripherals) {
+    // I2C1: PB6 SCL, PB7 SDA
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| {
+        w.afrl6().af4();
+        w.afrl7().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot6().open_drain();
+        w.ot7().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr6().pull_up();
+        w.pupdr7().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w
+    });
+
+    // I2C3: PA8 SCL, PC9 SDA
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| w.afrh8().af4());
+    gpioa.otyper.modify(|_, w| w.ot8().open_drain());
+    gpioa.pupdr.modify(|_, w| w.pupdr8().pull_up());
+    gpioa.moder.modify(|_, w| w.moder8().alternate());
+
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    let gpioc = &dp.GPIOC;
+    gpioc.afrh.modify(|_, w| w.afrh9().af4());
+    gpioc.otyper.modify(|_, w| w.ot9().open_drain());
+    gpioc.pupdr.modify(|_, w| w.pupdr9().pull_up());
+    gpioc.moder.modify(|_, w| w.moder9().alternate());
+}
+
+// 时钟相关的设置，见 s04c01
+fn setup_i2c_master(dp: &Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let master = &dp.I2C1;
+    master.cr2.modify(|_, w| unsafe { w.freq().bits(32) });
+    master.ccr.modify(|_, w| unsafe { w.ccr().bits(32) });
+    master.trise.write(|w| w.trise().bits(33));
+    master.cr1.modify(|_, w| w.pe().enabled());
+}
+
+fn setup_i2c_slave(dp: &Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.i2c3en().enabled());
+
+    let slave = &dp.I2C3;
+    slave.cr2.modify(|_, w| {
+        unsafe { w.freq().bits(32) };
+        w.itevten().enabled();
+        w.itbufen().enabled();
+        w.iterren().enabled();
+        w
+    });
+
+    unsafe {
+        NVIC::unmask(interrupt::I2C3_EV);
+        NVIC::unmask(interrupt::I2C3_ER)
+    };
+
+    // 地址和 PE 在 set_slave_address 中设置
+}
+
+#[interrupt]
+fn I2C3_EV() {
+    // 主机在主循环中独占了 dp，这里只访问 I2C3
+    let dp = unsafe { Peripherals::steal() };
+    let slave = &dp.I2C3;
+
+    cortex_m::interrupt::free(|cs| {
+        let mut state = G_SLAVE.borrow(cs).borrow_mut();
+
+        let slave_sr1 = slave.sr1.read();
+
+        let mut handled = false;
+
+        // 10 位地址下，主机写入时，ADDR 会在完整的 10 位地址匹配之后挂起
+        // 主机读取时，Repeated START 之后的读方向头字节匹配时，ADDR 会再挂起一次
+        if slave_sr1.addr().is_match() {
+            // 清理 ADDR 需要读 SR1 再读 SR2，DUALF 和 TRA 都在 SR2 中，正好一并读出来
+            slave.sr1.read();
+            let sr2 = slave.sr2.read();
+
+            let direction = if sr2.tra().bit_is_set() {
+                state.reply_idx = 0;
+                "read"
+            } else {
+                state.receive_idx = 0;
+                "write"
+            };
+
+            slave_rprintln!(
+                "ADDR matched, {:?} address, master {}",
+                matched_address(sr2.bits()),
+                direction
+            );
+
+            handled = true;
+        }
+
+        if slave_sr1.rx_ne().is_not_empty() {
+            let byte = slave.dr.read().dr().bits();
+            let idx = state.receive_idx;
+            if idx < state.receive_buf.len() {
+                state.receive_buf[idx] = byte;
+                state.receive_idx = idx + 1;
+            }
+            handled = true;
+        }
+
+        // 从机发送时，TX_E 挂起就写入下一个字节，数据发完之后就发送 0xFF 填充
+        if slave_sr1.tx_e().is_empty() {
+            let idx = state.reply_idx;
+            let byte = SLAVE_REPLY.get(idx).copied().unwrap_or(0xFF);
+            slave.dr.write(|w| w.dr().bits(byte));
+            state.reply_idx = idx + 1;
+            handled = true;
+        }
+
+        if slave_sr1.stopf().is_stop() {
+            // 清理 STOPF，见 s04c01
+            slave.sr1.read();
+            slave.cr1.modify(|_, w| w);
+
+            let idx = state.receive_idx;
+            if idx > 0 {
+                slave_rprintln!(
+                    "STOP, received: {:?}",
+                    core::str::from_utf8(&state.receive_buf[..idx])
+                );
+            }
+            state.receive_idx = 0;
+            handled = true;
+        }
+
+        if !handled {
+            slave_rprintln!(
+                "EVent not covered, slave_sr1: {:016b}, slave_sr2: {:08b}",
+                slave_sr1.bits(),
+                slave.sr2.read().bits()
+            );
+        }
+    });
+}
+
+#[interrupt]
+fn I2C3_ER() {
+    let dp = unsafe { Peripherals::steal() };
+    let slave = &dp.I2C3;
+
+    let sr1 = slave.sr1.read();
+
+    // 从机发送时，主机以 NACK 回复最后一个字节，表示不再需要数据了，这是正常的结束流程
+    // 从机模式下 AF 之后不会产生 STOPF，只需要清理 AF 即可
+    if sr1.af().bit_is_set() {
+        slave.sr1.modify(|_, w| w.af().clear_bit());
+        slave_rprintln!("NACK from master, transmission end");
+        return;
+    }
+
+    slave_rprintln!(
+        "Error SR1: 0b{:016b},\nSR2: 0b{:08b}",
+        sr1.bits(),
+        slave.sr2.read().bits()
+    );
+    slave.sr1.modify(|_, w| {
+        w.berr().clear_bit();
+        w.arlo().clear_bit();
+        w.ovr().clear_bit();
+        w
+    });
+}
@@ -0,0 +1,203 @@
+//! SMBus 智能电池
+//!
+//! 以 SMBus Host 的身份，周期性地读取一块符合 Smart Battery Data Specification 的电池（比如 BQ40Z50 方案的电池包）
+//! 所有读写均带 PEC，同时处理 SMBALERT#、Host Notify 与总线超时
+//!
+//! SMBus 的各个特性，见 utils::smbus
+//!
+//! 接线图：
+//!
+//! PB6 I2C1_SCL  <-> 电池 SMBC
+//! PB7 I2C1_SDA  <-> 电池 SMBD
+//! PB5 I2C1_SMBA <-> 电池（或 INA226 等带告警输出的芯片）的 ALERT，需要上拉
+//!
+//! 注意：SMBus 的时钟频率必须在 10 kHz ~ 100 kHz 之间
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::Peripherals;
+
+mod utils;
+use utils::{
+    blocking_master::MasterError,
+    setup_pll,
+    smbus::{software_reset, Smbus, SmbusError},
+};
+
+// Smart Battery 的默认地址
+const BATTERY_ADDRESS: u8 = 0x0B;
+
+// Smart Battery Data Specification 中的几个命令
+const CMD_TEMPERATURE: u8 = 0x08; // 单位 0.1 K
+const CMD_VOLTAGE: u8 = 0x09; // 单位 mV
+const CMD_CURRENT: u8 = 0x0A; // 单位 mA，有符号，放电为负
+const CMD_RELATIVE_STATE_OF_CHARGE: u8 = 0x0D; // 单位 %
+
+// SYSCLK 为 64 MHz，见 utils::setup_pll
+const SYSCLK_HZ: u32 = 64_000_000;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = Peripherals::take().unwrap();
+
+    setup_pll::setup(&dp);
+
+    setup_gpio(&dp);
+    setup_i2c(&dp);
+
+    let smbus = Smbus::new_host(&dp.I2C1, true);
+
+    loop {
+        match read_battery(&smbus) {
+            Ok(()) => {}
+            Err(SmbusError::Master(MasterError::Timeout)) => {
+                // 硬件检测到超时后已经释放了总线，但从机的状态机可能还停在传输中间
+                rprintln!("SMBus timeout, recovering bus");
+                recover_bus(&dp);
+            }
+            Err(e) => rprintln!("SMBus error: {:?}", e),
+        }
+
+        // SMBALERT# 为低电平有效，且多个设备可以共享，因此要一直读取 ARA，直到没有设备响应为止
+        if smbus.take_alert() {
+            loop {
+                match smbus.alert_response() {
+                    Ok(Some(device)) => rprintln!("SMBALERT# from device 0x{:02X}", device),
+                    Ok(None) => break,
+                    Err(e) => {
+                        rprintln!("Alert Response error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        // 电池会通过 Host Notify 发送 AlarmWarning，数据的每一位代表一种告警
+        if let Some(notify) = smbus.poll_host_notify() {
+            rprintln!(
+                "Host Notify from 0x{:02X}, data: 0b{:016b}",
+                notify.device,
+                notify.data
+            );
+        }
+
+        cortex_m::asm::delay(SYSCLK_HZ);
+    }
+}
+
+fn read_battery(smbus: &Smbus) -> Result<(), SmbusError> {
+    let temperature = smbus.read_word_data(BATTERY_ADDRESS, CMD_TEMPERATURE)?;
+    let voltage = smbus.read_word_data(BATTERY_ADDRESS, CMD_VOLTAGE)?;
+    let current = smbus.read_word_data(BATTERY_ADDRESS, CMD_CURRENT)? as i16;
+    let soc = smbus.read_word_data(BATTERY_ADDRESS, CMD_RELATIVE_STATE_OF_CHARGE)?;
+
+    rprintln!(
+        "{} mV, {} mA, {}%, {}.{} C",
+        voltage,
+        current,
+        soc,
+        (temperature as i32 - 2731) / 10,
+        (temperature as i32 - 2731).rem_euclid(10)
+    );
+
+    Ok(())
+}
+
+// 总线恢复
+//
+// 如果从机在发送某个字节的中途被打断，它可能会一直拉低 SDA，等待剩下的时钟
+// 此时主机需要把 SCL 切换为普通的 GPIO，手动产生最多 9 个时钟，直到从机释放 SDA，然后再产生一个 STOP condition
+fn recover_bus(dp: &Peripherals) {
+    let gpiob = &dp.GPIOB;
+
+    // 开漏输出，先释放两根线
+    gpiob.odr.modify(|_, w| {
+        w.odr6().high();
+        w.odr7().high();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder6().output();
+        w.moder7().output();
+        w
+    });
+
+    // 100 kHz 的半个周期大约是 5 us
+    let half_period = SYSCLK_HZ / 200_000;
+
+    for _ in 0..9 {
+        if gpiob.idr.read().idr7().is_high() {
+            break;
+        }
+        gpiob.odr.modify(|_, w| w.odr6().low());
+        cortex_m::asm::delay(half_period);
+        gpiob.odr.modify(|_, w| w.odr6().high());
+        cortex_m::asm::delay(half_period);
+    }
+
+    // STOP condition：SCL 为高时，SDA 产生上升沿
+    gpiob.odr.modify(|_, w| w.odr7().low());
+    cortex_m::asm::delay(half_period);
+    gpiob.odr.modify(|_, w| w.odr7().high());
+    cortex_m::asm::delay(half_period);
+
+    gpiob.moder.modify(|_, w| {
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w
+    });
+
+    // 最后复位 I2C 内部的状态机
+    software_reset(&dp.I2C1);
+}
+
+fn setup_gpio(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+
+    let gpiob = &dp.GPIOB;
+
+    // 开漏与上拉的原因，见 s04c01
+    gpiob.afrl.modify(|_, w| {
+        w.afrl5().af4();
+        w.afrl6().af4();
+        w.afrl7().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot5().open_drain();
+        w.ot6().open_drain();
+        w.ot7().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr5().pull_up();
+        w.pupdr6().pull_up();
+        w.pupdr7().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder5().alternate();
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w
+    });
+}
+
+fn setup_i2c(dp: &Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let i2c = &dp.I2C1;
+
+    // APB1 为 32 MHz，计算方法见 s04c01
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(32) });
+    // 100 kHz：高电平（含上升）时间为 5 us，5 us / 31.25 ns = 160
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(160) });
+    i2c.trise.write(|w| w.trise().bits(33));
+
+    // PE 在 Smbus::new_host 中打开
+}
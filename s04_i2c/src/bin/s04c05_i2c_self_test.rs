@@ -0,0 +1,544 @@
+//! I2C1 <-> I2C3 回环自检
+//!
+//! 接线与 s04c01 一致，I2C1 作为主机（发起方），I2C3 作为从机（响应方）
+//!
+//! s04c01 依靠“从机中断的优先级高于主机”来保证从机总能及时响应，
+//! 这里两边都不使用中断，而是由 utils::loopback 在主循环中轮流推进两边的状态，并检查收到的数据，
+//! 最后打印通过与失败的数量，可以当作板子出厂时的自检程序
+//!
+//! 主机一侧用 utils::fsm 的转移表描述，可以当作把其它驱动的标识位 if 链改写为状态机的模板，
+//! 把 TRACE 改为 true，就能看到每一次状态转移
+//!
+//! 每个 Case 中，主机先写入 initiator_tx，再以 Repeated START 读取 responder_tx.len() 个字节，
+//! 任意一边为空时，就只有单纯的写或者单纯的读
+//!
+//! 从机在以下情况下认为一次传输结束：
+//! - 主机读取之后，以 NACK 回复最后一个字节（AF）
+//! - 主机只写不读时，产生 STOP condition（STOPF）
+//!
+//! 接线图：
+//!
+//! I2C1 SCL PB6 <-> I2C3 SCL PA8
+//! I2C1 SDA PB7 <-> I2C3 SDA PC9
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+
+use stm32f4xx_hal::pac::{i2c1::RegisterBlock, Peripherals, I2C1};
+
+mod utils;
+use utils::{
+    addressing::I2cAddress,
+    fsm::{named_enum, Fsm, Transition},
+    loopback::{Case, EventFlags, Frame, Harness, Side, Step, Summary, RESPONDER_READY},
+    setup_pll,
+};
+
+const SLAVE_ADDRESS: I2cAddress = I2cAddress::SevenBit(0b1010101);
+
+// 500 kHz 的 SCL 下，32 个字节大约需要 600 us
+const MAX_POLLS: u32 = 1_000_000;
+
+const ROUNDS: u32 = 10;
+
+// 打印主机状态机的每一次转移，会拖慢轮询，只在排查问题时打开
+const TRACE: bool = false;
+
+const CASES: [Case; 5] = [
+    Case {
+        name: "write only",
+        initiator_tx: &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09],
+        responder_tx: &[],
+    },
+    Case {
+        name: "read only",
+        initiator_tx: &[],
+        responder_tx: &[0xA0, 0xA1, 0xA2, 0xA3],
+    },
+    Case {
+        name: "single byte read",
+        initiator_tx: &[],
+        responder_tx: &[0x5A],
+    },
+    Case {
+        name: "register read",
+        initiator_tx: &[0x10],
+        responder_tx: &[0xDE, 0xAD, 0xBE, 0xEF],
+    },
+    Case {
+        name: "full frame",
+        initiator_tx: &[
+            0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01, 0x7F, 0xBF, 0xDF, 0xEF, 0xF7, 0xFB,
+            0xFD, 0xFE,
+        ],
+        responder_tx: &[
+            0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA,
+            0x55, 0xAA,
+        ],
+    },
+];
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = Peripherals::take().expect("Cannot Get Peripherals");
+
+    setup_pll::setup(&dp);
+    setup_gpio(&dp);
+    setup_i2c_master(&dp);
+    setup_i2c_slave(&dp);
+
+    let mut harness = Harness::new(I2cMaster::new(&dp), I2cSlave::new(&dp), MAX_POLLS);
+
+    let mut total = Summary::default();
+    for round in 1..=ROUNDS {
+        rprintln!("round {}", round);
+        let summary = harness.run_all(&CASES);
+        total.passed += summary.passed;
+        total.failed += summary.failed;
+    }
+    total.report();
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+named_enum! {
+    enum MasterState {
+        Idle,
+        // 等待 SB
+        WaitStart,
+        // 等待 ADDR
+        WaitAddr,
+        Writing,
+        Reading,
+        // 等待硬件清理 CR1 中的 STOP
+        WaitStop,
+    }
+}
+
+// 除了 Begin 与 Error，其余事件都对应 SR1（或 CR1）中的一个标识位
+named_enum! {
+    enum MasterEvent {
+        Begin,
+        Sb,
+        Addr,
+        TxE,
+        Btf,
+        RxNe,
+        StopCleared,
+        Error,
+    }
+}
+
+// 状态机的上下文，guard 与 action 只能访问这里的数据
+//
+// 转移表是 const，上下文里不能放带生命周期的 &Peripherals，因此 action 通过 i2c1() 访问寄存器
+struct MasterCtx {
+    frame: Frame,
+}
+
+fn i2c1() -> &'static RegisterBlock {
+    unsafe { &*I2C1::ptr() }
+}
+
+impl MasterCtx {
+    // 发送的部分已经结束（或者本来就没有），接下来是读取
+    fn reading(&self) -> bool {
+        self.frame.tx_remaining() == 0
+    }
+}
+
+// 每一行读作：在 from 状态下收到 event，且 guard 成立时，执行 action，进入 to 状态
+// 同一状态与事件的多行按顺序匹配，因此带 guard 的行要写在不带 guard 的行之前
+const MASTER_TABLE: &[Transition<MasterState, MasterEvent, MasterCtx>] = {
+    use MasterEvent as E;
+    use MasterState as S;
+    &[
+        Transition::new(S::Idle, E::Begin, S::WaitStart)
+            .when(has_work)
+            .then(start),
+        Transition::new(S::WaitStart, E::Sb, S::WaitAddr).then(send_address),
+        Transition::new(S::WaitAddr, E::Addr, S::Reading)
+            .when(single_read)
+            .then(begin_single_read),
+        Transition::new(S::WaitAddr, E::Addr, S::Reading)
+            .when(MasterCtx::reading)
+            .then(clear_addr),
+        Transition::new(S::WaitAddr, E::Addr, S::Writing).then(clear_addr),
+        Transition::new(S::Writing, E::TxE, S::Writing)
+            .when(tx_left)
+            .then(write_byte),
+        // 最后一个字节真正发送完成之后，才能产生 Repeated START 或者 STOP condition
+        Transition::new(S::Writing, E::Btf, S::WaitStart)
+            .when(read_after_write)
+            .then(start),
+        Transition::new(S::Writing, E::Btf, S::WaitStop)
+            .when(MasterCtx::reading)
+            .then(stop),
+        Transition::new(S::Reading, E::RxNe, S::WaitStop)
+            .when(last_byte)
+            .then(read_byte),
+        Transition::new(S::Reading, E::RxNe, S::Reading)
+            .when(second_last_byte)
+            .then(read_second_last),
+        Transition::new(S::Reading, E::RxNe, S::Reading).then(read_byte),
+        Transition::new(S::WaitStop, E::StopCleared, S::Idle),
+        // 错误已经由 check_error 清理过了
+        Transition::any(E::Error, S::Idle),
+    ]
+};
+
+// 以下为 guard
+
+fn has_work(c: &MasterCtx) -> bool {
+    c.frame.tx_remaining() > 0 || c.frame.rx_remaining() > 0
+}
+
+fn single_read(c: &MasterCtx) -> bool {
+    c.reading() && c.frame.rx_remaining() == 1
+}
+
+fn tx_left(c: &MasterCtx) -> bool {
+    c.frame.tx_remaining() > 0
+}
+
+fn read_after_write(c: &MasterCtx) -> bool {
+    c.reading() && c.frame.rx_remaining() > 0
+}
+
+fn last_byte(c: &MasterCtx) -> bool {
+    c.frame.rx_remaining() == 1
+}
+
+fn second_last_byte(c: &MasterCtx) -> bool {
+    c.frame.rx_remaining() == 2
+}
+
+// 以下为 action
+
+fn start(_: &mut MasterCtx) {
+    i2c1().cr1.modify(|_, w| {
+        w.ack().ack();
+        w.start().start();
+        w
+    });
+}
+
+// 读 SR1 之后写 DR，以清理 SB
+fn send_address(c: &mut MasterCtx) {
+    let read = c.reading();
+    i2c1()
+        .dr
+        .write(|w| w.dr().bits(SLAVE_ADDRESS.first_byte(read)));
+}
+
+// 读 SR1 之后读 SR2，以清理 ADDR
+fn clear_addr(_: &mut MasterCtx) {
+    i2c1().sr1.read();
+    i2c1().sr2.read();
+}
+
+// 只接收 1 个字节时，必须在清理 ADDR 之前就关闭 ACK，见 utils::blocking_master
+fn begin_single_read(c: &mut MasterCtx) {
+    i2c1().cr1.modify(|_, w| w.ack().nak());
+    clear_addr(c);
+    stop(c);
+}
+
+fn write_byte(c: &mut MasterCtx) {
+    let byte = c.frame.next_tx().unwrap_or_default();
+    i2c1().dr.write(|w| w.dr().bits(byte));
+}
+
+fn stop(_: &mut MasterCtx) {
+    i2c1().cr1.modify(|_, w| w.stop().stop());
+}
+
+fn read_byte(c: &mut MasterCtx) {
+    c.frame.push_rx(i2c1().dr.read().dr().bits());
+}
+
+// 读取倒数第二个字节之后，立刻准备好 NACK 与 STOP，见 utils::blocking_master
+fn read_second_last(c: &mut MasterCtx) {
+    read_byte(c);
+    i2c1().cr1.modify(|_, w| {
+        w.ack().nak();
+        w.stop().stop();
+        w
+    });
+}
+
+// I2C1，主机，同时也是发起方
+//
+// 原本是一个 match 当前状态、再逐个检查标识位的 poll，现在改为 utils::fsm 的转移表，
+// poll 只负责把 SR1 中的标识位翻译为事件，交给状态机
+struct I2cMaster<'a> {
+    dp: &'a Peripherals,
+    ctx: MasterCtx,
+    fsm: Fsm<MasterState, MasterEvent, MasterCtx>,
+}
+
+impl<'a> I2cMaster<'a> {
+    fn new(dp: &'a Peripherals) -> Self {
+        let mut fsm = Fsm::new("I2C1", MasterState::Idle, MASTER_TABLE);
+        fsm.set_trace(TRACE);
+        Self {
+            dp,
+            ctx: MasterCtx {
+                frame: Frame::new(),
+            },
+            fsm,
+        }
+    }
+
+    // 与 utils::blocking_master 中的 check_error 相同，只不过不会一直等待
+    fn check_error(&self) -> Result<(), &'static str> {
+        let master = &self.dp.I2C1;
+        let sr1 = master.sr1.read();
+
+        let reason = if sr1.af().bit_is_set() {
+            master.cr1.modify(|_, w| w.stop().stop());
+            "nack"
+        } else if sr1.arlo().bit_is_set() {
+            "arbitration lost"
+        } else if sr1.berr().bit_is_set() {
+            "bus error"
+        } else {
+            return Ok(());
+        };
+
+        master.sr1.modify(|_, w| {
+            w.af().clear_bit();
+            w.arlo().clear_bit();
+            w.berr().clear_bit();
+            w
+        });
+        Err(reason)
+    }
+}
+
+impl Side for I2cMaster<'_> {
+    fn name(&self) -> &'static str {
+        "I2C1"
+    }
+
+    fn begin(&mut self, _flags: &EventFlags, tx: &[u8], rx_len: usize) {
+        self.ctx.frame.load(tx, rx_len);
+        // 既不写也不读时，没有匹配的转移，保持 Idle，下一次 poll 直接返回 Done
+        self.fsm.handle(MasterEvent::Begin, &mut self.ctx);
+    }
+
+    fn poll(&mut self, _flags: &EventFlags) -> Step {
+        if self.fsm.is_in(MasterState::Idle) {
+            return Step::Done;
+        }
+
+        if let Err(reason) = self.check_error() {
+            self.fsm.handle(MasterEvent::Error, &mut self.ctx);
+            return Step::Failed(reason);
+        }
+
+        let master = &self.dp.I2C1;
+        let sr1 = master.sr1.read();
+        let events = [
+            (sr1.sb().is_start(), MasterEvent::Sb),
+            (sr1.addr().is_match(), MasterEvent::Addr),
+            (sr1.tx_e().is_empty(), MasterEvent::TxE),
+            (sr1.btf().bit_is_set(), MasterEvent::Btf),
+            (sr1.rx_ne().is_not_empty(), MasterEvent::RxNe),
+            (
+                master.cr1.read().stop().bit_is_clear(),
+                MasterEvent::StopCleared,
+            ),
+        ];
+
+        // 每次 poll 最多转移一次：转移的 action 可能已经改变了硬件的状态（比如写 DR 会清理 BTF），
+        // 这次读到的 SR1 已经过时了，剩下的标识位留到下一次 poll 重新读取
+        for (set, event) in events {
+            if set && self.fsm.handle(event, &mut self.ctx) {
+                break;
+            }
+        }
+
+        if self.fsm.is_in(MasterState::Idle) {
+            Step::Done
+        } else {
+            Step::Pending
+        }
+    }
+
+    fn abort(&mut self) {
+        // 关闭 PE 会让 I2C1 释放 SCL 与 SDA
+        let master = &self.dp.I2C1;
+        master.cr1.modify(|_, w| w.pe().disabled());
+        master.cr1.modify(|_, w| w.pe().enabled());
+        self.fsm.force(MasterState::Idle, &mut self.ctx);
+    }
+
+    fn received(&self) -> &[u8] {
+        self.ctx.frame.received()
+    }
+}
+
+// I2C3，从机，同时也是响应方
+struct I2cSlave<'a> {
+    dp: &'a Peripherals,
+    frame: Frame,
+    // ADDR 时 SR2 的 TRA，即主机是否在读取
+    transmitting: bool,
+}
+
+impl<'a> I2cSlave<'a> {
+    fn new(dp: &'a Peripherals) -> Self {
+        Self {
+            dp,
+            frame: Frame::new(),
+            transmitting: false,
+        }
+    }
+}
+
+impl Side for I2cSlave<'_> {
+    fn name(&self) -> &'static str {
+        "I2C3"
+    }
+
+    fn begin(&mut self, flags: &EventFlags, tx: &[u8], rx_len: usize) {
+        self.frame.load(tx, rx_len);
+        self.transmitting = false;
+
+        // 清理上一次传输可能残留的 AF 与 STOPF
+        let slave = &self.dp.I2C3;
+        slave.sr1.modify(|_, w| w.af().clear_bit());
+        if slave.sr1.read().stopf().is_stop() {
+            slave.cr1.modify(|_, w| w);
+        }
+        slave.cr1.modify(|_, w| w.ack().ack());
+
+        // I2C 从机会拉低 SCL 等待软件处理，因此只要开启了 ACK，就可以让主机开始了
+        flags.set(RESPONDER_READY);
+    }
+
+    fn poll(&mut self, _flags: &EventFlags) -> Step {
+        let slave = &self.dp.I2C3;
+        let sr1 = slave.sr1.read();
+
+        if sr1.berr().bit_is_set() {
+            slave.sr1.modify(|_, w| w.berr().clear_bit());
+            return Step::Failed("bus error");
+        }
+        if sr1.ovr().bit_is_set() {
+            slave.sr1.modify(|_, w| w.ovr().clear_bit());
+            return Step::Failed("overrun");
+        }
+
+        if sr1.addr().is_match() {
+            // 上面已经读过 SR1，这里读 SR2 就清理了 ADDR
+            self.transmitting = slave.sr2.read().tra().bit_is_set();
+        }
+
+        if sr1.rx_ne().is_not_empty() && !self.frame.push_rx(slave.dr.read().dr().bits()) {
+            return Step::Failed("too many bytes");
+        }
+
+        if self.transmitting && sr1.tx_e().is_empty() {
+            // 要发的数据发完之后不再写入 DR，主机会以 NACK 结束读取
+            if let Some(byte) = self.frame.next_tx() {
+                slave.dr.write(|w| w.dr().bits(byte));
+            }
+        }
+
+        // 从机发送时，主机以 NACK 回复最后一个字节，此时不会再有 STOPF，见 s04c03
+        if sr1.af().bit_is_set() {
+            slave.sr1.modify(|_, w| w.af().clear_bit());
+            return Step::Done;
+        }
+
+        if sr1.stopf().is_stop() {
+            // 清理 STOPF，见 s04c01
+            slave.cr1.modify(|_, w| w);
+            return Step::Done;
+        }
+
+        Step::Pending
+    }
+
+    fn abort(&mut self) {
+        // 重新开启 PE 之后，ACK 需要再设置一遍，这在下一次 begin 中完成
+        let slave = &self.dp.I2C3;
+        slave.cr1.modify(|_, w| w.pe().disabled());
+        slave.cr1.modify(|_, w| w.pe().enabled());
+        self.transmitting = false;
+    }
+
+    fn received(&self) -> &[u8] {
+        self.frame.received()
+    }
+}
+
+fn setup_gpio(dp: &Peripherals) {
+    // I2C1: PB6 SCL, PB7 SDA
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| {
+        w.afrl6().af4();
+        w.afrl7().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot6().open_drain();
+        w.ot7().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr6().pull_up();
+        w.pupdr7().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w
+    });
+
+    // I2C3: PA8 SCL, PC9 SDA
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| w.afrh8().af4());
+    gpioa.otyper.modify(|_, w| w.ot8().open_drain());
+    gpioa.pupdr.modify(|_, w| w.pupdr8().pull_up());
+    gpioa.moder.modify(|_, w| w.moder8().alternate());
+
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    let gpioc = &dp.GPIOC;
+    gpioc.afrh.modify(|_, w| w.afrh9().af4());
+    gpioc.otyper.modify(|_, w| w.ot9().open_drain());
+    gpioc.pupdr.modify(|_, w| w.pupdr9().pull_up());
+    gpioc.moder.modify(|_, w| w.moder9().alternate());
+}
+
+// 时钟相关的设置，见 s04c01
+fn setup_i2c_master(dp: &Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let master = &dp.I2C1;
+    master.cr2.modify(|_, w| unsafe { w.freq().bits(32) });
+    master.ccr.modify(|_, w| unsafe { w.ccr().bits(32) });
+    master.trise.write(|w| w.trise().bits(33));
+    master.cr1.modify(|_, w| w.pe().enabled());
+}
+
+// 不开启任何中断，全部由轮询处理
+fn setup_i2c_slave(dp: &Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.i2c3en().enabled());
+
+    let slave = &dp.I2C3;
+    slave.cr2.modify(|_, w| unsafe { w.freq().bits(32) });
+    SLAVE_ADDRESS.set_as_own_address(slave);
+    slave.cr1.modify(|_, w| w.pe().enabled());
+}
@@ -0,0 +1,119 @@
+//! 打印外设寄存器的快照，并对比两次快照之间的差别
+//!
+//! 工具见 utils::regdump
+//!
+//! 这里一步一步地配置 I2C1，并向一个不存在的从机地址发起一次写入，每一步之后都打印 I2C1 的寄存器发生了哪些变化：
+//!
+//! 1. 开启时钟之前与之后的 RCC
+//! 2. 配置 CR2/CCR/TRISE，打开 PE
+//! 3. 发送 START 之后，SR1 中出现 SB，SR2 中的 MSL 与 BUSY 这里看不到（读取 SR2 有副作用，见 utils::regdump）
+//! 4. 写入地址之后，没有从机应答，SR1 中出现 AF
+//! 5. 发送 STOP，清除 AF
+//!
+//! 接线图：
+//!
+//! PB6 I2C1_SCL <-> 上拉电阻（或者任意一个 I2C 模块，只要它不使用 0x50 这个地址）
+//! PB7 I2C1_SDA <-> 上拉电阻
+//!
+//! 没有接任何设备时，PB6/PB7 内部的上拉也足以完成这个演示
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::Peripherals;
+
+mod utils;
+use utils::regdump::{self, Snapshot};
+
+// 一个没有设备的地址
+const ABSENT_ADDRESS: u8 = 0x50;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = Peripherals::take().unwrap();
+
+    let rcc = regdump::rcc();
+    let i2c1 = regdump::i2c(1).unwrap();
+
+    let rcc_before = Snapshot::take(&rcc);
+    rprintln!("{}", rcc_before);
+
+    setup_gpio(&dp);
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+    rprintln!("{}", rcc_before.diff(&Snapshot::take(&rcc)));
+
+    let mut before = Snapshot::take(&i2c1);
+    rprintln!("{}", before);
+
+    let i2c = &dp.I2C1;
+
+    // 步骤 2，HSI 16 MHz，标准模式 100 kHz
+    // CCR = 16 MHz / (2 * 100 kHz) = 80，TRISE = 1000 ns * 16 MHz + 1 = 17
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(16) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(80) });
+    i2c.trise.write(|w| w.trise().bits(17));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+    before = step("enable", &i2c1, &before);
+
+    // 步骤 3
+    i2c.cr1.modify(|_, w| w.start().start());
+    while i2c.sr1.read().sb().bit_is_clear() {}
+    before = step("start", &i2c1, &before);
+
+    // 步骤 4，写入 DR 同时清除 SB
+    i2c.dr.write(|w| w.dr().bits(ABSENT_ADDRESS << 1));
+    while i2c.sr1.read().addr().bit_is_clear() && i2c.sr1.read().af().bit_is_clear() {}
+    before = step("address", &i2c1, &before);
+    if before.get_field("SR1", "AF") == Some(1) {
+        rprintln!("no ACK from 0x{:02X}", ABSENT_ADDRESS);
+    }
+
+    // 步骤 5
+    i2c.cr1.modify(|_, w| w.stop().stop());
+    i2c.sr1.modify(|_, w| w.af().clear_bit());
+    while i2c.cr1.read().stop().is_stop() {}
+    step("stop", &i2c1, &before);
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+// 打印与上一次快照之间的差别，返回新的快照
+fn step(name: &str, peripheral: &regdump::Peripheral, before: &Snapshot) -> Snapshot {
+    let after = Snapshot::take(peripheral);
+    rprintln!("--- {} ---\n{}", name, before.diff(&after));
+    after
+}
+
+fn setup_gpio(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+
+    let gpiob = &dp.GPIOB;
+
+    // 开漏与上拉的原因，见 s04c01
+    gpiob.afrl.modify(|_, w| {
+        w.afrl6().af4();
+        w.afrl7().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot6().open_drain();
+        w.ot7().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr6().pull_up();
+        w.pupdr7().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w
+    });
+}
@@ -0,0 +1,197 @@
+//! 表驱动的状态机，用来代替协议驱动里“逐个检查标识位”的长串 if
+//!
+//! I2C、USART、USB 的中断（或者轮询）处理函数都长得差不多：读出状态寄存器，然后一个标识位一个 if，
+//! 每个 if 里面再看当前处于哪一步，哪些组合是合法的、哪些组合被漏掉了，只能逐行去读
+//!
+//! 这里把它拆成三部分：
+//!
+//! - 状态（S）与事件（E）：两个普通的 enum，用 named_enum! 定义，这样跟踪输出里能直接打印出名字
+//! - 转移表：一个 Transition 的数组，每一行为“在 from 状态下收到 event，且 guard 成立时，执行 action，并进入 to 状态”
+//!   同一个状态与事件可以有多行，按顺序检查 guard，第一条成立的生效；from 为 Source::Any 的行对所有状态都有效
+//! - 上下文（C）：guard 与 action 读写的数据，比如收发缓冲；状态机本身不关心它是什么
+//!
+//! 此外还可以给出一个 on_entry，状态真正发生变化时（不包括自己转移到自己）调用，适合放“进入某个状态必须做的事”
+//!
+//! 一次 handle 只处理一个事件，最多发生一次转移；没有匹配的行时什么也不做，返回 false，
+//! 调用者可以据此决定“本次没有被处理”的事件是忽略还是报错
+//!
+//! 打开 trace 之后，每次转移都会输出一行 “名字: 原状态 --事件--> 新状态”，
+//! 启用 defmt 特性时使用 defmt::trace!，否则使用 rprintln!
+
+#![allow(dead_code)]
+
+// 状态与事件都需要能给出自己的名字，由 named_enum! 自动实现
+pub(crate) trait Named {
+    fn name(&self) -> &'static str;
+}
+
+// 定义一个只有单元变体的 enum，并为其实现 Named
+#[allow(unused_macros)]
+macro_rules! named_enum {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($variant:ident),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        $vis enum $name {
+            $($variant),*
+        }
+
+        impl $crate::utils::fsm::Named for $name {
+            fn name(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => stringify!($variant)),*
+                }
+            }
+        }
+    };
+}
+
+#[allow(unused_imports)]
+pub(crate) use named_enum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Source<S> {
+    In(S),
+    // 任意状态，一般用于错误处理
+    Any,
+}
+
+pub(crate) struct Transition<S, E, C> {
+    pub(crate) from: Source<S>,
+    pub(crate) event: E,
+    pub(crate) guard: Option<fn(&C) -> bool>,
+    pub(crate) action: Option<fn(&mut C)>,
+    pub(crate) to: S,
+}
+
+impl<S: Copy, E: Copy, C> Transition<S, E, C> {
+    pub(crate) const fn new(from: S, event: E, to: S) -> Self {
+        Self {
+            from: Source::In(from),
+            event,
+            guard: None,
+            action: None,
+            to,
+        }
+    }
+
+    pub(crate) const fn any(event: E, to: S) -> Self {
+        Self {
+            from: Source::Any,
+            event,
+            guard: None,
+            action: None,
+            to,
+        }
+    }
+
+    pub(crate) const fn when(mut self, guard: fn(&C) -> bool) -> Self {
+        self.guard = Some(guard);
+        self
+    }
+
+    pub(crate) const fn then(mut self, action: fn(&mut C)) -> Self {
+        self.action = Some(action);
+        self
+    }
+}
+
+pub(crate) struct Fsm<S: 'static, E: 'static, C: 'static> {
+    name: &'static str,
+    state: S,
+    table: &'static [Transition<S, E, C>],
+    on_entry: Option<fn(S, &mut C)>,
+    trace: bool,
+}
+
+impl<S, E, C> Fsm<S, E, C>
+where
+    S: Copy + PartialEq + Named,
+    E: Copy + PartialEq + Named,
+{
+    pub(crate) const fn new(
+        name: &'static str,
+        initial: S,
+        table: &'static [Transition<S, E, C>],
+    ) -> Self {
+        Self {
+            name,
+            state: initial,
+            table,
+            on_entry: None,
+            trace: false,
+        }
+    }
+
+    pub(crate) const fn with_entry(mut self, on_entry: fn(S, &mut C)) -> Self {
+        self.on_entry = Some(on_entry);
+        self
+    }
+
+    pub(crate) fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    pub(crate) fn state(&self) -> S {
+        self.state
+    }
+
+    pub(crate) fn is_in(&self, state: S) -> bool {
+        self.state == state
+    }
+
+    // 查表处理一个事件，返回是否发生了转移（包括转移到自己）
+    pub(crate) fn handle(&mut self, event: E, ctx: &mut C) -> bool {
+        let state = self.state;
+        let row = self.table.iter().find(|row| {
+            let from_matches = match row.from {
+                Source::In(from) => from == state,
+                Source::Any => true,
+            };
+            from_matches && row.event == event && row.guard.is_none_or(|guard| guard(ctx))
+        });
+
+        match row {
+            Some(row) => {
+                if let Some(action) = row.action {
+                    action(ctx);
+                }
+                self.enter(Some(event), row.to, ctx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 不经过转移表，直接进入某个状态，比如出错之后复位，同样会调用 on_entry
+    pub(crate) fn force(&mut self, to: S, ctx: &mut C) {
+        self.enter(None, to, ctx);
+    }
+
+    fn enter(&mut self, event: Option<E>, to: S, ctx: &mut C) {
+        let from = self.state;
+        self.state = to;
+        if self.trace {
+            trace(
+                self.name,
+                from.name(),
+                event.map_or("force", |e| e.name()),
+                to.name(),
+            );
+        }
+        if from != to {
+            if let Some(on_entry) = self.on_entry {
+                on_entry(to, ctx);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+fn trace(name: &str, from: &str, event: &str, to: &str) {
+    defmt::trace!("{=str}: {=str} --{=str}--> {=str}", name, from, event, to);
+}
+
+#[cfg(not(feature = "defmt"))]
+fn trace(name: &str, from: &str, event: &str, to: &str) {
+    rtt_target::rprintln!("{}: {} --{}--> {}", name, from, event, to);
+}
@@ -1,2 +1,12 @@
+pub(crate) mod fsm;
 pub(crate) mod printing;
+pub(crate) mod regdump;
 pub(crate) mod setup_pll;
+pub(crate) mod smbus;
+
+// 与其它章节共用的模块，代码在工作区中单独的 crate 里
+// 与上面的模块一样，不是每个程序都会用到，因此允许未使用
+#[allow(unused_imports)]
+pub(crate) use mcu_common::{cycle_stats, irq, loopback, reg_batch, resources};
+#[allow(unused_imports)]
+pub(crate) use i2c_master::{addressing, blocking_master};
@@ -1,5 +1,6 @@
 // 将 println! 包裹了一下，节省了一点重复的格式化代码
 
+#[allow(unused_macros)]
 macro_rules! master_rprintln {
     ($s:literal)=>{
         rtt_target::rprintln!(concat!("\x1b[91mMaster:\t", $s ,"\x1b[0m"));
@@ -9,6 +10,7 @@ macro_rules! master_rprintln {
     };
 }
 
+#[allow(unused_macros)]
 macro_rules! slave_rprintln {
     ($s:literal)=>{
         rtt_target::rprintln!(concat!("\x1b[92mSlave:\t", $s ,"\x1b[0m"));
@@ -20,5 +22,7 @@ macro_rules! slave_rprintln {
 
 // 为了让 macro 属于某个层级，使用我看不懂的什么奇淫巧计……
 // https://users.rust-lang.org/t/how-to-namespace-a-macro-rules-macro-within-a-module-or-macro-export-it-without-polluting-the-top-level-namespace/63779/4
+#[allow(unused_imports)]
 pub(crate) use master_rprintln;
+#[allow(unused_imports)]
 pub(crate) use slave_rprintln;
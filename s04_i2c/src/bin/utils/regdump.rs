@@ -0,0 +1,950 @@
+//! 外设寄存器的快照与对比
+//!
+//! 程序不按预期工作时，最直接的办法是把相关外设的寄存器全部读出来，逐位对照参考手册
+//! 这里把 RCC、TIMx、I2Cx、DMA 的数据流与 QUADSPI 的寄存器及其中的字段整理成表，Snapshot::take 一次读出整个外设，
+//! 打印时只列出不为 0 的字段（只有 1 位的字段只打印名称），比如
+//!
+//! ```text
+//! I2C1 @ 0x40005400
+//!   CR1     +0x00 = 0x00000101  PE START
+//!   CR2     +0x04 = 0x00000020  FREQ=32
+//!   SR1     +0x14 = 0x00000001  SB
+//! ```
+//!
+//! 对同一个外设的两次快照，可以用 Diff 只打印发生了变化的寄存器与字段
+//!
+//! 字段的名称、位置与宽度与 PAC（也就是 SVD 文件）及参考手册 RM0430 中的一致，
+//! 只是 PAC 并不在运行时提供这些信息，因此这里手工整理成了常量表，只收录了常用的字段，表中没有的位会以 ?? 的形式打印出来
+//!
+//! 注意：有一些寄存器，读取本身就会改变外设的状态，它们被标记为 Access::SideEffect，快照中不会读取：
+//! - I2C 的 DR（读取会清除 RxNE）与 SR2（先读 SR1 再读 SR2 会清除 ADDR）
+//! - QUADSPI 的 DR（读取会从 FIFO 中取走数据）
+//! - TIM 的 DMAR（读取会触发一次 DMA burst 访问）
+//!
+//! 只写的寄存器（比如 TIM 的 EGR、QUADSPI 的 FCR）读出来总是 0，同样不会读取
+//!
+//! 没有开启时钟的外设，寄存器读出来全是 0，Snapshot::take 会顺便检查 RCC 中对应的时钟是否开启
+
+#![allow(dead_code)]
+
+use core::fmt;
+
+use stm32f4xx_hal::pac;
+
+// 一个外设最多的寄存器个数，RCC 最多
+const MAX_REGISTERS: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    Normal,
+    // 读取会改变外设的状态
+    SideEffect,
+    WriteOnly,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Field {
+    pub(crate) name: &'static str,
+    pub(crate) lsb: u8,
+    pub(crate) width: u8,
+}
+
+impl Field {
+    fn mask(&self) -> u32 {
+        (u32::MAX >> (32 - self.width)) << self.lsb
+    }
+
+    fn extract(&self, value: u32) -> u32 {
+        (value & self.mask()) >> self.lsb
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Register {
+    pub(crate) name: &'static str,
+    // 相对于外设基地址的偏移
+    pub(crate) offset: u32,
+    pub(crate) access: Access,
+    pub(crate) fields: &'static [Field],
+}
+
+// 外设的描述
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Peripheral {
+    pub(crate) name: &'static str,
+    pub(crate) base: u32,
+    pub(crate) registers: &'static [Register],
+    // 外设时钟在 RCC 中的位置：ENR 寄存器相对于 RCC 的偏移，以及所在的位
+    clock: Option<(u32, u8)>,
+}
+
+const fn f(name: &'static str, lsb: u8, width: u8) -> Field {
+    Field { name, lsb, width }
+}
+
+const fn r(name: &'static str, offset: u32, fields: &'static [Field]) -> Register {
+    Register {
+        name,
+        offset,
+        access: Access::Normal,
+        fields,
+    }
+}
+
+const fn side_effect(name: &'static str, offset: u32) -> Register {
+    Register {
+        name,
+        offset,
+        access: Access::SideEffect,
+        fields: &[],
+    }
+}
+
+const fn write_only(name: &'static str, offset: u32) -> Register {
+    Register {
+        name,
+        offset,
+        access: Access::WriteOnly,
+        fields: &[],
+    }
+}
+
+// RCC 中各个 ENR 寄存器的偏移
+const AHB1ENR: u32 = 0x30;
+const AHB3ENR: u32 = 0x38;
+const APB1ENR: u32 = 0x40;
+const APB2ENR: u32 = 0x44;
+
+pub(crate) fn rcc() -> Peripheral {
+    Peripheral {
+        name: "RCC",
+        base: pac::RCC::ptr() as u32,
+        registers: RCC_REGISTERS,
+        clock: None,
+    }
+}
+
+// TIM1 ~ TIM14，F413 没有 TIM15 之后的定时器
+// F401/F411 没有 TIM6/7/8/12/13/14，见 chip_caps，选了这两种芯片时返回 None
+pub(crate) fn tim(n: u8) -> Option<Peripheral> {
+    let (name, base, clock) = match n {
+        1 => ("TIM1", pac::TIM1::ptr() as u32, (APB2ENR, 0)),
+        2 => ("TIM2", pac::TIM2::ptr() as u32, (APB1ENR, 0)),
+        3 => ("TIM3", pac::TIM3::ptr() as u32, (APB1ENR, 1)),
+        4 => ("TIM4", pac::TIM4::ptr() as u32, (APB1ENR, 2)),
+        5 => ("TIM5", pac::TIM5::ptr() as u32, (APB1ENR, 3)),
+        #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+        6 => ("TIM6", pac::TIM6::ptr() as u32, (APB1ENR, 4)),
+        #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+        7 => ("TIM7", pac::TIM7::ptr() as u32, (APB1ENR, 5)),
+        #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+        8 => ("TIM8", pac::TIM8::ptr() as u32, (APB2ENR, 1)),
+        9 => ("TIM9", pac::TIM9::ptr() as u32, (APB2ENR, 16)),
+        10 => ("TIM10", pac::TIM10::ptr() as u32, (APB2ENR, 17)),
+        11 => ("TIM11", pac::TIM11::ptr() as u32, (APB2ENR, 18)),
+        #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+        12 => ("TIM12", pac::TIM12::ptr() as u32, (APB1ENR, 6)),
+        #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+        13 => ("TIM13", pac::TIM13::ptr() as u32, (APB1ENR, 7)),
+        #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+        14 => ("TIM14", pac::TIM14::ptr() as u32, (APB1ENR, 8)),
+        _ => return None,
+    };
+    // 各个定时器的寄存器位置相同，只是基本定时器与通用定时器缺少一部分，缺少的寄存器读出来是 0
+    Some(Peripheral {
+        name,
+        base,
+        registers: TIM_REGISTERS,
+        clock: Some(clock),
+    })
+}
+
+pub(crate) fn i2c(n: u8) -> Option<Peripheral> {
+    let (name, base, bit) = match n {
+        1 => ("I2C1", pac::I2C1::ptr() as u32, 21),
+        2 => ("I2C2", pac::I2C2::ptr() as u32, 22),
+        3 => ("I2C3", pac::I2C3::ptr() as u32, 23),
+        _ => return None,
+    };
+    Some(Peripheral {
+        name,
+        base,
+        registers: I2C_REGISTERS,
+        clock: Some((APB1ENR, bit)),
+    })
+}
+
+// DMA1/DMA2 的数据流 0 ~ 7，同时包含了该数据流在 LISR/HISR 中的中断标志
+pub(crate) fn dma_stream(dma: u8, stream: u8) -> Option<Peripheral> {
+    let (name, base, bit) = match dma {
+        1 => (DMA1_STREAM_NAMES, pac::DMA1::ptr() as u32, 21),
+        2 => (DMA2_STREAM_NAMES, pac::DMA2::ptr() as u32, 22),
+        _ => return None,
+    };
+    if stream >= 8 {
+        return None;
+    }
+    Some(Peripheral {
+        name: name[stream as usize],
+        base,
+        registers: &DMA_STREAM_REGISTERS[stream as usize],
+        clock: Some((AHB1ENR, bit)),
+    })
+}
+
+// F401/F411 没有 QUADSPI，见 chip_caps
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub(crate) fn quadspi() -> Peripheral {
+    Peripheral {
+        name: "QUADSPI",
+        base: pac::QUADSPI::ptr() as u32,
+        registers: QUADSPI_REGISTERS,
+        clock: Some((AHB3ENR, 1)),
+    }
+}
+
+// 一个外设在某一时刻的全部寄存器
+#[derive(Clone, Copy)]
+pub(crate) struct Snapshot {
+    peripheral: Peripheral,
+    // 没有读取的寄存器为 None
+    values: [Option<u32>; MAX_REGISTERS],
+    clock_enabled: bool,
+}
+
+impl Snapshot {
+    pub(crate) fn take(peripheral: &Peripheral) -> Self {
+        let mut values = [None; MAX_REGISTERS];
+        for (value, register) in values.iter_mut().zip(peripheral.registers) {
+            if register.access == Access::Normal {
+                *value = Some(read(peripheral.base + register.offset));
+            }
+        }
+
+        let clock_enabled = match peripheral.clock {
+            Some((enr, bit)) => read(pac::RCC::ptr() as u32 + enr) & (1 << bit) != 0,
+            None => true,
+        };
+
+        Self {
+            peripheral: *peripheral,
+            values,
+            clock_enabled,
+        }
+    }
+
+    pub(crate) fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+
+    // 按名称取得某个寄存器的值，没有读取时返回 None
+    pub(crate) fn get(&self, register: &str) -> Option<u32> {
+        self.registers()
+            .find(|(r, _)| r.name == register)
+            .and_then(|(_, value)| value)
+    }
+
+    // 按名称取得某个字段的值，比如 get_field("SR1", "SB")
+    pub(crate) fn get_field(&self, register: &str, field: &str) -> Option<u32> {
+        let (r, value) = self.registers().find(|(r, _)| r.name == register)?;
+        let field = r.fields.iter().find(|f| f.name == field)?;
+        Some(field.extract(value?))
+    }
+
+    // 与另一次快照的差别，两次快照必须来自同一个外设
+    pub(crate) fn diff<'a>(&'a self, later: &'a Snapshot) -> Diff<'a> {
+        Diff {
+            before: self,
+            after: later,
+        }
+    }
+
+    fn registers(&self) -> impl Iterator<Item = (&'static Register, Option<u32>)> + '_ {
+        self.peripheral
+            .registers
+            .iter()
+            .zip(self.values.iter().copied())
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} @ {:#010x}",
+            self.peripheral.name, self.peripheral.base
+        )?;
+        if !self.clock_enabled {
+            write!(f, " (clock disabled)")?;
+        }
+        for (register, value) in self.registers() {
+            write!(f, "\n  {:<7} +{:#04x} = ", register.name, register.offset)?;
+            match value {
+                Some(value) => {
+                    write!(f, "{:#010x} ", value)?;
+                    write_fields(f, register, value)?;
+                }
+                None if register.access == Access::WriteOnly => write!(f, "(write only)")?,
+                None => write!(f, "(not read)")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// 两次快照之间发生变化的寄存器
+pub(crate) struct Diff<'a> {
+    before: &'a Snapshot,
+    after: &'a Snapshot,
+}
+
+impl Diff<'_> {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.changes().next().is_none()
+    }
+
+    fn changes(&self) -> impl Iterator<Item = (&'static Register, u32, u32)> + '_ {
+        self.before
+            .registers()
+            .zip(self.after.values.iter())
+            .filter_map(|((register, before), after)| match (before, after) {
+                (Some(before), Some(after)) if before != *after => Some((register, before, *after)),
+                _ => None,
+            })
+    }
+}
+
+impl fmt::Display for Diff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} changes:", self.before.peripheral.name)?;
+        if self.is_empty() {
+            return write!(f, " none");
+        }
+        for (register, before, after) in self.changes() {
+            write!(
+                f,
+                "\n  {:<7} {:#010x} -> {:#010x} ",
+                register.name, before, after
+            )?;
+            let mut known = 0;
+            for field in register.fields {
+                known |= field.mask();
+                let (old, new) = (field.extract(before), field.extract(after));
+                if old != new {
+                    write!(f, " {}: {} -> {}", field.name, old, new)?;
+                }
+            }
+            let unknown = (before ^ after) & !known;
+            if unknown != 0 && !register.fields.is_empty() {
+                write!(f, " ??: {:#x}", unknown)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// 打印不为 0 的字段，以及表中没有收录、但不为 0 的位
+fn write_fields(f: &mut fmt::Formatter<'_>, register: &Register, value: u32) -> fmt::Result {
+    let mut known = 0;
+    for field in register.fields {
+        known |= field.mask();
+        let v = field.extract(value);
+        if v == 0 {
+            continue;
+        }
+        if field.width == 1 {
+            write!(f, " {}", field.name)?;
+        } else {
+            write!(f, " {}={}", field.name, v)?;
+        }
+    }
+    let unknown = value & !known;
+    if unknown != 0 && !register.fields.is_empty() {
+        write!(f, " ??={:#x}", unknown)?;
+    }
+    Ok(())
+}
+
+fn read(addr: u32) -> u32 {
+    unsafe { core::ptr::read_volatile(addr as *const u32) }
+}
+
+// 以下为各个外设的寄存器表
+
+static RCC_REGISTERS: &[Register] = &[
+    r(
+        "CR",
+        0x00,
+        &[
+            f("HSION", 0, 1),
+            f("HSIRDY", 1, 1),
+            f("HSITRIM", 3, 5),
+            f("HSICAL", 8, 8),
+            f("HSEON", 16, 1),
+            f("HSERDY", 17, 1),
+            f("HSEBYP", 18, 1),
+            f("CSSON", 19, 1),
+            f("PLLON", 24, 1),
+            f("PLLRDY", 25, 1),
+            f("PLLI2SON", 26, 1),
+            f("PLLI2SRDY", 27, 1),
+        ],
+    ),
+    r(
+        "PLLCFGR",
+        0x04,
+        &[
+            f("PLLM", 0, 6),
+            f("PLLN", 6, 9),
+            f("PLLP", 16, 2),
+            f("PLLSRC", 22, 1),
+            f("PLLQ", 24, 4),
+            f("PLLR", 28, 3),
+        ],
+    ),
+    r(
+        "CFGR",
+        0x08,
+        &[
+            f("SW", 0, 2),
+            f("SWS", 2, 2),
+            f("HPRE", 4, 4),
+            f("PPRE1", 10, 3),
+            f("PPRE2", 13, 3),
+            f("RTCPRE", 16, 5),
+            f("MCO1", 21, 2),
+            f("MCO1PRE", 24, 3),
+            f("MCO2PRE", 27, 3),
+            f("MCO2", 30, 2),
+        ],
+    ),
+    r(
+        "CIR",
+        0x0C,
+        &[
+            f("LSIRDYF", 0, 1),
+            f("LSERDYF", 1, 1),
+            f("HSIRDYF", 2, 1),
+            f("HSERDYF", 3, 1),
+            f("PLLRDYF", 4, 1),
+            f("PLLI2SRDYF", 5, 1),
+            f("CSSF", 7, 1),
+            f("LSIRDYIE", 8, 1),
+            f("LSERDYIE", 9, 1),
+            f("HSIRDYIE", 10, 1),
+            f("HSERDYIE", 11, 1),
+            f("PLLRDYIE", 12, 1),
+            f("PLLI2SRDYIE", 13, 1),
+        ],
+    ),
+    r("AHB1RSTR", 0x10, &[]),
+    r("AHB2RSTR", 0x14, &[]),
+    r("AHB3RSTR", 0x18, &[]),
+    r("APB1RSTR", 0x20, &[]),
+    r("APB2RSTR", 0x24, &[]),
+    r(
+        "AHB1ENR",
+        AHB1ENR,
+        &[
+            f("GPIOAEN", 0, 1),
+            f("GPIOBEN", 1, 1),
+            f("GPIOCEN", 2, 1),
+            f("GPIODEN", 3, 1),
+            f("GPIOEEN", 4, 1),
+            f("GPIOFEN", 5, 1),
+            f("GPIOGEN", 6, 1),
+            f("GPIOHEN", 7, 1),
+            f("CRCEN", 12, 1),
+            f("DMA1EN", 21, 1),
+            f("DMA2EN", 22, 1),
+        ],
+    ),
+    r("AHB2ENR", 0x34, &[f("RNGEN", 6, 1), f("OTGFSEN", 7, 1)]),
+    r("AHB3ENR", AHB3ENR, &[f("FSMCEN", 0, 1), f("QSPIEN", 1, 1)]),
+    r(
+        "APB1ENR",
+        APB1ENR,
+        &[
+            f("TIM2EN", 0, 1),
+            f("TIM3EN", 1, 1),
+            f("TIM4EN", 2, 1),
+            f("TIM5EN", 3, 1),
+            f("TIM6EN", 4, 1),
+            f("TIM7EN", 5, 1),
+            f("TIM12EN", 6, 1),
+            f("TIM13EN", 7, 1),
+            f("TIM14EN", 8, 1),
+            f("LPTIM1EN", 9, 1),
+            f("RTCAPBEN", 10, 1),
+            f("WWDGEN", 11, 1),
+            f("SPI2EN", 14, 1),
+            f("SPI3EN", 15, 1),
+            f("USART2EN", 17, 1),
+            f("USART3EN", 18, 1),
+            f("UART4EN", 19, 1),
+            f("UART5EN", 20, 1),
+            f("I2C1EN", 21, 1),
+            f("I2C2EN", 22, 1),
+            f("I2C3EN", 23, 1),
+            f("I2CFMP1EN", 24, 1),
+            f("CAN1EN", 25, 1),
+            f("CAN2EN", 26, 1),
+            f("CAN3EN", 27, 1),
+            f("PWREN", 28, 1),
+            f("DACEN", 29, 1),
+            f("UART7EN", 30, 1),
+            f("UART8EN", 31, 1),
+        ],
+    ),
+    r(
+        "APB2ENR",
+        APB2ENR,
+        &[
+            f("TIM1EN", 0, 1),
+            f("TIM8EN", 1, 1),
+            f("USART1EN", 4, 1),
+            f("USART6EN", 5, 1),
+            f("UART9EN", 6, 1),
+            f("UART10EN", 7, 1),
+            f("ADC1EN", 8, 1),
+            f("SDIOEN", 11, 1),
+            f("SPI1EN", 12, 1),
+            f("SPI4EN", 13, 1),
+            f("SYSCFGEN", 14, 1),
+            f("EXTITEN", 15, 1),
+            f("TIM9EN", 16, 1),
+            f("TIM10EN", 17, 1),
+            f("TIM11EN", 18, 1),
+            f("SPI5EN", 20, 1),
+            f("SAI1EN", 22, 1),
+            f("DFSDM1EN", 24, 1),
+            f("DFSDM2EN", 25, 1),
+        ],
+    ),
+    r(
+        "BDCR",
+        0x70,
+        &[
+            f("LSEON", 0, 1),
+            f("LSERDY", 1, 1),
+            f("LSEBYP", 2, 1),
+            f("LSEMOD", 3, 1),
+            f("RTCSEL", 8, 2),
+            f("RTCEN", 15, 1),
+            f("BDRST", 16, 1),
+        ],
+    ),
+    r(
+        "CSR",
+        0x74,
+        &[
+            f("LSION", 0, 1),
+            f("LSIRDY", 1, 1),
+            f("RMVF", 24, 1),
+            f("BORRSTF", 25, 1),
+            f("PINRSTF", 26, 1),
+            f("PORRSTF", 27, 1),
+            f("SFTRSTF", 28, 1),
+            f("IWDGRSTF", 29, 1),
+            f("WWDGRSTF", 30, 1),
+            f("LPWRRSTF", 31, 1),
+        ],
+    ),
+    r("SSCGR", 0x80, &[]),
+    r(
+        "PLLI2SCFGR",
+        0x84,
+        &[
+            f("PLLI2SM", 0, 6),
+            f("PLLI2SN", 6, 9),
+            f("PLLI2SSRC", 22, 1),
+            f("PLLI2SQ", 24, 4),
+            f("PLLI2SR", 28, 3),
+        ],
+    ),
+    r("DCKCFGR", 0x8C, &[]),
+    r("CKGATENR", 0x90, &[]),
+    r("DCKCFGR2", 0x94, &[]),
+];
+
+const TIM_CCMR1_FIELDS: &[Field] = &[
+    f("CC1S", 0, 2),
+    f("OC1FE", 2, 1),
+    f("OC1PE", 3, 1),
+    f("OC1M", 4, 3),
+    f("OC1CE", 7, 1),
+    f("CC2S", 8, 2),
+    f("OC2FE", 10, 1),
+    f("OC2PE", 11, 1),
+    f("OC2M", 12, 3),
+    f("OC2CE", 15, 1),
+];
+
+const TIM_CCMR2_FIELDS: &[Field] = &[
+    f("CC3S", 0, 2),
+    f("OC3FE", 2, 1),
+    f("OC3PE", 3, 1),
+    f("OC3M", 4, 3),
+    f("OC3CE", 7, 1),
+    f("CC4S", 8, 2),
+    f("OC4FE", 10, 1),
+    f("OC4PE", 11, 1),
+    f("OC4M", 12, 3),
+    f("OC4CE", 15, 1),
+];
+
+// CCMR 按输出比较模式解析，输入捕获模式下，第 2~7 位为 ICxPSC 与 ICxF
+static TIM_REGISTERS: &[Register] = &[
+    r(
+        "CR1",
+        0x00,
+        &[
+            f("CEN", 0, 1),
+            f("UDIS", 1, 1),
+            f("URS", 2, 1),
+            f("OPM", 3, 1),
+            f("DIR", 4, 1),
+            f("CMS", 5, 2),
+            f("ARPE", 7, 1),
+            f("CKD", 8, 2),
+        ],
+    ),
+    r(
+        "CR2",
+        0x04,
+        &[
+            f("CCPC", 0, 1),
+            f("CCUS", 2, 1),
+            f("CCDS", 3, 1),
+            f("MMS", 4, 3),
+            f("TI1S", 7, 1),
+            f("OIS1", 8, 1),
+            f("OIS1N", 9, 1),
+        ],
+    ),
+    r(
+        "SMCR",
+        0x08,
+        &[
+            f("SMS", 0, 3),
+            f("TS", 4, 3),
+            f("MSM", 7, 1),
+            f("ETF", 8, 4),
+            f("ETPS", 12, 2),
+            f("ECE", 14, 1),
+            f("ETP", 15, 1),
+        ],
+    ),
+    r(
+        "DIER",
+        0x0C,
+        &[
+            f("UIE", 0, 1),
+            f("CC1IE", 1, 1),
+            f("CC2IE", 2, 1),
+            f("CC3IE", 3, 1),
+            f("CC4IE", 4, 1),
+            f("COMIE", 5, 1),
+            f("TIE", 6, 1),
+            f("BIE", 7, 1),
+            f("UDE", 8, 1),
+            f("CC1DE", 9, 1),
+            f("CC2DE", 10, 1),
+            f("CC3DE", 11, 1),
+            f("CC4DE", 12, 1),
+            f("COMDE", 13, 1),
+            f("TDE", 14, 1),
+        ],
+    ),
+    r(
+        "SR",
+        0x10,
+        &[
+            f("UIF", 0, 1),
+            f("CC1IF", 1, 1),
+            f("CC2IF", 2, 1),
+            f("CC3IF", 3, 1),
+            f("CC4IF", 4, 1),
+            f("COMIF", 5, 1),
+            f("TIF", 6, 1),
+            f("BIF", 7, 1),
+            f("CC1OF", 9, 1),
+            f("CC2OF", 10, 1),
+            f("CC3OF", 11, 1),
+            f("CC4OF", 12, 1),
+        ],
+    ),
+    write_only("EGR", 0x14),
+    r("CCMR1", 0x18, TIM_CCMR1_FIELDS),
+    r("CCMR2", 0x1C, TIM_CCMR2_FIELDS),
+    r(
+        "CCER",
+        0x20,
+        &[
+            f("CC1E", 0, 1),
+            f("CC1P", 1, 1),
+            f("CC1NE", 2, 1),
+            f("CC1NP", 3, 1),
+            f("CC2E", 4, 1),
+            f("CC2P", 5, 1),
+            f("CC2NE", 6, 1),
+            f("CC2NP", 7, 1),
+            f("CC3E", 8, 1),
+            f("CC3P", 9, 1),
+            f("CC3NE", 10, 1),
+            f("CC3NP", 11, 1),
+            f("CC4E", 12, 1),
+            f("CC4P", 13, 1),
+            f("CC4NP", 15, 1),
+        ],
+    ),
+    r("CNT", 0x24, &[]),
+    r("PSC", 0x28, &[]),
+    r("ARR", 0x2C, &[]),
+    r("RCR", 0x30, &[]),
+    r("CCR1", 0x34, &[]),
+    r("CCR2", 0x38, &[]),
+    r("CCR3", 0x3C, &[]),
+    r("CCR4", 0x40, &[]),
+    r(
+        "BDTR",
+        0x44,
+        &[
+            f("DTG", 0, 8),
+            f("LOCK", 8, 2),
+            f("OSSI", 10, 1),
+            f("OSSR", 11, 1),
+            f("BKE", 12, 1),
+            f("BKP", 13, 1),
+            f("AOE", 14, 1),
+            f("MOE", 15, 1),
+        ],
+    ),
+    r("DCR", 0x48, &[f("DBA", 0, 5), f("DBL", 8, 5)]),
+    side_effect("DMAR", 0x4C),
+    r("OR", 0x50, &[]),
+];
+
+static I2C_REGISTERS: &[Register] = &[
+    r(
+        "CR1",
+        0x00,
+        &[
+            f("PE", 0, 1),
+            f("SMBUS", 1, 1),
+            f("SMBTYPE", 3, 1),
+            f("ENARP", 4, 1),
+            f("ENPEC", 5, 1),
+            f("ENGC", 6, 1),
+            f("NOSTRETCH", 7, 1),
+            f("START", 8, 1),
+            f("STOP", 9, 1),
+            f("ACK", 10, 1),
+            f("POS", 11, 1),
+            f("PEC", 12, 1),
+            f("ALERT", 13, 1),
+            f("SWRST", 15, 1),
+        ],
+    ),
+    r(
+        "CR2",
+        0x04,
+        &[
+            f("FREQ", 0, 6),
+            f("ITERREN", 8, 1),
+            f("ITEVTEN", 9, 1),
+            f("ITBUFEN", 10, 1),
+            f("DMAEN", 11, 1),
+            f("LAST", 12, 1),
+        ],
+    ),
+    r("OAR1", 0x08, &[f("ADD", 0, 10), f("ADDMODE", 15, 1)]),
+    r("OAR2", 0x0C, &[f("ENDUAL", 0, 1), f("ADD2", 1, 7)]),
+    side_effect("DR", 0x10),
+    r(
+        "SR1",
+        0x14,
+        &[
+            f("SB", 0, 1),
+            f("ADDR", 1, 1),
+            f("BTF", 2, 1),
+            f("ADD10", 3, 1),
+            f("STOPF", 4, 1),
+            f("RxNE", 6, 1),
+            f("TxE", 7, 1),
+            f("BERR", 8, 1),
+            f("ARLO", 9, 1),
+            f("AF", 10, 1),
+            f("OVR", 11, 1),
+            f("PECERR", 12, 1),
+            f("TIMEOUT", 14, 1),
+            f("SMBALERT", 15, 1),
+        ],
+    ),
+    side_effect("SR2", 0x18),
+    r(
+        "CCR",
+        0x1C,
+        &[f("CCR", 0, 12), f("DUTY", 14, 1), f("F/S", 15, 1)],
+    ),
+    r("TRISE", 0x20, &[f("TRISE", 0, 6)]),
+    r("FLTR", 0x24, &[f("DNF", 0, 4), f("ANOFF", 4, 1)]),
+];
+
+static DMA1_STREAM_NAMES: [&str; 8] = [
+    "DMA1_S0", "DMA1_S1", "DMA1_S2", "DMA1_S3", "DMA1_S4", "DMA1_S5", "DMA1_S6", "DMA1_S7",
+];
+static DMA2_STREAM_NAMES: [&str; 8] = [
+    "DMA2_S0", "DMA2_S1", "DMA2_S2", "DMA2_S3", "DMA2_S4", "DMA2_S5", "DMA2_S6", "DMA2_S7",
+];
+
+// 每个数据流在 LISR/HISR 中占 6 位，位置依次为 0、6、16、22
+const fn dma_flags(shift: u8) -> [Field; 5] {
+    [
+        f("FEIF", shift, 1),
+        f("DMEIF", shift + 2, 1),
+        f("TEIF", shift + 3, 1),
+        f("HTIF", shift + 4, 1),
+        f("TCIF", shift + 5, 1),
+    ]
+}
+
+const DMA_FLAGS_0: &[Field] = &dma_flags(0);
+const DMA_FLAGS_1: &[Field] = &dma_flags(6);
+const DMA_FLAGS_2: &[Field] = &dma_flags(16);
+const DMA_FLAGS_3: &[Field] = &dma_flags(22);
+
+const DMA_SXCR_FIELDS: &[Field] = &[
+    f("EN", 0, 1),
+    f("DMEIE", 1, 1),
+    f("TEIE", 2, 1),
+    f("HTIE", 3, 1),
+    f("TCIE", 4, 1),
+    f("PFCTRL", 5, 1),
+    f("DIR", 6, 2),
+    f("CIRC", 8, 1),
+    f("PINC", 9, 1),
+    f("MINC", 10, 1),
+    f("PSIZE", 11, 2),
+    f("MSIZE", 13, 2),
+    f("PINCOS", 15, 1),
+    f("PL", 16, 2),
+    f("DBM", 18, 1),
+    f("CT", 19, 1),
+    f("PBURST", 21, 2),
+    f("MBURST", 23, 2),
+    f("CHSEL", 25, 3),
+];
+
+const DMA_SXFCR_FIELDS: &[Field] = &[
+    f("FTH", 0, 2),
+    f("DMDIS", 2, 1),
+    f("FS", 3, 3),
+    f("FEIE", 7, 1),
+];
+
+// 数据流 n 的寄存器从 0x10 + 0x18 * n 开始
+const fn dma_stream_registers(stream: usize) -> [Register; 7] {
+    let flags = match stream % 4 {
+        0 => DMA_FLAGS_0,
+        1 => DMA_FLAGS_1,
+        2 => DMA_FLAGS_2,
+        _ => DMA_FLAGS_3,
+    };
+    let isr = if stream < 4 {
+        r("LISR", 0x00, flags)
+    } else {
+        r("HISR", 0x04, flags)
+    };
+    let base = 0x10 + 0x18 * stream as u32;
+    [
+        isr,
+        r("SxCR", base, DMA_SXCR_FIELDS),
+        r("SxNDTR", base + 0x04, &[]),
+        r("SxPAR", base + 0x08, &[]),
+        r("SxM0AR", base + 0x0C, &[]),
+        r("SxM1AR", base + 0x10, &[]),
+        r("SxFCR", base + 0x14, DMA_SXFCR_FIELDS),
+    ]
+}
+
+static DMA_STREAM_REGISTERS: [[Register; 7]; 8] = [
+    dma_stream_registers(0),
+    dma_stream_registers(1),
+    dma_stream_registers(2),
+    dma_stream_registers(3),
+    dma_stream_registers(4),
+    dma_stream_registers(5),
+    dma_stream_registers(6),
+    dma_stream_registers(7),
+];
+
+static QUADSPI_REGISTERS: &[Register] = &[
+    r(
+        "CR",
+        0x00,
+        &[
+            f("EN", 0, 1),
+            f("ABORT", 1, 1),
+            f("DMAEN", 2, 1),
+            f("TCEN", 3, 1),
+            f("SSHIFT", 4, 1),
+            f("DFM", 6, 1),
+            f("FSEL", 7, 1),
+            f("FTHRES", 8, 5),
+            f("TEIE", 16, 1),
+            f("TCIE", 17, 1),
+            f("FTIE", 18, 1),
+            f("SMIE", 19, 1),
+            f("TOIE", 20, 1),
+            f("APMS", 22, 1),
+            f("PMM", 23, 1),
+            f("PRESCALER", 24, 8),
+        ],
+    ),
+    r(
+        "DCR",
+        0x04,
+        &[f("CKMODE", 0, 1), f("CSHT", 8, 3), f("FSIZE", 16, 5)],
+    ),
+    r(
+        "SR",
+        0x08,
+        &[
+            f("TEF", 0, 1),
+            f("TCF", 1, 1),
+            f("FTF", 2, 1),
+            f("SMF", 3, 1),
+            f("TOF", 4, 1),
+            f("BUSY", 5, 1),
+            f("FLEVEL", 8, 6),
+        ],
+    ),
+    write_only("FCR", 0x0C),
+    r("DLR", 0x10, &[]),
+    r(
+        "CCR",
+        0x14,
+        &[
+            f("INSTRUCTION", 0, 8),
+            f("IMODE", 8, 2),
+            f("ADMODE", 10, 2),
+            f("ADSIZE", 12, 2),
+            f("ABMODE", 14, 2),
+            f("ABSIZE", 16, 2),
+            f("DCYC", 18, 5),
+            f("DMODE", 24, 2),
+            f("FMODE", 26, 2),
+            f("SIOO", 28, 1),
+            f("DHHC", 30, 1),
+            f("DDRM", 31, 1),
+        ],
+    ),
+    r("AR", 0x18, &[]),
+    r("ABR", 0x1C, &[]),
+    side_effect("DR", 0x20),
+    r("PSMKR", 0x24, &[]),
+    r("PSMAR", 0x28, &[]),
+    r("PIR", 0x2C, &[]),
+    r("LPTR", 0x30, &[]),
+];
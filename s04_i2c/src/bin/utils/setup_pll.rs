@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use stm32f4xx_hal::pac::Peripherals;
 
 pub fn setup(dp: &Peripherals) {
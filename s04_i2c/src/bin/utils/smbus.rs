@@ -0,0 +1,310 @@
+//! SMBus（System Management Bus）
+//!
+//! SMBus 是在 I2C 基础上制定的协议，常见于电池电量计、电源管理芯片上，它与 I2C 的主要区别有：
+//!
+//! 1. PEC（Packet Error Checking）：每次传输的末尾可以附加一个 CRC-8 校验字节，
+//!    校验的范围包括本次传输中的所有字节，连同地址字节（包括 Repeated START 之后的那个地址字节）
+//! 2. 超时：任何设备拉低 SCL 超过 25 ms（t_TIMEOUT），其它设备都要放弃当前的传输，
+//!    STM32 的 I2C 在 SMBus 模式下会自动检测它，并挂起 SR1 的 TIMEOUT
+//! 3. SMBALERT#：从设备可以通过一根额外的线通知主机“我有事”，主机随后从 Alert Response Address（0x0C）读取一个字节，
+//!    读到的就是拉低 SMBALERT# 的那个设备的地址，若有多个设备同时拉低，则地址最小的那个胜出，其余设备继续保持拉低
+//! 4. Host Notify：从设备也可以临时成为主机，向 SMBus Host 的地址（0x08）写入自己的地址和 2 字节的数据
+//!
+//! 这里的 PEC 是软件计算的，因为 blocking_master 的收发流程中，不方便插入硬件 PEC 需要的 PEC/LAST 位设置
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::i2c1::RegisterBlock;
+
+use super::{
+    addressing::I2cAddress,
+    blocking_master::{self, MasterError},
+};
+
+// Alert Response Address
+pub(crate) const ALERT_RESPONSE_ADDRESS: u8 = 0x0C;
+// SMBus Host 的地址，Host Notify 会写入这个地址
+pub(crate) const HOST_ADDRESS: u8 = 0x08;
+
+// CRC-8/SMBUS，多项式 x^8 + x^2 + x + 1，初始值 0，不反转
+pub(crate) const fn crc8_update(mut crc: u8, byte: u8) -> u8 {
+    crc ^= byte;
+    let mut bit = 0;
+    while bit < 8 {
+        crc = if crc & 0x80 != 0 {
+            (crc << 1) ^ 0x07
+        } else {
+            crc << 1
+        };
+        bit += 1;
+    }
+    crc
+}
+
+pub(crate) const fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0;
+    let mut index = 0;
+    while index < data.len() {
+        crc = crc8_update(crc, data[index]);
+        index += 1;
+    }
+    crc
+}
+
+// 标准的校验值，编译期检查一下实现是否正确
+const _: () = assert!(crc8(b"123456789") == 0xF4);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SmbusError {
+    Master(MasterError),
+    // 收到的 PEC 与计算的不一致
+    Pec { expect: u8, got: u8 },
+}
+
+impl From<MasterError> for SmbusError {
+    fn from(e: MasterError) -> Self {
+        Self::Master(e)
+    }
+}
+
+// 通过 Host Notify 收到的通知
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct HostNotify {
+    // 发出通知的设备的 7 位地址
+    pub(crate) device: u8,
+    pub(crate) data: u16,
+}
+
+pub(crate) struct Smbus<'a> {
+    i2c: &'a RegisterBlock,
+    pec: bool,
+}
+
+impl<'a> Smbus<'a> {
+    // 把 I2C 切换为 SMBus Host 模式
+    //
+    // 调用之前，I2C 的时钟等设置要先完成，且 PE 处于关闭状态
+    pub(crate) fn new_host(i2c: &'a RegisterBlock, pec: bool) -> Self {
+        i2c.cr1.modify(|_, w| {
+            // SMBUS: 0 为 I2C 模式，1 为 SMBus 模式，只有在 SMBus 模式下才会检测超时
+            w.smbus().set_bit();
+            // SMBTYPE: 0 为 Device，1 为 Host，Host 模式下会识别 SMBA 引脚上的 SMBALERT#
+            w.smbtype().set_bit();
+            // ENARP: 在 Host 模式下，开启对 SMBus Host 地址 0x08 的响应，用于接收 Host Notify
+            w.enarp().set_bit();
+            w
+        });
+
+        i2c.cr1.modify(|_, w| w.pe().enabled());
+        // 作为从机接收 Host Notify 时需要 ACK，原因见 s04c01
+        i2c.cr1.modify(|_, w| w.ack().ack());
+
+        Self { i2c, pec }
+    }
+
+    fn addr_byte(addr: u8, read: bool) -> u8 {
+        I2cAddress::SevenBit(addr).first_byte(read)
+    }
+
+    // 主机接收之后 ACK 会被关闭，这里要重新打开，否则无法响应 Host Notify
+    fn restore_ack(&self) {
+        self.i2c.cr1.modify(|_, w| w.ack().ack());
+    }
+
+    fn check_pec(&self, expect: u8, got: u8) -> Result<(), SmbusError> {
+        if expect == got {
+            Ok(())
+        } else {
+            Err(SmbusError::Pec { expect, got })
+        }
+    }
+
+    // Write Byte/Write Word 的通用流程：地址/W，命令，数据，（PEC）
+    fn write_data(&self, addr: u8, cmd: u8, data: &[u8]) -> Result<(), SmbusError> {
+        let mut buf = [0u8; 4];
+        buf[0] = cmd;
+        buf[1..1 + data.len()].copy_from_slice(data);
+        let mut len = 1 + data.len();
+
+        if self.pec {
+            let crc = crc8_update(Self::addr_byte(addr, false), cmd);
+            buf[len] = data.iter().fold(crc, |crc, &b| crc8_update(crc, b));
+            len += 1;
+        }
+
+        let result = blocking_master::write(self.i2c, I2cAddress::SevenBit(addr), &buf[..len]);
+        self.restore_ack();
+        result?;
+        Ok(())
+    }
+
+    // Read Byte/Read Word 的通用流程：地址/W，命令，Repeated START，地址/R，数据，（PEC）
+    fn read_data(&self, addr: u8, cmd: u8, data: &mut [u8]) -> Result<(), SmbusError> {
+        let mut buf = [0u8; 3];
+        let len = data.len() + self.pec as usize;
+
+        let result = blocking_master::write_read(
+            self.i2c,
+            I2cAddress::SevenBit(addr),
+            &[cmd],
+            &mut buf[..len],
+        );
+        self.restore_ack();
+        result?;
+
+        data.copy_from_slice(&buf[..data.len()]);
+
+        if self.pec {
+            let crc = [
+                Self::addr_byte(addr, false),
+                cmd,
+                Self::addr_byte(addr, true),
+            ]
+            .iter()
+            .chain(data.iter())
+            .fold(0, |crc, &b| crc8_update(crc, b));
+            self.check_pec(crc, buf[data.len()])?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn write_byte_data(&self, addr: u8, cmd: u8, data: u8) -> Result<(), SmbusError> {
+        self.write_data(addr, cmd, &[data])
+    }
+
+    // SMBus 的字（word）总是低字节在前
+    pub(crate) fn write_word_data(&self, addr: u8, cmd: u8, data: u16) -> Result<(), SmbusError> {
+        self.write_data(addr, cmd, &data.to_le_bytes())
+    }
+
+    pub(crate) fn read_byte_data(&self, addr: u8, cmd: u8) -> Result<u8, SmbusError> {
+        let mut data = [0u8; 1];
+        self.read_data(addr, cmd, &mut data)?;
+        Ok(data[0])
+    }
+
+    pub(crate) fn read_word_data(&self, addr: u8, cmd: u8) -> Result<u16, SmbusError> {
+        let mut data = [0u8; 2];
+        self.read_data(addr, cmd, &mut data)?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    // SMBA 引脚上是否出现过下降沿，读取的同时清理该标识位
+    pub(crate) fn take_alert(&self) -> bool {
+        if self.i2c.sr1.read().smbalert().bit_is_set() {
+            self.i2c.sr1.modify(|_, w| w.smbalert().clear_bit());
+            true
+        } else {
+            false
+        }
+    }
+
+    // 从 Alert Response Address 读取拉低 SMBALERT# 的设备地址
+    //
+    // 读到的字节中，高 7 位是设备地址，最低位由设备自己定义（通常为 0）
+    // 返回 Ok(None) 表示没有设备响应，也就是 SMBALERT# 已经被释放了
+    pub(crate) fn alert_response(&self) -> Result<Option<u8>, SmbusError> {
+        let mut buf = [0u8; 2];
+        let len = 1 + self.pec as usize;
+
+        let result = blocking_master::read(
+            self.i2c,
+            I2cAddress::SevenBit(ALERT_RESPONSE_ADDRESS),
+            &mut buf[..len],
+        );
+        self.restore_ack();
+
+        match result {
+            Err(MasterError::Nack) => return Ok(None),
+            other => other?,
+        }
+
+        if self.pec {
+            let crc = crc8_update(
+                crc8_update(0, Self::addr_byte(ALERT_RESPONSE_ADDRESS, true)),
+                buf[0],
+            );
+            self.check_pec(crc, buf[1])?;
+        }
+
+        Ok(Some(buf[0] >> 1))
+    }
+
+    // 以轮询的方式检查是否有设备发来了 Host Notify，没有的话立刻返回 None
+    //
+    // Host Notify 的格式为：START，0x08/W，设备地址，数据低字节，数据高字节，STOP，其中不带 PEC
+    pub(crate) fn poll_host_notify(&self) -> Option<HostNotify> {
+        let i2c = self.i2c;
+
+        if i2c.sr1.read().addr().is_not_match() {
+            return None;
+        }
+
+        // 清理 ADDR，并确认匹配的是 SMBus Host 地址
+        i2c.sr1.read();
+        let is_host = i2c.sr2.read().smbhost().bit_is_set();
+
+        let mut buf = [0u8; 3];
+        for byte in buf.iter_mut() {
+            loop {
+                let sr1 = i2c.sr1.read();
+                if sr1.rx_ne().is_not_empty() {
+                    break;
+                }
+                // 对方提前结束了传输
+                if sr1.stopf().is_stop() || sr1.timeout().bit_is_set() {
+                    Self::clear_stop(i2c);
+                    return None;
+                }
+            }
+            *byte = i2c.dr.read().dr().bits();
+        }
+
+        while i2c.sr1.read().stopf().is_no_stop() {}
+        Self::clear_stop(i2c);
+
+        if !is_host {
+            return None;
+        }
+
+        Some(HostNotify {
+            device: buf[0] >> 1,
+            data: u16::from_le_bytes([buf[1], buf[2]]),
+        })
+    }
+
+    // 清理 STOPF 与 TIMEOUT，见 s04c01
+    fn clear_stop(i2c: &RegisterBlock) {
+        i2c.sr1.read();
+        i2c.cr1.modify(|_, w| w);
+        i2c.sr1.modify(|_, w| w.timeout().clear_bit());
+    }
+}
+
+// 通过 SWRST 复位 I2C 外设，并恢复它原本的设置
+//
+// 当总线被卡住（比如 BUSY 一直为 1）时，单纯关闭 PE 是不够的，必须使用 SWRST 复位 I2C 内部的状态机
+// SWRST 会清空 I2C 的全部寄存器，因此这里先把它们保存下来
+pub(crate) fn software_reset(i2c: &RegisterBlock) {
+    let cr1 = i2c.cr1.read().bits();
+    let cr2 = i2c.cr2.read().bits();
+    let oar1 = i2c.oar1.read().bits();
+    let oar2 = i2c.oar2.read().bits();
+    let ccr = i2c.ccr.read().bits();
+    let trise = i2c.trise.read().bits();
+
+    i2c.cr1.modify(|_, w| w.swrst().set_bit());
+    i2c.cr1.modify(|_, w| w.swrst().clear_bit());
+
+    unsafe {
+        i2c.cr2.write(|w| w.bits(cr2));
+        i2c.oar1.write(|w| w.bits(oar1));
+        i2c.oar2.write(|w| w.bits(oar2));
+        i2c.ccr.write(|w| w.bits(ccr));
+        i2c.trise.write(|w| w.bits(trise));
+        // 最后再恢复 CR1，此时 PE 才会被重新打开，START/STOP 等一次性的位不应该被恢复
+        const ONE_SHOT_BITS: u32 = (1 << 8) | (1 << 9) | (1 << 12) | (1 << 15);
+        i2c.cr1.write(|w| w.bits(cr1 & !ONE_SHOT_BITS));
+    }
+}
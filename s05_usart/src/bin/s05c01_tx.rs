@@ -55,6 +55,7 @@
 
 use core::cell::{Cell, RefCell};
 
+use chip_caps::pinmap::Binding;
 use cortex_m::interrupt::Mutex;
 use panic_rtt_target as _;
 use rtt_target::rtt_init_print;
@@ -111,13 +112,16 @@ fn switch_to_hse(dp: &Peripherals) {
 
 // 可以作为 USART1 Tx/Rx 引脚的有 AF07 模式下的 (PA9 PA15 PB6)/(PA10 PB3 PB7)
 // 这里我们选择 PA9 作为 Tx，而且由于我们目前不需要接收，因此不用设置 Rx 引脚
+// 这张表也写在了 chip_caps::pinmap 中，bind! 会在编译时查表，选错了引脚就无法编译
+const TX: Binding = chip_caps::bind!(USART1_TX => PA9);
+
 fn set_gpio_in_alternate_mode(dp: &Peripherals) {
     dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
 
     let gpioa = &dp.GPIOA;
 
     // 依照上面所说，将 PA09 的 AF 切换为 AF07
-    gpioa.afrh.modify(|_, w| w.afrh9().af7());
+    gpioa.afrh.modify(|_, w| w.afrh9().bits(TX.af));
 
     // 启用内部拉高电阻，这样在 USART 不传输时，Tx 也能保持高电平
     gpioa.pupdr.modify(|_, w| w.pupdr9().pull_up());
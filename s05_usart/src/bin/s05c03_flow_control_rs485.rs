@@ -0,0 +1,200 @@
+//! 硬件流控制、RS-485 与单线半双工
+//!
+//! 三种模式的说明，见 utils::serial_mode
+//!
+//! 1. USART1 开启 RTS/CTS，我们故意让每个字节的处理都很慢，观察 RTS 如何让 PC 端暂停发送，且不会产生 ORE（溢出错误）
+//! 2. USART2 接一个 MAX485，每秒发送一次 ping，然后在 DE 释放后接收对方的回复
+//! 3. USART6 工作在单线半双工模式下，每秒发送一次 hello，然后在同一根线上接收回复
+//!
+//! 电路连接方案：
+//!
+//! USART1，需要一个带 RTS/CTS 的 USB 转串口模块（比如 FT232RL）
+//! PA9  TX  <-> 模块 RXD
+//! PA10 RX  <-> 模块 TXD
+//! PA11 CTS <-> 模块 RTS#
+//! PA12 RTS <-> 模块 CTS#
+//!
+//! USART2，MAX485
+//! PA2 TX <-> DI
+//! PA3 RX <-> RO
+//! PA4    <-> DE 与 /RE（两者接在一起）
+//!
+//! USART6，单线半双工
+//! PC6 TX <-> 对端的数据线，需要外部上拉
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, usart1::RegisterBlock, Peripherals};
+
+mod utils;
+
+use utils::serial_mode::{
+    half_duplex_send, set_flow_control, set_half_duplex, take_cts_changed, FlowControl, Rs485,
+};
+
+// HSE 12 MHz，AHB 与 APB 均不分频
+const CLOCK_HZ: u32 = 12_000_000;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    switch_to_hse(&dp);
+    setup_gpio(&dp);
+
+    dp.RCC.apb2enr.modify(|_, w| {
+        w.usart1en().enabled();
+        w.usart6en().enabled();
+        w
+    });
+    dp.RCC.apb1enr.modify(|_, w| w.usart2en().enabled());
+
+    for usart in [&*dp.USART1, &*dp.USART2, &*dp.USART6] {
+        setup_usart_115200(usart);
+    }
+
+    set_flow_control(&dp.USART1, FlowControl::RtsCts);
+    set_half_duplex(&dp.USART6, true);
+
+    let rs485 = Rs485::new(&dp.USART2, |on| {
+        dp.GPIOA
+            .bsrr
+            .write(|w| if on { w.bs4().set() } else { w.br4().reset() });
+    });
+
+    // 主循环大约每 1 ms 跑一圈，1000 圈就是 1 秒
+    let mut loop_cnt: u32 = 0;
+    let mut overrun_cnt: u32 = 0;
+
+    loop {
+        // USART1：慢速处理收到的字节
+        let usart1 = &dp.USART1;
+        let sr = usart1.sr.read();
+        if sr.ore().bit_is_set() {
+            // 开启 RTS 之后，ORE 不应该出现
+            overrun_cnt += 1;
+            rprintln!("USART1 overrun: {}", overrun_cnt);
+        }
+        if sr.rxne().bit_is_set() {
+            let byte = usart1.dr.read().dr().bits() as u8;
+            // 模拟一个很慢的处理过程，这期间 DR 一直是满的，RTS 会保持高电平
+            cortex_m::asm::delay(CLOCK_HZ / 100);
+            while usart1.sr.read().txe().bit_is_clear() {}
+            usart1.dr.write(|w| w.dr().bits(byte as u16));
+        }
+        if take_cts_changed(usart1) {
+            rprintln!("USART1 CTS changed");
+        }
+
+        if loop_cnt.is_multiple_of(1000) {
+            // USART2：RS-485
+            rs485.send(b"ping\r\n");
+            // USART6：单线半双工
+            half_duplex_send(&dp.USART6, b"hello\r\n");
+        }
+
+        // 两条半双工线路上的回复
+        if let Some(byte) = try_read(rs485.usart()) {
+            rprintln!("RS-485 received: {:?}", byte as char);
+        }
+        if let Some(byte) = try_read(&dp.USART6) {
+            rprintln!("single wire received: {:?}", byte as char);
+        }
+
+        loop_cnt = loop_cnt.wrapping_add(1);
+        cortex_m::asm::delay(CLOCK_HZ / 1000);
+    }
+}
+
+fn try_read(usart: &RegisterBlock) -> Option<u8> {
+    if usart.sr.read().rxne().bit_is_set() {
+        Some(usart.dr.read().dr().bits() as u8)
+    } else {
+        None
+    }
+}
+
+fn switch_to_hse(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+fn setup_gpio(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpiocen().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+
+    // USART1 的 TX/RX/CTS/RTS，以及 USART2 的 TX/RX，均为 AF7
+    gpioa.afrl.modify(|_, w| {
+        w.afrl2().af7();
+        w.afrl3().af7();
+        w
+    });
+    gpioa.afrh.modify(|_, w| {
+        w.afrh9().af7();
+        w.afrh10().af7();
+        w.afrh11().af7();
+        w.afrh12().af7();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr2().pull_up();
+        w.pupdr9().pull_up();
+        // CTS 悬空时应该视为“不允许发送”，因此这里上拉
+        w.pupdr11().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder2().alternate();
+        w.moder3().alternate();
+        // DE 为普通的推挽输出
+        w.moder4().output();
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w.moder11().alternate();
+        w.moder12().alternate();
+        w
+    });
+
+    // USART6 的 TX 为 AF8，单线半双工下必须是开漏输出
+    let gpioc = &dp.GPIOC;
+    gpioc.afrl.modify(|_, w| w.afrl6().af8());
+    gpioc.otyper.modify(|_, w| w.ot6().open_drain());
+    gpioc.pupdr.modify(|_, w| w.pupdr6().pull_up());
+    gpioc.moder.modify(|_, w| w.moder6().alternate());
+}
+
+// 8 bit 数据，1 bit 停止位，无奇偶校验，115200 Baud
+fn setup_usart_115200(usart: &RegisterBlock) {
+    usart.cr1.modify(|_, w| {
+        w.ue().enabled();
+        w.m().m8();
+        w
+    });
+    usart.cr2.modify(|_, w| w.stop().stop1());
+
+    // USARTDIV = 12 MHz / (16 * 115200) ≈ 6.51，整数部分 6，小数部分 0.51 * 16 ≈ 8
+    usart.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    usart.cr1.modify(|_, w| {
+        w.re().enabled();
+        w.te().enabled();
+        w
+    });
+}
@@ -0,0 +1,129 @@
+//! 自动波特检测
+//!
+//! 上电后，在 PC 端的串口工具里以任意波特发送一个大写的 U（也就是 0x55），
+//! 芯片测量出波特之后，以相同的波特回复检测结果，然后进入 echo 模式
+//!
+//! 检测原理，见 utils::autobaud
+//!
+//! 电路连接方案：
+//! GPIO PA9 <-> DAPLink Rx
+//! GPIO PA10 <-> DAPLink Tx
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, Peripherals, USART1};
+
+mod utils;
+
+use utils::autobaud::{detect, hand_over_to_usart1, nearest_standard, Training};
+
+// HSE 12 MHz，APB2 不分频
+const APB2_HZ: u32 = 12_000_000;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    switch_to_hse(&dp);
+    setup_tx_pin(&dp);
+
+    rprintln!("waiting for 'U' (0x55)...");
+
+    let detected = loop {
+        match detect(&dp, Training::Byte0x55, APB2_HZ, 100_000_000) {
+            Ok(detected) => break detected,
+            Err(e) => rprintln!("autobaud failed: {:?}, retry", e),
+        }
+    };
+
+    rprintln!(
+        "BRR: 0x{:04X}, baud: {}, standard: {:?}",
+        detected.brr,
+        detected.baud,
+        nearest_standard(detected.baud)
+    );
+
+    setup_usart1(&dp);
+    hand_over_to_usart1(&dp, detected);
+
+    // 引脚与 BRR 都准备好之后，才开启收发
+    dp.USART1.cr1.modify(|_, w| {
+        w.re().enabled();
+        w.te().enabled();
+        w
+    });
+
+    let mut serial1 = Usart1Writer(&dp.USART1);
+    writeln!(
+        serial1,
+        "\r\ndetected {} Baud ({:?}), BRR 0x{:04X}\r",
+        detected.baud,
+        nearest_standard(detected.baud),
+        detected.brr
+    )
+    .unwrap();
+
+    loop {
+        while dp.USART1.sr.read().rxne().bit_is_clear() {}
+        let byte = dp.USART1.dr.read().dr().bits() as u8;
+        serial1.write_byte(byte);
+    }
+}
+
+struct Usart1Writer<'a>(&'a USART1);
+
+impl Usart1Writer<'_> {
+    fn write_byte(&mut self, byte: u8) {
+        while self.0.sr.read().txe().bit_is_clear() {}
+        self.0.dr.write(|w| w.dr().bits(byte as u16));
+    }
+}
+
+impl Write for Usart1Writer<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+fn switch_to_hse(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// PA10 先交给 TIM1 测量，这里只设置 TX
+fn setup_tx_pin(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| w.afrh9().af7());
+    gpioa.pupdr.modify(|_, w| w.pupdr9().pull_up());
+    gpioa.moder.modify(|_, w| w.moder9().alternate());
+}
+
+// BRR 由 hand_over_to_usart1 写入，收发在引脚切换完成后再开启
+fn setup_usart1(dp: &Peripherals) {
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let serial1 = &dp.USART1;
+    serial1.cr1.modify(|_, w| {
+        w.ue().enabled();
+        w.m().m8();
+        // 16 倍超采样，BRR 的计算方法依赖于这一点
+        w.over8().clear_bit();
+        w
+    });
+    serial1.cr2.modify(|_, w| w.stop().stop1());
+}
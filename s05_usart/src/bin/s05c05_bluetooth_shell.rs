@@ -0,0 +1,363 @@
+//! 通过 HC-05 / HM-10 蓝牙串口模块使用命令行
+//!
+//! 同一个简单的命令行同时挂在两条线路上：
+//!
+//! - USART1：接 USB-TTL 模块，在电脑上使用，可以执行所有命令，包括蓝牙模块的配置
+//! - USART2：接蓝牙模块，手机上用任意“蓝牙串口”App 连接之后，就能使用同样的命令行，
+//!   只是不能执行 bt 与 bridge（不能通过蓝牙链路去修改蓝牙模块自己）
+//!
+//! 蓝牙模块的管理见 utils::bt_module，连接状态变化时会在 USART1 上给出提示
+//!
+//! 命令：
+//!
+//! help                 列出命令
+//! echo <文本>          原样回复
+//! status               线路与蓝牙模块的状态
+//! bt at                进入 AT 模式（此时蓝牙上的命令行暂停）
+//! bt exit              回到透明传输
+//! bt version           查询固件版本
+//! bt name [新名字]     查询或修改名字
+//! bt pin <配对码>      修改配对码，HC-05 为 4 位数字，HM-10 为 6 位数字
+//! bt baud <波特>       修改模块的波特，会重启模块
+//! bt raw <AT 指令>     发送任意 AT 指令，原样输出回复
+//! bridge               USART1 与蓝牙直接互通，按 Ctrl-] 退出
+//!
+//! 接线图：
+//!
+//! USB-TTL 模块，115200 8N1
+//! PA9  (USART1 Tx) <-> Rx
+//! PA10 (USART1 Rx) <-> Tx
+//! GND              <-> GND
+//!
+//! STM32 <-> HC-05 / HM-10（模块的电源为 3.6V ~ 6V，逻辑电平为 3.3V）
+//!   PA2 <-> RXD (USART2 Tx)
+//!   PA3 <-> TXD (USART2 Rx)
+//!   PA4  -> KEY / EN（仅 HC-05）
+//!   PA1 <-  STATE
+//!    5V <-> VCC
+//!   GND <-> GND
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, USART1};
+
+mod utils;
+
+use utils::bt_module::{BtError, BtModule, Kind};
+
+// HSE 12 MHz，AHB 与 APB 都不分频
+const SYSCLK_HZ: u32 = 12_000_000;
+const PCLK1_HZ: u32 = 12_000_000;
+
+// 改成 Kind::Hm10 即可使用 HM-10，两者出厂的数据波特都是 9600
+const BT_KIND: Kind = Kind::Hc05;
+const BT_BAUD: u32 = 9600;
+
+const LINE_MAX: usize = 64;
+// Ctrl-]
+const BRIDGE_EXIT: u8 = 0x1D;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut cp = pac::CorePeripherals::take().unwrap();
+
+    switch_to_hse(&dp);
+    setup_usart1(&dp);
+
+    let mut bt = BtModule::new(&dp, &mut cp, BT_KIND, BT_BAUD, PCLK1_HZ, SYSCLK_HZ);
+    let mut wired = Usart1Writer(&dp.USART1);
+
+    let mut wired_line = LineEditor::new();
+    let mut bt_line = LineEditor::new();
+
+    writeln!(wired, "\r\nbluetooth shell ready, module {:?}\r", BT_KIND).ok();
+    write!(wired, "> ").ok();
+
+    loop {
+        if let Some(byte) = wired.read_byte() {
+            if let Some(line) = wired_line.push(byte, &mut wired) {
+                let command = core::str::from_utf8(line).unwrap_or("");
+                run(Source::Wired(&mut bt), command, &mut wired);
+                write!(wired, "> ").ok();
+            }
+        }
+
+        match bt.poll_connection() {
+            Some(true) => {
+                writeln!(wired, "\r\n[bt connected]\r").ok();
+                write!(bt, "\r\nshell over bluetooth\r\n> ").ok();
+            }
+            Some(false) => {
+                writeln!(wired, "\r\n[bt disconnected]\r").ok();
+            }
+            None => {}
+        }
+
+        // AT 模式下模块收到的字节都是 AT 指令的回复，不交给命令行
+        if bt.in_at_mode() {
+            continue;
+        }
+        if let Some(byte) = bt.read_byte() {
+            if let Some(line) = bt_line.push(byte, &mut bt) {
+                let command = core::str::from_utf8(line).unwrap_or("");
+                run(Source::Bluetooth, command, &mut bt);
+                write!(bt, "> ").ok();
+            }
+        }
+    }
+}
+
+// 命令来自哪条线路，只有 USART1 上的命令可以操作蓝牙模块
+enum Source<'a> {
+    Wired(&'a mut BtModule),
+    Bluetooth,
+}
+
+#[derive(Debug)]
+enum CommandError {
+    Syntax,
+    // 这个命令不能通过蓝牙执行
+    WiredOnly,
+    Bt(BtError),
+}
+
+impl From<BtError> for CommandError {
+    fn from(e: BtError) -> Self {
+        CommandError::Bt(e)
+    }
+}
+
+fn run(source: Source, command: &str, out: &mut dyn Write) {
+    match execute(source, command, out) {
+        Ok(()) => None,
+        Err(CommandError::Syntax) => writeln!(out, "ERR syntax\r").ok(),
+        Err(CommandError::WiredOnly) => writeln!(out, "ERR wired console only\r").ok(),
+        Err(CommandError::Bt(e)) => writeln!(out, "ERR {:?}\r", e).ok(),
+    };
+}
+
+fn execute(source: Source, command: &str, out: &mut dyn Write) -> Result<(), CommandError> {
+    let (name, rest) = command
+        .trim()
+        .split_once(' ')
+        .unwrap_or((command.trim(), ""));
+    let rest = rest.trim();
+
+    match name {
+        "" => {}
+        "help" => {
+            writeln!(out, "help, echo <text>, status, bridge,\r").ok();
+            writeln!(
+                out,
+                "bt at|exit|version|name [new]|pin <pin>|baud <baud>|raw <cmd>\r"
+            )
+            .ok();
+        }
+        "echo" => {
+            writeln!(out, "{}\r", rest).ok();
+        }
+        "status" => match source {
+            Source::Wired(bt) => {
+                writeln!(
+                    out,
+                    "wired console, {:?} @ {} Baud, connected: {}, AT mode: {}\r",
+                    bt.kind(),
+                    bt.baud(),
+                    bt.is_connected(),
+                    bt.in_at_mode()
+                )
+                .ok();
+            }
+            Source::Bluetooth => {
+                writeln!(out, "bluetooth console\r").ok();
+            }
+        },
+        "bt" => match source {
+            Source::Wired(bt) => bt_command(bt, rest, out)?,
+            Source::Bluetooth => return Err(CommandError::WiredOnly),
+        },
+        "bridge" => match source {
+            Source::Wired(bt) => {
+                writeln!(out, "bridge to bluetooth, Ctrl-] to exit\r").ok();
+                bridge(bt);
+                writeln!(out, "\r\nbridge closed\r").ok();
+            }
+            Source::Bluetooth => return Err(CommandError::WiredOnly),
+        },
+        _ => return Err(CommandError::Syntax),
+    }
+    Ok(())
+}
+
+fn bt_command(bt: &mut BtModule, args: &str, out: &mut dyn Write) -> Result<(), CommandError> {
+    let (sub, arg) = args.split_once(' ').unwrap_or((args, ""));
+    let arg = arg.trim();
+    let mut reply = [0u8; 48];
+
+    match sub {
+        "at" => bt.enter_at()?,
+        "exit" => bt.exit_at(),
+        "version" => {
+            let version = bt.version(&mut reply)?;
+            writeln!(out, "{}\r", version).ok();
+        }
+        "name" if arg.is_empty() => {
+            let name = bt.name(&mut reply)?;
+            writeln!(out, "{}\r", name).ok();
+        }
+        "name" => bt.set_name(arg)?,
+        "pin" => bt.set_pin(arg)?,
+        "baud" => {
+            let baud = arg.parse().map_err(|_| CommandError::Syntax)?;
+            bt.set_baud(baud)?;
+        }
+        "raw" if !arg.is_empty() => {
+            let value = bt.command(format_args!("{}", arg), &mut reply)?;
+            writeln!(out, "{}\r", value).ok();
+        }
+        _ => return Err(CommandError::Syntax),
+    }
+    writeln!(out, "OK\r").ok();
+    Ok(())
+}
+
+// USART1 与蓝牙模块之间原样转发，直到 USART1 上收到 Ctrl-]
+fn bridge(bt: &mut BtModule) {
+    let usart1 = unsafe { &*USART1::ptr() };
+    let mut wired = Usart1Writer(usart1);
+    loop {
+        if let Some(byte) = wired.read_byte() {
+            if byte == BRIDGE_EXIT {
+                return;
+            }
+            bt.write_byte(byte);
+        }
+        if let Some(byte) = bt.read_byte() {
+            wired.write_byte(byte);
+        }
+    }
+}
+
+// 逐个字节收集一行命令，并回显，支持退格
+struct LineEditor {
+    buf: [u8; LINE_MAX],
+    len: usize,
+}
+
+impl LineEditor {
+    fn new() -> Self {
+        Self {
+            buf: [0; LINE_MAX],
+            len: 0,
+        }
+    }
+
+    // 收到回车时返回这一行的内容
+    fn push(&mut self, byte: u8, echo: &mut dyn Write) -> Option<&[u8]> {
+        match byte {
+            b'\r' | b'\n' => {
+                // \r\n 只算一次回车
+                if byte == b'\n' && self.len == 0 {
+                    return None;
+                }
+                echo.write_str("\r\n").ok();
+                let len = self.len;
+                self.len = 0;
+                return Some(&self.buf[..len]);
+            }
+            0x08 | 0x7F if self.len > 0 => {
+                self.len -= 1;
+                echo.write_str("\x08 \x08").ok();
+            }
+            0x20..=0x7E if self.len < LINE_MAX => {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                echo.write_char(byte as char).ok();
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+struct Usart1Writer<'a>(&'a pac::usart1::RegisterBlock);
+
+impl Usart1Writer<'_> {
+    fn read_byte(&mut self) -> Option<u8> {
+        if self.0.sr.read().rxne().bit_is_clear() {
+            return None;
+        }
+        Some(self.0.dr.read().dr().bits() as u8)
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        while self.0.sr.read().txe().bit_is_clear() {}
+        self.0.dr.write(|w| w.dr().bits(byte as u16));
+    }
+}
+
+impl Write for Usart1Writer<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+fn switch_to_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// USART1 收发，参数为 115200 8N1
+fn setup_usart1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| {
+        w.afrh9().af7();
+        w.afrh10().af7();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr9().pull_up();
+        w.pupdr10().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let serial1 = &dp.USART1;
+
+    serial1.cr1.modify(|_, w| w.ue().enabled());
+
+    // 12 MHz / (16 * 115200) = 6.51，整数部分为 6，小数部分为 0.51 * 16 ≈ 8
+    serial1.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    serial1.cr1.modify(|_, w| {
+        w.te().enabled();
+        w.re().enabled();
+        w
+    });
+}
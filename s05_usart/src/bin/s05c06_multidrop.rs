@@ -0,0 +1,213 @@
+//! 多块开发板组成的 RS-485 传感器网络
+//!
+//! 协议见 utils::multidrop：主机依次轮询每个从机，从机只在被轮询时发送，每帧带 CRC，DATA 需要 ACK，超时重发
+//!
+//! 所有板子烧录同一个程序，地址由 PB0 ~ PB2 上的拨码开关决定（开关接通为 1），地址 0 为主机，1 ~ 7 为从机
+//!
+//! - 从机每秒向主机报告一次“传感器读数”（这里用运行的秒数与收到的广播数代替）
+//! - 主机收到报告后通过 RTT 打印，每 5 秒广播一次自己的秒数
+//! - 从机 1 每 3 秒直接给从机 2 发一条消息，演示从机之间不经过主机转发的通信
+//! - 每 10 秒打印一次统计信息，拔掉某个从机，可以看到主机的 poll_timeouts 增加，其它从机不受影响
+//!
+//! 接线图：
+//!
+//! 每块板子一个 MAX485，所有 MAX485 的 A 与 A 相连、B 与 B 相连，总线两端各接一个 120 Ω 的终端电阻
+//!
+//! PA2 TX <-> DI
+//! PA3 RX <-> RO
+//! PA4    <-> DE 与 /RE（两者接在一起）
+//!
+//! PB0 ~ PB2 <-> 拨码开关，另一端接 GND，使用内部上拉
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, usart1::RegisterBlock, Peripherals};
+
+mod utils;
+
+use utils::{
+    multidrop::{Bus, Role, BROADCAST, MASTER},
+    serial_mode::Rs485,
+};
+
+// HSE 12 MHz，AHB 与 APB 均不分频
+const CLOCK_HZ: u32 = 12_000_000;
+
+const NODES: &[u8] = &[1, 2, 3, 4, 5, 6, 7];
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+    let dp = pac::Peripherals::take().unwrap();
+
+    // multidrop 用 CYCCNT 计时
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    switch_to_hse(&dp);
+    setup_gpio(&dp);
+
+    dp.RCC.apb1enr.modify(|_, w| w.usart2en().enabled());
+    setup_usart_115200(&dp.USART2);
+
+    let address = read_address(&dp);
+    let role = if address == MASTER {
+        Role::Master { nodes: NODES }
+    } else {
+        Role::Node
+    };
+    rprintln!("address {}, {:?}", address, role);
+
+    let rs485 = Rs485::new(&dp.USART2, |on| {
+        dp.GPIOA
+            .bsrr
+            .write(|w| if on { w.bs4().set() } else { w.br4().reset() });
+    });
+    let mut bus = Bus::new(rs485, address, role, CLOCK_HZ);
+
+    let mut last_second = cortex_m::peripheral::DWT::cycle_count();
+    let mut seconds: u32 = 0;
+    let mut broadcasts: u32 = 0;
+
+    loop {
+        // 主循环里不能有长时间的阻塞，否则会丢失接收的字节
+        bus.poll();
+
+        while let Some(message) = bus.recv() {
+            let payload = message.payload();
+            if message.dst == BROADCAST {
+                broadcasts += 1;
+            }
+            match payload {
+                [b'S', a, b, c, d, e, f, g, h] => rprintln!(
+                    "from {}: up {} s, {} broadcasts",
+                    message.src,
+                    u32::from_le_bytes([*a, *b, *c, *d]),
+                    u32::from_le_bytes([*e, *f, *g, *h])
+                ),
+                [b'T', a, b, c, d] => rprintln!(
+                    "broadcast from {}: {} s",
+                    message.src,
+                    u32::from_le_bytes([*a, *b, *c, *d])
+                ),
+                _ => rprintln!("from {} to {}: {:?}", message.src, message.dst, payload),
+            }
+        }
+
+        if cortex_m::peripheral::DWT::cycle_count().wrapping_sub(last_second) < CLOCK_HZ {
+            continue;
+        }
+        last_second = last_second.wrapping_add(CLOCK_HZ);
+        seconds += 1;
+
+        let result = if address == MASTER {
+            if seconds.is_multiple_of(5) {
+                let mut payload = [b'T'; 5];
+                payload[1..].copy_from_slice(&seconds.to_le_bytes());
+                bus.send(BROADCAST, &payload)
+            } else {
+                Ok(())
+            }
+        } else {
+            let mut payload = [b'S'; 9];
+            payload[1..5].copy_from_slice(&seconds.to_le_bytes());
+            payload[5..].copy_from_slice(&broadcasts.to_le_bytes());
+            let result = bus.send(MASTER, &payload);
+            if address == 1 && seconds.is_multiple_of(3) {
+                result.and(bus.send(2, b"hello node 2"))
+            } else {
+                result
+            }
+        };
+        if let Err(e) = result {
+            rprintln!("send failed: {:?}", e);
+        }
+
+        if seconds.is_multiple_of(10) {
+            rprintln!("{:?}", bus.stats());
+        }
+    }
+}
+
+// 拨码开关接通时引脚为低电平
+fn read_address(dp: &Peripherals) -> u8 {
+    !dp.GPIOB.idr.read().bits() as u8 & 0b111
+}
+
+fn switch_to_hse(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+fn setup_gpio(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w
+    });
+
+    // USART2 的 TX/RX 为 AF7
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| {
+        w.afrl2().af7();
+        w.afrl3().af7();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr2().pull_up();
+        // 发送时 /RE 为高，RO 处于高阻态，上拉以免 RX 上出现噪声
+        w.pupdr3().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder2().alternate();
+        w.moder3().alternate();
+        w.moder4().output();
+        w
+    });
+
+    let gpiob = &dp.GPIOB;
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr0().pull_up();
+        w.pupdr1().pull_up();
+        w.pupdr2().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder0().input();
+        w.moder1().input();
+        w.moder2().input();
+        w
+    });
+}
+
+// 8 bit 数据，1 bit 停止位，无奇偶校验，115200 Baud
+fn setup_usart_115200(usart: &RegisterBlock) {
+    usart.cr1.modify(|_, w| {
+        w.ue().enabled();
+        w.m().m8();
+        w
+    });
+    usart.cr2.modify(|_, w| w.stop().stop1());
+
+    // USARTDIV = 12 MHz / (16 * 115200) ≈ 6.51，整数部分 6，小数部分 0.51 * 16 ≈ 8
+    usart.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    usart.cr1.modify(|_, w| {
+        w.re().enabled();
+        w.te().enabled();
+        w
+    });
+}
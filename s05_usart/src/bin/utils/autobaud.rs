@@ -0,0 +1,181 @@
+//! 自动波特检测
+//!
+//! 在 USART 接管 RX 引脚之前，先把 RX 引脚交给 TIM1 的输入捕获通道，测量对方发来的第一个字节的位宽，
+//! 计算出 BRR 之后，再把引脚切换回 USART
+//!
+//! 两种训练方式：
+//!
+//! 1. 只测量起始位：要求对方发送的第一个字节最低位为 1（比如 'a' 或 '\r'），这样起始位的下降沿到随后的上升沿，正好是 1 个位宽
+//! 2. 0x55：0x55 按照低位在前的顺序发送，加上起始位与停止位，线上的电平为 0 1010 1010 1，每一位都在翻转
+//!    第 1 个下降沿（起始位）到第 5 个下降沿（bit 7），正好是 8 个位宽，求平均之后误差比只测量起始位小得多
+//!
+//! BRR 的计算
+//!
+//! 在 16 倍超采样下，USARTDIV = f_CK / (16 * baud)，而 BRR 的高 12 位是 USARTDIV 的整数部分，低 4 位是小数部分乘以 16，
+//! 因此整个 BRR 寄存器的值，正好就是 f_CK / baud，也就是一个位宽内 USART 时钟的周期数
+//! 若 TIM 的计数时钟与 USART 的时钟相同（这里 TIM1 和 USART1 都在 APB2 上，且 TIM1 不分频），测量到的位宽计数值就是 BRR 的值
+//!
+//! 限制：TIM1 是 16 位的定时器，在 12 MHz 下最多测量约 5.4 ms，因此 0x55 训练方式下，最低能检测约 1500 Baud
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::Peripherals;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Training {
+    StartBit,
+    Byte0x55,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AutobaudError {
+    // 等待边沿超时，对方没有发送数据
+    Timeout,
+    // 测量到的位宽太短，超出了 USART 的能力范围（BRR 不能小于 16）
+    TooFast,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Detected {
+    // 直接写入 BRR 的值
+    pub(crate) brr: u16,
+    // 由 BRR 反推的实际波特值
+    pub(crate) baud: u32,
+}
+
+// 常见的标准波特值
+const STANDARD_BAUDS: [u32; 11] = [
+    2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600, 1000000,
+];
+
+// 若测量结果与某个标准波特值的误差在 3% 以内，就认为对方使用的是这个波特值
+pub(crate) fn nearest_standard(baud: u32) -> Option<u32> {
+    STANDARD_BAUDS
+        .iter()
+        .copied()
+        .find(|&std| baud.abs_diff(std) * 100 <= std * 3)
+}
+
+// 把 PA10 交给 TIM1_CH3（AF1），并设置好输入捕获
+fn setup_capture(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.tim1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| w.afrh10().af1());
+    // 线路空闲时为高电平
+    gpioa.pupdr.modify(|_, w| w.pupdr10().pull_up());
+    gpioa.moder.modify(|_, w| w.moder10().alternate());
+
+    let tim = &dp.TIM1;
+    tim.cr1.modify(|_, w| w.cen().disabled());
+    tim.psc.write(|w| w.psc().bits(0));
+    tim.arr.write(|w| w.arr().bits(u16::MAX));
+
+    // CC3S = 01，通道 3 为输入，映射到 TI3
+    // IC3F = 0010，需要连续 4 次采样一致才认为是有效电平，可以滤掉短毛刺
+    tim.ccmr2_input().modify(|_, w| unsafe {
+        w.cc3s().bits(0b01);
+        w.ic3f().bits(0b0010);
+        w
+    });
+
+    tim.egr.write(|w| w.ug().set_bit());
+    tim.cr1.modify(|_, w| w.cen().enabled());
+}
+
+// 设置通道 3 的捕获边沿
+fn set_capture_edge(dp: &Peripherals, rising: bool) {
+    dp.TIM1.ccer.modify(|_, w| {
+        w.cc3e().clear_bit();
+        // CC3NP = 0 时，CC3P = 0 为上升沿，CC3P = 1 为下降沿
+        w.cc3np().clear_bit();
+        w.cc3p().bit(!rising);
+        w
+    });
+    // 切换边沿之后清理一下可能残留的标识位
+    dp.TIM1.sr.modify(|_, w| w.cc3if().clear_bit());
+    dp.TIM1.ccer.modify(|_, w| w.cc3e().set_bit());
+}
+
+// 等待一次捕获，max_spin 为最多轮询的次数
+fn wait_capture(dp: &Peripherals, max_spin: u32) -> Result<u16, AutobaudError> {
+    for _ in 0..max_spin {
+        if dp.TIM1.sr.read().cc3if().bit_is_set() {
+            // 读取 CCR3 会清理 CC3IF
+            return Ok(dp.TIM1.ccr3().read().ccr().bits());
+        }
+    }
+    Err(AutobaudError::Timeout)
+}
+
+// 等待 RX 线上连续 idle_ticks 个计数的高电平
+fn wait_line_idle(dp: &Peripherals, idle_ticks: u16) {
+    let tim = &dp.TIM1;
+    tim.cnt.write(|w| w.cnt().bits(0));
+    while tim.cnt.read().cnt().bits() < idle_ticks {
+        if dp.GPIOA.idr.read().idr10().is_low() {
+            tim.cnt.write(|w| w.cnt().bits(0));
+        }
+    }
+}
+
+// 测量对方的波特，usart_clock_hz 为 USART 所在总线的时钟频率
+//
+// 返回之后，PA10 依旧处于 TIM1 的 AF 下，需要调用 hand_over_to_usart1 把引脚交给 USART1
+pub(crate) fn detect(
+    dp: &Peripherals,
+    training: Training,
+    usart_clock_hz: u32,
+    max_spin: u32,
+) -> Result<Detected, AutobaudError> {
+    setup_capture(dp);
+
+    let ticks = match training {
+        Training::StartBit => {
+            set_capture_edge(dp, false);
+            let start = wait_capture(dp, max_spin)?;
+            set_capture_edge(dp, true);
+            let end = wait_capture(dp, max_spin)?;
+            end.wrapping_sub(start) as u32
+        }
+        Training::Byte0x55 => {
+            set_capture_edge(dp, false);
+            let start = wait_capture(dp, max_spin)?;
+            let mut end = start;
+            for _ in 0..4 {
+                end = wait_capture(dp, max_spin)?;
+            }
+            // 8 个位宽，四舍五入
+            (end.wrapping_sub(start) as u32 + 4) / 8
+        }
+    };
+
+    dp.TIM1.ccer.modify(|_, w| w.cc3e().clear_bit());
+
+    // 训练字节的剩余部分还在线上，此时若把引脚交给 USART，剩余部分中的下降沿会被当作起始位
+    // 因此这里要等待线路保持 11 个位宽的高电平，也就是确认整个字节（包括停止位）已经结束
+    wait_line_idle(dp, (ticks * 11).min(u16::MAX as u32) as u16);
+
+    dp.TIM1.cr1.modify(|_, w| w.cen().disabled());
+
+    // 16 倍超采样下，USARTDIV 最小为 1，也就是 BRR 最小为 16
+    if ticks < 16 {
+        return Err(AutobaudError::TooFast);
+    }
+
+    Ok(Detected {
+        brr: ticks as u16,
+        baud: usart_clock_hz / ticks,
+    })
+}
+
+// 把 PA10 切换回 USART1_RX（AF7），并写入 BRR
+//
+// 训练字节本身已经被 TIM1 “吃掉”了，USART1 不会收到它
+pub(crate) fn hand_over_to_usart1(dp: &Peripherals, detected: Detected) {
+    dp.GPIOA.afrh.modify(|_, w| w.afrh10().af7());
+    dp.USART1
+        .brr
+        .write(|w| unsafe { w.bits(detected.brr as u32) });
+}
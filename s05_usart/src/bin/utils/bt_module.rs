@@ -0,0 +1,464 @@
+//! HC-05 / HM-10 蓝牙串口模块的管理
+//!
+//! 两种模块平时都是“透明传输”：从 USART 写进去的字节原样发给已连接的手机或电脑，收到的字节原样从 USART 吐出来，
+//! 只有在 AT 模式下才能修改名字、配对码、波特之类的设置，两者的 AT 指令风格完全不同：
+//!
+//! HC-05（经典蓝牙 SPP）
+//!
+//! - KEY（PIO11）为高电平时，模块以当前的波特接受 AT 指令（部分固件需要在上电前拉高 KEY，此时固定为 38400 Baud）
+//! - 指令与回复都以 \r\n 结尾，查询的结果为 +NAME:xxx 这样的一行，最后以 OK 或者 ERROR:(x) 结束
+//! - STATE 引脚在连接后为高电平
+//!
+//! HM-10（BLE，CC2541）
+//!
+//! - 没有 KEY 引脚，未连接时收到的 AT 指令都会被执行，已连接时发送 AT 会直接断开连接，所以已连接时拒绝进入 AT 模式
+//! - 指令与回复都没有结尾符，回复为 OK、OK+Set:xxx、OK+NAME:xxx 这样的格式，只能以“一段时间没有新的字节”作为回复的结束
+//! - STATE 引脚默认在未连接时以 500 ms 闪烁，连接后保持高电平
+//!
+//! 连接状态：
+//!
+//! STATE 引脚持续高电平超过 STATE_STABLE_MS 才认为已连接，这样 HM-10 默认的闪烁不会被当作连接，
+//! poll_connection 在状态变化时返回新的状态
+//!
+//! 引脚固定为：USART2 PA2（TX）/PA3（RX），KEY 为 PA4（推挽输出），STATE 为 PA1（下拉输入）
+//! 超时由 DWT 的 CYCCNT 计算，new 时会打开周期计数器
+
+#![allow(dead_code)]
+
+use core::fmt::Write;
+
+use cortex_m::peripheral::DWT;
+use stm32f4xx_hal::pac::{self, usart1::RegisterBlock, Peripherals};
+
+// HC-05 一条指令的回复最慢约几百毫秒（比如 AT+INQ 之外的写 flash 操作）
+const REPLY_TIMEOUT_MS: u32 = 1000;
+// HM-10 的回复没有结尾符，超过这么久没有新的字节就认为回复结束
+const HM10_IDLE_MS: u32 = 100;
+// 拉高 KEY 之后，等待 HC-05 切换到 AT 模式的时间
+const KEY_SETTLE_MS: u32 = 50;
+const STATE_STABLE_MS: u32 = 800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Hc05,
+    Hm10,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BtError {
+    // 在超时之前没有收到完整的回复
+    Timeout,
+    // HC-05 回复的 ERROR:(x)，x 为十六进制的错误码
+    Error(u8),
+    // 回复的格式不认识
+    Unexpected,
+    // 回复比提供的缓冲更长
+    Overflow,
+    // HM-10 已连接，此时不能进入 AT 模式
+    Connected,
+    // 不在 AT 模式
+    NotInAtMode,
+    // 参数的长度或字符不符合模块的要求，或者模块不支持这个波特
+    InvalidArg,
+}
+
+pub(crate) struct BtModule {
+    kind: Kind,
+    usart: &'static RegisterBlock,
+    pclk_hz: u32,
+    cycles_per_ms: u32,
+    baud: u32,
+    at_mode: bool,
+
+    connected: bool,
+    // STATE 引脚最近一次变为高电平的时刻
+    high_since: Option<u32>,
+}
+
+impl BtModule {
+    // 配置引脚与 USART2，baud 为模块当前的数据波特，pclk_hz 为 APB1 的频率
+    pub(crate) fn new(
+        dp: &Peripherals,
+        cp: &mut pac::CorePeripherals,
+        kind: Kind,
+        baud: u32,
+        pclk_hz: u32,
+        sysclk_hz: u32,
+    ) -> Self {
+        cp.DCB.enable_trace();
+        cp.DWT.enable_cycle_counter();
+
+        dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+        dp.RCC.apb1enr.modify(|_, w| w.usart2en().enabled());
+
+        let gpioa = &dp.GPIOA;
+        gpioa.bsrr.write(|w| w.br4().set_bit());
+        gpioa.afrl.modify(|_, w| {
+            w.afrl2().af7();
+            w.afrl3().af7();
+            w
+        });
+        gpioa.pupdr.modify(|_, w| {
+            w.pupdr1().pull_down();
+            w.pupdr3().pull_up();
+            w
+        });
+        gpioa.moder.modify(|_, w| {
+            w.moder1().input();
+            w.moder2().alternate();
+            w.moder3().alternate();
+            w.moder4().output();
+            w
+        });
+
+        let usart = unsafe { &*pac::USART2::ptr() };
+        usart.cr1.modify(|_, w| w.ue().enabled());
+
+        let mut module = Self {
+            kind,
+            usart,
+            pclk_hz,
+            cycles_per_ms: sysclk_hz / 1000,
+            baud,
+            at_mode: false,
+            connected: false,
+            high_since: None,
+        };
+        module.set_local_baud(baud);
+
+        usart.cr1.modify(|_, w| {
+            w.te().enabled();
+            w.re().enabled();
+            w
+        });
+        module
+    }
+
+    pub(crate) fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub(crate) fn baud(&self) -> u32 {
+        self.baud
+    }
+
+    pub(crate) fn in_at_mode(&self) -> bool {
+        self.at_mode
+    }
+
+    // 16 倍超采样下，BRR 的值就是一个位宽内 USART 时钟的周期数，见 utils::autobaud
+    fn set_local_baud(&mut self, baud: u32) {
+        let brr = (self.pclk_hz + baud / 2) / baud;
+        self.usart.brr.write(|w| unsafe { w.bits(brr) });
+        self.baud = baud;
+    }
+
+    // ---- 透明传输 ----
+
+    pub(crate) fn read_byte(&mut self) -> Option<u8> {
+        let sr = self.usart.sr.read();
+        if sr.ore().bit_is_set() {
+            // 读取 SR 之后再读取 DR，清理溢出标识
+            self.usart.dr.read();
+        }
+        if sr.rxne().bit_is_clear() {
+            return None;
+        }
+        Some(self.usart.dr.read().dr().bits() as u8)
+    }
+
+    pub(crate) fn write_byte(&mut self, byte: u8) {
+        while self.usart.sr.read().txe().bit_is_clear() {}
+        self.usart.dr.write(|w| w.dr().bits(byte as u16));
+    }
+
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+    }
+
+    // ---- 连接状态 ----
+
+    fn state_high(&self) -> bool {
+        let gpioa = unsafe { &*pac::GPIOA::ptr() };
+        gpioa.idr.read().idr1().is_high()
+    }
+
+    pub(crate) fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    // 需要不停地调用，连接状态发生变化时返回新的状态
+    pub(crate) fn poll_connection(&mut self) -> Option<bool> {
+        let now = DWT::cycle_count();
+        let connected = if self.state_high() {
+            let since = *self.high_since.get_or_insert(now);
+            now.wrapping_sub(since) >= STATE_STABLE_MS * self.cycles_per_ms
+        } else {
+            self.high_since = None;
+            false
+        };
+
+        if connected == self.connected {
+            return None;
+        }
+        self.connected = connected;
+        Some(connected)
+    }
+
+    // ---- AT 模式 ----
+
+    pub(crate) fn enter_at(&mut self) -> Result<(), BtError> {
+        match self.kind {
+            Kind::Hc05 => {
+                let gpioa = unsafe { &*pac::GPIOA::ptr() };
+                gpioa.bsrr.write(|w| w.bs4().set_bit());
+                self.delay_ms(KEY_SETTLE_MS);
+            }
+            Kind::Hm10 => {
+                if self.state_high() {
+                    return Err(BtError::Connected);
+                }
+            }
+        }
+        self.at_mode = true;
+
+        let mut reply = [0u8; 8];
+        if let Err(e) = self.command(format_args!("AT"), &mut reply) {
+            self.exit_at();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // 回到透明传输，HC-05 释放 KEY 即可，HM-10 本来就不需要切换
+    pub(crate) fn exit_at(&mut self) {
+        if self.kind == Kind::Hc05 {
+            let gpioa = unsafe { &*pac::GPIOA::ptr() };
+            gpioa.bsrr.write(|w| w.br4().set_bit());
+        }
+        self.at_mode = false;
+    }
+
+    pub(crate) fn version<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a str, BtError> {
+        match self.kind {
+            Kind::Hc05 => self.command(format_args!("AT+VERSION?"), buf),
+            Kind::Hm10 => self.command(format_args!("AT+VERS?"), buf),
+        }
+    }
+
+    pub(crate) fn name<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a str, BtError> {
+        self.command(format_args!("AT+NAME?"), buf)
+    }
+
+    // HC-05 最多 32 个字符，HM-10 最多 12 个字符
+    pub(crate) fn set_name(&mut self, name: &str) -> Result<(), BtError> {
+        let max = match self.kind {
+            Kind::Hc05 => 32,
+            Kind::Hm10 => 12,
+        };
+        if name.is_empty() || name.len() > max || !name.bytes().all(|b| b.is_ascii_graphic()) {
+            return Err(BtError::InvalidArg);
+        }
+
+        let mut reply = [0u8; 32];
+        match self.kind {
+            Kind::Hc05 => self.command(format_args!("AT+NAME={}", name), &mut reply)?,
+            Kind::Hm10 => self.command(format_args!("AT+NAME{}", name), &mut reply)?,
+        };
+        Ok(())
+    }
+
+    // HC-05 为 4 位数字，HM-10 为 6 位数字，并且把 HM-10 设置为连接时必须输入配对码（AT+TYPE2）
+    pub(crate) fn set_pin(&mut self, pin: &str) -> Result<(), BtError> {
+        let len = match self.kind {
+            Kind::Hc05 => 4,
+            Kind::Hm10 => 6,
+        };
+        if pin.len() != len || !pin.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(BtError::InvalidArg);
+        }
+
+        let mut reply = [0u8; 16];
+        match self.kind {
+            Kind::Hc05 => {
+                // 2.0 之后的固件要求配对码带引号
+                match self.command(format_args!("AT+PSWD={}", pin), &mut reply) {
+                    Err(BtError::Error(_)) => {
+                        self.command(format_args!("AT+PSWD=\"{}\"", pin), &mut reply)?
+                    }
+                    result => result?,
+                };
+            }
+            Kind::Hm10 => {
+                self.command(format_args!("AT+PASS{}", pin), &mut reply)?;
+                self.command(format_args!("AT+TYPE2"), &mut reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 修改模块的数据波特，并重启模块让它生效，本机 USART2 的波特也随之修改
+    pub(crate) fn set_baud(&mut self, baud: u32) -> Result<(), BtError> {
+        let mut reply = [0u8; 16];
+        match self.kind {
+            Kind::Hc05 => {
+                self.command(format_args!("AT+UART={},0,0", baud), &mut reply)?;
+            }
+            Kind::Hm10 => {
+                let code = match baud {
+                    9600 => 0,
+                    19200 => 1,
+                    38400 => 2,
+                    57600 => 3,
+                    115200 => 4,
+                    _ => return Err(BtError::InvalidArg),
+                };
+                self.command(format_args!("AT+BAUD{}", code), &mut reply)?;
+            }
+        }
+
+        self.reset()?;
+        self.set_local_baud(baud);
+        Ok(())
+    }
+
+    // 重启模块，让修改的设置生效
+    //
+    // HC-05 启动时若 KEY 为高，会进入固定 38400 Baud 的 AT 模式，所以收到 OK 之后立刻释放 KEY，重启之后回到透明传输；
+    // HM-10 重启之后依旧接受 AT 指令
+    pub(crate) fn reset(&mut self) -> Result<(), BtError> {
+        let mut reply = [0u8; 16];
+        self.command(format_args!("AT+RESET"), &mut reply)?;
+        if self.kind == Kind::Hc05 {
+            self.exit_at();
+        }
+        // 等模块重新启动
+        self.delay_ms(REPLY_TIMEOUT_MS);
+        self.drain();
+        Ok(())
+    }
+
+    // 发送一条 AT 指令，返回回复中的值：
+    // HC-05 的 +NAME:xxx 返回 xxx，只有 OK 时返回空字符串；HM-10 的 OK+Set:xxx 返回 xxx，不以 OK 开头的回复原样返回
+    pub(crate) fn command<'a>(
+        &mut self,
+        args: core::fmt::Arguments,
+        buf: &'a mut [u8],
+    ) -> Result<&'a str, BtError> {
+        if !self.at_mode {
+            return Err(BtError::NotInAtMode);
+        }
+
+        self.drain();
+        self.write_fmt(args).ok();
+        if self.kind == Kind::Hc05 {
+            self.write_bytes(b"\r\n");
+        }
+
+        let len = match self.kind {
+            Kind::Hc05 => self.read_hc05_reply(buf)?,
+            Kind::Hm10 => self.read_hm10_reply(buf)?,
+        };
+        let reply = core::str::from_utf8(&buf[..len]).map_err(|_| BtError::Unexpected)?;
+        match self.kind {
+            // HM-10 的 AT+VERS? 直接回复 HMSoft V540 这样的字符串，没有 OK 前缀
+            Kind::Hm10 if !reply.starts_with("OK") => Ok(reply),
+            // 返回值只要冒号之后的部分
+            _ => Ok(reply.split_once(':').map_or("", |(_, value)| value)),
+        }
+    }
+
+    // HC-05：逐行读取，+ 开头的行保存到 buf 中，直到 OK 或 ERROR:(x)，返回保存的长度
+    fn read_hc05_reply(&mut self, buf: &mut [u8]) -> Result<usize, BtError> {
+        let start = DWT::cycle_count();
+        let timeout = REPLY_TIMEOUT_MS * self.cycles_per_ms;
+
+        let mut line = [0u8; 48];
+        let mut line_len = 0;
+        let mut saved = 0;
+        loop {
+            let byte = match self.read_byte() {
+                Some(byte) => byte,
+                None if DWT::cycle_count().wrapping_sub(start) > timeout => {
+                    return Err(BtError::Timeout)
+                }
+                None => continue,
+            };
+            match byte {
+                b'\r' => {}
+                b'\n' => {
+                    let text = &line[..line_len];
+                    line_len = 0;
+                    if text == b"OK" {
+                        return Ok(saved);
+                    }
+                    if let Some(code) = text.strip_prefix(b"ERROR:(") {
+                        return Err(BtError::Error(parse_hex(code)));
+                    }
+                    if text.first() == Some(&b'+') {
+                        let target = buf.get_mut(..text.len()).ok_or(BtError::Overflow)?;
+                        target.copy_from_slice(text);
+                        saved = text.len();
+                    }
+                }
+                _ => {
+                    *line.get_mut(line_len).ok_or(BtError::Overflow)? = byte;
+                    line_len += 1;
+                }
+            }
+        }
+    }
+
+    // HM-10：读取到 HM10_IDLE_MS 没有新字节为止
+    fn read_hm10_reply(&mut self, buf: &mut [u8]) -> Result<usize, BtError> {
+        let start = DWT::cycle_count();
+        let timeout = REPLY_TIMEOUT_MS * self.cycles_per_ms;
+        let idle = HM10_IDLE_MS * self.cycles_per_ms;
+
+        let mut len = 0;
+        let mut last = start;
+        loop {
+            let now = DWT::cycle_count();
+            match self.read_byte() {
+                Some(byte) => {
+                    *buf.get_mut(len).ok_or(BtError::Overflow)? = byte;
+                    len += 1;
+                    last = now;
+                }
+                None if len > 0 && now.wrapping_sub(last) > idle => break,
+                None if now.wrapping_sub(start) > timeout => return Err(BtError::Timeout),
+                None => {}
+            }
+        }
+
+        Ok(len)
+    }
+
+    // 丢弃之前残留的字节
+    fn drain(&mut self) {
+        while self.read_byte().is_some() {}
+    }
+
+    fn delay_ms(&self, ms: u32) {
+        let start = DWT::cycle_count();
+        while DWT::cycle_count().wrapping_sub(start) < ms * self.cycles_per_ms {}
+    }
+}
+
+impl Write for BtModule {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+fn parse_hex(digits: &[u8]) -> u8 {
+    digits
+        .iter()
+        .map_while(|&b| (b as char).to_digit(16))
+        .fold(0, |acc, digit| {
+            acc.wrapping_mul(16).wrapping_add(digit as u8)
+        })
+}
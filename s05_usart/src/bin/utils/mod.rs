@@ -0,0 +1,4 @@
+pub(crate) mod autobaud;
+pub(crate) mod bt_module;
+pub(crate) mod multidrop;
+pub(crate) mod serial_mode;
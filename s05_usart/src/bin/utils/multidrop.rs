@@ -0,0 +1,495 @@
+//! 多块开发板共享一条 RS-485 总线的简单协议
+//!
+//! 总线上有一个主机（地址 MASTER）与若干个从机（地址 1 ~ 0xFE），0xFF 为广播
+//! RS-485 是半双工的，两个节点同时发送就会冲突，这里用最简单也最可靠的方式避免冲突：主机轮询（polling）
+//!
+//! - 只有主机可以主动发送；主机依次向 nodes 中的每个从机发送 POLL
+//! - 从机只在被 POLL 时发送：有待发送的消息就发 DATA，没有就回复 EMPTY
+//! - 收到发给自己的 DATA（不是广播）的节点，立即回复 ACK，这是唯一一种不需要 POLL 的回复
+//! - 从机发给另一个从机的 DATA 不需要主机转发，总线上所有节点都能收到，由目的从机直接 ACK，
+//!   主机等这个 ACK（或者超时）之后，才继续轮询
+//!
+//! 帧格式，帧与帧之间以总线空闲（USART 的 IDLE）分隔，因此发送方必须连续地发出一整帧：
+//!
+//! | dst | src | kind | seq | len | payload（0 ~ MAX_PAYLOAD） | CRC16（小端序） |
+//!
+//! CRC 为 CRC-16/CCITT-FALSE，覆盖 CRC 之前的所有字节；CRC 错误、长度不符，或者接收时出现 NE/FE/ORE 的帧直接丢弃，
+//! 丢弃的 DATA 没有 ACK，发送方超时重发
+//!
+//! 重发与去重：每个节点的 DATA 带一个递增的 seq，ACK 带回同样的 seq；
+//! 接收方记录每个来源最后一次收到的 seq，重复的 DATA（ACK 丢失导致的重发）依旧回复 ACK，但不会再次交给应用
+//!
+//! 时间由 DWT 的 CYCCNT 给出，调用者需要先开启 DWT 的周期计数器
+
+#![allow(dead_code)]
+
+use cortex_m::peripheral::DWT;
+
+use super::serial_mode::Rs485;
+
+pub(crate) const MASTER: u8 = 0x00;
+pub(crate) const BROADCAST: u8 = 0xFF;
+
+pub(crate) const MAX_PAYLOAD: usize = 32;
+const HEADER_LEN: usize = 5;
+const CRC_LEN: usize = 2;
+const MAX_FRAME: usize = HEADER_LEN + MAX_PAYLOAD + CRC_LEN;
+
+const QUEUE_LEN: usize = 4;
+
+// 115200 Baud 下一个字节约 87 us，最长的帧约 3.4 ms，再加上对方的处理时间
+const REPLY_TIMEOUT_US: u32 = 6_000;
+// 收到一帧之后，等对方释放 DE 再回复
+const TURNAROUND_US: u32 = 100;
+const MAX_ATTEMPTS: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Kind {
+    Data = 0x01,
+    Ack = 0x02,
+    Poll = 0x03,
+    Empty = 0x04,
+}
+
+impl Kind {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(Kind::Data),
+            0x02 => Some(Kind::Ack),
+            0x03 => Some(Kind::Poll),
+            0x04 => Some(Kind::Empty),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BusError {
+    TooLong,
+    QueueFull,
+    BadAddress,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Message {
+    pub(crate) src: u8,
+    pub(crate) dst: u8,
+    len: u8,
+    data: [u8; MAX_PAYLOAD],
+}
+
+impl Message {
+    pub(crate) fn payload(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Stats {
+    pub(crate) tx_frames: u32,
+    pub(crate) rx_frames: u32,
+    // CRC 错误、长度不符、NE/FE/ORE
+    pub(crate) bad_frames: u32,
+    pub(crate) retries: u32,
+    // 重试 MAX_ATTEMPTS 次之后依旧没有 ACK 而丢掉的消息
+    pub(crate) dropped: u32,
+    pub(crate) duplicates: u32,
+    // 主机 POLL 之后从机没有回复
+    pub(crate) poll_timeouts: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    // 主机需要知道总线上有哪些从机
+    Master { nodes: &'static [u8] },
+    Node,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    // 等待 ACK，seq 为刚发出的 DATA，since 为发出时的 CYCCNT
+    AwaitAck { dst: u8, seq: u8, since: u32 },
+    // 主机等待被 POLL 的从机回复
+    AwaitReply { node: u8, since: u32 },
+    // 主机等待从机之间的 ACK，总线空出来之后才能继续
+    AwaitOther { since: u32 },
+}
+
+// 固定长度的环形队列
+struct Queue {
+    items: [Option<Message>; QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Self {
+            items: [None; QUEUE_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, message: Message) -> Result<(), BusError> {
+        if self.len == QUEUE_LEN {
+            return Err(BusError::QueueFull);
+        }
+        self.items[(self.head + self.len) % QUEUE_LEN] = Some(message);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn front(&self) -> Option<&Message> {
+        if self.len == 0 {
+            return None;
+        }
+        self.items[self.head].as_ref()
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        if self.len == 0 {
+            return None;
+        }
+        let message = self.items[self.head].take();
+        self.head = (self.head + 1) % QUEUE_LEN;
+        self.len -= 1;
+        message
+    }
+}
+
+pub(crate) struct Bus<'a, F: Fn(bool)> {
+    rs485: Rs485<'a, F>,
+    address: u8,
+    role: Role,
+    cycles_per_us: u32,
+
+    state: State,
+    // 主机下一次要 POLL 的从机在 nodes 中的下标
+    next_node: usize,
+
+    tx_queue: Queue,
+    rx_queue: Queue,
+    tx_seq: u8,
+    attempts: u8,
+    // 每个来源最后一次收到的 DATA 的 seq，下标为地址
+    last_seq: [Option<u8>; 256],
+
+    rx_buf: [u8; MAX_FRAME],
+    rx_len: usize,
+    rx_bad: bool,
+
+    stats: Stats,
+}
+
+impl<'a, F: Fn(bool)> Bus<'a, F> {
+    // USART 需要提前设置好波特率，并打开收发
+    pub(crate) fn new(rs485: Rs485<'a, F>, address: u8, role: Role, clock_hz: u32) -> Self {
+        Self {
+            rs485,
+            address,
+            role,
+            cycles_per_us: clock_hz / 1_000_000,
+            state: State::Idle,
+            next_node: 0,
+            tx_queue: Queue::new(),
+            rx_queue: Queue::new(),
+            tx_seq: 0,
+            attempts: 0,
+            last_seq: [None; 256],
+            rx_buf: [0; MAX_FRAME],
+            rx_len: 0,
+            rx_bad: false,
+            stats: Stats::default(),
+        }
+    }
+
+    pub(crate) fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    // 放入发送队列，主机在下一次空闲时发出，从机在下一次被 POLL 时发出
+    pub(crate) fn send(&mut self, dst: u8, payload: &[u8]) -> Result<(), BusError> {
+        if payload.len() > MAX_PAYLOAD {
+            return Err(BusError::TooLong);
+        }
+        if dst == self.address {
+            return Err(BusError::BadAddress);
+        }
+        let mut data = [0; MAX_PAYLOAD];
+        data[..payload.len()].copy_from_slice(payload);
+        self.tx_queue.push(Message {
+            src: self.address,
+            dst,
+            len: payload.len() as u8,
+            data,
+        })
+    }
+
+    pub(crate) fn recv(&mut self) -> Option<Message> {
+        self.rx_queue.pop()
+    }
+
+    // 在主循环中不断调用，两次调用之间的间隔必须小于一个字节的时间，否则会 ORE
+    pub(crate) fn poll(&mut self) {
+        if let Some(len) = self.receive() {
+            self.handle_frame(len);
+        }
+
+        match self.state {
+            State::Idle => {
+                if let Role::Master { nodes } = self.role {
+                    self.master_next(nodes);
+                }
+            }
+            State::AwaitAck { since, .. } if self.elapsed_us(since) > REPLY_TIMEOUT_US => {
+                self.ack_timeout();
+            }
+            State::AwaitReply { since, .. } if self.elapsed_us(since) > REPLY_TIMEOUT_US => {
+                self.stats.poll_timeouts += 1;
+                self.state = State::Idle;
+            }
+            State::AwaitOther { since } if self.elapsed_us(since) > REPLY_TIMEOUT_US => {
+                self.state = State::Idle;
+            }
+            _ => (),
+        }
+    }
+
+    // 直接比较周期数，CYCCNT 溢出时 wrapping_sub 依旧正确
+    fn elapsed_us(&self, since: u32) -> u32 {
+        DWT::cycle_count().wrapping_sub(since) / self.cycles_per_us
+    }
+
+    // 读取一个字节；遇到 IDLE 时，若已经收到了一些字节，返回这一帧的长度
+    fn receive(&mut self) -> Option<usize> {
+        let usart = self.rs485.usart();
+        let sr = usart.sr.read();
+
+        if sr.rxne().bit_is_set() {
+            // 读 DR 同时清除了 NE/FE/ORE
+            let byte = usart.dr.read().dr().bits() as u8;
+            if sr.nf().bit_is_set() || sr.fe().bit_is_set() || sr.ore().bit_is_set() {
+                self.rx_bad = true;
+            }
+            if self.rx_len < MAX_FRAME {
+                self.rx_buf[self.rx_len] = byte;
+                self.rx_len += 1;
+            } else {
+                self.rx_bad = true;
+            }
+            return None;
+        }
+
+        if sr.idle().bit_is_set() {
+            // IDLE 通过读 SR 再读 DR 清除
+            usart.dr.read();
+            let len = self.rx_len;
+            let bad = self.rx_bad;
+            self.rx_len = 0;
+            self.rx_bad = false;
+            if len == 0 {
+                return None;
+            }
+            if bad {
+                self.stats.bad_frames += 1;
+                return None;
+            }
+            return Some(len);
+        }
+
+        None
+    }
+
+    fn handle_frame(&mut self, len: usize) {
+        let frame = &self.rx_buf[..len];
+        if len < HEADER_LEN + CRC_LEN
+            || frame[4] as usize != len - HEADER_LEN - CRC_LEN
+            || crc16(&frame[..len - CRC_LEN])
+                != u16::from_le_bytes([frame[len - 2], frame[len - 1]])
+        {
+            self.stats.bad_frames += 1;
+            return;
+        }
+        let Some(kind) = Kind::from_u8(frame[2]) else {
+            self.stats.bad_frames += 1;
+            return;
+        };
+        self.stats.rx_frames += 1;
+
+        let (dst, src, seq) = (frame[0], frame[1], frame[3]);
+        let mut data = [0; MAX_PAYLOAD];
+        data[..len - HEADER_LEN - CRC_LEN].copy_from_slice(&frame[HEADER_LEN..len - CRC_LEN]);
+        let message = Message {
+            src,
+            dst,
+            len: (len - HEADER_LEN - CRC_LEN) as u8,
+            data,
+        };
+
+        match kind {
+            Kind::Data if dst == self.address || dst == BROADCAST => {
+                if dst != BROADCAST {
+                    self.send_frame(src, Kind::Ack, seq, &[]);
+                }
+                // ACK 丢失时对方会重发同一个 seq
+                if self.last_seq[src as usize] == Some(seq) {
+                    self.stats.duplicates += 1;
+                } else {
+                    self.last_seq[src as usize] = Some(seq);
+                    // 应用来不及取走时丢掉新消息，对方已经收到了 ACK，这里只能计数
+                    if self.rx_queue.push(message).is_err() {
+                        self.stats.dropped += 1;
+                    }
+                }
+                self.reply_done(src);
+            }
+            Kind::Data => {
+                // 从机之间的 DATA，主机等待目的从机的 ACK
+                if let State::AwaitReply { node, .. } = self.state {
+                    if node == src && dst != BROADCAST {
+                        self.state = State::AwaitOther {
+                            since: DWT::cycle_count(),
+                        };
+                        return;
+                    }
+                }
+                self.reply_done(src);
+            }
+            Kind::Ack if dst == self.address => {
+                if let State::AwaitAck {
+                    dst: peer,
+                    seq: sent,
+                    ..
+                } = self.state
+                {
+                    if peer == src && sent == seq {
+                        self.tx_queue.pop();
+                        self.attempts = 0;
+                        self.state = State::Idle;
+                    }
+                }
+            }
+            Kind::Ack => {
+                if let State::AwaitOther { .. } = self.state {
+                    self.state = State::Idle;
+                }
+            }
+            Kind::Poll if dst == self.address => self.answer_poll(),
+            Kind::Empty => self.reply_done(src),
+            Kind::Poll => (),
+        }
+    }
+
+    // 主机收到被 POLL 的从机的回复之后，轮到下一个
+    fn reply_done(&mut self, src: u8) {
+        if let State::AwaitReply { node, .. } = self.state {
+            if node == src {
+                self.state = State::Idle;
+            }
+        }
+    }
+
+    fn master_next(&mut self, nodes: &[u8]) {
+        if self.send_queued() {
+            return;
+        }
+        if nodes.is_empty() {
+            return;
+        }
+        let node = nodes[self.next_node % nodes.len()];
+        self.next_node = (self.next_node + 1) % nodes.len();
+        self.send_frame(node, Kind::Poll, 0, &[]);
+        self.state = State::AwaitReply {
+            node,
+            since: DWT::cycle_count(),
+        };
+    }
+
+    fn answer_poll(&mut self) {
+        if !self.send_queued() {
+            self.send_frame(MASTER, Kind::Empty, 0, &[]);
+        }
+    }
+
+    // 发出队列中的第一条消息，队列为空时返回 false
+    fn send_queued(&mut self) -> bool {
+        let Some(message) = self.tx_queue.front().copied() else {
+            return false;
+        };
+
+        // 重发时沿用同一个 seq
+        if self.attempts == 0 {
+            self.tx_seq = self.tx_seq.wrapping_add(1);
+        } else {
+            self.stats.retries += 1;
+        }
+        self.attempts += 1;
+
+        let seq = self.tx_seq;
+        self.send_frame(message.dst, Kind::Data, seq, message.payload());
+
+        if message.dst == BROADCAST {
+            self.tx_queue.pop();
+            self.attempts = 0;
+        } else {
+            self.state = State::AwaitAck {
+                dst: message.dst,
+                seq,
+                since: DWT::cycle_count(),
+            };
+        }
+        true
+    }
+
+    // 主机立即重发，从机等下一次被 POLL 时重发
+    fn ack_timeout(&mut self) {
+        self.state = State::Idle;
+        if self.attempts >= MAX_ATTEMPTS {
+            self.tx_queue.pop();
+            self.attempts = 0;
+            self.stats.dropped += 1;
+        }
+    }
+
+    fn send_frame(&mut self, dst: u8, kind: Kind, seq: u8, payload: &[u8]) {
+        let mut frame = [0u8; MAX_FRAME];
+        frame[0] = dst;
+        frame[1] = self.address;
+        frame[2] = kind as u8;
+        frame[3] = seq;
+        frame[4] = payload.len() as u8;
+        frame[HEADER_LEN..HEADER_LEN + payload.len()].copy_from_slice(payload);
+        let len = HEADER_LEN + payload.len();
+        let crc = crc16(&frame[..len]);
+        frame[len..len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+        let start = DWT::cycle_count();
+        while self.elapsed_us(start) < TURNAROUND_US {}
+
+        self.rs485.send(&frame[..len + CRC_LEN]);
+        self.stats.tx_frames += 1;
+    }
+}
+
+// CRC-16/CCITT-FALSE：多项式 0x1021，初始值 0xFFFF
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
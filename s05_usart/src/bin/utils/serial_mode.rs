@@ -0,0 +1,139 @@
+//! USART 的几种线路模式
+//!
+//! 1. 硬件流控制 RTS/CTS
+//!    RTS：本机接收缓冲（DR）满了的时候，硬件自动拉高 RTS，告诉对方暂停发送，DR 被读取后再自动拉低
+//!    CTS：本机每发送一个字节之前，硬件都会检查 CTS，CTS 为高时，暂停发送，直到对方拉低 CTS
+//!    注意 RTS/CTS 都是低电平有效的
+//!
+//! 2. RS-485
+//!    RS-485 是差分、半双工的总线，收发器（比如 MAX485）上有一个 DE（Driver Enable）引脚，
+//!    发送之前必须拉高 DE，发送完成之后必须立刻拉低 DE 释放总线，否则对方的回复会和我们的驱动器冲突
+//!    STM32F4 的 USART 没有硬件 DE 输出，因此这里用一个 GPIO 来控制 DE
+//!    “发送完成”必须以 TC 为准，而不能以 TXE 为准：TXE 只表示 DR 空了，此时最后一个字节还在移位寄存器里没有发出去
+//!
+//! 3. 单线半双工（Single-wire Half-duplex）
+//!    CR3 的 HDSEL 置 1 之后，RX 引脚不再使用，TX 引脚同时负责收发，TX 引脚需要设置为开漏并上拉
+//!    由于 TX 与 RX 在芯片内部连在一起，发送时接收器也会收到自己发出的数据，因此发送期间需要关闭接收器
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::usart1::RegisterBlock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FlowControl {
+    None,
+    // 仅由本机控制对方的发送
+    Rts,
+    // 仅由对方控制本机的发送
+    Cts,
+    RtsCts,
+}
+
+// 设置硬件流控制，对应的 RTS/CTS 引脚需要另外设置为 AF 模式
+pub(crate) fn set_flow_control(usart: &RegisterBlock, flow_control: FlowControl) {
+    let (rts, cts) = match flow_control {
+        FlowControl::None => (false, false),
+        FlowControl::Rts => (true, false),
+        FlowControl::Cts => (false, true),
+        FlowControl::RtsCts => (true, true),
+    };
+
+    usart.cr3.modify(|_, w| {
+        // RTSE: RTS Enable
+        w.rtse().bit(rts);
+        // CTSE: CTS Enable
+        w.ctse().bit(cts);
+        w
+    });
+}
+
+// CTS 线上的电平变化会挂起 SR 中的 CTS 标识位，可以用它来观察对方有没有暂停我们
+pub(crate) fn take_cts_changed(usart: &RegisterBlock) -> bool {
+    if usart.sr.read().cts().bit_is_set() {
+        usart.sr.modify(|_, w| w.cts().clear_bit());
+        true
+    } else {
+        false
+    }
+}
+
+// 单线半双工模式，修改 HDSEL 时 UE 需要处于关闭状态
+pub(crate) fn set_half_duplex(usart: &RegisterBlock, enable: bool) {
+    let ue = usart.cr1.read().ue().bit_is_set();
+    usart.cr1.modify(|_, w| w.ue().disabled());
+    usart.cr3.modify(|_, w| w.hdsel().bit(enable));
+    usart.cr1.modify(|_, w| w.ue().bit(ue));
+}
+
+// 阻塞地发送一串字节，并等待最后一个字节的停止位发送完成
+pub(crate) fn send_and_wait_tc(usart: &RegisterBlock, bytes: &[u8]) {
+    for &byte in bytes {
+        while usart.sr.read().txe().bit_is_clear() {}
+        usart.dr.write(|w| w.dr().bits(byte as u16));
+    }
+    while usart.sr.read().tc().bit_is_clear() {}
+}
+
+// 单线半双工下的发送，发送期间关闭接收器，避免收到自己发出的回声
+pub(crate) fn half_duplex_send(usart: &RegisterBlock, bytes: &[u8]) {
+    usart.cr1.modify(|_, w| w.re().disabled());
+    send_and_wait_tc(usart, bytes);
+    usart.cr1.modify(|_, w| w.re().enabled());
+}
+
+// 由 GPIO 控制 DE 的 RS-485 收发器
+//
+// set_de 用于设置 DE 引脚的电平，传入 true 时拉高
+pub(crate) struct Rs485<'a, F: Fn(bool)> {
+    usart: &'a RegisterBlock,
+    set_de: F,
+}
+
+impl<'a, F: Fn(bool)> Rs485<'a, F> {
+    pub(crate) fn new(usart: &'a RegisterBlock, set_de: F) -> Self {
+        // 默认处于接收状态
+        set_de(false);
+        Self { usart, set_de }
+    }
+
+    // 阻塞发送
+    //
+    // 从拉高 DE 到发出第一个字节之间，收发器需要一点建立时间（MAX485 的 t_DZH 最大约 70 ns），
+    // 相比于 CPU 写 DR 之后起始位才开始的延迟，这点时间可以忽略
+    pub(crate) fn send(&self, bytes: &[u8]) {
+        (self.set_de)(true);
+        send_and_wait_tc(self.usart, bytes);
+        // TC 挂起时，停止位已经完全发出，此时立刻释放总线
+        (self.set_de)(false);
+    }
+
+    // 非阻塞发送的开始部分：拉高 DE，写入第一个字节，并开启 TC 中断
+    //
+    // 之后的字节由调用者在 TXE 中断中写入，最后在 USART 中断里调用 on_tc，由 TC 中断负责释放总线
+    // 这样释放 DE 的时机只取决于中断延迟，不会因为 CPU 在忙别的事情而推迟
+    pub(crate) fn start_send(&self, first_byte: u8) {
+        (self.set_de)(true);
+        // TC 是通过“读 SR，再写 DR”清理的，这里写 DR 之前先读一次 SR
+        self.usart.sr.read();
+        self.usart.dr.write(|w| w.dr().bits(first_byte as u16));
+        self.usart.cr1.modify(|_, w| w.tcie().enabled());
+    }
+
+    // 在 USART 中断中调用，若发送已经完成，则释放总线并返回 true
+    //
+    // 注意，只有在最后一个字节写入 DR 之后，TC 才表示整个报文发送完成，调用者需要自己确认没有更多的字节要发送
+    pub(crate) fn on_tc(&self) -> bool {
+        if self.usart.sr.read().tc().bit_is_clear() {
+            return false;
+        }
+        (self.set_de)(false);
+        self.usart.cr1.modify(|_, w| w.tcie().disabled());
+        // TC 也可以通过写 0 来清理
+        self.usart.sr.modify(|_, w| w.tc().clear_bit());
+        true
+    }
+
+    pub(crate) fn usart(&self) -> &RegisterBlock {
+        self.usart
+    }
+}
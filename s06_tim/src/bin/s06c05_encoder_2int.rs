@@ -5,18 +5,22 @@
 #![no_std]
 #![no_main]
 
-use core::cell::{Cell, RefCell};
-
-use cortex_m::{interrupt::Mutex, peripheral::NVIC};
+use cortex_m::peripheral::NVIC;
 use stm32f4xx_hal::{interrupt, pac};
 
 use panic_rtt_target as _;
 use rtt_target::{rprint, rtt_init_print};
 
-static G_DP: Mutex<RefCell<Option<pac::Peripherals>>> = Mutex::new(RefCell::new(None));
+mod utils;
+
+use utils::resources::{LateResource, StaticCell};
+
+// 这两个全局量只在 main 与 TIM2 中断中使用，因此只需要屏蔽 TIM2 中断，而不必关闭所有的中断
+// 见 utils::resources
+static G_DP: LateResource<pac::Peripherals> = LateResource::masked("G_DP", interrupt::TIM2);
 // 由于我们要求最高频率触发中断，因此计数的功能就不能交给 TIM 的 CNT 寄存器实现了
 // 我们需要自己维护一个计数器
-static G_NUM: Mutex<Cell<i16>> = Mutex::new(Cell::new(0));
+static G_NUM: StaticCell<i16> = StaticCell::masked(0, interrupt::TIM2);
 
 #[cortex_m_rt::entry]
 fn main() -> ! {
@@ -92,9 +96,7 @@ fn main() -> ! {
     // 启动定时器
     tim2.cr1.modify(|_, w| w.cen().enabled());
 
-    cortex_m::interrupt::free(|cs| {
-        G_DP.borrow(cs).borrow_mut().replace(dp);
-    });
+    G_DP.init(dp);
 
     // 启用 NVIC 的 TIM2 中断
     unsafe { NVIC::unmask(interrupt::TIM2) };
@@ -105,12 +107,7 @@ fn main() -> ! {
 
 #[interrupt]
 fn TIM2() {
-    cortex_m::interrupt::free(|cs| {
-        let dp_ref = G_DP.borrow(cs).borrow();
-        let dp = dp_ref.as_ref().unwrap();
-
-        let mut num = G_NUM.borrow(cs).get();
-
+    G_DP.with(|dp| {
         let tim2 = &dp.TIM2;
 
         // 清理 TIM2 的 Update 中断标识位
@@ -119,13 +116,11 @@ fn TIM2() {
         // 然后依照 TIM 的 CR1 寄存器的 DIR 值，修改我们自己维护的计数器的值
         // 读取 CNT 的当前值没有意义，因为在中断触发的时候，CNT 的值必然是 0
         // 只要 Cortex 核心的处理速度远高于编码器的输出速度，那么 Cortex 读取 CNT 的值就总会是 0
-        num = match tim2.cr1.read().dir().bit() {
+        let num = G_NUM.update(|num| match tim2.cr1.read().dir().bit() {
             true => num - 1,
             false => num + 1,
-        };
+        });
 
         rprint!("\x1b[2K\r{}", num);
-
-        G_NUM.borrow(cs).set(num);
     });
 }
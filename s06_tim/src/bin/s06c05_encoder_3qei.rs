@@ -38,7 +38,7 @@ use panic_rtt_target as _;
 use rtt_target::{rprint, rtt_init_print};
 
 use stm32f4xx_hal::{
-    hal::Direction,
+    hal_02::Direction,
     interrupt,
     pac::{CorePeripherals, Peripherals, TIM2},
     prelude::*,
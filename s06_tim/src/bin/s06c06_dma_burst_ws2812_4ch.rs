@@ -0,0 +1,234 @@
+//! 用 TIM 的 DMA burst 同时驱动 4 条 ws2812 灯带
+//!
+//! s06c100 中，TIM3 每次 Update Event 通过 DMA 修改一个 CCR，因此一个 TIM 只能驱动一条灯带
+//! 这里改用 DMA burst（原理见 utils::dma_burst），每次 Update Event，DMA 依次写入 CCR1 ~ CCR4，
+//! TIM3 的 4 个通道在同一个周期里各自输出一个 bit，4 条灯带同时刷新，而且只占用一个 DMA Stream
+//!
+//! ws2812 的时序见 s06c100，这里同样把 SYSCLK/HCLK/PCLK 设置为 20 MHz，一个 tick 为 0.05 us
+//!
+//! 数据的排列方式为：每个 Update Event 一帧，每帧 4 个 u16，依次为 4 条灯带在这一 bit 上的 CCR 值
+//!
+//! 查询 DMA request mapping 可知，TIM3_UP 位于 DMA1 的 Stream 2 Channel 5 上
+//!
+//! 接线图：
+//!
+//! GPIO PB4 (TIM3_CH1) -> 灯带 1 的 DIN
+//! GPIO PB5 (TIM3_CH2) -> 灯带 2 的 DIN
+//! GPIO PB0 (TIM3_CH3) -> 灯带 3 的 DIN
+//! GPIO PB1 (TIM3_CH4) -> 灯带 4 的 DIN
+//!
+//! 每条灯带 8 颗 ws2812，VCC 接入 3.3V 或 5V 电源，GND 接地
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    dma_burst::{self, Burst},
+    ws2812::{FrameBuffer, Rgb, BITS_PER_LED},
+};
+
+const STRIPS: usize = 4;
+const LEDS_PER_STRIP: usize = 8;
+// 一个 bit 为 1.25 us，50 us 的低电平需要 40 帧
+const RESET_FRAMES: usize = 40;
+const FRAMES: usize = LEDS_PER_STRIP * BITS_PER_LED + RESET_FRAMES;
+
+const BURST: Burst = Burst::CCR1_TO_CCR4;
+
+// ws2812 使用频率固定，但占空比不同的 PWM 信号当作 bit 0 和 bit 1
+const N0: u16 = 8;
+const N1: u16 = 16;
+
+// 每条灯带上“跑动”的那颗灯的颜色
+const STRIP_COLORS: [Rgb; STRIPS] = [
+    Rgb::new(8, 0, 0),
+    Rgb::new(0, 8, 0),
+    Rgb::new(0, 0, 8),
+    Rgb::new(4, 4, 0),
+];
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_rcc(&dp);
+    setup_gpio(&dp);
+    setup_pwm(&dp);
+
+    dp.RCC.ahb1enr.modify(|_, w| w.dma1en().enabled());
+
+    let frames = cortex_m::singleton!(: [u16; FRAMES * STRIPS] = [0; FRAMES * STRIPS]).unwrap();
+
+    let mut step = 0;
+    loop {
+        fill_frames(frames, step);
+        send_frames(&dp, frames);
+
+        step = (step + 1) % LEDS_PER_STRIP;
+        // 20 MHz 下，约 100 ms
+        cortex_m::asm::delay(2_000_000);
+    }
+}
+
+// 第 n 条灯带的第 (step + n) 颗灯点亮，其余熄灭
+fn fill_frames(frames: &mut [u16], step: usize) {
+    frames.fill(0);
+
+    let mut strip_frame = FrameBuffer::<LEDS_PER_STRIP>::new();
+    for (strip, &color) in STRIP_COLORS.iter().enumerate() {
+        strip_frame.clear();
+        strip_frame.set((step + strip) % LEDS_PER_STRIP, color);
+        // 每帧 STRIPS 个 u16，第 strip 条灯带占其中的第 strip 个
+        strip_frame.encode_pwm(frames, BURST.frame_len(), strip, N0, N1);
+    }
+    // 最后 RESET_FRAMES 帧全部为 0，也就是保持低电平
+}
+
+// 启动一轮传输，并等待传输完成
+fn send_frames(dp: &pac::Peripherals, frames: &[u16]) {
+    let dma1 = &dp.DMA1;
+    let st = &dma1.st[2];
+    let tim = &dp.TIM3;
+
+    dma1.lifcr.write(|w| {
+        w.ctcif2().clear();
+        w.chtif2().clear();
+        w.cteif2().clear();
+        w.cfeif2().clear();
+        w.cdmeif2().clear();
+        w
+    });
+
+    dma_burst::setup_stream(st, 5, tim, BURST, frames, false);
+    dma_burst::setup_tim(tim, BURST);
+
+    st.cr.modify(|_, w| w.en().enabled());
+    tim.cr1.modify(|_, w| w.cen().enabled());
+
+    loop {
+        let lisr = dma1.lisr.read();
+        if lisr.teif2().is_error() {
+            panic!("DMA1 STREAM2 Transfer Error");
+        }
+        if lisr.tcif2().is_complete() {
+            break;
+        }
+    }
+
+    // 与 s06c100 一样，先关闭 TIM 的 DMA 请求，再停止计数，最后几帧都是 0，此时 4 路输出都处于低电平
+    tim.dier.modify(|_, w| w.ude().disabled());
+    tim.cr1.modify(|_, w| w.cen().disabled());
+    tim.cnt.reset();
+}
+
+// 将 SYSCLK/HCLK/PCLK 全部设置为 20 MHz，同 s06c100
+fn setup_rcc(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+
+    rcc.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(80);
+        }
+        w.pllp().div8();
+        w
+    });
+
+    rcc.cr.modify(|_, w| w.pllon().on());
+    while rcc.cr.read().pllrdy().is_not_ready() {}
+
+    rcc.cfgr.modify(|_, w| w.sw().pll());
+
+    while !rcc.cfgr.read().sws().is_pll() {}
+}
+
+// PB4/PB5/PB0/PB1 都切换到 AF2，也就是 TIM3 的 CH1 ~ CH4
+fn setup_gpio(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.ospeedr.modify(|_, w| {
+        w.ospeedr0().medium_speed();
+        w.ospeedr1().medium_speed();
+        w.ospeedr4().medium_speed();
+        w.ospeedr5().medium_speed();
+        w
+    });
+    // TIM3 停止时，由下拉电阻保持低电平
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr0().pull_down();
+        w.pupdr1().pull_down();
+        w.pupdr4().pull_down();
+        w.pupdr5().pull_down();
+        w
+    });
+    gpiob.afrl.modify(|_, w| {
+        w.afrl0().af2();
+        w.afrl1().af2();
+        w.afrl4().af2();
+        w.afrl5().af2();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder0().alternate();
+        w.moder1().alternate();
+        w.moder4().alternate();
+        w.moder5().alternate();
+        w
+    });
+}
+
+fn setup_pwm(dp: &pac::Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.tim3en().enabled());
+
+    let tim = &dp.TIM3;
+
+    // 20 MHz / 25 = 800 kHz
+    tim.arr.write(|w| w.arr().bits(25 - 1));
+    tim.cr1.modify(|_, w| {
+        w.dir().up();
+        w.arpe().enabled();
+        w
+    });
+
+    // 4 个通道都使用 PWM_MODE1，并开启 CCR 的预载，保证 4 路在同一个 Update Event 更新
+    tim.ccmr1_output().modify(|_, w| {
+        w.cc1s().output();
+        w.oc1m().pwm_mode1();
+        w.oc1pe().enabled();
+        w.cc2s().output();
+        w.oc2m().pwm_mode1();
+        w.oc2pe().enabled();
+        w
+    });
+    tim.ccmr2_output().modify(|_, w| {
+        w.cc3s().output();
+        w.oc3m().pwm_mode1();
+        w.oc3pe().enabled();
+        w.cc4s().output();
+        w.oc4m().pwm_mode1();
+        w.oc4pe().enabled();
+        w
+    });
+
+    tim.ccer.modify(|_, w| {
+        w.cc1e().set_bit();
+        w.cc2e().set_bit();
+        w.cc3e().set_bit();
+        w.cc4e().set_bit();
+        w
+    });
+}
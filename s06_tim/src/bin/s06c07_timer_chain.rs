@@ -0,0 +1,81 @@
+//! 级联 TIM2 与 TIM5，组成 64 bit 的微秒计数器
+//!
+//! 原理见 utils::chain
+//!
+//! 程序每秒打印一次开机以来的时间，同时设置一个 2 小时的超时，到时后打印一条消息
+//! 整个过程中没有使用任何中断，计时完全由 TIM2 与 TIM5 在硬件上完成
+//!
+//! 不需要额外接线
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::chain::{ChainedCounter, Deadline, Width};
+
+// 使用 HSE，APB1 不分频，TIM 的时钟为 12 MHz
+const TIM_CLK_HZ: u32 = 12_000_000;
+const TICK_HZ: u32 = 1_000_000;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    config_hse(&dp);
+
+    // debug 暂停时，两个 TIM 一起停止，否则暂停期间主 TIM 溢出产生的 TRGO 会丢失
+    dp.DBGMCU.apb1_fz.modify(|_, w| {
+        w.dbg_tim2_stop().set_bit();
+        w.dbg_tim5_stop().set_bit();
+        w
+    });
+
+    let counter = ChainedCounter::setup(&dp, Width::Bits64, TIM_CLK_HZ, TICK_HZ);
+
+    let (max_secs, _) = counter.split_seconds(counter.max());
+    rprintln!(
+        "64 bit counter at {} Hz, overflows after {} years",
+        counter.tick_hz(),
+        max_secs / (365 * 24 * 3600)
+    );
+
+    let long_timeout = Deadline::after(&counter, &dp, 2 * 3600 * TICK_HZ as u64);
+    let mut long_timeout_reported = false;
+
+    let mut next_print = Deadline::after(&counter, &dp, 0);
+    loop {
+        if next_print.is_expired(&counter, &dp) {
+            let (secs, micros) = counter.split_seconds(counter.now(&dp));
+            rprintln!(
+                "uptime {:02}:{:02}:{:02}.{:06}",
+                secs / 3600,
+                secs / 60 % 60,
+                secs % 60,
+                micros
+            );
+            // 以上一次的到期时间为基准，避免打印本身的耗时累积下来
+            next_print = Deadline(next_print.0 + TICK_HZ as u64);
+        }
+
+        if !long_timeout_reported && long_timeout.is_expired(&counter, &dp) {
+            rprintln!("2 hours timeout expired");
+            long_timeout_reported = true;
+        }
+    }
+}
+
+fn config_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
@@ -0,0 +1,258 @@
+//! 信号发生器测试治具
+//!
+//! 把这块板子变成一台简易的信号发生器，用来给另一块板子上的输入捕获、测频、编码器等驱动提供已知的输入信号
+//! 各种输出模式的原理见 utils::siggen
+//!
+//! 通过串口输入命令（以回车结束）：
+//!
+//! pwm <Hz> <占空比%>                       固定频率的 PWM
+//! sweep <起始Hz> <结束Hz> <周期ms> [占空比%] 锯齿波扫频，占空比默认 50%
+//! burst <Hz> <占空比%> <脉冲数>              输出指定个数的脉冲后停止
+//! pattern <高us> <低us> [<高us> <低us> ...] 循环输出以 us 为单位的高低电平序列，最多 8 段
+//! passthru                                 把 PB6 上的 PWM 原样转发到 PA8
+//! stop                                     停止输出，PA8 保持低电平
+//!
+//! 比如 pattern 100 50 20 200 会循环输出：高 100 us，低 50 us，高 20 us，低 200 us
+//!
+//! 接线图：
+//!
+//! PA8 (TIM1_CH1) -> 信号输出，接到被测板的输入，两块板子要共地
+//! PB6 (TIM4_CH1) <- passthru 的信号输入，不超过 3.3 V
+//!
+//! USB-TTL 模块，115200 8N1
+//! PA9  (USART1 Tx) <-> Rx
+//! PA10 (USART1 Rx) <-> Tx
+//! GND              <-> GND
+
+#![no_std]
+#![no_main]
+
+use core::{fmt::Write, str::FromStr};
+
+use cortex_m::peripheral::NVIC;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+#[cfg(feature = "stm32f413")]
+use utils::siggen::{self, GenError, PATTERN_MAX};
+
+chip_caps::require!(DAC, TIM6_TIM7);
+
+const LINE_MAX: usize = 80;
+
+#[cfg(feature = "stm32f413")]
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    setup_usart1(&dp);
+    siggen::setup(&dp);
+
+    unsafe {
+        NVIC::unmask(interrupt::TIM1_UP_TIM10);
+        NVIC::unmask(interrupt::TIM4);
+        NVIC::unmask(interrupt::TIM6_GLB_IT_DAC1_DAC2);
+    }
+
+    let mut console = Console { usart: &dp.USART1 };
+    let mut line = [0u8; LINE_MAX];
+
+    writeln!(console, "signal generator ready\r").ok();
+
+    loop {
+        write!(console, "> ").ok();
+        let len = console.read_line(&mut line);
+        let command = core::str::from_utf8(&line[..len]).unwrap_or("");
+
+        match execute(&dp, command) {
+            Ok(Some(freq_hz)) => writeln!(console, "OK, actual {} Hz\r", freq_hz).ok(),
+            Ok(None) => writeln!(console, "OK\r").ok(),
+            Err(CommandError::Syntax) => writeln!(console, "ERR syntax\r").ok(),
+            Err(CommandError::Gen(e)) => writeln!(console, "ERR {:?}\r", e).ok(),
+        };
+        rprintln!("{} -> {:?}", command, siggen::mode());
+    }
+}
+
+enum CommandError {
+    Syntax,
+    Gen(GenError),
+}
+
+impl From<GenError> for CommandError {
+    fn from(e: GenError) -> Self {
+        CommandError::Gen(e)
+    }
+}
+
+// 执行一行命令，输出频率有偏差时返回实际的频率
+fn execute(dp: &pac::Peripherals, command: &str) -> Result<Option<u32>, CommandError> {
+    let mut words = command.split_ascii_whitespace();
+    let Some(name) = words.next() else {
+        return Ok(None);
+    };
+
+    match name {
+        "pwm" => {
+            let freq = arg(&mut words)?;
+            let duty = arg(&mut words)?;
+            Ok(Some(siggen::pwm(dp, freq, duty)?))
+        }
+        "sweep" => {
+            let start = arg(&mut words)?;
+            let stop = arg(&mut words)?;
+            let period_ms = arg(&mut words)?;
+            let duty = arg(&mut words).unwrap_or(50.0);
+            siggen::sweep(dp, start, stop, period_ms, duty)?;
+            Ok(None)
+        }
+        "burst" => {
+            let freq = arg(&mut words)?;
+            let duty = arg(&mut words)?;
+            let count = arg(&mut words)?;
+            Ok(Some(siggen::burst(dp, freq, duty, count)?))
+        }
+        "pattern" => {
+            let mut segments = [(0, 0); PATTERN_MAX];
+            let mut count = 0;
+            while let Ok(high) = arg(&mut words) {
+                let low = arg(&mut words)?;
+                let segment = segments
+                    .get_mut(count)
+                    .ok_or(CommandError::Gen(GenError::BadPattern))?;
+                *segment = (high, low);
+                count += 1;
+            }
+            siggen::pattern(dp, &segments[..count])?;
+            Ok(None)
+        }
+        "passthru" => {
+            siggen::pass_through(dp);
+            Ok(None)
+        }
+        "stop" => {
+            siggen::stop(dp);
+            Ok(None)
+        }
+        _ => Err(CommandError::Syntax),
+    }
+}
+
+// 取出下一个参数，并解析为 T
+fn arg<'a, T: FromStr>(words: &mut impl Iterator<Item = &'a str>) -> Result<T, CommandError> {
+    words
+        .next()
+        .and_then(|word| word.parse().ok())
+        .ok_or(CommandError::Syntax)
+}
+
+#[interrupt]
+fn TIM1_UP_TIM10() {
+    siggen::on_tim1_update();
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn TIM6_GLB_IT_DAC1_DAC2() {
+    siggen::on_sweep_tick();
+}
+
+#[interrupt]
+fn TIM4() {
+    siggen::on_capture();
+}
+
+// 通过 USART1 实现的简单命令行
+struct Console<'a> {
+    usart: &'a pac::USART1,
+}
+
+impl Console<'_> {
+    // 阻塞地读取一行，并回显输入的字符，返回读到的字节数
+    fn read_line(&mut self, line: &mut [u8]) -> usize {
+        let mut len = 0;
+        loop {
+            while self.usart.sr.read().rxne().bit_is_clear() {}
+            let byte = self.usart.dr.read().dr().bits() as u8;
+            match byte {
+                b'\r' | b'\n' => {
+                    self.write_str("\r\n").ok();
+                    return len;
+                }
+                _ if len < line.len() => {
+                    line[len] = byte;
+                    len += 1;
+                    self.write_str(core::str::from_utf8(&[byte]).unwrap_or("?"))
+                        .ok();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Write for Console<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            while self.usart.sr.read().txe().bit_is_clear() {}
+            self.usart.dr.write(|w| w.dr().bits(byte as u16));
+        }
+        Ok(())
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// USART1 收发，参数为 115200 8N1
+fn setup_usart1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| {
+        w.afrh9().af7();
+        w.afrh10().af7();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr9().pull_up();
+        w.pupdr10().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let serial1 = &dp.USART1;
+
+    serial1.cr1.modify(|_, w| w.ue().enabled());
+
+    // 12 MHz / (16 * 115200) = 6.51，整数部分为 6，小数部分为 0.51 * 16 ≈ 8
+    serial1.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    serial1.cr1.modify(|_, w| {
+        w.te().enabled();
+        w.re().enabled();
+        w
+    });
+}
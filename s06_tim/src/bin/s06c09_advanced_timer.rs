@@ -0,0 +1,218 @@
+//! 高级定时器：互补输出、死区、刹车、重复计数器与六步换相
+//!
+//! 各项功能的说明见 utils::advanced_tim
+//!
+//! 这里让 TIM1 的三对互补输出，按照无刷电机六步换相的顺序轮流导通，可以接三个半桥驱动芯片，或者直接用逻辑分析仪观察：
+//!
+//! - PWM 为 20 kHz，占空比 30%，CHx 与 CHxN 之间有 500 ns 的死区
+//! - RCR 为 20，每 20 个 PWM 周期（1 ms）才产生一次更新中断，中断里每 250 次（250 ms）换相一次
+//! - 换相的状态提前写入预装载，由 COM 事件让三相同时切换，不会出现某一相已经切换、另一相还没切换的中间状态
+//! - PA6 为刹车输入，低电平有效，拉低时所有输出立即关闭，松开后由主循环重新打开
+//! - 配置完成之后锁定到 Level 1，死区与刹车的设置在复位之前都无法再被修改
+//!
+//! 接线图：
+//!
+//! PA8  TIM1_CH1   A 相上管
+//! PA7  TIM1_CH1N  A 相下管
+//! PA9  TIM1_CH2   B 相上管
+//! PB0  TIM1_CH2N  B 相下管
+//! PA10 TIM1_CH3   C 相上管
+//! PB1  TIM1_CH3N  C 相下管
+//! PA6  TIM1_BKIN  刹车按键，另一端接 GND，使用内部上拉
+
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use cortex_m::peripheral::NVIC;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::advanced_tim::{
+    AdvancedTimer, BdtrConfig, BreakPolarity, Channel, ComTrigger, Instance, LockLevel, Phase,
+};
+
+chip_caps::require!(TIM8);
+
+// 使用 HSE，APB2 不分频，TIM1 的时钟为 12 MHz
+const TIM_CLK_HZ: u32 = 12_000_000;
+
+// 12 MHz / 600 = 20 kHz
+const PWM_ARR: u16 = 600 - 1;
+const PWM_DUTY: u16 = 180;
+
+// 每 20 个 PWM 周期一次更新事件，即 1 kHz
+const REPETITION: u32 = 20;
+// 每 250 次更新事件换相一次
+const UPDATES_PER_STEP: u32 = 250;
+
+// 六步换相，每一步为 A、B、C 三相的状态
+const STEPS: [[Phase; 3]; 6] = [
+    [Phase::Pwm, Phase::Low, Phase::Off],
+    [Phase::Pwm, Phase::Off, Phase::Low],
+    [Phase::Off, Phase::Pwm, Phase::Low],
+    [Phase::Low, Phase::Pwm, Phase::Off],
+    [Phase::Low, Phase::Off, Phase::Pwm],
+    [Phase::Off, Phase::Low, Phase::Pwm],
+];
+
+static G_UPDATES: AtomicU32 = AtomicU32::new(0);
+static G_STEP: AtomicU32 = AtomicU32::new(0);
+static G_BROKEN: AtomicBool = AtomicBool::new(false);
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    setup_gpio(&dp);
+
+    let tim = AdvancedTimer::new(&dp, Instance::Tim1, TIM_CLK_HZ);
+    tim.set_period(0, PWM_ARR);
+    tim.set_repetition(REPETITION).unwrap();
+
+    for channel in Channel::ALL {
+        tim.setup_pwm(channel, true);
+        tim.set_duty(channel, PWM_DUTY);
+    }
+
+    tim.enable_commutation(ComTrigger::Software);
+    tim.preload_phases(&STEPS[0]);
+    tim.commutate();
+    tim.preload_phases(&STEPS[1]);
+
+    tim.configure_bdtr(&BdtrConfig {
+        dead_time_ns: 500,
+        brk: Some(BreakPolarity::ActiveLow),
+        // 刹车撤除后由软件决定何时恢复
+        auto_output: false,
+        // 关闭的相输出无效电平，而不是高阻，这样栅极不会悬空
+        ossr: true,
+        ossi: true,
+        lock: LockLevel::Level1,
+    })
+    .unwrap();
+
+    rprintln!(
+        "dead time {} ns, lock {:?}",
+        tim.dead_time_ns(),
+        tim.lock_level()
+    );
+
+    // 启用之前清除已经发生的刹车
+    tim.clear_break();
+    tim.regs().sr.modify(|_, w| w.uif().clear_bit());
+    tim.regs().dier.modify(|_, w| {
+        w.uie().enabled();
+        w.bie().set_bit();
+        w
+    });
+    unsafe {
+        NVIC::unmask(interrupt::TIM1_UP_TIM10);
+        NVIC::unmask(interrupt::TIM1_BRK_TIM9);
+    }
+
+    tim.enable_outputs();
+    tim.start();
+
+    loop {
+        // 刹车按键松开之后，重新打开输出
+        if G_BROKEN.load(Ordering::Relaxed) && dp.GPIOA.idr.read().idr6().is_high() {
+            tim.clear_break();
+            G_BROKEN.store(false, Ordering::Relaxed);
+            tim.regs().dier.modify(|_, w| w.bie().set_bit());
+            tim.enable_outputs();
+            rprintln!("break released, outputs enabled");
+        }
+    }
+}
+
+#[interrupt]
+fn TIM1_UP_TIM10() {
+    let tim = AdvancedTimer::attach(Instance::Tim1, TIM_CLK_HZ);
+    tim.regs().sr.modify(|_, w| w.uif().clear_bit());
+
+    let updates = G_UPDATES.load(Ordering::Relaxed) + 1;
+    if updates < UPDATES_PER_STEP {
+        G_UPDATES.store(updates, Ordering::Relaxed);
+        return;
+    }
+    G_UPDATES.store(0, Ordering::Relaxed);
+
+    // 让已经预装载的下一步生效，再预装载再下一步
+    tim.commutate();
+    let step = (G_STEP.load(Ordering::Relaxed) + 1) % STEPS.len() as u32;
+    G_STEP.store(step, Ordering::Relaxed);
+    tim.preload_phases(&STEPS[(step as usize + 1) % STEPS.len()]);
+}
+
+#[interrupt]
+fn TIM1_BRK_TIM9() {
+    let tim = AdvancedTimer::attach(Instance::Tim1, TIM_CLK_HZ);
+
+    // 刹车输入保持有效时，BIF 会被反复置位，因此先关闭刹车中断，由主循环在松开后重新打开
+    tim.regs().dier.modify(|_, w| w.bie().clear_bit());
+    G_BROKEN.store(true, Ordering::Relaxed);
+
+    rprintln!("break! outputs disabled by hardware");
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 所有引脚都是 AF01
+fn setup_gpio(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| {
+        w.afrl6().af1();
+        w.afrl7().af1();
+        w
+    });
+    gpioa.afrh.modify(|_, w| {
+        w.afrh8().af1();
+        w.afrh9().af1();
+        w.afrh10().af1();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| w.pupdr6().pull_up());
+    gpioa.moder.modify(|_, w| {
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| {
+        w.afrl0().af1();
+        w.afrl1().af1();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder0().alternate();
+        w.moder1().alternate();
+        w
+    });
+}
@@ -16,6 +16,12 @@
 //!
 //! 在实现上，我们将使用 TIM 的 PWM 输出功能，搭配 DMA 输出数据流。注意到 800 kHz 对于 中断 + Cortex CPU 改写寄存器来说，频率还是太高了，
 //! 因此，使用 DMA 就是必然的了。另外，我们还开启了另一个 TIM 来实现闪烁效果，并使用 WFI 和 Sleep on Exit，节省少许能源消耗。
+//! 外设时钟的开关都交给了 utils::clock_gate（见 s17c04），这样可以随时用 clock_gate::report 查看哪些外设还在耗电
+//!
+//! 两个中断处理函数对寄存器的修改都用 utils::reg_batch 事先算好了要写入的值：
+//! TIM3 的 CR1 与 DIER 中只有我们设置的那几位，可以直接 write，不必读-改-写；TIM2 的 UIF 是 rc_w0，直接写入也更安全
+//! 每个中断中操作寄存器的那一段用 utils::cycle_stats 统计了 CPU 周期，每 REPORT_EVERY 轮打印一次，
+//! 把 BATCHED 改为 false 就回到逐个字段 modify 的写法，可以对比两者的周期数
 //!
 //! 接线图：
 //!
@@ -29,11 +35,53 @@ use core::{
     sync::atomic::{AtomicU32, AtomicU8, Ordering},
 };
 
-use cortex_m::{asm, interrupt::Mutex, peripheral::NVIC};
+use cortex_m::{
+    asm,
+    interrupt::Mutex,
+    peripheral::{DWT, NVIC},
+};
 use panic_rtt_target as _;
 use rtt_target::{rprint, rprintln, rtt_init_print};
 use stm32f4xx_hal::{interrupt, pac};
 
+mod utils;
+
+use utils::{
+    clock_gate::{self, gates},
+    cycle_stats::{self, CycleStats},
+    reg_batch::{self, Batch, Field},
+};
+
+// 为 false 时使用逐个字段 modify 的写法，用来对比中断的耗时
+const BATCHED: bool = true;
+// 每完成多少轮传输打印一次中断的耗时
+const REPORT_EVERY: u32 = 16;
+
+static DMA_ISR_CYCLES: CycleStats = CycleStats::new("DMA1_STREAM4");
+static TIM2_ISR_CYCLES: CycleStats = CycleStats::new("TIM2");
+
+// TIMx_CR1
+const CR1_CEN: Field = Field::bit(0);
+const CR1_DIR: Field = Field::bit(4);
+// TIMx_DIER
+const DIER_CC1DE: Field = Field::bit(9);
+// TIMx_SR
+const SR_UIF: Field = Field::bit(0);
+// DMA_SxCR
+const DMA_SXCR_EN: Field = Field::bit(0);
+
+// TIM3 的 CR1 中只有 DIR（上计数，为 0）与 CEN 是我们设置过的，DIER 中只有 CC1DE，
+// 因此启动与停止时可以直接写入整个寄存器，修改 setup_pwm 时要记得同步修改这里
+const TIM3_CR1_USED: u32 = CR1_CEN.mask() | CR1_DIR.mask();
+const TIM3_START: Batch = Batch::new().set(CR1_DIR, 0).enable(CR1_CEN);
+const TIM3_STOP: Batch = Batch::new().set(CR1_DIR, 0).disable(CR1_CEN);
+const TIM3_DMA_ON: Batch = Batch::new().enable(DIER_CC1DE);
+const TIM3_DMA_OFF: Batch = Batch::new().disable(DIER_CC1DE);
+const _: () = assert!(TIM3_START.covers(TIM3_CR1_USED) && TIM3_STOP.covers(TIM3_CR1_USED));
+
+// DMA Stream 的 CR 中还有通道、突发、FIFO 等大量设置，只能读-改-写
+const DMA_ENABLE: Batch = Batch::new().enable(DMA_SXCR_EN);
+
 // 颜色表，具体的数值写在代码末尾
 // 可以注意到这里颜色表本身是 static，而且它是一个数组切片，且其中的元素也是多个数据切片
 // 这样有两个好处，第一个是，相较于使用 const + 数组的组合，我们可以节省大量的存储空间
@@ -61,9 +109,11 @@ fn main() -> ! {
 
     rprintln!("\nProgram Start");
 
-    let cp = pac::CorePeripherals::take().unwrap();
+    let mut cp = pac::CorePeripherals::take().unwrap();
     let dp = pac::Peripherals::take().unwrap();
 
+    cycle_stats::enable(&mut cp.DCB, &mut cp.DWT);
+
     setup_rcc(&dp);
     setup_low_power(&cp, &dp);
     setup_gpio(&dp);
@@ -71,6 +121,11 @@ fn main() -> ! {
     setup_pwm(&dp);
     setup_delay(&dp);
 
+    // 到这里所有外设都已经 claim 过了，Sleep 模式下只给它们保留时钟
+    // 之后 release 了 TIM3 也不影响它在 LPENR 中的位，下次 claim 之后 Sleep 模式下依旧有时钟
+    clock_gate::apply_sleep_gating(clock_gate::KEEP_FOR_RTT);
+    clock_gate::report();
+
     cortex_m::interrupt::free(|cs| {
         let mut dp_mut = G_DP.borrow(cs).borrow_mut();
         dp_mut.replace(dp);
@@ -126,8 +181,7 @@ fn setup_low_power(cp: &pac::CorePeripherals, dp: &pac::Peripherals) {
 
 // 开启 GPIO PB4 的 alternate 输出，让其输出 TIM3 的 CC1 的输出
 fn setup_gpio(dp: &pac::Peripherals) {
-    let rcc = &dp.RCC;
-    rcc.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    clock_gate::claim(gates::GPIOB);
 
     let gpiob = &dp.GPIOB;
     gpiob.ospeedr.modify(|_, w| w.ospeedr4().medium_speed());
@@ -138,9 +192,7 @@ fn setup_gpio(dp: &pac::Peripherals) {
 }
 
 fn setup_dma(dp: &pac::Peripherals) {
-    let rcc = &dp.RCC;
-
-    rcc.ahb1enr.modify(|_, w| w.dma1en().enabled());
+    clock_gate::claim(gates::DMA1);
 
     let pwm_dma = &dp.DMA1;
 
@@ -205,9 +257,7 @@ fn setup_dma(dp: &pac::Peripherals) {
 }
 
 fn setup_pwm(dp: &pac::Peripherals) {
-    let rcc = &dp.RCC;
-
-    rcc.apb1enr.modify(|_, w| w.tim3en().enabled());
+    clock_gate::claim(gates::TIM3);
 
     let pwm_tim = &dp.TIM3;
 
@@ -251,9 +301,7 @@ fn setup_pwm(dp: &pac::Peripherals) {
 // 第二个是我们需要让灯的某个状态保持一段时间，让我们可以观察到灯的变化
 // 不过就目前我们的设置来说，第一个时间可以包含在第二个时间里，因此这里我们直接使用单一的 TIM 完成两个延时功能
 fn setup_delay(dp: &pac::Peripherals) {
-    let rcc = &dp.RCC;
-
-    rcc.apb1enr.modify(|_, w| w.tim2en().enabled());
+    clock_gate::claim(gates::TIM2);
 
     let delay_tim = &dp.TIM2;
 
@@ -272,6 +320,8 @@ fn setup_delay(dp: &pac::Peripherals) {
 }
 
 // 开启三大外设
+// 三者是依次开启的，相互之间差了几个总线周期，这里只需要大致同时即可
+// 若需要几个 TIM 在同一个时钟沿启动（比如多路 PWM 的相位要对齐），见 utils::sync_start
 fn enable(dp: &pac::Peripherals) {
     dp.DMA1.st[4].cr.modify(|_, w| w.en().enabled());
     dp.TIM2.cr1.modify(|_, w| w.cen().enabled());
@@ -318,38 +368,10 @@ fn DMA1_STREAM4() {
 
                 // 清理工作，三大外设的关闭和重置
 
-                let rcc = &dp.RCC;
-
-                rcc.apb1enr.modify(|_, w| {
-                    w.tim2en().disabled();
-                    w.tim3en().disabled();
-                    w
-                });
-                rcc.apb1rstr.modify(|_, w| {
-                    w.tim2rst().reset();
-                    w.tim3rst().reset();
-                    w
-                });
-                rcc.apb1rstr.modify(|_, w| {
-                    w.tim2rst().clear_bit();
-                    w.tim3rst().clear_bit();
-                    w
-                });
-                rcc.ahb1enr.modify(|_, w| {
-                    w.gpioben().disabled();
-                    w.dma1en().disabled();
-                    w
-                });
-                rcc.ahb1rstr.modify(|_, w| {
-                    w.gpiobrst().reset();
-                    w.dma1rst().reset();
-                    w
-                });
-                rcc.ahb1rstr.modify(|_, w| {
-                    w.gpiobrst().clear_bit();
-                    w.dma1rst().clear_bit();
-                    w
-                });
+                for gate in [gates::TIM2, gates::TIM3, gates::GPIOB, gates::DMA1] {
+                    clock_gate::release(gate);
+                    clock_gate::reset(gate);
+                }
 
                 panic!("Stop here");
             }
@@ -359,19 +381,23 @@ fn DMA1_STREAM4() {
         // 主要就是清理半传输完成和全传输完成标识位，并打印一下信息
         // 注意，清理两个标识位非常重要，如果不清理，则下次 DMA 传输是无法开始的
         if hisr.tcif4().is_complete() {
+            let since = DWT::cycle_count();
+
             hifcr.write(|w| {
                 w.chtif4().clear();
                 w.ctcif4().clear();
                 w
             });
-            rprint!(
-                "\x1b[2K\rDMA1 STREAM4 Transfer Completed: {}",
-                G_CNT.fetch_add(1, Ordering::AcqRel)
-            );
 
             // 注意，这里我们必须关闭 TIM 的 CC 的 DMA 请求
             // 如果我们不关闭，DMA 会在 Stream 关闭之后依旧收到 DMA 请求，从而导致 FIFO 错误
-            dp.TIM3.dier.modify(|_, w| w.cc1de().disabled());
+            if BATCHED {
+                dp.TIM3
+                    .dier
+                    .write(|w| unsafe { w.bits(TIM3_DMA_OFF.value()) });
+            } else {
+                dp.TIM3.dier.modify(|_, w| w.cc1de().disabled());
+            }
 
             // 关闭计数，并重置 CNT 寄存器，因为我们不能确定执行到这里的时候，CNT 寄存器的状态
             // 因此我们要停止 TIM3 的计数，并清零 CNT 寄存器，让下一次启动 TIM3/PWM 的时候是一个初始化的状态
@@ -380,14 +406,28 @@ fn DMA1_STREAM4() {
             // 这里其实有一个小小的问题，那就是我们在停止计数器的时候，是无法确定最后一个从 DMA 读取到的 CCR 数据，是否已经完成了输出
             // 不过这里有一点很巧妙，那就是我们必然知道，倒数第二个波形必然是输出完成了的，因此当前 CC1 必然是处于底电平输出的，
             // 因此我们即便关闭了计数器，也不会导致 CC1 输出的电平变化，因此这里我们可以安全地关闭计数器功能
-            dp.TIM3.cr1.modify(|_, w| w.cen().disabled());
+            if BATCHED {
+                dp.TIM3.cr1.write(|w| unsafe { w.bits(TIM3_STOP.value()) });
+            } else {
+                dp.TIM3.cr1.modify(|_, w| w.cen().disabled());
+            }
             dp.TIM3.cnt.reset();
 
             // 为了节省一些能量，我们进一步关闭了 TIM3 外设
-            dp.RCC.apb1enr.modify(|_, w| w.tim3en().disabled());
+            clock_gate::release(gates::TIM3);
+
+            // DMA1 不需要 release，除了 Stream4 之外，RTT 在 Sleep 模式下也要用到它（见 s17c01）
 
-            // 如果你需要 RTT，则不要关掉 DMA
-            // dp.RCC.ahb1enr.modify(|_, w| w.dma1en().disabled());
+            // 打印不计入中断的耗时
+            DMA_ISR_CYCLES.record(since);
+
+            let cnt = G_CNT.fetch_add(1, Ordering::AcqRel);
+            rprint!("\x1b[2K\rDMA1 STREAM4 Transfer Completed: {}", cnt);
+            if cnt.is_multiple_of(REPORT_EVERY) {
+                rprintln!();
+                DMA_ISR_CYCLES.report();
+                TIM2_ISR_CYCLES.report();
+            }
         }
     })
 }
@@ -398,12 +438,18 @@ fn TIM2() {
         let dp_ref = G_DP.borrow(cs).borrow();
         let dp = dp_ref.as_ref().unwrap();
 
-        dp.TIM2.sr.modify(|_, w| w.uif().clear());
+        let since = DWT::cycle_count();
 
-        let pwm_dma = &dp.DMA1;
+        // UIF 是 rc_w0，直接写入只清除 UIF，读-改-写则可能清除掉读与写之间新出现的标识
+        if BATCHED {
+            dp.TIM2
+                .sr
+                .write(|w| unsafe { w.bits(reg_batch::clear_rc_w0(SR_UIF.mask())) });
+        } else {
+            dp.TIM2.sr.modify(|_, w| w.uif().clear());
+        }
 
-        // 如果你需要 RTT，从而没有关掉 DMA，则这里也不需要开启它
-        // dp.RCC.ahb1enr.modify(|_, w| w.dma1en().enabled());
+        let pwm_dma = &dp.DMA1;
 
         let pwm_st = &pwm_dma.st[4];
 
@@ -427,13 +473,28 @@ fn TIM2() {
 
         // 此处无需清理 DMA 的 ISR，因为它已经在 DMA 的中断中被清理了
 
-        pwm_st.cr.modify(|_, w| w.en().enabled());
+        if BATCHED {
+            pwm_st
+                .cr
+                .modify(|r, w| unsafe { w.bits(DMA_ENABLE.apply(r.bits())) });
+        } else {
+            pwm_st.cr.modify(|_, w| w.en().enabled());
+        }
 
         // 由于我们为了节省能量，每次数据输出完成，我们都关闭了 TIM3，
         // 因此这里我们还需要开启 TIM 的 DMA 请求和 TIM 时钟
-        dp.RCC.apb1enr.modify(|_, w| w.tim3en().enabled());
-        dp.TIM3.dier.modify(|_, w| w.cc1de().enabled());
-        dp.TIM3.cr1.modify(|_, w| w.cen().enabled());
+        clock_gate::claim(gates::TIM3);
+        if BATCHED {
+            dp.TIM3
+                .dier
+                .write(|w| unsafe { w.bits(TIM3_DMA_ON.value()) });
+            dp.TIM3.cr1.write(|w| unsafe { w.bits(TIM3_START.value()) });
+        } else {
+            dp.TIM3.dier.modify(|_, w| w.cc1de().enabled());
+            dp.TIM3.cr1.modify(|_, w| w.cen().enabled());
+        }
+
+        TIM2_ISR_CYCLES.record(since);
     });
 }
 
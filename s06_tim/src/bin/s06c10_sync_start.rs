@@ -0,0 +1,106 @@
+//! 同时启动三个 TIM，输出相位差固定为 120° 的三路 PWM
+//!
+//! 原理见 utils::sync_start
+//!
+//! TIM2 为主 TIM，TIM3 与 TIM4 都通过 ITR1 连接到 TIM2，三者都是 1 kHz、占空比 50% 的 PWM
+//! TIM3 与 TIM4 的 CNT 在启动前分别预置为 1/3 与 2/3 个周期，于是三路输出依次相差 120°，可以当作三相逆变的相位参考
+//!
+//! 每隔 2 秒停止并重新启动一次，用逻辑分析仪观察，每次重新启动之后，三路输出之间的相位关系都完全一样；
+//! 把 main 中的 group.start() 换成依次置位三个 TIM 的 CEN，就能看到三路输出之间多出了几个总线周期的偏差
+//!
+//! 接线图：
+//!
+//! PA5 TIM2_CH1 -> 逻辑分析仪 CH0（或者 RGB 灯的 R）
+//! PA6 TIM3_CH1 -> 逻辑分析仪 CH1（或者 RGB 灯的 G）
+//! PB6 TIM4_CH1 -> 逻辑分析仪 CH2（或者 RGB 灯的 B）
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::clock_gate::{self, gates};
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::sync_start::{self, Channel, SyncGroup, Tim};
+
+chip_caps::require!(TIM8);
+
+// 使用 HSE，APB1 不分频，TIM 的时钟为 12 MHz
+const TIM_CLK_HZ: u32 = 12_000_000;
+
+// 12 MHz / 12 = 1 MHz 计数，1000 个计数一个周期，即 1 kHz
+const PSC: u16 = (TIM_CLK_HZ / 1_000_000 - 1) as u16;
+const PERIOD: u32 = 1000;
+
+const RESTART_MS: u32 = 2000;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    setup_gpio(&dp);
+
+    let mut group = SyncGroup::new(Tim::Tim2);
+    group.add(Tim::Tim3).unwrap();
+    group.add(Tim::Tim4).unwrap();
+
+    for (index, tim) in [Tim::Tim2, Tim::Tim3, Tim::Tim4].into_iter().enumerate() {
+        sync_start::set_period(tim, PSC, PERIOD - 1).unwrap();
+        sync_start::set_duty(tim, Channel::Ch1, PERIOD / 2).unwrap();
+        // 向上计数，初始值越大越领先
+        group.set_phase(tim, PERIOD * index as u32 / 3).unwrap();
+    }
+
+    loop {
+        group.prepare();
+        group.start();
+
+        // 依次读取，相互之间相差的只是读取本身花费的时间
+        for (tim, count) in group.counters().into_iter().flatten() {
+            rprintln!("{:?} CNT {}", tim, count);
+        }
+
+        cortex_m::asm::delay(TIM_CLK_HZ / 1000 * RESTART_MS);
+        group.stop();
+        rprintln!("restart");
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// PA5 为 AF01，PA6 与 PB6 为 AF02
+fn setup_gpio(dp: &pac::Peripherals) {
+    clock_gate::claim(gates::GPIOA);
+    clock_gate::claim(gates::GPIOB);
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| {
+        w.afrl5().af1();
+        w.afrl6().af2();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder5().alternate();
+        w.moder6().alternate();
+        w
+    });
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| w.afrl6().af2());
+    gpiob.moder.modify(|_, w| w.moder6().alternate());
+}
@@ -0,0 +1,147 @@
+//! 用 GPIO 翻转驱动 ws2812
+//!
+//! s06c100 与 s06c06 都要求数据线接在某个 TIM 通道上，而且这个 TIM 的 Update 事件要有空闲的 DMA Stream
+//! 引脚已经被占用、或者板子上只剩下普通 GPIO 时，可以改用 utils::ws2812_bitbang，原理与限制见其说明
+//!
+//! 填充颜色的部分使用与 TIM + DMA 相同的 utils::ws2812::FrameBuffer，只有发送的后端不同
+//!
+//! 这里把 SYSCLK 提高到 96 MHz，一个 bit 为 120 个周期；SysTick 以 1 kHz 一直在中断，
+//! 发送期间它最多被推迟一颗灯的时间（约 30 us），不会丢失；彩虹每转一圈，输出一次 SysTick 实际的中断次数，
+//! 以及按 CYCCNT 经过的时间算出的应有次数，两者应该一致
+//!
+//! 接线图：
+//!
+//! GPIO PB12 -> 灯带的 DIN（PB12 只是举例，任意 GPIO 都可以）
+//!
+//! 灯带 16 颗 ws2812，VCC 接入 3.3V 或 5V 电源，GND 接地
+
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::peripheral::{syst::SystClkSource, DWT};
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    port::Port,
+    ws2812::{FrameBuffer, Rgb, Ws2812Out},
+    ws2812_bitbang::{BitBang, BitBangError},
+};
+
+const SYSCLK_HZ: u32 = 96_000_000;
+const LEDS: usize = 16;
+const FRAME_MS: u32 = 50;
+
+static G_TICKS: AtomicU32 = AtomicU32::new(0);
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut cp = pac::CorePeripherals::take().unwrap();
+
+    setup_rcc(&dp);
+
+    let mut strip = BitBang::new(&mut cp, Port::B, 12, SYSCLK_HZ).unwrap();
+
+    cp.SYST.set_clock_source(SystClkSource::Core);
+    cp.SYST.set_reload(SYSCLK_HZ / 1000 - 1);
+    cp.SYST.clear_current();
+    cp.SYST.enable_interrupt();
+    cp.SYST.enable_counter();
+
+    let mut frame = FrameBuffer::<LEDS>::new();
+    frame.set_brightness(32);
+
+    let mut step = 0usize;
+    let mut last = DWT::cycle_count();
+    loop {
+        for led in 0..LEDS {
+            frame.set(led, wheel(((led * 256 / LEDS + step) % 256) as u8));
+        }
+
+        match strip.show(&frame) {
+            Ok(()) => {}
+            // 下一帧会整帧重发，这里只记录一下
+            Err(BitBangError::Interrupted { led }) => rprintln!("interrupted before LED {}", led),
+            Err(e) => panic!("{:?}", e),
+        }
+
+        step = (step + 4) % 256;
+        if step == 0 {
+            let now = DWT::cycle_count();
+            let expected = now.wrapping_sub(last) / (SYSCLK_HZ / 1000);
+            last = now;
+            rprintln!(
+                "SysTick {} / {}",
+                G_TICKS.swap(0, Ordering::Relaxed),
+                expected
+            );
+        }
+
+        cortex_m::asm::delay(SYSCLK_HZ / 1000 * FRAME_MS);
+    }
+}
+
+#[cortex_m_rt::exception]
+fn SysTick() {
+    G_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+// 色轮，0 ~ 255 依次经过红、绿、蓝
+fn wheel(pos: u8) -> Rgb {
+    let pos = pos as u16 * 3;
+    match pos {
+        0..=255 => Rgb::new(255 - pos as u8, pos as u8, 0),
+        256..=511 => Rgb::new(0, 255 - (pos - 256) as u8, (pos - 256) as u8),
+        _ => Rgb::new((pos - 512) as u8, 0, 255 - (pos - 512) as u8),
+    }
+}
+
+// HSE 12 MHz / 6 * 96 / 2 = 96 MHz
+fn setup_rcc(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+
+    rcc.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(96);
+        }
+        w.pllp().div2();
+        w
+    });
+
+    // HCLK 超过 84 MHz，需要 Scale 1 mode
+    rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| unsafe { w.vos().bits(0b11) });
+
+    // 90 MHz < HCLK <= 100 MHz，FLASH 读取需要等待 3 个周期
+    dp.FLASH.acr.modify(|_, w| {
+        w.latency().ws3();
+        w.dcen().enabled();
+        w.icen().enabled();
+        w.prften().enabled();
+        w
+    });
+
+    // APB1 最高 50 MHz
+    rcc.cfgr.modify(|_, w| w.ppre1().div2());
+
+    rcc.cr.modify(|_, w| w.pllon().on());
+    while dp.PWR.csr.read().vosrdy().bit_is_clear() {}
+    while rcc.cr.read().pllrdy().is_not_ready() {}
+
+    rcc.cfgr.modify(|_, w| w.sw().pll());
+    while !rcc.cfgr.read().sws().is_pll() {}
+}
@@ -0,0 +1,97 @@
+//! 在 8 个普通 GPIO 上输出软件 PWM，做一个流水呼吸灯
+//!
+//! 原理见 utils::soft_pwm，所有通道都由 TIM7 的一个中断驱动
+//!
+//! 8 路都是 200 Hz，各自的亮度按三角波变化，相邻两路相差 1/8 个周期，看起来就是一个来回流动的光带；
+//! 各路的拉高时刻通过 spread_phases 均匀错开，用逻辑分析仪观察，同一时刻最多只有一两路在翻转，
+//! 把 spread_phases 那一行去掉，就能看到所有通道都在周期开头同时拉高
+//!
+//! 启动后输出一次每个周期的时间片个数，也就是每个周期 TIM7 的中断次数
+//!
+//! 接线图：
+//!
+//! PC0 ~ PC7 -> 各接一个 LED 正极 -- LED 负极 -> 220 欧电阻 -> 接地
+//! （PC0 ~ PC7 只是举例，任意 GPIO 都可以，也可以分散在不同的端口上）
+
+#![no_std]
+#![no_main]
+
+use cortex_m::peripheral::NVIC;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+use utils::port::Port;
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::soft_pwm::{self, SoftPwm};
+
+chip_caps::require!(TIM6_TIM7);
+
+const PWM_HZ: u32 = 200;
+const LEDS: u8 = 8;
+// 三角波的一个周期有多少步，每步 20 ms
+const WAVE_STEPS: u32 = 100;
+const STEP_MS: u32 = 20;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+
+    let mut pwm = SoftPwm::new(&dp, PWM_HZ).unwrap();
+    for pin in 0..LEDS {
+        pwm.add(Port::C, pin).unwrap();
+    }
+    pwm.spread_phases();
+    pwm.start();
+
+    unsafe { NVIC::unmask(interrupt::TIM7) };
+
+    let mut step = 0;
+    let mut reported = false;
+    loop {
+        for ch in 0..LEDS as u32 {
+            let pos = (step + ch * WAVE_STEPS / LEDS as u32) % WAVE_STEPS;
+            // 0 ~ 1 ~ 0 的三角波，平方之后亮度的变化看起来更均匀
+            let tri = pos.min(WAVE_STEPS - pos);
+            let level = tri as f32 / (WAVE_STEPS / 2) as f32;
+            pwm.set_duty(ch as usize, 100.0 * level * level).unwrap();
+        }
+        pwm.apply();
+
+        if !reported {
+            rprintln!(
+                "{} channels @ {} Hz, {} slices per period",
+                pwm.count(),
+                pwm.freq_hz(),
+                pwm.slices()
+            );
+            reported = true;
+        }
+
+        step = (step + 1) % WAVE_STEPS;
+        cortex_m::asm::delay(soft_pwm::TIM_CLK_HZ / 1000 * STEP_MS);
+    }
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+#[interrupt]
+fn TIM7() {
+    soft_pwm::on_tim7();
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
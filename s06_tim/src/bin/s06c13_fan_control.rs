@@ -0,0 +1,264 @@
+//! 4 线 PC 风扇的闭环转速控制
+//!
+//! 把输入捕获与 PWM 组合到一起：TIM5 测量 TACH 的脉冲周期，TIM3 输出 25 kHz 的 PWM，
+//! 主循环每 100 ms 读取一次转速，交给 FanController 计算新的占空比，原理见 utils::fan
+//!
+//! 通过串口输入命令（以回车结束），命令行与 s06c08 相同，只是改为了非阻塞读取，不影响控制循环：
+//!
+//! rpm <RPM>         闭环控制，保持指定的转速，0 为停止
+//! duty <占空比%>     开环输出固定的占空比，用来找出风扇的最低转速与最高转速
+//! pi <kp> <ki>      修改 PI 参数，kp 的单位为 %/RPM，ki 的单位为 %/(RPM·s)
+//! reset             清除 Fault
+//! status            输出当前的状态
+//!
+//! 每秒通过 RTT 输出一次目标转速、实际转速、占空比与状态，可以用来观察 PI 的响应；
+//! 用手指轻轻按住扇叶（小心！）就能看到堵转检测与重试的过程
+//!
+//! 接线图：
+//!
+//! STM32 <-> 4 线风扇（12V 供电，两者共地）
+//!   PA6 <-> PWM（蓝线，风扇内部上拉，PA6 为开漏输出且可承受 5V）
+//!   PA0 <-> TACH（绿线，开漏输出，外接 10k 上拉到 3.3V）
+//!   GND <-> GND（黑线）
+//!
+//! USB-TTL 模块，115200 8N1
+//! PA9  (USART1 Tx) <-> Rx
+//! PA10 (USART1 Rx) <-> Tx
+//! GND              <-> GND
+
+#![no_std]
+#![no_main]
+
+use core::{fmt::Write, str::FromStr};
+
+use cortex_m::peripheral::NVIC;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+use utils::fan::{self, FanController, TachReading};
+
+const LINE_MAX: usize = 40;
+
+const CONTROL_US: u32 = 100_000;
+const REPORT_US: u32 = 1_000_000;
+
+// 一个 12 cm 风扇大约 1500 RPM 对应 100%，即 15 RPM/%
+const KP: f32 = 0.01;
+const KI: f32 = 0.02;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    setup_usart1(&dp);
+    fan::setup(&dp);
+
+    unsafe { NVIC::unmask(interrupt::TIM5) };
+
+    let mut console = Console {
+        usart: &dp.USART1,
+        line: [0; LINE_MAX],
+        len: 0,
+    };
+    let mut controller = FanController::new(KP, KI, fan::now_us());
+    let mut last_reading: Option<TachReading> = None;
+
+    writeln!(console, "fan controller ready\r").ok();
+    write!(console, "> ").ok();
+
+    let mut last_control = fan::now_us();
+    let mut last_report = last_control;
+    loop {
+        if let Some(len) = console.poll_line() {
+            let mut line = [0u8; LINE_MAX];
+            line[..len].copy_from_slice(&console.line[..len]);
+            let command = core::str::from_utf8(&line[..len]).unwrap_or("");
+            match execute(&mut controller, command) {
+                Ok(true) => report(&mut console, &controller, last_reading),
+                Ok(false) => {
+                    let _ = writeln!(console, "OK\r");
+                }
+                Err(()) => {
+                    let _ = writeln!(console, "ERR syntax\r");
+                }
+            }
+            write!(console, "> ").ok();
+        }
+
+        let now = fan::now_us();
+        if now.wrapping_sub(last_control) >= CONTROL_US {
+            last_control = now;
+            let reading = fan::read_rpm();
+            if reading.is_some() {
+                last_reading = reading;
+            }
+            fan::set_duty(controller.update(now, reading));
+        }
+
+        if now.wrapping_sub(last_report) >= REPORT_US {
+            last_report = now;
+            rprintln!(
+                "target {:.0} rpm {:.0} duty {:.1}% {:?}",
+                controller.target(),
+                controller.rpm(),
+                controller.duty(),
+                controller.state()
+            );
+        }
+    }
+}
+
+// 执行一行命令，需要输出状态时返回 true
+fn execute(controller: &mut FanController, command: &str) -> Result<bool, ()> {
+    let mut words = command.split_ascii_whitespace();
+    let name = match words.next() {
+        Some(name) => name,
+        None => return Ok(false),
+    };
+
+    match name {
+        "rpm" => controller.set_target(arg(&mut words)?),
+        "duty" => controller.set_manual(Some(arg(&mut words)?)),
+        "pi" => {
+            let kp = arg(&mut words)?;
+            let ki = arg(&mut words)?;
+            controller.set_gains(kp, ki);
+        }
+        "reset" => controller.reset(),
+        "status" => return Ok(true),
+        _ => return Err(()),
+    }
+    Ok(false)
+}
+
+fn report(console: &mut Console, controller: &FanController, reading: Option<TachReading>) {
+    match controller.manual() {
+        Some(duty) => writeln!(console, "mode manual {:.1}%\r", duty).ok(),
+        None => writeln!(console, "mode closed-loop {:.0} rpm\r", controller.target()).ok(),
+    };
+    let (kp, ki) = controller.gains();
+    writeln!(
+        console,
+        "rpm {:.0}, duty {:.1}%, state {:?}, retries {}/{}, kp {}, ki {}\r",
+        controller.rpm(),
+        controller.duty(),
+        controller.state(),
+        controller.retries(),
+        fan::MAX_RETRIES,
+        kp,
+        ki
+    )
+    .ok();
+    if let Some(reading) = reading {
+        writeln!(console, "glitches {}\r", reading.glitches).ok();
+    }
+}
+
+// 取出下一个参数，并解析为 T
+fn arg<'a, T: FromStr>(words: &mut impl Iterator<Item = &'a str>) -> Result<T, ()> {
+    words.next().and_then(|word| word.parse().ok()).ok_or(())
+}
+
+#[interrupt]
+fn TIM5() {
+    fan::on_tach_capture();
+}
+
+// 通过 USART1 实现的简单命令行，与 s06c08 不同的是，这里不会阻塞等待输入
+struct Console<'a> {
+    usart: &'a pac::USART1,
+    line: [u8; LINE_MAX],
+    len: usize,
+}
+
+impl Console<'_> {
+    // 有字节就读取并回显，读到一整行时返回它的长度，行内容在 self.line 中
+    fn poll_line(&mut self) -> Option<usize> {
+        if self.usart.sr.read().rxne().bit_is_clear() {
+            return None;
+        }
+        let byte = self.usart.dr.read().dr().bits() as u8;
+        match byte {
+            b'\r' | b'\n' => {
+                self.write_str("\r\n").ok();
+                let len = self.len;
+                self.len = 0;
+                return Some(len);
+            }
+            _ if self.len < LINE_MAX => {
+                self.line[self.len] = byte;
+                self.len += 1;
+                self.write_str(core::str::from_utf8(&[byte]).unwrap_or("?"))
+                    .ok();
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+impl Write for Console<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            while self.usart.sr.read().txe().bit_is_clear() {}
+            self.usart.dr.write(|w| w.dr().bits(byte as u16));
+        }
+        Ok(())
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// USART1 收发，参数为 115200 8N1
+fn setup_usart1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| {
+        w.afrh9().af7();
+        w.afrh10().af7();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr9().pull_up();
+        w.pupdr10().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let serial1 = &dp.USART1;
+
+    serial1.cr1.modify(|_, w| w.ue().enabled());
+
+    // 12 MHz / (16 * 115200) = 6.51，整数部分为 6，小数部分为 0.51 * 16 ≈ 8
+    serial1.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    serial1.cr1.modify(|_, w| {
+        w.te().enabled();
+        w.re().enabled();
+        w
+    });
+}
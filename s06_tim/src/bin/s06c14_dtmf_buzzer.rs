@@ -0,0 +1,144 @@
+//! 用无源蜂鸣器播放 DTMF 拨号音与双音提示音
+//!
+//! 两个频率在同一个 PWM 通道上混合，原理见 utils::tone_synth
+//!
+//! 主循环先“拨”一个号码，再播放一段由双音、单音与静音组成的提示音，然后等待 2 秒重新开始；
+//! 音调放入队列之后就由 TIM1 的中断播放，主循环只是每隔 200 ms 输出一次队列中剩余的段数
+//!
+//! 拨号音可以用手机上的 DTMF 解码 App 验证，每个按键都应该被正确识别
+//!
+//! 接线图：
+//!
+//! 无源蜂鸣器（电磁式，不带振荡源）
+//! PA8 -> 1k 电阻 -> NPN 三极管（如 S8050）的基极
+//! 蜂鸣器接在 3.3V 与三极管的集电极之间，三极管的发射极接 GND，蜂鸣器两端反向并联一个二极管
+//!
+//! 或者接一个小喇叭，音质会好很多
+//! PA8 -> 1k 电阻 -> 功放模块（如 PAM8403）的输入，输入端对地接 47nF 电容，构成约 3.4 kHz 的 RC 低通
+
+#![no_std]
+#![no_main]
+
+use cortex_m::peripheral::NVIC;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+use utils::tone_synth::{self, Tone, ToneError};
+
+const SYSCLK_HZ: u32 = 96_000_000;
+
+const NUMBER: &str = "0123-456789";
+const KEY_ON_MS: u32 = 100;
+const KEY_OFF_MS: u32 = 60;
+
+// 上升的“叮咚”之后接一段两声的双音告警
+const ALERT: [Tone; 8] = [
+    Tone::single(1319, 150),
+    Tone::single(1047, 300),
+    Tone::rest(300),
+    Tone::pair(880, 1175, 120),
+    Tone::rest(80),
+    Tone::pair(880, 1175, 120),
+    Tone::rest(300),
+    Tone::pair(523, 784, 600),
+];
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_rcc(&dp);
+    tone_synth::setup(&dp);
+
+    unsafe { NVIC::unmask(interrupt::TIM1_UP_TIM10) };
+
+    rprintln!("sample rate {} Hz", tone_synth::SAMPLE_RATE_HZ);
+
+    loop {
+        rprintln!("dialing {}", NUMBER);
+        tone_synth::queue_dtmf(NUMBER, KEY_ON_MS, KEY_OFF_MS).unwrap();
+        wait_idle();
+
+        cortex_m::asm::delay(SYSCLK_HZ / 2);
+
+        rprintln!("alert");
+        for tone in ALERT {
+            match tone_synth::queue(tone) {
+                Ok(()) => {}
+                // 队列满了就等一等再放入
+                Err(ToneError::QueueFull) => {
+                    while tone_synth::pending() == tone_synth::QUEUE_LEN {}
+                    tone_synth::queue(tone).unwrap();
+                }
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+        wait_idle();
+
+        cortex_m::asm::delay(SYSCLK_HZ * 2);
+    }
+}
+
+// 等待队列播放完毕，期间输出剩余的段数
+fn wait_idle() {
+    while !tone_synth::is_idle() {
+        rprintln!("pending {}", tone_synth::pending());
+        cortex_m::asm::delay(SYSCLK_HZ / 5);
+    }
+}
+
+#[interrupt]
+fn TIM1_UP_TIM10() {
+    tone_synth::on_tim1_update();
+}
+
+// HSE 12 MHz / 6 * 96 / 2 = 96 MHz，APB2 不分频，TIM1 的时钟也是 96 MHz
+fn setup_rcc(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+
+    rcc.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(96);
+        }
+        w.pllp().div2();
+        w
+    });
+
+    // HCLK 超过 84 MHz，需要 Scale 1 mode
+    rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| unsafe { w.vos().bits(0b11) });
+
+    // 90 MHz < HCLK <= 100 MHz，FLASH 读取需要等待 3 个周期
+    dp.FLASH.acr.modify(|_, w| {
+        w.latency().ws3();
+        w.dcen().enabled();
+        w.icen().enabled();
+        w.prften().enabled();
+        w
+    });
+
+    // APB1 最高 50 MHz，APB2 保持不分频
+    rcc.cfgr.modify(|_, w| {
+        w.ppre1().div2();
+        w.ppre2().div1();
+        w
+    });
+
+    rcc.cr.modify(|_, w| w.pllon().on());
+    while dp.PWR.csr.read().vosrdy().bit_is_clear() {}
+    while rcc.cr.read().pllrdy().is_not_ready() {}
+
+    rcc.cfgr.modify(|_, w| w.sw().pll());
+    while !rcc.cfgr.read().sws().is_pll() {}
+}
@@ -0,0 +1,175 @@
+//! 用 ws2812 灯带显示话筒的音量（VU meter）
+//!
+//! 把 ADC + DMA 的连续采样、检波与 ws2812 组合到一起，流水线的原理见 utils::vu_meter
+//!
+//! 主循环只做轮询，没有任何等待：
+//!
+//! - 每 16 ms 有一帧新的采样，交给 LevelDetector 更新电平
+//! - 每 FRAME_MS 把电平画到灯带上，通过 utils::ws2812_bitbang 发送（16 颗灯约 0.5 ms），
+//!   发送期间 DMA 照常采样，只要比一帧短，就不会丢帧
+//! - 每秒通过 RTT 输出一次 RMS、峰值、丢帧数，以及 LCD1602 自定义字符所用的灯条（用 # 表示满格）
+//!
+//! 对着话筒说话或者拍手，灯条随之跳动，白色的灯是峰值保持；声音大到削波时，最高的一颗灯变为亮红色
+//!
+//! 接线图：
+//!
+//! 话筒模块（MAX4466 / MAX9814 等，输出偏置在 VCC / 2，使用 3.3V 供电）
+//!   OUT -> PA1（ADC1_1）
+//!   VCC -> 3.3V
+//!   GND -> GND
+//!
+//! GPIO PB12 -> 灯带的 DIN，灯带 16 颗 ws2812，VCC 接入 3.3V 或 5V 电源，GND 接地
+
+#![no_std]
+#![no_main]
+
+use cortex_m::peripheral::DWT;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+use utils::{
+    clock_gate::{self, gates},
+    port::Port,
+    vu_meter::{self, AdcStream, LevelDetector, Scale, BUFFER_LEN},
+    ws2812::{FrameBuffer, Ws2812Out},
+    ws2812_bitbang::BitBang,
+};
+
+const SYSCLK_HZ: u32 = 96_000_000;
+// APB1 为 48 MHz，APB1 上 TIM 的时钟自动 x2
+const TIM2_CLK_HZ: u32 = 96_000_000;
+
+const LEDS: usize = 16;
+const LCD_CELLS: usize = 16;
+const FRAME_MS: u32 = 20;
+const REPORT_MS: u32 = 1000;
+
+const SCALE: Scale = Scale { floor_db: -48.0 };
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut cp = pac::CorePeripherals::take().unwrap();
+
+    setup_rcc(&dp);
+    setup_gpio(&dp);
+
+    // APB2 为 96 MHz，ADCCLK 为 96 / 4 = 24 MHz
+    dp.ADC_COMMON.ccr.modify(|_, w| w.adcpre().div4());
+
+    let buffer = cortex_m::singleton!(: [u16; BUFFER_LEN] = [0; BUFFER_LEN]).unwrap();
+    let mut stream = AdcStream::new(&dp, buffer, 1, TIM2_CLK_HZ);
+    let mut detector = LevelDetector::new();
+
+    let mut strip = BitBang::new(&mut cp, Port::B, 12, SYSCLK_HZ).unwrap();
+    let mut frame = FrameBuffer::<LEDS>::new();
+    frame.set_brightness(32);
+
+    let cycles_per_ms = SYSCLK_HZ / 1000;
+    let mut last_frame = DWT::cycle_count();
+    let mut last_report = last_frame;
+    let mut clipped = false;
+    loop {
+        if let Some(samples) = stream.take_frame() {
+            detector.process(samples);
+        }
+        clipped |= vu_meter::take_clipped();
+
+        let now = DWT::cycle_count();
+        if now.wrapping_sub(last_frame) >= FRAME_MS * cycles_per_ms {
+            last_frame = now;
+            vu_meter::render_strip(&mut frame, &SCALE, &detector.levels(), clipped);
+            // 被中断打断的话，下一次整帧重发即可
+            strip.show(&frame).ok();
+            clipped = false;
+        }
+
+        if now.wrapping_sub(last_report) >= REPORT_MS * cycles_per_ms {
+            last_report = now;
+            let levels = detector.levels();
+
+            let mut cells = [b' '; LCD_CELLS];
+            vu_meter::lcd_bar(&SCALE, &levels, &mut cells);
+            // 在 RTT 上把 LCD 的字符画出来：满格为 #，部分点亮的格为点亮的列数
+            for cell in cells.iter_mut() {
+                *cell = match *cell {
+                    4 => b'#',
+                    n @ 0..=3 => b'1' + n,
+                    other => other,
+                };
+            }
+
+            rprintln!(
+                "rms {:.1} dB, peak {:.1} dB, hold {:.1} dB, dropped {} |{}|",
+                levels.rms_db,
+                levels.peak_db,
+                levels.hold_db,
+                stream.dropped(),
+                core::str::from_utf8(&cells).unwrap_or("")
+            );
+        }
+    }
+}
+
+#[interrupt]
+fn DMA2_STREAM0() {
+    vu_meter::on_dma2_stream0();
+}
+
+#[interrupt]
+fn ADC() {
+    vu_meter::on_adc();
+}
+
+// HSE 12 MHz / 6 * 96 / 2 = 96 MHz
+fn setup_rcc(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+
+    rcc.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(96);
+        }
+        w.pllp().div2();
+        w
+    });
+
+    // HCLK 超过 84 MHz，需要 Scale 1 mode
+    rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| unsafe { w.vos().bits(0b11) });
+
+    // 90 MHz < HCLK <= 100 MHz，FLASH 读取需要等待 3 个周期
+    dp.FLASH.acr.modify(|_, w| {
+        w.latency().ws3();
+        w.dcen().enabled();
+        w.icen().enabled();
+        w.prften().enabled();
+        w
+    });
+
+    // APB1 最高 50 MHz
+    rcc.cfgr.modify(|_, w| w.ppre1().div2());
+
+    rcc.cr.modify(|_, w| w.pllon().on());
+    while dp.PWR.csr.read().vosrdy().bit_is_clear() {}
+    while rcc.cr.read().pllrdy().is_not_ready() {}
+
+    rcc.cfgr.modify(|_, w| w.sw().pll());
+    while !rcc.cfgr.read().sws().is_pll() {}
+}
+
+// PA1 为 ADC1_1
+fn setup_gpio(dp: &pac::Peripherals) {
+    clock_gate::claim(gates::GPIOA);
+    dp.GPIOA.moder.modify(|_, w| w.moder1().analog());
+}
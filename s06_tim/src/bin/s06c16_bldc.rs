@@ -0,0 +1,199 @@
+//! 带霍尔传感器的无刷电机：六步换相、开环启动与 PID 转速闭环
+//!
+//! 把前面几节的内容组合到一起，原理见 utils::bldc：
+//!
+//! - TIM1 的互补输出、死区、刹车与 COM 事件（s06c09）驱动三相桥
+//! - TIM4 的霍尔传感器接口（三路输入异或）给出换相时刻与转速
+//! - ADC1 的注入通道在 PWM 导通段的中点采样电流，模拟看门狗负责过流保护
+//! - 转速环使用 utils::pid，与 s21 中的 PID 相同
+//!
+//! 操作：
+//!
+//! - 按下 PA0 的按键：Idle 时启动（先 Align、再开环 Ramp，霍尔稳定后切换到闭环），运行中则停止，Fault 时清除 Fault
+//! - PA1 上的电位器设置目标转速，0 ~ MAX_RPM
+//!
+//! 每 500 ms 通过 RTT 输出一次状态、目标转速、实际转速、占空比、电流的 ADC 原始值与霍尔状态
+//!
+//! 第一次上电时请限制电源的电流，先把 MAX_DUTY 设小一些，确认 hall_offset 正确之后再放开
+//!
+//! 接线图：
+//!
+//! PA8  TIM1_CH1   A 相上管
+//! PA7  TIM1_CH1N  A 相下管
+//! PA9  TIM1_CH2   B 相上管
+//! PB0  TIM1_CH2N  B 相下管
+//! PA10 TIM1_CH3   C 相上管
+//! PB1  TIM1_CH3N  C 相下管
+//! PA6  TIM1_BKIN  急停按键或者驱动板的故障输出，低电平有效，使用内部上拉
+//!
+//! PB6  TIM4_CH1   霍尔 H1
+//! PB7  TIM4_CH2   霍尔 H2
+//! PB8  TIM4_CH3   霍尔 H3
+//!
+//! PA4  ADC1_IN4   电流采样放大器的输出
+//! PA1  ADC1_IN1   电位器的中间脚，两端接 3.3V 与 GND
+//! PA0             按键，另一端接 GND，使用内部上拉
+
+#![no_std]
+#![no_main]
+
+use cortex_m::peripheral::NVIC;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::bldc::{self, Config, Direction, State};
+use utils::clock_gate::{self, gates};
+
+// 这里只用到 TIM1，但 utils::advanced_tim 中同时包含 TIM8
+chip_caps::require!(TIM8);
+
+// 使用 HSE，APB1 与 APB2 都不分频，所有 TIM 的时钟都是 12 MHz
+const TIM_CLK_HZ: u32 = 12_000_000;
+const PCLK2_HZ: u32 = 12_000_000;
+
+const POT_CHANNEL: u8 = 1;
+const MAX_RPM: f32 = 3000.0;
+
+const LOOP_MS: u32 = 20;
+const REPORT_MS: u32 = 500;
+
+// 一个常见的 4 对极航模电机，12V 供电
+const CONFIG: Config = Config {
+    pole_pairs: 4,
+    hall_offset: 1,
+    dead_time_ns: 500,
+    max_duty: 0.6,
+    ramp_duty: 0.15,
+    align_ms: 300,
+    ramp_start_hz: 2.0,
+    ramp_end_hz: 20.0,
+    ramp_ms: 1500,
+    // 采样电阻 10 mΩ、放大 20 倍时，3000 约为 12 A
+    current_limit: 3000,
+    kp: 0.0001,
+    ki: 0.0005,
+    kd: 0.0,
+};
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    bldc::setup(&dp, CONFIG, TIM_CLK_HZ, PCLK2_HZ);
+    setup_inputs(&dp);
+
+    unsafe {
+        NVIC::unmask(interrupt::TIM1_UP_TIM10);
+        NVIC::unmask(interrupt::TIM1_BRK_TIM9);
+        NVIC::unmask(interrupt::TIM4);
+        NVIC::unmask(interrupt::ADC);
+    }
+
+    rprintln!("hall {:03b}", bldc::read_hall());
+
+    let mut was_pressed = false;
+    let mut elapsed_ms = 0;
+
+    loop {
+        cortex_m::asm::delay(TIM_CLK_HZ / 1000 * LOOP_MS);
+        elapsed_ms += LOOP_MS;
+
+        let target_rpm = read_pot(&dp) as f32 * MAX_RPM / 4095.0;
+        bldc::set_target(target_rpm);
+
+        // 以 LOOP_MS 为间隔读取按键，顺便完成了消抖
+        let pressed = dp.GPIOA.idr.read().idr0().is_low();
+        if pressed && !was_pressed {
+            match bldc::status().state {
+                State::Idle => bldc::start(Direction::Forward, target_rpm),
+                State::Fault(_) => {
+                    if !bldc::clear_fault() {
+                        rprintln!("break input still active");
+                    }
+                }
+                _ => bldc::stop(),
+            }
+        }
+        was_pressed = pressed;
+
+        if elapsed_ms >= REPORT_MS {
+            elapsed_ms = 0;
+            let status = bldc::status();
+            rprintln!(
+                "{:?} target {} rpm {} duty {}% current {} hall {:03b}",
+                status.state,
+                status.target_rpm as u32,
+                status.rpm as u32,
+                (status.duty * 100.0) as u32,
+                status.current_raw,
+                status.hall
+            );
+        }
+    }
+}
+
+#[interrupt]
+fn TIM1_UP_TIM10() {
+    bldc::on_control_tick();
+}
+
+#[interrupt]
+fn TIM1_BRK_TIM9() {
+    bldc::on_break();
+}
+
+#[interrupt]
+fn TIM4() {
+    bldc::on_hall();
+}
+
+#[interrupt]
+fn ADC() {
+    bldc::on_adc();
+}
+
+// 规则通道由软件启动，与 TIM1 触发的注入通道互不影响
+fn read_pot(dp: &pac::Peripherals) -> u16 {
+    let adc = &dp.ADC1;
+    adc.sqr3.modify(|_, w| unsafe { w.sq1().bits(POT_CHANNEL) });
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+    adc.cr2.modify(|_, w| w.swstart().start());
+    while adc.sr.read().eoc().is_not_complete() {}
+    adc.dr.read().data().bits()
+}
+
+// PA0 按键上拉输入，PA1 电位器模拟输入
+fn setup_inputs(dp: &pac::Peripherals) {
+    clock_gate::claim(gates::GPIOA);
+
+    let gpioa = &dp.GPIOA;
+    gpioa.pupdr.modify(|_, w| w.pupdr0().pull_up());
+    gpioa.moder.modify(|_, w| {
+        w.moder0().input();
+        w.moder1().analog();
+        w
+    });
+
+    // 电位器的输出阻抗较高，采样时间 0b100 为 84 个周期
+    let shift = 3 * POT_CHANNEL as u32;
+    dp.ADC1
+        .smpr2
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << shift) | 0b100 << shift) });
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
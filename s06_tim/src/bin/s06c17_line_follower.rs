@@ -0,0 +1,225 @@
+//! 巡线小车：5 路红外循迹传感器 + PID + TB6612 双路电机驱动
+//!
+//! 电机驱动见 utils::hbridge，PID 与 s21 中的相同
+//!
+//! 控制：
+//!
+//! - 5 个传感器从左到右的权重为 -2 ~ 2，压在黑线上的传感器取平均，就是黑线相对车身中心的位置
+//! - PID 以 0 为目标，输出为转向量 steer（-1 ~ 1），左轮速度 BASE_SPEED - steer，右轮速度 BASE_SPEED + steer
+//! - 所有传感器都看不到黑线时（冲出了弯道），朝最后一次看到黑线的方向原地转，直到重新找到黑线
+//! - 所有传感器都压在黑线上，是终点的横线，刹车并停止，按下 PA0 的按键重新出发
+//!
+//! 电机的加减速由 hbridge 的变化率限制负责，PID 的输出可以直接给到电机，不用担心电流冲击
+//!
+//! 所有引脚都登记在 utils::pin_registry 中，debug 构建下启动时会打印一遍；把 PINS 中的某个引脚改成 PC0 之类已经被占用的引脚，
+//! 启动时就会 panic 并给出两个使用者的名字
+//!
+//! 使用 L298N 的话，把 CHIP 改为 Chip::L298n，STBY 不用接，L298N 的 ENA/ENB 接 PA6/PA7（需要拔掉板上的跳线帽）
+//!
+//! 接线图：
+//!
+//! PA6  TIM3_CH1 -> TB6612 PWMA
+//! PA7  TIM3_CH2 -> TB6612 PWMB
+//! PB12          -> TB6612 AIN1
+//! PB13          -> TB6612 AIN2
+//! PB14          -> TB6612 BIN1
+//! PB15          -> TB6612 BIN2
+//! PB10          -> TB6612 STBY
+//! TB6612 的 AO1/AO2 接左轮电机，BO1/BO2 接右轮电机，VM 接电池，VCC 接 3.3V
+//!
+//! PC0 ~ PC4     <- 循迹传感器模块的 D0，从左到右，压在黑线上时为高电平
+//! PA0           <- 按键，另一端接 GND，使用内部上拉
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    clock_gate::{self, gates},
+    hbridge::{Chip, DualMotor, Motor, MotorPins, Pin},
+    pid::{Pid, PidConfig},
+    pin_registry,
+    port::Port,
+};
+
+// 使用 HSE，APB1 不分频，TIM3 的时钟为 12 MHz
+const TIM_CLK_HZ: u32 = 12_000_000;
+const PWM_HZ: u32 = 20_000;
+
+const CHIP: Chip = Chip::Tb6612 {
+    stby: Pin::new(Port::B, 10),
+};
+const PINS: [MotorPins; 2] = [
+    MotorPins {
+        in1: Pin::new(Port::B, 12),
+        in2: Pin::new(Port::B, 13),
+    },
+    MotorPins {
+        in1: Pin::new(Port::B, 14),
+        in2: Pin::new(Port::B, 15),
+    },
+];
+const LEFT: Motor = Motor::A;
+const RIGHT: Motor = Motor::B;
+
+const BASE_SPEED: f32 = 0.5;
+const SEARCH_SPEED: f32 = 0.35;
+// 0.2 秒从 0 到满速
+const SLEW_PER_S: f32 = 5.0;
+
+const PID_CONFIG: PidConfig = PidConfig {
+    kp: 0.35,
+    ki: 0.05,
+    kd: 0.03,
+    out_min: -1.0,
+    out_max: 1.0,
+};
+
+const LOOP_MS: u32 = 5;
+const REPORT_MS: u32 = 200;
+
+const SENSORS: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Drive {
+    // 等待按键
+    Stopped,
+    Following,
+    // 看不到黑线，朝这个方向原地转
+    Searching { left: bool },
+}
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    setup_inputs(&dp);
+
+    let mut motors = DualMotor::new(&dp, CHIP, PINS, TIM_CLK_HZ, PWM_HZ, SLEW_PER_S);
+    let mut pid = Pid::new(PID_CONFIG);
+    // debug 构建中，引脚冲突在这之前就已经 panic 了
+    pin_registry::report();
+
+    let dt_s = LOOP_MS as f32 / 1000.0;
+    let mut drive = Drive::Stopped;
+    let mut last_position = 0.0;
+    let mut was_pressed = false;
+    let mut elapsed_ms = 0;
+
+    loop {
+        cortex_m::asm::delay(TIM_CLK_HZ / 1000 * LOOP_MS);
+        elapsed_ms += LOOP_MS;
+
+        let pressed = dp.GPIOA.idr.read().idr0().is_low();
+        if pressed && !was_pressed && drive == Drive::Stopped {
+            pid.reset();
+            drive = Drive::Following;
+        }
+        was_pressed = pressed;
+
+        let sensors = (dp.GPIOC.idr.read().bits() & ((1 << SENSORS) - 1)) as u8;
+
+        drive = match (drive, line_position(sensors)) {
+            (Drive::Stopped, _) => Drive::Stopped,
+            // 全黑：终点线
+            _ if sensors.count_ones() == SENSORS as u32 => {
+                motors.brake(LEFT);
+                motors.brake(RIGHT);
+                rprintln!("finish line");
+                Drive::Stopped
+            }
+            (_, Some(position)) => {
+                last_position = position;
+                let steer = pid.update(0.0, position, dt_s);
+                // 黑线在右边时 position 为正，steer 为负，左轮加速、右轮减速
+                motors.set_speed(LEFT, BASE_SPEED - steer);
+                motors.set_speed(RIGHT, BASE_SPEED + steer);
+                Drive::Following
+            }
+            (Drive::Following, None) => {
+                // 重新找到黑线时，积分与微分不应该沿用冲出去之前的历史
+                pid.reset();
+                Drive::Searching {
+                    left: last_position < 0.0,
+                }
+            }
+            (searching, None) => searching,
+        };
+
+        if let Drive::Searching { left } = drive {
+            let turn = if left { -SEARCH_SPEED } else { SEARCH_SPEED };
+            motors.set_speed(LEFT, turn);
+            motors.set_speed(RIGHT, -turn);
+        }
+
+        motors.update(dt_s);
+
+        if elapsed_ms >= REPORT_MS {
+            elapsed_ms = 0;
+            rprintln!(
+                "{:?} sensors {:05b} left {} right {} standby {}",
+                drive,
+                sensors,
+                (motors.speed(LEFT) * 100.0) as i32,
+                (motors.speed(RIGHT) * 100.0) as i32,
+                motors.is_standby()
+            );
+        }
+    }
+}
+
+// 压在黑线上的传感器的平均权重，从左到右为 -2 ~ 2，没有传感器压线时返回 None
+fn line_position(sensors: u8) -> Option<f32> {
+    let mut sum = 0;
+    let mut count = 0;
+    for i in 0..SENSORS {
+        if sensors & (1 << i) != 0 {
+            sum += i as i32 - (SENSORS / 2) as i32;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return None;
+    }
+    Some(sum as f32 / count as f32 / (SENSORS / 2) as f32)
+}
+
+// PA0 按键上拉输入，PC0 ~ PC4 传感器输入（模块自带推挽输出，不需要上下拉）
+fn setup_inputs(dp: &pac::Peripherals) {
+    clock_gate::claim(gates::GPIOA);
+    clock_gate::claim(gates::GPIOC);
+    pin_registry::claim(Port::A, 0, "button");
+    pin_registry::claim_pins(Port::C, &[0, 1, 2, 3, 4], "line sensor");
+
+    let gpioa = &dp.GPIOA;
+    gpioa.pupdr.modify(|_, w| w.pupdr0().pull_up());
+    gpioa.moder.modify(|_, w| w.moder0().input());
+
+    let gpioc = &dp.GPIOC;
+    gpioc.moder.modify(|_, w| {
+        w.moder0().input();
+        w.moder1().input();
+        w.moder2().input();
+        w.moder3().input();
+        w.moder4().input();
+        w
+    });
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
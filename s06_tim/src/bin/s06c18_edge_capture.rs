@@ -0,0 +1,189 @@
+//! 用输入捕获 + DMA 测量 ws2812_bitbang 输出的波形
+//!
+//! 捕获与统计的原理见 utils::edge_capture，这里把 s06c11 的 GPIO 翻转输出接回 PA5，自己测自己：
+//!
+//! - 每秒发送一帧 16 颗灯，共 384 bit、768 个边沿，发送之前先 arm，发送结束之后 DMA 也就搬完了
+//! - 每个脉冲按电平与宽度分为 T0H、T1H、T1L、T0L 四类，打印每一类的统计与直方图
+//!
+//! ws2812_bitbang 的标称值为 T0H 400 ns、T1H 800 ns，周期 1250 ns，所以 T0L 为 850 ns、T1L 为 450 ns；
+//! TIM2 以 96 MHz 计数，一个 tick 约 10.4 ns，直方图每个 bin 取 21 ns，即两个 tick
+//!
+//! 两颗灯之间会打开中断，这段低电平比 T0L 长，归入 unmatched，每帧 15 个；
+//! 最后一个 bit 的低电平没有结束的边沿，不参与统计
+//! 其它中断只会拉长这些间隔，灯内的各类脉冲不受影响，直方图的宽度就是 ws2812_bitbang 自身的抖动
+//!
+//! 接线图：
+//!
+//! PB12 -> PA5（用杜邦线直接相连）
+//! PB12 同时可以接灯带的 DIN，不影响测量
+
+#![no_std]
+#![no_main]
+
+use cortex_m::peripheral::DWT;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    edge_capture::{self, Class, EdgeCapture, CAPACITY},
+    port::Port,
+    ws2812::{FrameBuffer, Rgb, Ws2812Out, BITS_PER_LED},
+    ws2812_bitbang::BitBang,
+};
+
+const SYSCLK_HZ: u32 = 96_000_000;
+// APB1 为 48 MHz，APB1 上 TIM 的时钟自动 x2
+const TIM2_CLK_HZ: u32 = 96_000_000;
+
+const LEDS: usize = 16;
+// 每个 bit 有一个上升沿与一个下降沿
+const EDGES: usize = LEDS * BITS_PER_LED * 2;
+const _: () = assert!(EDGES <= CAPACITY);
+
+// 一帧约 0.5 ms，超过 5 ms 还没有捕获完，说明 PB12 与 PA5 没有接好
+const TIMEOUT_MS: u32 = 5;
+const REPORT_MS: u32 = 1000;
+
+const BIN_NS: u32 = 21;
+
+static CLASSES: [Class; 4] = [
+    Class {
+        name: "T0H",
+        high: true,
+        min_ns: 200,
+        max_ns: 600,
+        bin_ns: BIN_NS,
+    },
+    Class {
+        name: "T1H",
+        high: true,
+        min_ns: 600,
+        max_ns: 1000,
+        bin_ns: BIN_NS,
+    },
+    Class {
+        name: "T1L",
+        high: false,
+        min_ns: 200,
+        max_ns: 650,
+        bin_ns: BIN_NS,
+    },
+    Class {
+        name: "T0L",
+        high: false,
+        min_ns: 650,
+        max_ns: 1200,
+        bin_ns: BIN_NS,
+    },
+];
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut cp = pac::CorePeripherals::take().unwrap();
+
+    setup_rcc(&dp);
+
+    let buffer = cortex_m::singleton!(: [u32; CAPACITY] = [0; CAPACITY]).unwrap();
+    let mut capture = EdgeCapture::new(&dp, buffer, TIM2_CLK_HZ, 0);
+
+    let mut strip = BitBang::new(&mut cp, Port::B, 12, SYSCLK_HZ).unwrap();
+    let mut frame = FrameBuffer::<LEDS>::new();
+    frame.set_brightness(32);
+
+    let cycles_per_ms = SYSCLK_HZ / 1000;
+    let mut round = 0u8;
+    loop {
+        // 每一帧换一组颜色，让 0 与 1 的分布有些变化
+        for (index, pixel) in frame.pixels_mut().iter_mut().enumerate() {
+            *pixel = wheel(round.wrapping_add(index as u8 * 16));
+        }
+        round = round.wrapping_add(7);
+
+        capture.arm(&dp, EDGES);
+        if let Err(e) = strip.show(&frame) {
+            rprintln!("show failed: {:?}", e);
+        }
+
+        let start = DWT::cycle_count();
+        while !capture.is_done(&dp)
+            && DWT::cycle_count().wrapping_sub(start) < TIMEOUT_MS * cycles_per_ms
+        {}
+        let edges = capture.stop(&dp);
+
+        rprintln!(
+            "\n{} / {} edges, tick {} Hz",
+            edges,
+            EDGES,
+            capture.tick_hz()
+        );
+        if edges < EDGES {
+            rprintln!("timeout, is PB12 connected to PA5?");
+        }
+        if capture.overcaptured(&dp) {
+            rprintln!("over-capture, some edges were lost");
+        }
+
+        let (reports, unmatched) = edge_capture::analyze(&capture, edges, &CLASSES);
+        edge_capture::report(&CLASSES, &reports, unmatched);
+
+        let start = DWT::cycle_count();
+        while DWT::cycle_count().wrapping_sub(start) < REPORT_MS * cycles_per_ms {}
+    }
+}
+
+// 色轮，与 s06c11 相同，pos 只用 256 个位置
+fn wheel(pos: u8) -> Rgb {
+    match pos {
+        0..=84 => Rgb::new(255 - pos * 3, pos * 3, 0),
+        85..=169 => Rgb::new(0, 255 - (pos - 85) * 3, (pos - 85) * 3),
+        _ => Rgb::new((pos - 170) * 3, 0, 255 - (pos - 170) * 3),
+    }
+}
+
+// HSE 12 MHz / 6 * 96 / 2 = 96 MHz，与 s06c15 相同
+fn setup_rcc(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+
+    rcc.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(96);
+        }
+        w.pllp().div2();
+        w
+    });
+
+    // HCLK 超过 84 MHz，需要 Scale 1 mode
+    rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| unsafe { w.vos().bits(0b11) });
+
+    // 90 MHz < HCLK <= 100 MHz，FLASH 读取需要等待 3 个周期
+    dp.FLASH.acr.modify(|_, w| {
+        w.latency().ws3();
+        w.dcen().enabled();
+        w.icen().enabled();
+        w.prften().enabled();
+        w
+    });
+
+    // APB1 最高 50 MHz
+    rcc.cfgr.modify(|_, w| w.ppre1().div2());
+
+    rcc.cr.modify(|_, w| w.pllon().on());
+    while dp.PWR.csr.read().vosrdy().bit_is_clear() {}
+    while rcc.cr.read().pllrdy().is_not_ready() {}
+
+    rcc.cfgr.modify(|_, w| w.sw().pll());
+    while !rcc.cfgr.read().sws().is_pll() {}
+}
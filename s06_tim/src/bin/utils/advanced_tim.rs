@@ -0,0 +1,345 @@
+//! 高级定时器 TIM1/TIM8 独有的功能
+//!
+//! 两者的寄存器完全相同，相比通用定时器，多出了这几样东西：
+//!
+//! 1. 重复计数器 RCR：计数器每溢出一次 RCR 减 1，减到 0 时才产生更新事件（UEV），
+//!    于是“每 N 个 PWM 周期才更新一次 ARR/CCR、才进一次中断”，电机控制中常用来降低控制环路的频率
+//!    F413 上 RCR 只有 8 bit，N 最大为 256
+//! 2. 互补输出 CHxN 与死区：CHx 与 CHxN 驱动半桥的上下两管，两者切换时插入一段都为低的死区，防止上下管直通
+//!    死区长度由 BDTR 的 DTG 编码，见 encode_dead_time
+//! 3. 刹车输入 BKIN：有效时硬件立即清除 MOE，所有输出进入 OISx/OISxN 规定的空闲电平，完全不依赖软件
+//!    AOE 为 1 时，刹车信号撤除后，在下一次更新事件时自动重新置位 MOE
+//! 4. 锁定 LOCK：复位后只能写一次，写入之后，对应级别的配置在下一次复位之前都无法修改，防止跑飞的程序改乱死区、极性等关键参数
+//!    Level 1：DTG、BKE、BKP、AOE、OISx/OISxN
+//!    Level 2：Level 1 + CCxP/CCxNP、OSSR/OSSI
+//!    Level 3：Level 2 + OCxM/OCxPE
+//! 5. 换相事件 COM：CCPC 为 1 时，CCxE、CCxNE、OCxM 都变为预装载，写入之后不会立即生效，
+//!    要等到 COM 事件（软件置位 EGR 的 COMG，或者 CCUS 为 1 时由 TRGI 的上升沿触发）时，三相同时切换，
+//!    无刷电机的六步换相就是这样实现的
+//!
+//! 这里的死区计算假设 CR1 的 CKD 为 00，即 t_DTS 等于 TIM 的时钟周期
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::{self, tim1::RegisterBlock, Peripherals};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Instance {
+    Tim1,
+    Tim8,
+}
+
+// 只有 CH1~CH3 有互补输出
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Channel {
+    Ch1,
+    Ch2,
+    Ch3,
+}
+
+impl Channel {
+    pub(crate) const ALL: [Channel; 3] = [Channel::Ch1, Channel::Ch2, Channel::Ch3];
+
+    fn index(self) -> u32 {
+        self as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LockLevel {
+    Off,
+    Level1,
+    Level2,
+    Level3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BreakPolarity {
+    ActiveLow,
+    ActiveHigh,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ComTrigger {
+    // 只能通过 commutate 触发
+    Software,
+    // commutate 与 TRGI 的上升沿都可以触发，比如由霍尔传感器接口的 TIM 提供 TRGO
+    SoftwareOrTrgi,
+}
+
+// 换相时每一相的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Phase {
+    // CHx 输出 PWM，CHxN 输出互补的 PWM
+    Pwm,
+    // 上管关闭、下管常开，这一相接地
+    Low,
+    // 上下管都关闭，这一相悬空
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AdvError {
+    // 重复次数为 0，或者超过 256
+    Repetition,
+    // 死区超过了 DTG 能表示的最大值
+    DeadTimeTooLong,
+    // LOCK 已经写入过，BDTR 无法再修改
+    Locked,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BdtrConfig {
+    pub(crate) dead_time_ns: u32,
+    // None 表示不使用刹车输入
+    pub(crate) brk: Option<BreakPolarity>,
+    // 刹车撤除后，是否在下一次更新事件时自动恢复输出
+    pub(crate) auto_output: bool,
+    // 运行/空闲时，被关闭的通道是输出无效电平（true），还是高阻（false）
+    pub(crate) ossr: bool,
+    pub(crate) ossi: bool,
+    pub(crate) lock: LockLevel,
+}
+
+// DTG 的编码，ns 向上取整，保证实际的死区不会比要求的短
+//
+// DTG[7] = 0     ：DT = DTG[6:0] * t_DTS                ，0 ~ 127 个周期
+// DTG[7:6] = 10  ：DT = (64 + DTG[5:0]) * 2 * t_DTS     ，128 ~ 254 个周期
+// DTG[7:5] = 110 ：DT = (32 + DTG[4:0]) * 8 * t_DTS     ，256 ~ 504 个周期
+// DTG[7:5] = 111 ：DT = (32 + DTG[4:0]) * 16 * t_DTS    ，512 ~ 1008 个周期
+pub(crate) fn encode_dead_time(ns: u32, tim_clk_hz: u32) -> Option<u8> {
+    let ticks = (ns as u64 * tim_clk_hz as u64).div_ceil(1_000_000_000) as u32;
+    match ticks {
+        0..=127 => Some(ticks as u8),
+        128..=254 => Some(0x80 | (ticks.div_ceil(2) - 64) as u8),
+        255..=504 => Some(0xC0 | (ticks.div_ceil(8) - 32) as u8),
+        505..=1008 => Some(0xE0 | (ticks.div_ceil(16) - 32) as u8),
+        _ => None,
+    }
+}
+
+pub(crate) fn decode_dead_time(dtg: u8, tim_clk_hz: u32) -> u32 {
+    let dtg = dtg as u32;
+    let ticks = match dtg {
+        0x00..=0x7F => dtg,
+        0x80..=0xBF => (64 + (dtg & 0x3F)) * 2,
+        0xC0..=0xDF => (32 + (dtg & 0x1F)) * 8,
+        _ => (32 + (dtg & 0x1F)) * 16,
+    };
+    (ticks as u64 * 1_000_000_000 / tim_clk_hz as u64) as u32
+}
+
+pub(crate) struct AdvancedTimer {
+    tim: &'static RegisterBlock,
+    tim_clk_hz: u32,
+}
+
+impl AdvancedTimer {
+    // 开启时钟并复位该 TIM，GPIO 需要自行配置
+    pub(crate) fn new(dp: &Peripherals, instance: Instance, tim_clk_hz: u32) -> Self {
+        let rcc = &dp.RCC;
+        let tim = match instance {
+            Instance::Tim1 => {
+                rcc.apb2enr.modify(|_, w| w.tim1en().enabled());
+                rcc.apb2rstr.modify(|_, w| w.tim1rst().set_bit());
+                rcc.apb2rstr.modify(|_, w| w.tim1rst().clear_bit());
+                unsafe { &*pac::TIM1::ptr() }
+            }
+            Instance::Tim8 => {
+                rcc.apb2enr.modify(|_, w| w.tim8en().enabled());
+                rcc.apb2rstr.modify(|_, w| w.tim8rst().set_bit());
+                rcc.apb2rstr.modify(|_, w| w.tim8rst().clear_bit());
+                unsafe { &*pac::TIM8::ptr() }
+            }
+        };
+        Self { tim, tim_clk_hz }
+    }
+
+    // 不开启时钟也不复位，用于在中断处理函数中访问已经配置好的 TIM
+    pub(crate) fn attach(instance: Instance, tim_clk_hz: u32) -> Self {
+        let tim = match instance {
+            Instance::Tim1 => unsafe { &*pac::TIM1::ptr() },
+            Instance::Tim8 => unsafe { &*pac::TIM8::ptr() },
+        };
+        Self { tim, tim_clk_hz }
+    }
+
+    // 其余的通用功能（中断、DMA 等）直接操作寄存器
+    pub(crate) fn regs(&self) -> &'static RegisterBlock {
+        self.tim
+    }
+
+    // 边沿对齐，向上计数，PSC 与 ARR 立即生效
+    pub(crate) fn set_period(&self, psc: u16, arr: u16) {
+        let tim = self.tim;
+        tim.psc.write(|w| w.psc().bits(psc));
+        tim.arr.write(|w| w.arr().bits(arr));
+        tim.cr1.modify(|_, w| {
+            w.arpe().enabled();
+            // UG 只用来加载预装载寄存器，不产生更新中断
+            w.urs().counter_only();
+            w
+        });
+        tim.egr.write(|w| w.ug().set_bit());
+    }
+
+    // 每 n 个计数周期产生一次更新事件，在下一次更新事件时生效
+    pub(crate) fn set_repetition(&self, n: u32) -> Result<(), AdvError> {
+        if !(1..=256).contains(&n) {
+            return Err(AdvError::Repetition);
+        }
+        self.tim
+            .rcr
+            .write(|w| unsafe { w.rep().bits((n - 1) as u8) });
+        Ok(())
+    }
+
+    // PWM mode 1，CCR 预装载，complementary 为 true 时同时开启 CHxN
+    pub(crate) fn setup_pwm(&self, channel: Channel, complementary: bool) {
+        let tim = self.tim;
+        match channel {
+            Channel::Ch1 => tim.ccmr1_output().modify(|_, w| {
+                w.cc1s().output();
+                w.oc1m().pwm_mode1();
+                w.oc1pe().enabled();
+                w
+            }),
+            Channel::Ch2 => tim.ccmr1_output().modify(|_, w| {
+                w.cc2s().output();
+                w.oc2m().pwm_mode1();
+                w.oc2pe().enabled();
+                w
+            }),
+            Channel::Ch3 => tim.ccmr2_output().modify(|_, w| {
+                w.cc3s().output();
+                w.oc3m().pwm_mode1();
+                w.oc3pe().enabled();
+                w
+            }),
+        }
+        self.set_enables(channel, true, complementary);
+    }
+
+    pub(crate) fn set_duty(&self, channel: Channel, ccr: u16) {
+        let tim = self.tim;
+        match channel {
+            Channel::Ch1 => tim.ccr1().write(|w| w.ccr().bits(ccr)),
+            Channel::Ch2 => tim.ccr2().write(|w| w.ccr().bits(ccr)),
+            Channel::Ch3 => tim.ccr3().write(|w| w.ccr().bits(ccr)),
+        }
+    }
+
+    // CCER 中每个通道占 4 bit：CCxE、CCxP、CCxNE、CCxNP
+    fn set_enables(&self, channel: Channel, main: bool, complementary: bool) {
+        let shift = channel.index() * 4;
+        let bits = (main as u32) | ((complementary as u32) << 2);
+        self.tim
+            .ccer
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b0101 << shift)) | (bits << shift)) });
+    }
+
+    // 所有参数一次写入 BDTR，LOCK 不为 Off 时，这也是复位前最后一次修改的机会
+    pub(crate) fn configure_bdtr(&self, config: &BdtrConfig) -> Result<(), AdvError> {
+        let dtg = encode_dead_time(config.dead_time_ns, self.tim_clk_hz)
+            .ok_or(AdvError::DeadTimeTooLong)?;
+        if self.tim.bdtr.read().lock().bits() != 0 {
+            return Err(AdvError::Locked);
+        }
+
+        self.tim.bdtr.modify(|_, w| unsafe {
+            w.dtg().bits(dtg);
+            w.bke().bit(config.brk.is_some());
+            w.bkp().bit(config.brk == Some(BreakPolarity::ActiveHigh));
+            w.aoe().bit(config.auto_output);
+            w.ossr().bit(config.ossr);
+            w.ossi().bit(config.ossi);
+            w.lock().bits(config.lock as u8);
+            w
+        });
+        Ok(())
+    }
+
+    pub(crate) fn lock_level(&self) -> LockLevel {
+        match self.tim.bdtr.read().lock().bits() {
+            0 => LockLevel::Off,
+            1 => LockLevel::Level1,
+            2 => LockLevel::Level2,
+            _ => LockLevel::Level3,
+        }
+    }
+
+    // 实际的死区，单位 ns
+    pub(crate) fn dead_time_ns(&self) -> u32 {
+        decode_dead_time(self.tim.bdtr.read().dtg().bits(), self.tim_clk_hz)
+    }
+
+    pub(crate) fn start(&self) {
+        self.tim.cr1.modify(|_, w| w.cen().enabled());
+    }
+
+    pub(crate) fn stop(&self) {
+        self.tim.cr1.modify(|_, w| w.cen().disabled());
+    }
+
+    // 置位 MOE，输出才会出现在引脚上；刹车输入仍有效时，MOE 会立即被硬件再次清除
+    pub(crate) fn enable_outputs(&self) {
+        self.tim.bdtr.modify(|_, w| w.moe().set_bit());
+    }
+
+    pub(crate) fn disable_outputs(&self) {
+        self.tim.bdtr.modify(|_, w| w.moe().clear_bit());
+    }
+
+    pub(crate) fn outputs_enabled(&self) -> bool {
+        self.tim.bdtr.read().moe().bit_is_set()
+    }
+
+    // 是否发生过刹车，需要手动清除
+    pub(crate) fn break_pending(&self) -> bool {
+        self.tim.sr.read().bif().bit_is_set()
+    }
+
+    pub(crate) fn clear_break(&self) {
+        self.tim.sr.modify(|_, w| w.bif().clear_bit());
+    }
+
+    // 开启 CCxE/CCxNE/OCxM 的预装载，此后 preload_phases 的设置在 COM 事件时才生效
+    pub(crate) fn enable_commutation(&self, trigger: ComTrigger) {
+        self.tim.cr2.modify(|_, w| {
+            w.ccpc().set_bit();
+            w.ccus().bit(trigger == ComTrigger::SoftwareOrTrgi);
+            w
+        });
+    }
+
+    // 写入下一步换相时三相的状态
+    pub(crate) fn preload_phases(&self, phases: &[Phase; 3]) {
+        for (&channel, &phase) in Channel::ALL.iter().zip(phases) {
+            let (pwm, main, complementary) = match phase {
+                Phase::Pwm => (true, true, true),
+                // 强制 OCxREF 为低，下管在互补输出上常开
+                Phase::Low => (false, true, true),
+                Phase::Off => (false, false, false),
+            };
+            self.set_mode(channel, pwm);
+            self.set_enables(channel, main, complementary);
+        }
+    }
+
+    fn set_mode(&self, channel: Channel, pwm: bool) {
+        let tim = self.tim;
+        match (channel, pwm) {
+            (Channel::Ch1, true) => tim.ccmr1_output().modify(|_, w| w.oc1m().pwm_mode1()),
+            (Channel::Ch1, false) => tim.ccmr1_output().modify(|_, w| w.oc1m().force_inactive()),
+            (Channel::Ch2, true) => tim.ccmr1_output().modify(|_, w| w.oc2m().pwm_mode1()),
+            (Channel::Ch2, false) => tim.ccmr1_output().modify(|_, w| w.oc2m().force_inactive()),
+            (Channel::Ch3, true) => tim.ccmr2_output().modify(|_, w| w.oc3m().pwm_mode1()),
+            (Channel::Ch3, false) => tim.ccmr2_output().modify(|_, w| w.oc3m().force_inactive()),
+        }
+    }
+
+    // 软件触发一次 COM 事件，预装载的状态同时生效
+    pub(crate) fn commutate(&self) {
+        self.tim.egr.write(|w| w.comg().set_bit());
+    }
+}
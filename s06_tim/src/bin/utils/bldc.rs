@@ -0,0 +1,682 @@
+//! 带霍尔传感器的无刷电机（BLDC）六步换相驱动
+//!
+//! 用到的外设：
+//!
+//! - TIM1：三对互补输出驱动三个半桥，死区与刹车见 utils::advanced_tim；
+//!   RCR 让更新中断降到 CONTROL_HZ，作为控制循环的时钟；CH4 不接引脚，只在每个 PWM 周期的导通段中点触发 ADC
+//! - TIM4：霍尔传感器接口，CR2 的 TI1S 置位后，CH1~CH3 三个输入经过异或（XOR）合成为 TI1，
+//!   任何一个霍尔信号翻转都会产生一个 TI1F_ED，在从模式 Reset 下清零计数器，同时把计数值捕获到 CCR1，
+//!   于是 CCR1 直接就是两次换相之间的时间（1 us 一个计数）；计数器溢出（65 ms 没有换相）说明电机停了
+//! - ADC1：注入通道由 TIM1_CC4 触发，测量下管公共端的采样电阻上的电流；
+//!   模拟看门狗监视同一个通道，超过 current_limit 立即关闭输出，不需要等控制循环
+//!
+//! 霍尔状态为 H3:H2:H1 三个 bit，120° 安装的传感器依次经过 HALL_SEQUENCE 中的 6 个状态，0b000 与 0b111 不会出现，
+//! 出现了说明传感器断线或者没有供电
+//!
+//! 运行过程：
+//!
+//! 1. Align：固定在第 0 步、ramp_duty 的占空比，把转子拉到已知的位置
+//! 2. Ramp：开环，换相频率从 ramp_start_hz 线性升到 ramp_end_hz，不看霍尔，只检查霍尔是否按预期的方向依次变化
+//! 3. Run：连续 HANDOVER_EDGES 次换相都符合预期之后，交给霍尔换相，每个霍尔边沿立即切换到对应的一步，
+//!    转速由最近 6 次换相（一个电角度周期）的时间算出，交给 PID 调整占空比
+//!
+//! 占空比 = ramp_duty + PID 的输出，以开环时的占空比作为前馈，从 Ramp 切到 Run 时占空比不会突然掉到 0
+//!
+//! 霍尔状态与换相的对应关系和电机、接线都有关，需要实测 hall_offset（0 ~ 5）：
+//! Ramp 阶段电机能转起来，但切到 Run 之后抖动、电流很大，或者反转，就依次尝试其它的值，
+//! 在同样的占空比下电流最小、转速最高的那个值就是正确的
+//! Ramp 阶段报 Fault::HallLock，说明霍尔的顺序与开环换相的方向相反，交换 H1 与 H3 两根线即可
+//!
+//! 反转只需要在同样的霍尔状态下多走 3 步（180° 电角度），转矩的方向就反过来了
+//!
+//! 开环换相在控制循环中完成，CONTROL_HZ 为 1 kHz 时每秒最多换相 1000 次，ramp_end_hz 不要超过 100 Hz 左右
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::{self, Peripherals};
+
+use super::{
+    advanced_tim::{
+        AdvancedTimer, BdtrConfig, BreakPolarity, Channel, ComTrigger, Instance, LockLevel, Phase,
+    },
+    clock_gate::{self, gates},
+    pid::{Pid, PidConfig},
+    pin_registry,
+    port::Port,
+    resources::LateResource,
+};
+
+pub(crate) const PWM_HZ: u32 = 20_000;
+const REPETITION: u32 = 20;
+pub(crate) const CONTROL_HZ: u32 = PWM_HZ / REPETITION;
+
+// 电流采样使用 ADC1 的通道 4，即 PA4
+const CURRENT_CHANNEL: u8 = 4;
+
+// 霍尔连续这么多次按顺序变化，才从开环切换到霍尔换相
+const HANDOVER_EDGES: u8 = 12;
+
+// 按电角度顺序排列的霍尔状态，下标即转子所在的扇区
+const HALL_SEQUENCE: [u8; 6] = [0b001, 0b011, 0b010, 0b110, 0b100, 0b101];
+
+// 六步换相，每一步为 A、B、C 三相的状态，与 s06c09 相同
+const STEPS: [[Phase; 3]; 6] = [
+    [Phase::Pwm, Phase::Low, Phase::Off],
+    [Phase::Pwm, Phase::Off, Phase::Low],
+    [Phase::Off, Phase::Pwm, Phase::Low],
+    [Phase::Low, Phase::Pwm, Phase::Off],
+    [Phase::Low, Phase::Off, Phase::Pwm],
+    [Phase::Off, Phase::Low, Phase::Pwm],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Forward,
+    Reverse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    // 霍尔状态为 0b000 或 0b111
+    InvalidHall(u8),
+    // 开环结束时，霍尔仍没有按预期的顺序变化
+    HallLock,
+    // Run 状态下超过 65 ms 没有霍尔边沿
+    Stall,
+    OverCurrent,
+    // TIM1 的 BKIN 有效
+    Break,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum State {
+    Idle,
+    Align,
+    Ramp,
+    Run,
+    Fault(Fault),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Config {
+    pub(crate) pole_pairs: u8,
+    pub(crate) hall_offset: u8,
+    pub(crate) dead_time_ns: u32,
+    // 占空比均为 0.0 ~ 1.0
+    pub(crate) max_duty: f32,
+    pub(crate) ramp_duty: f32,
+    pub(crate) align_ms: u32,
+    // 开环的电角度频率，单位 Hz
+    pub(crate) ramp_start_hz: f32,
+    pub(crate) ramp_end_hz: f32,
+    pub(crate) ramp_ms: u32,
+    // ADC 原始值，12 bit
+    pub(crate) current_limit: u16,
+    // 转速环，单位为 占空比/RPM
+    pub(crate) kp: f32,
+    pub(crate) ki: f32,
+    pub(crate) kd: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Status {
+    pub(crate) state: State,
+    pub(crate) direction: Direction,
+    pub(crate) target_rpm: f32,
+    pub(crate) rpm: f32,
+    pub(crate) duty: f32,
+    pub(crate) current_raw: u16,
+    pub(crate) hall: u8,
+}
+
+struct Controller {
+    config: Config,
+    tim_clk_hz: u32,
+    pwm_period: u32,
+    pid: Pid,
+
+    state: State,
+    direction: Direction,
+    target_rpm: f32,
+    duty: f32,
+
+    // Align 与 Ramp 已经经过的控制周期数
+    ticks: u32,
+    // 开环换相的相位累加器，满 1 换相一次
+    ramp_acc: f32,
+    step: u8,
+
+    last_sector: Option<u8>,
+    good_edges: u8,
+
+    // 最近 6 次换相的间隔，单位 us
+    intervals: [u16; 6],
+    interval_index: usize,
+    interval_count: usize,
+
+    current_raw: u16,
+}
+
+static G_BLDC: LateResource<Controller> = LateResource::new("G_BLDC");
+
+// tim_clk_hz 为 TIM1 与 TIM4 的时钟，这里要求 APB1 与 APB2 的定时器时钟相同
+// TIM1_UP_TIM10、TIM1_BRK_TIM9、TIM4、ADC 四个中断需要在 bin 中 unmask，并分别调用 on_* 函数
+pub(crate) fn setup(dp: &Peripherals, config: Config, tim_clk_hz: u32, pclk2_hz: u32) {
+    setup_gpio(dp);
+
+    let pwm_period = tim_clk_hz / PWM_HZ;
+    let tim = AdvancedTimer::new(dp, Instance::Tim1, tim_clk_hz);
+    tim.set_period(0, (pwm_period - 1) as u16);
+    tim.set_repetition(REPETITION).unwrap();
+    for channel in Channel::ALL {
+        tim.setup_pwm(channel, true);
+        tim.set_duty(channel, 0);
+    }
+
+    // CH4 只用来触发 ADC，PA11 没有配置为复用功能，引脚上不会有输出
+    let regs = tim.regs();
+    regs.ccmr2_output().modify(|_, w| {
+        w.oc4m().pwm_mode1();
+        w.oc4pe().enabled();
+        w
+    });
+    regs.ccr4().write(|w| w.ccr().bits(1));
+    regs.ccer.modify(|_, w| w.cc4e().set_bit());
+
+    tim.enable_commutation(ComTrigger::Software);
+    tim.preload_phases(&[Phase::Off; 3]);
+    tim.commutate();
+
+    tim.configure_bdtr(&BdtrConfig {
+        dead_time_ns: config.dead_time_ns,
+        brk: Some(BreakPolarity::ActiveLow),
+        auto_output: false,
+        ossr: true,
+        ossi: true,
+        lock: LockLevel::Level1,
+    })
+    .unwrap();
+
+    tim.clear_break();
+    regs.sr.modify(|_, w| w.uif().clear_bit());
+    regs.dier.modify(|_, w| {
+        w.uie().enabled();
+        w.bie().set_bit();
+        w
+    });
+
+    setup_hall_timer(dp, tim_clk_hz);
+    setup_adc(dp, config.current_limit, pclk2_hz);
+
+    G_BLDC.init(Controller {
+        config,
+        tim_clk_hz,
+        pwm_period,
+        pid: Pid::new(pid_config(&config)),
+        state: State::Idle,
+        direction: Direction::Forward,
+        target_rpm: 0.0,
+        duty: 0.0,
+        ticks: 0,
+        ramp_acc: 0.0,
+        step: 0,
+        last_sector: None,
+        good_edges: 0,
+        intervals: [0; 6],
+        interval_index: 0,
+        interval_count: 0,
+        current_raw: 0,
+    });
+
+    // 输出由 MOE 控制，TIM1 一直运行，控制循环也一直运行
+    tim.start();
+}
+
+// Idle 时开始 Align，其它状态下只修改目标转速
+pub(crate) fn start(direction: Direction, target_rpm: f32) {
+    G_BLDC.with(|ctrl| {
+        ctrl.target_rpm = target_rpm;
+        if ctrl.state == State::Idle {
+            ctrl.direction = direction;
+            ctrl.begin();
+        }
+    })
+}
+
+pub(crate) fn set_target(rpm: f32) {
+    G_BLDC.with(|ctrl| ctrl.target_rpm = rpm)
+}
+
+pub(crate) fn stop() {
+    G_BLDC.with(|ctrl| {
+        ctrl.shutdown();
+        ctrl.state = State::Idle;
+    })
+}
+
+// Fault 时回到 Idle，刹车输入仍然有效时返回 false
+pub(crate) fn clear_fault() -> bool {
+    G_BLDC.with(|ctrl| {
+        let State::Fault(fault) = ctrl.state else {
+            return true;
+        };
+        if fault == Fault::Break {
+            let tim = ctrl.tim();
+            let gpioa = unsafe { &*pac::GPIOA::ptr() };
+            if gpioa.idr.read().idr6().is_low() {
+                return false;
+            }
+            tim.clear_break();
+            tim.regs().dier.modify(|_, w| w.bie().set_bit());
+        }
+        if fault == Fault::OverCurrent {
+            let adc = unsafe { &*pac::ADC1::ptr() };
+            adc.sr.modify(|_, w| w.awd().clear_bit());
+            adc.cr1.modify(|_, w| w.awdie().enabled());
+        }
+        ctrl.state = State::Idle;
+        true
+    })
+}
+
+pub(crate) fn status() -> Status {
+    G_BLDC.with(|ctrl| Status {
+        state: ctrl.state,
+        direction: ctrl.direction,
+        target_rpm: ctrl.target_rpm,
+        rpm: ctrl.rpm(),
+        duty: ctrl.duty,
+        current_raw: ctrl.current_raw,
+        hall: read_hall(),
+    })
+}
+
+// PB6/PB7/PB8 为 H1/H2/H3
+pub(crate) fn read_hall() -> u8 {
+    let gpiob = unsafe { &*pac::GPIOB::ptr() };
+    ((gpiob.idr.read().bits() >> 6) & 0b111) as u8
+}
+
+fn hall_sector(hall: u8) -> Option<u8> {
+    HALL_SEQUENCE
+        .iter()
+        .position(|&h| h == hall)
+        .map(|s| s as u8)
+}
+
+// 在 TIM1_UP_TIM10 中断中调用，频率为 CONTROL_HZ
+pub(crate) fn on_control_tick() {
+    let tim1 = unsafe { &*pac::TIM1::ptr() };
+    tim1.sr.modify(|_, w| w.uif().clear_bit());
+    G_BLDC.try_with(|ctrl| ctrl.control_tick());
+}
+
+// 在 TIM4 中断中调用，霍尔边沿或者超时
+pub(crate) fn on_hall() {
+    let tim4 = unsafe { &*pac::TIM4::ptr() };
+    let sr = tim4.sr.read();
+
+    if sr.cc1if().bit_is_set() {
+        // 读 CCR1 会清除 CC1IF
+        let interval = tim4.ccr1().read().bits() as u16;
+        let hall = read_hall();
+        G_BLDC.try_with(|ctrl| ctrl.hall_edge(hall, interval));
+    }
+
+    if sr.uif().bit_is_set() {
+        tim4.sr.modify(|_, w| w.uif().clear_bit());
+        G_BLDC.try_with(|ctrl| ctrl.hall_timeout());
+    }
+}
+
+// 在 ADC 中断中调用
+pub(crate) fn on_adc() {
+    let adc = unsafe { &*pac::ADC1::ptr() };
+    let sr = adc.sr.read();
+
+    if sr.awd().bit_is_set() {
+        // 先关输出，再处理其它的事情；过流一直存在时 AWD 会反复置位，关闭它的中断，由 clear_fault 重新打开
+        let tim1 = unsafe { &*pac::TIM1::ptr() };
+        tim1.bdtr.modify(|_, w| w.moe().clear_bit());
+        adc.cr1.modify(|_, w| w.awdie().disabled());
+        adc.sr.modify(|_, w| w.awd().clear_bit());
+        G_BLDC.try_with(|ctrl| ctrl.enter_fault(Fault::OverCurrent));
+    }
+
+    if sr.jeoc().bit_is_set() {
+        adc.sr.modify(|_, w| {
+            w.jeoc().clear_bit();
+            w.jstrt().clear_bit();
+            w
+        });
+        let raw = adc.jdr[0].read().jdata().bits();
+        G_BLDC.try_with(|ctrl| ctrl.current_raw = raw);
+    }
+}
+
+// 在 TIM1_BRK_TIM9 中断中调用，MOE 已经由硬件清除
+pub(crate) fn on_break() {
+    let tim1 = unsafe { &*pac::TIM1::ptr() };
+    // 刹车输入保持有效时 BIF 会被反复置位，由 clear_fault 重新打开
+    tim1.dier.modify(|_, w| w.bie().clear_bit());
+    G_BLDC.try_with(|ctrl| ctrl.enter_fault(Fault::Break));
+}
+
+impl Controller {
+    fn tim(&self) -> AdvancedTimer {
+        AdvancedTimer::attach(Instance::Tim1, self.tim_clk_hz)
+    }
+
+    fn begin(&mut self) {
+        self.pid.reset();
+        self.ticks = 0;
+        self.ramp_acc = 0.0;
+        self.step = 0;
+        self.last_sector = None;
+        self.good_edges = 0;
+        self.interval_count = 0;
+
+        self.set_duty(self.config.ramp_duty);
+        let tim = self.tim();
+        tim.preload_phases(&STEPS[0]);
+        tim.commutate();
+        tim.enable_outputs();
+        self.state = State::Align;
+    }
+
+    fn shutdown(&mut self) {
+        let tim = self.tim();
+        tim.disable_outputs();
+        tim.preload_phases(&[Phase::Off; 3]);
+        tim.commutate();
+        self.set_duty(0.0);
+    }
+
+    fn enter_fault(&mut self, fault: Fault) {
+        self.shutdown();
+        self.state = State::Fault(fault);
+    }
+
+    // 所有通道使用同一个占空比，只有 Phase::Pwm 的那一相会用到它，在下一次更新事件时生效
+    fn set_duty(&mut self, duty: f32) {
+        self.duty = duty.clamp(0.0, self.config.max_duty);
+        let ccr = (self.duty * self.pwm_period as f32) as u16;
+        let tim = self.tim();
+        for channel in Channel::ALL {
+            tim.set_duty(channel, ccr);
+        }
+        // 在导通段的中点采样电流，此时电流最平稳，离开关时刻的振铃也最远
+        tim.regs().ccr4().write(|w| w.ccr().bits((ccr / 2).max(1)));
+    }
+
+    // 沿着 direction 走 n 步，n 为 0 ~ 5
+    fn advance(&self, from: u8, n: u8) -> u8 {
+        match self.direction {
+            Direction::Forward => (from + n) % 6,
+            Direction::Reverse => (from + 6 - n) % 6,
+        }
+    }
+
+    fn commutate_to(&mut self, step: u8) {
+        self.step = step;
+        let tim = self.tim();
+        tim.preload_phases(&STEPS[step as usize]);
+        tim.commutate();
+    }
+
+    fn control_tick(&mut self) {
+        match self.state {
+            State::Align => {
+                self.ticks += 1;
+                if self.ticks * 1000 >= self.config.align_ms * CONTROL_HZ {
+                    self.ticks = 0;
+                    self.state = State::Ramp;
+                }
+            }
+            State::Ramp => self.ramp_tick(),
+            State::Run => {
+                let dt = 1.0 / CONTROL_HZ as f32;
+                let output = self.pid.update(self.target_rpm, self.rpm(), dt);
+                self.set_duty(self.config.ramp_duty + output);
+            }
+            State::Idle | State::Fault(_) => (),
+        }
+    }
+
+    fn ramp_tick(&mut self) {
+        let config = self.config;
+        let ramp_ticks = config.ramp_ms * CONTROL_HZ / 1000;
+        if self.ticks >= ramp_ticks {
+            if let (true, Some(sector)) = (self.good_edges >= HANDOVER_EDGES, self.last_sector) {
+                self.state = State::Run;
+                self.commutate_to(self.hall_step(sector));
+            } else {
+                self.enter_fault(Fault::HallLock);
+            }
+            return;
+        }
+
+        let progress = self.ticks as f32 / ramp_ticks as f32;
+        let hz = config.ramp_start_hz + (config.ramp_end_hz - config.ramp_start_hz) * progress;
+        self.ticks += 1;
+
+        // 每个电角度周期换相 6 次
+        self.ramp_acc += hz * 6.0 / CONTROL_HZ as f32;
+        if self.ramp_acc >= 1.0 {
+            self.ramp_acc -= 1.0;
+            let step = self.advance(self.step, 1);
+            self.commutate_to(step);
+        }
+    }
+
+    fn hall_edge(&mut self, hall: u8, interval: u16) {
+        if matches!(self.state, State::Idle | State::Fault(_)) {
+            return;
+        }
+
+        let Some(sector) = hall_sector(hall) else {
+            self.enter_fault(Fault::InvalidHall(hall));
+            return;
+        };
+
+        // 只有按 direction 的方向前进一个扇区，才是一次“好的”边沿
+        let expected = self.last_sector.map(|last| self.advance(last, 1));
+        if expected == Some(sector) {
+            self.good_edges = self.good_edges.saturating_add(1);
+            self.intervals[self.interval_index] = interval;
+            self.interval_index = (self.interval_index + 1) % self.intervals.len();
+            self.interval_count = (self.interval_count + 1).min(self.intervals.len());
+        } else {
+            self.good_edges = 0;
+            self.interval_count = 0;
+        }
+        self.last_sector = Some(sector);
+
+        if self.state == State::Run {
+            self.commutate_to(self.hall_step(sector));
+        }
+    }
+
+    // 转子位于 sector 时应当导通的那一步，反转时多走 3 步，转矩方向相反
+    fn hall_step(&self, sector: u8) -> u8 {
+        let offset = match self.direction {
+            Direction::Forward => self.config.hall_offset,
+            Direction::Reverse => self.config.hall_offset + 3,
+        };
+        (sector + offset) % 6
+    }
+
+    fn hall_timeout(&mut self) {
+        self.interval_count = 0;
+        self.good_edges = 0;
+        if self.state == State::Run {
+            self.enter_fault(Fault::Stall);
+        }
+    }
+
+    // 机械转速，一个电角度周期为 6 次换相，机械转一圈为 pole_pairs 个电角度周期
+    fn rpm(&self) -> f32 {
+        if self.interval_count == 0 {
+            return 0.0;
+        }
+        let sum: u32 = self.intervals[..self.interval_count]
+            .iter()
+            .map(|&i| i as u32)
+            .sum();
+        let electrical_period_us = sum as f32 * 6.0 / self.interval_count as f32;
+        60_000_000.0 / (electrical_period_us * self.config.pole_pairs as f32)
+    }
+}
+
+// 以 ramp_duty 为前馈，PID 只输出相对它的修正量
+fn pid_config(config: &Config) -> PidConfig {
+    PidConfig {
+        kp: config.kp,
+        ki: config.ki,
+        kd: config.kd,
+        out_min: -config.ramp_duty,
+        out_max: config.max_duty - config.ramp_duty,
+    }
+}
+
+// TIM1：PA8/PA7、PA9/PB0、PA10/PB1 为三对互补输出，PA6 为 BKIN，均为 AF1，与 s06c09 相同
+// TIM4：PB6/PB7/PB8 为 CH1~CH3，AF2
+// ADC1：PA4 为通道 4
+fn setup_gpio(dp: &Peripherals) {
+    clock_gate::claim(gates::GPIOA);
+    clock_gate::claim(gates::GPIOB);
+    pin_registry::claim_pins(Port::A, &[4, 6, 7, 8, 9, 10], "bldc");
+    pin_registry::claim_pins(Port::B, &[0, 1, 6, 7, 8], "bldc");
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| {
+        w.afrl6().af1();
+        w.afrl7().af1();
+        w
+    });
+    gpioa.afrh.modify(|_, w| {
+        w.afrh8().af1();
+        w.afrh9().af1();
+        w.afrh10().af1();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| w.pupdr6().pull_up());
+    gpioa.moder.modify(|_, w| {
+        w.moder4().analog();
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| {
+        w.afrl0().af1();
+        w.afrl1().af1();
+        w.afrl6().af2();
+        w.afrl7().af2();
+        w
+    });
+    gpiob.afrh.modify(|_, w| w.afrh8().af2());
+    // 霍尔传感器多为开漏输出，内部上拉约 40k，线长时最好再外接 4.7k 上拉
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr6().pull_up();
+        w.pupdr7().pull_up();
+        w.pupdr8().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder0().alternate();
+        w.moder1().alternate();
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w.moder8().alternate();
+        w
+    });
+}
+
+fn setup_hall_timer(dp: &Peripherals, tim_clk_hz: u32) {
+    clock_gate::claim(gates::TIM4);
+
+    let tim4 = &dp.TIM4;
+    tim4.psc
+        .write(|w| w.psc().bits((tim_clk_hz / 1_000_000 - 1) as u16));
+    tim4.arr.write(|w| w.arr().bits(u16::MAX));
+    tim4.cr1.modify(|_, w| {
+        w.ckd().div4();
+        // 从模式复位计数器时不产生更新中断，UIF 只表示溢出
+        w.urs().counter_only();
+        w
+    });
+    // CH1~CH3 异或之后作为 TI1
+    tim4.cr2.modify(|_, w| w.ti1s().set_bit());
+    tim4.smcr.modify(|_, w| {
+        w.ts().ti1f_ed();
+        w.sms().reset_mode();
+        w
+    });
+    // CC1S = 11，IC1 映射到 TRC，即 TI1F_ED；输入滤波 fDTS/4，N=8，滤掉霍尔信号上的毛刺
+    tim4.ccmr1_input().modify(|_, w| unsafe {
+        w.cc1s().bits(0b11);
+        w.ic1f().bits(0b0111);
+        w
+    });
+    tim4.ccer.modify(|_, w| w.cc1e().set_bit());
+    tim4.egr.write(|w| w.ug().set_bit());
+    tim4.sr.modify(|_, w| {
+        w.cc1if().clear_bit();
+        w.uif().clear_bit();
+        w
+    });
+    tim4.dier.modify(|_, w| {
+        w.cc1ie().enabled();
+        w.uie().enabled();
+        w
+    });
+    tim4.cr1.modify(|_, w| w.cen().enabled());
+}
+
+fn setup_adc(dp: &Peripherals, current_limit: u16, pclk2_hz: u32) {
+    clock_gate::claim(gates::ADC1);
+
+    // ADCCLK 最高 36 MHz
+    dp.ADC_COMMON.ccr.modify(|_, w| match pclk2_hz {
+        0..=36_000_000 => w.adcpre().div2(),
+        36_000_001..=72_000_000 => w.adcpre().div4(),
+        _ => w.adcpre().div6(),
+    });
+
+    let adc = &dp.ADC1;
+
+    // 注入序列只有一个通道时，使用的是 JSQ4
+    adc.jsqr.write(|w| unsafe {
+        w.jl().bits(0);
+        w.jsq4().bits(CURRENT_CHANNEL);
+        w
+    });
+
+    // 采样时间 0b001 为 15 个周期，采样电阻后面的运放输出阻抗很低
+    let shift = 3 * CURRENT_CHANNEL as u32;
+    adc.smpr2
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << shift) | 0b001 << shift) });
+
+    adc.htr.write(|w| w.ht().bits(current_limit));
+    adc.ltr.write(|w| w.lt().bits(0));
+    adc.cr1.modify(|_, w| unsafe {
+        w.awdch().bits(CURRENT_CHANNEL);
+        w.awdsgl().single_channel();
+        w.jawden().enabled();
+        w.awdie().enabled();
+        w.jeocie().enabled();
+        w
+    });
+
+    adc.cr2.modify(|_, w| {
+        w.jextsel().tim1cc4();
+        w.jexten().rising_edge();
+        w.adon().enabled();
+        w
+    });
+}
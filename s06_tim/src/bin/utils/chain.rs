@@ -0,0 +1,154 @@
+//! 级联两个 TIM，组成 48 bit 或 64 bit 的计数器
+//!
+//! 即便是 32 bit 的 TIM2/TIM5，以 1 MHz 计数时，也只要 71.6 分钟就会溢出；
+//! 常见的做法是在溢出中断里给一个软件变量加 1，但这样既需要中断，读取时也要小心处理中断与读取之间的竞争
+//!
+//! TIM 本身就提供了硬件的级联方法：
+//!
+//! 主 TIM 的 MMS = 010（Update），每次溢出时，在 TRGO 上输出一个脉冲
+//! 从 TIM 的 TS 选择连接到主 TIM 的 ITRx，SMS = 111（External Clock Mode 1），每收到一个 TRGO 脉冲就计数一次
+//!
+//! 这样，从 TIM 的 CNT 就是主 TIM 溢出的次数，两者拼起来就是一个更宽的计数器，全程不需要 CPU 参与
+//!
+//! 查询 TIMx internal trigger connection 表可知：
+//! TIM3 的 ITR1 连接到 TIM2，TIM5 的 ITR0 连接到 TIM2
+//! 因此以 TIM2 作为主 TIM，TIM3（16 bit）作为从 TIM 时为 48 bit，TIM5（32 bit）作为从 TIM 时为 64 bit
+//!
+//! 在 1 MHz 下，48 bit 约 8.9 年才会溢出，64 bit 则是约 58 万年，对于超时判断与时间戳来说，都可以认为永不溢出
+//!
+//! 读取时，两个 CNT 不可能同时读取，若在两次读取之间主 TIM 恰好溢出，就会拼出一个错误的值，
+//! 因此要按照“高 - 低 - 高”的顺序读取，若两次读到的高位不同，则说明中间发生过溢出，重新读取低位即可
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::Peripherals;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Width {
+    // TIM2 + TIM3
+    Bits48,
+    // TIM2 + TIM5
+    Bits64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ChainedCounter {
+    width: Width,
+    tick_hz: u32,
+}
+
+impl ChainedCounter {
+    // tim_clk_hz 为 APB1 上 TIM 的时钟，tick_hz 为计数频率，tim_clk_hz 必须是 tick_hz 的整数倍，且商不超过 65536
+    pub(crate) fn setup(dp: &Peripherals, width: Width, tim_clk_hz: u32, tick_hz: u32) -> Self {
+        dp.RCC.apb1enr.modify(|_, w| {
+            w.tim2en().enabled();
+            match width {
+                Width::Bits48 => w.tim3en().enabled(),
+                Width::Bits64 => w.tim5en().enabled(),
+            };
+            w
+        });
+
+        let master = &dp.TIM2;
+        master.cr1.modify(|_, w| w.cen().disabled());
+        master
+            .psc
+            .write(|w| w.psc().bits((tim_clk_hz / tick_hz - 1) as u16));
+        master.arr.write(|w| w.arr().bits(u32::MAX));
+        // 溢出时在 TRGO 上输出一个脉冲
+        master.cr2.modify(|_, w| w.mms().update());
+
+        // 从 TIM 不分频，每个 TRGO 脉冲计数一次
+        match width {
+            Width::Bits48 => {
+                let slave = &dp.TIM3;
+                slave.cr1.modify(|_, w| w.cen().disabled());
+                slave.psc.write(|w| w.psc().bits(0));
+                slave.arr.write(|w| w.arr().bits(u16::MAX));
+                slave.smcr.modify(|_, w| {
+                    w.ts().itr1();
+                    w.sms().ext_clock_mode();
+                    w
+                });
+                slave.egr.write(|w| w.ug().update());
+                slave.cr1.modify(|_, w| w.cen().enabled());
+            }
+            Width::Bits64 => {
+                let slave = &dp.TIM5;
+                slave.cr1.modify(|_, w| w.cen().disabled());
+                slave.psc.write(|w| w.psc().bits(0));
+                slave.arr.write(|w| w.arr().bits(u32::MAX));
+                slave.smcr.modify(|_, w| {
+                    w.ts().itr0();
+                    w.sms().ext_clock_mode();
+                    w
+                });
+                slave.egr.write(|w| w.ug().update());
+                slave.cr1.modify(|_, w| w.cen().enabled());
+            }
+        }
+
+        // 主 TIM 的 UG 同样会在 TRGO 上输出脉冲，因此先让主 TIM 的 PSC 生效，再清零从 TIM
+        master.egr.write(|w| w.ug().update());
+        match width {
+            Width::Bits48 => dp.TIM3.cnt.reset(),
+            Width::Bits64 => dp.TIM5.cnt.reset(),
+        }
+        // 最后启动主 TIM，从此刻开始计时
+        master.cr1.modify(|_, w| w.cen().enabled());
+
+        Self { width, tick_hz }
+    }
+
+    fn high(&self, dp: &Peripherals) -> u32 {
+        match self.width {
+            Width::Bits48 => dp.TIM3.cnt.read().bits() & 0xFFFF,
+            Width::Bits64 => dp.TIM5.cnt.read().bits(),
+        }
+    }
+
+    // 当前的计数值
+    pub(crate) fn now(&self, dp: &Peripherals) -> u64 {
+        loop {
+            let high = self.high(dp);
+            let low = dp.TIM2.cnt.read().bits();
+            if self.high(dp) == high {
+                return (high as u64) << 32 | low as u64;
+            }
+        }
+    }
+
+    pub(crate) fn tick_hz(&self) -> u32 {
+        self.tick_hz
+    }
+
+    // 计数器的最大值，超过之后会回绕
+    pub(crate) fn max(&self) -> u64 {
+        match self.width {
+            Width::Bits48 => (1 << 48) - 1,
+            Width::Bits64 => u64::MAX,
+        }
+    }
+
+    // 将计数值换算为秒与秒以下的 tick 数
+    pub(crate) fn split_seconds(&self, ticks: u64) -> (u64, u32) {
+        (
+            ticks / self.tick_hz as u64,
+            (ticks % self.tick_hz as u64) as u32,
+        )
+    }
+}
+
+// 一个超时，由 ChainedCounter 的计数值表示
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Deadline(pub(crate) u64);
+
+impl Deadline {
+    pub(crate) fn after(counter: &ChainedCounter, dp: &Peripherals, ticks: u64) -> Self {
+        Self(counter.now(dp) + ticks)
+    }
+
+    pub(crate) fn is_expired(&self, counter: &ChainedCounter, dp: &Peripherals) -> bool {
+        counter.now(dp) >= self.0
+    }
+}
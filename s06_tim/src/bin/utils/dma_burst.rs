@@ -0,0 +1,118 @@
+//! TIM 的 DMA burst 模式
+//!
+//! s06c100 中，TIM3 每次 Update Event 只会发出一次 DMA 请求，修改一个 CCR 寄存器，因此只能控制一路 PWM
+//!
+//! DMA burst 模式下，TIM 每收到一次 DMA 请求（这里是 Update Event），会连续发出 DBL + 1 次请求，
+//! 而 DMA 每次都写入同一个地址，也就是 DMAR 寄存器，TIM 会把这些数据依次转写到从 DBA 开始的连续寄存器中
+//!
+//! DBA 为起始寄存器相对于 CR1 的偏移量，以 4 字节为单位：
+//! CR1 = 0，……，CNT = 9，PSC = 10，ARR = 11，RCR = 12，CCR1 = 13，CCR2 = 14，CCR3 = 15，CCR4 = 16
+//! DBL 为传输的次数减 1
+//!
+//! 比如 DBA = 13，DBL = 3，那么每个 Update Event，DMA 都会依次写入 CCR1 ~ CCR4，4 路 PWM 在同一个周期里一起更新
+//! 若 DBA = 11，DBL = 5，则依次写入 ARR、RCR、CCR1 ~ CCR4，连周期也可以每次都不一样
+//! 注意 TIM2 ~ TIM5 没有 RCR，写入它的数据会被忽略，但这个位置依旧要在数据中占一个位置
+//!
+//! 配合 ARR 与 CCR 的预载（ARPE、OCxPE），新的值会在下一个 Update Event 统一生效，不会出现某一路先变、另一路后变的情况
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::{dma1, tim3};
+
+// burst 的起始寄存器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BurstBase {
+    Psc = 10,
+    Arr = 11,
+    Rcr = 12,
+    Ccr1 = 13,
+    Ccr2 = 14,
+    Ccr3 = 15,
+    Ccr4 = 16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Burst {
+    pub(crate) base: BurstBase,
+    // 每个 Update Event 写入的寄存器个数，1 ~ 18
+    pub(crate) len: u8,
+}
+
+impl Burst {
+    // 每个 Update Event 写入 CCR1 ~ CCR4
+    pub(crate) const CCR1_TO_CCR4: Burst = Burst {
+        base: BurstBase::Ccr1,
+        len: 4,
+    };
+
+    // 每个 Update Event 写入 ARR、RCR、CCR1 ~ CCR4
+    pub(crate) const ARR_TO_CCR4: Burst = Burst {
+        base: BurstBase::Arr,
+        len: 6,
+    };
+
+    // 数据中每一帧（也就是每个 Update Event）所占的元素个数
+    pub(crate) fn frame_len(&self) -> usize {
+        self.len as usize
+    }
+}
+
+// 设置 TIM 的 burst 参数，并开启 Update Event 的 DMA 请求
+//
+// TIM2 ~ TIM5 的寄存器布局是一样的，因此这里的 tim 可以是它们中的任何一个
+pub(crate) fn setup_tim(tim: &tim3::RegisterBlock, burst: Burst) {
+    tim.dcr.write(|w| unsafe {
+        w.dba().bits(burst.base as u8);
+        w.dbl().bits(burst.len - 1);
+        w
+    });
+    tim.dier.modify(|_, w| w.ude().enabled());
+}
+
+// DMA 的外设地址必须是 DMAR，而非具体的 CCR
+pub(crate) fn dmar_address(tim: &tim3::RegisterBlock) -> u32 {
+    tim.dmar.as_ptr() as u32
+}
+
+// 设置 DMA Stream，frames 的长度必须是 burst.len 的整数倍
+//
+// 设置完成之后并不开启 Stream
+// DMA 只记录了 frames 的地址，因此在 DMA 运行期间，frames 不能被修改，也不能被释放
+pub(crate) fn setup_stream(
+    st: &dma1::ST,
+    channel: u8,
+    tim: &tim3::RegisterBlock,
+    burst: Burst,
+    frames: &[u16],
+    circular: bool,
+) {
+    assert!(frames.len().is_multiple_of(burst.frame_len()));
+
+    if st.cr.read().en().is_enabled() {
+        st.cr.modify(|_, w| w.en().disabled());
+        while st.cr.read().en().is_enabled() {}
+    }
+
+    st.cr.write(|w| {
+        w.chsel().bits(channel);
+        w.pl().high();
+        w.msize().bits16();
+        w.psize().bits16();
+        w.minc().incremented();
+        // DMAR 的地址是固定的，由 TIM 负责把数据分发到各个寄存器
+        w.pinc().fixed();
+        w.dir().memory_to_peripheral();
+        w.circ().bit(circular);
+        w.tcie().enabled();
+        w.teie().enabled();
+        w
+    });
+
+    // burst 模式下，TIM 对每一次请求只接受一次写入，这里不使用 DMA 的 FIFO
+    st.fcr.modify(|_, w| w.dmdis().enabled());
+
+    st.par.write(|w| unsafe { w.pa().bits(dmar_address(tim)) });
+    st.m0ar
+        .write(|w| unsafe { w.m0a().bits(frames.as_ptr() as u32) });
+    st.ndtr.write(|w| w.ndt().bits(frames.len() as u16));
+}
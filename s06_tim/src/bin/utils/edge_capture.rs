@@ -0,0 +1,383 @@
+//! 输入捕获 + DMA：记录一段波形的每一个边沿，再统计各种脉冲宽度的分布
+//!
+//! s06c04 用输入捕获测量 US-100 的 Echo，每个边沿进一次中断，在中断里读取 CCR；
+//! 边沿之间相隔几百 us 时没有问题，但 ws2812 的一个 bit 只有 1.25 us，中断根本来不及，
+//! s02c02 的 edge_recorder 也只能看到 1 ~ 2 us 以上的脉冲，而且时间戳是进入中断时才读的，带着中断延迟的抖动
+//!
+//! 这里让 TIM2 的 CH1 同时捕获上升沿与下降沿（CC1P 与 CC1NP 都置 1），每次捕获产生一个 DMA 请求，
+//! DMA1 Stream 5 Channel 3 把 CCR1 搬到内存的缓冲里，整个过程不需要 CPU：
+//!
+//! - 时间戳是 TIM 在边沿到来的那个时钟锁存的，与 CPU 在做什么无关，精度就是 TIM 的一个 tick（96 MHz 时约 10.4 ns）
+//! - 两个边沿之间的最短间隔，取决于 DMA 搬运一次的时间（总线上十几个周期），96 MHz 下 200 ns 左右的脉冲依旧可以记录
+//! - DMA 来不及时，TIM 会置位 CC1OF（over-capture），此时中间丢了边沿，之后每个脉冲的电平都会颠倒，结果不可信
+//!
+//! 缓冲中只有时间戳，没有电平：arm 时记下引脚的电平（空闲电平），第一个边沿一定与它相反，之后依次交替
+//! TIM2 是 32 位的，ARR 为最大值，相邻两个时间戳直接 wrapping_sub 就是脉冲的宽度
+//!
+//! 之后的统计由 analyze 完成：使用者给出一张 Class 的表（比如 ws2812 的 T0H、T1H），
+//! 每个脉冲按照电平与宽度归入第一个符合的类，每一类统计最小、最大、平均、标准差，
+//! 再以平均值为中心画一张偏差的直方图，一眼就能看出抖动是集中在几个 tick 之内，还是偶尔有一个很离谱的
+//!
+//! 红外遥控这类慢得多的信号（NEC 的脉冲在 560 us ~ 9 ms），把 psc 设大一些即可，32 位的计数器不用担心溢出
+//!
+//! 注意：
+//! 1. 捕获的引脚为 PA5（TIM2_CH1）
+//! 2. 直方图的 bin_ns 不要小于一个 tick，否则有些 bin 永远是空的
+
+#![allow(dead_code)]
+
+use chip_caps::pinmap::Binding;
+use rtt_target::rprintln;
+use stm32f4xx_hal::pac::Peripherals;
+
+use super::{
+    clock_gate::{self, gates},
+    pin_registry,
+    port::Port,
+};
+
+pub(crate) const CAPACITY: usize = 2048;
+// 直方图的 bin 数，中间的那个 bin 以平均值为中心，两侧各 HIST_BINS / 2 个
+pub(crate) const HIST_BINS: usize = 9;
+
+const CAPTURE_PIN: Binding = chip_caps::bind!(TIM2_CH1 => PA5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Pulse {
+    pub(crate) high: bool,
+    pub(crate) ticks: u32,
+}
+
+pub(crate) struct EdgeCapture {
+    buffer: &'static mut [u32; CAPACITY],
+    // TIM2 计数的频率，即 tim_clk_hz / (psc + 1)
+    tick_hz: u32,
+    // 这一次要捕获的边沿数
+    requested: usize,
+    idle_high: bool,
+}
+
+impl EdgeCapture {
+    // tim_clk_hz 为 APB1 上 TIM 的时钟，每 psc + 1 个时钟计数一次
+    pub(crate) fn new(
+        dp: &Peripherals,
+        buffer: &'static mut [u32; CAPACITY],
+        tim_clk_hz: u32,
+        psc: u16,
+    ) -> Self {
+        clock_gate::claim(gates::TIM2);
+        clock_gate::claim(gates::DMA1);
+        clock_gate::claim(gates::GPIOA);
+
+        setup_gpio(dp);
+        setup_tim2(dp, psc);
+
+        Self {
+            buffer,
+            tick_hz: tim_clk_hz / (psc as u32 + 1),
+            requested: 0,
+            idle_high: false,
+        }
+    }
+
+    pub(crate) fn tick_hz(&self) -> u32 {
+        self.tick_hz
+    }
+
+    pub(crate) fn ticks_to_ns(&self, ticks: u32) -> u32 {
+        (ticks as u64 * 1_000_000_000 / self.tick_hz as u64) as u32
+    }
+
+    // 开始捕获接下来的 edges 个边沿（不超过 CAPACITY），之前的结果被丢弃
+    pub(crate) fn arm(&mut self, dp: &Peripherals, edges: usize) {
+        let edges = edges.min(CAPACITY);
+        let tim = &dp.TIM2;
+        let dma1 = &dp.DMA1;
+        let st = &dma1.st[5];
+
+        self.stop(dp);
+
+        dma1.hifcr.write(|w| {
+            w.ctcif5().clear();
+            w.chtif5().clear();
+            w.cteif5().clear();
+            w.cdmeif5().clear();
+            w.cfeif5().clear();
+            w
+        });
+
+        // 单次模式，搬完 edges 个就停下；CCR1 与缓冲都是 32 位
+        st.cr.write(|w| {
+            w.chsel().bits(3);
+            w.pl().very_high();
+            w.dir().peripheral_to_memory();
+            w.msize().bits32();
+            w.psize().bits32();
+            w.minc().incremented();
+            w.pinc().fixed();
+            w
+        });
+        st.par
+            .write(|w| unsafe { w.pa().bits(tim.ccr1().as_ptr() as u32) });
+        st.m0ar
+            .write(|w| unsafe { w.m0a().bits(self.buffer.as_mut_ptr() as u32) });
+        st.ndtr.write(|w| w.ndt().bits(edges as u16));
+
+        self.requested = edges;
+        self.idle_high = dp.GPIOA.idr.read().bits() & (1 << CAPTURE_PIN.num) != 0;
+
+        // 清掉之前残留的捕获与 over-capture 标志
+        tim.sr.write(|w| unsafe { w.bits(0) });
+        tim.cnt.reset();
+
+        st.cr.modify(|_, w| w.en().enabled());
+        tim.dier.modify(|_, w| w.cc1de().enabled());
+        tim.ccer.modify(|_, w| w.cc1e().set_bit());
+        tim.cr1.modify(|_, w| w.cen().enabled());
+    }
+
+    // 已经捕获到的边沿数
+    pub(crate) fn captured(&self, dp: &Peripherals) -> usize {
+        self.requested - dp.DMA1.st[5].ndtr.read().ndt().bits() as usize
+    }
+
+    pub(crate) fn is_done(&self, dp: &Peripherals) -> bool {
+        dp.DMA1.hisr.read().tcif5().is_complete()
+    }
+
+    // 有没有因为 DMA 来不及而丢掉边沿
+    pub(crate) fn overcaptured(&self, dp: &Peripherals) -> bool {
+        dp.TIM2.sr.read().cc1of().bit_is_set()
+    }
+
+    // 停止捕获，已经写入缓冲的边沿保留，返回边沿数
+    pub(crate) fn stop(&mut self, dp: &Peripherals) -> usize {
+        let tim = &dp.TIM2;
+        let st = &dp.DMA1.st[5];
+
+        tim.cr1.modify(|_, w| w.cen().disabled());
+        tim.ccer.modify(|_, w| w.cc1e().clear_bit());
+        tim.dier.modify(|_, w| w.cc1de().disabled());
+
+        if st.cr.read().en().is_enabled() {
+            st.cr.modify(|_, w| w.en().disabled());
+            while st.cr.read().en().is_enabled() {}
+        }
+
+        self.captured(dp)
+    }
+
+    // 前 edges 个边沿之间的脉冲，edges 一般为 stop 的返回值
+    pub(crate) fn pulses(&self, edges: usize) -> impl Iterator<Item = Pulse> + '_ {
+        let edges = &self.buffer[..edges.min(self.requested)];
+        let idle_high = self.idle_high;
+        edges
+            .windows(2)
+            .enumerate()
+            .map(move |(index, pair)| Pulse {
+                // 第 0 个边沿之后的电平与空闲电平相反，之后依次交替
+                high: (index % 2 == 0) != idle_high,
+                ticks: pair[1].wrapping_sub(pair[0]),
+            })
+    }
+}
+
+// PA5 设置为 TIM2_CH1 的输入，下拉，没有接信号时保持低电平
+fn setup_gpio(dp: &Peripherals) {
+    pin_registry::claim(Port::A, CAPTURE_PIN.num, "edge_capture");
+
+    let gpioa = &dp.GPIOA;
+    gpioa.pupdr.modify(|_, w| w.pupdr5().pull_down());
+    gpioa.afrl.modify(|_, w| w.afrl5().bits(CAPTURE_PIN.af));
+    gpioa.moder.modify(|_, w| w.moder5().alternate());
+}
+
+fn setup_tim2(dp: &Peripherals, psc: u16) {
+    let tim = &dp.TIM2;
+
+    tim.cr1.modify(|_, w| w.cen().disabled());
+    tim.psc.write(|w| w.psc().bits(psc));
+    tim.arr.write(|w| w.arr().bits(u32::MAX));
+    tim.egr.write(|w| w.ug().update());
+
+    // CC1 映射到 TI1，不分频、不滤波，每个边沿都捕获
+    tim.ccmr1_input().modify(|_, w| unsafe {
+        w.cc1s().ti1();
+        w.ic1psc().bits(0);
+        w.ic1f().bits(0);
+        w
+    });
+    // CC1P 与 CC1NP 都置 1：上升沿与下降沿都捕获
+    tim.ccer.modify(|_, w| {
+        w.cc1p().set_bit();
+        w.cc1np().set_bit();
+        w
+    });
+}
+
+// ---- 统计 ----
+
+// 一类脉冲：电平与宽度的范围，单位 ns，[min_ns, max_ns)
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Class {
+    pub(crate) name: &'static str,
+    pub(crate) high: bool,
+    pub(crate) min_ns: u32,
+    pub(crate) max_ns: u32,
+    // 直方图每个 bin 的宽度
+    pub(crate) bin_ns: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClassReport {
+    pub(crate) count: u32,
+    pub(crate) min_ns: u32,
+    pub(crate) max_ns: u32,
+    pub(crate) mean_ns: f32,
+    pub(crate) std_ns: f32,
+    // 相对平均值的偏差，第 HIST_BINS / 2 个 bin 为 [-bin_ns / 2, bin_ns / 2)
+    pub(crate) hist: [u32; HIST_BINS],
+    // 超出直方图两端的个数
+    pub(crate) below: u32,
+    pub(crate) above: u32,
+}
+
+impl ClassReport {
+    const EMPTY: Self = Self {
+        count: 0,
+        min_ns: u32::MAX,
+        max_ns: 0,
+        mean_ns: 0.0,
+        std_ns: 0.0,
+        hist: [0; HIST_BINS],
+        below: 0,
+        above: 0,
+    };
+
+    // 最大值与最小值之差，即峰峰值抖动
+    pub(crate) fn jitter_ns(&self) -> u32 {
+        self.max_ns.saturating_sub(self.min_ns)
+    }
+}
+
+// 统计前 edges 个边沿之间的脉冲，返回每一类的结果，以及不属于任何一类的脉冲数
+//
+// 需要遍历两遍：第一遍得到平均值，第二遍才能画出以平均值为中心的直方图
+pub(crate) fn analyze<const C: usize>(
+    capture: &EdgeCapture,
+    edges: usize,
+    classes: &[Class; C],
+) -> ([ClassReport; C], u32) {
+    let mut reports = [ClassReport::EMPTY; C];
+    let mut sums = [(0f64, 0f64); C];
+    let mut unmatched = 0;
+
+    let classify = |pulse: Pulse| {
+        let ns = capture.ticks_to_ns(pulse.ticks);
+        classes
+            .iter()
+            .position(|c| c.high == pulse.high && (c.min_ns..c.max_ns).contains(&ns))
+            .map(|index| (index, ns))
+    };
+
+    for pulse in capture.pulses(edges) {
+        let Some((index, ns)) = classify(pulse) else {
+            unmatched += 1;
+            continue;
+        };
+        let report = &mut reports[index];
+        report.count += 1;
+        report.min_ns = report.min_ns.min(ns);
+        report.max_ns = report.max_ns.max(ns);
+        sums[index].0 += ns as f64;
+        sums[index].1 += ns as f64 * ns as f64;
+    }
+
+    for (report, (sum, sum_sq)) in reports.iter_mut().zip(sums) {
+        if report.count == 0 {
+            continue;
+        }
+        let n = report.count as f64;
+        let mean = sum / n;
+        report.mean_ns = mean as f32;
+        report.std_ns = sqrt((sum_sq / n - mean * mean).max(0.0)) as f32;
+    }
+
+    for pulse in capture.pulses(edges) {
+        let Some((index, ns)) = classify(pulse) else {
+            continue;
+        };
+        let report = &mut reports[index];
+        let bin_ns = classes[index].bin_ns.max(1) as f32;
+        // 四舍五入到最近的 bin；core 中没有 floor，as 向 0 截断，负数需要再减 1
+        let scaled = (ns as f32 - report.mean_ns) / bin_ns + 0.5;
+        let mut offset = scaled as i32;
+        if (offset as f32) > scaled {
+            offset -= 1;
+        }
+        let bin = offset + (HIST_BINS / 2) as i32;
+        if bin < 0 {
+            report.below += 1;
+        } else if bin >= HIST_BINS as i32 {
+            report.above += 1;
+        } else {
+            report.hist[bin as usize] += 1;
+        }
+    }
+
+    (reports, unmatched)
+}
+
+// core 中没有 sqrt，这里也没有引入 libm，用牛顿迭代，统计的精度足够了
+fn sqrt(x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let mut y = if x > 1.0 { x / 2.0 } else { 1.0 };
+    for _ in 0..32 {
+        y = (y + x / y) / 2.0;
+    }
+    y
+}
+
+// 每一类打印一段：数量、最小、最大、平均、标准差、峰峰值，之后是直方图，每个 bin 一行
+pub(crate) fn report(classes: &[Class], reports: &[ClassReport], unmatched: u32) {
+    const BAR: &str = "########################################";
+
+    for (class, report) in classes.iter().zip(reports) {
+        if report.count == 0 {
+            rprintln!("{:<6} no pulse", class.name);
+            continue;
+        }
+        rprintln!(
+            "{:<6} n {:>5}  min {:>6} ns  max {:>6} ns  mean {:>8.1} ns  std {:>6.1} ns  p-p {:>5} ns",
+            class.name,
+            report.count,
+            report.min_ns,
+            report.max_ns,
+            report.mean_ns,
+            report.std_ns,
+            report.jitter_ns()
+        );
+
+        let peak = report.hist.iter().copied().max().unwrap_or(0).max(1);
+        let half = (HIST_BINS / 2) as i32;
+        let bin_ns = class.bin_ns as i32;
+        if report.below > 0 {
+            rprintln!("       <{:>+7} ns {:>5}", -half * bin_ns, report.below);
+        }
+        for (bin, &count) in report.hist.iter().enumerate() {
+            let len = (count as usize * BAR.len()).div_ceil(peak as usize);
+            rprintln!(
+                "        {:>+7} ns {:>5} {}",
+                (bin as i32 - half) * bin_ns,
+                count,
+                &BAR[..len]
+            );
+        }
+        if report.above > 0 {
+            rprintln!("       >{:>+7} ns {:>5}", half * bin_ns, report.above);
+        }
+    }
+    rprintln!("unmatched {}", unmatched);
+}
@@ -0,0 +1,435 @@
+//! 4 线 PC 风扇的转速测量与闭环控制
+//!
+//! 4 线风扇的 PWM 脚在风扇内部上拉，要求约 25 kHz 的 PWM；TACH 脚为开漏输出，每转一圈输出 PULSES_PER_REV 个脉冲
+//!
+//! 用到的 TIM：
+//!
+//! - TIM3_CH1（PA6）：25 kHz 的 PWM，引脚为开漏输出，高电平由风扇内部的上拉给出
+//! - TIM5_CH1（PA0）：32 bit，1 MHz 自由计数，在 TACH 的上升沿捕获，两次捕获之差就是一个脉冲周期，71 分钟才溢出一次，
+//!   同时 TIM5 的 CNT 也作为控制循环的时钟（now_us）
+//!
+//! TACH 上的毛刺：
+//!
+//! TACH 的走线往往与 PWM 的走线挨在一起，PWM 翻转时会在 TACH 上感应出很窄的毛刺，这里分两层过滤：
+//!
+//! 1. 输入滤波器 IC1F：CKD 4 分频，fDTS = 3 MHz，IC1F = 0b1111 时以 fDTS / 32 采样，连续 8 次一致才算一个边沿，
+//!    短于约 85 us 的脉冲直接被滤掉；正常 TACH 脉冲的宽度至少在 1 ms 以上，不受影响
+//! 2. 软件：与上一个周期相比短了一半以上的周期，不可能是风扇的真实转速变化，当作毛刺丢弃，并且不更新“上一个边沿”的时间，
+//!    这样下一个真实的边沿算出来的仍是完整的周期
+//!
+//! 控制：
+//!
+//! FanController 按固定周期调用 update，给出新的占空比：
+//!
+//! - PI 控制转速，积分项限制在 MIN_RUN_DUTY ~ 100，输出饱和时停止积分，避免积分饱和
+//! - 风扇静止时，低占空比往往转不起来，所以每次启动都先以 100% 占空比“踢”一下（Kickstart），有转速之后再交给 PI
+//! - 运行中超过 STALL_TIMEOUT_US 没有 TACH 脉冲，认为堵转：先停 RETRY_DELAY_US，再重新 Kickstart，
+//!   连续 MAX_RETRIES 次都转不起来则进入 Fault，占空比保持为 0，直到重新设置目标转速或者调用 reset
+//!
+//! 注意：不少风扇在 0% 占空比时仍会以最低转速运转，这里目标转速为 0 时只是输出 0%，是否真的停转取决于风扇
+//!
+//! TIM5 的中断需要在 bin 中 unmask，并在中断处理函数中调用 on_tach_capture
+
+#![allow(dead_code)]
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{self, Peripherals};
+
+use super::{
+    clock_gate::{self, gates},
+    pin_registry,
+    port::Port,
+};
+
+// 使用 HSE，APB1 不分频，所有 TIM 的时钟都是 12 MHz
+pub(crate) const TIM_CLK_HZ: u32 = 12_000_000;
+
+// Intel 的 4 线风扇规范：PWM 为 25 kHz，每转 2 个 TACH 脉冲
+const PWM_HZ: u32 = 25_000;
+const PWM_PERIOD: u32 = TIM_CLK_HZ / PWM_HZ;
+pub(crate) const PULSES_PER_REV: u32 = 2;
+
+// 超过 20000 RPM 的周期一定是毛刺
+const MIN_PULSE_PERIOD_US: u32 = 60_000_000 / (20_000 * PULSES_PER_REV);
+// 这么久没有 TACH 脉冲即认为转速为 0，同时也决定了能测量的最低转速（约 60 RPM）
+pub(crate) const STALL_TIMEOUT_US: u32 = 500_000;
+
+// 低于这个占空比，大多数风扇都转不动
+pub(crate) const MIN_RUN_DUTY: f32 = 20.0;
+const KICK_DUTY: f32 = 100.0;
+const KICK_US: u32 = 1_000_000;
+const RETRY_DELAY_US: u32 = 2_000_000;
+pub(crate) const MAX_RETRIES: u8 = 3;
+// 稳定运行这么久之后，重新计算重试次数
+const RETRY_RESET_US: u32 = 10_000_000;
+
+// TACH 的测量结果，在 TIM5 中断中累加，在 read_rpm 中取走
+struct Tach {
+    last_edge: Option<u32>,
+    last_period: u32,
+    sum: u32,
+    count: u32,
+    glitches: u32,
+}
+
+static G_TACH: Mutex<RefCell<Tach>> = Mutex::new(RefCell::new(Tach {
+    last_edge: None,
+    last_period: 0,
+    sum: 0,
+    count: 0,
+    glitches: 0,
+}));
+
+// PA6 TIM3_CH1 开漏输出 PWM，PA0 TIM5_CH1 上拉输入 TACH，占空比初始为 0
+pub(crate) fn setup(dp: &Peripherals) {
+    clock_gate::claim(gates::GPIOA);
+    clock_gate::claim(gates::TIM3);
+    clock_gate::claim(gates::TIM5);
+    pin_registry::claim_pins(Port::A, &[0, 6], "fan");
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| {
+        w.afrl0().af2();
+        w.afrl6().af2();
+        w
+    });
+    gpioa.otyper.modify(|_, w| w.ot6().open_drain());
+    // TACH 最好再外接一个 10k 上拉到 3.3V，内部上拉约 40k，边沿比较慢
+    gpioa.pupdr.modify(|_, w| w.pupdr0().pull_up());
+    gpioa.moder.modify(|_, w| {
+        w.moder0().alternate();
+        w.moder6().alternate();
+        w
+    });
+
+    let tim3 = &dp.TIM3;
+    tim3.arr.write(|w| w.arr().bits((PWM_PERIOD - 1) as u16));
+    tim3.ccr1().write(|w| w.ccr().bits(0));
+    tim3.ccmr1_output().modify(|_, w| {
+        w.cc1s().output();
+        w.oc1m().pwm_mode1();
+        w.oc1pe().enabled();
+        w
+    });
+    tim3.cr1.modify(|_, w| w.arpe().enabled());
+    tim3.ccer.modify(|_, w| w.cc1e().set_bit());
+    tim3.cr1.modify(|_, w| w.cen().enabled());
+
+    let tim5 = &dp.TIM5;
+    tim5.cr1.modify(|_, w| w.ckd().div4());
+    tim5.psc
+        .write(|w| w.psc().bits((TIM_CLK_HZ / 1_000_000 - 1) as u16));
+    tim5.arr.write(|w| w.arr().bits(u32::MAX));
+    tim5.ccmr1_input().modify(|_, w| unsafe {
+        w.cc1s().bits(0b01);
+        w.ic1f().bits(0b1111);
+        w
+    });
+    // 上升沿捕获
+    tim5.ccer.modify(|_, w| {
+        w.cc1p().clear_bit();
+        w.cc1np().clear_bit();
+        w.cc1e().set_bit();
+        w
+    });
+    tim5.egr.write(|w| w.ug().set_bit());
+    tim5.sr.modify(|_, w| w.cc1if().clear_bit());
+    tim5.dier.modify(|_, w| w.cc1ie().enabled());
+    tim5.cr1.modify(|_, w| w.cen().enabled());
+}
+
+// duty 为 0 ~ 100 的百分比，下一个 PWM 周期生效
+pub(crate) fn set_duty(duty: f32) {
+    let tim3 = unsafe { &*pac::TIM3::ptr() };
+    let ccr = (PWM_PERIOD as f32 * duty.clamp(0.0, 100.0) / 100.0) as u16;
+    tim3.ccr1().write(|w| w.ccr().bits(ccr));
+}
+
+// TIM5 的计数值，1 us 一个计数
+pub(crate) fn now_us() -> u32 {
+    let tim5 = unsafe { &*pac::TIM5::ptr() };
+    tim5.cnt.read().bits()
+}
+
+// 在 TIM5 中断中调用
+pub(crate) fn on_tach_capture() {
+    let tim5 = unsafe { &*pac::TIM5::ptr() };
+    if tim5.sr.read().cc1if().bit_is_clear() {
+        return;
+    }
+    // 读取 CCR1 会清除 CC1IF
+    let edge = tim5.ccr1().read().bits();
+
+    cortex_m::interrupt::free(|cs| {
+        let mut tach = G_TACH.borrow(cs).borrow_mut();
+        let last = match tach.last_edge {
+            Some(last) => last,
+            // 停转之后的第一个边沿，只作为起点
+            None => {
+                tach.last_edge = Some(edge);
+                return;
+            }
+        };
+
+        let period = edge.wrapping_sub(last);
+        if period < MIN_PULSE_PERIOD_US || period < tach.last_period / 2 {
+            tach.glitches += 1;
+            return;
+        }
+        tach.last_edge = Some(edge);
+        tach.last_period = period;
+        tach.sum += period;
+        tach.count += 1;
+    });
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TachReading {
+    // 距离上次读取之间的平均转速，停转时为 0
+    pub(crate) rpm: f32,
+    // 这段时间内有效的脉冲周期个数，为 0 表示已经停转
+    pub(crate) pulses: u32,
+    // 累计丢弃的毛刺个数
+    pub(crate) glitches: u32,
+}
+
+// 取走上次读取以来的测量结果，没有新的脉冲时返回 None（转得很慢，或者刚刚启动）
+pub(crate) fn read_rpm() -> Option<TachReading> {
+    let now = now_us();
+    cortex_m::interrupt::free(|cs| {
+        let mut tach = G_TACH.borrow(cs).borrow_mut();
+        let glitches = tach.glitches;
+
+        if let Some(average) = tach.sum.checked_div(tach.count) {
+            let reading = TachReading {
+                rpm: 60_000_000.0 / (average * PULSES_PER_REV) as f32,
+                pulses: tach.count,
+                glitches,
+            };
+            tach.sum = 0;
+            tach.count = 0;
+            return Some(reading);
+        }
+
+        let stalled = tach
+            .last_edge
+            .is_none_or(|last| now.wrapping_sub(last) > STALL_TIMEOUT_US);
+        if !stalled {
+            return None;
+        }
+        // 重新开始测量，下一个边沿不与很久以前的边沿求差
+        tach.last_edge = None;
+        tach.last_period = 0;
+        Some(TachReading {
+            rpm: 0.0,
+            pulses: 0,
+            glitches,
+        })
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FanState {
+    Off,
+    // 以 KICK_DUTY 启动，直到 KICK_US 之后检查是否转起来了
+    Kickstart,
+    Running,
+    // 堵转之后，停止一段时间再重试
+    Retry,
+    // 重试次数用完
+    Fault,
+}
+
+pub(crate) struct FanController {
+    target_rpm: f32,
+    // 为 Some 时不使用 PI，直接输出这个占空比，堵转检测依然有效
+    manual_duty: Option<f32>,
+    kp: f32,
+    ki: f32,
+    integral: f32,
+
+    rpm: f32,
+    duty: f32,
+    state: FanState,
+    since_us: u32,
+    last_us: u32,
+    retries: u8,
+}
+
+impl FanController {
+    // kp 的单位为 %/RPM，ki 的单位为 %/(RPM·s)
+    pub(crate) fn new(kp: f32, ki: f32, now_us: u32) -> Self {
+        Self {
+            target_rpm: 0.0,
+            manual_duty: None,
+            kp,
+            ki,
+            integral: MIN_RUN_DUTY,
+            rpm: 0.0,
+            duty: 0.0,
+            state: FanState::Off,
+            since_us: now_us,
+            last_us: now_us,
+            retries: 0,
+        }
+    }
+
+    // 设置目标转速，回到闭环控制；同时清除 Fault
+    pub(crate) fn set_target(&mut self, rpm: f32) {
+        self.target_rpm = rpm.max(0.0);
+        self.manual_duty = None;
+        self.clear_fault();
+    }
+
+    // 开环输出固定的占空比，None 则回到闭环控制
+    pub(crate) fn set_manual(&mut self, duty: Option<f32>) {
+        self.manual_duty = duty.map(|duty| duty.clamp(0.0, 100.0));
+        self.clear_fault();
+    }
+
+    pub(crate) fn set_gains(&mut self, kp: f32, ki: f32) {
+        self.kp = kp;
+        self.ki = ki;
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.clear_fault();
+    }
+
+    pub(crate) fn target(&self) -> f32 {
+        self.target_rpm
+    }
+
+    pub(crate) fn manual(&self) -> Option<f32> {
+        self.manual_duty
+    }
+
+    pub(crate) fn gains(&self) -> (f32, f32) {
+        (self.kp, self.ki)
+    }
+
+    pub(crate) fn rpm(&self) -> f32 {
+        self.rpm
+    }
+
+    pub(crate) fn duty(&self) -> f32 {
+        self.duty
+    }
+
+    pub(crate) fn state(&self) -> FanState {
+        self.state
+    }
+
+    pub(crate) fn retries(&self) -> u8 {
+        self.retries
+    }
+
+    // 按固定周期调用，reading 为 read_rpm 的结果，返回新的占空比
+    pub(crate) fn update(&mut self, now_us: u32, reading: Option<TachReading>) -> f32 {
+        if let Some(reading) = reading {
+            self.rpm = reading.rpm;
+        }
+        let dt = now_us.wrapping_sub(self.last_us) as f32 / 1_000_000.0;
+        self.last_us = now_us;
+        let elapsed = now_us.wrapping_sub(self.since_us);
+
+        let wanted = match self.manual_duty {
+            Some(duty) => duty > 0.0,
+            None => self.target_rpm > 0.0,
+        };
+
+        self.duty = match self.state {
+            FanState::Off => {
+                if wanted {
+                    self.enter(FanState::Kickstart, now_us);
+                    KICK_DUTY
+                } else {
+                    0.0
+                }
+            }
+            FanState::Kickstart => {
+                if !wanted {
+                    self.enter(FanState::Off, now_us);
+                    0.0
+                } else if elapsed < KICK_US {
+                    KICK_DUTY
+                } else if self.rpm > 0.0 {
+                    // 从最低占空比开始积分，而不是从 100% 慢慢降下来
+                    self.integral = MIN_RUN_DUTY;
+                    self.enter(FanState::Running, now_us);
+                    self.output(dt)
+                } else {
+                    self.stalled(now_us)
+                }
+            }
+            FanState::Running => {
+                if !wanted {
+                    self.enter(FanState::Off, now_us);
+                    0.0
+                } else if self.rpm == 0.0 {
+                    self.stalled(now_us)
+                } else {
+                    if elapsed > RETRY_RESET_US {
+                        self.retries = 0;
+                    }
+                    self.output(dt)
+                }
+            }
+            FanState::Retry => {
+                if !wanted {
+                    self.enter(FanState::Off, now_us);
+                    0.0
+                } else if elapsed >= RETRY_DELAY_US {
+                    self.enter(FanState::Kickstart, now_us);
+                    KICK_DUTY
+                } else {
+                    0.0
+                }
+            }
+            FanState::Fault => 0.0,
+        };
+        self.duty
+    }
+
+    fn enter(&mut self, state: FanState, now_us: u32) {
+        self.state = state;
+        self.since_us = now_us;
+    }
+
+    fn stalled(&mut self, now_us: u32) -> f32 {
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.enter(FanState::Fault, now_us);
+        } else {
+            self.enter(FanState::Retry, now_us);
+        }
+        0.0
+    }
+
+    fn clear_fault(&mut self) {
+        self.retries = 0;
+        if self.state == FanState::Fault {
+            self.state = FanState::Off;
+        }
+    }
+
+    // 闭环时为 PI 的输出，开环时为设置的占空比
+    fn output(&mut self, dt: f32) -> f32 {
+        if let Some(duty) = self.manual_duty {
+            return duty;
+        }
+
+        let error = self.target_rpm - self.rpm;
+        let unclamped = self.kp * error + self.integral;
+        let duty = unclamped.clamp(MIN_RUN_DUTY, 100.0);
+        // 输出已经饱和、且误差还在往饱和的方向推时，不再积分
+        let saturated =
+            (unclamped > 100.0 && error > 0.0) || (unclamped < MIN_RUN_DUTY && error < 0.0);
+        if !saturated {
+            self.integral = (self.integral + self.ki * error * dt).clamp(MIN_RUN_DUTY, 100.0);
+        }
+        duty
+    }
+}
@@ -0,0 +1,301 @@
+//! 双路直流电机驱动：L298N 与 TB6612FNG 模块
+//!
+//! 两种模块的用法几乎一样，每个电机一路 PWM 加两个方向引脚 IN1/IN2，区别在于刹车与滑行（coast）的方式：
+//!
+//! | 模块   | 正转               | 反转               | 刹车（两端短接）       | 滑行（两端悬空） |
+//! | ------ | ------------------ | ------------------ | ---------------------- | ---------------- |
+//! | L298N  | IN1=H IN2=L EN=PWM | IN1=L IN2=H EN=PWM | IN1=IN2=L，EN=H        | EN=L             |
+//! | TB6612 | IN1=H IN2=L PWM    | IN1=L IN2=H PWM    | IN1=IN2=H，PWM 任意    | IN1=IN2=L        |
+//!
+//! PWM 的低电平期间，L298N 的桥臂全部关断，电机滑行；TB6612 则是下桥短接，电机刹车（slow decay）
+//! 所以同样的占空比，TB6612 上的转速随占空比的变化更线性，L298N 在低占空比时转速掉得更厉害
+//!
+//! TB6612 还有一个 STBY 引脚，低电平时整个芯片进入待机，两路输出都是高阻态，耗电不到 1 uA；
+//! 这里在两个电机都处于滑行、且速度已经降到 0 时自动进入待机，任意一个电机需要输出时自动退出待机
+//!
+//! 速度的变化率限制：
+//!
+//! set_speed 只修改目标速度，update 每次最多把实际速度向目标移动 slew_per_s * dt，
+//! 避免突然满速启动或者直接反转时，电机的冲击电流拉垮电源（L298N 上的压降本来就大，电池电压一跌，单片机就复位了）
+//! 反转时速度会先经过 0，到 0 的那一刻才切换方向引脚
+//!
+//! 用到的 TIM：TIM3_CH1（PA6）为电机 A 的 PWM，TIM3_CH2（PA7）为电机 B 的 PWM
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::{self, Peripherals};
+
+use super::{
+    clock_gate::{self, gates},
+    pin_registry,
+    port::Port,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Pin {
+    pub(crate) port: Port,
+    pub(crate) pin: u8,
+}
+
+impl Pin {
+    pub(crate) const fn new(port: Port, pin: u8) -> Self {
+        Self { port, pin }
+    }
+
+    fn setup(self) {
+        clock_gate::claim(self.port.gate());
+        self.port.set_output(self.pin, "hbridge");
+    }
+
+    fn write(self, high: bool) {
+        let bit = if high { self.pin } else { self.pin + 16 };
+        self.port.regs().bsrr.write(|w| unsafe { w.bits(1 << bit) });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Chip {
+    L298n,
+    Tb6612 { stby: Pin },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MotorPins {
+    pub(crate) in1: Pin,
+    pub(crate) in2: Pin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Motor {
+    // TIM3_CH1
+    A,
+    // TIM3_CH2
+    B,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Mode {
+    // -1.0 ~ 1.0，正数为正转
+    Drive(f32),
+    Brake,
+    Coast,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    pins: MotorPins,
+    mode: Mode,
+    // 经过变化率限制的实际速度，Brake 与 Coast 时依旧按变化率降到 0，用于判断能否待机、能否换向
+    speed: f32,
+}
+
+pub(crate) struct DualMotor {
+    chip: Chip,
+    channels: [Channel; 2],
+    period: u16,
+    // 每秒最多变化多少，1.0 表示从 0 到满速需要 1 秒
+    slew_per_s: f32,
+    standby: bool,
+}
+
+impl DualMotor {
+    // 两个电机初始都处于滑行状态，TB6612 处于待机
+    pub(crate) fn new(
+        dp: &Peripherals,
+        chip: Chip,
+        pins: [MotorPins; 2],
+        tim_clk_hz: u32,
+        pwm_hz: u32,
+        slew_per_s: f32,
+    ) -> Self {
+        for motor in pins {
+            motor.in1.setup();
+            motor.in2.setup();
+        }
+        if let Chip::Tb6612 { stby } = chip {
+            stby.setup();
+        }
+
+        clock_gate::claim(gates::GPIOA);
+        clock_gate::claim(gates::TIM3);
+        pin_registry::claim_pins(Port::A, &[6, 7], "hbridge");
+
+        let gpioa = &dp.GPIOA;
+        gpioa.afrl.modify(|_, w| {
+            w.afrl6().af2();
+            w.afrl7().af2();
+            w
+        });
+        gpioa.moder.modify(|_, w| {
+            w.moder6().alternate();
+            w.moder7().alternate();
+            w
+        });
+
+        // L298N 的开关速度很慢，PWM 最好不超过 20 kHz；TB6612 可以到 100 kHz
+        let period = (tim_clk_hz / pwm_hz).min(u16::MAX as u32) as u16;
+        let tim3 = &dp.TIM3;
+        tim3.arr.write(|w| w.arr().bits(period - 1));
+        tim3.ccr1().write(|w| w.ccr().bits(0));
+        tim3.ccr2().write(|w| w.ccr().bits(0));
+        tim3.ccmr1_output().modify(|_, w| {
+            w.cc1s().output();
+            w.oc1m().pwm_mode1();
+            w.oc1pe().enabled();
+            w.cc2s().output();
+            w.oc2m().pwm_mode1();
+            w.oc2pe().enabled();
+            w
+        });
+        tim3.cr1.modify(|_, w| w.arpe().enabled());
+        tim3.ccer.modify(|_, w| {
+            w.cc1e().set_bit();
+            w.cc2e().set_bit();
+            w
+        });
+        tim3.egr.write(|w| w.ug().set_bit());
+        tim3.cr1.modify(|_, w| w.cen().enabled());
+
+        let channel = |pins| Channel {
+            pins,
+            mode: Mode::Coast,
+            speed: 0.0,
+        };
+        let mut driver = Self {
+            chip,
+            channels: [channel(pins[0]), channel(pins[1])],
+            period,
+            slew_per_s,
+            standby: false,
+        };
+        for motor in [Motor::A, Motor::B] {
+            driver.apply(motor);
+        }
+        driver.set_standby(true);
+        driver
+    }
+
+    // 设置目标速度，-1.0 ~ 1.0，由 update 按变化率逐渐达到
+    pub(crate) fn set_speed(&mut self, motor: Motor, speed: f32) {
+        self.channels[motor as usize].mode = Mode::Drive(speed.clamp(-1.0, 1.0));
+    }
+
+    // 刹车与滑行立即生效，不受变化率限制
+    pub(crate) fn brake(&mut self, motor: Motor) {
+        self.channels[motor as usize].mode = Mode::Brake;
+        self.wake();
+        self.apply(motor);
+    }
+
+    pub(crate) fn coast(&mut self, motor: Motor) {
+        self.channels[motor as usize].mode = Mode::Coast;
+        self.apply(motor);
+    }
+
+    pub(crate) fn set_slew(&mut self, slew_per_s: f32) {
+        self.slew_per_s = slew_per_s;
+    }
+
+    pub(crate) fn mode(&self, motor: Motor) -> Mode {
+        self.channels[motor as usize].mode
+    }
+
+    // 当前实际输出的速度
+    pub(crate) fn speed(&self, motor: Motor) -> f32 {
+        self.channels[motor as usize].speed
+    }
+
+    pub(crate) fn is_standby(&self) -> bool {
+        self.standby
+    }
+
+    // 按固定周期调用，dt_s 为距离上一次调用的时间
+    pub(crate) fn update(&mut self, dt_s: f32) {
+        let step = self.slew_per_s * dt_s;
+        for motor in [Motor::A, Motor::B] {
+            let channel = &mut self.channels[motor as usize];
+            let target = match channel.mode {
+                Mode::Drive(speed) => speed,
+                // 刹车与滑行时实际上已经没有输出，这里只是让速度的记录按变化率回到 0，
+                // 这样刹车之后马上反转时，依旧会从 0 开始加速
+                Mode::Brake | Mode::Coast => 0.0,
+            };
+            // 反转时先停在 0，下一次 update 再往反方向走
+            let limit = if channel.speed * target < 0.0 {
+                0.0
+            } else {
+                target
+            };
+            channel.speed += (limit - channel.speed).clamp(-step, step);
+        }
+
+        let idle = self
+            .channels
+            .iter()
+            .all(|channel| channel.mode == Mode::Coast && channel.speed == 0.0);
+        if idle {
+            self.set_standby(true);
+        } else {
+            self.wake();
+        }
+
+        for motor in [Motor::A, Motor::B] {
+            self.apply(motor);
+        }
+    }
+
+    fn wake(&mut self) {
+        self.set_standby(false);
+    }
+
+    // 只有 TB6612 有 STBY 引脚，L298N 上只记录状态
+    fn set_standby(&mut self, standby: bool) {
+        if let Chip::Tb6612 { stby } = self.chip {
+            stby.write(!standby);
+        }
+        self.standby = standby;
+    }
+
+    // 按当前的模式与速度设置方向引脚与占空比
+    fn apply(&self, motor: Motor) {
+        let channel = &self.channels[motor as usize];
+        let MotorPins { in1, in2 } = channel.pins;
+
+        let duty = match (channel.mode, self.chip) {
+            (Mode::Drive(_), _) => {
+                // 速度为 0 时保持上一次的方向，等同于占空比为 0 的驱动
+                if channel.speed > 0.0 {
+                    in2.write(false);
+                    in1.write(true);
+                } else if channel.speed < 0.0 {
+                    in1.write(false);
+                    in2.write(true);
+                }
+                channel.speed.abs()
+            }
+            (Mode::Brake, Chip::L298n) => {
+                in1.write(false);
+                in2.write(false);
+                1.0
+            }
+            (Mode::Brake, Chip::Tb6612 { .. }) => {
+                in1.write(true);
+                in2.write(true);
+                0.0
+            }
+            (Mode::Coast, Chip::L298n) => 0.0,
+            (Mode::Coast, Chip::Tb6612 { .. }) => {
+                in1.write(false);
+                in2.write(false);
+                0.0
+            }
+        };
+
+        let ccr = (self.period as f32 * duty) as u16;
+        let tim3 = unsafe { &*pac::TIM3::ptr() };
+        match motor {
+            Motor::A => tim3.ccr1().write(|w| w.ccr().bits(ccr)),
+            Motor::B => tim3.ccr2().write(|w| w.ccr().bits(ccr)),
+        }
+    }
+}
@@ -0,0 +1,27 @@
+// 带 #[cfg] 的模块用到了部分芯片上没有的外设，用到它们的程序在开头写有 chip_caps::require!，见 chip_caps
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub(crate) mod advanced_tim;
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub(crate) mod bldc;
+pub(crate) mod chain;
+pub(crate) mod dma_burst;
+pub(crate) mod edge_capture;
+pub(crate) mod fan;
+pub(crate) mod hbridge;
+pub(crate) mod pin_registry;
+pub(crate) mod port;
+#[cfg(feature = "stm32f413")]
+pub(crate) mod siggen;
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub(crate) mod soft_pwm;
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub(crate) mod sync_start;
+pub(crate) mod tone_synth;
+pub(crate) mod vu_meter;
+pub(crate) mod ws2812_bitbang;
+
+// 与其它章节共用的模块，代码在工作区中单独的 crate 里
+// 与上面的模块一样，不是每个程序都会用到，因此允许未使用
+#[allow(unused_imports)]
+pub(crate) use mcu_common::{clock_gate, cycle_stats, pid, reg_batch, resources, ws2812};
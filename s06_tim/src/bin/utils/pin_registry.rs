@@ -0,0 +1,118 @@
+//! 引脚的占用登记
+//!
+//! 同一个引脚在不同的驱动中有不同的用途，比如 PA6 在 fan 中是 TIM3_CH1，在 bldc 中是 TIM1_BKIN，
+//! 两个驱动放在同一个程序里时，后初始化的那个会悄悄改掉前一个的设置，表现出来往往只是“某个功能不工作”，很难查
+//!
+//! 因此驱动在配置引脚之前，先通过 claim 登记自己的名字：
+//!
+//! - 引脚没有被登记过：记下使用者
+//! - 引脚已经被登记过：直接 panic，panic 信息中包含引脚与两个使用者的名字，比如
+//!   "PA6 claimed by hbridge, already owned by fan"
+//! - release：驱动不再使用这个引脚时取消登记，之后其他驱动可以重新 claim
+//!
+//! 与 clock_gate 的引用计数不同，引脚只能有一个使用者，同一个驱动重复 claim 同一个引脚也会 panic
+//!
+//! 登记只在 debug 构建（debug_assertions）中生效，release 构建中这些函数都是空的，不占用 RAM，也没有运行时开销
+//!
+//! 直接操作 GPIOx 寄存器、没有经过 claim 的代码，这里当然是检查不到的
+
+#![allow(dead_code)]
+
+#[cfg(debug_assertions)]
+use core::cell::RefCell;
+
+#[cfg(debug_assertions)]
+use cortex_m::interrupt::Mutex;
+use rtt_target::rprintln;
+
+use super::port::{Port, PORTS};
+
+const PINS: usize = 16;
+
+#[cfg(debug_assertions)]
+static G_OWNERS: Mutex<RefCell<[[Option<&'static str>; PINS]; PORTS]>> =
+    Mutex::new(RefCell::new([[None; PINS]; PORTS]));
+
+// 登记 owner 使用这个引脚，引脚已经被登记过时 panic
+pub(crate) fn claim(port: Port, pin: u8, owner: &'static str) {
+    assert!(
+        (pin as usize) < PINS,
+        "P{}{} is not a pin",
+        port.name(),
+        pin
+    );
+
+    #[cfg(debug_assertions)]
+    cortex_m::interrupt::free(|cs| {
+        let mut owners = G_OWNERS.borrow(cs).borrow_mut();
+        let slot = &mut owners[port.index()][pin as usize];
+        if let Some(previous) = *slot {
+            panic!(
+                "P{}{} claimed by {}, already owned by {}",
+                port.name(),
+                pin,
+                owner,
+                previous
+            );
+        }
+        *slot = Some(owner);
+    });
+    #[cfg(not(debug_assertions))]
+    let _ = owner;
+}
+
+// 同一个端口上的多个引脚
+pub(crate) fn claim_pins(port: Port, pins: &[u8], owner: &'static str) {
+    for &pin in pins {
+        claim(port, pin, owner);
+    }
+}
+
+// 取消登记，没有登记过时什么也不做
+pub(crate) fn release(port: Port, pin: u8) {
+    #[cfg(debug_assertions)]
+    cortex_m::interrupt::free(|cs| {
+        if let Some(slot) = G_OWNERS
+            .borrow(cs)
+            .borrow_mut()
+            .get_mut(port.index())
+            .and_then(|pins| pins.get_mut(pin as usize))
+        {
+            *slot = None;
+        }
+    });
+    #[cfg(not(debug_assertions))]
+    let _ = (port, pin);
+}
+
+#[cfg(debug_assertions)]
+pub(crate) fn owner(port: Port, pin: u8) -> Option<&'static str> {
+    cortex_m::interrupt::free(|cs| {
+        G_OWNERS
+            .borrow(cs)
+            .borrow()
+            .get(port.index())
+            .and_then(|pins| pins.get(pin as usize).copied())
+            .flatten()
+    })
+}
+
+// release 构建中不做登记，总是返回 None
+#[cfg(not(debug_assertions))]
+pub(crate) fn owner(_port: Port, _pin: u8) -> Option<&'static str> {
+    None
+}
+
+// 打印所有登记过的引脚与它们的使用者
+pub(crate) fn report() {
+    rprintln!("claimed pins:\r");
+    for port in Port::ALL {
+        for pin in 0..PINS as u8 {
+            if let Some(owner) = owner(port, pin) {
+                rprintln!("  P{}{:<2} {}\r", port.name(), pin, owner);
+            }
+        }
+    }
+    #[cfg(not(debug_assertions))]
+    rprintln!("  (pin registry is disabled in release builds)\r");
+}
@@ -0,0 +1,85 @@
+//! 按运行时给定的端口号与引脚号访问 GPIO
+//!
+//! pac 中 GPIOA ~ GPIOH 是不同的类型，驱动想要“任意引脚”时没法写成一个函数；
+//! 好在它们的寄存器布局完全相同，依次相隔 0x400，这里统一按照 GPIOA 的 RegisterBlock 访问
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac;
+
+use super::{
+    clock_gate::{Bus, Gate},
+    pin_registry,
+};
+
+pub(crate) const PORTS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Port {
+    A = 0,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+}
+
+impl Port {
+    pub(crate) const ALL: [Port; PORTS] = [
+        Port::A,
+        Port::B,
+        Port::C,
+        Port::D,
+        Port::E,
+        Port::F,
+        Port::G,
+        Port::H,
+    ];
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+
+    // 'A' ~ 'H'
+    pub(crate) fn name(self) -> char {
+        (b'A' + self as u8) as char
+    }
+
+    pub(crate) fn gate(self) -> Gate {
+        Gate::new(Bus::Ahb1, self as u8)
+    }
+
+    // GPIOA ~ GPIOH 依次相隔 0x400
+    pub(crate) fn base(self) -> usize {
+        0x4002_0000 + 0x400 * self as usize
+    }
+
+    pub(crate) fn regs(self) -> &'static pac::gpioa::RegisterBlock {
+        unsafe { &*(self.base() as *const pac::gpioa::RegisterBlock) }
+    }
+
+    // BSRR 的地址，在时序要求严格的地方直接 write_volatile
+    pub(crate) fn bsrr(self) -> *mut u32 {
+        (self.base() + 0x18) as *mut u32
+    }
+
+    // 以 owner 的名义登记引脚（见 utils::pin_registry），再把它设置为推挽输出并保持低电平，时钟需要调用者先 claim
+    pub(crate) fn set_output(self, pin: u8, owner: &'static str) {
+        pin_registry::claim(self, pin, owner);
+        let gpio = self.regs();
+        let shift = pin * 2;
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << (pin + 16)) });
+        gpio.otyper
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << pin)) });
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b01 << shift)) });
+    }
+
+    pub(crate) fn set_high_speed(self, pin: u8) {
+        self.regs()
+            .ospeedr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (0b11 << (pin * 2))) });
+    }
+}
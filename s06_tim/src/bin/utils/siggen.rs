@@ -0,0 +1,504 @@
+//! 用 TIM1 做一台简易的信号发生器，输出在 PA8（TIM1_CH1）
+//!
+//! 主要用来在另一块板子上测试输入捕获、测频之类的驱动，支持以下几种输出：
+//!
+//! 1. pwm：固定频率与占空比的 PWM
+//! 2. sweep：频率在 start ~ stop 之间线性扫描，到头之后从 start 重新开始（锯齿波扫频），
+//!    由 TIM6 每毫秒产生一次中断，更新 TIM1 的 PSC 与 ARR
+//! 3. burst：只输出 N 个脉冲，然后停止
+//!    这里用到了高级定时器才有的重复计数器 RCR：开启 One-Pulse Mode 之后，TIM1 要经过 RCR + 1 次溢出才会产生更新事件并停止，
+//!    也就是正好输出 RCR + 1 个周期。F413 上 TIM1 的 RCR 只有 8 bit，超过 256 个脉冲时，每 256 个脉冲在更新中断中重新启动一次，
+//!    两段之间会多出几微秒的中断延迟
+//! 4. pattern：以 1 us 为单位的“高 - 低”序列，循环输出，每个周期的更新中断中写入下一段的 ARR 与 CCR1，
+//!    由于 ARR 与 CCR1 都开启了预装载，写入的值在下一个周期才生效，因此输出的波形没有毛刺
+//! 5. passthru：TIM4 以 PWM 输入模式测量 PB6 上的信号，每个周期都把测得的周期与高电平时间原样复制到 TIM1，
+//!    两者都以 1 us 计数，可以跟随约 16 Hz ~ 100 kHz 的信号
+//!
+//! TIM1 为高级定时器，除了 CC1E 之外，还需要置位 BDTR 的 MOE，输出才会真正出现在引脚上
+//!
+//! 中断处理函数需要在 bin 中定义，并分别调用这里的 on_tim1_update、on_sweep_tick、on_capture
+
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering};
+
+use stm32f4xx_hal::pac::{self, Peripherals};
+
+use super::{pin_registry, port::Port};
+
+// 使用 HSE，APB1 与 APB2 都不分频，所有 TIM 的时钟都是 12 MHz
+pub(crate) const TIM_CLK_HZ: u32 = 12_000_000;
+
+// pattern 与 passthru 以 1 us 计数
+const US_PSC: u16 = (TIM_CLK_HZ / 1_000_000 - 1) as u16;
+
+// pattern 最多有几段“高 - 低”
+pub(crate) const PATTERN_MAX: usize = 8;
+
+// pattern 每一段都要进一次中断，太短的话中断来不及预装载下一段
+pub(crate) const SEGMENT_MIN_US: u32 = 20;
+
+// TIM1 的 RCR 只有 8 bit
+const RCR_MAX: u32 = 256;
+
+const SWEEP_TICK_HZ: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GenError {
+    // 频率为 0，或者高于 TIM_CLK_HZ / 2
+    FreqOutOfRange,
+    // 占空比不在 0 ~ 100 之间
+    BadDuty,
+    // pattern 为空，或者超过了 PATTERN_MAX 段
+    BadPattern,
+    // pattern 中某一段的总时长超过了 65536 us，或者短于 SEGMENT_MIN_US
+    SegmentOutOfRange,
+    // burst 的脉冲数为 0
+    ZeroCount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+    Idle,
+    Pwm,
+    Sweep,
+    Burst,
+    Pattern,
+    PassThrough,
+}
+
+impl Mode {
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            1 => Mode::Pwm,
+            2 => Mode::Sweep,
+            3 => Mode::Burst,
+            4 => Mode::Pattern,
+            5 => Mode::PassThrough,
+            _ => Mode::Idle,
+        }
+    }
+}
+
+// 一组 PSC 与 ARR，输出频率为 TIM_CLK_HZ / (PSC + 1) / (ARR + 1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Timing {
+    pub(crate) psc: u16,
+    pub(crate) arr: u16,
+}
+
+impl Timing {
+    // 选择能放得下的最小 PSC，这样 ARR 最大，占空比的分辨率也最高
+    pub(crate) fn for_freq(freq_hz: u32) -> Result<Self, GenError> {
+        if freq_hz == 0 || freq_hz > TIM_CLK_HZ / 2 {
+            return Err(GenError::FreqOutOfRange);
+        }
+        let ticks = TIM_CLK_HZ / freq_hz;
+        let psc = (ticks - 1) / 65536;
+        let arr = ticks / (psc + 1) - 1;
+        Ok(Self {
+            psc: psc as u16,
+            arr: arr as u16,
+        })
+    }
+
+    // 实际的输出频率，由于整除，与要求的频率会有些许偏差
+    pub(crate) fn freq_hz(&self) -> u32 {
+        TIM_CLK_HZ / ((self.psc as u32 + 1) * (self.arr as u32 + 1))
+    }
+
+    // PWM mode 1 下，CNT < CCR1 时输出高电平
+    fn ccr_for(&self, duty: f32) -> u16 {
+        let period = self.arr as u32 + 1;
+        ((period as f32 * duty / 100.0) as u32).min(0xFFFF) as u16
+    }
+}
+
+static G_MODE: AtomicU8 = AtomicU8::new(Mode::Idle as u8);
+
+// burst 还剩多少个脉冲没有启动
+static G_BURST_REMAINING: AtomicU32 = AtomicU32::new(0);
+
+// sweep 的参数，频率单位为 Hz，占空比为 0.1 %
+static G_SWEEP_START: AtomicU32 = AtomicU32::new(0);
+static G_SWEEP_STOP: AtomicU32 = AtomicU32::new(0);
+static G_SWEEP_MS: AtomicU32 = AtomicU32::new(1);
+static G_SWEEP_ELAPSED: AtomicU32 = AtomicU32::new(0);
+static G_SWEEP_DUTY: AtomicU32 = AtomicU32::new(500);
+
+// pattern 的每一段，高 16 bit 为高电平时间，低 16 bit 为低电平时间，单位 us
+static G_PATTERN: [AtomicU32; PATTERN_MAX] = [const { AtomicU32::new(0) }; PATTERN_MAX];
+static G_PATTERN_LEN: AtomicUsize = AtomicUsize::new(0);
+// 下一个要写入预装载寄存器的段
+static G_PATTERN_NEXT: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn mode() -> Mode {
+    Mode::from_bits(G_MODE.load(Ordering::Relaxed))
+}
+
+fn set_mode(mode: Mode) {
+    G_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+// PA8 切换到 AF01，配置 TIM1_CH1，TIM4 与 TIM6 的中断需要在 bin 中 unmask
+pub(crate) fn setup(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w
+    });
+    rcc.apb2enr.modify(|_, w| w.tim1en().enabled());
+    rcc.apb1enr.modify(|_, w| {
+        w.tim4en().enabled();
+        w.tim6en().enabled();
+        w
+    });
+
+    pin_registry::claim(Port::A, 8, "siggen");
+    dp.GPIOA.afrh.modify(|_, w| w.afrh8().af1());
+    dp.GPIOA.ospeedr.modify(|_, w| w.ospeedr8().high_speed());
+    dp.GPIOA.moder.modify(|_, w| w.moder8().alternate());
+
+    let tim1 = &dp.TIM1;
+    tim1.cr1.modify(|_, w| {
+        w.arpe().enabled();
+        // UG 只用来加载预装载寄存器，不产生更新中断
+        w.urs().counter_only();
+        w
+    });
+    tim1.ccmr1_output().reset();
+    tim1.ccmr1_output().modify(|_, w| {
+        w.cc1s().output();
+        w.oc1m().force_inactive();
+        w.oc1pe().enabled();
+        w
+    });
+    tim1.ccer.modify(|_, w| w.cc1e().set_bit());
+    tim1.bdtr.modify(|_, w| w.moe().set_bit());
+
+    // TIM6 为 sweep 提供 1 kHz 的中断
+    let tim6 = &dp.TIM6;
+    tim6.psc.write(|w| w.psc().bits(US_PSC));
+    tim6.arr
+        .write(|w| w.arr().bits((1_000_000 / SWEEP_TICK_HZ - 1) as u16));
+    tim6.egr.write(|w| w.ug().set_bit());
+    tim6.sr.modify(|_, w| w.uif().clear_bit());
+    tim6.dier.modify(|_, w| w.uie().enabled());
+
+    setup_pwm_input(dp);
+}
+
+// TIM4_CH1（PB6，AF02）的 PWM 输入模式：
+// CC1 在上升沿捕获周期，CC2 在下降沿捕获高电平时间，从模式为 reset mode，每个上升沿都把 CNT 清零
+fn setup_pwm_input(dp: &Peripherals) {
+    pin_registry::claim(Port::B, 6, "siggen");
+    dp.GPIOB.afrl.modify(|_, w| w.afrl6().af2());
+    dp.GPIOB.moder.modify(|_, w| w.moder6().alternate());
+
+    let tim4 = &dp.TIM4;
+    tim4.psc.write(|w| w.psc().bits(US_PSC));
+    tim4.arr.write(|w| w.arr().bits(0xFFFF));
+
+    tim4.ccmr1_input().reset();
+    tim4.ccmr1_input().modify(|_, w| unsafe {
+        // CC1 与 CC2 都接到 TI1
+        w.cc1s().bits(0b01);
+        w.cc2s().bits(0b10);
+        w.ic1f().bits(0b0011);
+        w
+    });
+    tim4.ccer.modify(|_, w| {
+        w.cc1p().clear_bit();
+        w.cc2p().set_bit();
+        w
+    });
+    tim4.smcr.modify(|_, w| unsafe {
+        // TS = TI1FP1，SMS = reset mode
+        w.ts().bits(0b101);
+        w.sms().bits(0b100);
+        w
+    });
+    tim4.dier.modify(|_, w| w.cc1ie().enabled());
+}
+
+// 停止所有输出，引脚保持低电平
+pub(crate) fn stop(dp: &Peripherals) {
+    set_mode(Mode::Idle);
+
+    dp.TIM6.cr1.modify(|_, w| w.cen().clear_bit());
+    dp.TIM4.cr1.modify(|_, w| w.cen().clear_bit());
+    dp.TIM4.ccer.modify(|_, w| {
+        w.cc1e().clear_bit();
+        w.cc2e().clear_bit();
+        w
+    });
+
+    let tim1 = &dp.TIM1;
+    tim1.cr1.modify(|_, w| {
+        w.cen().clear_bit();
+        w.opm().clear_bit();
+        w
+    });
+    tim1.dier.modify(|_, w| w.uie().disabled());
+    tim1.ccmr1_output().modify(|_, w| w.oc1m().force_inactive());
+    tim1.cnt.write(|w| w.cnt().bits(0));
+    tim1.rcr.write(|w| unsafe { w.rep().bits(0) });
+    tim1.sr.modify(|_, w| w.uif().clear_bit());
+}
+
+// 写入预装载寄存器，并用 UG 立即加载
+fn load(tim1: &pac::tim1::RegisterBlock, timing: Timing, ccr: u16) {
+    tim1.psc.write(|w| w.psc().bits(timing.psc));
+    tim1.arr.write(|w| w.arr().bits(timing.arr));
+    tim1.ccr1().write(|w| w.ccr().bits(ccr));
+    tim1.egr.write(|w| w.ug().set_bit());
+}
+
+fn check_duty(duty: f32) -> Result<(), GenError> {
+    if (0.0..=100.0).contains(&duty) {
+        Ok(())
+    } else {
+        Err(GenError::BadDuty)
+    }
+}
+
+// 返回实际的频率
+pub(crate) fn pwm(dp: &Peripherals, freq_hz: u32, duty: f32) -> Result<u32, GenError> {
+    check_duty(duty)?;
+    let timing = Timing::for_freq(freq_hz)?;
+
+    stop(dp);
+    let tim1 = &dp.TIM1;
+    load(tim1, timing, timing.ccr_for(duty));
+    tim1.ccmr1_output().modify(|_, w| w.oc1m().pwm_mode1());
+    set_mode(Mode::Pwm);
+    tim1.cr1.modify(|_, w| w.cen().enabled());
+
+    Ok(timing.freq_hz())
+}
+
+pub(crate) fn sweep(
+    dp: &Peripherals,
+    start_hz: u32,
+    stop_hz: u32,
+    period_ms: u32,
+    duty: f32,
+) -> Result<(), GenError> {
+    check_duty(duty)?;
+    let timing = Timing::for_freq(start_hz)?;
+    Timing::for_freq(stop_hz)?;
+
+    stop(dp);
+    G_SWEEP_START.store(start_hz, Ordering::Relaxed);
+    G_SWEEP_STOP.store(stop_hz, Ordering::Relaxed);
+    G_SWEEP_MS.store(period_ms.max(1), Ordering::Relaxed);
+    G_SWEEP_ELAPSED.store(0, Ordering::Relaxed);
+    G_SWEEP_DUTY.store((duty * 10.0) as u32, Ordering::Relaxed);
+
+    let tim1 = &dp.TIM1;
+    load(tim1, timing, timing.ccr_for(duty));
+    tim1.ccmr1_output().modify(|_, w| w.oc1m().pwm_mode1());
+    set_mode(Mode::Sweep);
+    tim1.cr1.modify(|_, w| w.cen().enabled());
+    dp.TIM6.cr1.modify(|_, w| w.cen().enabled());
+
+    Ok(())
+}
+
+pub(crate) fn burst(
+    dp: &Peripherals,
+    freq_hz: u32,
+    duty: f32,
+    count: u32,
+) -> Result<u32, GenError> {
+    check_duty(duty)?;
+    if count == 0 {
+        return Err(GenError::ZeroCount);
+    }
+    let timing = Timing::for_freq(freq_hz)?;
+
+    stop(dp);
+    let tim1 = &dp.TIM1;
+    // 停止时 CNT 为 0，PWM mode 1 会在 CNT < CCR1 时输出高电平，
+    // 因此这里改用 PWM mode 2：每个周期先输出低电平，再输出高电平，停止之后引脚保持低电平
+    let period = timing.arr as u32 + 1;
+    let high = timing.ccr_for(duty) as u32;
+    tim1.ccr1()
+        .write(|w| w.ccr().bits((period - high.min(period)) as u16));
+    tim1.psc.write(|w| w.psc().bits(timing.psc));
+    tim1.arr.write(|w| w.arr().bits(timing.arr));
+    tim1.ccmr1_output().modify(|_, w| w.oc1m().pwm_mode2());
+    tim1.cr1.modify(|_, w| w.opm().set_bit());
+
+    G_BURST_REMAINING.store(count, Ordering::Relaxed);
+    set_mode(Mode::Burst);
+    // 只有 burst 与 pattern 需要更新中断，其他模式下频率可能高达数 MHz，每个周期都进中断会让 CPU 忙不过来
+    tim1.dier.modify(|_, w| w.uie().enabled());
+    start_burst_chunk(tim1);
+
+    Ok(timing.freq_hz())
+}
+
+// 启动下一段不超过 256 个脉冲的 burst，没有剩余时返回 false
+fn start_burst_chunk(tim1: &pac::tim1::RegisterBlock) -> bool {
+    let remaining = G_BURST_REMAINING.load(Ordering::Relaxed);
+    if remaining == 0 {
+        return false;
+    }
+    let chunk = remaining.min(RCR_MAX);
+    G_BURST_REMAINING.store(remaining - chunk, Ordering::Relaxed);
+
+    tim1.rcr
+        .write(|w| unsafe { w.rep().bits((chunk - 1) as u8) });
+    tim1.egr.write(|w| w.ug().set_bit());
+    tim1.cr1.modify(|_, w| w.cen().enabled());
+    true
+}
+
+// segments 为若干段 (高电平 us, 低电平 us)，循环输出
+pub(crate) fn pattern(dp: &Peripherals, segments: &[(u32, u32)]) -> Result<(), GenError> {
+    if segments.is_empty() || segments.len() > PATTERN_MAX {
+        return Err(GenError::BadPattern);
+    }
+    for &(high, low) in segments {
+        if high + low < SEGMENT_MIN_US || high + low > 65536 {
+            return Err(GenError::SegmentOutOfRange);
+        }
+    }
+
+    stop(dp);
+    for (slot, &(high, low)) in G_PATTERN.iter().zip(segments) {
+        slot.store((high << 16) | low, Ordering::Relaxed);
+    }
+    G_PATTERN_LEN.store(segments.len(), Ordering::Relaxed);
+
+    let tim1 = &dp.TIM1;
+    let (psc_arr, ccr) = segment(0);
+    load(tim1, psc_arr, ccr);
+    // 第 0 段已经生效，预装载第 1 段，之后每次更新中断再预装载下一段
+    G_PATTERN_NEXT.store(1 % segments.len(), Ordering::Relaxed);
+    preload_next_segment(tim1);
+
+    tim1.ccmr1_output().modify(|_, w| w.oc1m().pwm_mode1());
+    set_mode(Mode::Pattern);
+    tim1.dier.modify(|_, w| w.uie().enabled());
+    tim1.cr1.modify(|_, w| w.cen().enabled());
+
+    Ok(())
+}
+
+fn segment(index: usize) -> (Timing, u16) {
+    let raw = G_PATTERN[index].load(Ordering::Relaxed);
+    let (high, low) = (raw >> 16, raw & 0xFFFF);
+    (
+        Timing {
+            psc: US_PSC,
+            arr: (high + low - 1) as u16,
+        },
+        high as u16,
+    )
+}
+
+fn preload_next_segment(tim1: &pac::tim1::RegisterBlock) {
+    let len = G_PATTERN_LEN.load(Ordering::Relaxed).max(1);
+    let next = G_PATTERN_NEXT.load(Ordering::Relaxed);
+    let (timing, ccr) = segment(next);
+    tim1.arr.write(|w| w.arr().bits(timing.arr));
+    tim1.ccr1().write(|w| w.ccr().bits(ccr));
+    G_PATTERN_NEXT.store((next + 1) % len, Ordering::Relaxed);
+}
+
+pub(crate) fn pass_through(dp: &Peripherals) {
+    stop(dp);
+
+    let tim1 = &dp.TIM1;
+    // 在收到第一个完整周期之前保持低电平
+    load(
+        tim1,
+        Timing {
+            psc: US_PSC,
+            arr: 0xFFFF,
+        },
+        0,
+    );
+    tim1.ccmr1_output().modify(|_, w| w.oc1m().pwm_mode1());
+    set_mode(Mode::PassThrough);
+    tim1.cr1.modify(|_, w| w.cen().enabled());
+
+    let tim4 = &dp.TIM4;
+    tim4.cnt.write(|w| w.cnt().bits(0));
+    tim4.sr.modify(|_, w| w.cc1if().clear_bit());
+    tim4.ccer.modify(|_, w| {
+        w.cc1e().set_bit();
+        w.cc2e().set_bit();
+        w
+    });
+    tim4.cr1.modify(|_, w| w.cen().enabled());
+}
+
+// 在 TIM1_UP_TIM10 中断中调用
+pub(crate) fn on_tim1_update() {
+    let tim1 = unsafe { &*pac::TIM1::ptr() };
+    if tim1.sr.read().uif().bit_is_clear() {
+        return;
+    }
+    tim1.sr.modify(|_, w| w.uif().clear_bit());
+
+    match mode() {
+        // 最后一段已经发送完，停止输出
+        Mode::Burst if !start_burst_chunk(tim1) => {
+            tim1.ccmr1_output().modify(|_, w| w.oc1m().force_inactive());
+            set_mode(Mode::Idle);
+        }
+        Mode::Pattern => preload_next_segment(tim1),
+        _ => {}
+    }
+}
+
+// 在 TIM6_DAC 中断中调用
+pub(crate) fn on_sweep_tick() {
+    let tim6 = unsafe { &*pac::TIM6::ptr() };
+    tim6.sr.modify(|_, w| w.uif().clear_bit());
+    if mode() != Mode::Sweep {
+        return;
+    }
+
+    let period_ms = G_SWEEP_MS.load(Ordering::Relaxed);
+    let elapsed = (G_SWEEP_ELAPSED.load(Ordering::Relaxed) + 1) % period_ms;
+    G_SWEEP_ELAPSED.store(elapsed, Ordering::Relaxed);
+
+    let start = G_SWEEP_START.load(Ordering::Relaxed) as i64;
+    let stop = G_SWEEP_STOP.load(Ordering::Relaxed) as i64;
+    let freq = start + (stop - start) * elapsed as i64 / period_ms as i64;
+    let Ok(timing) = Timing::for_freq(freq as u32) else {
+        return;
+    };
+    let duty = G_SWEEP_DUTY.load(Ordering::Relaxed) as f32 / 10.0;
+
+    // PSC 与 ARR 都有预装载，在 TIM1 的下一次更新事件时一起生效
+    let tim1 = unsafe { &*pac::TIM1::ptr() };
+    tim1.psc.write(|w| w.psc().bits(timing.psc));
+    tim1.arr.write(|w| w.arr().bits(timing.arr));
+    tim1.ccr1().write(|w| w.ccr().bits(timing.ccr_for(duty)));
+}
+
+// 在 TIM4 中断中调用
+pub(crate) fn on_capture() {
+    let tim4 = unsafe { &*pac::TIM4::ptr() };
+    let sr = tim4.sr.read();
+    if sr.cc1if().bit_is_clear() {
+        return;
+    }
+    // 读取 CCR1 会清除 CC1IF
+    let period = tim4.ccr1().read().ccr().bits() as u32;
+    let high = tim4.ccr2().read().ccr().bits();
+
+    if mode() != Mode::PassThrough || period == 0 {
+        return;
+    }
+
+    let tim1 = unsafe { &*pac::TIM1::ptr() };
+    tim1.arr.write(|w| w.arr().bits((period - 1) as u16));
+    tim1.ccr1().write(|w| w.ccr().bits(high));
+}
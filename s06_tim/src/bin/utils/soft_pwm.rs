@@ -0,0 +1,430 @@
+//! 用一个 TIM 的中断在任意 GPIO 上输出多路软件 PWM
+//!
+//! 硬件 PWM 通道用完了、或者引脚没有 TIM 复用功能时，LED、小功率加热片、风扇这类只需要 100 Hz ~ 1 kHz 的负载，
+//! 可以由 SoftPwm 在普通 GPIO 上输出，最多 MAX_CHANNELS 路，每一路有自己的占空比与相位
+//!
+//! 原理：
+//!
+//! 不是以固定的步长不停地进中断，而是把一个 PWM 周期按所有通道的翻转时刻切成若干段（时间片），
+//! 每段的开头进一次中断，通过 BSRR 一次性完成这一时刻所有端口上的拉高与拉低
+//!
+//! - TIM7 以 1 us 计数，每段时间片的长度写入 ARR；ARR 开启了预装载，中断里写入的是“下一段”的长度，
+//!   与 siggen 的 pattern 相同，当前这段的长度已经在上一次中断中装好了，不受中断延迟的影响
+//! - 一个周期的中断次数等于不同翻转时刻的个数，N 路最多 2N + 1 次，与占空比的分辨率无关
+//! - 中断延迟让所有翻转都推迟几乎相同的时间，对占空比没有影响
+//!
+//! 每一段都要进一次中断，两个翻转时刻相差不到 MIN_SLICE_US 时，后一个会提前到与前一个同时发生，
+//! 所以占空比与相位的实际误差最大为 MIN_SLICE_US；同一个引脚的两次翻转合并到一起时，以后一次为准，
+//! 比如占空比小于 MIN_SLICE_US 的通道会保持低电平
+//!
+//! 相位：
+//!
+//! 所有通道都在周期开头同时拉高的话，电源上的电流在这一刻突然增加 N 倍，调用 spread_phases 把各个通道的
+//! 拉高时刻在周期内均匀错开，可以把这个尖峰分散开
+//!
+//! 修改：
+//!
+//! set_duty、set_phase、set_freq 只修改 SoftPwm 中的设置，调用 apply 之后才会重新计算时间片，
+//! 中断在当前周期结束时换用新的时间片，所以一次 apply 中的所有修改在同一个周期生效，不会出现半个周期的毛刺
+//!
+//! TIM7 的中断需要在 bin 中 unmask，并在中断处理函数中调用 on_tim7
+
+#![allow(dead_code)]
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{self, Peripherals};
+
+use super::{
+    clock_gate::{self, gates},
+    port::{Port, PORTS},
+};
+
+// 使用 HSE，APB1 不分频，TIM7 的时钟为 12 MHz
+pub(crate) const TIM_CLK_HZ: u32 = 12_000_000;
+const TICK_HZ: u32 = 1_000_000;
+
+pub(crate) const MAX_CHANNELS: usize = 16;
+// 每个通道最多两个翻转时刻，再加上周期开头
+const MAX_BOUNDARIES: usize = MAX_CHANNELS * 2 + 1;
+
+// 一段时间片的最短长度，必须长于一次中断处理的时间
+pub(crate) const MIN_SLICE_US: u32 = 20;
+
+// 周期不超过 16 bit 的 ARR，也不能短到只剩几段时间片
+pub(crate) const MIN_FREQ_HZ: u32 = 20;
+pub(crate) const MAX_FREQ_HZ: u32 = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SoftPwmError {
+    InvalidPin,
+    // 这个引脚已经是另一个通道了
+    PinInUse,
+    TooManyChannels,
+    NoSuchChannel,
+    FreqOutOfRange,
+}
+
+// 周期中的一个时刻，以及这个时刻各个端口需要拉高、拉低的引脚
+#[derive(Clone, Copy)]
+struct Boundary {
+    at: u16,
+    set: [u16; PORTS],
+    reset: [u16; PORTS],
+}
+
+impl Boundary {
+    const fn at(at: u16) -> Self {
+        Self {
+            at,
+            set: [0; PORTS],
+            reset: [0; PORTS],
+        }
+    }
+
+    // 后加入的翻转覆盖同一个引脚上先加入的翻转
+    fn add(&mut self, port: Port, pin: u8, high: bool) {
+        let mask = 1 << pin;
+        let (set, reset) = (&mut self.set[port.index()], &mut self.reset[port.index()]);
+        if high {
+            *set |= mask;
+            *reset &= !mask;
+        } else {
+            *reset |= mask;
+            *set &= !mask;
+        }
+    }
+
+    fn apply(&self) {
+        for (port, (&set, &reset)) in Port::ALL.iter().zip(self.set.iter().zip(&self.reset)) {
+            let bits = set as u32 | ((reset as u32) << 16);
+            if bits != 0 {
+                unsafe { port.bsrr().write_volatile(bits) };
+            }
+        }
+    }
+}
+
+// 一个周期的所有时间片，第 0 个 Boundary 总是在周期开头
+#[derive(Clone, Copy)]
+struct Schedule {
+    period: u16,
+    len: usize,
+    boundaries: [Boundary; MAX_BOUNDARIES],
+}
+
+impl Schedule {
+    const EMPTY: Self = Self {
+        period: 0,
+        len: 0,
+        boundaries: [Boundary::at(0); MAX_BOUNDARIES],
+    };
+
+    // 第 index 个 Boundary 到下一个 Boundary（或周期结束）的时间
+    fn slice(&self, index: usize) -> u16 {
+        let end = if index + 1 < self.len {
+            self.boundaries[index + 1].at
+        } else {
+            self.period
+        };
+        end - self.boundaries[index].at
+    }
+}
+
+// 中断与 SoftPwm 共享的状态
+struct Engine {
+    active: Schedule,
+    // apply 之后、当前周期结束之前，新的时间片放在这里
+    pending: Option<Schedule>,
+    // 下一次中断对应的 Boundary
+    next: usize,
+}
+
+impl Engine {
+    const fn new() -> Self {
+        Self {
+            active: Schedule::EMPTY,
+            pending: None,
+            next: 0,
+        }
+    }
+
+    // 在第 next 个 Boundary 处翻转引脚，返回再下一段时间片的长度，用来预装载 ARR
+    fn advance(&mut self) -> u16 {
+        let index = self.next;
+        self.active.boundaries[index].apply();
+
+        self.next = index + 1;
+        if self.next == self.active.len {
+            // 一个周期结束，新的时间片从下一个周期开头生效
+            self.next = 0;
+            if let Some(schedule) = self.pending.take() {
+                self.active = schedule;
+            }
+        }
+        self.active.slice(self.next)
+    }
+}
+
+static G_ENGINE: Mutex<RefCell<Engine>> = Mutex::new(RefCell::new(Engine::new()));
+
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    port: Port,
+    pin: u8,
+    // 百分比
+    duty: f32,
+    phase: f32,
+}
+
+// 排序前的一次翻转，key 的最低位为 0 表示从上一个周期末尾挪到了开头，需要排在开头原有的翻转之前
+#[derive(Clone, Copy)]
+struct Edge {
+    key: u32,
+    port: Port,
+    pin: u8,
+    high: bool,
+}
+
+pub(crate) struct SoftPwm {
+    period: u16,
+    channels: [Option<Channel>; MAX_CHANNELS],
+    count: usize,
+    running: bool,
+}
+
+impl SoftPwm {
+    // 配置 TIM7，此时还没有开始输出
+    pub(crate) fn new(dp: &Peripherals, freq_hz: u32) -> Result<Self, SoftPwmError> {
+        let period = period_for(freq_hz)?;
+
+        clock_gate::claim(gates::TIM7);
+        let tim7 = &dp.TIM7;
+        tim7.cr1.modify(|_, w| {
+            w.arpe().enabled();
+            // UG 只用来加载预装载寄存器，不产生更新中断
+            w.urs().counter_only();
+            w
+        });
+        tim7.psc
+            .write(|w| w.psc().bits((TIM_CLK_HZ / TICK_HZ - 1) as u16));
+        tim7.dier.modify(|_, w| w.uie().enabled());
+
+        Ok(Self {
+            period,
+            channels: [None; MAX_CHANNELS],
+            count: 0,
+            running: false,
+        })
+    }
+
+    // 添加一路输出，引脚设置为推挽输出、低电平，返回通道号，初始占空比为 0
+    pub(crate) fn add(&mut self, port: Port, pin: u8) -> Result<usize, SoftPwmError> {
+        if pin > 15 {
+            return Err(SoftPwmError::InvalidPin);
+        }
+        if self.channels().any(|ch| ch.port == port && ch.pin == pin) {
+            return Err(SoftPwmError::PinInUse);
+        }
+        if self.count == MAX_CHANNELS {
+            return Err(SoftPwmError::TooManyChannels);
+        }
+
+        clock_gate::claim(port.gate());
+        port.set_output(pin, "soft_pwm");
+
+        let index = self.count;
+        self.channels[index] = Some(Channel {
+            port,
+            pin,
+            duty: 0.0,
+            phase: 0.0,
+        });
+        self.count += 1;
+        Ok(index)
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn freq_hz(&self) -> u32 {
+        TICK_HZ / self.period as u32
+    }
+
+    pub(crate) fn set_freq(&mut self, freq_hz: u32) -> Result<(), SoftPwmError> {
+        self.period = period_for(freq_hz)?;
+        Ok(())
+    }
+
+    // duty 为 0 ~ 100 的百分比
+    pub(crate) fn set_duty(&mut self, channel: usize, duty: f32) -> Result<(), SoftPwmError> {
+        self.channel_mut(channel)?.duty = duty.clamp(0.0, 100.0);
+        Ok(())
+    }
+
+    // phase 为拉高时刻在周期中的位置，0 ~ 100 的百分比
+    pub(crate) fn set_phase(&mut self, channel: usize, phase: f32) -> Result<(), SoftPwmError> {
+        self.channel_mut(channel)?.phase = phase.clamp(0.0, 100.0);
+        Ok(())
+    }
+
+    // 把各个通道的相位在周期内均匀错开
+    pub(crate) fn spread_phases(&mut self) {
+        let count = self.count as f32;
+        for (index, ch) in self.channels.iter_mut().flatten().enumerate() {
+            ch.phase = 100.0 * index as f32 / count;
+        }
+    }
+
+    // 按当前的设置重新计算时间片，输出中的话在当前周期结束时生效
+    pub(crate) fn apply(&mut self) {
+        let schedule = self.build();
+        cortex_m::interrupt::free(|cs| {
+            G_ENGINE.borrow(cs).borrow_mut().pending = Some(schedule);
+        });
+    }
+
+    // 一个周期中有几段时间片，也就是每个周期进几次中断
+    pub(crate) fn slices(&self) -> usize {
+        self.build().len
+    }
+
+    pub(crate) fn start(&mut self) {
+        if self.running {
+            return;
+        }
+
+        let tim7 = unsafe { &*pac::TIM7::ptr() };
+        let schedule = self.build();
+        cortex_m::interrupt::free(|cs| {
+            let mut engine = G_ENGINE.borrow(cs).borrow_mut();
+            engine.active = schedule;
+            engine.pending = None;
+            engine.next = 0;
+
+            // 第 0 段的长度直接加载到影子寄存器，然后由软件完成周期开头的翻转，并预装载第 1 段
+            tim7.cnt.write(|w| w.cnt().bits(0));
+            tim7.arr.write(|w| w.arr().bits(schedule.slice(0) - 1));
+            tim7.egr.write(|w| w.ug().set_bit());
+            let slice = engine.advance();
+            tim7.arr.write(|w| w.arr().bits(slice - 1));
+            tim7.sr.modify(|_, w| w.uif().clear_bit());
+            tim7.cr1.modify(|_, w| w.cen().enabled());
+        });
+        self.running = true;
+    }
+
+    // 停止输出，所有通道保持低电平
+    pub(crate) fn stop(&mut self) {
+        let tim7 = unsafe { &*pac::TIM7::ptr() };
+        // 清除 UIF 之后，已经挂起的中断进来也不会再翻转引脚
+        cortex_m::interrupt::free(|_| {
+            tim7.cr1.modify(|_, w| w.cen().disabled());
+            tim7.sr.modify(|_, w| w.uif().clear_bit());
+            for ch in self.channels() {
+                ch.port
+                    .regs()
+                    .bsrr
+                    .write(|w| unsafe { w.bits(1 << (ch.pin + 16)) });
+            }
+        });
+        self.running = false;
+    }
+
+    fn channels(&self) -> impl Iterator<Item = &Channel> {
+        self.channels.iter().flatten()
+    }
+
+    fn channel_mut(&mut self, channel: usize) -> Result<&mut Channel, SoftPwmError> {
+        self.channels
+            .get_mut(channel)
+            .and_then(Option::as_mut)
+            .ok_or(SoftPwmError::NoSuchChannel)
+    }
+
+    fn build(&self) -> Schedule {
+        let period = self.period as u32;
+        let ticks = |percent: f32| ((period as f32 * percent / 100.0 + 0.5) as u32).min(period);
+
+        // 每个通道转换为一次拉高与一次拉低，占空比为 0 或 100% 时只在周期开头给出固定的电平
+        let mut edges = [Edge {
+            key: 0,
+            port: Port::A,
+            pin: 0,
+            high: false,
+        }; MAX_CHANNELS * 2];
+        let mut count = 0;
+        let mut push = |at: u32, port: Port, pin: u8, high: bool| {
+            // 离周期结束太近的翻转挪到下一个周期的开头
+            let key = if at + MIN_SLICE_US > period {
+                0
+            } else {
+                at << 1 | 1
+            };
+            edges[count] = Edge {
+                key,
+                port,
+                pin,
+                high,
+            };
+            count += 1;
+        };
+        for ch in self.channels() {
+            let duty = ticks(ch.duty);
+            if duty == 0 || duty == period {
+                push(0, ch.port, ch.pin, duty == period);
+                continue;
+            }
+            let on = ticks(ch.phase) % period;
+            push(on, ch.port, ch.pin, true);
+            push((on + duty) % period, ch.port, ch.pin, false);
+        }
+
+        // 插入排序，最多 32 个元素，而且需要保持同一时刻的先后顺序
+        let edges = &mut edges[..count];
+        for i in 1..edges.len() {
+            let mut j = i;
+            while j > 0 && edges[j - 1].key > edges[j].key {
+                edges.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+
+        let mut schedule = Schedule::EMPTY;
+        schedule.period = self.period;
+        schedule.len = 1;
+        for edge in edges.iter() {
+            let at = (edge.key >> 1) as u16;
+            let last = schedule.len - 1;
+            if ((at - schedule.boundaries[last].at) as u32) < MIN_SLICE_US {
+                schedule.boundaries[last].add(edge.port, edge.pin, edge.high);
+            } else {
+                schedule.boundaries[schedule.len] = Boundary::at(at);
+                schedule.boundaries[schedule.len].add(edge.port, edge.pin, edge.high);
+                schedule.len += 1;
+            }
+        }
+        schedule
+    }
+}
+
+fn period_for(freq_hz: u32) -> Result<u16, SoftPwmError> {
+    if !(MIN_FREQ_HZ..=MAX_FREQ_HZ).contains(&freq_hz) {
+        return Err(SoftPwmError::FreqOutOfRange);
+    }
+    Ok((TICK_HZ / freq_hz) as u16)
+}
+
+// 在 TIM7 中断中调用
+pub(crate) fn on_tim7() {
+    let tim7 = unsafe { &*pac::TIM7::ptr() };
+    if tim7.sr.read().uif().bit_is_clear() {
+        return;
+    }
+    tim7.sr.modify(|_, w| w.uif().clear_bit());
+
+    let slice = cortex_m::interrupt::free(|cs| G_ENGINE.borrow(cs).borrow_mut().advance());
+    tim7.arr.write(|w| w.arr().bits(slice - 1));
+}
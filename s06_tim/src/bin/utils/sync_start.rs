@@ -0,0 +1,338 @@
+//! 让多个 TIM 在同一时刻开始计数，输出相位对齐（或者有固定相位差）的 PWM
+//!
+//! 依次写入各个 TIM 的 CEN 时，每两次写入之间隔着若干个总线周期，而且中间若有中断打断，间隔就更不确定了，
+//! 于是几路 PWM 之间的相位每次上电都不一样，RGB 灯的三个通道、H 桥的几个相位都不希望出现这种情况
+//!
+//! F413 上没有一个能同时启动所有 TIM 的寄存器，不过 TIM 之间的主从模式可以做到同样的效果：
+//!
+//! 主 TIM 的 MMS = 001（Enable），CEN 置位的时候，TRGO 同时变为高电平
+//! 从 TIM 的 TS 选择连接到主 TIM 的 ITRx，SMS = 110（Trigger Mode），在 TRGI 的上升沿由硬件置位自己的 CEN
+//!
+//! 这样，只要写一次主 TIM 的 CR1，所有的从 TIM 就在同一个时钟沿启动，与软件的执行时序无关
+//! 从 TIM 的启动相比主 TIM 晚了固定的几个 CK_INT 周期（TRGI 的同步电路），这个延迟每次都一样，
+//! 而且 PSC 的计数器也是在启动时才开始，PSC 较大时，这一延迟还不到 CNT 的一个计数
+//!
+//! 用法：
+//!
+//! 1. SyncGroup::new 指定主 TIM，add 添加从 TIM，不能连接到主 TIM 的会返回错误（见 itr）
+//! 2. 对每个 TIM 调用 set_period 与 set_duty，这里的 ARR 与 CCR 都开启了预装载
+//! 3. 需要相位差时，用 set_phase 指定启动时 CNT 的初始值，向上计数时，初始值为 n 相当于领先 n 个计数
+//! 4. prepare 停止所有 TIM，配置主从模式，产生一次更新事件载入预装载的值，再写入初始的 CNT
+//! 5. start 只写入一次主 TIM 的 CR1
+//!
+//! 停止时从 TIM 不会跟随主 TIM 停止（Trigger Mode 只负责启动），因此 stop 会依次清除所有 TIM 的 CEN，
+//! 之后需要再次 prepare 才能重新对齐
+//!
+//! 查询 TIMx internal trigger connection 表可知，F413 上：
+//!
+//! TIM1 ITR0~3：TIM5 TIM2 TIM3 TIM4
+//! TIM2 ITR0~3：TIM1 TIM8 TIM3 TIM4
+//! TIM3 ITR0~3：TIM1 TIM2 TIM5 TIM4
+//! TIM4 ITR0~3：TIM1 TIM2 TIM3 TIM8
+//! TIM5 ITR0~3：TIM2 TIM3 TIM4 TIM8
+//! TIM8 ITR0~3：TIM1 TIM2 TIM4 TIM5
+//!
+//! 这里用到的 CR1、CR2、SMCR、EGR、CCMRx、CCER、CNT、PSC、ARR、CCRx 在这 6 个 TIM 中的偏移完全相同，
+//! 因此都当作 TIM2 的寄存器来访问；TIM2/TIM5 之外的 TIM 只有 16 bit，ARR 与 CCR 不能超过 0xFFFF
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::{self, tim2::RegisterBlock};
+
+use super::clock_gate::{self, gates, Gate};
+
+// 一个 SyncGroup 最多能有几个从 TIM
+pub(crate) const MAX_SLAVES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Tim {
+    Tim1,
+    Tim2,
+    Tim3,
+    Tim4,
+    Tim5,
+    Tim8,
+}
+
+impl Tim {
+    fn regs(self) -> &'static RegisterBlock {
+        let ptr = match self {
+            Tim::Tim1 => pac::TIM1::ptr() as *const RegisterBlock,
+            Tim::Tim2 => pac::TIM2::ptr(),
+            Tim::Tim3 => pac::TIM3::ptr() as *const RegisterBlock,
+            Tim::Tim4 => pac::TIM4::ptr() as *const RegisterBlock,
+            Tim::Tim5 => pac::TIM5::ptr() as *const RegisterBlock,
+            Tim::Tim8 => pac::TIM8::ptr() as *const RegisterBlock,
+        };
+        unsafe { &*ptr }
+    }
+
+    fn gate(self) -> Gate {
+        match self {
+            Tim::Tim1 => gates::TIM1,
+            Tim::Tim2 => gates::TIM2,
+            Tim::Tim3 => gates::TIM3,
+            Tim::Tim4 => gates::TIM4,
+            Tim::Tim5 => gates::TIM5,
+            Tim::Tim8 => gates::TIM8,
+        }
+    }
+
+    fn max_count(self) -> u32 {
+        match self {
+            Tim::Tim2 | Tim::Tim5 => u32::MAX,
+            _ => u16::MAX as u32,
+        }
+    }
+
+    fn is_advanced(self) -> bool {
+        matches!(self, Tim::Tim1 | Tim::Tim8)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Channel {
+    Ch1,
+    Ch2,
+    Ch3,
+    Ch4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SyncError {
+    // 从 TIM 的 ITR0~3 中没有连接到主 TIM 的
+    NotConnected(Tim),
+    // 主 TIM 不能同时作为从 TIM，同一个 TIM 也不能添加两次
+    Duplicate(Tim),
+    // 超过了 MAX_SLAVES
+    TooManySlaves,
+    // set_phase 指定的 TIM 不在这个 SyncGroup 中
+    NotInGroup(Tim),
+    // ARR、CCR 或者 CNT 的初始值超过了 16 bit TIM 的范围
+    OutOfRange(Tim),
+}
+
+// slave 的哪一个 ITR 连接到了 master
+pub(crate) fn itr(slave: Tim, master: Tim) -> Option<u8> {
+    use Tim::*;
+    let table = match slave {
+        Tim1 => [Tim5, Tim2, Tim3, Tim4],
+        Tim2 => [Tim1, Tim8, Tim3, Tim4],
+        Tim3 => [Tim1, Tim2, Tim5, Tim4],
+        Tim4 => [Tim1, Tim2, Tim3, Tim8],
+        Tim5 => [Tim2, Tim3, Tim4, Tim8],
+        Tim8 => [Tim1, Tim2, Tim4, Tim5],
+    };
+    table.iter().position(|&t| t == master).map(|i| i as u8)
+}
+
+// 开启时钟，边沿对齐，向上计数，ARR 预装载，在 prepare 时才载入
+pub(crate) fn set_period(tim: Tim, psc: u16, arr: u32) -> Result<(), SyncError> {
+    if arr > tim.max_count() {
+        return Err(SyncError::OutOfRange(tim));
+    }
+    clock_gate::claim(tim.gate());
+
+    let regs = tim.regs();
+    regs.cr1.modify(|_, w| {
+        w.cen().disabled();
+        w.dir().up();
+        w.arpe().enabled();
+        // UG 只用来载入预装载的值，不产生更新中断
+        w.urs().counter_only();
+        w
+    });
+    regs.psc.write(|w| w.psc().bits(psc));
+    // TIM2 与 TIM3 的 ARR、CCR 字段宽度不同，这里直接写入整个寄存器
+    regs.arr.write(|w| w.bits(arr));
+    Ok(())
+}
+
+// PWM mode 1，CCR 预装载，CNT < CCR 时输出高电平
+pub(crate) fn set_duty(tim: Tim, channel: Channel, ccr: u32) -> Result<(), SyncError> {
+    if ccr > tim.max_count() {
+        return Err(SyncError::OutOfRange(tim));
+    }
+
+    let regs = tim.regs();
+    match channel {
+        Channel::Ch1 => {
+            regs.ccmr1_output().modify(|_, w| {
+                w.cc1s().output();
+                w.oc1m().pwm_mode1();
+                w.oc1pe().enabled();
+                w
+            });
+            regs.ccr1().write(|w| w.bits(ccr));
+        }
+        Channel::Ch2 => {
+            regs.ccmr1_output().modify(|_, w| {
+                w.cc2s().output();
+                w.oc2m().pwm_mode1();
+                w.oc2pe().enabled();
+                w
+            });
+            regs.ccr2().write(|w| w.bits(ccr));
+        }
+        Channel::Ch3 => {
+            regs.ccmr2_output().modify(|_, w| {
+                w.cc3s().output();
+                w.oc3m().pwm_mode1();
+                w.oc3pe().enabled();
+                w
+            });
+            regs.ccr3().write(|w| w.bits(ccr));
+        }
+        Channel::Ch4 => {
+            regs.ccmr2_output().modify(|_, w| {
+                w.cc4s().output();
+                w.oc4m().pwm_mode1();
+                w.oc4pe().enabled();
+                w
+            });
+            regs.ccr4().write(|w| w.bits(ccr));
+        }
+    }
+
+    // CCER 中每个通道占 4 bit，CCxE 为最低位
+    let shift = channel as u32 * 4;
+    regs.ccer
+        .modify(|r, w| unsafe { w.bits(r.bits() | (1 << shift)) });
+
+    // 高级定时器还需要置位 MOE，输出才会出现在引脚上
+    if tim.is_advanced() {
+        let tim1 = match tim {
+            Tim::Tim1 => unsafe { &*pac::TIM1::ptr() },
+            _ => unsafe { &*pac::TIM8::ptr() },
+        };
+        tim1.bdtr.modify(|_, w| w.moe().set_bit());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Member {
+    tim: Tim,
+    // 启动时 CNT 的初始值
+    phase: u32,
+}
+
+pub(crate) struct SyncGroup {
+    master: Member,
+    slaves: [Option<Member>; MAX_SLAVES],
+}
+
+impl SyncGroup {
+    pub(crate) fn new(master: Tim) -> Self {
+        Self {
+            master: Member {
+                tim: master,
+                phase: 0,
+            },
+            slaves: [None; MAX_SLAVES],
+        }
+    }
+
+    pub(crate) fn add(&mut self, slave: Tim) -> Result<(), SyncError> {
+        if self.member(slave).is_some() {
+            return Err(SyncError::Duplicate(slave));
+        }
+        if itr(slave, self.master.tim).is_none() {
+            return Err(SyncError::NotConnected(slave));
+        }
+        let slot = self
+            .slaves
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(SyncError::TooManySlaves)?;
+        *slot = Some(Member {
+            tim: slave,
+            phase: 0,
+        });
+        Ok(())
+    }
+
+    // 启动时 CNT 的初始值，必须小于等于这个 TIM 的 ARR，在下一次 prepare 时生效
+    pub(crate) fn set_phase(&mut self, tim: Tim, count: u32) -> Result<(), SyncError> {
+        if count > tim.max_count() {
+            return Err(SyncError::OutOfRange(tim));
+        }
+        match self.member_mut(tim) {
+            Some(member) => {
+                member.phase = count;
+                Ok(())
+            }
+            None => Err(SyncError::NotInGroup(tim)),
+        }
+    }
+
+    // 停止所有 TIM，配置主从模式，并载入预装载的值与 CNT 的初始值
+    pub(crate) fn prepare(&self) {
+        let master = self.master.tim;
+        let master_regs = master.regs();
+        master_regs.cr1.modify(|_, w| w.cen().disabled());
+        // CEN 置位时 TRGO 变为高电平
+        master_regs.cr2.modify(|_, w| w.mms().enable());
+        // 主 TIM 自身不受任何触发的控制
+        master_regs.smcr.modify(|_, w| w.sms().disabled());
+
+        for slave in self.slaves.iter().flatten() {
+            let regs = slave.tim.regs();
+            regs.cr1.modify(|_, w| w.cen().disabled());
+            // add 时已经检查过，这里一定能找到
+            let ts = itr(slave.tim, master).unwrap();
+            regs.smcr.modify(|_, w| unsafe {
+                w.ts().bits(ts);
+                w.sms().trigger_mode();
+                w
+            });
+        }
+
+        // UG 会把 CNT 清零，因此先载入预装载的值，再写入 CNT 的初始值
+        for member in self.members() {
+            let regs = member.tim.regs();
+            regs.egr.write(|w| w.ug().set_bit());
+            regs.cnt.write(|w| w.bits(member.phase));
+        }
+    }
+
+    // 从这里开始，所有 TIM 的计数都是对齐的
+    pub(crate) fn start(&self) {
+        self.master.tim.regs().cr1.modify(|_, w| w.cen().enabled());
+    }
+
+    // 从 TIM 先停止，主 TIM 最后停止，各路输出停在哪个电平取决于停下时的 CNT
+    pub(crate) fn stop(&self) {
+        for slave in self.slaves.iter().flatten() {
+            slave.tim.regs().cr1.modify(|_, w| w.cen().disabled());
+        }
+        self.master.tim.regs().cr1.modify(|_, w| w.cen().disabled());
+    }
+
+    // 读取所有 TIM 的 CNT，依次读取，相互之间差了几个总线周期，只用于粗略的检查
+    pub(crate) fn counters(&self) -> [Option<(Tim, u32)>; MAX_SLAVES + 1] {
+        let mut counters = [None; MAX_SLAVES + 1];
+        for (slot, member) in counters.iter_mut().zip(self.members()) {
+            *slot = Some((member.tim, member.tim.regs().cnt.read().bits()));
+        }
+        counters
+    }
+
+    fn members(&self) -> impl Iterator<Item = &Member> {
+        core::iter::once(&self.master).chain(self.slaves.iter().flatten())
+    }
+
+    fn member(&self, tim: Tim) -> Option<&Member> {
+        self.members().find(|member| member.tim == tim)
+    }
+
+    fn member_mut(&mut self, tim: Tim) -> Option<&mut Member> {
+        if self.master.tim == tim {
+            return Some(&mut self.master);
+        }
+        self.slaves
+            .iter_mut()
+            .flatten()
+            .find(|member| member.tim == tim)
+    }
+}
@@ -0,0 +1,332 @@
+//! 在一个 PWM 通道上混合两个频率，给无源蜂鸣器（或者加上 RC 低通的小喇叭）发出 DTMF 拨号音以及多音调的提示音
+//!
+//! 只输出单一频率的话，直接把 PWM 的频率设置为音调的频率即可；两个频率叠加之后就不再是方波了，
+//! 这里改用“PWM DAC”的方式：
+//!
+//! - TIM1_CH1（PA8）以 TIM_CLK_HZ / 256 的频率输出 8 bit 分辨率的 PWM，这个载波远高于人耳的范围，蜂鸣器与喇叭只跟随它的平均值
+//! - TIM1 的重复计数器 RCR 让更新事件每 RCR + 1 个 PWM 周期才产生一次，即采样率 SAMPLE_RATE_HZ，
+//!   每次更新中断计算一个新的采样，写入 CCR1，CCR1 开启了预装载，新的占空比在下一个 PWM 周期开始时才生效
+//! - 每个频率有一个 32 bit 的相位累加器（NCO），每个采样加上 f * 2^32 / SAMPLE_RATE_HZ，高 8 bit 用来查正弦表，
+//!   两个正弦值按各自的幅度相加，就是这一个采样的值
+//!
+//! 每段音调的开头与结尾各有 RAMP_SAMPLES 的线性渐变，避免突然开始或者截断产生“咔哒”声
+//!
+//! 音调通过 queue 放入队列，由中断依次播放，主循环不需要等待
+//! 播放期间（包括静音段）输出的中心为 50% 占空比，队列空了之后输出回到 0，所以一整串音调的开始与结束各有一次轻微的“咔哒”声
+//!
+//! 采样率为 31.25 kHz 时，每个采样之间只有约 3000 个 SYSCLK 周期，中断处理函数要尽量短；
+//! 比起只需要在每个音符开始时改一次 ARR 的单音旋律，这里对 TIM 的更新中断是一个不小的压力
+//!
+//! TIM1_UP_TIM10 中断需要在 bin 中 unmask，并在中断处理函数中调用 on_tim1_update
+
+#![allow(dead_code)]
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{self, Peripherals};
+
+use super::{
+    clock_gate::{self, gates},
+    pin_registry,
+    port::Port,
+};
+
+// SYSCLK 96 MHz，APB2 不分频，TIM1 的时钟为 96 MHz
+pub(crate) const TIM_CLK_HZ: u32 = 96_000_000;
+
+// 8 bit 的 PWM，载波为 375 kHz
+const PWM_STEPS: u32 = 256;
+// 每 12 个 PWM 周期更新一次占空比
+const RCR: u32 = 11;
+pub(crate) const SAMPLE_RATE_HZ: u32 = TIM_CLK_HZ / PWM_STEPS / (RCR + 1);
+
+// 2 ms
+const RAMP_SAMPLES: u32 = SAMPLE_RATE_HZ / 500;
+
+pub(crate) const QUEUE_LEN: usize = 32;
+
+// 两个音调的幅度之和不超过 127，这样叠加之后不会超出 PWM 的范围
+pub(crate) const MAX_AMPLITUDE: u8 = 127;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ToneError {
+    QueueFull,
+    // 频率高于采样率的一半
+    FreqTooHigh,
+    // 不是 DTMF 的按键
+    InvalidKey,
+}
+
+// 一段音调，两个频率为 0 时就是一段静音
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Tone {
+    freq: [u32; 2],
+    amplitude: [u8; 2],
+    ms: u32,
+}
+
+impl Tone {
+    pub(crate) const fn single(freq: u32, ms: u32) -> Self {
+        Self {
+            freq: [freq, 0],
+            amplitude: [MAX_AMPLITUDE, 0],
+            ms,
+        }
+    }
+
+    // 两个频率等幅度叠加
+    pub(crate) const fn pair(low: u32, high: u32, ms: u32) -> Self {
+        Self {
+            freq: [low, high],
+            amplitude: [MAX_AMPLITUDE / 2, MAX_AMPLITUDE / 2],
+            ms,
+        }
+    }
+
+    pub(crate) const fn rest(ms: u32) -> Self {
+        Self {
+            freq: [0, 0],
+            amplitude: [0, 0],
+            ms,
+        }
+    }
+
+    // 分别指定两个频率的幅度，两者之和超过 MAX_AMPLITUDE 时按比例缩小
+    pub(crate) fn with_amplitude(mut self, first: u8, second: u8) -> Self {
+        let sum = first as u32 + second as u32;
+        let scale = |a: u8| {
+            if sum > MAX_AMPLITUDE as u32 {
+                (a as u32 * MAX_AMPLITUDE as u32 / sum) as u8
+            } else {
+                a
+            }
+        };
+        self.amplitude = [scale(first), scale(second)];
+        self
+    }
+
+    // DTMF 按键对应的两个频率，高频组比低频组大 2 dB（约 1.26 倍），补偿电话线路对高频的衰减
+    pub(crate) fn dtmf(key: char, ms: u32) -> Result<Self, ToneError> {
+        const LOW: [u32; 4] = [697, 770, 852, 941];
+        const HIGH: [u32; 4] = [1209, 1336, 1477, 1633];
+        const KEYS: [[char; 4]; 4] = [
+            ['1', '2', '3', 'A'],
+            ['4', '5', '6', 'B'],
+            ['7', '8', '9', 'C'],
+            ['*', '0', '#', 'D'],
+        ];
+
+        let key = key.to_ascii_uppercase();
+        for (row, keys) in KEYS.iter().enumerate() {
+            if let Some(col) = keys.iter().position(|&k| k == key) {
+                return Ok(Self::pair(LOW[row], HIGH[col], ms).with_amplitude(56, 71));
+            }
+        }
+        Err(ToneError::InvalidKey)
+    }
+
+    pub(crate) fn ms(&self) -> u32 {
+        self.ms
+    }
+}
+
+// 一个周期 256 个点的正弦表，编译时用泰勒级数计算
+const SINE: [i8; 256] = build_sine();
+
+const fn build_sine() -> [i8; 256] {
+    let mut table = [0i8; 256];
+    let mut i = 0;
+    while i < 256 {
+        // 先把角度折到 -pi/2 ~ pi/2，泰勒级数在这个范围内的误差远小于 1/127
+        let mut x = (i as f32 / 256.0) * 2.0 * core::f32::consts::PI;
+        if x > 1.5 * core::f32::consts::PI {
+            x -= 2.0 * core::f32::consts::PI;
+        } else if x > 0.5 * core::f32::consts::PI {
+            x = core::f32::consts::PI - x;
+        }
+        let x2 = x * x;
+        let sin = x * (1.0 - x2 / 6.0 * (1.0 - x2 / 20.0 * (1.0 - x2 / 42.0 * (1.0 - x2 / 72.0))));
+        let value = sin * 127.0;
+        table[i] = if value >= 0.0 {
+            (value + 0.5) as i8
+        } else {
+            (value - 0.5) as i8
+        };
+        i += 1;
+    }
+    table
+}
+
+// 中断中正在播放的音调
+struct Voice {
+    phase: [u32; 2],
+    step: [u32; 2],
+    amplitude: [u8; 2],
+    elapsed: u32,
+    total: u32,
+}
+
+struct Synth {
+    queue: [Tone; QUEUE_LEN],
+    head: usize,
+    len: usize,
+    voice: Option<Voice>,
+}
+
+impl Synth {
+    fn pop(&mut self) -> Option<Tone> {
+        if self.len == 0 {
+            return None;
+        }
+        let tone = self.queue[self.head];
+        self.head = (self.head + 1) % QUEUE_LEN;
+        self.len -= 1;
+        Some(tone)
+    }
+
+    // 计算下一个采样，返回 CCR1 的值
+    fn next_sample(&mut self) -> u16 {
+        if self.voice.is_none() {
+            self.voice = self.pop().map(|tone| Voice {
+                phase: [0, 0],
+                step: tone.freq.map(phase_step),
+                amplitude: tone.amplitude,
+                elapsed: 0,
+                total: tone.ms * SAMPLE_RATE_HZ / 1000,
+            });
+        }
+        let voice = match self.voice.as_mut() {
+            Some(voice) => voice,
+            None => return 0,
+        };
+
+        let mut mixed = 0i32;
+        for i in 0..2 {
+            voice.phase[i] = voice.phase[i].wrapping_add(voice.step[i]);
+            mixed += SINE[(voice.phase[i] >> 24) as usize] as i32 * voice.amplitude[i] as i32;
+        }
+        // 开头与结尾的渐变
+        let edge = voice.elapsed.min(voice.total - voice.elapsed);
+        if edge < RAMP_SAMPLES {
+            mixed = mixed * edge as i32 / RAMP_SAMPLES as i32;
+        }
+
+        voice.elapsed += 1;
+        if voice.elapsed >= voice.total {
+            self.voice = None;
+        }
+
+        // mixed 的范围为 ±127 * 127，缩放到 0 ~ 255，中心为 128
+        (128 + mixed / 127).clamp(0, PWM_STEPS as i32 - 1) as u16
+    }
+}
+
+static G_SYNTH: Mutex<RefCell<Synth>> = Mutex::new(RefCell::new(Synth {
+    queue: [Tone::rest(0); QUEUE_LEN],
+    head: 0,
+    len: 0,
+    voice: None,
+}));
+
+// f * 2^32 / SAMPLE_RATE_HZ
+fn phase_step(freq: u32) -> u32 {
+    (((freq as u64) << 32) / SAMPLE_RATE_HZ as u64) as u32
+}
+
+// PA8 切换到 AF01，TIM1_CH1 输出 PWM，并开启更新中断
+pub(crate) fn setup(dp: &Peripherals) {
+    clock_gate::claim(gates::GPIOA);
+    clock_gate::claim(gates::TIM1);
+    pin_registry::claim(Port::A, 8, "tone_synth");
+
+    dp.GPIOA.afrh.modify(|_, w| w.afrh8().af1());
+    dp.GPIOA.ospeedr.modify(|_, w| w.ospeedr8().high_speed());
+    dp.GPIOA.moder.modify(|_, w| w.moder8().alternate());
+
+    let tim1 = &dp.TIM1;
+    tim1.cr1.modify(|_, w| {
+        w.arpe().enabled();
+        // UG 只用来加载预装载寄存器，不产生更新中断
+        w.urs().counter_only();
+        w
+    });
+    tim1.psc.write(|w| w.psc().bits(0));
+    tim1.arr.write(|w| w.arr().bits((PWM_STEPS - 1) as u16));
+    tim1.rcr.write(|w| unsafe { w.rep().bits(RCR as u8) });
+    tim1.ccr1().write(|w| w.ccr().bits(0));
+    tim1.ccmr1_output().modify(|_, w| {
+        w.cc1s().output();
+        w.oc1m().pwm_mode1();
+        w.oc1pe().enabled();
+        w
+    });
+    tim1.ccer.modify(|_, w| w.cc1e().set_bit());
+    tim1.bdtr.modify(|_, w| w.moe().set_bit());
+    tim1.egr.write(|w| w.ug().set_bit());
+
+    tim1.dier.modify(|_, w| w.uie().enabled());
+    tim1.cr1.modify(|_, w| w.cen().enabled());
+}
+
+// 放入队列，队列满了返回 QueueFull，长度为 0 的音调直接忽略
+pub(crate) fn queue(tone: Tone) -> Result<(), ToneError> {
+    if tone.freq.iter().any(|&f| f >= SAMPLE_RATE_HZ / 2) {
+        return Err(ToneError::FreqTooHigh);
+    }
+    if tone.ms == 0 {
+        return Ok(());
+    }
+    cortex_m::interrupt::free(|cs| {
+        let mut synth = G_SYNTH.borrow(cs).borrow_mut();
+        if synth.len == QUEUE_LEN {
+            return Err(ToneError::QueueFull);
+        }
+        let tail = (synth.head + synth.len) % QUEUE_LEN;
+        synth.queue[tail] = tone;
+        synth.len += 1;
+        Ok(())
+    })
+}
+
+// 按键音 on_ms，按键之间间隔 off_ms，不是 DTMF 按键的字符（比如空格与 -）当作一段 on_ms 的停顿
+pub(crate) fn queue_dtmf(keys: &str, on_ms: u32, off_ms: u32) -> Result<(), ToneError> {
+    for key in keys.chars() {
+        queue(Tone::dtmf(key, on_ms).unwrap_or(Tone::rest(on_ms)))?;
+        queue(Tone::rest(off_ms))?;
+    }
+    Ok(())
+}
+
+// 队列中还有几段音调没有开始播放
+pub(crate) fn pending() -> usize {
+    cortex_m::interrupt::free(|cs| G_SYNTH.borrow(cs).borrow().len)
+}
+
+pub(crate) fn is_idle() -> bool {
+    cortex_m::interrupt::free(|cs| {
+        let synth = G_SYNTH.borrow(cs).borrow();
+        synth.len == 0 && synth.voice.is_none()
+    })
+}
+
+// 清空队列并立刻静音
+pub(crate) fn clear() {
+    cortex_m::interrupt::free(|cs| {
+        let mut synth = G_SYNTH.borrow(cs).borrow_mut();
+        synth.len = 0;
+        synth.voice = None;
+    });
+}
+
+// 在 TIM1_UP_TIM10 中断中调用
+pub(crate) fn on_tim1_update() {
+    let tim1 = unsafe { &*pac::TIM1::ptr() };
+    if tim1.sr.read().uif().bit_is_clear() {
+        return;
+    }
+    tim1.sr.modify(|_, w| w.uif().clear_bit());
+
+    let ccr = cortex_m::interrupt::free(|cs| G_SYNTH.borrow(cs).borrow_mut().next_sample());
+    tim1.ccr1().write(|w| w.ccr().bits(ccr));
+}
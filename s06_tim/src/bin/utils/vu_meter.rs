@@ -0,0 +1,430 @@
+//! 音频电平表（VU meter）：ADC + DMA 采样 -> 峰值/RMS 检波 -> 对数刻度 -> 灯条
+//!
+//! 整条流水线分为互不依赖的几段，每一段都可以单独替换：
+//!
+//! 1. AdcStream：TIM2 以 SAMPLE_RATE_HZ 输出 TRGO 触发 ADC1，DMA2 Stream 0 以循环模式把结果写入一个双缓冲，
+//!    半传输与传输完成中断只记录“哪一半写满了”，主循环用 take_frame 取出写满的那一半，整个采样过程不需要 CPU 参与
+//!    同时开启 ADC 的模拟看门狗作为“窗口比较器”：采样超出 [CLIP_LOW, CLIP_HIGH] 时立刻记下削波，
+//!    不需要等这一帧算完，就算削波只有一个采样也不会漏掉
+//! 2. LevelDetector：去掉直流偏置（驻极体话筒模块的输出偏置在 VCC / 2），算出一帧的峰值与 RMS，换算为 dBFS，
+//!    上升立刻跟随，下降按照 RELEASE_DB_PER_S 衰减，峰值保持 HOLD_MS 之后再以 FALL_DB_PER_S 回落
+//! 3. Scale：把 dBFS 按 floor_db ~ 0 dB 的范围线性映射为 0 ~ 1，也就是对数刻度
+//! 4. 渲染：render_strip 画到 ws2812 的帧缓冲里，lcd_bar 生成 LCD1602 的自定义字符（见 LCD_BAR_GLYPHS）
+//!
+//! 每一段都是“有数据就处理，没有就立刻返回”，主循环只需要轮询，不需要等待
+//!
+//! 对数换算直接使用均方值：dB = 10 * log10(mean square)，省掉了开平方
+
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use stm32f4xx_hal::pac::{self, interrupt, Peripherals, NVIC};
+
+use super::{
+    clock_gate::{self, gates},
+    ws2812::{FrameBuffer, Rgb},
+};
+
+pub(crate) const SAMPLE_RATE_HZ: u32 = 16_000;
+// 一帧 256 个采样，16 ms
+pub(crate) const FRAME_LEN: usize = 256;
+pub(crate) const BUFFER_LEN: usize = FRAME_LEN * 2;
+
+// 12 bit ADC 以中点为 0 时的满量程
+const FULL_SCALE: f32 = 2048.0;
+
+// 离满量程还差约 1% 时就认为削波了
+pub(crate) const CLIP_LOW: u16 = 40;
+pub(crate) const CLIP_HIGH: u16 = 4095 - 40;
+
+// 每写满半个缓冲加 1，主循环据此判断有没有新的一帧，以及有没有来不及处理的帧
+static G_HALVES: AtomicU32 = AtomicU32::new(0);
+static G_CLIPPED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) struct AdcStream {
+    buffer: &'static mut [u16; BUFFER_LEN],
+    // 已经处理过的半缓冲的个数
+    taken: u32,
+    dropped: u32,
+}
+
+impl AdcStream {
+    // channel 为 ADC1 的通道（0 ~ 9），对应的引脚需要事先设置为 analog 模式；tim_clk_hz 为 TIM2 的时钟
+    //
+    // ADCCLK 不能超过 36 MHz，ADC_COMMON 的分频需要事先设置好
+    pub(crate) fn new(
+        dp: &Peripherals,
+        buffer: &'static mut [u16; BUFFER_LEN],
+        channel: u8,
+        tim_clk_hz: u32,
+    ) -> Self {
+        assert!(channel < 10);
+
+        clock_gate::claim(gates::TIM2);
+        clock_gate::claim(gates::ADC1);
+        clock_gate::claim(gates::DMA2);
+
+        setup_tim2(dp, tim_clk_hz);
+        setup_adc(dp, channel);
+        setup_dma(dp, buffer);
+
+        unsafe {
+            NVIC::unmask(interrupt::DMA2_STREAM0);
+            NVIC::unmask(interrupt::ADC);
+        }
+        dp.TIM2.cr1.modify(|_, w| w.cen().enabled());
+
+        Self {
+            buffer,
+            taken: 0,
+            dropped: 0,
+        }
+    }
+
+    // 有写满的半个缓冲时返回它，DMA 此时正在写另一半，下一次半传输之前，这一半的内容不会变化
+    //
+    // 主循环来不及处理时，只返回最新的一帧，跳过的帧数记在 dropped 中
+    pub(crate) fn take_frame(&mut self) -> Option<&[u16]> {
+        let halves = G_HALVES.load(Ordering::Acquire);
+        if halves == self.taken {
+            return None;
+        }
+        self.dropped += halves.wrapping_sub(self.taken) - 1;
+        self.taken = halves;
+
+        // 奇数次为半传输，写满的是前一半
+        let start = if halves % 2 == 1 { 0 } else { FRAME_LEN };
+        Some(&self.buffer[start..start + FRAME_LEN])
+    }
+
+    pub(crate) fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
+
+// 上一次调用之后是否发生过削波
+pub(crate) fn take_clipped() -> bool {
+    let clipped = G_CLIPPED.swap(false, Ordering::AcqRel);
+    if clipped {
+        // 中断里关掉了 AWDIE，避免削波期间每个采样都进一次中断，这里重新打开
+        let adc = unsafe { &*pac::ADC1::ptr() };
+        adc.sr.modify(|_, w| w.awd().clear_bit());
+        adc.cr1.modify(|_, w| w.awdie().enabled());
+    }
+    clipped
+}
+
+fn setup_tim2(dp: &Peripherals, tim_clk_hz: u32) {
+    let tim = &dp.TIM2;
+    tim.cr1.modify(|_, w| w.cen().disabled());
+    tim.psc.write(|w| w.psc().bits(0));
+    tim.arr
+        .write(|w| w.arr().bits(tim_clk_hz / SAMPLE_RATE_HZ - 1));
+    // 每次溢出在 TRGO 上输出一个脉冲，触发 ADC
+    tim.cr2.modify(|_, w| w.mms().update());
+    tim.egr.write(|w| w.ug().update());
+}
+
+fn setup_adc(dp: &Peripherals, channel: u8) {
+    let adc = &dp.ADC1;
+
+    adc.sqr3.modify(|_, w| unsafe { w.sq1().bits(channel) });
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+
+    // 采样时间 0b011 为 56 个周期，话筒模块的输出阻抗较高，采样时间长一些更准确
+    let shift = 3 * channel as u32;
+    adc.smpr2
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << shift) | 0b011 << shift) });
+
+    // 模拟看门狗只监视这一个通道
+    adc.ltr.write(|w| w.lt().bits(CLIP_LOW));
+    adc.htr.write(|w| w.ht().bits(CLIP_HIGH));
+    adc.cr1.modify(|_, w| unsafe {
+        w.awdch().bits(channel);
+        w.awdsgl().single_channel();
+        w.awden().enabled();
+        w.awdie().enabled();
+        w
+    });
+
+    adc.cr2.modify(|_, w| {
+        w.extsel().tim2trgo();
+        w.exten().rising_edge();
+        // DMA 循环模式下，DDS 必须置位，否则 DMA 的第一轮结束后 ADC 就不再发出请求了
+        w.dds().continuous();
+        w.dma().enabled();
+        w.adon().enabled();
+        w
+    });
+}
+
+// DMA2 Stream 0 Channel 0 为 ADC1
+fn setup_dma(dp: &Peripherals, buffer: &mut [u16; BUFFER_LEN]) {
+    let dma2 = &dp.DMA2;
+    let st = &dma2.st[0];
+
+    if st.cr.read().en().is_enabled() {
+        st.cr.modify(|_, w| w.en().disabled());
+        while st.cr.read().en().is_enabled() {}
+    }
+
+    dma2.lifcr.write(|w| {
+        w.ctcif0().clear();
+        w.chtif0().clear();
+        w.cteif0().clear();
+        w.cdmeif0().clear();
+        w.cfeif0().clear();
+        w
+    });
+
+    st.cr.write(|w| {
+        w.chsel().bits(0);
+        w.pl().high();
+        w.dir().peripheral_to_memory();
+        w.msize().bits16();
+        w.psize().bits16();
+        w.minc().incremented();
+        w.pinc().fixed();
+        w.circ().enabled();
+        w.htie().enabled();
+        w.tcie().enabled();
+        w
+    });
+    st.par
+        .write(|w| unsafe { w.pa().bits(dp.ADC1.dr.as_ptr() as u32) });
+    st.m0ar
+        .write(|w| unsafe { w.m0a().bits(buffer.as_mut_ptr() as u32) });
+    st.ndtr.write(|w| w.ndt().bits(BUFFER_LEN as u16));
+
+    st.cr.modify(|_, w| w.en().enabled());
+}
+
+// 在 DMA2_STREAM0 中断中调用
+pub(crate) fn on_dma2_stream0() {
+    let dma2 = unsafe { &*pac::DMA2::ptr() };
+    let lisr = dma2.lisr.read();
+    if lisr.htif0().bit_is_set() {
+        dma2.lifcr.write(|w| w.chtif0().clear());
+        G_HALVES.fetch_add(1, Ordering::Release);
+    }
+    if lisr.tcif0().bit_is_set() {
+        dma2.lifcr.write(|w| w.ctcif0().clear());
+        G_HALVES.fetch_add(1, Ordering::Release);
+    }
+}
+
+// 在 ADC 中断中调用
+pub(crate) fn on_adc() {
+    let adc = unsafe { &*pac::ADC1::ptr() };
+    if adc.sr.read().awd().bit_is_set() {
+        adc.sr.modify(|_, w| w.awd().clear_bit());
+        adc.cr1.modify(|_, w| w.awdie().disabled());
+        G_CLIPPED.store(true, Ordering::Release);
+    }
+}
+
+// ---- 检波 ----
+
+// 比 floor 还低的信号都显示为 floor，也避免对 0 取对数
+pub(crate) const SILENCE_DB: f32 = -90.0;
+
+const RELEASE_DB_PER_S: f32 = 20.0;
+const HOLD_MS: u32 = 800;
+const FALL_DB_PER_S: f32 = 10.0;
+// 直流偏置每帧向这一帧的平均值靠近 1/8
+const DC_SHIFT: f32 = 0.125;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Levels {
+    // 带有衰减的 RMS 与峰值，单位 dBFS
+    pub(crate) rms_db: f32,
+    pub(crate) peak_db: f32,
+    // 峰值保持
+    pub(crate) hold_db: f32,
+}
+
+pub(crate) struct LevelDetector {
+    frame_ms: u32,
+    dc: f32,
+    levels: Levels,
+    // 峰值保持还剩多久开始回落
+    hold_left_ms: u32,
+}
+
+impl LevelDetector {
+    pub(crate) fn new() -> Self {
+        Self {
+            frame_ms: FRAME_LEN as u32 * 1000 / SAMPLE_RATE_HZ,
+            dc: FULL_SCALE,
+            levels: Levels {
+                rms_db: SILENCE_DB,
+                peak_db: SILENCE_DB,
+                hold_db: SILENCE_DB,
+            },
+            hold_left_ms: 0,
+        }
+    }
+
+    pub(crate) fn levels(&self) -> Levels {
+        self.levels
+    }
+
+    // 处理一帧采样，返回更新之后的电平
+    pub(crate) fn process(&mut self, samples: &[u16]) -> Levels {
+        if samples.is_empty() {
+            return self.levels;
+        }
+
+        let mut sum = 0u32;
+        for &s in samples {
+            sum += s as u32;
+        }
+        let mean = sum as f32 / samples.len() as f32;
+        self.dc += (mean - self.dc) * DC_SHIFT;
+
+        let mut square_sum = 0.0f32;
+        let mut peak = 0.0f32;
+        for &s in samples {
+            let v = s as f32 - self.dc;
+            square_sum += v * v;
+            let a = if v < 0.0 { -v } else { v };
+            if a > peak {
+                peak = a;
+            }
+        }
+
+        let full = FULL_SCALE * FULL_SCALE;
+        let rms_db = power_db(square_sum / samples.len() as f32 / full);
+        let peak_db = power_db(peak * peak / full);
+
+        let dt = self.frame_ms as f32 / 1000.0;
+        let levels = &mut self.levels;
+        levels.rms_db = follow(levels.rms_db, rms_db, RELEASE_DB_PER_S * dt);
+        levels.peak_db = follow(levels.peak_db, peak_db, RELEASE_DB_PER_S * dt);
+
+        if levels.peak_db >= levels.hold_db {
+            levels.hold_db = levels.peak_db;
+            self.hold_left_ms = HOLD_MS;
+        } else if self.hold_left_ms > self.frame_ms {
+            self.hold_left_ms -= self.frame_ms;
+        } else {
+            self.hold_left_ms = 0;
+            levels.hold_db = (levels.hold_db - FALL_DB_PER_S * dt).max(levels.peak_db);
+        }
+
+        *levels
+    }
+}
+
+// 上升立刻跟随，下降每帧最多 release_db
+fn follow(current: f32, new: f32, release_db: f32) -> f32 {
+    if new >= current {
+        new
+    } else {
+        (current - release_db).max(new)
+    }
+}
+
+// 功率比换算为 dB
+fn power_db(ratio: f32) -> f32 {
+    if ratio <= 0.0 {
+        return SILENCE_DB;
+    }
+    (10.0 / core::f32::consts::LN_10 * ln(ratio)).max(SILENCE_DB)
+}
+
+// 与 s21 的 utils::analog::ln 相同：拆出指数，尾数部分用 atanh 级数展开，0.1 dB 的精度用不了几项
+fn ln(x: f32) -> f32 {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127;
+    let m = f32::from_bits((bits & 0x007F_FFFF) | 0x3F80_0000);
+
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    let sum = s * (1.0 + s2 * (1.0 / 3.0 + s2 * (1.0 / 5.0 + s2 / 7.0)));
+
+    exponent as f32 * core::f32::consts::LN_2 + 2.0 * sum
+}
+
+// ---- 刻度 ----
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Scale {
+    // 灯条最底端对应的 dBFS，比如 -48 dB
+    pub(crate) floor_db: f32,
+}
+
+impl Scale {
+    // 0 ~ 1
+    pub(crate) fn fraction(&self, db: f32) -> f32 {
+        ((db - self.floor_db) / -self.floor_db).clamp(0.0, 1.0)
+    }
+
+    // 点亮 segments 格中的几格，四舍五入
+    pub(crate) fn segments(&self, db: f32, segments: usize) -> usize {
+        (self.fraction(db) * segments as f32 + 0.5) as usize
+    }
+}
+
+// ---- 渲染 ----
+
+const GREEN_UNTIL: f32 = 0.6;
+const YELLOW_UNTIL: f32 = 0.85;
+
+// 第 0 颗灯为最低格；RMS 为实心的灯条，峰值保持为一颗白色的灯，削波时最高的一颗灯为亮红色
+pub(crate) fn render_strip<const N: usize>(
+    frame: &mut FrameBuffer<N>,
+    scale: &Scale,
+    levels: &Levels,
+    clipped: bool,
+) {
+    let lit = scale.segments(levels.rms_db, N);
+    let hold = scale.segments(levels.hold_db, N);
+
+    for led in 0..N {
+        let position = led as f32 / N as f32;
+        let color = if led < lit {
+            if position < GREEN_UNTIL {
+                Rgb::new(0, 255, 0)
+            } else if position < YELLOW_UNTIL {
+                Rgb::new(255, 160, 0)
+            } else {
+                Rgb::new(255, 0, 0)
+            }
+        } else if hold > 0 && led == hold - 1 {
+            Rgb::new(160, 160, 160)
+        } else {
+            Rgb::OFF
+        };
+        frame.set(led, color);
+    }
+
+    if clipped && N > 0 {
+        frame.set(N - 1, Rgb::new(255, 0, 0));
+    }
+}
+
+// LCD1602 的自定义字符，第 k 个字符点亮左边的 k + 1 列，需要先写入 CGRAM 的 0 ~ 4 号字符
+pub(crate) const LCD_BAR_GLYPHS: [[u8; 8]; 5] = [
+    [0b10000; 8],
+    [0b11000; 8],
+    [0b11100; 8],
+    [0b11110; 8],
+    [0b11111; 8],
+];
+const LCD_COLUMNS_PER_CELL: usize = 5;
+
+// 把 RMS 画成一行字符，每格 5 列，最后一格不满时使用部分点亮的字符，空白的格为空格
+pub(crate) fn lcd_bar(scale: &Scale, levels: &Levels, cells: &mut [u8]) {
+    let columns = scale.segments(levels.rms_db, cells.len() * LCD_COLUMNS_PER_CELL);
+    for (i, cell) in cells.iter_mut().enumerate() {
+        let lit = columns
+            .saturating_sub(i * LCD_COLUMNS_PER_CELL)
+            .min(LCD_COLUMNS_PER_CELL);
+        *cell = match lit {
+            0 => b' ',
+            n => (n - 1) as u8,
+        };
+    }
+}
@@ -0,0 +1,161 @@
+//! 用 GPIO 翻转驱动 ws2812，给没有合适 TIM 通道或 DMA Stream 的引脚使用
+//!
+//! 每个 bit 的时序由 DWT 的 CYCCNT 计数决定，而不是数指令：
+//!
+//! - 每个 bit 的起点都是上一个 bit 的起点加上固定的周期（1.25 us），先拉高，等到 T0H 或 T1H 之后再拉低
+//! - 写 BSRR 只需要一次总线访问，每次翻转的误差只有几个 HCLK 周期，与前面的代码执行了多久无关
+//!
+//! 一颗灯的 24 bit 必须连续发送，期间被中断打断，高电平被拉长就会被当作 bit 1，因此：
+//!
+//! - 每颗灯发送时屏蔽中断，约 30 us，这就是这个后端给其它中断额外带来的最大延迟，与灯带长度无关
+//! - 两颗灯之间打开中断，挂起的中断在这里得到处理；ws2812 把超过复位时间的低电平当作一帧的结束，
+//!   所以两颗灯之间的中断处理必须短于 MAX_GAP_US，否则后面的数据会从第一颗灯重新开始写，
+//!   发送过程中检测到这种情况会立刻返回 Interrupted，调用者可以等复位之后重发
+//! - 整个发送期间 CPU 一直在忙等，一次最多发送 MAX_LEDS 颗灯（约 2 ms），超出则直接返回 TooLong，
+//!   避免主循环被长时间占用，也让上面的间隔检测失败的机会保持在很小的范围内；更长的灯带应该使用 TIM + DMA
+//!
+//! SYSCLK 至少为 MIN_SYSCLK_HZ，否则 T0H 只有十几个周期，读取 CYCCNT 与写 BSRR 的开销就占了一大半
+
+#![allow(dead_code)]
+
+use cortex_m::peripheral::DWT;
+use stm32f4xx_hal::pac;
+
+use super::{
+    clock_gate,
+    port::Port,
+    ws2812::{FrameBuffer, Ws2812Out, BITS_PER_LED},
+};
+
+pub(crate) const MAX_LEDS: usize = 64;
+pub(crate) const MIN_SYSCLK_HZ: u32 = 48_000_000;
+
+// 一个 bit 的周期，以及 bit 0、bit 1 的高电平时间，单位 ns
+const PERIOD_NS: u32 = 1250;
+const T0H_NS: u32 = 400;
+const T1H_NS: u32 = 800;
+// 老款 ws2812 的复位时间为 50 us，新款 ws2812b 为 280 us，这里两者都能满足
+const RESET_US: u32 = 300;
+// 两颗灯之间的低电平不能超过老款的复位时间，留一些余量
+const MAX_GAP_US: u32 = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BitBangError {
+    InvalidPin,
+    ClockTooSlow,
+    // 帧缓冲超过 MAX_LEDS
+    TooLong,
+    // 第 led 颗灯之前的间隔超过了 MAX_GAP_US，灯带很可能已经提前锁存
+    Interrupted { led: usize },
+}
+
+pub(crate) struct BitBang {
+    port: Port,
+    pin: u8,
+    period: u32,
+    t0h: u32,
+    t1h: u32,
+    reset: u32,
+    max_gap: u32,
+}
+
+impl BitBang {
+    // 把引脚设置为推挽输出、最高速度，并保持低电平
+    //
+    // 同时打开 DWT 的周期计数器，sysclk_hz 必须是实际的 SYSCLK 频率
+    pub(crate) fn new(
+        cp: &mut pac::CorePeripherals,
+        port: Port,
+        pin: u8,
+        sysclk_hz: u32,
+    ) -> Result<Self, BitBangError> {
+        if pin > 15 {
+            return Err(BitBangError::InvalidPin);
+        }
+        if sysclk_hz < MIN_SYSCLK_HZ {
+            return Err(BitBangError::ClockTooSlow);
+        }
+
+        cp.DCB.enable_trace();
+        cp.DWT.enable_cycle_counter();
+
+        clock_gate::claim(port.gate());
+
+        port.set_high_speed(pin);
+        port.set_output(pin, "ws2812_bitbang");
+
+        let cycles = |ns: u32| (sysclk_hz as u64 * ns as u64 / 1_000_000_000) as u32;
+        Ok(Self {
+            port,
+            pin,
+            period: cycles(PERIOD_NS),
+            t0h: cycles(T0H_NS),
+            t1h: cycles(T1H_NS),
+            reset: cycles(RESET_US * 1000),
+            max_gap: cycles(MAX_GAP_US * 1000),
+        })
+    }
+
+    // 屏蔽中断，发送一颗灯的 24 bit，等最后一个 bit 的低电平也保持够了再返回，返回值为返回时的 CYCCNT
+    //
+    // last_end 为上一颗灯返回时的 CYCCNT，屏蔽中断之后再检查间隔，超过 max_gap 则不发送，返回 None
+    fn send_led(&self, grb: u32, last_end: Option<u32>) -> Option<u32> {
+        let bsrr = self.port.bsrr();
+        let set = 1u32 << self.pin;
+        let reset = 1u32 << (self.pin + 16);
+
+        cortex_m::interrupt::free(|_| {
+            if let Some(end) = last_end {
+                // 上一颗灯结束到现在的低电平时间，包括中间处理中断的时间
+                if DWT::cycle_count().wrapping_sub(end) > self.max_gap {
+                    return None;
+                }
+            }
+
+            // 第一个 bit 不需要等待
+            let mut start = DWT::cycle_count().wrapping_sub(self.period);
+            for bit in (0..BITS_PER_LED).rev() {
+                let high = if grb & (1 << bit) != 0 {
+                    self.t1h
+                } else {
+                    self.t0h
+                };
+                while DWT::cycle_count().wrapping_sub(start) < self.period {}
+                start = start.wrapping_add(self.period);
+                unsafe { bsrr.write_volatile(set) };
+                while DWT::cycle_count().wrapping_sub(start) < high {}
+                unsafe { bsrr.write_volatile(reset) };
+            }
+            while DWT::cycle_count().wrapping_sub(start) < self.period {}
+            Some(DWT::cycle_count())
+        })
+    }
+
+    // 保持低电平，直到灯带锁存
+    fn latch(&self) {
+        let start = DWT::cycle_count();
+        while DWT::cycle_count().wrapping_sub(start) < self.reset {}
+    }
+}
+
+impl Ws2812Out for BitBang {
+    type Error = BitBangError;
+
+    fn show<const N: usize>(&mut self, frame: &FrameBuffer<N>) -> Result<(), Self::Error> {
+        if N > MAX_LEDS {
+            return Err(BitBangError::TooLong);
+        }
+
+        let mut last_end = None;
+        for (led, grb) in frame.grb_words().enumerate() {
+            last_end = self.send_led(grb, last_end);
+            if last_end.is_none() {
+                self.latch();
+                return Err(BitBangError::Interrupted { led });
+            }
+        }
+
+        self.latch();
+        Ok(())
+    }
+}
@@ -283,8 +283,7 @@ fn main() -> ! {
     loop {}
 }
 
-#[interrupt]
-fn EXTI17_RTC_ALARM() {
+fn on_rtc_alarm() {
     cortex_m::interrupt::free(|_cs| unsafe {
         let dp = pac::Peripherals::steal();
         dp.EXTI.pr.modify(|_, w| w.pr17().clear());
@@ -368,3 +367,16 @@ fn EXTI17_RTC_ALARM() {
         );
     });
 }
+
+// Alarm 的中断在 F413 的 pac 中叫 EXTI17_RTC_ALARM，在 F401/F411/F412 的 pac 中叫 RTC_ALARM，处理是一样的
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn EXTI17_RTC_ALARM() {
+    on_rtc_alarm();
+}
+
+#[cfg(not(feature = "stm32f413"))]
+#[interrupt]
+fn RTC_ALARM() {
+    on_rtc_alarm();
+}
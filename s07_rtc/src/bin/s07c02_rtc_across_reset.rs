@@ -15,6 +15,12 @@ use stm32f4xx_hal::pac::{self, interrupt, Peripherals, NVIC};
 
 static G_DP: Mutex<RefCell<Option<Peripherals>>> = Mutex::new(RefCell::new(None));
 
+// RTC Alarm 的中断，F413 的 pac 中叫 EXTI17_RTC_ALARM，F401/F411/F412 的 pac 中叫 RTC_ALARM
+#[cfg(feature = "stm32f413")]
+const RTC_ALARM_IRQ: interrupt = interrupt::EXTI17_RTC_ALARM;
+#[cfg(not(feature = "stm32f413"))]
+const RTC_ALARM_IRQ: interrupt = interrupt::RTC_ALARM;
+
 #[cortex_m_rt::entry]
 fn main() -> ! {
     rtt_init_print!();
@@ -189,7 +195,7 @@ fn enable_alarm_interrupt() {
         dp.EXTI.imr.modify(|_, w| w.mr17().unmasked());
 
         // 启用 RTC_Alarm 中断
-        unsafe { NVIC::unmask(interrupt::EXTI17_RTC_ALARM) };
+        unsafe { NVIC::unmask(RTC_ALARM_IRQ) };
 
         // 如果 RTC 的 Alarm A 的中断位已经被拉起来了，
         // 就立刻手动设置一下 Pending Register，触发一下中断处理
@@ -202,8 +208,7 @@ fn enable_alarm_interrupt() {
     });
 }
 
-#[interrupt]
-fn EXTI17_RTC_ALARM() {
+fn on_rtc_alarm() {
     cortex_m::interrupt::free(|cs| {
         let dp_ref = G_DP.borrow(cs).borrow();
         let dp = dp_ref.as_ref().unwrap();
@@ -285,3 +290,16 @@ fn EXTI17_RTC_ALARM() {
         );
     });
 }
+
+// 中断处理函数的名字必须与 pac 中的一致，见 RTC_ALARM_IRQ
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn EXTI17_RTC_ALARM() {
+    on_rtc_alarm();
+}
+
+#[cfg(not(feature = "stm32f413"))]
+#[interrupt]
+fn RTC_ALARM() {
+    on_rtc_alarm();
+}
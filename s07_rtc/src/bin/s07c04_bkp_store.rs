@@ -0,0 +1,139 @@
+//! 在 RTC_BKPxR 中保存带校验的结构体
+//!
+//! 原理见 utils::bkp_store，注意 F413 没有 Backup SRAM，这里用的是 RTC 的 80 字节的备份寄存器
+//!
+//! 每次启动时，从备份寄存器中读出“启动记录”，记下本次的复位原因（来自 RCC_CSR），并把启动次数加 1，再写回去
+//! 按下 Reset 按键，启动次数会不断增加；接上 VBAT 的话，即便拔掉 USB 再插上，记录也依旧存在
+//! 若在调试器中执行一次 Backup Domain Reset，或者记录被意外改写，校验会失败，记录从 0 开始
+//!
+//! 启动记录之后的寄存器用来保存出错现场，见 utils::crash_dump：
+//! DEMO_CRASH 为 true 时，第 3n + 1 次启动会主动 panic，第 3n + 2 次启动会执行一条未定义指令触发 HardFault，
+//! 处理函数保存现场之后立即软件复位，下一次启动时打印上一次的出错现场，因此可以看到 panic 与 HardFault 的记录交替出现
+//!
+//! 不需要额外接线
+
+#![no_std]
+#![no_main]
+
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::{exception, ExceptionFrame};
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    bkp_store::{self, Slot},
+    crash_dump::{self, CrashKind, CrashRecord},
+};
+
+// 是否主动触发 panic 与 HardFault
+const DEMO_CRASH: bool = true;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BootRecord {
+    boot_count: u32,
+    // 各种复位原因出现的次数
+    power_on: u16,
+    pin: u16,
+    software: u16,
+    watchdog: u16,
+    brown_out: u16,
+    low_power: u16,
+}
+
+static BOOT_RECORD: Slot<BootRecord> = Slot::new(0, 0xB007);
+static CRASH: Slot<CrashRecord> = Slot::new(BOOT_RECORD.end(), crash_dump::TAG);
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    bkp_store::unlock(&dp);
+
+    let mut record = match BOOT_RECORD.load(&dp) {
+        Some(record) => record,
+        None => {
+            rprintln!("no valid boot record, start from zero");
+            BootRecord::default()
+        }
+    };
+
+    // 复位原因的标志位可能同时置位多个，例如上电时 POR 与 PIN 都会置位，这里只记录优先级最高的那一个
+    let csr = dp.RCC.csr.read();
+    let cause = if csr.borrstf().bit_is_set() && csr.porrstf().bit_is_set() {
+        record.power_on += 1;
+        "power on"
+    } else if csr.borrstf().bit_is_set() {
+        record.brown_out += 1;
+        "brown out"
+    } else if csr.wdgrstf().bit_is_set() || csr.wwdgrstf().bit_is_set() {
+        record.watchdog += 1;
+        "watchdog"
+    } else if csr.lpwrrstf().bit_is_set() {
+        record.low_power += 1;
+        "low power"
+    } else if csr.sftrstf().bit_is_set() {
+        record.software += 1;
+        "software"
+    } else {
+        record.pin += 1;
+        "pin"
+    };
+    // 清除复位标志，否则下一次复位时无法区分
+    dp.RCC.csr.modify(|_, w| w.rmvf().set_bit());
+
+    record.boot_count += 1;
+    BOOT_RECORD.save(&dp, &record);
+
+    rprintln!("reset cause: {}", cause);
+    rprintln!("{:#?}", record);
+    rprintln!(
+        "{} of {} backup registers used",
+        CRASH.end(),
+        bkp_store::REGISTER_COUNT
+    );
+
+    match crash_dump::take(&dp, &CRASH) {
+        Some(crash) => match crash.kind() {
+            Some(CrashKind::Panic) => rprintln!("last crash: panic at line {}", crash.line),
+            Some(CrashKind::HardFault) => rprintln!(
+                "last crash: HardFault at PC {:#010x}, LR {:#010x}, CFSR {:#010x}, HFSR {:#010x}, address {:#010x}",
+                crash.pc,
+                crash.lr,
+                crash.cfsr,
+                crash.hfsr,
+                crash.fault_address
+            ),
+            None => rprintln!("last crash: unknown kind"),
+        },
+        None => rprintln!("no crash recorded"),
+    }
+
+    if DEMO_CRASH {
+        match record.boot_count % 3 {
+            1 => panic!("deliberate panic on boot {}", record.boot_count),
+            // 未定义指令产生 UsageFault，它没有被单独启用，因此升级为 HardFault
+            2 => cortex_m::asm::udf(),
+            _ => {}
+        }
+    }
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+// 保存现场之后立即复位，不在出错的状态下做更多的事情
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    crash_dump::save_panic(&CRASH, info);
+    SCB::sys_reset()
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    crash_dump::save_hard_fault(&CRASH, frame);
+    SCB::sys_reset()
+}
@@ -0,0 +1,206 @@
+//! 外置的 DS1302/DS1307 时钟芯片，与内部的 RTC 使用同一套 DateTime
+//!
+//! 驱动见 utils::ds1302、utils::ds1307 与 utils::rtc，三者都实现了 utils::datetime::Clock
+//!
+//! 启动时依次检查 DS1307、DS1302 与内部 RTC，以第一个时间有效的时钟为准，校准另外两个；
+//! 三个都无效时（比如第一次上电），使用 DEFAULT_TIME
+//! 之后每秒打印一次三个时钟的时间，正常情况下三者应该一致，长时间运行后可以对比它们各自的走时误差
+//!
+//! 两个芯片的 NV RAM 开头的 4 个字节各保存一个启动计数，每次启动加 1，只要电池有电，拔掉 USB 之后计数也不会丢失
+//!
+//! DS1302 的 VCC1 接的是可充电的电池（比如 ML2032）或超级电容时，可以把 TRICKLE_CHARGE 改为 true，
+//! 接的是普通的 CR2032 时，千万不要打开
+//!
+//! 接线图：
+//!
+//! STM32 <-> DS1307 模块
+//!    5V <-> VCC
+//!   PB8 <-> SCL (I2C1)
+//!   PB9 <-> SDA (I2C1)
+//!   GND <-> GND
+//!
+//! STM32 <-> DS1302 模块
+//!  3.3V <-> VCC
+//!   PC0 <-> CE（RST）
+//!   PC1 <-> SCLK
+//!   PC2 <-> I/O（DAT）
+//!   GND <-> GND
+//!
+//! 内部 RTC 需要 32.768 kHz 的 LSE，与 s07c02 相同
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    bkp_store,
+    datetime::{Clock, DateTime},
+    ds1302::{Ds1302, TrickleDiodes, TrickleResistor},
+    ds1307::Ds1307,
+    rtc::InternalRtc,
+};
+
+const TRICKLE_CHARGE: bool = false;
+
+// 启动计数在 NV RAM 中的位置
+const BOOT_COUNT_OFFSET: usize = 0;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    setup_i2c1(&dp);
+    bkp_store::unlock(&dp);
+
+    let mut internal = InternalRtc::new(&dp);
+    let mut ds1302 = Ds1302::new(&dp);
+    let mut ds1307 = match Ds1307::new(&dp.I2C1) {
+        Ok(ds1307) => ds1307,
+        Err(e) => {
+            rprintln!("DS1307 not found: {:?}", e);
+            #[allow(clippy::empty_loop)]
+            loop {}
+        }
+    };
+
+    ds1302.set_trickle(if TRICKLE_CHARGE {
+        Some((TrickleDiodes::One, TrickleResistor::K4))
+    } else {
+        None
+    });
+
+    // 振荡器停止时，芯片里的时间不再可信
+    let ds1307_time = match ds1307.is_halted() {
+        Ok(false) => ds1307.now().ok(),
+        _ => None,
+    };
+    let ds1302_time = if ds1302.is_halted() {
+        None
+    } else {
+        ds1302.now().ok()
+    };
+    let internal_time = if internal.is_set() {
+        internal.now().ok()
+    } else {
+        None
+    };
+
+    let (source, reference) = if let Some(time) = ds1307_time {
+        ("DS1307", time)
+    } else if let Some(time) = ds1302_time {
+        ("DS1302", time)
+    } else if let Some(time) = internal_time {
+        ("internal RTC", time)
+    } else {
+        ("default", default_time())
+    };
+    rprintln!("reference time from {}: {}", source, reference);
+
+    if ds1307_time.is_none() {
+        ds1307.set(&reference).unwrap();
+    }
+    if ds1302_time.is_none() {
+        ds1302.set(&reference).unwrap();
+    }
+    if internal_time.is_none() {
+        internal.set(&reference).unwrap();
+    }
+
+    let mut count = [0u8; 4];
+    ds1307.read_ram(BOOT_COUNT_OFFSET, &mut count).unwrap();
+    let ds1307_boots = u32::from_le_bytes(count).wrapping_add(1);
+    ds1307
+        .write_ram(BOOT_COUNT_OFFSET, &ds1307_boots.to_le_bytes())
+        .unwrap();
+
+    ds1302.read_ram(BOOT_COUNT_OFFSET, &mut count).unwrap();
+    let ds1302_boots = u32::from_le_bytes(count).wrapping_add(1);
+    ds1302
+        .write_ram(BOOT_COUNT_OFFSET, &ds1302_boots.to_le_bytes())
+        .unwrap();
+
+    rprintln!(
+        "boot count: DS1307 {}, DS1302 {}",
+        ds1307_boots,
+        ds1302_boots
+    );
+
+    let mut last_second = None;
+    loop {
+        // 以内部 RTC 为节拍，每秒打印一次
+        let now = internal.now().unwrap();
+        if last_second == Some(now.second) {
+            continue;
+        }
+        last_second = Some(now.second);
+
+        rprintln!("internal {}", now);
+        print_clock("DS1307  ", &mut ds1307);
+        print_clock("DS1302  ", &mut ds1302);
+    }
+}
+
+fn print_clock<C: Clock>(name: &str, clock: &mut C)
+where
+    C::Error: core::fmt::Debug,
+{
+    match clock.now() {
+        Ok(time) => rprintln!("{} {}", name, time),
+        Err(e) => rprintln!("{} error: {:?}", name, e),
+    }
+}
+
+fn default_time() -> DateTime {
+    DateTime::new(2024, 1, 1, 0, 0, 0).unwrap()
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// I2C1 使用 PB8/PB9，100 kHz，配置方法见 s04
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
@@ -0,0 +1,98 @@
+//! 用 LSE（或者 GPS 的 PPS）校准 HSI 驱动的 TIM5，得到长时间准确的单调时钟
+//!
+//! 原理见 utils::fll
+//!
+//! 这里使用默认的 16 MHz HSI，TIM5 的标称频率就是 16 MHz
+//! 每个窗口（WINDOW_S 秒）结束时输出：
+//!
+//! - freq：估计的 TIM5 实际频率，以及它相对标称频率的偏差，HSI 的偏差通常在几千 ppm 的量级
+//! - err：这个窗口的测量值相对估计值的偏差，锁定之后应该只有几十 ppb
+//! - 从 start 开始经过的时间，分别用三种方式计算：
+//!   ref 为参考源经过的秒数，raw 为直接按标称频率换算的 TIM5 计数，fll 为经过校准的 now_ns
+//!   运行一段时间之后可以看到，raw 与 ref 的差距越来越大，而 fll 始终紧跟 ref
+//!
+//! 把 REFERENCE 改为 Reference::Pps 就改为使用 GPS 模块的 PPS
+//!
+//! 接线图：
+//!
+//! 内部 RTC 需要 32.768 kHz 的 LSE，与 s07c02 相同
+//!
+//! 使用 PPS 时：
+//! STM32 <-> GPS 模块
+//!   PA3 <-> PPS
+//!   GND <-> GND
+
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+use utils::{
+    bkp_store,
+    fll::{self, Reference},
+    rtc::InternalRtc,
+};
+
+const TIM_CLK_HZ: u32 = 16_000_000;
+const REFERENCE: Reference = Reference::RtcSecond;
+const WINDOW_S: u32 = 16;
+
+static G_WINDOW_DONE: AtomicBool = AtomicBool::new(false);
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Program Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    bkp_store::unlock(&dp);
+    if REFERENCE == Reference::RtcSecond {
+        // 只是为了确保 RTC 已经运行在 LSE 上
+        InternalRtc::new(&dp);
+    }
+
+    fll::start(&dp, REFERENCE, TIM_CLK_HZ, WINDOW_S);
+
+    loop {
+        if !G_WINDOW_DONE.swap(false, Ordering::Relaxed) {
+            continue;
+        }
+
+        let Some(status) = fll::status() else {
+            continue;
+        };
+
+        let raw_ms = fll::now_ticks() * 1000 / TIM_CLK_HZ as u64;
+        let fll_ms = fll::now_ns() / 1_000_000;
+
+        rprintln!(
+            "freq {}.{:03} Hz ({} ppb) err {} ppb {}",
+            status.freq_mhz / 1000,
+            status.freq_mhz % 1000,
+            status.offset_ppb,
+            status.last_error_ppb,
+            if status.locked { "locked" } else { "tracking" }
+        );
+        rprintln!(
+            "  ref {} s, raw {} ms, fll {} ms, rejected {}",
+            status.seconds,
+            raw_ms,
+            fll_ms,
+            status.rejected
+        );
+    }
+}
+
+#[interrupt]
+fn TIM5() {
+    if fll::on_tim5() {
+        G_WINDOW_DONE.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,139 @@
+//! 按挂钟时间执行任务，并在两次任务之间进入 Stop 模式
+//!
+//! 调度器见 utils::cron，闹钟与唤醒计时器见 utils::rtc
+//!
+//! 这里注册了三个任务：
+//!
+//! - "every 15 s"：演示短周期的任务，运行起来马上就能看到效果
+//! - "every day 07:30"：每天早上执行一次
+//! - "every mon 09:00"：每周一执行一次
+//!
+//! 每次醒来时打印醒来的原因，然后执行到期的任务，重新设置闹钟，再次进入 Stop 模式
+//! 没有任务到期的那些唤醒来自唤醒计时器，它每 RESYNC_S 秒唤醒一次，防止闹钟错过
+//!
+//! Stop 模式下 Cortex 核心与 HSI 都会停下，RTC 继续由 LSE 驱动，醒来之后 SYSCLK 会切回 HSI，
+//! 这里本来就使用默认的 HSI，所以不需要重新配置时钟
+//! 为了让 RTT 在 Stop 模式下也能保持连接，需要设置 DBGMCU_CR 的 DBG_STOP
+//!
+//! 接线图：
+//!
+//! 内部 RTC 需要 32.768 kHz 的 LSE，与 s07c02 相同
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+use utils::{
+    bkp_store,
+    cron::Cron,
+    datetime::{Clock, DateTime},
+    rtc::{self, InternalRtc},
+};
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Program Start");
+
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+    let dp = pac::Peripherals::take().unwrap();
+
+    dp.DBGMCU.cr.modify(|_, w| w.dbg_stop().set_bit());
+
+    bkp_store::unlock(&dp);
+    let mut rtc = InternalRtc::new(&dp);
+
+    if !rtc.is_set() {
+        rtc.set(&default_time()).unwrap();
+    }
+
+    let now = rtc.now().unwrap();
+    rprintln!("now {}", now);
+
+    let mut cron: Cron<4> = Cron::new();
+    cron.add("tick", "every 15 s", tick, &now).unwrap();
+    cron.add("morning", "every day 07:30", morning, &now)
+        .unwrap();
+    cron.add("weekly", "every mon 09:00", weekly, &now).unwrap();
+
+    for task in cron.tasks() {
+        rprintln!(
+            "{}: {}, next {}",
+            task.name,
+            task.schedule,
+            DateTime::from_seconds(task.next).unwrap()
+        );
+    }
+
+    // 上电之后可能还留着上次运行的标志
+    rtc.take_events();
+    rtc::enable_interrupts(&dp);
+
+    // Stop 模式：SLEEPDEEP 为 1，PDDS 为 0，LPDS 为 1 让电压调节器也进入低功耗模式
+    dp.PWR.cr.modify(|_, w| {
+        w.pdds().clear_bit();
+        w.lpds().set_bit();
+        w
+    });
+    cp.SCB.set_sleepdeep();
+
+    loop {
+        let now = rtc.now().unwrap();
+        let events = rtc.take_events();
+        if events.any() {
+            rprintln!(
+                "{} wakeup by{}{}{}",
+                now,
+                if events.alarm_a { " alarm_a" } else { "" },
+                if events.alarm_b { " alarm_b" } else { "" },
+                if events.wakeup { " wakeup_timer" } else { "" },
+            );
+        }
+
+        cron.run_due(&now);
+        cron.arm(&mut rtc, &now);
+
+        // 在 arm 与 wfi 之间触发的中断会保持挂起，wfi 会立刻返回，不会错过
+        cortex_m::asm::wfi();
+    }
+}
+
+// RTC 没有设置过时间时使用的时间，2024-01-01 是星期一，半分钟之后就能看到 morning 执行
+fn default_time() -> DateTime {
+    DateTime::new(2024, 1, 1, 7, 29, 30).unwrap()
+}
+
+fn tick(now: &DateTime) {
+    rprintln!("{} tick", now);
+}
+
+fn morning(now: &DateTime) {
+    rprintln!("{} good morning", now);
+}
+
+fn weekly(now: &DateTime) {
+    rprintln!("{} weekly report", now);
+}
+
+// F401/F411/F412 的 pac 中，Alarm 的中断名为 RTC_ALARM
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn EXTI17_RTC_ALARM() {
+    rtc::clear_exti();
+}
+
+#[cfg(not(feature = "stm32f413"))]
+#[interrupt]
+fn RTC_ALARM() {
+    rtc::clear_exti();
+}
+
+#[interrupt]
+fn RTC_WKUP() {
+    rtc::clear_exti();
+}
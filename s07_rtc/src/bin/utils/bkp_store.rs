@@ -0,0 +1,164 @@
+//! 在 Backup Domain 中保存带校验的数据
+//!
+//! F405/F407/F42x/F446 等型号有 4 KB 的 Backup SRAM（BKPSRAM），但 F413 并没有，
+//! F413 的 Backup Domain 里能保存数据的只有 RTC 的 20 个 32 bit 的 RTC_BKPxR 寄存器，一共 80 个字节
+//!
+//! 与 BKPSRAM 一样，只要 VDD 与 VBAT 中的一个有电，它们的内容就可以跨越 System Reset 而不丢失，
+//! 读写时也不需要像 FLASH 那样先擦除，速度与读写普通的外设寄存器一样
+//! 但也有例外：Backup Domain Reset（RCC_BDCR 的 BDRST）与入侵检测（Tamper）事件都会清空这些寄存器
+//!
+//! 因此这里在数据前加上一个头，里面有标签、长度与 CRC-32，读取时三者都对得上，才认为数据有效：
+//!
+//! | 寄存器        | 内容                                  |
+//! | ------------- | ------------------------------------- |
+//! | first         | 标签（高 16 bit）与长度（低 16 bit）  |
+//! | first + 1     | 数据的 CRC-32                         |
+//! | first + 2 ... | 数据，按小端序每 4 个字节放一个寄存器 |
+//!
+//! CRC-32 的多项式与 CRC 外设（见 s15）相同，为 0x04C11DB7，这里用软件计算，就不必再占用 CRC 外设了
+//!
+//! 写入之前，需要先开启 PWR 的时钟，并置位 PWR_CR 的 DBP，解除 Backup Domain 的写保护，见 unlock
+//!
+//! utils::crash_dump 在它之上保存 panic 与 HardFault 的现场，出错时不必再去擦写 Flash
+//!
+//! s21 的 settings（外部 Flash 中的 键 -> 值 设置）没有改为使用这里：它最多 16 个条目、将近 400 字节，
+//! 80 字节的备份寄存器放不下，而且设置本来就很少修改，Flash 的速度不是问题；
+//! 备份寄存器适合的是只有几个字、又需要频繁更新的状态，比如 s07c04 中的启动记录与出错现场
+
+#![allow(dead_code)]
+
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+
+use stm32f4xx_hal::pac::Peripherals;
+
+// RTC_BKPxR 的个数
+pub(crate) const REGISTER_COUNT: usize = 20;
+
+// 头所占的寄存器数
+const HEADER_LEN: usize = 2;
+
+// 解除 Backup Domain 的写保护，每次上电之后都要执行一次
+pub(crate) fn unlock(dp: &Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| w.dbp().set_bit());
+}
+
+// 保存在 first 开始的若干个 RTC_BKPxR 中的一个 T
+//
+// T 应当是只包含整数、布尔值之类的简单结构体，读出时直接按字节还原，
+// 因此不能包含引用、指针，以及并非所有位模式都合法的类型（例如没有覆盖所有取值的 enum）
+pub(crate) struct Slot<T: Copy> {
+    first: usize,
+    tag: u16,
+    _type: PhantomData<T>,
+}
+
+impl<T: Copy> Slot<T> {
+    // 占用的寄存器数，包含头
+    pub(crate) const REGISTERS: usize = HEADER_LEN + size_of::<T>().div_ceil(4);
+
+    // tag 用于区分不同的数据，修改了 T 的定义时，也应该换一个 tag，让旧的数据失效
+    pub(crate) const fn new(first: usize, tag: u16) -> Self {
+        assert!(first + Self::REGISTERS <= REGISTER_COUNT);
+        assert!(size_of::<T>() <= u16::MAX as usize);
+        Self {
+            first,
+            tag,
+            _type: PhantomData,
+        }
+    }
+
+    // 下一个 Slot 可以从这里开始
+    pub(crate) const fn end(&self) -> usize {
+        self.first + Self::REGISTERS
+    }
+
+    pub(crate) fn save(&self, dp: &Peripherals, value: &T) {
+        let bytes = as_bytes(value);
+
+        let mut crc = Crc32::new();
+        for (i, chunk) in bytes.chunks(4).enumerate() {
+            let word = pack(chunk);
+            crc.update(word);
+            dp.RTC.bkpr[self.first + HEADER_LEN + i].write(|w| w.bkp().bits(word));
+        }
+
+        // 最后再写头，这样写到一半被复位时，旧的头与新的数据对不上，读取时会被发现
+        dp.RTC.bkpr[self.first + 1].write(|w| w.bkp().bits(crc.finish()));
+        dp.RTC.bkpr[self.first].write(|w| w.bkp().bits(self.header()));
+    }
+
+    // 数据不存在或者校验失败时返回 None
+    pub(crate) fn load(&self, dp: &Peripherals) -> Option<T> {
+        if dp.RTC.bkpr[self.first].read().bkp().bits() != self.header() {
+            return None;
+        }
+
+        let mut value = MaybeUninit::<T>::uninit();
+        // SAFETY: 这里只把 value 当作一段字节来写入，校验通过之前不会把它当作 T 使用
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+
+        let mut crc = Crc32::new();
+        for (i, chunk) in bytes.chunks_mut(4).enumerate() {
+            let word = dp.RTC.bkpr[self.first + HEADER_LEN + i].read().bkp().bits();
+            crc.update(word);
+            chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+        }
+
+        if crc.finish() != dp.RTC.bkpr[self.first + 1].read().bkp().bits() {
+            return None;
+        }
+
+        // SAFETY: 所有字节都已写入，且与 save 时的内容一致
+        Some(unsafe { value.assume_init() })
+    }
+
+    // 只需要清除头，数据就失效了
+    pub(crate) fn erase(&self, dp: &Peripherals) {
+        dp.RTC.bkpr[self.first].write(|w| w.bkp().bits(0));
+    }
+
+    fn header(&self) -> u32 {
+        (self.tag as u32) << 16 | size_of::<T>() as u32
+    }
+}
+
+fn as_bytes<T: Copy>(value: &T) -> &[u8] {
+    // SAFETY: T 为 Copy，这里只读取它的字节
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+// 不足 4 个字节的部分补 0
+fn pack(chunk: &[u8]) -> u32 {
+    let mut word = [0u8; 4];
+    word[..chunk.len()].copy_from_slice(chunk);
+    u32::from_le_bytes(word)
+}
+
+// 与 CRC 外设相同的 CRC-32：多项式 0x04C11DB7，初值 0xFFFFFFFF，按 32 bit 的字输入，不反转，不异或输出
+struct Crc32(u32);
+
+impl Crc32 {
+    fn new() -> Self {
+        Self(0xFFFF_FFFF)
+    }
+
+    fn update(&mut self, word: u32) {
+        let mut crc = self.0 ^ word;
+        for _ in 0..32 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+        self.0 = crc;
+    }
+
+    fn finish(&self) -> u32 {
+        self.0
+    }
+}
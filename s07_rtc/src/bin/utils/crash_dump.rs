@@ -0,0 +1,115 @@
+//! 把 panic 与 HardFault 的现场保存在 RTC_BKPxR 中，下次启动时读出
+//!
+//! s12 的 flash_log 在 panic 与 HardFault 时把日志写入内部 Flash，但写 Flash 要先解锁、等待每个字的编程完成，
+//! 扇区满了还要擦除 1~2 秒，而出错时系统的状态本身就不可信，这时做的事情越少越好
+//! 备份寄存器不需要擦除，写一个字与写普通的外设寄存器一样快，只要 VDD 或 VBAT 有电就能跨越复位保存下来，
+//! 正好用来保存“最后一次出错”的现场：几个字就够了，然后立即复位，下次启动时再慢慢打印或者写入 Flash
+//!
+//! 记录使用 utils::bkp_store 的 Slot 保存，有标签、长度与 CRC-32，复位时写到一半也能被发现
+//!
+//! HardFault 记录的是硬件压栈的 PC、LR、xPSR，以及 SCB 中的 CFSR、HFSR，BFAR 或 MMFAR 有效时记录出错的地址
+//! panic 记录的是 panic 所在的行号，文件名与信息太长，放不进 80 字节的备份寄存器
+//!
+//! 用法：
+//!
+//! 1. 在程序中定义一个 static CRASH: Slot<CrashRecord>，与其它 Slot 的寄存器不重叠
+//! 2. 在 #[panic_handler] 中调用 save_panic，在 #[exception] HardFault 中调用 save_hard_fault，之后复位
+//! 3. 启动时调用 take，取出并清除上一次的记录
+//!
+//! 处理函数中拿不到 main 里的 Peripherals，这里用 steal 取得，只访问 PWR、RTC 与 SCB，不会影响其它外设
+
+#![allow(dead_code)]
+
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::ExceptionFrame;
+use stm32f4xx_hal::pac::Peripherals;
+
+use super::bkp_store::{self, Slot};
+
+// 修改 CrashRecord 的定义时，也应该换一个标签
+pub(crate) const TAG: u16 = 0xDEAD;
+
+// CFSR 中 MMFAR 与 BFAR 是否有效的标志
+const CFSR_MMARVALID: u32 = 1 << 7;
+const CFSR_BFARVALID: u32 = 1 << 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub(crate) enum CrashKind {
+    Panic = 1,
+    HardFault = 2,
+}
+
+// 所有字段都是 u32，kind 在 load 之后由 kind() 检查，不直接存 enum，避免读出非法的位模式
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct CrashRecord {
+    kind: u32,
+    pub(crate) pc: u32,
+    pub(crate) lr: u32,
+    pub(crate) xpsr: u32,
+    pub(crate) cfsr: u32,
+    pub(crate) hfsr: u32,
+    // BFAR 或 MMFAR，两者都无效时为 0
+    pub(crate) fault_address: u32,
+    // panic 所在的行号，HardFault 时为 0
+    pub(crate) line: u32,
+}
+
+impl CrashRecord {
+    pub(crate) fn kind(&self) -> Option<CrashKind> {
+        match self.kind {
+            1 => Some(CrashKind::Panic),
+            2 => Some(CrashKind::HardFault),
+            _ => None,
+        }
+    }
+}
+
+// 在 #[panic_handler] 中调用
+pub(crate) fn save_panic(slot: &Slot<CrashRecord>, info: &core::panic::PanicInfo) {
+    let record = CrashRecord {
+        kind: CrashKind::Panic as u32,
+        line: info.location().map_or(0, |location| location.line()),
+        ..Default::default()
+    };
+    save(slot, &record);
+}
+
+// 在 #[exception] HardFault 中调用
+pub(crate) fn save_hard_fault(slot: &Slot<CrashRecord>, frame: &ExceptionFrame) {
+    let scb = unsafe { &*SCB::PTR };
+    let cfsr = scb.cfsr.read();
+    let fault_address = if cfsr & CFSR_BFARVALID != 0 {
+        scb.bfar.read()
+    } else if cfsr & CFSR_MMARVALID != 0 {
+        scb.mmfar.read()
+    } else {
+        0
+    };
+
+    let record = CrashRecord {
+        kind: CrashKind::HardFault as u32,
+        pc: frame.pc(),
+        lr: frame.lr(),
+        xpsr: frame.xpsr(),
+        cfsr,
+        hfsr: scb.hfsr.read(),
+        fault_address,
+        line: 0,
+    };
+    save(slot, &record);
+}
+
+// 取出上一次的记录，并清除它，避免下次启动时重复报告
+pub(crate) fn take(dp: &Peripherals, slot: &Slot<CrashRecord>) -> Option<CrashRecord> {
+    let record = slot.load(dp)?;
+    slot.erase(dp);
+    Some(record)
+}
+
+fn save(slot: &Slot<CrashRecord>, record: &CrashRecord) {
+    let dp = unsafe { Peripherals::steal() };
+    // 出错时可能还没有执行过 unlock，再做一次也没有坏处
+    bkp_store::unlock(&dp);
+    slot.save(&dp, record);
+}
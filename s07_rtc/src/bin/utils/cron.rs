@@ -0,0 +1,316 @@
+//! 按挂钟时间执行任务，类似 cron
+//!
+//! 任务用一个简单的表达式描述什么时候执行：
+//!
+//! - "every day 07:30"、"every day 07:30:15"：每天的固定时刻
+//! - "every mon 09:00"：每周固定的星期与时刻，星期为 mon/tue/wed/thu/fri/sat/sun
+//! - "every 15 min"、"every 10 s"、"every 2 h"：固定的周期，单位为 s/sec/min/h/hour
+//!
+//! 周期必须能整除一天，并且从每天的 00:00:00 开始对齐，比如 "every 15 min" 总是在 xx:00、xx:15、xx:30、xx:45 执行，
+//! 与任务是什么时候添加的无关，这样复位之后执行的时刻也不会变
+//!
+//! 所有的时间都换算为 DateTime::to_seconds 的秒数，每个任务记下自己下一次执行的时刻
+//! 调度器本身不知道中断与低功耗，使用的方式是循环执行：
+//!
+//! 1. run_due：执行所有到期的任务，错过的多次只执行一次，然后算出各自的下一次
+//! 2. arm：把最近的时刻设置到 Alarm A，次近的设置到 Alarm B，再让唤醒计时器每 RESYNC_S 秒唤醒一次
+//! 3. 进入 Stop 模式，等待上面三者之一把芯片唤醒，回到 1
+//!
+//! 多个任务的时刻冲突时不需要特别处理，两个闹钟总是指向最近的两个不同的时刻，每次醒来都会重新设置
+//! 唤醒计时器是保底的：闹钟只比较日期与时分秒，超过 MAX_ALARM_AHEAD_S 的时刻不会设置到闹钟上；
+//! 另外设置闹钟的瞬间恰好越过了闹钟的时刻，或者 RTC 的时间被修改，闹钟也可能错过，这些情况都由唤醒计时器兜底
+
+#![allow(dead_code)]
+
+use core::fmt;
+
+use super::{
+    datetime::{DateTime, Weekday, SECONDS_PER_DAY},
+    rtc::{Alarm, InternalRtc},
+};
+
+// 唤醒计时器的周期
+pub(crate) const RESYNC_S: u32 = 60;
+
+// 闹钟不比较月份，时刻太远的话，可能在前面某个月的同一天提前触发
+pub(crate) const MAX_ALARM_AHEAD_S: u32 = 28 * SECONDS_PER_DAY;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Schedule {
+    Daily {
+        hour: u8,
+        minute: u8,
+        second: u8,
+    },
+    Weekly {
+        weekday: Weekday,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    },
+    // 能整除一天的秒数
+    Every {
+        seconds: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CronError {
+    // 表达式的格式不对
+    Syntax,
+    // 时刻不合法，或者周期不能整除一天
+    OutOfRange,
+    // 任务表已满
+    Full,
+}
+
+impl Schedule {
+    pub(crate) fn parse(expr: &str) -> Result<Self, CronError> {
+        let mut words = expr.split_whitespace();
+        if words.next() != Some("every") {
+            return Err(CronError::Syntax);
+        }
+        let (Some(first), Some(second), None) = (words.next(), words.next(), words.next()) else {
+            return Err(CronError::Syntax);
+        };
+
+        if first == "day" {
+            let (hour, minute, second) = parse_time(second)?;
+            return Ok(Schedule::Daily {
+                hour,
+                minute,
+                second,
+            });
+        }
+
+        if let Some(weekday) = Weekday::from_short_name(first) {
+            let (hour, minute, second) = parse_time(second)?;
+            return Ok(Schedule::Weekly {
+                weekday,
+                hour,
+                minute,
+                second,
+            });
+        }
+
+        let count: u32 = first.parse().map_err(|_| CronError::Syntax)?;
+        let unit = match second {
+            "s" | "sec" => 1,
+            "min" => 60,
+            "h" | "hour" => 3600,
+            _ => return Err(CronError::Syntax),
+        };
+        let seconds = count.checked_mul(unit).ok_or(CronError::OutOfRange)?;
+        if seconds == 0 || !SECONDS_PER_DAY.is_multiple_of(seconds) {
+            return Err(CronError::OutOfRange);
+        }
+        Ok(Schedule::Every { seconds })
+    }
+
+    // now 之后（不含 now）第一次执行的时刻
+    pub(crate) fn next_after(&self, now: u32) -> u32 {
+        let midnight = now - now % SECONDS_PER_DAY;
+        match *self {
+            Schedule::Daily {
+                hour,
+                minute,
+                second,
+            } => {
+                let at = midnight + time_of_day(hour, minute, second);
+                if at > now {
+                    at
+                } else {
+                    at + SECONDS_PER_DAY
+                }
+            }
+            Schedule::Weekly {
+                weekday,
+                hour,
+                minute,
+                second,
+            } => {
+                // 2000-01-01 是星期六
+                let today = ((now / SECONDS_PER_DAY + 5) % 7 + 1) as u8;
+                let days_ahead = (weekday.number() + 7 - today) % 7;
+                let at = midnight
+                    + days_ahead as u32 * SECONDS_PER_DAY
+                    + time_of_day(hour, minute, second);
+                if at > now {
+                    at
+                } else {
+                    at + 7 * SECONDS_PER_DAY
+                }
+            }
+            Schedule::Every { seconds } => (now / seconds + 1) * seconds,
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Schedule::Daily {
+                hour,
+                minute,
+                second,
+            } => write!(f, "every day {:02}:{:02}:{:02}", hour, minute, second),
+            Schedule::Weekly {
+                weekday,
+                hour,
+                minute,
+                second,
+            } => write!(
+                f,
+                "every {} {:02}:{:02}:{:02}",
+                weekday.short_name(),
+                hour,
+                minute,
+                second
+            ),
+            Schedule::Every { seconds } => write!(f, "every {} s", seconds),
+        }
+    }
+}
+
+// HH:MM 或 HH:MM:SS
+fn parse_time(text: &str) -> Result<(u8, u8, u8), CronError> {
+    let mut fields = text.split(':');
+    let mut next = |required| match fields.next() {
+        Some(field) => field.parse::<u8>().map_err(|_| CronError::Syntax),
+        None if required => Err(CronError::Syntax),
+        None => Ok(0),
+    };
+    let (hour, minute, second) = (next(true)?, next(true)?, next(false)?);
+    if fields.next().is_some() {
+        return Err(CronError::Syntax);
+    }
+    if hour >= 24 || minute >= 60 || second >= 60 {
+        return Err(CronError::OutOfRange);
+    }
+    Ok((hour, minute, second))
+}
+
+fn time_of_day(hour: u8, minute: u8, second: u8) -> u32 {
+    hour as u32 * 3600 + minute as u32 * 60 + second as u32
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Task {
+    pub(crate) name: &'static str,
+    pub(crate) schedule: Schedule,
+    // 执行时传入当前的时间
+    pub(crate) job: fn(&DateTime),
+    // 下一次执行的时刻，DateTime::to_seconds 的秒数
+    pub(crate) next: u32,
+    // 已经执行的次数
+    pub(crate) runs: u32,
+}
+
+// 最多 N 个任务
+pub(crate) struct Cron<const N: usize> {
+    tasks: [Option<Task>; N],
+}
+
+impl<const N: usize> Cron<N> {
+    pub(crate) const fn new() -> Self {
+        Self { tasks: [None; N] }
+    }
+
+    // 添加一个任务，第一次执行的时刻从 now 开始计算
+    pub(crate) fn add(
+        &mut self,
+        name: &'static str,
+        expr: &str,
+        job: fn(&DateTime),
+        now: &DateTime,
+    ) -> Result<(), CronError> {
+        let schedule = Schedule::parse(expr)?;
+        let slot = self
+            .tasks
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(CronError::Full)?;
+        slot.replace(Task {
+            name,
+            schedule,
+            job,
+            next: schedule.next_after(now.to_seconds()),
+            runs: 0,
+        });
+        Ok(())
+    }
+
+    // 按名字删除任务，返回是否找到
+    pub(crate) fn remove(&mut self, name: &str) -> bool {
+        match self
+            .tasks
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|task| task.name == name))
+        {
+            Some(slot) => {
+                slot.take();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn tasks(&self) -> impl Iterator<Item = &Task> {
+        self.tasks.iter().flatten()
+    }
+
+    // 执行所有到期的任务，返回执行的个数
+    // 睡眠期间错过了多次的任务（比如 RTC 的时间被往后调了）只执行一次，下一次从 now 之后重新计算
+    pub(crate) fn run_due(&mut self, now: &DateTime) -> usize {
+        let now_s = now.to_seconds();
+        let mut ran = 0;
+        for task in self.tasks.iter_mut().flatten() {
+            if task.next > now_s {
+                continue;
+            }
+            (task.job)(now);
+            task.runs += 1;
+            task.next = task.schedule.next_after(now_s);
+            ran += 1;
+        }
+        ran
+    }
+
+    // 最近的两个不同的时刻，几个任务在同一时刻执行时只算一个
+    pub(crate) fn upcoming(&self) -> (Option<u32>, Option<u32>) {
+        let mut first: Option<u32> = None;
+        let mut second: Option<u32> = None;
+        for next in self.tasks().map(|task| task.next) {
+            match first {
+                Some(first_at) if next == first_at => {}
+                Some(first_at) if next > first_at => match second {
+                    Some(second_at) if second_at <= next => {}
+                    _ => second = Some(next),
+                },
+                _ => {
+                    second = first;
+                    first = Some(next);
+                }
+            }
+        }
+        (first, second)
+    }
+
+    // 按照任务表重新设置两个闹钟与唤醒计时器，应该在 run_due 之后、睡眠之前调用
+    pub(crate) fn arm(&self, rtc: &mut InternalRtc, now: &DateTime) {
+        let now_s = now.to_seconds();
+        let (first, second) = self.upcoming();
+
+        for (alarm, at) in [(Alarm::A, first), (Alarm::B, second)] {
+            match at
+                .filter(|&at| at.saturating_sub(now_s) <= MAX_ALARM_AHEAD_S)
+                .and_then(DateTime::from_seconds)
+            {
+                Some(at) => rtc.set_alarm(alarm, &at),
+                None => rtc.disable_alarm(alarm),
+            }
+        }
+
+        rtc.set_wakeup(RESYNC_S);
+    }
+}
@@ -0,0 +1,252 @@
+//! 日期与时间
+//!
+//! STM32 自带的 RTC（utils::rtc）、DS1302（utils::ds1302）与 DS1307（utils::ds1307）都以 BCD 码保存年月日、星期与时分秒，
+//! 年份都只有两位，星期都是一个 1 ~ 7 的数字，这里把它们统一为同一个 DateTime，
+//! 三者都实现了 Clock，可以互相校准，比如用外置的时钟芯片在上电时恢复内部 RTC 的时间
+//!
+//! 星期的编号由使用者决定，芯片只是每天加 1，这里与 STM32 的 RTC 一致，1 为星期一，7 为星期日
+
+#![allow(dead_code)]
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Weekday {
+    Monday = 1,
+    Tuesday = 2,
+    Wednesday = 3,
+    Thursday = 4,
+    Friday = 5,
+    Saturday = 6,
+    Sunday = 7,
+}
+
+impl Weekday {
+    pub(crate) fn from_number(number: u8) -> Option<Self> {
+        Some(match number {
+            1 => Weekday::Monday,
+            2 => Weekday::Tuesday,
+            3 => Weekday::Wednesday,
+            4 => Weekday::Thursday,
+            5 => Weekday::Friday,
+            6 => Weekday::Saturday,
+            7 => Weekday::Sunday,
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn number(self) -> u8 {
+        self as u8
+    }
+
+    // 不区分大小写，比如 "mon"、"Mon"
+    pub(crate) fn from_short_name(name: &str) -> Option<Self> {
+        (1..=7)
+            .filter_map(Self::from_number)
+            .find(|weekday| weekday.short_name().eq_ignore_ascii_case(name))
+    }
+
+    pub(crate) fn short_name(self) -> &'static str {
+        match self {
+            Weekday::Monday => "Mon",
+            Weekday::Tuesday => "Tue",
+            Weekday::Wednesday => "Wed",
+            Weekday::Thursday => "Thu",
+            Weekday::Friday => "Fri",
+            Weekday::Saturday => "Sat",
+            Weekday::Sunday => "Sun",
+        }
+    }
+}
+
+// 所有的时钟都只保存两位年份，这里认为是 2000 ~ 2099 年
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DateTime {
+    pub(crate) year: u16,
+    pub(crate) month: u8,
+    pub(crate) day: u8,
+    pub(crate) weekday: Weekday,
+    // 24 小时制
+    pub(crate) hour: u8,
+    pub(crate) minute: u8,
+    pub(crate) second: u8,
+}
+
+impl DateTime {
+    pub(crate) const YEAR_MIN: u16 = 2000;
+    pub(crate) const YEAR_MAX: u16 = 2099;
+
+    // 检查各个字段，并根据日期算出星期
+    pub(crate) fn new(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Option<Self> {
+        let datetime = Self {
+            year,
+            month,
+            day,
+            weekday: weekday_of(year, month, day),
+            hour,
+            minute,
+            second,
+        };
+        datetime.is_valid().then_some(datetime)
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        (Self::YEAR_MIN..=Self::YEAR_MAX).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    // 两位年份
+    pub(crate) fn year_of_century(&self) -> u8 {
+        (self.year - Self::YEAR_MIN) as u8
+    }
+
+    // 自 2000-01-01 00:00:00 以来的秒数，到 2099 年年底约为 3.16e9，u32 放得下
+    // 用来比较两个时间的先后，或者计算相隔的秒数
+    pub(crate) fn to_seconds(self) -> u32 {
+        let mut days = 0u32;
+        for year in Self::YEAR_MIN..self.year {
+            days += if is_leap_year(year) { 366 } else { 365 };
+        }
+        for month in 1..self.month {
+            days += days_in_month(self.year, month) as u32;
+        }
+        days += self.day as u32 - 1;
+        days * SECONDS_PER_DAY
+            + self.hour as u32 * 3600
+            + self.minute as u32 * 60
+            + self.second as u32
+    }
+
+    // to_seconds 的逆运算，超出 2099 年时返回 None
+    pub(crate) fn from_seconds(seconds: u32) -> Option<Self> {
+        let mut days = seconds / SECONDS_PER_DAY;
+        let second_of_day = seconds % SECONDS_PER_DAY;
+
+        let mut year = Self::YEAR_MIN;
+        loop {
+            let year_days = if is_leap_year(year) { 366 } else { 365 };
+            if days < year_days {
+                break;
+            }
+            days -= year_days;
+            year += 1;
+        }
+        let mut month = 1;
+        while days >= days_in_month(year, month) as u32 {
+            days -= days_in_month(year, month) as u32;
+            month += 1;
+        }
+
+        Self::new(
+            year,
+            month,
+            days as u8 + 1,
+            (second_of_day / 3600) as u8,
+            (second_of_day / 60 % 60) as u8,
+            (second_of_day % 60) as u8,
+        )
+    }
+}
+
+pub(crate) const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {} {:02}:{:02}:{:02}",
+            self.year,
+            self.month,
+            self.day,
+            self.weekday.short_name(),
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+// 外置时钟芯片的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RtcError {
+    // 通信出错，比如 I2C 的地址没有被 ACK
+    Bus,
+    // 芯片中的时间不合法，比如从来没有设置过，或者要写入的时间不合法
+    InvalidTime,
+    // NV RAM 的读写超出了范围
+    OutOfRange,
+}
+
+// 可以读取、设置时间的时钟
+pub(crate) trait Clock {
+    type Error;
+
+    fn now(&mut self) -> Result<DateTime, Self::Error>;
+    fn set(&mut self, datetime: &DateTime) -> Result<(), Self::Error>;
+}
+
+pub(crate) fn is_leap_year(year: u16) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+pub(crate) fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Sakamoto 算法，month 为 1 ~ 12
+pub(crate) fn weekday_of(year: u16, month: u8, day: u8) -> Weekday {
+    const OFFSET: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+    let year = if month < 3 {
+        year.saturating_sub(1)
+    } else {
+        year
+    };
+    let month_offset = OFFSET[(month.clamp(1, 12) - 1) as usize];
+    // 0 为星期日
+    let days = (year + year / 4 - year / 100 + year / 400 + month_offset + day as u16) % 7;
+    match days {
+        0 => Weekday::Sunday,
+        n => Weekday::from_number(n as u8).unwrap(),
+    }
+}
+
+// DS1302 与 DS1307 的小时寄存器，在 12 小时制与 24 小时制下的格式不同
+// 12 小时制时第 6 位为 1，第 5 位为 PM
+pub(crate) fn hour_from_register(reg: u8) -> u8 {
+    if reg & (1 << 6) == 0 {
+        return from_bcd(reg & 0x3F);
+    }
+    // 12 小时制下为 1 ~ 12，12 AM 是 0 点，12 PM 是 12 点
+    let hour = from_bcd(reg & 0x1F) % 12;
+    if reg & (1 << 5) != 0 {
+        hour + 12
+    } else {
+        hour
+    }
+}
+
+// 两位的 BCD 码与二进制互相转换，调用者需要保证 bcd 的每 4 位都小于 10
+pub(crate) fn from_bcd(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0x0F)
+}
+
+pub(crate) fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
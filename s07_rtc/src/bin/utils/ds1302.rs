@@ -0,0 +1,295 @@
+//! DS1302 实时时钟芯片（3 线接口，GPIO 模拟）
+//!
+//! DS1302 使用 CE、SCLK、I/O 三根线通信，不是标准的 SPI：数据只有一根双向的 I/O，且先发送最低位，
+//! 这里直接用 GPIO 模拟：
+//!
+//! - CE 拉高之后开始一次传输，拉低之后结束
+//! - 主机首先发送一个命令字节：第 7 位固定为 1，第 6 位为 1 时访问 RAM、为 0 时访问时钟，第 5~1 位为地址，第 0 位为 1 时读取
+//! - 写入时，主机在 SCLK 的上升沿之前准备好 I/O，芯片在上升沿采样
+//! - 读取时，命令字节最后一个上升沿之后，芯片在每个 SCLK 的下降沿输出一位，主机需要把 I/O 切换为输入
+//!
+//! 时钟寄存器的顺序为：秒（最高位为 CH）、分、时、日、月、星期、年、写保护（WP），与 DS1307 不同，日在星期之前
+//! 地址 31 为 burst 模式：一次连续读写全部 8 个时钟寄存器（或者全部 31 字节的 RAM），
+//! 芯片在 burst 读取开始时锁存当前时间，因此读到的时间是一致的，这里读写时间都使用 burst 模式
+//! 注意 burst 写入时钟寄存器时，必须把 8 个字节全部写完，否则数据不会生效
+//!
+//! WP（写保护）为 1 时，所有的写入都会被忽略，这里在每次写入之前都会先清除 WP
+//!
+//! 涓流充电（trickle charge）：VCC1 可以接可充电的电池或者超级电容，由 VCC2 通过 1~2 个二极管与 2k/4k/8k 的电阻充电，见 set_trickle
+//! 充电电流约为 (VCC2 - 二极管压降 * 二极管个数 - VCC1) / 电阻，接普通的纽扣电池时一定不能打开
+//!
+//! NV RAM 一共 31 字节，由 VCC1 保持
+//!
+//! 各个引脚都是直接操作寄存器，时序按 12 MHz 的 SYSCLK（HSE）计算，DS1302 在 2V 时 SCLK 最高 500 kHz，这里取 250 kHz 左右
+//!
+//! 接线图：
+//!
+//! STM32 <-> DS1302 模块
+//!  3.3V <-> VCC（VCC2）
+//!   PC0 <-> CE（RST）
+//!   PC1 <-> SCLK
+//!   PC2 <-> I/O（DAT）
+//!   GND <-> GND
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac;
+
+use super::datetime::{from_bcd, hour_from_register, to_bcd, Clock, DateTime, RtcError, Weekday};
+
+pub(crate) const RAM_LEN: usize = 31;
+
+const PIN_CE: u8 = 0;
+const PIN_SCLK: u8 = 1;
+const PIN_IO: u8 = 2;
+
+const SYSCLK_HZ: u32 = 12_000_000;
+// SCLK 的半个周期，2 us
+const HALF_PERIOD_CYCLES: u32 = SYSCLK_HZ / 500_000;
+// CE 拉高之后到第一个 SCLK 上升沿，至少 4 us
+const CE_SETUP_CYCLES: u32 = SYSCLK_HZ / 250_000;
+
+// 写入时的命令字节，读取时再加上 CMD_READ
+const CMD_SECONDS: u8 = 0x80;
+const CMD_WP: u8 = 0x8E;
+const CMD_TRICKLE: u8 = 0x90;
+const CMD_CLOCK_BURST: u8 = 0xBE;
+// RAM 的地址需要左移一位放进来
+const CMD_RAM: u8 = 0xC0;
+const CMD_READ: u8 = 1 << 0;
+
+const SECONDS_CH: u8 = 1 << 7;
+const WP: u8 = 1 << 7;
+
+// TCS 为 1010 时才会打开充电，其他值都会关闭
+const TRICKLE_TCS: u8 = 0b1010 << 4;
+const TRICKLE_OFF: u8 = 0x5C;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TrickleDiodes {
+    One = 0b01,
+    Two = 0b10,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TrickleResistor {
+    K2 = 0b01,
+    K4 = 0b10,
+    K8 = 0b11,
+}
+
+pub(crate) struct Ds1302 {
+    // 不能同时创建两个 Ds1302，它们会争抢同样的 GPIO
+    _private: (),
+}
+
+impl Ds1302 {
+    pub(crate) fn new(dp: &pac::Peripherals) -> Self {
+        dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+
+        let gpioc = &dp.GPIOC;
+        // CE 与 SCLK 在空闲时必须为低电平
+        gpioc.bsrr.write(|w| {
+            w.br0().reset();
+            w.br1().reset();
+            w
+        });
+        gpioc.moder.modify(|_, w| {
+            w.moder0().output();
+            w.moder1().output();
+            w.moder2().input();
+            w
+        });
+
+        Self { _private: () }
+    }
+
+    pub(crate) fn is_halted(&self) -> bool {
+        self.read_register(CMD_SECONDS) & SECONDS_CH != 0
+    }
+
+    // 停止/启动振荡器，秒的数值保持不变
+    pub(crate) fn set_halted(&self, halted: bool) {
+        let seconds = self.read_register(CMD_SECONDS);
+        let seconds = if halted {
+            seconds | SECONDS_CH
+        } else {
+            seconds & !SECONDS_CH
+        };
+        self.write_register(CMD_SECONDS, seconds);
+    }
+
+    // None 为关闭充电
+    pub(crate) fn set_trickle(&self, charge: Option<(TrickleDiodes, TrickleResistor)>) {
+        let value = match charge {
+            Some((diodes, resistor)) => TRICKLE_TCS | (diodes as u8) << 2 | resistor as u8,
+            None => TRICKLE_OFF,
+        };
+        self.write_register(CMD_TRICKLE, value);
+    }
+
+    pub(crate) fn read_ram(&self, offset: usize, buf: &mut [u8]) -> Result<(), RtcError> {
+        check_ram(offset, buf.len())?;
+        // RAM 的 burst 读取只能从地址 0 开始，这里逐个字节读取
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = self.transfer_read(CMD_RAM | ((offset + i) as u8) << 1, 1)[0];
+        }
+        Ok(())
+    }
+
+    pub(crate) fn write_ram(&self, offset: usize, data: &[u8]) -> Result<(), RtcError> {
+        check_ram(offset, data.len())?;
+        self.clear_write_protect();
+        for (i, &byte) in data.iter().enumerate() {
+            self.transfer_write(CMD_RAM | ((offset + i) as u8) << 1, &[byte]);
+        }
+        Ok(())
+    }
+
+    fn read_register(&self, cmd: u8) -> u8 {
+        self.transfer_read(cmd, 1)[0]
+    }
+
+    fn write_register(&self, cmd: u8, value: u8) {
+        self.clear_write_protect();
+        self.transfer_write(cmd, &[value]);
+    }
+
+    fn clear_write_protect(&self) {
+        self.transfer_write(CMD_WP, &[0]);
+    }
+
+    fn transfer_write(&self, cmd: u8, data: &[u8]) {
+        begin();
+        write_byte(cmd);
+        for &byte in data {
+            write_byte(byte);
+        }
+        end();
+    }
+
+    // 最多读取 8 个字节，够 burst 读取全部的时钟寄存器
+    fn transfer_read(&self, cmd: u8, len: usize) -> [u8; 8] {
+        let mut buf = [0u8; 8];
+        begin();
+        write_byte(cmd | CMD_READ);
+        set_io_output(false);
+        for byte in buf.iter_mut().take(len) {
+            *byte = read_byte();
+        }
+        end();
+        buf
+    }
+}
+
+impl Clock for Ds1302 {
+    type Error = RtcError;
+
+    // burst 读出全部的时钟寄存器
+    fn now(&mut self) -> Result<DateTime, RtcError> {
+        let regs = self.transfer_read(CMD_CLOCK_BURST, 7);
+
+        let datetime = DateTime {
+            second: from_bcd(regs[0] & 0x7F),
+            minute: from_bcd(regs[1] & 0x7F),
+            hour: hour_from_register(regs[2]),
+            day: from_bcd(regs[3] & 0x3F),
+            month: from_bcd(regs[4] & 0x1F),
+            weekday: Weekday::from_number(regs[5] & 0x07).ok_or(RtcError::InvalidTime)?,
+            year: DateTime::YEAR_MIN + from_bcd(regs[6]) as u16,
+        };
+        if !datetime.is_valid() {
+            return Err(RtcError::InvalidTime);
+        }
+        Ok(datetime)
+    }
+
+    // burst 写入全部 8 个时钟寄存器，同时清除 CH，时钟开始走动，写入之后总是 24 小时制
+    fn set(&mut self, datetime: &DateTime) -> Result<(), RtcError> {
+        if !datetime.is_valid() {
+            return Err(RtcError::InvalidTime);
+        }
+        self.clear_write_protect();
+        self.transfer_write(
+            CMD_CLOCK_BURST,
+            &[
+                to_bcd(datetime.second),
+                to_bcd(datetime.minute),
+                to_bcd(datetime.hour),
+                to_bcd(datetime.day),
+                to_bcd(datetime.month),
+                datetime.weekday.number(),
+                to_bcd(datetime.year_of_century()),
+                // 写完之后重新打开写保护
+                WP,
+            ],
+        );
+        Ok(())
+    }
+}
+
+fn check_ram(offset: usize, len: usize) -> Result<(), RtcError> {
+    if offset + len > RAM_LEN {
+        return Err(RtcError::OutOfRange);
+    }
+    Ok(())
+}
+
+// 这里只操作 PC0 ~ PC2，通过 BSRR 写入，不会影响 GPIOC 的其他引脚
+fn set_pin(pin: u8, high: bool) {
+    let gpioc = unsafe { &*pac::GPIOC::ptr() };
+    let bit = if high { 1 << pin } else { 1 << (pin + 16) };
+    gpioc.bsrr.write(|w| unsafe { w.bits(bit) });
+}
+
+fn set_io_output(output: bool) {
+    let gpioc = unsafe { &*pac::GPIOC::ptr() };
+    gpioc.moder.modify(|_, w| {
+        if output {
+            w.moder2().output()
+        } else {
+            w.moder2().input()
+        }
+    });
+}
+
+fn begin() {
+    set_pin(PIN_SCLK, false);
+    set_pin(PIN_CE, true);
+    cortex_m::asm::delay(CE_SETUP_CYCLES);
+}
+
+fn end() {
+    set_pin(PIN_SCLK, false);
+    set_pin(PIN_CE, false);
+    set_io_output(false);
+    cortex_m::asm::delay(CE_SETUP_CYCLES);
+}
+
+// 先发送最低位，芯片在 SCLK 上升沿采样
+fn write_byte(byte: u8) {
+    set_io_output(true);
+    for i in 0..8 {
+        set_pin(PIN_IO, byte & (1 << i) != 0);
+        cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+        set_pin(PIN_SCLK, true);
+        cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+        set_pin(PIN_SCLK, false);
+    }
+}
+
+// 命令字节最后一个下降沿之后，第一位就已经出现在 I/O 上了，之后每个下降沿输出下一位
+fn read_byte() -> u8 {
+    let mut byte = 0;
+    for i in 0..8 {
+        cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+        let gpioc = unsafe { &*pac::GPIOC::ptr() };
+        if gpioc.idr.read().bits() & (1 << PIN_IO) != 0 {
+            byte |= 1 << i;
+        }
+        set_pin(PIN_SCLK, true);
+        cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+        set_pin(PIN_SCLK, false);
+    }
+    byte
+}
@@ -0,0 +1,186 @@
+//! DS1307 实时时钟芯片（I2C）
+//!
+//! 寄存器：
+//!
+//! | 地址        | 内容                                     |
+//! | ----------- | ---------------------------------------- |
+//! | 0x00        | 秒，最高位为 CH（Clock Halt）            |
+//! | 0x01        | 分                                       |
+//! | 0x02        | 时，第 6 位为 1 时是 12 小时制           |
+//! | 0x03        | 星期 1 ~ 7                               |
+//! | 0x04 ~ 0x06 | 日、月、年                               |
+//! | 0x07        | 控制寄存器，SQW/OUT 引脚的输出           |
+//! | 0x08 ~ 0x3F | 56 字节的 NV RAM                         |
+//!
+//! 时间都是 BCD 码，换算为 utils::datetime::DateTime
+//!
+//! CH 为 1 时振荡器停止，时间不再走动，第一次上电（或者电池没电之后）CH 为 1，需要写一次时间才会开始走
+//! 芯片在收到 START 时把当前时间锁存到一组缓冲中，因此一次连续读取 7 个字节得到的时间是一致的，
+//! 不会出现读完秒之后进位，分钟却已经加 1 的问题；反过来，分几次读取就可能出现这样的问题
+//!
+//! NV RAM 与时间一样由 VBAT 保持，掉电之后内容不会丢失，地址超过 0x3F 之后会回到 0x00，这里不允许跨越末尾
+//! DS1307 没有涓流充电（trickle charge），VBAT 需要接一次性的纽扣电池，不能接可充电电池，需要充电的话请使用 DS1302（utils::ds1302）
+//!
+//! DS1307 是 5V 器件，VCC 低于 1.25 * VBAT 时会切换到电池供电并停止响应 I2C，
+//! 因此 VCC 需要 5V，SCL/SDA 上拉到 3.3V 即可（STM32 的 PB8/PB9 可以耐受 5V）
+//!
+//! I2C 外设需要事先配置好，这里只通过 utils::blocking_master 收发数据
+//! 地址固定为 0x68
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::i2c1::RegisterBlock;
+
+use super::{
+    addressing::I2cAddress,
+    blocking_master::{self, MasterError},
+    datetime::{from_bcd, hour_from_register, to_bcd, Clock, DateTime, RtcError, Weekday},
+};
+
+pub(crate) const ADDR: u8 = 0x68;
+
+pub(crate) const RAM_LEN: usize = 56;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_CONTROL: u8 = 0x07;
+const REG_RAM: u8 = 0x08;
+
+const SECONDS_CH: u8 = 1 << 7;
+
+impl From<MasterError> for RtcError {
+    fn from(_: MasterError) -> Self {
+        RtcError::Bus
+    }
+}
+
+// SQW/OUT 引脚的输出
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SquareWave {
+    // 不输出方波，引脚保持为指定的电平
+    Off { high: bool },
+    Hz1,
+    Hz4096,
+    Hz8192,
+    Hz32768,
+}
+
+pub(crate) struct Ds1307<'a> {
+    i2c: &'a RegisterBlock,
+    addr: I2cAddress,
+}
+
+impl<'a> Ds1307<'a> {
+    pub(crate) fn new(i2c: &'a RegisterBlock) -> Result<Self, RtcError> {
+        let dev = Self {
+            i2c,
+            addr: I2cAddress::SevenBit(ADDR),
+        };
+        // 顺便确认芯片在总线上
+        dev.read(REG_CONTROL, &mut [0])?;
+        Ok(dev)
+    }
+
+    pub(crate) fn is_halted(&self) -> Result<bool, RtcError> {
+        let mut seconds = [0];
+        self.read(REG_SECONDS, &mut seconds)?;
+        Ok(seconds[0] & SECONDS_CH != 0)
+    }
+
+    // 停止/启动振荡器，秒的数值保持不变
+    pub(crate) fn set_halted(&self, halted: bool) -> Result<(), RtcError> {
+        let mut seconds = [0];
+        self.read(REG_SECONDS, &mut seconds)?;
+        let seconds = if halted {
+            seconds[0] | SECONDS_CH
+        } else {
+            seconds[0] & !SECONDS_CH
+        };
+        self.write(REG_SECONDS, &[seconds])
+    }
+
+    pub(crate) fn set_square_wave(&self, output: SquareWave) -> Result<(), RtcError> {
+        // OUT 为第 7 位，SQWE 为第 4 位，RS1/RS0 为最低两位
+        let control = match output {
+            SquareWave::Off { high } => (high as u8) << 7,
+            SquareWave::Hz1 => 0x10,
+            SquareWave::Hz4096 => 0x11,
+            SquareWave::Hz8192 => 0x12,
+            SquareWave::Hz32768 => 0x13,
+        };
+        self.write(REG_CONTROL, &[control])
+    }
+
+    pub(crate) fn read_ram(&self, offset: usize, buf: &mut [u8]) -> Result<(), RtcError> {
+        check_ram(offset, buf.len())?;
+        self.read(REG_RAM + offset as u8, buf)
+    }
+
+    pub(crate) fn write_ram(&self, offset: usize, data: &[u8]) -> Result<(), RtcError> {
+        check_ram(offset, data.len())?;
+        self.write(REG_RAM + offset as u8, data)
+    }
+
+    fn read(&self, reg: u8, buf: &mut [u8]) -> Result<(), RtcError> {
+        blocking_master::write_read(self.i2c, self.addr, &[reg], buf)?;
+        Ok(())
+    }
+
+    // 寄存器地址之后最多跟 RAM_LEN 个字节
+    fn write(&self, reg: u8, data: &[u8]) -> Result<(), RtcError> {
+        let mut buf = [0u8; 1 + RAM_LEN];
+        buf[0] = reg;
+        buf[1..=data.len()].copy_from_slice(data);
+        blocking_master::write(self.i2c, self.addr, &buf[..=data.len()])?;
+        Ok(())
+    }
+}
+
+impl Clock for Ds1307<'_> {
+    type Error = RtcError;
+
+    // 一次读出全部 7 个时间寄存器
+    fn now(&mut self) -> Result<DateTime, RtcError> {
+        let mut regs = [0u8; 7];
+        self.read(REG_SECONDS, &mut regs)?;
+
+        let datetime = DateTime {
+            second: from_bcd(regs[0] & 0x7F),
+            minute: from_bcd(regs[1] & 0x7F),
+            hour: hour_from_register(regs[2]),
+            weekday: Weekday::from_number(regs[3] & 0x07).ok_or(RtcError::InvalidTime)?,
+            day: from_bcd(regs[4] & 0x3F),
+            month: from_bcd(regs[5] & 0x1F),
+            year: DateTime::YEAR_MIN + from_bcd(regs[6]) as u16,
+        };
+        if !datetime.is_valid() {
+            return Err(RtcError::InvalidTime);
+        }
+        Ok(datetime)
+    }
+
+    // 一次写入全部 7 个时间寄存器，同时清除 CH，时钟开始走动，写入之后总是 24 小时制
+    fn set(&mut self, datetime: &DateTime) -> Result<(), RtcError> {
+        if !datetime.is_valid() {
+            return Err(RtcError::InvalidTime);
+        }
+        self.write(
+            REG_SECONDS,
+            &[
+                to_bcd(datetime.second),
+                to_bcd(datetime.minute),
+                to_bcd(datetime.hour),
+                datetime.weekday.number(),
+                to_bcd(datetime.day),
+                to_bcd(datetime.month),
+                to_bcd(datetime.year_of_century()),
+            ],
+        )
+    }
+}
+
+fn check_ram(offset: usize, len: usize) -> Result<(), RtcError> {
+    if offset + len > RAM_LEN {
+        return Err(RtcError::OutOfRange);
+    }
+    Ok(())
+}
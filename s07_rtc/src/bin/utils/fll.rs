@@ -0,0 +1,339 @@
+//! 用 RTC（LSE）或 GPS 的 PPS 校准 TIM 的时钟：一个简单的锁频环（FLL）
+//!
+//! 只有 HSI 时，TIM 的时钟出厂精度只有 ±1%，而且会随温度漂移，用它计时，一小时就可能差出半分钟；
+//! 32.768 kHz 的 LSE 晶振通常在 ±20 ppm 之内，GPS 模块的 PPS 更是在 1 us 以内，
+//! 拿它们的“秒”去量 TIM 在一秒之内走了多少个计数，就知道 TIM 的实际频率
+//!
+//! TIM5 是 32 bit 的，而且它的 CH4 可以通过 TIM5_OR 的 IT4_RMP 重映射到芯片内部的信号：
+//!
+//! - Reference::RtcSecond：IT4_RMP = 11，RTC 的唤醒中断信号；唤醒定时器设置为每秒一次，
+//!   每个秒的时刻被硬件捕获到 CCR4，与中断延迟无关；注意 WUTF 必须在每次捕获之后清除，否则不会再有新的边沿
+//! - Reference::Pps：IT4_RMP = 00，PA3（TIM5_CH4，AF2）接 GPS 模块的 PPS 输出
+//!
+//! 每一秒的计数值偏离标称频率超过 TOLERANCE_PPM（PPS 丢失、或者被干扰多出一个边沿）时，当前窗口作废
+//! 连续 window_s 个好的秒组成一个窗口，窗口的总计数除以秒数就是这段时间内的平均频率，
+//! 窗口越长，分辨率越高：16 MHz 下 64 s 的窗口，一个计数只相当于 1 ppb
+//!
+//! 环路滤波：第一个窗口的结果直接作为频率的估计值，之后每个窗口只修正偏差的 1/2^GAIN_SHIFT，
+//! 这样单个窗口的测量误差（比如 PPS 本身的抖动）不会让估计值跳来跳去，温度引起的缓慢漂移又能跟得上
+//! 连续 LOCK_WINDOWS 个窗口的偏差都小于 LOCK_PPM，认为已经锁定
+//!
+//! 单调时钟：TIM5 在更新中断中扩展为 64 bit 的计数，now_ns 用当前的频率估计值把计数换算为 ns；
+//! 每次更新估计值时，以那一刻为新的起点（rebase），之前已经走过的时间不会因为换算系数变化而跳变，时钟始终单调
+
+#![allow(dead_code)]
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{self, interrupt, Peripherals, NVIC};
+
+const TOLERANCE_PPM: u32 = 50_000;
+const GAIN_SHIFT: u32 = 2;
+const LOCK_PPM: i64 = 2;
+const LOCK_WINDOWS: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Reference {
+    RtcSecond,
+    Pps,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FllStatus {
+    pub(crate) nominal_hz: u32,
+    // 估计的 TIM5 实际频率，单位 mHz
+    pub(crate) freq_mhz: u64,
+    // 估计值相对标称值的偏差
+    pub(crate) offset_ppb: i64,
+    // 最近一个窗口的测量值相对估计值的偏差
+    pub(crate) last_error_ppb: i64,
+    pub(crate) windows: u32,
+    pub(crate) seconds: u32,
+    pub(crate) rejected: u32,
+    pub(crate) locked: bool,
+}
+
+struct Fll {
+    nominal_hz: u32,
+    window_s: u32,
+
+    // TIM5 的高 32 bit
+    high: u32,
+
+    last_capture: Option<u32>,
+    window_ticks: u64,
+    window_count: u32,
+
+    // 0 表示还没有第一个窗口，沿用标称频率
+    freq_mhz: u64,
+    good_windows: u8,
+
+    // 单调时钟的起点
+    base_ticks: u64,
+    base_ns: u64,
+
+    status: FllStatus,
+}
+
+static G_FLL: Mutex<RefCell<Option<Fll>>> = Mutex::new(RefCell::new(None));
+
+// tim_clk_hz 为 TIM5 的标称时钟，window_s 为一个窗口的秒数
+// 使用 RtcSecond 时，RTC 需要已经运行在 LSE 上，Backup Domain 的写保护需要提前解除
+pub(crate) fn start(dp: &Peripherals, reference: Reference, tim_clk_hz: u32, window_s: u32) {
+    cortex_m::interrupt::free(|cs| {
+        G_FLL.borrow(cs).replace(Some(Fll {
+            nominal_hz: tim_clk_hz,
+            window_s,
+            high: 0,
+            last_capture: None,
+            window_ticks: 0,
+            window_count: 0,
+            freq_mhz: 0,
+            good_windows: 0,
+            base_ticks: 0,
+            base_ns: 0,
+            status: FllStatus {
+                nominal_hz: tim_clk_hz,
+                freq_mhz: tim_clk_hz as u64 * 1000,
+                offset_ppb: 0,
+                last_error_ppb: 0,
+                windows: 0,
+                seconds: 0,
+                rejected: 0,
+                locked: false,
+            },
+        }))
+    });
+
+    match reference {
+        Reference::RtcSecond => setup_rtc_wakeup(dp),
+        Reference::Pps => setup_pps_pin(dp),
+    }
+
+    dp.RCC.apb1enr.modify(|_, w| w.tim5en().enabled());
+    let tim5 = &dp.TIM5;
+    tim5.cr1.modify(|_, w| w.cen().disabled());
+    tim5.psc.write(|w| w.psc().bits(0));
+    tim5.arr.write(|w| w.arr().bits(u32::MAX));
+    let rmp = match reference {
+        Reference::RtcSecond => 0b11,
+        Reference::Pps => 0b00,
+    };
+    tim5.or.modify(|_, w| unsafe { w.it4_rmp().bits(rmp) });
+    // CC4S = 01，IC4 映射到 TI4，上升沿捕获；PPS 加一点输入滤波
+    tim5.ccmr2_input().modify(|_, w| unsafe {
+        w.cc4s().bits(0b01);
+        w.ic4f().bits(0b0011);
+        w
+    });
+    tim5.ccer.modify(|_, w| {
+        w.cc4p().clear_bit();
+        w.cc4np().clear_bit();
+        w.cc4e().set_bit();
+        w
+    });
+    tim5.cr1.modify(|_, w| w.urs().counter_only());
+    tim5.egr.write(|w| w.ug().set_bit());
+    tim5.sr.modify(|_, w| {
+        w.cc4if().clear_bit();
+        w.cc4of().clear_bit();
+        w.uif().clear_bit();
+        w
+    });
+    tim5.dier.modify(|_, w| {
+        w.cc4ie().enabled();
+        w.uie().enabled();
+        w
+    });
+    tim5.cr1.modify(|_, w| w.cen().enabled());
+
+    unsafe { NVIC::unmask(interrupt::TIM5) };
+}
+
+pub(crate) fn status() -> Option<FllStatus> {
+    cortex_m::interrupt::free(|cs| G_FLL.borrow(cs).borrow().as_ref().map(|f| f.status))
+}
+
+// 64 bit 的 TIM5 计数
+pub(crate) fn now_ticks() -> u64 {
+    cortex_m::interrupt::free(|cs| {
+        G_FLL
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map_or(0, |fll| fll.ticks())
+    })
+}
+
+// 经过校准的单调时钟，从 start 开始计时
+pub(crate) fn now_ns() -> u64 {
+    cortex_m::interrupt::free(|cs| {
+        G_FLL
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map_or(0, |fll| fll.ticks_to_ns(fll.ticks()))
+    })
+}
+
+// 把一段 TIM5 计数换算为 ns，用于测量两个事件之间的间隔
+pub(crate) fn ticks_to_ns(ticks: u64) -> u64 {
+    cortex_m::interrupt::free(|cs| {
+        G_FLL
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map_or(0, |fll| scale(ticks, fll.status.freq_mhz))
+    })
+}
+
+// 在 TIM5 中断中调用，返回是否刚刚结束了一个窗口
+pub(crate) fn on_tim5() -> bool {
+    let tim5 = unsafe { &*pac::TIM5::ptr() };
+    let sr = tim5.sr.read();
+
+    cortex_m::interrupt::free(|cs| {
+        let mut fll_mut = G_FLL.borrow(cs).borrow_mut();
+        let Some(fll) = fll_mut.as_mut() else {
+            return false;
+        };
+
+        // 先处理溢出，再处理捕获，捕获值扩展为 64 bit 时需要正确的高位
+        if sr.uif().bit_is_set() {
+            tim5.sr.modify(|_, w| w.uif().clear_bit());
+            fll.high = fll.high.wrapping_add(1);
+        }
+
+        if sr.cc4if().bit_is_clear() {
+            return false;
+        }
+
+        // 读 CCR4 会清除 CC4IF
+        let capture = tim5.ccr4().read().bits();
+        let rtc = unsafe { &*pac::RTC::ptr() };
+        rtc.isr.modify(|_, w| w.wutf().clear_bit());
+        if sr.cc4of().bit_is_set() {
+            tim5.sr.modify(|_, w| w.cc4of().clear_bit());
+        }
+
+        fll.on_capture(capture)
+    })
+}
+
+impl Fll {
+    fn ticks(&self) -> u64 {
+        let tim5 = unsafe { &*pac::TIM5::ptr() };
+        let cnt = tim5.cnt.read().bits();
+        // 溢出已经发生、但更新中断还没来得及处理
+        let high = if tim5.sr.read().uif().bit_is_set() && cnt < u32::MAX / 2 {
+            self.high.wrapping_add(1)
+        } else {
+            self.high
+        };
+        (high as u64) << 32 | cnt as u64
+    }
+
+    fn ticks_to_ns(&self, ticks: u64) -> u64 {
+        self.base_ns + scale(ticks - self.base_ticks, self.status.freq_mhz)
+    }
+
+    // 把 32 bit 的捕获值扩展为 64 bit，捕获一定早于现在，而且相距不会超过一次溢出
+    fn extend(&self, capture: u32) -> u64 {
+        let now = self.ticks();
+        now - (now as u32).wrapping_sub(capture) as u64
+    }
+
+    fn on_capture(&mut self, capture: u32) -> bool {
+        let Some(last) = self.last_capture.replace(capture) else {
+            return false;
+        };
+
+        let ticks = capture.wrapping_sub(last);
+        let tolerance = (self.nominal_hz as u64 * TOLERANCE_PPM as u64 / 1_000_000) as u32;
+        if ticks.abs_diff(self.nominal_hz) > tolerance {
+            self.status.rejected += 1;
+            self.window_ticks = 0;
+            self.window_count = 0;
+            return false;
+        }
+
+        self.status.seconds += 1;
+        self.window_ticks += ticks as u64;
+        self.window_count += 1;
+        if self.window_count < self.window_s {
+            return false;
+        }
+
+        let measured_mhz = self.window_ticks * 1000 / self.window_count as u64;
+        self.window_ticks = 0;
+        self.window_count = 0;
+
+        // 以这一秒的时刻为新的起点，在这之前的时间用旧的估计值换算
+        let at = self.extend(capture);
+        self.base_ns = self.ticks_to_ns(at);
+        self.base_ticks = at;
+
+        let freq_mhz = if self.freq_mhz == 0 {
+            measured_mhz
+        } else {
+            let error = measured_mhz as i64 - self.freq_mhz as i64;
+            (self.freq_mhz as i64 + (error >> GAIN_SHIFT)) as u64
+        };
+
+        let error_ppb = ppb(measured_mhz, freq_mhz);
+        if error_ppb.abs() < LOCK_PPM * 1000 {
+            self.good_windows = self.good_windows.saturating_add(1);
+        } else {
+            self.good_windows = 0;
+        }
+
+        self.freq_mhz = freq_mhz;
+        self.status.freq_mhz = freq_mhz;
+        self.status.offset_ppb = ppb(freq_mhz, self.nominal_hz as u64 * 1000);
+        self.status.last_error_ppb = error_ppb;
+        self.status.windows += 1;
+        self.status.locked = self.good_windows >= LOCK_WINDOWS;
+        true
+    }
+}
+
+// (a - b) / b，单位 ppb
+fn ppb(a_mhz: u64, b_mhz: u64) -> i64 {
+    ((a_mhz as i128 - b_mhz as i128) * 1_000_000_000 / b_mhz as i128) as i64
+}
+
+// ticks / freq，单位 ns
+fn scale(ticks: u64, freq_mhz: u64) -> u64 {
+    (ticks as u128 * 1_000_000_000_000 / freq_mhz as u128) as u64
+}
+
+// 唤醒定时器使用 ck_spre（1 Hz），WUT 为 0，每秒一次
+// WUTIE 打开之后内部的唤醒中断信号才会变化，但 NVIC 中的 RTC_WKUP 不需要 unmask
+fn setup_rtc_wakeup(dp: &Peripherals) {
+    let rtc = &dp.RTC;
+    rtc.wpr.write(|w| w.key().bits(0xCA));
+    rtc.wpr.write(|w| w.key().bits(0x53));
+
+    rtc.cr.modify(|_, w| w.wute().clear_bit());
+    while rtc.isr.read().wutwf().bit_is_clear() {}
+    rtc.wutr.write(|w| w.wut().bits(0));
+    rtc.cr.modify(|_, w| unsafe {
+        w.wucksel().bits(0b100);
+        w.wutie().set_bit();
+        w.wute().set_bit();
+        w
+    });
+    rtc.isr.modify(|_, w| w.wutf().clear_bit());
+
+    rtc.wpr.write(|w| w.key().bits(0xFF));
+}
+
+// PA3 TIM5_CH4，AF2
+fn setup_pps_pin(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| w.afrl3().af2());
+    gpioa.pupdr.modify(|_, w| w.pupdr3().pull_down());
+    gpioa.moder.modify(|_, w| w.moder3().alternate());
+}
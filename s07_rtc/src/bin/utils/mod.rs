@@ -0,0 +1,13 @@
+pub(crate) mod bkp_store;
+pub(crate) mod crash_dump;
+pub(crate) mod cron;
+pub(crate) mod datetime;
+pub(crate) mod ds1302;
+pub(crate) mod ds1307;
+pub(crate) mod fll;
+pub(crate) mod rtc;
+
+// 与其它章节共用的模块，代码在工作区中单独的 crate 里
+// 与上面的模块一样，不是每个程序都会用到，因此允许未使用
+#[allow(unused_imports)]
+pub(crate) use i2c_master::{addressing, blocking_master};
@@ -0,0 +1,308 @@
+//! STM32 内部的 RTC
+//!
+//! 配置流程与 s07c02 相同：使用 32.768 kHz 的 LSE，只有 RTC 尚未初始化（INITS 为 0）时才会配置，
+//! 这样只要 VBAT 有电，复位之后 RTC 依旧保持之前的时间
+//!
+//! 读写 RTC_TR/RTC_DR 的细节见 s07c01，这里把它们换算为 utils::datetime::DateTime
+//!
+//! 使用之前需要先解除 Backup Domain 的写保护，见 utils::bkp_store::unlock
+//!
+//! 除了读写时间，这里还封装了两个闹钟与唤醒计时器，utils::cron 用它们在指定的时刻唤醒芯片：
+//!
+//! - Alarm A/B：日期与时分秒都相同时触发，星期不参与比较，因此最远只能设置到一个月之内，
+//!   通过 EXTI17 进入 EXTI17_RTC_ALARM 中断（F401/F411/F412 的 pac 中名为 RTC_ALARM，见 ALARM_IRQ）
+//! - 唤醒计时器：以 1 Hz 的 ck_spre 计数，每 1 ~ WAKEUP_MAX_S 秒触发一次，通过 EXTI22 进入 RTC_WKUP 中断
+//!
+//! 两条 EXTI 线都能把芯片从 Stop 模式中唤醒，中断处理函数中只需要清除 EXTI 的 PR，
+//! RTC 自己的标志由 take_events 读出并清除
+
+#![allow(dead_code)]
+
+use cortex_m::peripheral::NVIC;
+use stm32f4xx_hal::pac::{interrupt, Peripherals, RTC};
+
+use super::datetime::{from_bcd, to_bcd, Clock, DateTime, Weekday};
+
+// Alarm A/B 的中断，只有 F413 的 pac 把它叫做 EXTI17_RTC_ALARM
+#[cfg(feature = "stm32f413")]
+pub(crate) const ALARM_IRQ: interrupt = interrupt::EXTI17_RTC_ALARM;
+#[cfg(not(feature = "stm32f413"))]
+pub(crate) const ALARM_IRQ: interrupt = interrupt::RTC_ALARM;
+
+pub(crate) struct InternalRtc<'a> {
+    rtc: &'a RTC,
+}
+
+impl<'a> InternalRtc<'a> {
+    // 若 RTC 尚未初始化，启动 LSE 并把 RTC 配置为 1 Hz，此时日历从 2000-01-01 开始
+    pub(crate) fn new(dp: &'a Peripherals) -> Self {
+        let rtc = &dp.RTC;
+
+        if rtc.isr.read().inits().is_not_initalized() {
+            dp.RCC.bdcr.modify(|_, w| w.lseon().on());
+            while dp.RCC.bdcr.read().lserdy().is_not_ready() {}
+            dp.RCC.bdcr.modify(|_, w| {
+                w.rtcsel().lse();
+                w.rtcen().enabled();
+                w
+            });
+
+            with_init_mode(rtc, || {
+                // 32.768 kHz/(1+127)/(1+255) = 1 Hz
+                rtc.prer.modify(|_, w| {
+                    w.prediv_s().bits(255);
+                    w.prediv_a().bits(127);
+                    w
+                });
+                rtc.cr.modify(|_, w| w.fmt().twenty_four_hour());
+            });
+        }
+
+        Self { rtc }
+    }
+
+    // RTC 是否已经设置过时间
+    // INITS 只是检查年份是否为 0，因此设置为 2000 年的时间也会被认为没有设置过
+    pub(crate) fn is_set(&self) -> bool {
+        self.rtc.isr.read().inits().is_initalized()
+    }
+}
+
+// RTC_CR
+const CR_WUCKSEL_MASK: u32 = 0b111;
+// ck_spre，也就是 1 Hz
+const CR_WUCKSEL_CK_SPRE: u32 = 0b100;
+const CR_WUTE: u32 = 1 << 10;
+const CR_WUTIE: u32 = 1 << 14;
+// RTC_ISR
+const ISR_WUTWF: u32 = 1 << 2;
+const ISR_WUTF: u32 = 1 << 10;
+
+// 唤醒计时器最长的周期，WUT 为 16 位
+pub(crate) const WAKEUP_MAX_S: u32 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Alarm {
+    A,
+    B,
+}
+
+impl Alarm {
+    fn index(self) -> usize {
+        match self {
+            Alarm::A => 0,
+            Alarm::B => 1,
+        }
+    }
+
+    // RTC_CR 的 ALRxE
+    fn enable_bit(self) -> u32 {
+        1 << (8 + self.index())
+    }
+
+    // RTC_CR 的 ALRxIE
+    fn interrupt_bit(self) -> u32 {
+        1 << (12 + self.index())
+    }
+
+    // RTC_ISR 的 ALRxWF
+    fn write_allowed_bit(self) -> u32 {
+        1 << self.index()
+    }
+
+    // RTC_ISR 的 ALRxF
+    fn flag_bit(self) -> u32 {
+        1 << (8 + self.index())
+    }
+}
+
+// take_events 读出的标志
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct RtcEvents {
+    pub(crate) alarm_a: bool,
+    pub(crate) alarm_b: bool,
+    pub(crate) wakeup: bool,
+}
+
+impl RtcEvents {
+    pub(crate) fn any(&self) -> bool {
+        self.alarm_a || self.alarm_b || self.wakeup
+    }
+}
+
+impl InternalRtc<'_> {
+    // 设置闹钟并打开它的中断，at 的星期不参与比较
+    pub(crate) fn set_alarm(&mut self, alarm: Alarm, at: &DateTime) {
+        // MSK1 ~ MSK4 均为 0，日期（WDSEL 为 0）与时分秒都要相同，PM 为 0
+        let value = (to_bcd(at.day) as u32) << 24
+            | (to_bcd(at.hour) as u32) << 16
+            | (to_bcd(at.minute) as u32) << 8
+            | to_bcd(at.second) as u32;
+
+        let rtc = self.rtc;
+        with_write_access(rtc, || {
+            let enable = alarm.enable_bit() | alarm.interrupt_bit();
+            rtc.cr.modify(|r, w| unsafe { w.bits(r.bits() & !enable) });
+            while rtc.isr.read().bits() & alarm.write_allowed_bit() == 0 {}
+
+            rtc.alrmr[alarm.index()].write(|w| unsafe { w.bits(value) });
+            // 标志为 rc_w0，写 0 清除，其余的标志写回读到的值，保持不变
+            rtc.isr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !alarm.flag_bit()) });
+
+            rtc.cr.modify(|r, w| unsafe { w.bits(r.bits() | enable) });
+        });
+    }
+
+    pub(crate) fn disable_alarm(&mut self, alarm: Alarm) {
+        let rtc = self.rtc;
+        with_write_access(rtc, || {
+            let enable = alarm.enable_bit() | alarm.interrupt_bit();
+            rtc.cr.modify(|r, w| unsafe { w.bits(r.bits() & !enable) });
+        });
+    }
+
+    // 每 seconds 秒唤醒一次，seconds 为 1 ~ WAKEUP_MAX_S，超出时取最近的值
+    pub(crate) fn set_wakeup(&mut self, seconds: u32) {
+        let reload = seconds.clamp(1, WAKEUP_MAX_S) - 1;
+
+        let rtc = self.rtc;
+        with_write_access(rtc, || {
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(CR_WUTE | CR_WUTIE)) });
+            while rtc.isr.read().bits() & ISR_WUTWF == 0 {}
+
+            rtc.wutr.write(|w| unsafe { w.bits(reload) });
+            rtc.cr.modify(|r, w| unsafe {
+                w.bits((r.bits() & !CR_WUCKSEL_MASK) | CR_WUCKSEL_CK_SPRE)
+            });
+            rtc.isr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !ISR_WUTF) });
+
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | CR_WUTE | CR_WUTIE) });
+        });
+    }
+
+    pub(crate) fn disable_wakeup(&mut self) {
+        let rtc = self.rtc;
+        with_write_access(rtc, || {
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(CR_WUTE | CR_WUTIE)) });
+        });
+    }
+
+    // 读出并清除闹钟与唤醒计时器的标志
+    pub(crate) fn take_events(&mut self) -> RtcEvents {
+        let isr = self.rtc.isr.read().bits();
+        let events = RtcEvents {
+            alarm_a: isr & Alarm::A.flag_bit() != 0,
+            alarm_b: isr & Alarm::B.flag_bit() != 0,
+            wakeup: isr & ISR_WUTF != 0,
+        };
+
+        let clear = Alarm::A.flag_bit() | Alarm::B.flag_bit() | ISR_WUTF;
+        self.rtc
+            .isr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !clear) });
+
+        events
+    }
+}
+
+// 打开 EXTI17（闹钟）与 EXTI22（唤醒计时器）的上升沿中断，二者都可以唤醒 Stop 模式
+pub(crate) fn enable_interrupts(dp: &Peripherals) {
+    dp.RCC.apb2enr.modify(|_, w| w.syscfgen().enabled());
+    dp.EXTI.rtsr.modify(|_, w| {
+        w.tr17().enabled();
+        w.tr22().enabled();
+        w
+    });
+    dp.EXTI.imr.modify(|_, w| {
+        w.mr17().unmasked();
+        w.mr22().unmasked();
+        w
+    });
+    unsafe {
+        NVIC::unmask(ALARM_IRQ);
+        NVIC::unmask(interrupt::RTC_WKUP);
+    }
+}
+
+// 在 Alarm 与 RTC_WKUP 中断中调用，清除 EXTI 的挂起位
+pub(crate) fn clear_exti() {
+    let exti = unsafe { &*stm32f4xx_hal::pac::EXTI::ptr() };
+    exti.pr.write(|w| {
+        w.pr17().clear();
+        w.pr22().clear();
+        w
+    });
+}
+
+impl Clock for InternalRtc<'_> {
+    type Error = core::convert::Infallible;
+
+    fn now(&mut self) -> Result<DateTime, Self::Error> {
+        let rtc = self.rtc;
+
+        // 等待影子寄存器同步，之后先读 TR 再读 DR，DR 会在读取 TR 时被锁定
+        rtc.isr.modify(|_, w| w.rsf().clear());
+        while rtc.isr.read().rsf().is_not_synced() {}
+        let tr = rtc.tr.read().bits();
+        let dr = rtc.dr.read().bits();
+
+        Ok(DateTime {
+            year: DateTime::YEAR_MIN + from_bcd((dr >> 16) as u8) as u16,
+            month: from_bcd((dr >> 8) as u8 & 0x1F),
+            day: from_bcd(dr as u8 & 0x3F),
+            // WDU 为 0 表示没有设置，当作星期一
+            weekday: Weekday::from_number((dr >> 13) as u8 & 0b111).unwrap_or(Weekday::Monday),
+            hour: from_bcd((tr >> 16) as u8 & 0x3F),
+            minute: from_bcd((tr >> 8) as u8 & 0x7F),
+            second: from_bcd(tr as u8 & 0x7F),
+        })
+    }
+
+    fn set(&mut self, datetime: &DateTime) -> Result<(), Self::Error> {
+        // 字段的位置与 s07c01 中逐个设置的字段一致，这里直接拼出整个寄存器，
+        // 顺便绕开 MT 被生成为 bool 的问题
+        let dr = (to_bcd(datetime.year_of_century()) as u32) << 16
+            | (datetime.weekday.number() as u32) << 13
+            | (to_bcd(datetime.month) as u32) << 8
+            | to_bcd(datetime.day) as u32;
+        // PM 为 0，24 小时制
+        let tr = (to_bcd(datetime.hour) as u32) << 16
+            | (to_bcd(datetime.minute) as u32) << 8
+            | to_bcd(datetime.second) as u32;
+
+        let rtc = self.rtc;
+        with_init_mode(rtc, || {
+            rtc.dr.write(|w| unsafe { w.bits(dr) });
+            rtc.tr.write(|w| unsafe { w.bits(tr) });
+        });
+
+        Ok(())
+    }
+}
+
+// 解除 RTC 的写保护并进入初始化模式，执行完 f 之后再恢复
+fn with_init_mode(rtc: &RTC, f: impl FnOnce()) {
+    with_write_access(rtc, || {
+        rtc.isr.modify(|_, w| w.init().init_mode());
+        while rtc.isr.read().initf().is_not_allowed() {}
+
+        f();
+
+        rtc.isr.modify(|_, w| w.init().free_running_mode());
+    });
+}
+
+// 只解除写保护，闹钟与唤醒计时器的设置不需要进入初始化模式
+fn with_write_access(rtc: &RTC, f: impl FnOnce()) {
+    rtc.wpr.write(|w| w.key().bits(0xCA));
+    rtc.wpr.write(|w| w.key().bits(0x53));
+
+    f();
+
+    rtc.wpr.write(|w| w.key().bits(0xFF));
+}
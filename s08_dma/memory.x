@@ -1,7 +1,56 @@
 /* 说明见 s01_rcc 的 memory.x */
 
+/*
+与其它章节不同，这里把 SRAM 拆成了两块，配合 src/bin/utils/placement.rs 使用，说明见那里
+
+SRAM1 的起始地址为 0x2000 0000，大小为 256 KiB，作为 RAM，放置 .data、.bss 与 DMA 缓冲区
+SRAM2 的起始地址为 0x2004 0000，大小为 64 KiB，放置栈，以及需要在 SRAM 中执行的代码与数据
+*/
 MEMORY
 {
   FLASH : ORIGIN = 0x08000000, LENGTH = 512K
-  RAM : ORIGIN = 0x20000000, LENGTH = 320K
+  RAM : ORIGIN = 0x20000000, LENGTH = 256K
+  SRAM2 : ORIGIN = 0x20040000, LENGTH = 64K
 }
+
+/* 栈顶，cortex-m-rt 默认为 RAM 的末尾，这里改到 SRAM2 的末尾 */
+_stack_start = ORIGIN(SRAM2) + LENGTH(SRAM2);
+
+/* 为栈保留的最小空间 */
+_min_stack_size = 16K;
+
+SECTIONS
+{
+  /*
+  标记了 #[link_section = ".sram2.text"] 或 #[link_section = ".sram2.data"] 的函数与静态量
+  运行地址（VMA）在 SRAM2，但初始内容存放在 FLASH 中（LMA），由 placement::init 复制过去
+  */
+  .sram2 : ALIGN(4)
+  {
+    __ssram2 = .;
+    *(.sram2.text .sram2.text.*);
+    *(.sram2.data .sram2.data.*);
+    . = ALIGN(4);
+    __esram2 = .;
+  } > SRAM2 AT > FLASH
+
+  __sisram2 = LOADADDR(.sram2);
+
+  /*
+  DMA 缓冲区，NOLOAD 表示不占用 FLASH 的空间，由 placement::init 清零
+  */
+  .dma_buffer (NOLOAD) : ALIGN(16)
+  {
+    __sdma_buffer = .;
+    *(.dma_buffer .dma_buffer.*);
+    . = ALIGN(4);
+    __edma_buffer = .;
+  } > RAM
+}
+INSERT AFTER .bss;
+
+/* 链接期的检查，条件不满足时链接失败，而不是在运行时悄悄出错 */
+ASSERT(__sdma_buffer >= ORIGIN(RAM) && __edma_buffer <= ORIGIN(RAM) + LENGTH(RAM),
+  "DMA buffers must be placed in SRAM1");
+ASSERT(__esram2 + _min_stack_size <= _stack_start,
+  "Not enough room left in SRAM2 for the stack");
@@ -0,0 +1,184 @@
+//! 把代码、栈与 DMA 缓冲区放到合适的内存区域
+//!
+//! 这一节的 memory.x 与其它章节不同，把 SRAM 拆成了 SRAM1 与 SRAM2，说明见 utils::placement
+//!
+//! 程序会：
+//! 1. 打印栈、SRAM2 中的函数与静态量、DMA 缓冲区、Flash 中的常量各自的地址，确认它们落在了预期的区域
+//! 2. 用 check_dma_address 检查几个地址，其中往 Flash 写与 CCM 的地址会被拒绝
+//! 3. 用 DMA2 Stream0 将 Flash 中的常量搬运到 DMA 缓冲区，传输完成中断的处理函数在 SRAM2 中执行
+//!
+//! 接线图：
+//!
+//! 无需额外接线
+
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt, NVIC};
+
+mod utils;
+
+use utils::placement::{self, check_dma_address, dma_buffer, Access};
+
+const LIST_LEN: usize = 64;
+
+// 源数据，是常量，位于 Flash 中，DMA 可以读
+static SRC_LIST: [u32; LIST_LEN] = {
+    let mut list = [0; LIST_LEN];
+    let mut i = 0;
+    while i < LIST_LEN {
+        list[i] = 0x1000_0001u32.wrapping_mul(i as u32);
+        i += 1;
+    }
+    list
+};
+
+// 目标缓冲区，位于 SRAM1 的 .dma_buffer 段中
+dma_buffer!(static DST_LIST: [u32; LIST_LEN]);
+
+static DONE: AtomicBool = AtomicBool::new(false);
+
+// 中断处理函数中会访问的计数器，放在 SRAM2 中，与处理函数本身放在一起
+#[link_section = ".sram2.data"]
+static IRQ_COUNT: AtomicU32 = AtomicU32::new(0);
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    // 必须在调用任何 SRAM2 中的函数之前完成
+    placement::init();
+
+    rtt_init_print!();
+
+    let stack_var = 0u32;
+    rprintln!(
+        "stack variable:   {:#010x}\r",
+        &stack_var as *const _ as u32
+    );
+    rprintln!(
+        "sram2 function:   {:#010x}\r",
+        sum_in_sram2 as *const () as u32
+    );
+    rprintln!(
+        "sram2 static:     {:#010x}\r",
+        &IRQ_COUNT as *const _ as u32
+    );
+    rprintln!("dma buffer:       {:#010x}\r", DST_LIST.addr());
+    rprintln!("flash constant:   {:#010x}\r", SRC_LIST.as_ptr() as u32);
+
+    // 在 SRAM2 中执行的函数，调用方式与普通函数没有区别
+    rprintln!("sum from sram2:   {:#010x}\r", sum_in_sram2(&SRC_LIST));
+
+    // 各种地址的检查结果
+    let checks = [
+        ("flash as source", SRC_LIST.as_ptr() as u32, Access::Read),
+        (
+            "flash as destination",
+            SRC_LIST.as_ptr() as u32,
+            Access::Write,
+        ),
+        ("dma buffer as destination", DST_LIST.addr(), Access::Write),
+        ("ccm as destination", placement::CCM_START, Access::Write),
+    ];
+    for (name, addr, access) in checks {
+        rprintln!(
+            "{}: {:?}\r",
+            name,
+            check_dma_address(addr, LIST_LEN, 4, access)
+        );
+    }
+
+    // 真正要用的源与目标，检查不通过的话，就不启动 DMA
+    check_dma_address(SRC_LIST.as_ptr() as u32, LIST_LEN, 4, Access::Read).unwrap();
+    check_dma_address(DST_LIST.addr(), DST_LIST.len(), 4, Access::Write).unwrap();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    dp.RCC.ahb1enr.modify(|_, w| w.dma2en().enabled());
+
+    let dma2 = &dp.DMA2;
+    let st0 = &dma2.st[0];
+
+    if st0.cr.read().en().is_enabled() {
+        st0.cr.modify(|_, w| w.en().disabled());
+        while st0.cr.read().en().is_enabled() {}
+    }
+
+    // 与 s08c01 相同的 memory-to-memory 模式，只是数据宽度改为了 word
+    // 在 memory-to-memory 模式下，PAR 为源地址，M0AR 为目标地址
+    st0.cr.modify(|_, w| unsafe {
+        w.dir().memory_to_memory();
+        w.minc().incremented();
+        w.pinc().incremented();
+        w.msize().bits(DST_LIST.size_bits());
+        w.psize().bits(DST_LIST.size_bits());
+        w.mburst().incr4();
+        w.pburst().incr4();
+        w.tcie().enabled();
+        w.teie().enabled();
+        w
+    });
+    // memory-to-memory 模式下，硬件会强制使用 FIFO
+    st0.fcr.modify(|_, w| w.fth().full());
+
+    st0.par
+        .write(|w| unsafe { w.pa().bits(SRC_LIST.as_ptr() as u32) });
+    st0.m0ar.write(|w| unsafe { w.m0a().bits(DST_LIST.addr()) });
+    st0.ndtr.write(|w| w.ndt().bits(DST_LIST.len() as u16));
+
+    dma2.lifcr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+    dma2.hifcr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+
+    unsafe { NVIC::unmask(interrupt::DMA2_STREAM0) };
+
+    st0.cr.modify(|_, w| w.en().enabled());
+
+    while !DONE.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+
+    // DMA 已经结束，可以访问目标缓冲区了
+    let dst = unsafe { DST_LIST.as_mut() };
+    let matched = dst.iter().zip(SRC_LIST.iter()).all(|(d, s)| d == s);
+    rprintln!(
+        "transfer {}, irq count {}\r",
+        if matched { "matched" } else { "MISMATCHED" },
+        IRQ_COUNT.load(Ordering::Relaxed)
+    );
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+// 放在 SRAM2 中执行的函数
+//
+// inline(never) 保证它确实作为一个单独的函数存在，而不是被内联到 Flash 中的调用者里
+#[inline(never)]
+#[link_section = ".sram2.text"]
+fn sum_in_sram2(list: &[u32]) -> u32 {
+    list.iter().fold(0u32, |acc, v| acc.wrapping_add(*v))
+}
+
+// 中断处理函数同样可以放在 SRAM2 中，中断向量表里记录的就是它在 SRAM2 中的地址
+#[link_section = ".sram2.text"]
+#[interrupt]
+fn DMA2_STREAM0() {
+    let dma2 = unsafe { &*pac::DMA2::ptr() };
+    let lisr = dma2.lisr.read();
+
+    IRQ_COUNT.fetch_add(1, Ordering::Relaxed);
+
+    if lisr.teif0().is_error() {
+        dma2.lifcr.write(|w| w.cteif0().clear());
+        panic!("Transfer Error\r\n");
+    }
+
+    if lisr.tcif0().is_complete() {
+        dma2.lifcr.write(|w| w.ctcif0().clear());
+        NVIC::mask(interrupt::DMA2_STREAM0);
+        DONE.store(true, Ordering::Release);
+    }
+}
@@ -0,0 +1,221 @@
+//! 代码、栈与 DMA 缓冲区在内存中的放置
+//!
+//! F405/F407/F429 这类芯片上有一块 64 KiB 的 CCM RAM（0x1000_0000），它直接挂在 Cortex 核心的 D-Bus 上，
+//! 访问不经过 Bus Matrix，因此不会与 DMA 争抢总线，很适合放栈和中断里频繁访问的数据；
+//! 但也正因为如此，DMA 完全访问不到 CCM，把 DMA 缓冲区放进 CCM 之后，DMA 不会报错，只是什么都没有搬运
+//!
+//! 我们的 STM32F413 没有 CCM，但它的 SRAM 分为 SRAM1（256 KiB）与 SRAM2（64 KiB）两块，
+//! 二者在 Bus Matrix 上是两个独立的 slave，核心访问 SRAM2 时，DMA 可以同时访问 SRAM1，互不等待
+//! 因此这里的做法与 CCM 类似（见 memory.x）：
+//!
+//! - 栈放在 SRAM2 的顶部
+//! - 标记了 #[link_section = ".sram2.text"] 的函数（比如中断处理函数）在 SRAM2 中执行，
+//!   执行时间不受 Flash 等待周期与 ART 加速器命中与否的影响，而且在擦写 Flash 期间也能正常执行
+//! - 标记了 #[link_section = ".sram2.data"] 的静态量放在 SRAM2 中
+//! - 由 dma_buffer! 声明的 DMA 缓冲区放在 SRAM1 的 .dma_buffer 段中
+//!
+//! 另外，检查分为两层：
+//!
+//! 1. 编译期与链接期：DmaBuffer 的元素类型只能是 u8/u16/u32，长度不能超过 NDTR 的上限；
+//!    memory.x 中的 ASSERT 检查 .dma_buffer 段确实落在 SRAM1 中，以及 SRAM2 给栈留下了足够的空间
+//! 2. 运行时：check_dma_address 检查任意一个地址范围能否作为 DMA 的源或目标，
+//!    用于那些不是由 dma_buffer! 声明的缓冲区（比如 Flash 中的常量，或者栈上的数组）
+//!    CCM 的地址范围（0x1000_0000 ~ 0x1001_0000）也会被拒绝：上面的链接布局在 F413 上从来不会用到它，
+//!    但代码移植到有 CCM 的芯片、又有人把缓冲区放进了 CCM 时，这里能给出一个明确的错误，而不是一次什么都没搬运的 DMA
+//!
+//! 注意：.sram2 段中的函数，要在 init 复制完成之后才能被调用，因此 init 应该是 main 里的第一件事
+
+#![allow(dead_code)]
+
+use core::{cell::UnsafeCell, mem::MaybeUninit, ptr};
+
+pub(crate) const FLASH_START: u32 = 0x0800_0000;
+pub(crate) const FLASH_END: u32 = 0x0808_0000;
+pub(crate) const SRAM1_START: u32 = 0x2000_0000;
+pub(crate) const SRAM1_END: u32 = 0x2004_0000;
+pub(crate) const SRAM2_START: u32 = 0x2004_0000;
+pub(crate) const SRAM2_END: u32 = 0x2005_0000;
+// F413 上并不存在，列在这里是为了在移植到有 CCM 的芯片时，能被检查出来
+pub(crate) const CCM_START: u32 = 0x1000_0000;
+pub(crate) const CCM_END: u32 = 0x1001_0000;
+
+// NDTR 为 16 bit
+pub(crate) const MAX_TRANSFERS: usize = u16::MAX as usize;
+
+extern "C" {
+    static mut __ssram2: u32;
+    static mut __esram2: u32;
+    static __sisram2: u32;
+    static mut __sdma_buffer: u32;
+    static mut __edma_buffer: u32;
+}
+
+// 把 .sram2 段的内容从 Flash 复制到 SRAM2，并把 .dma_buffer 段清零
+//
+// cortex-m-rt 只会初始化 .data 与 .bss，我们自己加的段需要自己处理
+pub(crate) fn init() {
+    unsafe {
+        let start = ptr::addr_of_mut!(__ssram2);
+        let end = ptr::addr_of_mut!(__esram2);
+        let load = ptr::addr_of!(__sisram2);
+        let count = end.offset_from(start) as usize;
+        ptr::copy_nonoverlapping(load, start, count);
+
+        let start = ptr::addr_of_mut!(__sdma_buffer);
+        let end = ptr::addr_of_mut!(__edma_buffer);
+        let count = end.offset_from(start) as usize;
+        ptr::write_bytes(start, 0, count);
+    }
+
+    // 确保复制完成之后，才从 SRAM2 中取指令
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    // DMA 从这里读
+    Read,
+    // DMA 往这里写
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PlacementError {
+    // 在 CCM 中，DMA 无法访问
+    Ccm,
+    // 在 Flash 中，DMA 只能读，不能写
+    FlashWrite,
+    // 跨越了两个区域，或者不在任何 DMA 可以访问的区域中
+    Unreachable,
+    // 地址没有按照数据宽度对齐
+    Misaligned,
+    // 传输次数为 0，或者超过了 NDTR 的上限
+    Length,
+}
+
+// 检查 [addr, addr + count * width) 能否作为 DMA 的存储器端
+//
+// width 为 DMA 的 MSIZE，单位为字节
+pub(crate) fn check_dma_address(
+    addr: u32,
+    count: usize,
+    width: usize,
+    access: Access,
+) -> Result<(), PlacementError> {
+    if count == 0 || count > MAX_TRANSFERS {
+        return Err(PlacementError::Length);
+    }
+    if !(addr as usize).is_multiple_of(width) {
+        return Err(PlacementError::Misaligned);
+    }
+
+    let end = addr as u64 + (count * width) as u64;
+    let within = |start: u32, stop: u32| addr >= start && end <= stop as u64;
+
+    if addr < CCM_END && end > CCM_START as u64 {
+        return Err(PlacementError::Ccm);
+    }
+    if within(FLASH_START, FLASH_END) {
+        return match access {
+            Access::Read => Ok(()),
+            Access::Write => Err(PlacementError::FlashWrite),
+        };
+    }
+    // SRAM1 与 SRAM2 在地址上是连续的，DMA 也都可以访问，跨越二者也没有问题
+    if within(SRAM1_START, SRAM2_END) {
+        return Ok(());
+    }
+    Err(PlacementError::Unreachable)
+}
+
+// DMA 一次搬运的数据宽度，对应 DMA 的 MSIZE/PSIZE
+//
+// 只为 u8/u16/u32 实现，于是 DmaBuffer 里不会出现 DMA 无法处理的类型，全零也一定是合法的值
+pub(crate) trait DmaWord: Copy + private::Sealed {
+    // MSIZE/PSIZE 的编码
+    const SIZE_BITS: u8;
+}
+
+impl DmaWord for u8 {
+    const SIZE_BITS: u8 = 0b00;
+}
+impl DmaWord for u16 {
+    const SIZE_BITS: u8 = 0b01;
+}
+impl DmaWord for u32 {
+    const SIZE_BITS: u8 = 0b10;
+}
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for u8 {}
+    impl Sealed for u16 {}
+    impl Sealed for u32 {}
+}
+
+// DMA 缓冲区
+//
+// 对齐到 16 字节，这样 MSIZE 为 word、MBURST 为 INCR4 时，每个 burst 都不会跨越 1 KiB 的边界
+// UnsafeCell 保证了它不会像 s08c01_mem2mem_02flash2mem 中的 DST_LIST 那样，被链接器放进 Flash
+#[repr(C, align(16))]
+pub(crate) struct DmaBuffer<W: DmaWord, const N: usize> {
+    data: UnsafeCell<MaybeUninit<[W; N]>>,
+}
+
+// DmaBuffer 只通过 unsafe 的 as_mut 访问，由调用者保证访问时 DMA 没有在运行
+unsafe impl<W: DmaWord, const N: usize> Sync for DmaBuffer<W, N> {}
+
+impl<W: DmaWord, const N: usize> DmaBuffer<W, N> {
+    // 编译期检查，长度不符合要求时，会在用到这个 DmaBuffer 的地方编译失败
+    const VALID_LEN: () = assert!(
+        N > 0 && N <= MAX_TRANSFERS,
+        "DMA buffer length must be within 1 ..= 65535"
+    );
+
+    pub(crate) const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_LEN;
+        Self {
+            data: UnsafeCell::new(MaybeUninit::zeroed()),
+        }
+    }
+
+    pub(crate) const fn len(&self) -> usize {
+        N
+    }
+
+    // 写入 DMA 的 M0AR/M1AR/PAR
+    pub(crate) fn addr(&self) -> u32 {
+        let addr = self.data.get() as u32;
+        debug_assert!(
+            (SRAM1_START..SRAM1_END).contains(&addr),
+            "DmaBuffer is not in SRAM1, declare it with dma_buffer!"
+        );
+        addr
+    }
+
+    pub(crate) fn size_bits(&self) -> u8 {
+        W::SIZE_BITS
+    }
+
+    // 调用者需要保证，在返回的引用存续期间，DMA 没有在读写这个缓冲区，也没有其它引用
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn as_mut(&self) -> &mut [W; N] {
+        // DmaWord 只有整数类型，全零总是合法的值，而 init 已经把 .dma_buffer 段清零
+        (*self.data.get()).assume_init_mut()
+    }
+}
+
+// 声明一个放在 .dma_buffer 段中的 DMA 缓冲区
+//
+// 例如：dma_buffer!(static RX_BUF: [u16; 256]);
+macro_rules! dma_buffer {
+    ($vis:vis static $name:ident: [$word:ty; $len:expr]) => {
+        #[link_section = ".dma_buffer"]
+        $vis static $name: $crate::utils::placement::DmaBuffer<$word, { $len }> =
+            $crate::utils::placement::DmaBuffer::new();
+    };
+}
+
+pub(crate) use dma_buffer;
@@ -30,14 +30,14 @@
 //!
 //! 1. 开始采样，接通 GPIO 口至 ADC 的通路，并在内部保存一下 GPIO 口的电压（比如通过一个小电容存储一下电压）
 //! 2. 断开 GPIO 到 ADC 的通路，之后对电压进行量化的过程，全部是针对保存在 ADC 内部的电压进行的
-//! NOTE: 这样做的好处是，ADC 在执行电压量化的过程中，ADC 量化的电压是固定的，防止 GPIO 输入的电压不断变化带来错误。
+//!    NOTE: 这样做的好处是，ADC 在执行电压量化的过程中，ADC 量化的电压是固定的，防止 GPIO 输入的电压不断变化带来错误。
 //! 3. 启用 ADC 内部的一个 DAC（数字到模拟转换器），通过不断修改 DAC 的值修改 DAC 输出的电压，最终找到一个最近似的值
-//! NOTE1: DAC 并非使用 PWM 的形式输出等效电压，而是在内部使用了一个被称为 R-2R 梯形网络（R-2R Ladder Network）的电阻电路来，稳定的输出一个确定的电压（理论上来说，DAC 可以输出离散的模拟信号）
-//! NOTE2：DAC 量化 ADC 采样到的电压，是通过二分法进行的，因此 STM32 的 ADC 才被称为 逐次逼近型（successive approximation）ADC
-//! IMPORTANT：正是因为量化过程是二分逼近的，导致 ADC 转换电压需要花费多个 ADC 时钟周期
-//! NOTE3：DAC 的 R-2R 梯形电阻网络的精度，决定了 ADC 的量化精度，也就是 ADC 的分辨率（resolution）指标的由来
+//!    NOTE1: DAC 并非使用 PWM 的形式输出等效电压，而是在内部使用了一个被称为 R-2R 梯形网络（R-2R Ladder Network）的电阻电路来，稳定的输出一个确定的电压（理论上来说，DAC 可以输出离散的模拟信号）
+//!    NOTE2：DAC 量化 ADC 采样到的电压，是通过二分法进行的，因此 STM32 的 ADC 才被称为 逐次逼近型（successive approximation）ADC
+//!    IMPORTANT：正是因为量化过程是二分逼近的，导致 ADC 转换电压需要花费多个 ADC 时钟周期
+//!    NOTE3：DAC 的 R-2R 梯形电阻网络的精度，决定了 ADC 的量化精度，也就是 ADC 的分辨率（resolution）指标的由来
 //! 4. DAC 的值就作为 ADC 的值输出出去
-//! IMPORTANT：注意 ADC 输出的值仅为 DAC 的值，这个值是相对于 V_{REF-} 和 V_{REF+} 这两个电压的值，我们需要在外部手动执行一些计算，才能将 ADC 寄存器的值对应上实际的电压值
+//!    IMPORTANT：注意 ADC 输出的值仅为 DAC 的值，这个值是相对于 V_{REF-} 和 V_{REF+} 这两个电压的值，我们需要在外部手动执行一些计算，才能将 ADC 寄存器的值对应上实际的电压值
 //!
 //!
 //! ADC 的几个重要的输入电压
@@ -253,7 +253,10 @@ fn setup_adc() {
         // 采样通道 6 时，让 ADC 等待 480 个 ADCCLK 周期，再进入量化过程
         // SMPR2: ADC SaMPle time Register 2
         // SMP6: channel 6 SaMPling time selection
-        voltage_sampler.smpr2.modify(|_, w| w.smp6().cycles480());
+        // 480 个周期对应 SMP6 的 0b111，F401/F411 的 pac 没有为它生成 cycles480()，因此直接写位
+        voltage_sampler
+            .smpr2
+            .modify(|r, w| unsafe { w.bits(r.bits() | 0b111 << (3 * 6)) });
 
         // 使用外部触发源，触发 ADC 单次采样、量化
         voltage_sampler.cr2.modify(|_, w| {
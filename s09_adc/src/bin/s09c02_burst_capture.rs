@@ -0,0 +1,161 @@
+//! 用 ADC + DMA 实现一个简单的单通道示波器
+//!
+//! 以 100 kHz 对 GPIO PA6（ADC1_6）连续采样，结果通过 DMA 写入一个 4096 点的环形缓冲区
+//! 触发之后再采集 3072 个点，这样一次采集中，触发前有 1024 个点，触发后有 3072 个点
+//!
+//! 原理见 utils::capture
+//!
+//! 采集完成后，通过 RTT 以 CSV 的格式输出波形，每行一个点：序号（以触发点为 0），电压（mV）
+//! 把 RTT 的输出保存下来，就可以用任意的表格或绘图工具画出波形了
+//!
+//! TRIGGER 常量用于选择触发源：PA0 的上升沿，或者模拟看门狗
+//!
+//! 接线图：
+//!
+//! 被测信号 -> GPIO PA6（注意，analog 模式下引脚不是 FT 的，电压不能超过 3.3 V）
+//! 触发信号 -> GPIO PA0（使用 GPIO 触发时）
+//! 两者与开发板共地
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{interrupt, Peripherals};
+
+mod utils;
+
+use utils::capture::{CaptureConfig, Scope, Trigger};
+
+const SAMPLES: usize = 4096;
+
+// 在超过 2.0 V 或低于 0.5 V 时触发
+// const TRIGGER: Trigger = Trigger::AnalogWatchdog {
+//     low: (0.5 / 3.3 * 4095.0) as u16,
+//     high: (2.0 / 3.3 * 4095.0) as u16,
+// };
+const TRIGGER: Trigger = Trigger::RisingEdgePa0;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = Peripherals::take().expect("Cannot Get Peripherals");
+
+    // 与 s09c01 相同，HCLK 与 APB2 为 60 MHz，ADCCLK 为 30 MHz
+    setup_pll(&dp);
+    setup_gpio(&dp);
+
+    dp.RCC.apb1enr.modify(|_, w| w.tim2en().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.adc1en().enabled());
+    dp.RCC.ahb1enr.modify(|_, w| w.dma2en().enabled());
+    dp.ADC_COMMON.ccr.modify(|_, w| w.adcpre().div2());
+
+    let buffer = cortex_m::singleton!(: [u16; SAMPLES] = [0; SAMPLES]).unwrap();
+
+    let mut scope = Scope::new(
+        &dp,
+        buffer,
+        CaptureConfig {
+            channel: 6,
+            // APB1 为 30 MHz，APB1 的 TIM 时钟自动 x2，为 60 MHz
+            tim_clk_hz: 60_000_000,
+            sample_hz: 100_000,
+            trigger: TRIGGER,
+            post_trigger: SAMPLES * 3 / 4,
+        },
+    )
+    .unwrap();
+
+    loop {
+        scope.arm(&dp);
+        rprintln!("# armed, waiting for trigger");
+
+        let capture = loop {
+            if let Some(capture) = scope.poll(&dp) {
+                break capture;
+            }
+        };
+
+        let sample_hz = scope.config().sample_hz;
+        rprintln!(
+            "# {} samples at {} Hz, trigger at sample {}",
+            capture.len(),
+            sample_hz,
+            capture.trigger_offset
+        );
+        rprintln!("index,mV");
+        for (i, raw) in capture.samples().enumerate() {
+            let index = i as i32 - capture.trigger_offset as i32;
+            let millivolt = raw as u32 * 3300 / 4095;
+            rprintln!("{},{}", index, millivolt);
+        }
+    }
+}
+
+#[interrupt]
+fn EXTI0() {
+    utils::capture::on_exti0();
+}
+
+#[interrupt]
+fn ADC() {
+    utils::capture::on_adc();
+}
+
+fn setup_pll(dp: &Peripherals) {
+    dp.RCC.cr.modify(|_, w| w.hseon().on());
+    while dp.RCC.cr.read().hserdy().is_not_ready() {}
+
+    // 12 MHz / 6 * 120 / 4 = 60 MHz，详细说明见 s09c01
+    dp.RCC.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(120);
+        }
+        w.pllp().div4();
+        w
+    });
+
+    // Scale 3 mode
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| unsafe { w.vos().bits(0b01) });
+
+    // 30 MHz < HCLK <= 64 MHz，FLASH 读取需要等待 1 个周期
+    dp.FLASH.acr.modify(|_, w| {
+        w.dcrst().reset();
+        w.icrst().reset();
+        w
+    });
+    dp.FLASH.acr.modify(|_, w| {
+        w.latency().ws1();
+        w.dcen().enabled();
+        w.icen().enabled();
+        w.prften().enabled();
+        w
+    });
+
+    // APB1 最高 50 MHz，这里 /2 分频
+    dp.RCC.cfgr.modify(|_, w| w.ppre1().div2());
+
+    dp.RCC.cr.modify(|_, w| w.pllon().on());
+    while dp.PWR.csr.read().vosrdy().bit_is_clear() {}
+    while dp.RCC.cr.read().pllrdy().is_not_ready() {}
+
+    dp.RCC.cfgr.modify(|_, w| w.sw().pll());
+    while !dp.RCC.cfgr.read().sws().is_pll() {}
+}
+
+fn setup_gpio(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+
+    dp.GPIOA.moder.modify(|_, w| {
+        // GPIO PA0 为触发输入，下拉电阻保证没有接线时不会误触发
+        w.moder0().input();
+        // GPIO PA6 为 ADC1_6
+        w.moder6().analog();
+        w
+    });
+    dp.GPIOA.pupdr.modify(|_, w| w.pupdr0().pull_down());
+}
@@ -0,0 +1,200 @@
+//! 用过零检测做一个可控硅（TRIAC）相位调光器
+//!
+//! 过零检测的原理见 utils::zero_cross
+//!
+//! 可控硅一旦被触发，就会一直导通到这个半周期的电流过零为止，因此调光的方法是：
+//! 在每个半周期的过零点之后，延迟一段时间再给出触发脉冲，延迟越长，这个半周期导通的部分越少，灯越暗
+//! 功率为 p（0 ~ 1）时，这里简单地让导通角线性变化：触发点的相位为 (1 - p) * 180°，并没有按照正弦波的面积做校正
+//!
+//! 每次过零都在 ADC 的中断里启动 TIM3 的单脉冲模式：CNT 从 0 开始，到 CCR1 时 PB4 变为高电平，到 ARR 时变回低电平并停止
+//! CCR1 扣除了从过零的那次采样到现在已经过去的时间，因此中断的延迟不会影响触发的相位
+//!
+//! 主循环让功率在 10% ~ 90% 之间来回变化，并每秒输出一次测得的交流电频率
+//!
+//! 警告：这里涉及市电，检测端必须经过变压器隔离，触发端必须经过光耦可控硅（比如 MOC3021）隔离，开发板不能与市电有任何直接的连接
+//!
+//! 接线图：
+//!
+//! 交流电 -> 变压器降压 -> 分压，并叠加 1.65 V 的偏置 -> GPIO PA1（ADC1_1），峰峰值不能超过 3.3 V
+//! GPIO PB4（TIM3_CH1）-> 限流电阻 -> MOC3021 的 LED -> GND，MOC3021 的输出按照其 datasheet 驱动可控硅
+
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt, Peripherals};
+
+mod utils;
+
+use utils::zero_cross::{self, DetectorConfig};
+
+// APB1 为 30 MHz，APB1 的 TIM 时钟自动 x2，为 60 MHz
+const TIM_CLK_HZ: u32 = 60_000_000;
+
+// 偏置电压 1.65 V 对应 2048，迟滞约 ±40 mV
+const DETECTOR: DetectorConfig = DetectorConfig {
+    channel: 1,
+    tim_clk_hz: TIM_CLK_HZ,
+    sample_hz: 20_000,
+    threshold: 2048,
+    hysteresis: 50,
+};
+
+// 触发脉冲的宽度
+const GATE_PULSE_US: u32 = 100;
+// 半周期末尾的这段时间内不再触发，否则可能在下一个半周期开始之后才触发，反而全亮一下
+const GUARD_US: u32 = 500;
+
+// 功率，单位 0.1%
+static G_POWER: AtomicU32 = AtomicU32::new(0);
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = Peripherals::take().expect("Cannot Get Peripherals");
+
+    // 与 s09c02 相同，HCLK 与 APB2 为 60 MHz，ADCCLK 为 30 MHz
+    setup_pll(&dp);
+    setup_gpio(&dp);
+
+    dp.RCC.apb1enr.modify(|_, w| {
+        w.tim2en().enabled();
+        w.tim3en().enabled();
+        w.tim5en().enabled();
+        w
+    });
+    dp.RCC.apb2enr.modify(|_, w| w.adc1en().enabled());
+    dp.ADC_COMMON.ccr.modify(|_, w| w.adcpre().div2());
+
+    setup_gate(&dp);
+    zero_cross::setup(&dp, &DETECTOR).unwrap();
+    zero_cross::start(&dp);
+
+    let mut power = 100;
+    let mut step: i32 = 10;
+    loop {
+        G_POWER.store(power as u32, Ordering::Relaxed);
+
+        match zero_cross::frequency_hz() {
+            Some(hz) => rprintln!("{:.2} Hz, power {}%", hz, power / 10),
+            None => rprintln!("no signal"),
+        }
+
+        if !(100..=900).contains(&(power + step)) {
+            step = -step;
+        }
+        power += step;
+
+        cortex_m::asm::delay(60_000_000);
+    }
+}
+
+#[interrupt]
+fn ADC() {
+    if let (Some(crossing), Some(period)) = (zero_cross::on_adc(), zero_cross::period_us()) {
+        schedule_gate(crossing.at_us, period);
+    }
+}
+
+// 在 crossing_at_us 之后的合适时刻给出触发脉冲
+fn schedule_gate(crossing_at_us: u32, period: u32) {
+    // 每个半周期都要触发，两种边沿都以它为起点
+    let half = period / 2;
+    let power = G_POWER.load(Ordering::Relaxed);
+    let fire_at = half * (1000 - power) / 1000;
+    if fire_at + GATE_PULSE_US + GUARD_US > half {
+        return;
+    }
+
+    // 扣除从那次采样到现在已经过去的时间
+    let elapsed = zero_cross::now_us().wrapping_sub(crossing_at_us);
+    let delay = fire_at.saturating_sub(elapsed).max(1);
+
+    let tim3 = unsafe { &*pac::TIM3::ptr() };
+    tim3.ccr1().write(|w| w.ccr().bits(delay as u16));
+    tim3.arr
+        .write(|w| w.arr().bits((delay + GATE_PULSE_US) as u16));
+    tim3.cnt.reset();
+    tim3.cr1.modify(|_, w| w.cen().enabled());
+}
+
+// TIM3 以 1 MHz 计数，单脉冲模式，PWM mode 2：CNT >= CCR1 时输出高电平
+fn setup_gate(dp: &Peripherals) {
+    let tim3 = &dp.TIM3;
+    tim3.psc
+        .write(|w| w.psc().bits((TIM_CLK_HZ / 1_000_000 - 1) as u16));
+    tim3.cr1.modify(|_, w| w.opm().enabled());
+    tim3.ccmr1_output().modify(|_, w| {
+        w.cc1s().output();
+        w.oc1m().pwm_mode2();
+        w
+    });
+    // 停止时 CNT 为 0，小于 CCR1，输出保持低电平
+    tim3.ccr1().write(|w| w.ccr().bits(u16::MAX));
+    tim3.ccer.modify(|_, w| w.cc1e().set_bit());
+}
+
+fn setup_pll(dp: &Peripherals) {
+    dp.RCC.cr.modify(|_, w| w.hseon().on());
+    while dp.RCC.cr.read().hserdy().is_not_ready() {}
+
+    // 12 MHz / 6 * 120 / 4 = 60 MHz，详细说明见 s09c01
+    dp.RCC.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(120);
+        }
+        w.pllp().div4();
+        w
+    });
+
+    // Scale 3 mode
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| unsafe { w.vos().bits(0b01) });
+
+    // 30 MHz < HCLK <= 64 MHz，FLASH 读取需要等待 1 个周期
+    dp.FLASH.acr.modify(|_, w| {
+        w.dcrst().reset();
+        w.icrst().reset();
+        w
+    });
+    dp.FLASH.acr.modify(|_, w| {
+        w.latency().ws1();
+        w.dcen().enabled();
+        w.icen().enabled();
+        w.prften().enabled();
+        w
+    });
+
+    // APB1 最高 50 MHz，这里 /2 分频
+    dp.RCC.cfgr.modify(|_, w| w.ppre1().div2());
+
+    dp.RCC.cr.modify(|_, w| w.pllon().on());
+    while dp.PWR.csr.read().vosrdy().bit_is_clear() {}
+    while dp.RCC.cr.read().pllrdy().is_not_ready() {}
+
+    dp.RCC.cfgr.modify(|_, w| w.sw().pll());
+    while !dp.RCC.cfgr.read().sws().is_pll() {}
+}
+
+fn setup_gpio(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w
+    });
+
+    // GPIO PA1 为 ADC1_1
+    dp.GPIOA.moder.modify(|_, w| w.moder1().analog());
+
+    // GPIO PB4 为 TIM3_CH1，下拉保证 TIM3 配置之前光耦不会被点亮
+    dp.GPIOB.pupdr.modify(|_, w| w.pupdr4().pull_down());
+    dp.GPIOB.afrl.modify(|_, w| w.afrl4().af2());
+    dp.GPIOB.moder.modify(|_, w| w.moder4().alternate());
+}
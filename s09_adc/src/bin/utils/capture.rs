@@ -0,0 +1,318 @@
+//! 单通道的“示波器”：触发之后抓取一段 ADC 波形
+//!
+//! 整个采样过程不需要 CPU 参与：
+//!
+//! TIM2 以固定的频率输出 TRGO，每个 TRGO 触发 ADC1 的一次转换，
+//! ADC1 每完成一次转换就发出 DMA 请求，DMA2 Stream 0 Channel 0 以循环模式把结果写入一个环形缓冲区
+//!
+//! 在触发之前，环形缓冲区会被反复覆盖，因此缓冲区里一直保存着“最近的 N 个采样”，
+//! 触发时，只记录下当时 DMA 写到了哪里（由 NDTR 推算），之后继续采样 post_trigger 个点，再停止 TIM2
+//! 这样缓冲区里就同时保存了触发之前（pre-trigger）与触发之后（post-trigger）的波形
+//!
+//! 触发源有两种：
+//!
+//! 1. GPIO PA0 的上升沿，经过 EXTI0 触发中断
+//! 2. ADC 的模拟看门狗（Analog Watchdog），采样值超出 [low, high] 的范围时，ADC 触发中断
+//!
+//! 中断里只做一件事：记下触发位置，因此中断本身对采样毫无影响
+
+#![allow(dead_code)]
+
+use core::cell::Cell;
+
+use assert_policy::ensure;
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{interrupt, Peripherals, NVIC};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Trigger {
+    // GPIO PA0 的上升沿
+    RisingEdgePa0,
+    // 采样值低于 low 或者高于 high
+    AnalogWatchdog { low: u16, high: u16 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CaptureConfig {
+    // ADC 所用的通道
+    pub(crate) channel: u8,
+    // TIM2 的时钟频率与采样频率
+    pub(crate) tim_clk_hz: u32,
+    pub(crate) sample_hz: u32,
+    pub(crate) trigger: Trigger,
+    // 触发之后再采样的点数，必须小于缓冲区的长度，剩下的部分就是触发之前的采样
+    pub(crate) post_trigger: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CaptureError {
+    // 通道超过了 9，这里只设置了 SMPR2 中的采样时间
+    BadChannel,
+    // post_trigger 不小于缓冲区的长度
+    BadPostTrigger,
+}
+
+// 触发时 DMA 正在写入的下标
+static G_TRIGGER_AT: Mutex<Cell<Option<usize>>> = Mutex::new(Cell::new(None));
+// 环形缓冲区的长度，中断里计算下标时需要
+static G_LEN: Mutex<Cell<usize>> = Mutex::new(Cell::new(0));
+
+pub(crate) struct Scope {
+    buffer: &'static mut [u16],
+    config: CaptureConfig,
+}
+
+// 采集完成的一段波形
+pub(crate) struct Capture<'a> {
+    buffer: &'a [u16],
+    // 最早的采样在 buffer 中的下标
+    oldest: usize,
+    // 触发点相对于最早的采样的位置
+    pub(crate) trigger_offset: usize,
+}
+
+impl Capture<'_> {
+    // 按照时间先后顺序返回所有采样
+    pub(crate) fn samples(&self) -> impl Iterator<Item = u16> + '_ {
+        self.buffer[self.oldest..]
+            .iter()
+            .chain(self.buffer[..self.oldest].iter())
+            .copied()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl Scope {
+    // ADC1、TIM2、DMA2 的时钟，以及 GPIO 的 analog 模式需要提前设置好
+    // 参数不对时，debug 构建直接 panic，release 构建返回错误，此时什么都没有配置，策略见 assert_policy
+    pub(crate) fn new(
+        dp: &Peripherals,
+        buffer: &'static mut [u16],
+        config: CaptureConfig,
+    ) -> Result<Self, CaptureError> {
+        ensure!(config.channel < 10, CaptureError::BadChannel);
+        ensure!(
+            config.post_trigger < buffer.len(),
+            CaptureError::BadPostTrigger
+        );
+
+        setup_tim2(dp, &config);
+        setup_adc(dp, &config);
+        if config.trigger == Trigger::RisingEdgePa0 {
+            setup_exti0(dp);
+        }
+
+        Ok(Self { buffer, config })
+    }
+
+    // 开始采样，并等待触发
+    pub(crate) fn arm(&mut self, dp: &Peripherals) {
+        let len = self.buffer.len();
+        cortex_m::interrupt::free(|cs| {
+            G_TRIGGER_AT.borrow(cs).set(None);
+            G_LEN.borrow(cs).set(len);
+        });
+
+        setup_dma(dp, self.buffer);
+
+        let adc = &dp.ADC1;
+        // 重新开启 DMA 之前，要先清除 OVR 并重新设置 ADC 的 DMA 位
+        adc.sr.modify(|_, w| w.ovr().clear_bit());
+        adc.cr2.modify(|_, w| w.dma().disabled());
+        adc.cr2.modify(|_, w| w.dma().enabled());
+
+        match self.config.trigger {
+            Trigger::RisingEdgePa0 => {
+                dp.EXTI.pr.write(|w| w.pr0().clear());
+                dp.EXTI.imr.modify(|_, w| w.mr0().unmasked());
+            }
+            Trigger::AnalogWatchdog { .. } => {
+                adc.sr.modify(|_, w| w.awd().clear_bit());
+                adc.cr1.modify(|_, w| w.awdie().enabled());
+            }
+        }
+
+        dp.TIM2.cnt.reset();
+        dp.TIM2.cr1.modify(|_, w| w.cen().enabled());
+    }
+
+    // 触发之后，采集到足够多的点时返回采集结果，否则返回 None
+    pub(crate) fn poll(&self, dp: &Peripherals) -> Option<Capture<'_>> {
+        let len = self.buffer.len();
+        let trigger_at = cortex_m::interrupt::free(|cs| G_TRIGGER_AT.borrow(cs).get())?;
+
+        if distance(trigger_at, write_index(dp, len), len) < self.config.post_trigger {
+            return None;
+        }
+
+        // 停止采样，之后 DMA 不会再写入
+        dp.TIM2.cr1.modify(|_, w| w.cen().disabled());
+        // 此时可能还有一次转换没有完成，等待它完成并被 DMA 搬走，60 MHz 的 HCLK 下 27 个 ADCCLK 不到 60 个周期
+        cortex_m::asm::delay(100);
+
+        // 轮询与停止之间可能又多采了几个点，因此以实际停止的位置为准，
+        // 停止位置就是下一次要写入的位置，也就是最早的那个采样
+        let oldest = write_index(dp, len);
+        let trigger_offset = distance(oldest, trigger_at, len);
+
+        dp.DMA2.st[0].cr.modify(|_, w| w.en().disabled());
+        while dp.DMA2.st[0].cr.read().en().is_enabled() {}
+
+        Some(Capture {
+            buffer: &self.buffer[..],
+            oldest,
+            trigger_offset,
+        })
+    }
+
+    pub(crate) fn config(&self) -> &CaptureConfig {
+        &self.config
+    }
+}
+
+// DMA 下一次写入的下标
+fn write_index(dp: &Peripherals, len: usize) -> usize {
+    let remaining = dp.DMA2.st[0].ndtr.read().ndt().bits() as usize;
+    (len - remaining) % len
+}
+
+// 环形缓冲区中，从 from 向后数到 to 的距离
+fn distance(from: usize, to: usize, len: usize) -> usize {
+    (to + len - from) % len
+}
+
+// 记录触发位置，只有第一次触发有效
+fn record_trigger(dp: &Peripherals) {
+    cortex_m::interrupt::free(|cs| {
+        let trigger_at = G_TRIGGER_AT.borrow(cs);
+        if trigger_at.get().is_none() {
+            let len = G_LEN.borrow(cs).get();
+            trigger_at.set(Some(write_index(dp, len)));
+        }
+    });
+}
+
+fn setup_tim2(dp: &Peripherals, config: &CaptureConfig) {
+    let tim = &dp.TIM2;
+    tim.cr1.modify(|_, w| w.cen().disabled());
+    tim.psc.write(|w| w.psc().bits(0));
+    tim.arr
+        .write(|w| w.arr().bits(config.tim_clk_hz / config.sample_hz - 1));
+    // 每次溢出在 TRGO 上输出一个脉冲，触发 ADC
+    tim.cr2.modify(|_, w| w.mms().update());
+    tim.egr.write(|w| w.ug().update());
+}
+
+fn setup_adc(dp: &Peripherals, config: &CaptureConfig) {
+    let adc = &dp.ADC1;
+
+    adc.sqr3
+        .modify(|_, w| unsafe { w.sq1().bits(config.channel) });
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+
+    // 30 MHz 的 ADCCLK 下，15 + 12 个周期为 0.9 us，采样频率可以到 1 MHz 左右
+    // 通道 0 ~ 9 的采样时间位于 SMPR2，每个通道占 3 个位，0b001 为 15 个周期，通道已经在 Scope::new 中检查过了
+    let shift = 3 * config.channel as u32;
+    adc.smpr2
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << shift) | 0b001 << shift) });
+
+    adc.cr2.modify(|_, w| {
+        w.extsel().tim2trgo();
+        w.exten().rising_edge();
+        // DMA 循环模式下，DDS 必须置位，否则 DMA 的第一轮结束后 ADC 就不再发出请求了
+        w.dds().continuous();
+        w.dma().enabled();
+        w
+    });
+
+    if let Trigger::AnalogWatchdog { low, high } = config.trigger {
+        adc.ltr.write(|w| w.lt().bits(low));
+        adc.htr.write(|w| w.ht().bits(high));
+        adc.cr1.modify(|_, w| unsafe {
+            // 只监视一个通道
+            w.awdch().bits(config.channel);
+            w.awdsgl().single_channel();
+            w.awden().enabled();
+            w
+        });
+        unsafe { NVIC::unmask(interrupt::ADC) };
+    }
+
+    adc.cr2.modify(|_, w| w.adon().enabled());
+}
+
+// DMA2 Stream 0 Channel 0 为 ADC1，每次 arm 都重新设置
+fn setup_dma(dp: &Peripherals, buffer: &mut [u16]) {
+    let dma2 = &dp.DMA2;
+    let st = &dma2.st[0];
+
+    if st.cr.read().en().is_enabled() {
+        st.cr.modify(|_, w| w.en().disabled());
+        while st.cr.read().en().is_enabled() {}
+    }
+
+    dma2.lifcr.write(|w| {
+        w.ctcif0().clear();
+        w.chtif0().clear();
+        w.cteif0().clear();
+        w.cdmeif0().clear();
+        w.cfeif0().clear();
+        w
+    });
+
+    st.cr.write(|w| {
+        w.chsel().bits(0);
+        w.pl().high();
+        w.dir().peripheral_to_memory();
+        w.msize().bits16();
+        w.psize().bits16();
+        w.minc().incremented();
+        w.pinc().fixed();
+        // 循环模式，缓冲区写满后从头开始覆盖
+        w.circ().enabled();
+        w
+    });
+    st.par
+        .write(|w| unsafe { w.pa().bits(dp.ADC1.dr.as_ptr() as u32) });
+    st.m0ar
+        .write(|w| unsafe { w.m0a().bits(buffer.as_mut_ptr() as u32) });
+    st.ndtr.write(|w| w.ndt().bits(buffer.len() as u16));
+
+    st.cr.modify(|_, w| w.en().enabled());
+}
+
+// PA0 上升沿，EXTI0
+fn setup_exti0(dp: &Peripherals) {
+    dp.RCC.apb2enr.modify(|_, w| w.syscfgen().enabled());
+    dp.SYSCFG
+        .exticr1
+        .modify(|_, w| unsafe { w.exti0().bits(0) });
+    dp.EXTI.rtsr.modify(|_, w| w.tr0().enabled());
+    // 在 arm 之前，先不接受中断
+    dp.EXTI.imr.modify(|_, w| w.mr0().masked());
+    unsafe { NVIC::unmask(interrupt::EXTI0) };
+}
+
+// 在 EXTI0 的中断里调用
+pub(crate) fn on_exti0() {
+    let dp = unsafe { Peripherals::steal() };
+    dp.EXTI.pr.write(|w| w.pr0().clear());
+    // 只需要触发一次
+    dp.EXTI.imr.modify(|_, w| w.mr0().masked());
+    record_trigger(&dp);
+}
+
+// 在 ADC 的中断里调用
+pub(crate) fn on_adc() {
+    let dp = unsafe { Peripherals::steal() };
+    let adc = &dp.ADC1;
+    if adc.sr.read().awd().bit_is_set() {
+        adc.sr.modify(|_, w| w.awd().clear_bit());
+        // 只需要触发一次，否则波形超出范围期间会不停地进入中断
+        adc.cr1.modify(|_, w| w.awdie().disabled());
+        record_trigger(&dp);
+    }
+}
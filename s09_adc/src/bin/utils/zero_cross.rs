@@ -0,0 +1,314 @@
+//! 用 ADC 的模拟看门狗代替比较器，检测交流电的过零点（或者任意一个模拟量的阈值），并给出相位角
+//!
+//! F4 系列没有模拟比较器，这里用 ADC 来模拟一个带迟滞的比较器：
+//!
+//! TIM2 以固定的频率输出 TRGO，每个 TRGO 触发 ADC1 的一次转换（与 utils::capture 相同），
+//! 模拟看门狗监视这个通道，采样值跑出窗口 [LTR, HTR] 时产生中断
+//!
+//! - 信号在阈值之下时，窗口为 [0, threshold + hysteresis]，信号升过上沿，产生一次 Rising
+//! - 信号在阈值之上时，窗口为 [threshold - hysteresis, 4095]，信号降过下沿，产生一次 Falling
+//!
+//! 每次产生中断，就把窗口切换到另一边，于是一次过零只会产生一次中断，阈值附近的噪声只要小于 hysteresis，也不会来回触发
+//!
+//! 时间戳由 TIM5 的输入捕获给出：
+//! TIM5 以 1 MHz 自由计数，TS 选择 ITR0（连接到 TIM2），CC1 捕获 TRC，
+//! 于是 TIM2 每触发一次 ADC 转换，TIM5 都会把当时的 CNT 锁存到 CCR1 中，中断里读到的 CCR1 就是触发中断的那次采样的时刻，
+//! 与中断的延迟无关（只要延迟短于一个采样周期）
+//!
+//! 过零点实际发生在上一次采样与这一次采样之间，因此时间戳的分辨率就是采样周期，
+//! 以 20 kHz 采样 50 Hz 的交流电时为 50 us，即 0.9°
+//!
+//! 时间都以 TIM5 的 CNT 为准，单位 us，约 71.6 分钟回绕一次，计算时间差时都使用 wrapping_sub
+//!
+//! 启动之后的第一次中断只用于确定信号在阈值的哪一边，不会被当作一次过零
+//!
+//! 测量交流电时，需要先用变压器隔离降压，再叠加 1.65 V 的直流偏置，让信号在 0 ~ 3.3 V 之间摆动，阈值就设置为偏置电压
+//!
+//! on_adc 需要在 ADC 的中断中调用
+
+#![allow(dead_code)]
+
+use core::cell::Cell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{self, interrupt, Peripherals, NVIC};
+
+const ADC_MAX: u16 = 4095;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Edge {
+    Rising,
+    Falling,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Crossing {
+    pub(crate) edge: Edge,
+    // 发生这次过零的采样的时刻，TIM5 的 CNT，单位 us
+    pub(crate) at_us: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DetectorConfig {
+    // ADC1 所用的通道，0 ~ 15
+    pub(crate) channel: u8,
+    // APB1 上 TIM 的时钟
+    pub(crate) tim_clk_hz: u32,
+    pub(crate) sample_hz: u32,
+    // 阈值与迟滞，都是 ADC 的原始读数
+    pub(crate) threshold: u16,
+    pub(crate) hysteresis: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DetectorError {
+    // 通道超过了 15
+    Channel,
+    // threshold ± hysteresis 超出了 0 ~ 4095
+    Threshold,
+    // TIM 的时钟不是 1 MHz 的整数倍，或者采样频率为 0、高于 100 kHz
+    Rate,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    // 第一次中断之后才知道信号在阈值的哪一边
+    primed: bool,
+    above: bool,
+    low: u16,
+    high: u16,
+    last_rising: Option<u32>,
+    last_falling: Option<u32>,
+    // 相邻两次 Rising 之间的时间，即一个完整的周期
+    period_us: Option<u32>,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            primed: false,
+            above: false,
+            low: 0,
+            high: ADC_MAX,
+            last_rising: None,
+            last_falling: None,
+            period_us: None,
+        }
+    }
+}
+
+static G_STATE: Mutex<Cell<State>> = Mutex::new(Cell::new(State::new()));
+
+// 配置 TIM2、TIM5 与 ADC1，GPIO 的 analog 模式需要提前设置好
+// ADC1、TIM2、TIM5 的时钟，以及 ADCPRE 也需要提前设置好
+pub(crate) fn setup(dp: &Peripherals, config: &DetectorConfig) -> Result<(), DetectorError> {
+    if config.channel > 15 {
+        return Err(DetectorError::Channel);
+    }
+    if config.threshold < config.hysteresis
+        || config.threshold as u32 + config.hysteresis as u32 > ADC_MAX as u32
+    {
+        return Err(DetectorError::Threshold);
+    }
+    if !config.tim_clk_hz.is_multiple_of(1_000_000) || !(1..=100_000).contains(&config.sample_hz) {
+        return Err(DetectorError::Rate);
+    }
+
+    cortex_m::interrupt::free(|cs| {
+        G_STATE.borrow(cs).set(State {
+            low: config.threshold - config.hysteresis,
+            high: config.threshold + config.hysteresis,
+            ..State::new()
+        })
+    });
+
+    let psc = (config.tim_clk_hz / 1_000_000 - 1) as u16;
+
+    // TIM2 以 1 MHz 计数，每个采样周期溢出一次，在 TRGO 上输出一个脉冲
+    let tim2 = &dp.TIM2;
+    tim2.cr1.modify(|_, w| w.cen().disabled());
+    tim2.psc.write(|w| w.psc().bits(psc));
+    tim2.arr
+        .write(|w| w.arr().bits(1_000_000 / config.sample_hz - 1));
+    tim2.cr2.modify(|_, w| w.mms().update());
+    tim2.egr.write(|w| w.ug().update());
+
+    // TIM5 以 1 MHz 自由计数，TIM2 的每个 TRGO 都把 CNT 捕获到 CCR1
+    let tim5 = &dp.TIM5;
+    tim5.cr1.modify(|_, w| w.cen().disabled());
+    tim5.psc.write(|w| w.psc().bits(psc));
+    tim5.arr.write(|w| w.arr().bits(u32::MAX));
+    // 只选择 TRGI 的来源，不使用任何从模式，TIM5 照常计数
+    tim5.smcr.modify(|_, w| {
+        w.ts().itr0();
+        w.sms().disabled();
+        w
+    });
+    // CC1S = 11，IC1 映射到 TRC
+    tim5.ccmr1_input()
+        .modify(|_, w| unsafe { w.cc1s().bits(0b11) });
+    tim5.ccer.modify(|_, w| w.cc1e().set_bit());
+    tim5.egr.write(|w| w.ug().update());
+    tim5.cr1.modify(|_, w| w.cen().enabled());
+
+    let adc = &dp.ADC1;
+    adc.sqr3
+        .modify(|_, w| unsafe { w.sq1().bits(config.channel) });
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+    // 采样时间为 84 个周期，阻抗较高的分压电路也能充分充电，20 kHz 下依旧绰绰有余
+    // 通道 0 ~ 9 的采样时间位于 SMPR2，10 ~ 15 位于 SMPR1，每个通道占 3 个位，0b100 为 84 个周期
+    if config.channel < 10 {
+        let shift = 3 * config.channel as u32;
+        adc.smpr2
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << shift) | 0b100 << shift) });
+    } else {
+        let shift = 3 * (config.channel - 10) as u32;
+        adc.smpr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << shift) | 0b100 << shift) });
+    }
+    adc.cr2.modify(|_, w| {
+        w.extsel().tim2trgo();
+        w.exten().rising_edge();
+        w
+    });
+
+    // 先使用 [0, 0] 的窗口，除非采样值恰好为 0，第一次采样就会触发，由此确定信号在阈值的哪一边
+    set_window(adc, 0, 0);
+    adc.cr1.modify(|_, w| unsafe {
+        w.awdch().bits(config.channel);
+        w.awdsgl().single_channel();
+        w.awden().enabled();
+        w
+    });
+    adc.cr2.modify(|_, w| w.adon().enabled());
+
+    unsafe { NVIC::unmask(interrupt::ADC) };
+
+    Ok(())
+}
+
+pub(crate) fn start(dp: &Peripherals) {
+    cortex_m::interrupt::free(|cs| {
+        let state = G_STATE.borrow(cs);
+        state.set(State {
+            low: state.get().low,
+            high: state.get().high,
+            ..State::new()
+        });
+    });
+
+    let adc = &dp.ADC1;
+    set_window(adc, 0, 0);
+    adc.sr.modify(|_, w| w.awd().clear_bit());
+    adc.cr1.modify(|_, w| w.awdie().enabled());
+
+    dp.TIM2.cnt.reset();
+    dp.TIM2.cr1.modify(|_, w| w.cen().enabled());
+}
+
+pub(crate) fn stop(dp: &Peripherals) {
+    dp.TIM2.cr1.modify(|_, w| w.cen().disabled());
+    dp.ADC1.cr1.modify(|_, w| w.awdie().disabled());
+}
+
+fn set_window(adc: &pac::adc1::RegisterBlock, low: u16, high: u16) {
+    adc.ltr.write(|w| w.lt().bits(low));
+    adc.htr.write(|w| w.ht().bits(high));
+}
+
+// 当前时刻，TIM5 的 CNT，单位 us
+pub(crate) fn now_us() -> u32 {
+    let tim5 = unsafe { &*pac::TIM5::ptr() };
+    tim5.cnt.read().bits()
+}
+
+// 在 ADC 的中断中调用，发生了一次过零时返回它
+pub(crate) fn on_adc() -> Option<Crossing> {
+    let (adc, tim5) = unsafe { (&*pac::ADC1::ptr(), &*pac::TIM5::ptr()) };
+    if adc.sr.read().awd().bit_is_clear() {
+        return None;
+    }
+
+    // 触发这次中断的采样的时刻
+    let at_us = tim5.ccr1().read().bits();
+    let value = adc.dr.read().data().bits();
+
+    let crossing = cortex_m::interrupt::free(|cs| {
+        let cell = G_STATE.borrow(cs);
+        let mut state = cell.get();
+
+        let edge = if state.primed {
+            state.above = !state.above;
+            Some(if state.above {
+                Edge::Rising
+            } else {
+                Edge::Falling
+            })
+        } else {
+            state.primed = true;
+            state.above = value > (state.low + state.high) / 2;
+            None
+        };
+
+        if state.above {
+            set_window(adc, state.low, ADC_MAX);
+        } else {
+            set_window(adc, 0, state.high);
+        }
+
+        match edge {
+            Some(Edge::Rising) => {
+                if let Some(last) = state.last_rising {
+                    state.period_us = Some(at_us.wrapping_sub(last));
+                }
+                state.last_rising = Some(at_us);
+            }
+            Some(Edge::Falling) => state.last_falling = Some(at_us),
+            None => (),
+        }
+
+        cell.set(state);
+        edge.map(|edge| Crossing { edge, at_us })
+    });
+
+    // 窗口切换之后再清除标志，否则切换前的那次转换可能再次置位 AWD
+    adc.sr.modify(|_, w| w.awd().clear_bit());
+
+    crossing
+}
+
+// 一个完整周期的时长
+pub(crate) fn period_us() -> Option<u32> {
+    cortex_m::interrupt::free(|cs| G_STATE.borrow(cs).get().period_us)
+}
+
+pub(crate) fn frequency_hz() -> Option<f32> {
+    period_us().map(|period| 1_000_000.0 / period as f32)
+}
+
+pub(crate) fn last_crossing(edge: Edge) -> Option<u32> {
+    let state = cortex_m::interrupt::free(|cs| G_STATE.borrow(cs).get());
+    match edge {
+        Edge::Rising => state.last_rising,
+        Edge::Falling => state.last_falling,
+    }
+}
+
+// 当前的相位角，以上一次 Rising 为 0°，范围 0 ~ 360°
+// 超过一个周期都没有新的 Rising 时（信号消失了），返回 None
+pub(crate) fn phase_deg() -> Option<f32> {
+    let state = cortex_m::interrupt::free(|cs| G_STATE.borrow(cs).get());
+    let period = state.period_us?;
+    let elapsed = now_us().wrapping_sub(state.last_rising?);
+    if elapsed >= period {
+        return None;
+    }
+    Some(elapsed as f32 * 360.0 / period as f32)
+}
+
+// 相位角 deg 距离上一个过零点有多少 us，deg 超过 360° 时按 360° 取余
+// 调光器在每个半周期都要触发，此时 deg 取 0 ~ 180°，并以两种边沿的过零点为起点
+pub(crate) fn delay_for_phase(deg: f32) -> Option<u32> {
+    let period = period_us()?;
+    Some((deg % 360.0 * period as f32 / 360.0) as u32)
+}
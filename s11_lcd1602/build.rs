@@ -16,7 +16,13 @@ fn main() {
 
     println!("cargo:rerun-if-changed=memory.x");
 
-    println!("cargo:rustc-link-arg=--nmagic");
+    // 链接脚本只用于 src/bin 中的程序，src/lib.rs 的测试（见其中的说明）是在 PC 上链接的
+    println!("cargo:rustc-link-arg-bins=--nmagic");
 
-    println!("cargo:rustc-link-arg=-Tlink.x");
+    println!("cargo:rustc-link-arg-bins=-Tlink.x");
+
+    // 启用了 defmt 特性时，才需要 defmt 的链接器脚本，见 s12_defmt 的 build.rs
+    if env::var_os("CARGO_FEATURE_DEFMT").is_some() {
+        println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    }
 }
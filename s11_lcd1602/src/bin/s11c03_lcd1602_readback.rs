@@ -0,0 +1,95 @@
+//! 读回 LCD1602 的 DDRAM 与 CGRAM
+//!
+//! 在 4 bit 模式下，写入字符时开启“写后校验”，并在最后把 DDRAM 和 CGRAM 整个读出来打印到 RTT
+//! 若某根数据线接触不良，RTT 上会打印出写入值与读回值，以及两者不同的数据位
+//!
+//! 接线图与 s11c02 一致，注意 RW 必须接到 A1 上，而不能直接接地，否则无法读取
+
+#![no_std]
+#![no_main]
+
+// A0/A1/A2 RS/RW/E
+// B4~B7 D4~D7
+
+use panic_rtt_target as _;
+use rtt_target::{rprint, rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    pins::{init, DataWidth, Font, LineMode, Pins},
+    readback::{dump_cgram, dump_ddram, read_u8_from_pos, DdramWriter, CGRAM_LEN, DDRAM_LEN},
+};
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+    let cp = pac::CorePeripherals::take().unwrap();
+
+    // 初始化流程，见 s11c02
+    init::<Pins<4>>(&dp, &cp, LineMode::TwoLine, Font::Font5x8);
+
+    let bus = Pins::<4>::BUS;
+
+    // 写入一个自定义字符（一个小方框）到 CGRAM 的第 0 个字符
+    bus.command(&dp, 0b0100_0000);
+    for row in [
+        0b00000, 0b11111, 0b10001, 0b10001, 0b10001, 0b11111, 0b00000, 0b00000,
+    ] {
+        bus.write_data(&dp, row);
+    }
+
+    // 开启写后校验，写入两行字符
+    let mut writer = DdramWriter::new(&bus, &dp, true);
+
+    writer.set_pos(0, 0);
+    writer.write_bytes(b"Hello, LCD1602");
+    writer.set_pos(1, 0);
+    writer.write_bytes(b"readback \x00 test");
+
+    match writer.last_mismatch() {
+        None => rprintln!("all bytes verified"),
+        Some(mismatch) => rprintln!(
+            "{} mismatch, last at 0x{:02X}: wrote 0x{:02X}, read 0x{:02X}, bad bits 0b{:08b}",
+            writer.mismatch_cnt(),
+            mismatch.addr,
+            mismatch.wrote,
+            mismatch.read,
+            mismatch.bad_bits()
+        ),
+    }
+
+    rprintln!(
+        "char at (1, 9): 0x{:02X}",
+        read_u8_from_pos(&bus, &dp, 1, 9)
+    );
+
+    let mut ddram = [0u8; DDRAM_LEN];
+    dump_ddram(&bus, &dp, &mut ddram);
+    rprintln!("DDRAM:");
+    for row in ddram.chunks_exact(DDRAM_LEN / 2) {
+        for &byte in row {
+            // 不可打印的字符用 . 代替
+            let c = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            rprint!("{}", c);
+        }
+        rprintln!("");
+    }
+
+    let mut cgram = [0u8; CGRAM_LEN];
+    dump_cgram(&bus, &dp, &mut cgram);
+    rprintln!("CGRAM char 0:");
+    for row in &cgram[..8] {
+        rprintln!("{:05b}", row);
+    }
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
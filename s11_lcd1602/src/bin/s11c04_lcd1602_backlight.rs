@@ -0,0 +1,111 @@
+//! LCD1602 背光跟随环境光调节
+//!
+//! 用一个光敏电阻和一个 10k 电阻组成分压电路，接在 PA4（ADC1_IN4）上，环境越亮，PA4 上的电压越高
+//! 每 500 ms 采样一次，把采样值换算为背光的亮度，并在 200 ms 内渐变过去，同时把亮度显示在屏幕上
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//!
+//! 背光，见 utils::backlight
+//! PA6 -> 1k 电阻 -> NPN 三极管基极，集电极接 LCD K，发射极接 GND，LCD A 接 5 V
+//!
+//! 光敏电阻
+//! 3.3 V -> 光敏电阻 -> PA4 -> 10k 电阻 -> GND
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    backlight::{Backlight, BacklightPin},
+    common::delay,
+    pins::{init, Bus, DataWidth, Font, LineMode, Pins},
+};
+
+// 环境光低于这个值时，背光保持最低亮度，不完全关闭，防止看不见屏幕
+const MIN_PERCENT: u8 = 5;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+    let cp = pac::CorePeripherals::take().unwrap();
+
+    // Backlight::new 中会开启 GPIOA 的时钟，因此要先于 setup_adc 调用
+    let mut backlight = Backlight::new(&dp, BacklightPin::Pwm);
+
+    setup_adc(&dp);
+
+    // 初始化流程，见 s11c02
+    init::<Pins<4>>(&dp, &cp, LineMode::TwoLine, Font::Font5x8);
+
+    let bus = Pins::<4>::BUS;
+
+    for &c in b"Backlight:" {
+        bus.write_data(&dp, c);
+    }
+
+    // 开机时背光从 0 渐亮到 50%
+    backlight.fade_to(&dp, &cp, 50, 1000);
+
+    loop {
+        // 12 bit 的采样值映射到 MIN_PERCENT..=100
+        let raw = read_adc(&dp);
+        let target = (MIN_PERCENT as u32 + raw as u32 * (100 - MIN_PERCENT) as u32 / 4095) as u8;
+
+        if target != backlight.percent() {
+            backlight.fade_to(&dp, &cp, target, 200);
+            show_percent(&bus, &dp, target);
+            rprintln!("adc: {:4}, backlight: {:3}%", raw, target);
+        }
+
+        delay(&cp, 500_000);
+    }
+}
+
+// 在第一行的第 11 列显示三位数的百分比
+fn show_percent(bus: &Bus, dp: &pac::Peripherals, percent: u8) {
+    bus.command(dp, 0b1000_0000 | 11);
+
+    let digits = [percent / 100, percent / 10 % 10, percent % 10];
+    for (index, &digit) in digits.iter().enumerate() {
+        // 去掉开头的 0
+        let c = if index < 2 && digits[..=index].iter().all(|&d| d == 0) {
+            b' '
+        } else {
+            b'0' + digit
+        };
+        bus.write_data(dp, c);
+    }
+    bus.write_data(dp, b'%');
+}
+
+fn setup_adc(dp: &pac::Peripherals) {
+    dp.GPIOA.moder.modify(|_, w| w.moder4().analog());
+
+    dp.RCC.apb2enr.modify(|_, w| w.adc1en().enabled());
+
+    let adc = &dp.ADC1;
+    adc.sqr3.modify(|_, w| unsafe { w.sq1().bits(4) });
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+    // 通道 4 的采样时间为 480 个周期（0b111），F401/F411 的 pac 中 SMP4 没有 cycles480()，直接写位
+    adc.smpr2
+        .modify(|r, w| unsafe { w.bits(r.bits() | 0b111 << (3 * 4)) });
+    adc.cr2.modify(|_, w| w.adon().enabled());
+}
+
+fn read_adc(dp: &pac::Peripherals) -> u16 {
+    let adc = &dp.ADC1;
+    adc.cr2.modify(|_, w| w.swstart().start());
+    while adc.sr.read().eoc().bit_is_clear() {}
+    adc.dr.read().data().bits()
+}
@@ -0,0 +1,74 @@
+//! LCD1602 上的进度条、大数字与旋转指示
+//!
+//! 部件的实现见 utils::widgets
+//!
+//! 屏幕的布局：
+//!
+//! - 第 0~10 列：两行高的三位数，显示当前的百分比
+//! - 第 0 行第 15 列：旋转指示，每一步转一帧
+//! - 第 1 行第 12~15 列：4 个字符宽的进度条，一共 20 级
+//!
+//! 百分比每 50 ms 加 1，到 100 之后停留一秒，再从 0 开始
+//!
+//! 三个部件一共占用 CGRAM 中的 7 个字符：大数字 0~3，进度条 4~5，旋转指示 6
+//!
+//! 接线图与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    common::delay,
+    pins::{init, DataWidth, Font, LineMode, Pins},
+    widgets::{BigNumber, ProgressBar, Spinner},
+};
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    // release 构建中，参数检查失败时不会 panic，而是输出到 RTT，见 assert_policy
+    assert_policy::set_hook(|failure| {
+        rprintln!(
+            "check failed: {} at {}:{}",
+            failure.condition,
+            failure.location.file(),
+            failure.location.line()
+        )
+    });
+
+    let dp = pac::Peripherals::take().unwrap();
+    let cp = pac::CorePeripherals::take().unwrap();
+
+    // 初始化流程，见 s11c02
+    init::<Pins<4>>(&dp, &cp, LineMode::TwoLine, Font::Font5x8);
+
+    let bus = Pins::<4>::BUS;
+
+    let mut number = BigNumber::new(0, 3);
+    let mut bar = ProgressBar::new(BigNumber::SLOTS, 4);
+    let mut spinner = Spinner::new(BigNumber::SLOTS + ProgressBar::SLOTS);
+
+    let mut frame = 0u32;
+
+    loop {
+        for percent in 0..=100u8 {
+            number.draw(&bus, &dp, 0, percent as u32);
+            bar.draw(&bus, &dp, 1, 12, percent);
+            spinner.draw(&bus, &dp, 0, 15, frame);
+            frame = frame.wrapping_add(1);
+
+            delay(&cp, 50_000);
+        }
+
+        rprintln!("done, restart");
+        delay(&cp, 1_000_000);
+    }
+}
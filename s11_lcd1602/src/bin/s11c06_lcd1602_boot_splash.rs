@@ -0,0 +1,109 @@
+//! 开机自检画面
+//!
+//! 自检的实现见 utils::boot，这里演示如何在程序开始时调用它
+//!
+//! 程序启动后：
+//!
+//! 1. 初始化 LCD1602
+//! 2. 运行 boot::run，屏幕上先显示检查的进度，然后依次显示两页结果
+//! 3. 把结果输出到 RTT，之后进入程序本身：第一行显示自检是否通过，第二行显示开机之后的秒数
+//!
+//! CONFIG 中列出了这里要检查的项目，按照自己板子上接的东西修改即可：
+//! 这里探测 I2C1 上的 DS3231（0x68）与 AT24C32（0x57），读取 W25Q32 的 ID，并检查 RTC 是否设置过（见 s07c02）
+//!
+//! 按下复位按钮，第一页的 RST 会显示 pin；拔掉电源再接上，会显示 power on
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//!
+//! 自检所用的 I2C 与 QSPI，见 utils::boot
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    boot::{self, BootConfig, ClockSource},
+    common::delay,
+    pins::{init, DataWidth, Font, LineMode, Pins},
+    readback::DdramWriter,
+};
+
+const CONFIG: BootConfig = BootConfig {
+    expected_clock: ClockSource::Hsi,
+    i2c_devices: &[0x68, 0x57],
+    check_qspi: true,
+    check_rtc: true,
+    page_ms: 2000,
+};
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Program Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let cp = pac::CorePeripherals::take().unwrap();
+
+    // 初始化流程，见 s11c02
+    init::<Pins<4>>(&dp, &cp, LineMode::TwoLine, Font::Font5x8);
+
+    let bus = Pins::<4>::BUS;
+
+    let report = boot::run(&bus, &dp, &cp, &CONFIG);
+    let (passed, total) = report.score();
+    rprintln!("{:?}", report);
+    rprintln!("self test: {}/{} passed", passed, total);
+
+    // 从这里开始才是程序本身
+    let mut writer = DdramWriter::new(&bus, &dp, false);
+    writer.set_pos(0, 0);
+    if report.all_ok() {
+        writer.write_bytes(b"Ready");
+    } else {
+        writer.write_bytes(b"Check failed");
+    }
+
+    let mut seconds = 0u32;
+    let mut line = [b' '; 16];
+    loop {
+        let mut cursor = Cursor {
+            buf: &mut line,
+            pos: 0,
+        };
+        write!(cursor, "Up {:>6} s", seconds).ok();
+        writer.set_pos(1, 0);
+        writer.write_bytes(&line);
+
+        delay(&cp, 1_000_000);
+        seconds += 1;
+    }
+}
+
+// 把格式化的结果写进一行的缓冲中
+struct Cursor<'a> {
+    buf: &'a mut [u8; 16],
+    pos: usize,
+}
+
+impl Write for Cursor<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &c in s.as_bytes() {
+            if self.pos < self.buf.len() {
+                self.buf[self.pos] = c;
+                self.pos += 1;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+//! LCD1602 的背光控制
+//!
+//! LCD1602 的背光是 15 号引脚 A（LED+）和 16 号引脚 K（LED-）之间的一颗 LED，
+//! 大部分模块已经在板上串好了限流电阻，直接接 5 V 时电流大约在 20 mA 上下，
+//! 这已经接近 GPIO 的极限了，因此这里通过一颗 NPN 三极管（比如 S8050）来驱动背光
+//!
+//! GPIO -> 1k 电阻 -> 三极管基极
+//! LCD K -> 三极管集电极
+//! 三极管发射极 -> GND
+//! LCD A -> 5 V
+//!
+//! 背光有两种接法：
+//! 1. 接在 PA6 上，由 TIM3_CH1（AF02）输出 PWM，可以调节亮度
+//! 2. 接在 PA3 上，作为普通的 GPIO 输出，只能开关
+//!
+//! 人眼对亮度的感知不是线性的，占空比从 10% 变为 20%，看起来的变化要比从 80% 变为 90% 大得多
+//! 因此我们用一张 gamma = 2.2 的表，把“感知亮度”的百分比转换为实际的占空比
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac;
+
+use super::common::delay;
+
+// s11 的示例都运行在默认的 HSI 上，因此 TIM3 的时钟为 16 MHz
+// PWM 的频率为 16 MHz / 16 / 1000 = 1 kHz，远高于人眼能察觉的闪烁频率
+const PWM_PSC: u16 = 16 - 1;
+const PWM_ARR: u16 = 1000 - 1;
+
+// (percent / 100) ^ 2.2 * 1000，下标为百分比
+// CCR 为 1000 时大于 ARR，PWM Mode 1 下输出会一直保持高电平，正好对应 100% 亮度
+const GAMMA_TABLE: [u16; 101] = [
+    0, 0, 0, 0, 1, 1, 2, 3, 4, 5, //
+    6, 8, 9, 11, 13, 15, 18, 20, 23, 26, //
+    29, 32, 36, 39, 43, 47, 52, 56, 61, 66, //
+    71, 76, 82, 87, 93, 99, 106, 112, 119, 126, //
+    133, 141, 148, 156, 164, 173, 181, 190, 199, 208, //
+    218, 227, 237, 247, 258, 268, 279, 290, 302, 313, //
+    325, 337, 349, 362, 375, 388, 401, 414, 428, 442, //
+    456, 471, 485, 500, 516, 531, 547, 563, 579, 595, //
+    612, 629, 646, 664, 681, 699, 718, 736, 755, 774, //
+    793, 813, 832, 852, 873, 893, 914, 935, 957, 978, //
+    1000,
+];
+
+// fade_to 中每一步的间隔
+const FADE_STEP_US: u32 = 10_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BacklightPin {
+    // PA6，TIM3_CH1
+    Pwm,
+    // PA3，普通 GPIO
+    Plain,
+}
+
+pub struct Backlight {
+    pin: BacklightPin,
+    percent: u8,
+}
+
+impl Backlight {
+    pub fn new(dp: &pac::Peripherals, pin: BacklightPin) -> Self {
+        dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+
+        match pin {
+            BacklightPin::Pwm => setup_pwm(dp),
+            BacklightPin::Plain => {
+                dp.GPIOA.odr.modify(|_, w| w.odr3().low());
+                dp.GPIOA.moder.modify(|_, w| w.moder3().output());
+            }
+        }
+
+        Self { pin, percent: 0 }
+    }
+
+    pub fn percent(&self) -> u8 {
+        self.percent
+    }
+
+    // 设置背光亮度，对于只能开关的背光，亮度大于 0 即为开
+    pub fn set_backlight(&mut self, dp: &pac::Peripherals, percent: u8) {
+        let percent = percent.min(100);
+        self.percent = percent;
+
+        match self.pin {
+            BacklightPin::Pwm => dp
+                .TIM3
+                .ccr1()
+                .write(|w| w.ccr().bits(GAMMA_TABLE[percent as usize])),
+            BacklightPin::Plain => dp.GPIOA.odr.modify(|_, w| w.odr3().bit(percent > 0)),
+        }
+    }
+
+    // 在 duration_ms 毫秒内，把亮度逐渐调节到 target
+    // 这个函数会阻塞到渐变结束，与 LCD 的其他操作共用 common::delay，因此渐变时不会同时刷新屏幕
+    pub fn fade_to(
+        &mut self,
+        dp: &pac::Peripherals,
+        cp: &pac::CorePeripherals,
+        target: u8,
+        duration_ms: u32,
+    ) {
+        let target = target.min(100);
+
+        if self.pin == BacklightPin::Plain {
+            self.set_backlight(dp, target);
+            return;
+        }
+
+        let start = self.percent as i32;
+        let steps = (duration_ms * 1000 / FADE_STEP_US).max(1) as i32;
+        let delta = target as i32 - start;
+
+        for step in 1..=steps {
+            self.set_backlight(dp, (start + delta * step / steps) as u8);
+            delay(cp, FADE_STEP_US);
+        }
+    }
+}
+
+// 将 PA6 切换到 AF02，并让 TIM3_CH1 输出 PWM，配置方法的详细说明见 s06c03
+fn setup_pwm(dp: &pac::Peripherals) {
+    dp.GPIOA.afrl.modify(|_, w| w.afrl6().af2());
+    dp.GPIOA.moder.modify(|_, w| w.moder6().alternate());
+
+    dp.RCC.apb1enr.modify(|_, w| w.tim3en().enabled());
+
+    let pwm_timer = &dp.TIM3;
+
+    pwm_timer.psc.write(|w| w.psc().bits(PWM_PSC));
+    pwm_timer.arr.write(|w| w.arr().bits(PWM_ARR));
+    pwm_timer.cr1.modify(|_, w| w.arpe().enabled());
+
+    let ccmr1_output = pwm_timer.ccmr1_output();
+    ccmr1_output.reset();
+    ccmr1_output.modify(|_, w| {
+        w.cc1s().output();
+        // CNT < CCR 时输出高电平，CCR 越大越亮
+        w.oc1m().pwm_mode1();
+        w
+    });
+
+    // 初始亮度为 0
+    pwm_timer.ccr1().write(|w| w.ccr().bits(0));
+    ccmr1_output.modify(|_, w| w.oc1pe().enabled());
+
+    // 手动产生一次更新事件，让 PSC、ARR 和 CCR1 的预载值立刻生效
+    pwm_timer.egr.write(|w| w.ug().update());
+
+    pwm_timer.ccer.modify(|_, w| w.cc1e().set_bit());
+    pwm_timer.cr1.modify(|_, w| w.cen().enabled());
+}
@@ -0,0 +1,615 @@
+//! 开机自检，并把结果显示在 LCD1602 上
+//!
+//! 每个程序开机时都先调用一次 run，快速检查几项最容易出问题的东西，再把控制权交还给程序：
+//!
+//! 1. 时钟：RCC_CFGR 的 SWS 是否为期望的时钟源，并由 PLLCFGR 算出 SYSCLK
+//! 2. 复位原因：读取 RCC_CSR 中的复位标志，判定方式与 s07c04 相同，读取之后清除
+//! 3. I2C：在 I2C1（PB8/PB9）上逐个探测 BootConfig 中列出的地址，有 ACK 就认为设备存在
+//! 4. QSPI Flash：用 single mode 读取 JEDEC ID（0x9F），流程见 s19c01，读到全 0 或全 1 说明没有接 Flash
+//! 5. RTC：RTC 是否已经启用（RCC_BDCR 的 RTCEN），日历是否已经设置过（RTC_ISR 的 INITS）
+//!
+//! 哪些项目需要检查，由 BootConfig 决定，不检查的项目在结果中显示为 skip
+//! 所选的芯片没有 QUADSPI 时（F401、F411，见 chip_caps），QSPI Flash 的检查总是 skip
+//!
+//! 检查进行时，屏幕第一行显示进度与旋转指示，第二行是进度条（见 utils::widgets）
+//! 检查完成后，依次显示两页结果，每页停留 page_ms 毫秒：
+//!
+//! 第一页：CLK HSI 16MHz ok    第二页：I2C 2/3 miss 3C
+//!         RST power on                QSPI EF4016 RTC ok
+//!
+//! 最后清屏，CGRAM 也可以被程序重新使用
+//!
+//! 这里只做检查与显示，不会修复任何问题；检查所用的外设（I2C1、QUADSPI）在结束时都会复位，不会影响程序之后的配置
+//!
+//! 接线图：
+//!
+//! PB8  (AF4)  -> I2C1_SCL，需要上拉
+//! PB9  (AF4)  -> I2C1_SDA，需要上拉
+//! PB1  (AF9)  -> W25Qxx CLK
+//! PC9  (AF9)  -> W25Qxx DI IO0
+//! PC10 (AF9)  -> W25Qxx DO IO1
+//! PB6  (AF10) -> W25Qxx /CS
+//!
+//! single mode 用不到 IO2、IO3，因此 PA1（BK1_IO3）依旧可以作为 LCD1602 的 RW
+
+#![allow(dead_code)]
+
+use core::fmt::{self, Write};
+
+use chip_caps::pinmap::Binding;
+use stm32f4xx_hal::pac;
+
+use super::{
+    common::delay,
+    pins::Bus,
+    readback::{ddram_addr, CMD_SET_DDRAM_ADDR},
+    widgets::{ProgressBar, Spinner},
+};
+
+// LCD1602 一行的字符数
+const COLUMNS: usize = 16;
+
+// 开发板上的 HSE 为 12 MHz
+pub const HSE_HZ: u32 = 12_000_000;
+pub const HSI_HZ: u32 = 16_000_000;
+
+// I2C 与 QSPI 的轮询上限，16 MHz 下约几毫秒，超时说明总线或外设没有响应
+const SPIN_LIMIT: u32 = 50_000;
+
+const I2C1_SCL: Binding = chip_caps::bind!(I2C1_SCL => PB8);
+const I2C1_SDA: Binding = chip_caps::bind!(I2C1_SDA => PB9);
+
+// 检查之间的停顿，让进度条看得见
+const STEP_US: u32 = 150_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockSource {
+    Hsi,
+    Hse,
+    Pll,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetCause {
+    PowerOn,
+    BrownOut,
+    Watchdog,
+    LowPower,
+    Software,
+    Pin,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RtcState {
+    // RTC 没有启用，比如备份域刚上电
+    Disabled,
+    // RTC 在运行，但日历从来没有设置过
+    NotSet,
+    Valid,
+}
+
+pub struct BootConfig {
+    pub expected_clock: ClockSource,
+    // 需要探测的 7 位 I2C 地址，为空时不检查 I2C
+    pub i2c_devices: &'static [u8],
+    pub check_qspi: bool,
+    pub check_rtc: bool,
+    // 每一页结果停留的时间，不超过 10 秒
+    pub page_ms: u32,
+}
+
+impl BootConfig {
+    // s11 的示例都运行在默认的 HSI 上，也没有接其他设备
+    pub const DEFAULT: Self = Self {
+        expected_clock: ClockSource::Hsi,
+        i2c_devices: &[],
+        check_qspi: false,
+        check_rtc: false,
+        page_ms: 1500,
+    };
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct I2cResult {
+    pub found: u8,
+    pub expected: u8,
+    // 第一个没有响应的地址
+    pub first_missing: Option<u8>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Report {
+    pub clock: ClockSource,
+    pub clock_ok: bool,
+    pub sysclk_hz: u32,
+    pub reset: ResetCause,
+    // 不检查的项目为 None
+    pub i2c: Option<I2cResult>,
+    // 检查了但是没有读到 Flash 时为 Some(None)
+    pub qspi_id: Option<Option<u32>>,
+    pub rtc: Option<RtcState>,
+}
+
+impl Report {
+    // (通过的项目数, 检查的项目数)，复位原因只是信息，不计入
+    pub fn score(&self) -> (u8, u8) {
+        let checks = [
+            Some(self.clock_ok),
+            self.i2c.map(|i2c| i2c.found == i2c.expected),
+            self.qspi_id.map(|id| id.is_some()),
+            self.rtc.map(|rtc| rtc == RtcState::Valid),
+        ];
+        let total = checks.iter().flatten().count() as u8;
+        let passed = checks.iter().flatten().filter(|&&ok| ok).count() as u8;
+        (passed, total)
+    }
+
+    pub fn all_ok(&self) -> bool {
+        let (passed, total) = self.score();
+        passed == total
+    }
+}
+
+// 运行全部检查，在屏幕上显示结果，返回结果供程序进一步处理（比如输出到 RTT）
+// LCD1602 需要已经 init 过，运行时会使用 CGRAM 的 0 ~ 2 号字符
+pub fn run(
+    bus: &Bus,
+    dp: &pac::Peripherals,
+    cp: &pac::CorePeripherals,
+    config: &BootConfig,
+) -> Report {
+    let mut screen = Screen::new(bus, dp);
+    let check_qspi = config.check_qspi && chip_caps::has::QUADSPI;
+    let steps =
+        3 + check_qspi as u8 + config.check_rtc as u8 + !config.i2c_devices.is_empty() as u8;
+    let mut step = 0u8;
+    let mut progress = |screen: &mut Screen, name: &str| {
+        step += 1;
+        screen.progress(step, steps, name);
+        delay(cp, STEP_US);
+    };
+
+    progress(&mut screen, "clock");
+    let (clock, sysclk_hz) = read_clock();
+    let clock_ok = clock == config.expected_clock;
+
+    progress(&mut screen, "reset");
+    let reset = read_reset_cause(dp);
+
+    let i2c = if config.i2c_devices.is_empty() {
+        None
+    } else {
+        progress(&mut screen, "i2c");
+        Some(probe_i2c(dp, sysclk_hz, config.i2c_devices))
+    };
+
+    let qspi_id = if check_qspi {
+        progress(&mut screen, "qspi");
+        Some(read_qspi_id(dp))
+    } else {
+        None
+    };
+
+    let rtc = if config.check_rtc {
+        progress(&mut screen, "rtc");
+        Some(read_rtc(dp))
+    } else {
+        None
+    };
+
+    let report = Report {
+        clock,
+        clock_ok,
+        sysclk_hz,
+        reset,
+        i2c,
+        qspi_id,
+        rtc,
+    };
+
+    progress(&mut screen, if report.all_ok() { "pass" } else { "FAIL" });
+
+    let page_us = config.page_ms.min(10_000) * 1000;
+    screen.page_system(&report);
+    delay(cp, page_us);
+    screen.page_peripherals(&report);
+    delay(cp, page_us);
+    screen.clear();
+
+    report
+}
+
+fn read_clock() -> (ClockSource, u32) {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let cfgr = rcc.cfgr.read();
+
+    let source = if cfgr.sws().is_hse() {
+        ClockSource::Hse
+    } else if cfgr.sws().is_pll() {
+        ClockSource::Pll
+    } else {
+        ClockSource::Hsi
+    };
+
+    let sysclk_hz = match source {
+        ClockSource::Hsi => HSI_HZ,
+        ClockSource::Hse => HSE_HZ,
+        ClockSource::Pll => {
+            let pllcfgr = rcc.pllcfgr.read();
+            let input = if pllcfgr.pllsrc().is_hse() {
+                HSE_HZ
+            } else {
+                HSI_HZ
+            };
+            let m = pllcfgr.pllm().bits() as u32;
+            let n = pllcfgr.plln().bits() as u32;
+            // PLLP 为 0b00 ~ 0b11，对应 2、4、6、8 分频
+            let p = (pllcfgr.pllp().bits() as u32 + 1) * 2;
+            (input / m.max(1)) * n / p
+        }
+    };
+
+    (source, sysclk_hz)
+}
+
+// 复位标志可能同时置位多个，只取优先级最高的那一个，读取之后清除，否则下一次复位时无法区分
+fn read_reset_cause(dp: &pac::Peripherals) -> ResetCause {
+    let csr = dp.RCC.csr.read();
+    let cause = if csr.borrstf().bit_is_set() && csr.porrstf().bit_is_set() {
+        ResetCause::PowerOn
+    } else if csr.borrstf().bit_is_set() {
+        ResetCause::BrownOut
+    } else if csr.wdgrstf().bit_is_set() || csr.wwdgrstf().bit_is_set() {
+        ResetCause::Watchdog
+    } else if csr.lpwrrstf().bit_is_set() {
+        ResetCause::LowPower
+    } else if csr.sftrstf().bit_is_set() {
+        ResetCause::Software
+    } else {
+        ResetCause::Pin
+    };
+    dp.RCC.csr.modify(|_, w| w.rmvf().set_bit());
+    cause
+}
+
+fn probe_i2c(dp: &pac::Peripherals, sysclk_hz: u32, addresses: &[u8]) -> I2cResult {
+    setup_i2c(dp, sysclk_hz);
+
+    let mut result = I2cResult {
+        found: 0,
+        expected: addresses.len() as u8,
+        first_missing: None,
+    };
+    for &addr in addresses {
+        if i2c_ack(&dp.I2C1, addr) {
+            result.found += 1;
+        } else if result.first_missing.is_none() {
+            result.first_missing = Some(addr);
+        }
+    }
+
+    // 复位 I2C1，把 PB8、PB9 恢复为输入
+    dp.RCC.apb1rstr.modify(|_, w| w.i2c1rst().set_bit());
+    dp.RCC.apb1rstr.modify(|_, w| w.i2c1rst().clear_bit());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().disabled());
+    dp.GPIOB.moder.modify(|_, w| {
+        w.moder8().input();
+        w.moder9().input();
+        w
+    });
+
+    result
+}
+
+// 100 kHz 标准模式，APB1 不分频时 PCLK1 即为 SYSCLK，这里只考虑 PCLK1 不超过 50 MHz 的情况
+fn setup_i2c(dp: &pac::Peripherals, sysclk_hz: u32) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().bits(I2C1_SCL.af);
+        w.afrh9().bits(I2C1_SDA.af);
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let pclk1_mhz = (sysclk_hz / 1_000_000).clamp(2, 50);
+    let i2c = &dp.I2C1;
+    i2c.cr1.modify(|_, w| w.pe().disabled());
+    i2c.cr2
+        .modify(|_, w| unsafe { w.freq().bits(pclk1_mhz as u8) });
+    // 标准模式下 SCL 的高、低电平各为 CCR 个 PCLK1 周期
+    i2c.ccr
+        .modify(|_, w| unsafe { w.ccr().bits((pclk1_mhz * 5) as u16) });
+    i2c.trise.write(|w| w.trise().bits(pclk1_mhz as u8 + 1));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
+
+// 发送 START 与地址（写方向），返回是否收到 ACK，最后总是发送 STOP
+fn i2c_ack(i2c: &pac::I2C1, addr: u8) -> bool {
+    i2c.cr1.modify(|_, w| w.start().start());
+    if !spin_until(|| i2c.sr1.read().sb().bit_is_set()) {
+        i2c.cr1.modify(|_, w| w.stop().stop());
+        return false;
+    }
+
+    i2c.dr.write(|w| w.dr().bits(addr << 1));
+    let acked = spin_until(|| {
+        let sr1 = i2c.sr1.read();
+        sr1.addr().bit_is_set() || sr1.af().bit_is_set()
+    }) && i2c.sr1.read().addr().bit_is_set();
+
+    if acked {
+        // 读 SR1 之后再读 SR2，清除 ADDR
+        i2c.sr2.read();
+    } else {
+        i2c.sr1.modify(|_, w| w.af().clear_bit());
+    }
+    i2c.cr1.modify(|_, w| w.stop().stop());
+    spin_until(|| i2c.cr1.read().stop().bit_is_clear());
+
+    acked
+}
+
+// 与 s19c01 相同，只是只配置 single mode 需要的 4 个引脚，并且所有的等待都有上限
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn read_qspi_id(dp: &pac::Peripherals) -> Option<u32> {
+    const QSPI_CLK: Binding = chip_caps::bind!(QUADSPI_CLK => PB1);
+    const QSPI_NCS: Binding = chip_caps::bind!(QUADSPI_BK1_NCS => PB6);
+    const QSPI_IO0: Binding = chip_caps::bind!(QUADSPI_BK1_IO0 => PC9);
+    const QSPI_IO1: Binding = chip_caps::bind!(QUADSPI_BK1_IO1 => PC10);
+
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioben().enabled();
+        w.gpiocen().enabled();
+        w
+    });
+    dp.GPIOB.afrl.modify(|_, w| {
+        w.afrl1().bits(QSPI_CLK.af);
+        w.afrl6().bits(QSPI_NCS.af);
+        w
+    });
+    dp.GPIOB.moder.modify(|_, w| {
+        w.moder1().alternate();
+        w.moder6().alternate();
+        w
+    });
+    dp.GPIOC.afrh.modify(|_, w| {
+        w.afrh9().bits(QSPI_IO0.af);
+        w.afrh10().bits(QSPI_IO1.af);
+        w
+    });
+    dp.GPIOC.moder.modify(|_, w| {
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let rcc = &dp.RCC;
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().reset());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().clear_bit());
+    rcc.ahb3enr.modify(|_, w| w.qspien().enabled());
+
+    let qspi = &dp.QUADSPI;
+    // 只读 ID，容量随便给一个，时钟 HCLK / 4
+    qspi.dcr.write(|w| unsafe { w.fsize().bits(21) });
+    qspi.cr.write(|w| unsafe {
+        w.prescaler().bits(3);
+        w.en().set_bit();
+        w
+    });
+
+    let mut id = 0;
+    let idle = spin_until(|| qspi.sr.read().busy().bit_is_clear());
+    if idle {
+        qspi.dlr.write(|w| unsafe { w.dl().bits(3 - 1) });
+        qspi.ccr.write(|w| unsafe {
+            w.fmode().bits(0b01);
+            w.dmode().bits(0b01);
+            w.imode().bits(0b01);
+            w.instruction().bits(0x9F);
+            w
+        });
+        // 3 个字节到齐之后 FIFO 中就有数据了
+        if spin_until(|| qspi.sr.read().ftf().bit_is_set() || qspi.sr.read().tcf().bit_is_set()) {
+            id = qspi.dr.read().data().bits() & 0x00FF_FFFF;
+        }
+    }
+
+    // 复位 QUADSPI，引脚恢复为输入
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().reset());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().clear_bit());
+    rcc.ahb3enr.modify(|_, w| w.qspien().disabled());
+    dp.GPIOB.moder.modify(|_, w| {
+        w.moder1().input();
+        w.moder6().input();
+        w
+    });
+    dp.GPIOC.moder.modify(|_, w| {
+        w.moder9().input();
+        w.moder10().input();
+        w
+    });
+
+    // DR 中低字节是最先收到的厂商 ID，这里转换为 厂商 类型 容量 的顺序，与手册中的写法一致
+    let id = ((id & 0xFF) << 16) | (id & 0xFF00) | ((id >> 16) & 0xFF);
+    (id != 0 && id != 0x00FF_FFFF).then_some(id)
+}
+
+// pac 中没有 QUADSPI，run 也不会调用它
+#[cfg(not(any(feature = "stm32f412", feature = "stm32f413")))]
+fn read_qspi_id(_dp: &pac::Peripherals) -> Option<u32> {
+    None
+}
+
+// 只读取，不需要打开备份域的写权限
+fn read_rtc(dp: &pac::Peripherals) -> RtcState {
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    if dp.RCC.bdcr.read().rtcen().bit_is_clear() {
+        return RtcState::Disabled;
+    }
+    if dp.RTC.isr.read().inits().is_not_initalized() {
+        return RtcState::NotSet;
+    }
+    RtcState::Valid
+}
+
+fn spin_until(mut f: impl FnMut() -> bool) -> bool {
+    (0..SPIN_LIMIT).any(|_| f())
+}
+
+// 一行文字的缓冲，超出 16 个字符的部分被截掉，不足的部分补空格
+struct Line {
+    buf: [u8; COLUMNS],
+    len: usize,
+}
+
+impl Line {
+    fn new() -> Self {
+        Self {
+            buf: [b' '; COLUMNS],
+            len: 0,
+        }
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &c in s.as_bytes() {
+            if self.len < COLUMNS {
+                self.buf[self.len] = c;
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Screen<'a> {
+    bus: &'a Bus,
+    dp: &'a pac::Peripherals,
+    bar: ProgressBar,
+    spinner: Spinner,
+}
+
+impl<'a> Screen<'a> {
+    fn new(bus: &'a Bus, dp: &'a pac::Peripherals) -> Self {
+        Self {
+            bus,
+            dp,
+            bar: ProgressBar::new(0, COLUMNS as u8),
+            spinner: Spinner::new(ProgressBar::SLOTS),
+        }
+    }
+
+    fn write_line(&self, row: u8, line: &Line) {
+        self.bus
+            .command(self.dp, CMD_SET_DDRAM_ADDR | ddram_addr(row, 0));
+        for &c in line.buf.iter() {
+            self.bus.write_data(self.dp, c);
+        }
+    }
+
+    // 第一行：POST 2/5 i2c，最后一列是旋转指示；第二行：进度条
+    fn progress(&mut self, step: u8, steps: u8, name: &str) {
+        let mut line = Line::new();
+        write!(line, "POST {}/{} {}", step, steps, name).ok();
+        self.write_line(0, &line);
+        self.spinner
+            .draw(self.bus, self.dp, 0, COLUMNS as u8 - 1, step as u32);
+        let percent = (step as u32 * 100 / steps.max(1) as u32) as u8;
+        self.bar.draw(self.bus, self.dp, 1, 0, percent);
+    }
+
+    fn page_system(&self, report: &Report) {
+        let mut line = Line::new();
+        let source = match report.clock {
+            ClockSource::Hsi => "HSI",
+            ClockSource::Hse => "HSE",
+            ClockSource::Pll => "PLL",
+        };
+        write!(
+            line,
+            "CLK {} {}MHz {}",
+            source,
+            report.sysclk_hz / 1_000_000,
+            if report.clock_ok { "ok" } else { "!!" }
+        )
+        .ok();
+        self.write_line(0, &line);
+
+        let mut line = Line::new();
+        let reset = match report.reset {
+            ResetCause::PowerOn => "power on",
+            ResetCause::BrownOut => "brown out",
+            ResetCause::Watchdog => "watchdog",
+            ResetCause::LowPower => "low power",
+            ResetCause::Software => "software",
+            ResetCause::Pin => "pin",
+        };
+        write!(line, "RST {}", reset).ok();
+        self.write_line(1, &line);
+    }
+
+    fn page_peripherals(&self, report: &Report) {
+        let mut line = Line::new();
+        match report.i2c {
+            None => write!(line, "I2C skip"),
+            Some(I2cResult {
+                found,
+                expected,
+                first_missing: Some(addr),
+            }) => write!(line, "I2C {}/{} miss {:02X}", found, expected, addr),
+            Some(I2cResult {
+                found, expected, ..
+            }) => write!(line, "I2C {}/{} ok", found, expected),
+        }
+        .ok();
+        self.write_line(0, &line);
+
+        let mut line = Line::new();
+        match report.qspi_id {
+            None => write!(line, "QSPI skip"),
+            Some(None) => write!(line, "QSPI none"),
+            Some(Some(id)) => write!(line, "QSPI {:06X}", id),
+        }
+        .ok();
+        match report.rtc {
+            None => Ok(()),
+            Some(RtcState::Disabled) => write!(line, " RTC off"),
+            Some(RtcState::NotSet) => write!(line, " RTC ??"),
+            Some(RtcState::Valid) => write!(line, " RTC ok"),
+        }
+        .ok();
+        self.write_line(1, &line);
+    }
+
+    fn clear(&self) {
+        self.bus.command(self.dp, 0b0000_0001);
+    }
+}
@@ -1,3 +1,7 @@
-pub(crate) mod common;
-pub(crate) mod mode_4pin;
-pub(crate) mod mode_8pin;
+pub(crate) mod backlight;
+pub(crate) mod boot;
+
+// LCD1602 的驱动与 s21 共用，代码在工作区中单独的 crate 里，见 lcd1602 的 src/lib.rs
+// 不是每个程序都会用到，因此允许未使用
+#[allow(unused_imports)]
+pub(crate) use lcd1602::{common, mode_4pin, mode_8pin, pins, readback, widgets};
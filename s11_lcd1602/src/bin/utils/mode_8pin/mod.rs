@@ -1,2 +0,0 @@
-pub(crate) mod send;
-pub(crate) mod setup;
@@ -0,0 +1,117 @@
+//! 把 defmt 日志保存在内部 Flash 中，死机之后再取出来
+//!
+//! logger 见 utils::flash_log，它代替了 s12c01 中的 defmt-rtt，日志依旧从 RTT 输出，同时会被保存在 Flash 的扇区 8 与 9 中
+//!
+//! 程序的流程：
+//!
+//! 1. 上电时若按住了 PA0 上的按键，则把 Flash 中保存的日志从 RTT 重新输出一遍，之后松开按键即清空日志，再次按下则保留日志
+//! 2. 否则每秒打印一条日志，并调用一次 defmt::flush()，把暂存的日志写入 Flash
+//! 3. 第 10 条日志之后主动 panic，panic 的信息同样会被写入 Flash，之后停在原地
+//!
+//! 因此可以先正常运行一次，等 panic 之后断开调试器，再按住按键复位，就能看到上一次运行直到 panic 为止的全部日志
+//!
+//! Host 端的操作与 s12c01 相同，由 defmt-print 解码：
+//! `defmt-print -e <ELF 文件路径> tcp --port 8888`
+//! 注意 dump 出的日志只能用生成它的 ELF 文件解码，重新编译之后，之前保存的日志就无法解码了
+//!
+//! 另外，也可以不经过 MCU，直接用 OpenOCD 把两个扇区读出来，比如在 OpenOCD 的 telnet 中执行
+//! `dump_image flash_log.bin 0x08080000 0x40000`，格式见 utils::flash_log
+//!
+//! 接线图：
+//!
+//! PA0 <-> 按键 <-> 3.3V（PA0 内部下拉，按下为高电平）
+
+#![no_std]
+#![no_main]
+
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::flash_log;
+
+const PANIC_AFTER: u32 = 10;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
+
+    flash_log::init();
+    setup_button(&dp);
+
+    if button_pressed(&dp) {
+        defmt::info!("dumping {} bytes of stored log", flash_log::stored_len());
+        flash_log::dump();
+        defmt::info!("dump done, release the button to erase, press again to keep");
+
+        while button_pressed(&dp) {}
+        // 松开之后给 2 秒的时间决定是否保留
+        cortex_m::asm::delay(16_000_000 * 2);
+        if button_pressed(&dp) {
+            defmt::info!("log kept");
+        } else {
+            flash_log::erase();
+            defmt::info!("log erased");
+        }
+        #[allow(clippy::empty_loop)]
+        loop {}
+    }
+
+    // SysTick 使用 16 MHz 的 HSI，每秒溢出一次
+    let mut syst = cp.SYST;
+    syst.set_clock_source(cortex_m::peripheral::syst::SystClkSource::Core);
+    syst.set_reload(16_000_000 - 1);
+    syst.clear_current();
+    syst.enable_counter();
+
+    defmt::info!("boot, {} bytes of log stored", flash_log::stored_len());
+    defmt::flush();
+
+    let mut count = 0;
+    loop {
+        while !syst.has_wrapped() {}
+
+        count += 1;
+        defmt::info!("tick {}", count);
+        let dropped = flash_log::take_dropped();
+        if dropped > 0 {
+            defmt::warn!("{} frame(s) dropped before reaching flash", dropped);
+        }
+        defmt::flush();
+
+        if count == PANIC_AFTER {
+            defmt::panic!("something went wrong after {} ticks", count);
+        }
+    }
+}
+
+// 先把 panic 信息交给 logger，再写入 Flash
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    defmt::error!("{}", defmt::Display2Format(info));
+    defmt::flush();
+    loop {}
+}
+
+// HardFault 时同样把日志写入 Flash
+#[cortex_m_rt::exception]
+unsafe fn HardFault(frame: &cortex_m_rt::ExceptionFrame) -> ! {
+    defmt::error!("HardFault at PC {=u32:#010x}", frame.pc());
+    defmt::flush();
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+// PA0 内部下拉输入
+fn setup_button(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.GPIOA.pupdr.modify(|_, w| w.pupdr0().pull_down());
+    dp.GPIOA.moder.modify(|_, w| w.moder0().input());
+    // 等待下拉生效
+    cortex_m::asm::delay(1000);
+}
+
+fn button_pressed(dp: &pac::Peripherals) -> bool {
+    dp.GPIOA.idr.read().idr0().is_high()
+}
@@ -0,0 +1,463 @@
+//! 同时输出到 RTT 与内部 Flash 的 defmt global logger
+//!
+//! defmt-rtt 只能在调试器连着的时候看到日志，设备死机、或者从现场拿回来之后，之前发生了什么就不知道了
+//! 这里自己实现一个 global logger，编码之后的 defmt 帧除了写入 RTT，还会暂存在 RAM 中，
+//! 调用 defmt::flush() 时，再把暂存的帧追加到内部 Flash 中的一块环形区域里，之后可以用 dump 重新从 RTT 输出，交给 defmt-print 解码
+//!
+//! 注意：使用这个 logger 时，不能再 use defmt_rtt as _，一个程序只能有一个 global logger
+//!
+//! Flash 的布局：
+//!
+//! 使用扇区 8 与 9（0x0808_0000 开始的两个 128 KB 扇区），memory.x 只给程序分配了前 512 KB，烧录程序不会覆盖这里
+//! 每个扇区的开头是 8 字节的头：MAGIC 与一个递增的序号，序号大的扇区是正在写入的扇区，另一个扇区保存的是更早的日志
+//! 头之后是一条一条的记录：2 字节的长度（小端）加上若干完整的 defmt 帧，长度为 0xFFFF（擦除之后的值）表示后面没有记录了
+//! 当前扇区写满之后，擦除另一个扇区并切换过去，因此最多会丢掉最早的 128 KB 日志，Flash 的擦写次数也只是每写满一个扇区一次
+//!
+//! 为什么不在每一帧结束时直接写入 Flash：
+//!
+//! 1. logger 工作时中断是关闭的，写入 Flash 每个字节要等十几微秒，擦除一个扇区则要 1~2 秒
+//! 2. 每条记录都有 2 字节的长度，帧越零碎，浪费的空间越多
+//!
+//! 因此由程序自己决定什么时候调用 defmt::flush()，比如主循环中每隔一段时间一次，以及 panic 与 HardFault 中
+//! 暂存区满了之后，新的帧会被丢弃（而不是覆盖旧的帧），丢弃的帧数可以通过 take_dropped 取得，由程序自己决定如何报告
+//!
+//! 暂存区与 Flash 中都只保存完整的帧，因此 dump 出的字节流与 RTT 上的字节流格式完全相同，
+//! 可以直接用 defmt-print 解码，见 s12c02
+
+#![allow(dead_code)]
+
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use critical_section::{CriticalSection, Mutex, RestoreState};
+use rtt_target::{ChannelMode, UpChannel};
+use stm32f4xx_hal::pac;
+
+const FLASH_BASE: u32 = 0x0800_0000;
+// 扇区 8 与扇区 9
+const SECTORS: [(u32, u8); 2] = [(0x8_0000, 8), (0xA_0000, 9)];
+const SECTOR_SIZE: u32 = 128 * 1024;
+
+// "DFLG"
+const MAGIC: u32 = 0x4746_4C44;
+const HEADER_LEN: u32 = 8;
+const EMPTY_RECORD: u16 = 0xFFFF;
+
+// RAM 中暂存区的大小，也是一条记录的最大长度
+const STAGE_LEN: usize = 1024;
+
+// 日志在 Flash 中的写入位置
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    // SECTORS 中的序号
+    sector: usize,
+    // 相对于扇区开头的偏移
+    offset: u32,
+    sequence: u32,
+}
+
+// 暂存区，只保存完整的帧
+struct Stage {
+    buf: [u8; STAGE_LEN],
+    // 完整的帧的末尾
+    committed: usize,
+    // 包括正在写入的帧的末尾
+    len: usize,
+    // 正在写入的帧放不下了
+    overflow: bool,
+    dropped: u32,
+}
+
+impl Stage {
+    const fn new() -> Self {
+        Self {
+            buf: [0; STAGE_LEN],
+            committed: 0,
+            len: 0,
+            overflow: false,
+            dropped: 0,
+        }
+    }
+
+    fn begin_frame(&mut self) {
+        self.len = self.committed;
+        self.overflow = false;
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        if self.overflow {
+            return;
+        }
+        let end = self.len + bytes.len();
+        if end > STAGE_LEN {
+            self.overflow = true;
+            return;
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+    }
+
+    fn end_frame(&mut self) {
+        if self.overflow {
+            self.dropped += 1;
+            self.len = self.committed;
+        } else {
+            self.committed = self.len;
+        }
+    }
+}
+
+// 编码之后的字节的去向
+struct Output {
+    rtt: Option<UpChannel>,
+    stage: Stage,
+}
+
+impl Output {
+    fn write(&mut self, bytes: &[u8]) {
+        if let Some(rtt) = self.rtt.as_mut() {
+            rtt.write(bytes);
+        }
+        self.stage.push(bytes);
+    }
+}
+
+struct State {
+    encoder: defmt::Encoder,
+    output: Output,
+    // init 之前为 None，此时的日志只会暂存在 RAM 中
+    position: Option<Position>,
+}
+
+static G_STATE: Mutex<RefCell<State>> = Mutex::new(RefCell::new(State {
+    encoder: defmt::Encoder::new(),
+    output: Output {
+        rtt: None,
+        stage: Stage::new(),
+    },
+    position: None,
+}));
+
+// defmt 要求 logger 不能重入，acquire 与 release 之间一直处于临界区中
+static TAKEN: AtomicBool = AtomicBool::new(false);
+static mut RESTORE: RestoreState = RestoreState::invalid();
+
+#[defmt::global_logger]
+struct FlashLogger;
+
+unsafe impl defmt::Logger for FlashLogger {
+    fn acquire() {
+        let restore = unsafe { critical_section::acquire() };
+        if TAKEN.load(Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly");
+        }
+        TAKEN.store(true, Ordering::Relaxed);
+        unsafe { RESTORE = restore };
+
+        with_state(|state| {
+            state.output.stage.begin_frame();
+            let output = &mut state.output;
+            state.encoder.start_frame(|bytes| output.write(bytes));
+        });
+    }
+
+    // defmt::flush() 会先 acquire，再调用这里，因此这里同样处于临界区中
+    unsafe fn flush() {
+        with_state(persist);
+    }
+
+    unsafe fn release() {
+        with_state(|state| {
+            let output = &mut state.output;
+            state.encoder.end_frame(|bytes| output.write(bytes));
+            state.output.stage.end_frame();
+        });
+
+        TAKEN.store(false, Ordering::Relaxed);
+        let restore = unsafe { RESTORE };
+        unsafe { critical_section::release(restore) };
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        with_state(|state| {
+            let output = &mut state.output;
+            state.encoder.write(bytes, |bytes| output.write(bytes));
+        });
+    }
+}
+
+// 只能在 acquire 与 release 之间，或者 critical_section::with 中调用
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    let cs = unsafe { CriticalSection::new() };
+    f(&mut G_STATE.borrow_ref_mut(cs))
+}
+
+// 启用 RTT，并找到 Flash 中日志的末尾，若两个扇区都没有日志，则擦除扇区 8 作为第一个扇区
+// 需要在第一条日志之前调用，之前的日志不会出现在 RTT 上，但仍然会被写入 Flash
+pub(crate) fn init() {
+    let channels = rtt_target::rtt_init! {
+        up: {
+            0: {
+                size: 1024,
+                name: "defmt"
+            }
+        }
+    };
+
+    let position = find_position().unwrap_or_else(|| {
+        erase_sector(0);
+        write_header(0, 1);
+        Position {
+            sector: 0,
+            offset: HEADER_LEN,
+            sequence: 1,
+        }
+    });
+
+    critical_section::with(|cs| {
+        let mut state = G_STATE.borrow_ref_mut(cs);
+        state.output.rtt = Some(channels.up.0);
+        state.position = Some(position);
+    });
+}
+
+// Flash 中日志的字节数，不包括扇区头与记录的长度
+pub(crate) fn stored_len() -> u32 {
+    let mut len = 0;
+    for_each_record(|_, record_len| len += record_len as u32);
+    len
+}
+
+// 按照从旧到新的顺序，把 Flash 中的日志原样写入 RTT，交给 defmt-print 解码
+// RTT 的缓冲写满之后会等待 Host 读走，dump 期间不要在中断中打印日志，否则可能与 dump 的内容交错在一起
+pub(crate) fn dump() {
+    for_each_record(|addr, len| {
+        let mut rest = unsafe { core::slice::from_raw_parts(addr as *const u8, len as usize) };
+        // RTT 的缓冲可能放不下一整条记录，能写多少写多少，剩下的等 Host 读走之后再写
+        while !rest.is_empty() {
+            let written = critical_section::with(|cs| {
+                let mut state = G_STATE.borrow_ref_mut(cs);
+                match state.output.rtt.as_mut() {
+                    Some(rtt) => {
+                        rtt.set_mode(ChannelMode::NoBlockTrim);
+                        let written = rtt.write(rest);
+                        rtt.set_mode(ChannelMode::NoBlockSkip);
+                        written
+                    }
+                    None => rest.len(),
+                }
+            });
+            rest = &rest[written..];
+        }
+    });
+}
+
+// 取得并清零暂存区满了之后被丢弃的帧数
+pub(crate) fn take_dropped() -> u32 {
+    critical_section::with(|cs| {
+        let mut state = G_STATE.borrow_ref_mut(cs);
+        core::mem::take(&mut state.output.stage.dropped)
+    })
+}
+
+// 清空 Flash 中的日志，需要 1~2 秒
+pub(crate) fn erase() {
+    critical_section::with(|cs| {
+        let mut state = G_STATE.borrow_ref_mut(cs);
+        let sequence = state.position.map_or(0, |p| p.sequence) + 1;
+        erase_sector(1);
+        erase_sector(0);
+        write_header(0, sequence);
+        state.position = Some(Position {
+            sector: 0,
+            offset: HEADER_LEN,
+            sequence,
+        });
+    });
+}
+
+// 把暂存区中完整的帧写入 Flash
+fn persist(state: &mut State) {
+    let mut position = match state.position {
+        Some(position) => position,
+        None => return,
+    };
+    let stage = &mut state.output.stage;
+    if stage.committed == 0 {
+        return;
+    }
+
+    let len = stage.committed as u32;
+    if position.offset + 2 + len > SECTOR_SIZE {
+        let next = 1 - position.sector;
+        erase_sector(next);
+        position = Position {
+            sector: next,
+            offset: HEADER_LEN,
+            sequence: position.sequence + 1,
+        };
+        write_header(next, position.sequence);
+    }
+
+    let addr = SECTORS[position.sector].0 + position.offset;
+    program(addr, &(len as u16).to_le_bytes());
+    program(addr + 2, &stage.buf[..stage.committed]);
+    position.offset += 2 + len;
+    state.position = Some(position);
+
+    // 正在写入的帧（如果有的话）移到暂存区的开头
+    stage.buf.copy_within(stage.committed..stage.len, 0);
+    stage.len -= stage.committed;
+    stage.committed = 0;
+}
+
+fn find_position() -> Option<Position> {
+    let sequences = [read_header(0), read_header(1)];
+    let sector = match sequences {
+        [Some(a), Some(b)] if b > a => 1,
+        [Some(_), _] => 0,
+        [None, Some(_)] => 1,
+        [None, None] => return None,
+    };
+
+    let mut offset = HEADER_LEN;
+    while offset + 2 <= SECTOR_SIZE {
+        let len = read_u16(SECTORS[sector].0 + offset);
+        if len == EMPTY_RECORD {
+            break;
+        }
+        offset += 2 + len as u32;
+    }
+
+    Some(Position {
+        sector,
+        // 记录损坏导致越界时，当作已经写满
+        offset: offset.min(SECTOR_SIZE),
+        sequence: sequences[sector].unwrap(),
+    })
+}
+
+// 从旧到新，依次给出每条记录的内容所在的地址与长度
+fn for_each_record(mut f: impl FnMut(u32, u16)) {
+    let mut sectors = [(0, read_header(0)), (1, read_header(1))];
+    sectors.sort_unstable_by_key(|&(_, sequence)| sequence);
+
+    for (sector, sequence) in sectors {
+        if sequence.is_none() {
+            continue;
+        }
+        let base = FLASH_BASE + SECTORS[sector].0;
+        let mut offset = HEADER_LEN;
+        while offset + 2 <= SECTOR_SIZE {
+            let len = read_u16(SECTORS[sector].0 + offset);
+            if len == EMPTY_RECORD || offset + 2 + len as u32 > SECTOR_SIZE {
+                break;
+            }
+            f(base + offset + 2, len);
+            offset += 2 + len as u32;
+        }
+    }
+}
+
+fn read_header(sector: usize) -> Option<u32> {
+    let addr = SECTORS[sector].0;
+    if read_u32(addr) != MAGIC {
+        return None;
+    }
+    Some(read_u32(addr + 4))
+}
+
+fn write_header(sector: usize, sequence: u32) {
+    let addr = SECTORS[sector].0;
+    program(addr, &MAGIC.to_le_bytes());
+    program(addr + 4, &sequence.to_le_bytes());
+}
+
+// 记录的长度不一定对齐，逐个字节读取
+fn read_u8(offset: u32) -> u8 {
+    unsafe { core::ptr::read_volatile((FLASH_BASE + offset) as *const u8) }
+}
+
+fn read_u16(offset: u32) -> u16 {
+    u16::from_le_bytes([read_u8(offset), read_u8(offset + 1)])
+}
+
+fn read_u32(offset: u32) -> u32 {
+    u32::from_le_bytes([
+        read_u8(offset),
+        read_u8(offset + 1),
+        read_u8(offset + 2),
+        read_u8(offset + 3),
+    ])
+}
+
+// 以下为内部 Flash 的擦写，流程见 s21 的 utils::internal_flash，PSIZE 为 x8
+
+fn unlock() {
+    let flash = unsafe { &*pac::FLASH::ptr() };
+    if flash.cr.read().lock().bit_is_set() {
+        flash.keyr.write(|w| w.key().bits(0x4567_0123));
+        flash.keyr.write(|w| w.key().bits(0xCDEF_89AB));
+    }
+    flash.sr.write(|w| {
+        w.pgserr().set_bit();
+        w.pgperr().set_bit();
+        w.pgaerr().set_bit();
+        w.wrperr().set_bit();
+        w.operr().set_bit();
+        w
+    });
+}
+
+fn lock() {
+    let flash = unsafe { &*pac::FLASH::ptr() };
+    flash.cr.modify(|_, w| w.lock().set_bit());
+}
+
+// 擦除与写入之后，ART 的数据缓存中可能还留着旧的内容
+fn flush_data_cache() {
+    let acr = unsafe { &(*pac::FLASH::ptr()).acr };
+    acr.modify(|_, w| w.dcen().clear_bit());
+    acr.modify(|_, w| w.dcrst().set_bit());
+    acr.modify(|_, w| w.dcrst().clear_bit());
+    acr.modify(|_, w| w.dcen().set_bit());
+}
+
+fn program(offset: u32, data: &[u8]) {
+    let flash = unsafe { &*pac::FLASH::ptr() };
+    while flash.sr.read().bsy().bit_is_set() {}
+    unlock();
+
+    flash.cr.modify(|_, w| {
+        w.psize().bits(0b00);
+        w.pg().set_bit();
+        w
+    });
+    for (i, &byte) in data.iter().enumerate() {
+        unsafe { core::ptr::write_volatile((FLASH_BASE + offset + i as u32) as *mut u8, byte) };
+        while flash.sr.read().bsy().bit_is_set() {}
+    }
+    flash.cr.modify(|_, w| w.pg().clear_bit());
+
+    lock();
+    flush_data_cache();
+}
+
+fn erase_sector(sector: usize) {
+    let flash = unsafe { &*pac::FLASH::ptr() };
+    while flash.sr.read().bsy().bit_is_set() {}
+    unlock();
+
+    flash.cr.modify(|_, w| unsafe {
+        w.ser().set_bit();
+        w.snb().bits(SECTORS[sector].1)
+    });
+    flash.cr.modify(|_, w| w.strt().set_bit());
+    while flash.sr.read().bsy().bit_is_set() {}
+    flash.cr.modify(|_, w| w.ser().clear_bit());
+
+    lock();
+    flush_data_cache();
+}
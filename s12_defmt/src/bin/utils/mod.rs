@@ -0,0 +1 @@
+pub(crate) mod flash_log;
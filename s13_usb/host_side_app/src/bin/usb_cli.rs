@@ -0,0 +1,467 @@
+//! USB Host 端的命令行工具
+//!
+//! 配合 MCU 上运行的 s13c05_vendor_shell 使用，packet 格式的约定见 s13c05_vendor_shell.rs 的开头
+//!
+//! 与 receiver_sender 不同，这里我们把常用的操作整理成了几个子命令
+//!
+//! usb_cli list                      列出所有 VID/PID 匹配的设备，以及它们的序列号
+//! usb_cli echo [次数] [字节数]      回环测试，逐个 packet 比对 Device 发回的数据
+//! usb_cli bench [packet 数]         分别测量 OUT 方向和 IN 方向的吞吐量
+//! usb_cli cmd <命令>                在 Device 的 shell 上执行一条命令
+//! usb_cli script [文件]             逐行执行文件中的命令，不给出文件则从 stdin 读取，遇到 ERR 就以非 0 状态退出
+//! usb_cli peek <地址> [字数]        读取寄存器，字数大于 1 时按块读取，最多 14 个 u32
+//! usb_cli poke <地址> <值>          写入寄存器，并打印读回的值，需要先执行 usb_cli cmd reg write on
+//!
+//! 地址与值可以写成 0x 开头的十六进制，或者十进制
+//!
+//! 另外，全部子命令都可以在最前面加上 `--serial <序列号>`，以在多个同型号设备中挑选一个
+
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    process,
+    time::{Duration, Instant},
+};
+
+use rusb::{DeviceHandle, GlobalContext};
+
+const VID: u16 = 0x1209;
+const PID: u16 = 0x0001;
+
+const MANUFACTURER_NAME: &str = "random manufacturer";
+const PRODUCT_NAME: &str = "random product";
+
+// 与 Device 端一致的 endpoint 地址与 packet 大小
+const EP_OUT: u8 = 0x01;
+const EP_IN: u8 = 0x81;
+const PACKET_SIZE: usize = 64;
+
+// 与 Device 端一致的 packet tag
+const TAG_ECHO: u8 = 0x00;
+const TAG_SINK: u8 = 0x01;
+const TAG_SOURCE: u8 = 0x02;
+const TAG_CMD: u8 = 0x03;
+const TAG_REG: u8 = 0x04;
+
+// 与 Device 端 utils::reg_access 一致的 op 与限制
+const OP_READ32: u8 = 0x00;
+const OP_WRITE32: u8 = 0x01;
+const OP_READN: u8 = 0x02;
+const MAX_WORDS: usize = 14;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // 先把全局参数 --serial 拿出来
+    let mut serial = None;
+    if args.first().map(String::as_str) == Some("--serial") {
+        if args.len() < 2 {
+            usage_and_exit();
+        }
+        serial = Some(args.remove(1));
+        args.remove(0);
+    }
+
+    let Some(sub_cmd) = args.first().cloned() else {
+        usage_and_exit();
+    };
+
+    if sub_cmd == "list" {
+        list_devices();
+        return;
+    }
+
+    let handle = open_device(serial.as_deref());
+    handle.claim_interface(0).unwrap();
+
+    let result = match sub_cmd.as_str() {
+        "echo" => echo_test(
+            &handle,
+            parse_arg(&args, 1, 100),
+            parse_arg(&args, 2, PACKET_SIZE - 1),
+        ),
+        "bench" => bench(&handle, parse_arg(&args, 1, 1000)),
+        "cmd" => {
+            if args.len() < 2 {
+                usage_and_exit();
+            }
+            // 与 script 相同，Device 回复 ERR 时以 1 退出
+            match run_command(&handle, &args[1..].join(" ")) {
+                Ok(false) => {
+                    handle.release_interface(0).unwrap();
+                    process::exit(1);
+                }
+                result => result.map(|_| ()),
+            }
+        }
+        "script" => run_script(&handle, args.get(1).map(String::as_str)),
+        "peek" => {
+            let addr = parse_u32_arg(&args, 1).unwrap_or_else(|| usage_and_exit());
+            peek(&handle, addr, parse_arg(&args, 2, 1))
+        }
+        "poke" => {
+            let addr = parse_u32_arg(&args, 1).unwrap_or_else(|| usage_and_exit());
+            let value = parse_u32_arg(&args, 2).unwrap_or_else(|| usage_and_exit());
+            poke(&handle, addr, value)
+        }
+        _ => usage_and_exit(),
+    };
+
+    // 无论成功与否，都先释放对 interface 的占用
+    handle.release_interface(0).unwrap();
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn usage_and_exit() -> ! {
+    eprintln!("usage: usb_cli [--serial <serial>] <list|echo [count] [size]|bench [packets]|cmd <line>|script [file]|peek <addr> [words]|poke <addr> <value>>");
+    process::exit(2);
+}
+
+fn parse_arg(args: &[String], index: usize, default: usize) -> usize {
+    match args.get(index) {
+        Some(s) => s.parse().unwrap_or_else(|_| usage_and_exit()),
+        None => default,
+    }
+}
+
+// 0x 开头的按十六进制解析，否则按十进制
+fn parse_u32_arg(args: &[String], index: usize) -> Option<u32> {
+    let s = args.get(index)?;
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.parse(),
+    };
+    Some(parsed.unwrap_or_else(|_| usage_and_exit()))
+}
+
+// 筛选出 VID/PID、生产商名、产品名都匹配的设备，返回设备以及它的序列号
+fn matched_devices() -> Vec<(rusb::Device<GlobalContext>, String)> {
+    rusb::devices()
+        .unwrap()
+        .iter()
+        .filter_map(|cur_device| {
+            let device_desc = cur_device.device_descriptor().ok()?;
+            if device_desc.vendor_id() != VID || device_desc.product_id() != PID {
+                return None;
+            }
+
+            let handle = cur_device.open().ok()?;
+            if handle.read_manufacturer_string_ascii(&device_desc).ok()? != MANUFACTURER_NAME {
+                return None;
+            }
+            if handle.read_product_string_ascii(&device_desc).ok()? != PRODUCT_NAME {
+                return None;
+            }
+            let serial = handle.read_serial_number_string_ascii(&device_desc).ok()?;
+
+            Some((cur_device, serial))
+        })
+        .collect()
+}
+
+fn list_devices() {
+    let device_list = matched_devices();
+    if device_list.is_empty() {
+        println!("No matched USB device found");
+        return;
+    }
+    for (device, serial) in device_list {
+        println!(
+            "bus {:03} addr {:03}: VID & PID: 0x{:04x} & 0x{:04x}, serial: \"{}\"",
+            device.bus_number(),
+            device.address(),
+            VID,
+            PID,
+            serial
+        );
+    }
+}
+
+fn open_device(serial: Option<&str>) -> DeviceHandle<GlobalContext> {
+    let mut device_list: Vec<_> = matched_devices()
+        .into_iter()
+        .filter(|(_, cur_serial)| serial.is_none_or(|serial| serial == cur_serial))
+        .collect();
+
+    match device_list.len() {
+        0 => {
+            eprintln!("No matched USB device found, exit");
+            process::exit(1);
+        }
+        1 => (),
+        _ => {
+            eprintln!("multiple USB devices found, use --serial to pick one.\nexit");
+            process::exit(1);
+        }
+    }
+
+    let (device, _) = device_list.pop().unwrap();
+    device.open().unwrap()
+}
+
+// 清空 IN endpoint 中残留的 packet，防止上一次中断运行时残留的数据干扰这一次的结果
+fn drain_in(handle: &DeviceHandle<GlobalContext>) {
+    let mut buf = [0u8; PACKET_SIZE];
+    while handle
+        .read_interrupt(EP_IN, &mut buf, Duration::from_millis(20))
+        .is_ok()
+    {}
+}
+
+fn echo_test(handle: &DeviceHandle<GlobalContext>, count: usize, size: usize) -> rusb::Result<()> {
+    if size == 0 || size > PACKET_SIZE - 1 {
+        eprintln!("echo size must be in 1..={}", PACKET_SIZE - 1);
+        process::exit(2);
+    }
+
+    drain_in(handle);
+
+    let mut out_packet = [0u8; PACKET_SIZE];
+    let mut in_packet = [0u8; PACKET_SIZE];
+    let mut mismatch = 0;
+
+    let start = Instant::now();
+    for round in 0..count {
+        out_packet[0] = TAG_ECHO;
+        // 每一轮的负载都不一样，防止 Device 返回的是上一轮的数据，而我们却没有发现
+        for (index, byte) in out_packet[1..=size].iter_mut().enumerate() {
+            *byte = (round + index) as u8;
+        }
+
+        handle.write_interrupt(EP_OUT, &out_packet[..=size], TIMEOUT)?;
+        let read_len = handle.read_interrupt(EP_IN, &mut in_packet, TIMEOUT)?;
+
+        if in_packet[..read_len] != out_packet[..=size] {
+            mismatch += 1;
+            println!(
+                "round {}: mismatch, send {:02X?}, receive {:02X?}",
+                round,
+                &out_packet[..=size],
+                &in_packet[..read_len]
+            );
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "echo {} packets of {} bytes, {} mismatch, average round trip {:.3} ms",
+        count,
+        size,
+        mismatch,
+        elapsed.as_secs_f64() * 1000.0 / count.max(1) as f64
+    );
+
+    Ok(())
+}
+
+fn bench(handle: &DeviceHandle<GlobalContext>, packets: usize) -> rusb::Result<()> {
+    drain_in(handle);
+
+    // OUT 方向，连续发送 SINK packet
+    let mut out_packet = [0u8; PACKET_SIZE];
+    out_packet[0] = TAG_SINK;
+
+    let start = Instant::now();
+    for _ in 0..packets {
+        handle.write_interrupt(EP_OUT, &out_packet, TIMEOUT)?;
+    }
+    print_throughput("OUT", packets, start.elapsed());
+
+    // IN 方向，让 Device 连续发出指定数量的 SOURCE packet
+    let mut source_packet = [0u8; 5];
+    source_packet[0] = TAG_SOURCE;
+    source_packet[1..5].copy_from_slice(&(packets as u32).to_le_bytes());
+
+    let mut in_packet = [0u8; PACKET_SIZE];
+    let mut lost = 0;
+
+    let start = Instant::now();
+    handle.write_interrupt(EP_OUT, &source_packet, TIMEOUT)?;
+    for expected in (1..=packets as u32).rev() {
+        handle.read_interrupt(EP_IN, &mut in_packet, TIMEOUT)?;
+        let seq = u32::from_le_bytes([in_packet[1], in_packet[2], in_packet[3], in_packet[4]]);
+        if in_packet[0] != TAG_SOURCE || seq != expected {
+            lost += 1;
+        }
+    }
+    print_throughput("IN", packets, start.elapsed());
+
+    if lost > 0 {
+        println!("IN: {} packets out of sequence", lost);
+    }
+
+    Ok(())
+}
+
+fn print_throughput(dir: &str, packets: usize, elapsed: Duration) {
+    let bytes = packets * PACKET_SIZE;
+    println!(
+        "{:>3}: {} bytes in {:.3} s, {:.1} KiB/s",
+        dir,
+        bytes,
+        elapsed.as_secs_f64(),
+        bytes as f64 / 1024.0 / elapsed.as_secs_f64()
+    );
+}
+
+// 执行一条命令，打印 Device 的回复，并在回复以 ERR 结尾时返回 Ok(false)
+fn run_command(handle: &DeviceHandle<GlobalContext>, line: &str) -> rusb::Result<bool> {
+    if line.len() > PACKET_SIZE - 1 {
+        eprintln!("command too long, {} bytes at most", PACKET_SIZE - 1);
+        return Ok(false);
+    }
+
+    drain_in(handle);
+
+    let mut out_packet = Vec::with_capacity(PACKET_SIZE);
+    out_packet.push(TAG_CMD);
+    out_packet.extend_from_slice(line.as_bytes());
+    handle.write_interrupt(EP_OUT, &out_packet, TIMEOUT)?;
+
+    // 回复可能跨越多个 packet，我们一直读取，直到读到一行 OK 或 ERR
+    let mut reply = String::new();
+    let mut in_packet = [0u8; PACKET_SIZE];
+    loop {
+        let read_len = handle.read_interrupt(EP_IN, &mut in_packet, TIMEOUT)?;
+        if read_len == 0 || in_packet[0] != TAG_CMD {
+            continue;
+        }
+        reply.push_str(&String::from_utf8_lossy(&in_packet[1..read_len]));
+
+        if let Some(last_line) = reply.strip_suffix('\n').and_then(|s| s.lines().last()) {
+            if last_line == "OK" || last_line.starts_with("ERR") {
+                print!("{}", reply);
+                return Ok(last_line == "OK");
+            }
+        }
+    }
+}
+
+fn run_script(handle: &DeviceHandle<GlobalContext>, path: Option<&str>) -> rusb::Result<()> {
+    let reader: Box<dyn BufRead> = match path {
+        Some(path) => Box::new(BufReader::new(File::open(path).unwrap_or_else(|e| {
+            eprintln!("cannot open {}: {}", path, e);
+            process::exit(1);
+        }))),
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line.unwrap();
+        let line = line.trim();
+
+        // 允许空行和 # 开头的注释
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        println!("> {}", line);
+        if !run_command(handle, line)? {
+            eprintln!("script stopped at line {}", line_no + 1);
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+// 发送一个 REG 请求，返回 Device 回复的数据部分，状态不为 0 时打印原因并返回 None
+fn reg_request(
+    handle: &DeviceHandle<GlobalContext>,
+    op: u8,
+    addr: u32,
+    params: &[u8],
+) -> rusb::Result<Option<Vec<u32>>> {
+    drain_in(handle);
+
+    let mut out_packet = vec![TAG_REG, op];
+    out_packet.extend_from_slice(&addr.to_le_bytes());
+    out_packet.extend_from_slice(params);
+    handle.write_interrupt(EP_OUT, &out_packet, TIMEOUT)?;
+
+    // 回复为 | tag | status | op | addr (4) | 数据 |，op 与 addr 对不上的是之前残留的回复
+    let mut in_packet = [0u8; PACKET_SIZE];
+    loop {
+        let read_len = handle.read_interrupt(EP_IN, &mut in_packet, TIMEOUT)?;
+        if read_len < 7 || in_packet[0] != TAG_REG {
+            continue;
+        }
+        if in_packet[2] != op || in_packet[3..7] != addr.to_le_bytes() {
+            continue;
+        }
+
+        let status = in_packet[1];
+        if status != 0 {
+            let reason = match status {
+                0x01 => "bad request",
+                0x02 => "address not aligned to 4 bytes",
+                0x03 => "address not in whitelist, see `usb_cli cmd reg list`",
+                0x04 => "region is read only",
+                0x05 => "write disabled, run `usb_cli cmd reg write on` first",
+                _ => "unknown status",
+            };
+            eprintln!("{:#010X}: {}", addr, reason);
+            return Ok(None);
+        }
+
+        let words = in_packet[7..read_len]
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        return Ok(Some(words));
+    }
+}
+
+fn peek(handle: &DeviceHandle<GlobalContext>, addr: u32, words: usize) -> rusb::Result<()> {
+    if words == 0 || words > MAX_WORDS {
+        eprintln!("word count must be in 1..={}", MAX_WORDS);
+        process::exit(2);
+    }
+
+    let reply = if words == 1 {
+        reg_request(handle, OP_READ32, addr, &[])?
+    } else {
+        reg_request(handle, OP_READN, addr, &[words as u8])?
+    };
+    let Some(values) = reply else {
+        process::exit(1);
+    };
+
+    // 每行 4 个 u32，类似调试器的内存窗口
+    for (row, chunk) in values.chunks(4).enumerate() {
+        // 靠近地址空间顶端时，行首的地址回绕到 0，而不是溢出
+        print!("{:08X}:", addr.wrapping_add(row as u32 * 16));
+        for value in chunk {
+            print!(" {:08X}", value);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn poke(handle: &DeviceHandle<GlobalContext>, addr: u32, value: u32) -> rusb::Result<()> {
+    let Some(values) = reg_request(handle, OP_WRITE32, addr, &value.to_le_bytes())? else {
+        process::exit(1);
+    };
+    // 回复中的数据来自 Device，不能假定它一定带有读回的值
+    let Some(read_back) = values.first() else {
+        eprintln!("{:#010X}: reply carries no read-back value", addr);
+        process::exit(1);
+    };
+
+    // 有的位只读，或者写入之后立即被硬件改变，读回的值不一定等于写入的值
+    println!(
+        "{:08X}: wrote {:08X}, read back {:08X}",
+        addr, value, read_back
+    );
+
+    Ok(())
+}
@@ -153,7 +153,7 @@ fn main() -> ! {
     //
     // TIP: 实际上，UsbBusType 是 UsbBus<USB> 的别名，不过由于 UsbBus 已经是与之关联的 trait 的名称了
     // 因此 synopsys_usb_otg crate 定义了一个别名，方便我们使用
-    let usb_bus_alloc = UsbBusType::new(usb, unsafe { &mut EP_OUT_MEM });
+    let usb_bus_alloc = UsbBusType::new(usb, unsafe { &mut *core::ptr::addr_of_mut!(EP_OUT_MEM) });
 
     // 在生成了 UsbBusAllocator 之后，我们就可以通过它来构建我们自己的 MyUSBClass 实例对象了
     //
@@ -231,6 +231,6 @@ fn main() -> ! {
 
         // 这里的 10 ms 的轮询等待，是在满足 USB 2.0 Spec 的情况下，
         // 随便设置的一个值
-        delay.delay_ms(10u8);
+        delay.delay_ms(10u32);
     }
 }
@@ -80,7 +80,7 @@ fn main() -> ! {
         &clocks,
     );
 
-    let usb_bus_alloc = UsbBusType::new(usb, unsafe { &mut EP_MEM });
+    let usb_bus_alloc = UsbBusType::new(usb, unsafe { &mut *core::ptr::addr_of_mut!(EP_MEM) });
 
     let mut my_usb = MyUSBClass::new(&usb_bus_alloc);
 
@@ -99,6 +99,6 @@ fn main() -> ! {
 
     loop {
         usb_dev.poll(&mut [&mut my_usb]);
-        delay.delay_ms(10u8);
+        delay.delay_ms(10u32);
     }
 }
@@ -189,7 +189,7 @@ fn main() -> ! {
         &clocks,
     );
 
-    let usb_bus_alloc = UsbBusType::new(usb, unsafe { &mut EP_OUT_MEM });
+    let usb_bus_alloc = UsbBusType::new(usb, unsafe { &mut *core::ptr::addr_of_mut!(EP_OUT_MEM) });
 
     let mut my_usb_class = MyUSBClass::new(&usb_bus_alloc);
 
@@ -221,7 +221,7 @@ fn main() -> ! {
             // 此时，我们就可以等待一段时间，再询问 UsbDevice
             //
             // 这里等待的毫秒数，等价于 s13c01 中的等待时间
-            delay.delay_ms(10u8);
+            delay.delay_ms(10u32);
             continue;
         };
 
@@ -235,7 +235,7 @@ fn main() -> ! {
         // 这里我们要稍稍等一下（比如这里为 10 us），让 Host 和 Device 完成剩下的工作，
         // 再跳转回本 loop{} 的头部，开始下一次的询问
         // 不等待一段时间的话，USB 设备的枚举过程会失败
-        delay.delay_us(10u8);
+        delay.delay_us(10u32);
     }
 
     // 在上面的枚举完成之后，就是实际执行“业务代码”的循环了
@@ -244,7 +244,7 @@ fn main() -> ! {
     loop {
         if !usb_dev.poll(&mut [&mut my_usb_class]) {
             // 这里我们将空循环的等待时间缩短了，这样我们可以稍稍提高设备的平均响应速度
-            delay.delay_us(100u16);
+            delay.delay_us(100u32);
             continue;
         };
 
@@ -0,0 +1,579 @@
+//! 配合 Host 端命令行工具 usb_cli 的 vendor device
+//!
+//! 在 custom_tx_rx 中，Host 和 Device 之间只交换了固定的 "hello" 和 "hi"，
+//! 这里我们将 Interrupt IN/OUT 这对 endpoint 的用途扩展一下，让 Host 端的 usb_cli 可以
+//!
+//! 1. 做回环测试（Host 发什么，Device 就回什么）
+//! 2. 测量 OUT 方向和 IN 方向的吞吐量
+//! 3. 以“一行一条命令”的形式，调用 Device 上的简单 shell
+//!
+//! 为了区分这几种用途，我们约定，每个 OUT/IN packet 的第一个字节为 packet 的类型（tag），之后的字节才是负载（payload）
+//!
+//! | tag  | 方向     | 含义                                                                   |
+//! | ---- | -------- | ---------------------------------------------------------------------- |
+//! | 0x00 | OUT & IN | ECHO：Device 将收到的整个 packet 原样从 IN endpoint 发回               |
+//! | 0x01 | OUT      | SINK：Device 仅统计收到的字节数，然后丢弃，用于测量 OUT 方向的吞吐量   |
+//! | 0x02 | OUT      | SOURCE：payload 为小端序的 u32，表示 Device 需要连续发出的 packet 数   |
+//! | 0x02 | IN       | SOURCE 的数据 packet，每个都是 64 byte 满包                            |
+//! | 0x03 | OUT & IN | CMD：payload 为一行 ASCII 命令，回复的 payload 为文本，可跨多个 packet |
+//! | 0x04 | OUT & IN | REG：读写寄存器，payload 的格式见 utils::reg_access，回复总在一个 packet 之内 |
+//!
+//! CMD 的回复文本，总是以一行 "OK" 或 "ERR <原因>" 结尾，Host 端可以据此判定回复是否结束、命令是否执行成功
+//!
+//! shell 中还有一个 i2c scan 命令，它使用 utils::i2c_scan 扫描 I2C1 上 0x08 ~ 0x77 的所有地址，
+//! 以 i2cdetect 的格式回复找到的设备，并标注常见设备的名称，方便在接上新模块之后确认接线与地址
+//! 扫描过程中若总线被卡住，会先尝试恢复总线（见 recover_i2c_bus），再重试一次
+//!
+//! 注意，扫描是在 OTG_FS 中断中阻塞完成的，100 kHz 下大约需要几十毫秒，这期间 Device 不会响应 Host，
+//! 对于这样一个调试用的 shell 来说是可以接受的
+//!
+//! sof 命令给出 utils::sof_timing 的统计：TIM2 在硬件上捕获每一个 SOF，测量 host 的帧周期，
+//! 以及本地时钟相对 host 的偏差（ppm），每 1000 帧更新一次
+//! 把 USE_HSE 改为 false，PLL 的时钟源就换成了 HSI，此时可以用 sof trim on 打开 HSITRIM 的闭环微调，
+//! 观察偏差是如何被拉回来的
+//!
+//! REG 只允许访问 utils::reg_access 中白名单里的地址，写入默认关闭，需要先执行 reg write on，
+//! reg list 列出白名单以及写入是否打开；Host 端用 usb_cli peek/poke 发起读写
+//!
+//! Host 端的配套程序为 .\host_side_app\src\bin\usb_cli.rs
+//!
+//! 接线图：
+//!
+//! PB8 I2C1_SCL
+//! PB9 I2C1_SDA
+//!
+//! 两根线都需要上拉，这里打开了芯片内部的上拉，线比较长、设备比较多时，还是应该外接 4.7k 左右的上拉电阻
+
+#![no_std]
+#![no_main]
+
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use cortex_m::{interrupt::Mutex, peripheral::NVIC};
+use defmt_rtt as _;
+use panic_probe as _;
+
+use stm32f4xx_hal::{
+    otg_fs::{UsbBusType, USB},
+    pac::{self, interrupt},
+    prelude::*,
+};
+use usb_device::{class_prelude::*, prelude::*};
+
+mod utils;
+
+use crate::shell_usb_class::ShellUSBClass;
+use utils::clocks::Clocks;
+
+const SYSCLK_HZ: u32 = 96_000_000;
+
+// false 时 PLL 使用 HSI，用于 sof trim 的实验
+const USE_HSE: bool = true;
+
+static COUNT: AtomicU32 = AtomicU32::new(0);
+defmt::timestamp!("{}", COUNT.fetch_add(1, Ordering::Relaxed));
+
+static G_USB_DEVICE: Mutex<RefCell<Option<UsbDevice<UsbBusType>>>> = Mutex::new(RefCell::new(None));
+static G_SHELL_USB_CLASS: Mutex<RefCell<Option<ShellUSBClass<UsbBusType>>>> =
+    Mutex::new(RefCell::new(None));
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    static mut EP_OUT_MEM: [u32; 40] = [0u32; 40];
+    static mut USB_BUS_ALLOC: Option<UsbBusAllocator<UsbBusType>> = None;
+
+    defmt::info!("program start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_i2c_gpio(&dp);
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let rcc = dp.RCC.constrain();
+
+    let cfgr = if USE_HSE {
+        rcc.cfgr.use_hse(12.MHz())
+    } else {
+        rcc.cfgr
+    };
+
+    let clocks = cfgr.sysclk(SYSCLK_HZ.Hz()).require_pll48clk().freeze();
+
+    let gpioa = dp.GPIOA.split();
+
+    let usb = USB::new(
+        (dp.OTG_FS_GLOBAL, dp.OTG_FS_DEVICE, dp.OTG_FS_PWRCLK),
+        (gpioa.pa11, gpioa.pa12),
+        &clocks,
+    );
+
+    USB_BUS_ALLOC.replace(UsbBusType::new(usb, EP_OUT_MEM));
+
+    // 从 RCC 的寄存器读出实际的时钟，I2C 的分频系数据此计算
+    let actual = Clocks::read();
+    let checked = actual
+        .check_limits()
+        .and_then(|_| actual.check_usb())
+        .and_then(|_| utils::i2c_scan::setup_master(&dp.I2C1, &actual));
+    if let Err(e) = checked {
+        defmt::panic!("{}", defmt::Display2Format(&e));
+    }
+
+    let usb_bus_alloc = USB_BUS_ALLOC.as_ref().unwrap();
+    let shell_usb_class = ShellUSBClass::new(usb_bus_alloc, dp.I2C1, actual);
+    let usb_device_builder = UsbDeviceBuilder::new(usb_bus_alloc, UsbVidPid(0x1209, 0x0001));
+    let default_desc = StringDescriptors::default()
+        .manufacturer("random manufacturer")
+        .product("random product")
+        .serial_number("random serial");
+    let usb_dev = usb_device_builder.strings(&[default_desc]).unwrap().build();
+
+    // 必须在 USB 外设初始化之后
+    utils::sof_timing::start(actual.timclk1);
+
+    cortex_m::interrupt::free(|cs| {
+        G_USB_DEVICE.borrow(cs).borrow_mut().replace(usb_dev);
+        G_SHELL_USB_CLASS
+            .borrow(cs)
+            .borrow_mut()
+            .replace(shell_usb_class);
+    });
+
+    unsafe { NVIC::unmask(interrupt::OTG_FS) }
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+#[interrupt]
+fn OTG_FS() {
+    cortex_m::interrupt::free(|cs| {
+        let mut usb_device_mut = G_USB_DEVICE.borrow(cs).borrow_mut();
+        let usb_device = usb_device_mut.as_mut().unwrap();
+        let mut shell_usb_class_mut = G_SHELL_USB_CLASS.borrow(cs).borrow_mut();
+        let shell_usb_class = shell_usb_class_mut.as_mut().unwrap();
+
+        // 注意这里和 custom_tx_rx 的区别
+        // 即便 .poll() 返回了 false，我们也要尝试推送一下待发送的数据
+        // 因为 IN 方向上一个 packet 发送完成时，.poll() 是会返回 false 的，而此时恰恰是我们应该推送下一个 packet 的时候
+        usb_device.poll(&mut [shell_usb_class]);
+
+        if usb_device.state() != UsbDeviceState::Configured {
+            return;
+        }
+
+        shell_usb_class.flush();
+    })
+}
+
+#[interrupt]
+fn TIM2() {
+    utils::sof_timing::on_tim2();
+}
+
+// PB8、PB9 为 I2C1，AF4，开漏输出
+fn setup_i2c_gpio(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+}
+
+// 总线恢复，与 s04c04 中的做法相同
+//
+// 如果从机在发送某个字节的中途被打断，它可能会一直拉低 SDA，等待剩下的时钟
+// 此时主机需要把 SCL 切换为普通的 GPIO，手动产生最多 9 个时钟，直到从机释放 SDA，然后再产生一个 STOP condition
+//
+// 返回 SDA 是否已经被释放
+fn recover_i2c_bus(i2c: &pac::i2c1::RegisterBlock, clocks: &Clocks) -> bool {
+    let gpiob = unsafe { &*pac::GPIOB::ptr() };
+
+    i2c.cr1.modify(|_, w| w.pe().disabled());
+
+    // 开漏输出，先释放两根线
+    gpiob.odr.modify(|_, w| {
+        w.odr8().high();
+        w.odr9().high();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().output();
+        w.moder9().output();
+        w
+    });
+
+    // 100 kHz 的半个周期大约是 5 us
+    let half_period = SYSCLK_HZ / 200_000;
+
+    for _ in 0..9 {
+        if gpiob.idr.read().idr9().is_high() {
+            break;
+        }
+        gpiob.odr.modify(|_, w| w.odr8().low());
+        cortex_m::asm::delay(half_period);
+        gpiob.odr.modify(|_, w| w.odr8().high());
+        cortex_m::asm::delay(half_period);
+    }
+    let released = gpiob.idr.read().idr9().is_high();
+
+    // STOP condition：SCL 为高时，SDA 产生上升沿
+    gpiob.odr.modify(|_, w| w.odr9().low());
+    cortex_m::asm::delay(half_period);
+    gpiob.odr.modify(|_, w| w.odr9().high());
+    cortex_m::asm::delay(half_period);
+
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    // 最后复位 I2C 内部的状态机，SWRST 会清空全部寄存器，因此需要重新配置一遍
+    i2c.cr1.modify(|_, w| w.swrst().set_bit());
+    i2c.cr1.modify(|_, w| w.swrst().clear_bit());
+    // 启动时已经用同样的时钟检查过，这里不会出错
+    utils::i2c_scan::setup_master(i2c, clocks).ok();
+
+    released
+}
+
+mod shell_usb_class {
+    use core::fmt::{self, Write};
+
+    use stm32f4xx_hal::pac;
+    use usb_device::{class_prelude::*, endpoint};
+
+    use crate::utils::{
+        clocks::Clocks,
+        i2c_scan::{self, ScanError},
+        reg_access,
+        sof_timing::{self, TrimError},
+    };
+
+    // 单个 packet 的大小，Full-Speed 的 Interrupt endpoint 最大也就是 64 byte
+    const PACKET_SIZE: usize = 64;
+
+    // 各种 packet 的 tag，与 usb_cli 中的定义必须保持一致
+    const TAG_ECHO: u8 = 0x00;
+    const TAG_SINK: u8 = 0x01;
+    const TAG_SOURCE: u8 = 0x02;
+    const TAG_CMD: u8 = 0x03;
+    const TAG_REG: u8 = 0x04;
+
+    pub(super) struct ShellUSBClass<'a, B: UsbBus> {
+        iface_index: InterfaceNumber,
+        interrupt_in: EndpointIn<'a, B>,
+        in_empty: bool,
+        interrupt_out: EndpointOut<'a, B>,
+
+        // 等待从 IN endpoint 发出去的数据，注意，这里存储的数据是不带 tag 的
+        // 每次发送时，我们都会在 packet 的最前面补上 tx_tag
+        // i2c scan 的回复大约有 600 byte
+        tx_buf: [u8; 1024],
+        tx_tag: u8,
+        tx_len: usize,
+        tx_pos: usize,
+
+        // SOURCE 模式下，还需要发出多少个 packet
+        source_remaining: u32,
+
+        // 一些统计信息，可以通过 stat 命令读取
+        rx_packet_cnt: u32,
+        sink_byte_cnt: u32,
+
+        // i2c scan 使用的 I2C1，以及恢复总线之后重新配置它所需的时钟
+        i2c: pac::I2C1,
+        clocks: Clocks,
+    }
+
+    impl<'a, B: UsbBus> ShellUSBClass<'a, B> {
+        pub(super) fn new(alloc: &'a UsbBusAllocator<B>, i2c: pac::I2C1, clocks: Clocks) -> Self {
+            Self {
+                iface_index: alloc.interface(),
+                interrupt_in: alloc.interrupt::<endpoint::In>(PACKET_SIZE as u16, 1),
+                in_empty: true,
+                interrupt_out: alloc.interrupt::<endpoint::Out>(PACKET_SIZE as u16, 1),
+                tx_buf: [0u8; 1024],
+                tx_tag: TAG_ECHO,
+                tx_len: 0,
+                tx_pos: 0,
+                source_remaining: 0,
+                rx_packet_cnt: 0,
+                sink_byte_cnt: 0,
+                i2c,
+                clocks,
+            }
+        }
+
+        // 如果 IN endpoint 空闲，就从待发送的数据中取出一个 packet 发出去
+        pub(super) fn flush(&mut self) {
+            if !self.in_empty {
+                return;
+            }
+
+            let mut packet = [0u8; PACKET_SIZE];
+
+            let packet_len = if self.tx_pos < self.tx_len {
+                // 有待发送的 ECHO/CMD 回复
+                let chunk_len = (self.tx_len - self.tx_pos).min(PACKET_SIZE - 1);
+                packet[0] = self.tx_tag;
+                packet[1..1 + chunk_len]
+                    .copy_from_slice(&self.tx_buf[self.tx_pos..self.tx_pos + chunk_len]);
+                self.tx_pos += chunk_len;
+                1 + chunk_len
+            } else if self.source_remaining > 0 {
+                // SOURCE 模式，发送满包，负载部分填充一个递减的序号，方便 Host 端检查是否丢包
+                packet[0] = TAG_SOURCE;
+                packet[1..5].copy_from_slice(&self.source_remaining.to_le_bytes());
+                self.source_remaining -= 1;
+                PACKET_SIZE
+            } else {
+                return;
+            };
+
+            match self.interrupt_in.write(&packet[..packet_len]) {
+                Ok(_) => self.in_empty = false,
+                Err(UsbError::WouldBlock) => (),
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+
+        // 处理一个刚刚从 OUT endpoint 收到的 packet
+        fn dispatch(&mut self, packet: &[u8]) {
+            self.rx_packet_cnt = self.rx_packet_cnt.wrapping_add(1);
+
+            let Some((&tag, payload)) = packet.split_first() else {
+                return;
+            };
+
+            match tag {
+                TAG_ECHO => {
+                    self.tx_tag = TAG_ECHO;
+                    self.tx_buf[..payload.len()].copy_from_slice(payload);
+                    self.tx_len = payload.len();
+                    self.tx_pos = 0;
+                }
+                TAG_SINK => {
+                    self.sink_byte_cnt = self.sink_byte_cnt.wrapping_add(packet.len() as u32);
+                }
+                TAG_SOURCE => {
+                    if payload.len() >= 4 {
+                        self.source_remaining =
+                            u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                        defmt::info!("source {} packets", self.source_remaining);
+                    }
+                }
+                TAG_CMD => self.run_command(payload),
+                TAG_REG => {
+                    let mut reply = [0u8; reg_access::MAX_REPLY_LEN];
+                    let reply_len = reg_access::handle(payload, &mut reply);
+                    self.tx_tag = TAG_REG;
+                    self.tx_buf[..reply_len].copy_from_slice(&reply[..reply_len]);
+                    self.tx_len = reply_len;
+                    self.tx_pos = 0;
+                }
+                _ => defmt::warn!("unknown tag: {:#04X}", tag),
+            }
+        }
+
+        // 一个非常简单的 shell，每个 CMD packet 就是一行命令
+        fn run_command(&mut self, line: &[u8]) {
+            self.tx_tag = TAG_CMD;
+            self.tx_len = 0;
+            self.tx_pos = 0;
+
+            let Ok(line) = core::str::from_utf8(line) else {
+                self.push_str("ERR not utf8\n");
+                return;
+            };
+
+            let line = line.trim();
+            let (cmd, arg) = line.split_once(' ').unwrap_or((line, ""));
+
+            defmt::info!("cmd: {}", line);
+
+            match cmd {
+                "help" => {
+                    self.push_str("help         show this message\n");
+                    self.push_str("ping         reply pong\n");
+                    self.push_str("echo <text>  reply <text>\n");
+                    self.push_str("uid          read 96 bit unique ID\n");
+                    self.push_str("stat         packet counters\n");
+                    self.push_str("i2c scan     probe I2C1 for devices\n");
+                    self.push_str("sof          USB frame timing and clock drift\n");
+                    self.push_str("sof trim <on|off>  HSI trimming from SOF\n");
+                    self.push_str("reg list     register access whitelist\n");
+                    self.push_str("reg write <on|off>  allow register writes\n");
+                    self.push_str("OK\n");
+                }
+                "ping" => self.push_str("pong\nOK\n"),
+                "echo" => {
+                    self.push_str(arg);
+                    self.push_str("\nOK\n");
+                }
+                "uid" => {
+                    // 芯片的 Unique device ID 是存储在固定地址上的 96 bit 数据
+                    // 见 Reference Manual 的 Device electronic signature 章节
+                    let uid_ptr = 0x1FFF_7A10 as *const u32;
+                    for offset in (0..3).rev() {
+                        let word = unsafe { uid_ptr.add(offset).read_volatile() };
+                        self.push_hex_u32(word);
+                    }
+                    self.push_str("\nOK\n");
+                }
+                "stat" => {
+                    self.push_str("rx packets: ");
+                    self.push_hex_u32(self.rx_packet_cnt);
+                    self.push_str("\nsink bytes: ");
+                    self.push_hex_u32(self.sink_byte_cnt);
+                    self.push_str("\nOK\n");
+                }
+                "i2c" => match arg {
+                    "scan" => self.i2c_scan(),
+                    _ => self.push_str("ERR usage: i2c scan\n"),
+                },
+                "sof" => match arg {
+                    "" => self.sof_stat(),
+                    "trim on" | "trim off" => match sof_timing::set_trim(arg == "trim on") {
+                        Ok(()) => self.push_str("OK\n"),
+                        Err(TrimError::NotHsi) => self.push_str("ERR PLL source is not HSI\n"),
+                        Err(TrimError::NotStarted) => self.push_str("ERR sof timing not started\n"),
+                    },
+                    _ => self.push_str("ERR usage: sof [trim on|off]\n"),
+                },
+                "reg" => match arg {
+                    "list" => {
+                        reg_access::write_regions(self).ok();
+                        self.push_str("OK\n");
+                    }
+                    "write on" | "write off" => {
+                        reg_access::set_write_enabled(arg == "write on");
+                        defmt::info!("register write: {}", reg_access::write_enabled());
+                        self.push_str("OK\n");
+                    }
+                    _ => self.push_str("ERR usage: reg <list|write on|write off>\n"),
+                },
+                "" => self.push_str("OK\n"),
+                _ => self.push_str("ERR unknown command\n"),
+            }
+        }
+
+        fn i2c_scan(&mut self) {
+            let clocks = self.clocks;
+            let result = i2c_scan::scan(&self.i2c, || {
+                defmt::warn!("i2c bus stuck, recovering");
+                super::recover_i2c_bus(&self.i2c, &clocks)
+            });
+
+            match result {
+                Ok(scan) => {
+                    defmt::info!("i2c scan: {} device(s)", scan.count());
+                    i2c_scan::write_table(&scan, self).ok();
+                    self.push_str("OK\n");
+                }
+                Err(ScanError::BusStuck(addr)) => {
+                    writeln!(self, "ERR bus stuck at {:02X}", addr).ok();
+                }
+            }
+        }
+
+        fn sof_stat(&mut self) {
+            let Some(stats) = sof_timing::stats() else {
+                self.push_str("ERR sof timing not started\n");
+                return;
+            };
+
+            writeln!(self, "frames:  {} ({} missed)", stats.frames, stats.missed).ok();
+            if stats.windows == 0 {
+                self.push_str("no complete window yet\n");
+            } else {
+                writeln!(
+                    self,
+                    "period:  {}..{} ticks (nominal {})",
+                    stats.period_min, stats.period_max, stats.expected_period
+                )
+                .ok();
+                writeln!(self, "drift:   {} ppm", stats.drift_ppm).ok();
+            }
+            match stats.trim {
+                Some(trim) => writeln!(self, "trim:    {} ({} steps)", trim, stats.trim_steps).ok(),
+                None => writeln!(self, "trim:    off").ok(),
+            };
+            self.push_str("OK\n");
+        }
+
+        fn push_str(&mut self, s: &str) {
+            let bytes = s.as_bytes();
+            let copy_len = bytes.len().min(self.tx_buf.len() - self.tx_len);
+            self.tx_buf[self.tx_len..self.tx_len + copy_len].copy_from_slice(&bytes[..copy_len]);
+            self.tx_len += copy_len;
+        }
+
+        fn push_hex_u32(&mut self, value: u32) {
+            const HEX: &[u8; 16] = b"0123456789ABCDEF";
+            let mut digits = [0u8; 8];
+            for (index, digit) in digits.iter_mut().enumerate() {
+                *digit = HEX[(value >> (28 - index * 4)) as usize & 0xF];
+            }
+            self.push_str(core::str::from_utf8(&digits).unwrap());
+        }
+    }
+
+    // 让 core::fmt 的格式化输出直接写进待发送的数据中
+    impl<B: UsbBus> Write for ShellUSBClass<'_, B> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.push_str(s);
+            Ok(())
+        }
+    }
+
+    impl<'a, B: UsbBus> UsbClass<B> for ShellUSBClass<'a, B> {
+        fn get_configuration_descriptors(
+            &self,
+            writer: &mut DescriptorWriter,
+        ) -> usb_device::Result<()> {
+            writer.interface(self.iface_index, 0xFF, 0x00, 0x00)?;
+            writer.endpoint(&self.interrupt_out)?;
+            writer.endpoint(&self.interrupt_in)?;
+            Ok(())
+        }
+
+        fn endpoint_out(&mut self, addr: EndpointAddress) {
+            if addr != self.interrupt_out.address() {
+                return;
+            }
+            let mut packet = [0u8; PACKET_SIZE];
+            let packet_len = self.interrupt_out.read(&mut packet).unwrap();
+            self.dispatch(&packet[..packet_len]);
+        }
+
+        fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+            if addr != self.interrupt_in.address() {
+                return;
+            }
+            self.in_empty = true;
+        }
+    }
+}
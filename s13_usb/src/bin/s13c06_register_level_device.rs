@@ -0,0 +1,102 @@
+//! 直接操作寄存器实现的最小化 USB 设备
+//!
+//! 效果与 s13c01 完全相同：一个没有任何功能，但可以正常被 Linux 和 Windows 枚举的设备，VID/PID、字符串描述符也都一样
+//! 区别在于，这里没有使用 usb-device、synopsys-usb-otg，也没有使用 stm32f4xx-hal 的 otg_fs 模块，
+//! 从 core 的复位、FIFO 的划分，到 SETUP 包的解析与控制传输的状态机，全部都在 utils::raw_usb 中手动完成
+//!
+//! 阅读顺序建议为：
+//! 1. utils::raw_usb 的模块说明，了解整体的流程
+//! 2. utils::raw_usb::regs，对照 Reference Manual 的 OTG_FS registers 节
+//! 3. utils::raw_usb::setup 与 utils::raw_usb::descriptor，对照 USB 2.0 Specification 的第 9 章
+//! 4. utils::raw_usb 中的 RawUsb::poll，以及它调用的各个函数
+//!
+//! 将日志等级设置为 debug（DEFMT_LOG=debug），可以看到 host 发来的每一个 SETUP 包，与 _note/minimal_usb_request.adoc 对照
+//!
+//! 接线图：
+//!
+//! 与 s13c01 相同，开发板的 USB 口（PA11 D-，PA12 D+）接到电脑上，注意 D+ 上不能有外部的上拉电阻
+
+#![no_std]
+#![no_main]
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::raw_usb::RawUsb;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    defmt::info!("program start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_clock(&dp);
+
+    let mut usb = RawUsb::new();
+    usb.init(&dp);
+
+    let mut last_state = usb.state();
+    defmt::info!("{:?}", last_state);
+
+    // 这里没有像 s13c01 那样在两次轮询之间等待，
+    // 因为这里每处理一个事件都只是读写几个寄存器，持续轮询可以保证 RxFIFO 中的数据及时被读走
+    loop {
+        usb.poll();
+
+        let cur_state = usb.state();
+        if cur_state != last_state {
+            defmt::info!("{:?}", cur_state);
+            last_state = cur_state;
+        }
+    }
+}
+
+// HSE 12 MHz，SYSCLK 96 MHz，PLL48CLK 48 MHz
+//
+// 12 MHz / 6 * 96 = 192 MHz
+// 192 MHz / 2 = 96 MHz，作为 SYSCLK
+// 192 MHz / 4 = 48 MHz，作为 USB 的时钟
+fn setup_clock(dp: &pac::Peripherals) {
+    dp.RCC.cr.modify(|_, w| w.hseon().on());
+
+    dp.RCC.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(96);
+            w.pllq().bits(4);
+        }
+        w.pllp().div2();
+        w
+    });
+
+    // HCLK 超过 84 MHz，需要使用 Scale 1
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| unsafe { w.vos().bits(0b11) });
+
+    while dp.RCC.cr.read().hserdy().is_not_ready() {}
+
+    dp.RCC.cr.modify(|_, w| w.pllon().on());
+
+    // 90 MHz < HCLK <= 100 MHz，FLASH 读取需要等待 3 个周期
+    dp.FLASH.acr.modify(|_, w| {
+        w.latency().ws3();
+        w.dcen().enabled();
+        w.icen().enabled();
+        w.prften().enabled();
+        w
+    });
+
+    // APB1 最高 50 MHz
+    dp.RCC.cfgr.modify(|_, w| w.ppre1().div2());
+
+    while dp.PWR.csr.read().vosrdy().bit_is_clear() {}
+    while dp.RCC.cr.read().pllrdy().is_not_ready() {}
+
+    dp.RCC.cfgr.modify(|_, w| w.sw().pll());
+    while !dp.RCC.cfgr.read().sws().is_pll() {}
+}
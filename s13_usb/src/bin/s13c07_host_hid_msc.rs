@@ -0,0 +1,209 @@
+//! 直接操作寄存器实现的 USB host
+//!
+//! 前面的几节里，STM32 都是 device，这里反过来，让 STM32 作为 host，接入一个 USB 键盘或者 U 盘：
+//!
+//! - 接入键盘时，把键盘上敲下的字符通过 RTT 打印出来
+//! - 接入 U 盘时，打印 U 盘的容量，并读取第 0 个块（MBR），打印其中的分区表
+//!
+//! 拔掉设备之后，会回到等待设备接入的状态，可以换一个设备再试
+//!
+//! 与 s13c06 相同，没有使用任何 USB 相关的库，阅读顺序建议为：
+//! 1. utils::raw_usb_host 的模块说明，以及 UsbHost 的 init 与 wait_for_device
+//! 2. UsbHost 的 packet_in/packet_out，对照 Reference Manual 中 Host programming model 里 channel 的部分
+//! 3. utils::raw_usb_host::enumerate，与 s13c06 的 RawUsb::poll 对照，一边是发请求，一边是回应请求
+//! 4. utils::raw_usb_host::hid_keyboard 与 utils::raw_usb_host::msc
+//!
+//! 接线图：
+//!
+//! 需要一个 USB-A 母座
+//!
+//! USB-A 母座  <-> STM32 / 电源
+//! VBUS (红)   <-> 外部 5 V 电源（F413 的引脚无法输出 5 V，开发板的 5 V 也要确认能提供足够的电流，U 盘可能需要 100 mA 以上）
+//! D-   (白)   <-> PA11
+//! D+   (绿)   <-> PA12
+//! GND  (黑)   <-> GND（与外部电源共地）
+//!
+//! 注意：
+//! 作为 host 时，D+ 与 D- 上需要 15 kΩ 的下拉电阻，这由 OTG_FS 的 PHY 在 host 模式下自动提供，不需要外接
+//! 不要同时把开发板的 USB 口接到电脑上
+
+#![no_std]
+#![no_main]
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::raw_usb_host::{
+    delay_ms,
+    enumerate::{enumerate, DeviceInfo},
+    hid_keyboard::BootKeyboard,
+    msc::{BlockDevice, MassStorage, BLOCK_SIZE},
+    HostError, Pipe, UsbHost,
+};
+
+// 只有一个设备，地址固定为 1
+const DEVICE_ADDRESS: u8 = 1;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    defmt::info!("program start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_clock(&dp);
+
+    let mut host = UsbHost::init(&dp);
+
+    loop {
+        defmt::info!("waiting for device");
+        let speed = host.wait_for_device();
+        defmt::info!("device attached, {:?} Speed", speed);
+
+        match enumerate(&mut host, DEVICE_ADDRESS) {
+            Ok((info, mut ep0)) => {
+                defmt::info!(
+                    "VID {=u16:#06x}, PID {=u16:#06x}, bMaxPacketSize0 {}",
+                    info.vid,
+                    info.pid,
+                    info.max_packet0
+                );
+                for itf in info.interfaces.iter().flatten() {
+                    defmt::info!("{:?}", itf);
+                }
+
+                match BootKeyboard::setup(&mut host, &mut ep0, &info) {
+                    Ok(Some(keyboard)) => run_keyboard(&mut host, keyboard),
+                    Ok(None) => run_mass_storage(&mut host, &mut ep0, &info),
+                    Err(e) => defmt::error!("keyboard setup failed: {:?}", e),
+                }
+            }
+            Err(e) => defmt::error!("enumeration failed: {:?}", e),
+        }
+
+        // 等待设备被拔掉，释放所有 channel，回到初始状态
+        while host.is_connected() {}
+        host.free_all();
+        defmt::info!("device detached");
+    }
+}
+
+// 持续读取键盘，直到被拔掉
+fn run_keyboard(host: &mut UsbHost, mut keyboard: BootKeyboard) {
+    defmt::info!("boot keyboard ready, start typing");
+
+    loop {
+        match keyboard.poll_chars(host, |c| defmt::println!("{}", c)) {
+            Ok(()) => {}
+            Err(HostError::Disconnected) => break,
+            Err(e) => defmt::warn!("keyboard: {:?}", e),
+        }
+        delay_ms(keyboard.interval_ms());
+    }
+
+    keyboard.release(host);
+}
+
+fn run_mass_storage(host: &mut UsbHost, ep0: &mut Pipe, info: &DeviceInfo) {
+    let mut msc = match MassStorage::setup(host, ep0, info) {
+        Ok(Some(msc)) => msc,
+        Ok(None) => {
+            defmt::warn!("neither a boot keyboard nor a mass storage device");
+            return;
+        }
+        Err(e) => {
+            defmt::error!("mass storage setup failed: {:?}", e);
+            return;
+        }
+    };
+
+    let blocks = msc.block_count();
+    defmt::info!(
+        "mass storage ready, {} blocks, {} MiB",
+        blocks,
+        blocks as u64 * BLOCK_SIZE as u64 / 1024 / 1024
+    );
+
+    let mut block = [0u8; BLOCK_SIZE];
+    match msc.read_block(0, &mut block) {
+        Ok(()) => print_mbr(&block),
+        Err(e) => defmt::error!("read block 0 failed: {:?}", e),
+    }
+
+    msc.release();
+}
+
+// MBR 的最后两个字节为 0x55 0xAA，分区表从第 446 个字节开始，共 4 项，每项 16 个字节
+fn print_mbr(block: &[u8; BLOCK_SIZE]) {
+    if block[510..] != [0x55, 0xAA] {
+        defmt::info!("block 0 is not a MBR");
+        return;
+    }
+
+    for (i, entry) in block[446..510].chunks(16).enumerate() {
+        let kind = entry[4];
+        if kind == 0 {
+            continue;
+        }
+        let start = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]);
+        let sectors = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]);
+        defmt::info!(
+            "partition {}: type {=u8:#04x}, start LBA {}, {} sectors",
+            i,
+            kind,
+            start,
+            sectors
+        );
+    }
+}
+
+// 与 s13c06 相同
+//
+// HSE 12 MHz，SYSCLK 96 MHz，PLL48CLK 48 MHz
+//
+// 12 MHz / 6 * 96 = 192 MHz
+// 192 MHz / 2 = 96 MHz，作为 SYSCLK
+// 192 MHz / 4 = 48 MHz，作为 USB 的时钟
+fn setup_clock(dp: &pac::Peripherals) {
+    dp.RCC.cr.modify(|_, w| w.hseon().on());
+
+    dp.RCC.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(96);
+            w.pllq().bits(4);
+        }
+        w.pllp().div2();
+        w
+    });
+
+    // HCLK 超过 84 MHz，需要使用 Scale 1
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| unsafe { w.vos().bits(0b11) });
+
+    while dp.RCC.cr.read().hserdy().is_not_ready() {}
+
+    dp.RCC.cr.modify(|_, w| w.pllon().on());
+
+    // 90 MHz < HCLK <= 100 MHz，FLASH 读取需要等待 3 个周期
+    dp.FLASH.acr.modify(|_, w| {
+        w.latency().ws3();
+        w.dcen().enabled();
+        w.icen().enabled();
+        w.prften().enabled();
+        w
+    });
+
+    // APB1 最高 50 MHz
+    dp.RCC.cfgr.modify(|_, w| w.ppre1().div2());
+
+    while dp.PWR.csr.read().vosrdy().bit_is_clear() {}
+    while dp.RCC.cr.read().pllrdy().is_not_ready() {}
+
+    dp.RCC.cfgr.modify(|_, w| w.sw().pll());
+    while !dp.RCC.cfgr.read().sws().is_pll() {}
+}
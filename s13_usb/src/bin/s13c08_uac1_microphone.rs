@@ -0,0 +1,516 @@
+//! USB Audio Class 1.0 话筒
+//!
+//! 把 ADC 采集到的话筒信号，以 16 kHz、16 bit、单声道的 PCM 提供给 host，
+//! Linux（arecord -l）与 Windows（声音设置中的“输入”）都会把它识别为一个普通的 USB 话筒，不需要安装驱动
+//!
+//! 与之前的例子相比，这里第一次用到了 Isochronous（同步）传输：
+//!
+//! - host 每个帧（1 ms）都会读取一次 Isochronous IN endpoint，带宽在设备被配置时就预留好了
+//! - 数据出错不会重传，也没有握手，设备来不及准备数据，这一帧就只能是零长度包
+//! - 由于会占用预留的带宽，音频 interface 的 alternate setting 0 不带任何 endpoint，
+//!   host 开始录音时才通过 SET_INTERFACE 切换到带有 endpoint 的 alternate setting 1，停止录音时再切回 0
+//!
+//! 描述符的结构（UAC1 规范的 4.3 与 4.5 节）：
+//!
+//! ```text
+//! Interface 0：AudioControl
+//!   Header（class-specific，列出所有的 AudioStreaming interface）
+//!   Input Terminal  1：Microphone
+//!   Output Terminal 2：USB Streaming，数据来自 Terminal 1
+//! Interface 1：AudioStreaming
+//!   Alternate 0：没有 endpoint
+//!   Alternate 1：
+//!     AS General（连接到 Terminal 2，PCM 格式）
+//!     Format Type I（1 声道，2 byte，16 bit，16000 Hz）
+//!     Endpoint：Isochronous IN，Asynchronous
+//!     AS Endpoint（支持 Sampling Frequency Control）
+//! ```
+//!
+//! 关于采样率的“反馈”：
+//! ADC 的采样率由 HSE 决定，host 的帧由 host 自己的时钟决定，两者不可能完全相等，
+//! 如果每一帧都固定发送 16 个采样，utils::mic_adc 的环形缓冲迟早会溢出或者读空
+//! OUT 方向的异步 endpoint 需要一个单独的 feedback endpoint 告诉 host 实际的速率，
+//! 而 IN 方向的异步 endpoint 由设备自己决定每一帧的长度，host 按照实际收到的采样数计算速率即可
+//! 因此这里按照环形缓冲的填充程度，在每一帧发送 15、16 或 17 个采样，把填充程度保持在 TARGET_FILL 附近
+//!
+//! 发送的时机：
+//! DMA 每 1 ms 处理完半个缓冲之后，手动挂起 OTG_FS 中断（NVIC::pend），
+//! 这样即使 USB 上没有事件，OTG_FS 中断也会每 1 ms 运行一次，检查 endpoint 是否空闲，并写入下一帧的数据
+//!
+//! 每 1000 帧通过 defmt 输出一次统计：环形缓冲的填充程度，以及长包、短包、欠载、溢出的次数
+//!
+//! 接线图：
+//!
+//! 话筒模块（MAX4466 / MAX9814 等，输出偏置在 VCC / 2，使用 3.3V 供电）
+//!   OUT -> PA1（ADC1_1）
+//!   VCC -> 3.3V
+//!   GND -> GND
+//!
+//! 开发板的 USB 口（PA11 D-，PA12 D+）接到电脑上
+
+#![no_std]
+#![no_main]
+
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use cortex_m::{interrupt::Mutex, peripheral::NVIC};
+use defmt_rtt as _;
+use panic_probe as _;
+
+use stm32f4xx_hal::{
+    otg_fs::{UsbBusType, USB},
+    pac::{self, interrupt},
+    prelude::*,
+};
+use usb_device::{class_prelude::*, prelude::*};
+
+mod utils;
+
+use crate::mic_usb_class::MicUSBClass;
+use utils::{
+    clocks::Clocks,
+    mic_adc::{self, BUFFER_LEN},
+};
+
+static COUNT: AtomicU32 = AtomicU32::new(0);
+defmt::timestamp!("{}", COUNT.fetch_add(1, Ordering::Relaxed));
+
+static G_USB_DEVICE: Mutex<RefCell<Option<UsbDevice<UsbBusType>>>> = Mutex::new(RefCell::new(None));
+static G_MIC_USB_CLASS: Mutex<RefCell<Option<MicUSBClass<UsbBusType>>>> =
+    Mutex::new(RefCell::new(None));
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    static mut EP_OUT_MEM: [u32; 40] = [0u32; 40];
+    static mut USB_BUS_ALLOC: Option<UsbBusAllocator<UsbBusType>> = None;
+    static mut ADC_BUFFER: [u16; BUFFER_LEN] = [0; BUFFER_LEN];
+
+    defmt::info!("program start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    let rcc = dp.RCC.constrain();
+
+    let clocks = rcc
+        .cfgr
+        .use_hse(12.MHz())
+        .sysclk(96.MHz())
+        .require_pll48clk()
+        .freeze();
+
+    // 从 RCC 的寄存器读出实际的时钟，mic_adc 据此计算分频系数，并检查采样率能否准确得到
+    let actual = Clocks::read();
+    if let Err(e) = actual.check_limits().and_then(|_| actual.check_usb()) {
+        defmt::panic!("{}", defmt::Display2Format(&e));
+    }
+
+    let gpioa = dp.GPIOA.split();
+
+    // PA1 为 ADC1_1
+    let _mic_pin = gpioa.pa1.into_analog();
+
+    let usb = USB::new(
+        (dp.OTG_FS_GLOBAL, dp.OTG_FS_DEVICE, dp.OTG_FS_PWRCLK),
+        (gpioa.pa11, gpioa.pa12),
+        &clocks,
+    );
+
+    USB_BUS_ALLOC.replace(UsbBusType::new(usb, EP_OUT_MEM));
+    let usb_bus_alloc = USB_BUS_ALLOC.as_ref().unwrap();
+
+    let mic_usb_class = MicUSBClass::new(usb_bus_alloc);
+
+    let usb_device_builder = UsbDeviceBuilder::new(usb_bus_alloc, UsbVidPid(0x1209, 0x0001));
+    let default_desc = StringDescriptors::default()
+        .manufacturer("random manufacturer")
+        .product("random microphone")
+        .serial_number("random serial");
+    let usb_dev = usb_device_builder
+        .strings(&[default_desc])
+        .unwrap()
+        // 音频设备的 class 由 interface 决定
+        .device_class(0x00)
+        .build();
+
+    cortex_m::interrupt::free(|cs| {
+        G_USB_DEVICE.borrow(cs).borrow_mut().replace(usb_dev);
+        G_MIC_USB_CLASS
+            .borrow(cs)
+            .borrow_mut()
+            .replace(mic_usb_class);
+    });
+
+    // ADC 从这里开始以 48 kHz 连续采样，即使 host 还没有开始录音
+    if let Err(e) = mic_adc::start(ADC_BUFFER, 1, &actual) {
+        defmt::panic!("{}", defmt::Display2Format(&e));
+    }
+
+    unsafe { NVIC::unmask(interrupt::OTG_FS) }
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+#[interrupt]
+fn DMA2_STREAM0() {
+    // 有新的 PCM 采样时，让 OTG_FS 中断运行一次，把数据写入 endpoint
+    if mic_adc::on_dma2_stream0() {
+        NVIC::pend(interrupt::OTG_FS);
+    }
+}
+
+#[interrupt]
+fn OTG_FS() {
+    cortex_m::interrupt::free(|cs| {
+        let mut usb_device_mut = G_USB_DEVICE.borrow(cs).borrow_mut();
+        let usb_device = usb_device_mut.as_mut().unwrap();
+        let mut mic_usb_class_mut = G_MIC_USB_CLASS.borrow(cs).borrow_mut();
+        let mic_usb_class = mic_usb_class_mut.as_mut().unwrap();
+
+        usb_device.poll(&mut [mic_usb_class]);
+
+        // 无论 poll 有没有事件，都检查一次 endpoint，因为这里有可能是 DMA 中断挂起的
+        if usb_device.state() == UsbDeviceState::Configured {
+            mic_usb_class.service();
+        }
+    })
+}
+
+mod mic_usb_class {
+    use usb_device::{
+        class_prelude::*,
+        control::{Recipient, Request, RequestType},
+        device,
+        endpoint::{self, IsochronousSynchronizationType, IsochronousUsageType},
+    };
+
+    use crate::utils::mic_adc::{self, PCM_RATE_HZ};
+
+    // 每一帧的标称采样数
+    const SAMPLES_PER_FRAME: usize = (PCM_RATE_HZ / 1000) as usize;
+    // 最多比标称值多 1 个采样，endpoint 的 wMaxPacketSize 按它计算
+    const MAX_SAMPLES: usize = SAMPLES_PER_FRAME + 1;
+    const MAX_PACKET_SIZE: u16 = (MAX_SAMPLES * 2) as u16;
+
+    // 环形缓冲的目标填充程度，3 ms，host 帧与 DMA 中断之间的抖动不会让它读空
+    const TARGET_FILL: usize = SAMPLES_PER_FRAME * 3;
+    // 偏离目标这么多之后，才开始发送长包或短包，避免在 15 与 17 之间来回跳
+    const FILL_MARGIN: usize = SAMPLES_PER_FRAME / 2;
+
+    const STATS_FRAMES: u32 = 1000;
+
+    // UAC1 规范附录 A 中的常量
+    const AUDIO: u8 = 0x01;
+    const AUDIOCONTROL: u8 = 0x01;
+    const AUDIOSTREAMING: u8 = 0x02;
+
+    const CS_INTERFACE: u8 = 0x24;
+    const CS_ENDPOINT: u8 = 0x25;
+
+    const AC_HEADER: u8 = 0x01;
+    const AC_INPUT_TERMINAL: u8 = 0x02;
+    const AC_OUTPUT_TERMINAL: u8 = 0x03;
+    const AS_GENERAL: u8 = 0x01;
+    const AS_FORMAT_TYPE: u8 = 0x02;
+    const EP_GENERAL: u8 = 0x01;
+
+    const FORMAT_TYPE_I: u8 = 0x01;
+    const WAVE_FORMAT_PCM: u16 = 0x0001;
+    const TERMINAL_USB_STREAMING: u16 = 0x0101;
+    const TERMINAL_MICROPHONE: u16 = 0x0201;
+
+    const INPUT_TERMINAL_ID: u8 = 1;
+    const OUTPUT_TERMINAL_ID: u8 = 2;
+
+    // class-specific 请求
+    const SET_CUR: u8 = 0x01;
+    const GET_CUR: u8 = 0x81;
+    // endpoint 的 control selector，位于 wValue 的高字节
+    const SAMPLING_FREQ_CONTROL: u8 = 0x01;
+
+    pub(super) struct MicUSBClass<'a, B: UsbBus> {
+        control_iface: InterfaceNumber,
+        stream_iface: InterfaceNumber,
+        iso_in: EndpointIn<'a, B>,
+
+        // 当前 AudioStreaming interface 的 alternate setting，1 表示 host 正在录音
+        alt_setting: u8,
+        // 已经写入 endpoint、尚未被 host 取走
+        in_flight: bool,
+        // 打开音频流之后，先攒够 TARGET_FILL 个采样再开始发送
+        primed: bool,
+
+        frames: u32,
+        long_frames: u32,
+        short_frames: u32,
+        // 环形缓冲中的采样不够一帧
+        underruns: u32,
+    }
+
+    impl<'a, B: UsbBus> MicUSBClass<'a, B> {
+        pub(super) fn new(alloc: &'a UsbBusAllocator<B>) -> Self {
+            Self {
+                control_iface: alloc.interface(),
+                stream_iface: alloc.interface(),
+                // 每一帧都读取一次
+                iso_in: alloc.isochronous::<endpoint::In>(
+                    IsochronousSynchronizationType::Asynchronous,
+                    IsochronousUsageType::Data,
+                    MAX_PACKET_SIZE,
+                    1,
+                ),
+                alt_setting: 0,
+                in_flight: false,
+                primed: false,
+                frames: 0,
+                long_frames: 0,
+                short_frames: 0,
+                underruns: 0,
+            }
+        }
+
+        // 在每次 OTG_FS 中断中调用，endpoint 空闲时写入下一帧
+        pub(super) fn service(&mut self) {
+            if self.alt_setting != 1 || self.in_flight {
+                return;
+            }
+
+            let fill = mic_adc::fill();
+            if !self.primed {
+                if fill < TARGET_FILL {
+                    return;
+                }
+                self.primed = true;
+            }
+
+            // 按照填充程度决定这一帧的采样数，这就是 Asynchronous IN endpoint 的“速率反馈”
+            let wanted = if fill > TARGET_FILL + FILL_MARGIN {
+                self.long_frames += 1;
+                SAMPLES_PER_FRAME + 1
+            } else if fill < TARGET_FILL - FILL_MARGIN {
+                self.short_frames += 1;
+                SAMPLES_PER_FRAME - 1
+            } else {
+                SAMPLES_PER_FRAME
+            };
+
+            let mut samples = [0i16; MAX_SAMPLES];
+            let count = mic_adc::read(&mut samples[..wanted]);
+            if count < wanted {
+                self.underruns += 1;
+            }
+
+            let mut packet = [0u8; MAX_PACKET_SIZE as usize];
+            for (bytes, sample) in packet.chunks_exact_mut(2).zip(&samples[..count]) {
+                bytes.copy_from_slice(&sample.to_le_bytes());
+            }
+
+            match self.iso_in.write(&packet[..count * 2]) {
+                Ok(_) => self.in_flight = true,
+                Err(UsbError::WouldBlock) => (),
+                Err(e) => defmt::warn!("iso write: {:?}", e),
+            }
+
+            self.frames += 1;
+            if self.frames.is_multiple_of(STATS_FRAMES) {
+                defmt::info!(
+                    "frames {}, fill {}, long {}, short {}, underrun {}, overrun {}",
+                    self.frames,
+                    fill,
+                    self.long_frames,
+                    self.short_frames,
+                    self.underruns,
+                    mic_adc::overruns()
+                );
+            }
+        }
+
+        fn start_stream(&mut self) {
+            mic_adc::clear();
+            self.in_flight = false;
+            self.primed = false;
+            self.frames = 0;
+            self.long_frames = 0;
+            self.short_frames = 0;
+            self.underruns = 0;
+        }
+    }
+
+    impl<B: UsbBus> UsbClass<B> for MicUSBClass<'_, B> {
+        fn get_configuration_descriptors(
+            &self,
+            writer: &mut DescriptorWriter,
+        ) -> usb_device::Result<()> {
+            let stream_iface: u8 = self.stream_iface.into();
+
+            // AudioControl interface，没有 endpoint
+            writer.interface(self.control_iface, AUDIO, AUDIOCONTROL, 0x00)?;
+
+            // Header 中的 wTotalLength 包括 Header 本身与之后所有的 Terminal（9 + 12 + 9）
+            let total_len: u16 = 9 + 12 + 9;
+            let [total_lo, total_hi] = total_len.to_le_bytes();
+            #[rustfmt::skip]
+            let header = [
+                AC_HEADER,
+                0x00, 0x01,                         // bcdADC，1.00
+                total_lo, total_hi,                 // wTotalLength
+                1,                                  // bInCollection，一个 AudioStreaming interface
+                stream_iface,                       // baInterfaceNr(1)
+            ];
+            writer.write(CS_INTERFACE, &header)?;
+
+            let [mic_lo, mic_hi] = TERMINAL_MICROPHONE.to_le_bytes();
+            #[rustfmt::skip]
+            let input_terminal = [
+                AC_INPUT_TERMINAL,
+                INPUT_TERMINAL_ID,                  // bTerminalID
+                mic_lo, mic_hi,                     // wTerminalType
+                0,                                  // bAssocTerminal
+                1,                                  // bNrChannels
+                0x00, 0x00,                         // wChannelConfig，单声道没有空间位置
+                0,                                  // iChannelNames
+                0,                                  // iTerminal
+            ];
+            writer.write(CS_INTERFACE, &input_terminal)?;
+
+            let [usb_lo, usb_hi] = TERMINAL_USB_STREAMING.to_le_bytes();
+            #[rustfmt::skip]
+            let output_terminal = [
+                AC_OUTPUT_TERMINAL,
+                OUTPUT_TERMINAL_ID,                 // bTerminalID
+                usb_lo, usb_hi,                     // wTerminalType
+                0,                                  // bAssocTerminal
+                INPUT_TERMINAL_ID,                  // bSourceID
+                0,                                  // iTerminal
+            ];
+            writer.write(CS_INTERFACE, &output_terminal)?;
+
+            // AudioStreaming interface，alternate setting 0 不占用带宽
+            writer.interface_alt(
+                self.stream_iface,
+                device::DEFAULT_ALTERNATE_SETTING,
+                AUDIO,
+                AUDIOSTREAMING,
+                0x00,
+                None,
+            )?;
+
+            writer.interface_alt(self.stream_iface, 1, AUDIO, AUDIOSTREAMING, 0x00, None)?;
+
+            let [pcm_lo, pcm_hi] = WAVE_FORMAT_PCM.to_le_bytes();
+            #[rustfmt::skip]
+            let as_general = [
+                AS_GENERAL,
+                OUTPUT_TERMINAL_ID,                 // bTerminalLink
+                1,                                  // bDelay，单位为帧
+                pcm_lo, pcm_hi,                     // wFormatTag
+            ];
+            writer.write(CS_INTERFACE, &as_general)?;
+
+            let [f0, f1, f2, _] = PCM_RATE_HZ.to_le_bytes();
+            #[rustfmt::skip]
+            let format_type = [
+                AS_FORMAT_TYPE,
+                FORMAT_TYPE_I,                      // bFormatType
+                1,                                  // bNrChannels
+                2,                                  // bSubframeSize，每个采样 2 byte
+                16,                                 // bBitResolution
+                1,                                  // bSamFreqType，只有一个采样率
+                f0, f1, f2,                         // tSamFreq，3 byte
+            ];
+            writer.write(CS_INTERFACE, &format_type)?;
+
+            // 音频 endpoint 的描述符比标准的多出 2 byte：bRefresh 与 bSynchAddress，这里都不使用
+            writer.endpoint_ex(&self.iso_in, |extra| {
+                extra[0] = 0;
+                extra[1] = 0;
+                Ok(2)
+            })?;
+
+            #[rustfmt::skip]
+            let as_endpoint = [
+                EP_GENERAL,
+                0x01,                               // bmAttributes，支持 Sampling Frequency Control
+                0,                                  // bLockDelayUnits
+                0x00, 0x00,                         // wLockDelay
+            ];
+            writer.write(CS_ENDPOINT, &as_endpoint)?;
+
+            Ok(())
+        }
+
+        fn get_alt_setting(&mut self, interface: InterfaceNumber) -> Option<u8> {
+            if interface == self.stream_iface {
+                Some(self.alt_setting)
+            } else if interface == self.control_iface {
+                Some(device::DEFAULT_ALTERNATE_SETTING)
+            } else {
+                None
+            }
+        }
+
+        fn set_alt_setting(&mut self, interface: InterfaceNumber, alternative: u8) -> bool {
+            if interface != self.stream_iface || alternative > 1 {
+                return false;
+            }
+            if alternative == 1 && self.alt_setting != 1 {
+                defmt::info!("stream start");
+                self.start_stream();
+            } else if alternative == 0 && self.alt_setting == 1 {
+                defmt::info!("stream stop after {} frames", self.frames);
+            }
+            self.alt_setting = alternative;
+            true
+        }
+
+        fn reset(&mut self) {
+            self.alt_setting = 0;
+            self.in_flight = false;
+        }
+
+        fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+            if addr == self.iso_in.address() {
+                self.in_flight = false;
+            }
+        }
+
+        // 只支持一个采样率，SET_CUR 只接受 16000
+        fn control_out(&mut self, xfer: ControlOut<B>) {
+            let req = *xfer.request();
+            if !self.is_sampling_freq_request(&req) || req.request != SET_CUR {
+                return;
+            }
+
+            let data = xfer.data();
+            if data.len() == 3 && u32::from_le_bytes([data[0], data[1], data[2], 0]) == PCM_RATE_HZ
+            {
+                xfer.accept().ok();
+            } else {
+                xfer.reject().ok();
+            }
+        }
+
+        fn control_in(&mut self, xfer: ControlIn<B>) {
+            let req = *xfer.request();
+            if !self.is_sampling_freq_request(&req) || req.request != GET_CUR {
+                return;
+            }
+
+            let [f0, f1, f2, _] = PCM_RATE_HZ.to_le_bytes();
+            xfer.accept_with(&[f0, f1, f2]).ok();
+        }
+    }
+
+    impl<B: UsbBus> MicUSBClass<'_, B> {
+        // 发给 iso_in 的 Sampling Frequency Control 请求
+        fn is_sampling_freq_request(&self, req: &Request) -> bool {
+            req.request_type == RequestType::Class
+                && req.recipient == Recipient::Endpoint
+                && req.index as u8 == u8::from(self.iso_in.address())
+                && (req.value >> 8) as u8 == SAMPLING_FREQ_CONTROL
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! 直接操作寄存器实现的 Isochronous 回环设备
+//!
+//! 在 s13c06 的基础上，用 RawUsb::with_iso 创建设备，vendor interface 多出一个 alternate setting 1，
+//! 其中有 Isochronous IN（0x81）与 Isochronous OUT（0x02）两个 endpoint，最大包长均为 192 byte
+//! Isochronous endpoint 的处理见 utils::raw_usb::iso
+//!
+//! 这里的 IsoHandler 为 Loopback：
+//!
+//! - OUT 方向收到的包，会在下一个可用的帧原样从 IN 方向发回，包的第一个字节之前不加任何额外的内容
+//! - 没有待回环的数据时，IN 方向每一帧发送 4 byte：小端序的帧号 u16，以及一个递增的序号 u16，
+//!   host 可以据此检查丢失的帧，以及每一帧之间的时间间隔
+//!
+//! 每 1000 个 SOF 通过 defmt 输出一次 IsoStats，重点看：
+//!
+//! - sof_skipped：主循环没有及时处理 SOF，这里的 poll 只有寄存器读写，正常情况下应该一直是 0
+//! - in_missed：数据已经准备好，但 host 没有在那一帧来读，host 没有打开 IN 方向的传输时，这个数会一直增加
+//! - out_missed：准备好了接收，但那一帧 host 没有发送
+//!
+//! host 一侧需要先 SET_INTERFACE 切换到 alternate setting 1，然后用 libusb 的异步 Isochronous 传输收发，
+//! 在 Linux 上也可以用 usbmon 配合 Wireshark 观察每一帧的 Isochronous 包
+//!
+//! 接线图：
+//!
+//! 与 s13c01 相同，开发板的 USB 口（PA11 D-，PA12 D+）接到电脑上，注意 D+ 上不能有外部的上拉电阻
+
+#![no_std]
+#![no_main]
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::raw_usb::{
+    iso::{IsoHandler, ISO_MAX_PACKET},
+    RawUsb,
+};
+
+const STATS_SOF: u32 = 1000;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    defmt::info!("program start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_clock(&dp);
+
+    let mut usb = RawUsb::with_iso();
+    usb.init(&dp);
+
+    let mut loopback = Loopback::new();
+
+    let mut last_state = usb.state();
+    let mut last_active = false;
+    let mut last_report = 0;
+    defmt::info!("{:?}", last_state);
+
+    loop {
+        usb.poll_with(&mut loopback);
+
+        let cur_state = usb.state();
+        if cur_state != last_state {
+            defmt::info!("{:?}", cur_state);
+            last_state = cur_state;
+        }
+
+        let active = usb.iso_active();
+        if active != last_active {
+            defmt::info!("iso {}", if active { "start" } else { "stop" });
+            last_active = active;
+            last_report = 0;
+            loopback.reset();
+        }
+
+        if let Some(stats) = usb.iso_stats() {
+            if active && stats.sof >= last_report + STATS_SOF {
+                last_report = stats.sof;
+                defmt::info!("{}", stats);
+            }
+        }
+    }
+}
+
+struct Loopback {
+    pending: [u8; ISO_MAX_PACKET],
+    pending_len: usize,
+    seq: u16,
+}
+
+impl Loopback {
+    fn new() -> Self {
+        Self {
+            pending: [0; ISO_MAX_PACKET],
+            pending_len: 0,
+            seq: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pending_len = 0;
+        self.seq = 0;
+    }
+}
+
+impl IsoHandler for Loopback {
+    fn fill_in(&mut self, frame: u16, buf: &mut [u8]) -> usize {
+        if self.pending_len > 0 {
+            let len = self.pending_len;
+            buf[..len].copy_from_slice(&self.pending[..len]);
+            self.pending_len = 0;
+            return len;
+        }
+
+        buf[0..2].copy_from_slice(&frame.to_le_bytes());
+        buf[2..4].copy_from_slice(&self.seq.to_le_bytes());
+        self.seq = self.seq.wrapping_add(1);
+        4
+    }
+
+    // 上一个包还没来得及发回时，新的包直接覆盖它
+    fn on_out(&mut self, _frame: u16, data: &[u8]) {
+        self.pending[..data.len()].copy_from_slice(data);
+        self.pending_len = data.len();
+    }
+}
+
+// 与 s13c06 相同
+// HSE 12 MHz，SYSCLK 96 MHz，PLL48CLK 48 MHz
+fn setup_clock(dp: &pac::Peripherals) {
+    dp.RCC.cr.modify(|_, w| w.hseon().on());
+
+    dp.RCC.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(96);
+            w.pllq().bits(4);
+        }
+        w.pllp().div2();
+        w
+    });
+
+    // HCLK 超过 84 MHz，需要使用 Scale 1
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| unsafe { w.vos().bits(0b11) });
+
+    while dp.RCC.cr.read().hserdy().is_not_ready() {}
+
+    dp.RCC.cr.modify(|_, w| w.pllon().on());
+
+    // 90 MHz < HCLK <= 100 MHz，FLASH 读取需要等待 3 个周期
+    dp.FLASH.acr.modify(|_, w| {
+        w.latency().ws3();
+        w.dcen().enabled();
+        w.icen().enabled();
+        w.prften().enabled();
+        w
+    });
+
+    // APB1 最高 50 MHz
+    dp.RCC.cfgr.modify(|_, w| w.ppre1().div2());
+
+    while dp.PWR.csr.read().vosrdy().bit_is_clear() {}
+    while dp.RCC.cr.read().pllrdy().is_not_ready() {}
+
+    dp.RCC.cfgr.modify(|_, w| w.sw().pll());
+    while !dp.RCC.cfgr.read().sws().is_pll() {}
+}
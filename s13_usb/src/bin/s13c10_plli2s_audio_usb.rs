@@ -0,0 +1,204 @@
+//! 同时为 I2S 与 USB 提供准确的时钟
+//!
+//! I2S 的 MCK 需要是采样率的 256 倍（48 kHz 对应 12.288 MHz），USB 需要准确的 48 MHz，而 SYSCLK 又想尽量跑到 100 MHz，
+//! 一个主 PLL 很难同时满足这三者，STM32F413 为此多提供了一个 PLLI2S（见 utils::clocks 中 solve_plli2s 的说明）
+//!
+//! 程序的流程：
+//!
+//! 1. 用 HAL 把 SYSCLK 设置为 SYSCLK_HZ，此时主 PLL 的 Q 输出不一定是 48 MHz
+//! 2. 用 Clocks::check_usb 检查主 PLL 能否提供 USB 的时钟，不能时就要求 PLLI2S 同时提供 48 MHz
+//! 3. 列出几种常见采样率的求解结果，以及它们的误差（ppm），可以看到 PLLI2S 需要兼顾 USB 时，误差明显变大
+//! 4. 按照 SAMPLE_HZ 求解并启动 PLLI2S，误差超过 TOLERANCE_PPM 时 panic
+//! 5. 再次用 Clocks::check_usb 确认 48 MHz，之后启动 I2S2（主机发送，输出 MCK）与一个最简单的 USB 设备
+//!
+//! I2S2 一直发送一个 1 kHz 的方波，可以用示波器或逻辑分析仪测量 WS 的频率（即采样率）与 MCK 的频率，
+//! 与此同时，USB 设备应该能被电脑正常枚举（与 s13c01 相同，没有任何功能）
+//!
+//! 把 SYSCLK_HZ 改为 96 MHz，主 PLL 就能提供 48 MHz，PLLI2S 只为 I2S 服务，48 kHz 的误差会变为 0
+//!
+//! 接线图：
+//!
+//! PC6  I2S2_MCK  -> 示波器 / Codec 的 MCLK
+//! PB13 I2S2_CK   -> 示波器 / Codec 的 BCLK
+//! PB12 I2S2_WS   -> 示波器 / Codec 的 LRCK
+//! PB15 I2S2_SD   -> 示波器 / Codec 的 DIN
+//!
+//! 开发板的 USB 口（PA11 D-，PA12 D+）接到电脑上
+
+#![no_std]
+#![no_main]
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+use stm32f4xx_hal::{
+    otg_fs::{UsbBusType, USB},
+    pac,
+    prelude::*,
+};
+use usb_device::{class_prelude::*, prelude::*};
+
+mod utils;
+
+use utils::clocks::{self, Clocks, I2sRequest, Pll48Source, HSE_HZ};
+
+const SYSCLK_HZ: u32 = 100_000_000;
+const SAMPLE_HZ: u32 = 44_100;
+const TOLERANCE_PPM: u32 = 500;
+
+// 列表中展示的采样率
+const SHOWCASE_HZ: [u32; 5] = [8_000, 16_000, 44_100, 48_000, 96_000];
+
+// 方波的频率与幅度
+const TONE_HZ: u32 = 1_000;
+const TONE_AMPLITUDE: i16 = 8_000;
+
+// SPI_I2SCFGR：I2SMOD、I2SCFG = 10（主机发送），Philips 标准，16 bit 数据，16 bit 声道
+const I2SCFGR_I2SMOD: u32 = 1 << 11;
+const I2SCFGR_MASTER_TX: u32 = 0b10 << 8;
+const I2SCFGR_I2SE: u32 = 1 << 10;
+
+static mut EP_OUT_MEM: [u32; 2] = [0u32; 2];
+
+struct EmptyClass {
+    iface_index: InterfaceNumber,
+}
+
+impl<B: UsbBus> UsbClass<B> for EmptyClass {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        writer.interface(self.iface_index, 0xFF, 0x00, 0x00)?;
+        Ok(())
+    }
+}
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    defmt::info!("program start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.use_hse(12.MHz()).sysclk(SYSCLK_HZ.Hz()).freeze();
+
+    // 安全性：RCC 已经被 HAL 拿走了，这里只访问 HAL 不会再修改的 PLLI2S 相关的寄存器
+    let rcc_regs = unsafe { &*pac::RCC::ptr() };
+
+    let pll48 = match Clocks::read().check_usb() {
+        Ok(()) => Pll48Source::MainPll,
+        Err(e) => {
+            defmt::info!(
+                "main PLL: {}, PLLI2S has to provide it",
+                defmt::Display2Format(&e)
+            );
+            Pll48Source::PllI2s
+        }
+    };
+
+    for sample_hz in SHOWCASE_HZ {
+        for source in [Pll48Source::MainPll, Pll48Source::PllI2s] {
+            let request = I2sRequest {
+                sample_hz,
+                mclk: true,
+                channel_bits: 16,
+                tolerance_ppm: u32::MAX,
+            };
+            match clocks::solve_plli2s(HSE_HZ, &request, source) {
+                Ok(config) => defmt::info!(
+                    "{} Hz, 48 MHz from {}: {}",
+                    sample_hz,
+                    source,
+                    defmt::Display2Format(&config)
+                ),
+                Err(e) => defmt::info!(
+                    "{} Hz, 48 MHz from {}: {}",
+                    sample_hz,
+                    source,
+                    defmt::Display2Format(&e)
+                ),
+            }
+        }
+    }
+
+    let request = I2sRequest {
+        sample_hz: SAMPLE_HZ,
+        mclk: true,
+        channel_bits: 16,
+        tolerance_ppm: TOLERANCE_PPM,
+    };
+    let config = match clocks::solve_plli2s(HSE_HZ, &request, pll48)
+        .and_then(|config| config.apply(rcc_regs).map(|_| config))
+    {
+        Ok(config) => config,
+        Err(e) => defmt::panic!("{}", defmt::Display2Format(&e)),
+    };
+    defmt::info!("using {}", defmt::Display2Format(&config));
+
+    let actual = Clocks::read();
+    if let Err(e) = actual.check_limits().and_then(|_| actual.check_usb()) {
+        defmt::panic!("{}", defmt::Display2Format(&e));
+    }
+    defmt::info!("clocks: {}", actual);
+
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+    let gpioc = dp.GPIOC.split();
+
+    let _ws = gpiob.pb12.into_alternate::<5>();
+    let _ck = gpiob.pb13.into_alternate::<5>();
+    let _sd = gpiob.pb15.into_alternate::<5>();
+    let _mck = gpioc.pc6.into_alternate::<5>();
+
+    rcc_regs.apb1enr.modify(|_, w| w.spi2en().enabled());
+    let i2s = &dp.SPI2;
+    i2s.i2scfgr
+        .write(|w| unsafe { w.bits(I2SCFGR_I2SMOD | I2SCFGR_MASTER_TX) });
+    config.apply_prescaler(i2s);
+    i2s.i2scfgr
+        .modify(|r, w| unsafe { w.bits(r.bits() | I2SCFGR_I2SE) });
+
+    let usb = USB::new(
+        (dp.OTG_FS_GLOBAL, dp.OTG_FS_DEVICE, dp.OTG_FS_PWRCLK),
+        (gpioa.pa11, gpioa.pa12),
+        &clocks,
+    );
+    let usb_bus_alloc = UsbBusType::new(usb, unsafe { &mut *core::ptr::addr_of_mut!(EP_OUT_MEM) });
+    let mut class = EmptyClass {
+        iface_index: usb_bus_alloc.interface(),
+    };
+    let default_desc = StringDescriptors::default()
+        .manufacturer("random manufacturer")
+        .product("plli2s demo")
+        .serial_number("random serial");
+    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus_alloc, UsbVidPid(0x1209, 0x0001))
+        .strings(&[default_desc])
+        .unwrap()
+        .build();
+
+    // 每半个周期的帧数，每帧左右两个声道
+    let half_period = SAMPLE_HZ / TONE_HZ / 2;
+    let mut frame = 0u32;
+    let mut right = false;
+
+    loop {
+        usb_dev.poll(&mut [&mut class]);
+
+        if i2s.sr.read().txe().bit_is_clear() {
+            continue;
+        }
+        let high = (frame / half_period).is_multiple_of(2);
+        let sample = if high {
+            TONE_AMPLITUDE
+        } else {
+            -TONE_AMPLITUDE
+        };
+        i2s.dr.write(|w| w.dr().bits(sample as u16));
+
+        if right {
+            frame = frame.wrapping_add(1);
+        }
+        right = !right;
+    }
+}
@@ -0,0 +1,554 @@
+//! 从 RCC 的寄存器读出实际的时钟频率，并检查驱动对时钟的要求
+//!
+//! 驱动里的分频系数都是根据某个时钟算出来的：I2C 的 FREQ/CCR/TRISE 取决于 PCLK1，ADC 的预分频取决于 PCLK2，
+//! TIM 的 ARR 取决于 TIMCLK，USB 则要求 PLL48CLK 恰好是 48 MHz
+//! 以前这些频率都是由调用者以常量或参数的形式传进来的，一旦和实际的设置对不上（比如改了 SYSCLK，却忘了 APB1 多了一个 2 分频），
+//! 外设依旧会“工作”，只是波特率、采样率不对，很难查
+//!
+//! 因此这里：
+//!
+//! - Clocks::read 直接从 RCC 的 CFGR、PLLCFGR 算出当前的各个时钟，不管时钟是 HAL 配置的，还是直接写寄存器配置的
+//! - 驱动在初始化时用 check_* 检查自己的要求，不满足时返回 ClockError，而不是带着错误的分频系数继续运行
+//! - ClockError 实现了 Display，可以直接得到 "ADCCLK 45 MHz exceeds 36 MHz limit" 这样的描述
+//! - solve_plli2s 为 I2S 计算 PLLI2S 与 I2S 预分频的设置，必要时同时让 PLLI2S 提供 USB 的 48 MHz（见下方的说明）
+//!
+//! 假设：
+//! - HSE 为 12 MHz（与 s01 相同）
+//! - TIMPRE 为 0，APB 分频系数为 1 时 TIMCLK = PCLK，否则 TIMCLK = 2 * PCLK
+//! - PLL48CLK 来自主 PLL 的 Q 输出，或者 PLLI2S 的 Q 输出，由 DCKCFGR2 的 CK48MSEL 决定；
+//!   F401/F411 没有 DCKCFGR2，PLL48CLK 只能来自主 PLL
+//! - PLLI2S 的输入与主 PLL 相同（PLLI2SCFGR 的 PLLI2SSRC 为 0），而不是外部的 I2S_CKIN
+
+#![allow(dead_code)]
+
+use core::fmt;
+
+use stm32f4xx_hal::pac;
+
+pub(crate) const HSE_HZ: u32 = 12_000_000;
+pub(crate) const HSI_HZ: u32 = 16_000_000;
+
+// STM32F413 的上限，见 Datasheet 的 General operating conditions
+pub(crate) const SYSCLK_MAX_HZ: u32 = 100_000_000;
+pub(crate) const PCLK1_MAX_HZ: u32 = 50_000_000;
+pub(crate) const PCLK2_MAX_HZ: u32 = 100_000_000;
+// USB 要求 48 MHz ±0.25%
+pub(crate) const USB_HZ: u32 = 48_000_000;
+pub(crate) const USB_TOLERANCE_PPM: u32 = 2500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Clocks {
+    pub(crate) sysclk: u32,
+    pub(crate) hclk: u32,
+    pub(crate) pclk1: u32,
+    pub(crate) pclk2: u32,
+    pub(crate) timclk1: u32,
+    pub(crate) timclk2: u32,
+    // PLL 没有打开时为 None
+    pub(crate) pll48: Option<u32>,
+    // PLLI2S 的 R 输出，也就是 I2S 的时钟源，PLLI2S 没有打开时为 None
+    pub(crate) plli2s_r: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum ClockError {
+    TooHigh {
+        clock: &'static str,
+        hz: u32,
+        max_hz: u32,
+    },
+    TooLow {
+        clock: &'static str,
+        hz: u32,
+        min_hz: u32,
+    },
+    // 分频之后得不到准确的频率
+    NotMultiple {
+        clock: &'static str,
+        hz: u32,
+        of_hz: u32,
+    },
+    Inaccurate {
+        clock: &'static str,
+        hz: u32,
+        want_hz: u32,
+        tolerance_ppm: u32,
+    },
+    // 需要的时钟没有打开
+    Missing {
+        clock: &'static str,
+    },
+    // 这个时钟正在为某个外设提供时钟，此时修改它会让外设出错
+    InUse {
+        clock: &'static str,
+        user: &'static str,
+    },
+}
+
+impl fmt::Display for ClockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClockError::TooHigh { clock, hz, max_hz } => {
+                write!(f, "{} {} exceeds {} limit", clock, Hz(hz), Hz(max_hz))
+            }
+            ClockError::TooLow { clock, hz, min_hz } => {
+                write!(f, "{} {} is below {} minimum", clock, Hz(hz), Hz(min_hz))
+            }
+            ClockError::NotMultiple { clock, hz, of_hz } => {
+                write!(f, "{} {} is not a multiple of {}", clock, Hz(hz), Hz(of_hz))
+            }
+            ClockError::Inaccurate {
+                clock,
+                hz,
+                want_hz,
+                tolerance_ppm,
+            } => write!(
+                f,
+                "{} {} is not within {} ppm of {}",
+                clock,
+                Hz(hz),
+                tolerance_ppm,
+                Hz(want_hz)
+            ),
+            ClockError::Missing { clock } => write!(f, "{} is not running", clock),
+            ClockError::InUse { clock, user } => {
+                write!(f, "{} is in use by {}, refusing to change it", clock, user)
+            }
+        }
+    }
+}
+
+// 以合适的单位显示频率，比如 45 MHz、12.5 MHz、48 kHz
+struct Hz(u32);
+
+impl fmt::Display for Hz {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (value, unit) = match self.0 {
+            1_000_000.. => (self.0 as f32 / 1e6, "MHz"),
+            1_000.. => (self.0 as f32 / 1e3, "kHz"),
+            _ => (self.0 as f32, "Hz"),
+        };
+        write!(f, "{} {}", value, unit)
+    }
+}
+
+impl Clocks {
+    pub(crate) fn read() -> Self {
+        let rcc = unsafe { &*pac::RCC::ptr() };
+        let cfgr = rcc.cfgr.read().bits();
+        let pllcfgr = rcc.pllcfgr.read().bits();
+
+        // PLL 的输入为 HSE 或 HSI，VCO = 输入 / M * N
+        let pll_on = rcc.cr.read().pllrdy().is_ready();
+        let pll_in = if pllcfgr & (1 << 22) != 0 {
+            HSE_HZ
+        } else {
+            HSI_HZ
+        };
+        let pllm = pllcfgr & 0x3F;
+        let plln = (pllcfgr >> 6) & 0x1FF;
+        let pllp = (((pllcfgr >> 16) & 0b11) + 1) * 2;
+        let pllq = (pllcfgr >> 24) & 0xF;
+        let vco = (pll_in as u64 * plln as u64 / pllm.max(1) as u64) as u32;
+
+        // PLLI2S 的结构与主 PLL 相同，VCO = 输入 / PLLI2SM * PLLI2SN
+        let plli2scfgr = rcc.plli2scfgr.read().bits();
+        // 输入为外部的 I2S_CKIN 时，频率无从得知，当作没有打开
+        let plli2s_on = rcc.cr.read().plli2srdy().bit_is_set() && plli2scfgr & (1 << 22) == 0;
+        let plli2sm = plli2scfgr & 0x3F;
+        let plli2sn = (plli2scfgr >> 6) & 0x1FF;
+        let plli2sq = (plli2scfgr >> 24) & 0xF;
+        let plli2sr = (plli2scfgr >> 28) & 0b111;
+        let plli2s_vco = (pll_in as u64 * plli2sn as u64 / plli2sm.max(1) as u64) as u32;
+        let ck48msel = read_ck48msel(rcc);
+
+        let sysclk = match (cfgr >> 2) & 0b11 {
+            0b00 => HSI_HZ,
+            0b01 => HSE_HZ,
+            _ => vco / pllp,
+        };
+
+        let hpre = match (cfgr >> 4) & 0xF {
+            0b1000 => 2,
+            0b1001 => 4,
+            0b1010 => 8,
+            0b1011 => 16,
+            0b1100 => 64,
+            0b1101 => 128,
+            0b1110 => 256,
+            0b1111 => 512,
+            _ => 1,
+        };
+        let ppre = |bits: u32| match bits & 0b111 {
+            0b100 => 2,
+            0b101 => 4,
+            0b110 => 8,
+            0b111 => 16,
+            _ => 1,
+        };
+        let ppre1 = ppre(cfgr >> 10);
+        let ppre2 = ppre(cfgr >> 13);
+
+        let hclk = sysclk / hpre;
+        let pclk1 = hclk / ppre1;
+        let pclk2 = hclk / ppre2;
+        let timclk = |pclk: u32, ppre: u32| if ppre == 1 { pclk } else { pclk * 2 };
+
+        Self {
+            sysclk,
+            hclk,
+            pclk1,
+            pclk2,
+            timclk1: timclk(pclk1, ppre1),
+            timclk2: timclk(pclk2, ppre2),
+            // PLLQ、PLLI2SQ 为 0 或 1，PLLI2SR 为 0 或 1 是非法的设置
+            pll48: if ck48msel {
+                (plli2s_on && plli2sq >= 2).then(|| plli2s_vco / plli2sq)
+            } else {
+                (pll_on && pllq >= 2).then(|| vco / pllq)
+            },
+            plli2s_r: (plli2s_on && plli2sr >= 2).then(|| plli2s_vco / plli2sr),
+        }
+    }
+
+    // 芯片本身的限制
+    pub(crate) fn check_limits(&self) -> Result<(), ClockError> {
+        check_max("SYSCLK", self.sysclk, SYSCLK_MAX_HZ)?;
+        check_max("PCLK1", self.pclk1, PCLK1_MAX_HZ)?;
+        check_max("PCLK2", self.pclk2, PCLK2_MAX_HZ)?;
+        Ok(())
+    }
+
+    // OTG_FS 需要 48 MHz ±0.25%
+    pub(crate) fn check_usb(&self) -> Result<(), ClockError> {
+        let pll48 = self
+            .pll48
+            .ok_or(ClockError::Missing { clock: "PLL48CLK" })?;
+        check_accuracy("PLL48CLK", pll48, USB_HZ, USB_TOLERANCE_PPM)
+    }
+}
+
+pub(crate) fn check_max(clock: &'static str, hz: u32, max_hz: u32) -> Result<(), ClockError> {
+    if hz > max_hz {
+        return Err(ClockError::TooHigh { clock, hz, max_hz });
+    }
+    Ok(())
+}
+
+pub(crate) fn check_min(clock: &'static str, hz: u32, min_hz: u32) -> Result<(), ClockError> {
+    if hz < min_hz {
+        return Err(ClockError::TooLow { clock, hz, min_hz });
+    }
+    Ok(())
+}
+
+pub(crate) fn check_range(
+    clock: &'static str,
+    hz: u32,
+    min_hz: u32,
+    max_hz: u32,
+) -> Result<(), ClockError> {
+    check_min(clock, hz, min_hz)?;
+    check_max(clock, hz, max_hz)
+}
+
+// hz 能被 of_hz 整除，比如定时器的时钟能否分频得到准确的采样率
+pub(crate) fn check_multiple(clock: &'static str, hz: u32, of_hz: u32) -> Result<(), ClockError> {
+    if of_hz == 0 || !hz.is_multiple_of(of_hz) {
+        return Err(ClockError::NotMultiple { clock, hz, of_hz });
+    }
+    Ok(())
+}
+
+pub(crate) fn check_accuracy(
+    clock: &'static str,
+    hz: u32,
+    want_hz: u32,
+    tolerance_ppm: u32,
+) -> Result<(), ClockError> {
+    let error_ppm = (hz as i64 - want_hz as i64).unsigned_abs() * 1_000_000 / want_hz as u64;
+    if error_ppm > tolerance_ppm as u64 {
+        return Err(ClockError::Inaccurate {
+            clock,
+            hz,
+            want_hz,
+            tolerance_ppm,
+        });
+    }
+    Ok(())
+}
+
+// PLLI2S 与 I2S 预分频
+//
+// 时钟的路径为：
+//
+// ```text
+// PLL 输入（HSE）/ PLLI2SM -> VCO 输入（1 ~ 2 MHz）* PLLI2SN -> VCO（100 ~ 432 MHz）
+//   / PLLI2SR -> I2SCLK / (2 * I2SDIV + ODD) -> MCK = 256 * Fs（输出 MCK 时）
+//                                            -> CK = 2 * 声道位数 * Fs（不输出 MCK 时）
+//   / PLLI2SQ -> PLL48CLK（CK48MSEL 为 1 时）
+// ```
+//
+// 音频的采样率（48 kHz、44.1 kHz）与 12 MHz 的 HSE 之间没有简单的整数关系，只能在所有的 M、N、R、I2SDIV 中找误差最小的一组，
+// 误差以 ppm 给出：几十 ppm 的误差听不出来，但如果 I2S 的另一端（比如 USB 的 Host）按照准确的采样率收发数据，
+// 缓冲迟早会溢出或者读空，误差越大发生得越早
+//
+// 主 PLL 为了得到某个 SYSCLK（比如 100 MHz），有时无法同时从 Q 输出 48 MHz，此时 USB 的时钟只能由 PLLI2S 提供，
+// VCO 就必须是 48 MHz 的整数倍，I2S 能选的频率少了很多，误差通常也会变大；solve_plli2s 的 pll48 参数就是用来说明这种情况的
+
+pub(crate) const PLLI2S_VCO_IN_MIN_HZ: u32 = 1_000_000;
+pub(crate) const PLLI2S_VCO_IN_MAX_HZ: u32 = 2_000_000;
+pub(crate) const PLLI2S_VCO_MIN_HZ: u32 = 100_000_000;
+pub(crate) const PLLI2S_VCO_MAX_HZ: u32 = 432_000_000;
+
+// 各个分频、倍频系数的合法范围，见 Reference Manual 的 RCC_PLLI2SCFGR 与 SPI_I2SPR
+const PLLI2SM_RANGE: core::ops::RangeInclusive<u32> = 2..=63;
+const PLLI2SN_RANGE: core::ops::RangeInclusive<u32> = 50..=432;
+const PLLI2SQ_RANGE: core::ops::RangeInclusive<u32> = 2..=15;
+const PLLI2SR_RANGE: core::ops::RangeInclusive<u32> = 2..=7;
+const I2SDIV_RANGE: core::ops::RangeInclusive<u32> = 2..=255;
+
+// 输出 MCK 时，MCK 固定为 256 * Fs
+const MCK_PER_FS: u32 = 256;
+
+// AHB2ENR 的 OTGFSEN
+const AHB2ENR_OTGFSEN: u32 = 1 << 7;
+// DCKCFGR2 的 CK48MSEL
+const DCKCFGR2_CK48MSEL: u32 = 1 << 27;
+// DCKCFGR 的 I2S1SRC（APB2 上的 I2S：SPI1、4、5）与 I2S2SRC（APB1 上的 I2S：SPI2、3），00 表示 PLLI2S 的 R 输出
+const DCKCFGR_I2S1SRC_MASK: u32 = 0b11 << 25;
+const DCKCFGR_I2S2SRC_MASK: u32 = 0b11 << 27;
+
+// DCKCFGR2 只有 F412/F413 才有，F401/F411 的 PLL48CLK 固定来自主 PLL
+const HAS_CK48MSEL: bool = cfg!(any(feature = "stm32f412", feature = "stm32f413"));
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn read_ck48msel(rcc: &pac::rcc::RegisterBlock) -> bool {
+    rcc.dckcfgr2.read().bits() & DCKCFGR2_CK48MSEL != 0
+}
+
+#[cfg(not(any(feature = "stm32f412", feature = "stm32f413")))]
+fn read_ck48msel(_rcc: &pac::rcc::RegisterBlock) -> bool {
+    false
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn write_ck48msel(rcc: &pac::rcc::RegisterBlock, from_plli2s: bool) {
+    rcc.dckcfgr2.modify(|r, w| unsafe {
+        w.bits(if from_plli2s {
+            r.bits() | DCKCFGR2_CK48MSEL
+        } else {
+            r.bits() & !DCKCFGR2_CK48MSEL
+        })
+    });
+}
+
+// solve_plli2s 在这些芯片上不会给出 pll48，这里也就没有什么需要切换的
+#[cfg(not(any(feature = "stm32f412", feature = "stm32f413")))]
+fn write_ck48msel(_rcc: &pac::rcc::RegisterBlock, _from_plli2s: bool) {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct I2sRequest {
+    pub(crate) sample_hz: u32,
+    // 是否输出 MCK，大多数音频 Codec 都需要它
+    pub(crate) mclk: bool,
+    // 每个声道的位数，16 或 32，只在不输出 MCK 时影响分频
+    pub(crate) channel_bits: u32,
+    // 超过这个误差时，solve_plli2s 返回 Err
+    pub(crate) tolerance_ppm: u32,
+}
+
+// USB 的 48 MHz 由谁提供
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Pll48Source {
+    // 主 PLL 的 Q 输出已经是 48 MHz 了（用 Clocks::check_usb 确认），PLLI2S 只为 I2S 服务
+    MainPll,
+    // PLLI2S 的 Q 输出必须是 48 MHz
+    PllI2s,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct PllI2sConfig {
+    pub(crate) m: u32,
+    pub(crate) n: u32,
+    pub(crate) r: u32,
+    // 只有 Pll48Source::PllI2s 时才有
+    pub(crate) q: Option<u32>,
+    pub(crate) i2sdiv: u32,
+    pub(crate) odd: bool,
+    pub(crate) mclk: bool,
+    pub(crate) i2sclk: u32,
+    // 实际的采样率，单位为 mHz，44.1 kHz 这样的频率通常得不到整数
+    pub(crate) sample_mhz: u64,
+    // 实际的采样率相对于要求的误差，为正时偏快
+    pub(crate) error_ppm: i32,
+    pub(crate) pll48: Option<u32>,
+}
+
+impl fmt::Display for PllI2sConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "M={} N={} R={} I2SDIV={} ODD={}: I2SCLK {}, Fs {}.{:03} Hz ({:+} ppm)",
+            self.m,
+            self.n,
+            self.r,
+            self.i2sdiv,
+            self.odd as u8,
+            Hz(self.i2sclk),
+            self.sample_mhz / 1000,
+            self.sample_mhz % 1000,
+            self.error_ppm
+        )?;
+        match (self.q, self.pll48) {
+            (Some(q), Some(pll48)) => write!(f, ", Q={} PLL48CLK {}", q, Hz(pll48)),
+            _ => Ok(()),
+        }
+    }
+}
+
+// 在所有合法的 M、N、R（以及 Q）中，找采样率误差最小的一组
+//
+// 误差相同时，优先选 VCO 输入频率较高（M 较小）的一组，VCO 输入越高，PLL 的抖动越小
+// 一共只有几万种组合，在 MCU 上也只需要几毫秒，可以在启动时直接计算
+pub(crate) fn solve_plli2s(
+    pll_in_hz: u32,
+    request: &I2sRequest,
+    pll48: Pll48Source,
+) -> Result<PllI2sConfig, ClockError> {
+    let per_fs = if request.mclk {
+        MCK_PER_FS
+    } else {
+        2 * request.channel_bits
+    };
+    // 以 mHz 为单位计算，避免 44.1 kHz 之类的频率的舍入误差
+    let want_mhz = request.sample_hz as u64 * 1000;
+
+    // F401/F411 没有 CK48MSEL，PLLI2S 的 Q 输出不能作为 USB 的时钟
+    if pll48 == Pll48Source::PllI2s && !HAS_CK48MSEL {
+        return Err(ClockError::Missing {
+            clock: "PLLI2S with PLL48CLK",
+        });
+    }
+
+    let mut best: Option<PllI2sConfig> = None;
+
+    for m in PLLI2SM_RANGE {
+        let vco_in = pll_in_hz / m;
+        if !pll_in_hz.is_multiple_of(m)
+            || !(PLLI2S_VCO_IN_MIN_HZ..=PLLI2S_VCO_IN_MAX_HZ).contains(&vco_in)
+        {
+            continue;
+        }
+
+        for n in PLLI2SN_RANGE {
+            let vco = vco_in * n;
+            if !(PLLI2S_VCO_MIN_HZ..=PLLI2S_VCO_MAX_HZ).contains(&vco) {
+                continue;
+            }
+
+            let q = match pll48 {
+                Pll48Source::MainPll => None,
+                Pll48Source::PllI2s => {
+                    // USB 的时钟要求是硬性的，VCO 不是 48 MHz 的整数倍时直接跳过
+                    if !vco.is_multiple_of(USB_HZ) || !PLLI2SQ_RANGE.contains(&(vco / USB_HZ)) {
+                        continue;
+                    }
+                    Some(vco / USB_HZ)
+                }
+            };
+
+            for r in PLLI2SR_RANGE {
+                let i2sclk = vco / r;
+                // 2 * I2SDIV + ODD，取最接近的整数
+                let total = ((i2sclk as u64 * 1000 + want_mhz * per_fs as u64 / 2)
+                    / (want_mhz * per_fs as u64)) as u32;
+                if !I2SDIV_RANGE.contains(&(total / 2)) {
+                    continue;
+                }
+
+                let sample_mhz = i2sclk as u64 * 1000 / (per_fs as u64 * total as u64);
+                let error_ppm =
+                    ((sample_mhz as i64 - want_mhz as i64) * 1_000_000 / want_mhz as i64) as i32;
+
+                if best.is_some_and(|best| best.error_ppm.abs() <= error_ppm.abs()) {
+                    continue;
+                }
+                best = Some(PllI2sConfig {
+                    m,
+                    n,
+                    r,
+                    q,
+                    i2sdiv: total / 2,
+                    odd: total % 2 == 1,
+                    mclk: request.mclk,
+                    i2sclk,
+                    sample_mhz,
+                    error_ppm,
+                    pll48: q.map(|q| vco / q),
+                });
+            }
+        }
+    }
+
+    let best = best.ok_or(ClockError::Missing {
+        clock: match pll48 {
+            Pll48Source::MainPll => "PLLI2S",
+            Pll48Source::PllI2s => "PLLI2S with PLL48CLK",
+        },
+    })?;
+
+    if best.error_ppm.unsigned_abs() > request.tolerance_ppm {
+        return Err(ClockError::Inaccurate {
+            clock: "I2S Fs",
+            hz: (best.sample_mhz / 1000) as u32,
+            want_hz: request.sample_hz,
+            tolerance_ppm: request.tolerance_ppm,
+        });
+    }
+    Ok(best)
+}
+
+impl PllI2sConfig {
+    // 按照这组设置重新启动 PLLI2S，并把 I2S1、I2S2 的时钟源都设置为 PLLI2S 的 R 输出
+    //
+    // PLLI2S 只能在关闭时修改，关闭期间它的所有输出都会停止，因此 OTG_FS 的时钟已经打开时：
+    // - 如果 USB 正在使用 PLLI2S 的 48 MHz，不能关闭它
+    // - 如果这组设置要求 USB 改用 PLLI2S 的 48 MHz，切换的瞬间 USB 的时钟也会中断
+    // 这两种情况都返回 ClockError::InUse，请在初始化 USB 之前调用
+    pub(crate) fn apply(&self, rcc: &pac::rcc::RegisterBlock) -> Result<(), ClockError> {
+        let usb_on = rcc.ahb2enr.read().bits() & AHB2ENR_OTGFSEN != 0;
+        if usb_on && (read_ck48msel(rcc) || self.pll48.is_some()) {
+            return Err(ClockError::InUse {
+                clock: "PLLI2S",
+                user: "OTG_FS",
+            });
+        }
+
+        rcc.cr.modify(|_, w| w.plli2son().clear_bit());
+        while rcc.cr.read().plli2srdy().bit_is_set() {}
+
+        // Q 不用于 USB 时，也必须是合法的值，这里取最大的分频系数
+        let q = self.q.unwrap_or(*PLLI2SQ_RANGE.end());
+        // PLLI2SSRC 为 0，输入与主 PLL 相同
+        let bits = self.m | (self.n << 6) | (q << 24) | (self.r << 28);
+        rcc.plli2scfgr.write(|w| unsafe { w.bits(bits) });
+
+        rcc.dckcfgr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(DCKCFGR_I2S1SRC_MASK | DCKCFGR_I2S2SRC_MASK))
+        });
+
+        rcc.cr.modify(|_, w| w.plli2son().set_bit());
+        while rcc.cr.read().plli2srdy().bit_is_clear() {}
+
+        // 不由 PLLI2S 提供 48 MHz 时，切回主 PLL 的 Q 输出
+        write_ck48msel(rcc, self.pll48.is_some());
+        Ok(())
+    }
+
+    // 写入 SPI_I2SPR，I2S 需要在 I2SE 为 0 时设置
+    pub(crate) fn apply_prescaler(&self, spi: &pac::spi1::RegisterBlock) {
+        let bits = self.i2sdiv | ((self.odd as u32) << 8) | ((self.mclk as u32) << 9);
+        spi.i2spr.write(|w| unsafe { w.bits(bits) });
+    }
+}
@@ -0,0 +1,186 @@
+//! I2C 总线扫描，类似 Linux 上的 i2cdetect -r
+//!
+//! 对 0x08 ~ 0x77 的每一个 7 位地址，使用 utils::blocking_master 读取 1 个字节，有 ACK 就说明该地址上有设备
+//! 这里使用读而不是只写地址的 quick write，因为部分 EEPROM 会把 quick write 当作一次写入
+//!
+//! 出错时的处理：
+//! - NACK：该地址上没有设备，blocking_master 已经产生了 STOP，直接扫描下一个地址
+//! - 仲裁丢失、总线错误，或者总线一直处于 BUSY：某个从机很可能卡在了传输的中途，一直拉低 SDA，
+//!   此时调用 recover 恢复总线（手动产生时钟让从机释放 SDA，再复位 I2C 外设），然后重试该地址一次
+//!
+//! BUSY 时 START 永远不会产生，blocking_master 会一直等待 SB，因此每次探测之前都要先确认总线空闲
+
+#![allow(dead_code)]
+
+use core::fmt::Write;
+
+use stm32f4xx_hal::pac::i2c1::RegisterBlock;
+
+use super::{
+    addressing::I2cAddress,
+    blocking_master::{self, MasterError},
+    clocks::{self, ClockError, Clocks},
+};
+
+pub(crate) const FIRST_ADDR: u8 = 0x08;
+pub(crate) const LAST_ADDR: u8 = 0x77;
+
+// STOP 之后等待 BUSY 清除的最大轮询次数，100 kHz 下一个 STOP 只需要几微秒
+const IDLE_SPIN: u32 = 10_000;
+
+// 常见设备的地址范围与名称，同一个地址可能对应多种设备，这里只列出最常见的
+const KNOWN_DEVICES: [(u8, u8, &str); 12] = [
+    (0x0B, 0x0B, "SBS smart battery"),
+    (0x20, 0x26, "MCP23017/PCF8574 GPIO expander"),
+    (0x27, 0x27, "PCF8574 LCD backpack"),
+    (0x36, 0x36, "AS5600 angle encoder"),
+    (0x3C, 0x3D, "SSD1306 OLED"),
+    (0x40, 0x40, "PCA9685 PWM / INA219"),
+    (0x44, 0x45, "SHT3x humidity"),
+    (0x48, 0x4B, "ADS1115 ADC / LM75"),
+    (0x50, 0x57, "AT24 EEPROM"),
+    (0x68, 0x68, "MPU6050 IMU / DS1307 RTC"),
+    (0x69, 0x69, "MPU6050 IMU (AD0 high)"),
+    (0x76, 0x77, "BME280/BMP280"),
+];
+
+pub(crate) fn known_name(addr: u8) -> Option<&'static str> {
+    KNOWN_DEVICES
+        .iter()
+        .find(|(first, last, _)| (*first..=*last).contains(&addr))
+        .map(|(_, _, name)| *name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Scan {
+    // 第 n 位为 1 表示地址 n 上有设备
+    found: u128,
+    // 总线恢复的次数
+    pub(crate) recoveries: u8,
+}
+
+impl Scan {
+    pub(crate) fn contains(&self, addr: u8) -> bool {
+        addr < 128 && self.found & (1 << addr) != 0
+    }
+
+    pub(crate) fn count(&self) -> u32 {
+        self.found.count_ones()
+    }
+
+    pub(crate) fn addresses(&self) -> impl Iterator<Item = u8> + '_ {
+        (FIRST_ADDR..=LAST_ADDR).filter(|&addr| self.contains(addr))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ScanError {
+    // 恢复之后总线依旧无法使用，参数为当时正在探测的地址
+    BusStuck(u8),
+}
+
+// 配置为 100 kHz 的主机
+// FREQ 的有效范围为 2 ~ 50 MHz，PCLK1 不在这个范围内时返回错误，I2C 保持关闭
+pub(crate) fn setup_master(i2c: &RegisterBlock, clocks: &Clocks) -> Result<(), ClockError> {
+    let pclk1_hz = clocks.pclk1;
+    clocks::check_range("PCLK1", pclk1_hz, 2_000_000, 50_000_000)?;
+
+    let freq_mhz = pclk1_hz / 1_000_000;
+    i2c.cr1.modify(|_, w| w.pe().disabled());
+    i2c.cr2
+        .modify(|_, w| unsafe { w.freq().bits(freq_mhz as u8) });
+    // 标准模式下高低电平各占一半，CCR = pclk1 / (2 * 100 kHz)
+    i2c.ccr
+        .modify(|_, w| unsafe { w.ccr().bits((pclk1_hz / 200_000) as u16) });
+    // 标准模式的最大上升时间为 1000 ns，见 s04c01
+    i2c.trise.write(|w| w.trise().bits(freq_mhz as u8 + 1));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+    Ok(())
+}
+
+fn wait_idle(i2c: &RegisterBlock) -> bool {
+    (0..IDLE_SPIN).any(|_| i2c.sr2.read().busy().bit_is_clear())
+}
+
+// recover 返回 false 表示恢复失败
+pub(crate) fn scan(
+    i2c: &RegisterBlock,
+    mut recover: impl FnMut() -> bool,
+) -> Result<Scan, ScanError> {
+    let mut scan = Scan::default();
+
+    for addr in FIRST_ADDR..=LAST_ADDR {
+        let mut retried = false;
+        loop {
+            if !wait_idle(i2c) {
+                if retried || !recover() {
+                    return Err(ScanError::BusStuck(addr));
+                }
+                scan.recoveries += 1;
+                retried = true;
+                continue;
+            }
+
+            let mut byte = [0u8];
+            match blocking_master::read(i2c, I2cAddress::SevenBit(addr), &mut byte) {
+                Ok(()) => scan.found |= 1 << addr,
+                Err(MasterError::Nack) => {}
+                Err(_) if !retried => {
+                    if !recover() {
+                        return Err(ScanError::BusStuck(addr));
+                    }
+                    scan.recoveries += 1;
+                    retried = true;
+                    continue;
+                }
+                // 重试之后依旧出错，当作没有设备
+                Err(_) => {}
+            }
+            break;
+        }
+    }
+
+    Ok(scan)
+}
+
+// 以 i2cdetect 的格式输出，之后再列出已知设备的名称
+//
+//      0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+// 00:                         -- -- -- -- -- -- -- --
+// ...
+// 70: -- -- -- -- -- -- 76 --
+pub(crate) fn write_table(scan: &Scan, out: &mut impl Write) -> core::fmt::Result {
+    out.write_str("    ")?;
+    for col in 0..16 {
+        write!(out, "  {:X}", col)?;
+    }
+    out.write_str("\n")?;
+
+    for row in (0..128u8).step_by(16) {
+        write!(out, "{:02X}:", row)?;
+        for addr in row..row + 16 {
+            if !(FIRST_ADDR..=LAST_ADDR).contains(&addr) {
+                out.write_str("   ")?;
+            } else if scan.contains(addr) {
+                write!(out, " {:02X}", addr)?;
+            } else {
+                out.write_str(" --")?;
+            }
+        }
+        out.write_str("\n")?;
+    }
+
+    for addr in scan.addresses() {
+        writeln!(
+            out,
+            "{:02X} {}",
+            addr,
+            known_name(addr).unwrap_or("unknown")
+        )?;
+    }
+    write!(out, "{} device(s)", scan.count())?;
+    if scan.recoveries > 0 {
+        write!(out, ", {} bus recovery", scan.recoveries)?;
+    }
+    out.write_str("\n")
+}
@@ -0,0 +1,339 @@
+//! 话筒输入：ADC 以 48 kHz 连续采样，经过 FIR 低通滤波之后 3 倍抽取为 16 kHz 的 i16 PCM
+//!
+//! 流水线：
+//!
+//! 1. TIM2 以 ADC_RATE_HZ 输出 TRGO 触发 ADC1，DMA2 Stream 0 以循环模式把结果写入双缓冲，每半个缓冲 1 ms（48 个采样）
+//! 2. 半传输、传输完成中断中（on_dma2_stream0），对写满的那一半做去直流、抽取，结果写入 G_PCM 这个环形缓冲
+//! 3. USB 的一侧用 read 从 G_PCM 中取出采样，fill 给出缓冲中现有的采样数，用于调整每个 packet 的采样数
+//!
+//! 抽取之前必须先低通滤波，否则 8 kHz 以上的成分会混叠到 0 ~ 8 kHz 之中
+//! 这里用 27 阶的 FIR（Hamming 窗，截止 7 kHz）：6 kHz 处 -2 dB，8 kHz 处 -13 dB，10 kHz 以上低于 -48 dB
+//! 由于每 3 个输入只需要 1 个输出，FIR 只在需要输出的那个采样上计算，计算量是直接滤波的 1/3
+//!
+//! 话筒模块的输出偏置在 VCC / 2，先用一个一阶高通去掉直流，再把 12 bit 的结果放大为 16 bit
+//!
+//! ADC 的采样率由 HSE 决定，USB 的帧由 host 决定，两者之间总会有一点偏差，
+//! 环形缓冲的填充程度会缓慢地上升或下降，这正是 USB 一侧需要调整 packet 长度的原因
+
+#![allow(dead_code)]
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{self, interrupt, NVIC};
+
+use super::clocks::{self, ClockError, Clocks};
+
+pub(crate) const ADC_RATE_HZ: u32 = 48_000;
+pub(crate) const PCM_RATE_HZ: u32 = 16_000;
+const DECIMATION: usize = (ADC_RATE_HZ / PCM_RATE_HZ) as usize;
+
+// 每半个缓冲 1 ms
+pub(crate) const HALF_LEN: usize = (ADC_RATE_HZ / 1000) as usize;
+pub(crate) const BUFFER_LEN: usize = HALF_LEN * 2;
+
+// 环形缓冲可以存放 16 ms 的 PCM
+pub(crate) const PCM_FIFO_LEN: usize = 256;
+
+// 一阶高通的系数，截止频率约 (1 - 0.995) * 48 kHz / 2π ≈ 38 Hz
+const DC_ALPHA: f32 = 0.995;
+
+// VDDA 在 2.4 V 以上时，ADCCLK 最高 36 MHz
+const ADCCLK_MAX_HZ: u32 = 36_000_000;
+// 采样 56 个周期 + 逐次逼近 12 个周期
+const ADC_CYCLES_PER_SAMPLE: u32 = 56 + 12;
+
+// 由 scipy.signal.firwin(27, 7000, fs=48000, window="hamming") 得到，系数之和为 1
+#[rustfmt::skip]
+const FIR: [f32; 27] = [
+    -0.001196, -0.002484, -0.002344, 0.001617, 0.009134, 0.013026, 0.002885,
+    -0.022404, -0.044512, -0.031979, 0.036014, 0.146069, 0.249904, 0.292542,
+    0.249904, 0.146069, 0.036014, -0.031979, -0.044512, -0.022404, 0.002885,
+    0.013026, 0.009134, 0.001617, -0.002344, -0.002484, -0.001196,
+];
+
+static G_MIC: Mutex<RefCell<Option<Decimator>>> = Mutex::new(RefCell::new(None));
+static G_PCM: Mutex<RefCell<PcmFifo>> = Mutex::new(RefCell::new(PcmFifo::new()));
+
+// 注意：buffer 要在整个程序运行期间保持有效，DMA 会一直写入它
+//
+// channel 为 ADC1 的通道（0 ~ 9），对应的引脚需要事先设置为 analog 模式
+//
+// 时钟不满足要求时返回错误，此时什么都没有配置：
+// - TIMCLK1 必须是 ADC_RATE_HZ 的整数倍，否则采样率不准，与 USB 一侧的速率偏差会更大
+// - ADCCLK 最高 36 MHz，PCLK2 太高、最大的 8 分频也降不下来时出错
+// - ADCCLK 太低时，一次转换（采样 56 个周期 + 转换 12 个周期）来不及在一个采样周期内完成
+pub(crate) fn start(
+    buffer: &'static mut [u16; BUFFER_LEN],
+    channel: u8,
+    clocks: &Clocks,
+) -> Result<(), ClockError> {
+    assert!(channel < 10);
+
+    clocks::check_multiple("TIMCLK1", clocks.timclk1, ADC_RATE_HZ)?;
+    let adcpre = adc_prescaler(clocks.pclk2)?;
+    clocks::check_min(
+        "ADCCLK",
+        clocks.pclk2 / adcpre,
+        ADC_RATE_HZ * ADC_CYCLES_PER_SAMPLE,
+    )?;
+
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    rcc.apb1enr.modify(|_, w| w.tim2en().enabled());
+    rcc.apb2enr.modify(|_, w| w.adc1en().enabled());
+    rcc.ahb1enr.modify(|_, w| w.dma2en().enabled());
+
+    let buffer_ptr = buffer.as_ptr();
+    cortex_m::interrupt::free(|cs| {
+        G_MIC.borrow(cs).replace(Some(Decimator::new(buffer)));
+    });
+
+    setup_tim2(clocks.timclk1);
+    setup_adc(channel, adcpre);
+    setup_dma(buffer_ptr);
+
+    unsafe { NVIC::unmask(interrupt::DMA2_STREAM0) };
+
+    let tim = unsafe { &*pac::TIM2::ptr() };
+    tim.cr1.modify(|_, w| w.cen().enabled());
+    Ok(())
+}
+
+// 能让 ADCCLK 不超过 36 MHz 的最小分频系数
+fn adc_prescaler(pclk2_hz: u32) -> Result<u32, ClockError> {
+    [2, 4, 6, 8]
+        .into_iter()
+        .find(|div| pclk2_hz / div <= ADCCLK_MAX_HZ)
+        .ok_or(ClockError::TooHigh {
+            clock: "ADCCLK",
+            hz: pclk2_hz / 8,
+            max_hz: ADCCLK_MAX_HZ,
+        })
+}
+
+// 取出至多 out.len() 个采样，返回实际取出的个数
+pub(crate) fn read(out: &mut [i16]) -> usize {
+    cortex_m::interrupt::free(|cs| G_PCM.borrow(cs).borrow_mut().pop(out))
+}
+
+// 环形缓冲中现有的采样数
+pub(crate) fn fill() -> usize {
+    cortex_m::interrupt::free(|cs| G_PCM.borrow(cs).borrow().len)
+}
+
+// 清空环形缓冲与丢失计数，比如 host 刚刚打开音频流时，旧的采样已经没有意义了
+pub(crate) fn clear() {
+    cortex_m::interrupt::free(|cs| {
+        let mut pcm = G_PCM.borrow(cs).borrow_mut();
+        pcm.len = 0;
+        pcm.overruns = 0;
+    });
+}
+
+// 因为环形缓冲满了而丢掉的采样数
+pub(crate) fn overruns() -> u32 {
+    cortex_m::interrupt::free(|cs| G_PCM.borrow(cs).borrow().overruns)
+}
+
+fn setup_tim2(tim_clk_hz: u32) {
+    let tim = unsafe { &*pac::TIM2::ptr() };
+    tim.cr1.modify(|_, w| w.cen().disabled());
+    tim.psc.write(|w| w.psc().bits(0));
+    tim.arr
+        .write(|w| w.arr().bits(tim_clk_hz / ADC_RATE_HZ - 1));
+    // 每次溢出在 TRGO 上输出一个脉冲，触发 ADC
+    tim.cr2.modify(|_, w| w.mms().update());
+    tim.egr.write(|w| w.ug().update());
+}
+
+fn setup_adc(channel: u8, adcpre: u32) {
+    let common = unsafe { &*pac::ADC_COMMON::ptr() };
+    common.ccr.modify(|_, w| match adcpre {
+        2 => w.adcpre().div2(),
+        4 => w.adcpre().div4(),
+        6 => w.adcpre().div6(),
+        _ => w.adcpre().div8(),
+    });
+
+    let adc = unsafe { &*pac::ADC1::ptr() };
+    adc.sqr3.modify(|_, w| unsafe { w.sq1().bits(channel) });
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+
+    // 采样时间 0b011 为 56 个周期，话筒模块的输出阻抗较高，采样时间长一些更准确
+    let shift = 3 * channel as u32;
+    adc.smpr2
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << shift) | 0b011 << shift) });
+
+    adc.cr2.modify(|_, w| {
+        w.extsel().tim2trgo();
+        w.exten().rising_edge();
+        // DMA 循环模式下，DDS 必须置位，否则 DMA 的第一轮结束后 ADC 就不再发出请求了
+        w.dds().continuous();
+        w.dma().enabled();
+        w.adon().enabled();
+        w
+    });
+}
+
+// DMA2 Stream 0 Channel 0 为 ADC1
+fn setup_dma(buffer: *const u16) {
+    let dma2 = unsafe { &*pac::DMA2::ptr() };
+    let adc = unsafe { &*pac::ADC1::ptr() };
+    let st = &dma2.st[0];
+
+    if st.cr.read().en().is_enabled() {
+        st.cr.modify(|_, w| w.en().disabled());
+        while st.cr.read().en().is_enabled() {}
+    }
+
+    dma2.lifcr.write(|w| {
+        w.ctcif0().clear();
+        w.chtif0().clear();
+        w.cteif0().clear();
+        w.cdmeif0().clear();
+        w.cfeif0().clear();
+        w
+    });
+
+    st.cr.write(|w| {
+        w.chsel().bits(0);
+        w.pl().high();
+        w.dir().peripheral_to_memory();
+        w.msize().bits16();
+        w.psize().bits16();
+        w.minc().incremented();
+        w.pinc().fixed();
+        w.circ().enabled();
+        w.htie().enabled();
+        w.tcie().enabled();
+        w
+    });
+    st.par
+        .write(|w| unsafe { w.pa().bits(adc.dr.as_ptr() as u32) });
+    st.m0ar.write(|w| unsafe { w.m0a().bits(buffer as u32) });
+    st.ndtr.write(|w| w.ndt().bits(BUFFER_LEN as u16));
+
+    st.cr.modify(|_, w| w.en().enabled());
+}
+
+// 在 DMA2_STREAM0 中断中调用，返回是否有新的 PCM 采样
+pub(crate) fn on_dma2_stream0() -> bool {
+    let dma2 = unsafe { &*pac::DMA2::ptr() };
+    let lisr = dma2.lisr.read();
+
+    // 半传输时写满的是前一半，传输完成时写满的是后一半
+    let start = if lisr.htif0().bit_is_set() {
+        dma2.lifcr.write(|w| w.chtif0().clear());
+        0
+    } else if lisr.tcif0().bit_is_set() {
+        dma2.lifcr.write(|w| w.ctcif0().clear());
+        HALF_LEN
+    } else {
+        return false;
+    };
+
+    cortex_m::interrupt::free(|cs| {
+        let mut mic = G_MIC.borrow(cs).borrow_mut();
+        let Some(mic) = mic.as_mut() else {
+            return false;
+        };
+        let mut pcm = G_PCM.borrow(cs).borrow_mut();
+        mic.process(start, &mut pcm);
+        true
+    })
+}
+
+struct Decimator {
+    buffer: &'static [u16; BUFFER_LEN],
+    // 去直流
+    x1: f32,
+    y1: f32,
+    // FIR 的历史输入，index 为下一个写入的位置
+    history: [f32; FIR.len()],
+    index: usize,
+    // 还要再输入几个采样，才需要计算下一个输出
+    phase: usize,
+}
+
+impl Decimator {
+    fn new(buffer: &'static [u16; BUFFER_LEN]) -> Self {
+        Self {
+            buffer,
+            x1: 2048.0,
+            y1: 0.0,
+            history: [0.0; FIR.len()],
+            index: 0,
+            phase: 0,
+        }
+    }
+
+    fn process(&mut self, start: usize, pcm: &mut PcmFifo) {
+        let buffer = self.buffer;
+        for &raw in &buffer[start..start + HALF_LEN] {
+            let x = raw as f32;
+            let y = x - self.x1 + DC_ALPHA * self.y1;
+            self.x1 = x;
+            self.y1 = y;
+
+            self.history[self.index] = y;
+            self.index = (self.index + 1) % FIR.len();
+
+            if self.phase > 0 {
+                self.phase -= 1;
+                continue;
+            }
+            self.phase = DECIMATION - 1;
+
+            // history[index] 是最旧的输入，FIR 是对称的，卷积的方向无所谓
+            let mut acc = 0.0;
+            let mut i = self.index;
+            for coeff in FIR {
+                acc += coeff * self.history[i];
+                i = (i + 1) % FIR.len();
+            }
+
+            // 12 bit 放大为 16 bit
+            let sample = (acc * 16.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            pcm.push(sample);
+        }
+    }
+}
+
+struct PcmFifo {
+    buf: [i16; PCM_FIFO_LEN],
+    head: usize,
+    len: usize,
+    overruns: u32,
+}
+
+impl PcmFifo {
+    const fn new() -> Self {
+        Self {
+            buf: [0; PCM_FIFO_LEN],
+            head: 0,
+            len: 0,
+            overruns: 0,
+        }
+    }
+
+    // 满了的时候丢掉最旧的采样，host 没有在读取时，缓冲里总是最新的 16 ms
+    fn push(&mut self, sample: i16) {
+        if self.len == PCM_FIFO_LEN {
+            self.head = (self.head + 1) % PCM_FIFO_LEN;
+            self.len -= 1;
+            self.overruns = self.overruns.wrapping_add(1);
+        }
+        self.buf[(self.head + self.len) % PCM_FIFO_LEN] = sample;
+        self.len += 1;
+    }
+
+    fn pop(&mut self, out: &mut [i16]) -> usize {
+        let n = out.len().min(self.len);
+        for slot in out[..n].iter_mut() {
+            *slot = self.buf[self.head];
+            self.head = (self.head + 1) % PCM_FIFO_LEN;
+        }
+        self.len -= n;
+        n
+    }
+}
@@ -0,0 +1,13 @@
+pub(crate) mod clocks;
+pub(crate) mod i2c_scan;
+pub(crate) mod mic_adc;
+pub(crate) mod raw_usb;
+pub(crate) mod raw_usb_host;
+pub(crate) mod reg_access;
+pub(crate) mod sof_timing;
+pub(crate) mod usb_io;
+
+// 与其它章节共用的模块，代码在工作区中单独的 crate 里
+// 与上面的模块一样，不是每个程序都会用到，因此允许未使用
+#[allow(unused_imports)]
+pub(crate) use i2c_master::{addressing, blocking_master};
@@ -0,0 +1,150 @@
+//! 描述符
+//!
+//! 与 s13c01 中由 usb-device 生成的描述符相同：
+//! 一个 Configuration，其中只有一个没有任何 endpoint 的“厂商自定义 interface”（bInterfaceClass 为 0xFF）
+//! 另外还有一个带有 Isochronous endpoint 的版本 ISO_CONFIGURATION_DESCRIPTOR，见 raw_usb::iso
+//!
+//! 各个字段的含义见 USB 2.0 Specification 的 Standard USB Descriptor Definitions 节
+
+#![allow(dead_code)]
+
+use super::iso::{ISO_IN_EP, ISO_MAX_PACKET, ISO_OUT_EP};
+
+// Descriptor Types 表
+pub(crate) const DEVICE: u8 = 1;
+pub(crate) const CONFIGURATION: u8 = 2;
+pub(crate) const STRING: u8 = 3;
+pub(crate) const INTERFACE: u8 = 4;
+pub(crate) const ENDPOINT: u8 = 5;
+pub(crate) const DEVICE_QUALIFIER: u8 = 6;
+
+pub(crate) const VID: u16 = 0x1209;
+pub(crate) const PID: u16 = 0x0001;
+
+// Endpoint 0 的最大包长，OTG_FS 支持 8/16/32/64，这里用最大的 64
+pub(crate) const EP0_MAX_PACKET: u8 = 64;
+
+#[rustfmt::skip]
+pub(crate) const DEVICE_DESCRIPTOR: [u8; 18] = [
+    18,                             // bLength
+    DEVICE,                         // bDescriptorType
+    0x00, 0x02,                     // bcdUSB，USB 2.0
+    0x00,                           // bDeviceClass，由 interface 决定
+    0x00,                           // bDeviceSubClass
+    0x00,                           // bDeviceProtocol
+    EP0_MAX_PACKET,                 // bMaxPacketSize0
+    VID as u8, (VID >> 8) as u8,    // idVendor
+    PID as u8, (PID >> 8) as u8,    // idProduct
+    0x10, 0x00,                     // bcdDevice
+    1,                              // iManufacturer
+    2,                              // iProduct
+    3,                              // iSerialNumber
+    1,                              // bNumConfigurations
+];
+
+// Configuration Descriptor 之后紧跟着它包含的 Interface Descriptor，GET_DESCRIPTOR 时要一起返回
+#[rustfmt::skip]
+pub(crate) const CONFIGURATION_DESCRIPTOR: [u8; 18] = [
+    // Configuration Descriptor
+    9,                              // bLength
+    CONFIGURATION,                  // bDescriptorType
+    18, 0,                          // wTotalLength，包含后面的 Interface Descriptor
+    1,                              // bNumInterfaces
+    1,                              // bConfigurationValue，SET_CONFIGURATION 时使用的编号
+    0,                              // iConfiguration
+    0x80,                           // bmAttributes，bit 7 必须为 1，总线供电，不支持远程唤醒
+    50,                             // bMaxPower，单位为 2 mA，也就是 100 mA
+    // Interface Descriptor
+    9,                              // bLength
+    INTERFACE,                      // bDescriptorType
+    0,                              // bInterfaceNumber
+    0,                              // bAlternateSetting
+    0,                              // bNumEndpoints，不包含 Endpoint 0
+    0xFF,                           // bInterfaceClass，厂商自定义
+    0x00,                           // bInterfaceSubClass
+    0x00,                           // bInterfaceProtocol
+    0,                              // iInterface
+];
+
+// 带有 Isochronous endpoint 的 Configuration，见 raw_usb::iso
+//
+// Isochronous endpoint 会占用预留的带宽，因此 interface 的 alternate setting 0 不带任何 endpoint，
+// host 需要收发数据时，才通过 SET_INTERFACE 切换到带有两个 endpoint 的 alternate setting 1
+#[rustfmt::skip]
+pub(crate) const ISO_CONFIGURATION_DESCRIPTOR: [u8; 41] = [
+    // Configuration Descriptor
+    9,                              // bLength
+    CONFIGURATION,                  // bDescriptorType
+    41, 0,                          // wTotalLength
+    1,                              // bNumInterfaces，alternate setting 不算作新的 interface
+    1,                              // bConfigurationValue
+    0,                              // iConfiguration
+    0x80,                           // bmAttributes
+    50,                             // bMaxPower
+    // Interface Descriptor，alternate setting 0
+    9,                              // bLength
+    INTERFACE,                      // bDescriptorType
+    0,                              // bInterfaceNumber
+    0,                              // bAlternateSetting
+    0,                              // bNumEndpoints
+    0xFF,                           // bInterfaceClass，厂商自定义
+    0x00,                           // bInterfaceSubClass
+    0x00,                           // bInterfaceProtocol
+    0,                              // iInterface
+    // Interface Descriptor，alternate setting 1
+    9,                              // bLength
+    INTERFACE,                      // bDescriptorType
+    0,                              // bInterfaceNumber
+    1,                              // bAlternateSetting
+    2,                              // bNumEndpoints
+    0xFF,                           // bInterfaceClass
+    0x00,                           // bInterfaceSubClass
+    0x00,                           // bInterfaceProtocol
+    0,                              // iInterface
+    // Endpoint Descriptor，Isochronous IN
+    7,                              // bLength
+    ENDPOINT,                       // bDescriptorType
+    0x80 | ISO_IN_EP as u8,         // bEndpointAddress，bit 7 为 1 表示 IN
+    0x01,                           // bmAttributes，Isochronous，没有同步，数据 endpoint
+    ISO_MAX_PACKET as u8, (ISO_MAX_PACKET >> 8) as u8, // wMaxPacketSize
+    1,                              // bInterval，每一帧一次
+    // Endpoint Descriptor，Isochronous OUT
+    7,                              // bLength
+    ENDPOINT,                       // bDescriptorType
+    ISO_OUT_EP as u8,               // bEndpointAddress
+    0x01,                           // bmAttributes
+    ISO_MAX_PACKET as u8, (ISO_MAX_PACKET >> 8) as u8, // wMaxPacketSize
+    1,                              // bInterval
+];
+
+// String Descriptor 0 比较特殊，它是设备支持的语言 ID 的列表，这里只有 0x0409（English - United States）
+pub(crate) const LANGUAGE_IDS: [u8; 4] = [4, STRING, 0x09, 0x04];
+
+pub(crate) const MANUFACTURER: &str = "random manufacturer";
+pub(crate) const PRODUCT: &str = "random product";
+pub(crate) const SERIAL_NUMBER: &str = "random serial";
+
+// 按照 index 生成 String Descriptor，内容为 UTF-16LE 编码的字符串，返回写入的长度
+// index 不存在时返回 None
+pub(crate) fn string(index: u8, buf: &mut [u8]) -> Option<usize> {
+    let text = match index {
+        0 => {
+            buf[..LANGUAGE_IDS.len()].copy_from_slice(&LANGUAGE_IDS);
+            return Some(LANGUAGE_IDS.len());
+        }
+        1 => MANUFACTURER,
+        2 => PRODUCT,
+        3 => SERIAL_NUMBER,
+        _ => return None,
+    };
+
+    let mut len = 2;
+    for unit in text.encode_utf16() {
+        buf[len..len + 2].copy_from_slice(&unit.to_le_bytes());
+        len += 2;
+    }
+    buf[0] = len as u8;
+    buf[1] = STRING;
+
+    Some(len)
+}
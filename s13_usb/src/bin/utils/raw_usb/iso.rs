@@ -0,0 +1,313 @@
+//! Isochronous endpoint
+//!
+//! 与控制、批量、中断传输不同，Isochronous 传输没有握手，也不会重传，数据必须在某一个确定的帧里收发，
+//! 因此 OTG_FS 要求软件为每一次传输指定“在偶数帧还是奇数帧”进行（DIEPCTLx/DOEPCTLx 的 SEVNFRM 与 SODDFRM）
+//!
+//! 这里固定使用 Endpoint 1 作为 Isochronous IN，Endpoint 2 作为 Isochronous OUT，
+//! 它们只存在于 vendor interface 的 alternate setting 1 中（见 descriptor::ISO_CONFIGURATION_DESCRIPTOR），
+//! host 通过 SET_INTERFACE 切换到 alternate setting 1 时 activate，切回 0 时 deactivate
+//!
+//! 每一帧的处理：
+//!
+//! 1. SOF（GINTSTS 的 SOF）：帧开始，从 DSTS 读出帧号，若帧号不连续，说明 poll 来不及处理，跳过了一些帧；
+//!    若 IN endpoint 空闲，向 IsoHandler 要下一帧的数据，写入 TxFIFO，指定在下一帧发送
+//! 2. IN 传输完成（DIEPINT1 的 XFRC）：这一帧的数据被 host 取走了，立刻准备再下一帧的数据，
+//!    这样在稳定状态下，每一帧都有数据在 TxFIFO 中等待 host
+//! 3. OUT 数据（RxFIFO 中 Endpoint 2 的 OUT_DATA）：读出到缓冲中，传输完成（DOEPINT2 的 XFRC）时交给 IsoHandler，
+//!    并重新准备好接收下一帧
+//! 4. 帧结束时仍有未完成的传输，core 会置位 GINTSTS 的 IISOIXFR（IN）或 INCOMPISOOUT（OUT），
+//!    此时关闭对应的 endpoint，计为丢失的帧，等待下一次重新准备
+//!
+//! 所有计数都在 IsoStats 中，可以在主循环中读取
+
+#![allow(dead_code)]
+
+use super::regs::*;
+
+pub(crate) const ISO_IN_EP: usize = 1;
+pub(crate) const ISO_OUT_EP: usize = 2;
+
+// 每一帧最多 192 byte，正好是 48 kHz、16 bit、双声道音频一帧的数据量
+pub(crate) const ISO_MAX_PACKET: usize = 192;
+
+// Endpoint 1 的 TxFIFO，能放下两个包
+pub(crate) const TX1_FIFO_WORDS: u32 = (ISO_MAX_PACKET as u32 / 4) * 2;
+
+// 帧号只有 11 bit（DSTS 的 FNSOF 的低 11 位）
+const FRAME_MASK: u16 = 0x7FF;
+
+// 等待 endpoint 被关闭的最大轮询次数
+const DISABLE_POLLS: u32 = 10_000;
+
+// 由使用 Isochronous endpoint 的一方实现
+pub(crate) trait IsoHandler {
+    // 把在 frame 这一帧发送的数据写入 buf，返回长度，返回 0 表示这一帧不发送
+    fn fill_in(&mut self, frame: u16, buf: &mut [u8]) -> usize;
+    // 在 frame 这一帧收到了 data
+    fn on_out(&mut self, frame: u16, data: &[u8]);
+}
+
+// 不使用 Isochronous endpoint 时的占位
+impl IsoHandler for () {
+    fn fill_in(&mut self, _frame: u16, _buf: &mut [u8]) -> usize {
+        0
+    }
+
+    fn on_out(&mut self, _frame: u16, _data: &[u8]) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub(crate) struct IsoStats {
+    pub(crate) sof: u32,
+    // 两次 SOF 之间跳过的帧数
+    pub(crate) sof_skipped: u32,
+    pub(crate) in_sent: u32,
+    // 准备好了数据，但 host 没有在那一帧取走（IISOIXFR）
+    pub(crate) in_missed: u32,
+    pub(crate) out_received: u32,
+    pub(crate) out_bytes: u32,
+    // 准备好了接收，但那一帧没有收到数据（INCOMPISOOUT）
+    pub(crate) out_missed: u32,
+}
+
+pub(crate) struct IsoEndpoints {
+    active: bool,
+    last_frame: Option<u16>,
+    in_buf: [u8; ISO_MAX_PACKET],
+    out_buf: [u8; ISO_MAX_PACKET],
+    out_len: usize,
+    stats: IsoStats,
+}
+
+impl IsoEndpoints {
+    pub(crate) const fn new() -> Self {
+        Self {
+            active: false,
+            last_frame: None,
+            in_buf: [0; ISO_MAX_PACKET],
+            out_buf: [0; ISO_MAX_PACKET],
+            out_len: 0,
+            stats: IsoStats {
+                sof: 0,
+                sof_skipped: 0,
+                in_sent: 0,
+                in_missed: 0,
+                out_received: 0,
+                out_bytes: 0,
+                out_missed: 0,
+            },
+        }
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub(crate) fn stats(&self) -> IsoStats {
+        self.stats
+    }
+
+    // 打开两个 endpoint 与相关的中断，并准备好接收下一帧的 OUT 数据
+    pub(crate) fn activate(&mut self) {
+        if self.active {
+            return;
+        }
+
+        let iso = EPTYP__ISOCHRONOUS << DEPCTL__EPTYP__SHIFT;
+        write(
+            endpoint(DIEPCTL0, ISO_IN_EP),
+            DEPCTL__USBAEP
+                | iso
+                | (ISO_IN_EP as u32) << DIEPCTL__TXFNUM__SHIFT
+                | ISO_MAX_PACKET as u32
+                | DEPCTL__SNAK,
+        );
+        write(
+            endpoint(DOEPCTL0, ISO_OUT_EP),
+            DEPCTL__USBAEP | iso | ISO_MAX_PACKET as u32 | DEPCTL__SNAK,
+        );
+
+        set_bits(DAINTMSK, daint_iep(ISO_IN_EP) | daint_oep(ISO_OUT_EP));
+        write(GINTSTS, GINT__SOF | GINT__IISOIXFR | GINT__INCOMPISOOUT);
+        set_bits(GINTMSK, GINT__SOF | GINT__IISOIXFR | GINT__INCOMPISOOUT);
+
+        self.active = true;
+        self.last_frame = None;
+        self.out_len = 0;
+        self.stats = IsoStats::default();
+
+        self.arm_out();
+    }
+
+    // 关闭两个 endpoint，TxFIFO 中未发出的数据直接丢弃
+    pub(crate) fn deactivate(&mut self) {
+        if !self.active {
+            return;
+        }
+        self.active = false;
+
+        clear_bits(GINTMSK, GINT__SOF | GINT__IISOIXFR | GINT__INCOMPISOOUT);
+        clear_bits(DAINTMSK, daint_iep(ISO_IN_EP) | daint_oep(ISO_OUT_EP));
+
+        disable_endpoint(endpoint(DIEPCTL0, ISO_IN_EP), endpoint(DIEPINT0, ISO_IN_EP));
+        flush_tx_fifo(ISO_IN_EP);
+        disable_endpoint(
+            endpoint(DOEPCTL0, ISO_OUT_EP),
+            endpoint(DOEPINT0, ISO_OUT_EP),
+        );
+
+        clear_bits(endpoint(DIEPCTL0, ISO_IN_EP), DEPCTL__USBAEP);
+        clear_bits(endpoint(DOEPCTL0, ISO_OUT_EP), DEPCTL__USBAEP);
+    }
+
+    // GINTSTS 的 SOF
+    pub(crate) fn on_sof(&mut self, handler: &mut impl IsoHandler) {
+        let frame = current_frame();
+        self.stats.sof = self.stats.sof.wrapping_add(1);
+        if let Some(last) = self.last_frame {
+            let skipped = frame.wrapping_sub(last).wrapping_sub(1) & FRAME_MASK;
+            self.stats.sof_skipped = self.stats.sof_skipped.wrapping_add(skipped as u32);
+        }
+        self.last_frame = Some(frame);
+
+        // endpoint 仍然使能，说明上一次准备的数据正在等待 host，不需要再准备
+        if read(endpoint(DIEPCTL0, ISO_IN_EP)) & DEPCTL__EPENA == 0 {
+            self.arm_in(handler);
+        }
+    }
+
+    // DIEPINT1
+    pub(crate) fn on_in_endpoint(&mut self, handler: &mut impl IsoHandler) {
+        let reg = endpoint(DIEPINT0, ISO_IN_EP);
+        let int = read(reg);
+        write(reg, int);
+
+        if int & DEPINT__XFRC != 0 {
+            self.stats.in_sent = self.stats.in_sent.wrapping_add(1);
+            self.arm_in(handler);
+        }
+    }
+
+    // RxFIFO 中 Endpoint 2 的 OUT_DATA，bcnt 个字节都要读出来，超过缓冲的部分丢弃
+    pub(crate) fn on_rx_data(&mut self, bcnt: usize) {
+        let mut pos = 0;
+        for _ in 0..bcnt.div_ceil(4) {
+            let word = read_fifo().to_le_bytes();
+            for byte in word {
+                if pos < bcnt && pos < ISO_MAX_PACKET {
+                    self.out_buf[pos] = byte;
+                }
+                pos += 1;
+            }
+        }
+        self.out_len = bcnt.min(ISO_MAX_PACKET);
+    }
+
+    // DOEPINT2
+    pub(crate) fn on_out_endpoint(&mut self, handler: &mut impl IsoHandler) {
+        let reg = endpoint(DOEPINT0, ISO_OUT_EP);
+        let int = read(reg);
+        write(reg, int);
+
+        if int & DEPINT__XFRC != 0 {
+            self.stats.out_received = self.stats.out_received.wrapping_add(1);
+            self.stats.out_bytes = self.stats.out_bytes.wrapping_add(self.out_len as u32);
+            handler.on_out(current_frame(), &self.out_buf[..self.out_len]);
+            self.out_len = 0;
+            self.arm_out();
+        }
+    }
+
+    // GINTSTS 的 IISOIXFR：准备好的数据没有在预定的帧发出去，关闭 endpoint，丢弃 TxFIFO 中的数据
+    pub(crate) fn on_incomplete_in(&mut self) {
+        let ctl = endpoint(DIEPCTL0, ISO_IN_EP);
+        if read(ctl) & DEPCTL__EPENA != 0 {
+            disable_endpoint(ctl, endpoint(DIEPINT0, ISO_IN_EP));
+            flush_tx_fifo(ISO_IN_EP);
+            self.stats.in_missed = self.stats.in_missed.wrapping_add(1);
+        }
+    }
+
+    // GINTSTS 的 INCOMPISOOUT：这一帧没有收到数据，关闭 endpoint 之后，重新准备好接收下一帧
+    pub(crate) fn on_incomplete_out(&mut self) {
+        let ctl = endpoint(DOEPCTL0, ISO_OUT_EP);
+        if read(ctl) & DEPCTL__EPENA != 0 {
+            disable_endpoint(ctl, endpoint(DOEPINT0, ISO_OUT_EP));
+            self.stats.out_missed = self.stats.out_missed.wrapping_add(1);
+        }
+        self.arm_out();
+    }
+
+    // 准备在下一帧发送的数据
+    fn arm_in(&mut self, handler: &mut impl IsoHandler) {
+        let frame = current_frame().wrapping_add(1) & FRAME_MASK;
+        let len = handler.fill_in(frame, &mut self.in_buf).min(ISO_MAX_PACKET);
+        if len == 0 {
+            return;
+        }
+
+        write(
+            endpoint(DIEPTSIZ0, ISO_IN_EP),
+            1 << DIEPTSIZ__MCNT__SHIFT | 1 << DEPTSIZ__PKTCNT__SHIFT | len as u32,
+        );
+        set_bits(
+            endpoint(DIEPCTL0, ISO_IN_EP),
+            DEPCTL__EPENA | DEPCTL__CNAK | frame_parity(frame),
+        );
+
+        for chunk in self.in_buf[..len].chunks(4) {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            write_fifo(ISO_IN_EP, u32::from_le_bytes(word));
+        }
+    }
+
+    // 准备在下一帧接收数据
+    fn arm_out(&mut self) {
+        let frame = current_frame().wrapping_add(1) & FRAME_MASK;
+        write(
+            endpoint(DOEPTSIZ0, ISO_OUT_EP),
+            1 << DEPTSIZ__PKTCNT__SHIFT | ISO_MAX_PACKET as u32,
+        );
+        set_bits(
+            endpoint(DOEPCTL0, ISO_OUT_EP),
+            DEPCTL__EPENA | DEPCTL__CNAK | frame_parity(frame),
+        );
+    }
+}
+
+// 最近一次 SOF 的帧号
+pub(crate) fn current_frame() -> u16 {
+    (((read(DSTS) & DSTS__FNSOF__MASK) >> DSTS__FNSOF__SHIFT) as u16) & FRAME_MASK
+}
+
+fn frame_parity(frame: u16) -> u32 {
+    if frame & 1 == 0 {
+        DEPCTL__SEVNFRM
+    } else {
+        DEPCTL__SODDFRM
+    }
+}
+
+// 先 SNAK，再 EPDIS，等待 EPDISD
+fn disable_endpoint(ctl: usize, int: usize) {
+    if read(ctl) & DEPCTL__EPENA == 0 {
+        return;
+    }
+    set_bits(ctl, DEPCTL__SNAK);
+    set_bits(ctl, DEPCTL__EPDIS);
+    for _ in 0..DISABLE_POLLS {
+        if read(int) & DEPINT__EPDISD != 0 {
+            break;
+        }
+    }
+    write(int, DEPINT__EPDISD);
+}
+
+fn flush_tx_fifo(ep: usize) {
+    write(
+        GRSTCTL,
+        GRSTCTL__TXFFLSH | (ep as u32) << GRSTCTL__TXFNUM__SHIFT,
+    );
+    while read(GRSTCTL) & GRSTCTL__TXFFLSH != 0 {}
+}
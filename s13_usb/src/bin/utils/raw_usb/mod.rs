@@ -0,0 +1,574 @@
+//! 不依赖 usb-device 与 synopsys-usb-otg，直接操作寄存器的 OTG_FS device 驱动
+//!
+//! 目的是看清楚前面几个例子中，那几个 crate 在内部做了什么，因此只实现了刚好能完成枚举的部分：
+//! 只有 Endpoint 0，只处理标准请求，不使用中断，也不使用 DMA
+//!
+//! 整个过程可以分为以下几步，对照 Reference Manual 的 OTG_FS programming model 节阅读：
+//!
+//! 1. Core initialization
+//!    等待 AHB 空闲，软件复位 core，打开 PHY，关闭 VBUS 检测，强制为 device 模式
+//! 2. Device initialization
+//!    设置为 Full Speed，划分 FIFO，然后释放 D+ 上的 soft disconnect，host 此时才能检测到设备的接入
+//! 3. USB Reset（GINTSTS 的 USBRST）
+//!    host 检测到设备之后，会先复位总线，此时需要清零地址，打开 Endpoint 0 的中断，准备好接收 SETUP 包
+//! 4. Enumeration done（GINTSTS 的 ENUMDNE）
+//!    速度协商完成，设置 Endpoint 0 的最大包长
+//! 5. 控制传输
+//!    host 通过 Endpoint 0 发送 SETUP 包，设备解析之后，经过可选的 DATA 阶段，最后由 STATUS 阶段结束这次传输
+//!
+//! 关于 FIFO：
+//! OTG_FS 内部有 1.25 KB（320 word）的 RAM，所有 OUT endpoint 共用一个 RxFIFO，每个 IN endpoint 有自己的 TxFIFO
+//! 收到的数据（包括 SETUP 包）都以“状态 + 数据”的形式进入 RxFIFO，读取 GRXSTSP 得到状态，再从 FIFO 中读出 BCNT 个字节的数据
+//!
+//! 关于控制传输：
+//!
+//! | 请求                 | SETUP | DATA           | STATUS              |
+//! | -------------------- | ----- | -------------- | ------------------- |
+//! | 无 DATA 阶段         | OUT   | 无             | IN，零长度包        |
+//! | DATA 阶段为 IN       | OUT   | IN，一个或多个 | OUT，零长度包       |
+//!
+//! 遇到不支持的请求时，以 STALL 回复，host 会认为这个请求失败了，但不影响之后的请求
+//!
+//! 用 with_iso 创建时，interface 多出一个带有 Isochronous IN/OUT endpoint 的 alternate setting 1，
+//! 数据由 poll_with 传入的 IsoHandler 提供与接收，细节见 iso 模块
+
+#![allow(dead_code)]
+
+pub(crate) mod descriptor;
+pub(crate) mod iso;
+pub(crate) mod regs;
+pub(crate) mod setup;
+
+use stm32f4xx_hal::pac::Peripherals;
+
+use iso::{IsoEndpoints, IsoHandler, IsoStats, ISO_IN_EP, ISO_OUT_EP, TX1_FIFO_WORDS};
+use regs::*;
+use setup::{Direction, RequestType, SetupPacket};
+
+// FIFO 的划分，单位为 word
+// RxFIFO 需要能放下 SETUP 包与最大的 OUT 包，以及每个包附带的状态，这里给得比较宽裕
+const RX_FIFO_WORDS: u32 = 128;
+// Endpoint 0 的 TxFIFO，至少要能放下一个 64 byte 的包
+const TX0_FIFO_WORDS: u32 = 64;
+
+// HCLK 的频率，用于计算等待时间
+pub(crate) const HCLK_HZ: u32 = 96_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum DeviceState {
+    Default,
+    Addressed,
+    Configured,
+    Suspend,
+}
+
+// 控制传输的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum Control {
+    // 等待 SETUP
+    Idle,
+    // 正在发送 DATA 阶段的数据
+    DataIn,
+    // DATA 阶段结束，等待 host 发来 STATUS 阶段的零长度包
+    StatusOut,
+    // 没有 DATA 阶段，已经写入了 STATUS 阶段的零长度包，等待 host 取走
+    StatusIn,
+}
+
+pub(crate) struct RawUsb {
+    state: DeviceState,
+    // 挂起之前的状态，唤醒时恢复
+    resume_state: DeviceState,
+    control: Control,
+    configuration: u8,
+    // 最近一次从 RxFIFO 中读出的 SETUP 包
+    setup: [u32; 2],
+    // DATA IN 阶段要发送的数据
+    buf: [u8; 128],
+    in_len: usize,
+    in_pos: usize,
+    // 数据长度小于 host 要求的长度，且恰好是最大包长的整数倍时，要额外发送一个零长度包，host 才知道数据已经结束了
+    in_zlp: bool,
+    // 为 None 时，没有 Isochronous endpoint
+    iso: Option<IsoEndpoints>,
+    alt_setting: u8,
+}
+
+impl RawUsb {
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: DeviceState::Default,
+            resume_state: DeviceState::Default,
+            control: Control::Idle,
+            configuration: 0,
+            setup: [0; 2],
+            buf: [0; 128],
+            in_len: 0,
+            in_pos: 0,
+            in_zlp: false,
+            iso: None,
+            alt_setting: 0,
+        }
+    }
+
+    // 带有 Isochronous IN/OUT endpoint 的设备
+    pub(crate) const fn with_iso() -> Self {
+        let mut usb = Self::new();
+        usb.iso = Some(IsoEndpoints::new());
+        usb
+    }
+
+    // 没有 Isochronous endpoint 时返回 None
+    pub(crate) fn iso_stats(&self) -> Option<IsoStats> {
+        self.iso.as_ref().map(|iso| iso.stats())
+    }
+
+    // host 是否已经切换到了带有 Isochronous endpoint 的 alternate setting
+    pub(crate) fn iso_active(&self) -> bool {
+        self.iso.as_ref().is_some_and(|iso| iso.is_active())
+    }
+
+    pub(crate) fn state(&self) -> DeviceState {
+        self.state
+    }
+
+    // 调用之前，PLL 的 48 MHz 输出必须已经就绪
+    pub(crate) fn init(&mut self, dp: &Peripherals) {
+        // Core initialization
+        reset_core(dp);
+
+        // 我们的开发板没有把 VBUS 接到 PA9，reset_core 中关闭了 VBUS 检测，这里手动告诉 core，B-session 总是有效的
+        set_bits(GOTGCTL, GOTGCTL__BVALOEN | GOTGCTL__BVALOVAL);
+
+        // TRDT 为 USB turnaround time，取决于 HCLK，HCLK 在 32 MHz 以上时为 6
+        modify(GUSBCFG, |v| {
+            (v & !(GUSBCFG__FHMOD | GUSBCFG__TRDT__MASK))
+                | GUSBCFG__FDMOD
+                | GUSBCFG__PHYSEL
+                | 6 << GUSBCFG__TRDT__SHIFT
+        });
+        // 强制切换模式之后，至少要等待 25 ms 才会生效
+        cortex_m::asm::delay(HCLK_HZ / 40);
+
+        // Device initialization
+
+        // Full Speed，地址为 0
+        write(DCFG, DCFG__DSPD__FULL_SPEED);
+        // 在准备好之前，先保持 soft disconnect
+        set_bits(DCTL, DCTL__SDIS);
+
+        write(GRXFSIZ, RX_FIFO_WORDS);
+        // 高 16 bit 为 TxFIFO 0 的大小，低 16 bit 为它的起始地址，紧跟在 RxFIFO 之后
+        write(DIEPTXF0, TX0_FIFO_WORDS << 16 | RX_FIFO_WORDS);
+        if self.iso.is_some() {
+            // Isochronous IN endpoint 的 TxFIFO 紧跟在 TxFIFO 0 之后
+            write(
+                DIEPTXF1,
+                TX1_FIFO_WORDS << 16 | (RX_FIFO_WORDS + TX0_FIFO_WORDS),
+            );
+        }
+        flush_fifos();
+
+        // 这里通过轮询 GINTSTS 处理事件，不需要打开 GAHBCFG 的 GINTMSK
+        // GINTSTS 中的标志不受 GINTMSK 的影响，poll 中读取时会与 GINTMSK 相与，只处理这里列出的事件
+        write(GINTSTS, 0xFFFF_FFFF);
+        write(
+            GINTMSK,
+            GINT__USBRST
+                | GINT__ENUMDNE
+                | GINT__RXFLVL
+                | GINT__IEPINT
+                | GINT__OEPINT
+                | GINT__USBSUSP
+                | GINT__WKUPINT,
+        );
+
+        // 释放 soft disconnect，D+ 被内部上拉，host 检测到设备接入
+        clear_bits(DCTL, DCTL__SDIS);
+    }
+
+    // 在主循环中不断调用
+    pub(crate) fn poll(&mut self) {
+        self.poll_with(&mut ());
+    }
+
+    // 与 poll 相同，Isochronous endpoint 的数据交给 handler
+    pub(crate) fn poll_with(&mut self, handler: &mut impl IsoHandler) {
+        let status = read(GINTSTS) & read(GINTMSK);
+
+        if status & GINT__USBRST != 0 {
+            write(GINTSTS, GINT__USBRST);
+            self.on_reset();
+        }
+
+        if status & GINT__ENUMDNE != 0 {
+            write(GINTSTS, GINT__ENUMDNE);
+            // Endpoint 0 的最大包长为 64 byte，并清除 global IN NAK
+            clear_bits(DIEPCTL0, DIEPCTL0__MPSIZ__MASK);
+            set_bits(DCTL, DCTL__CGINAK);
+        }
+
+        if status & GINT__USBSUSP != 0 {
+            write(GINTSTS, GINT__USBSUSP);
+            if self.state != DeviceState::Suspend {
+                self.resume_state = self.state;
+                self.state = DeviceState::Suspend;
+            }
+        }
+
+        if status & GINT__WKUPINT != 0 {
+            write(GINTSTS, GINT__WKUPINT);
+            if self.state == DeviceState::Suspend {
+                self.state = self.resume_state;
+            }
+        }
+
+        // SOF 要在端点中断之前处理，帧号与“endpoint 是否空闲”才是这一帧的
+        if status & GINT__SOF != 0 {
+            write(GINTSTS, GINT__SOF);
+            if let Some(iso) = self.iso.as_mut() {
+                iso.on_sof(handler);
+            }
+        }
+
+        // RXFLVL 会一直置位，直到 RxFIFO 被读空
+        while read(GINTSTS) & GINT__RXFLVL != 0 {
+            self.on_rx();
+        }
+
+        // OEPINT 与 IEPINT 是只读的，要清除的是 DOEPINTx 与 DIEPINTx 中的标志
+        if status & GINT__OEPINT != 0 {
+            self.on_out_endpoint(handler);
+        }
+
+        if status & GINT__IEPINT != 0 {
+            self.on_in_endpoint(handler);
+        }
+
+        if status & GINT__IISOIXFR != 0 {
+            write(GINTSTS, GINT__IISOIXFR);
+            if let Some(iso) = self.iso.as_mut() {
+                iso.on_incomplete_in();
+            }
+        }
+
+        if status & GINT__INCOMPISOOUT != 0 {
+            write(GINTSTS, GINT__INCOMPISOOUT);
+            if let Some(iso) = self.iso.as_mut() {
+                iso.on_incomplete_out();
+            }
+        }
+    }
+
+    fn on_reset(&mut self) {
+        set_bits(DOEPCTL0, DEPCTL__SNAK);
+
+        // 只打开 Endpoint 0 的中断，OUT 方向关心 SETUP 完成与传输完成，IN 方向关心传输完成
+        write(DAINTMSK, DAINT__IEP0 | DAINT__OEP0);
+        write(DOEPMSK, DOEPMSK__STUPM | DEPMSK__XFRCM);
+        write(DIEPMSK, DEPMSK__XFRCM);
+
+        clear_bits(DCFG, DCFG__DAD__MASK);
+        flush_fifos();
+
+        self.state = DeviceState::Default;
+        self.control = Control::Idle;
+        self.configuration = 0;
+        self.set_alt_setting(0);
+
+        arm_ep0_out();
+    }
+
+    fn on_rx(&mut self) {
+        let status = read(GRXSTSP);
+        let epnum = (status & GRXSTSP__EPNUM__MASK) as usize;
+        let pktsts = (status & GRXSTSP__PKTSTS__MASK) >> GRXSTSP__PKTSTS__SHIFT;
+        let bcnt = ((status & GRXSTSP__BCNT__MASK) >> GRXSTSP__BCNT__SHIFT) as usize;
+
+        match pktsts {
+            // 此时只是把 SETUP 包读出来，要等到 DOEPINT0 的 STUP 置位，也就是 SETUP 阶段真正结束时再处理
+            PKTSTS__SETUP_DATA => self.setup = [read_fifo(), read_fifo()],
+            PKTSTS__OUT_DATA if epnum == ISO_OUT_EP && self.iso.is_some() => {
+                if let Some(iso) = self.iso.as_mut() {
+                    iso.on_rx_data(bcnt);
+                }
+            }
+            // 这里支持的请求都没有 OUT 方向的 DATA 阶段，STATUS 阶段的 OUT 又是零长度包，
+            // 因此收到的数据都可以直接丢弃，但还是要把它们从 RxFIFO 中读出来
+            PKTSTS__OUT_DATA => {
+                for _ in 0..bcnt.div_ceil(4) {
+                    read_fifo();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn on_out_endpoint(&mut self, handler: &mut impl IsoHandler) {
+        let daint = read(DAINT);
+
+        if daint & daint_oep(ISO_OUT_EP) != 0 {
+            if let Some(iso) = self.iso.as_mut() {
+                iso.on_out_endpoint(handler);
+            }
+        }
+
+        if daint & DAINT__OEP0 == 0 {
+            return;
+        }
+
+        let int = read(DOEPINT0);
+        write(DOEPINT0, int);
+
+        // STATUS 阶段的零长度包收到了，这次控制传输结束
+        if int & DEPINT__XFRC != 0 && self.control == Control::StatusOut {
+            self.control = Control::Idle;
+        }
+
+        if int & DOEPINT__STUP != 0 {
+            self.on_setup(SetupPacket::parse(self.setup));
+        }
+
+        // 无论是哪种情况，都要重新准备好 Endpoint 0 OUT，接收下一个 SETUP 或者 STATUS 阶段的包
+        arm_ep0_out();
+    }
+
+    fn on_in_endpoint(&mut self, handler: &mut impl IsoHandler) {
+        let daint = read(DAINT);
+
+        if daint & daint_iep(ISO_IN_EP) != 0 {
+            if let Some(iso) = self.iso.as_mut() {
+                iso.on_in_endpoint(handler);
+            }
+        }
+
+        if daint & DAINT__IEP0 == 0 {
+            return;
+        }
+
+        let int = read(DIEPINT0);
+        write(DIEPINT0, int);
+
+        if int & DEPINT__XFRC == 0 {
+            return;
+        }
+
+        match self.control {
+            Control::DataIn if self.in_pos < self.in_len || self.in_zlp => self.send_next_packet(),
+            // 数据都发完了，接下来由 host 发送 STATUS 阶段的零长度包
+            Control::DataIn => self.control = Control::StatusOut,
+            Control::StatusIn => self.control = Control::Idle,
+            _ => (),
+        }
+    }
+
+    fn on_setup(&mut self, setup: SetupPacket) {
+        defmt::debug!(
+            "{} {} {} {}",
+            setup,
+            setup.direction(),
+            setup.kind(),
+            setup.recipient()
+        );
+
+        // 新的 SETUP 总是会打断之前未完成的控制传输
+        self.control = Control::Idle;
+
+        if setup.kind() != RequestType::Standard {
+            stall_ep0();
+            return;
+        }
+
+        match setup.direction() {
+            Direction::In => match self.standard_in(&setup) {
+                Some(len) => self.start_data_in(len, setup.length as usize),
+                None => stall_ep0(),
+            },
+            // 不支持带有 OUT DATA 阶段的请求
+            Direction::Out if setup.length != 0 => stall_ep0(),
+            Direction::Out => {
+                if self.standard_out(&setup) {
+                    self.control = Control::StatusIn;
+                    write_packet(&[]);
+                } else {
+                    stall_ep0();
+                }
+            }
+        }
+    }
+
+    // 处理需要返回数据的标准请求，把数据写入 buf，返回数据的长度，不支持时返回 None
+    fn standard_in(&mut self, setup: &SetupPacket) -> Option<usize> {
+        match setup.request {
+            setup::GET_DESCRIPTOR => match setup.descriptor_type() {
+                descriptor::DEVICE => self.reply(&descriptor::DEVICE_DESCRIPTOR),
+                descriptor::CONFIGURATION if self.iso.is_some() => {
+                    self.reply(&descriptor::ISO_CONFIGURATION_DESCRIPTOR)
+                }
+                descriptor::CONFIGURATION => self.reply(&descriptor::CONFIGURATION_DESCRIPTOR),
+                descriptor::STRING => descriptor::string(setup.descriptor_index(), &mut self.buf),
+                // 只支持 Full Speed 的设备，被问到 DEVICE_QUALIFIER 时，要以 STALL 回复
+                _ => None,
+            },
+            setup::GET_CONFIGURATION => self.reply(&[self.configuration]),
+            // 总线供电，不支持远程唤醒，endpoint 也没有 halt
+            setup::GET_STATUS => self.reply(&[0, 0]),
+            setup::GET_INTERFACE => self.reply(&[self.alt_setting]),
+            _ => None,
+        }
+    }
+
+    // 处理没有 DATA 阶段的标准请求，返回是否支持
+    fn standard_out(&mut self, setup: &SetupPacket) -> bool {
+        match setup.request {
+            setup::SET_ADDRESS => {
+                // 与很多 USB 控制器不同，OTG_FS 要求收到 SET_ADDRESS 之后立刻写入新的地址，
+                // core 自己会保证 STATUS 阶段依旧使用旧的地址 0
+                let address = (setup.value & 0x7F) as u32;
+                modify(DCFG, |v| {
+                    (v & !DCFG__DAD__MASK) | address << DCFG__DAD__SHIFT
+                });
+                self.state = if address == 0 {
+                    DeviceState::Default
+                } else {
+                    DeviceState::Addressed
+                };
+                true
+            }
+            setup::SET_CONFIGURATION => match setup.value {
+                0 => {
+                    self.configuration = 0;
+                    self.state = DeviceState::Addressed;
+                    self.set_alt_setting(0);
+                    true
+                }
+                1 => {
+                    self.configuration = 1;
+                    self.state = DeviceState::Configured;
+                    self.set_alt_setting(0);
+                    true
+                }
+                _ => false,
+            },
+            // 没有 Isochronous endpoint 时只有 alternate setting 0
+            setup::SET_INTERFACE => match setup.value {
+                0 => {
+                    self.set_alt_setting(0);
+                    true
+                }
+                1 if self.iso.is_some() && self.state == DeviceState::Configured => {
+                    self.set_alt_setting(1);
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn set_alt_setting(&mut self, alt_setting: u8) {
+        self.alt_setting = alt_setting;
+        if let Some(iso) = self.iso.as_mut() {
+            if alt_setting == 1 {
+                iso.activate();
+            } else {
+                iso.deactivate();
+            }
+        }
+    }
+
+    fn reply(&mut self, data: &[u8]) -> Option<usize> {
+        self.buf[..data.len()].copy_from_slice(data);
+        Some(data.len())
+    }
+
+    fn start_data_in(&mut self, len: usize, requested: usize) {
+        // 返回的数据不能超过 host 要求的长度（wLength），超过的部分直接截断
+        let len = len.min(requested);
+        self.in_len = len;
+        self.in_pos = 0;
+        self.in_zlp = len < requested && len.is_multiple_of(descriptor::EP0_MAX_PACKET as usize);
+        self.control = Control::DataIn;
+        self.send_next_packet();
+    }
+
+    fn send_next_packet(&mut self) {
+        let n = (self.in_len - self.in_pos).min(descriptor::EP0_MAX_PACKET as usize);
+        if n == 0 {
+            // 这就是那个额外的零长度包
+            self.in_zlp = false;
+        }
+        write_packet(&self.buf[self.in_pos..self.in_pos + n]);
+        self.in_pos += n;
+    }
+}
+
+// 配置引脚、打开时钟并复位 core，device 模式与 host 模式共用
+//
+// 复位之后打开内置的 Full Speed PHY，并关闭 VBUS 检测
+pub(crate) fn reset_core(dp: &Peripherals) {
+    // PA11 为 D-，PA12 为 D+，AF10
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.GPIOA.afrh.modify(|_, w| {
+        w.afrh11().af10();
+        w.afrh12().af10();
+        w
+    });
+    dp.GPIOA.ospeedr.modify(|_, w| {
+        w.ospeedr11().very_high_speed();
+        w.ospeedr12().very_high_speed();
+        w
+    });
+    dp.GPIOA.moder.modify(|_, w| {
+        w.moder11().alternate();
+        w.moder12().alternate();
+        w
+    });
+
+    dp.RCC.ahb2enr.modify(|_, w| w.otgfsen().enabled());
+
+    // 等待 AHB 空闲之后，才能复位 core
+    while read(GRSTCTL) & GRSTCTL__AHBIDL == 0 {}
+    set_bits(GRSTCTL, GRSTCTL__CSRST);
+    while read(GRSTCTL) & GRSTCTL__CSRST != 0 {}
+
+    write(GCCFG, GCCFG__PWRDWN);
+}
+
+// 通过 Endpoint 0 发送一个包，长度不超过 64 byte
+fn write_packet(data: &[u8]) {
+    // 先设置传输大小并使能 endpoint，再把数据写入 TxFIFO
+    write(DIEPTSIZ0, 1 << DEPTSIZ__PKTCNT__SHIFT | data.len() as u32);
+    set_bits(DIEPCTL0, DEPCTL__EPENA | DEPCTL__CNAK);
+
+    // TxFIFO 只能按 word 写入，不足 4 byte 的部分补 0，core 会按照 XFRSIZ 只发送有效的部分
+    for chunk in data.chunks(4) {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        write_fifo(0, u32::from_le_bytes(word));
+    }
+}
+
+// 准备好 Endpoint 0 OUT，最多连续接收 3 个 SETUP 包，或者一个最长 64 byte 的 OUT 包
+fn arm_ep0_out() {
+    write(
+        DOEPTSIZ0,
+        3 << DOEPTSIZ0__STUPCNT__SHIFT
+            | 1 << DEPTSIZ__PKTCNT__SHIFT
+            | descriptor::EP0_MAX_PACKET as u32,
+    );
+    set_bits(DOEPCTL0, DEPCTL__EPENA | DEPCTL__CNAK);
+}
+
+// IN 与 OUT 方向同时 STALL，下一个 SETUP 包到来时，core 会自动清除 STALL
+fn stall_ep0() {
+    set_bits(DIEPCTL0, DEPCTL__STALL);
+    set_bits(DOEPCTL0, DEPCTL__STALL);
+}
+
+pub(crate) fn flush_fifos() {
+    write(GRSTCTL, GRSTCTL__TXFFLSH | GRSTCTL__TXFNUM__ALL);
+    while read(GRSTCTL) & GRSTCTL__TXFFLSH != 0 {}
+    write(GRSTCTL, GRSTCTL__RXFFLSH);
+    while read(GRSTCTL) & GRSTCTL__RXFFLSH != 0 {}
+}
@@ -0,0 +1,267 @@
+//! OTG_FS 的寄存器
+//!
+//! 与 s01c01_basic_0addr 一样，这里直接以地址访问寄存器，所有的偏移与位置均来自 Reference Manual 的 OTG_FS registers 节
+//! 只列出了 device 模式与 host 模式下用得到的部分
+//!
+//! 命名方式为 寄存器名__位名，多个位组成的字段另外给出 __SHIFT 与 __MASK
+
+#![allow(dead_code)]
+
+const BASE: usize = 0x5000_0000;
+
+// Core global control and status registers
+pub(crate) const GOTGCTL: usize = 0x000;
+pub(crate) const GOTGCTL__BVALOEN: u32 = 1 << 7;
+pub(crate) const GOTGCTL__BVALOVAL: u32 = 1 << 6;
+
+pub(crate) const GAHBCFG: usize = 0x008;
+pub(crate) const GAHBCFG__GINTMSK: u32 = 1 << 0;
+
+pub(crate) const GUSBCFG: usize = 0x00C;
+pub(crate) const GUSBCFG__PHYSEL: u32 = 1 << 6;
+pub(crate) const GUSBCFG__TRDT__SHIFT: u32 = 10;
+pub(crate) const GUSBCFG__TRDT__MASK: u32 = 0xF << GUSBCFG__TRDT__SHIFT;
+pub(crate) const GUSBCFG__FHMOD: u32 = 1 << 29;
+pub(crate) const GUSBCFG__FDMOD: u32 = 1 << 30;
+
+pub(crate) const GRSTCTL: usize = 0x010;
+pub(crate) const GRSTCTL__CSRST: u32 = 1 << 0;
+pub(crate) const GRSTCTL__RXFFLSH: u32 = 1 << 4;
+pub(crate) const GRSTCTL__TXFFLSH: u32 = 1 << 5;
+pub(crate) const GRSTCTL__TXFNUM__SHIFT: u32 = 6;
+// TXFNUM 为 0x10 时，表示刷新所有的 TxFIFO
+pub(crate) const GRSTCTL__TXFNUM__ALL: u32 = 0x10 << GRSTCTL__TXFNUM__SHIFT;
+pub(crate) const GRSTCTL__AHBIDL: u32 = 1 << 31;
+
+// GINTSTS 与 GINTMSK 的位是一一对应的
+pub(crate) const GINTSTS: usize = 0x014;
+pub(crate) const GINTMSK: usize = 0x018;
+pub(crate) const GINT__SOF: u32 = 1 << 3;
+pub(crate) const GINT__RXFLVL: u32 = 1 << 4;
+pub(crate) const GINT__USBSUSP: u32 = 1 << 11;
+pub(crate) const GINT__USBRST: u32 = 1 << 12;
+pub(crate) const GINT__ENUMDNE: u32 = 1 << 13;
+pub(crate) const GINT__IEPINT: u32 = 1 << 18;
+pub(crate) const GINT__OEPINT: u32 = 1 << 19;
+// 有 Isochronous IN endpoint 没能在这一帧发出数据
+pub(crate) const GINT__IISOIXFR: u32 = 1 << 20;
+// 有 Isochronous OUT endpoint 没能在这一帧收到数据
+pub(crate) const GINT__INCOMPISOOUT: u32 = 1 << 21;
+pub(crate) const GINT__HPRTINT: u32 = 1 << 24;
+pub(crate) const GINT__HCINT: u32 = 1 << 25;
+pub(crate) const GINT__DISCINT: u32 = 1 << 29;
+pub(crate) const GINT__WKUPINT: u32 = 1 << 31;
+
+// 读取 GRXSTSP 会把这一项从 RxFIFO 中弹出
+pub(crate) const GRXSTSP: usize = 0x020;
+pub(crate) const GRXSTSP__EPNUM__MASK: u32 = 0xF;
+pub(crate) const GRXSTSP__BCNT__SHIFT: u32 = 4;
+pub(crate) const GRXSTSP__BCNT__MASK: u32 = 0x7FF << GRXSTSP__BCNT__SHIFT;
+pub(crate) const GRXSTSP__PKTSTS__SHIFT: u32 = 17;
+pub(crate) const GRXSTSP__PKTSTS__MASK: u32 = 0xF << GRXSTSP__PKTSTS__SHIFT;
+pub(crate) const PKTSTS__OUT_DATA: u32 = 0b0010;
+pub(crate) const PKTSTS__OUT_COMPLETE: u32 = 0b0011;
+pub(crate) const PKTSTS__SETUP_COMPLETE: u32 = 0b0100;
+pub(crate) const PKTSTS__SETUP_DATA: u32 = 0b0110;
+// host 模式下，GRXSTSP 的 EPNUM 字段为 CHNUM，PKTSTS 的含义也不同
+pub(crate) const PKTSTS__HOST_IN_DATA: u32 = 0b0010;
+
+// 单位均为 word（4 byte）
+pub(crate) const GRXFSIZ: usize = 0x024;
+pub(crate) const DIEPTXF0: usize = 0x028;
+// Endpoint 1 的 TxFIFO，格式与 DIEPTXF0 相同，之后的 endpoint 依次向后偏移 4
+pub(crate) const DIEPTXF1: usize = 0x104;
+// host 模式下，0x028 为 non-periodic TxFIFO（控制与批量传输）的 HNPTXFSIZ，格式与 DIEPTXF0 相同
+pub(crate) const HNPTXFSIZ: usize = 0x028;
+// periodic TxFIFO（中断与同步传输）
+pub(crate) const HPTXFSIZ: usize = 0x100;
+
+pub(crate) const GCCFG: usize = 0x038;
+pub(crate) const GCCFG__PWRDWN: u32 = 1 << 16;
+pub(crate) const GCCFG__VBDEN: u32 = 1 << 21;
+
+// Host-mode registers
+pub(crate) const HCFG: usize = 0x400;
+pub(crate) const HCFG__FSLSPCS__MASK: u32 = 0b11;
+// PHY 时钟为 48 MHz，用于 Full Speed 设备
+pub(crate) const HCFG__FSLSPCS__48MHZ: u32 = 0b01;
+// PHY 时钟为 6 MHz，用于直接接在端口上的 Low Speed 设备
+pub(crate) const HCFG__FSLSPCS__6MHZ: u32 = 0b10;
+
+// 帧间隔，单位为 PHY 时钟的周期，1 ms 一帧
+pub(crate) const HFIR: usize = 0x404;
+
+// 当前的帧号，bit 0 为 1 时是奇数帧
+pub(crate) const HFNUM: usize = 0x408;
+
+pub(crate) const HAINT: usize = 0x414;
+
+// HPRT 中 PENA、PCDET、PENCHNG、POCCHNG 四个位写 1 清零（PENA 写 1 甚至会关闭端口），
+// 因此修改 HPRT 时，必须先把读回的值中的这几位清零，见 modify_hprt
+pub(crate) const HPRT: usize = 0x440;
+pub(crate) const HPRT__PCSTS: u32 = 1 << 0;
+pub(crate) const HPRT__PCDET: u32 = 1 << 1;
+pub(crate) const HPRT__PENA: u32 = 1 << 2;
+pub(crate) const HPRT__PENCHNG: u32 = 1 << 3;
+pub(crate) const HPRT__POCCHNG: u32 = 1 << 5;
+pub(crate) const HPRT__PRST: u32 = 1 << 8;
+pub(crate) const HPRT__PPWR: u32 = 1 << 12;
+pub(crate) const HPRT__PSPD__SHIFT: u32 = 17;
+pub(crate) const HPRT__PSPD__MASK: u32 = 0b11 << HPRT__PSPD__SHIFT;
+pub(crate) const PSPD__LOW_SPEED: u32 = 0b10;
+const HPRT__W1C: u32 = HPRT__PCDET | HPRT__PENA | HPRT__PENCHNG | HPRT__POCCHNG;
+
+// Channel 0 的寄存器，其它 channel 的寄存器依次向后偏移 0x20，OTG_FS 一共有 8 个 channel
+pub(crate) const HCCHAR0: usize = 0x500;
+pub(crate) const HCINT0: usize = 0x508;
+pub(crate) const HCINTMSK0: usize = 0x50C;
+pub(crate) const HCTSIZ0: usize = 0x510;
+pub(crate) const CHANNEL_STRIDE: usize = 0x20;
+pub(crate) const CHANNEL_COUNT: usize = 8;
+
+pub(crate) const HCCHAR__EPNUM__SHIFT: u32 = 11;
+pub(crate) const HCCHAR__EPDIR_IN: u32 = 1 << 15;
+pub(crate) const HCCHAR__LSDEV: u32 = 1 << 17;
+pub(crate) const HCCHAR__EPTYP__SHIFT: u32 = 18;
+pub(crate) const HCCHAR__MCNT__SHIFT: u32 = 20;
+pub(crate) const HCCHAR__DAD__SHIFT: u32 = 22;
+pub(crate) const HCCHAR__ODDFRM: u32 = 1 << 29;
+pub(crate) const HCCHAR__CHDIS: u32 = 1 << 30;
+pub(crate) const HCCHAR__CHENA: u32 = 1 << 31;
+
+pub(crate) const HCINT__XFRC: u32 = 1 << 0;
+pub(crate) const HCINT__CHH: u32 = 1 << 1;
+pub(crate) const HCINT__STALL: u32 = 1 << 3;
+pub(crate) const HCINT__NAK: u32 = 1 << 4;
+pub(crate) const HCINT__ACK: u32 = 1 << 5;
+pub(crate) const HCINT__TXERR: u32 = 1 << 7;
+pub(crate) const HCINT__BBERR: u32 = 1 << 8;
+pub(crate) const HCINT__FRMOR: u32 = 1 << 9;
+pub(crate) const HCINT__DTERR: u32 = 1 << 10;
+
+pub(crate) const HCTSIZ__PKTCNT__SHIFT: u32 = 19;
+pub(crate) const HCTSIZ__DPID__SHIFT: u32 = 29;
+pub(crate) const DPID__DATA0: u32 = 0b00;
+pub(crate) const DPID__DATA1: u32 = 0b10;
+pub(crate) const DPID__SETUP: u32 = 0b11;
+
+// Device-mode registers
+pub(crate) const DCFG: usize = 0x800;
+pub(crate) const DCFG__DSPD__FULL_SPEED: u32 = 0b11;
+pub(crate) const DCFG__DAD__SHIFT: u32 = 4;
+pub(crate) const DCFG__DAD__MASK: u32 = 0x7F << DCFG__DAD__SHIFT;
+
+pub(crate) const DCTL: usize = 0x804;
+pub(crate) const DCTL__SDIS: u32 = 1 << 1;
+pub(crate) const DCTL__CGINAK: u32 = 1 << 8;
+
+// 最近一次 SOF 的帧号
+pub(crate) const DSTS: usize = 0x808;
+pub(crate) const DSTS__FNSOF__SHIFT: u32 = 8;
+pub(crate) const DSTS__FNSOF__MASK: u32 = 0x3FFF << DSTS__FNSOF__SHIFT;
+
+pub(crate) const DIEPMSK: usize = 0x810;
+pub(crate) const DOEPMSK: usize = 0x814;
+pub(crate) const DEPMSK__XFRCM: u32 = 1 << 0;
+pub(crate) const DOEPMSK__STUPM: u32 = 1 << 3;
+
+pub(crate) const DAINT: usize = 0x818;
+pub(crate) const DAINTMSK: usize = 0x81C;
+pub(crate) const DAINT__IEP0: u32 = 1 << 0;
+pub(crate) const DAINT__OEP0: u32 = 1 << 16;
+// 低 16 bit 为 IN endpoint，高 16 bit 为 OUT endpoint
+pub(crate) const fn daint_iep(ep: usize) -> u32 {
+    1 << ep
+}
+pub(crate) const fn daint_oep(ep: usize) -> u32 {
+    1 << (16 + ep)
+}
+
+// Endpoint 0 的寄存器，其它 endpoint 的寄存器依次向后偏移 0x20
+pub(crate) const DIEPCTL0: usize = 0x900;
+pub(crate) const DIEPINT0: usize = 0x908;
+pub(crate) const DIEPTSIZ0: usize = 0x910;
+pub(crate) const DOEPCTL0: usize = 0xB00;
+pub(crate) const DOEPINT0: usize = 0xB08;
+pub(crate) const DOEPTSIZ0: usize = 0xB10;
+pub(crate) const ENDPOINT_STRIDE: usize = 0x20;
+
+// DIEPCTLx 与 DOEPCTLx 共有的位
+// Endpoint 1 ~ 3 的 MPSIZ 直接以 byte 为单位
+pub(crate) const DEPCTL__MPSIZ__MASK: u32 = 0x7FF;
+pub(crate) const DEPCTL__USBAEP: u32 = 1 << 15;
+// Isochronous endpoint 的 EONUM：为 1 时，endpoint 将在奇数帧收发数据
+pub(crate) const DEPCTL__EONUM: u32 = 1 << 16;
+pub(crate) const DEPCTL__EPTYP__SHIFT: u32 = 18;
+pub(crate) const DEPCTL__EPTYP__MASK: u32 = 0b11 << DEPCTL__EPTYP__SHIFT;
+pub(crate) const EPTYP__ISOCHRONOUS: u32 = 0b01;
+pub(crate) const DEPCTL__STALL: u32 = 1 << 21;
+// 只有 IN endpoint 有，使用哪一个 TxFIFO
+pub(crate) const DIEPCTL__TXFNUM__SHIFT: u32 = 22;
+pub(crate) const DEPCTL__CNAK: u32 = 1 << 26;
+pub(crate) const DEPCTL__SNAK: u32 = 1 << 27;
+// Isochronous endpoint 的 SEVNFRM 与 SODDFRM：指定在偶数帧还是奇数帧收发数据
+pub(crate) const DEPCTL__SEVNFRM: u32 = 1 << 28;
+pub(crate) const DEPCTL__SODDFRM: u32 = 1 << 29;
+pub(crate) const DEPCTL__EPDIS: u32 = 1 << 30;
+pub(crate) const DEPCTL__EPENA: u32 = 1 << 31;
+// Endpoint 0 的 MPSIZ 为 0b00 时，表示 64 byte
+pub(crate) const DIEPCTL0__MPSIZ__MASK: u32 = 0b11;
+
+// DIEPINTx 与 DOEPINTx 共有的位
+pub(crate) const DEPINT__XFRC: u32 = 1 << 0;
+pub(crate) const DEPINT__EPDISD: u32 = 1 << 1;
+pub(crate) const DOEPINT__STUP: u32 = 1 << 3;
+
+pub(crate) const DEPTSIZ__PKTCNT__SHIFT: u32 = 19;
+pub(crate) const DOEPTSIZ0__STUPCNT__SHIFT: u32 = 29;
+// Isochronous IN endpoint 每一帧发送几个包
+pub(crate) const DIEPTSIZ__MCNT__SHIFT: u32 = 29;
+
+// 每个 endpoint 的 FIFO 都占 0x1000 的地址空间，读写其中任意地址都等价
+const FIFO: usize = 0x1000;
+
+pub(crate) fn read(offset: usize) -> u32 {
+    unsafe { ((BASE + offset) as *const u32).read_volatile() }
+}
+
+pub(crate) fn write(offset: usize, value: u32) {
+    unsafe { ((BASE + offset) as *mut u32).write_volatile(value) }
+}
+
+pub(crate) fn modify(offset: usize, f: impl FnOnce(u32) -> u32) {
+    write(offset, f(read(offset)));
+}
+
+pub(crate) fn set_bits(offset: usize, mask: u32) {
+    modify(offset, |v| v | mask);
+}
+
+pub(crate) fn clear_bits(offset: usize, mask: u32) {
+    modify(offset, |v| v & !mask);
+}
+
+// 注意：RxFIFO 只有一个，所有 OUT endpoint 的数据都从 FIFO 0 的地址读出
+pub(crate) fn read_fifo() -> u32 {
+    read(FIFO)
+}
+
+// host 模式下，ep 为 channel 的编号
+pub(crate) fn write_fifo(ep: usize, value: u32) {
+    write(FIFO * (ep + 1), value);
+}
+
+// channel 的寄存器，base 为 channel 0 的寄存器
+pub(crate) fn channel(base: usize, ch: usize) -> usize {
+    base + ch * CHANNEL_STRIDE
+}
+
+// endpoint 的寄存器，base 为 endpoint 0 的寄存器
+pub(crate) fn endpoint(base: usize, ep: usize) -> usize {
+    base + ep * ENDPOINT_STRIDE
+}
+
+// 修改 HPRT 时，不会意外地清除写 1 清零的位
+pub(crate) fn modify_hprt(f: impl FnOnce(u32) -> u32) {
+    write(HPRT, f(read(HPRT) & !HPRT__W1C));
+}
@@ -0,0 +1,114 @@
+//! SETUP 包的解析
+//!
+//! 对照 USB 2.0 Specification 的 Format of Setup Data 表，以及 _note/minimal_usb_request.adoc 中的解析
+
+#![allow(dead_code)]
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Direction {
+    // Host -> Device
+    Out,
+    // Device -> Host
+    In,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum RequestType {
+    Standard,
+    Class,
+    Vendor,
+    Reserved,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Recipient {
+    Device,
+    Interface,
+    Endpoint,
+    Other,
+}
+
+// Standard Request Codes 表
+pub(crate) const GET_STATUS: u8 = 0;
+pub(crate) const CLEAR_FEATURE: u8 = 1;
+pub(crate) const SET_FEATURE: u8 = 3;
+pub(crate) const SET_ADDRESS: u8 = 5;
+pub(crate) const GET_DESCRIPTOR: u8 = 6;
+pub(crate) const SET_DESCRIPTOR: u8 = 7;
+pub(crate) const GET_CONFIGURATION: u8 = 8;
+pub(crate) const SET_CONFIGURATION: u8 = 9;
+pub(crate) const GET_INTERFACE: u8 = 10;
+pub(crate) const SET_INTERFACE: u8 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct SetupPacket {
+    pub(crate) request_type: u8,
+    pub(crate) request: u8,
+    pub(crate) value: u16,
+    pub(crate) index: u16,
+    pub(crate) length: u16,
+}
+
+impl SetupPacket {
+    // SETUP 包总是 8 个字节，从 RxFIFO 中以两个 word 的形式读出，USB 为小端序
+    pub(crate) fn parse(words: [u32; 2]) -> Self {
+        let [b0, b1, b2, b3] = words[0].to_le_bytes();
+        let [b4, b5, b6, b7] = words[1].to_le_bytes();
+        Self {
+            request_type: b0,
+            request: b1,
+            value: u16::from_le_bytes([b2, b3]),
+            index: u16::from_le_bytes([b4, b5]),
+            length: u16::from_le_bytes([b6, b7]),
+        }
+    }
+
+    // host 模式下，把 SETUP 包按照同样的格式打包为两个 word，写入 TxFIFO
+    pub(crate) fn to_words(self) -> [u32; 2] {
+        let [v0, v1] = self.value.to_le_bytes();
+        let [i0, i1] = self.index.to_le_bytes();
+        let [l0, l1] = self.length.to_le_bytes();
+        [
+            u32::from_le_bytes([self.request_type, self.request, v0, v1]),
+            u32::from_le_bytes([i0, i1, l0, l1]),
+        ]
+    }
+
+    // bmRequestType 的 bit 7
+    pub(crate) fn direction(&self) -> Direction {
+        if self.request_type & 0x80 != 0 {
+            Direction::In
+        } else {
+            Direction::Out
+        }
+    }
+
+    // bmRequestType 的 bit 6 ~ 5
+    pub(crate) fn kind(&self) -> RequestType {
+        match (self.request_type >> 5) & 0b11 {
+            0 => RequestType::Standard,
+            1 => RequestType::Class,
+            2 => RequestType::Vendor,
+            _ => RequestType::Reserved,
+        }
+    }
+
+    // bmRequestType 的 bit 4 ~ 0
+    pub(crate) fn recipient(&self) -> Recipient {
+        match self.request_type & 0x1F {
+            0 => Recipient::Device,
+            1 => Recipient::Interface,
+            2 => Recipient::Endpoint,
+            _ => Recipient::Other,
+        }
+    }
+
+    // GET_DESCRIPTOR 时，wValue 的高字节为 descriptor type，低字节为 descriptor index
+    pub(crate) fn descriptor_type(&self) -> u8 {
+        (self.value >> 8) as u8
+    }
+
+    pub(crate) fn descriptor_index(&self) -> u8 {
+        self.value as u8
+    }
+}
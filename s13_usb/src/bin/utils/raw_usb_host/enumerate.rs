@@ -0,0 +1,257 @@
+//! 枚举：host 端视角下的 s13c06
+//!
+//! 步骤与 _note/minimal_usb_request.adoc 中记录的 host 请求顺序基本一致：
+//!
+//! 1. 以地址 0 读取 device descriptor 的前 8 个字节，得到 endpoint 0 的最大包长（bMaxPacketSize0）
+//! 2. SET_ADDRESS，之后设备使用新的地址
+//! 3. 读取完整的 device descriptor，得到 VID、PID
+//! 4. 先读取 configuration descriptor 的前 9 个字节得到 wTotalLength，再读取完整的 configuration，
+//!    从中解析出 interface 与 endpoint
+//! 5. SET_CONFIGURATION，设备进入 Configured 状态，class 驱动可以开始工作
+
+#![allow(dead_code)]
+
+use super::super::raw_usb::descriptor::{CONFIGURATION, DEVICE, ENDPOINT, INTERFACE};
+use super::super::raw_usb::setup::{SetupPacket, GET_DESCRIPTOR, SET_ADDRESS, SET_CONFIGURATION};
+use super::{delay_ms, EndpointType, HostError, Pipe, UsbHost};
+
+// 只记录前几个 interface，以及每个 interface 的前几个 endpoint，对于键盘和 U 盘来说足够了
+const MAX_INTERFACES: usize = 4;
+const MAX_ENDPOINTS: usize = 4;
+const CONFIG_BUF_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub(crate) struct EndpointInfo {
+    // bit 7 为方向，1 为 IN
+    pub(crate) address: u8,
+    // bit 1 ~ 0 为传输类型
+    pub(crate) attributes: u8,
+    pub(crate) max_packet: u16,
+    // 中断传输的轮询间隔，Full/Low Speed 下单位为 ms
+    pub(crate) interval: u8,
+}
+
+impl EndpointInfo {
+    pub(crate) fn number(&self) -> u8 {
+        self.address & 0x0F
+    }
+
+    pub(crate) fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+
+    pub(crate) fn kind(&self) -> EndpointType {
+        match self.attributes & 0b11 {
+            0 => EndpointType::Control,
+            1 => EndpointType::Isochronous,
+            2 => EndpointType::Bulk,
+            _ => EndpointType::Interrupt,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub(crate) struct InterfaceInfo {
+    pub(crate) number: u8,
+    pub(crate) class: u8,
+    pub(crate) subclass: u8,
+    pub(crate) protocol: u8,
+    pub(crate) endpoints: [Option<EndpointInfo>; MAX_ENDPOINTS],
+}
+
+impl InterfaceInfo {
+    // 查找指定类型与方向的第一个 endpoint
+    pub(crate) fn find_endpoint(&self, kind: EndpointType, dir_in: bool) -> Option<EndpointInfo> {
+        self.endpoints
+            .iter()
+            .flatten()
+            .find(|ep| ep.kind() == kind && ep.is_in() == dir_in)
+            .copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub(crate) struct DeviceInfo {
+    pub(crate) address: u8,
+    pub(crate) max_packet0: u8,
+    pub(crate) vid: u16,
+    pub(crate) pid: u16,
+    pub(crate) configuration: u8,
+    pub(crate) interfaces: [Option<InterfaceInfo>; MAX_INTERFACES],
+}
+
+impl DeviceInfo {
+    pub(crate) fn find_interface(
+        &self,
+        class: u8,
+        subclass: u8,
+        protocol: u8,
+    ) -> Option<InterfaceInfo> {
+        self.interfaces
+            .iter()
+            .flatten()
+            .find(|itf| itf.class == class && itf.subclass == subclass && itf.protocol == protocol)
+            .copied()
+    }
+}
+
+// 枚举刚复位过的设备，并为它分配地址 address
+//
+// 返回设备的信息，以及之后与设备 endpoint 0 通信用的 Pipe
+pub(crate) fn enumerate(host: &mut UsbHost, address: u8) -> Result<(DeviceInfo, Pipe), HostError> {
+    // 此时还不知道 endpoint 0 的最大包长，而所有设备都至少支持 8 字节
+    let mut ep0 = host.alloc_pipe(0, 0, EndpointType::Control, 8)?;
+    let result = enumerate_with(host, &mut ep0, address);
+    match result {
+        Ok(info) => Ok((info, ep0)),
+        Err(e) => {
+            host.free_pipe(ep0);
+            Err(e)
+        }
+    }
+}
+
+fn enumerate_with(
+    host: &mut UsbHost,
+    ep0: &mut Pipe,
+    address: u8,
+) -> Result<DeviceInfo, HostError> {
+    let mut buf = [0u8; 18];
+
+    // 第一步，只读前 8 个字节，里面已经包含了 bMaxPacketSize0
+    host.control_in(ep0, &get_descriptor(DEVICE, 0, 8), &mut buf[..8])?;
+    if buf[1] != DEVICE {
+        return Err(HostError::Descriptor);
+    }
+    let max_packet0 = buf[7];
+    if !matches!(max_packet0, 8 | 16 | 32 | 64) {
+        return Err(HostError::Descriptor);
+    }
+    ep0.max_packet = max_packet0 as u16;
+
+    // 第二步，SET_ADDRESS 的 STATUS 阶段完成之后，设备才切换到新的地址
+    host.control_out(
+        ep0,
+        &SetupPacket {
+            request_type: 0x00,
+            request: SET_ADDRESS,
+            value: address as u16,
+            index: 0,
+            length: 0,
+        },
+        &[],
+    )?;
+    // USB 2.0 Spec 9.2.6.3，给设备 2 ms 的恢复时间
+    delay_ms(2);
+    ep0.address = address;
+
+    // 第三步，完整的 device descriptor
+    let n = host.control_in(ep0, &get_descriptor(DEVICE, 0, 18), &mut buf)?;
+    if n < 18 || buf[1] != DEVICE {
+        return Err(HostError::Descriptor);
+    }
+    let vid = u16::from_le_bytes([buf[8], buf[9]]);
+    let pid = u16::from_le_bytes([buf[10], buf[11]]);
+
+    // 第四步，先读 configuration descriptor 本身，得到包含所有下级描述符的总长度
+    let mut config = [0u8; CONFIG_BUF_LEN];
+    let n = host.control_in(ep0, &get_descriptor(CONFIGURATION, 0, 9), &mut config[..9])?;
+    if n < 9 || config[1] != CONFIGURATION {
+        return Err(HostError::Descriptor);
+    }
+    let total = (u16::from_le_bytes([config[2], config[3]]) as usize).min(CONFIG_BUF_LEN);
+    let configuration = config[5];
+
+    let n = host.control_in(
+        ep0,
+        &get_descriptor(CONFIGURATION, 0, total as u16),
+        &mut config[..total],
+    )?;
+    let interfaces = parse_configuration(&config[..n])?;
+
+    // 第五步
+    host.control_out(
+        ep0,
+        &SetupPacket {
+            request_type: 0x00,
+            request: SET_CONFIGURATION,
+            value: configuration as u16,
+            index: 0,
+            length: 0,
+        },
+        &[],
+    )?;
+
+    Ok(DeviceInfo {
+        address,
+        max_packet0,
+        vid,
+        pid,
+        configuration,
+        interfaces,
+    })
+}
+
+fn get_descriptor(descriptor_type: u8, index: u8, length: u16) -> SetupPacket {
+    SetupPacket {
+        // Device -> Host，Standard，Device
+        request_type: 0x80,
+        request: GET_DESCRIPTOR,
+        value: (descriptor_type as u16) << 8 | index as u16,
+        index: 0,
+        length,
+    }
+}
+
+// configuration descriptor 之后紧跟着各个描述符，每个描述符的第 0 个字节为长度，第 1 个字节为类型
+// endpoint descriptor 属于它之前最近的那个 interface descriptor，其它类型（例如 HID descriptor）直接跳过
+fn parse_configuration(data: &[u8]) -> Result<[Option<InterfaceInfo>; MAX_INTERFACES], HostError> {
+    let mut interfaces = [None; MAX_INTERFACES];
+    let mut current: Option<usize> = None;
+    let mut count = 0;
+
+    let mut offset = 0;
+    while offset + 2 <= data.len() {
+        let len = data[offset] as usize;
+        if len < 2 || offset + len > data.len() {
+            return Err(HostError::Descriptor);
+        }
+        let desc = &data[offset..offset + len];
+
+        match desc[1] {
+            // 只记录 alternate setting 为 0 的 interface
+            INTERFACE if len >= 9 && desc[3] == 0 => {
+                current = None;
+                if count < MAX_INTERFACES {
+                    interfaces[count] = Some(InterfaceInfo {
+                        number: desc[2],
+                        class: desc[5],
+                        subclass: desc[6],
+                        protocol: desc[7],
+                        endpoints: [None; MAX_ENDPOINTS],
+                    });
+                    current = Some(count);
+                    count += 1;
+                }
+            }
+            INTERFACE => current = None,
+            ENDPOINT if len >= 7 => {
+                if let Some(Some(itf)) = current.map(|i| interfaces[i].as_mut()) {
+                    if let Some(slot) = itf.endpoints.iter_mut().find(|ep| ep.is_none()) {
+                        *slot = Some(EndpointInfo {
+                            address: desc[2],
+                            attributes: desc[3],
+                            max_packet: u16::from_le_bytes([desc[4], desc[5]]) & 0x7FF,
+                            interval: desc[6],
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        offset += len;
+    }
+
+    Ok(interfaces)
+}
@@ -0,0 +1,192 @@
+//! HID Boot Protocol 键盘
+//!
+//! 支持 Boot Protocol 的键盘（interface 的 class/subclass/protocol 为 3/1/1），
+//! 在切换到 Boot Protocol 之后，不需要解析 Report Descriptor，输入报告的格式是固定的 8 个字节：
+//!
+//! | 字节  | 含义                                                          |
+//! | ----- | ------------------------------------------------------------- |
+//! | 0     | 修饰键，bit 0~7 依次为左 Ctrl/Shift/Alt/GUI，右 Ctrl/Shift/Alt/GUI |
+//! | 1     | 保留                                                          |
+//! | 2 ~ 7 | 当前按下的按键的 usage ID，最多 6 个，0 表示空                    |
+//!
+//! 见 Device Class Definition for HID 1.11 的 Appendix B，与 HID Usage Tables 的 Keyboard/Keypad Page
+
+#![allow(dead_code)]
+
+use super::super::raw_usb::setup::SetupPacket;
+use super::enumerate::DeviceInfo;
+use super::{EndpointType, HostError, Pipe, UsbHost};
+
+const CLASS_HID: u8 = 0x03;
+const SUBCLASS_BOOT: u8 = 0x01;
+const PROTOCOL_KEYBOARD: u8 = 0x01;
+
+// HID class request
+const SET_IDLE: u8 = 0x0A;
+const SET_PROTOCOL: u8 = 0x0B;
+
+const MOD_LEFT_SHIFT: u8 = 1 << 1;
+const MOD_RIGHT_SHIFT: u8 = 1 << 5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) struct KeyReport {
+    pub(crate) modifiers: u8,
+    pub(crate) keys: [u8; 6],
+}
+
+impl KeyReport {
+    pub(crate) fn shift(&self) -> bool {
+        self.modifiers & (MOD_LEFT_SHIFT | MOD_RIGHT_SHIFT) != 0
+    }
+
+    // 在这次报告中按下，而上次报告中没有的按键
+    pub(crate) fn newly_pressed<'a>(
+        &'a self,
+        last: &'a KeyReport,
+    ) -> impl Iterator<Item = u8> + 'a {
+        self.keys
+            .iter()
+            .copied()
+            // 0 为空，1 ~ 3 为错误码（例如同时按下太多键时，6 个位置都会是 1）
+            .filter(|&k| k > 3)
+            .filter(move |k| !last.keys.contains(k))
+    }
+}
+
+pub(crate) struct BootKeyboard {
+    pipe: Pipe,
+    // 中断 endpoint 的轮询间隔，单位 ms
+    interval: u8,
+    last: KeyReport,
+}
+
+impl BootKeyboard {
+    // 在枚举结果中查找 Boot Protocol 键盘，找到的话切换到 Boot Protocol，并分配中断 IN 的 Pipe
+    //
+    // 返回 Ok(None) 表示这个设备不是键盘
+    pub(crate) fn setup(
+        host: &mut UsbHost,
+        ep0: &mut Pipe,
+        info: &DeviceInfo,
+    ) -> Result<Option<Self>, HostError> {
+        let Some(itf) = info.find_interface(CLASS_HID, SUBCLASS_BOOT, PROTOCOL_KEYBOARD) else {
+            return Ok(None);
+        };
+        let Some(ep) = itf.find_endpoint(EndpointType::Interrupt, true) else {
+            return Err(HostError::Descriptor);
+        };
+
+        // 0 为 Boot Protocol，1 为 Report Protocol
+        host.control_out(ep0, &class_request(SET_PROTOCOL, 0, itf.number), &[])?;
+
+        // Idle rate 为 0，只有按键状态改变时键盘才发送报告，而不是周期性地重复发送
+        // 这个请求是可选的，不少键盘会以 STALL 回复，忽略即可
+        match host.control_out(ep0, &class_request(SET_IDLE, 0, itf.number), &[]) {
+            Ok(()) | Err(HostError::Stall) => {}
+            Err(e) => return Err(e),
+        }
+
+        let pipe = host.alloc_pipe(
+            info.address,
+            ep.number(),
+            EndpointType::Interrupt,
+            ep.max_packet,
+        )?;
+
+        Ok(Some(Self {
+            pipe,
+            interval: ep.interval.max(1),
+            last: KeyReport::default(),
+        }))
+    }
+
+    pub(crate) fn interval_ms(&self) -> u32 {
+        self.interval as u32
+    }
+
+    // 发起一次中断 IN 传输，键盘没有新的报告（NAK）时返回 Ok(None)
+    //
+    // 两次调用之间，应该至少间隔 interval_ms
+    pub(crate) fn poll(&mut self, host: &mut UsbHost) -> Result<Option<KeyReport>, HostError> {
+        let mut buf = [0u8; 8];
+        let n = match host.transfer_in(&mut self.pipe, &mut buf) {
+            Ok(n) => n,
+            Err(HostError::Nak) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if n < 3 {
+            return Ok(None);
+        }
+
+        let mut keys = [0u8; 6];
+        keys[..n - 2].copy_from_slice(&buf[2..n]);
+        Ok(Some(KeyReport {
+            modifiers: buf[0],
+            keys,
+        }))
+    }
+
+    // 与 poll 相同，但只返回新按下的按键对应的字符，交给 on_char 处理
+    pub(crate) fn poll_chars(
+        &mut self,
+        host: &mut UsbHost,
+        mut on_char: impl FnMut(char),
+    ) -> Result<(), HostError> {
+        if let Some(report) = self.poll(host)? {
+            for usage in report.newly_pressed(&self.last) {
+                if let Some(c) = to_ascii(usage, report.shift()) {
+                    on_char(c);
+                }
+            }
+            self.last = report;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn release(self, host: &mut UsbHost) {
+        host.free_pipe(self.pipe);
+    }
+}
+
+fn class_request(request: u8, value: u16, interface: u8) -> SetupPacket {
+    SetupPacket {
+        // Host -> Device，Class，Interface
+        request_type: 0x21,
+        request,
+        value,
+        index: interface as u16,
+        length: 0,
+    }
+}
+
+// 把 Keyboard/Keypad Page 的 usage ID 转换为字符，按照 US 布局，不认识的按键返回 None
+pub(crate) fn to_ascii(usage: u8, shift: bool) -> Option<char> {
+    const DIGITS: &[u8; 10] = b"1234567890";
+    const DIGITS_SHIFT: &[u8; 10] = b"!@#$%^&*()";
+    // 0x2D ~ 0x38
+    const SYMBOLS: &[u8; 12] = b"-=[]\\#;'`,./";
+    const SYMBOLS_SHIFT: &[u8; 12] = b"_+{}|~:\"~<>?";
+
+    let byte = match usage {
+        // a ~ z
+        0x04..=0x1D => {
+            let base = if shift { b'A' } else { b'a' };
+            base + (usage - 0x04)
+        }
+        // 1 ~ 9, 0
+        0x1E..=0x27 => {
+            let table = if shift { DIGITS_SHIFT } else { DIGITS };
+            table[(usage - 0x1E) as usize]
+        }
+        0x28 => b'\n',
+        0x2A => 0x08,
+        0x2B => b'\t',
+        0x2C => b' ',
+        0x2D..=0x38 => {
+            let table = if shift { SYMBOLS_SHIFT } else { SYMBOLS };
+            table[(usage - 0x2D) as usize]
+        }
+        _ => return None,
+    };
+    Some(byte as char)
+}
@@ -0,0 +1,501 @@
+//! 直接操作寄存器的 OTG_FS host 驱动
+//!
+//! 与 raw_usb 一样，目的是看清楚 host 端到底做了什么，因此只支持直接接在端口上的一个设备（不支持 hub），
+//! 全部使用轮询，每次只传输一个包，不使用中断与 DMA
+//!
+//! 对照 Reference Manual 的 OTG_FS programming model 节中 Host programming model 的部分阅读：
+//!
+//! 1. Host initialization
+//!    复位 core，强制为 host 模式，划分 FIFO，打开端口电源（PPWR）
+//! 2. 等待设备接入（HPRT 的 PCSTS），然后复位端口（PRST），复位结束后端口被使能（PENA），从 PSPD 读出设备的速度
+//!    若设备为 Low Speed，还要把 PHY 的时钟切换为 6 MHz，并再复位一次端口
+//! 3. 通过 channel 与设备通信
+//!    device 模式下的 endpoint 是设备自己的，host 模式下则是由 channel 来“扮演”设备的某个 endpoint：
+//!    HCCHAR 中写入设备地址、endpoint 编号、方向、类型、最大包长，HCTSIZ 中写入 PID 与长度，使能 channel 之后，
+//!    core 就会在合适的帧里发出 token，收发数据，并在 HCINT 中报告结果（ACK/NAK/STALL/错误）
+//!    OTG_FS 一共有 8 个 channel，每个 Pipe 独占一个
+//! 4. 枚举，见 enumerate
+//! 5. class 驱动，见 hid_keyboard 与 msc
+//!
+//! 关于 FIFO：
+//! host 模式下依旧只有一个 RxFIFO，但 TxFIFO 分为两个：non-periodic（控制与批量传输）和 periodic（中断与同步传输）
+//! 写入数据时，只要写到 channel 对应的 FIFO 地址上，core 会按照 channel 的类型放入正确的 TxFIFO
+//!
+//! 关于 NAK：
+//! 设备还没准备好时，会以 NAK 回复，这不是错误，对于控制与批量传输，只需要重新发起这一次传输即可；
+//! 而对于中断传输，NAK 表示“没有新的数据”，直接返回 HostError::Nak，交给 class 驱动处理
+//!
+//! 硬件上，STM32F413 不能输出 VBUS 的 5 V，需要外部给设备供电，见 s13c07 的接线图
+
+#![allow(dead_code)]
+
+pub(crate) mod enumerate;
+pub(crate) mod hid_keyboard;
+pub(crate) mod msc;
+
+use stm32f4xx_hal::pac::Peripherals;
+
+use super::raw_usb::regs::*;
+use super::raw_usb::setup::SetupPacket;
+use super::raw_usb::{flush_fifos, reset_core, HCLK_HZ};
+
+// FIFO 的划分，单位为 word，总共 320 word
+const RX_FIFO_WORDS: u32 = 128;
+const NON_PERIODIC_TX_WORDS: u32 = 96;
+const PERIODIC_TX_WORDS: u32 = 64;
+
+// 控制与批量传输遇到 NAK 时的最大重试次数
+const NAK_RETRIES: u32 = 10_000;
+// 等待一次传输完成的最大轮询次数，96 MHz 下大约几十毫秒
+const TIMEOUT_LOOPS: u32 = 200_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Speed {
+    Low,
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum EndpointType {
+    Control = 0,
+    Isochronous = 1,
+    Bulk = 2,
+    Interrupt = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum HostError {
+    // 设备被拔掉了
+    Disconnected,
+    // 8 个 channel 都被占用了
+    NoChannel,
+    // 设备以 STALL 回复，表示不支持这个请求，或者 endpoint 被 halt 了
+    Stall,
+    // 中断传输时表示没有新数据；控制与批量传输时表示重试多次之后设备依旧没有准备好
+    Nak,
+    // CRC 错误、超时、位填充错误等
+    Transaction,
+    // 设备发送的数据超过了最大包长
+    Babble,
+    DataToggle,
+    Timeout,
+    // 描述符的内容不对
+    Descriptor,
+}
+
+// host 端对设备某个 endpoint 的抽象，独占一个 channel
+#[derive(Debug, defmt::Format)]
+pub(crate) struct Pipe {
+    ch: usize,
+    pub(crate) address: u8,
+    pub(crate) endpoint: u8,
+    pub(crate) kind: EndpointType,
+    pub(crate) max_packet: u16,
+    // 批量与中断传输的 DATA0/DATA1 交替，由软件记录
+    toggle: bool,
+}
+
+pub(crate) struct UsbHost {
+    speed: Speed,
+    // 被占用的 channel，每个 bit 对应一个
+    channels: u8,
+}
+
+impl UsbHost {
+    // 调用之前，PLL 的 48 MHz 输出必须已经就绪
+    pub(crate) fn init(dp: &Peripherals) -> Self {
+        reset_core(dp);
+
+        modify(GUSBCFG, |v| {
+            (v & !GUSBCFG__FDMOD) | GUSBCFG__FHMOD | GUSBCFG__PHYSEL
+        });
+        // 强制切换模式之后，至少要等待 25 ms 才会生效
+        delay_ms(25);
+
+        set_phy_clock(Speed::Full);
+
+        write(GRXFSIZ, RX_FIFO_WORDS);
+        write(HNPTXFSIZ, NON_PERIODIC_TX_WORDS << 16 | RX_FIFO_WORDS);
+        write(
+            HPTXFSIZ,
+            PERIODIC_TX_WORDS << 16 | (RX_FIFO_WORDS + NON_PERIODIC_TX_WORDS),
+        );
+        flush_fifos();
+
+        // 全部使用轮询，不打开任何中断
+        write(GINTSTS, 0xFFFF_FFFF);
+        write(GINTMSK, 0);
+
+        // 端口上电，F413 并不会因此输出 VBUS，但 PPWR 为 0 时 core 不会检测设备的接入
+        modify_hprt(|v| v | HPRT__PPWR);
+
+        Self {
+            speed: Speed::Full,
+            channels: 0,
+        }
+    }
+
+    pub(crate) fn is_connected(&self) -> bool {
+        read(HPRT) & HPRT__PCSTS != 0
+    }
+
+    pub(crate) fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    // 阻塞直到有设备接入，复位端口，返回设备的速度
+    pub(crate) fn wait_for_device(&mut self) -> Speed {
+        loop {
+            while !self.is_connected() {}
+            // USB 2.0 Spec 7.1.7.3，接入之后至少等待 100 ms，让电源与插头的接触稳定下来
+            delay_ms(100);
+            if self.is_connected() {
+                break;
+            }
+        }
+        modify_hprt(|v| v | HPRT__PCDET);
+
+        self.reset_port();
+        self.speed
+    }
+
+    fn reset_port(&mut self) {
+        loop {
+            // USB 2.0 Spec 7.1.7.5，复位信号至少持续 10 ms
+            modify_hprt(|v| v | HPRT__PRST);
+            delay_ms(15);
+            modify_hprt(|v| v & !HPRT__PRST);
+
+            let mut loops = 0;
+            while read(HPRT) & HPRT__PENA == 0 && loops < TIMEOUT_LOOPS {
+                loops += 1;
+            }
+            modify_hprt(|v| v | HPRT__PENCHNG);
+
+            self.speed = if (read(HPRT) & HPRT__PSPD__MASK) >> HPRT__PSPD__SHIFT == PSPD__LOW_SPEED
+            {
+                Speed::Low
+            } else {
+                Speed::Full
+            };
+
+            // PHY 的时钟不对时，切换之后要再复位一次端口
+            if !set_phy_clock(self.speed) {
+                break;
+            }
+        }
+
+        // 复位之后的恢复时间，至少 10 ms
+        delay_ms(20);
+    }
+
+    pub(crate) fn alloc_pipe(
+        &mut self,
+        address: u8,
+        endpoint: u8,
+        kind: EndpointType,
+        max_packet: u16,
+    ) -> Result<Pipe, HostError> {
+        let ch = (0..CHANNEL_COUNT)
+            .find(|ch| self.channels & (1 << ch) == 0)
+            .ok_or(HostError::NoChannel)?;
+        self.channels |= 1 << ch;
+        Ok(Pipe {
+            ch,
+            address,
+            endpoint,
+            kind,
+            max_packet,
+            toggle: false,
+        })
+    }
+
+    pub(crate) fn free_pipe(&mut self, pipe: Pipe) {
+        halt(pipe.ch);
+        self.channels &= !(1 << pipe.ch);
+    }
+
+    // 释放所有 channel，设备被拔掉之后调用
+    pub(crate) fn free_all(&mut self) {
+        for ch in 0..CHANNEL_COUNT {
+            halt(ch);
+        }
+        self.channels = 0;
+    }
+
+    // 带有 IN 方向 DATA 阶段的控制传输，返回收到的长度
+    pub(crate) fn control_in(
+        &mut self,
+        pipe: &mut Pipe,
+        setup: &SetupPacket,
+        buf: &mut [u8],
+    ) -> Result<usize, HostError> {
+        self.setup_stage(pipe, setup)?;
+
+        // DATA 阶段总是从 DATA1 开始，直到收到短包，或者收够了 wLength
+        let len = buf.len().min(setup.length as usize);
+        let mut received = 0;
+        let mut toggle = true;
+        while received < len {
+            let n = self.packet_in(pipe, toggle, &mut buf[received..len])?;
+            received += n;
+            toggle = !toggle;
+            if n < pipe.max_packet as usize {
+                break;
+            }
+        }
+
+        // STATUS 阶段，方向与 DATA 阶段相反，为 DATA1 的零长度包
+        self.packet_out(pipe, DPID__DATA1, &[])?;
+        Ok(received)
+    }
+
+    // 没有 DATA 阶段，或者 DATA 阶段为 OUT 方向的控制传输
+    pub(crate) fn control_out(
+        &mut self,
+        pipe: &mut Pipe,
+        setup: &SetupPacket,
+        data: &[u8],
+    ) -> Result<(), HostError> {
+        self.setup_stage(pipe, setup)?;
+
+        let mut toggle = true;
+        for chunk in data.chunks(pipe.max_packet as usize) {
+            self.packet_out(pipe, dpid(toggle), chunk)?;
+            toggle = !toggle;
+        }
+
+        // STATUS 阶段为 IN 方向，DATA1 的零长度包
+        self.packet_in(pipe, true, &mut [])?;
+        Ok(())
+    }
+
+    fn setup_stage(&mut self, pipe: &mut Pipe, setup: &SetupPacket) -> Result<(), HostError> {
+        let [w0, w1] = setup.to_words();
+        let mut bytes = [0u8; 8];
+        bytes[..4].copy_from_slice(&w0.to_le_bytes());
+        bytes[4..].copy_from_slice(&w1.to_le_bytes());
+        self.packet_out(pipe, DPID__SETUP, &bytes)
+    }
+
+    // 批量或中断 IN 传输，直到收到短包或者 buf 被填满，返回收到的长度
+    pub(crate) fn transfer_in(
+        &mut self,
+        pipe: &mut Pipe,
+        buf: &mut [u8],
+    ) -> Result<usize, HostError> {
+        let mut received = 0;
+        loop {
+            let n = self.packet_in(pipe, pipe.toggle, &mut buf[received..])?;
+            pipe.toggle = !pipe.toggle;
+            received += n;
+            if n < pipe.max_packet as usize || received == buf.len() {
+                return Ok(received);
+            }
+        }
+    }
+
+    // 批量或中断 OUT 传输
+    pub(crate) fn transfer_out(&mut self, pipe: &mut Pipe, data: &[u8]) -> Result<(), HostError> {
+        for chunk in data.chunks(pipe.max_packet as usize) {
+            self.packet_out(pipe, dpid(pipe.toggle), chunk)?;
+            pipe.toggle = !pipe.toggle;
+        }
+        Ok(())
+    }
+
+    // 清除 endpoint 的 halt 状态，并把 toggle 重置为 DATA0
+    pub(crate) fn clear_halt(
+        &mut self,
+        control: &mut Pipe,
+        pipe: &mut Pipe,
+        dir_in: bool,
+    ) -> Result<(), HostError> {
+        let setup = SetupPacket {
+            // Host -> Device，Standard，Endpoint
+            request_type: 0x02,
+            request: super::raw_usb::setup::CLEAR_FEATURE,
+            // ENDPOINT_HALT
+            value: 0,
+            index: pipe.endpoint as u16 | if dir_in { 0x80 } else { 0 },
+            length: 0,
+        };
+        self.control_out(control, &setup, &[])?;
+        pipe.toggle = false;
+        Ok(())
+    }
+
+    // 接收一个包，返回收到的长度，超过 buf 长度的部分会被丢弃
+    fn packet_in(&mut self, pipe: &Pipe, toggle: bool, buf: &mut [u8]) -> Result<usize, HostError> {
+        for _ in 0..NAK_RETRIES {
+            // IN 方向的 XFRSIZ 必须是最大包长的整数倍
+            start_channel(pipe, self.speed, true, dpid(toggle), pipe.max_packet as u32);
+
+            let mut received = 0;
+            let result = self.wait_channel(pipe.ch, |bytes| {
+                let n = bytes.len().min(buf.len() - received);
+                buf[received..received + n].copy_from_slice(&bytes[..n]);
+                received += bytes.len();
+            });
+            halt(pipe.ch);
+
+            match result {
+                Ok(()) => return Ok(received),
+                Err(HostError::Nak) if pipe.kind != EndpointType::Interrupt => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(HostError::Nak)
+    }
+
+    // 发送一个包，长度不超过最大包长
+    fn packet_out(&mut self, pipe: &Pipe, pid: u32, data: &[u8]) -> Result<(), HostError> {
+        for _ in 0..NAK_RETRIES {
+            start_channel(pipe, self.speed, false, pid, data.len() as u32);
+
+            // 使能 channel 之后再写入数据，被 NAK 时要从头再来，数据也要重新写一遍
+            for chunk in data.chunks(4) {
+                let mut word = [0u8; 4];
+                word[..chunk.len()].copy_from_slice(chunk);
+                write_fifo(pipe.ch, u32::from_le_bytes(word));
+            }
+
+            let result = self.wait_channel(pipe.ch, |_| {});
+            halt(pipe.ch);
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(HostError::Nak) if pipe.kind != EndpointType::Interrupt => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(HostError::Nak)
+    }
+
+    // 等待 channel 的传输结束，期间把 RxFIFO 中收到的数据交给 on_data
+    fn wait_channel(&mut self, ch: usize, mut on_data: impl FnMut(&[u8])) -> Result<(), HostError> {
+        for _ in 0..TIMEOUT_LOOPS {
+            if !self.is_connected() {
+                return Err(HostError::Disconnected);
+            }
+
+            // IN 方向的数据要先从 RxFIFO 中读走，core 才会报告 XFRC
+            while read(GINTSTS) & GINT__RXFLVL != 0 {
+                let status = read(GRXSTSP);
+                let pktsts = (status & GRXSTSP__PKTSTS__MASK) >> GRXSTSP__PKTSTS__SHIFT;
+                let bcnt = ((status & GRXSTSP__BCNT__MASK) >> GRXSTSP__BCNT__SHIFT) as usize;
+                let chnum = (status & GRXSTSP__EPNUM__MASK) as usize;
+
+                let mut packet = [0u8; 64];
+                for i in 0..bcnt.div_ceil(4) {
+                    let word = read_fifo().to_le_bytes();
+                    for (j, byte) in word.into_iter().enumerate() {
+                        if let Some(slot) = packet.get_mut(i * 4 + j) {
+                            *slot = byte;
+                        }
+                    }
+                }
+                if pktsts == PKTSTS__HOST_IN_DATA && chnum == ch && bcnt > 0 {
+                    on_data(&packet[..bcnt.min(packet.len())]);
+                }
+            }
+
+            let int = read(channel(HCINT0, ch));
+            if int & HCINT__XFRC != 0 {
+                return Ok(());
+            }
+            if int & HCINT__STALL != 0 {
+                return Err(HostError::Stall);
+            }
+            // 帧溢出表示这一帧里没来得及完成，与 NAK 一样重试即可
+            if int & (HCINT__NAK | HCINT__FRMOR) != 0 {
+                return Err(HostError::Nak);
+            }
+            if int & HCINT__TXERR != 0 {
+                return Err(HostError::Transaction);
+            }
+            if int & HCINT__BBERR != 0 {
+                return Err(HostError::Babble);
+            }
+            if int & HCINT__DTERR != 0 {
+                return Err(HostError::DataToggle);
+            }
+        }
+        Err(HostError::Timeout)
+    }
+}
+
+// 按照设备的速度设置 PHY 的时钟与帧间隔，返回是否有修改
+fn set_phy_clock(speed: Speed) -> bool {
+    let (fslspcs, frame_interval) = match speed {
+        Speed::Full => (HCFG__FSLSPCS__48MHZ, 48_000),
+        Speed::Low => (HCFG__FSLSPCS__6MHZ, 6_000),
+    };
+    if read(HCFG) & HCFG__FSLSPCS__MASK == fslspcs {
+        return false;
+    }
+    modify(HCFG, |v| (v & !HCFG__FSLSPCS__MASK) | fslspcs);
+    write(HFIR, frame_interval);
+    true
+}
+
+fn dpid(toggle: bool) -> u32 {
+    if toggle {
+        DPID__DATA1
+    } else {
+        DPID__DATA0
+    }
+}
+
+fn start_channel(pipe: &Pipe, speed: Speed, dir_in: bool, pid: u32, size: u32) {
+    let ch = pipe.ch;
+
+    let mut hcchar = pipe.max_packet as u32
+        | (pipe.endpoint as u32) << HCCHAR__EPNUM__SHIFT
+        | (pipe.kind as u32) << HCCHAR__EPTYP__SHIFT
+        // 每帧只发起一次
+        | 1 << HCCHAR__MCNT__SHIFT
+        | (pipe.address as u32) << HCCHAR__DAD__SHIFT;
+    if dir_in {
+        hcchar |= HCCHAR__EPDIR_IN;
+    }
+    if speed == Speed::Low {
+        hcchar |= HCCHAR__LSDEV;
+    }
+    // 中断传输要指定在奇数帧还是偶数帧发起，这里总是选择下一帧
+    if pipe.kind == EndpointType::Interrupt && read(HFNUM) & 1 == 0 {
+        hcchar |= HCCHAR__ODDFRM;
+    }
+
+    write(channel(HCINT0, ch), 0xFFFF_FFFF);
+    write(
+        channel(HCTSIZ0, ch),
+        pid << HCTSIZ__DPID__SHIFT | 1 << HCTSIZ__PKTCNT__SHIFT | size,
+    );
+    write(channel(HCCHAR0, ch), hcchar | HCCHAR__CHENA);
+}
+
+// 关闭 channel，并等待 core 确认（CHH）
+fn halt(ch: usize) {
+    if read(channel(HCCHAR0, ch)) & HCCHAR__CHENA != 0 {
+        set_bits(channel(HCCHAR0, ch), HCCHAR__CHDIS | HCCHAR__CHENA);
+
+        let mut loops = 0;
+        while read(channel(HCINT0, ch)) & HCINT__CHH == 0 && loops < TIMEOUT_LOOPS {
+            // IN 方向的 channel 关闭时，core 会在 RxFIFO 中放入一项“channel halted”，要把它读走
+            while read(GINTSTS) & GINT__RXFLVL != 0 {
+                let status = read(GRXSTSP);
+                let bcnt = ((status & GRXSTSP__BCNT__MASK) >> GRXSTSP__BCNT__SHIFT) as usize;
+                for _ in 0..bcnt.div_ceil(4) {
+                    read_fifo();
+                }
+            }
+            loops += 1;
+        }
+    }
+    write(channel(HCINT0, ch), 0xFFFF_FFFF);
+}
+
+pub(crate) fn delay_ms(ms: u32) {
+    cortex_m::asm::delay(HCLK_HZ / 1000 * ms);
+}
@@ -0,0 +1,269 @@
+//! Mass Storage Class，Bulk-Only Transport + SCSI transparent command set
+//!
+//! 这是绝大多数 U 盘使用的组合（interface 的 class/subclass/protocol 为 8/6/0x50），每条命令分三个阶段：
+//!
+//! 1. host 通过 bulk OUT 发送 31 字节的 CBW（Command Block Wrapper），其中包含 SCSI 命令
+//! 2. 可选的数据阶段，方向与长度由 CBW 指定
+//! 3. host 通过 bulk IN 接收 13 字节的 CSW（Command Status Wrapper），得到命令的执行结果
+//!
+//! 见 USB Mass Storage Class Bulk-Only Transport 1.0，以及 SCSI Block Commands（SBC）
+//!
+//! 为了让上层（例如文件系统）不必关心 USB 的细节，这里定义了 BlockDevice trait，以 512 字节的块为单位读写
+
+#![allow(dead_code)]
+
+use super::enumerate::DeviceInfo;
+use super::{EndpointType, HostError, Pipe, UsbHost};
+
+pub(crate) const BLOCK_SIZE: usize = 512;
+
+// 以块为单位读写的存储设备
+pub(crate) trait BlockDevice {
+    type Error;
+
+    fn block_count(&self) -> u32;
+    fn read_block(&mut self, lba: u32, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), Self::Error>;
+    fn write_block(&mut self, lba: u32, buf: &[u8; BLOCK_SIZE]) -> Result<(), Self::Error>;
+}
+
+const CLASS_MSC: u8 = 0x08;
+const SUBCLASS_SCSI: u8 = 0x06;
+const PROTOCOL_BBB: u8 = 0x50;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CBW_LEN: usize = 31;
+const CSW_LEN: usize = 13;
+
+// SCSI 命令
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2A;
+
+// 刚插入的 U 盘，往往要过一段时间才能就绪
+const READY_RETRIES: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum MscError {
+    Host(HostError),
+    // CSW 中的 bCSWStatus 不为 0，即命令执行失败
+    CommandFailed(u8),
+    // CSW 的签名或 tag 不对
+    BadStatus,
+    // 只支持 512 字节的块
+    UnsupportedBlockSize(u32),
+    // lba 超出了设备的容量
+    OutOfRange,
+}
+
+impl From<HostError> for MscError {
+    fn from(e: HostError) -> Self {
+        MscError::Host(e)
+    }
+}
+
+enum Data<'b> {
+    None,
+    In(&'b mut [u8]),
+    Out(&'b [u8]),
+}
+
+pub(crate) struct MassStorage<'a> {
+    host: &'a mut UsbHost,
+    // 清除 bulk endpoint 的 halt 状态时要用到 endpoint 0
+    ep0: &'a mut Pipe,
+    bulk_in: Pipe,
+    bulk_out: Pipe,
+    tag: u32,
+    block_count: u32,
+}
+
+impl<'a> MassStorage<'a> {
+    // 在枚举结果中查找 U 盘，找到的话分配两个 bulk Pipe，等待设备就绪，并读出容量
+    //
+    // 返回 Ok(None) 表示这个设备不是 U 盘
+    pub(crate) fn setup(
+        host: &'a mut UsbHost,
+        ep0: &'a mut Pipe,
+        info: &DeviceInfo,
+    ) -> Result<Option<Self>, MscError> {
+        let Some(itf) = info.find_interface(CLASS_MSC, SUBCLASS_SCSI, PROTOCOL_BBB) else {
+            return Ok(None);
+        };
+        let (Some(ep_in), Some(ep_out)) = (
+            itf.find_endpoint(EndpointType::Bulk, true),
+            itf.find_endpoint(EndpointType::Bulk, false),
+        ) else {
+            return Err(HostError::Descriptor.into());
+        };
+
+        let bulk_in = host.alloc_pipe(
+            info.address,
+            ep_in.number(),
+            EndpointType::Bulk,
+            ep_in.max_packet,
+        )?;
+        let bulk_out = match host.alloc_pipe(
+            info.address,
+            ep_out.number(),
+            EndpointType::Bulk,
+            ep_out.max_packet,
+        ) {
+            Ok(pipe) => pipe,
+            Err(e) => {
+                host.free_pipe(bulk_in);
+                return Err(e.into());
+            }
+        };
+
+        let mut msc = Self {
+            host,
+            ep0,
+            bulk_in,
+            bulk_out,
+            tag: 0,
+            block_count: 0,
+        };
+        msc.wait_ready()?;
+        msc.read_capacity()?;
+        Ok(Some(msc))
+    }
+
+    pub(crate) fn release(self) {
+        self.host.free_pipe(self.bulk_in);
+        self.host.free_pipe(self.bulk_out);
+    }
+
+    fn wait_ready(&mut self) -> Result<(), MscError> {
+        let mut cmd = [0u8; 6];
+        cmd[0] = TEST_UNIT_READY;
+
+        let mut last = MscError::CommandFailed(0);
+        for _ in 0..READY_RETRIES {
+            match self.command(&cmd, Data::None) {
+                Ok(()) => return Ok(()),
+                // 没有就绪时，设备会报告命令失败，按照规定，要用 REQUEST SENSE 把错误信息读走
+                Err(e @ MscError::CommandFailed(_)) => {
+                    last = e;
+                    self.request_sense()?;
+                    super::delay_ms(100);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last)
+    }
+
+    fn request_sense(&mut self) -> Result<(), MscError> {
+        let mut sense = [0u8; 18];
+        let mut cmd = [0u8; 6];
+        cmd[0] = REQUEST_SENSE;
+        cmd[4] = sense.len() as u8;
+        self.command(&cmd, Data::In(&mut sense))?;
+        defmt::debug!(
+            "sense key {=u8:#x}, asc {=u8:#x}",
+            sense[2] & 0x0F,
+            sense[12]
+        );
+        Ok(())
+    }
+
+    fn read_capacity(&mut self) -> Result<(), MscError> {
+        let mut cmd = [0u8; 10];
+        cmd[0] = READ_CAPACITY_10;
+        let mut data = [0u8; 8];
+        self.command(&cmd, Data::In(&mut data))?;
+
+        // 大端序，前 4 个字节为最后一个块的 LBA，后 4 个字节为块的大小
+        let last_lba = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let block_len = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        if block_len as usize != BLOCK_SIZE {
+            return Err(MscError::UnsupportedBlockSize(block_len));
+        }
+        self.block_count = last_lba.wrapping_add(1);
+        Ok(())
+    }
+
+    fn rw_command(opcode: u8, lba: u32) -> [u8; 10] {
+        let mut cmd = [0u8; 10];
+        cmd[0] = opcode;
+        cmd[2..6].copy_from_slice(&lba.to_be_bytes());
+        // 每次只传输 1 个块
+        cmd[7..9].copy_from_slice(&1u16.to_be_bytes());
+        cmd
+    }
+
+    // 执行一条 SCSI 命令，包含 CBW、数据、CSW 三个阶段
+    fn command(&mut self, cb: &[u8], data: Data) -> Result<(), MscError> {
+        self.tag = self.tag.wrapping_add(1);
+
+        let (len, dir_in) = match &data {
+            Data::None => (0, false),
+            Data::In(buf) => (buf.len(), true),
+            Data::Out(buf) => (buf.len(), false),
+        };
+
+        let mut cbw = [0u8; CBW_LEN];
+        cbw[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        cbw[4..8].copy_from_slice(&self.tag.to_le_bytes());
+        cbw[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+        cbw[12] = if dir_in { 0x80 } else { 0x00 };
+        // bCBWLUN，只使用 LUN 0
+        cbw[13] = 0;
+        cbw[14] = cb.len() as u8;
+        cbw[15..15 + cb.len()].copy_from_slice(cb);
+        self.host.transfer_out(&mut self.bulk_out, &cbw)?;
+
+        // 数据阶段，设备以 STALL 结束数据阶段时，要先清除 endpoint 的 halt 状态，然后依旧要去读取 CSW
+        let data_result = match data {
+            Data::None => Ok(()),
+            Data::In(buf) => self.host.transfer_in(&mut self.bulk_in, buf).map(|_| ()),
+            Data::Out(buf) => self.host.transfer_out(&mut self.bulk_out, buf),
+        };
+        match data_result {
+            Ok(()) => {}
+            Err(HostError::Stall) if dir_in => {
+                self.host.clear_halt(self.ep0, &mut self.bulk_in, true)?
+            }
+            Err(HostError::Stall) => self.host.clear_halt(self.ep0, &mut self.bulk_out, false)?,
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut csw = [0u8; CSW_LEN];
+        let n = self.host.transfer_in(&mut self.bulk_in, &mut csw)?;
+        let signature = u32::from_le_bytes([csw[0], csw[1], csw[2], csw[3]]);
+        let tag = u32::from_le_bytes([csw[4], csw[5], csw[6], csw[7]]);
+        if n != CSW_LEN || signature != CSW_SIGNATURE || tag != self.tag {
+            return Err(MscError::BadStatus);
+        }
+
+        match csw[12] {
+            0 => Ok(()),
+            status => Err(MscError::CommandFailed(status)),
+        }
+    }
+}
+
+impl BlockDevice for MassStorage<'_> {
+    type Error = MscError;
+
+    fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    fn read_block(&mut self, lba: u32, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), MscError> {
+        if lba >= self.block_count {
+            return Err(MscError::OutOfRange);
+        }
+        self.command(&Self::rw_command(READ_10, lba), Data::In(buf))
+    }
+
+    fn write_block(&mut self, lba: u32, buf: &[u8; BLOCK_SIZE]) -> Result<(), MscError> {
+        if lba >= self.block_count {
+            return Err(MscError::OutOfRange);
+        }
+        self.command(&Self::rw_command(WRITE_10, lba), Data::Out(buf))
+    }
+}
@@ -0,0 +1,193 @@
+//! 通过 USB 读写寄存器，方便在 Host 端边试边看，而不必每次都重新编译、烧录
+//!
+//! 请求与回复都放在 vendor shell 的一个 packet 中（tag 为 REG，见 s13c05_vendor_shell），tag 之后的格式为：
+//!
+//! 请求：| op (1) | addr (4) | 参数 |
+//! 回复：| status (1) | op (1) | addr (4) | 数据 |
+//!
+//! 多字节的字段均为小端序，回复中带上 op 与 addr，Host 端可以据此确认这是哪一个请求的回复
+//!
+//! | op   | 名称    | 参数             | 回复的数据             |
+//! | ---- | ------- | ---------------- | ---------------------- |
+//! | 0x00 | READ32  | 无               | 1 个 u32               |
+//! | 0x01 | WRITE32 | value: u32       | 写入之后读回的 u32     |
+//! | 0x02 | READN   | count: u8        | count 个连续的 u32     |
+//!
+//! 安全措施：
+//!
+//! 1. 只允许访问 REGIONS 中列出的地址范围，访问不存在的地址会触发 BusFault，有些外设也不应该被随意改动
+//! 2. 全部访问都必须 4 字节对齐，并按 32 bit 访问，部分外设不支持 8/16 bit 访问
+//! 3. 写入默认是关闭的，需要先在 shell 中执行 reg write on；另外，有的区域只读：
+//!    - RCC、FLASH 接口：改错了时钟或者 Flash 等待周期，芯片会直接跑飞
+//!    - GPIOA：PA11、PA12 是 USB，PA13、PA14 是 SWD，改错了 Host 与调试器都连不上了
+//!    - SRAM：栈与全局变量都在这里
+//!    - OTG_FS 不在表中，改动它会立即断开与 Host 的连接
+//!
+//! 注意读取也可能有副作用，比如读取 USART、SPI 的 DR 会清除 RXNE，读取 ADC 的 DR 会清除 EOC
+//!
+//! 即使这样，写入依然可能让外设进入奇怪的状态，复位即可恢复，寄存器的内容不会被保存下来
+
+#![allow(dead_code)]
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+pub(crate) const OP_READ32: u8 = 0x00;
+pub(crate) const OP_WRITE32: u8 = 0x01;
+pub(crate) const OP_READN: u8 = 0x02;
+
+// 64 byte 的 packet，去掉 tag、status、op、addr 之后，还能放下 14 个 u32
+pub(crate) const MAX_WORDS: usize = 14;
+pub(crate) const REPLY_HEADER_LEN: usize = 6;
+pub(crate) const MAX_REPLY_LEN: usize = REPLY_HEADER_LEN + MAX_WORDS * 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum Status {
+    Ok = 0x00,
+    // 请求的长度不对，或者 op 未知
+    BadRequest = 0x01,
+    Unaligned = 0x02,
+    // 地址不在白名单中，或者 READN 跨出了所在的区域
+    Denied = 0x03,
+    // 该区域只读
+    ReadOnly = 0x04,
+    // 写入还没有打开
+    WriteDisabled = 0x05,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    Read,
+    ReadWrite,
+}
+
+pub(crate) struct Region {
+    pub(crate) name: &'static str,
+    pub(crate) start: u32,
+    // 不包含 end
+    pub(crate) end: u32,
+    pub(crate) access: Access,
+}
+
+const fn region(name: &'static str, start: u32, end: u32, access: Access) -> Region {
+    Region {
+        name,
+        start,
+        end,
+        access,
+    }
+}
+
+// STM32F413 的存储器映像，见 Reference Manual 的 Memory map 章节
+pub(crate) const REGIONS: [Region; 15] = [
+    region("TIM2..5", 0x4000_0000, 0x4000_1000, Access::ReadWrite),
+    region("TIM6/7", 0x4000_1000, 0x4000_1800, Access::ReadWrite),
+    region("SPI2/3", 0x4000_3800, 0x4000_4000, Access::ReadWrite),
+    region("USART2/3", 0x4000_4400, 0x4000_4C00, Access::ReadWrite),
+    region("I2C1..3", 0x4000_5400, 0x4000_6000, Access::ReadWrite),
+    region("DAC", 0x4000_7400, 0x4000_7800, Access::ReadWrite),
+    region("TIM1/8", 0x4001_0000, 0x4001_0800, Access::ReadWrite),
+    region("USART1/6", 0x4001_1000, 0x4001_1800, Access::ReadWrite),
+    region("ADC1", 0x4001_2000, 0x4001_2400, Access::ReadWrite),
+    region("SPI1", 0x4001_3000, 0x4001_3400, Access::ReadWrite),
+    region("GPIOA", 0x4002_0000, 0x4002_0400, Access::Read),
+    region("GPIOB..H", 0x4002_0400, 0x4002_2000, Access::ReadWrite),
+    region("RCC/FLASH", 0x4002_3800, 0x4002_4000, Access::Read),
+    region("SRAM", 0x2000_0000, 0x2005_0000, Access::Read),
+    region("UID/flash size", 0x1FFF_7A10, 0x1FFF_7A24, Access::Read),
+];
+
+static WRITE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn set_write_enabled(enabled: bool) {
+    WRITE_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn write_enabled() -> bool {
+    WRITE_ENABLED.load(Ordering::Relaxed)
+}
+
+// addr 开始的 words 个 u32 都落在同一个区域中时，返回该区域
+pub(crate) fn find_region(addr: u32, words: usize) -> Option<&'static Region> {
+    let end = addr.checked_add(words as u32 * 4)?;
+    REGIONS
+        .iter()
+        .find(|region| region.start <= addr && end <= region.end)
+}
+
+// 处理一个请求（不含 tag），回复写入 reply，返回回复的长度
+pub(crate) fn handle(request: &[u8], reply: &mut [u8; MAX_REPLY_LEN]) -> usize {
+    let (status, len) = match request {
+        [op, a0, a1, a2, a3, params @ ..] => {
+            let addr = u32::from_le_bytes([*a0, *a1, *a2, *a3]);
+            reply[1] = *op;
+            reply[2..6].copy_from_slice(&addr.to_le_bytes());
+            match execute(*op, addr, params, &mut reply[REPLY_HEADER_LEN..]) {
+                Ok(words) => (Status::Ok, REPLY_HEADER_LEN + words * 4),
+                Err(status) => (status, REPLY_HEADER_LEN),
+            }
+        }
+        _ => {
+            reply[1..REPLY_HEADER_LEN].fill(0);
+            (Status::BadRequest, REPLY_HEADER_LEN)
+        }
+    };
+    reply[0] = status as u8;
+    len
+}
+
+// 执行请求，返回写入 data 的 u32 个数
+fn execute(op: u8, addr: u32, params: &[u8], data: &mut [u8]) -> Result<usize, Status> {
+    let (words, value) = match (op, params) {
+        (OP_READ32, []) => (1, None),
+        (OP_WRITE32, [v0, v1, v2, v3]) => (1, Some(u32::from_le_bytes([*v0, *v1, *v2, *v3]))),
+        (OP_READN, [count]) if (1..=MAX_WORDS).contains(&(*count as usize)) => {
+            (*count as usize, None)
+        }
+        _ => return Err(Status::BadRequest),
+    };
+
+    if !addr.is_multiple_of(4) {
+        return Err(Status::Unaligned);
+    }
+    let region = find_region(addr, words).ok_or(Status::Denied)?;
+
+    let ptr = addr as *mut u32;
+    if let Some(value) = value {
+        if region.access != Access::ReadWrite {
+            return Err(Status::ReadOnly);
+        }
+        if !write_enabled() {
+            return Err(Status::WriteDisabled);
+        }
+        defmt::info!("reg write {:#010X} <- {:#010X}", addr, value);
+        unsafe { ptr.write_volatile(value) };
+    }
+
+    for (index, chunk) in data.chunks_exact_mut(4).take(words).enumerate() {
+        let word = unsafe { ptr.add(index).read_volatile() };
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    Ok(words)
+}
+
+// 以文本形式列出白名单，供 shell 的 reg list 使用
+pub(crate) fn write_regions(w: &mut impl Write) -> fmt::Result {
+    for region in REGIONS.iter() {
+        writeln!(
+            w,
+            "{:08X}..{:08X} {} {}",
+            region.start,
+            region.end,
+            match region.access {
+                Access::Read => "r ",
+                Access::ReadWrite => "rw",
+            },
+            region.name
+        )?;
+    }
+    writeln!(w, "write: {}", if write_enabled() { "on" } else { "off" })
+}
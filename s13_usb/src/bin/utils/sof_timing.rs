@@ -0,0 +1,273 @@
+//! USB SOF 计时：测量 host 的帧周期，得到本地时钟相对 host 的偏差，必要时微调 HSI
+//!
+//! Full Speed 下 host 每 1 ms 发出一个 SOF，USB 规范要求它的帧周期误差在 ±500 ppm 之内，
+//! 实际的 host 通常好得多，因此 SOF 可以当作一个还不错的外部时间基准
+//!
+//! 在 STM32F4 上，OTG_FS 的 SOF 脉冲在芯片内部接到了 TIM2 的 ITR1（需要把 TIM2_OR 的 ITR1_RMP 设置为 0b10），
+//! 这里让 TIM2 以 TIMCLK 全速自由计数，ITR1 经 TRC 捕获到 CCR1，时间戳完全由硬件记录，与中断延迟无关
+//! 注意，F405/F407 这类较老的 OTG 核心，还需要把 OTG_FS_GCCFG 的 SOFOUTEN 置位，SOF 脉冲才会送到 TIM2
+//!
+//! 每 WINDOW_FRAMES 个 SOF 为一个窗口，窗口内 TIM2 的计数值与理论值之差，就是本地时钟相对 host 的偏差：
+//!
+//! drift_ppm = (ticks - tim_clk_hz * WINDOW_FRAMES / 1000) * 1_000_000 / 理论值
+//!
+//! drift_ppm 为正，表示本地时钟偏快；TIM2、SYSCLK 与 48 MHz 的 PLL48CLK 都来自同一个 PLL，
+//! 因此这个偏差也就是 USB 所用的 48 MHz 时钟的偏差
+//!
+//! 两次捕获之间超过 1.5 帧时（host 挂起 USB、总线复位，或者中断处理不及时导致 CC1OF），当前窗口作废，从下一个 SOF 重新开始
+//!
+//! 当 PLL 的时钟源为 HSI 时，可以打开 trim，根据每个窗口的结果调整 RCC_CR 的 HSITRIM：
+//!
+//! - HSITRIM 为 5 bit，默认值 16，每一步的大小数据手册并没有给出准确值，只给出了上限，
+//!   因此这里的控制只是简单的步进：偏差超出 TRIM_DEADBAND_PPM 时朝相反方向调整一步
+//! - 调整之后的那个窗口跨越了调整前后两个频率，不能用来判断，需要等 TRIM_SETTLE_WINDOWS 个窗口再做下一次调整
+//! - 死区必须大于半步，否则 HSITRIM 会在两个值之间来回跳
+//!
+//! HSI 即便经过微调，也很难稳定在 USB 要求的 ±500 ppm 之内，trim 只用于实验，正式使用 USB 时还是应该用 HSE
+
+#![allow(dead_code)]
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{self, interrupt, NVIC};
+
+pub(crate) const WINDOW_FRAMES: u32 = 1000;
+
+const TRIM_DEADBAND_PPM: i32 = 2000;
+const TRIM_SETTLE_WINDOWS: u8 = 2;
+const TRIM_MAX: u8 = 0x1F;
+
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub(crate) struct SofStats {
+    // 捕获到的 SOF 总数
+    pub(crate) frames: u32,
+    // 估计漏掉的 SOF 数
+    pub(crate) missed: u32,
+    // 完整结束的窗口数
+    pub(crate) windows: u32,
+    // 以下为最近一个完整窗口的结果，单位为 TIM2 的计数
+    pub(crate) expected_period: u32,
+    pub(crate) period_min: u32,
+    pub(crate) period_max: u32,
+    pub(crate) window_ticks: u32,
+    pub(crate) drift_ppm: i32,
+    // None 表示没有打开 trim
+    pub(crate) trim: Option<u8>,
+    pub(crate) trim_steps: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum TrimError {
+    // PLL 的时钟源不是 HSI，调整 HSITRIM 没有意义
+    NotHsi,
+    NotStarted,
+}
+
+struct SofTimer {
+    tim_clk_hz: u32,
+    expected_period: u32,
+
+    last_capture: Option<u32>,
+    window_start: u32,
+    window_frames: u32,
+    window_min: u32,
+    window_max: u32,
+
+    trim_enabled: bool,
+    settle: u8,
+
+    stats: SofStats,
+}
+
+static G_SOF: Mutex<RefCell<Option<SofTimer>>> = Mutex::new(RefCell::new(None));
+
+// tim_clk_hz 为 TIM2 的计数时钟，也就是 APB1 的定时器时钟
+// 需要在 USB 外设初始化之后调用
+pub(crate) fn start(tim_clk_hz: u32) {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    rcc.apb1enr.modify(|_, w| w.tim2en().enabled());
+
+    let expected_period = tim_clk_hz / 1000;
+    cortex_m::interrupt::free(|cs| {
+        G_SOF.borrow(cs).replace(Some(SofTimer {
+            tim_clk_hz,
+            expected_period,
+            last_capture: None,
+            window_start: 0,
+            window_frames: 0,
+            window_min: u32::MAX,
+            window_max: 0,
+            trim_enabled: false,
+            settle: 0,
+            stats: SofStats {
+                frames: 0,
+                missed: 0,
+                windows: 0,
+                expected_period,
+                period_min: 0,
+                period_max: 0,
+                window_ticks: 0,
+                drift_ppm: 0,
+                trim: None,
+                trim_steps: 0,
+            },
+        }))
+    });
+
+    let tim2 = unsafe { &*pac::TIM2::ptr() };
+    tim2.cr1.modify(|_, w| w.cen().disabled());
+    tim2.psc.write(|w| w.psc().bits(0));
+    tim2.arr.write(|w| w.arr().bits(u32::MAX));
+    // ITR1 重映射到 OTG_FS 的 SOF
+    tim2.or.modify(|_, w| unsafe { w.itr1_rmp().bits(0b10) });
+    // 只选择 TRGI 的来源，不使用任何从模式，TIM2 照常计数
+    tim2.smcr.modify(|_, w| {
+        w.ts().itr1();
+        w.sms().disabled();
+        w
+    });
+    // CC1S = 11，IC1 映射到 TRC
+    tim2.ccmr1_input()
+        .modify(|_, w| unsafe { w.cc1s().bits(0b11) });
+    tim2.ccer.modify(|_, w| w.cc1e().set_bit());
+    tim2.egr.write(|w| w.ug().update());
+    tim2.sr.modify(|_, w| {
+        w.cc1if().clear();
+        w.cc1of().clear();
+        w
+    });
+    tim2.dier.modify(|_, w| w.cc1ie().enabled());
+    tim2.cr1.modify(|_, w| w.cen().enabled());
+
+    unsafe { NVIC::unmask(interrupt::TIM2) };
+}
+
+pub(crate) fn stats() -> Option<SofStats> {
+    cortex_m::interrupt::free(|cs| G_SOF.borrow(cs).borrow().as_ref().map(|t| t.stats))
+}
+
+// 打开或关闭 HSI 的闭环微调，关闭时保留当前的 HSITRIM
+pub(crate) fn set_trim(enable: bool) -> Result<(), TrimError> {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    if enable && !rcc.pllcfgr.read().pllsrc().is_hsi() {
+        return Err(TrimError::NotHsi);
+    }
+
+    cortex_m::interrupt::free(|cs| {
+        let mut sof_mut = G_SOF.borrow(cs).borrow_mut();
+        let timer = sof_mut.as_mut().ok_or(TrimError::NotStarted)?;
+        timer.trim_enabled = enable;
+        timer.settle = 0;
+        timer.stats.trim = enable.then(|| rcc.cr.read().hsitrim().bits());
+        Ok(())
+    })
+}
+
+// 在 TIM2 中断中调用，返回是否刚刚结束了一个窗口
+pub(crate) fn on_tim2() -> bool {
+    let tim2 = unsafe { &*pac::TIM2::ptr() };
+    let sr = tim2.sr.read();
+    if sr.cc1if().bit_is_clear() {
+        return false;
+    }
+
+    // 读 CCR1 会清除 CC1IF
+    let capture = tim2.ccr1().read().bits();
+    let overcapture = sr.cc1of().bit_is_set();
+    if overcapture {
+        tim2.sr.modify(|_, w| w.cc1of().clear());
+    }
+
+    cortex_m::interrupt::free(|cs| {
+        let mut sof_mut = G_SOF.borrow(cs).borrow_mut();
+        let Some(timer) = sof_mut.as_mut() else {
+            return false;
+        };
+        timer.on_capture(capture, overcapture)
+    })
+}
+
+impl SofTimer {
+    fn on_capture(&mut self, capture: u32, overcapture: bool) -> bool {
+        self.stats.frames = self.stats.frames.wrapping_add(1);
+
+        let Some(last) = self.last_capture.replace(capture) else {
+            self.restart_window(capture);
+            return false;
+        };
+
+        let period = capture.wrapping_sub(last);
+        if overcapture || period > self.expected_period + self.expected_period / 2 {
+            // 四舍五入得到间隔的帧数，CC1OF 时至少漏了一个
+            let lost = ((period + self.expected_period / 2) / self.expected_period).max(2) - 1;
+            self.stats.missed = self.stats.missed.wrapping_add(lost);
+            self.restart_window(capture);
+            return false;
+        }
+
+        self.window_min = self.window_min.min(period);
+        self.window_max = self.window_max.max(period);
+        self.window_frames += 1;
+        if self.window_frames < WINDOW_FRAMES {
+            return false;
+        }
+
+        let ticks = capture.wrapping_sub(self.window_start);
+        // WINDOW_FRAMES 个帧的理论计数值，用 u64 计算避免溢出
+        let expected = self.tim_clk_hz as u64 * WINDOW_FRAMES as u64 / 1000;
+        let drift_ppm = (ticks as i64 - expected as i64) * 1_000_000 / expected as i64;
+
+        self.stats.windows = self.stats.windows.wrapping_add(1);
+        self.stats.period_min = self.window_min;
+        self.stats.period_max = self.window_max;
+        self.stats.window_ticks = ticks;
+        self.stats.drift_ppm = drift_ppm as i32;
+
+        if self.trim_enabled {
+            self.trim_step();
+        }
+
+        self.restart_window(capture);
+        true
+    }
+
+    fn restart_window(&mut self, capture: u32) {
+        self.window_start = capture;
+        self.window_frames = 0;
+        self.window_min = u32::MAX;
+        self.window_max = 0;
+    }
+
+    fn trim_step(&mut self) {
+        if self.settle > 0 {
+            self.settle -= 1;
+            return;
+        }
+
+        let rcc = unsafe { &*pac::RCC::ptr() };
+        let trim = rcc.cr.read().hsitrim().bits();
+
+        // 本地时钟偏快，HSITRIM 减小一步，反之增大一步
+        let new_trim = if self.stats.drift_ppm > TRIM_DEADBAND_PPM {
+            trim.saturating_sub(1)
+        } else if self.stats.drift_ppm < -TRIM_DEADBAND_PPM {
+            (trim + 1).min(TRIM_MAX)
+        } else {
+            trim
+        };
+
+        if new_trim != trim {
+            rcc.cr.modify(|_, w| w.hsitrim().bits(new_trim));
+            self.settle = TRIM_SETTLE_WINDOWS;
+            self.stats.trim_steps = self.stats.trim_steps.wrapping_add(1);
+            defmt::info!(
+                "HSITRIM {} -> {} ({} ppm)",
+                trim,
+                new_trim,
+                self.stats.drift_ppm
+            );
+        }
+        self.stats.trim = Some(new_trim);
+    }
+}
@@ -0,0 +1,179 @@
+//! 以字节流的形式使用一对 Bulk endpoint
+//!
+//! UsbPipe 持有一个 Bulk IN 和一个 Bulk OUT endpoint，实现 embedded-io 的 Read、Write、ReadReady、WriteReady，
+//! 这样命令行、帧的收发这些只依赖 embedded-io 的代码（见 s21、s22 的 utils::io），也可以直接跑在 USB 上
+//! 之后的 CDC ACM class 只需要负责描述符与控制请求，数据接口的两个 endpoint 交给 UsbPipe 即可
+//!
+//! 使用方式：
+//!
+//! - 在 class 的 get_configuration_descriptors 中，用 ep_in()、ep_out() 写出两个 endpoint 的描述符
+//! - 在 class 的 endpoint_in_complete 中调用 UsbPipe::endpoint_in_complete
+//! - usb_dev.poll 必须持续被调用（通常在 USB 中断中），否则 read、write 会一直等待下去
+//!
+//! USB 的数据以 packet 为单位到达，Read 会把收到的 packet 先放在内部的缓冲中，再按调用者需要的长度取出；
+//! Write 每次最多写出一个 packet，写出满包之后，flush 会补发一个 0 长度的 packet（ZLP），
+//! 让 Host 知道这次传输已经结束，否则 Host 端的 read 可能会一直等待后续的数据
+
+#![allow(dead_code)]
+
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
+use usb_device::{class_prelude::*, endpoint};
+
+// Full-Speed 的 Bulk endpoint 最大为 64 byte
+const PACKET_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct UsbIoError(pub(crate) UsbError);
+
+impl core::fmt::Display for UsbIoError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "usb error: {:?}", self.0)
+    }
+}
+
+// embedded-io 要求错误类型实现 core::error::Error
+impl core::error::Error for UsbIoError {}
+
+impl embedded_io::Error for UsbIoError {
+    fn kind(&self) -> ErrorKind {
+        match self.0 {
+            // 设备还没有被 Host 配置，或者已经被重置
+            UsbError::InvalidState => ErrorKind::NotConnected,
+            UsbError::BufferOverflow => ErrorKind::OutOfMemory,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+pub(crate) struct UsbPipe<'a, B: UsbBus> {
+    ep_in: EndpointIn<'a, B>,
+    ep_out: EndpointOut<'a, B>,
+
+    // 最近收到的一个 packet，rx_pos 之前的部分已经被读走了
+    rx_buf: [u8; PACKET_SIZE],
+    rx_pos: usize,
+    rx_len: usize,
+
+    // 已经写入 IN endpoint，但 Host 还没有取走
+    in_busy: bool,
+    // 上一个发出的 packet 是满包，传输结束时需要补一个 ZLP
+    need_zlp: bool,
+}
+
+impl<'a, B: UsbBus> UsbPipe<'a, B> {
+    pub(crate) fn new(alloc: &'a UsbBusAllocator<B>) -> Self {
+        Self {
+            ep_in: alloc.bulk::<endpoint::In>(PACKET_SIZE as u16),
+            ep_out: alloc.bulk::<endpoint::Out>(PACKET_SIZE as u16),
+            rx_buf: [0u8; PACKET_SIZE],
+            rx_pos: 0,
+            rx_len: 0,
+            in_busy: false,
+            need_zlp: false,
+        }
+    }
+
+    pub(crate) fn ep_in(&self) -> &EndpointIn<'a, B> {
+        &self.ep_in
+    }
+
+    pub(crate) fn ep_out(&self) -> &EndpointOut<'a, B> {
+        &self.ep_out
+    }
+
+    // 由 class 的 endpoint_in_complete 转发过来
+    pub(crate) fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.ep_in.address() {
+            self.in_busy = false;
+        }
+    }
+
+    // 由 class 的 reset 转发过来，丢弃还没读走的数据
+    pub(crate) fn reset(&mut self) {
+        self.rx_pos = 0;
+        self.rx_len = 0;
+        self.in_busy = false;
+        self.need_zlp = false;
+    }
+
+    // 缓冲为空时，尝试从 OUT endpoint 取一个 packet，不会等待
+    // 返回缓冲中是否有数据，收到的 ZLP 不算
+    fn fill_rx(&mut self) -> Result<bool, UsbIoError> {
+        if self.rx_pos < self.rx_len {
+            return Ok(true);
+        }
+        match self.ep_out.read(&mut self.rx_buf) {
+            Ok(len) => {
+                self.rx_pos = 0;
+                self.rx_len = len;
+                Ok(len > 0)
+            }
+            Err(UsbError::WouldBlock) => Ok(false),
+            Err(e) => Err(UsbIoError(e)),
+        }
+    }
+
+    // 写出一个 packet，IN endpoint 忙时等待，data 可以为空（ZLP）
+    fn write_packet(&mut self, data: &[u8]) -> Result<(), UsbIoError> {
+        loop {
+            match self.ep_in.write(data) {
+                Ok(_) => {
+                    self.in_busy = true;
+                    self.need_zlp = data.len() == PACKET_SIZE;
+                    return Ok(());
+                }
+                Err(UsbError::WouldBlock) => continue,
+                Err(e) => return Err(UsbIoError(e)),
+            }
+        }
+    }
+}
+
+impl<B: UsbBus> ErrorType for UsbPipe<'_, B> {
+    type Error = UsbIoError;
+}
+
+impl<B: UsbBus> Read for UsbPipe<'_, B> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while !self.fill_rx()? {}
+
+        let len = (self.rx_len - self.rx_pos).min(buf.len());
+        buf[..len].copy_from_slice(&self.rx_buf[self.rx_pos..self.rx_pos + len]);
+        self.rx_pos += len;
+        Ok(len)
+    }
+}
+
+impl<B: UsbBus> ReadReady for UsbPipe<'_, B> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        self.fill_rx()
+    }
+}
+
+impl<B: UsbBus> Write for UsbPipe<'_, B> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(PACKET_SIZE);
+        self.write_packet(&buf[..len])?;
+        Ok(len)
+    }
+
+    // 只保证补发了 ZLP，不等待 Host 取走数据：Host 端没有在读时，等待是没有尽头的
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        if self.need_zlp {
+            self.write_packet(&[])?;
+        }
+        Ok(())
+    }
+}
+
+impl<B: UsbBus> WriteReady for UsbPipe<'_, B> {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.in_busy)
+    }
+}
@@ -0,0 +1,37 @@
+// 说明见 s01_rcc 的 build.rs
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    println!("cargo:rustc-link-search={}", out.display());
+
+    // s14c03 作为 bootloader 之后的 app 时，需要链接到扇区 2 开始的地址，见 memory_app.x
+    // 编译时设置环境变量 S14_LINK_AS_APP，比如 S14_LINK_AS_APP=1 cargo build --bin s14c03_app
+    // 作为 s14c04 的 A/B 启动中的镜像时，设置为 a 或 b，分别链接到槽 A 与槽 B，见 memory_slot_a.x 与 memory_slot_b.x
+    // 注意该变量对整个 crate 生效，编译其他程序时不要设置
+    let memory: &[u8] = match env::var_os("S14_LINK_AS_APP") {
+        Some(slot) if slot == "a" => include_bytes!("memory_slot_a.x"),
+        Some(slot) if slot == "b" => include_bytes!("memory_slot_b.x"),
+        Some(_) => include_bytes!("memory_app.x"),
+        None => include_bytes!("memory.x"),
+    };
+
+    File::create(out.join("memory.x"))
+        .unwrap()
+        .write_all(memory)
+        .unwrap();
+
+    println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=memory_app.x");
+    println!("cargo:rerun-if-changed=memory_slot_a.x");
+    println!("cargo:rerun-if-changed=memory_slot_b.x");
+    println!("cargo:rerun-if-env-changed=S14_LINK_AS_APP");
+
+    println!("cargo:rustc-link-arg=--nmagic");
+
+    println!("cargo:rustc-link-arg=-Tlink.x");
+}
@@ -0,0 +1,9 @@
+/* 说明见 s01_rcc 的 memory.x */
+
+/* bootloader 占用扇区 0 与扇区 1（共 32 KB），app 从扇区 2 开始，见 src/bin/utils/boot.rs */
+
+MEMORY
+{
+  FLASH : ORIGIN = 0x08008000, LENGTH = 480K
+  RAM : ORIGIN = 0x20000000, LENGTH = 320K
+}
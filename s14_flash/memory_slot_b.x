@@ -0,0 +1,10 @@
+/* 说明见 s01_rcc 的 memory.x */
+
+/* s14c04 的 A/B 启动中的槽 B：扇区 6（0x0804_0000，128 KB）
+   槽的前 512 字节是镜像的头部（见 telemetry_core::image），app 从头部之后开始 */
+
+MEMORY
+{
+  FLASH : ORIGIN = 0x08040200, LENGTH = 128K - 512
+  RAM : ORIGIN = 0x20000000, LENGTH = 320K
+}
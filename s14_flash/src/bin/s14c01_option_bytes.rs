@@ -0,0 +1,70 @@
+//! 读取与修改 Option Bytes
+//!
+//! 原理见 utils::option_bytes
+//!
+//! 程序启动时打印当前的 Option Bytes，然后尝试给 bootloader 所在的扇区 0 与扇区 1 加上写保护
+//! DRY_RUN 为 true 时，只打印将要发生的修改，确认无误之后，再改为 false 真正写入
+//!
+//! 写保护可以随时解除：把 TARGET 中的 with_write_protect 的 true 改为 false 再运行一次即可
+//! 本例不修改 RDP，需要修改时，请务必先读懂 utils::option_bytes 中关于 RDP 的说明
+//!
+//! 不需要额外接线
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::option_bytes::{self, Change};
+
+const DRY_RUN: bool = true;
+
+// bootloader 所在的扇区
+const BOOTLOADER_SECTORS: [u8; 2] = [0, 1];
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    let current = option_bytes::read(&dp);
+    rprintln!("RDP: {:?}", current.rdp);
+    rprintln!("BOR: {:?}", current.bor);
+    for sector in 0..option_bytes::SECTOR_COUNT {
+        if current.is_write_protected(sector) {
+            rprintln!("sector {} is write protected", sector);
+        }
+    }
+
+    let target = BOOTLOADER_SECTORS.iter().fold(current, |target, &sector| {
+        target.with_write_protect(sector, true)
+    });
+
+    for change in option_bytes::changes(&current, &target) {
+        match change {
+            Change::Rdp { from, to } => rprintln!("RDP: {:?} -> {:?}", from, to),
+            Change::Bor { from, to } => rprintln!("BOR: {:?} -> {:?}", from, to),
+            Change::WriteProtect { sector, protect } => rprintln!(
+                "sector {}: {}",
+                sector,
+                if protect { "protect" } else { "unprotect" }
+            ),
+        }
+    }
+
+    match option_bytes::program(&dp, &target, None, DRY_RUN) {
+        Ok(0) => rprintln!("nothing to change"),
+        Ok(count) if DRY_RUN => rprintln!("dry run: {} change(s) pending", count),
+        Ok(count) => rprintln!("{} change(s) written", count),
+        Err(e) => rprintln!("rejected: {:?}", e),
+    }
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
@@ -0,0 +1,111 @@
+//! 一个最简单的 bootloader：检查 app 并跳转过去
+//!
+//! 原理见 utils::boot
+//!
+//! bootloader 位于扇区 0 与扇区 1（与 s14c01 中加上写保护的扇区一致），app 位于扇区 2 开始的 APP_ADDRESS
+//! 上电之后，若 PA0 上的按键没有按下，且 APP_ADDRESS 处有一个有效的程序，就跳转过去；否则留在 bootloader 中，LED 闪烁
+//! 之后的 DFU、YMODEM 等升级功能，都可以放在“留在 bootloader 中”这一分支里
+//!
+//! 为了演示 jump_to 会清理现场，跳转之前这里故意切换到了 PLL，并打开了 SysTick 的中断与 DMA2 的时钟
+//!
+//! 使用方法：
+//!
+//! 1. 正常编译并烧录本程序
+//! 2. 设置 S14_LINK_AS_APP 编译 s14c03，并烧录到 0x0800_8000，见 s14c03 的说明
+//!
+//! 两个程序使用同一个 RTT 控制块的搜索范围，跳转之后 OpenOCD 需要重新 rtt setup/start 才能看到 app 的输出
+//!
+//! 接线图：
+//!
+//! PA0 <-> 按键 <-> 3.3V（PA0 内部下拉，按下为高电平）
+//! PA15 <-> LED（高电平点亮）
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::boot;
+
+// 扇区 2 的起始地址
+const APP_ADDRESS: u32 = 0x0800_8000;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nbootloader start");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+
+    setup_gpio(&dp);
+
+    if !button_pressed(&dp) {
+        match boot::check_image(APP_ADDRESS) {
+            Ok(()) => {
+                // 故意留下一些 app 不知道的状态
+                setup_pll(&dp);
+                cp.SYST.set_reload(100_000);
+                cp.SYST.enable_interrupt();
+                cp.SYST.enable_counter();
+                dp.RCC.ahb1enr.modify(|_, w| w.dma2en().enabled());
+
+                rprintln!("jumping to app at {:#010x}", APP_ADDRESS);
+                // jump_to 成功时不会返回，能走到这里的只有 Err
+                let Err(e) = boot::jump_to(&dp, APP_ADDRESS);
+                rprintln!("jump failed: {:?}", e);
+            }
+            Err(e) => rprintln!("no valid app at {:#010x}: {:?}", APP_ADDRESS, e),
+        }
+    } else {
+        rprintln!("button pressed, stay in bootloader");
+    }
+
+    // 留在 bootloader 中
+    loop {
+        dp.GPIOA.odr.modify(|r, w| w.odr15().bit(!r.odr15().bit()));
+        cortex_m::asm::delay(16_000_000 / 4);
+    }
+}
+
+// 跳转之后 SysTick 中断已经被关闭，这里不会在 app 中被调用
+#[cortex_m_rt::exception]
+fn SysTick() {}
+
+// PA0 内部下拉输入，PA15 推挽输出
+fn setup_gpio(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.GPIOA.pupdr.modify(|_, w| w.pupdr0().pull_down());
+    dp.GPIOA.moder.modify(|_, w| {
+        w.moder0().input();
+        w.moder15().output();
+        w
+    });
+    // 等待下拉生效
+    cortex_m::asm::delay(1000);
+}
+
+fn button_pressed(dp: &pac::Peripherals) -> bool {
+    dp.GPIOA.idr.read().idr0().is_high()
+}
+
+// HSI 16 MHz / 16 * 192 / 4 = 48 MHz
+fn setup_pll(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.pllcfgr.modify(|_, w| unsafe {
+        w.pllsrc().hsi();
+        w.pllm().bits(16);
+        w.plln().bits(192);
+        w.pllp().div4()
+    });
+    rcc.cr.modify(|_, w| w.pllon().on());
+    while rcc.cr.read().pllrdy().is_not_ready() {}
+
+    dp.FLASH.acr.modify(|_, w| w.latency().ws1());
+    rcc.cfgr.modify(|_, w| w.sw().pll());
+    while !rcc.cfgr.read().sws().is_pll() {}
+}
@@ -0,0 +1,92 @@
+//! 由 s14c02 启动的 app
+//!
+//! 原理见 utils::boot
+//!
+//! main 的开头调用 boot::app_init，确认自己是否由 bootloader 启动，并重新打开中断
+//! 之后检查 bootloader 留下的状态是否都已经被清理干净，再用 SysTick 中断让 LED 闪烁，确认中断与向量表都工作正常
+//!
+//! 编译与烧录（需要链接到 0x0800_8000）：
+//!
+//! ```shell
+//! S14_LINK_AS_APP=1 cargo build --bin s14c03_app
+//! ```
+//!
+//! 之后在 OpenOCD 的 telnet 中执行 `program <ELF 文件路径>`，ELF 中已经包含了地址信息，不会覆盖 bootloader
+//! 注意编译完之后要去掉 S14_LINK_AS_APP 再编译其他程序，否则它们也会被链接到 0x0800_8000
+//!
+//! 本程序也可以作为 s14c04 的 A/B 启动中的镜像，此时 S14_LINK_AS_APP 设置为 a 或 b，编译之后还需要签名，见 s14c04 的说明
+//! 由 bootloader 启动、初始化完成之后，调用 rollback::confirm 把防回滚计数器提高到自己的安全版本号
+//!
+//! 由调试器直接下载运行时，app_init 会返回 Launch::Direct，程序依旧可以运行
+//!
+//! 接线图：
+//!
+//! PA15 <-> LED（高电平点亮）
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    boot::{self, Launch},
+    rollback,
+};
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+
+    let launch = boot::app_init(&dp);
+
+    rtt_init_print!();
+    rprintln!("\napp start");
+    match launch {
+        Launch::Bootloader => rprintln!("launched by bootloader"),
+        Launch::Direct => rprintln!("launched directly"),
+    }
+    rprintln!("vector table at {:#010x}", boot::vector_table_address());
+
+    // bootloader 切换过 PLL、打开过 SysTick 与 DMA2，这里应该都已经恢复为复位之后的状态
+    rprintln!("SYSCLK is HSI: {}", dp.RCC.cfgr.read().sws().is_hsi());
+    rprintln!("PLL off: {}", dp.RCC.cr.read().pllrdy().is_not_ready());
+    rprintln!(
+        "DMA2 clock off: {}",
+        dp.RCC.ahb1enr.read().dma2en().is_disabled()
+    );
+
+    setup_led(&dp);
+
+    // HSI 16 MHz，每 0.25 秒进入一次 SysTick 中断
+    cp.SYST
+        .set_clock_source(cortex_m::peripheral::syst::SystClkSource::Core);
+    cp.SYST.set_reload(16_000_000 / 4 - 1);
+    cp.SYST.clear_current();
+    cp.SYST.enable_interrupt();
+    cp.SYST.enable_counter();
+
+    // 初始化都完成了，确认这个镜像可以正常启动，之后安全版本号更低的镜像不会再被 s14c04 启动
+    if launch == Launch::Bootloader {
+        rollback::confirm(&dp);
+        rprintln!("rollback counter: {:?}", rollback::read(&dp));
+    }
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+#[cortex_m_rt::exception]
+fn SysTick() {
+    let gpioa = unsafe { &*pac::GPIOA::ptr() };
+    gpioa.odr.modify(|r, w| w.odr15().bit(!r.odr15().bit()));
+}
+
+fn setup_led(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.GPIOA.moder.modify(|_, w| w.moder15().output());
+}
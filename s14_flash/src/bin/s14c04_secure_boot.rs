@@ -0,0 +1,154 @@
+//! 检查签名的 A/B bootloader
+//!
+//! 在 s14c02 的基础上：
+//!
+//! 1. app 有两个槽，槽 A 位于扇区 5（0x0802_0000），槽 B 位于扇区 6（0x0804_0000），各 128 KB，
+//!    升级时把新的镜像写入另一个槽，写到一半时掉电，原来的槽也不受影响
+//! 2. 每个槽的开头是带有 HMAC 的头部（格式见 crypto_core::image），跳转之前检查整个镜像的 MAC，
+//!    被改动过的镜像、用别的密钥签名的镜像、写到一半的镜像都不会被启动
+//! 3. 安全版本号低于防回滚计数器（见 utils::rollback）的镜像不会被启动；
+//!    启动一个镜像之前记下它的安全版本号，app 正常启动之后调用 rollback::confirm，计数器才提高到这个值
+//!
+//! 两个槽都可以启动时，选择版本号大的那一个，版本号相同时选择槽 A
+//! 在 HSI 16 MHz 下，检查 MAC 大约需要每 KB 几毫秒，app 越大，启动越慢
+//!
+//! IMAGE_KEY 是签名用的密钥，这里用的是一个公开的开发用密钥，实际使用时必须换掉，
+//! 并且用 s14c01 给 bootloader 所在的扇区加上写保护、开启 RDP Level 1，否则密钥可以被调试器读出来
+//!
+//! 使用方法（以 s14c03 作为 app）：
+//!
+//! ```shell
+//! # 1. 正常编译并烧录本程序
+//! # 2. 把 app 链接到槽 A，转换为纯二进制文件，再加上头部
+//! S14_LINK_AS_APP=a cargo build --release --bin s14c03_app
+//! arm-none-eabi-objcopy -O binary target/thumbv7em-none-eabihf/release/s14c03_app app_a.bin
+//! cargo run -p image_tool --target x86_64-unknown-linux-gnu -- sign --key <IMAGE_KEY 的十六进制> --version 1 app_a.bin app_a.img
+//! # 3. 在 OpenOCD 的 telnet 中烧录到槽 A
+//! program app_a.img 0x08020000
+//! ```
+//!
+//! 之后可以用 S14_LINK_AS_APP=b 与 --version 2 生成槽 B 的镜像，烧录到 0x0804_0000，复位后应该启动槽 B；
+//! 如果槽 B 的镜像加上了 --security-version 1，启动过一次槽 B 之后，槽 A（安全版本号为 0）就再也不会被启动了
+//!
+//! IMAGE_KEY 对应的十六进制为
+//! 73313420646576656c6f706d656e74206b65792c206368616e6765206d652121
+//!
+//! 本程序链接到 0x0800_0000，大小不能超过扇区 0 ~ 4（128 KB），否则会与槽 A 重叠
+//!
+//! 接线图：
+//!
+//! PA0 <-> 按键 <-> 3.3V（PA0 内部下拉，按下为高电平）
+//! PA15 <-> LED（高电平点亮）
+
+#![no_std]
+#![no_main]
+
+use crypto_core::image::{self, ImageHeader, HEADER_LEN};
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{boot, rollback};
+
+// 槽的名字与起始地址
+const SLOTS: [(char, u32); 2] = [('A', 0x0802_0000), ('B', 0x0804_0000)];
+const SLOT_LEN: usize = 128 * 1024;
+
+// 开发用的密钥，与 image_tool 使用的密钥必须相同
+const IMAGE_KEY: &[u8; 32] = b"s14 development key, change me!!";
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nsecure bootloader start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_gpio(&dp);
+
+    if button_pressed(&dp) {
+        rprintln!("button pressed, stay in bootloader");
+    } else {
+        match rollback::read(&dp) {
+            Some(min_security) => {
+                rprintln!("rollback counter: {}", min_security);
+                match select_slot(min_security) {
+                    Some((name, address, header)) => {
+                        let app_address = address + HEADER_LEN as u32;
+                        // 计数器由 app 确认之后再提高，跳转失败时计数器保持不变
+                        rollback::set_pending(&dp, header.security_version);
+                        rprintln!("jumping to slot {} at {:#010x}", name, app_address);
+                        let Err(e) = boot::jump_to(&dp, app_address);
+                        rollback::set_pending(&dp, 0);
+                        rprintln!("jump failed: {:?}", e);
+                    }
+                    None => rprintln!("no bootable image"),
+                }
+            }
+            // 计数器被改动过，无法判断哪些镜像已经被撤销了
+            None => rprintln!("rollback counter corrupted, refuse to boot"),
+        }
+    }
+
+    // 留在 bootloader 中
+    loop {
+        dp.GPIOA.odr.modify(|r, w| w.odr15().bit(!r.odr15().bit()));
+        cortex_m::asm::delay(16_000_000 / 4);
+    }
+}
+
+// 检查两个槽，返回可以启动的、版本号最大的那一个
+fn select_slot(min_security: u32) -> Option<(char, u32, ImageHeader)> {
+    let mut best: Option<(char, u32, ImageHeader)> = None;
+
+    for (name, address) in SLOTS {
+        // 槽位于 Flash 中，可以直接当作一段内存读取
+        let slot = unsafe { core::slice::from_raw_parts(address as *const u8, SLOT_LEN) };
+
+        let header = match image::verify(IMAGE_KEY, slot) {
+            Ok(header) => header,
+            Err(e) => {
+                rprintln!("slot {}: {:?}", name, e);
+                continue;
+            }
+        };
+
+        rprintln!(
+            "slot {}: version {}, security version {}, {} bytes",
+            name,
+            header.version,
+            header.security_version,
+            header.image_len
+        );
+        if header.security_version < min_security {
+            rprintln!("slot {}: revoked", name);
+            continue;
+        }
+
+        match best {
+            Some((_, _, current)) if current.version >= header.version => (),
+            _ => best = Some((name, address, header)),
+        }
+    }
+
+    best
+}
+
+// PA0 内部下拉输入，PA15 推挽输出
+fn setup_gpio(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.GPIOA.pupdr.modify(|_, w| w.pupdr0().pull_down());
+    dp.GPIOA.moder.modify(|_, w| {
+        w.moder0().input();
+        w.moder15().output();
+        w
+    });
+    // 等待下拉生效
+    cortex_m::asm::delay(1000);
+}
+
+fn button_pressed(dp: &pac::Peripherals) -> bool {
+    dp.GPIOA.idr.read().idr0().is_high()
+}
@@ -0,0 +1,260 @@
+//! bootloader 与 app 之间的交接
+//!
+//! bootloader 跳转到 app 时，芯片并没有复位，bootloader 留下的状态都会被 app 继承：
+//! 打开的中断、挂起的中断、还在运行的 DMA、SysTick、切换过的时钟、PSP 栈……
+//! app 一般按照刚复位的芯片来初始化，这些残留的状态轻则让 app 的初始化出错，重则在 app 还没准备好的时候触发中断，跳到不存在的中断处理函数中
+//!
+//! 因此 jump_to 在跳转之前会：
+//!
+//! 1. 检查 app 的向量表：第一个字是初始的栈顶，必须位于 RAM 中；第二个字是复位向量，必须位于 Flash 中，且最低位为 1（Thumb）
+//! 2. 关闭中断（PRIMASK），并关闭 SysTick，清除 NVIC 中所有中断的使能与挂起
+//! 3. 停止 DMA1 与 DMA2 的全部 8 个数据流，清除它们的中断标志
+//! 4. 复位所有已经开启时钟的外设，并关闭它们的时钟
+//! 5. 把系统时钟切换回 HSI，关闭 PLL 与 HSE
+//! 6. 在 RTC_BKP18R/RTC_BKP19R 中留下 app 的地址与一个标记，供 app_init 检查
+//! 7. 把 VTOR 指向 app 的向量表，切换回 MSP，把 MSP 设置为 app 的栈顶，最后跳转到 app 的复位向量
+//!
+//! app 这一侧，在 main 的开头调用 app_init：
+//!
+//! 1. 读取并清除 bootloader 留下的标记，判断自己是否由 bootloader 启动
+//! 2. 确保 VTOR 指向自己的向量表（由调试器直接下载运行时，VTOR 可能还是 0）
+//! 3. 重新打开中断，bootloader 跳转之前关闭了中断
+//!
+//! app 的 memory.x 中，FLASH 的 ORIGIN 需要改为 app 的地址，VTOR 要求向量表按 512 字节对齐（F413 有 102 个中断），扇区的起始地址都满足要求
+//!
+//! 注意：
+//! 1. 只能在特权模式下调用 jump_to
+//! 2. RTC_BKPxR 会被 Backup Domain Reset 清空，这里使用最后两个，不要与 s07 的 utils::bkp_store 的数据重叠
+
+#![allow(dead_code)]
+
+use core::convert::Infallible;
+
+use cortex_m::peripheral::{NVIC, SCB, SYST};
+use stm32f4xx_hal::pac::Peripherals;
+
+const RAM_START: u32 = 0x2000_0000;
+const RAM_END: u32 = RAM_START + 320 * 1024;
+const FLASH_START: u32 = 0x0800_0000;
+const FLASH_END: u32 = FLASH_START + 1536 * 1024;
+
+// VTOR 的对齐要求
+const VECTOR_TABLE_ALIGN: u32 = 512;
+
+// 交接标记所在的 RTC_BKPxR
+const BKP_ADDRESS: usize = 18;
+const BKP_MAGIC: usize = 19;
+// "BOOT"
+const HANDOFF_MAGIC: u32 = 0x544F_4F42;
+
+// F413 的 NVIC 中，ICER/ICPR 用到的寄存器个数
+const NVIC_REGISTERS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BootError {
+    // 地址不满足 VTOR 的对齐要求，或者不在 Flash 中
+    Address,
+    // 初始的栈顶不在 RAM 中，一般说明这里没有程序（擦除之后为 0xFFFF_FFFF）
+    StackPointer,
+    // 复位向量不在 Flash 中，或者不是 Thumb 地址
+    ResetVector,
+}
+
+// app 是怎样启动的
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Launch {
+    // 由 bootloader 通过 jump_to 启动
+    Bootloader,
+    // 复位之后直接运行，或者由调试器直接下载运行
+    Direct,
+}
+
+// 检查 address 处是不是一个可以启动的程序
+pub(crate) fn check_image(address: u32) -> Result<(), BootError> {
+    if !address.is_multiple_of(VECTOR_TABLE_ALIGN) || !(FLASH_START..FLASH_END).contains(&address) {
+        return Err(BootError::Address);
+    }
+
+    let (stack_pointer, reset_vector) = read_vectors(address);
+    // 栈顶可以等于 RAM 的末尾，栈是向下增长的
+    if !(RAM_START + 4..=RAM_END).contains(&stack_pointer) || !stack_pointer.is_multiple_of(4) {
+        return Err(BootError::StackPointer);
+    }
+    if !(FLASH_START..FLASH_END).contains(&reset_vector) || reset_vector & 1 == 0 {
+        return Err(BootError::ResetVector);
+    }
+    Ok(())
+}
+
+// 清理现场并跳转到 address 处的程序，只有 address 处的程序无效时才会返回
+pub(crate) fn jump_to(dp: &Peripherals, address: u32) -> Result<Infallible, BootError> {
+    check_image(address)?;
+
+    cortex_m::interrupt::disable();
+
+    deinit_core();
+    deinit_dma(dp);
+    deinit_peripherals(dp);
+    deinit_clocks(dp);
+
+    // 外设都复位了，这里重新打开 PWR 的时钟来写入 RTC_BKPxR，之后再关闭
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| w.dbp().set_bit());
+    dp.RTC.bkpr[BKP_ADDRESS].write(|w| w.bkp().bits(address));
+    dp.RTC.bkpr[BKP_MAGIC].write(|w| w.bkp().bits(HANDOFF_MAGIC));
+    dp.PWR.cr.modify(|_, w| w.dbp().clear_bit());
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().disabled());
+
+    unsafe {
+        let scb = &*SCB::PTR;
+        scb.vtor.write(address);
+
+        // app 可能假设自己运行在使用 MSP 的特权模式中，CONTROL 清零之后 SPSEL 为 0，切换回 MSP
+        // 这里不能再使用栈上的变量，bootload 只通过寄存器传递 address
+        cortex_m::register::control::write(cortex_m::register::control::Control::from_bits(0));
+        cortex_m::asm::isb();
+
+        // 设置 MSP 为向量表的第一个字，并跳转到第二个字
+        cortex_m::asm::bootload(address as *const u32)
+    }
+}
+
+// 在 app 的 main 的开头调用
+pub(crate) fn app_init(dp: &Peripherals) -> Launch {
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| w.dbp().set_bit());
+
+    let magic = dp.RTC.bkpr[BKP_MAGIC].read().bkp().bits();
+    let address = dp.RTC.bkpr[BKP_ADDRESS].read().bkp().bits();
+    // 标记只能使用一次，之后的复位（比如看门狗复位）不再算作由 bootloader 启动
+    dp.RTC.bkpr[BKP_MAGIC].write(|w| w.bkp().bits(0));
+    dp.RTC.bkpr[BKP_ADDRESS].write(|w| w.bkp().bits(0));
+
+    let own_address = vector_table_address();
+    let launch = if magic == HANDOFF_MAGIC && address == own_address {
+        Launch::Bootloader
+    } else {
+        Launch::Direct
+    };
+
+    unsafe {
+        let scb = &*SCB::PTR;
+        if scb.vtor.read() != own_address {
+            scb.vtor.write(own_address);
+        }
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+        cortex_m::interrupt::enable();
+    }
+
+    launch
+}
+
+// 当前程序的向量表的地址，由 cortex-m-rt 的 link.x 给出
+pub(crate) fn vector_table_address() -> u32 {
+    extern "C" {
+        static __vector_table: u32;
+    }
+    core::ptr::addr_of!(__vector_table) as u32
+}
+
+fn read_vectors(address: u32) -> (u32, u32) {
+    unsafe {
+        (
+            core::ptr::read_volatile(address as *const u32),
+            core::ptr::read_volatile((address + 4) as *const u32),
+        )
+    }
+}
+
+// SysTick 与 NVIC
+fn deinit_core() {
+    unsafe {
+        let syst = &*SYST::PTR;
+        syst.csr.write(0);
+        syst.rvr.write(0);
+        syst.cvr.write(0);
+
+        let nvic = &*NVIC::PTR;
+        for i in 0..NVIC_REGISTERS {
+            nvic.icer[i].write(0xFFFF_FFFF);
+            nvic.icpr[i].write(0xFFFF_FFFF);
+        }
+
+        // SysTick 与 PendSV 也可能处于挂起状态
+        let scb = &*SCB::PTR;
+        scb.icsr.write((1 << 25) | (1 << 27));
+    }
+}
+
+// 停止全部 16 个数据流，并清除它们的中断标志
+fn deinit_dma(dp: &Peripherals) {
+    for dma in [&*dp.DMA1, &*dp.DMA2] {
+        for stream in dma.st.iter() {
+            stream.cr.modify(|_, w| w.en().disabled());
+            while stream.cr.read().en().is_enabled() {}
+        }
+        dma.lifcr.write(|w| unsafe { w.bits(0x0F7D_0F7D) });
+        dma.hifcr.write(|w| unsafe { w.bits(0x0F7D_0F7D) });
+    }
+}
+
+// 复位所有已经开启时钟的外设，再关闭它们的时钟
+// xxxRSTR 与 xxxENR 中，同一个外设的位置是相同的
+fn deinit_peripherals(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+
+    let ahb1 = rcc.ahb1enr.read().bits();
+    let ahb2 = rcc.ahb2enr.read().bits();
+    let apb1 = rcc.apb1enr.read().bits();
+    let apb2 = rcc.apb2enr.read().bits();
+
+    rcc.ahb1rstr.write(|w| unsafe { w.bits(ahb1) });
+    rcc.ahb2rstr.write(|w| unsafe { w.bits(ahb2) });
+    rcc.apb1rstr.write(|w| unsafe { w.bits(apb1) });
+    rcc.apb2rstr.write(|w| unsafe { w.bits(apb2) });
+
+    rcc.ahb1rstr.reset();
+    rcc.ahb2rstr.reset();
+    rcc.apb1rstr.reset();
+    rcc.apb2rstr.reset();
+
+    rcc.ahb1enr.reset();
+    rcc.ahb2enr.reset();
+    rcc.apb1enr.reset();
+    rcc.apb2enr.reset();
+
+    // F401/F411 没有 AHB3（FSMC 与 QUADSPI），也就没有 AHB3ENR/AHB3RSTR，与 mcu_common 的 clock_gate 相同
+    #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+    {
+        let ahb3 = rcc.ahb3enr.read().bits();
+        rcc.ahb3rstr.write(|w| unsafe { w.bits(ahb3) });
+        rcc.ahb3rstr.reset();
+        rcc.ahb3enr.reset();
+    }
+}
+
+// 与复位之后一样，使用 HSI 作为系统时钟，关闭 PLL 与 HSE，不分频
+fn deinit_clocks(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+
+    rcc.cr.modify(|_, w| w.hsion().on());
+    while rcc.cr.read().hsirdy().is_not_ready() {}
+
+    rcc.cfgr.modify(|_, w| w.sw().hsi());
+    while !rcc.cfgr.read().sws().is_hsi() {}
+    rcc.cfgr.reset();
+
+    rcc.cr.modify(|_, w| {
+        w.pllon().off();
+        w.plli2son().off();
+        w.hseon().off();
+        w.csson().off();
+        w
+    });
+    while rcc.cr.read().pllrdy().is_ready() {}
+    rcc.cr.modify(|_, w| w.hsebyp().not_bypassed());
+
+    // 关闭时钟相关的中断，并清除它们的标志
+    rcc.cir.write(|w| unsafe { w.bits(0x00BF_0000) });
+}
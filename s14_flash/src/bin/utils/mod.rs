@@ -0,0 +1,7 @@
+pub(crate) mod boot;
+pub(crate) mod option_bytes;
+pub(crate) mod rollback;
+
+// 与其它章节共用的模块，代码在工作区中单独的 crate 里
+#[allow(unused_imports)]
+pub(crate) use mcu_common::optcr;
@@ -0,0 +1,251 @@
+//! 读取与修改 Option Bytes
+//!
+//! Option Bytes 保存在 FLASH 的一块独立区域中，芯片复位时被加载到 FLASH_OPTCR，掉电也会保留
+//! 这里管理其中的三项：
+//!
+//! 1. RDP（Read Protection）
+//!    0xAA 为 Level 0，不保护；0xCC 为 Level 2，调试接口被永久关闭，Option Bytes 也不能再修改，这一步不可逆；
+//!    其余的值都是 Level 1，调试器无法读取 FLASH，从 Level 1 回到 Level 0 时，整个 FLASH 会被擦除（Mass Erase），
+//!    当然也包括正在运行的这个程序
+//! 2. nWRP（Write Protection）
+//!    每一位对应一个扇区，为 0 时对应的扇区被写保护，无法擦除与写入，可以随时解除；
+//!    这里只处理 FLASH_OPTCR 中的扇区，F401/F411 只有扇区 0 ~ 7，F412 为 0 ~ 11，
+//!    F413 的扇区 12 ~ 15 在 FLASH_OPTCR1 中，这里不处理，bootloader 通常放在最前面的几个扇区中
+//!    另外，SPRMOD（bit 31）为 1 时，nWRP 的含义会变为 PCROP（读保护），这里不处理这种情况
+//! 3. BOR_LEV，BOR 的阈值，提高之前请先确认供电电压足够高，见 s17 的 utils::supervisor
+//!
+//! 解锁、写入、重新上锁的流程与 BorLevel，与 s17 的 utils::supervisor 共用，见 mcu_common::optcr
+//!
+//! 由于改错的代价很高，这里的 program 提供了 dry run：只比较当前值与目标值，返回将要发生的修改，而不写入任何东西
+//! 涉及 RDP 的修改，还必须传入与之对应的 RdpConfirm，否则会被拒绝
+
+#![allow(dead_code)]
+
+use assert_policy::check;
+use chip_caps::CHIP;
+use stm32f4xx_hal::pac::Peripherals;
+
+use super::optcr::{self, BorLevel};
+
+// FLASH_OPTCR 中 nWRP 管理的扇区数，最多为 12 个
+pub(crate) const SECTOR_COUNT: u8 = if CHIP.flash_sectors() < 12 {
+    CHIP.flash_sectors()
+} else {
+    12
+};
+
+// FLASH_OPTCR 的 SPRMOD
+const SPRMOD: u32 = 1 << 31;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rdp {
+    Level0,
+    Level1,
+    Level2,
+}
+
+impl Rdp {
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0xAA => Self::Level0,
+            0xCC => Self::Level2,
+            _ => Self::Level1,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Self::Level0 => 0xAA,
+            Self::Level1 => 0x55,
+            Self::Level2 => 0xCC,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OptionBytes {
+    pub(crate) rdp: Rdp,
+    pub(crate) bor: BorLevel,
+    // 被写保护的扇区，bit n 为 1 表示扇区 n 被保护（注意与 nWRP 的极性相反）
+    pub(crate) write_protected: u16,
+}
+
+impl OptionBytes {
+    pub(crate) fn is_write_protected(&self, sector: u8) -> bool {
+        self.write_protected & (1 << sector) != 0
+    }
+
+    // sector 超出范围时保持不变，策略见 assert_policy
+    pub(crate) fn with_write_protect(mut self, sector: u8, protect: bool) -> Self {
+        if !check!(sector < SECTOR_COUNT) {
+            return self;
+        }
+        if protect {
+            self.write_protected |= 1 << sector;
+        } else {
+            self.write_protected &= !(1 << sector);
+        }
+        self
+    }
+}
+
+// 一项修改
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Change {
+    Rdp { from: Rdp, to: Rdp },
+    Bor { from: BorLevel, to: BorLevel },
+    WriteProtect { sector: u8, protect: bool },
+}
+
+// 修改 RDP 时的确认，必须与实际发生的修改一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RdpConfirm {
+    // Level 0 -> Level 1
+    EnableReadProtection,
+    // Level 1 -> Level 0，整个 FLASH 会被擦除
+    MassEraseToLevel0,
+    // -> Level 2，永久生效，之后再也无法调试与修改 Option Bytes
+    PermanentLevel2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Error {
+    // 已经处于 RDP Level 2，什么也改不了
+    Level2Locked,
+    // SPRMOD 为 1，nWRP 的含义是 PCROP，不处理
+    PcropMode,
+    // 修改了 RDP，但没有给出对应的确认
+    NotConfirmed,
+    // FLASH_OPTCR 没能解锁
+    Locked,
+    // FLASH_SR 中出现了错误标志
+    Program,
+    // 写入之后读回的值不对
+    Verify,
+}
+
+pub(crate) fn read(dp: &Peripherals) -> OptionBytes {
+    let optcr = dp.FLASH.optcr.read();
+    let mask = (1 << SECTOR_COUNT) - 1;
+    OptionBytes {
+        rdp: Rdp::from_bits(optcr.rdp().bits()),
+        bor: BorLevel::from_bits(optcr.bor_lev().bits()),
+        write_protected: !optcr.n_wrp().bits() & mask,
+    }
+}
+
+// 按照 RDP、BOR、各个扇区的顺序，列出从 current 到 target 的所有修改
+pub(crate) fn changes(current: &OptionBytes, target: &OptionBytes) -> Changes {
+    Changes {
+        current: *current,
+        target: *target,
+        cursor: 0,
+    }
+}
+
+pub(crate) struct Changes {
+    current: OptionBytes,
+    target: OptionBytes,
+    // 0 为 RDP，1 为 BOR，2 之后为扇区
+    cursor: u8,
+}
+
+impl Iterator for Changes {
+    type Item = Change;
+
+    fn next(&mut self) -> Option<Change> {
+        while self.cursor < 2 + SECTOR_COUNT {
+            let cursor = self.cursor;
+            self.cursor += 1;
+
+            let (current, target) = (&self.current, &self.target);
+            match cursor {
+                0 if current.rdp != target.rdp => {
+                    return Some(Change::Rdp {
+                        from: current.rdp,
+                        to: target.rdp,
+                    })
+                }
+                1 if current.bor != target.bor => {
+                    return Some(Change::Bor {
+                        from: current.bor,
+                        to: target.bor,
+                    })
+                }
+                2.. => {
+                    let sector = cursor - 2;
+                    let protect = target.is_write_protected(sector);
+                    if current.is_write_protected(sector) != protect {
+                        return Some(Change::WriteProtect { sector, protect });
+                    }
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+}
+
+// 将 Option Bytes 修改为 target，返回修改的项数
+//
+// dry_run 为 true 时只做检查，不写入；检查的内容与真正写入时完全相同，因此 dry run 通过，就意味着写入时不会被拒绝
+// 修改 RDP 时，confirm 必须与修改的方向对应
+pub(crate) fn program(
+    dp: &Peripherals,
+    target: &OptionBytes,
+    confirm: Option<RdpConfirm>,
+    dry_run: bool,
+) -> Result<usize, Error> {
+    let current = read(dp);
+
+    if current.rdp == Rdp::Level2 {
+        return Err(Error::Level2Locked);
+    }
+    if dp.FLASH.optcr.read().bits() & SPRMOD != 0 {
+        return Err(Error::PcropMode);
+    }
+
+    if current.rdp != target.rdp {
+        let required = match target.rdp {
+            Rdp::Level2 => RdpConfirm::PermanentLevel2,
+            Rdp::Level0 => RdpConfirm::MassEraseToLevel0,
+            Rdp::Level1 => RdpConfirm::EnableReadProtection,
+        };
+        if confirm != Some(required) {
+            return Err(Error::NotConfirmed);
+        }
+    }
+
+    let count = changes(&current, target).count();
+    if dry_run || count == 0 {
+        return Ok(count);
+    }
+
+    let flash = &dp.FLASH;
+
+    let mask = (1 << SECTOR_COUNT) - 1;
+    optcr::modify(flash, |r, w| unsafe {
+        w.rdp().bits(target.rdp.bits());
+        w.bor_lev().bits(target.bor.bits());
+        // 只改动 SECTOR_COUNT 个扇区对应的位
+        w.n_wrp()
+            .bits((r.n_wrp().bits() & !mask) | (!target.write_protected & mask));
+        w
+    })
+    .map_err(|_| Error::Locked)?;
+
+    let sr = flash.sr.read();
+    if sr.wrperr().bit_is_set()
+        || sr.pgaerr().bit_is_set()
+        || sr.pgperr().bit_is_set()
+        || sr.pgserr().bit_is_set()
+    {
+        return Err(Error::Program);
+    }
+
+    if read(dp) != *target {
+        return Err(Error::Verify);
+    }
+
+    Ok(count)
+}
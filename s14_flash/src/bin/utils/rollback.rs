@@ -0,0 +1,70 @@
+//! 防回滚计数器：bootloader 启动过的最高的安全版本号
+//!
+//! 计数器只能增加，不能减少，安全版本号低于它的镜像不会再被启动，说明见 crypto_core::image
+//!
+//! 计数器保存在 RTC_BKP16R 中，RTC_BKP15R 保存它的反码，两者对不上时说明被改动过（或者写到一半时掉电），
+//! 此时无法知道原来的值，只能拒绝启动任何镜像
+//! Backup Domain Reset 之后两者都为 0，这是唯一允许的“对不上”的情况，表示计数器从 0 开始
+//!
+//! 注意：RTC_BKPxR 会被 Backup Domain Reset 清空，而 VBAT 与 VDD 同时断电就会引起 Backup Domain Reset，
+//! 因此能接触到板子的人可以通过断电把计数器清零；需要更强的保证时，可以改用 OTP 区域（0x1FFF_7800）中逐位写 0 的计数，
+//! OTP 中的位一旦写为 0 就不能再改回 1，但 OTP 的空间一共只有 512 字节，写错了也无法挽回，这里就不演示了
+//!
+//! 计数器不在跳转之前提高：跳转失败，或者新的镜像启动之后就崩溃了，计数器却已经提高，旧的镜像也就再也不能启动了，
+//! 两个槽里可能都没有能用的镜像。因此 bootloader 只把镜像的安全版本号记在 RTC_BKP14R 中（set_pending），
+//! app 由 bootloader 启动、完成了自己的初始化之后，再调用 confirm，这时计数器才真正提高
+//!
+//! 使用的 RTC_BKPxR 与 utils::boot（18、19）以及 s21 的 utils::dfu（17）都不冲突
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::Peripherals;
+
+const BKP_COUNTER: usize = 16;
+const BKP_COUNTER_INV: usize = 15;
+// 等待 app 确认的安全版本号
+const BKP_PENDING: usize = 14;
+
+// 读取计数器，返回 None 表示计数器被改动过
+pub(crate) fn read(dp: &Peripherals) -> Option<u32> {
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    let counter = dp.RTC.bkpr[BKP_COUNTER].read().bkp().bits();
+    let inverted = dp.RTC.bkpr[BKP_COUNTER_INV].read().bkp().bits();
+
+    match (counter, inverted) {
+        (0, 0) => Some(0),
+        (counter, inverted) if counter == !inverted => Some(counter),
+        _ => None,
+    }
+}
+
+// 把计数器提高到 value，value 不大于当前值，或者计数器已经被改动过时，什么也不做
+pub(crate) fn raise(dp: &Peripherals, value: u32) {
+    match read(dp) {
+        Some(counter) if counter < value => (),
+        _ => return,
+    }
+
+    dp.PWR.cr.modify(|_, w| w.dbp().set_bit());
+    // 先写反码：如果两次写入之间掉电，两者对不上，结果是拒绝启动，而不是退回到更低的值
+    dp.RTC.bkpr[BKP_COUNTER_INV].write(|w| w.bkp().bits(!value));
+    dp.RTC.bkpr[BKP_COUNTER].write(|w| w.bkp().bits(value));
+    dp.PWR.cr.modify(|_, w| w.dbp().clear_bit());
+}
+
+// bootloader 在跳转之前调用，记下即将启动的镜像的安全版本号
+pub(crate) fn set_pending(dp: &Peripherals, value: u32) {
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| w.dbp().set_bit());
+    dp.RTC.bkpr[BKP_PENDING].write(|w| w.bkp().bits(value));
+    dp.PWR.cr.modify(|_, w| w.dbp().clear_bit());
+}
+
+// app 在确认自己正常启动之后调用，把计数器提高到 bootloader 记下的安全版本号
+// 只有由 bootloader 启动时才调用（见 boot::app_init 的返回值），否则记下的值属于之前的某一次启动
+pub(crate) fn confirm(dp: &Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    let pending = dp.RTC.bkpr[BKP_PENDING].read().bkp().bits();
+    set_pending(dp, 0);
+    raise(dp, pending);
+}
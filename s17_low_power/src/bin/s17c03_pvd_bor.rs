@@ -0,0 +1,91 @@
+//! 电源监控：PVD 中断与 BOR 阈值
+//!
+//! 原理见 utils::supervisor
+//!
+//! 程序启动时打印当前的 BOR 阈值，若 TARGET_BOR 不为 None，则尝试修改它
+//! 之后开启 PVD，VDD 跌破 2.9 V 时，在中断里立刻熄灭 PA15 上的 LED（模拟关闭大电流的负载），
+//! VDD 恢复之后，再点亮 LED
+//!
+//! 中断里只做最要紧的事，打印的工作留给主循环
+//!
+//! 接线图：
+//!
+//! 使用可调电源代替 ST-Link 给开发板的 3.3 V 供电，从 3.3 V 缓慢地调低到 2.8 V，再调回 3.3 V
+//! 注意不要低于当前的 BOR 阈值，否则芯片会被复位
+
+#![no_std]
+#![no_main]
+
+use core::cell::Cell;
+
+use cortex_m::interrupt::Mutex;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{interrupt, Peripherals};
+
+mod utils;
+
+use utils::supervisor::{self, BorLevel, PowerEvent, PvdLevel};
+
+// 需要修改 BOR 阈值时，改为 Some(..)，修改会写入 Option Bytes，掉电保留
+const TARGET_BOR: Option<BorLevel> = None;
+
+static G_EVENT: Mutex<Cell<Option<PowerEvent>>> = Mutex::new(Cell::new(None));
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = Peripherals::take().unwrap();
+
+    dp.DBGMCU.cr.modify(|_, w| w.dbg_sleep().set_bit());
+    dp.RCC.ahb1enr.modify(|_, w| w.dma1en().enabled());
+
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.GPIOA.moder.modify(|_, w| w.moder15().output());
+    dp.GPIOA.odr.modify(|_, w| w.odr15().high());
+
+    let bor = supervisor::bor_level(&dp);
+    rprintln!("BOR: {:?} ({} mV)", bor, bor.millivolts());
+
+    if let Some(target) = TARGET_BOR {
+        match supervisor::set_bor_level(&dp, target) {
+            Ok(()) => rprintln!("BOR set to {:?} ({} mV)", target, target.millivolts()),
+            Err(e) => rprintln!("BOR unchanged: {:?}", e),
+        }
+    }
+
+    supervisor::setup_pvd(&dp, PvdLevel::V2_9, on_power_event);
+    rprintln!(
+        "PVD: {} mV, supply low: {}",
+        PvdLevel::V2_9.millivolts(),
+        supervisor::supply_low(&dp)
+    );
+
+    loop {
+        cortex_m::asm::wfi();
+
+        if let Some(event) = cortex_m::interrupt::free(|cs| G_EVENT.borrow(cs).take()) {
+            match event {
+                PowerEvent::Falling => rprintln!("VDD falling, load switched off"),
+                PowerEvent::Recovered => rprintln!("VDD recovered, load switched on"),
+            }
+        }
+    }
+}
+
+// 在 PVD 中断中执行
+fn on_power_event(event: PowerEvent) {
+    let dp = unsafe { Peripherals::steal() };
+    match event {
+        PowerEvent::Falling => dp.GPIOA.odr.modify(|_, w| w.odr15().low()),
+        PowerEvent::Recovered => dp.GPIOA.odr.modify(|_, w| w.odr15().high()),
+    }
+    cortex_m::interrupt::free(|cs| G_EVENT.borrow(cs).set(Some(event)));
+}
+
+#[interrupt]
+fn PVD() {
+    supervisor::on_pvd_interrupt();
+}
@@ -0,0 +1,99 @@
+//! 外设时钟审计
+//!
+//! 在 s17c01_wfi_3sleep_on_exit 的基础上，所有外设时钟都通过 utils::clock_gate 打开，
+//! 然后模拟“某个库打开了时钟却忘了关”的情况，直接改写 RCC 打开 ADC1 与 SPI1，
+//! 再用 report 查看当前的时钟状态，用 disable_unused 关掉这些没人认领的时钟，
+//! 最后用 apply_sleep_gating 让 Sleep 模式下只有 GPIOA、TIM2 与 RTT 所需的 DMA1 还有时钟
+//!
+//! 可以用万用表比较 disable_unused 与 apply_sleep_gating 前后的电流
+//!
+//! 接线图：
+//!
+//! PA15 <-> LED <-> GND
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+
+use stm32f4xx_hal::{
+    interrupt,
+    pac::{self, CorePeripherals, Peripherals, NVIC},
+};
+
+mod utils;
+
+use utils::clock_gate::{self, gates, KEEP_FOR_RTT};
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    rprintln!("\nProgram Start\r");
+
+    let dp = Peripherals::take().unwrap();
+    let mut cp = CorePeripherals::take().unwrap();
+
+    dp.DBGMCU.cr.reset();
+    dp.DBGMCU.cr.modify(|_, w| w.dbg_sleep().set_bit());
+
+    let rcc = &dp.RCC;
+
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+
+    rcc.cfgr.modify(|_, w| w.hpre().div8());
+
+    // RTT 在 Sleep 模式下需要 DMA1 的时钟，这里也作为一个使用者 claim 它
+    clock_gate::claim(gates::DMA1);
+
+    clock_gate::claim(gates::GPIOA);
+    dp.GPIOA.moder.modify(|_, w| w.moder15().output());
+
+    clock_gate::claim(gates::TIM2);
+    let tim2 = &dp.TIM2;
+    tim2.psc.write(|w| w.psc().bits(1_000));
+    tim2.arr.write(|w| w.arr().bits(1_000));
+    tim2.dier.modify(|_, w| w.uie().enabled());
+    tim2.cr1.modify(|_, w| w.cen().enabled());
+
+    // 模拟被忘掉的时钟
+    rcc.apb2enr.modify(|_, w| {
+        w.adc1en().enabled();
+        w.spi1en().enabled();
+        w
+    });
+
+    clock_gate::report();
+
+    let disabled = clock_gate::disable_unused(KEEP_FOR_RTT);
+    rprintln!(
+        "{} clock(s) disabled, {} still clocked\r",
+        disabled,
+        clock_gate::clocked_count()
+    );
+
+    clock_gate::apply_sleep_gating(KEEP_FOR_RTT);
+    clock_gate::report();
+
+    cp.SCB.set_sleeponexit();
+
+    unsafe { NVIC::unmask(interrupt::TIM2) };
+
+    cortex_m::asm::wfi();
+    unreachable!("Don't forget to enable Sleep on Exit");
+}
+
+#[interrupt]
+fn TIM2() {
+    let tim2 = unsafe { &*pac::TIM2::ptr() };
+    tim2.sr.modify(|_, w| w.uif().clear());
+
+    let gpioa = unsafe { &*pac::GPIOA::ptr() };
+    gpioa
+        .odr
+        .modify(|r, w| w.odr15().bit(r.odr15().bit() ^ true));
+}
@@ -0,0 +1,217 @@
+//! 电源监控：PVD 与 BOR
+//!
+//! PVD（Programmable Voltage Detector）持续比较 VDD 与 PWR_CR 中 PLS 选择的阈值，
+//! VDD 低于阈值时 PWR_CSR 的 PVDO 置位，高于阈值时清零；PVDO 的变化通过 EXTI line 16 产生中断
+//! 因此只要同时开启 EXTI16 的上升沿与下降沿，就可以分别知道“电压正在掉下去”与“电压恢复了”
+//!
+//! 电压跌到 PVD 阈值，到跌破 BOR（Brown-Out Reset）阈值芯片被复位，中间通常只有很短的时间（取决于电源上的电容），
+//! 所以 PVD 的中断里只适合做最要紧的事情：关掉大电流的负载，停止正在进行的 FLASH 写入，保存少量状态之类的
+//!
+//! BOR 的阈值不在寄存器里，而是在 Option Bytes 里（FLASH_OPTCR 的 BOR_LEV），修改之后掉电也会保留
+//! BorLevel 与修改 Option Bytes 的流程，与 s14 的 utils::option_bytes 共用，见 mcu_common::optcr
+//!
+//! 需要注意的是，若把 BOR 的阈值设置得比实际的供电电压还高，芯片会一直处于复位状态，再也无法运行程序，
+//! 因此 set_bor_level 在提高阈值之前，会先借用 PVD 确认 VDD 比新的阈值还高出一截，否则拒绝修改
+
+#![allow(dead_code)]
+
+use core::cell::Cell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{interrupt, Peripherals, NVIC};
+
+use super::optcr;
+pub(crate) use super::optcr::BorLevel;
+
+// PVD 的阈值，这里的电压为 VDD 下降时的阈值，上升时的阈值要再高约 0.1 V
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PvdLevel {
+    V2_0,
+    V2_1,
+    V2_3,
+    V2_5,
+    V2_6,
+    V2_7,
+    V2_8,
+    V2_9,
+}
+
+impl PvdLevel {
+    fn pls(self) -> u8 {
+        self as u8
+    }
+
+    fn from_pls(pls: u8) -> Self {
+        match pls & 0b111 {
+            0 => Self::V2_0,
+            1 => Self::V2_1,
+            2 => Self::V2_3,
+            3 => Self::V2_5,
+            4 => Self::V2_6,
+            5 => Self::V2_7,
+            6 => Self::V2_8,
+            _ => Self::V2_9,
+        }
+    }
+
+    pub(crate) fn millivolts(self) -> u16 {
+        match self {
+            Self::V2_0 => 2000,
+            Self::V2_1 => 2100,
+            Self::V2_3 => 2300,
+            Self::V2_5 => 2500,
+            Self::V2_6 => 2600,
+            Self::V2_7 => 2700,
+            Self::V2_8 => 2800,
+            Self::V2_9 => 2900,
+        }
+    }
+}
+
+// 提高到这一等级之前，VDD 至少要高于哪个 PVD 阈值，留出 0.2 V 的余量
+fn bor_guard(level: BorLevel) -> Option<PvdLevel> {
+    match level {
+        BorLevel::Off => None,
+        BorLevel::Level1 => Some(PvdLevel::V2_3),
+        BorLevel::Level2 => Some(PvdLevel::V2_6),
+        BorLevel::Level3 => Some(PvdLevel::V2_9),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PowerEvent {
+    // VDD 跌破了 PVD 阈值
+    Falling,
+    // VDD 重新回到 PVD 阈值之上
+    Recovered,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BorError {
+    // RDP 为 Level 2，Option Bytes 永久不可修改
+    Protected,
+    // VDD 不够高，提高阈值后芯片可能会一直复位
+    SupplyTooLow,
+    // OPTCR 没能解锁
+    Locked,
+    // 写入之后读回的值不对
+    Verify,
+}
+
+pub(crate) type Callback = fn(PowerEvent);
+
+static G_CALLBACK: Mutex<Cell<Option<Callback>>> = Mutex::new(Cell::new(None));
+
+// 开启 PVD，并在 VDD 穿过阈值时调用 callback，callback 在 PVD 的中断中执行
+pub(crate) fn setup_pvd(dp: &Peripherals, level: PvdLevel, callback: Callback) {
+    cortex_m::interrupt::free(|cs| G_CALLBACK.borrow(cs).set(Some(callback)));
+
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    set_pvd_level(dp, level);
+    dp.PWR.cr.modify(|_, w| w.pvde().set_bit());
+
+    // EXTI16 连接到 PVD 的输出，上升沿为电压下降，下降沿为电压恢复
+    dp.EXTI.rtsr.modify(|_, w| w.tr16().enabled());
+    dp.EXTI.ftsr.modify(|_, w| w.tr16().enabled());
+    dp.EXTI.pr.write(|w| w.pr16().clear());
+    dp.EXTI.imr.modify(|_, w| w.mr16().unmasked());
+
+    unsafe { NVIC::unmask(interrupt::PVD) };
+}
+
+fn set_pvd_level(dp: &Peripherals, level: PvdLevel) {
+    dp.PWR
+        .cr
+        .modify(|_, w| unsafe { w.pls().bits(level.pls()) });
+}
+
+pub(crate) fn pvd_level(dp: &Peripherals) -> PvdLevel {
+    PvdLevel::from_pls(dp.PWR.cr.read().pls().bits())
+}
+
+// VDD 当前是否低于 PVD 阈值
+pub(crate) fn supply_low(dp: &Peripherals) -> bool {
+    dp.PWR.csr.read().pvdo().bit_is_set()
+}
+
+// 在 PVD 的中断里调用
+pub(crate) fn on_pvd_interrupt() {
+    let dp = unsafe { Peripherals::steal() };
+    dp.EXTI.pr.write(|w| w.pr16().clear());
+
+    let event = if supply_low(&dp) {
+        PowerEvent::Falling
+    } else {
+        PowerEvent::Recovered
+    };
+
+    if let Some(callback) = cortex_m::interrupt::free(|cs| G_CALLBACK.borrow(cs).get()) {
+        callback(event);
+    }
+}
+
+pub(crate) fn bor_level(dp: &Peripherals) -> BorLevel {
+    optcr::bor_level(&dp.FLASH)
+}
+
+// 修改 BOR 阈值，新的阈值立刻生效，并且掉电保留
+//
+// 降低阈值总是安全的；提高阈值之前，会用 PVD 确认 VDD 高出新阈值 0.2 V 以上
+pub(crate) fn set_bor_level(dp: &Peripherals, level: BorLevel) -> Result<(), BorError> {
+    let current = bor_level(dp);
+    if current == level {
+        return Ok(());
+    }
+
+    // RDP 为 0xCC 时是 Level 2，此时 Option Bytes 已经被永久锁定
+    if dp.FLASH.optcr.read().rdp().bits() == 0xCC {
+        return Err(BorError::Protected);
+    }
+
+    if level > current {
+        if let Some(guard) = bor_guard(level) {
+            if !supply_above(dp, guard) {
+                return Err(BorError::SupplyTooLow);
+            }
+        }
+    }
+
+    optcr::modify(&dp.FLASH, |_, w| unsafe { w.bor_lev().bits(level.bits()) })
+        .map_err(|_| BorError::Locked)?;
+
+    if bor_level(dp) != level {
+        return Err(BorError::Verify);
+    }
+
+    Ok(())
+}
+
+// 临时把 PVD 阈值改为 guard，检查 VDD 是否高于它，之后恢复原来的设置
+fn supply_above(dp: &Peripherals, guard: PvdLevel) -> bool {
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+
+    let pvd_enabled = dp.PWR.cr.read().pvde().bit_is_set();
+    let old_level = pvd_level(dp);
+
+    // 检查期间 PVDO 的变化不代表真正的掉电，不能让它进入 callback
+    let irq_enabled = dp.EXTI.imr.read().mr16().is_unmasked();
+    dp.EXTI.imr.modify(|_, w| w.mr16().masked());
+
+    set_pvd_level(dp, guard);
+    dp.PWR.cr.modify(|_, w| w.pvde().set_bit());
+    // 等待 PVD 的比较器稳定，手册上为几十微秒，这里在 16 MHz 下多等一些
+    cortex_m::asm::delay(2_000);
+    let above = !supply_low(dp);
+
+    set_pvd_level(dp, old_level);
+    if !pvd_enabled {
+        dp.PWR.cr.modify(|_, w| w.pvde().clear_bit());
+    }
+    cortex_m::asm::delay(2_000);
+    dp.EXTI.pr.write(|w| w.pr16().clear());
+    if irq_enabled {
+        dp.EXTI.imr.modify(|_, w| w.mr16().unmasked());
+    }
+
+    above
+}
@@ -23,8 +23,11 @@ use stm32f4xx_hal::{
     pac::{Peripherals, NVIC},
 };
 
+chip_caps::require!(RNG);
+
 static G_DP: Mutex<RefCell<Option<Peripherals>>> = Mutex::new(RefCell::new(None));
 
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
 #[cortex_m_rt::entry]
 fn main() -> ! {
     rtt_init_print!();
@@ -95,6 +98,7 @@ fn main() -> ! {
     loop {}
 }
 
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
 #[interrupt]
 fn TIM2() {
     cortex_m::interrupt::free(|cs| {
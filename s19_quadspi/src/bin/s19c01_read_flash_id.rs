@@ -89,6 +89,9 @@ use rtt_target::{rprintln, rtt_init_print};
 
 use stm32f4xx_hal::pac::Peripherals;
 
+chip_caps::require!(QUADSPI);
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
 #[cortex_m_rt::entry]
 fn main() -> ! {
     rtt_init_print!();
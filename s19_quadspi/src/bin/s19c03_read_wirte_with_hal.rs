@@ -9,15 +9,19 @@ use rtt_target::{rprintln, rtt_init_print};
 use stm32f4xx_hal::{
     pac::{CorePeripherals, Peripherals},
     prelude::*,
-    qspi::{
-        AddressSize, Bank1, FlashSize, Qspi, QspiConfig, QspiMode, QspiReadCommand,
-        QspiWriteCommand,
-    },
     timer::SysDelay,
 };
+// HAL 只在有 QUADSPI 的芯片上提供 qspi 模块
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use stm32f4xx_hal::qspi::{
+    AddressSize, Bank1, FlashSize, Qspi, QspiConfig, QspiMode, QspiReadCommand, QspiWriteCommand,
+};
 
 use panic_rtt_target as _;
 
+chip_caps::require!(QUADSPI);
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
 #[cortex_m_rt::entry]
 fn main() -> ! {
     rtt_init_print!();
@@ -134,7 +138,7 @@ fn reboot_w25q32(qspi: &mut Qspi<Bank1>, delay: &mut SysDelay) {
         })
         .unwrap();
 
-    delay.delay_ms(50u8);
+    delay.delay_ms(50u32);
 }
 
 // 读取 flash id，若非 W25Q32 则直接 panic
@@ -161,7 +165,7 @@ fn check_w25q32_id(qspi: &mut Qspi<Bank1>) {
 fn wait_w25q32_not_busy(qspi: &mut Qspi<Bank1>, delay: &mut SysDelay) {
     let mut buf = [0u8; 1];
     loop {
-        delay.delay_ms(1u8);
+        delay.delay_ms(1u32);
         qspi.indirect_read(
             QspiReadCommand::new(&mut buf, QspiMode::SingleChannel)
                 .instruction(0x05, QspiMode::SingleChannel),
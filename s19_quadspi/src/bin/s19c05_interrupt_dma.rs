@@ -0,0 +1,290 @@
+//! 用中断与 DMA 完成 QUADSPI 的数据阶段
+//!
+//! 前面几节的读写都是 CPU 忙等完成的，这里用 utils::engine 把数据阶段交给中断或 DMA，
+//! 写入 4 KB 数据，再分别用中断和 DMA 把它们读回来，在等待读取完成的同时，CPU 还能做别的事情（这里只是数一数循环了多少次）
+//!
+//! 具体的原理见 utils::engine
+//!
+//! 实验会擦除 W25Q32 的第 0 个扇区（0x000000 ~ 0x000FFF）
+//! 另外，这里使用的 0x32 与 0x6B 指令都需要开启 quad mode，开启的方法见本章 c02
+//!
+//! 接线图同本章 c01 顶部的说明
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+
+use stm32f4xx_hal::{
+    interrupt,
+    pac::{self, Peripherals, NVIC},
+};
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::{
+    auto_poll,
+    command::{self, run_blocking, w25q, Command, Lines},
+    engine::{self, Buffer, Completion, Transport},
+};
+
+chip_caps::require!(QUADSPI);
+
+const SECTOR_ADDRESS: u32 = 0x00_0000;
+const DATA_LEN: usize = 4096;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Program Start");
+
+    let dp = Peripherals::take().unwrap();
+
+    use_hse(&dp);
+    setup_gpio(&dp);
+    setup_qspi(&dp);
+
+    dp.RCC.ahb1enr.modify(|_, w| w.dma2en().enabled());
+
+    unsafe {
+        NVIC::unmask(command::IRQ);
+        NVIC::unmask(interrupt::DMA2_STREAM7);
+    }
+
+    let qspi = &dp.QUADSPI;
+
+    reboot_w25q32(qspi);
+    check_quad_mode(qspi);
+
+    // DMA 与中断都要在 main 返回之后访问缓冲区，因此缓冲区必须是 'static 的
+    let source = cortex_m::singleton!(: [u8; DATA_LEN] = [0; DATA_LEN]).unwrap();
+    for (i, byte) in source.iter_mut().enumerate() {
+        *byte = (i as u8) ^ (i >> 8) as u8;
+    }
+    let source: &'static [u8] = source;
+
+    rprintln!("erase sector 0x{:06X}", SECTOR_ADDRESS);
+    run_blocking(qspi, &w25q::WRITE_ENABLE, 0, None);
+    run_blocking(qspi, &w25q::SECTOR_ERASE_4K, SECTOR_ADDRESS, None);
+    wait_w25q32_not_busy(qspi);
+
+    // 一次 Page Program 最多写入 256 字节，因此 4 KB 数据要分成 16 页写入，每一页的数据阶段由 DMA 完成
+    rprintln!("program {} bytes with DMA", DATA_LEN);
+    for (page, chunk) in source.chunks(w25q::PAGE_SIZE).enumerate() {
+        let address = SECTOR_ADDRESS + (page * w25q::PAGE_SIZE) as u32;
+
+        run_blocking(qspi, &w25q::WRITE_ENABLE, 0, None);
+        let ticket = engine::start(
+            &dp,
+            &w25q::QUAD_PAGE_PROGRAM,
+            address,
+            Buffer::Write(chunk),
+            Transport::Dma,
+            None,
+        )
+        .unwrap_or_else(|(e, _)| panic!("page program start failed: {:?}", e));
+
+        let completion = ticket.wait();
+        if let Err(e) = completion.result {
+            panic!("page program at 0x{:06X} failed: {:?}", address, e);
+        }
+
+        wait_w25q32_not_busy(qspi);
+    }
+
+    let target = cortex_m::singleton!(: [u8; DATA_LEN] = [0; DATA_LEN]).unwrap();
+
+    // 中断方式读取，读取完成后由回调打印一条消息
+    rprintln!("read back with interrupt");
+    let ticket = engine::start(
+        &dp,
+        &w25q::FAST_READ_QUAD_OUTPUT,
+        SECTOR_ADDRESS,
+        Buffer::Read(target),
+        Transport::Interrupt,
+        Some(on_read_done),
+    )
+    .unwrap_or_else(|(e, _)| panic!("read start failed: {:?}", e));
+
+    // 传输进行期间，CPU 可以做别的事情
+    let mut spin = 0u32;
+    let completion = loop {
+        if let Some(completion) = ticket.poll() {
+            break completion;
+        }
+        spin += 1;
+    };
+    rprintln!("main loop spun {} times while reading", spin);
+    let target = check_read_back(completion, source);
+
+    // 清空缓冲区，再用 DMA 读取一次
+    target.fill(0);
+    rprintln!("read back with DMA");
+    let completion = engine::start(
+        &dp,
+        &w25q::FAST_READ_QUAD_OUTPUT,
+        SECTOR_ADDRESS,
+        Buffer::Read(target),
+        Transport::Dma,
+        Some(on_read_done),
+    )
+    .unwrap_or_else(|(e, _)| panic!("read start failed: {:?}", e))
+    .wait();
+    check_read_back(completion, source);
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+// 传输结束的回调，运行在中断里
+fn on_read_done(completion: &Completion) {
+    rprintln!("[callback] read done: {:?}", completion.result);
+}
+
+// 比较读回的数据，并交还读取缓冲区
+fn check_read_back(completion: Completion, source: &[u8]) -> &'static mut [u8] {
+    if let Err(e) = completion.result {
+        panic!("read failed: {:?}", e);
+    }
+
+    let target = match completion.buffer {
+        Buffer::Read(buf) => buf,
+        Buffer::Write(_) => unreachable!(),
+    };
+
+    match target.iter().zip(source).position(|(a, b)| a != b) {
+        Some(index) => rprintln!(
+            "mismatch at 0x{:04X}: 0x{:02X} != 0x{:02X}",
+            index,
+            target[index],
+            source[index]
+        ),
+        None => rprintln!("{} bytes match", target.len()),
+    }
+
+    target
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn QUADSPI() {
+    engine::on_quadspi_interrupt();
+}
+
+#[cfg(feature = "stm32f412")]
+#[interrupt]
+fn QUAD_SPI() {
+    engine::on_quadspi_interrupt();
+}
+
+#[interrupt]
+fn DMA2_STREAM7() {
+    engine::on_dma_interrupt();
+}
+
+// 执行 0x66 0x99 的 W25Q32 重置命令，重置后需要等待 30 us
+fn reboot_w25q32(qspi: &pac::QUADSPI) {
+    rprintln!("Reboting W25Q32");
+    run_blocking(qspi, &Command::instruction_only(0x66), 0, None);
+    run_blocking(qspi, &Command::instruction_only(0x99), 0, None);
+    // 12 MHz 下，30 us 为 360 个周期
+    cortex_m::asm::delay(360);
+}
+
+// 0x35 读取 SR2，其中第 1 位为 Quad Enable
+fn check_quad_mode(qspi: &pac::QUADSPI) {
+    let mut sr2 = [0u8; 1];
+    run_blocking(
+        qspi,
+        &Command::instruction_only(0x35).with_data(Lines::Single),
+        0,
+        Some(&mut sr2),
+    );
+    if sr2[0] >> 1 & 1 == 0 {
+        panic!("Quad Mode not enabled, run s19c02 first");
+    }
+}
+
+// 等待 W25Q32 完成写入/擦除，轮询由 QUADSPI 的状态标志轮询模式完成，见 utils::auto_poll
+fn wait_w25q32_not_busy(qspi: &pac::QUADSPI) {
+    auto_poll::run_blocking(qspi, &w25q::READ_STATUS_1, &auto_poll::W25Q_NOT_BUSY);
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn setup_qspi(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb3enr.modify(|_, w| w.qspien().disabled());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().reset());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().clear_bit());
+    rcc.ahb3enr.modify(|_, w| w.qspien().enabled());
+
+    let qspi = &dp.QUADSPI;
+
+    // 12 MHz / 2 = 6 MHz
+    qspi.cr.modify(|_, w| unsafe {
+        w.prescaler().bits(2 - 1);
+        w.sshift().set_bit();
+        w
+    });
+
+    qspi.dcr.modify(|_, w| unsafe {
+        // W25Q32 为 4 MB，2^(21 + 1) = 4 MB
+        w.fsize().bits(21);
+        w.ckmode().set_bit();
+        w
+    });
+
+    qspi.cr.modify(|_, w| w.en().set_bit());
+}
+
+fn use_hse(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 配置 quad mode 需要的 6 线 QuadSPI
+fn setup_gpio(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w.gpiocen().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| w.afrl1().af9()); // IO3 /HOLD /RESET
+    gpioa.moder.modify(|_, w| w.moder1().alternate());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| {
+        w.afrl1().af9(); // CLK
+        w.afrl6().af10(); // nCS
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder1().alternate();
+        w.moder6().alternate();
+        w
+    });
+
+    let gpioc = &dp.GPIOC;
+    gpioc.afrh.modify(|_, w| {
+        w.afrh8().af9(); // IO2 /WP
+        w.afrh9().af9(); // IO0
+        w.afrh10().af9(); // IO1
+        w
+    });
+    gpioc.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+}
@@ -0,0 +1,192 @@
+//! 用状态标志轮询模式等待 W25Q32 的擦除
+//!
+//! 擦除一个 64 KB 的块（0xD8），W25Q32 需要 150 ms 左右，最长可达 2 s
+//! c02 中的 wait_w25q32_not_busy 在这段时间里一直由 CPU 发送 0x05 并检查 BUSY 位，
+//! 这里改为 QUADSPI 的状态标志轮询模式：由 QUADSPI 自己不停地读取状态寄存器，BUSY 位变为 0 时触发 SMF 中断
+//!
+//! 第一次擦除时，CPU 在等待期间数一数循环了多少次，用来说明 CPU 是空闲的；
+//! 第二次擦除时，CPU 直接通过 WFI 睡眠，直到 SMF 中断将其唤醒
+//!
+//! 具体的原理见 utils::auto_poll
+//!
+//! 实验会擦除 W25Q32 的第 1 个块（0x010000 ~ 0x01FFFF）
+//!
+//! 接线图同本章 c01 顶部的说明
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+
+use stm32f4xx_hal::{
+    interrupt,
+    pac::{self, Peripherals, NVIC},
+};
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::{
+    auto_poll,
+    command::{self, run_blocking, w25q, Command, Lines},
+};
+
+chip_caps::require!(QUADSPI);
+
+const BLOCK_ADDRESS: u32 = 0x01_0000;
+
+// 64 KB Block Erase
+const BLOCK_ERASE_64K: Command = Command::instruction_only(0xD8).with_address(Lines::Single);
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Program Start");
+
+    let dp = Peripherals::take().unwrap();
+
+    use_hse(&dp);
+    setup_gpio(&dp);
+    setup_qspi(&dp);
+
+    unsafe { NVIC::unmask(command::IRQ) };
+
+    let qspi = &dp.QUADSPI;
+
+    reboot_w25q32(qspi);
+
+    // 第一次擦除：CPU 在等待期间继续工作
+    write_enable(qspi);
+    rprintln!("erase block 0x{:06X}", BLOCK_ADDRESS);
+    run_blocking(qspi, &BLOCK_ERASE_64K, BLOCK_ADDRESS, None);
+
+    let poll = auto_poll::start(qspi, &w25q::READ_STATUS_1, &auto_poll::W25Q_NOT_BUSY);
+    let mut spin = 0u32;
+    let status = loop {
+        if let Some(status) = poll.poll() {
+            break status;
+        }
+        spin += 1;
+    };
+    rprintln!(
+        "erase done, status 0x{:02X}, main loop spun {} times",
+        status,
+        spin
+    );
+
+    // 第二次擦除：CPU 睡眠等待
+    write_enable(qspi);
+    rprintln!("erase block 0x{:06X} again", BLOCK_ADDRESS);
+    run_blocking(qspi, &BLOCK_ERASE_64K, BLOCK_ADDRESS, None);
+
+    let status = auto_poll::start(qspi, &w25q::READ_STATUS_1, &auto_poll::W25Q_NOT_BUSY).wait();
+    rprintln!("erase done, status 0x{:02X}", status);
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+// 发送 Write Enable 后，同样用轮询模式确认 WEL 已经置位，不过这里不需要中断
+fn write_enable(qspi: &pac::QUADSPI) {
+    run_blocking(qspi, &w25q::WRITE_ENABLE, 0, None);
+    auto_poll::run_blocking(qspi, &w25q::READ_STATUS_1, &auto_poll::W25Q_WRITE_ENABLED);
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn QUADSPI() {
+    auto_poll::on_interrupt();
+}
+
+#[cfg(feature = "stm32f412")]
+#[interrupt]
+fn QUAD_SPI() {
+    auto_poll::on_interrupt();
+}
+
+// 执行 0x66 0x99 的 W25Q32 重置命令，重置后需要等待 30 us
+fn reboot_w25q32(qspi: &pac::QUADSPI) {
+    rprintln!("Reboting W25Q32");
+    run_blocking(qspi, &Command::instruction_only(0x66), 0, None);
+    run_blocking(qspi, &Command::instruction_only(0x99), 0, None);
+    // 12 MHz 下，30 us 为 360 个周期
+    cortex_m::asm::delay(360);
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn setup_qspi(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb3enr.modify(|_, w| w.qspien().disabled());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().reset());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().clear_bit());
+    rcc.ahb3enr.modify(|_, w| w.qspien().enabled());
+
+    let qspi = &dp.QUADSPI;
+
+    // 12 MHz / 2 = 6 MHz
+    qspi.cr.modify(|_, w| unsafe {
+        w.prescaler().bits(2 - 1);
+        w.sshift().set_bit();
+        w
+    });
+
+    qspi.dcr.modify(|_, w| unsafe {
+        // W25Q32 为 4 MB，2^(21 + 1) = 4 MB
+        w.fsize().bits(21);
+        w.ckmode().set_bit();
+        w
+    });
+
+    qspi.cr.modify(|_, w| w.en().set_bit());
+}
+
+fn use_hse(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 配置 quad mode 需要的 6 线 QuadSPI
+fn setup_gpio(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w.gpiocen().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| w.afrl1().af9()); // IO3 /HOLD /RESET
+    gpioa.moder.modify(|_, w| w.moder1().alternate());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| {
+        w.afrl1().af9(); // CLK
+        w.afrl6().af10(); // nCS
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder1().alternate();
+        w.moder6().alternate();
+        w
+    });
+
+    let gpioc = &dp.GPIOC;
+    gpioc.afrh.modify(|_, w| {
+        w.afrh8().af9(); // IO2 /WP
+        w.afrh9().af9(); // IO0
+        w.afrh10().af9(); // IO1
+        w
+    });
+    gpioc.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+}
@@ -0,0 +1,335 @@
+//! 双闪存模式：用两片 W25Q32 读写数据
+//!
+//! 两片 W25Q32 分别接在 QUADSPI 的 Bank 1 与 Bank 2 上，在双闪存模式下一起工作，原理见 utils::dual_flash
+//!
+//! 实验流程：
+//!
+//! 1. 在双闪存模式下擦除一个“双倍扇区”（两片 Flash 各自的第 0 个扇区），写入 8 KB 数据
+//! 2. 在双闪存模式下读回 8 KB 数据，并记录耗时
+//! 3. 切换到单闪存模式，分别从两片 Flash 中各读取 4 KB 数据，并记录耗时
+//! 4. 比较单闪存模式读到的数据是否与双闪存模式的数据交错关系相符，并比较耗时
+//!
+//! 两片 Flash 都需要提前开启 quad mode，方法见本章 c02
+//!
+//! 接线图
+//!
+//!                     STM32 <-> W25Q32 #1 / W25Q32 #2
+//!           CLK PB1 (AF 9) <-> CLK                   (脚 6，两片共用)
+//!      BK1_nCS PB6 (AF 10) <-> /CS                   (脚 1，两片共用)
+//!      BK1_IO0  PC9 (AF 9) <-> #1 DI IO0             (脚 5)
+//!      BK1_IO1 PC10 (AF 9) <-> #1 DO IO1             (脚 2)
+//!      BK1_IO2  PC8 (AF 9) <-> #1 /WP IO2            (脚 3)
+//!      BK1_IO3  PA1 (AF 9) <-> #1 /HOLD /RESET IO3   (脚 7)
+//!      BK2_IO0 PA6 (AF 10) <-> #2 DI IO0             (脚 5)
+//!      BK2_IO1 PA7 (AF 10) <-> #2 DO IO1             (脚 2)
+//!      BK2_IO2 PC4 (AF 10) <-> #2 /WP IO2            (脚 3)
+//!      BK2_IO3 PC5 (AF 10) <-> #2 /HOLD /RESET IO3   (脚 7)
+//!
+//! 注：两片 Flash 共用 CLK 与 /CS，走线长度最好差不多
+
+#![no_std]
+#![no_main]
+
+use cortex_m::peripheral::DWT;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+
+use stm32f4xx_hal::{
+    interrupt,
+    pac::{self, Peripherals, NVIC},
+};
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::{
+    auto_poll,
+    command::{self, run_blocking, w25q, Command, Lines},
+    dual_flash::{self, Bank, FlashMode},
+    engine::{self, Buffer, Transport},
+};
+
+chip_caps::require!(QUADSPI);
+
+// W25Q32 为 4 MB，2^(21 + 1) = 4 MB
+const W25Q32_FSIZE: u8 = 21;
+const DATA_LEN: usize = dual_flash::DUAL_SECTOR_SIZE as usize;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Program Start");
+
+    let dp = Peripherals::take().unwrap();
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+
+    // 用 DWT 的周期计数器统计读取的耗时
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    use_hse(&dp);
+    setup_gpio(&dp);
+    setup_qspi(&dp);
+
+    dp.RCC.ahb1enr.modify(|_, w| w.dma2en().enabled());
+
+    unsafe {
+        NVIC::unmask(command::IRQ);
+        NVIC::unmask(interrupt::DMA2_STREAM7);
+    }
+
+    let qspi = &dp.QUADSPI;
+
+    dual_flash::set_flash_mode(qspi, FlashMode::Dual, W25Q32_FSIZE);
+
+    // 双闪存模式下，两片 Flash 会同时收到重置命令
+    reboot_w25q32(qspi);
+    check_quad_mode(qspi);
+
+    let source = cortex_m::singleton!(: [u8; DATA_LEN] = [0; DATA_LEN]).unwrap();
+    for (i, byte) in source.iter_mut().enumerate() {
+        *byte = (i as u8).wrapping_mul(7) ^ (i >> 8) as u8;
+    }
+    let source: &'static [u8] = source;
+
+    // AR 为 0，两片 Flash 收到的地址都是 0
+    rprintln!("erase sector 0 of both chips");
+    write_enable(qspi);
+    run_blocking(qspi, &w25q::SECTOR_ERASE_4K, 0, None);
+    auto_poll::run_blocking(qspi, &w25q::READ_STATUS_1, &dual_flash::W25Q_NOT_BUSY_DUAL);
+
+    rprintln!("program {} bytes in dual-flash mode", DATA_LEN);
+    for (page, chunk) in source.chunks(dual_flash::DUAL_PAGE_SIZE).enumerate() {
+        let address = (page * dual_flash::DUAL_PAGE_SIZE) as u32;
+
+        write_enable(qspi);
+        let completion = engine::start(
+            &dp,
+            &w25q::QUAD_PAGE_PROGRAM,
+            address,
+            Buffer::Write(chunk),
+            Transport::Dma,
+            None,
+        )
+        .unwrap_or_else(|(e, _)| panic!("page program start failed: {:?}", e))
+        .wait();
+        if let Err(e) = completion.result {
+            panic!("page program at 0x{:06X} failed: {:?}", address, e);
+        }
+
+        auto_poll::run_blocking(qspi, &w25q::READ_STATUS_1, &dual_flash::W25Q_NOT_BUSY_DUAL);
+    }
+
+    let dual_buf = cortex_m::singleton!(: [u8; DATA_LEN] = [0; DATA_LEN]).unwrap();
+    let (dual_buf, dual_cycles) = timed_read(&dp, dual_buf, 0);
+    match dual_buf.iter().zip(source).position(|(a, b)| a != b) {
+        Some(index) => rprintln!("dual-flash mismatch at 0x{:04X}", index),
+        None => rprintln!("dual-flash: {} bytes match", DATA_LEN),
+    }
+
+    // 每片 Flash 中应有的数据
+    let mut expect_bank1 = [0u8; DATA_LEN / 2];
+    let mut expect_bank2 = [0u8; DATA_LEN / 2];
+    dual_flash::deinterleave(source, &mut expect_bank1, &mut expect_bank2);
+
+    let (_, sample_address) = dual_flash::locate(0x101);
+    rprintln!(
+        "byte 0x101 in dual-flash mode is byte 0x{:03X} of bank 2",
+        sample_address
+    );
+
+    let mut chip_buf: &mut [u8] =
+        cortex_m::singleton!(: [u8; DATA_LEN / 2] = [0; DATA_LEN / 2]).unwrap();
+    let mut single_cycles = 0;
+    for (bank, expect) in [(Bank::One, &expect_bank1), (Bank::Two, &expect_bank2)] {
+        dual_flash::set_flash_mode(qspi, FlashMode::Single(bank), W25Q32_FSIZE);
+
+        let (buf, cycles) = timed_read(&dp, chip_buf, 0);
+        single_cycles += cycles;
+        match buf.iter().zip(expect.iter()).position(|(a, b)| a != b) {
+            Some(index) => rprintln!("{:?} mismatch at 0x{:04X}", bank, index),
+            None => rprintln!("{:?}: {} bytes match", bank, buf.len()),
+        }
+        chip_buf = buf;
+    }
+
+    rprintln!(
+        "read {} bytes: dual-flash {} cycles, single-flash x2 {} cycles",
+        DATA_LEN,
+        dual_cycles,
+        single_cycles
+    );
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+// 用 DMA 读取整个缓冲区，返回缓冲区与耗费的 CPU 周期数
+fn timed_read(dp: &Peripherals, buf: &'static mut [u8], address: u32) -> (&'static mut [u8], u32) {
+    let begin = DWT::cycle_count();
+    let completion = engine::start(
+        dp,
+        &w25q::FAST_READ_QUAD_OUTPUT,
+        address,
+        Buffer::Read(buf),
+        Transport::Dma,
+        None,
+    )
+    .unwrap_or_else(|(e, _)| panic!("read start failed: {:?}", e))
+    .wait();
+    let cycles = DWT::cycle_count().wrapping_sub(begin);
+
+    if let Err(e) = completion.result {
+        panic!("read failed: {:?}", e);
+    }
+    match completion.buffer {
+        Buffer::Read(buf) => (buf, cycles),
+        Buffer::Write(_) => unreachable!(),
+    }
+}
+
+// 双闪存模式下，两片 Flash 都要确认 WEL 已经置位
+fn write_enable(qspi: &pac::QUADSPI) {
+    run_blocking(qspi, &w25q::WRITE_ENABLE, 0, None);
+    auto_poll::run_blocking(
+        qspi,
+        &w25q::READ_STATUS_1,
+        &dual_flash::W25Q_WRITE_ENABLED_DUAL,
+    );
+}
+
+// 执行 0x66 0x99 的 W25Q32 重置命令，重置后需要等待 30 us
+fn reboot_w25q32(qspi: &pac::QUADSPI) {
+    rprintln!("Reboting W25Q32 x2");
+    run_blocking(qspi, &Command::instruction_only(0x66), 0, None);
+    run_blocking(qspi, &Command::instruction_only(0x99), 0, None);
+    // 12 MHz 下，30 us 为 360 个周期
+    cortex_m::asm::delay(360);
+}
+
+// 0x35 读取 SR2，双闪存模式下会读到两个字节，分别来自两片 Flash
+fn check_quad_mode(qspi: &pac::QUADSPI) {
+    let mut sr2 = [0u8; 2];
+    run_blocking(
+        qspi,
+        &Command::instruction_only(0x35).with_data(Lines::Single),
+        0,
+        Some(&mut sr2),
+    );
+    for (bank, value) in [(Bank::One, sr2[0]), (Bank::Two, sr2[1])] {
+        if value >> 1 & 1 == 0 {
+            panic!("Quad Mode of {:?} not enabled, run s19c02 first", bank);
+        }
+    }
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn QUADSPI() {
+    engine::on_quadspi_interrupt();
+}
+
+#[cfg(feature = "stm32f412")]
+#[interrupt]
+fn QUAD_SPI() {
+    engine::on_quadspi_interrupt();
+}
+
+#[interrupt]
+fn DMA2_STREAM7() {
+    engine::on_dma_interrupt();
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn setup_qspi(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb3enr.modify(|_, w| w.qspien().disabled());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().reset());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().clear_bit());
+    rcc.ahb3enr.modify(|_, w| w.qspien().enabled());
+
+    let qspi = &dp.QUADSPI;
+
+    // 12 MHz / 2 = 6 MHz
+    qspi.cr.modify(|_, w| unsafe {
+        w.prescaler().bits(2 - 1);
+        w.sshift().set_bit();
+        w
+    });
+
+    qspi.dcr.modify(|_, w| unsafe {
+        // 先按单片 W25Q32 设置，切换到双闪存模式时由 set_flash_mode 修改
+        w.fsize().bits(W25Q32_FSIZE);
+        w.ckmode().set_bit();
+        w
+    });
+
+    qspi.cr.modify(|_, w| w.en().set_bit());
+}
+
+fn use_hse(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 配置两片 Flash 需要的 10 根线
+fn setup_gpio(dp: &Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w.gpiocen().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| {
+        w.afrl1().af9(); // BK1_IO3 /HOLD /RESET
+        w.afrl6().af10(); // BK2_IO0
+        w.afrl7().af10(); // BK2_IO1
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder1().alternate();
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w
+    });
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| {
+        w.afrl1().af9(); // CLK
+        w.afrl6().af10(); // nCS
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder1().alternate();
+        w.moder6().alternate();
+        w
+    });
+
+    let gpioc = &dp.GPIOC;
+    gpioc.afrl.modify(|_, w| {
+        w.afrl4().af10(); // BK2_IO2
+        w.afrl5().af10(); // BK2_IO3
+        w
+    });
+    gpioc.afrh.modify(|_, w| {
+        w.afrh8().af9(); // BK1_IO2 /WP
+        w.afrh9().af9(); // BK1_IO0
+        w.afrh10().af9(); // BK1_IO1
+        w
+    });
+    gpioc.moder.modify(|_, w| {
+        w.moder4().alternate();
+        w.moder5().alternate();
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+}
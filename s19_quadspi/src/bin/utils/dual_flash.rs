@@ -0,0 +1,107 @@
+//! QUADSPI 的双闪存模式（dual-flash mode）
+//!
+//! s19c01 中提到过，STM32 的 QUADSPI 可以同时驱动两片 Flash，两片 Flash 共用 CLK，各自拥有 4 根数据线，一共 8 根数据线
+//!
+//! DFM = 0 时为单闪存模式，由 FSEL 决定使用哪一片 Flash（0 为 Bank 1，1 为 Bank 2）
+//! DFM = 1 时为双闪存模式，FSEL 被忽略，两片 Flash 同时工作：
+//!
+//! 1. 指令、地址、交替字节阶段，两片 Flash 收到的内容是一样的
+//! 2. 数据阶段，每个时钟周期传输 8 bit，QUADSPI 以字节为单位交错地访问两片 Flash：
+//!    偶数字节来自/去往 Bank 1，奇数字节来自/去往 Bank 2
+//! 3. 地址阶段发送给 Flash 的地址，是 AR 中地址的一半，因此 AR 中的地址必须是偶数，DLR 中的长度也必须是偶数
+//! 4. FSIZE 要按照两片 Flash 的总容量来设置
+//!
+//! 也就是说，在 QUADSPI 看来，两片 Flash 合成了一片容量翻倍、页与扇区大小也翻倍的 Flash，同样时钟下，数据阶段的速度也翻倍了
+//!
+//! 需要注意的是，读取状态寄存器这类命令，两片 Flash 会各自返回一个字节，因此要读 2 个字节，
+//! 第 0 个字节为 Bank 1 的状态，第 1 个字节为 Bank 2 的状态，轮询时两者都要满足条件才算完成
+//!
+//! 另外，双闪存模式下，两片 Flash 的片选可以共用 BK1_nCS，这里就是这样接线的
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::QUADSPI;
+
+use super::{
+    auto_poll::{MatchMode, PollConfig},
+    command::w25q,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Bank {
+    One,
+    Two,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FlashMode {
+    Single(Bank),
+    Dual,
+}
+
+// 切换单/双闪存模式
+//
+// chip_fsize 为单片 Flash 的 FSIZE，双闪存模式下 FSIZE 会自动加 1
+// DFM、FSEL 与 FSIZE 只能在 QUADSPI 空闲时修改，这里会先关闭 QUADSPI，修改完成后再开启
+pub(crate) fn set_flash_mode(qspi: &QUADSPI, mode: FlashMode, chip_fsize: u8) {
+    while qspi.sr.read().busy().bit_is_set() {}
+    qspi.cr.modify(|_, w| w.en().clear_bit());
+
+    let (dfm, fsel, fsize) = match mode {
+        FlashMode::Single(Bank::One) => (false, false, chip_fsize),
+        FlashMode::Single(Bank::Two) => (false, true, chip_fsize),
+        FlashMode::Dual => (true, false, chip_fsize + 1),
+    };
+
+    qspi.cr.modify(|_, w| {
+        w.dfm().bit(dfm);
+        w.fsel().bit(fsel);
+        w
+    });
+    qspi.dcr.modify(|_, w| unsafe { w.fsize().bits(fsize) });
+
+    qspi.cr.modify(|_, w| w.en().set_bit());
+}
+
+// 双闪存模式下的地址，对应到哪一片 Flash 的哪一个地址
+pub(crate) fn locate(address: u32) -> (Bank, u32) {
+    let bank = if address & 1 == 0 {
+        Bank::One
+    } else {
+        Bank::Two
+    };
+    (bank, address >> 1)
+}
+
+// 把双闪存模式下读到的交错数据，拆分为两片 Flash 各自的数据
+pub(crate) fn deinterleave(data: &[u8], bank1: &mut [u8], bank2: &mut [u8]) {
+    for (i, pair) in data.chunks_exact(2).enumerate() {
+        bank1[i] = pair[0];
+        bank2[i] = pair[1];
+    }
+}
+
+// 双闪存模式下的页大小，一次 Page Program 每片 Flash 写入 256 字节
+pub(crate) const DUAL_PAGE_SIZE: usize = w25q::PAGE_SIZE * 2;
+// 双闪存模式下的扇区大小
+pub(crate) const DUAL_SECTOR_SIZE: u32 = 4096 * 2;
+
+// 两片 W25Q 的 BUSY 位都变为 0
+//
+// AND 模式下，所有参与比较的位都要匹配，因此两个字节的 BUSY 位都为 0 时才匹配
+pub(crate) const W25Q_NOT_BUSY_DUAL: PollConfig = PollConfig {
+    mask: (w25q::STATUS_BUSY as u32) << 8 | w25q::STATUS_BUSY as u32,
+    match_value: 0,
+    match_mode: MatchMode::And,
+    status_len: 2,
+    interval: 0x10,
+};
+
+// 两片 W25Q 的 WEL 位都变为 1
+pub(crate) const W25Q_WRITE_ENABLED_DUAL: PollConfig = PollConfig {
+    mask: 0b10 << 8 | 0b10,
+    match_value: 0b10 << 8 | 0b10,
+    match_mode: MatchMode::And,
+    status_len: 2,
+    interval: 0x10,
+};
@@ -0,0 +1,404 @@
+//! 由中断或 DMA 驱动的 QUADSPI 间接模式传输
+//!
+//! c01 ~ c02 中的读写都是忙等的：CPU 一直轮询 FLEVEL/TCF，在读写几 KB 数据的时候，CPU 什么也做不了
+//! 这里把数据阶段交给中断或者 DMA 来完成，start 只负责写入命令，之后立刻返回一个 Ticket，
+//! CPU 可以去做别的事情，再通过 Ticket 查询或等待结果，也可以在启动时给出一个回调函数，由中断在传输结束时调用
+//!
+//! 中断方式：
+//! 开启 FTIE（FIFO Threshold）、TCIE（Transfer Complete）与 TEIE（Transfer Error）
+//! 读取时，FIFO 中的数据达到阈值就触发中断，在中断里把 FIFO 读空；写入时，FIFO 中的空位达到阈值就触发中断，在中断里把 FIFO 填满
+//! TCF 被置位后，读取时还要把 FIFO 中剩下的数据读出来
+//!
+//! DMA 方式：
+//! 查询 DMA request mapping 可知，QUADSPI 位于 DMA2 的 Stream 7 Channel 3 上
+//! CR 的 DMAEN 置 1 后，QUADSPI 会在 FTF 置位时发出 DMA 请求
+//! 写入时，QUADSPI 把最后一个字节发送出去之后才会置位 TCF，因此以 TCF 作为结束的标志；
+//! 读取时，TCF 置位的时候，最后几个字节还在 FIFO 里，要等 DMA 把它们搬走，因此以 DMA 的 TCIF 作为结束的标志
+//!
+//! 在两个中断里分别调用 on_quadspi_interrupt 与 on_dma_interrupt 即可，它们会通过 Peripherals::steal 访问寄存器
+
+#![allow(dead_code)]
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{Peripherals, QUADSPI};
+
+use super::command::{read_dr_u8, write_ccr, write_dr_u8, Command, FunctionalMode};
+
+// QUADSPI 的 FIFO 深度为 32 字节
+const FIFO_DEPTH: u8 = 32;
+// DMA 一次最多传输 65535 个数据
+const DMA_MAX_LEN: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Transport {
+    Interrupt,
+    Dma,
+}
+
+// 传输用的缓冲区，要跨越中断使用，因此必须是 'static 的
+#[derive(Debug)]
+pub(crate) enum Buffer {
+    Read(&'static mut [u8]),
+    Write(&'static [u8]),
+}
+
+impl Buffer {
+    fn len(&self) -> usize {
+        match self {
+            Buffer::Read(buf) => buf.len(),
+            Buffer::Write(buf) => buf.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EngineError {
+    // 上一次传输还没有结束
+    Busy,
+    // 缓冲区为空，或者超过了 DMA 的单次传输上限
+    Length,
+    // QUADSPI 的 TEF，通常是访问了超出 FSIZE 的地址
+    Transfer,
+    // DMA 的 TEIF，通常是缓冲区位于 DMA 无法访问的内存中（比如 CCM RAM）
+    Dma,
+}
+
+// 传输结束后交还给调用者的结果，缓冲区会一并交还
+#[derive(Debug)]
+pub(crate) struct Completion {
+    pub(crate) buffer: Buffer,
+    pub(crate) result: Result<(), EngineError>,
+}
+
+// 传输结束时，在中断里调用的函数，因此不能太耗时
+pub(crate) type Callback = fn(&Completion);
+
+struct Job {
+    buffer: Buffer,
+    transport: Transport,
+    // 中断方式下，已经读写了多少字节
+    index: usize,
+    callback: Option<Callback>,
+}
+
+enum State {
+    Idle,
+    Running(Job),
+    Done(Completion),
+}
+
+static G_STATE: Mutex<RefCell<State>> = Mutex::new(RefCell::new(State::Idle));
+
+// 一次传输的凭证，只能通过 start 获得
+//
+// 注意 Ticket 被 drop 并不会停止传输，传输结束后，结果会一直保留到下一次 poll
+pub(crate) struct Ticket {
+    _private: (),
+}
+
+impl Ticket {
+    // 传输还没有结束时返回 None
+    pub(crate) fn poll(&self) -> Option<Completion> {
+        cortex_m::interrupt::free(|cs| take_completion(&mut G_STATE.borrow(cs).borrow_mut()))
+    }
+
+    // 等待传输结束，等待期间 CPU 处于睡眠状态
+    //
+    // 检查与 WFI 放在同一个临界区里，防止中断恰好发生在检查之后、WFI 之前，导致 CPU 一直睡下去
+    // 临界区里的 WFI 依旧可以被挂起的中断唤醒，退出临界区后中断会立刻得到处理
+    pub(crate) fn wait(self) -> Completion {
+        loop {
+            let completion = cortex_m::interrupt::free(|cs| {
+                let completion = take_completion(&mut G_STATE.borrow(cs).borrow_mut());
+                if completion.is_none() {
+                    cortex_m::asm::wfi();
+                }
+                completion
+            });
+            if let Some(completion) = completion {
+                return completion;
+            }
+        }
+    }
+}
+
+fn take_completion(state: &mut State) -> Option<Completion> {
+    match core::mem::replace(state, State::Idle) {
+        State::Done(completion) => Some(completion),
+        other => {
+            *state = other;
+            None
+        }
+    }
+}
+
+// 启动一次间接模式的传输
+//
+// QUADSPI、DMA2 的时钟，以及两者的 NVIC 中断需要提前开启
+// 读取还是写入由 buffer 的类型决定，命令的数据阶段不能为 Lines::None
+pub(crate) fn start(
+    dp: &Peripherals,
+    command: &Command,
+    address: u32,
+    buffer: Buffer,
+    transport: Transport,
+    callback: Option<Callback>,
+) -> Result<Ticket, (EngineError, Buffer)> {
+    let len = buffer.len();
+    if len == 0 || (transport == Transport::Dma && len > DMA_MAX_LEN) {
+        return Err((EngineError::Length, buffer));
+    }
+
+    let mode = match buffer {
+        Buffer::Read(_) => FunctionalMode::IndirectRead,
+        Buffer::Write(_) => FunctionalMode::IndirectWrite,
+    };
+
+    // 先占用状态，防止与另一次传输重叠
+    let claimed = cortex_m::interrupt::free(|cs| {
+        let mut state = G_STATE.borrow(cs).borrow_mut();
+        if !matches!(*state, State::Idle) {
+            return Err((EngineError::Busy, buffer));
+        }
+        if transport == Transport::Dma {
+            setup_dma(dp, &buffer);
+        }
+        *state = State::Running(Job {
+            buffer,
+            transport,
+            index: 0,
+            callback,
+        });
+        Ok(())
+    });
+    claimed?;
+
+    let qspi = &dp.QUADSPI;
+
+    match transport {
+        Transport::Interrupt => {
+            qspi.cr.modify(|_, w| unsafe {
+                w.dmaen().clear_bit();
+                // FTHRES + 1 为阈值，读取时 FIFO 中有 16 个字节、写入时 FIFO 中有 16 个空位，就触发一次中断
+                w.fthres().bits(16 - 1);
+                w.ftie().set_bit();
+                w.tcie().set_bit();
+                w.teie().set_bit();
+                w
+            });
+        }
+        Transport::Dma => {
+            qspi.cr.modify(|_, w| unsafe {
+                w.dmaen().set_bit();
+                // DMA 每次搬运 1 个字节，因此每有 1 个字节（或 1 个空位）就发出一次请求
+                w.fthres().bits(0);
+                w.ftie().clear_bit();
+                // 读取时以 DMA 的 TCIF 作为结束标志
+                w.tcie().bit(mode == FunctionalMode::IndirectWrite);
+                w.teie().set_bit();
+                w
+            });
+        }
+    }
+
+    // 写入 CCR/AR 之后，传输就开始了，之后的事情都交给中断
+    write_ccr(qspi, command, mode, address, len);
+
+    Ok(Ticket { _private: () })
+}
+
+// DMA2 Stream 7 Channel 3，按照 buffer 的方向设置好之后直接开启
+fn setup_dma(dp: &Peripherals, buffer: &Buffer) {
+    let dma2 = &dp.DMA2;
+    let st7 = &dma2.st[7];
+
+    if st7.cr.read().en().is_enabled() {
+        st7.cr.modify(|_, w| w.en().disabled());
+        while st7.cr.read().en().is_enabled() {}
+    }
+
+    dma2.hifcr.write(|w| {
+        w.ctcif7().clear();
+        w.chtif7().clear();
+        w.cteif7().clear();
+        w.cdmeif7().clear();
+        w.cfeif7().clear();
+        w
+    });
+
+    let (memory_address, read) = match buffer {
+        Buffer::Read(buf) => (buf.as_ptr() as u32, true),
+        Buffer::Write(buf) => (buf.as_ptr() as u32, false),
+    };
+
+    st7.cr.write(|w| {
+        w.chsel().bits(3);
+        if read {
+            w.dir().peripheral_to_memory();
+        } else {
+            w.dir().memory_to_peripheral();
+        }
+        w.msize().bits8();
+        w.psize().bits8();
+        w.minc().incremented();
+        w.pinc().fixed();
+        // 读取时，以 DMA 的传输完成作为整个传输的结束
+        w.tcie().bit(read);
+        w.teie().enabled();
+        w
+    });
+
+    // 不使用 DMA 的 FIFO，每收到一个请求就直接搬运一个字节
+    st7.fcr.modify(|_, w| w.dmdis().enabled());
+
+    st7.m0ar.write(|w| unsafe { w.bits(memory_address) });
+    st7.par
+        .write(|w| unsafe { w.pa().bits(dp.QUADSPI.dr.as_ptr() as u32) });
+    st7.ndtr.write(|w| w.ndt().bits(buffer.len() as u16));
+
+    st7.cr.modify(|_, w| w.en().enabled());
+}
+
+// 取出正在进行的传输，其它状态保持不变
+fn take_job(state: &mut State) -> Option<Job> {
+    match core::mem::replace(state, State::Idle) {
+        State::Running(job) => Some(job),
+        other => {
+            *state = other;
+            None
+        }
+    }
+}
+
+// 关闭本次传输开启的所有中断与 DMA，并把结果存下来
+fn finish(dp: &Peripherals, job: Job, result: Result<(), EngineError>) -> State {
+    dp.QUADSPI.cr.modify(|_, w| {
+        w.ftie().clear_bit();
+        w.tcie().clear_bit();
+        w.teie().clear_bit();
+        w.dmaen().clear_bit();
+        w
+    });
+    dp.QUADSPI.fcr.write(|w| {
+        w.ctcf().set_bit();
+        w.ctef().set_bit();
+        w
+    });
+
+    if job.transport == Transport::Dma {
+        let st7 = &dp.DMA2.st[7];
+        st7.cr.modify(|_, w| w.en().disabled());
+        while st7.cr.read().en().is_enabled() {}
+    }
+
+    let completion = Completion {
+        buffer: job.buffer,
+        result,
+    };
+    if let Some(callback) = job.callback {
+        callback(&completion);
+    }
+    State::Done(completion)
+}
+
+// 读空 FIFO，或者把 FIFO 填满，返回是否已经处理完所有数据
+fn service_fifo(qspi: &QUADSPI, job: &mut Job) -> bool {
+    match &mut job.buffer {
+        Buffer::Read(buf) => {
+            while job.index < buf.len() && qspi.sr.read().flevel().bits() > 0 {
+                buf[job.index] = read_dr_u8(qspi);
+                job.index += 1;
+            }
+            job.index == buf.len()
+        }
+        Buffer::Write(buf) => {
+            while job.index < buf.len() && qspi.sr.read().flevel().bits() < FIFO_DEPTH {
+                write_dr_u8(qspi, buf[job.index]);
+                job.index += 1;
+            }
+            job.index == buf.len()
+        }
+    }
+}
+
+// 在 QUADSPI 的中断里调用
+pub(crate) fn on_quadspi_interrupt() {
+    let dp = unsafe { Peripherals::steal() };
+    let qspi = &dp.QUADSPI;
+
+    cortex_m::interrupt::free(|cs| {
+        let mut state = G_STATE.borrow(cs).borrow_mut();
+        let job = match &mut *state {
+            State::Running(job) => job,
+            // 没有正在进行的传输，关闭中断，防止反复进入
+            _ => {
+                qspi.cr.modify(|_, w| {
+                    w.ftie().clear_bit();
+                    w.tcie().clear_bit();
+                    w.teie().clear_bit();
+                    w
+                });
+                return;
+            }
+        };
+
+        let sr = qspi.sr.read();
+
+        if sr.tef().bit_is_set() {
+            if let Some(job) = take_job(&mut state) {
+                *state = finish(&dp, job, Err(EngineError::Transfer));
+            }
+            return;
+        }
+
+        if job.transport == Transport::Interrupt {
+            let all_done = service_fifo(qspi, job);
+            // 写入时，所有数据都进入 FIFO 之后，FTF 会一直处于置位状态，要关闭 FTIE，只等 TCF
+            if all_done && matches!(job.buffer, Buffer::Write(_)) {
+                qspi.cr.modify(|_, w| w.ftie().clear_bit());
+            }
+        }
+
+        if sr.tcf().bit_is_set() {
+            // 读取时，TCF 置位之后 FIFO 中可能还有数据
+            if job.transport == Transport::Interrupt {
+                service_fifo(qspi, job);
+            }
+            if let Some(job) = take_job(&mut state) {
+                *state = finish(&dp, job, Ok(()));
+            }
+        }
+    });
+}
+
+// 在 DMA2 Stream 7 的中断里调用
+pub(crate) fn on_dma_interrupt() {
+    let dp = unsafe { Peripherals::steal() };
+    let dma2 = &dp.DMA2;
+
+    let hisr = dma2.hisr.read();
+    dma2.hifcr.write(|w| {
+        w.ctcif7().clear();
+        w.cteif7().clear();
+        w
+    });
+
+    let result = if hisr.teif7().is_error() {
+        Err(EngineError::Dma)
+    } else if hisr.tcif7().is_complete() {
+        Ok(())
+    } else {
+        return;
+    };
+
+    cortex_m::interrupt::free(|cs| {
+        let mut state = G_STATE.borrow(cs).borrow_mut();
+        if let Some(job) = take_job(&mut state) {
+            // DMA 读完最后一个字节时，QUADSPI 可能还在收尾（比如拉高 nCS），等它空闲下来
+            while dp.QUADSPI.sr.read().busy().bit_is_set() {}
+            *state = finish(&dp, job, result);
+        }
+    });
+}
@@ -0,0 +1,11 @@
+// 本章的模块都离不开 QUADSPI，F401/F411 上整个 #[cfg] 掉，用到它们的程序在开头写有 chip_caps::require!，见 chip_caps
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub(crate) mod dual_flash;
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub(crate) mod engine;
+
+// 与 s21 共用的模块，代码在工作区中单独的 crate 里
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+#[allow(unused_imports)]
+pub(crate) use quadspi_core::{auto_poll, command};
@@ -17,6 +17,9 @@ use panic_rtt_target as _;
 use rtt_target::{rprintln, rtt_init_print};
 use stm32f4xx_hal::pac::Peripherals;
 
+chip_caps::require!(DAC);
+
+#[cfg(feature = "stm32f413")]
 #[cortex_m_rt::entry]
 fn main() -> ! {
     rtt_init_print!();
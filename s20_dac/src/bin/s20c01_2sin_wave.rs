@@ -17,6 +17,9 @@ use stm32f4xx_hal::pac;
 mod wave_data;
 use wave_data::COS_WAVE_100 as COS_WAVE;
 
+chip_caps::require!(DAC);
+
+#[cfg(feature = "stm32f413")]
 #[cortex_m_rt::entry]
 fn main() -> ! {
     rtt_init_print!();
@@ -86,6 +89,7 @@ fn setup_gpio(dp: &pac::Peripherals) {
 }
 
 // 启动 DAC
+#[cfg(feature = "stm32f413")]
 fn setup_dac(dp: &pac::Peripherals) {
     dp.RCC.apb1enr.modify(|_, w| w.dacen().enabled());
     dp.DAC.cr.modify(|_, w| w.en1().enabled());
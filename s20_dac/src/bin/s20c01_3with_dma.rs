@@ -22,8 +22,11 @@ use stm32f4xx_hal::{
 mod wave_data;
 use wave_data::COS_WAVE_100 as COS_WAVE;
 
+chip_caps::require!(DAC);
+
 static G_DP: Mutex<RefCell<Option<pac::Peripherals>>> = Mutex::new(RefCell::new(None));
 
+#[cfg(feature = "stm32f413")]
 #[cortex_m_rt::entry]
 fn main() -> ! {
     rtt_init_print!();
@@ -105,6 +108,7 @@ fn setup_gpio(dp: &pac::Peripherals) {
 
 // 设置 DMA
 // 查询 DMA request mapping 可知，DAC 的 channel 1 发出的 dma request 处于 DMA1 的 Stream 5 Channel 7 上
+#[cfg(feature = "stm32f413")]
 fn setup_dma(dp: &pac::Peripherals) {
     dp.RCC.ahb1enr.modify(|_, w| w.dma1en().enabled());
 
@@ -180,6 +184,7 @@ fn setup_dma(dp: &pac::Peripherals) {
 
 // 配置 DAC
 // 主要是配置 DAC 的触发，以及发送 DMA 请求的部分
+#[cfg(feature = "stm32f413")]
 fn setup_dac(dp: &pac::Peripherals) {
     dp.RCC.apb1enr.modify(|_, w| w.dacen().enabled());
 
@@ -216,6 +221,7 @@ fn setup_tim(dp: &pac::Peripherals) {
 }
 
 // 中断处理，主要是打印出现的错误
+#[cfg(feature = "stm32f413")]
 #[interrupt]
 fn TIM6_GLB_IT_DAC1_DAC2() {
     cortex_m::interrupt::free(|cs| {
@@ -0,0 +1,114 @@
+//! 用外接的 MCP4922 SPI DAC 输出电压与波形
+//!
+//! F411 之类的型号没有片上 DAC，这时可以在 SPI 上接一个 MCP4921/MCP4922，驱动见 utils::mcp492x
+//!
+//! 程序分两个阶段：
+//!
+//! 1. 同步更新：A 通道从 0 升到满量程，同时 B 通道从满量程降到 0，每一步先分别写入两个通道，再用 LDAC 同时更新，
+//!    用示波器同时观察两个通道，可以看到两者总是在同一时刻跳变；重复 RAMP_CYCLES 次
+//! 2. 流式输出：TIM3 以 SAMPLE_HZ 的速率触发 DMA，A、B 两个通道的帧交错排列，每个通道的采样率为 SAMPLE_HZ / 2，
+//!    A 输出 WAVE_HZ 的余弦波，B 输出相差 90° 的同频余弦波，接到示波器的 X-Y 模式上是一个圆
+//!    缓冲分成两半，DMA 发送其中一半时，在中断中用 DDS（相位累加器）重新填充另一半，因此频率可以随时修改
+//!
+//! 使用默认的 16 MHz HSI，SPI1 的 SCK 为 8 MHz，一帧 2 us，加上余量，每帧至少需要约 2.5 us
+//!
+//! 接线图：
+//!
+//! STM32 <-> MCP4922
+//!   PA5 <-> SCK
+//!   PA7 <-> SDI
+//!   PA6 <-> CS
+//!   PB0 <-> LDAC
+//!  3.3V <-> VDD、VREFA、VREFB
+//!   GND <-> VSS、AVSS、SHDN 接 VDD
+//!
+//! 输出为 VOUTA 与 VOUTB
+
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+mod wave_data;
+
+use utils::mcp492x::{self, Channel, ChannelConfig, Mcp492x, MAX_VALUE};
+use wave_data::COS_WAVE_100 as COS_WAVE;
+
+const CLK_HZ: u32 = 16_000_000;
+
+const RAMP_STEP: u16 = 16;
+const RAMP_CYCLES: u32 = 20;
+
+const SAMPLE_HZ: u32 = 100_000;
+const WAVE_HZ: u32 = 500;
+// 每次中断填充 STREAM_LEN / 2 帧，即 1 ms
+const STREAM_LEN: usize = 200;
+
+const CONFIG: [ChannelConfig; 2] = [ChannelConfig::DEFAULT; 2];
+
+// DDS 的相位与每个通道采样的相位增量，相位的一整圈为 2^32
+static G_PHASE: AtomicU32 = AtomicU32::new(0);
+static G_PHASE_STEP: AtomicU32 = AtomicU32::new(0);
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    static mut STREAM_BUFFER: [u16; STREAM_LEN] = [0; STREAM_LEN];
+
+    rtt_init_print!();
+    rprintln!("Program Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    // 16 MHz / 2^(0 + 1) = 8 MHz
+    let mut dac = Mcp492x::new(&dp, CONFIG, CLK_HZ, 0);
+
+    rprintln!("synchronized ramp");
+    for _ in 0..RAMP_CYCLES {
+        for value in (0..=MAX_VALUE).step_by(RAMP_STEP as usize) {
+            dac.write_both(value, MAX_VALUE - value);
+        }
+    }
+
+    rprintln!("streaming {} Hz quadrature", WAVE_HZ);
+    set_frequency(WAVE_HZ);
+    fill(STREAM_BUFFER);
+    dac.start_stream(STREAM_BUFFER, CLK_HZ, SAMPLE_HZ).unwrap();
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+fn set_frequency(hz: u32) {
+    // 每个通道的采样率为 SAMPLE_HZ / 2
+    let step = ((hz as u64) << 32) / (SAMPLE_HZ as u64 / 2);
+    G_PHASE_STEP.store(step as u32, Ordering::Relaxed);
+}
+
+// 交错填充 A、B 两个通道的帧，B 比 A 超前 90°
+fn fill(frames: &mut [u16]) {
+    let step = G_PHASE_STEP.load(Ordering::Relaxed);
+    let mut phase = G_PHASE.load(Ordering::Relaxed);
+    for pair in frames.chunks_exact_mut(2) {
+        pair[0] = mcp492x::frame(Channel::A, CONFIG[0], lookup(phase));
+        pair[1] = mcp492x::frame(Channel::B, CONFIG[1], lookup(phase.wrapping_add(1 << 30)));
+        phase = phase.wrapping_add(step);
+    }
+    G_PHASE.store(phase, Ordering::Relaxed);
+}
+
+fn lookup(phase: u32) -> u16 {
+    let index = ((phase >> 16) as usize * COS_WAVE.len()) >> 16;
+    COS_WAVE[index]
+}
+
+#[interrupt]
+fn DMA1_STREAM2() {
+    if let Some(half) = mcp492x::on_dma1_stream2() {
+        mcp492x::fill_half(half, fill);
+    }
+}
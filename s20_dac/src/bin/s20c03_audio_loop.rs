@@ -0,0 +1,234 @@
+//! 音频直通与环路延迟的自测
+//!
+//! ADC1 采集 PA1 上的音频，经过一个可选的低通滤波，再从 DAC 输出，整条通路都由 DMA 搬运，
+//! CPU 只在每半个缓冲（64 个采样，约 1.3 ms）处理一次，见 utils::audio_loop 与 utils::biquad
+//!
+//! HCLK 100 MHz，TIM2 的时钟为 100 MHz，采样率 48 kHz 取整为 2083 个 tick 一个采样，实际约 48.008 kHz
+//! ADCCLK 为 APB2 的 100 MHz 4 分频，25 MHz，不超过 36 MHz 的上限
+//!
+//! 程序每 REPORT_MS 打印一次统计，并在开始时与每 LATENCY_EVERY 次打印之后测量一次环路延迟，
+//! 理论值为 2 * BLOCK + 1 = 129 个采样，测量值会再多一两个采样，来自 DAC 的建立时间与 ADC 的采样时间
+//!
+//! 打开 FILTER 时，低通滤波器本身也有群延迟，测量的结果会变大，脉冲的幅度也会变小
+//! 打开 USE_MCP492X 时，输出换成外接的 MCP4921/4922（接线见 s20c02），延迟中会多出 TIM2 与 TIM3 的相位差
+//!
+//! 接线图：
+//!
+//! 测量延迟时：PA4（DAC_OUT1）直接连到 PA1（ADC1_IN1）
+//! 直通音频时：音频信号经过隔直电容、偏置到 1.65 V 之后接到 PA1，PA4 经过隔直电容接到功放或耳机放大器
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+#[cfg(feature = "stm32f413")]
+use utils::audio_loop::{self, Latency, LoopConfig, Output, BUFFER_LEN};
+use utils::{
+    biquad::Biquad,
+    mcp492x::{self, ChannelConfig, Mcp492x},
+};
+
+chip_caps::require!(DAC);
+
+const HCLK_HZ: u32 = 100_000_000;
+const TIM_CLK_HZ: u32 = 100_000_000;
+const SAMPLE_HZ: u32 = 48_000;
+
+#[cfg(feature = "stm32f413")]
+const CONFIG: LoopConfig = LoopConfig {
+    channel: 1,
+    tim_clk_hz: TIM_CLK_HZ,
+    sample_hz: SAMPLE_HZ,
+};
+
+// 4 kHz 的二阶 Butterworth 低通
+const FILTER: bool = false;
+const CUTOFF_HZ: f32 = 4_000.0;
+
+const USE_MCP492X: bool = false;
+
+const REPORT_MS: u32 = 1000;
+const LATENCY_EVERY: u32 = 10;
+
+#[cfg(feature = "stm32f413")]
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    static mut INPUT: [u16; BUFFER_LEN] = [0; BUFFER_LEN];
+    static mut OUTPUT: [u16; BUFFER_LEN] = [0; BUFFER_LEN];
+    static mut MCP_STREAM: [u16; BUFFER_LEN] = [0; BUFFER_LEN];
+
+    rtt_init_print!();
+    rprintln!("Program Start");
+
+    let mut cp = pac::CorePeripherals::take().unwrap();
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_rcc(&dp);
+    setup_gpio(&dp);
+
+    // 统计处理一块所用的周期
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    let filter =
+        FILTER.then(|| Biquad::lowpass(audio_loop::actual_rate(&CONFIG), CUTOFF_HZ, 0.707));
+
+    let output = if USE_MCP492X {
+        // APB2 100 MHz / 2^(2 + 1) = 12.5 MHz
+        let mut dac = Mcp492x::new(&dp, [ChannelConfig::DEFAULT; 2], HCLK_HZ, 2);
+        let silence = mcp492x::frame(mcp492x::Channel::A, ChannelConfig::DEFAULT, 2048);
+        MCP_STREAM.fill(silence);
+        // 流式输出由 DMA 与中断维持，之后不再需要 dac
+        dac.start_stream(MCP_STREAM, TIM_CLK_HZ, SAMPLE_HZ).unwrap();
+        Output::Mcp492x(ChannelConfig::DEFAULT)
+    } else {
+        Output::Dac(OUTPUT)
+    };
+
+    audio_loop::start(&dp, CONFIG, INPUT, output, filter).unwrap();
+    rprintln!(
+        "loop running at {} Hz, filter: {}",
+        audio_loop::actual_rate(&CONFIG),
+        FILTER
+    );
+
+    audio_loop::start_latency();
+
+    // 延时依靠 HCLK 计数，不需要很准
+    let mut reports = 0u32;
+    loop {
+        cortex_m::asm::delay(HCLK_HZ / 1000 * REPORT_MS);
+
+        let stats = audio_loop::stats();
+        rprintln!(
+            "blocks {} late {} max {} cycles, dma errors {}, adc overruns {}, dac underruns {}",
+            stats.blocks,
+            stats.late,
+            stats.max_cycles,
+            stats.dma_errors,
+            stats.adc_overruns,
+            stats.dac_underruns
+        );
+
+        match audio_loop::latency() {
+            Latency::Done(samples) => {
+                rprintln!(
+                    "round trip latency: {} samples, {} us",
+                    samples,
+                    samples as u64 * 1_000_000 / SAMPLE_HZ as u64
+                );
+            }
+            Latency::Timeout => rprintln!("no impulse detected, is PA4 connected to PA1?"),
+            Latency::Idle | Latency::Quiet { .. } | Latency::Listening { .. } => {}
+        }
+
+        reports += 1;
+        if reports.is_multiple_of(LATENCY_EVERY) {
+            audio_loop::start_latency();
+        }
+    }
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn DMA2_STREAM0() {
+    audio_loop::on_dma2_stream0();
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn ADC() {
+    audio_loop::on_adc();
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn DMA1_STREAM5() {
+    audio_loop::on_dma1_stream5();
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn TIM6_GLB_IT_DAC1_DAC2() {
+    audio_loop::on_dac();
+}
+
+// 外接 MCP492x 时，缓冲由 DMA2_STREAM0 中填充，这里只清除标志
+#[interrupt]
+fn DMA1_STREAM2() {
+    mcp492x::on_dma1_stream2();
+}
+
+// 将 STM32F413 的 HCLK 拉到 100 MHz，与 s20c01_3 相同
+fn setup_rcc(dp: &pac::Peripherals) {
+    // 启动 HSE
+    dp.RCC.cr.modify(|_, w| w.hseon().on());
+    while dp.RCC.cr.read().hserdy().is_not_ready() {}
+
+    // 设置 PLL
+    dp.RCC.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(100)
+        };
+        w.pllp().div2();
+        w
+    });
+
+    // 提高供电电压
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| unsafe { w.vos().bits(0b01) });
+
+    // 启动 PLL
+    dp.RCC.cr.modify(|_, w| w.pllon().on());
+    while dp.PWR.csr.read().vosrdy().bit_is_clear() {}
+    while dp.RCC.cr.read().pllrdy().is_not_ready() {}
+
+    // 设置 Flash 读取延迟
+    dp.FLASH.acr.modify(|_, w| {
+        w.latency().ws3();
+        w.dcen().enabled();
+        w.icen().enabled();
+        w.prften().enabled();
+        w
+    });
+
+    // 配置 APB 分频，APB1 50 MHz，APB1 上的定时器为 100 MHz
+    dp.RCC.cfgr.modify(|_, w| w.ppre1().div2());
+
+    // 切换系统时钟源
+    dp.RCC.cfgr.modify(|_, w| w.sw().pll());
+    while !dp.RCC.cfgr.read().sws().is_pll() {}
+}
+
+// 打开外设时钟，PA1 与 PA4 切换到 analog
+#[cfg(feature = "stm32f413")]
+fn setup_gpio(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.dma1en().enabled();
+        w.dma2en().enabled();
+        w
+    });
+    dp.RCC.apb1enr.modify(|_, w| {
+        w.tim2en().enabled();
+        w.dacen().enabled();
+        w
+    });
+    dp.RCC.apb2enr.modify(|_, w| w.adc1en().enabled());
+
+    // 100 MHz / 4 = 25 MHz
+    dp.ADC_COMMON.ccr.modify(|_, w| w.adcpre().div4());
+
+    dp.GPIOA.moder.modify(|_, w| {
+        w.moder1().analog();
+        w.moder4().analog();
+        w
+    });
+}
@@ -0,0 +1,536 @@
+//! 音频直通：ADC 采集 -> 可选的 biquad 滤波 -> DAC 输出，以及环路延迟的自测
+//!
+//! 采样时钟只有一个：TIM2 的 TRGO 同时触发 ADC1 的转换与 DAC1 的输出，输入与输出的采样率完全相同，不会有漂移
+//!
+//! 数据流：
+//!
+//! - 输入：ADC1 每完成一次转换，DMA2 Stream 0 Channel 0 把结果写入输入缓冲，循环模式，缓冲分为两半，每半 BLOCK 个采样
+//! - 处理：输入缓冲的一半写满时（半传输、传输完成中断），在 on_dma2_stream0 中处理这一半，结果写入输出缓冲中对应的那一半
+//! - 输出：两种方式，见 Output
+//!
+//! 两个 DMA 同时启动、步调一致，输入的第 k 半写满时，输出的 DMA 正好开始读取另一半，
+//! 因此处理必须在 BLOCK 个采样周期之内完成，第 n 个输入采样会在第 n + 2 * BLOCK 个采样周期输出，
+//! 再加上 DAC 本身的一个采样（触发时输出的是上一次 DMA 写入的值），理论上的环路延迟为 2 * BLOCK + 1 个采样
+//! 处理结束时，如果输出的 DMA 已经在读取刚刚写入的那一半，说明处理太慢了，记为一次 late
+//!
+//! 延迟自测（start_latency）：把 DAC 的输出直接接到 ADC 的输入上，先输出一段静音，测量输入的基线与噪声，
+//! 然后输出一个脉冲，并记下它在采样序号中的位置，之后在输入中找到第一个明显偏离基线的采样，
+//! 两者序号的差就是整个环路（缓冲、DAC、模拟通路、ADC）的延迟，结果见 latency
+//! 测量期间直通被静音，否则环路会把脉冲一遍遍地转回来
+//!
+//! 采样值在 ADC、DAC 两侧都是 12 位无符号数，处理时减去中点 2048，按有符号的 f32 计算
+//!
+//! 没有实现 I2S 输出：I2S 的位时钟来自 PLLI2S，与 TIM2 不是同一个时钟，输入与输出的采样率会有细微的差别，
+//! 缓冲迟早会溢出或读空，需要额外的采样率匹配（见 s13c10），这里只保留同一个时钟驱动的两种输出
+
+#![allow(dead_code)]
+
+use core::cell::RefCell;
+
+use cortex_m::{interrupt::Mutex, peripheral::DWT};
+use stm32f4xx_hal::pac::{self, interrupt, Peripherals, NVIC};
+
+use super::{
+    biquad::Biquad,
+    mcp492x::{self, Channel, ChannelConfig, Half},
+};
+
+// 每一半缓冲的采样数，越小延迟越低，但中断越频繁，处理的时间余量也越少
+pub(crate) const BLOCK: usize = 64;
+pub(crate) const BUFFER_LEN: usize = 2 * BLOCK;
+
+const MID: f32 = 2048.0;
+const MAX: f32 = 4095.0;
+
+// 延迟自测的参数
+const QUIET_SAMPLES: u32 = 4 * BUFFER_LEN as u32;
+const IMPULSE_SAMPLES: u32 = 4;
+const IMPULSE_AMPLITUDE: f32 = 1500.0;
+// 检测阈值至少为这么多 LSB，或者噪声峰值的 4 倍
+const MIN_THRESHOLD: f32 = 200.0;
+// 发出脉冲之后这么多个采样还没有检测到，就认为环路没有接上
+const LISTEN_SAMPLES: u32 = 16 * BUFFER_LEN as u32;
+
+pub(crate) enum Output {
+    // 片上 DAC1（PA4），同样由 TIM2 的 TRGO 触发，DMA1 Stream 5 Channel 7 从这个缓冲中读取
+    Dac(&'static mut [u16; BUFFER_LEN]),
+    // 外接的 MCP4921/4922 的 A 通道（见 utils::mcp492x），需要在 start 之前以相同的采样率、
+    // 长度为 BUFFER_LEN 的缓冲调用 Mcp492x::start_stream，TIM3 与 TIM2 的时钟与周期相同，同样不会漂移，
+    // 只是两者的相位取决于启动的先后；DMA1_STREAM2 中断中仍然需要调用 mcp492x::on_dma1_stream2 清除标志
+    Mcp492x(ChannelConfig),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LoopConfig {
+    // ADC1 的通道，只支持 0 ~ 9
+    pub(crate) channel: u8,
+    pub(crate) tim_clk_hz: u32,
+    pub(crate) sample_hz: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LoopError {
+    BadChannel,
+    // 采样率太高，或者太低，TIM2 的周期超出范围
+    BadRate,
+    Running,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Stats {
+    // 处理过的块数
+    pub(crate) blocks: u32,
+    // 处理没有在输出读到之前完成的块数
+    pub(crate) late: u32,
+    // 处理一块所用的最长 CPU 周期，需要事先打开 DWT 的 CYCCNT
+    pub(crate) max_cycles: u32,
+    pub(crate) dma_errors: u32,
+    pub(crate) adc_overruns: u32,
+    pub(crate) dac_underruns: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Latency {
+    Idle,
+    // 输出静音，统计输入的基线
+    Quiet { until: u32 },
+    // 已经发出脉冲，等待它从输入回来
+    Listening { emitted_at: u32 },
+    // 环路的延迟，单位为采样
+    Done(u32),
+    // 没有检测到脉冲
+    Timeout,
+}
+
+struct State {
+    input: &'static mut [u16; BUFFER_LEN],
+    output: Output,
+    filter: Option<Biquad>,
+    // 输入采样的序号，只会增加，回绕需要连续运行一天以上
+    index: u32,
+    stats: Stats,
+    latency: Latency,
+    baseline_sum: f32,
+    baseline_count: u32,
+    baseline: f32,
+    threshold: f32,
+    noise_min: f32,
+    noise_max: f32,
+}
+
+static G_STATE: Mutex<RefCell<Option<State>>> = Mutex::new(RefCell::new(None));
+
+// ADC1、DMA1/2、TIM2 的时钟，DAC 的时钟，以及 GPIO 的 analog 模式需要提前设置好
+pub(crate) fn start(
+    dp: &Peripherals,
+    config: LoopConfig,
+    input: &'static mut [u16; BUFFER_LEN],
+    output: Output,
+    filter: Option<Biquad>,
+) -> Result<(), LoopError> {
+    if config.channel > 9 {
+        return Err(LoopError::BadChannel);
+    }
+    let period = config.tim_clk_hz / config.sample_hz;
+    if period < 100 {
+        return Err(LoopError::BadRate);
+    }
+
+    let input_ptr = input.as_mut_ptr();
+    let dac_ptr = match &output {
+        Output::Dac(buffer) => Some(buffer.as_ptr()),
+        Output::Mcp492x(_) => None,
+    };
+
+    cortex_m::interrupt::free(|cs| {
+        let mut state = G_STATE.borrow(cs).borrow_mut();
+        if state.is_some() {
+            return Err(LoopError::Running);
+        }
+        // 一开始输出中点，也就是静音
+        let mut output = output;
+        if let Output::Dac(buffer) = &mut output {
+            buffer.fill(MID as u16);
+        }
+        state.replace(State {
+            input,
+            output,
+            filter,
+            index: 0,
+            stats: Stats::default(),
+            latency: Latency::Idle,
+            baseline_sum: 0.0,
+            baseline_count: 0,
+            baseline: 0.0,
+            threshold: MIN_THRESHOLD,
+            noise_min: 0.0,
+            noise_max: 0.0,
+        });
+        Ok(())
+    })?;
+
+    setup_tim2(dp, period);
+    setup_adc(dp, config.channel);
+    setup_adc_dma(dp, input_ptr);
+    if let Some(buffer) = dac_ptr {
+        setup_dac(dp);
+        setup_dac_dma(dp, buffer);
+    }
+
+    unsafe {
+        NVIC::unmask(interrupt::DMA2_STREAM0);
+        NVIC::unmask(interrupt::ADC);
+    }
+
+    dp.TIM2.cnt.reset();
+    dp.TIM2.cr1.modify(|_, w| w.cen().enabled());
+    Ok(())
+}
+
+// 实际的采样率，TIM2 的周期是取整之后的
+pub(crate) fn actual_rate(config: &LoopConfig) -> f32 {
+    config.tim_clk_hz as f32 / (config.tim_clk_hz / config.sample_hz) as f32
+}
+
+// 更换滤波器，None 时直接直通
+pub(crate) fn set_filter(filter: Option<Biquad>) {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(state) = G_STATE.borrow(cs).borrow_mut().as_mut() {
+            state.filter = filter;
+        }
+    });
+}
+
+pub(crate) fn stats() -> Stats {
+    cortex_m::interrupt::free(|cs| {
+        G_STATE
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map(|state| state.stats)
+            .unwrap_or_default()
+    })
+}
+
+// 开始一次延迟自测，之前的结果会被清除
+pub(crate) fn start_latency() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(state) = G_STATE.borrow(cs).borrow_mut().as_mut() {
+            state.latency = Latency::Quiet {
+                until: state.index.wrapping_add(QUIET_SAMPLES),
+            };
+            state.baseline_sum = 0.0;
+            state.baseline_count = 0;
+            state.noise_min = f32::MAX;
+            state.noise_max = f32::MIN;
+        }
+    });
+}
+
+pub(crate) fn latency() -> Latency {
+    cortex_m::interrupt::free(|cs| {
+        G_STATE
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map_or(Latency::Idle, |state| state.latency)
+    })
+}
+
+// 在 DMA2_STREAM0 中断中调用，处理刚刚写满的那一半输入
+pub(crate) fn on_dma2_stream0() {
+    let dma2 = unsafe { &*pac::DMA2::ptr() };
+    let lisr = dma2.lisr.read();
+
+    if lisr.teif0().is_error() || lisr.feif0().is_error() {
+        dma2.lifcr.write(|w| {
+            w.cteif0().clear();
+            w.cfeif0().clear();
+            w
+        });
+        with_stats(|stats| stats.dma_errors += 1);
+    }
+
+    let half = if lisr.htif0().is_half() {
+        dma2.lifcr.write(|w| w.chtif0().clear());
+        Half::First
+    } else if lisr.tcif0().is_complete() {
+        dma2.lifcr.write(|w| w.ctcif0().clear());
+        Half::Second
+    } else {
+        return;
+    };
+
+    let start = DWT::cycle_count();
+    cortex_m::interrupt::free(|cs| {
+        if let Some(state) = G_STATE.borrow(cs).borrow_mut().as_mut() {
+            state.process(half);
+            state.stats.blocks += 1;
+            let cycles = DWT::cycle_count().wrapping_sub(start);
+            state.stats.max_cycles = state.stats.max_cycles.max(cycles);
+            if output_half(&state.output) == Some(half) {
+                state.stats.late += 1;
+            }
+        }
+    });
+}
+
+// 在 ADC 中断中调用，ADC 在 DMA 来不及读取时会置位 OVR，并停止 DMA 请求，需要重新开启
+pub(crate) fn on_adc() {
+    let adc = unsafe { &*pac::ADC1::ptr() };
+    if adc.sr.read().ovr().bit_is_set() {
+        adc.cr2.modify(|_, w| w.dma().disabled());
+        adc.sr.modify(|_, w| w.ovr().clear_bit());
+        adc.cr2.modify(|_, w| w.dma().enabled());
+        with_stats(|stats| stats.adc_overruns += 1);
+    }
+}
+
+// 在 TIM6_GLB_IT_DAC1_DAC2 中断中调用
+pub(crate) fn on_dac() {
+    let dac = unsafe { &*pac::DAC::ptr() };
+    if dac.sr.read().dmaudr1().is_underrun() {
+        dac.sr.modify(|_, w| w.dmaudr1().no_underrun());
+        with_stats(|stats| stats.dac_underruns += 1);
+    }
+}
+
+// 在 DMA1_STREAM5 中断中调用，只统计错误
+pub(crate) fn on_dma1_stream5() {
+    let dma1 = unsafe { &*pac::DMA1::ptr() };
+    let hisr = dma1.hisr.read();
+    if hisr.teif5().is_error() || hisr.feif5().is_error() {
+        dma1.hifcr.write(|w| {
+            w.cteif5().clear();
+            w.cfeif5().clear();
+            w
+        });
+        with_stats(|stats| stats.dma_errors += 1);
+    }
+}
+
+fn with_stats(f: impl FnOnce(&mut Stats)) {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(state) = G_STATE.borrow(cs).borrow_mut().as_mut() {
+            f(&mut state.stats);
+        }
+    });
+}
+
+impl State {
+    fn process(&mut self, half: Half) {
+        let range = match half {
+            Half::First => 0..BLOCK,
+            Half::Second => BLOCK..BUFFER_LEN,
+        };
+
+        // 先把这半个缓冲复制出来，下面的 probe 需要可变地借用 self
+        let mut block = [0u16; BLOCK];
+        block.copy_from_slice(&self.input[range.clone()]);
+        for out in block.iter_mut() {
+            let x = *out as f32 - MID;
+            let y = match self.filter.as_mut() {
+                Some(filter) => filter.process(x),
+                None => x,
+            };
+            let y = self.probe(x, y);
+            *out = (y + MID).clamp(0.0, MAX) as u16;
+            self.index = self.index.wrapping_add(1);
+        }
+
+        match &mut self.output {
+            Output::Dac(buffer) => buffer[range].copy_from_slice(&block),
+            Output::Mcp492x(config) => {
+                let config = *config;
+                mcp492x::fill_half(half, |frames| {
+                    for (frame, &value) in frames.iter_mut().zip(block.iter()) {
+                        *frame = mcp492x::frame(Channel::A, config, value);
+                    }
+                });
+            }
+        }
+    }
+
+    // 延迟自测的状态机，x 为当前的输入，y 为处理之后的输出，返回实际要输出的值
+    fn probe(&mut self, x: f32, y: f32) -> f32 {
+        let index = self.index;
+        match self.latency {
+            Latency::Idle | Latency::Done(_) | Latency::Timeout => y,
+            Latency::Quiet { until } => {
+                // 前一半时间等环路中残留的信号消失，后一半时间统计基线与噪声
+                if until.wrapping_sub(index) < QUIET_SAMPLES / 2 {
+                    self.baseline_sum += x;
+                    self.baseline_count += 1;
+                    self.noise_min = self.noise_min.min(x);
+                    self.noise_max = self.noise_max.max(x);
+                }
+                if index == until {
+                    self.baseline = self.baseline_sum / self.baseline_count.max(1) as f32;
+                    self.threshold = (4.0 * (self.noise_max - self.noise_min)).max(MIN_THRESHOLD);
+                    self.latency = Latency::Listening { emitted_at: index };
+                    return IMPULSE_AMPLITUDE;
+                }
+                0.0
+            }
+            Latency::Listening { emitted_at } => {
+                let elapsed = index.wrapping_sub(emitted_at);
+                if elapsed > 0 && (x - self.baseline).abs() > self.threshold {
+                    self.latency = Latency::Done(elapsed);
+                } else if elapsed > LISTEN_SAMPLES {
+                    self.latency = Latency::Timeout;
+                }
+                if elapsed < IMPULSE_SAMPLES {
+                    IMPULSE_AMPLITUDE
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+// 输出的 DMA 当前正在读取的那一半
+fn output_half(output: &Output) -> Option<Half> {
+    let dma1 = unsafe { &*pac::DMA1::ptr() };
+    let remaining = match output {
+        Output::Dac(_) => dma1.st[5].ndtr.read().ndt().bits(),
+        Output::Mcp492x(_) => dma1.st[2].ndtr.read().ndt().bits(),
+    } as usize;
+    if remaining == 0 {
+        return None;
+    }
+    Some(if BUFFER_LEN - remaining < BLOCK {
+        Half::First
+    } else {
+        Half::Second
+    })
+}
+
+fn setup_tim2(dp: &Peripherals, period: u32) {
+    let tim = &dp.TIM2;
+    tim.cr1.modify(|_, w| w.cen().disabled());
+    tim.psc.write(|w| w.psc().bits(0));
+    tim.arr.write(|w| w.arr().bits(period - 1));
+    // 每次溢出在 TRGO 上输出一个脉冲，同时触发 ADC 与 DAC
+    tim.cr2.modify(|_, w| w.mms().update());
+    tim.egr.write(|w| w.ug().update());
+}
+
+fn setup_adc(dp: &Peripherals, channel: u8) {
+    let adc = &dp.ADC1;
+
+    adc.sqr3.modify(|_, w| unsafe { w.sq1().bits(channel) });
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+
+    // 84 个周期的采样时间，25 MHz 的 ADCCLK 下一次转换约 3.8 us，对音频来说绰绰有余，输入阻抗也可以高一些
+    let shift = 3 * channel as u32;
+    adc.smpr2
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << shift) | 0b100 << shift) });
+
+    adc.cr1.modify(|_, w| w.ovrie().enabled());
+    adc.cr2.modify(|_, w| {
+        w.extsel().tim2trgo();
+        w.exten().rising_edge();
+        // DMA 循环模式下，DDS 必须置位，否则 DMA 的第一轮结束后 ADC 就不再发出请求了
+        w.dds().continuous();
+        w.dma().enabled();
+        w.adon().enabled();
+        w
+    });
+}
+
+// DMA2 Stream 0 Channel 0 为 ADC1，半传输与传输完成时中断
+fn setup_adc_dma(dp: &Peripherals, buffer: *mut u16) {
+    let dma2 = &dp.DMA2;
+    let st = &dma2.st[0];
+
+    if st.cr.read().en().is_enabled() {
+        st.cr.modify(|_, w| w.en().disabled());
+        while st.cr.read().en().is_enabled() {}
+    }
+
+    dma2.lifcr.write(|w| {
+        w.ctcif0().clear();
+        w.chtif0().clear();
+        w.cteif0().clear();
+        w.cdmeif0().clear();
+        w.cfeif0().clear();
+        w
+    });
+
+    st.cr.write(|w| {
+        w.chsel().bits(0);
+        w.pl().very_high();
+        w.dir().peripheral_to_memory();
+        w.msize().bits16();
+        w.psize().bits16();
+        w.minc().incremented();
+        w.pinc().fixed();
+        w.circ().enabled();
+        w.htie().enabled();
+        w.tcie().enabled();
+        w.teie().enabled();
+        w
+    });
+    st.par
+        .write(|w| unsafe { w.pa().bits(dp.ADC1.dr.as_ptr() as u32) });
+    st.m0ar.write(|w| unsafe { w.m0a().bits(buffer as u32) });
+    st.ndtr.write(|w| w.ndt().bits(BUFFER_LEN as u16));
+
+    st.cr.modify(|_, w| w.en().enabled());
+}
+
+// 与 s20c01_3 相同，TIM2 的 TRGO 把 DHR 转移到 DOR，同时发出 DMA 请求写入下一个值
+fn setup_dac(dp: &Peripherals) {
+    let dac = &dp.DAC;
+    dac.dhr12r1.write(|w| w.dacc1dhr().bits(MID as u16));
+    dac.cr.modify(|_, w| {
+        w.tsel1().tim2_trgo();
+        w.ten1().enabled();
+        w.dmaen1().enabled();
+        w.dmaudrie1().enabled();
+        w.en1().enabled();
+        w
+    });
+    unsafe { NVIC::unmask(interrupt::TIM6_GLB_IT_DAC1_DAC2) };
+}
+
+// DMA1 Stream 5 Channel 7 为 DAC1，只打开错误中断，缓冲的填充由输入一侧的中断完成
+fn setup_dac_dma(dp: &Peripherals, buffer: *const u16) {
+    let dma1 = &dp.DMA1;
+    let st = &dma1.st[5];
+
+    if st.cr.read().en().is_enabled() {
+        st.cr.modify(|_, w| w.en().disabled());
+        while st.cr.read().en().is_enabled() {}
+    }
+
+    dma1.hifcr.write(|w| {
+        w.ctcif5().clear();
+        w.chtif5().clear();
+        w.cteif5().clear();
+        w.cdmeif5().clear();
+        w.cfeif5().clear();
+        w
+    });
+
+    st.cr.write(|w| {
+        w.chsel().bits(7);
+        w.pl().very_high();
+        w.dir().memory_to_peripheral();
+        w.msize().bits16();
+        w.psize().bits16();
+        w.minc().incremented();
+        w.pinc().fixed();
+        w.circ().enabled();
+        w.teie().enabled();
+        w
+    });
+    st.par
+        .write(|w| unsafe { w.pa().bits(dp.DAC.dhr12r1.as_ptr() as u32) });
+    st.m0ar.write(|w| unsafe { w.m0a().bits(buffer as u32) });
+    st.ndtr.write(|w| w.ndt().bits(BUFFER_LEN as u16));
+
+    st.cr.modify(|_, w| w.en().enabled());
+    unsafe { NVIC::unmask(interrupt::DMA1_STREAM5) };
+}
@@ -0,0 +1,114 @@
+//! 二阶 IIR 滤波器（biquad）
+//!
+//! 系数按照 RBJ 的 Audio EQ Cookbook 计算，以 a0 归一化之后：
+//!
+//! y[n] = b0 * x[n] + b1 * x[n-1] + b2 * x[n-2] - a1 * y[n-1] - a2 * y[n-2]
+//!
+//! 这里使用转置直接 II 型（Transposed Direct Form II）实现，只需要保存两个状态 z1、z2，
+//! 每个采样 5 次乘法、4 次加法，在 Cortex-M4F 上只需要几十个周期
+//!
+//! 计算系数需要 sin/cos，这里没有引入 libm，而是用泰勒展开算出 (0, π) 之间的 sin/cos，精度在 1e-6 左右，
+//! 对于计算滤波器系数来说足够了，系数只在创建滤波器时计算一次
+
+#![allow(dead_code)]
+
+use core::f32::consts::{FRAC_PI_2, PI};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    // 二阶低通，q 为 0.707 时是 Butterworth 响应
+    pub(crate) fn lowpass(sample_hz: f32, cutoff_hz: f32, q: f32) -> Self {
+        let (sin, cos) = sin_cos(omega(sample_hz, cutoff_hz));
+        let alpha = sin / (2.0 * q);
+        let b1 = 1.0 - cos;
+        Self::normalized(b1 / 2.0, b1, b1 / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    // 二阶高通，比如去掉 ADC 输入上的直流偏置
+    pub(crate) fn highpass(sample_hz: f32, cutoff_hz: f32, q: f32) -> Self {
+        let (sin, cos) = sin_cos(omega(sample_hz, cutoff_hz));
+        let alpha = sin / (2.0 * q);
+        let b1 = -(1.0 + cos);
+        Self::normalized(
+            -b1 / 2.0,
+            b1,
+            -b1 / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    // 陷波，比如去掉 50 Hz 的工频干扰，q 越大凹口越窄
+    pub(crate) fn notch(sample_hz: f32, center_hz: f32, q: f32) -> Self {
+        let (sin, cos) = sin_cos(omega(sample_hz, center_hz));
+        let alpha = sin / (2.0 * q);
+        Self::normalized(1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    fn normalized(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub(crate) fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+}
+
+// 数字角频率，限制在 (0, π) 之内，超过奈奎斯特频率的设置没有意义
+fn omega(sample_hz: f32, hz: f32) -> f32 {
+    (2.0 * PI * hz / sample_hz).clamp(1e-4, PI - 1e-4)
+}
+
+// x 在 [0, π] 之内，超过 π/2 的部分利用 sin(π - x) = sin(x)、cos(π - x) = -cos(x) 折回来，
+// 泰勒展开只需要覆盖 [0, π/2]
+fn sin_cos(x: f32) -> (f32, f32) {
+    if x > FRAC_PI_2 {
+        let (sin, cos) = sin_cos_small(PI - x);
+        (sin, -cos)
+    } else {
+        sin_cos_small(x)
+    }
+}
+
+fn sin_cos_small(x: f32) -> (f32, f32) {
+    let x2 = x * x;
+    // 展开到 x^11 与 x^12，在 π/2 处的误差小于 1e-6
+    let sin = x
+        * (1.0
+            - x2 / 6.0
+                * (1.0 - x2 / 20.0 * (1.0 - x2 / 42.0 * (1.0 - x2 / 72.0 * (1.0 - x2 / 110.0)))));
+    let cos = 1.0
+        - x2 / 2.0
+            * (1.0
+                - x2 / 12.0
+                    * (1.0
+                        - x2 / 30.0 * (1.0 - x2 / 56.0 * (1.0 - x2 / 90.0 * (1.0 - x2 / 132.0)))));
+    (sin, cos)
+}
@@ -0,0 +1,412 @@
+//! MCP4921（单通道）/MCP4922（双通道）12 bit SPI DAC
+//!
+//! F411 之类的型号没有片上 DAC，外接一个 SPI DAC 是最简单的替代方案
+//!
+//! 每次写入为一个 16 bit 的帧，高位在前：
+//!
+//! | bit | 15  | 14  | 13  | 12   | 11 ~ 0 |
+//! | --- | --- | --- | --- | ---- | ------ |
+//! |     | A/B | BUF | GA  | SHDN | D11~D0 |
+//!
+//! - A/B：0 写入通道 A，1 写入通道 B（MCP4921 只有 A）
+//! - BUF：1 时 VREF 输入经过缓冲，VREF 可以接高阻的分压电路
+//! - GA：0 为 2 倍增益，1 为 1 倍增益，输出 = VREF * D / 4096 * 增益，不会超过 VDD
+//! - SHDN：0 时该通道关闭，输出变为约 500k 下拉
+//!
+//! CS 拉低之后开始接收，CS 的上升沿把这 16 bit 写入该通道的输入寄存器；
+//! LDAC 的下降沿把两个通道的输入寄存器同时转移到输出，LDAC 一直为低时，CS 的上升沿就会立即更新输出
+//! 因此，先分别写入 A、B，再给 LDAC 一个低脉冲，两个通道就能在同一时刻变化（write_both）
+//!
+//! 流式输出（start_stream）不需要 CPU 参与每一个采样：
+//!
+//! - TIM3 每个周期产生一个更新事件，触发 DMA1 Stream 2（Channel 5，TIM3_UP），把缓冲中的下一帧写入 SPI1 的 DR
+//! - CS 不再由 GPIO 控制，而是 TIM3_CH1 的 PWM mode 2 输出：每个周期的前 cs_low_ticks 为低，SPI 在这段时间发送完 16 bit，
+//!   之后变高，上升沿锁存这一帧
+//! - LDAC 保持为低，每一帧在 CS 上升沿立即输出
+//! - DMA 为循环模式，半传输、传输完成中断中（on_dma1_stream2），调用者重新填充刚刚发送完的那一半
+//!
+//! 双通道的流式输出把 A、B 两个通道的帧交错放在缓冲中即可，两个通道相差一个 TIM3 周期
+//!
+//! SPI 使用单线只发送模式（BIDIMODE + BIDIOE），没有接收，也就不会有 OVR
+//! MCP492x 支持 Mode 0 与 Mode 3，SCK 最高 20 MHz
+
+#![allow(dead_code)]
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{self, interrupt, Peripherals, NVIC};
+
+pub(crate) const MAX_VALUE: u16 = 0x0FFF;
+
+// CS 的最短高电平时间为 15 ns，这里留出 TIM3 的几个周期
+const CS_HIGH_MIN_TICKS: u32 = 4;
+// 从更新事件到 DMA 写入 DR、SPI 开始发送，留出的余量
+const DMA_LATENCY_NS: u32 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Channel {
+    A,
+    B,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Gain {
+    X1,
+    X2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ChannelConfig {
+    pub(crate) buffered: bool,
+    pub(crate) gain: Gain,
+}
+
+impl ChannelConfig {
+    pub(crate) const DEFAULT: Self = Self {
+        buffered: false,
+        gain: Gain::X1,
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StreamError {
+    // 采样率太高，一个 TIM3 周期内发送不完 16 bit
+    RateTooHigh,
+    // 缓冲长度为 0、为奇数，或者超过了 DMA 的 65535
+    BufferLength,
+    Running,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Half {
+    First,
+    Second,
+}
+
+// 组装一个 16 bit 的帧，value 超过 12 bit 的部分被截掉
+pub(crate) const fn frame(channel: Channel, config: ChannelConfig, value: u16) -> u16 {
+    let mut word = value & MAX_VALUE;
+    if let Channel::B = channel {
+        word |= 1 << 15;
+    }
+    if config.buffered {
+        word |= 1 << 14;
+    }
+    if let Gain::X1 = config.gain {
+        word |= 1 << 13;
+    }
+    // SHDN 为 1 时通道工作
+    word | 1 << 12
+}
+
+// SHDN 为 0，关闭该通道
+pub(crate) const fn shutdown_frame(channel: Channel) -> u16 {
+    match channel {
+        Channel::A => 0,
+        Channel::B => 1 << 15,
+    }
+}
+
+static G_STREAM: Mutex<RefCell<Option<&'static mut [u16]>>> = Mutex::new(RefCell::new(None));
+
+pub(crate) struct Mcp492x {
+    spi: &'static pac::spi1::RegisterBlock,
+    config: [ChannelConfig; 2],
+    pclk2_hz: u32,
+    br: u8,
+}
+
+impl Mcp492x {
+    // br 为 SPI1 CR1 的 BR，SCK = pclk2_hz / 2^(br + 1)
+    pub(crate) fn new(dp: &Peripherals, config: [ChannelConfig; 2], pclk2_hz: u32, br: u8) -> Self {
+        setup_gpio(dp);
+        dp.RCC.apb2enr.modify(|_, w| w.spi1en().enabled());
+
+        let spi = unsafe { &*pac::SPI1::ptr() };
+        spi.cr1.write(|w| {
+            w.bidimode().bidirectional();
+            w.bidioe().output_enabled();
+            w.dff().sixteen_bit();
+            w.lsbfirst().msbfirst();
+            // Mode 0
+            w.cpol().idle_low();
+            w.cpha().first_edge();
+            w.br().bits(br);
+            w.mstr().master();
+            w.ssm().enabled();
+            w.ssi().slave_not_selected();
+            w
+        });
+        spi.cr1.modify(|_, w| w.spe().enabled());
+
+        Self {
+            spi,
+            config,
+            pclk2_hz,
+            br,
+        }
+    }
+
+    // 只写入输入寄存器，输出不变，需要 load 之后才生效
+    pub(crate) fn write(&mut self, channel: Channel, value: u16) {
+        let config = self.config[channel as usize];
+        self.send(frame(channel, config, value));
+    }
+
+    // 写入并立即更新这一个通道的输出
+    pub(crate) fn write_and_load(&mut self, channel: Channel, value: u16) {
+        self.write(channel, value);
+        self.load();
+    }
+
+    // 两个通道同时变化
+    pub(crate) fn write_both(&mut self, a: u16, b: u16) {
+        self.write(Channel::A, a);
+        self.write(Channel::B, b);
+        self.load();
+    }
+
+    // LDAC 低脉冲，最短 100 ns
+    pub(crate) fn load(&mut self) {
+        let gpiob = unsafe { &*pac::GPIOB::ptr() };
+        gpiob.bsrr.write(|w| w.br0().reset());
+        cortex_m::asm::delay(16);
+        gpiob.bsrr.write(|w| w.bs0().set());
+    }
+
+    pub(crate) fn shutdown(&mut self, channel: Channel) {
+        self.send(shutdown_frame(channel));
+        self.load();
+    }
+
+    pub(crate) fn set_config(&mut self, channel: Channel, config: ChannelConfig) {
+        self.config[channel as usize] = config;
+    }
+
+    fn send(&mut self, word: u16) {
+        let gpioa = unsafe { &*pac::GPIOA::ptr() };
+        gpioa.bsrr.write(|w| w.br6().reset());
+        while self.spi.sr.read().txe().is_not_empty() {}
+        self.spi.dr.write(|w| w.dr().bits(word));
+        // 只发送模式下没有 RXNE，必须等 TXE 之后再等 BSY 清零，才能保证最后一个 bit 已经发出
+        while self.spi.sr.read().txe().is_not_empty() {}
+        while self.spi.sr.read().bsy().is_busy() {}
+        gpioa.bsrr.write(|w| w.bs6().set());
+    }
+
+    // buffer 中是已经组装好的帧（见 frame），长度必须为偶数，以便分成两半轮流填充
+    // tim_clk_hz 为 TIM3 的时钟
+    pub(crate) fn start_stream(
+        &mut self,
+        buffer: &'static mut [u16],
+        tim_clk_hz: u32,
+        sample_hz: u32,
+    ) -> Result<(), StreamError> {
+        let len = buffer.len();
+        if len == 0 || !len.is_multiple_of(2) || len > u16::MAX as usize {
+            return Err(StreamError::BufferLength);
+        }
+
+        // 16 个 SCK 周期，加上 DMA 的延迟
+        let sck_hz = self.pclk2_hz >> (self.br + 1);
+        let frame_ns = 16 * 1_000_000_000u64 / sck_hz as u64 + DMA_LATENCY_NS as u64;
+        let cs_low_ticks = (frame_ns * tim_clk_hz as u64).div_ceil(1_000_000_000) as u32;
+        let period = tim_clk_hz / sample_hz;
+        if period < cs_low_ticks + CS_HIGH_MIN_TICKS || period > u16::MAX as u32 + 1 {
+            return Err(StreamError::RateTooHigh);
+        }
+
+        let buffer_ptr = buffer.as_ptr();
+        cortex_m::interrupt::free(|cs| {
+            let mut stream = G_STREAM.borrow(cs).borrow_mut();
+            if stream.is_some() {
+                return Err(StreamError::Running);
+            }
+            stream.replace(buffer);
+            Ok(())
+        })?;
+
+        // LDAC 保持为低，CS 的上升沿直接更新输出
+        let gpiob = unsafe { &*pac::GPIOB::ptr() };
+        gpiob.bsrr.write(|w| w.br0().reset());
+
+        setup_dma(buffer_ptr, len as u16, self.spi.dr.as_ptr() as u32);
+        setup_tim3(period, cs_low_ticks);
+
+        // PA6 交给 TIM3_CH1
+        let gpioa = unsafe { &*pac::GPIOA::ptr() };
+        gpioa.moder.modify(|_, w| w.moder6().alternate());
+
+        let dma1 = unsafe { &*pac::DMA1::ptr() };
+        dma1.st[2].cr.modify(|_, w| w.en().enabled());
+        unsafe { NVIC::unmask(interrupt::DMA1_STREAM2) };
+
+        let tim3 = unsafe { &*pac::TIM3::ptr() };
+        tim3.cr1.modify(|_, w| w.cen().enabled());
+
+        Ok(())
+    }
+
+    // 停止流式输出，恢复 GPIO 控制的 CS 与 LDAC，返回缓冲
+    pub(crate) fn stop_stream(&mut self) -> Option<&'static mut [u16]> {
+        let tim3 = unsafe { &*pac::TIM3::ptr() };
+        let dma1 = unsafe { &*pac::DMA1::ptr() };
+        let gpioa = unsafe { &*pac::GPIOA::ptr() };
+        let gpiob = unsafe { &*pac::GPIOB::ptr() };
+
+        NVIC::mask(interrupt::DMA1_STREAM2);
+        tim3.cr1.modify(|_, w| w.cen().disabled());
+        tim3.dier.modify(|_, w| w.ude().disabled());
+        let st = &dma1.st[2];
+        st.cr.modify(|_, w| w.en().disabled());
+        while st.cr.read().en().is_enabled() {}
+
+        // 等最后一帧发送完毕，再把 CS 拉高
+        while self.spi.sr.read().bsy().is_busy() {}
+        gpioa.bsrr.write(|w| w.bs6().set());
+        gpioa.moder.modify(|_, w| w.moder6().output());
+        gpiob.bsrr.write(|w| w.bs0().set());
+
+        cortex_m::interrupt::free(|cs| G_STREAM.borrow(cs).borrow_mut().take())
+    }
+}
+
+// 在 DMA1_STREAM2 中断中调用，返回刚刚发送完、可以重新填充的那一半
+pub(crate) fn on_dma1_stream2() -> Option<Half> {
+    let dma1 = unsafe { &*pac::DMA1::ptr() };
+    let lisr = dma1.lisr.read();
+
+    if lisr.teif2().is_error() {
+        dma1.lifcr.write(|w| w.cteif2().clear());
+    }
+
+    // 半传输时发送完的是前一半，传输完成时是后一半
+    if lisr.htif2().is_half() {
+        dma1.lifcr.write(|w| w.chtif2().clear());
+        Some(Half::First)
+    } else if lisr.tcif2().is_complete() {
+        dma1.lifcr.write(|w| w.ctcif2().clear());
+        Some(Half::Second)
+    } else {
+        None
+    }
+}
+
+// 重新填充缓冲的一半，没有在流式输出时返回 false
+pub(crate) fn fill_half(half: Half, f: impl FnOnce(&mut [u16])) -> bool {
+    cortex_m::interrupt::free(|cs| {
+        let mut stream = G_STREAM.borrow(cs).borrow_mut();
+        let Some(buffer) = stream.as_mut() else {
+            return false;
+        };
+        let mid = buffer.len() / 2;
+        match half {
+            Half::First => f(&mut buffer[..mid]),
+            Half::Second => f(&mut buffer[mid..]),
+        }
+        true
+    })
+}
+
+// PA5 SPI1_SCK、PA7 SPI1_MOSI 为 AF5
+// PA6 为 CS，平时是推挽输出，流式输出时切换为 TIM3_CH1（AF2）
+// PB0 为 LDAC，推挽输出
+// CS 与 LDAC 空闲时均为高
+fn setup_gpio(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+    gpioa.bsrr.write(|w| w.bs6().set());
+    gpioa.afrl.modify(|_, w| {
+        w.afrl5().af5();
+        w.afrl6().af2();
+        w.afrl7().af5();
+        w
+    });
+    gpioa.ospeedr.modify(|_, w| {
+        w.ospeedr5().high_speed();
+        w.ospeedr6().high_speed();
+        w.ospeedr7().high_speed();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder5().alternate();
+        w.moder6().output();
+        w.moder7().alternate();
+        w
+    });
+
+    let gpiob = &dp.GPIOB;
+    gpiob.bsrr.write(|w| w.bs0().set());
+    gpiob.moder.modify(|_, w| w.moder0().output());
+}
+
+// 查询 DMA request mapping 可知，TIM3_UP 位于 DMA1 的 Stream 2 Channel 5 上
+// DMA 直接写 SPI1 的 DR，SPI 本身不需要开启 TXDMAEN
+fn setup_dma(buffer: *const u16, len: u16, dr_addr: u32) {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    rcc.ahb1enr.modify(|_, w| w.dma1en().enabled());
+
+    let dma1 = unsafe { &*pac::DMA1::ptr() };
+    let st = &dma1.st[2];
+
+    if st.cr.read().en().is_enabled() {
+        st.cr.modify(|_, w| w.en().disabled());
+        while st.cr.read().en().is_enabled() {}
+    }
+
+    dma1.lifcr.write(|w| {
+        w.ctcif2().clear();
+        w.chtif2().clear();
+        w.cteif2().clear();
+        w.cdmeif2().clear();
+        w.cfeif2().clear();
+        w
+    });
+
+    st.cr.write(|w| {
+        w.chsel().bits(5);
+        w.pl().high();
+        w.dir().memory_to_peripheral();
+        w.msize().bits16();
+        w.psize().bits16();
+        w.minc().incremented();
+        w.pinc().fixed();
+        w.circ().enabled();
+        w.htie().enabled();
+        w.tcie().enabled();
+        w.teie().enabled();
+        w
+    });
+    st.par.write(|w| unsafe { w.pa().bits(dr_addr) });
+    st.m0ar.write(|w| unsafe { w.m0a().bits(buffer as u32) });
+    st.ndtr.write(|w| w.ndt().bits(len));
+}
+
+// 每个周期开始时更新事件触发 DMA，CH1 在 CNT < CCR1 时为低
+fn setup_tim3(period: u32, cs_low_ticks: u32) {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    rcc.apb1enr.modify(|_, w| w.tim3en().enabled());
+
+    let tim3 = unsafe { &*pac::TIM3::ptr() };
+    tim3.cr1.modify(|_, w| w.cen().disabled());
+    tim3.psc.write(|w| w.psc().bits(0));
+    tim3.arr.write(|w| w.arr().bits((period - 1) as u16));
+    tim3.ccr1().write(|w| w.ccr().bits(cs_low_ticks as u16));
+    tim3.ccmr1_output().modify(|_, w| {
+        w.cc1s().output();
+        w.oc1m().pwm_mode2();
+        w.oc1pe().enabled();
+        w
+    });
+    tim3.ccer.modify(|_, w| w.cc1e().set_bit());
+    tim3.cr1.modify(|_, w| w.arpe().enabled());
+    // UG 加载预装载寄存器，此时 UDE 还没有打开，不会触发 DMA
+    tim3.egr.write(|w| w.ug().set_bit());
+    tim3.dier.modify(|_, w| w.ude().enabled());
+}
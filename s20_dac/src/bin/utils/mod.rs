@@ -0,0 +1,5 @@
+// audio_loop 用到了只有 F413 才有的 DAC，用到它的程序在开头写有 chip_caps::require!，见 chip_caps
+#[cfg(feature = "stm32f413")]
+pub(crate) mod audio_loop;
+pub(crate) mod biquad;
+pub(crate) mod mcp492x;
@@ -0,0 +1,240 @@
+//! 传感器框架
+//!
+//! 随着传感器越来越多（超声波测距、ADC、温湿度、气压……），如果每个传感器都写一个自己的主循环，
+//! 那么我们每次想组合几个传感器，都得重新安排一遍采样的时机，以及数据的去向
+//!
+//! 因此这里我们把这个问题拆成三部分（见 utils::sensor）：
+//!
+//! Sensor：每个传感器驱动只负责“采样一次，并给出带单位的结果”
+//! Scheduler：按照每个传感器各自的采样间隔，依照 TIM2 + TIM5 级联提供的毫秒计时调用它们
+//! Sink：读数的去向，比如 RTT 日志、LCD1602 轮流显示、通过串口输出的遥测数据
+//!
+//! 这个示例使用了两个芯片内部就有的“传感器”，因此除了 LCD 和串口之外，不需要额外接线
+//! 1. 内部温度传感器（ADC1 channel 18）
+//! 2. 内部参考电压 V_{REFINT}（ADC1 channel 17），通过它我们可以反算出 V_{DDA} 的实际电压
+//!
+//! 两者的出厂校准值都保存在 System Memory 中，见 datasheet 的 Temperature sensor calibration values 和 Internal reference voltage calibration values 两节
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//!
+//! USB-TTL 模块
+//! PA9 (USART1 Tx) <-> Rx
+//! GND             <-> GND
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    lcd1602::Lcd1602,
+    sensor::{
+        scheduler::Scheduler,
+        sink::{ByteWrite, LcdPageSink, RttSink, Sink, TelemetrySink},
+        Celsius, Sensor, SensorError, Volt,
+    },
+    ticker,
+};
+
+// 出厂校准值，均为 V_{DDA} = 3.3 V 时的 ADC 读数
+// 30 ℃ 时温度传感器的读数
+const TS_CAL1: *const u16 = 0x1FFF_7A2C as *const u16;
+// 110 ℃ 时温度传感器的读数
+const TS_CAL2: *const u16 = 0x1FFF_7A2E as *const u16;
+// 30 ℃ 时 V_{REFINT} 的读数
+const VREFINT_CAL: *const u16 = 0x1FFF_7A2A as *const u16;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_adc(&dp);
+    setup_usart1(&dp);
+
+    let mut temp_sensor = InternalTemp { adc: &dp.ADC1 };
+    let mut vdda_sensor = Vdda { adc: &dp.ADC1 };
+
+    let mut scheduler = Scheduler::<4>::new();
+    // 两个传感器错开 100 ms 启动，防止它们总是在同一次 poll 中被采样
+    scheduler.register(&mut temp_sensor, 1000, 0).ok().unwrap();
+    scheduler.register(&mut vdda_sensor, 500, 100).ok().unwrap();
+
+    let mut rtt_sink = RttSink;
+    let mut lcd_sink = LcdPageSink::<_, 4>::new(Lcd1602::new(&dp), 2000);
+    let mut telemetry_sink = TelemetrySink::new(Usart1Writer { usart: &dp.USART1 });
+
+    rprintln!("sensor framework started");
+
+    loop {
+        let sinks: &mut [&mut dyn Sink] = &mut [&mut rtt_sink, &mut lcd_sink, &mut telemetry_sink];
+        scheduler.poll(ticker::millis(), sinks);
+    }
+}
+
+// 对 ADC1 的指定通道执行一次软件触发的转换
+fn read_adc_channel(adc: &pac::ADC1, channel: u8) -> Result<u16, SensorError> {
+    adc.sqr3.modify(|_, w| unsafe { w.sq1().bits(channel) });
+    adc.cr2.modify(|_, w| w.swstart().start());
+
+    // 6 MHz 的 ADCCLK 下，480 个周期的采样加上量化，也就 80 us 多一点，这里给 2 ms 足够了
+    let start = ticker::millis();
+    while adc.sr.read().eoc().bit_is_clear() {
+        if ticker::millis().wrapping_sub(start) > 2 {
+            return Err(SensorError::Timeout);
+        }
+    }
+
+    // 读取 DR 会自动清除 EOC
+    Ok(adc.dr.read().data().bits())
+}
+
+// 依照 V_{REFINT} 的读数，把以 3.3 V 为基准的读数换算为以实际 V_{DDA} 为基准的读数
+fn compensate(raw: u16, vrefint_raw: u16) -> f32 {
+    let vrefint_cal = unsafe { VREFINT_CAL.read_volatile() } as f32;
+    raw as f32 * vrefint_cal / vrefint_raw as f32
+}
+
+struct InternalTemp<'a> {
+    adc: &'a pac::ADC1,
+}
+
+impl Sensor for InternalTemp<'_> {
+    type Output = Celsius;
+
+    fn name(&self) -> &'static str {
+        "mcu"
+    }
+
+    fn sample(&mut self) -> Result<Celsius, SensorError> {
+        let vrefint_raw = read_adc_channel(self.adc, 17)?;
+        let temp_raw = compensate(read_adc_channel(self.adc, 18)?, vrefint_raw);
+
+        let (cal1, cal2) = unsafe {
+            (
+                TS_CAL1.read_volatile() as f32,
+                TS_CAL2.read_volatile() as f32,
+            )
+        };
+
+        // 在两个校准点之间做线性插值
+        let temp = 30.0 + (temp_raw - cal1) * (110.0 - 30.0) / (cal2 - cal1);
+
+        // 超出芯片工作温度范围的读数，一定是出错了
+        if !(-40.0..=125.0).contains(&temp) {
+            return Err(SensorError::OutOfRange);
+        }
+
+        Ok(Celsius(temp))
+    }
+}
+
+struct Vdda<'a> {
+    adc: &'a pac::ADC1,
+}
+
+impl Sensor for Vdda<'_> {
+    type Output = Volt;
+
+    fn name(&self) -> &'static str {
+        "vdda"
+    }
+
+    fn sample(&mut self) -> Result<Volt, SensorError> {
+        let vrefint_raw = read_adc_channel(self.adc, 17)?;
+        if vrefint_raw == 0 {
+            return Err(SensorError::OutOfRange);
+        }
+
+        let vrefint_cal = unsafe { VREFINT_CAL.read_volatile() } as f32;
+
+        Ok(Volt(3.3 * vrefint_cal / vrefint_raw as f32))
+    }
+}
+
+// 遥测数据直接从 USART1 以轮询的方式发送出去
+// 如果想改为通过 USB CDC 发送，只需要另外实现一个 ByteWrite 即可
+struct Usart1Writer<'a> {
+    usart: &'a pac::USART1,
+}
+
+impl ByteWrite for Usart1Writer<'_> {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            while self.usart.sr.read().txe().bit_is_clear() {}
+            self.usart.dr.write(|w| w.dr().bits(byte as u16));
+        }
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+fn setup_adc(dp: &pac::Peripherals) {
+    dp.RCC.apb2enr.modify(|_, w| w.adc1en().enabled());
+
+    // 启用内部温度传感器与 V_{REFINT}
+    // TSVREFE: Temperature Sensor and V_{REFINT} Enable
+    dp.ADC_COMMON.ccr.modify(|_, w| {
+        w.adcpre().div2();
+        w.tsvrefe().enabled();
+        w
+    });
+
+    let adc = &dp.ADC1;
+
+    // 依照 datasheet，温度传感器的采样时间至少需要 10 us，这里两个通道都给最长的采样时间
+    // 0b111 为 480 个周期，F401/F411 的 pac 中 SMPR1 的字段没有 cycles480()，因此直接写位
+    adc.smpr1.modify(|r, w| unsafe {
+        w.bits(r.bits() | 0b111 << (3 * (17 - 10)) | 0b111 << (3 * (18 - 10)))
+    });
+
+    // 序列长度为 1，每次采样前再修改 SQ1
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+
+    adc.cr2.modify(|_, w| w.adon().enabled());
+
+    // 温度传感器从启用到稳定需要最多 10 us
+    cortex_m::asm::delay(12 * 10);
+}
+
+// 准备 USART1 的 Tx，参数为 115200 8N1，只发送不接收
+fn setup_usart1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| w.afrh9().af7());
+    gpioa.pupdr.modify(|_, w| w.pupdr9().pull_up());
+    gpioa.moder.modify(|_, w| w.moder9().alternate());
+
+    let serial1 = &dp.USART1;
+
+    serial1.cr1.modify(|_, w| w.ue().enabled());
+
+    // 12 MHz / (16 * 115200) = 6.51，整数部分为 6，小数部分为 0.51 * 16 ≈ 8
+    serial1.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    serial1.cr1.modify(|_, w| w.te().enabled());
+}
@@ -0,0 +1,126 @@
+//! 带温湿度补偿的超声波测距
+//!
+//! 把 BME280 作为 EnvironmentSource 交给 Ultrasonic（见 utils::sensor::ultrasonic），
+//! 每次测距前读取一次气温和湿度，据此计算声速，再把补偿前后的距离、声速以及气温湿度一起交给 Sink
+//!
+//! 把手捂在 BME280 上，或者用电吹风吹一下，可以看到 sonar.raw 基本不变，而 sonar.dist 随着温度变化
+//!
+//! 若没有 BME280，可以把 env 换成 FixedAir(Celsius(20.0))，
+//! 或者用 TemperatureSensor 包装任意一个输出为 Celsius 的传感器（比如 s21c01 中的 InternalTemp，虽然芯片温度并不等于气温）
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//!
+//! STM32 <-> US-100（拔掉跳线帽，使用脉冲模式）
+//!  3.3V <-> VCC
+//!   PA5 <-> Trig
+//!  PB10 <-> Echo
+//!   GND <-> GND
+//!
+//! STM32 <-> BME280
+//!  3.3V <-> VCC
+//!   PB8 <-> SCL (I2C1)
+//!   PB9 <-> SDA (I2C1)
+//!   GND <-> GND, SDO
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    bme280::{self, Bme280},
+    lcd1602::Lcd1602,
+    sensor::{
+        scheduler::Scheduler,
+        sink::{LcdPageSink, RttSink, Sink},
+        ultrasonic::Ultrasonic,
+    },
+    ticker,
+};
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_i2c1(&dp);
+
+    let env = Bme280::new(&dp.I2C1, bme280::ADDR_SDO_LOW).unwrap();
+    let mut sonar = Ultrasonic::new(&dp, env);
+
+    let mut scheduler = Scheduler::<1>::new();
+    // US-100 两次测量之间至少要间隔一段时间，让上一次的回波散去
+    scheduler.register(&mut sonar, 200, 0).ok().unwrap();
+
+    let mut rtt_sink = RttSink;
+    // sonar 一次采样最多给出 5 个读数
+    let mut lcd_sink = LcdPageSink::<_, 5>::new(Lcd1602::new(&dp), 2000);
+
+    rprintln!("compensated sonar started");
+
+    loop {
+        let sinks: &mut [&mut dyn Sink] = &mut [&mut rtt_sink, &mut lcd_sink];
+        scheduler.poll(ticker::millis(), sinks);
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 配置 I2C1 为 100 kHz 的主机，收发由 utils::blocking_master 以轮询的方式完成
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    // PB6/PB7 被 LCD 占用了，这里使用 I2C1 的另一组引脚 PB8/PB9
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    // 大多数 BME280 模块板上自带上拉电阻，这里的内部上拉只是保底
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+
+    // APB1 直接使用 12 MHz 的 HSE
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    // 标准模式下，高低电平各占一半，12 MHz / (2 * 100 kHz) = 60
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    // 标准模式下最大上升时间为 1000 ns，即 12 个 APB1 时钟周期，再 +1
+    i2c.trise.write(|w| w.trise().bits(13));
+
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
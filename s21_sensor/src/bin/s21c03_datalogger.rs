@@ -0,0 +1,346 @@
+//! 把传感器读数记录到 QSPI Flash 中，并通过串口导出
+//!
+//! BME280 每 5 秒采样一次，读数通过 LogSink 写入 W25Q32 中从 1 MB 处开始的 64 个扇区（256 KB），
+//! 记录格式与掉电保护的做法见 utils::datalog
+//!
+//! 串口上可以输入以下命令（以回车结束）：
+//!
+//! stat           打印记录数、损坏的记录数、容量与 seq 的范围
+//! dump           从旧到新导出所有记录
+//! dump <seq>     只导出 seq 不小于 <seq> 的记录，Host 端记下上次导出的最后一个 seq，就可以增量导出
+//! erase          擦除全部记录
+//!
+//! 导出的每条记录为一行
+//! L,<seq>,<毫秒>,<传感器名>,<物理量名>,<数值>,<单位>\n
+//! 最后一行为
+//! END,<导出的记录数>\n
+//! 与 s21c01 的遥测格式类似，可以直接保存为 CSV
+//!
+//! 导出期间不会采样，导出结束后 Scheduler 会自动跳过错过的采样时刻
+//!
+//! 接线图：
+//!
+//! W25Q32 与 s19 一致
+//! PB1  CLK
+//! PB6  nCS
+//! PC9  IO0
+//! PC10 IO1
+//! PC8  IO2 /WP
+//! PA1  IO3 /HOLD /RESET
+//!
+//! BME280 与 s21c02 一致
+//! PB8 SCL
+//! PB9 SDA
+//!
+//! USB-TTL 模块，115200 8N1
+//! PA9  (USART1 Tx) <-> Rx
+//! PA10 (USART1 Rx) <-> Tx
+//! GND              <-> GND
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::qspi_flash::QspiFlash;
+use utils::{
+    bme280::{self, Bme280},
+    datalog::{DataLog, LogFlash, LogSink},
+    sensor::{
+        scheduler::Scheduler,
+        sink::{ByteWrite, LineBuf, RttSink, Sink},
+    },
+    ticker,
+};
+
+chip_caps::require!(QUADSPI);
+
+// 日志区域，避开 s19 中实验用的前几个块
+const LOG_START: u32 = 0x10_0000;
+const LOG_SECTORS: u32 = 64;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_i2c1(&dp);
+    setup_usart1(&dp);
+    setup_qspi_gpio(&dp);
+    setup_qspi(&dp);
+
+    let mut log = DataLog::mount(QspiFlash::new(&dp.QUADSPI), LOG_START, LOG_SECTORS).unwrap();
+    rprintln!(
+        "datalog mounted, next seq {}, capacity {}",
+        log.next_seq(),
+        log.capacity()
+    );
+
+    let mut bme = Bme280::new(&dp.I2C1, bme280::ADDR_SDO_LOW).unwrap();
+
+    let mut scheduler = Scheduler::<1>::new();
+    scheduler.register(&mut bme, 5000, 0).ok().unwrap();
+
+    let mut rtt_sink = RttSink;
+    let mut log_sink = LogSink::new(&mut log);
+
+    let mut writer = Usart1Writer { usart: &dp.USART1 };
+    let mut command = LineBuf::<32>::new();
+
+    loop {
+        let sinks: &mut [&mut dyn Sink] = &mut [&mut rtt_sink, &mut log_sink];
+        scheduler.poll(ticker::millis(), sinks);
+
+        // 以轮询的方式接收命令，一次只处理一个字节，不耽误采样
+        let usart = &dp.USART1;
+        if usart.sr.read().rxne().bit_is_clear() {
+            continue;
+        }
+        let byte = usart.dr.read().dr().bits() as u8;
+        match byte {
+            b'\r' | b'\n' => {
+                let line = core::str::from_utf8(command.as_bytes()).unwrap_or("");
+                run_command(line.trim(), &mut log_sink, &mut writer);
+                command.clear();
+            }
+            _ => {
+                command.write_char(byte as char).ok();
+            }
+        }
+    }
+}
+
+fn run_command<F: LogFlash>(line: &str, sink: &mut LogSink<F>, writer: &mut impl ByteWrite) {
+    let mut words = line.split_ascii_whitespace();
+    let mut out = LineBuf::<80>::new();
+
+    match (words.next(), words.next()) {
+        (None, _) => {}
+        (Some("stat"), None) => {
+            let failures = sink.failures();
+            let stats = sink.log().stats();
+            writeln!(
+                out,
+                "STAT,{},{},{},{:?},{:?},{}",
+                stats.records,
+                stats.corrupt,
+                stats.capacity,
+                stats.oldest_seq,
+                stats.newest_seq,
+                failures
+            )
+            .ok();
+            writer.write_bytes(out.as_bytes());
+        }
+        (Some("dump"), since) => {
+            let since = match since.map(str::parse::<u32>) {
+                None => 0,
+                Some(Ok(seq)) => seq,
+                Some(Err(_)) => {
+                    writer.write_bytes(b"ERR,bad seq\n");
+                    return;
+                }
+            };
+            let mut count = 0u32;
+            sink.log().for_each(since, |record| {
+                out.clear();
+                writeln!(
+                    out,
+                    "L,{},{},{},{},{:.3},{}",
+                    record.seq,
+                    record.time_ms,
+                    record.sensor(),
+                    record.quantity(),
+                    record.value,
+                    record.unit()
+                )
+                .ok();
+                writer.write_bytes(out.as_bytes());
+                count += 1;
+            });
+            out.clear();
+            writeln!(out, "END,{}", count).ok();
+            writer.write_bytes(out.as_bytes());
+        }
+        (Some("erase"), None) => {
+            sink.log().erase_all();
+            writer.write_bytes(b"OK\n");
+        }
+        _ => writer.write_bytes(b"ERR,unknown command\n"),
+    }
+}
+
+struct Usart1Writer<'a> {
+    usart: &'a pac::USART1,
+}
+
+impl ByteWrite for Usart1Writer<'_> {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            while self.usart.sr.read().txe().bit_is_clear() {}
+            self.usart.dr.write(|w| w.dr().bits(byte as u16));
+        }
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 与 s21c02 相同，I2C1 使用 PB8/PB9，100 kHz
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
+
+// USART1 收发，参数为 115200 8N1
+fn setup_usart1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| {
+        w.afrh9().af7();
+        w.afrh10().af7();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr9().pull_up();
+        w.pupdr10().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let serial1 = &dp.USART1;
+
+    serial1.cr1.modify(|_, w| w.ue().enabled());
+
+    // 12 MHz / (16 * 115200) = 6.51，整数部分为 6，小数部分为 0.51 * 16 ≈ 8
+    serial1.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    serial1.cr1.modify(|_, w| {
+        w.te().enabled();
+        w.re().enabled();
+        w
+    });
+}
+
+// 与 s19 相同的 6 线 QuadSPI 引脚，这里只用到了单线命令，IO2/IO3 依旧需要配置，以免 /WP 与 /HOLD 悬空
+fn setup_qspi_gpio(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w.gpiocen().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| w.afrl1().af9()); // IO3 /HOLD /RESET
+    gpioa.moder.modify(|_, w| w.moder1().alternate());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| {
+        w.afrl1().af9(); // CLK
+        w.afrl6().af10(); // nCS
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder1().alternate();
+        w.moder6().alternate();
+        w
+    });
+
+    let gpioc = &dp.GPIOC;
+    gpioc.afrh.modify(|_, w| {
+        w.afrh8().af9(); // IO2 /WP
+        w.afrh9().af9(); // IO0
+        w.afrh10().af9(); // IO1
+        w
+    });
+    gpioc.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn setup_qspi(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb3enr.modify(|_, w| w.qspien().disabled());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().reset());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().clear_bit());
+    rcc.ahb3enr.modify(|_, w| w.qspien().enabled());
+
+    let qspi = &dp.QUADSPI;
+
+    // 12 MHz / 2 = 6 MHz
+    qspi.cr.modify(|_, w| unsafe {
+        w.prescaler().bits(2 - 1);
+        w.sshift().set_bit();
+        w
+    });
+
+    qspi.dcr.modify(|_, w| unsafe {
+        // W25Q32 为 4 MB，2^(21 + 1) = 4 MB
+        w.fsize().bits(21);
+        w.ckmode().set_bit();
+        w
+    });
+
+    qspi.cr.modify(|_, w| w.en().set_bit());
+}
@@ -0,0 +1,394 @@
+//! 在 LCD1602 上浏览读数、修改参数的菜单
+//!
+//! 传感器与 s21c02 相同，只是超声波测距所用的气温不再来自 BME280，而是菜单里手动设置的数值，
+//! 所有读数都交给 ReadingCache 记下来，菜单的结构见 utils::ui：
+//!
+//! Main
+//! ├── Readings   逐条浏览所有传感器的最新读数
+//! ├── Live       几个常用的读数，以及开机时间
+//! └── Settings   BME280 与超声波的采样间隔、LCD 的对比度，以及测距时使用的气温
+//!
+//! Prev/Next 按键与编码器的旋转用于选择条目、调整数值，Enter 进入子菜单或者开始/确认编辑，Back 返回上一级或者放弃编辑
+//!
+//! 除了气温之外，Settings 中的参数都保存在 utils::config 中，存放在芯片内部 Flash 的扇区 8 与 9（见 utils::internal_flash），
+//! 修改之后停止操作 5 秒才会写入 Flash：擦除一个扇区需要 1~2 秒，期间屏幕与按键都没有响应，不应该每按一下就写一次
+//! 配置中还有板子的序列号，开机时打印出来
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//!
+//! US-100 与 s21c02 一致
+//! PA5  Trig
+//! PB10 Echo
+//!
+//! BME280 与 s21c02 一致
+//! PB8 SCL
+//! PB9 SDA
+//!
+//! 按键，见 utils::keypad
+//! PC0 Prev
+//! PC1 Next
+//! PC2 Enter（可以接编码器自带的按键）
+//! PC3 Back
+//!
+//! 编码器，见 utils::encoder
+//! PC6 A
+//! PC7 B
+//!
+//! LCD 对比度，TIM12_CH1 输出的 PWM 经过 RC 低通滤波（10k + 10uF）之后接到 LCD1602 的 V0（3 号引脚）
+//! PB14 -> 10k -> V0，V0 -> 10uF -> GND
+
+#![no_std]
+#![no_main]
+
+use core::cell::Cell;
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    bme280::{self, Bme280},
+    config::{Config, ConfigStore, Rate},
+    encoder::{self, Encoder},
+    internal_flash::InternalFlash,
+    keypad::{self, Key, KeyEvent, Keypad},
+    lcd1602::Lcd1602,
+    sensor::{
+        scheduler::Scheduler,
+        sink::{RttSink, Sink},
+        ultrasonic::{Air, EnvironmentSource, Ultrasonic},
+        Celsius, SensorError,
+    },
+    ticker,
+    ui::{Event, Item, Menu, Number, ReadingCache, Ui},
+};
+
+chip_caps::require!(TIM12);
+
+// 配置存放在内部 Flash 的扇区 8 与 9，在 memory.x 分配给程序的 512 KB 之后
+const CONFIG_START: u32 = 0x08_0000;
+
+// 配置修改之后，停止操作多久才写入 Flash
+const SAVE_DELAY_MS: u32 = 5000;
+
+const CONTRAST_ARR: u16 = 1200 - 1;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_i2c1(&dp);
+    keypad::setup(&dp);
+    encoder::setup(&dp);
+
+    let mut store = ConfigStore::new(InternalFlash::new(&dp.FLASH), CONFIG_START).unwrap();
+    let (loaded, origin) = store.load();
+    rprintln!("config loaded: {:?}", origin);
+    rprintln!("serial: {}", loaded.serial());
+
+    setup_contrast(&dp);
+    set_contrast(&dp, loaded.lcd_contrast());
+
+    // 菜单中可以修改的参数
+    let config = Cell::new(loaded);
+    let air_temp = Cell::new(20.0f32);
+
+    let mut bme = Bme280::new(&dp.I2C1, bme280::ADDR_SDO_LOW).unwrap();
+    let mut sonar = Ultrasonic::new(&dp, ManualAir(&air_temp));
+
+    let mut scheduler = Scheduler::<2>::new();
+    scheduler
+        .register(&mut bme, loaded.period_ms(Rate::Bme), 0)
+        .ok()
+        .unwrap();
+    scheduler
+        .register(&mut sonar, loaded.period_ms(Rate::Sonar), 100)
+        .ok()
+        .unwrap();
+
+    // BME280 有 3 个读数，sonar 有 5 个
+    let cache = ReadingCache::<8>::new();
+
+    let temp = || cache.value("bme", "temp");
+    let rh = || cache.value("bme", "rh");
+    let dist = || cache.value("sonar", "dist");
+    let uptime = || Some(ticker::millis() as f32 / 1000.0);
+    let live_items = [
+        Item::Value {
+            label: "Temp",
+            unit: "C",
+            get: &temp,
+        },
+        Item::Value {
+            label: "RH",
+            unit: "%",
+            get: &rh,
+        },
+        Item::Value {
+            label: "Dist",
+            unit: "mm",
+            get: &dist,
+        },
+        Item::Value {
+            label: "Up",
+            unit: "s",
+            get: &uptime,
+        },
+    ];
+
+    // 通过 Config 的访问函数读写，超出范围的值会被限制住
+    let update = |f: &dyn Fn(&mut Config)| {
+        let mut new = config.get();
+        f(&mut new);
+        config.set(new);
+    };
+    let get_bme_period = || config.get().period_ms(Rate::Bme) as f32 / 1000.0;
+    let set_bme_period = |value: f32| {
+        update(&|c| {
+            c.set_period_ms(Rate::Bme, (value * 1000.0) as u32);
+        })
+    };
+    let get_sonar_period = || config.get().period_ms(Rate::Sonar) as f32;
+    let set_sonar_period = |value: f32| {
+        update(&|c| {
+            c.set_period_ms(Rate::Sonar, value as u32);
+        })
+    };
+    let get_lcd_contrast = || config.get().lcd_contrast() as f32;
+    let set_lcd_contrast = |value: f32| update(&|c| c.set_lcd_contrast(value as u8));
+    let get_air_temp = || air_temp.get();
+    let set_air_temp = |value| air_temp.set(value);
+    let settings_items = [
+        Item::Number {
+            label: "BME",
+            unit: "s",
+            number: Number {
+                get: &get_bme_period,
+                set: &set_bme_period,
+                min: 1.0,
+                max: 60.0,
+                step: 1.0,
+                decimals: 0,
+            },
+        },
+        Item::Number {
+            label: "Sonar",
+            unit: "ms",
+            number: Number {
+                get: &get_sonar_period,
+                set: &set_sonar_period,
+                min: 100.0,
+                max: 5000.0,
+                step: 100.0,
+                decimals: 0,
+            },
+        },
+        Item::Number {
+            label: "Contrast",
+            unit: "%",
+            number: Number {
+                get: &get_lcd_contrast,
+                set: &set_lcd_contrast,
+                min: 0.0,
+                max: 100.0,
+                step: 5.0,
+                decimals: 0,
+            },
+        },
+        Item::Number {
+            label: "Air",
+            unit: "C",
+            number: Number {
+                get: &get_air_temp,
+                set: &set_air_temp,
+                min: -20.0,
+                max: 50.0,
+                step: 0.5,
+                decimals: 1,
+            },
+        },
+    ];
+
+    let root_items = [
+        Item::Readings {
+            label: "Readings",
+            source: &cache,
+        },
+        Item::Menu(Menu {
+            title: "Live",
+            items: &live_items,
+        }),
+        Item::Menu(Menu {
+            title: "Settings",
+            items: &settings_items,
+        }),
+    ];
+    let root = Menu {
+        title: "Main",
+        items: &root_items,
+    };
+
+    let mut ui = Ui::<_, 2>::new(Lcd1602::new(&dp), &root, 500);
+    let mut keys = Keypad::new();
+    let mut knob = Encoder::new();
+
+    let mut rtt_sink = RttSink;
+    let mut cache_sink = &cache;
+
+    // 上一次写入 Flash 的配置、上一轮循环时的配置，以及最近一次修改配置的时间
+    let mut saved = loaded;
+    let mut last = loaded;
+    let mut changed_ms = 0;
+
+    rprintln!("menu started");
+
+    loop {
+        let now = ticker::millis();
+
+        if let Some(KeyEvent::Press(key) | KeyEvent::Repeat(key)) = keys.poll(now) {
+            ui.handle(match key {
+                Key::Prev => Event::Prev,
+                Key::Next => Event::Next,
+                Key::Enter => Event::Enter,
+                Key::Back => Event::Back,
+            });
+        }
+
+        let detents = knob.poll();
+        let event = if detents > 0 {
+            Event::Next
+        } else {
+            Event::Prev
+        };
+        for _ in 0..detents.unsigned_abs() {
+            ui.handle(event);
+        }
+
+        // 设置可能在上面的事件中被修改了，每一轮都同步给 Scheduler 与 PWM
+        let current = config.get();
+        scheduler.set_period("bme", current.period_ms(Rate::Bme));
+        scheduler.set_period("sonar", current.period_ms(Rate::Sonar));
+        set_contrast(&dp, current.lcd_contrast());
+
+        if current != last {
+            last = current;
+            changed_ms = now;
+        }
+        if current != saved && now.wrapping_sub(changed_ms) >= SAVE_DELAY_MS {
+            match store.save(&current) {
+                Ok(()) => rprintln!("config saved"),
+                Err(e) => rprintln!("failed to save config: {:?}", e),
+            }
+            saved = current;
+        }
+
+        let sinks: &mut [&mut dyn Sink] = &mut [&mut rtt_sink, &mut cache_sink];
+        scheduler.poll(now, sinks);
+
+        ui.refresh(now);
+    }
+}
+
+// LCD 对比度的 PWM
+//
+// V0 的电压越低，对比度越高，因此对比度 100% 对应占空比 0%
+// PWM 的频率为 12 MHz / 1200 = 10 kHz，RC 滤波的时间常数为 0.1 s，纹波可以忽略
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn setup_contrast(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.tim12en().enabled());
+
+    dp.GPIOB.afrh.modify(|_, w| w.afrh14().af9());
+    dp.GPIOB.moder.modify(|_, w| w.moder14().alternate());
+
+    let tim = &dp.TIM12;
+    tim.psc.write(|w| w.psc().bits(0));
+    tim.arr.write(|w| unsafe { w.arr().bits(CONTRAST_ARR) });
+    tim.cr1.modify(|_, w| w.arpe().enabled());
+
+    let ccmr1_output = tim.ccmr1_output();
+    ccmr1_output.reset();
+    ccmr1_output.modify(|_, w| {
+        unsafe { w.cc1s().bits(0b00) };
+        w.oc1m().pwm_mode1();
+        w.oc1pe().set_bit();
+        w
+    });
+
+    tim.egr.write(|w| w.ug().update());
+    tim.ccer.modify(|_, w| w.cc1e().set_bit());
+    tim.cr1.modify(|_, w| w.cen().enabled());
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn set_contrast(dp: &pac::Peripherals, percent: u8) {
+    let ccr = (CONTRAST_ARR as u32 + 1) * (100 - percent.min(100) as u32) / 100;
+    dp.TIM12
+        .ccr1()
+        .write(|w| unsafe { w.ccr().bits(ccr as u16) });
+}
+
+// 使用菜单中设置的气温来计算声速
+struct ManualAir<'a>(&'a Cell<f32>);
+
+impl EnvironmentSource for ManualAir<'_> {
+    fn air(&mut self) -> Result<Air, SensorError> {
+        Ok(Air {
+            temp: Celsius(self.0.get()),
+            humidity: None,
+        })
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 与 s21c02 相同，I2C1 使用 PB8/PB9，100 kHz
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
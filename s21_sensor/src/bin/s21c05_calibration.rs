@@ -0,0 +1,424 @@
+//! 传感器校准，结果保存在 QSPI Flash 中，开机时自动加载
+//!
+//! 这里以 ADC 的两点线性校准为例：PC0 与 PC1 两个模拟输入，默认按 3.3 V / 4095 换算为电压，
+//! 但实际的 V_{DDA}、ADC 的偏移与增益误差、输入端的分压电阻误差，都会让读数偏离实际电压
+//! 校准时先把输入接地，再接到一个接近满量程的电压上，每次都输入万用表测得的实际电压，就可以求出增益与偏移
+//!
+//! 校准框架见 utils::calibration，参数保存在 utils::settings 中，位于 W25Q32 的 0xF_0000 处的两个扇区，
+//! 与 s21c03 的日志区域互不干扰
+//!
+//! 串口上可以输入以下命令（以回车结束）：
+//!
+//! list          列出所有可以校准的输入，以及保存的参数
+//! read          读取所有输入校准后的电压
+//! cal <key>     校准指定的输入，按提示操作，直接回车或者输入 q 取消
+//! forget <key>  删除保存的参数，重启后恢复默认值
+//!
+//! 接线图：
+//!
+//! W25Q32 与 s21c03 一致
+//! PB1  CLK
+//! PB6  nCS
+//! PC9  IO0
+//! PC10 IO1
+//! PC8  IO2 /WP
+//! PA1  IO3 /HOLD /RESET
+//!
+//! 模拟输入，电压不要超过 3.3 V
+//! PC0 (ADC1_IN10) <-> 被测电压 0
+//! PC1 (ADC1_IN11) <-> 被测电压 1
+//!
+//! USB-TTL 模块，115200 8N1
+//! PA9  (USART1 Tx) <-> Rx
+//! PA10 (USART1 Rx) <-> Tx
+//! GND              <-> GND
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::qspi_flash::QspiFlash;
+use utils::{
+    calibration::{self, Calibrate, Linear, Prompt},
+    sensor::{sink::LineBuf, Sensor, SensorError, Volt},
+    settings::{SettingsStore, Values},
+    ticker,
+};
+
+chip_caps::require!(QUADSPI);
+
+// 设置区域，紧挨着 s21c03 的日志区域之前
+const SETTINGS_START: u32 = 0x0F_0000;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_adc(&dp);
+    setup_usart1(&dp);
+    setup_qspi_gpio(&dp);
+    setup_qspi(&dp);
+
+    let mut store = SettingsStore::open(QspiFlash::new(&dp.QUADSPI), SETTINGS_START).unwrap();
+
+    let mut in0 = AdcInput::new(&dp.ADC1, 10, "adc10");
+    let mut in1 = AdcInput::new(&dp.ADC1, 11, "adc11");
+
+    let applied = calibration::load_all(&mut [&mut in0, &mut in1], &store);
+    rprintln!(
+        "settings generation {}, {} calibration(s) applied",
+        store.generation(),
+        applied
+    );
+
+    let mut console = Console { usart: &dp.USART1 };
+    let mut line = LineBuf::<32>::new();
+
+    loop {
+        console.read_line(&mut line);
+        let command = core::str::from_utf8(line.as_bytes()).unwrap_or("");
+        let mut words = command.split_ascii_whitespace();
+        let mut out = LineBuf::<80>::new();
+
+        match (words.next(), words.next()) {
+            (None, _) => {}
+            (Some("list"), None) => {
+                for key in [in0.key(), in1.key()] {
+                    out.clear();
+                    match store.get(key) {
+                        Some(values) => {
+                            let cal = Linear::from_values(&values);
+                            write!(
+                                out,
+                                "{}: gain {:e}, offset {:.4}",
+                                key, cal.gain, cal.offset
+                            )
+                            .ok();
+                        }
+                        None => {
+                            write!(out, "{}: default", key).ok();
+                        }
+                    }
+                    console.say(core::str::from_utf8(out.as_bytes()).unwrap_or(""));
+                }
+            }
+            (Some("read"), None) => {
+                for input in [&mut in0, &mut in1] {
+                    out.clear();
+                    match input.sample() {
+                        Ok(Volt(volt)) => write!(out, "{}: {:.4} V", input.key(), volt).ok(),
+                        Err(e) => write!(out, "{}: {:?}", input.key(), e).ok(),
+                    };
+                    console.say(core::str::from_utf8(out.as_bytes()).unwrap_or(""));
+                }
+            }
+            (Some("cal"), Some(key)) => {
+                let routines: &mut [&mut dyn Calibrate] = &mut [&mut in0, &mut in1];
+                match calibration::run(routines, key, &mut console, &mut store) {
+                    Ok(values) => {
+                        let cal = Linear::from_values(&values);
+                        write!(out, "saved, gain {:e}, offset {:.4}", cal.gain, cal.offset).ok();
+                    }
+                    Err(e) => {
+                        write!(out, "calibration failed: {:?}", e).ok();
+                    }
+                }
+                console.say(core::str::from_utf8(out.as_bytes()).unwrap_or(""));
+            }
+            (Some("forget"), Some(key)) => {
+                if !store.remove(key) {
+                    console.say("no such key");
+                } else if store.commit().is_ok() {
+                    console.say("removed, default values will be used after reboot");
+                } else {
+                    console.say("failed to write settings");
+                }
+            }
+            _ => console.say("unknown command"),
+        }
+    }
+}
+
+// ADC1 的一个外部通道，校准前按 3.3 V / 4095 换算
+struct AdcInput<'a> {
+    adc: &'a pac::ADC1,
+    channel: u8,
+    key: &'static str,
+    cal: Linear,
+}
+
+impl<'a> AdcInput<'a> {
+    fn new(adc: &'a pac::ADC1, channel: u8, key: &'static str) -> Self {
+        Self {
+            adc,
+            channel,
+            key,
+            cal: Linear {
+                gain: 3.3 / 4095.0,
+                offset: 0.0,
+            },
+        }
+    }
+
+    // 未经校准的 ADC 读数
+    fn raw(&self) -> Result<f32, SensorError> {
+        let adc = self.adc;
+        adc.sqr3
+            .modify(|_, w| unsafe { w.sq1().bits(self.channel) });
+        adc.cr2.modify(|_, w| w.swstart().start());
+
+        let start = ticker::millis();
+        while adc.sr.read().eoc().bit_is_clear() {
+            if ticker::millis().wrapping_sub(start) > 2 {
+                return Err(SensorError::Timeout);
+            }
+        }
+
+        Ok(adc.dr.read().data().bits() as f32)
+    }
+}
+
+impl Sensor for AdcInput<'_> {
+    type Output = Volt;
+
+    fn name(&self) -> &'static str {
+        self.key
+    }
+
+    fn sample(&mut self) -> Result<Volt, SensorError> {
+        Ok(Volt(self.cal.apply(self.raw()?)))
+    }
+}
+
+impl Calibrate for AdcInput<'_> {
+    fn key(&self) -> &'static str {
+        self.key
+    }
+
+    fn run(&mut self, prompt: &mut dyn Prompt) -> Result<Values, calibration::CalError> {
+        prompt.say(self.key);
+        let cal = calibration::two_point(
+            prompt,
+            "connect input to GND, enter measured volt",
+            "connect input to about 3 V, enter measured volt",
+            || self.raw(),
+        )?;
+        Ok(cal.to_values())
+    }
+
+    fn apply(&mut self, values: &Values) {
+        self.cal = Linear::from_values(values);
+    }
+}
+
+// 通过 USART1 实现的简单命令行
+struct Console<'a> {
+    usart: &'a pac::USART1,
+}
+
+impl Console<'_> {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            while self.usart.sr.read().txe().bit_is_clear() {}
+            self.usart.dr.write(|w| w.dr().bits(byte as u16));
+        }
+    }
+
+    // 阻塞地读取一行，并回显输入的字符
+    fn read_line<const N: usize>(&mut self, line: &mut LineBuf<N>) {
+        line.clear();
+        loop {
+            while self.usart.sr.read().rxne().bit_is_clear() {}
+            let byte = self.usart.dr.read().dr().bits() as u8;
+            match byte {
+                b'\r' | b'\n' => {
+                    self.write_bytes(b"\r\n");
+                    return;
+                }
+                _ => {
+                    self.write_bytes(&[byte]);
+                    line.write_char(byte as char).ok();
+                }
+            }
+        }
+    }
+}
+
+impl Prompt for Console<'_> {
+    fn say(&mut self, message: &str) {
+        self.write_bytes(message.as_bytes());
+        self.write_bytes(b"\r\n");
+    }
+
+    fn ask(&mut self, message: &str) -> Option<f32> {
+        let mut line = LineBuf::<16>::new();
+        loop {
+            self.write_bytes(message.as_bytes());
+            self.write_bytes(b"> ");
+            self.read_line(&mut line);
+
+            let answer = core::str::from_utf8(line.as_bytes()).unwrap_or("").trim();
+            if answer.is_empty() || answer == "q" {
+                return None;
+            }
+            if let Ok(value) = answer.parse() {
+                return Some(value);
+            }
+            self.say("not a number");
+        }
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+fn setup_adc(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.adc1en().enabled());
+
+    dp.GPIOC.moder.modify(|_, w| {
+        w.moder0().analog();
+        w.moder1().analog();
+        w
+    });
+
+    dp.ADC_COMMON.ccr.modify(|_, w| w.adcpre().div2());
+
+    let adc = &dp.ADC1;
+
+    // 被测电压的内阻未知，给最长的采样时间
+    // 通道 10、11 是 SMPR1 的最低 6 位，0b111 为 480 个周期（F401/F411 的 pac 没有 cycles480()）
+    adc.smpr1
+        .modify(|r, w| unsafe { w.bits(r.bits() | 0b111_111) });
+
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+
+    adc.cr2.modify(|_, w| w.adon().enabled());
+}
+
+// 与 s21c03 相同，USART1 收发，参数为 115200 8N1
+fn setup_usart1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| {
+        w.afrh9().af7();
+        w.afrh10().af7();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr9().pull_up();
+        w.pupdr10().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let serial1 = &dp.USART1;
+
+    serial1.cr1.modify(|_, w| w.ue().enabled());
+
+    // 12 MHz / (16 * 115200) = 6.51，整数部分为 6，小数部分为 0.51 * 16 ≈ 8
+    serial1.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    serial1.cr1.modify(|_, w| {
+        w.te().enabled();
+        w.re().enabled();
+        w
+    });
+}
+
+// 与 s21c03 相同
+fn setup_qspi_gpio(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w.gpiocen().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| w.afrl1().af9()); // IO3 /HOLD /RESET
+    gpioa.moder.modify(|_, w| w.moder1().alternate());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| {
+        w.afrl1().af9(); // CLK
+        w.afrl6().af10(); // nCS
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder1().alternate();
+        w.moder6().alternate();
+        w
+    });
+
+    let gpioc = &dp.GPIOC;
+    gpioc.afrh.modify(|_, w| {
+        w.afrh8().af9(); // IO2 /WP
+        w.afrh9().af9(); // IO0
+        w.afrh10().af9(); // IO1
+        w
+    });
+    gpioc.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn setup_qspi(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb3enr.modify(|_, w| w.qspien().disabled());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().reset());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().clear_bit());
+    rcc.ahb3enr.modify(|_, w| w.qspien().enabled());
+
+    let qspi = &dp.QUADSPI;
+
+    // 12 MHz / 2 = 6 MHz
+    qspi.cr.modify(|_, w| unsafe {
+        w.prescaler().bits(2 - 1);
+        w.sshift().set_bit();
+        w
+    });
+
+    qspi.dcr.modify(|_, w| unsafe {
+        // W25Q32 为 4 MB，2^(21 + 1) = 4 MB
+        w.fsize().bits(21);
+        w.ckmode().set_bit();
+        w
+    });
+
+    qspi.cr.modify(|_, w| w.en().set_bit());
+}
@@ -0,0 +1,452 @@
+//! 上电自检
+//!
+//! 上电后依次运行以下测试，框架见 utils::selftest：
+//!
+//! E1 ram        对栈上 4 KB 的区域做 March C- 测试
+//! E2 flash crc  分别用 CRC 外设与软件计算整个固件镜像的 CRC-32，两者应该一致，结果可以与 Host 端对 .bin 文件的计算结果对比
+//! E3 i2c probe  EXPECTED_I2C 中的每一个设备都应该应答
+//! E4 spi loop   SPI2 的 MOSI 与 MISO 短接，发出的数据应该原样收回（与 s03c01 相同）
+//! E5 adc ref    通过 V_{REFINT} 反算出的 V_{DDA} 应该在 3.0 V ~ 3.6 V 之间
+//! E6 crypto     运行 crypto_core::vectors 中的测试向量，与 Host 端运行的是同一份代码
+//!
+//! 结果输出到 RTT 与串口，并由 LED 指示：全部通过时 1 Hz 均匀闪烁，否则快闪的次数就是第一个失败项的错误码
+//!
+//! 串口上可以输入以下命令（以回车结束）：
+//!
+//! list             列出所有测试
+//! selftest         重新运行所有测试
+//! selftest <名称>  只运行一项测试，比如 selftest adc ref
+//!
+//! 接线图：
+//!
+//! BME280 模块
+//! PB8 SCL
+//! PB9 SDA
+//!
+//! PB14 (SPI2_MISO) <-> PB15 (SPI2_MOSI)
+//!
+//! PC13 -> 1k -> LED -> GND
+//!
+//! USB-TTL 模块，115200 8N1
+//! PA9  (USART1 Tx) <-> Rx
+//! PA10 (USART1 Rx) <-> Tx
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    addressing::I2cAddress,
+    blocking_master, bme280,
+    selftest::{self, march_c, Summary, TestCase},
+    sensor::sink::LineBuf,
+    ticker,
+};
+
+const TESTS: [TestCase<pac::Peripherals>; 6] = [
+    TestCase {
+        name: "ram",
+        code: 1,
+        run: test_ram,
+    },
+    TestCase {
+        name: "flash crc",
+        code: 2,
+        run: test_flash_crc,
+    },
+    TestCase {
+        name: "i2c probe",
+        code: 3,
+        run: test_i2c_probe,
+    },
+    TestCase {
+        name: "spi loop",
+        code: 4,
+        run: test_spi_loopback,
+    },
+    TestCase {
+        name: "adc ref",
+        code: 5,
+        run: test_adc_reference,
+    },
+    TestCase {
+        name: "crypto",
+        code: 6,
+        run: test_crypto,
+    },
+];
+
+// 板子上应该存在的 I2C 设备
+const EXPECTED_I2C: [(u8, &str); 1] = [(bme280::ADDR_SDO_LOW, "BME280")];
+
+// 30 ℃ 时 V_{REFINT} 的读数，V_{DDA} = 3.3 V
+const VREFINT_CAL: *const u16 = 0x1FFF_7A2A as *const u16;
+
+const FLASH_BASE: usize = 0x0800_0000;
+
+// 由 cortex-m-rt 的 link.x 定义
+extern "C" {
+    // .data 在 RAM 中的起止地址
+    static __sdata: u32;
+    static __edata: u32;
+    // .data 的初始值在 Flash 中的起始地址，它位于 .text 与 .rodata 之后，是镜像的最后一部分
+    static __sidata: u32;
+}
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_usart1(&dp);
+    setup_i2c1(&dp);
+    setup_spi2(&dp);
+    setup_adc(&dp);
+    setup_led(&dp);
+
+    let mut console = Usart1Writer { usart: &dp.USART1 };
+    writeln!(console, "selftest at boot\r").ok();
+
+    let mut summary = selftest::run(&TESTS, &dp, None, &mut console);
+    let mut command = LineBuf::<32>::new();
+
+    loop {
+        let on = summary.led_on(ticker::millis());
+        dp.GPIOC
+            .bsrr
+            .write(|w| if on { w.bs13().set() } else { w.br13().reset() });
+
+        // 以轮询的方式接收命令，一次只处理一个字节，不耽误 LED 的闪烁
+        let usart = &dp.USART1;
+        if usart.sr.read().rxne().bit_is_clear() {
+            continue;
+        }
+        let byte = usart.dr.read().dr().bits() as u8;
+        match byte {
+            b'\r' | b'\n' => {
+                let line = core::str::from_utf8(command.as_bytes()).unwrap_or("");
+                if let Some(result) = run_command(line.trim(), &dp, &mut console) {
+                    summary = result;
+                }
+                command.clear();
+            }
+            _ => {
+                command.write_char(byte as char).ok();
+            }
+        }
+    }
+}
+
+// 执行一行命令，运行了测试时返回新的结果
+fn run_command(line: &str, dp: &pac::Peripherals, console: &mut Usart1Writer) -> Option<Summary> {
+    if line.is_empty() {
+        return None;
+    }
+
+    if line == "list" {
+        for test in TESTS.iter() {
+            writeln!(console, "E{} {}\r", test.code, test.name).ok();
+        }
+        return None;
+    }
+
+    match line.strip_prefix("selftest") {
+        Some("") => Some(selftest::run(&TESTS, dp, None, console)),
+        Some(name) if name.starts_with(' ') => {
+            Some(selftest::run(&TESTS, dp, Some(name.trim()), console))
+        }
+        _ => {
+            writeln!(console, "ERR unknown command\r").ok();
+            None
+        }
+    }
+}
+
+const RAM_TEST_WORDS: usize = 1024;
+
+fn test_ram(_dp: &pac::Peripherals) -> Result<(), u32> {
+    let mut area = [0u32; RAM_TEST_WORDS];
+    march_c(&mut area)
+}
+
+// 失败时返回 CRC 外设的结果
+fn test_flash_crc(dp: &pac::Peripherals) -> Result<(), u32> {
+    let image = unsafe {
+        let data_len =
+            core::ptr::addr_of!(__edata) as usize - core::ptr::addr_of!(__sdata) as usize;
+        let end = core::ptr::addr_of!(__sidata) as usize + data_len;
+        // link.x 中 .data 按 4 字节对齐，因此镜像的长度总是 4 的倍数
+        core::slice::from_raw_parts(FLASH_BASE as *const u32, (end - FLASH_BASE) / 4)
+    };
+
+    // CRC 外设的用法见 s15c01
+    dp.RCC.ahb1enr.modify(|_, w| w.crcen().enabled());
+    let crc = &dp.CRC;
+    crc.cr.write(|w| w.reset().reset());
+    let mut soft = 0xFFFF_FFFF;
+    for &word in image {
+        crc.dr.write(|w| w.dr().bits(word));
+        soft = crc32_mpeg2(soft, word);
+    }
+    let hard = crc.dr.read().dr().bits();
+
+    rprintln!(
+        "image {:#010X}..{:#010X}, CRC-32/MPEG-2 {:#010X}",
+        FLASH_BASE,
+        FLASH_BASE + image.len() * 4,
+        hard
+    );
+
+    if hard == soft {
+        Ok(())
+    } else {
+        Err(hard)
+    }
+}
+
+// 与 CRC 外设相同的 CRC-32/MPEG-2，以 32 位的字为单位，高位在前
+fn crc32_mpeg2(crc: u32, word: u32) -> u32 {
+    let mut crc = crc ^ word;
+    for _ in 0..32 {
+        crc = if crc & 0x8000_0000 != 0 {
+            (crc << 1) ^ 0x04C1_1DB7
+        } else {
+            crc << 1
+        };
+    }
+    crc
+}
+
+// 读取 1 个字节，有应答就说明设备存在，失败时返回没有应答的地址
+fn test_i2c_probe(dp: &pac::Peripherals) -> Result<(), u32> {
+    for (addr, name) in EXPECTED_I2C {
+        let mut byte = [0u8];
+        match blocking_master::read(&dp.I2C1, I2cAddress::SevenBit(addr), &mut byte) {
+            Ok(()) => rprintln!("{} found at {:#04X}", name, addr),
+            Err(e) => {
+                rprintln!("{} missing at {:#04X}: {:?}", name, addr, e);
+                return Err(addr as u32);
+            }
+        }
+    }
+    Ok(())
+}
+
+const SPI_PATTERN: [u8; 6] = [0x00, 0xFF, 0x55, 0xAA, 0x01, 0x80];
+
+// 失败时返回 (序号 << 8) | 收到的字节
+fn test_spi_loopback(dp: &pac::Peripherals) -> Result<(), u32> {
+    let spi = &dp.SPI2;
+    for (index, &expected) in SPI_PATTERN.iter().enumerate() {
+        while spi.sr.read().txe().is_not_empty() {}
+        spi.dr.write(|w| w.dr().bits(expected as u16));
+        while spi.sr.read().rxne().is_empty() {}
+        let received = spi.dr.read().dr().bits() as u8;
+        if received != expected {
+            return Err((index as u32) << 8 | received as u32);
+        }
+    }
+    Ok(())
+}
+
+// 失败时返回以 mV 为单位的 V_{DDA}，转换超时返回 0
+fn test_adc_reference(dp: &pac::Peripherals) -> Result<(), u32> {
+    let adc = &dp.ADC1;
+    adc.sqr3.modify(|_, w| unsafe { w.sq1().bits(17) });
+    adc.cr2.modify(|_, w| w.swstart().start());
+
+    let start = ticker::millis();
+    while adc.sr.read().eoc().bit_is_clear() {
+        if ticker::millis().wrapping_sub(start) > 2 {
+            return Err(0);
+        }
+    }
+    let raw = adc.dr.read().data().bits() as u32;
+    if raw == 0 {
+        return Err(0);
+    }
+
+    let vrefint_cal = unsafe { VREFINT_CAL.read_volatile() } as u32;
+    let vdda_mv = 3300 * vrefint_cal / raw;
+    rprintln!("V_DDA {} mV", vdda_mv);
+
+    if (3000..=3600).contains(&vdda_mv) {
+        Ok(())
+    } else {
+        Err(vdda_mv)
+    }
+}
+
+// 失败时返回 crypto_core::vectors::CHECKS 中第一个失败项的下标
+fn test_crypto(_dp: &pac::Peripherals) -> Result<(), u32> {
+    crypto_core::vectors::run_all().map_err(|index| {
+        rprintln!("{} failed", crypto_core::vectors::CHECKS[index].name);
+        index as u32
+    })
+}
+
+// 以轮询的方式从 USART1 发送
+struct Usart1Writer<'a> {
+    usart: &'a pac::USART1,
+}
+
+impl Write for Usart1Writer<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            while self.usart.sr.read().txe().bit_is_clear() {}
+            self.usart.dr.write(|w| w.dr().bits(byte as u16));
+        }
+        Ok(())
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 与 s21c02 相同，I2C1 使用 PB8/PB9，100 kHz
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
+
+// SPI2 作为主机，Mode 0，8 位，12 MHz / 16 = 750 kHz，只用到了 PB13 ~ PB15
+fn setup_spi2(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.spi2en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh13().af5();
+        w.afrh14().af5();
+        w.afrh15().af5();
+        w
+    });
+    // 短接线没有接好时，MISO 读到的是固定的 0x00，而不是随机的值
+    gpiob.pupdr.modify(|_, w| w.pupdr14().pull_down());
+    gpiob.moder.modify(|_, w| {
+        w.moder13().alternate();
+        w.moder14().alternate();
+        w.moder15().alternate();
+        w
+    });
+
+    dp.SPI2.cr1.write(|w| {
+        w.mstr().master();
+        w.ssm().enabled();
+        w.ssi().slave_not_selected();
+        w.dff().eight_bit();
+        w.br().div16();
+        w
+    });
+    dp.SPI2.cr1.modify(|_, w| w.spe().enabled());
+}
+
+// 只用到了 V_{REFINT}，采样时间至少需要 10 us
+fn setup_adc(dp: &pac::Peripherals) {
+    dp.RCC.apb2enr.modify(|_, w| w.adc1en().enabled());
+
+    dp.ADC_COMMON.ccr.modify(|_, w| {
+        w.adcpre().div2();
+        w.tsvrefe().enabled();
+        w
+    });
+
+    let adc = &dp.ADC1;
+    // 温度传感器（通道 17）采样 480 个周期，写位而不是 cycles480()，后者在 F401/F411 的 pac 中不存在
+    adc.smpr1
+        .modify(|r, w| unsafe { w.bits(r.bits() | 0b111 << (3 * (17 - 10))) });
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+    adc.cr2.modify(|_, w| w.adon().enabled());
+
+    // V_{REFINT} 从启用到稳定需要最多 10 us
+    cortex_m::asm::delay(12 * 10);
+}
+
+fn setup_led(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.GPIOC.moder.modify(|_, w| w.moder13().output());
+}
+
+// USART1 收发，参数为 115200 8N1
+fn setup_usart1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| {
+        w.afrh9().af7();
+        w.afrh10().af7();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr9().pull_up();
+        w.pupdr10().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let serial1 = &dp.USART1;
+
+    serial1.cr1.modify(|_, w| w.ue().enabled());
+
+    // 12 MHz / (16 * 115200) = 6.51，整数部分为 6，小数部分为 0.51 * 16 ≈ 8
+    serial1.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    serial1.cr1.modify(|_, w| {
+        w.te().enabled();
+        w.re().enabled();
+        w
+    });
+}
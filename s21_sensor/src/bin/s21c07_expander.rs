@@ -0,0 +1,238 @@
+//! 通过 MCP23017 扩展 GPIO，并用扩展出来的引脚作为 MCP4131 数字电位器的片选
+//!
+//! MCP23017 的 GPA0 ~ GPA3 接了 4 个按键，开启电平变化中断，INT 接到 PA8，由 utils::exti 回调 mcp23017::on_interrupt
+//! 主循环收到变化之后：
+//!
+//! GPA0 按下  电位器减少一档
+//! GPA1 按下  电位器增加一档
+//! GPA2 按下  关断/恢复电位器
+//! GPA3 按下  电位器回到中间
+//!
+//! GPB4 ~ GPB7 的 LED 显示 GPA0 ~ GPA3 当前是否被按下
+//!
+//! 电位器的片选接在 MCP23017 的 GPB0 上，对于 utils::mcp41xx 来说，它和一个普通的 GPIO 没有区别
+//! 每次操作之后读回滑片的位置并打印，电位器的两端接 3.3V 与 GND 时，可以用万用表测量滑片的电压来对照
+//!
+//! 接线图：
+//!
+//! MCP23017
+//!  PB8 <-> SCL (I2C1)
+//!  PB9 <-> SDA (I2C1)
+//!  PA8 <-> INTA（开漏输出，使用 PA8 的内部上拉）
+//!  A0/A1/A2 接 GND，地址为 0x20；RESET 接 3.3V
+//!  GPA0 ~ GPA3 <-> 按键 <-> GND
+//!  GPB4 ~ GPB7 -> 1k -> LED -> GND
+//!
+//! MCP4131
+//!  GPB0 (MCP23017)  -> CS
+//!  PB13 (SPI2_SCK)  -> SCK
+//!  PB15 (SPI2_MOSI) -> 4.7k -> SDI/SDO
+//!  PB14 (SPI2_MISO) <- SDI/SDO
+//!  P0A 接 3.3V，P0B 接 GND，P0W 接万用表
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    exti::{self, Port, Trigger},
+    mcp23017::{self, Mcp23017},
+    mcp41xx::{Mcp41xx, Resolution, Wiper},
+};
+
+const INT_PIN: u8 = 8;
+
+const BUTTON_DOWN: u8 = 0;
+const BUTTON_UP: u8 = 1;
+const BUTTON_SHUTDOWN: u8 = 2;
+const BUTTON_CENTER: u8 = 3;
+const BUTTONS: u16 = 0x000F;
+
+const POT_CS_PIN: u8 = 8;
+// GPB4 ~ GPB7
+const LED_SHIFT: u16 = 12;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    setup_i2c1(&dp);
+    setup_spi2(&dp);
+    setup_int_pin(&dp);
+
+    let expander = match Mcp23017::new(&dp.I2C1, mcp23017::ADDR_BASE) {
+        Ok(expander) => expander,
+        Err(e) => {
+            rprintln!("MCP23017 not found: {:?}", e);
+            #[allow(clippy::empty_loop)]
+            loop {}
+        }
+    };
+
+    for button in [BUTTON_DOWN, BUTTON_UP, BUTTON_SHUTDOWN, BUTTON_CENTER] {
+        expander.input_pin(button, true).unwrap();
+        expander.set_interrupt(button, true).unwrap();
+    }
+    for led in 0..4 {
+        expander.output_pin(LED_SHIFT as u8 + led, false).unwrap();
+    }
+
+    exti::register(
+        &dp,
+        Port::A,
+        INT_PIN,
+        Trigger::Falling,
+        mcp23017::on_interrupt,
+    )
+    .unwrap();
+
+    let cs = expander.output_pin(POT_CS_PIN, true).unwrap();
+    let mut pot = Mcp41xx::new(&dp.SPI2, cs, Resolution::Bits7).unwrap();
+    let center = pot.resolution().full_scale() / 2;
+    if let Err(e) = pot.set_wiper(Wiper::W0, center) {
+        rprintln!("MCP4131 not responding: {:?}", e);
+    }
+
+    rprintln!("ready");
+
+    loop {
+        let change = match expander.poll_interrupt() {
+            Ok(Some(change)) => change,
+            Ok(None) => continue,
+            Err(e) => {
+                rprintln!("expander error: {:?}", e);
+                continue;
+            }
+        };
+
+        // 按键按下时为低电平
+        let down = !change.levels & BUTTONS;
+        expander
+            .modify_port(BUTTONS << LED_SHIFT, down << LED_SHIFT)
+            .ok();
+
+        // 只处理按下，松开时什么都不做
+        let pressed = change.pins & down;
+        let result = if pressed & (1 << BUTTON_DOWN) != 0 {
+            pot.decrement(Wiper::W0)
+        } else if pressed & (1 << BUTTON_UP) != 0 {
+            pot.increment(Wiper::W0)
+        } else if pressed & (1 << BUTTON_SHUTDOWN) != 0 {
+            pot.is_shutdown(Wiper::W0)
+                .and_then(|shutdown| pot.shutdown(Wiper::W0, !shutdown))
+        } else if pressed & (1 << BUTTON_CENTER) != 0 {
+            pot.set_wiper(Wiper::W0, center)
+        } else {
+            continue;
+        };
+
+        if let Err(e) = result {
+            rprintln!("pot error: {:?}", e);
+            continue;
+        }
+
+        match (pot.wiper(Wiper::W0), pot.is_shutdown(Wiper::W0)) {
+            (Ok(wiper), Ok(shutdown)) => rprintln!(
+                "wiper {}/{}{}",
+                wiper,
+                pot.resolution().full_scale(),
+                if shutdown { " (shutdown)" } else { "" }
+            ),
+            (Err(e), _) | (_, Err(e)) => rprintln!("pot error: {:?}", e),
+        }
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 与 s21c02 相同，I2C1 使用 PB8/PB9，100 kHz
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
+
+// SPI2 作为主机，Mode 0，8 位，12 MHz / 64 = 187.5 kHz
+// MCP4131 的 SDI/SDO 共用一个引脚，读取时 SCK 不能超过 250 kHz
+// 片选由 MCP23017 负责，这里只用到了 PB13 ~ PB15
+fn setup_spi2(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.spi2en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh13().af5();
+        w.afrh14().af5();
+        w.afrh15().af5();
+        w
+    });
+    // 芯片没有接好时，读到的 CMDERR 为 0，可以据此发现问题
+    gpiob.pupdr.modify(|_, w| w.pupdr14().pull_down());
+    gpiob.moder.modify(|_, w| {
+        w.moder13().alternate();
+        w.moder14().alternate();
+        w.moder15().alternate();
+        w
+    });
+
+    dp.SPI2.cr1.write(|w| {
+        w.mstr().master();
+        w.ssm().enabled();
+        w.ssi().slave_not_selected();
+        w.dff().eight_bit();
+        w.cpol().idle_low();
+        w.cpha().first_edge();
+        w.br().div64();
+        w
+    });
+    dp.SPI2.cr1.modify(|_, w| w.spe().enabled());
+}
+
+// MCP23017 的 INT 为开漏输出，PA8 使用内部上拉
+fn setup_int_pin(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.GPIOA.pupdr.modify(|_, w| w.pupdr8().pull_up());
+    dp.GPIOA.moder.modify(|_, w| w.moder8().input());
+}
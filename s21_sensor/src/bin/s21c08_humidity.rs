@@ -0,0 +1,159 @@
+//! SHT3x/SHT4x 温湿度传感器
+//!
+//! 驱动见 utils::sht，这里把它注册到 Scheduler 中，读数通过 RTT 打印，并在 LCD1602 上轮流显示 sht.temp 与 sht.rh
+//!
+//! 启动时打印传感器的序列号，之后每 2 s 采样一次
+//! 为了演示加热器的效果，HeaterCycle 每采样 HEATER_CYCLE 次，就开启加热器 HEATER_ON 次采样的时间，
+//! 加热期间可以看到温度上升、湿度下降，关闭之后再慢慢恢复，每次开关加热器时都会在 RTT 中打印一行
+//!
+//! 使用 SHT4x 时，把 MODEL 改为 Model::Sht4x 即可，SHT4x 没有周期测量模式，PERIODIC 需要改为 false
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//!
+//! STM32 <-> SHT3x/SHT4x 模块
+//!  3.3V <-> VCC
+//!   PB8 <-> SCL (I2C1)
+//!   PB9 <-> SDA (I2C1)
+//!   GND <-> GND, ADDR（SHT3x 的 ADDR 接 GND 时地址为 0x44）
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    lcd1602::Lcd1602,
+    sensor::{
+        scheduler::Scheduler,
+        sink::{LcdPageSink, RttSink, Sink},
+        Sensor, SensorError,
+    },
+    sht::{self, Mode, Model, Rate, Sht, ShtMeasurement},
+    ticker,
+};
+
+const MODEL: Model = Model::Sht3x;
+// 仅 SHT3x：让芯片以 1 Hz 自行测量，采样时只读取结果
+const PERIODIC: bool = true;
+
+const SAMPLE_PERIOD_MS: u32 = 2000;
+// 每 30 次采样（1 分钟）中，有 5 次采样（10 s）开启加热器
+const HEATER_CYCLE: u32 = 30;
+const HEATER_ON: u32 = 5;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_i2c1(&dp);
+
+    let mut sht = Sht::new(&dp.I2C1, MODEL, sht::ADDR_DEFAULT).unwrap();
+    rprintln!(
+        "{:?} serial number {:08X}",
+        sht.model(),
+        sht.serial_number()
+    );
+    if PERIODIC {
+        sht.set_mode(Mode::Periodic(Rate::Mps1)).unwrap();
+    }
+
+    let mut heater_cycle = HeaterCycle { sht, count: 0 };
+
+    let mut scheduler = Scheduler::<1>::new();
+    scheduler
+        .register(&mut heater_cycle, SAMPLE_PERIOD_MS, 0)
+        .ok()
+        .unwrap();
+
+    let mut rtt_sink = RttSink;
+    // 一次采样给出温度与湿度两个读数
+    let mut lcd_sink = LcdPageSink::<_, 2>::new(Lcd1602::new(&dp), 2000);
+
+    rprintln!("humidity sensor started");
+
+    loop {
+        let sinks: &mut [&mut dyn Sink] = &mut [&mut rtt_sink, &mut lcd_sink];
+        scheduler.poll(ticker::millis(), sinks);
+    }
+}
+
+// 按照采样的次数周期性地开关加热器，其余的事情都交给 Sht
+struct HeaterCycle<'a> {
+    sht: Sht<'a>,
+    count: u32,
+}
+
+impl Sensor for HeaterCycle<'_> {
+    type Output = ShtMeasurement;
+
+    fn name(&self) -> &'static str {
+        self.sht.name()
+    }
+
+    fn sample(&mut self) -> Result<ShtMeasurement, SensorError> {
+        let heat = self.count % HEATER_CYCLE < HEATER_ON;
+        self.count = self.count.wrapping_add(1);
+
+        if heat != self.sht.heater() {
+            self.sht.set_heater(heat)?;
+            rprintln!("heater {}", if heat { "on" } else { "off" });
+        }
+
+        self.sht.sample()
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 与 s21c02 相同，I2C1 使用 PB8/PB9，100 kHz
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
@@ -0,0 +1,187 @@
+//! AS5600 磁编码器
+//!
+//! 驱动见 utils::as5600，启动时先打印磁铁的状态（是否检测到磁铁、AGC 与磁场强度），
+//! 然后把当前位置设为 0°（只写入 ZPOS，掉电丢失，不会烧写 OTP）
+//!
+//! 之后每 100 ms 采样一次，角度与角速度通过 RTT 打印，并在 LCD1602 上轮流显示 angle.angle 与 angle.speed
+//! 每秒额外打印一次 VelocityEstimator 累计的连续位置与圈数
+//!
+//! 磁铁没有放好时，采样会返回 NotReady，此时可以对照 RTT 中打印的磁铁状态调整磁铁的高度
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//!
+//! STM32 <-> AS5600 模块
+//!  3.3V <-> VCC（模块上 3.3V 模式的跳线需要短接）
+//!   PB8 <-> SCL (I2C1)
+//!   PB9 <-> SDA (I2C1)
+//!   GND <-> GND, DIR（DIR 接 GND 时顺时针转动角度增加）
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    as5600::{As5600, As5600Measurement, FastThreshold, SlowFilter},
+    lcd1602::Lcd1602,
+    sensor::{
+        scheduler::Scheduler,
+        sink::{LcdPageSink, RttSink, Sink},
+        Sensor, SensorError,
+    },
+    ticker,
+};
+
+const SAMPLE_PERIOD_MS: u32 = 100;
+// 每 10 次采样（1 s）打印一次连续位置
+const POSITION_REPORT: u32 = 10;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_i2c1(&dp);
+
+    let mut encoder = match As5600::new(&dp.I2C1) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            rprintln!("AS5600 not found: {:?}", e);
+            #[allow(clippy::empty_loop)]
+            loop {}
+        }
+    };
+
+    let status = encoder.diagnostics().unwrap();
+    rprintln!(
+        "magnet {}{}{}, AGC {}, magnitude {}",
+        if status.detected {
+            "detected"
+        } else {
+            "not detected"
+        },
+        if status.too_weak { ", too weak" } else { "" },
+        if status.too_strong {
+            ", too strong"
+        } else {
+            ""
+        },
+        status.agc,
+        status.magnitude
+    );
+    rprintln!(
+        "zero position burns left: {}",
+        encoder.burns_left().unwrap()
+    );
+
+    // 静止时噪声小，转动时又能及时跟上
+    encoder
+        .set_filter(SlowFilter::X8, FastThreshold::Lsb6)
+        .unwrap();
+    if status.detected {
+        encoder.set_zero_here().unwrap();
+    }
+
+    let mut report = PositionReport { encoder, count: 0 };
+
+    let mut scheduler = Scheduler::<1>::new();
+    scheduler
+        .register(&mut report, SAMPLE_PERIOD_MS, 0)
+        .ok()
+        .unwrap();
+
+    let mut rtt_sink = RttSink;
+    // 一次采样给出角度与角速度两个读数
+    let mut lcd_sink = LcdPageSink::<_, 2>::new(Lcd1602::new(&dp), 2000);
+
+    rprintln!("angle sensor started");
+
+    loop {
+        let sinks: &mut [&mut dyn Sink] = &mut [&mut rtt_sink, &mut lcd_sink];
+        scheduler.poll(ticker::millis(), sinks);
+    }
+}
+
+// 每 POSITION_REPORT 次采样打印一次连续位置，其余的事情都交给 As5600
+struct PositionReport<'a> {
+    encoder: As5600<'a>,
+    count: u32,
+}
+
+impl Sensor for PositionReport<'_> {
+    type Output = As5600Measurement;
+
+    fn name(&self) -> &'static str {
+        self.encoder.name()
+    }
+
+    fn sample(&mut self) -> Result<As5600Measurement, SensorError> {
+        let measurement = self.encoder.sample()?;
+
+        self.count = self.count.wrapping_add(1);
+        if self.count.is_multiple_of(POSITION_REPORT) {
+            let velocity = self.encoder.velocity();
+            rprintln!(
+                "position {} deg, {} turns",
+                velocity.position(),
+                velocity.turns()
+            );
+        }
+
+        Ok(measurement)
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 与 s21c02 相同，I2C1 使用 PB8/PB9，100 kHz
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
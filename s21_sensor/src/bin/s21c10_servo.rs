@@ -0,0 +1,170 @@
+//! 同一套 Servo API 驱动 STM32 自己的 TIM 与 PCA9685 上的舵机
+//!
+//! 驱动见 utils::servo 与 utils::pca9685，两者都实现了 SetDutyCycle，sweep 只知道自己拿到的是一个 Servo，
+//! 不知道背后是直接写 CCR 还是一次 I2C 传输
+//!
+//! 两个舵机在 0° ~ 180° 之间来回转动，每 20 ms 转 2°，
+//! 每转完一个来回，PCA9685 进入 SLEEP 1 s，PCA9685 上的舵机此时没有脉冲，可以用手转动，
+//! 唤醒之后输出自动恢复，继续转动
+//!
+//! PCA9685 上另外 3 个通道接了 LED，分别演示 full on、25% 占空比、以及错开相位的 50% 占空比
+//!
+//! 接线图：
+//!
+//! STM32 <-> 舵机 1
+//!  PD12 (TIM4_CH1) -> 信号线
+//!
+//! STM32 <-> PCA9685 模块
+//!  3.3V <-> VCC
+//!   PB8 <-> SCL (I2C1)
+//!   PB9 <-> SDA (I2C1)
+//!   GND <-> GND, OE
+//!   A0~A5 不接（模块上已经下拉），地址为 0x40
+//!
+//! PCA9685 模块
+//!  PWM0  -> 舵机 2 信号线
+//!  PWM13 -> 1k -> LED -> GND
+//!  PWM14 -> 1k -> LED -> GND
+//!  PWM15 -> 1k -> LED -> GND
+//!
+//! 两个舵机都由外部的 5V 供电（PCA9685 模块的 V+），与 STM32 共地
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::{hal::pwm::SetDutyCycle, pac};
+
+mod utils;
+
+use utils::{
+    pca9685::{self, Pca9685},
+    servo::{self, Servo, ServoConfig, Tim4Channel},
+    ticker,
+};
+
+const STEP_DEG: usize = 2;
+const STEP_MS: u32 = 20;
+const SLEEP_MS: u32 = 1000;
+
+const PCA_SERVO_CHANNEL: u8 = 0;
+const LED_FULL_CHANNEL: u8 = 13;
+const LED_QUARTER_CHANNEL: u8 = 14;
+const LED_PHASE_CHANNEL: u8 = 15;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_i2c1(&dp);
+    servo::setup_tim4(&dp, servo::PERIOD_US);
+
+    // 舵机的周期为 20 ms，也就是 50 Hz
+    let pca = match Pca9685::new(&dp.I2C1, pca9685::ADDR_BASE, 1_000_000 / servo::PERIOD_US) {
+        Ok(pca) => pca,
+        Err(e) => {
+            rprintln!("PCA9685 not found: {:?}", e);
+            #[allow(clippy::empty_loop)]
+            loop {}
+        }
+    };
+    rprintln!(
+        "PCA9685 running at {} Hz, {} ns per tick",
+        pca.frequency(),
+        pca.tick_ns()
+    );
+
+    pca.set_full_on(LED_FULL_CHANNEL).unwrap();
+    pca.set_duty(LED_QUARTER_CHANNEL, 0, pca9685::TICKS / 4)
+        .unwrap();
+    pca.set_duty(LED_PHASE_CHANNEL, pca9685::TICKS / 2, pca9685::TICKS / 2)
+        .unwrap();
+
+    let mut tim_servo = Servo::new(
+        Tim4Channel::new(1).unwrap(),
+        servo::PERIOD_US,
+        ServoConfig::SG90,
+    );
+    let mut pca_servo = Servo::new(
+        pca.channel(PCA_SERVO_CHANNEL).unwrap(),
+        servo::PERIOD_US,
+        ServoConfig::SG90,
+    );
+
+    tim_servo.center().unwrap();
+    pca_servo.center().unwrap();
+    ticker::delay_ms(500);
+
+    rprintln!("servo sweep started");
+
+    loop {
+        let forward = (0..=180).step_by(STEP_DEG);
+        let backward = (0..=180).rev().step_by(STEP_DEG);
+        for angle in forward.chain(backward) {
+            sweep_step(&mut tim_servo, angle as f32);
+            sweep_step(&mut pca_servo, angle as f32);
+            ticker::delay_ms(STEP_MS);
+        }
+
+        rprintln!("PCA9685 sleep");
+        pca.sleep().unwrap();
+        ticker::delay_ms(SLEEP_MS);
+        pca.wake().unwrap();
+        rprintln!("PCA9685 wake");
+    }
+}
+
+// 不论舵机接在哪里，用法都是一样的
+fn sweep_step<P: SetDutyCycle>(servo: &mut Servo<P>, angle: f32) {
+    if let Err(e) = servo.set_angle(angle) {
+        rprintln!("servo error: {:?}", e);
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 与 s21c02 相同，I2C1 使用 PB8/PB9，100 kHz
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
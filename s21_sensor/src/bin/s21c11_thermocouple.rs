@@ -0,0 +1,249 @@
+//! 热电偶测温，并用 PID 控制一个加热器
+//!
+//! 热电偶的驱动见 utils::thermocouple，PID 见 utils::pid
+//! Scheduler 按转换时间周期性地读取热电偶，读数除了交给 RttSink 与 LcdPageSink，还交给 HeaterControl 这个 Sink：
+//! 它收到热端温度之后更新 PID，得到 0 ~ 1 的输出，再以 HEATER_WINDOW_MS 为周期，按比例接通固态继电器（时间比例控制）
+//! 固态继电器只能开关，不适合高频的 PWM，这样的慢速 PWM 对于热惯性很大的加热器已经足够了
+//!
+//! 热电偶出现故障（开路、短路），或者连续多次读取失败时，立即关闭加热器，并清空 PID 的状态，故障排除之后重新开始控制
+//!
+//! PID 的参数与加热器的功率、热容都有关系，这里的参数只是一个起点，需要根据实际的对象整定
+//!
+//! 使用 MAX31855 时，把 MODEL 改为 Model::Max31855，此时还会额外输出冷端温度 tc.cj
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//!
+//! STM32 <-> MAX6675/MAX31855 模块
+//!  3.3V <-> VCC
+//!  PB12 <-> CS
+//!  PB13 <-> SCK (SPI2)
+//!  PB14 <-> SO (SPI2_MISO)
+//!   GND <-> GND
+//!
+//! STM32 <-> 固态继电器（SSR）
+//!   PC8 -> DC+（输入端）
+//!   GND -> DC-
+//! 继电器的输出端串在加热器的电源回路中
+
+#![no_std]
+#![no_main]
+
+use core::convert::Infallible;
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::{
+    hal::digital::{ErrorType, OutputPin},
+    pac,
+};
+
+mod utils;
+
+use utils::{
+    lcd1602::Lcd1602,
+    pid::{Pid, PidConfig},
+    sensor::{
+        scheduler::{worth_reporting, Scheduler},
+        sink::{LcdPageSink, RttSink, Sink},
+        Reading, SensorError,
+    },
+    thermocouple::{Model, Thermocouple},
+    ticker,
+};
+
+const MODEL: Model = Model::Max6675;
+// 对最近 4 次读数取平均
+const AVERAGE: usize = 4;
+
+const SETPOINT_C: f32 = 60.0;
+const HEATER_WINDOW_MS: u32 = 2000;
+
+const PID_CONFIG: PidConfig = PidConfig {
+    kp: 0.05,
+    ki: 0.001,
+    kd: 0.2,
+    out_min: 0.0,
+    out_max: 1.0,
+};
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_spi2(&dp);
+    setup_heater(&dp);
+
+    let mut thermocouple = Thermocouple::<_, AVERAGE>::new(&dp.SPI2, Pb12, MODEL).unwrap();
+
+    let mut scheduler = Scheduler::<1>::new();
+    scheduler
+        .register(&mut thermocouple, MODEL.conversion_ms(), 0)
+        .ok()
+        .unwrap();
+
+    let mut rtt_sink = RttSink;
+    let mut lcd_sink = LcdPageSink::<_, 2>::new(Lcd1602::new(&dp), 2000);
+    let mut heater = HeaterControl::new(SETPOINT_C);
+
+    rprintln!("heater control started, setpoint {} C", SETPOINT_C);
+
+    loop {
+        let sinks: &mut [&mut dyn Sink] = &mut [&mut rtt_sink, &mut lcd_sink, &mut heater];
+        scheduler.poll(ticker::millis(), sinks);
+    }
+}
+
+struct HeaterControl {
+    pid: Pid,
+    setpoint: f32,
+    // 0 ~ 1，一个周期中接通的比例
+    output: f32,
+    last_update_ms: Option<u32>,
+    window_start_ms: u32,
+}
+
+impl HeaterControl {
+    fn new(setpoint: f32) -> Self {
+        Self {
+            pid: Pid::new(PID_CONFIG),
+            setpoint,
+            output: 0.0,
+            last_update_ms: None,
+            window_start_ms: ticker::millis(),
+        }
+    }
+}
+
+impl Sink for HeaterControl {
+    fn publish(&mut self, now_ms: u32, sensor_name: &'static str, reading: &Reading) {
+        if sensor_name != "tc" || reading.quantity != "temp" {
+            return;
+        }
+
+        let dt_s = match self.last_update_ms {
+            Some(last) => now_ms.wrapping_sub(last) as f32 / 1000.0,
+            None => 0.0,
+        };
+        self.last_update_ms = Some(now_ms);
+
+        self.output = self.pid.update(self.setpoint, reading.value, dt_s);
+        rprintln!("[{:>8}] heater {:.0} %", now_ms, self.output * 100.0);
+    }
+
+    fn error(
+        &mut self,
+        now_ms: u32,
+        _sensor_name: &'static str,
+        error: SensorError,
+        error_cnt: u32,
+    ) {
+        // 偶尔一次 NotReady 不影响控制，其他情况下宁可停止加热
+        if !worth_reporting(error, error_cnt) {
+            return;
+        }
+        if self.output > 0.0 || self.last_update_ms.is_some() {
+            rprintln!("[{:>8}] heater off: {:?}", now_ms, error);
+        }
+        self.output = 0.0;
+        self.last_update_ms = None;
+        self.pid.reset();
+    }
+
+    fn flush(&mut self, now_ms: u32) {
+        let elapsed = now_ms.wrapping_sub(self.window_start_ms);
+        if elapsed >= HEATER_WINDOW_MS {
+            self.window_start_ms = now_ms;
+        }
+        let on_ms = (self.output * HEATER_WINDOW_MS as f32) as u32;
+        set_heater(elapsed % HEATER_WINDOW_MS < on_ms);
+    }
+}
+
+// PB12 作为热电偶模块的片选
+struct Pb12;
+
+impl ErrorType for Pb12 {
+    type Error = Infallible;
+}
+
+impl OutputPin for Pb12 {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let gpiob = unsafe { &*pac::GPIOB::ptr() };
+        gpiob.bsrr.write(|w| w.br12().reset());
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let gpiob = unsafe { &*pac::GPIOB::ptr() };
+        gpiob.bsrr.write(|w| w.bs12().set());
+        Ok(())
+    }
+}
+
+fn set_heater(on: bool) {
+    let gpioc = unsafe { &*pac::GPIOC::ptr() };
+    if on {
+        gpioc.bsrr.write(|w| w.bs8().set());
+    } else {
+        gpioc.bsrr.write(|w| w.br8().reset());
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// SPI2 作为主机，Mode 0，8 位，12 MHz / 4 = 3 MHz，MAX6675 的 SCK 最高 4.3 MHz
+// 芯片只输出数据，因此只用到了 PB13/PB14，片选 PB12 由 Pb12 控制
+fn setup_spi2(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.spi2en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.bsrr.write(|w| w.bs12().set());
+    gpiob.afrh.modify(|_, w| {
+        w.afrh13().af5();
+        w.afrh14().af5();
+        w
+    });
+    // 模块没有接好时，MISO 读到的是全 1，驱动可以据此发现问题
+    gpiob.pupdr.modify(|_, w| w.pupdr14().pull_up());
+    gpiob.moder.modify(|_, w| {
+        w.moder12().output();
+        w.moder13().alternate();
+        w.moder14().alternate();
+        w
+    });
+
+    dp.SPI2.cr1.write(|w| {
+        w.mstr().master();
+        w.ssm().enabled();
+        w.ssi().slave_not_selected();
+        w.dff().eight_bit();
+        w.cpol().idle_low();
+        w.cpha().first_edge();
+        w.br().div4();
+        w
+    });
+    dp.SPI2.cr1.modify(|_, w| w.spe().enabled());
+}
+
+// PC8 推挽输出，驱动固态继电器，上电时保持关闭
+fn setup_heater(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.GPIOC.bsrr.write(|w| w.br8().reset());
+    dp.GPIOC.moder.modify(|_, w| w.moder8().output());
+}
@@ -0,0 +1,193 @@
+//! 模拟量传感器的换算
+//!
+//! 换算见 utils::analog，每个 ADC 通道声明为一个 AnalogInput，声明时就给定它的换算方式：
+//!
+//! - ntc：PC0 (ADC1_IN10)，10k NTC，B 值 3950，与 10k 电阻分压
+//! - ntc2：PC1 (ADC1_IN11)，同样的 NTC，使用手册中三个温度点求出的 Steinhart–Hart 系数
+//! - vin：PC2 (ADC1_IN12)，100k/22k 分压，测量最高约 18 V 的电压
+//! - pot：PC3 (ADC1_IN13)，电位器，0.1 ~ 3.2 V 换算为 0 ~ 100 %
+//! - ir：PC4 (ADC1_IN14)，GP2Y0A21 红外测距，按手册中的曲线查表
+//!
+//! 两个 NTC 接在同一个位置时，可以对比两种模型在 25 °C 以外的差别
+//!
+//! 读数通过 RttSink 打印，同时在 LCD 上轮流显示
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//!
+//! NTC，两路相同
+//! 3.3V <-> 10k 电阻 <-> PC0/PC1 <-> NTC <-> GND
+//!
+//! 被测电压，注意不要超过 18 V
+//! 被测电压 <-> 100k 电阻 <-> PC2 <-> 22k 电阻 <-> GND
+//!
+//! 电位器
+//! 3.3V <-> 电位器一端，滑动端 <-> PC3，另一端 <-> GND
+//!
+//! GP2Y0A21（5V 供电，输出不超过 3.1 V）
+//!   5V <-> VCC
+//!  PC4 <-> Vo
+//!  GND <-> GND
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    analog::{AnalogInput, Beta, Conversion, NtcModel, Placement, SteinhartHart, Table},
+    lcd1602::Lcd1602,
+    sensor::{
+        scheduler::Scheduler,
+        sink::{LcdPageSink, RttSink, Sink},
+    },
+    ticker,
+};
+
+const NTC_FIXED: f32 = 10_000.0;
+
+// 10k B3950 NTC 手册中的阻值表，取其中三个点：0 °C、25 °C、85 °C
+const NTC_POINTS: [(f32, f32); 3] = [(32_960.0, 0.0), (10_000.0, 25.0), (1_451.0, 85.0)];
+
+// GP2Y0A21 的输出电压与距离，单位为 V 与 mm，10 cm 以内输出会反过来下降，无法区分
+static IR_CURVE: [(f32, f32); 9] = [
+    (2.30, 100.0),
+    (1.65, 150.0),
+    (1.30, 200.0),
+    (0.92, 300.0),
+    (0.74, 400.0),
+    (0.60, 500.0),
+    (0.50, 600.0),
+    (0.45, 700.0),
+    (0.40, 800.0),
+];
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_adc(&dp);
+
+    let steinhart_hart = SteinhartHart::from_points(NTC_POINTS).unwrap();
+    rprintln!(
+        "Steinhart-Hart: A {:e}, B {:e}, C {:e}",
+        steinhart_hart.a,
+        steinhart_hart.b,
+        steinhart_hart.c
+    );
+
+    let mut ntc = AnalogInput::new(
+        &dp.ADC1,
+        10,
+        "ntc",
+        Conversion::Ntc {
+            model: NtcModel::Beta(Beta::NTC_10K_3950),
+            fixed: NTC_FIXED,
+            placement: Placement::Low,
+        },
+    );
+    let mut ntc2 = AnalogInput::new(
+        &dp.ADC1,
+        11,
+        "ntc2",
+        Conversion::Ntc {
+            model: NtcModel::SteinhartHart(steinhart_hart),
+            fixed: NTC_FIXED,
+            placement: Placement::Low,
+        },
+    );
+    let mut vin = AnalogInput::new(
+        &dp.ADC1,
+        12,
+        "vin",
+        Conversion::Divider {
+            r_top: 100_000.0,
+            r_bottom: 22_000.0,
+        },
+    );
+    let mut pot = AnalogInput::new(
+        &dp.ADC1,
+        13,
+        "pot",
+        Conversion::Percent {
+            min: 0.1,
+            max: 3.2,
+            quantity: "pos",
+        },
+    );
+    let mut ir = AnalogInput::new(
+        &dp.ADC1,
+        14,
+        "ir",
+        Conversion::Table {
+            table: Table::new(&IR_CURVE),
+            quantity: "dist",
+            unit: "mm",
+        },
+    );
+
+    let mut scheduler = Scheduler::<5>::new();
+    scheduler.register(&mut ntc, 1000, 0).ok().unwrap();
+    scheduler.register(&mut ntc2, 1000, 10).ok().unwrap();
+    scheduler.register(&mut vin, 500, 20).ok().unwrap();
+    scheduler.register(&mut pot, 200, 30).ok().unwrap();
+    scheduler.register(&mut ir, 200, 40).ok().unwrap();
+
+    let mut rtt_sink = RttSink;
+    let mut lcd_sink = LcdPageSink::<_, 5>::new(Lcd1602::new(&dp), 2000);
+
+    loop {
+        let sinks: &mut [&mut dyn Sink] = &mut [&mut rtt_sink, &mut lcd_sink];
+        scheduler.poll(ticker::millis(), sinks);
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+fn setup_adc(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.adc1en().enabled());
+
+    dp.GPIOC.moder.modify(|_, w| {
+        w.moder0().analog();
+        w.moder1().analog();
+        w.moder2().analog();
+        w.moder3().analog();
+        w.moder4().analog();
+        w
+    });
+
+    dp.ADC_COMMON.ccr.modify(|_, w| w.adcpre().div2());
+
+    let adc = &dp.ADC1;
+
+    // 分压电阻与 NTC 的阻抗都不小，给最长的采样时间
+    // 通道 10 ~ 14 依次占 SMPR1 的 3 位，全部为 0b111（480 个周期）
+    // F401/F411 的 pac 中这些字段没有 cycles480()，所以直接写位
+    let smp_480 = (0..5).fold(0, |bits, i| bits | 0b111 << (3 * i));
+    adc.smpr1
+        .modify(|r, w| unsafe { w.bits(r.bits() | smp_480) });
+
+    // 序列长度为 1，每次采样前再修改 SQ1
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+
+    adc.cr2.modify(|_, w| w.adon().enabled());
+}
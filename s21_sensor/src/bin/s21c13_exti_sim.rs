@@ -0,0 +1,207 @@
+//! 用软件触发的 EXTI 事件测试中断的处理路径，不需要任何外部信号
+//!
+//! 原理见 utils::exti_sim，测试框架见 utils::selftest
+//!
+//! E1 keypad     依次触发 EXTI0 ~ EXTI3，utils::keypad 的中断处理函数应该清除 PR
+//! E2 dispatch   触发 EXTI5，utils::exti 应该调用 PB5 上注册的回调
+//! E3 rtc alarm  触发 EXTI17，RTC 闹钟的中断处理函数应该运行一次，即便 RTC_ISR 中的 ALRAF 并没有被置位
+//! E4 masked     EXTI6 没有使能，触发它应该被拒绝，中断处理函数不应该运行
+//!
+//! 结果输出到 RTT，并由 LED 指示：全部通过时 1 Hz 均匀闪烁，否则快闪的次数就是第一个失败项的错误码
+//!
+//! 不需要连接按键，软件触发时引脚的电平不会变化，因此 keypad 不会产生按键事件
+//!
+//! 接线图：
+//!
+//! PC13 -> 1k -> LED -> GND
+
+#![no_std]
+#![no_main]
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use panic_rtt_target as _;
+use rtt_target::rtt_init_print;
+use stm32f4xx_hal::pac::{self, interrupt, NVIC};
+
+mod utils;
+
+use utils::{
+    exti::{self, Port, Trigger},
+    exti_sim::{self, SimError},
+    keypad,
+    selftest::{self, TestCase},
+    ticker,
+};
+
+const TESTS: [TestCase<pac::Peripherals>; 4] = [
+    TestCase {
+        name: "keypad",
+        code: 1,
+        run: test_keypad,
+    },
+    TestCase {
+        name: "dispatch",
+        code: 2,
+        run: test_dispatch,
+    },
+    TestCase {
+        name: "rtc alarm",
+        code: 3,
+        run: test_rtc_alarm,
+    },
+    TestCase {
+        name: "masked",
+        code: 4,
+        run: test_masked,
+    },
+];
+
+// 中断处理函数在几个微秒之内就会运行，这里留足余量
+const TIMEOUT_MS: u32 = 5;
+
+const DISPATCH_PIN: u8 = 5;
+const MASKED_LINE: u8 = 6;
+
+// RTC 闹钟的中断处理函数运行的次数
+static G_ALARMS: AtomicU32 = AtomicU32::new(0);
+
+// 只有 F413 的 pac 把 RTC Alarm 的中断叫做 EXTI17_RTC_ALARM，其它芯片为 RTC_ALARM
+#[cfg(feature = "stm32f413")]
+const RTC_ALARM_IRQ: interrupt = interrupt::EXTI17_RTC_ALARM;
+#[cfg(not(feature = "stm32f413"))]
+const RTC_ALARM_IRQ: interrupt = interrupt::RTC_ALARM;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_led(&dp);
+
+    keypad::setup(&dp);
+    setup_dispatch(&dp);
+    setup_rtc_alarm_line(&dp);
+
+    let summary = selftest::run(&TESTS, &dp, None, &mut RttOnly);
+
+    loop {
+        let on = summary.led_on(ticker::millis());
+        dp.GPIOC
+            .bsrr
+            .write(|w| if on { w.bs13().set() } else { w.br13().reset() });
+    }
+}
+
+fn test_keypad(_dp: &pac::Peripherals) -> Result<(), u32> {
+    for line in 0..4 {
+        exti_sim::check(line, TIMEOUT_MS)?;
+    }
+    Ok(())
+}
+
+// 失败时，若 PR 已经被清除但回调没有被调用，detail 为回调被调用的次数
+fn test_dispatch(_dp: &pac::Peripherals) -> Result<(), u32> {
+    let before = exti_sim::hits(DISPATCH_PIN);
+    exti_sim::check(DISPATCH_PIN, TIMEOUT_MS)?;
+    let after = exti_sim::hits(DISPATCH_PIN);
+    if after == before + 1 {
+        Ok(())
+    } else {
+        Err(after - before)
+    }
+}
+
+fn test_rtc_alarm(_dp: &pac::Peripherals) -> Result<(), u32> {
+    let before = G_ALARMS.load(Ordering::Relaxed);
+    exti_sim::check(exti_sim::LINE_RTC_ALARM, TIMEOUT_MS)?;
+    let after = G_ALARMS.load(Ordering::Relaxed);
+    if after == before + 1 {
+        Ok(())
+    } else {
+        Err(after - before)
+    }
+}
+
+// 失败时 detail 为 fire 的结果，0 表示触发成功了
+fn test_masked(_dp: &pac::Peripherals) -> Result<(), u32> {
+    match exti_sim::fire(MASKED_LINE) {
+        Err(SimError::Masked) => Ok(()),
+        Err(e) => Err(e as u32),
+        Ok(()) => Err(0),
+    }
+}
+
+// PB5 下拉输入，上升沿触发，回调只记录调用的次数
+fn setup_dispatch(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.GPIOB.pupdr.modify(|_, w| w.pupdr5().pull_down());
+    dp.GPIOB.moder.modify(|_, w| w.moder5().input());
+
+    exti::register(dp, Port::B, DISPATCH_PIN, Trigger::Rising, exti_sim::record).unwrap();
+}
+
+// 与 s07c01 相同，RTC 闹钟通过上升沿触发的 EXTI17 传递给 NVIC
+// 这里不需要真的启动 RTC，测试的只是中断的处理路径
+fn setup_rtc_alarm_line(dp: &pac::Peripherals) {
+    let line = 1 << exti_sim::LINE_RTC_ALARM;
+    let exti = &dp.EXTI;
+    exti.rtsr.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+    exti.pr.write(|w| unsafe { w.bits(line) });
+    exti.imr.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+
+    unsafe { NVIC::unmask(RTC_ALARM_IRQ) };
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn EXTI17_RTC_ALARM() {
+    on_rtc_alarm();
+}
+
+#[cfg(not(feature = "stm32f413"))]
+#[interrupt]
+fn RTC_ALARM() {
+    on_rtc_alarm();
+}
+
+fn on_rtc_alarm() {
+    let dp = unsafe { pac::Peripherals::steal() };
+    dp.EXTI.pr.write(|w| w.pr17().clear());
+
+    // 软件触发时 ALRAF 为 0，不能因此认为这次中断无效
+    if dp.RTC.isr.read().alraf().bit_is_set() {
+        dp.RTC.isr.modify(|_, w| w.alraf().clear());
+    }
+
+    G_ALARMS.fetch_add(1, Ordering::Relaxed);
+}
+
+// 结果已经由 selftest::run 输出到 RTT，这里没有串口，丢弃即可
+struct RttOnly;
+
+impl Write for RttOnly {
+    fn write_str(&mut self, _s: &str) -> core::fmt::Result {
+        Ok(())
+    }
+}
+
+// 切换到 HSE 时钟源，utils::ticker 假设 TIM 的时钟为 12 MHz
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+fn setup_led(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.GPIOC.moder.modify(|_, w| w.moder13().output());
+}
@@ -0,0 +1,104 @@
+//! 多个使用者共用一个 TIM6 延时
+//!
+//! 原理见 utils::delay
+//!
+//! - 主循环用一个 Delay 每隔 500 ms 刷新一次 LCD1602，utils::lcd1602 内部的延时也自动改用了 TIM6
+//! - SysTick 每 1 ms 中断一次，在中断里用另一个 Delay 在 PA8 上输出一个 5 us 的脉冲
+//! - SysTick 留给了中断本身，没有任何驱动需要独占它
+//!
+//! 主循环的延时经常被 SysTick 中断打断，LCD 的第二行显示由 utils::ticker 测得的实际延时，应该始终在 500 ms 左右，
+//! PA8 上的脉冲宽度可以用逻辑分析仪检查
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s21c01 相同
+//! PA8 -> 逻辑分析仪
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+
+use cortex_m::peripheral::syst::SystClkSource;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::delay::{Delay, DelayProvider};
+use utils::{
+    lcd1602::Lcd1602,
+    sensor::sink::{LineBuf, TextPanel},
+    ticker,
+};
+
+chip_caps::require!(TIM6_TIM7);
+
+const PERIOD_MS: u32 = 500;
+const PULSE_US: u32 = 5;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    let provider = DelayProvider::setup(&dp);
+    setup_pulse_pin(&dp);
+
+    let mut lcd = Lcd1602::new(&dp);
+    let delay = provider.handle();
+
+    // 12 MHz / 12000 = 1 kHz
+    cp.SYST.set_clock_source(SystClkSource::Core);
+    cp.SYST.set_reload(12_000 - 1);
+    cp.SYST.clear_current();
+    cp.SYST.enable_interrupt();
+    cp.SYST.enable_counter();
+
+    let mut count = 0u32;
+    loop {
+        let start = ticker::micros();
+        delay.ms(PERIOD_MS);
+        let elapsed = ticker::micros() - start;
+
+        count += 1;
+        let mut line = LineBuf::<16>::new();
+        write!(line, "tick {}", count).ok();
+        lcd.write_line(0, line.as_bytes());
+        line.clear();
+        write!(line, "{} us", elapsed).ok();
+        lcd.write_line(1, line.as_bytes());
+    }
+}
+
+#[cortex_m_rt::exception]
+fn SysTick() {
+    // 中断里同样可以直接拿到一个句柄
+    if let Some(delay) = Delay::get() {
+        let gpioa = unsafe { &*pac::GPIOA::ptr() };
+        gpioa.bsrr.write(|w| w.bs8().set());
+        delay.us(PULSE_US);
+        gpioa.bsrr.write(|w| w.br8().reset());
+    }
+}
+
+fn setup_pulse_pin(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.GPIOA.moder.modify(|_, w| w.moder8().output());
+}
+
+// 切换到 HSE 时钟源，utils::ticker 与 utils::delay 都假设 TIM 的时钟为 12 MHz
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
@@ -0,0 +1,219 @@
+//! 用 MS5611 做一个简单的升降速度表（vario）
+//!
+//! 驱动见 utils::ms5611，这里把它包在 Vario 里注册到 Scheduler 中，每 50 ms 采样一次，
+//! 输出相对于上电位置的高度 vario.alt，以及升降速度 vario.climb，通过 RTT 打印，并在 LCD1602 上轮流显示
+//!
+//! 直接对气压高度求差分得到的速度噪声很大（OSR 4096 时高度噪声约 10 cm，50 ms 一次就是 ±2 m/s），
+//! 因此用一个 alpha-beta 滤波器同时估计高度与速度：每次先按上一次的速度预测高度，再按预测的误差修正高度与速度
+//!
+//! 如果有 IMU，可以把去掉重力之后的竖直方向加速度交给 Vario（实现 VerticalAccel），预测时会把加速度也算进去，
+//! 速度的响应会快很多；这里没有 IMU，使用的 NoImu 总是返回 None，此时就是单纯的气压 vario
+//!
+//! 上电后先静止几秒，等高度稳定之后再移动开发板，抬高 1 m 左右就能看到 climb 的变化
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//!
+//! STM32 <-> MS5611 模块（GY-63）
+//!  3.3V <-> VCC
+//!   PB8 <-> SCL (I2C1)
+//!   PB9 <-> SDA (I2C1)
+//!   GND <-> GND, CSB（CSB 接地时地址为 0x77）
+//!  3.3V <-> PS（选择 I2C）
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    lcd1602::Lcd1602,
+    ms5611::{self, Ms5611, Osr},
+    sensor::{
+        scheduler::Scheduler,
+        sink::{LcdPageSink, RttSink, Sink},
+        Measurement, Reading, Sensor, SensorError,
+    },
+    ticker,
+};
+
+const SAMPLE_PERIOD_MS: u32 = 50;
+
+// alpha-beta 滤波器的增益，alpha 越小高度越平滑，beta 越小速度越平滑，但响应也越慢
+const ALPHA: f32 = 0.1;
+const BETA: f32 = 0.005;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_i2c1(&dp);
+
+    let mut baro = Ms5611::new(ms5611::I2c::new(&dp.I2C1, ms5611::ADDR_CSB_LOW)).unwrap();
+    // 气压决定高度的噪声，用最高的 OSR；温度只用于补偿，OSR 256 就够了，一次采样约 11 ms
+    baro.set_osr(Osr::X4096, Osr::X256);
+    baro.zero_altitude().unwrap();
+    rprintln!("p0 = {:.0} Pa", baro.sea_level());
+
+    let mut vario = Vario::new(baro, NoImu);
+
+    let mut scheduler = Scheduler::<1>::new();
+    scheduler
+        .register(&mut vario, SAMPLE_PERIOD_MS, 0)
+        .ok()
+        .unwrap();
+
+    let mut rtt_sink = RttSink;
+    // 一次采样给出高度与速度两个读数
+    let mut lcd_sink = LcdPageSink::<_, 2>::new(Lcd1602::new(&dp), 1000);
+
+    rprintln!("vario started");
+
+    loop {
+        let sinks: &mut [&mut dyn Sink] = &mut [&mut rtt_sink, &mut lcd_sink];
+        scheduler.poll(ticker::millis(), sinks);
+    }
+}
+
+// 竖直方向（向上为正）的加速度，单位 m/s^2，已经去掉了重力
+trait VerticalAccel {
+    fn vertical_accel(&mut self) -> Option<f32>;
+}
+
+struct NoImu;
+
+impl VerticalAccel for NoImu {
+    fn vertical_accel(&mut self) -> Option<f32> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct VarioMeasurement {
+    alt: f32,
+    climb: f32,
+}
+
+impl Measurement for VarioMeasurement {
+    fn for_each_reading(&self, f: &mut dyn FnMut(Reading)) {
+        f(Reading {
+            quantity: "alt",
+            value: self.alt,
+            unit: "m",
+        });
+        f(Reading {
+            quantity: "climb",
+            value: self.climb,
+            unit: "m/s",
+        });
+    }
+}
+
+struct Vario<B, A> {
+    baro: Ms5611<B>,
+    imu: A,
+    // 第一次采样之前为 None
+    state: Option<VarioMeasurement>,
+    last_us: u64,
+}
+
+impl<B: ms5611::Bus, A: VerticalAccel> Vario<B, A> {
+    fn new(baro: Ms5611<B>, imu: A) -> Self {
+        Self {
+            baro,
+            imu,
+            state: None,
+            last_us: 0,
+        }
+    }
+}
+
+impl<B: ms5611::Bus, A: VerticalAccel> Sensor for Vario<B, A> {
+    type Output = VarioMeasurement;
+
+    fn name(&self) -> &'static str {
+        "vario"
+    }
+
+    fn sample(&mut self) -> Result<VarioMeasurement, SensorError> {
+        let measured = self.baro.sample()?.altitude;
+        let now = ticker::micros();
+        let dt = (now - self.last_us) as f32 / 1_000_000.0;
+        self.last_us = now;
+
+        let state = match self.state {
+            // 第一次采样，直接以测得的高度为初值，速度为 0
+            None => VarioMeasurement {
+                alt: measured,
+                climb: 0.0,
+            },
+            Some(prev) => {
+                let accel = self.imu.vertical_accel().unwrap_or(0.0);
+                let predicted = prev.alt + prev.climb * dt + 0.5 * accel * dt * dt;
+                let climb = prev.climb + accel * dt;
+
+                let residual = measured - predicted;
+                VarioMeasurement {
+                    alt: predicted + ALPHA * residual,
+                    climb: climb + BETA * residual / dt,
+                }
+            }
+        };
+        self.state = Some(state);
+        Ok(state)
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 与 s21c02 相同，I2C1 使用 PB8/PB9，100 kHz
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
@@ -0,0 +1,210 @@
+//! 通过 74HC595 / 74HC165 级联扩展 GPIO，并用 595 背板驱动 LCD1602
+//!
+//! 原理见 utils::shift_reg，两条链共用 SPI2 的 SCK/MOSI/MISO，各自只多占一根锁存线：
+//!
+//! - 输出链有两片 595：第 0 片接 8 个 LED，第 1 片是 LCD1602 的背板，LCD 通过 utils::lcd1602::ShiftBus 驱动
+//! - 输入链有一片 165：接 8 个按键
+//!
+//! 每 50 ms 读取一次按键：
+//!
+//! - LED0 ~ LED6 显示按键 0 ~ 6 是否按下，7 个引脚在一个 batch 中修改，只移一次
+//! - LED7 通过单个引脚的 StatefulOutputPin::toggle 闪烁，和一个普通的 GPIO 没有区别
+//! - 按住按键 7 时关闭 LCD 的背光
+//!
+//! LCD 的第一行显示按键的状态，第二行显示循环的次数
+//!
+//! 接线图：
+//!
+//! SPI2
+//!  PB13 (SPI2_SCK)  -> 两片 595 的 SRCLK，165 的 CLK
+//!  PB15 (SPI2_MOSI) -> 第 0 片 595 的 SER，第 0 片的 QH' 接第 1 片的 SER
+//!  PB14 (SPI2_MISO) <- 165 的 QH
+//!
+//! 74HC595 × 2
+//!  PB12 -> RCLK（两片并联）
+//!  OE 接 GND，SRCLR 接 3.3V
+//!  第 0 片 QA ~ QH -> 1k -> LED0 ~ LED7 -> GND
+//!  第 1 片 QA ~ QH -> LCD1602 的 RS、RW、E、背光三极管、D4 ~ D7
+//!
+//! 74HC165
+//!  PB11 -> SH/LD
+//!  CLK INH 接 GND，SER 接 GND
+//!  A ~ H 各接一个 10k 上拉到 3.3V，再通过按键接 GND
+//!
+//! 595、165 都使用 3.3V 供电；LCD1602 若使用 5V 供电，595 输出的 3.3V 高电平一般也能被识别
+
+#![no_std]
+#![no_main]
+
+use core::{convert::Infallible, fmt::Write};
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::{
+    hal::digital::{ErrorType, OutputPin, StatefulOutputPin},
+    pac,
+};
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::delay::DelayProvider;
+use utils::{
+    lcd1602::{Lcd1602, ShiftBus},
+    sensor::sink::{LineBuf, TextPanel},
+    shift_reg::{InputChain, OutputChain, SpiLink},
+};
+
+chip_caps::require!(TIM6_TIM7);
+
+const LED_CHIP: usize = 0;
+const LCD_CHIP: usize = 1;
+const HEARTBEAT_PIN: u16 = 7;
+const BACKLIGHT_BUTTON: u8 = 7;
+
+const RCLK_PIN: u8 = 12;
+const SHLD_PIN: u8 = 11;
+
+const POLL_MS: u32 = 50;
+const LCD_EVERY: u32 = 10;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nProgram Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    setup_spi2(&dp);
+    setup_latch_pins(&dp);
+    let provider = DelayProvider::setup(&dp);
+    let delay = provider.handle();
+
+    let outputs = OutputChain::<_, _, 2>::new(SpiLink::new(&dp.SPI2), PortBPin(RCLK_PIN)).unwrap();
+    let inputs = InputChain::<_, _, 1>::new(SpiLink::new(&dp.SPI2), PortBPin(SHLD_PIN)).unwrap();
+
+    let mut lcd = Lcd1602::with_bus(ShiftBus::new(&outputs, LCD_CHIP));
+    lcd.set_backlight(true);
+
+    let mut heartbeat = outputs.output_pin(HEARTBEAT_PIN).unwrap();
+
+    rprintln!(
+        "{} outputs, {} inputs",
+        OutputChain::<SpiLink, PortBPin, 2>::PIN_COUNT,
+        InputChain::<SpiLink, PortBPin, 1>::PIN_COUNT
+    );
+
+    let mut count = 0u32;
+    let mut last_buttons = 0u8;
+    loop {
+        delay.ms(POLL_MS);
+        count += 1;
+
+        // 按下时为低电平
+        let buttons = !inputs.read().unwrap()[0];
+        if buttons != last_buttons {
+            rprintln!("buttons {:08b}", buttons);
+            last_buttons = buttons;
+        }
+
+        outputs
+            .batch(|chain| {
+                for bit in 0..7 {
+                    chain
+                        .set(LED_CHIP as u16 * 8 + bit, buttons & (1 << bit) != 0)
+                        .ok();
+                }
+            })
+            .unwrap();
+        heartbeat.toggle().unwrap();
+
+        lcd.set_backlight(buttons & (1 << BACKLIGHT_BUTTON) == 0);
+
+        if count.is_multiple_of(LCD_EVERY) {
+            let mut line = LineBuf::<16>::new();
+            write!(line, "keys {:08b}", buttons).ok();
+            lcd.write_line(0, line.as_bytes());
+            line.clear();
+            write!(line, "loop {}", count).ok();
+            lcd.write_line(1, line.as_bytes());
+        }
+    }
+}
+
+// GPIOB 上的一个推挽输出，作为 RCLK 或 SH/LD
+struct PortBPin(u8);
+
+impl ErrorType for PortBPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for PortBPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let gpiob = unsafe { &*pac::GPIOB::ptr() };
+        gpiob.bsrr.write(|w| unsafe { w.bits(1 << (self.0 + 16)) });
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let gpiob = unsafe { &*pac::GPIOB::ptr() };
+        gpiob.bsrr.write(|w| unsafe { w.bits(1 << self.0) });
+        Ok(())
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// SPI2 作为主机，Mode 0，8 位，12 MHz / 8 = 1.5 MHz
+fn setup_spi2(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.spi2en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh13().af5();
+        w.afrh14().af5();
+        w.afrh15().af5();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder13().alternate();
+        w.moder14().alternate();
+        w.moder15().alternate();
+        w
+    });
+
+    dp.SPI2.cr1.write(|w| {
+        w.mstr().master();
+        w.ssm().enabled();
+        w.ssi().slave_not_selected();
+        w.dff().eight_bit();
+        w.cpol().idle_low();
+        w.cpha().first_edge();
+        w.br().div8();
+        w
+    });
+    dp.SPI2.cr1.modify(|_, w| w.spe().enabled());
+}
+
+// PB12 为 595 的 RCLK，空闲为低；PB11 为 165 的 SH/LD，空闲为高
+fn setup_latch_pins(dp: &pac::Peripherals) {
+    let gpiob = &dp.GPIOB;
+    gpiob.bsrr.write(|w| {
+        w.br12().reset();
+        w.bs11().set();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder11().output();
+        w.moder12().output();
+        w
+    });
+}
@@ -0,0 +1,241 @@
+//! 用 MAX30102 测量心率与血氧饱和度
+//!
+//! 驱动见 utils::max30102，估计算法见 utils::spo2
+//!
+//! MAX30102 以 100 sps 采样、芯片内 4 个平均，FIFO 中为 25 Hz 的红光、红外采样；FIFO 中有 16 个采样时拉低 INT，
+//! INT 接到 PA8，由 utils::exti 回调 max30102::on_interrupt，因此 I2C 大约每 640 ms 才需要一次批量读出
+//!
+//! PulseOx 注册到 Scheduler 中，每 100 ms 调用一次：
+//!
+//! - 调用 service 读出 FIFO，把新的采样逐个交给 Spo2Estimator
+//! - 每 TEMP_PERIOD_MS 启动一次芯片温度的测量，结果用于 SpO2 的温度修正
+//! - 心跳次数足够时输出 pulse.bpm、pulse.spo2、pulse.temp，通过 RTT 打印，并在 LCD1602 上轮流显示
+//!   没有手指或者还在等待心跳时返回 NotReady
+//!
+//! 手指轻轻地放在传感器上，不要用力按压，也不要移动，大约 5 秒之后开始有结果
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//!
+//! STM32 <-> MAX30102 模块
+//!  3.3V <-> VIN
+//!   PB8 <-> SCL (I2C1)
+//!   PB9 <-> SDA (I2C1)
+//!   PA8 <-> INT（开漏输出，使用 PA8 的内部上拉）
+//!   GND <-> GND
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    exti::{self, Port, Trigger},
+    lcd1602::Lcd1602,
+    max30102::{self, Config, Max30102},
+    sensor::{
+        scheduler::Scheduler,
+        sink::{LcdPageSink, RttSink, Sink},
+        Measurement, Reading, Sensor, SensorError,
+    },
+    spo2::{Spo2Estimator, Status},
+    ticker,
+};
+
+const INT_PIN: u8 = 8;
+
+const SAMPLE_PERIOD_MS: u32 = 100;
+const TEMP_PERIOD_MS: u32 = 5000;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_i2c1(&dp);
+    setup_int_pin(&dp);
+
+    let config = Config::FINGER;
+    let sensor = match Max30102::new(&dp.I2C1, config) {
+        Ok(sensor) => sensor,
+        Err(e) => {
+            rprintln!("MAX30102 not found: {:?}", e);
+            #[allow(clippy::empty_loop)]
+            loop {}
+        }
+    };
+
+    exti::register(
+        &dp,
+        Port::A,
+        INT_PIN,
+        Trigger::Falling,
+        max30102::on_interrupt,
+    )
+    .unwrap();
+
+    let mut pulse_ox = PulseOx::new(sensor, config.output_hz());
+
+    let mut scheduler = Scheduler::<1>::new();
+    scheduler
+        .register(&mut pulse_ox, SAMPLE_PERIOD_MS, 0)
+        .ok()
+        .unwrap();
+
+    let mut rtt_sink = RttSink;
+    // 一次采样给出心率、血氧、温度三个读数
+    let mut lcd_sink = LcdPageSink::<_, 3>::new(Lcd1602::new(&dp), 1000);
+
+    rprintln!("pulse oximeter started, {} Hz", config.output_hz());
+
+    loop {
+        let sinks: &mut [&mut dyn Sink] = &mut [&mut rtt_sink, &mut lcd_sink];
+        scheduler.poll(ticker::millis(), sinks);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PulseMeasurement {
+    bpm: f32,
+    spo2: f32,
+    temp: f32,
+}
+
+impl Measurement for PulseMeasurement {
+    fn for_each_reading(&self, f: &mut dyn FnMut(Reading)) {
+        f(Reading {
+            quantity: "bpm",
+            value: self.bpm,
+            unit: "bpm",
+        });
+        f(Reading {
+            quantity: "spo2",
+            value: self.spo2,
+            unit: "%",
+        });
+        f(Reading {
+            quantity: "temp",
+            value: self.temp,
+            unit: "C",
+        });
+    }
+}
+
+struct PulseOx<'a> {
+    sensor: Max30102<'a>,
+    estimator: Spo2Estimator,
+    last_temp_ms: Option<u32>,
+    status: Status,
+}
+
+impl<'a> PulseOx<'a> {
+    fn new(sensor: Max30102<'a>, sample_hz: f32) -> Self {
+        Self {
+            sensor,
+            estimator: Spo2Estimator::new(sample_hz),
+            last_temp_ms: None,
+            status: Status::NoFinger,
+        }
+    }
+}
+
+impl Sensor for PulseOx<'_> {
+    type Output = PulseMeasurement;
+
+    fn name(&self) -> &'static str {
+        "pulse"
+    }
+
+    fn sample(&mut self) -> Result<PulseMeasurement, SensorError> {
+        let now = ticker::millis();
+        let temp_due = match self.last_temp_ms {
+            None => true,
+            Some(last) => now.wrapping_sub(last) >= TEMP_PERIOD_MS,
+        };
+        if temp_due {
+            self.sensor.start_temperature()?;
+            self.last_temp_ms = Some(now);
+        }
+
+        self.sensor.service()?;
+        if let Some(t) = self.sensor.temperature() {
+            self.estimator.set_temperature(t);
+        }
+        while let Some(sample) = self.sensor.pop() {
+            self.estimator.process(sample);
+        }
+
+        let status = self.estimator.status();
+        if status != self.status {
+            rprintln!("{:?} (lost {})", status, self.sensor.lost());
+            self.status = status;
+        }
+
+        let estimate = self.estimator.estimate().ok_or(SensorError::NotReady)?;
+        Ok(PulseMeasurement {
+            bpm: estimate.bpm,
+            spo2: estimate.spo2,
+            temp: self.sensor.temperature().unwrap_or(f32::NAN),
+        })
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 与 s21c02 相同，I2C1 使用 PB8/PB9，100 kHz
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
+
+// MAX30102 的 INT 为开漏输出，PA8 使用内部上拉
+fn setup_int_pin(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.GPIOA.pupdr.modify(|_, w| w.pupdr8().pull_up());
+    dp.GPIOA.moder.modify(|_, w| w.moder8().input());
+}
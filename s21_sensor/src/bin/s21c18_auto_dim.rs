@@ -0,0 +1,177 @@
+//! 环境光传感器与自动调光
+//!
+//! 驱动见 utils::bh1750 与 utils::tsl2561，两者的输出都是 Lux，注册到 Scheduler 之后用法完全相同，
+//! 把 USE_TSL2561 改为 true 即可换用 TSL2561
+//!
+//! 读数同时交给三个 Sink：
+//! - RttSink：打印读数
+//! - LcdPageSink：在 LCD1602 上显示读数
+//! - AutoDim：根据读数算出亮度，主循环把它交给 LCD 的背光与 ws2812 灯带（见 utils::auto_dim）
+//!
+//! ws2812 灯带上循环显示彩虹，用手遮住传感器，可以看到背光与灯带在约 2 s 内一起变暗，移开后再慢慢恢复
+//!
+//! 接线图：
+//!
+//! LCD1602 与 s11c02 一致
+//! A0/A1/A2 RS/RW/E
+//! B4~B7    D4~D7
+//! LCD 的背光 A 脚经 NPN 三极管（基极串 1k 电阻）接 PB14（TIM12_CH1），K 脚接 GND
+//!
+//! STM32 <-> BH1750 / TSL2561 模块
+//!  3.3V <-> VCC
+//!   PB8 <-> SCL (I2C1)
+//!   PB9 <-> SDA (I2C1)
+//!   GND <-> GND, ADDR（BH1750 的 ADDR 接 GND 时地址为 0x23；TSL2561 的 ADDR 悬空时地址为 0x39）
+//!
+//! PB15（SPI2_MOSI） <-> ws2812 灯带的 DIN，灯带使用 5V 供电时，DIN 最好经过一个 74HCT 系列的缓冲器
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::auto_dim::{AutoDim, DimConfig, Dimmer, Tim12Backlight};
+use utils::{
+    bh1750::{self, Bh1750, Resolution},
+    lcd1602::Lcd1602,
+    sensor::{
+        scheduler::Scheduler,
+        sink::{LcdPageSink, RttSink, Sink},
+        DynSensor,
+    },
+    ticker,
+    tsl2561::{self, Tsl2561},
+    ws2812::{FrameBuffer, Rgb, Ws2812Out},
+    ws2812_spi::SpiWs2812,
+};
+
+chip_caps::require!(TIM12);
+
+const USE_TSL2561: bool = false;
+
+const SAMPLE_PERIOD_MS: u32 = 500;
+const FRAME_PERIOD_MS: u32 = 40;
+const LEDS: usize = 16;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_i2c1(&dp);
+
+    let mut bh;
+    let mut tsl;
+    let sensor: &mut dyn DynSensor = if USE_TSL2561 {
+        tsl = Tsl2561::new(&dp.I2C1, tsl2561::ADDR_FLOAT).unwrap();
+        rprintln!("TSL2561, package {:?}", tsl.package());
+        &mut tsl
+    } else {
+        bh = Bh1750::new(&dp.I2C1, bh1750::ADDR_LOW).unwrap();
+        bh.set_mode(bh1750::Mode::OneShot, Resolution::High2)
+            .unwrap();
+        rprintln!("BH1750");
+        &mut bh
+    };
+
+    let mut scheduler = Scheduler::<1>::new();
+    scheduler
+        .register(sensor, SAMPLE_PERIOD_MS, 0)
+        .ok()
+        .unwrap();
+
+    let mut rtt_sink = RttSink;
+    let mut lcd_sink = LcdPageSink::<_, 1>::new(Lcd1602::new(&dp), 2000);
+    let mut auto_dim = AutoDim::new(DimConfig::INDOOR);
+
+    let mut backlight = Tim12Backlight::new(&dp);
+    let mut strip = SpiWs2812::new(&dp);
+    let mut frame = FrameBuffer::<LEDS>::new();
+
+    let mut last_frame_ms = ticker::millis();
+    let mut hue: u8 = 0;
+
+    loop {
+        let now_ms = ticker::millis();
+        let sinks: &mut [&mut dyn Sink] = &mut [&mut rtt_sink, &mut lcd_sink, &mut auto_dim];
+        scheduler.poll(now_ms, sinks);
+
+        if let Some(level) = auto_dim.take_change() {
+            let dimmers: [&mut dyn Dimmer; 2] = [&mut backlight, &mut frame];
+            for dimmer in dimmers {
+                dimmer.set_level(level);
+            }
+            rprintln!("level {}%", (level * 100.0) as u32);
+        }
+
+        if now_ms.wrapping_sub(last_frame_ms) >= FRAME_PERIOD_MS {
+            last_frame_ms = now_ms;
+            for (index, pixel) in frame.pixels_mut().iter_mut().enumerate() {
+                *pixel = wheel(hue.wrapping_add((index * 256 / LEDS) as u8));
+            }
+            hue = hue.wrapping_add(2);
+            strip.show(&frame).ok();
+        }
+    }
+}
+
+// 0 ~ 255 的色相，红 -> 绿 -> 蓝 -> 红
+fn wheel(hue: u8) -> Rgb {
+    let step = (hue % 85) * 3;
+    match hue / 85 {
+        0 => Rgb::new(255 - step, step, 0),
+        1 => Rgb::new(0, 255 - step, step),
+        _ => Rgb::new(step, 0, 255 - step),
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// 与 s21c02 相同，I2C1 使用 PB8/PB9，100 kHz
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
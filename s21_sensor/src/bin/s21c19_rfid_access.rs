@@ -0,0 +1,528 @@
+//! RFID 门禁
+//!
+//! 读卡芯片 MFRC522 的驱动见 utils::mfrc522，这里使用 IRQ 方式检测卡片：
+//! 每 ARM_PERIOD_MS 发出一次 REQA，有卡片回应时 IRQ 引脚触发 EXTI，主循环读出 UID，
+//! UID 与保存的卡片相符时打开门锁（继电器）UNLOCK_MS，否则拒绝
+//!
+//! 卡片读完之后会进入 HALT 状态，一直放在读卡器上也只会被读到一次，拿开再放上去才会再次被读到
+//!
+//! 允许的卡片保存在 utils::settings 中（与 s21c05 共用 W25Q32 0xF_0000 处的设置区域），
+//! 键为 card0 ~ card9，4 个 f32 按位存放 UID 的长度与 UID（最长 10 字节），并不是真的浮点数
+//!
+//! 串口上可以输入以下命令（以回车结束）：
+//!
+//! list          列出保存的卡片
+//! enroll        下一张读到的卡片将被保存
+//! forget <n>    删除 cardn
+//!
+//! 接线图：
+//!
+//! W25Q32 与 s21c03 一致
+//! PB1  CLK
+//! PB6  nCS
+//! PC9  IO0
+//! PC10 IO1
+//! PC8  IO2 /WP
+//! PA1  IO3 /HOLD /RESET
+//!
+//! STM32 <-> MFRC522 模块（RC522）
+//!  3.3V <-> 3.3V
+//!  PB12 <-> SDA (NSS)
+//!  PB13 <-> SCK (SPI2)
+//!  PB14 <-> MISO (SPI2)
+//!  PB15 <-> MOSI (SPI2)
+//!   PC6 <-> IRQ
+//!   PC7 <-> RST
+//!   GND <-> GND
+//!
+//! STM32 <-> 继电器模块（高电平吸合）
+//!   PC5 -> IN
+//!
+//! USB-TTL 模块，115200 8N1
+//! PA9  (USART1 Tx) <-> Rx
+//! PA10 (USART1 Rx) <-> Tx
+//! GND              <-> GND
+
+#![no_std]
+#![no_main]
+
+use core::{convert::Infallible, fmt::Write};
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::{
+    hal::digital::{ErrorType, OutputPin},
+    pac,
+};
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::qspi_flash::QspiFlash;
+use utils::{
+    datalog::LogFlash,
+    exti::{self, Port, Trigger},
+    mfrc522::{self, Mfrc522, RfidError, Uid},
+    sensor::sink::LineBuf,
+    settings::{SettingsStore, Values},
+    ticker,
+};
+
+chip_caps::require!(QUADSPI);
+
+// 与 s21c05 相同的设置区域
+const SETTINGS_START: u32 = 0x0F_0000;
+
+const MAX_CARDS: usize = 10;
+const ARM_PERIOD_MS: u32 = 100;
+const UNLOCK_MS: u32 = 3000;
+const IRQ_PIN: u8 = 6;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_usart1(&dp);
+    setup_qspi_gpio(&dp);
+    setup_qspi(&dp);
+    setup_spi2(&dp);
+    setup_rfid_pins(&dp);
+    setup_relay(&dp);
+
+    let mut store = SettingsStore::open(QspiFlash::new(&dp.QUADSPI), SETTINGS_START).unwrap();
+
+    let mut reader = match Mfrc522::new(&dp.SPI2, Pb12) {
+        Ok(reader) => reader,
+        Err(e) => {
+            rprintln!("MFRC522 not found: {:?}", e);
+            #[allow(clippy::empty_loop)]
+            loop {}
+        }
+    };
+    rprintln!("MFRC522 version {:#04x}", reader.version().unwrap());
+
+    reader.enable_irq().unwrap();
+    exti::register(
+        &dp,
+        Port::C,
+        IRQ_PIN,
+        Trigger::Falling,
+        mfrc522::on_interrupt,
+    )
+    .unwrap();
+
+    let mut console = Console { usart: &dp.USART1 };
+    let mut line = LineBuf::<32>::new();
+    console.say("rfid access, commands: list, enroll, forget <n>");
+
+    let mut enrolling = false;
+    let mut last_arm_ms = ticker::millis();
+    let mut unlocked_at: Option<u32> = None;
+
+    loop {
+        let now_ms = ticker::millis();
+
+        if now_ms.wrapping_sub(last_arm_ms) >= ARM_PERIOD_MS {
+            last_arm_ms = now_ms;
+            if let Err(e) = reader.arm() {
+                rprintln!("arm failed: {:?}", e);
+            }
+        }
+
+        match reader.service() {
+            Ok(None) => {}
+            Ok(Some(uid)) => {
+                let mut out = LineBuf::<64>::new();
+                write_uid(&mut out, &uid);
+                if enrolling {
+                    enrolling = false;
+                    match enroll(&mut store, &uid) {
+                        Ok(slot) => write!(out, " saved as card{}", slot).ok(),
+                        Err(message) => write!(out, " not saved: {}", message).ok(),
+                    };
+                } else if find(&store, &uid).is_some() {
+                    unlocked_at = Some(now_ms);
+                    set_relay(true);
+                    out.write_str(" granted").ok();
+                } else {
+                    out.write_str(" denied").ok();
+                }
+                console.say(as_str(&out));
+            }
+            // 卡片离开得太快、两张卡片同时靠近等，等下一次 REQA 重试即可
+            Err(RfidError::Timeout | RfidError::Collision | RfidError::Protocol) => {}
+            Err(e) => rprintln!("read failed: {:?}", e),
+        }
+
+        if let Some(at) = unlocked_at {
+            if now_ms.wrapping_sub(at) >= UNLOCK_MS {
+                unlocked_at = None;
+                set_relay(false);
+            }
+        }
+
+        if !console.poll_line(&mut line) {
+            continue;
+        }
+        let command = as_str(&line);
+        let mut words = command.split_ascii_whitespace();
+        let mut out = LineBuf::<64>::new();
+
+        match (words.next(), words.next()) {
+            (None, _) => {}
+            (Some("list"), None) => {
+                let mut count = 0;
+                for slot in 0..MAX_CARDS {
+                    let Some(uid) = load(&store, slot) else {
+                        continue;
+                    };
+                    out.clear();
+                    write!(out, "card{}: ", slot).ok();
+                    write_uid(&mut out, &uid);
+                    console.say(as_str(&out));
+                    count += 1;
+                }
+                if count == 0 {
+                    console.say("no cards");
+                }
+            }
+            (Some("enroll"), None) => {
+                enrolling = true;
+                console.say("present the card to enroll");
+            }
+            (Some("forget"), Some(slot)) => match slot.parse::<usize>() {
+                Ok(slot) if slot < MAX_CARDS => {
+                    let key = card_key(slot);
+                    if !store.remove(as_str(&key)) {
+                        console.say("no such card");
+                    } else if store.commit().is_ok() {
+                        console.say("removed");
+                    } else {
+                        console.say("failed to write settings");
+                    }
+                }
+                _ => console.say("slot should be 0 ~ 9"),
+            },
+            _ => console.say("unknown command"),
+        }
+        line.clear();
+    }
+}
+
+fn card_key(slot: usize) -> LineBuf<8> {
+    let mut key = LineBuf::new();
+    write!(key, "card{}", slot).ok();
+    key
+}
+
+// 第一个字节为 UID 的长度，之后依次为 UID，每 4 个字节按位转为一个 f32
+// f32::from_bits/to_bits 不会改变其中的位，即使是 NaN 也一样
+fn uid_to_values(uid: &Uid) -> Values {
+    let mut bytes = [0u8; 16];
+    let uid = uid.as_bytes();
+    bytes[0] = uid.len() as u8;
+    bytes[1..1 + uid.len()].copy_from_slice(uid);
+
+    let mut values = [0.0; 4];
+    for (value, chunk) in values.iter_mut().zip(bytes.chunks_exact(4)) {
+        *value = f32::from_bits(u32::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    values
+}
+
+// SAK 没有保存，比较时只看 UID
+fn uid_from_values(values: &Values) -> Option<Uid> {
+    let mut bytes = [0u8; 16];
+    for (chunk, value) in bytes.chunks_exact_mut(4).zip(values) {
+        chunk.copy_from_slice(&value.to_bits().to_le_bytes());
+    }
+    let len = bytes[0] as usize;
+    Uid::new(bytes.get(1..1 + len)?, 0)
+}
+
+fn load<F: LogFlash>(store: &SettingsStore<F>, slot: usize) -> Option<Uid> {
+    let key = card_key(slot);
+    uid_from_values(&store.get(as_str(&key))?)
+}
+
+fn find<F: LogFlash>(store: &SettingsStore<F>, uid: &Uid) -> Option<usize> {
+    (0..MAX_CARDS)
+        .find(|&slot| load(store, slot).is_some_and(|saved| saved.as_bytes() == uid.as_bytes()))
+}
+
+// 已经保存过的卡片返回原来的编号，否则保存到第一个空位
+fn enroll<F: LogFlash>(store: &mut SettingsStore<F>, uid: &Uid) -> Result<usize, &'static str> {
+    if let Some(slot) = find(store, uid) {
+        return Ok(slot);
+    }
+    let slot = (0..MAX_CARDS)
+        .find(|&slot| load(store, slot).is_none())
+        .ok_or("all slots in use")?;
+    let key = card_key(slot);
+    store
+        .set(as_str(&key), uid_to_values(uid))
+        .map_err(|_| "settings full")?;
+    store.commit().map_err(|_| "failed to write settings")?;
+    Ok(slot)
+}
+
+fn write_uid<const N: usize>(out: &mut LineBuf<N>, uid: &Uid) {
+    for (index, byte) in uid.as_bytes().iter().enumerate() {
+        if index > 0 {
+            out.write_char(':').ok();
+        }
+        write!(out, "{:02X}", byte).ok();
+    }
+}
+
+fn as_str<const N: usize>(buf: &LineBuf<N>) -> &str {
+    core::str::from_utf8(buf.as_bytes()).unwrap_or("")
+}
+
+// 通过 USART1 实现的简单命令行，与 s21c05 不同，这里不能阻塞主循环
+struct Console<'a> {
+    usart: &'a pac::USART1,
+}
+
+impl Console<'_> {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            while self.usart.sr.read().txe().bit_is_clear() {}
+            self.usart.dr.write(|w| w.dr().bits(byte as u16));
+        }
+    }
+
+    fn say(&mut self, message: &str) {
+        self.write_bytes(message.as_bytes());
+        self.write_bytes(b"\r\n");
+    }
+
+    // 读取已经收到的字符，并回显，读到回车时返回 true，此时 line 中为完整的一行，处理完之后由调用者清空
+    fn poll_line<const N: usize>(&mut self, line: &mut LineBuf<N>) -> bool {
+        while self.usart.sr.read().rxne().bit_is_set() {
+            let byte = self.usart.dr.read().dr().bits() as u8;
+            match byte {
+                b'\r' | b'\n' => {
+                    self.write_bytes(b"\r\n");
+                    return true;
+                }
+                _ => {
+                    self.write_bytes(&[byte]);
+                    line.write_char(byte as char).ok();
+                }
+            }
+        }
+        false
+    }
+}
+
+// PB12 作为 MFRC522 的片选
+struct Pb12;
+
+impl ErrorType for Pb12 {
+    type Error = Infallible;
+}
+
+impl OutputPin for Pb12 {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let gpiob = unsafe { &*pac::GPIOB::ptr() };
+        gpiob.bsrr.write(|w| w.br12().reset());
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let gpiob = unsafe { &*pac::GPIOB::ptr() };
+        gpiob.bsrr.write(|w| w.bs12().set());
+        Ok(())
+    }
+}
+
+fn set_relay(on: bool) {
+    let gpioc = unsafe { &*pac::GPIOC::ptr() };
+    if on {
+        gpioc.bsrr.write(|w| w.bs5().set());
+    } else {
+        gpioc.bsrr.write(|w| w.br5().reset());
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// SPI2 作为主机，Mode 0，8 位，12 MHz / 4 = 3 MHz，MFRC522 的 SCK 最高 10 MHz
+fn setup_spi2(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.spi2en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.bsrr.write(|w| w.bs12().set());
+    gpiob.afrh.modify(|_, w| {
+        w.afrh13().af5();
+        w.afrh14().af5();
+        w.afrh15().af5();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder12().output();
+        w.moder13().alternate();
+        w.moder14().alternate();
+        w.moder15().alternate();
+        w
+    });
+
+    dp.SPI2.cr1.write(|w| {
+        w.mstr().master();
+        w.ssm().enabled();
+        w.ssi().slave_not_selected();
+        w.dff().eight_bit();
+        w.cpol().idle_low();
+        w.cpha().first_edge();
+        w.br().div4();
+        w
+    });
+    dp.SPI2.cr1.modify(|_, w| w.spe().enabled());
+}
+
+// PC7 为 MFRC522 的 RST（低电平复位并进入掉电模式），PC6 为 IRQ 输入
+// 芯片的 IRQ 被配置为推挽输出，这里的上拉只是为了在芯片复位期间保持高电平
+fn setup_rfid_pins(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+
+    let gpioc = &dp.GPIOC;
+    gpioc.pupdr.modify(|_, w| w.pupdr6().pull_up());
+    gpioc.moder.modify(|_, w| w.moder6().input());
+
+    // 拉低 RST 1 ms 再释放，确保芯片从一个确定的状态开始
+    gpioc.bsrr.write(|w| w.br7().reset());
+    gpioc.moder.modify(|_, w| w.moder7().output());
+    ticker::delay_ms(1);
+    gpioc.bsrr.write(|w| w.bs7().set());
+    // 晶振起振时间
+    ticker::delay_ms(50);
+}
+
+// PC5 推挽输出，驱动继电器，上电时保持断开
+fn setup_relay(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.GPIOC.bsrr.write(|w| w.br5().reset());
+    dp.GPIOC.moder.modify(|_, w| w.moder5().output());
+}
+
+// 与 s21c03 相同，USART1 收发，参数为 115200 8N1
+fn setup_usart1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| {
+        w.afrh9().af7();
+        w.afrh10().af7();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr9().pull_up();
+        w.pupdr10().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let serial1 = &dp.USART1;
+
+    serial1.cr1.modify(|_, w| w.ue().enabled());
+
+    // 12 MHz / (16 * 115200) = 6.51，整数部分为 6，小数部分为 0.51 * 16 ≈ 8
+    serial1.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    serial1.cr1.modify(|_, w| {
+        w.te().enabled();
+        w.re().enabled();
+        w
+    });
+}
+
+// 与 s21c03 相同
+fn setup_qspi_gpio(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w.gpiocen().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| w.afrl1().af9()); // IO3 /HOLD /RESET
+    gpioa.moder.modify(|_, w| w.moder1().alternate());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| {
+        w.afrl1().af9(); // CLK
+        w.afrl6().af10(); // nCS
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder1().alternate();
+        w.moder6().alternate();
+        w
+    });
+
+    let gpioc = &dp.GPIOC;
+    gpioc.afrh.modify(|_, w| {
+        w.afrh8().af9(); // IO2 /WP
+        w.afrh9().af9(); // IO0
+        w.afrh10().af9(); // IO1
+        w
+    });
+    gpioc.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn setup_qspi(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb3enr.modify(|_, w| w.qspien().disabled());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().reset());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().clear_bit());
+    rcc.ahb3enr.modify(|_, w| w.qspien().enabled());
+
+    let qspi = &dp.QUADSPI;
+
+    // 12 MHz / 2 = 6 MHz
+    qspi.cr.modify(|_, w| unsafe {
+        w.prescaler().bits(2 - 1);
+        w.sshift().set_bit();
+        w
+    });
+
+    qspi.dcr.modify(|_, w| unsafe {
+        // W25Q32 为 4 MB，2^(21 + 1) = 4 MB
+        w.fsize().bits(21);
+        w.ckmode().set_bit();
+        w
+    });
+
+    qspi.cr.modify(|_, w| w.en().set_bit());
+}
@@ -0,0 +1,527 @@
+//! 需要认证才能执行危险命令的串口命令行
+//!
+//! 认证的流程见 utils::shell_auth，密钥保存在 utils::settings 中（与 s21c05 共用 W25Q32 0xF_0000 处的设置区域），
+//! 挑战使用的随机数来自 RNG，RNG 需要 48 MHz 的时钟，这里只为它打开了 PLL 的 Q 输出，SYSCLK 依旧是 HSE
+//!
+//! 串口上可以输入以下命令（以回车结束）：
+//!
+//! auth                 申请一个挑战
+//! auth <mac>           应答最近的挑战，mac 为 64 个十六进制字符
+//! auth status          查看认证状态
+//! auth logout          结束会话
+//! auth setkey <key>    设置密钥，key 为 64 个十六进制字符；已经有密钥时，需要先认证
+//! erase <n>            擦除内部 Flash 的扇区 n（8 ~ 15，程序只占用了前 512 KB），需要认证
+//! wrp <n> on|off       打开或解除扇区 n（0 ~ 11）的写保护，修改的是 Option Bytes，需要认证
+//! dfu                  重启进入内置的 bootloader，见 utils::dfu，需要认证
+//!
+//! 第一次使用时，先生成一个密钥：
+//!
+//! ```shell
+//! openssl rand -hex 32
+//! ```
+//!
+//! 然后 auth setkey <key>，之后每次执行危险命令之前，先 auth 取得挑战，再按 utils::shell_auth 中的方法计算应答
+//!
+//! 命令行默认使用 USART1，把 SHELL_OVER_RTT 改为 true 之后改用 RTT，命令行本身的代码不需要任何修改，见 utils::io
+//! 使用 RTT 时，可以在 OpenOCD 的 telnet 中把 Shell 通道映射为一个 TCP 端口，再用 telnet 之类的工具连接：
+//!
+//! ```shell
+//! rtt setup 0x20000000 0x50000 "SEGGER RTT"
+//! rtt start
+//! rtt server start 9091 1
+//! ```
+//!
+//! 接线图：
+//!
+//! W25Q32 与 s21c03 一致
+//! PB1  CLK
+//! PB6  nCS
+//! PC9  IO0
+//! PC10 IO1
+//! PC8  IO2 /WP
+//! PA1  IO3 /HOLD /RESET
+//!
+//! USB-TTL 模块，115200 8N1
+//! PA9  (USART1 Tx) <-> Rx
+//! PA10 (USART1 Rx) <-> Tx
+//! GND              <-> GND
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+
+use embedded_io::{Read, Write as IoWrite};
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init, set_print_channel};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::qspi_flash::QspiFlash;
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use utils::shell_auth::{self, AuthError, Key, Mac, Nonce, ShellAuth};
+use utils::{
+    datalog::LogFlash, dfu, internal_flash::InternalFlash, io, sensor::sink::LineBuf,
+    settings::SettingsStore, ticker,
+};
+
+chip_caps::require!(QUADSPI, RNG);
+
+// true 时命令行使用 RTT 的 1 号通道，日志依旧输出到 0 号 up 通道，此时不需要 USB-TTL 模块
+const SHELL_OVER_RTT: bool = false;
+
+// 与 s21c05 相同的设置区域
+const SETTINGS_START: u32 = 0x0F_0000;
+
+// 可以擦除的内部 Flash 扇区
+const ERASABLE_SECTORS: core::ops::RangeInclusive<u8> = 8..=15;
+// FLASH_OPTCR 中 nWRP 管理的扇区数
+const WRP_SECTORS: u8 = 12;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+
+    // 必须在初始化任何外设之前
+    dfu::enter_if_requested(&dp);
+
+    // rtt_init! 只能调用一次，因此不论命令行用不用 RTT，Shell 通道都会被创建
+    let channels = rtt_init! {
+        up: {
+            0: {
+                size: 1024,
+                name: "Terminal"
+            }
+            1: {
+                size: 256,
+                name: "Shell"
+            }
+        }
+        // OpenOCD 的 rtt server 收发使用同一个编号的通道，因此 Shell 的 down 通道也放在 1 号
+        down: {
+            0: {
+                size: 16,
+                name: "Terminal"
+            }
+            1: {
+                size: 64,
+                name: "Shell"
+            }
+        }
+    };
+    set_print_channel(channels.up.0);
+    setup(&dp);
+
+    if SHELL_OVER_RTT {
+        shell(
+            &dp,
+            Console::new(io::Rtt::new(channels.up.1, channels.down.1)),
+        )
+    } else {
+        shell(&dp, Console::new(io::Usart::new(&dp.USART1)))
+    }
+}
+
+fn setup(dp: &pac::Peripherals) {
+    setup_hse(dp);
+    ticker::setup(dp);
+    setup_rng(dp);
+    setup_usart1(dp);
+    setup_qspi_gpio(dp);
+    setup_qspi(dp);
+}
+
+// 命令行本身只通过 Console 收发字节，不关心背后是 USART 还是 RTT
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn shell<T: Read + IoWrite>(dp: &pac::Peripherals, mut console: Console<T>) -> ! {
+    let mut store = SettingsStore::open(QspiFlash::new(&dp.QUADSPI), SETTINGS_START).unwrap();
+    let mut auth = ShellAuth::new(shell_auth::load_key(&store));
+    rprintln!("auth key loaded: {}", auth.has_key());
+
+    let mut line = LineBuf::<80>::new();
+    let mut out = LineBuf::<80>::new();
+    console.say("secure shell, commands: auth, erase <n>, wrp <n> on|off, dfu");
+
+    loop {
+        console.read_line(&mut line);
+        let command = core::str::from_utf8(line.as_bytes()).unwrap_or("");
+        let mut words = command.split_ascii_whitespace();
+        let now = ticker::millis();
+        out.clear();
+
+        match (words.next(), words.next(), words.next()) {
+            (None, _, _) => continue,
+            (Some("auth"), None, None) => match auth.challenge(now, random_nonce(&dp.RNG)) {
+                Ok(nonce) => {
+                    write!(out, "challenge ").ok();
+                    write_hex(&mut out, &nonce);
+                }
+                Err(e) => write_auth_error(&mut out, e),
+            },
+            (Some("auth"), Some("status"), None) => {
+                if !auth.has_key() {
+                    write!(out, "no key").ok();
+                } else if let Some(remaining) = auth.session_remaining(now) {
+                    write!(out, "authenticated, {} s left", remaining / 1000).ok();
+                } else {
+                    write!(out, "not authenticated").ok();
+                }
+            }
+            (Some("auth"), Some("logout"), None) => {
+                auth.logout();
+                write!(out, "logged out").ok();
+            }
+            (Some("auth"), Some("setkey"), Some(hex)) => {
+                let mut key: Key = [0; shell_auth::KEY_LEN];
+                if auth.has_key() && auth.authorize(now).is_err() {
+                    write!(out, "authenticate with the old key first").ok();
+                } else if shell_auth::parse_hex(hex, &mut key).is_none() {
+                    write!(out, "key must be {} hex digits", shell_auth::KEY_LEN * 2).ok();
+                } else if shell_auth::save_key(&mut store, &key).is_err() {
+                    write!(out, "failed to write settings").ok();
+                } else {
+                    auth.set_key(key);
+                    write!(out, "key saved").ok();
+                }
+            }
+            (Some("auth"), Some(hex), None) => {
+                let mut mac: Mac = [0; 32];
+                if shell_auth::parse_hex(hex, &mut mac).is_none() {
+                    write!(out, "mac must be {} hex digits", mac.len() * 2).ok();
+                } else {
+                    match auth.respond(now, &mac) {
+                        Ok(()) => {
+                            write!(out, "authenticated for {} s", shell_auth::SESSION_MS / 1000)
+                                .ok();
+                        }
+                        Err(e) => write_auth_error(&mut out, e),
+                    }
+                }
+            }
+            (Some("erase"), Some(sector), None) => match sector.parse::<u8>() {
+                Ok(sector) if ERASABLE_SECTORS.contains(&sector) => match auth.authorize(now) {
+                    Ok(()) => {
+                        erase_sector(&dp.FLASH, sector);
+                        write!(out, "sector {} erased", sector).ok();
+                    }
+                    Err(e) => write_auth_error(&mut out, e),
+                },
+                _ => {
+                    write!(out, "sector must be 8 ~ 15").ok();
+                }
+            },
+            (Some("wrp"), Some(sector), Some(state @ ("on" | "off"))) => {
+                match sector.parse::<u8>() {
+                    Ok(sector) if sector < WRP_SECTORS => match auth.authorize(now) {
+                        Ok(()) => {
+                            match set_write_protect(&dp.FLASH, sector, state == "on") {
+                                Ok(()) => write!(out, "sector {} wrp {}", sector, state),
+                                Err(e) => write!(out, "failed: {}", e),
+                            }
+                            .ok();
+                        }
+                        Err(e) => write_auth_error(&mut out, e),
+                    },
+                    _ => {
+                        write!(out, "sector must be 0 ~ {}", WRP_SECTORS - 1).ok();
+                    }
+                }
+            }
+            (Some("dfu"), None, None) => match auth.authorize(now) {
+                Ok(()) => {
+                    console.say("rebooting to system bootloader");
+                    dfu::reboot_to_dfu(dp);
+                }
+                Err(e) => write_auth_error(&mut out, e),
+            },
+            _ => {
+                write!(out, "unknown command").ok();
+            }
+        }
+
+        console.say(as_str(&out));
+        // 只打印命令名，参数中可能有密钥
+        let name = command.split_ascii_whitespace().next().unwrap_or("");
+        rprintln!("{} -> {}", name, as_str(&out));
+    }
+}
+
+fn write_auth_error<const N: usize>(out: &mut LineBuf<N>, error: AuthError) {
+    match error {
+        AuthError::NoKey => write!(out, "denied: no key, use auth setkey"),
+        AuthError::NoChallenge => write!(out, "denied: no pending challenge"),
+        AuthError::BadResponse => write!(out, "denied: bad response"),
+        AuthError::Locked(ms) => write!(out, "denied: locked for {} s", ms.div_ceil(1000)),
+        AuthError::NotAuthenticated => write!(out, "denied: authenticate first"),
+    }
+    .ok();
+}
+
+fn write_hex<const N: usize>(out: &mut LineBuf<N>, bytes: &[u8]) {
+    for byte in bytes {
+        write!(out, "{:02x}", byte).ok();
+    }
+}
+
+fn as_str<const N: usize>(buf: &LineBuf<N>) -> &str {
+    core::str::from_utf8(buf.as_bytes()).unwrap_or("")
+}
+
+// 从 RNG 取出一个挑战，RNG 报告错误时丢弃这个数，重新等待
+fn random_nonce(rng: &pac::RNG) -> Nonce {
+    let mut nonce: Nonce = [0; shell_auth::NONCE_LEN];
+    for chunk in nonce.chunks_exact_mut(4) {
+        let word = loop {
+            let sr = rng.sr.read();
+            if sr.seis().bit_is_set() || sr.ceis().bit_is_set() {
+                // 清除错误标志并重新启动 RNG
+                rng.sr.modify(|_, w| {
+                    w.seis().clear_bit();
+                    w.ceis().clear_bit();
+                    w
+                });
+                rng.cr.modify(|_, w| w.rngen().clear_bit());
+                rng.cr.modify(|_, w| w.rngen().set_bit());
+                continue;
+            }
+            if sr.drdy().bit_is_set() {
+                break rng.dr.read().bits();
+            }
+        };
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    nonce
+}
+
+// 扇区 5 之后都是 128 KB
+fn erase_sector(flash: &pac::FLASH, sector: u8) {
+    let mut internal = InternalFlash::new(flash);
+    internal.start_erase(0x2_0000 + (sector as u32 - 5) * InternalFlash::SECTOR_SIZE);
+    internal.wait_idle();
+}
+
+// 修改一个扇区的 nWRP，流程见 s14 的 utils::option_bytes
+fn set_write_protect(flash: &pac::FLASH, sector: u8, protect: bool) -> Result<(), &'static str> {
+    // RDP Level 2 时 Option Bytes 不能再修改
+    if flash.optcr.read().rdp().bits() == 0xCC {
+        return Err("RDP level 2");
+    }
+
+    if flash.optcr.read().optlock().bit_is_set() {
+        flash.optkeyr.write(|w| w.optkey().bits(0x0819_2A3B));
+        flash.optkeyr.write(|w| w.optkey().bits(0x4C5D_6E7F));
+        if flash.optcr.read().optlock().bit_is_set() {
+            return Err("OPTCR locked");
+        }
+    }
+
+    while flash.sr.read().bsy().bit_is_set() {}
+
+    // nWRP 为 0 时写保护
+    flash.optcr.modify(|r, w| unsafe {
+        let n_wrp = r.n_wrp().bits();
+        w.n_wrp().bits(if protect {
+            n_wrp & !(1 << sector)
+        } else {
+            n_wrp | 1 << sector
+        })
+    });
+    flash.optcr.modify(|_, w| w.optstrt().set_bit());
+    while flash.sr.read().bsy().bit_is_set() {}
+
+    flash.optcr.modify(|_, w| w.optlock().set_bit());
+
+    if flash.sr.read().wrperr().bit_is_set() {
+        return Err("write protection error");
+    }
+    let protected = flash.optcr.read().n_wrp().bits() & 1 << sector == 0;
+    if protected != protect {
+        return Err("verify failed");
+    }
+    Ok(())
+}
+
+// 简单的命令行，传输方式见 utils::io
+struct Console<T> {
+    io: T,
+}
+
+impl<T: Read + IoWrite> Console<T> {
+    fn new(io: T) -> Self {
+        Self { io }
+    }
+
+    // 出错时也没有别的地方可以报告，直接忽略
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.io.write_all(bytes).ok();
+    }
+
+    fn say(&mut self, message: &str) {
+        self.write_bytes(message.as_bytes());
+        self.write_bytes(b"\r\n");
+    }
+
+    // 阻塞地读取一行，并回显输入的字符，出错的字节被丢弃
+    fn read_line<const N: usize>(&mut self, line: &mut LineBuf<N>) {
+        line.clear();
+        let mut byte = [0u8];
+        loop {
+            if self.io.read(&mut byte).is_err() {
+                continue;
+            }
+            match byte[0] {
+                b'\r' | b'\n' => {
+                    self.write_bytes(b"\r\n");
+                    return;
+                }
+                _ => {
+                    self.write_bytes(&byte);
+                    line.write_char(byte[0] as char).ok();
+                }
+            }
+        }
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// PLL 只用来产生 RNG 的 48 MHz 时钟，见 s18c01
+// 12 MHz / 6 * 96 = 192 MHz，Q 输出 192 MHz / 4 = 48 MHz，P 输出没有使用
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn setup_rng(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(96);
+            w.pllq().bits(4);
+        }
+        w.pllp().div2();
+        w
+    });
+    rcc.cr.modify(|_, w| w.pllon().on());
+    while rcc.cr.read().pllrdy().is_not_ready() {}
+
+    rcc.dckcfgr2.modify(|_, w| w.ck48msel().pll());
+    rcc.ahb2enr.modify(|_, w| w.rngen().enabled());
+
+    dp.RNG.cr.modify(|_, w| w.rngen().set_bit());
+}
+
+// 与 s21c03 相同，USART1 收发，参数为 115200 8N1
+fn setup_usart1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| {
+        w.afrh9().af7();
+        w.afrh10().af7();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr9().pull_up();
+        w.pupdr10().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let serial1 = &dp.USART1;
+
+    serial1.cr1.modify(|_, w| w.ue().enabled());
+
+    // 12 MHz / (16 * 115200) = 6.51，整数部分为 6，小数部分为 0.51 * 16 ≈ 8
+    serial1.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    serial1.cr1.modify(|_, w| {
+        w.te().enabled();
+        w.re().enabled();
+        w
+    });
+}
+
+// 与 s21c03 相同
+fn setup_qspi_gpio(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w.gpiocen().enabled();
+        w
+    });
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| w.afrl1().af9()); // IO3 /HOLD /RESET
+    gpioa.moder.modify(|_, w| w.moder1().alternate());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrl.modify(|_, w| {
+        w.afrl1().af9(); // CLK
+        w.afrl6().af10(); // nCS
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder1().alternate();
+        w.moder6().alternate();
+        w
+    });
+
+    let gpioc = &dp.GPIOC;
+    gpioc.afrh.modify(|_, w| {
+        w.afrh8().af9(); // IO2 /WP
+        w.afrh9().af9(); // IO0
+        w.afrh10().af9(); // IO1
+        w
+    });
+    gpioc.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn setup_qspi(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.ahb3enr.modify(|_, w| w.qspien().disabled());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().reset());
+    rcc.ahb3rstr.modify(|_, w| w.qspirst().clear_bit());
+    rcc.ahb3enr.modify(|_, w| w.qspien().enabled());
+
+    let qspi = &dp.QUADSPI;
+
+    // 12 MHz / 2 = 6 MHz
+    qspi.cr.modify(|_, w| unsafe {
+        w.prescaler().bits(2 - 1);
+        w.sshift().set_bit();
+        w
+    });
+
+    qspi.dcr.modify(|_, w| unsafe {
+        // W25Q32 为 4 MB，2^(21 + 1) = 4 MB
+        w.fsize().bits(21);
+        w.ckmode().set_bit();
+        w
+    });
+
+    qspi.cr.modify(|_, w| w.en().set_bit());
+}
@@ -0,0 +1,455 @@
+//! 不使用 embassy，用 core::future 编写由中断驱动的驱动
+//!
+//! 执行器见 utils::executor，中断与任务之间的通知见 utils::wait_cell，这里同时运行 4 个任务：
+//!
+//! 1. uart：USART1 的 RXNE 中断把收到的字节放入 ByteChannel，任务逐个取出，收到回车后把整行回显到串口
+//! 2. i2c：每秒读取一次 BME280 的 chip id（0xD0 寄存器，应为 0x60），
+//!    每一步都是“检查 SR1，条件不满足时打开中断，await 一个 WaitCell”，I2C1 的事件与错误中断关闭中断并 signal
+//! 3. dma：每秒用 DMA2 Stream0 做一次内存到内存的复制，传输完成（或出错）的中断 signal 结果，任务比较两个缓冲是否相同
+//! 4. heartbeat：每 500 ms 翻转一次 LED，以及在 RTT 上输出各任务的计数
+//!
+//! 没有任何任务就绪时，CPU 处于 WFE 睡眠，SysTick 以 1 kHz 唤醒 sleep_ms 中的任务
+//!
+//! 与之前的示例对比：
+//! 同样的 I2C 流程，utils::blocking_master 在等待每一个标识位时都在空转，而这里等待时其他任务可以继续运行；
+//! 与 s04c01 中完全由中断驱动的状态机相比，流程依旧是从上往下顺序书写的，状态保存在 async fn 生成的 Future 中
+//!
+//! 注意：DMA 的缓冲位于 dma 任务的 Future 中，因此这个 Future 在传输过程中不能被 drop，
+//! 这里的任务都固定在 main 的栈上，且 main 永远不会返回，满足这个条件
+//!
+//! 接线图：
+//!
+//! BME280 模块
+//! PB8 SCL
+//! PB9 SDA
+//!
+//! PC13 -> 1k -> LED -> GND
+//!
+//! USB-TTL 模块，115200 8N1
+//! PA9  (USART1 Tx) <-> Rx
+//! PA10 (USART1 Rx) <-> Tx
+
+#![no_std]
+#![no_main]
+
+use core::{
+    pin::pin,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use cortex_m::peripheral::{syst::SystClkSource, NVIC};
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+use utils::{
+    blocking_master::{self, MasterError},
+    executor::{self, sleep_ms},
+    ticker,
+    wait_cell::{ByteChannel, WaitCell},
+};
+
+// 与 s21c02 相同，BME280 的 SDO 接地
+const BME280_ADDR: u8 = 0x76;
+const BME280_REG_ID: u8 = 0xD0;
+const BME280_CHIP_ID: u8 = 0x60;
+
+const DMA_WORDS: usize = 64;
+
+// F413 的 pac 中 I2C1 的事件与错误中断名为 I2C1_EVT、I2C1_ERR，F401/F411/F412 则为 I2C1_EV、I2C1_ER
+#[cfg(feature = "stm32f413")]
+const I2C1_IRQS: [interrupt; 2] = [interrupt::I2C1_EVT, interrupt::I2C1_ERR];
+#[cfg(not(feature = "stm32f413"))]
+const I2C1_IRQS: [interrupt; 2] = [interrupt::I2C1_EV, interrupt::I2C1_ER];
+
+static UART_RX: ByteChannel<64> = ByteChannel::new();
+static I2C_EVENT: WaitCell<()> = WaitCell::new();
+static DMA_DONE: WaitCell<Result<(), ()>> = WaitCell::new();
+
+// 各任务完成的次数，由 heartbeat 任务输出
+static G_LINES: AtomicU32 = AtomicU32::new(0);
+static G_I2C_OK: AtomicU32 = AtomicU32::new(0);
+static G_DMA_OK: AtomicU32 = AtomicU32::new(0);
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nasync drivers");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut cp = pac::CorePeripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_led(&dp);
+    setup_usart1(&dp);
+    setup_i2c1(&dp);
+    dp.RCC.ahb1enr.modify(|_, w| w.dma2en().enabled());
+
+    // SysTick 使用 12 MHz 的核心时钟，1 kHz
+    cp.SYST.set_clock_source(SystClkSource::Core);
+    cp.SYST.set_reload(12_000 - 1);
+    cp.SYST.clear_current();
+    cp.SYST.enable_interrupt();
+    cp.SYST.enable_counter();
+
+    unsafe {
+        NVIC::unmask(interrupt::USART1);
+        for irq in I2C1_IRQS {
+            NVIC::unmask(irq);
+        }
+        NVIC::unmask(interrupt::DMA2_STREAM0);
+    }
+
+    let uart = pin!(uart_task(&dp.USART1));
+    let i2c = pin!(i2c_task(&dp.I2C1));
+    let dma = pin!(dma_task(&dp.DMA2));
+    let heartbeat = pin!(heartbeat_task(&dp.GPIOC));
+
+    executor::run([uart, i2c, dma, heartbeat])
+}
+
+async fn uart_task(usart: &pac::USART1) {
+    let mut line = [0u8; 64];
+    let mut len = 0;
+
+    loop {
+        let byte = UART_RX.recv().await;
+        match byte {
+            b'\r' | b'\n' => {
+                if len == 0 {
+                    continue;
+                }
+                // 发送只是写几个字节，这里依旧以轮询的方式完成
+                usart_write(usart, b"echo: ");
+                usart_write(usart, &line[..len]);
+                usart_write(usart, b"\r\n");
+                len = 0;
+                G_LINES.fetch_add(1, Ordering::Relaxed);
+            }
+            _ if len < line.len() => {
+                line[len] = byte;
+                len += 1;
+            }
+            // 过长的行直接截断
+            _ => (),
+        }
+    }
+}
+
+async fn i2c_task(i2c: &pac::I2C1) {
+    loop {
+        match read_register(i2c, BME280_ADDR, BME280_REG_ID).await {
+            Ok(BME280_CHIP_ID) => {
+                G_I2C_OK.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(id) => rprintln!("i2c: unexpected chip id {:#04x}", id),
+            Err(e) => rprintln!("i2c: {:?}", e),
+        }
+        sleep_ms(1000).await;
+    }
+}
+
+async fn dma_task(dma: &pac::DMA2) {
+    let mut src = [0u32; DMA_WORDS];
+    let mut dst = [0u32; DMA_WORDS];
+    let mut round = 0u32;
+
+    loop {
+        round = round.wrapping_add(1);
+        for (index, word) in src.iter_mut().enumerate() {
+            *word = round.wrapping_mul(0x9E37_79B9) ^ index as u32;
+        }
+        dst.fill(0);
+
+        match dma_copy(dma, &src, &mut dst).await {
+            Ok(()) if src == dst => {
+                G_DMA_OK.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(()) => rprintln!("dma: data mismatch"),
+            Err(()) => rprintln!("dma: transfer error"),
+        }
+        sleep_ms(1000).await;
+    }
+}
+
+async fn heartbeat_task(gpioc: &pac::GPIOC) {
+    let mut count = 0u32;
+    loop {
+        gpioc.odr.modify(|r, w| w.odr13().bit(!r.odr13().bit()));
+        count += 1;
+        if count.is_multiple_of(10) {
+            rprintln!(
+                "lines: {}, i2c ok: {}, dma ok: {}, uart dropped: {}",
+                G_LINES.load(Ordering::Relaxed),
+                G_I2C_OK.load(Ordering::Relaxed),
+                G_DMA_OK.load(Ordering::Relaxed),
+                UART_RX.dropped()
+            );
+        }
+        sleep_ms(500).await;
+    }
+}
+
+// 等待 SR1 中的某个标识位，出错时与 blocking_master 的处理相同
+//
+// buf 为 true 时，同时打开 ITBUFEN：TXE 与 RXNE 只有在 ITBUFEN 打开时才会产生中断，
+// 而在等待 SB、ADDR、BTF 时打开它，TXE 会不停地触发中断，因此只在需要时打开
+async fn wait_flag(
+    i2c: &pac::I2C1,
+    buf: bool,
+    flag: impl Fn(&pac::i2c1::sr1::R) -> bool,
+) -> Result<(), MasterError> {
+    loop {
+        blocking_master::check_error(i2c)?;
+        if flag(&i2c.sr1.read()) {
+            return Ok(());
+        }
+
+        // 先清掉遗留的通知再打开中断；如果标识位在这两步之间出现，中断会在打开之后立刻触发，不会错过
+        I2C_EVENT.reset();
+        i2c.cr2.modify(|_, w| {
+            w.itevten().enabled();
+            w.iterren().enabled();
+            w.itbufen().bit(buf);
+            w
+        });
+        I2C_EVENT.wait().await;
+    }
+}
+
+// 读取一个 8 位寄存器：START、地址+写、寄存器地址、Repeated START、地址+读、1 个字节、STOP
+async fn read_register(i2c: &pac::I2C1, addr: u8, reg: u8) -> Result<u8, MasterError> {
+    i2c.cr1.modify(|_, w| w.start().start());
+    wait_flag(i2c, false, |sr1| sr1.sb().is_start()).await?;
+    i2c.dr.write(|w| w.dr().bits(addr << 1));
+    wait_flag(i2c, false, |sr1| sr1.addr().is_match()).await?;
+    i2c.sr1.read();
+    i2c.sr2.read();
+
+    i2c.dr.write(|w| w.dr().bits(reg));
+    wait_flag(i2c, false, |sr1| sr1.btf().bit_is_set()).await?;
+
+    i2c.cr1.modify(|_, w| w.start().start());
+    wait_flag(i2c, false, |sr1| sr1.sb().is_start()).await?;
+    i2c.dr.write(|w| w.dr().bits(addr << 1 | 1));
+    wait_flag(i2c, false, |sr1| sr1.addr().is_match()).await?;
+
+    // 与 blocking_master::read 相同，只接收 1 个字节时，必须在清理 ADDR 之前关闭 ACK
+    i2c.cr1.modify(|_, w| w.ack().nak());
+    i2c.sr1.read();
+    i2c.sr2.read();
+    i2c.cr1.modify(|_, w| w.stop().stop());
+
+    wait_flag(i2c, true, |sr1| sr1.rx_ne().is_not_empty()).await?;
+    Ok(i2c.dr.read().dr().bits())
+}
+
+// 事件与错误中断的处理相同
+fn on_i2c1_irq() {
+    let i2c = unsafe { &*pac::I2C1::ptr() };
+    // 标识位留给任务处理，这里只关闭中断，否则标识位没有被清理之前，中断会不停地触发
+    i2c.cr2.modify(|_, w| {
+        w.itevten().disabled();
+        w.itbufen().disabled();
+        w.iterren().disabled();
+        w
+    });
+    I2C_EVENT.signal(());
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn I2C1_EVT() {
+    on_i2c1_irq();
+}
+
+#[cfg(feature = "stm32f413")]
+#[interrupt]
+fn I2C1_ERR() {
+    on_i2c1_irq();
+}
+
+#[cfg(not(feature = "stm32f413"))]
+#[interrupt]
+fn I2C1_EV() {
+    on_i2c1_irq();
+}
+
+#[cfg(not(feature = "stm32f413"))]
+#[interrupt]
+fn I2C1_ER() {
+    on_i2c1_irq();
+}
+
+// 用 DMA2 Stream0 把 src 复制到 dst，流程与 s08c01 相同，只是等待的方式换成了中断
+async fn dma_copy(dma: &pac::DMA2, src: &[u32], dst: &mut [u32]) -> Result<(), ()> {
+    let st = &dma.st[0];
+    let len = src.len().min(dst.len());
+
+    if st.cr.read().en().is_enabled() {
+        st.cr.modify(|_, w| w.en().disabled());
+        while st.cr.read().en().is_enabled() {}
+    }
+    clear_dma_flags(dma);
+
+    st.par
+        .write(|w| unsafe { w.pa().bits(src.as_ptr() as u32) });
+    st.m0ar
+        .write(|w| unsafe { w.m0a().bits(dst.as_mut_ptr() as u32) });
+    st.ndtr.write(|w| w.ndt().bits(len as u16));
+    st.cr.write(|w| {
+        w.chsel().bits(0);
+        w.pl().medium();
+        w.dir().memory_to_memory();
+        w.psize().bits32();
+        w.pinc().incremented();
+        w.msize().bits32();
+        w.minc().incremented();
+        w.tcie().enabled();
+        w.teie().enabled();
+        w
+    });
+
+    DMA_DONE.reset();
+    st.cr.modify(|_, w| w.en().enabled());
+    DMA_DONE.wait().await
+}
+
+fn clear_dma_flags(dma: &pac::dma2::RegisterBlock) {
+    dma.lifcr.write(|w| {
+        w.ctcif0().clear();
+        w.chtif0().clear();
+        w.cteif0().clear();
+        w.cdmeif0().clear();
+        w.cfeif0().clear();
+        w
+    });
+}
+
+#[interrupt]
+fn DMA2_STREAM0() {
+    let dma = unsafe { &*pac::DMA2::ptr() };
+    let lisr = dma.lisr.read();
+    clear_dma_flags(dma);
+
+    if lisr.teif0().is_error() {
+        DMA_DONE.signal(Err(()));
+    } else if lisr.tcif0().is_complete() {
+        DMA_DONE.signal(Ok(()));
+    }
+}
+
+#[interrupt]
+fn USART1() {
+    let usart = unsafe { &*pac::USART1::ptr() };
+    // 先读 SR 再读 DR，同时清除 RXNE 与 ORE
+    let sr = usart.sr.read();
+    let byte = usart.dr.read().dr().bits() as u8;
+    if sr.rxne().bit_is_set() {
+        UART_RX.push(byte);
+    }
+}
+
+#[cortex_m_rt::exception]
+fn SysTick() {
+    executor::on_tick();
+}
+
+fn usart_write(usart: &pac::USART1, bytes: &[u8]) {
+    for &byte in bytes {
+        while usart.sr.read().txe().bit_is_clear() {}
+        usart.dr.write(|w| w.dr().bits(byte as u16));
+    }
+}
+
+// 切换到 HSE 时钟源，utils::ticker 假设 TIM 的时钟为 12 MHz
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+fn setup_led(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.GPIOC.moder.modify(|_, w| w.moder13().output());
+}
+
+// 与 s21c02 相同，I2C1 使用 PB8/PB9，100 kHz
+fn setup_i2c1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.i2c1en().enabled());
+
+    let gpiob = &dp.GPIOB;
+    gpiob.afrh.modify(|_, w| {
+        w.afrh8().af4();
+        w.afrh9().af4();
+        w
+    });
+    gpiob.otyper.modify(|_, w| {
+        w.ot8().open_drain();
+        w.ot9().open_drain();
+        w
+    });
+    gpiob.pupdr.modify(|_, w| {
+        w.pupdr8().pull_up();
+        w.pupdr9().pull_up();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder8().alternate();
+        w.moder9().alternate();
+        w
+    });
+
+    let i2c = &dp.I2C1;
+    i2c.cr2.modify(|_, w| unsafe { w.freq().bits(12) });
+    i2c.ccr.modify(|_, w| unsafe { w.ccr().bits(60) });
+    i2c.trise.write(|w| w.trise().bits(13));
+    i2c.cr1.modify(|_, w| w.pe().enabled());
+}
+
+// USART1 使用 PA9/PA10，115200 8N1，打开 RXNE 中断
+fn setup_usart1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| {
+        w.afrh9().af7();
+        w.afrh10().af7();
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr9().pull_up();
+        w.pupdr10().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let serial1 = &dp.USART1;
+
+    serial1.cr1.modify(|_, w| w.ue().enabled());
+
+    // 12 MHz / (16 * 115200) = 6.51，整数部分为 6，小数部分为 0.51 * 16 ≈ 8
+    serial1.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    serial1.cr1.modify(|_, w| {
+        w.te().enabled();
+        w.re().enabled();
+        w.rxneie().enabled();
+        w
+    });
+}
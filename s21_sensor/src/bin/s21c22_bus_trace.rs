@@ -0,0 +1,140 @@
+//! 记录 I2C/SPI 传输的内容与耗时
+//!
+//! utils::bus_trace::Traced 可以包在任意一个 embedded-hal 的总线外面，这里包的是 stm32f4xx_hal 提供的 I2C1 与 SPI2，
+//! 每秒执行一轮以下传输，每一次传输都会在 RTT 上输出一行：
+//!
+//! 1. 读取 BME280 的 chip id（0xD0，应为 0x60）
+//! 2. 连续读取 BME280 的 26 字节校准数据（0x88 ~ 0xA1），只输出前几个字节
+//! 3. 读取 0x77 处（不存在的设备），可以看到 NoAcknowledge 的错误以及它花的时间
+//! 4. SPI2 的 MOSI 与 MISO 短接，发出 4 个字节并收回
+//!
+//! 按下按键可以打开或关闭输出，关闭之后只剩下每一轮的汇总
+//! 调试新传感器的驱动时，把驱动拿到的总线换成 Traced::new("名字", 总线) 即可，驱动本身不需要修改
+//!
+//! 接线图：
+//!
+//! BME280 模块，SDO 接地（地址 0x76）
+//! PB8 SCL
+//! PB9 SDA
+//!
+//! PB14 (SPI2_MISO) <-> PB15 (SPI2_MOSI)
+//!
+//! PA0 <-> 按键 <-> 3.3V（PA0 内部下拉，按下为高电平）
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::{
+    hal::{
+        i2c::I2c,
+        spi::{SpiBus, MODE_0},
+    },
+    pac,
+    prelude::*,
+};
+
+mod utils;
+
+use utils::{
+    bus_trace::{self, Traced},
+    ticker,
+};
+
+const BME280_ADDR: u8 = 0x76;
+const BME280_REG_ID: u8 = 0xD0;
+const BME280_REG_CALIB: u8 = 0x88;
+// 用来演示 NACK 的地址，BME280 的 SDO 接地时，这里没有设备
+const ABSENT_ADDR: u8 = 0x77;
+
+const ROUND_MS: u32 = 1000;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("\nbus trace");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    // ticker 假设 TIM 的时钟为 12 MHz，下面 freeze 之后才会切换到 HSE，在此之前的时间是不准的
+    ticker::setup(&dp);
+
+    // 直接使用 HSE，APB1 不分频，TIM 的时钟正好为 12 MHz
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.use_hse(12.MHz()).freeze();
+
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+
+    let button = gpioa.pa0.into_pull_down_input();
+
+    let i2c = dp.I2C1.i2c((gpiob.pb8, gpiob.pb9), 100.kHz(), &clocks);
+    let mut i2c = Traced::new("bme280", i2c);
+
+    let spi = dp.SPI2.spi(
+        (gpiob.pb13, gpiob.pb14, gpiob.pb15),
+        MODE_0,
+        1.MHz(),
+        &clocks,
+    );
+    let mut spi = Traced::new("spi loop", spi);
+
+    let mut round = 0u32;
+    let mut last_round = ticker::millis();
+    let mut was_pressed = false;
+
+    loop {
+        // 按键按下的瞬间切换输出
+        let pressed = button.is_high();
+        if pressed && !was_pressed {
+            let enabled = !bus_trace::is_enabled();
+            bus_trace::set_enabled(enabled);
+            rprintln!("trace {}", if enabled { "on" } else { "off" });
+        }
+        was_pressed = pressed;
+
+        if ticker::millis().wrapping_sub(last_round) < ROUND_MS {
+            continue;
+        }
+        last_round = ticker::millis();
+        round += 1;
+
+        let mut errors = 0;
+
+        let mut id = [0u8; 1];
+        if i2c
+            .write_read(BME280_ADDR, &[BME280_REG_ID], &mut id)
+            .is_err()
+        {
+            errors += 1;
+        }
+
+        let mut calib = [0u8; 26];
+        if i2c
+            .write_read(BME280_ADDR, &[BME280_REG_CALIB], &mut calib)
+            .is_err()
+        {
+            errors += 1;
+        }
+
+        // 这一次本来就应该失败
+        let mut absent = [0u8; 1];
+        let absent_ok = i2c.read(ABSENT_ADDR, &mut absent).is_ok();
+
+        let sent = round.to_be_bytes();
+        let mut received = [0u8; 4];
+        match spi.transfer(&mut received, &sent) {
+            Ok(()) if received == sent => (),
+            _ => errors += 1,
+        }
+
+        rprintln!(
+            "round {}: chip id {:#04x}, {} error(s), 0x77 {}",
+            round,
+            id[0],
+            errors,
+            if absent_ok { "answered?" } else { "nack" }
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! 在 TFT 屏幕上用触摸操作菜单
+//!
+//! 屏幕为 240x240 的 ST7789，触摸为 XPT2046 电阻屏控制器，两者共用 SPI1：
+//! 屏幕使用 Mode 3、6 MHz，XPT2046 每次访问时临时切换为 Mode 0、1.5 MHz（见 utils::xpt2046）
+//!
+//! 菜单与 s21c04 使用同一个 utils::ui，只是 TextPanel 由 LCD1602 换成了 TFT（utils::tft_panel），
+//! 四个按键换成了屏幕底部的四个按钮，点击（按下再抬起）按钮就相当于按下对应的按键
+//!
+//! 触摸的校准保存在 utils::settings 中，位于芯片内部 Flash 的扇区 10 与 11，与 s21c04 的配置（扇区 8 与 9）互不干扰
+//! 以下情况会进入校准：
+//!
+//! - Flash 中还没有校准参数
+//! - 开机时正按着屏幕
+//! - 在菜单 Touch -> Recal 中把数值改为 1 并确认
+//!
+//! 校准时屏幕上依次显示三个十字，用触控笔按住十字的中心，直到十字消失
+//!
+//! 接线图：
+//!
+//! PA5 SPI1_SCK  -> ST7789 SCL、XPT2046 T_CLK
+//! PA6 SPI1_MISO -> XPT2046 T_DO
+//! PA7 SPI1_MOSI -> ST7789 SDA、XPT2046 T_DIN
+//! PB1           -> ST7789 CS
+//! PB2           -> ST7789 DC
+//! PB0           -> XPT2046 T_CS
+//! PC4           -> XPT2046 T_IRQ（PENIRQ，开漏输出，使用内部上拉）
+//! 3.3V          -> ST7789 RES、BLK
+
+#![no_std]
+#![no_main]
+
+use core::{cell::Cell, convert::Infallible};
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::{
+    hal::digital::{ErrorType, OutputPin},
+    pac,
+};
+
+mod utils;
+
+use utils::{
+    exti::{self, Port, Trigger},
+    framebuffer::{self, Color, FrameBuffer, Rect},
+    internal_flash::InternalFlash,
+    settings::SettingsStore,
+    st7789::{PanelBus, SpiPanelBus, St7789},
+    tft_panel::TftPanel,
+    ticker,
+    ui::{Item, Menu, Number, Ui},
+    xpt2046::{self, Affine, Config, TouchEvent, Xpt2046},
+};
+
+// 设置存放在内部 Flash 的扇区 10 与 11
+const SETTINGS_START: u32 = 0x0C_0000;
+
+const SYSCLK_HZ: u32 = 12_000_000;
+
+const WIDTH: u16 = 240;
+const HEIGHT: u16 = 240;
+
+// PENIRQ 所在的引脚，EXTI4
+const PENIRQ_PIN: u8 = 4;
+
+// 校准的三个目标点，分布在三个角附近
+const TARGETS: [(u16, u16); 3] = [(24, 24), (216, 120), (60, 216)];
+const CROSS_SIZE: u16 = 10;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    static mut FRAME: [Color; WIDTH as usize * HEIGHT as usize] =
+        [0; WIDTH as usize * HEIGHT as usize];
+
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_spi1(&dp);
+    setup_penirq(&dp);
+
+    let mut store = SettingsStore::open(InternalFlash::new(&dp.FLASH), SETTINGS_START).unwrap();
+    let saved = Affine::load(&store);
+    rprintln!("touch calibration: {:?}", saved);
+
+    // 12 MHz / 2 = 6 MHz
+    let mut lcd = St7789::new(SpiPanelBus::new(&dp, &dp.SPI1, 0), 0, 0);
+    lcd.init(SYSCLK_HZ);
+    let mut panel = TftPanel::new(FrameBuffer::new(FRAME, WIDTH, HEIGHT), lcd);
+
+    let cal = saved.unwrap_or(Affine::uncalibrated(WIDTH, HEIGHT));
+    let mut touch = Xpt2046::new(&dp.SPI1, Pb0, Config::default(), cal).unwrap();
+
+    let held_at_boot = touch.sample().unwrap().is_some();
+    if saved.is_none() || held_at_boot {
+        if held_at_boot {
+            // 先等手离开屏幕，否则第一个点会直接使用开机时按住的位置
+            while touch.sample().unwrap().is_some() {}
+        }
+        calibrate(&mut touch, &mut panel, &mut store);
+    }
+
+    // 菜单中显示与修改的数据
+    let taps = Cell::new(0u32);
+    let last_x = Cell::new(None::<f32>);
+    let last_y = Cell::new(None::<f32>);
+    let level = Cell::new(5.0f32);
+    let recal = Cell::new(false);
+
+    let uptime = || Some(ticker::millis() as f32 / 1000.0);
+    let get_taps = || Some(taps.get() as f32);
+    let get_level = || level.get();
+    let set_level = |value| level.set(value);
+    let get_x = || last_x.get();
+    let get_y = || last_y.get();
+    let get_recal = || 0.0;
+    let set_recal = |value: f32| recal.set(value >= 1.0);
+
+    let touch_items = [
+        Item::Value {
+            label: "X",
+            unit: "px",
+            get: &get_x,
+        },
+        Item::Value {
+            label: "Y",
+            unit: "px",
+            get: &get_y,
+        },
+        Item::Number {
+            label: "Recal",
+            unit: "",
+            number: Number {
+                get: &get_recal,
+                set: &set_recal,
+                min: 0.0,
+                max: 1.0,
+                step: 1.0,
+                decimals: 0,
+            },
+        },
+    ];
+    let main_items = [
+        Item::Value {
+            label: "Uptime",
+            unit: "s",
+            get: &uptime,
+        },
+        Item::Value {
+            label: "Taps",
+            unit: "",
+            get: &get_taps,
+        },
+        Item::Number {
+            label: "Level",
+            unit: "",
+            number: Number {
+                get: &get_level,
+                set: &set_level,
+                min: 0.0,
+                max: 10.0,
+                step: 1.0,
+                decimals: 0,
+            },
+        },
+        Item::Menu(Menu {
+            title: "Touch",
+            items: &touch_items,
+        }),
+    ];
+    let root = Menu {
+        title: "Main",
+        items: &main_items,
+    };
+
+    let mut ui = Ui::<_, 2>::new(panel, &root, 500);
+
+    loop {
+        match touch.poll() {
+            Ok(Some(TouchEvent::Down(point))) => ui.panel().highlight(Some(point)),
+            Ok(Some(TouchEvent::Move(_))) => {}
+            Ok(Some(TouchEvent::Up(point))) => {
+                ui.panel().highlight(None);
+                last_x.set(Some(point.x as f32));
+                last_y.set(Some(point.y as f32));
+                if let Some(event) = ui.panel().button_at(point) {
+                    taps.set(taps.get() + 1);
+                    ui.handle(event);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => rprintln!("touch error: {:?}", e),
+        }
+
+        if recal.replace(false) {
+            calibrate(&mut touch, ui.panel(), &mut store);
+        }
+
+        ui.refresh(ticker::millis());
+    }
+}
+
+// 校准并保存，失败时继续使用原来的参数
+fn calibrate<B: PanelBus>(
+    touch: &mut Xpt2046<Pb0>,
+    panel: &mut TftPanel<B>,
+    store: &mut SettingsStore<InternalFlash>,
+) {
+    let result = touch.calibrate(&TARGETS, |index, target| {
+        let fb = panel.frame_buffer();
+        fb.clear(framebuffer::BLACK);
+        draw_cross(fb, target);
+        let hint = [b'1' + index as u8, b'/', b'3'];
+        fb.draw_text(
+            96,
+            110,
+            core::str::from_utf8(&hint).unwrap_or(""),
+            framebuffer::WHITE,
+            u8::MAX,
+            2,
+        );
+        panel.flush();
+    });
+
+    match result {
+        Ok(cal) => {
+            rprintln!("touch calibrated: {:?}", cal);
+            match cal.save(store) {
+                Ok(()) => rprintln!("saved, generation {}", store.generation()),
+                Err(e) => rprintln!("failed to save: {:?}", e),
+            }
+        }
+        Err(e) => rprintln!("calibration failed: {:?}", e),
+    }
+
+    panel.redraw();
+}
+
+fn draw_cross(fb: &mut FrameBuffer, (x, y): (u16, u16)) {
+    let color = framebuffer::RED;
+    fb.fill_rect(
+        Rect::new(x.saturating_sub(CROSS_SIZE), y, CROSS_SIZE * 2 + 1, 1),
+        color,
+    );
+    fb.fill_rect(
+        Rect::new(x, y.saturating_sub(CROSS_SIZE), 1, CROSS_SIZE * 2 + 1),
+        color,
+    );
+}
+
+// PB0 作为 XPT2046 的片选
+struct Pb0;
+
+impl ErrorType for Pb0 {
+    type Error = Infallible;
+}
+
+impl OutputPin for Pb0 {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let gpiob = unsafe { &*pac::GPIOB::ptr() };
+        gpiob.bsrr.write(|w| w.br0().reset());
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let gpiob = unsafe { &*pac::GPIOB::ptr() };
+        gpiob.bsrr.write(|w| w.bs0().set());
+        Ok(())
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// SPI1 的模式与分频由 SpiPanelBus 设置，这里只负责引脚
+fn setup_spi1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioaen().enabled();
+        w.gpioben().enabled();
+        w
+    });
+    dp.RCC.apb2enr.modify(|_, w| w.spi1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrl.modify(|_, w| {
+        w.afrl5().af5();
+        w.afrl6().af5();
+        w.afrl7().af5();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder5().alternate();
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w
+    });
+
+    // 两个片选在切换为输出之前先设置为高电平
+    let gpiob = &dp.GPIOB;
+    gpiob.bsrr.write(|w| {
+        w.bs0().set();
+        w.bs1().set();
+        w
+    });
+    gpiob.moder.modify(|_, w| {
+        w.moder0().output();
+        w.moder1().output();
+        w.moder2().output();
+        w
+    });
+}
+
+// PC4 上拉输入，下降沿触发 EXTI4
+fn setup_penirq(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.GPIOC.pupdr.modify(|_, w| w.pupdr4().pull_up());
+    dp.GPIOC.moder.modify(|_, w| w.moder4().input());
+
+    exti::register(dp, Port::C, PENIRQ_PIN, Trigger::Falling, xpt2046::pen_irq).unwrap();
+}
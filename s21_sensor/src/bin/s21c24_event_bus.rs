@@ -0,0 +1,201 @@
+//! 用事件总线（utils::event_bus）连接中断与主循环
+//!
+//! 三个 Topic，分别来自三种不同的来源：
+//!
+//! - keys：utils::keypad 的四个按键，消抖在主循环中完成，因此由主循环发布
+//! - button：PB5 上的按钮，在 EXTI 的回调（中断）中发布按下的时刻，这里故意没有消抖，一次按下可能发布好几个事件
+//! - heartbeat：TIM4 每秒一次的更新中断，发布一个递增的计数
+//!
+//! 两个互不相关的订阅者各自读取自己关心的 Topic，发布的一方不需要知道有几个订阅者：
+//!
+//! - 日志：订阅全部三个 Topic，把每个事件打印到 RTT
+//! - LED：订阅 keys 与 button，每个事件翻转一次 PC13 上的 LED
+//!
+//! 按 Enter 打印各个 Topic 的统计
+//! 按 Back 让日志停下 5 秒，模拟一个处理得很慢的订阅者，期间多按几次 PB5 上的按钮，
+//! 超过 button 的容量之后，日志会报告丢失了多少个事件，而 LED 依然每次都会翻转
+//!
+//! 接线图：
+//!
+//! PC0 ~ PC3 -> 四个按键（见 utils::keypad）
+//! PB5       -> 按钮 -> GND（使用内部上拉）
+//! PC13      -> 1k -> LED -> GND
+
+#![no_std]
+#![no_main]
+
+use core::fmt::{self, Write};
+
+use panic_rtt_target as _;
+use rtt_target::{rprint, rprintln, rtt_init_print};
+use stm32f4xx_hal::pac::{self, interrupt, NVIC};
+
+mod utils;
+
+use utils::{
+    event_bus::{self, Topic, TopicInfo},
+    exti::{self, Port, Trigger},
+    keypad::{self, Key, KeyEvent, Keypad},
+    ticker,
+};
+
+const BUTTON_PIN: u8 = 5;
+// 日志停下的时间
+const STALL_MS: u32 = 5000;
+
+static KEYS: Topic<KeyEvent, 8> = Topic::new("keys");
+// 事件为按下的时刻，单位 ms
+static BUTTON: Topic<u32, 4> = Topic::new("button");
+static HEARTBEAT: Topic<u32, 4> = Topic::new("heartbeat");
+
+static TOPICS: [&dyn TopicInfo; 3] = [&KEYS, &BUTTON, &HEARTBEAT];
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Program Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_led(&dp);
+    keypad::setup(&dp);
+    setup_button(&dp);
+    setup_heartbeat(&dp);
+
+    let mut keypad = Keypad::new();
+
+    // 日志
+    let mut log_keys = KEYS.subscribe();
+    let mut log_button = BUTTON.subscribe();
+    let mut log_heartbeat = HEARTBEAT.subscribe();
+    let mut reported_lost = 0;
+
+    // LED
+    let mut led_keys = KEYS.subscribe();
+    let mut led_button = BUTTON.subscribe();
+    let mut led_on = false;
+
+    let mut stall_until = None::<u32>;
+
+    loop {
+        let now = ticker::millis();
+
+        if let Some(event) = keypad.poll(now) {
+            KEYS.publish(event);
+        }
+
+        // LED 的订阅者每一轮都读取
+        while led_keys.try_recv().is_some() || led_button.try_recv().is_some() {
+            led_on = !led_on;
+            set_led(&dp, led_on);
+        }
+
+        // 日志停下期间，事件留在各自的环形缓冲里，直到被覆盖
+        if let Some(until) = stall_until {
+            if (now.wrapping_sub(until) as i32) < 0 {
+                continue;
+            }
+            stall_until = None;
+            rprintln!("log resumed");
+        }
+
+        while let Some(event) = log_keys.try_recv() {
+            rprintln!("[{}] key {:?}", now, event);
+            match event {
+                KeyEvent::Press(Key::Enter) => {
+                    event_bus::write_stats(&TOPICS, &mut Rtt).ok();
+                }
+                KeyEvent::Press(Key::Back) => {
+                    rprintln!("log stalled for {} ms", STALL_MS);
+                    stall_until = Some(now.wrapping_add(STALL_MS));
+                }
+                _ => {}
+            }
+        }
+        while let Some(pressed_at) = log_button.try_recv() {
+            rprintln!("[{}] button pressed at {}", now, pressed_at);
+        }
+        while let Some(count) = log_heartbeat.try_recv() {
+            rprintln!("[{}] heartbeat {}", now, count);
+        }
+
+        let lost = log_keys.lost() + log_button.lost() + log_heartbeat.lost();
+        if lost != reported_lost {
+            rprintln!("log lost {} event(s) in total", lost);
+            reported_lost = lost;
+        }
+    }
+}
+
+// EXTI 的回调，在中断中执行
+fn on_button(_line: u8) {
+    BUTTON.publish(ticker::millis());
+}
+
+#[interrupt]
+fn TIM4() {
+    static mut COUNT: u32 = 0;
+
+    let tim4 = unsafe { &*pac::TIM4::ptr() };
+    tim4.sr.modify(|_, w| w.uif().clear_bit());
+
+    *COUNT += 1;
+    HEARTBEAT.publish(*COUNT);
+}
+
+// 让 write_stats 直接输出到 RTT
+struct Rtt;
+
+impl Write for Rtt {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        rprint!("{}", s);
+        Ok(())
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+fn setup_led(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.GPIOC.moder.modify(|_, w| w.moder13().output());
+}
+
+fn set_led(dp: &pac::Peripherals, on: bool) {
+    dp.GPIOC
+        .bsrr
+        .write(|w| if on { w.bs13().set() } else { w.br13().reset() });
+}
+
+// PB5 上拉输入，按下时为下降沿
+fn setup_button(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.GPIOB.pupdr.modify(|_, w| w.pupdr5().pull_up());
+    dp.GPIOB.moder.modify(|_, w| w.moder5().input());
+
+    exti::register(dp, Port::B, BUTTON_PIN, Trigger::Falling, on_button).unwrap();
+}
+
+// 12 MHz / 12000 = 1 kHz，计数 1000 次为 1 秒
+fn setup_heartbeat(dp: &pac::Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.tim4en().enabled());
+
+    let tim4 = &dp.TIM4;
+    tim4.psc.write(|w| w.psc().bits(12_000 - 1));
+    tim4.arr.write(|w| w.arr().bits(1000 - 1));
+    // 让预分频立即生效，并清除 UG 产生的更新标志
+    tim4.egr.write(|w| w.ug().update());
+    tim4.sr.modify(|_, w| w.uif().clear_bit());
+    tim4.dier.modify(|_, w| w.uie().enabled());
+    tim4.cr1.modify(|_, w| w.cen().enabled());
+
+    unsafe { NVIC::unmask(interrupt::TIM4) };
+}
@@ -0,0 +1,285 @@
+//! 通过 RTT 命令行观察、修改运行中的变量
+//!
+//! 变量的登记与读写见 utils::watch，这里登记了几个变量：
+//!
+//! | 名字      | 类型 | 权限 | 含义                                             |
+//! | --------- | ---- | ---- | ------------------------------------------------ |
+//! | uptime_s  | u32  | r    | 开机以来的秒数                                   |
+//! | presses   | u16  | r    | PB5 上的按钮按下的次数，在 EXTI 的回调中累加     |
+//! | loop_hz   | f32  | r    | 主循环每秒运行的次数，经过一阶低通               |
+//! | alpha     | f32  | rw   | 上面那个低通的系数，0 ~ 1，越小越平滑            |
+//! | blink_ms  | u32  | rw   | PC13 上的 LED 翻转的间隔                         |
+//! | led_on    | bool | rw   | 是否闪烁 LED                                     |
+//!
+//! 命令行运行在 RTT 的 1 号通道上，不会阻塞主循环，等待输入的时候 LED 照样闪烁、计数照样增加：
+//!
+//! watch                     列出全部变量
+//! watch get <name>          读取一个变量
+//! watch set <name> <value>  修改一个变量，比如 watch set blink_ms 100
+//! mon <name> <ms>           每隔 ms 毫秒打印一次变量的值，再次输入任何命令时停止
+//!
+//! 连接方法与 s21c20 的 SHELL_OVER_RTT 相同，在 OpenOCD 的 telnet 中：
+//!
+//! ```shell
+//! rtt setup 0x20000000 0x50000 "SEGGER RTT"
+//! rtt start
+//! rtt server start 9091 1
+//! ```
+//!
+//! 接线图：
+//!
+//! PB5  -> 按钮 -> GND（使用内部上拉）
+//! PC13 -> 1k -> LED -> GND
+
+#![no_std]
+#![no_main]
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering},
+};
+
+use embedded_io::{Read, ReadReady, Write as IoWrite};
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init, set_print_channel};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    exti::{self, Port, Trigger},
+    io,
+    sensor::sink::LineBuf,
+    ticker,
+    watch::{self, AtomicF32},
+};
+
+const BUTTON_PIN: u8 = 5;
+const LINE_LEN: usize = 64;
+
+static UPTIME_S: AtomicU32 = AtomicU32::new(0);
+static PRESSES: AtomicU16 = AtomicU16::new(0);
+static LOOP_HZ: AtomicF32 = AtomicF32::new(0.0);
+static ALPHA: AtomicF32 = AtomicF32::new(0.1);
+static BLINK_MS: AtomicU32 = AtomicU32::new(500);
+static LED_ON: AtomicBool = AtomicBool::new(true);
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    let channels = rtt_init! {
+        up: {
+            0: {
+                size: 1024,
+                name: "Terminal"
+            }
+            1: {
+                size: 512,
+                name: "Shell"
+            }
+        }
+        // OpenOCD 的 rtt server 收发使用同一个编号的通道，因此 Shell 的 down 通道也放在 1 号
+        down: {
+            0: {
+                size: 16,
+                name: "Terminal"
+            }
+            1: {
+                size: 64,
+                name: "Shell"
+            }
+        }
+    };
+    set_print_channel(channels.up.0);
+    rprintln!("Program Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_led(&dp);
+    setup_button(&dp);
+
+    watch::watch("uptime_s", &UPTIME_S).unwrap();
+    watch::watch("presses", &PRESSES).unwrap();
+    watch::watch("loop_hz", &LOOP_HZ).unwrap();
+    watch::watch_mut("alpha", &ALPHA).unwrap();
+    watch::watch_mut("blink_ms", &BLINK_MS).unwrap();
+    watch::watch_mut("led_on", &LED_ON).unwrap();
+
+    let mut shell = Shell::new(io::Rtt::new(channels.up.1, channels.down.1));
+    shell.say(
+        "watch shell, commands: watch, watch get <name>, watch set <name> <value>, mon <name> <ms>",
+    );
+
+    let mut led = false;
+    let mut last_blink = ticker::millis();
+    let mut last_second = ticker::millis();
+    let mut loops = 0u32;
+    let mut monitor: Option<(&'static str, u32, u32)> = None;
+
+    loop {
+        let now = ticker::millis();
+        loops += 1;
+
+        // 每秒更新一次运行时间与循环频率
+        if now.wrapping_sub(last_second) >= 1000 {
+            last_second = last_second.wrapping_add(1000);
+            UPTIME_S.fetch_add(1, Ordering::Relaxed);
+            let alpha = ALPHA.load().clamp(0.0, 1.0);
+            let hz = LOOP_HZ.load();
+            LOOP_HZ.store(hz + alpha * (loops as f32 - hz));
+            loops = 0;
+        }
+
+        if !LED_ON.load(Ordering::Relaxed) {
+            led = false;
+            set_led(&dp, false);
+        } else if now.wrapping_sub(last_blink) >= BLINK_MS.load(Ordering::Relaxed) {
+            last_blink = now;
+            led = !led;
+            set_led(&dp, led);
+        }
+
+        if let Some((name, period, next)) = monitor {
+            if (now.wrapping_sub(next) as i32) >= 0 {
+                monitor = Some((name, period, now.wrapping_add(period)));
+                match watch::get(name) {
+                    Ok(value) => writeln!(shell, "[{}] {} = {}", now, name, value).ok(),
+                    Err(e) => writeln!(shell, "{}: {}", name, e).ok(),
+                };
+            }
+        }
+
+        if let Some(line) = shell.poll_line() {
+            // 任何输入都会停止 mon
+            monitor = None;
+            let mut words = line.split_ascii_whitespace();
+            match words.next() {
+                None => {}
+                Some("watch") => {
+                    let mut out = LineBuf::<512>::new();
+                    if !matches!(watch::command(words, &mut out), Ok(true)) {
+                        writeln!(out, "usage: watch [get <name> | set <name> <value>]").ok();
+                    }
+                    shell.write_text(core::str::from_utf8(out.as_bytes()).unwrap_or(""));
+                }
+                Some("mon") => match (words.next(), words.next().map(str::parse::<u32>)) {
+                    (Some(name), Some(Ok(period))) if period > 0 => {
+                        match watch::registered_name(name) {
+                            Ok(name) => monitor = Some((name, period, now)),
+                            // name 还借用着 shell 中的行缓冲，这里只打印错误
+                            Err(e) => {
+                                writeln!(shell, "{}", e).ok();
+                            }
+                        }
+                    }
+                    _ => shell.say("usage: mon <name> <ms>"),
+                },
+                Some(_) => shell.say("unknown command"),
+            }
+        }
+    }
+}
+
+// EXTI 的回调，在中断中执行
+fn on_button(_line: u8) {
+    PRESSES.fetch_add(1, Ordering::Relaxed);
+}
+
+// 非阻塞的命令行：每次 poll_line 只读取已经到达的字节，读到回车时返回一整行
+struct Shell<T> {
+    io: T,
+    line: [u8; LINE_LEN],
+    len: usize,
+    done: bool,
+}
+
+impl<T: Read + ReadReady + IoWrite> Shell<T> {
+    fn new(io: T) -> Self {
+        Self {
+            io,
+            line: [0; LINE_LEN],
+            len: 0,
+            done: false,
+        }
+    }
+
+    fn write_text(&mut self, text: &str) {
+        // 终端需要 \r\n
+        for (index, part) in text.split('\n').enumerate() {
+            if index > 0 {
+                self.io.write_all(b"\r\n").ok();
+            }
+            self.io.write_all(part.as_bytes()).ok();
+        }
+    }
+
+    fn say(&mut self, message: &str) {
+        self.write_text(message);
+        self.write_text("\n");
+    }
+
+    fn poll_line(&mut self) -> Option<&str> {
+        if self.done {
+            self.done = false;
+            self.len = 0;
+        }
+        while matches!(self.io.read_ready(), Ok(true)) {
+            let mut byte = [0u8];
+            if self.io.read(&mut byte).is_err() {
+                break;
+            }
+            match byte[0] {
+                b'\r' | b'\n' => {
+                    self.io.write_all(b"\r\n").ok();
+                    self.done = true;
+                    return Some(core::str::from_utf8(&self.line[..self.len]).unwrap_or(""));
+                }
+                // 放不下的字符直接丢弃
+                c if self.len < LINE_LEN => {
+                    self.io.write_all(&byte).ok();
+                    self.line[self.len] = c;
+                    self.len += 1;
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+impl<T: Read + ReadReady + IoWrite> Write for Shell<T> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_text(s);
+        Ok(())
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+fn setup_led(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.GPIOC.moder.modify(|_, w| w.moder13().output());
+}
+
+fn set_led(dp: &pac::Peripherals, on: bool) {
+    dp.GPIOC
+        .bsrr
+        .write(|w| if on { w.bs13().set() } else { w.br13().reset() });
+}
+
+// PB5 上拉输入，按下时为下降沿
+fn setup_button(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.GPIOB.pupdr.modify(|_, w| w.pupdr5().pull_up());
+    dp.GPIOB.moder.modify(|_, w| w.moder5().input());
+
+    exti::register(dp, Port::B, BUTTON_PIN, Trigger::Falling, on_button).unwrap();
+}
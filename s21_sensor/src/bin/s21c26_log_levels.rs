@@ -0,0 +1,251 @@
+//! 按模块过滤的日志，级别在命令行中调整，并保存在设置中
+//!
+//! 日志的实现见 utils::log，这里有三个来源：
+//!
+//! - timer：TIM4 每 100 ms 一次的更新中断，每次写一条 trace，每秒写一条 debug
+//! - exti：PB5 上按钮的 EXTI 回调，每次按下写一条 info
+//! - main：主循环每秒写一条 info，统计这一秒主循环运行的次数
+//!
+//! 默认所有模块都是 info，因此开机后只能看到按钮与主循环的日志
+//! 中断中的 trace 与 debug 被过滤时，只多了一次原子读取，主循环的次数几乎不受影响，
+//! 打开之后（log set timer trace）可以对比一下每秒的次数
+//!
+//! 日志输出到 RTT 的 0 号通道，命令行在 1 号通道上（连接方法见 s21c25）：
+//!
+//! log                          列出各模块的级别
+//! log set <module|all> <level> 修改级别，比如 log set timer debug
+//! log save                     保存当前的级别，复位之后依然有效
+//!
+//! 接线图：
+//!
+//! PB5  -> 按钮 -> GND（使用内部上拉）
+
+#![no_std]
+#![no_main]
+
+use core::fmt::{self, Write};
+
+use embedded_io::{Read, ReadReady, Write as IoWrite};
+use panic_rtt_target as _;
+use rtt_target::{rprint, rprintln, rtt_init, set_print_channel};
+use stm32f4xx_hal::pac::{self, interrupt, NVIC};
+
+mod utils;
+
+use utils::{
+    exti::{self, Port, Trigger},
+    internal_flash::InternalFlash,
+    io,
+    log::{self, log, Level, Module},
+    sensor::sink::LineBuf,
+    settings::SettingsStore,
+    ticker,
+};
+
+const BUTTON_PIN: u8 = 5;
+const LINE_LEN: usize = 64;
+// 每一轮主循环最多输出的日志条数，避免日志很多时命令行没有响应
+const DRAIN_PER_LOOP: usize = 4;
+
+// 设置存放在内部 Flash 的扇区 10 与 11，与 s21c23 相同
+const SETTINGS_START: u32 = 0x0C_0000;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    let channels = rtt_init! {
+        up: {
+            0: {
+                size: 1024,
+                name: "Terminal"
+            }
+            1: {
+                size: 512,
+                name: "Shell"
+            }
+        }
+        down: {
+            0: {
+                size: 16,
+                name: "Terminal"
+            }
+            1: {
+                size: 64,
+                name: "Shell"
+            }
+        }
+    };
+    set_print_channel(channels.up.0);
+    rprintln!("Program Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+
+    let mut store = SettingsStore::open(InternalFlash::new(&dp.FLASH), SETTINGS_START).unwrap();
+    log::load_levels(&store);
+    log::write_levels(&mut Rtt).ok();
+
+    setup_button(&dp);
+    setup_heartbeat(&dp);
+
+    let mut shell = Shell::new(io::Rtt::new(channels.up.1, channels.down.1));
+    shell.say("log shell, commands: log, log set <module|all> <level>, log save");
+
+    let mut last_second = ticker::millis();
+    let mut loops = 0u32;
+
+    loop {
+        let now = ticker::millis();
+        loops += 1;
+
+        if now.wrapping_sub(last_second) >= 1000 {
+            last_second = last_second.wrapping_add(1000);
+            log!(Module::Main, Level::Info, "{} loops/s", loops);
+            loops = 0;
+        }
+
+        log::drain(&mut Rtt, DRAIN_PER_LOOP).ok();
+
+        if let Some(line) = shell.poll_line() {
+            log!(Module::Shell, Level::Debug, "command: {}", line);
+            let mut words = line.split_ascii_whitespace();
+            match words.next() {
+                None => {}
+                Some("log") => {
+                    let mut out = LineBuf::<512>::new();
+                    if !matches!(log::command(words, &mut store, &mut out), Ok(true)) {
+                        writeln!(out, "usage: log [set <module|all> <level> | save]").ok();
+                    }
+                    shell.write_text(core::str::from_utf8(out.as_bytes()).unwrap_or(""));
+                }
+                Some(_) => shell.say("unknown command"),
+            }
+        }
+    }
+}
+
+// EXTI 的回调，在中断中执行
+fn on_button(_line: u8) {
+    log!(Module::Exti, Level::Info, "button pressed");
+}
+
+#[interrupt]
+fn TIM4() {
+    static mut COUNT: u32 = 0;
+
+    let tim4 = unsafe { &*pac::TIM4::ptr() };
+    tim4.sr.modify(|_, w| w.uif().clear_bit());
+
+    *COUNT += 1;
+    log!(Module::Timer, Level::Trace, "tick {}", *COUNT);
+    if (*COUNT).is_multiple_of(10) {
+        log!(Module::Timer, Level::Debug, "{} s", *COUNT / 10);
+    }
+}
+
+// 让 utils::log 直接输出到 RTT
+struct Rtt;
+
+impl Write for Rtt {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        rprint!("{}", s);
+        Ok(())
+    }
+}
+
+// 非阻塞的命令行，与 s21c25 相同
+struct Shell<T> {
+    io: T,
+    line: [u8; LINE_LEN],
+    len: usize,
+    done: bool,
+}
+
+impl<T: Read + ReadReady + IoWrite> Shell<T> {
+    fn new(io: T) -> Self {
+        Self {
+            io,
+            line: [0; LINE_LEN],
+            len: 0,
+            done: false,
+        }
+    }
+
+    fn write_text(&mut self, text: &str) {
+        // 终端需要 \r\n
+        for (index, part) in text.split('\n').enumerate() {
+            if index > 0 {
+                self.io.write_all(b"\r\n").ok();
+            }
+            self.io.write_all(part.as_bytes()).ok();
+        }
+    }
+
+    fn say(&mut self, message: &str) {
+        self.write_text(message);
+        self.write_text("\n");
+    }
+
+    fn poll_line(&mut self) -> Option<&str> {
+        if self.done {
+            self.done = false;
+            self.len = 0;
+        }
+        while matches!(self.io.read_ready(), Ok(true)) {
+            let mut byte = [0u8];
+            if self.io.read(&mut byte).is_err() {
+                break;
+            }
+            match byte[0] {
+                b'\r' | b'\n' => {
+                    self.io.write_all(b"\r\n").ok();
+                    self.done = true;
+                    return Some(core::str::from_utf8(&self.line[..self.len]).unwrap_or(""));
+                }
+                // 放不下的字符直接丢弃
+                c if self.len < LINE_LEN => {
+                    self.io.write_all(&byte).ok();
+                    self.line[self.len] = c;
+                    self.len += 1;
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// PB5 上拉输入，按下时为下降沿
+fn setup_button(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+    dp.GPIOB.pupdr.modify(|_, w| w.pupdr5().pull_up());
+    dp.GPIOB.moder.modify(|_, w| w.moder5().input());
+
+    exti::register(dp, Port::B, BUTTON_PIN, Trigger::Falling, on_button).unwrap();
+}
+
+// 12 MHz / 12000 = 1 kHz，计数 100 次为 100 ms
+fn setup_heartbeat(dp: &pac::Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.tim4en().enabled());
+
+    let tim4 = &dp.TIM4;
+    tim4.psc.write(|w| w.psc().bits(12_000 - 1));
+    tim4.arr.write(|w| w.arr().bits(100 - 1));
+    // 让预分频立即生效，并清除 UG 产生的更新标志
+    tim4.egr.write(|w| w.ug().update());
+    tim4.sr.modify(|_, w| w.uif().clear_bit());
+    tim4.dier.modify(|_, w| w.uie().enabled());
+    tim4.cr1.modify(|_, w| w.cen().enabled());
+
+    unsafe { NVIC::unmask(interrupt::TIM4) };
+}
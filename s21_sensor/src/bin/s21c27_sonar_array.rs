@@ -0,0 +1,150 @@
+//! 三个超声波模块组成的保险杠
+//!
+//! 轮流测距与合成的原理见 utils::sensor::sonar_array，这里把三个模块装在机器人的左前、正前、右前：
+//!
+//! - 每个模块测量结束，把它的结果打印到 RTT
+//! - 每 200 ms 合成一次，打印最近的障碍物及其方向
+//! - 最近的障碍物在 STOP_MM 以内，或者有模块看不见时，点亮 PC13 上的 LED（代替电机的刹车信号）
+//!
+//! 正前方只关心 1 米以内，两侧只关心 0.6 米以内，量程越小，一轮越快；这里一轮大约 3 * (7 + 25) ≈ 100 ms
+//! 试着把 SONARS 中的 quiet_ms 改成 0，再把一块木板斜放在两个模块前面，可以看到偶尔会出现偏近的距离，这就是串扰
+//!
+//! 气温固定为 20 ℃，有 BME280 时可以参考 s21c02，定期调用 set_air
+//!
+//! 接线图：
+//!
+//! STM32 <-> US-100（拔掉跳线帽，使用脉冲模式）或者 HC-SR04，模块使用 5V 供电时，Echo 需要分压到 3.3V
+//!
+//!       左   前   右
+//! Trig  PA5  PA6  PA7
+//! Echo  PB10 PB12 PB13
+//!
+//! PC13 -> 1k -> LED -> GND
+
+#![no_std]
+#![no_main]
+
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use stm32f4xx_hal::pac;
+
+mod utils;
+
+use utils::{
+    exti::Port,
+    sensor::{
+        sonar_array::{Pin, SonarArray, SonarConfig},
+        ultrasonic::Air,
+        Celsius,
+    },
+    ticker,
+};
+
+static SONARS: [SonarConfig; 3] = [
+    SonarConfig {
+        name: "left",
+        trig: Pin::new(Port::A, 5),
+        echo: Pin::new(Port::B, 10),
+        bearing_deg: -45,
+        max_range_mm: 600,
+        quiet_ms: 25,
+    },
+    SonarConfig {
+        name: "front",
+        trig: Pin::new(Port::A, 6),
+        echo: Pin::new(Port::B, 12),
+        bearing_deg: 0,
+        max_range_mm: 1000,
+        quiet_ms: 25,
+    },
+    SonarConfig {
+        name: "right",
+        trig: Pin::new(Port::A, 7),
+        echo: Pin::new(Port::B, 13),
+        bearing_deg: 45,
+        max_range_mm: 600,
+        quiet_ms: 25,
+    },
+];
+
+// 障碍物在这个距离以内时刹车
+const STOP_MM: f32 = 250.0;
+// 合成的间隔
+const REPORT_MS: u32 = 200;
+// 超过大约三轮没有更新的读数视为过期
+const MAX_AGE_MS: u32 = 300;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Program Start");
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    ticker::setup(&dp);
+    setup_led(&dp);
+
+    let mut sonars = SonarArray::new(&dp, &SONARS).unwrap();
+    sonars.set_air(Air {
+        temp: Celsius(20.0),
+        humidity: None,
+    });
+
+    let mut last_report = ticker::millis();
+
+    loop {
+        if let Some((index, result)) = sonars.poll() {
+            let name = sonars.config(index).name;
+            match result {
+                Ok(distance) => rprintln!("{:<5} {:>6.0} mm", name, distance.0),
+                Err(e) => rprintln!("{:<5} {:?}", name, e),
+            }
+        }
+
+        let now = ticker::millis();
+        if now.wrapping_sub(last_report) >= REPORT_MS {
+            last_report = now;
+
+            let fused = sonars.fused(MAX_AGE_MS);
+            let stop = fused.blind > 0 || fused.nearest.is_some_and(|o| o.distance.0 < STOP_MM);
+            set_led(&dp, stop);
+
+            match fused.nearest {
+                Some(o) => rprintln!(
+                    "nearest {:.0} mm at {} deg ({}), blind {}{}",
+                    o.distance.0,
+                    o.bearing_deg,
+                    o.name,
+                    fused.blind,
+                    if stop { ", STOP" } else { "" }
+                ),
+                None => rprintln!(
+                    "clear, blind {}{}",
+                    fused.blind,
+                    if stop { ", STOP" } else { "" }
+                ),
+            }
+        }
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+fn setup_led(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.GPIOC.moder.modify(|_, w| w.moder13().output());
+}
+
+fn set_led(dp: &pac::Peripherals, on: bool) {
+    dp.GPIOC
+        .bsrr
+        .write(|w| if on { w.bs13().set() } else { w.br13().reset() });
+}
@@ -0,0 +1,398 @@
+//! 模拟量传感器的换算：把 ADC 读数换算为实际的物理量
+//!
+//! - 分压电阻：由输出电压反推输入电压，或者由分压比反推其中一个电阻的阻值
+//! - NTC 热敏电阻：B 值（beta）模型，与更精确的 Steinhart–Hart 方程，后者可以由三组阻值/温度求出系数
+//! - 查表：手册只给出一张曲线/表格的传感器（比如某些湿度、压力、气体传感器），在相邻两点之间线性插值
+//! - 百分比：比如电位器、液位，把输入电压按给定的范围换算为 0 ~ 100 %
+//!
+//! Conversion 把以上这些统一起来，AnalogInput 则是带有一个 Conversion 的 ADC1 通道，实现了 Sensor，
+//! 声明通道的时候就给定换算方式，之后 Scheduler 拿到的就是换算好的物理量
+//!
+//! 对于电阻分压（比如 NTC），只要分压电阻的电源就是 V_{DDA}，分压比就等于 raw / 4095，与 V_{DDA} 的实际电压无关，
+//! 因此换算电阻时直接使用分压比，而不是先换算为电压
+//!
+//! ADC1 需要事先配置好（序列长度为 1，通道的采样时间等，见 s21c12），这里每次采样前修改 SQ1，软件触发一次转换
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac;
+
+use super::{
+    sensor::{Measurement, Reading, Sensor, SensorError},
+    ticker,
+};
+
+// 12 位 ADC 的满量程读数
+pub(crate) const ADC_MAX: u16 = 4095;
+// 换算电压时使用的参考电压，没有测量 V_{DDA} 时按 3.3 V 计算
+pub(crate) const VREF: f32 = 3.3;
+
+const KELVIN_OFFSET: f32 = 273.15;
+
+// 分压电路的输出电压
+pub(crate) fn divider_output(vin: f32, r_top: f32, r_bottom: f32) -> f32 {
+    vin * r_bottom / (r_top + r_bottom)
+}
+
+// 由分压电路的输出电压反推输入电压，比如用 ADC 测量超过 3.3 V 的电压
+pub(crate) fn divider_input(vout: f32, r_top: f32, r_bottom: f32) -> f32 {
+    vout * (r_top + r_bottom) / r_bottom
+}
+
+// 已知上方的电阻与分压比（输出/输入），求下方的电阻，分压比为 1 时下方开路，返回 None
+pub(crate) fn bottom_resistance(ratio: f32, r_top: f32) -> Option<f32> {
+    if !(0.0..1.0).contains(&ratio) {
+        return None;
+    }
+    Some(r_top * ratio / (1.0 - ratio))
+}
+
+// 已知下方的电阻与分压比（输出/输入），求上方的电阻，分压比为 0 时上方开路，返回 None
+pub(crate) fn top_resistance(ratio: f32, r_bottom: f32) -> Option<f32> {
+    if ratio <= 0.0 || ratio > 1.0 {
+        return None;
+    }
+    Some(r_bottom * (1.0 - ratio) / ratio)
+}
+
+// 把 value 按 min ~ max 换算为 0 ~ 100 %，超出范围的部分截断，min 可以大于 max（反向）
+pub(crate) fn percent_of_range(value: f32, min: f32, max: f32) -> f32 {
+    if min == max {
+        return 0.0;
+    }
+    ((value - min) / (max - min) * 100.0).clamp(0.0, 100.0)
+}
+
+// NTC 的 B 值模型：1/T = 1/T0 + ln(R/R0)/B，温度单位为 K
+// 手册一般会给出 25 °C 时的阻值（R25）与 B25/50 或 B25/85，在这两个温度之间误差很小，离得越远误差越大
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Beta {
+    // T0 时的阻值，单位 Ω
+    pub(crate) r0: f32,
+    // 单位 °C，一般为 25
+    pub(crate) t0: f32,
+    pub(crate) beta: f32,
+}
+
+impl Beta {
+    // 最常见的 10k NTC，B25/50 = 3950
+    pub(crate) const NTC_10K_3950: Beta = Beta {
+        r0: 10_000.0,
+        t0: 25.0,
+        beta: 3950.0,
+    };
+
+    pub(crate) fn celsius(&self, resistance: f32) -> f32 {
+        let inv_t = 1.0 / (self.t0 + KELVIN_OFFSET) + ln(resistance / self.r0) / self.beta;
+        1.0 / inv_t - KELVIN_OFFSET
+    }
+}
+
+// Steinhart–Hart 方程：1/T = A + B*ln(R) + C*ln(R)^3，温度单位为 K
+// 在 -40 ~ 150 °C 的范围内误差一般小于 0.02 °C，系数可以由 from_points 根据三组实测的阻值与温度求出
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct SteinhartHart {
+    pub(crate) a: f32,
+    pub(crate) b: f32,
+    pub(crate) c: f32,
+}
+
+impl SteinhartHart {
+    // points 为三组 (阻值 Ω, 温度 °C)，温度最好覆盖实际使用的范围，且互不相同，否则返回 None
+    pub(crate) fn from_points(points: [(f32, f32); 3]) -> Option<Self> {
+        let [(r1, t1), (r2, t2), (r3, t3)] = points;
+        let (l1, l2, l3) = (ln(r1), ln(r2), ln(r3));
+        let (y1, y2, y3) = (
+            1.0 / (t1 + KELVIN_OFFSET),
+            1.0 / (t2 + KELVIN_OFFSET),
+            1.0 / (t3 + KELVIN_OFFSET),
+        );
+
+        if l1 == l2 || l2 == l3 || l1 == l3 {
+            return None;
+        }
+        let g2 = (y2 - y1) / (l2 - l1);
+        let g3 = (y3 - y1) / (l3 - l1);
+        let c = (g3 - g2) / (l3 - l2) / (l1 + l2 + l3);
+        let b = g2 - c * (l1 * l1 + l1 * l2 + l2 * l2);
+        let a = y1 - (b + c * l1 * l1) * l1;
+
+        if !(a.is_finite() && b.is_finite() && c.is_finite()) {
+            return None;
+        }
+        Some(Self { a, b, c })
+    }
+
+    pub(crate) fn celsius(&self, resistance: f32) -> f32 {
+        let l = ln(resistance);
+        1.0 / (self.a + self.b * l + self.c * l * l * l) - KELVIN_OFFSET
+    }
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum NtcModel {
+    Beta(Beta),
+    SteinhartHart(SteinhartHart),
+}
+
+impl NtcModel {
+    pub(crate) fn celsius(&self, resistance: f32) -> f32 {
+        match self {
+            NtcModel::Beta(beta) => beta.celsius(resistance),
+            NtcModel::SteinhartHart(sh) => sh.celsius(resistance),
+        }
+    }
+}
+
+// NTC 在分压电路中的位置，另一侧为固定电阻，整个分压电路接在 V_{DDA} 与 GND 之间
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Placement {
+    // NTC 接 GND，固定电阻接 V_{DDA}，温度越高读数越小
+    Low,
+    // NTC 接 V_{DDA}，固定电阻接 GND，温度越高读数越大
+    High,
+}
+
+// 查表换算，points 为 (输入, 输出)，输入必须是单调的（递增或者递减都可以），
+// 相邻两点之间线性插值，超出表格范围时取两端的值
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Table<'a> {
+    points: &'a [(f32, f32)],
+}
+
+impl<'a> Table<'a> {
+    // 表格至少需要两个点
+    pub(crate) const fn new(points: &'a [(f32, f32)]) -> Self {
+        assert!(points.len() >= 2);
+        Self { points }
+    }
+
+    pub(crate) fn lookup(&self, x: f32) -> f32 {
+        let points = self.points;
+        let (first, last) = (points[0], points[points.len() - 1]);
+        let ascending = first.0 <= last.0;
+
+        // 在表格两端之外
+        let below = if ascending {
+            x <= first.0
+        } else {
+            x >= first.0
+        };
+        if below {
+            return first.1;
+        }
+        let above = if ascending { x >= last.0 } else { x <= last.0 };
+        if above {
+            return last.1;
+        }
+
+        for pair in points.windows(2) {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            let inside = if ascending {
+                x0 <= x && x <= x1
+            } else {
+                x1 <= x && x <= x0
+            };
+            if inside {
+                if x0 == x1 {
+                    return y0;
+                }
+                return y0 + (y1 - y0) * (x - x0) / (x1 - x0);
+            }
+        }
+
+        // 表格不单调时才会走到这里
+        last.1
+    }
+}
+
+// 一个通道的换算方式，输入为 ADC 的原始读数
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) enum Conversion<'a> {
+    // 原始读数 0 ~ 4095
+    Raw,
+    // 按 VREF 换算的电压
+    Volt,
+    // 经过分压之后的电压，换算为分压之前的电压
+    Divider {
+        r_top: f32,
+        r_bottom: f32,
+    },
+    // NTC 热敏电阻与 fixed 欧姆的固定电阻分压
+    Ntc {
+        model: NtcModel,
+        fixed: f32,
+        placement: Placement,
+    },
+    // 电压查表，quantity 与 unit 为查表之后的物理量
+    Table {
+        table: Table<'a>,
+        quantity: &'static str,
+        unit: &'static str,
+    },
+    // 电压按 min ~ max 换算为百分比，quantity 比如 "level"、"pos"
+    Percent {
+        min: f32,
+        max: f32,
+        quantity: &'static str,
+    },
+}
+
+impl Conversion<'_> {
+    // 换算之后的物理量名称与单位
+    pub(crate) fn quantity(&self) -> (&'static str, &'static str) {
+        match self {
+            Conversion::Raw => ("raw", ""),
+            Conversion::Volt | Conversion::Divider { .. } => ("volt", "V"),
+            Conversion::Ntc { .. } => ("temp", "C"),
+            Conversion::Table { quantity, unit, .. } => (*quantity, *unit),
+            Conversion::Percent { quantity, .. } => (*quantity, "%"),
+        }
+    }
+
+    // 换算一个原始读数，NTC 开路或者短路（读数在两端）时返回 OutOfRange
+    pub(crate) fn apply(&self, raw: u16) -> Result<f32, SensorError> {
+        let ratio = raw.min(ADC_MAX) as f32 / ADC_MAX as f32;
+        let volt = ratio * VREF;
+
+        match self {
+            Conversion::Raw => Ok(raw as f32),
+            Conversion::Volt => Ok(volt),
+            Conversion::Divider { r_top, r_bottom } => Ok(divider_input(volt, *r_top, *r_bottom)),
+            Conversion::Ntc {
+                model,
+                fixed,
+                placement,
+            } => {
+                if raw == 0 || raw >= ADC_MAX {
+                    return Err(SensorError::OutOfRange);
+                }
+                let resistance = match placement {
+                    Placement::Low => bottom_resistance(ratio, *fixed),
+                    Placement::High => top_resistance(ratio, *fixed),
+                }
+                .ok_or(SensorError::OutOfRange)?;
+                Ok(model.celsius(resistance))
+            }
+            Conversion::Table { table, .. } => Ok(table.lookup(volt)),
+            Conversion::Percent { min, max, .. } => Ok(percent_of_range(volt, *min, *max)),
+        }
+    }
+}
+
+// 换算之后的一个读数
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct AnalogValue {
+    pub(crate) quantity: &'static str,
+    pub(crate) unit: &'static str,
+    pub(crate) value: f32,
+}
+
+impl Measurement for AnalogValue {
+    fn for_each_reading(&self, f: &mut dyn FnMut(Reading)) {
+        f(Reading {
+            quantity: self.quantity,
+            value: self.value,
+            unit: self.unit,
+        })
+    }
+}
+
+// ADC1 的一个通道，以及它的换算方式
+pub(crate) struct AnalogInput<'a> {
+    adc: &'a pac::ADC1,
+    channel: u8,
+    name: &'static str,
+    conversion: Conversion<'a>,
+}
+
+impl<'a> AnalogInput<'a> {
+    pub(crate) fn new(
+        adc: &'a pac::ADC1,
+        channel: u8,
+        name: &'static str,
+        conversion: Conversion<'a>,
+    ) -> Self {
+        Self {
+            adc,
+            channel,
+            name,
+            conversion,
+        }
+    }
+
+    pub(crate) fn conversion(&self) -> &Conversion<'a> {
+        &self.conversion
+    }
+
+    // 软件触发一次转换，返回原始读数
+    pub(crate) fn raw(&self) -> Result<u16, SensorError> {
+        let adc = self.adc;
+        adc.sqr3
+            .modify(|_, w| unsafe { w.sq1().bits(self.channel) });
+        adc.cr2.modify(|_, w| w.swstart().start());
+
+        let start = ticker::millis();
+        while adc.sr.read().eoc().bit_is_clear() {
+            if ticker::millis().wrapping_sub(start) > 2 {
+                return Err(SensorError::Timeout);
+            }
+        }
+
+        Ok(adc.dr.read().data().bits())
+    }
+}
+
+impl Sensor for AnalogInput<'_> {
+    type Output = AnalogValue;
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn sample(&mut self) -> Result<AnalogValue, SensorError> {
+        let value = self.conversion.apply(self.raw()?)?;
+        let (quantity, unit) = self.conversion.quantity();
+        Ok(AnalogValue {
+            quantity,
+            unit,
+            value,
+        })
+    }
+}
+
+// no_std 下没有 f32::ln，这里自己实现一个自然对数：把 x 拆成 m * 2^e（m 在 [1, 2) 之间），
+// ln(x) = e * ln(2) + ln(m)，ln(m) 用 atanh 级数 2 * (s + s^3/3 + s^5/5 + ...) 计算，其中 s = (m - 1)/(m + 1)
+// m 在 [1, 2) 之间时 s 不超过 1/3，取到 s^11 误差已经小于 f32 的精度
+pub(crate) fn ln(x: f32) -> f32 {
+    if x <= 0.0 {
+        return f32::NAN;
+    }
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127;
+    let m = f32::from_bits((bits & 0x007F_FFFF) | 0x3F80_0000);
+
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    let mut term = s;
+    let mut sum = 0.0;
+    let mut k = 1.0;
+    for _ in 0..6 {
+        sum += term / k;
+        term *= s2;
+        k += 2.0;
+    }
+
+    exponent as f32 * core::f32::consts::LN_2 + 2.0 * sum
+}
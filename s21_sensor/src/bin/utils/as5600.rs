@@ -0,0 +1,380 @@
+//! AS5600 12 位磁编码器（I2C）
+//!
+//! 芯片正上方放一块径向充磁的圆形磁铁，芯片测量磁场的方向，得到磁铁转过的绝对角度，0 ~ 4095 对应 0° ~ 360°
+//! 与 utils::encoder 中的增量式编码器不同，它上电就知道当前的角度，不需要回零
+//!
+//! 两个角度寄存器：
+//! - RAW ANGLE：未经任何处理的角度
+//! - ANGLE：减去零点（ZPOS）之后的角度，并带有 1 LSB 的迟滞，输出更稳定，一般使用这个
+//!
+//! 两者都经过芯片内部的数字滤波，滤波的强度由 CONF 中的 SF（slow filter）与 FTH（fast filter threshold）决定，见 set_filter
+//!
+//! 零点：
+//! - set_zero / set_zero_here 写入的 ZPOS 掉电后丢失，每次上电都要重新写
+//! - burn_angle 把 ZPOS（与 MPOS）永久烧写进芯片的 OTP，最多只能烧写 3 次，烧写时电源电压必须稳定，
+//!   这里只在磁铁状态正常、且还有剩余次数时才会执行，调用之前请确认零点确实是你想要的
+//!
+//! 磁铁的状态（见 diagnostics）：
+//! - MD：检测到磁铁；ML/MH：磁场太弱/太强，需要调整磁铁与芯片之间的距离
+//! - AGC：自动增益，磁场越弱，AGC 越大，理想情况下应该在量程的中间附近
+//! - MAGNITUDE：CORDIC 算出的磁场强度
+//!
+//! VelocityEstimator 根据相邻两次的角度与时间差估算角速度，并累计转过的圈数，得到连续的位置，
+//! 可以作为电机控制的反馈；As5600Knob 把 AS5600 当作旋钮，实现了 utils::encoder::Knob，可以替代机械编码器
+//!
+//! I2C 外设需要事先配置好（见 s21c02），这里只通过 utils::blocking_master 收发数据
+//! 地址固定为 0x36
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::i2c1::RegisterBlock;
+
+use super::{
+    addressing::I2cAddress,
+    blocking_master,
+    encoder::Knob,
+    sensor::{Degree, DegreePerSecond, Measurement, Reading, Sensor, SensorError},
+    ticker,
+};
+
+pub(crate) const ADDR: u8 = 0x36;
+
+// 一圈的计数
+pub(crate) const COUNTS_PER_TURN: u16 = 4096;
+
+const REG_ZMCO: u8 = 0x00;
+const REG_ZPOS: u8 = 0x01;
+const REG_CONF: u8 = 0x07;
+const REG_STATUS: u8 = 0x0B;
+const REG_RAW_ANGLE: u8 = 0x0C;
+const REG_ANGLE: u8 = 0x0E;
+const REG_AGC: u8 = 0x1A;
+const REG_MAGNITUDE: u8 = 0x1B;
+const REG_BURN: u8 = 0xFF;
+
+const STATUS_MH: u8 = 1 << 3;
+const STATUS_ML: u8 = 1 << 4;
+const STATUS_MD: u8 = 1 << 5;
+
+const BURN_ANGLE: u8 = 0x80;
+// ZMCO 为已经烧写零点的次数
+const BURN_MAX: u8 = 3;
+
+// CONF 中 SF 与 FTH 所在的位
+const CONF_SF_SHIFT: u16 = 8;
+const CONF_FTH_SHIFT: u16 = 10;
+const CONF_FILTER_MASK: u16 = (0b11 << CONF_SF_SHIFT) | (0b111 << CONF_FTH_SHIFT);
+
+// slow filter，越慢噪声越小，但响应越迟钝
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum SlowFilter {
+    X16 = 0b00,
+    X8 = 0b01,
+    X4 = 0b10,
+    X2 = 0b11,
+}
+
+// fast filter threshold，角度的变化超过这个阈值时，临时切换到快速滤波，兼顾静止时的稳定与转动时的响应
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum FastThreshold {
+    // 只使用 slow filter
+    SlowOnly = 0b000,
+    Lsb6 = 0b001,
+    Lsb7 = 0b010,
+    Lsb9 = 0b011,
+    Lsb18 = 0b100,
+    Lsb21 = 0b101,
+    Lsb24 = 0b110,
+    Lsb10 = 0b111,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct MagnetStatus {
+    pub(crate) detected: bool,
+    pub(crate) too_weak: bool,
+    pub(crate) too_strong: bool,
+    pub(crate) agc: u8,
+    pub(crate) magnitude: u16,
+}
+
+impl MagnetStatus {
+    pub(crate) fn is_ok(&self) -> bool {
+        self.detected && !self.too_weak && !self.too_strong
+    }
+}
+
+// 一次采样的结果
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct As5600Measurement {
+    pub(crate) angle: Degree,
+    pub(crate) speed: DegreePerSecond,
+}
+
+impl Measurement for As5600Measurement {
+    fn for_each_reading(&self, f: &mut dyn FnMut(Reading)) {
+        self.angle.for_each_reading(f);
+        self.speed.for_each_reading(f);
+    }
+}
+
+pub(crate) fn counts_to_degree(counts: u16) -> f32 {
+    counts as f32 * 360.0 / COUNTS_PER_TURN as f32
+}
+
+pub(crate) struct As5600<'a> {
+    i2c: &'a RegisterBlock,
+    addr: I2cAddress,
+    velocity: VelocityEstimator,
+}
+
+impl<'a> As5600<'a> {
+    // 检查磁铁是否就位，不在时依旧返回 Ok，只是采样会失败，可以之后再用 diagnostics 检查
+    pub(crate) fn new(i2c: &'a RegisterBlock) -> Result<Self, SensorError> {
+        let dev = Self {
+            i2c,
+            addr: I2cAddress::SevenBit(ADDR),
+            velocity: VelocityEstimator::new(0.3),
+        };
+        // 顺便确认芯片在总线上
+        dev.read_u8(REG_STATUS)?;
+        Ok(dev)
+    }
+
+    // 0 ~ 4095
+    pub(crate) fn raw_angle(&self) -> Result<u16, SensorError> {
+        self.read_u12(REG_RAW_ANGLE)
+    }
+
+    // 0 ~ 4095，相对于 ZPOS，带迟滞
+    pub(crate) fn angle(&self) -> Result<u16, SensorError> {
+        self.read_u12(REG_ANGLE)
+    }
+
+    pub(crate) fn diagnostics(&self) -> Result<MagnetStatus, SensorError> {
+        let status = self.read_u8(REG_STATUS)?;
+        Ok(MagnetStatus {
+            detected: status & STATUS_MD != 0,
+            too_weak: status & STATUS_ML != 0,
+            too_strong: status & STATUS_MH != 0,
+            agc: self.read_u8(REG_AGC)?,
+            magnitude: self.read_u12(REG_MAGNITUDE)?,
+        })
+    }
+
+    pub(crate) fn set_filter(
+        &self,
+        slow: SlowFilter,
+        fast: FastThreshold,
+    ) -> Result<(), SensorError> {
+        let conf = self.read_u16(REG_CONF)?;
+        let conf = (conf & !CONF_FILTER_MASK)
+            | ((slow as u16) << CONF_SF_SHIFT)
+            | ((fast as u16) << CONF_FTH_SHIFT);
+        self.write_u16(REG_CONF, conf)
+    }
+
+    // 以 RAW ANGLE 为 raw_zero 的位置作为 0°
+    pub(crate) fn set_zero(&mut self, raw_zero: u16) -> Result<(), SensorError> {
+        self.write_u16(REG_ZPOS, raw_zero & (COUNTS_PER_TURN - 1))?;
+        // 零点变了，角度会跳变，不能拿来计算速度
+        self.velocity.reset();
+        Ok(())
+    }
+
+    // 以当前位置作为 0°
+    pub(crate) fn set_zero_here(&mut self) -> Result<(), SensorError> {
+        let raw = self.raw_angle()?;
+        self.set_zero(raw)
+    }
+
+    // 还可以烧写零点的次数
+    pub(crate) fn burns_left(&self) -> Result<u8, SensorError> {
+        let zmco = self.read_u8(REG_ZMCO)? & 0b11;
+        Ok(BURN_MAX.saturating_sub(zmco))
+    }
+
+    // 把当前的 ZPOS 永久烧写进 OTP，见文件开头的说明
+    pub(crate) fn burn_angle(&self) -> Result<(), SensorError> {
+        if !self.diagnostics()?.is_ok() {
+            return Err(SensorError::OutOfRange);
+        }
+        if self.burns_left()? == 0 {
+            return Err(SensorError::Unsupported);
+        }
+        self.write_u8(REG_BURN, BURN_ANGLE)?;
+        // 烧写完成之前芯片不响应 I2C，datasheet 要求至少等待 1 ms
+        ticker::delay_ms(2);
+        Ok(())
+    }
+
+    pub(crate) fn velocity(&self) -> &VelocityEstimator {
+        &self.velocity
+    }
+
+    fn read_u8(&self, reg: u8) -> Result<u8, SensorError> {
+        let mut buf = [0u8];
+        blocking_master::write_read(self.i2c, self.addr, &[reg], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    // 16 位的寄存器都是高字节在前
+    fn read_u16(&self, reg: u8) -> Result<u16, SensorError> {
+        let mut buf = [0u8; 2];
+        blocking_master::write_read(self.i2c, self.addr, &[reg], &mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    fn read_u12(&self, reg: u8) -> Result<u16, SensorError> {
+        Ok(self.read_u16(reg)? & (COUNTS_PER_TURN - 1))
+    }
+
+    fn write_u8(&self, reg: u8, value: u8) -> Result<(), SensorError> {
+        blocking_master::write(self.i2c, self.addr, &[reg, value])?;
+        Ok(())
+    }
+
+    fn write_u16(&self, reg: u8, value: u16) -> Result<(), SensorError> {
+        let [high, low] = value.to_be_bytes();
+        blocking_master::write(self.i2c, self.addr, &[reg, high, low])?;
+        Ok(())
+    }
+}
+
+impl Sensor for As5600<'_> {
+    type Output = As5600Measurement;
+
+    fn name(&self) -> &'static str {
+        "angle"
+    }
+
+    fn sample(&mut self) -> Result<As5600Measurement, SensorError> {
+        // 没有磁铁时 ANGLE 依旧有输出，只不过是噪声
+        if self.read_u8(REG_STATUS)? & STATUS_MD == 0 {
+            return Err(SensorError::NotReady);
+        }
+
+        let angle = self.angle()?;
+        let speed = self.velocity.update(angle, ticker::micros());
+
+        Ok(As5600Measurement {
+            angle: Degree(counts_to_degree(angle)),
+            speed: DegreePerSecond(speed),
+        })
+    }
+}
+
+// 角速度与连续位置的估算
+//
+// 相邻两次采样之间，角度的变化取 -2048 ~ 2047 中的那一个，因此两次采样之间转过的角度必须小于半圈，
+// 比如每 10 ms 采样一次时，转速不能超过 50 r/s（3000 rpm）
+// 原始的差分噪声很大，这里再做一次一阶低通（指数平滑），alpha 越小越平滑，但也越滞后
+pub(crate) struct VelocityEstimator {
+    alpha: f32,
+    last: Option<(u16, u64)>,
+    // 自第一次采样以来累计转过的计数，可以超过一圈
+    position: i64,
+    speed: f32,
+}
+
+impl VelocityEstimator {
+    pub(crate) const fn new(alpha: f32) -> Self {
+        Self {
+            alpha,
+            last: None,
+            position: 0,
+            speed: 0.0,
+        }
+    }
+
+    // 下一次 update 只记录角度，不计算速度
+    pub(crate) fn reset(&mut self) {
+        self.last = None;
+        self.speed = 0.0;
+    }
+
+    // 输入 0 ~ 4095 的角度与微秒时间戳，返回平滑之后的角速度（°/s），顺时针为正
+    pub(crate) fn update(&mut self, counts: u16, now_us: u64) -> f32 {
+        if let Some((last_counts, last_us)) = self.last {
+            let half = (COUNTS_PER_TURN / 2) as i32;
+            let diff = (counts as i32 - last_counts as i32 + half)
+                .rem_euclid(COUNTS_PER_TURN as i32)
+                - half;
+            self.position += diff as i64;
+
+            let dt_us = now_us.saturating_sub(last_us);
+            if dt_us > 0 {
+                let speed = diff as f32 * 360.0 / COUNTS_PER_TURN as f32 * 1e6 / dt_us as f32;
+                self.speed += self.alpha * (speed - self.speed);
+            }
+        }
+        self.last = Some((counts, now_us));
+        self.speed
+    }
+
+    pub(crate) fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    // 连续的位置（°），转过一圈之后继续增加，而不是回到 0
+    pub(crate) fn position(&self) -> f32 {
+        self.position as f32 * 360.0 / COUNTS_PER_TURN as f32
+    }
+
+    // 转过的整圈数，逆时针为负
+    pub(crate) fn turns(&self) -> i32 {
+        self.position.div_euclid(COUNTS_PER_TURN as i64) as i32
+    }
+}
+
+// 把 AS5600 当作旋钮，每转过 360° / steps_per_turn 算一格
+pub(crate) struct As5600Knob<'a> {
+    dev: As5600<'a>,
+    counts_per_step: u16,
+    // 上一次折算为整格时的角度
+    last: Option<u16>,
+}
+
+impl<'a> As5600Knob<'a> {
+    // EC11 一圈是 20 格，这里的 steps_per_turn 可以任意设置，不过至少要是 2
+    pub(crate) fn new(dev: As5600<'a>, steps_per_turn: u16) -> Self {
+        Self {
+            dev,
+            counts_per_step: COUNTS_PER_TURN / steps_per_turn.clamp(2, COUNTS_PER_TURN),
+            last: None,
+        }
+    }
+
+    pub(crate) fn release(self) -> As5600<'a> {
+        self.dev
+    }
+}
+
+impl Knob for As5600Knob<'_> {
+    // 读取失败时当作没有转动
+    fn poll(&mut self) -> i16 {
+        let Ok(counts) = self.dev.angle() else {
+            return 0;
+        };
+        let Some(last) = self.last else {
+            self.last = Some(counts);
+            return 0;
+        };
+
+        let half = (COUNTS_PER_TURN / 2) as i16;
+        let diff = (counts as i16 - last as i16 + half).rem_euclid(COUNTS_PER_TURN as i16) - half;
+        let steps = diff / self.counts_per_step as i16;
+        // 不足一格的部分留到下一次
+        self.last = Some(
+            (last as i16 + steps * self.counts_per_step as i16).rem_euclid(COUNTS_PER_TURN as i16)
+                as u16,
+        );
+        steps
+    }
+}
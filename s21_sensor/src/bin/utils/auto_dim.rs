@@ -0,0 +1,204 @@
+//! 根据环境光自动调节显示的亮度
+//!
+//! AutoDim 是一个 Sink，注册到 Scheduler 之后，任何给出 "light" 读数的传感器（BH1750、TSL2561、光敏电阻……）都可以驱动它，
+//! 它只负责算出一个 0 ~ 1 的亮度 level，再由各个 Dimmer 把 level 换算成自己的输出：
+//!
+//! - Tim12Backlight：LCD1602 的背光（A 脚）经过一个三极管接到 TIM12_CH1 的 PWM
+//! - FrameBuffer：ws2812 的全局亮度
+//!
+//! 换算：
+//! - 人眼对亮度的感受接近对数，所以 level 与 log(lux) 成线性：dark_lux 以下为 min_level，bright_lux 以上为 max_level
+//! - level 是“看起来的亮度”，PWM 占空比与之大致是平方关系（见 perceived_to_duty），由 Dimmer 自己换算
+//! - 每次 flush 时，level 以 time_constant_ms 为时间常数追赶目标值，灯被遮挡一下、或者有人走过时不会闪
+//! - level 的变化小于 MIN_STEP 时不认为“有变化”，避免在阈值附近反复刷新 ws2812
+//!
+//! 光照传感器连续出错时，level 保持不变
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac;
+
+use super::{
+    sensor::{sink::Sink, Reading},
+    ws2812::FrameBuffer,
+};
+
+const MIN_STEP: f32 = 0.01;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct DimConfig {
+    pub(crate) dark_lux: f32,
+    pub(crate) bright_lux: f32,
+    pub(crate) min_level: f32,
+    pub(crate) max_level: f32,
+    pub(crate) time_constant_ms: u32,
+}
+
+impl DimConfig {
+    // 室内的常见范围：夜间开着小灯约 5 lx，办公室约 500 lx
+    pub(crate) const INDOOR: Self = Self {
+        dark_lux: 5.0,
+        bright_lux: 500.0,
+        min_level: 0.1,
+        max_level: 1.0,
+        time_constant_ms: 2000,
+    };
+}
+
+pub(crate) trait Dimmer {
+    // level 为 0 ~ 1 的感知亮度
+    fn set_level(&mut self, level: f32);
+}
+
+// 感知亮度到 PWM 占空比，近似 CIE 1931 的亮度曲线
+pub(crate) fn perceived_to_duty(level: f32) -> f32 {
+    let level = level.clamp(0.0, 1.0);
+    level * level
+}
+
+// core 中没有 log2，这里利用浮点数的格式：指数部分就是整数部分，尾数 m 在 [1, 2) 之间用二次多项式近似，误差小于 0.005
+fn log2(x: f32) -> f32 {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127;
+    let m = f32::from_bits((bits & 0x007F_FFFF) | 0x3F80_0000);
+    exponent as f32 + (-0.344_848_43 * m + 2.024_665_8) * m - 0.674_877_6
+}
+
+pub(crate) struct AutoDim {
+    config: DimConfig,
+    target: f32,
+    level: f32,
+    // 上一次报告给调用者的 level
+    applied: f32,
+    last_ms: Option<u32>,
+    lux: Option<f32>,
+}
+
+impl AutoDim {
+    // 在收到第一个读数之前，level 为 max_level
+    pub(crate) fn new(config: DimConfig) -> Self {
+        Self {
+            config,
+            target: config.max_level,
+            level: config.max_level,
+            applied: config.max_level,
+            last_ms: None,
+            lux: None,
+        }
+    }
+
+    pub(crate) fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub(crate) fn lux(&self) -> Option<f32> {
+        self.lux
+    }
+
+    // level 自上次调用以来的变化超过 MIN_STEP 时，返回新的 level，调用者据此更新各个 Dimmer
+    pub(crate) fn take_change(&mut self) -> Option<f32> {
+        // 最后一点小于 MIN_STEP 的变化，在到达目标时也要报告，否则会停在离目标差一点的地方
+        let settled = self.level == self.target && self.level != self.applied;
+        if (self.level - self.applied).abs() < MIN_STEP && !settled {
+            return None;
+        }
+        self.applied = self.level;
+        Some(self.level)
+    }
+
+    fn target_for(&self, lux: f32) -> f32 {
+        let DimConfig {
+            dark_lux,
+            bright_lux,
+            min_level,
+            max_level,
+            ..
+        } = self.config;
+        if lux <= dark_lux {
+            return min_level;
+        }
+        if lux >= bright_lux {
+            return max_level;
+        }
+        let t = (log2(lux) - log2(dark_lux)) / (log2(bright_lux) - log2(dark_lux));
+        min_level + (max_level - min_level) * t
+    }
+}
+
+impl Sink for AutoDim {
+    fn publish(&mut self, _now_ms: u32, _sensor_name: &'static str, reading: &Reading) {
+        if reading.quantity != "light" {
+            return;
+        }
+        self.lux = Some(reading.value);
+        self.target = self.target_for(reading.value);
+    }
+
+    fn flush(&mut self, now_ms: u32) {
+        let dt = match self.last_ms.replace(now_ms) {
+            Some(last) => now_ms.wrapping_sub(last),
+            None => return,
+        };
+        // 一阶低通，dt 远大于时间常数时直接到达目标
+        let alpha = (dt as f32 / self.config.time_constant_ms.max(1) as f32).min(1.0);
+        self.level += (self.target - self.level) * alpha;
+        if (self.target - self.level).abs() < MIN_STEP / 2.0 {
+            self.level = self.target;
+        }
+    }
+}
+
+impl<const N: usize> Dimmer for FrameBuffer<N> {
+    fn set_level(&mut self, level: f32) {
+        self.set_brightness((perceived_to_duty(level) * u8::MAX as f32) as u8);
+    }
+}
+
+// TIM12_CH1（PB14，AF9），1 kHz 的 PWM，高电平点亮
+pub(crate) struct Tim12Backlight;
+
+// 假设 APB1 的 TIM 时钟为 12 MHz（直接使用 HSE，且不分频）
+const TIM_CLK_HZ: u32 = 12_000_000;
+const BACKLIGHT_PWM_HZ: u32 = 1000;
+const BACKLIGHT_PERIOD: u32 = 1000;
+
+impl Tim12Backlight {
+    // 背光初始为全亮
+    pub(crate) fn new(dp: &pac::Peripherals) -> Self {
+        dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+        dp.RCC.apb1enr.modify(|_, w| w.tim12en().enabled());
+
+        let gpiob = &dp.GPIOB;
+        gpiob.afrh.modify(|_, w| w.afrh14().af9());
+        gpiob.moder.modify(|_, w| w.moder14().alternate());
+
+        let tim = &dp.TIM12;
+        tim.psc.write(|w| {
+            w.psc()
+                .bits((TIM_CLK_HZ / BACKLIGHT_PWM_HZ / BACKLIGHT_PERIOD - 1) as u16)
+        });
+        tim.arr.write(|w| unsafe { w.bits(BACKLIGHT_PERIOD - 1) });
+        tim.ccr1().write(|w| unsafe { w.bits(BACKLIGHT_PERIOD) });
+        tim.ccmr1_output().modify(|_, w| {
+            unsafe { w.cc1s().bits(0b00) };
+            w.oc1m().pwm_mode1();
+            w.oc1pe().set_bit();
+            w
+        });
+        tim.cr1.modify(|_, w| w.arpe().enabled());
+        tim.ccer.modify(|_, w| w.cc1e().set_bit());
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.cr1.modify(|_, w| w.cen().enabled());
+
+        Self
+    }
+}
+
+impl Dimmer for Tim12Backlight {
+    fn set_level(&mut self, level: f32) {
+        let tim = unsafe { &*pac::TIM12::ptr() };
+        let ccr = (perceived_to_duty(level) * BACKLIGHT_PERIOD as f32) as u32;
+        tim.ccr1().write(|w| unsafe { w.bits(ccr) });
+    }
+}
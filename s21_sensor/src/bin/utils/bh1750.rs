@@ -0,0 +1,192 @@
+//! BH1750 环境光传感器（I2C）
+//!
+//! 芯片内部已经完成了光谱的校正，读出的 16 bit 计数除以 1.2 就是 lux，不需要额外的计算
+//!
+//! 分辨率：
+//! - High：1 lx，最长 180 ms
+//! - High2：0.5 lx，最长 180 ms，计数的含义是 0.5 lx，最大只到约 32767 lx
+//! - Low：4 lx，最长 24 ms
+//!
+//! 测量时间（MTreg，31 ~ 254，默认 69）：
+//! 积分时间与 MTreg 成正比，灵敏度也与 MTreg 成正比，因此换算时要乘上 69 / MTreg
+//! 调大 MTreg 可以在很暗的地方得到更高的分辨率（254 时 High2 约 0.11 lx），调小则可以测到更亮的光（31 时约 100000 lx）
+//! 另一个用途是补偿传感器上面的透光窗口，窗口透过率为 50% 时，把 MTreg 设置为 138 即可
+//!
+//! 测量模式：
+//! - Continuous：芯片不停地测量，采样时只读取最新的结果，结果可能是上一个测量周期的
+//! - OneShot：每次采样时发出测量命令，等待测量完成后读取结果，之后芯片自动进入 Power Down，功耗最低
+//!
+//! I2C 外设需要事先配置好（见 s21c02），这里只通过 utils::blocking_master 收发数据
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::i2c1::RegisterBlock;
+
+use super::{
+    addressing::I2cAddress,
+    blocking_master,
+    sensor::{Lux, Sensor, SensorError},
+    ticker,
+};
+
+// ADDR 引脚接 GND（或悬空）时为 0x23，接 VCC 时为 0x5C
+pub(crate) const ADDR_LOW: u8 = 0x23;
+pub(crate) const ADDR_HIGH: u8 = 0x5C;
+
+const POWER_DOWN: u8 = 0x00;
+const POWER_ON: u8 = 0x01;
+// 只清空数据寄存器，Power Down 时无效
+const RESET: u8 = 0x07;
+const CONTINUOUS: u8 = 0x10;
+const ONE_SHOT: u8 = 0x20;
+// MTreg 分两条命令写入，高 3 位与低 5 位
+const MTREG_HIGH: u8 = 0x40;
+const MTREG_LOW: u8 = 0x60;
+
+pub(crate) const MTREG_MIN: u8 = 31;
+pub(crate) const MTREG_DEFAULT: u8 = 69;
+pub(crate) const MTREG_MAX: u8 = 254;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Resolution {
+    High,
+    High2,
+    Low,
+}
+
+impl Resolution {
+    // 加到 CONTINUOUS 或 ONE_SHOT 上的低位
+    fn command_bits(self) -> u8 {
+        match self {
+            Resolution::High => 0x00,
+            Resolution::High2 => 0x01,
+            Resolution::Low => 0x03,
+        }
+    }
+
+    // MTreg 为默认值时的最长测量时间
+    fn max_time_ms(self) -> u32 {
+        match self {
+            Resolution::High | Resolution::High2 => 180,
+            Resolution::Low => 24,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Mode {
+    Continuous,
+    OneShot,
+}
+
+pub(crate) struct Bh1750<'a> {
+    i2c: &'a RegisterBlock,
+    addr: I2cAddress,
+    resolution: Resolution,
+    mode: Mode,
+    mtreg: u8,
+}
+
+impl<'a> Bh1750<'a> {
+    // 上电并清空数据寄存器，之后处于 OneShot 模式、High 分辨率、默认的 MTreg
+    pub(crate) fn new(i2c: &'a RegisterBlock, addr: u8) -> Result<Self, SensorError> {
+        let bh = Self {
+            i2c,
+            addr: I2cAddress::SevenBit(addr),
+            resolution: Resolution::High,
+            mode: Mode::OneShot,
+            mtreg: MTREG_DEFAULT,
+        };
+        bh.command(POWER_ON)?;
+        bh.command(RESET)?;
+        bh.command(POWER_DOWN)?;
+        Ok(bh)
+    }
+
+    pub(crate) fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    pub(crate) fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub(crate) fn mtreg(&self) -> u8 {
+        self.mtreg
+    }
+
+    pub(crate) fn set_mode(
+        &mut self,
+        mode: Mode,
+        resolution: Resolution,
+    ) -> Result<(), SensorError> {
+        self.mode = mode;
+        self.resolution = resolution;
+        match mode {
+            // 连续模式需要先让芯片开始测量，第一个结果要等一个测量时间之后才有
+            Mode::Continuous => self.command(CONTINUOUS | resolution.command_bits()),
+            Mode::OneShot => self.command(POWER_DOWN),
+        }
+    }
+
+    // 超出 MTREG_MIN ~ MTREG_MAX 时返回 OutOfRange
+    pub(crate) fn set_mtreg(&mut self, mtreg: u8) -> Result<(), SensorError> {
+        if !(MTREG_MIN..=MTREG_MAX).contains(&mtreg) {
+            return Err(SensorError::OutOfRange);
+        }
+        self.command(MTREG_HIGH | (mtreg >> 5))?;
+        self.command(MTREG_LOW | (mtreg & 0b1_1111))?;
+        self.mtreg = mtreg;
+        // 连续模式下新的 MTreg 要在重新发出测量命令之后才生效
+        if self.mode == Mode::Continuous {
+            self.command(CONTINUOUS | self.resolution.command_bits())?;
+        }
+        Ok(())
+    }
+
+    // 当前设置下的最长测量时间，连续模式的采样间隔不应短于它
+    pub(crate) fn measurement_time_ms(&self) -> u32 {
+        (self.resolution.max_time_ms() * self.mtreg as u32).div_ceil(MTREG_DEFAULT as u32)
+    }
+
+    fn command(&self, command: u8) -> Result<(), SensorError> {
+        blocking_master::write(self.i2c, self.addr, &[command])?;
+        Ok(())
+    }
+
+    fn convert(&self, raw: u16) -> Lux {
+        let mut lux = raw as f32 / 1.2 * MTREG_DEFAULT as f32 / self.mtreg as f32;
+        if self.resolution == Resolution::High2 {
+            lux /= 2.0;
+        }
+        Lux(lux)
+    }
+}
+
+impl Sensor for Bh1750<'_> {
+    type Output = Lux;
+
+    fn name(&self) -> &'static str {
+        "bh1750"
+    }
+
+    fn sample(&mut self) -> Result<Lux, SensorError> {
+        if self.mode == Mode::OneShot {
+            self.command(ONE_SHOT | self.resolution.command_bits())?;
+            ticker::delay_ms(self.measurement_time_ms());
+        }
+
+        let mut buf = [0u8; 2];
+        blocking_master::read(self.i2c, self.addr, &mut buf)?;
+        let raw = u16::from_be_bytes(buf);
+        // 计数饱和，光线超出了当前 MTreg 下的量程
+        if raw == u16::MAX {
+            return Err(SensorError::OutOfRange);
+        }
+        Ok(self.convert(raw))
+    }
+}
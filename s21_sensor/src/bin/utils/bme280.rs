@@ -0,0 +1,296 @@
+//! BME280 温湿度气压传感器（I2C）
+//!
+//! BME280 给出的是未经处理的 ADC 值，需要结合芯片出厂时写入的校准参数，才能换算为实际的物理量
+//! 换算公式直接照搬 datasheet 的 Appendix A: Alternative compensation formulas in double precision floating point，
+//! Cortex-M4 的 FPU 只支持单精度，双精度运算由软件模拟，不过每次采样只算一次，这点开销可以接受
+//!
+//! 这里使用 forced mode：每次采样时写一次 ctrl_meas 启动一次转换，转换完成后芯片自动回到 sleep mode
+//! 三项的过采样均为 x1，关闭 IIR 滤波，此时一次转换最多约 10 ms
+//!
+//! I2C 外设需要事先配置好（见 s21c02），这里只通过 utils::blocking_master 收发数据
+//! SDO 接地时地址为 0x76，接 VDDIO 时为 0x77
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::i2c1::RegisterBlock;
+
+use super::{
+    addressing::I2cAddress,
+    blocking_master::{self, MasterError},
+    sensor::{
+        ultrasonic::{Air, EnvironmentSource},
+        Celsius, Measurement, Pascal, Reading, RelativeHumidity, Sensor, SensorError,
+    },
+    ticker,
+};
+
+pub(crate) const ADDR_SDO_LOW: u8 = 0x76;
+pub(crate) const ADDR_SDO_HIGH: u8 = 0x77;
+
+const REG_CALIB_00: u8 = 0x88;
+const REG_ID: u8 = 0xD0;
+const REG_RESET: u8 = 0xE0;
+const REG_CALIB_26: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_STATUS: u8 = 0xF3;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_CONFIG: u8 = 0xF5;
+const REG_PRESS_MSB: u8 = 0xF7;
+
+const CHIP_ID: u8 = 0x60;
+const RESET_WORD: u8 = 0xB6;
+
+// osrs_t = x1, osrs_p = x1, mode = forced
+#[allow(clippy::unusual_byte_groupings)]
+const CTRL_MEAS_FORCED: u8 = 0b001_001_01;
+// osrs_h = x1
+const CTRL_HUM_X1: u8 = 0b001;
+// STATUS 中的 measuring 位
+const STATUS_MEASURING: u8 = 1 << 3;
+// 对应的通道被跳过时（过采样为 skipped），ADC 读数为这个值
+const ADC_SKIPPED: i32 = 0x80000;
+
+// 最长转换时间约为 9.3 ms，留一些余量
+const CONVERSION_TIMEOUT_MS: u32 = 20;
+
+impl From<MasterError> for SensorError {
+    fn from(_: MasterError) -> Self {
+        SensorError::Bus
+    }
+}
+
+// 出厂校准参数，见 datasheet 的 Table 16: Compensation parameter storage, naming and data type
+#[derive(Default)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+    h1: u8,
+    h2: i16,
+    h3: u8,
+    h4: i16,
+    h5: i16,
+    h6: i8,
+}
+
+impl Calibration {
+    // calib00 ~ calib25 位于 0x88 ~ 0xA1，calib26 ~ calib41 位于 0xE1 ~ 0xF0，实际只用到其中的一部分
+    fn parse(low: &[u8; 26], high: &[u8; 7]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([low[i], low[i + 1]]);
+        let i16_at = |i: usize| i16::from_le_bytes([low[i], low[i + 1]]);
+
+        Self {
+            t1: u16_at(0),
+            t2: i16_at(2),
+            t3: i16_at(4),
+            p1: u16_at(6),
+            p2: i16_at(8),
+            p3: i16_at(10),
+            p4: i16_at(12),
+            p5: i16_at(14),
+            p6: i16_at(16),
+            p7: i16_at(18),
+            p8: i16_at(20),
+            p9: i16_at(22),
+            h1: low[25],
+            h2: i16::from_le_bytes([high[0], high[1]]),
+            h3: high[2],
+            // dig_H4 与 dig_H5 都是 12 bit 的有符号数，共用了 0xE5 这个字节
+            h4: ((high[3] as i8 as i16) << 4) | (high[4] & 0x0F) as i16,
+            h5: ((high[5] as i8 as i16) << 4) | (high[4] >> 4) as i16,
+            h6: high[6] as i8,
+        }
+    }
+
+    // 返回温度（℃）与 t_fine，后者是气压与湿度补偿的输入
+    fn temperature(&self, adc_t: i32) -> (f64, f64) {
+        let adc_t = adc_t as f64;
+        let t1 = self.t1 as f64;
+
+        let var1 = (adc_t / 16384.0 - t1 / 1024.0) * self.t2 as f64;
+        let var2 =
+            (adc_t / 131072.0 - t1 / 8192.0) * (adc_t / 131072.0 - t1 / 8192.0) * self.t3 as f64;
+        let t_fine = var1 + var2;
+
+        (t_fine / 5120.0, t_fine)
+    }
+
+    // 单位 Pa
+    fn pressure(&self, adc_p: i32, t_fine: f64) -> Option<f64> {
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * self.p6 as f64 / 32768.0;
+        var2 += var1 * self.p5 as f64 * 2.0;
+        var2 = var2 / 4.0 + self.p4 as f64 * 65536.0;
+        var1 = (self.p3 as f64 * var1 * var1 / 524288.0 + self.p2 as f64 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * self.p1 as f64;
+        if var1 == 0.0 {
+            // 避免除以 0
+            return None;
+        }
+
+        let mut p = 1048576.0 - adc_p as f64;
+        p = (p - var2 / 4096.0) * 6250.0 / var1;
+        var1 = self.p9 as f64 * p * p / 2147483648.0;
+        var2 = p * self.p8 as f64 / 32768.0;
+        Some(p + (var1 + var2 + self.p7 as f64) / 16.0)
+    }
+
+    // 单位 %
+    fn humidity(&self, adc_h: i32, t_fine: f64) -> f64 {
+        let mut h = t_fine - 76800.0;
+        h = (adc_h as f64 - (self.h4 as f64 * 64.0 + self.h5 as f64 / 16384.0 * h))
+            * (self.h2 as f64 / 65536.0
+                * (1.0
+                    + self.h6 as f64 / 67108864.0 * h * (1.0 + self.h3 as f64 / 67108864.0 * h)));
+        h *= 1.0 - self.h1 as f64 * h / 524288.0;
+        h.clamp(0.0, 100.0)
+    }
+}
+
+// 一次采样的结果
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Bme280Measurement {
+    pub(crate) temp: Celsius,
+    pub(crate) press: Pascal,
+    pub(crate) rh: RelativeHumidity,
+}
+
+impl Measurement for Bme280Measurement {
+    fn for_each_reading(&self, f: &mut dyn FnMut(Reading)) {
+        self.temp.for_each_reading(f);
+        self.press.for_each_reading(f);
+        self.rh.for_each_reading(f);
+    }
+}
+
+pub(crate) struct Bme280<'a> {
+    i2c: &'a RegisterBlock,
+    addr: I2cAddress,
+    calib: Calibration,
+}
+
+impl<'a> Bme280<'a> {
+    // 检查芯片 ID，复位芯片，并读取校准参数
+    pub(crate) fn new(i2c: &'a RegisterBlock, addr: u8) -> Result<Self, SensorError> {
+        let mut bme = Self {
+            i2c,
+            addr: I2cAddress::SevenBit(addr),
+            calib: Calibration::default(),
+        };
+
+        let mut id = [0u8];
+        bme.read_regs(REG_ID, &mut id)?;
+        if id[0] != CHIP_ID {
+            return Err(SensorError::OutOfRange);
+        }
+
+        bme.write_reg(REG_RESET, RESET_WORD)?;
+        // 复位之后，芯片需要 2 ms 把校准参数从 NVM 复制到寄存器
+        ticker::delay_ms(3);
+
+        let mut low = [0u8; 26];
+        let mut high = [0u8; 7];
+        bme.read_regs(REG_CALIB_00, &mut low)?;
+        bme.read_regs(REG_CALIB_26, &mut high)?;
+        bme.calib = Calibration::parse(&low, &high);
+
+        // ctrl_hum 要在写 ctrl_meas 之后才会生效，而 ctrl_meas 在每次采样时都会写一次
+        bme.write_reg(REG_CTRL_HUM, CTRL_HUM_X1)?;
+        // t_sb 在 forced mode 下没有意义，filter 关闭
+        bme.write_reg(REG_CONFIG, 0)?;
+
+        Ok(bme)
+    }
+
+    fn write_reg(&self, reg: u8, value: u8) -> Result<(), SensorError> {
+        blocking_master::write(self.i2c, self.addr, &[reg, value])?;
+        Ok(())
+    }
+
+    // BME280 读取时地址会自动递增，因此一次就可以读出连续的多个寄存器
+    fn read_regs(&self, reg: u8, buf: &mut [u8]) -> Result<(), SensorError> {
+        blocking_master::write_read(self.i2c, self.addr, &[reg], buf)?;
+        Ok(())
+    }
+
+    // 启动一次转换，并等待转换完成
+    fn convert(&self) -> Result<(), SensorError> {
+        self.write_reg(REG_CTRL_MEAS, CTRL_MEAS_FORCED)?;
+
+        let start = ticker::millis();
+        let mut status = [0u8];
+        loop {
+            ticker::delay_ms(1);
+            self.read_regs(REG_STATUS, &mut status)?;
+            if status[0] & STATUS_MEASURING == 0 {
+                return Ok(());
+            }
+            if ticker::millis().wrapping_sub(start) > CONVERSION_TIMEOUT_MS {
+                return Err(SensorError::Timeout);
+            }
+        }
+    }
+}
+
+impl Sensor for Bme280<'_> {
+    type Output = Bme280Measurement;
+
+    fn name(&self) -> &'static str {
+        "bme"
+    }
+
+    fn sample(&mut self) -> Result<Bme280Measurement, SensorError> {
+        self.convert()?;
+
+        // press_msb ~ hum_lsb 一共 8 个字节，一次读出，保证三者来自同一次转换
+        let mut data = [0u8; 8];
+        self.read_regs(REG_PRESS_MSB, &mut data)?;
+
+        let adc_p = ((data[0] as i32) << 12) | ((data[1] as i32) << 4) | ((data[2] as i32) >> 4);
+        let adc_t = ((data[3] as i32) << 12) | ((data[4] as i32) << 4) | ((data[5] as i32) >> 4);
+        let adc_h = ((data[6] as i32) << 8) | data[7] as i32;
+
+        if adc_t == ADC_SKIPPED || adc_p == ADC_SKIPPED {
+            return Err(SensorError::NotReady);
+        }
+
+        let (temp, t_fine) = self.calib.temperature(adc_t);
+        let press = self
+            .calib
+            .pressure(adc_p, t_fine)
+            .ok_or(SensorError::OutOfRange)?;
+        let rh = self.calib.humidity(adc_h, t_fine);
+
+        // 超出 datasheet 给出的工作范围，多半是读数出错了
+        if !(-40.0..=85.0).contains(&temp) || !(30_000.0..=110_000.0).contains(&press) {
+            return Err(SensorError::OutOfRange);
+        }
+
+        Ok(Bme280Measurement {
+            temp: Celsius(temp as f32),
+            press: Pascal(press as f32),
+            rh: RelativeHumidity(rh as f32),
+        })
+    }
+}
+
+impl EnvironmentSource for Bme280<'_> {
+    fn air(&mut self) -> Result<Air, SensorError> {
+        let m = self.sample()?;
+        Ok(Air {
+            temp: m.temp,
+            humidity: Some(m.rh),
+        })
+    }
+}
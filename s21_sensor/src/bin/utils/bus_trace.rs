@@ -0,0 +1,277 @@
+//! 记录每一次 I2C/SPI 传输的包装
+//!
+//! Traced<B> 包着任意一个实现了 embedded-hal 总线 trait 的对象（I2c、SpiBus 或 SpiDevice），自身也实现同样的 trait，
+//! 因此可以直接交给原本使用这条总线的驱动，驱动不需要做任何修改
+//!
+//! 每一次传输在 RTT 上输出一行，比如：
+//!
+//! ```text
+//! [bme280] 0x76 W1 d0 R1 60 | 212 us
+//! [bme280] 0x76 W1 d0 R1 | 96 us NoAcknowledge(Address)
+//! [loop] W4 de ad be ef | 35 us
+//! ```
+//!
+//! 依次为：名字、I2C 地址、每一个操作的方向与长度（W 写，R 读，T 同时收发）以及前 PREVIEW_LEN 个字节、耗时（来自 utils::ticker），出错时附上 ErrorKind
+//! 读操作的数据是传输之后才有的，出错时不再输出
+//!
+//! 输出一行 RTT 大约需要几十微秒，这段时间不计入耗时，但会让相邻两次传输之间的间隔变长，对时序敏感的场合要注意
+//! set_enabled(false) 之后只剩下一次原子读取的开销，因此可以在整个程序中一直保留这层包装，只在需要时打开
+
+#![allow(dead_code)]
+
+use core::{
+    fmt::{Debug, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use rtt_target::rprintln;
+use stm32f4xx_hal::hal::{
+    i2c::{self, I2c},
+    spi::{self, SpiBus, SpiDevice},
+};
+
+use super::{sensor::sink::LineBuf, ticker};
+
+// 每个操作最多输出的字节数
+const PREVIEW_LEN: usize = 4;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+// 打开或关闭所有 Traced 的输出，可以在中断中调用
+pub(crate) fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub(crate) struct Traced<B> {
+    name: &'static str,
+    inner: B,
+}
+
+impl<B> Traced<B> {
+    pub(crate) fn new(name: &'static str, inner: B) -> Self {
+        Self { name, inner }
+    }
+
+    pub(crate) fn inner(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    pub(crate) fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+// 一行记录，超出长度的部分会被丢弃
+struct Record {
+    line: LineBuf<96>,
+    start: u64,
+}
+
+impl Record {
+    // 关闭时返回 None，调用者直接把传输交给 inner
+    fn begin(name: &str) -> Option<Self> {
+        if !is_enabled() {
+            return None;
+        }
+        let mut line = LineBuf::new();
+        write!(line, "[{}]", name).ok();
+        Some(Self {
+            line,
+            start: ticker::micros(),
+        })
+    }
+
+    fn address(&mut self, address: u16) {
+        write!(self.line, " {:#04x}", address).ok();
+    }
+
+    fn op(&mut self, direction: char, data: Option<&[u8]>, len: usize) {
+        write!(self.line, " {}{}", direction, len).ok();
+        for byte in data.unwrap_or_default().iter().take(PREVIEW_LEN) {
+            write!(self.line, " {:02x}", byte).ok();
+        }
+        if data.is_some_and(|data| data.len() > PREVIEW_LEN) {
+            self.line.write_str(" ..").ok();
+        }
+    }
+
+    // 耗时在 finish 之前就要取得，这样格式化读操作的数据所花的时间不会被算进去
+    fn elapsed(&self) -> u64 {
+        ticker::micros() - self.start
+    }
+
+    fn finish(mut self, elapsed: u64, error: Option<&dyn Debug>) {
+        write!(self.line, " | {} us", elapsed).ok();
+        if let Some(error) = error {
+            write!(self.line, " {:?}", error).ok();
+        }
+        rprintln!(
+            "{}",
+            core::str::from_utf8(self.line.as_bytes()).unwrap_or("?")
+        );
+    }
+}
+
+// 把 I2C 的各个操作写入记录，读操作只有在成功之后才有数据
+fn record_i2c(record: &mut Record, operations: &[i2c::Operation], ok: bool) {
+    for operation in operations {
+        match operation {
+            i2c::Operation::Write(data) => record.op('W', Some(*data), data.len()),
+            i2c::Operation::Read(buf) => record.op('R', ok.then_some(&**buf), buf.len()),
+        }
+    }
+}
+
+// 只有 u8 的数据会被逐字节输出，更宽的字只输出长度
+fn preview<W: Copy + Into<u32>>(words: &[W], bytes: &mut [u8; PREVIEW_LEN + 1]) -> Option<usize> {
+    if core::mem::size_of::<W>() != 1 {
+        return None;
+    }
+    for (byte, &word) in bytes.iter_mut().zip(words) {
+        *byte = word.into() as u8;
+    }
+    Some(words.len().min(bytes.len()))
+}
+
+fn record_spi<W: Copy + Into<u32>>(
+    record: &mut Record,
+    operations: &[spi::Operation<W>],
+    ok: bool,
+) {
+    // 多取一个字节，这样 op 才知道后面还有数据
+    let mut bytes = [0u8; PREVIEW_LEN + 1];
+
+    for operation in operations {
+        let (direction, count, len) = match operation {
+            spi::Operation::Write(data) => ('W', preview(data, &mut bytes), data.len()),
+            spi::Operation::Read(buf) => ('R', preview(buf, &mut bytes).filter(|_| ok), buf.len()),
+            spi::Operation::Transfer(read, write) => {
+                ('T', preview(write, &mut bytes), write.len().max(read.len()))
+            }
+            spi::Operation::TransferInPlace(buf) => {
+                ('T', preview(buf, &mut bytes).filter(|_| ok), buf.len())
+            }
+            spi::Operation::DelayNs(ns) => {
+                write!(record.line, " D{}ns", ns).ok();
+                continue;
+            }
+        };
+        record.op(direction, count.map(|n| &bytes[..n]), len);
+    }
+}
+
+impl<B: i2c::ErrorType> i2c::ErrorType for Traced<B> {
+    type Error = B::Error;
+}
+
+// 驱动调用的 read、write、write_read 默认都会转为 transaction，因此只需要包装这一个方法
+impl<A, B> I2c<A> for Traced<B>
+where
+    A: i2c::AddressMode + Copy + Into<u16>,
+    B: I2c<A>,
+{
+    fn transaction(
+        &mut self,
+        address: A,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), B::Error> {
+        let Some(mut record) = Record::begin(self.name) else {
+            return self.inner.transaction(address, operations);
+        };
+
+        let result = self.inner.transaction(address, operations);
+        let elapsed = record.elapsed();
+
+        record.address(address.into());
+        record_i2c(&mut record, operations, result.is_ok());
+        let kind = result.as_ref().err().map(i2c::Error::kind);
+        record.finish(elapsed, kind.as_ref().map(|kind| kind as &dyn Debug));
+        result
+    }
+}
+
+impl<B: spi::ErrorType> spi::ErrorType for Traced<B> {
+    type Error = B::Error;
+}
+
+// SpiDevice 的一次 transaction 从片选拉低到片选拉高
+impl<W, B> SpiDevice<W> for Traced<B>
+where
+    W: Copy + Into<u32> + 'static,
+    B: SpiDevice<W>,
+{
+    fn transaction(&mut self, operations: &mut [spi::Operation<'_, W>]) -> Result<(), B::Error> {
+        let Some(mut record) = Record::begin(self.name) else {
+            return self.inner.transaction(operations);
+        };
+
+        let result = self.inner.transaction(operations);
+        let elapsed = record.elapsed();
+
+        record_spi(&mut record, operations, result.is_ok());
+        let kind = result.as_ref().err().map(spi::Error::kind);
+        record.finish(elapsed, kind.as_ref().map(|kind| kind as &dyn Debug));
+        result
+    }
+}
+
+// SpiBus 没有 transaction 的概念，每一次调用各记一行，片选由调用者自己控制
+impl<W, B> SpiBus<W> for Traced<B>
+where
+    W: Copy + Into<u32> + 'static,
+    B: SpiBus<W>,
+{
+    fn read(&mut self, words: &mut [W]) -> Result<(), B::Error> {
+        self.trace_bus(&mut [spi::Operation::Read(words)])
+    }
+
+    fn write(&mut self, words: &[W]) -> Result<(), B::Error> {
+        self.trace_bus(&mut [spi::Operation::Write(words)])
+    }
+
+    fn transfer(&mut self, read: &mut [W], write: &[W]) -> Result<(), B::Error> {
+        self.trace_bus(&mut [spi::Operation::Transfer(read, write)])
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [W]) -> Result<(), B::Error> {
+        self.trace_bus(&mut [spi::Operation::TransferInPlace(words)])
+    }
+
+    fn flush(&mut self) -> Result<(), B::Error> {
+        self.inner.flush()
+    }
+}
+
+impl<B> Traced<B> {
+    // 执行单个 SpiBus 操作并记录，DelayNs 不会出现在这里
+    fn trace_bus<W>(&mut self, operations: &mut [spi::Operation<'_, W>; 1]) -> Result<(), B::Error>
+    where
+        W: Copy + Into<u32> + 'static,
+        B: SpiBus<W>,
+    {
+        let record = Record::begin(self.name);
+
+        let result = match &mut operations[0] {
+            spi::Operation::Read(words) => self.inner.read(words),
+            spi::Operation::Write(words) => self.inner.write(words),
+            spi::Operation::Transfer(read, write) => self.inner.transfer(read, write),
+            spi::Operation::TransferInPlace(words) => self.inner.transfer_in_place(words),
+            spi::Operation::DelayNs(_) => Ok(()),
+        };
+
+        if let Some(mut record) = record {
+            // SpiBus 的调用可能在数据真正发送完成之前就返回，记录的耗时需要包含 flush
+            let result = result.and_then(|_| self.inner.flush());
+            let elapsed = record.elapsed();
+            record_spi(&mut record, operations, result.is_ok());
+            let kind = result.as_ref().err().map(spi::Error::kind);
+            record.finish(elapsed, kind.as_ref().map(|kind| kind as &dyn Debug));
+            return result;
+        }
+        result
+    }
+}
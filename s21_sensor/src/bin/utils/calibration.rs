@@ -0,0 +1,168 @@
+//! 传感器校准的通用框架
+//!
+//! 需要校准的驱动实现 Calibrate：
+//!
+//! - key：校准结果在 utils::settings 中的键，不超过 8 个字节
+//! - run：与用户交互，完成一次校准，返回校准参数（最多 4 个 f32）
+//! - apply：使用校准参数，启动时从 Flash 读出的参数，与刚刚校准得到的参数，都通过它交给驱动
+//!
+//! 与 Scheduler::poll 的 Sink 一样，所有需要校准的驱动以 &mut [&mut dyn Calibrate] 的形式交给这里的函数，
+//! 这个切片可以在每次调用时临时组装，驱动平时依旧可以注册到 Scheduler 上或者直接使用
+//!
+//! 交互通过 Prompt 完成，串口命令行与菜单都可以实现它，校准过程只需要“显示提示、让用户输入一个数”
+//!
+//! 常见的校准方式：
+//! - 偏移：让传感器处于已知状态（零输入、静止、无触摸……），测得的读数即为偏移
+//! - 两点线性：分别在两个已知输入下测量，求出增益与偏移，见 two_point
+
+#![allow(dead_code)]
+
+use super::{
+    datalog::LogFlash,
+    sensor::SensorError,
+    settings::{SettingsError, SettingsStore, Values},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum CalError {
+    // 用户取消了校准
+    Cancelled,
+    // 两个校准点离得太近，或者算出来的参数不合理
+    OutOfRange,
+    // 找不到这个 key 对应的驱动
+    Unknown,
+    Sensor(SensorError),
+    Settings(SettingsError),
+}
+
+impl From<SensorError> for CalError {
+    fn from(e: SensorError) -> Self {
+        CalError::Sensor(e)
+    }
+}
+
+impl From<SettingsError> for CalError {
+    fn from(e: SettingsError) -> Self {
+        CalError::Settings(e)
+    }
+}
+
+pub(crate) trait Prompt {
+    // 显示一条信息
+    fn say(&mut self, message: &str);
+
+    // 显示提示，并等待用户输入一个数值，用户取消时返回 None
+    fn ask(&mut self, message: &str) -> Option<f32>;
+}
+
+pub(crate) trait Calibrate {
+    fn key(&self) -> &'static str;
+
+    fn run(&mut self, prompt: &mut dyn Prompt) -> Result<Values, CalError>;
+
+    fn apply(&mut self, values: &Values);
+}
+
+// 启动时调用，把 Flash 中已有的校准参数交给各个驱动，返回应用了几个
+pub(crate) fn load_all<F: LogFlash>(
+    routines: &mut [&mut dyn Calibrate],
+    store: &SettingsStore<F>,
+) -> u32 {
+    let mut applied = 0;
+    for routine in routines.iter_mut() {
+        if let Some(values) = store.get(routine.key()) {
+            routine.apply(&values);
+            applied += 1;
+        }
+    }
+    applied
+}
+
+// 运行 key 对应的校准，成功后立刻应用，并写入 Flash
+pub(crate) fn run<F: LogFlash>(
+    routines: &mut [&mut dyn Calibrate],
+    key: &str,
+    prompt: &mut dyn Prompt,
+    store: &mut SettingsStore<F>,
+) -> Result<Values, CalError> {
+    let routine = routines
+        .iter_mut()
+        .find(|routine| routine.key() == key)
+        .ok_or(CalError::Unknown)?;
+
+    let values = routine.run(prompt)?;
+    routine.apply(&values);
+
+    store.set(key, values)?;
+    store.commit()?;
+    Ok(values)
+}
+
+// 线性校准的参数：实际值 = raw * gain + offset
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Linear {
+    pub(crate) gain: f32,
+    pub(crate) offset: f32,
+}
+
+impl Linear {
+    pub(crate) fn apply(&self, raw: f32) -> f32 {
+        raw * self.gain + self.offset
+    }
+
+    pub(crate) fn to_values(self) -> Values {
+        [self.gain, self.offset, 0.0, 0.0]
+    }
+
+    pub(crate) fn from_values(values: &Values) -> Self {
+        Self {
+            gain: values[0],
+            offset: values[1],
+        }
+    }
+}
+
+// 取 n 次采样的平均值，降低单次采样的噪声
+pub(crate) fn average(
+    n: u32,
+    mut sample: impl FnMut() -> Result<f32, SensorError>,
+) -> Result<f32, SensorError> {
+    let mut sum = 0.0;
+    for _ in 0..n {
+        sum += sample()?;
+    }
+    Ok(sum / n as f32)
+}
+
+// 两点线性校准
+//
+// 依次提示用户施加 low_hint 与 high_hint 所描述的输入，并输入此时的实际值（比如万用表的读数），
+// sample 返回未经校准的原始读数
+pub(crate) fn two_point(
+    prompt: &mut dyn Prompt,
+    low_hint: &str,
+    high_hint: &str,
+    mut sample: impl FnMut() -> Result<f32, SensorError>,
+) -> Result<Linear, CalError> {
+    let low = prompt.ask(low_hint).ok_or(CalError::Cancelled)?;
+    let raw_low = average(16, &mut sample)?;
+
+    let high = prompt.ask(high_hint).ok_or(CalError::Cancelled)?;
+    let raw_high = average(16, &mut sample)?;
+
+    // 两点太近的话，噪声会被放大到增益里
+    let span = raw_high - raw_low;
+    if span.abs() < 1.0 || high == low {
+        return Err(CalError::OutOfRange);
+    }
+
+    let gain = (high - low) / span;
+    Ok(Linear {
+        gain,
+        offset: low - raw_low * gain,
+    })
+}
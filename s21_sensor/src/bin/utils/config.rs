@@ -0,0 +1,134 @@
+//! 带版本号的配置，存两份，启动时自动迁移旧版本
+//!
+//! utils::settings 适合保存数量不定的校准参数，而这里的 Config 是一个固定的结构体，
+//! 各个字段由 postcard 序列化（与 s22 的遥测消息一样），因此增删字段时不需要手动计算偏移
+//!
+//! 存储介质与 utils::settings 相同，只要实现了 LogFlash 就可以，比如 QSPI Flash 或者芯片内部的 Flash（见 utils::internal_flash）
+//!
+//! Flash 中的格式、两份副本之间的修复与版本的迁移都在 telemetry_core::config 中，
+//! 那里的测试在 Host 上用 RAM 模拟 Flash，覆盖了第一份损坏、从第二份修复以及旧版本迁移的情况，
+//! 这里只定义 Config 本身与它的旧版本
+//!
+//! 修改 Config 的定义时：
+//! 1. 把旧的定义改名（比如 ConfigV1）留下来，VERSION 加 1
+//! 2. 在 Versioned::migrate 中添加一项，把旧版本的 payload 反序列化为旧的结构体，再转换为新的 Config
+//!
+//! 这样已经烧录过旧版本固件的板子，升级后依旧保留原来的配置
+
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+use telemetry_core::config::Versioned;
+
+use super::datalog::{pack, unpack};
+
+pub(crate) const VERSION: u16 = 2;
+
+pub(crate) const SERIAL_LEN: usize = 12;
+
+// 保存在 Flash 的两个扇区中，见 telemetry_core::config
+pub(crate) type ConfigStore<F> = telemetry_core::config::ConfigStore<F, Config>;
+
+// 当前版本的配置，只通过下面的访问函数读写，它们会把数值限制在合理的范围内
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Config {
+    // LCD1602 的对比度，0~100 %
+    lcd_contrast: u8,
+    // 板子的序列号，开机时打印出来，用来区分同时接在电脑上的几块板子，不足的部分补 0
+    serial: [u8; SERIAL_LEN],
+    bme_period_ms: u32,
+    sonar_period_ms: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            lcd_contrast: 40,
+            serial: pack("0001"),
+            bme_period_ms: 5000,
+            sonar_period_ms: 500,
+        }
+    }
+}
+
+// 可以在运行时调整的采样间隔
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Rate {
+    Bme,
+    Sonar,
+}
+
+impl Config {
+    pub(crate) fn lcd_contrast(&self) -> u8 {
+        self.lcd_contrast
+    }
+
+    pub(crate) fn set_lcd_contrast(&mut self, percent: u8) {
+        self.lcd_contrast = percent.min(100);
+    }
+
+    pub(crate) fn serial(&self) -> &str {
+        unpack(&self.serial)
+    }
+
+    // 只允许 ASCII 的数字与字母，超长的部分会被截断
+    pub(crate) fn set_serial(&mut self, serial: &str) -> bool {
+        if serial.is_empty() || !serial.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return false;
+        }
+        self.serial = pack(serial);
+        true
+    }
+
+    pub(crate) fn period_ms(&self, rate: Rate) -> u32 {
+        match rate {
+            Rate::Bme => self.bme_period_ms,
+            Rate::Sonar => self.sonar_period_ms,
+        }
+    }
+
+    // 返回实际设置的值
+    pub(crate) fn set_period_ms(&mut self, rate: Rate, period_ms: u32) -> u32 {
+        let (min, max, field) = match rate {
+            // BME280 forced mode 一次转换约 10 ms
+            Rate::Bme => (100, 3_600_000, &mut self.bme_period_ms),
+            // 两次测距之间要等回波散去
+            Rate::Sonar => (60, 60_000, &mut self.sonar_period_ms),
+        };
+        *field = period_ms.clamp(min, max);
+        *field
+    }
+}
+
+// 版本 1 的配置，那时只有两个采样间隔
+#[derive(Deserialize)]
+struct ConfigV1 {
+    bme_period_ms: u32,
+    sonar_period_ms: u32,
+}
+
+impl From<ConfigV1> for Config {
+    fn from(v1: ConfigV1) -> Self {
+        Self {
+            bme_period_ms: v1.bme_period_ms,
+            sonar_period_ms: v1.sonar_period_ms,
+            ..Self::default()
+        }
+    }
+}
+
+impl Versioned for Config {
+    const VERSION: u16 = VERSION;
+
+    fn migrate(version: u16, payload: &[u8]) -> Option<Self> {
+        match version {
+            1 => postcard::from_bytes::<ConfigV1>(payload)
+                .ok()
+                .map(Config::from),
+            _ => None,
+        }
+    }
+}
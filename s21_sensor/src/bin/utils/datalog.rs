@@ -0,0 +1,278 @@
+//! 把传感器读数记录到外部 Flash 中的数据记录器
+//!
+//! 每个读数是一条 32 字节的定长记录，时间戳来自 utils::ticker，
+//! 记录的格式定义在 telemetry_core::record 中，Host 端的 telemetry_host 用同一份定义解析 Flash 的转储文件
+//!
+//! 日志区域由若干个扇区组成，首尾相连，当作一个环形缓冲区使用：
+//!
+//! - 记录依次写入，32 整除页大小，因此一条记录总是在一次 Page Program 中写完
+//! - 每当一个扇区写入第一条记录之后，立刻擦除下一个扇区（只发出命令，不等待），
+//!   因此写指针前方总有一个已经擦除好的扇区，写入时不需要等待擦除，这就是“软实时”的含义：
+//!   单次写入的耗时只是一次 Page Program（1 ms 以内），只有擦除还没有结束时才会等一下
+//! - 代价是始终有一个扇区是空的，能保存的记录数为 (扇区数 - 1) * 每扇区记录数
+//!
+//! 掉电保护：
+//!
+//! 启动时（mount）扫描整个区域，CRC 正确的记录才算有效，seq 最大的有效记录之后就是写指针
+//! - 写入记录时掉电：这条记录的 CRC 不对，会被跳过，写指针也会跳过这个写坏了的位置
+//! - 擦除扇区时掉电：扇区里残留的内容 CRC 不对，会被忽略，mount 时会重新擦除这个扇区
+//!
+//! 无论在哪里掉电，最多损失正在写入的那一条记录
+
+#![allow(dead_code)]
+
+use telemetry_core::record::Slot;
+
+use super::sensor::{sink::Sink, Reading};
+
+// 存储介质的 trait 定义在 telemetry_core 中，这样 Host 端的测试可以用 RAM 代替 Flash
+pub(crate) use telemetry_core::{
+    flash::LogFlash,
+    record::{pack, unpack, Record, RECORD_SIZE},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum LogError {
+    // 起始地址没有对齐到扇区，或者扇区数少于 2
+    Geometry,
+    // 写入之后读回的内容不一致，这个位置会被跳过
+    Verify,
+}
+
+fn new_record(seq: u32, time_ms: u32, sensor_name: &str, reading: &Reading) -> Record {
+    Record::new(
+        seq,
+        time_ms,
+        reading.value,
+        sensor_name,
+        reading.quantity,
+        reading.unit,
+    )
+}
+
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct LogStats {
+    pub(crate) records: u32,
+    pub(crate) corrupt: u32,
+    pub(crate) capacity: u32,
+    // 没有记录时为 None
+    pub(crate) oldest_seq: Option<u32>,
+    pub(crate) newest_seq: Option<u32>,
+}
+
+pub(crate) struct DataLog<F: LogFlash> {
+    flash: F,
+    start: u32,
+    sectors: u32,
+    // 下一条记录写入的位置，以记录为单位
+    head: u32,
+    next_seq: u32,
+}
+
+impl<F: LogFlash> DataLog<F> {
+    const SLOTS_PER_SECTOR: u32 = F::SECTOR_SIZE / RECORD_SIZE as u32;
+
+    // 扫描 [start, start + sectors * SECTOR_SIZE)，恢复写指针与序号
+    pub(crate) fn mount(flash: F, start: u32, sectors: u32) -> Result<Self, LogError> {
+        if sectors < 2
+            || !start.is_multiple_of(F::SECTOR_SIZE)
+            || !F::PAGE_SIZE.is_multiple_of(RECORD_SIZE as u32)
+        {
+            return Err(LogError::Geometry);
+        }
+
+        let mut log = Self {
+            flash,
+            start,
+            sectors,
+            head: 0,
+            next_seq: 0,
+        };
+
+        // seq 最大的有效记录
+        let mut newest: Option<(u32, u32)> = None;
+        for slot in 0..log.total_slots() {
+            if let Slot::Valid(record) = log.read_slot(slot) {
+                if newest.is_none_or(|(seq, _)| record.seq > seq) {
+                    newest = Some((record.seq, slot));
+                }
+            }
+        }
+
+        let Some((seq, slot)) = newest else {
+            // 全新的 Flash，或者从来没有写入过有效记录，从第一个扇区开始
+            log.erase_sector_blocking(0);
+            return Ok(log);
+        };
+
+        log.next_seq = seq.wrapping_add(1);
+        log.head = log.next_slot(slot);
+
+        // 跳过掉电时写坏的位置，只在当前扇区内寻找；找不到的话，下一个扇区早已擦除好了
+        while !log.head.is_multiple_of(Self::SLOTS_PER_SECTOR)
+            && !matches!(log.read_slot(log.head), Slot::Erased)
+        {
+            log.head = log.next_slot(log.head);
+        }
+
+        // 当前扇区已经有记录了，那么下一个扇区应该已经擦除过，但擦除可能因为掉电没有完成，重新擦除一次
+        if !log.head.is_multiple_of(Self::SLOTS_PER_SECTOR) {
+            let next_sector = (log.head / Self::SLOTS_PER_SECTOR + 1) % sectors;
+            log.erase_sector_blocking(next_sector);
+        }
+
+        Ok(log)
+    }
+
+    fn total_slots(&self) -> u32 {
+        self.sectors * Self::SLOTS_PER_SECTOR
+    }
+
+    fn next_slot(&self, slot: u32) -> u32 {
+        (slot + 1) % self.total_slots()
+    }
+
+    fn slot_addr(&self, slot: u32) -> u32 {
+        self.start + slot * RECORD_SIZE as u32
+    }
+
+    fn read_slot(&mut self, slot: u32) -> Slot {
+        let mut raw = [0u8; RECORD_SIZE];
+        self.flash.read(self.slot_addr(slot), &mut raw);
+        Record::decode(&raw)
+    }
+
+    fn erase_sector_blocking(&mut self, sector: u32) {
+        self.flash.start_erase(self.start + sector * F::SECTOR_SIZE);
+        self.flash.wait_idle();
+    }
+
+    // 能保存的记录数，始终有一个扇区是空的
+    pub(crate) fn capacity(&self) -> u32 {
+        (self.sectors - 1) * Self::SLOTS_PER_SECTOR
+    }
+
+    // 追加一条记录，返回它的 seq
+    pub(crate) fn append(
+        &mut self,
+        time_ms: u32,
+        sensor_name: &str,
+        reading: &Reading,
+    ) -> Result<u32, LogError> {
+        let seq = self.next_seq;
+        let slot = self.head;
+        let raw = new_record(seq, time_ms, sensor_name, reading).encode();
+
+        self.flash.program(self.slot_addr(slot), &raw);
+
+        // 无论成功与否，这个位置都已经用过了
+        self.next_seq = seq.wrapping_add(1);
+        self.head = self.next_slot(slot);
+
+        // 扇区的第一条记录写入之后，提前擦除下一个扇区
+        if slot.is_multiple_of(Self::SLOTS_PER_SECTOR) {
+            let next_sector = (slot / Self::SLOTS_PER_SECTOR + 1) % self.sectors;
+            // 读回校验要在擦除之前做，否则要等擦除结束才能读
+            let verified = self.verify(slot, &raw);
+            self.flash
+                .start_erase(self.start + next_sector * F::SECTOR_SIZE);
+            return verified.map(|_| seq);
+        }
+
+        self.verify(slot, &raw).map(|_| seq)
+    }
+
+    fn verify(&mut self, slot: u32, raw: &[u8; RECORD_SIZE]) -> Result<(), LogError> {
+        let mut readback = [0u8; RECORD_SIZE];
+        self.flash.read(self.slot_addr(slot), &mut readback);
+        if &readback == raw {
+            Ok(())
+        } else {
+            Err(LogError::Verify)
+        }
+    }
+
+    // 按照从旧到新的顺序，遍历 seq >= since 的所有有效记录
+    pub(crate) fn for_each(&mut self, since: u32, mut f: impl FnMut(&Record)) {
+        // 写指针所在扇区的下一个扇区，存放的是最旧的记录（或者已经被擦除了）
+        let head_sector = self.head / Self::SLOTS_PER_SECTOR;
+        let first = ((head_sector + 1) % self.sectors) * Self::SLOTS_PER_SECTOR;
+
+        let mut slot = first;
+        for _ in 0..self.total_slots() {
+            if let Slot::Valid(record) = self.read_slot(slot) {
+                if record.seq >= since {
+                    f(&record);
+                }
+            }
+            slot = self.next_slot(slot);
+        }
+    }
+
+    // 扫描整个区域进行统计
+    pub(crate) fn stats(&mut self) -> LogStats {
+        let mut stats = LogStats {
+            capacity: self.capacity(),
+            ..Default::default()
+        };
+        for slot in 0..self.total_slots() {
+            match self.read_slot(slot) {
+                Slot::Valid(record) => {
+                    stats.records += 1;
+                    stats.oldest_seq =
+                        Some(stats.oldest_seq.map_or(record.seq, |s| s.min(record.seq)));
+                    stats.newest_seq =
+                        Some(stats.newest_seq.map_or(record.seq, |s| s.max(record.seq)));
+                }
+                Slot::Corrupt => stats.corrupt += 1,
+                Slot::Erased => {}
+            }
+        }
+        stats
+    }
+
+    // 擦除全部记录，seq 不会重置，这样导出到 Host 端的数据依旧不会重复
+    pub(crate) fn erase_all(&mut self) {
+        for sector in 0..self.sectors {
+            self.erase_sector_blocking(sector);
+        }
+        self.head = 0;
+    }
+
+    pub(crate) fn next_seq(&self) -> u32 {
+        self.next_seq
+    }
+}
+
+// 把每一个读数都写入 DataLog 的 Sink
+pub(crate) struct LogSink<'a, F: LogFlash> {
+    log: &'a mut DataLog<F>,
+    // 写入失败的次数
+    failures: u32,
+}
+
+impl<'a, F: LogFlash> LogSink<'a, F> {
+    pub(crate) fn new(log: &'a mut DataLog<F>) -> Self {
+        Self { log, failures: 0 }
+    }
+
+    pub(crate) fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    // 导出等操作需要直接访问 DataLog
+    pub(crate) fn log(&mut self) -> &mut DataLog<F> {
+        self.log
+    }
+}
+
+impl<F: LogFlash> Sink for LogSink<'_, F> {
+    fn publish(&mut self, now_ms: u32, sensor_name: &'static str, reading: &Reading) {
+        if self.log.append(now_ms, sensor_name, reading).is_err() {
+            self.failures += 1;
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! 多个驱动共用的延时，基于一个基本定时器 TIM6，不占用 SysTick
+//!
+//! stm32f4xx_hal 的 SysDelay 会独占 SysTick，一个程序里只能有一个；每个驱动都要一个延时对象的时候，只能互相抢，
+//! 或者像 utils::lcd1602 那样用 cortex_m::asm::delay 按 CPU 周期估算，换了时钟频率就不准了
+//!
+//! 这里让 TIM6 以 1 MHz 自由计数，从不停止，也从不被改写，每次延时只是反复读取 CNT，累加经过的计数，直到够数为止
+//! 因此：
+//!
+//! - Delay 只是一个零大小的句柄，可以随意复制，每个驱动各拿一个
+//! - 主循环与中断处理函数可以同时使用，它们只读取 CNT，互不影响
+//! - 延时期间被中断打断，只要这次打断短于 TIM6 的一次回绕（65.5 ms），延时就依旧准确，不会因此变长很多
+//!
+//! Delay 实现了 embedded-hal 1.0 的 DelayNs，以及 embedded-hal 0.2 的 DelayUs/DelayMs，可以直接交给使用这两个 trait 的驱动
+//! 分辨率为 1 us，不足 1 us 的部分向上取整
+//!
+//! DelayProvider::setup 之后才能得到 Delay，拿不到 provider 的地方（比如中断处理函数），可以使用 Delay::get
+
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use stm32f4xx_hal::{
+    hal::delay::DelayNs,
+    hal_02::blocking::delay::{DelayMs, DelayUs},
+    pac,
+};
+
+// 与 utils::ticker 相同，假设 APB1 上 TIM 的时钟为 12 MHz（直接使用 HSE，且不分频）
+const TIM_CLK_HZ: u32 = 12_000_000;
+
+static G_READY: AtomicBool = AtomicBool::new(false);
+
+pub(crate) struct DelayProvider {
+    _private: (),
+}
+
+impl DelayProvider {
+    // 开启 TIM6，以 1 MHz 自由计数，只应该调用一次
+    pub(crate) fn setup(dp: &pac::Peripherals) -> Self {
+        dp.RCC.apb1enr.modify(|_, w| w.tim6en().enabled());
+
+        let tim6 = &dp.TIM6;
+        tim6.cr1.modify(|_, w| w.cen().disabled());
+        tim6.psc
+            .write(|w| w.psc().bits((TIM_CLK_HZ / 1_000_000 - 1) as u16));
+        tim6.arr.write(|w| w.arr().bits(u16::MAX));
+        // 让 PSC 立刻生效，这里没有打开中断，UIF 不需要理会
+        tim6.egr.write(|w| w.ug().update());
+        tim6.cr1.modify(|_, w| w.cen().enabled());
+
+        G_READY.store(true, Ordering::Release);
+
+        Self { _private: () }
+    }
+
+    pub(crate) fn handle(&self) -> Delay {
+        Delay { _private: () }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Delay {
+    _private: (),
+}
+
+impl Delay {
+    // DelayProvider::setup 之前返回 None
+    pub(crate) fn get() -> Option<Self> {
+        G_READY
+            .load(Ordering::Acquire)
+            .then_some(Self { _private: () })
+    }
+
+    pub(crate) fn us(&self, us: u32) {
+        let mut last = now();
+        let mut elapsed = 0u32;
+        while elapsed < us {
+            let current = now();
+            elapsed = elapsed.saturating_add(current.wrapping_sub(last) as u32);
+            last = current;
+        }
+    }
+
+    pub(crate) fn ms(&self, ms: u32) {
+        // 分段延时，避免 ms * 1000 溢出
+        for _ in 0..ms {
+            self.us(1000);
+        }
+    }
+}
+
+fn now() -> u16 {
+    // 这里只读取 CNT，TIM6 由 DelayProvider 配置之后不再被改写
+    let tim6 = unsafe { &*pac::TIM6::ptr() };
+    tim6.cnt.read().cnt().bits()
+}
+
+impl DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.us(ns.div_ceil(1000));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.us(us);
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        self.ms(ms);
+    }
+}
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        self.us(us);
+    }
+}
+
+impl DelayUs<u16> for Delay {
+    fn delay_us(&mut self, us: u16) {
+        self.us(us as u32);
+    }
+}
+
+impl DelayUs<u8> for Delay {
+    fn delay_us(&mut self, us: u8) {
+        self.us(us as u32);
+    }
+}
+
+impl DelayMs<u32> for Delay {
+    fn delay_ms(&mut self, ms: u32) {
+        self.ms(ms);
+    }
+}
+
+impl DelayMs<u16> for Delay {
+    fn delay_ms(&mut self, ms: u16) {
+        self.ms(ms as u32);
+    }
+}
+
+impl DelayMs<u8> for Delay {
+    fn delay_ms(&mut self, ms: u8) {
+        self.ms(ms as u32);
+    }
+}
@@ -0,0 +1,56 @@
+//! 重启进入芯片内置的 System Memory Bootloader（USB DFU / USART 等）
+//!
+//! 内置的 bootloader 位于 System Memory（0x1FFF_0000），正常情况下需要在复位时把 BOOT0 拉高才会运行
+//! 不方便去动 BOOT0 的时候，可以由程序跳转过去，但跳转之前芯片应该尽量接近刚复位的状态，
+//! 最简单的做法是先留下一个标记再软件复位，复位之后在 main 的最开头检查标记并跳转，此时还没有任何外设被初始化
+//!
+//! 标记保存在 RTC_BKP17R 中，软件复位不会清空它；s14 的 utils::boot 使用的是 RTC_BKP18R/RTC_BKP19R，两者不冲突
+//!
+//! 跳转时把 SYSCFG_MEMRM 设置为 System Memory，让它映射到 0x0000_0000，与 BOOT0 启动时一致
+
+#![allow(dead_code)]
+
+use cortex_m::peripheral::SCB;
+use stm32f4xx_hal::pac::Peripherals;
+
+const SYSTEM_MEMORY: u32 = 0x1FFF_0000;
+
+const BKP_MAGIC: usize = 17;
+// "DFU!"
+const DFU_MAGIC: u32 = 0x2155_4644;
+
+// 留下标记并复位，不会返回
+pub(crate) fn reboot_to_dfu(dp: &Peripherals) -> ! {
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| w.dbp().set_bit());
+    dp.RTC.bkpr[BKP_MAGIC].write(|w| w.bkp().bits(DFU_MAGIC));
+
+    SCB::sys_reset()
+}
+
+// 在 main 的最开头调用，有标记时清除标记并跳转到内置的 bootloader，否则直接返回
+pub(crate) fn enter_if_requested(dp: &Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().enabled());
+    dp.PWR.cr.modify(|_, w| w.dbp().set_bit());
+
+    let requested = dp.RTC.bkpr[BKP_MAGIC].read().bkp().bits() == DFU_MAGIC;
+    // 标记只使用一次，否则从 bootloader 复位回来之后又会跳过去
+    dp.RTC.bkpr[BKP_MAGIC].write(|w| w.bkp().bits(0));
+
+    dp.PWR.cr.modify(|_, w| w.dbp().clear_bit());
+    dp.RCC.apb1enr.modify(|_, w| w.pwren().disabled());
+
+    if !requested {
+        return;
+    }
+
+    dp.RCC.apb2enr.modify(|_, w| w.syscfgen().enabled());
+    dp.SYSCFG
+        .memrm
+        .modify(|_, w| unsafe { w.mem_mode().bits(0b01) });
+
+    unsafe {
+        // 设置 MSP 为向量表的第一个字，并跳转到第二个字
+        cortex_m::asm::bootload(SYSTEM_MEMORY as *const u32)
+    }
+}
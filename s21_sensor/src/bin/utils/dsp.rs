@@ -0,0 +1,123 @@
+//! 一维信号的几个简单数字滤波器，给 PPG（光电容积脉搏波）这类慢变化的信号使用
+//!
+//! - DcBlocker：一阶高通，y[n] = x[n] - x[n-1] + alpha * y[n-1]，去掉直流与缓慢的漂移，只留下交流部分
+//!   截止频率约为 (1 - alpha) * fs / (2π)，alpha 越接近 1，截止频率越低
+//! - Ema：指数滑动平均，一阶低通，value += alpha * (x - value)，用来跟踪直流分量
+//! - MovingAverage：N 点滑动平均，去掉高频噪声，群延迟为 (N - 1) / 2 个采样
+//!
+//! 三者都只用 f32 的加减乘，不需要 libm
+
+#![allow(dead_code)]
+
+pub(crate) struct DcBlocker {
+    alpha: f32,
+    x1: f32,
+    y1: f32,
+    primed: bool,
+}
+
+impl DcBlocker {
+    pub(crate) const fn new(alpha: f32) -> Self {
+        Self {
+            alpha,
+            x1: 0.0,
+            y1: 0.0,
+            primed: false,
+        }
+    }
+
+    pub(crate) fn process(&mut self, x: f32) -> f32 {
+        // 第一个采样直接作为 x[n-1]，否则输出会从一个很大的阶跃开始，要很久才能回到 0
+        if !self.primed {
+            self.x1 = x;
+            self.primed = true;
+        }
+        let y = x - self.x1 + self.alpha * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.y1 = 0.0;
+        self.primed = false;
+    }
+}
+
+pub(crate) struct Ema {
+    alpha: f32,
+    value: Option<f32>,
+}
+
+impl Ema {
+    pub(crate) const fn new(alpha: f32) -> Self {
+        Self { alpha, value: None }
+    }
+
+    // 第一个采样直接作为初值
+    pub(crate) fn process(&mut self, x: f32) -> f32 {
+        let value = match self.value {
+            Some(value) => value + self.alpha * (x - value),
+            None => x,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    pub(crate) fn value(&self) -> Option<f32> {
+        self.value
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.value = None;
+    }
+}
+
+pub(crate) struct MovingAverage<const N: usize> {
+    buf: [f32; N],
+    index: usize,
+    len: usize,
+    sum: f32,
+}
+
+impl<const N: usize> MovingAverage<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            buf: [0.0; N],
+            index: 0,
+            len: 0,
+            sum: 0.0,
+        }
+    }
+
+    // 不满 N 个采样时，返回已有采样的平均
+    pub(crate) fn process(&mut self, x: f32) -> f32 {
+        if self.len == N {
+            self.sum -= self.buf[self.index];
+        } else {
+            self.len += 1;
+        }
+        self.buf[self.index] = x;
+        self.sum += x;
+        self.index = (self.index + 1) % N;
+        self.sum / self.len as f32
+    }
+
+    pub(crate) fn average(&self) -> Option<f32> {
+        if self.len == 0 {
+            return None;
+        }
+        Some(self.sum / self.len as f32)
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.index = 0;
+        self.len = 0;
+        self.sum = 0.0;
+    }
+}
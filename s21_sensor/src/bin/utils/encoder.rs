@@ -0,0 +1,105 @@
+//! 旋转编码器，使用 TIM3 的编码器接口模式
+//!
+//! 编码器接口的原理见 s06c05，这里 TIM3 在 A/B 两相的每个边沿都计数一次，
+//! 常见的 EC11 编码器每转过一格（一个 detent）正好产生 4 个边沿，因此 Encoder::poll 按 4 个计数折算为一格
+//!
+//! 编码器自带的按键可以直接当作 utils::keypad 中的 Enter 键
+//!
+//! 接线图：
+//!
+//! PC6 (TIM3_CH1) <-> A
+//! PC7 (TIM3_CH2) <-> B
+//! GND            <-> C
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac;
+
+const COUNTS_PER_DETENT: i16 = 4;
+
+pub(crate) fn setup(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.tim3en().enabled());
+
+    // 编码器的 A/B 两相在转动时接地，平时靠上拉保持高电平
+    let gpioc = &dp.GPIOC;
+    gpioc.pupdr.modify(|_, w| {
+        w.pupdr6().pull_up();
+        w.pupdr7().pull_up();
+        w
+    });
+    gpioc.afrl.modify(|_, w| {
+        w.afrl6().af2();
+        w.afrl7().af2();
+        w
+    });
+    gpioc.moder.modify(|_, w| {
+        w.moder6().alternate();
+        w.moder7().alternate();
+        w
+    });
+
+    let tim3 = &dp.TIM3;
+
+    // 机械触点也会抖动，这里打开输入滤波
+    tim3.ccmr1_input().modify(|_, w| {
+        w.cc1s().ti1();
+        w.ic1f().fdts_div16_n8();
+        w.cc2s().ti2();
+        // IC2F 没有生成枚举，0b1101 即 fDTS/16, N=8
+        w.ic2f().variant(0b1101);
+        w
+    });
+    tim3.ccer.modify(|_, w| {
+        w.cc1p().clear_bit();
+        w.cc1np().clear_bit();
+        w.cc2p().clear_bit();
+        w.cc2np().clear_bit();
+        w
+    });
+    // TI1 与 TI2 的边沿都计数
+    tim3.smcr.modify(|_, w| w.sms().encoder_mode_3());
+    tim3.arr.write(|w| w.arr().bits(0xFFFF));
+    tim3.cr1.modify(|_, w| w.cen().enabled());
+}
+
+// 可以当作旋钮使用的输入，除了这里的 Encoder，utils::as5600::As5600Knob 也实现了它
+pub(crate) trait Knob {
+    // 返回自上次调用以来转过的格数，顺时针为正
+    fn poll(&mut self) -> i16;
+}
+
+pub(crate) struct Encoder {
+    // 上一次折算为整格时的计数值
+    last_cnt: u16,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        Self { last_cnt: cnt() }
+    }
+
+    // 返回自上次调用以来转过的格数，顺时针为正
+    //
+    // CNT 是 16 位的，两次调用之间只要转过的格数不超过 8000 多格，用 wrapping_sub 就能得到正确的差值
+    // 不足一格的计数会留到下一次
+    pub(crate) fn poll(&mut self) -> i16 {
+        let diff = cnt().wrapping_sub(self.last_cnt) as i16;
+        let detents = diff / COUNTS_PER_DETENT;
+        self.last_cnt = self
+            .last_cnt
+            .wrapping_add((detents * COUNTS_PER_DETENT) as u16);
+        detents
+    }
+}
+
+impl Knob for Encoder {
+    fn poll(&mut self) -> i16 {
+        Encoder::poll(self)
+    }
+}
+
+fn cnt() -> u16 {
+    let tim3 = unsafe { &*pac::TIM3::ptr() };
+    tim3.cnt.read().bits() as u16
+}
@@ -0,0 +1,168 @@
+//! 事件总线：中断与主循环之间的发布/订阅
+//!
+//! 之前的例子中，中断与主循环之间的通信都是各写各的：这里一个 AtomicBool，那里一个计数器，
+//! 想让第二个模块也知道“按键按下了”，就得再加一个标志，并且在中断里多写一行
+//!
+//! 这里把它们统一成 Topic：
+//!
+//! - 每个 Topic 是一个 static，在定义的时候就确定了事件的类型 T 与容量 N，不需要动态分配
+//! - publish 可以在中断或主循环中调用，不会阻塞：事件写入一个长度为 N 的环形缓冲，缓冲满了就覆盖最旧的事件
+//! - subscribe 得到一个 Subscriber，每个 Subscriber 有自己的读取位置，因此同一个事件会被每个订阅者各读到一次（广播）
+//! - 订阅者读得太慢，最旧的事件被覆盖时，跳过被覆盖的部分，并累计在 lost 中，而不是让发布者等待
+//!
+//! 事件的序号是一个只增不减的 u32，环形缓冲中的位置为 序号 % N，订阅者保存自己下一个要读的序号，
+//! 序号之差就是还没有读的事件数，超过 N 就说明有事件被覆盖了
+//!
+//! 所有的操作都在 cortex_m::interrupt::free 中进行，T 应该是一个小的 Copy 类型（比如枚举、时间戳），
+//! 临界区中只复制一个 T，不会明显推迟其他中断
+//!
+//! 为了能统一打印各个 Topic 的统计，Topic 实现了 TopicInfo，程序把自己的 Topic 列在一个 static 数组里，
+//! 交给 write_stats 即可
+
+#![allow(dead_code)]
+
+use core::{
+    cell::RefCell,
+    fmt::{self, Write},
+};
+
+use cortex_m::interrupt::Mutex;
+
+struct Ring<T, const N: usize> {
+    slots: [Option<T>; N],
+    // 已经发布的事件总数，也就是下一个事件的序号
+    head: u32,
+    subscribers: u8,
+    // 所有订阅者累计丢失的事件数
+    lost: u32,
+}
+
+pub(crate) struct Topic<T: Copy, const N: usize> {
+    name: &'static str,
+    ring: Mutex<RefCell<Ring<T, N>>>,
+}
+
+impl<T: Copy, const N: usize> Topic<T, N> {
+    pub(crate) const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            ring: Mutex::new(RefCell::new(Ring {
+                slots: [None; N],
+                head: 0,
+                subscribers: 0,
+                lost: 0,
+            })),
+        }
+    }
+
+    // 中断与主循环中都可以调用
+    pub(crate) fn publish(&self, event: T) {
+        cortex_m::interrupt::free(|cs| {
+            let mut ring = self.ring.borrow(cs).borrow_mut();
+            let index = ring.head as usize % N;
+            ring.slots[index] = Some(event);
+            ring.head = ring.head.wrapping_add(1);
+        });
+    }
+
+    // 订阅者只会收到订阅之后发布的事件
+    pub(crate) fn subscribe(&'static self) -> Subscriber<T, N> {
+        let next = cortex_m::interrupt::free(|cs| {
+            let mut ring = self.ring.borrow(cs).borrow_mut();
+            ring.subscribers = ring.subscribers.saturating_add(1);
+            ring.head
+        });
+        Subscriber {
+            topic: self,
+            next,
+            lost: 0,
+        }
+    }
+}
+
+pub(crate) struct Subscriber<T: Copy + 'static, const N: usize> {
+    topic: &'static Topic<T, N>,
+    // 下一个要读取的事件的序号
+    next: u32,
+    lost: u32,
+}
+
+impl<T: Copy, const N: usize> Subscriber<T, N> {
+    // 取出下一个事件，没有新事件时返回 None
+    pub(crate) fn try_recv(&mut self) -> Option<T> {
+        cortex_m::interrupt::free(|cs| {
+            let mut ring = self.topic.ring.borrow(cs).borrow_mut();
+            let unread = ring.head.wrapping_sub(self.next);
+            if unread == 0 {
+                return None;
+            }
+            // 最旧的 unread - N 个事件已经被覆盖了
+            if unread > N as u32 {
+                let skipped = unread - N as u32;
+                self.lost = self.lost.wrapping_add(skipped);
+                ring.lost = ring.lost.wrapping_add(skipped);
+                self.next = ring.head.wrapping_sub(N as u32);
+            }
+            let event = ring.slots[self.next as usize % N];
+            self.next = self.next.wrapping_add(1);
+            event
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        cortex_m::interrupt::free(|cs| self.topic.ring.borrow(cs).borrow().head == self.next)
+    }
+
+    // 这个订阅者因为读得太慢而丢失的事件数
+    pub(crate) fn lost(&self) -> u32 {
+        self.lost
+    }
+}
+
+// 不同类型、不同容量的 Topic 都可以放进同一个 &dyn TopicInfo 的数组中
+pub(crate) trait TopicInfo: Sync {
+    fn name(&self) -> &'static str;
+    fn capacity(&self) -> usize;
+    fn published(&self) -> u32;
+    fn subscribers(&self) -> u8;
+    fn lost(&self) -> u32;
+}
+
+impl<T: Copy + Send, const N: usize> TopicInfo for Topic<T, N> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn capacity(&self) -> usize {
+        N
+    }
+
+    fn published(&self) -> u32 {
+        cortex_m::interrupt::free(|cs| self.ring.borrow(cs).borrow().head)
+    }
+
+    fn subscribers(&self) -> u8 {
+        cortex_m::interrupt::free(|cs| self.ring.borrow(cs).borrow().subscribers)
+    }
+
+    fn lost(&self) -> u32 {
+        cortex_m::interrupt::free(|cs| self.ring.borrow(cs).borrow().lost)
+    }
+}
+
+// 每个 Topic 一行：名称、容量、订阅者数、发布的事件数、丢失的事件数
+pub(crate) fn write_stats(topics: &[&dyn TopicInfo], w: &mut impl Write) -> fmt::Result {
+    writeln!(w, "topic        cap subs published lost")?;
+    for topic in topics {
+        writeln!(
+            w,
+            "{:<12} {:>3} {:>4} {:>9} {:>4}",
+            topic.name(),
+            topic.capacity(),
+            topic.subscribers(),
+            topic.published(),
+            topic.lost()
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! 不依赖 embassy 的最小 async 执行器
+//!
+//! 任务的数量在编译期确定（最多 32 个），每个任务是一个 Pin<&mut dyn Future<Output = ()>>，通常用 core::pin::pin! 固定在 main 的栈上，
+//! 因此不需要堆，也不需要 static 的任务池
+//!
+//! 每个任务对应 READY 中的一个位，任务的 Waker 只是带着自己的序号，wake 时把对应的位置 1，再执行 SEV；
+//! run 每一轮取走 READY 中的所有位，依次 poll 这些任务，没有任务就绪时执行 WFE 睡眠
+//! 用 WFE 而不是 WFI，是因为中断可能恰好在“检查 READY”与“睡眠”之间 wake 了某个任务，
+//! 此时 SEV 已经设置了事件寄存器，WFE 会立刻返回，不会错过这次唤醒
+//!
+//! Waker 只是一个整数，clone 与 drop 都不需要做任何事情，在中断中 wake 也只是一次原子操作
+//! 代价是同一时刻只能运行一个执行器，run 的第二次调用会与第一次共用 READY
+//!
+//! 另外提供 sleep_ms，它需要在 1 kHz 的中断（比如 SysTick）中调用 on_tick，时间本身由 utils::ticker 提供
+
+#![allow(dead_code)]
+
+use core::{
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use super::{ticker, wait_cell::WakerSet};
+
+// 第 n 位为 1 表示第 n 个任务需要被 poll
+static READY: AtomicU32 = AtomicU32::new(0);
+
+// 同时在 sleep_ms 中的任务数
+const MAX_SLEEPERS: usize = 8;
+static SLEEPERS: WakerSet<MAX_SLEEPERS> = WakerSet::new();
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake_task, wake_task, drop_waker);
+
+fn clone_waker(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+// wake 与 wake_by_ref 的行为相同
+fn wake_task(data: *const ()) {
+    READY.fetch_or(1 << data as usize, Ordering::Release);
+    cortex_m::asm::sev();
+}
+
+fn drop_waker(_: *const ()) {}
+
+fn task_waker(index: usize) -> Waker {
+    // 安全性：VTABLE 中的函数只把 data 当作整数使用，不会解引用
+    unsafe { Waker::from_raw(RawWaker::new(index as *const (), &VTABLE)) }
+}
+
+// 运行所有任务，已经完成的任务不再被 poll，所有任务都完成之后，只是一直睡眠
+pub(crate) fn run<const N: usize>(mut tasks: [Pin<&mut dyn Future<Output = ()>>; N]) -> ! {
+    assert!(N <= 32);
+
+    // 第一轮 poll 所有任务
+    READY.store(((1u64 << N) - 1) as u32, Ordering::Relaxed);
+    let mut done = 0u32;
+
+    loop {
+        let ready = READY.swap(0, Ordering::Acquire) & !done;
+        if ready == 0 {
+            cortex_m::asm::wfe();
+            continue;
+        }
+
+        for (index, task) in tasks.iter_mut().enumerate() {
+            if ready & (1 << index) == 0 {
+                continue;
+            }
+            let waker = task_waker(index);
+            let mut cx = Context::from_waker(&waker);
+            if task.as_mut().poll(&mut cx).is_ready() {
+                done |= 1 << index;
+            }
+        }
+    }
+}
+
+// 在 1 kHz 的中断中调用
+pub(crate) fn on_tick() {
+    SLEEPERS.wake_all();
+}
+
+// 至少等待 ms 毫秒，精度取决于 on_tick 的调用频率
+pub(crate) async fn sleep_ms(ms: u32) {
+    let start = ticker::millis();
+    poll_fn(|cx| {
+        if ticker::millis().wrapping_sub(start) >= ms {
+            Poll::Ready(())
+        } else {
+            SLEEPERS.register(cx);
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+// 让出一次，让其他就绪的任务先运行
+pub(crate) async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx: &mut Context| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
@@ -0,0 +1,188 @@
+//! EXTI 回调
+//!
+//! 有些驱动需要在“某个引脚出现边沿”时得到通知，比如 GPIO 扩展芯片的 INT 输出
+//! 如果每个驱动都自己定义 EXTIx 的中断处理函数，EXTI9_5、EXTI15_10 这种多条线共用一个中断的情况就没法处理了
+//! 因此这里统一定义 EXTI 的中断处理函数，驱动通过 register 把回调函数挂到对应的 EXTI 线上，
+//! 中断发生时，按照 PR 中挂起的线，依次调用对应的回调
+//!
+//! 回调在中断中执行，应该尽量短，通常只是设置一个标识，具体的处理（比如通过 I2C 读取芯片的状态）放到主循环里
+//!
+//! 这里只负责 EXTI 与 SYSCFG 的设置，引脚本身（输入模式、上下拉）需要调用者事先配置好
+//!
+//! 注意：
+//! 1. EXTI0 ~ EXTI3 的中断处理函数已经由 utils::keypad 定义，因此这里只管理 EXTI4 ~ EXTI15
+//! 2. 同一个 EXTI 线只能连接到一个 GPIO 端口，因此编号相同的引脚（比如 PA8 与 PB8）不能同时注册
+
+#![allow(dead_code)]
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use stm32f4xx_hal::pac::{self, interrupt, NVIC};
+
+pub(crate) const FIRST_LINE: u8 = 4;
+pub(crate) const LAST_LINE: u8 = 15;
+
+// 参数为触发中断的 EXTI 线，也就是引脚编号
+pub(crate) type Callback = fn(line: u8);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Port {
+    A = 0,
+    B = 1,
+    C = 2,
+    D = 3,
+    E = 4,
+    F = 5,
+    G = 6,
+    H = 7,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Trigger {
+    Rising,
+    Falling,
+    Both,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum ExtiError {
+    // 引脚编号不在 4 ~ 15 之间
+    Unavailable,
+    // 这个 EXTI 线已经注册过回调了
+    InUse,
+}
+
+static G_CALLBACKS: Mutex<RefCell<[Option<Callback>; 16]>> = Mutex::new(RefCell::new([None; 16]));
+
+pub(crate) fn register(
+    dp: &pac::Peripherals,
+    port: Port,
+    pin: u8,
+    trigger: Trigger,
+    callback: Callback,
+) -> Result<(), ExtiError> {
+    if !(FIRST_LINE..=LAST_LINE).contains(&pin) {
+        return Err(ExtiError::Unavailable);
+    }
+
+    cortex_m::interrupt::free(|cs| {
+        let mut callbacks = G_CALLBACKS.borrow(cs).borrow_mut();
+        let slot = &mut callbacks[pin as usize];
+        if slot.is_some() {
+            return Err(ExtiError::InUse);
+        }
+        *slot = Some(callback);
+        Ok(())
+    })?;
+
+    dp.RCC.apb2enr.modify(|_, w| w.syscfgen().enabled());
+
+    // 每个 EXTICR 寄存器管理 4 个 EXTI 线，每个线 4 bit，值为端口编号
+    let shift = (pin % 4) * 4;
+    let set = |bits: u32| (bits & !(0xF << shift)) | ((port as u32) << shift);
+    match pin / 4 {
+        1 => dp
+            .SYSCFG
+            .exticr2
+            .modify(|r, w| unsafe { w.bits(set(r.bits())) }),
+        2 => dp
+            .SYSCFG
+            .exticr3
+            .modify(|r, w| unsafe { w.bits(set(r.bits())) }),
+        _ => dp
+            .SYSCFG
+            .exticr4
+            .modify(|r, w| unsafe { w.bits(set(r.bits())) }),
+    }
+
+    let line = 1u32 << pin;
+    let (rising, falling) = match trigger {
+        Trigger::Rising => (true, false),
+        Trigger::Falling => (false, true),
+        Trigger::Both => (true, true),
+    };
+    let exti = &dp.EXTI;
+    exti.rtsr.modify(|r, w| unsafe {
+        w.bits(if rising {
+            r.bits() | line
+        } else {
+            r.bits() & !line
+        })
+    });
+    exti.ftsr.modify(|r, w| unsafe {
+        w.bits(if falling {
+            r.bits() | line
+        } else {
+            r.bits() & !line
+        })
+    });
+    exti.pr.write(|w| unsafe { w.bits(line) });
+    exti.imr.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+
+    unsafe {
+        NVIC::unmask(match pin {
+            4 => interrupt::EXTI4,
+            5..=9 => interrupt::EXTI9_5,
+            _ => interrupt::EXTI15_10,
+        })
+    }
+
+    Ok(())
+}
+
+// 屏蔽对应的 EXTI 线，并移除回调
+// 共用的 EXTI9_5、EXTI15_10 中断可能还有其他线在使用，因此这里不去关闭 NVIC 中的中断
+pub(crate) fn unregister(dp: &pac::Peripherals, pin: u8) {
+    if !(FIRST_LINE..=LAST_LINE).contains(&pin) {
+        return;
+    }
+
+    dp.EXTI
+        .imr
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << pin)) });
+
+    cortex_m::interrupt::free(|cs| {
+        G_CALLBACKS.borrow(cs).borrow_mut()[pin as usize] = None;
+    });
+}
+
+// 清理 lines 中挂起的线，并调用各自的回调
+fn dispatch(lines: u32) {
+    let exti = unsafe { &*pac::EXTI::ptr() };
+    let pending = exti.pr.read().bits() & lines;
+    exti.pr.write(|w| unsafe { w.bits(pending) });
+
+    // 先复制一份回调表，这样回调本身不必在临界区中执行
+    let callbacks = cortex_m::interrupt::free(|cs| *G_CALLBACKS.borrow(cs).borrow());
+
+    for line in FIRST_LINE..=LAST_LINE {
+        if pending & (1 << line) == 0 {
+            continue;
+        }
+        if let Some(callback) = callbacks[line as usize] {
+            callback(line);
+        }
+    }
+}
+
+#[interrupt]
+fn EXTI4() {
+    dispatch(1 << 4);
+}
+
+#[interrupt]
+fn EXTI9_5() {
+    dispatch(0b11111 << 5);
+}
+
+#[interrupt]
+fn EXTI15_10() {
+    dispatch(0b111111 << 10);
+}
@@ -0,0 +1,118 @@
+//! 用软件触发 EXTI，在没有外部信号的情况下测试中断的处理路径
+//!
+//! 向 EXTI_SWIER 中为 0 的某一位写 1 时，若这条线在 EXTI_IMR 中没有被屏蔽，EXTI_PR 中对应的位就会被置位，
+//! 效果与引脚上出现了一个有效的边沿完全相同：NVIC 中挂起对应的中断，中断处理函数照常运行
+//! 这与 RTSR/FTSR 中选择的边沿无关，SWIER 中的那一位会在 PR 被清除时一并清除
+//!
+//! 这样按键、RTC 闹钟这些依赖外部事件的逻辑，就可以在板子上自动测试，不需要有人去按按键，也不需要等闹钟
+//!
+//! - fire 只负责触发
+//! - fire_and_wait 触发之后，等待中断处理函数清除 PR，以此确认中断确实被处理了
+//! - check 与 fire_and_wait 相同，只是错误的形式与 utils::selftest 的 TestCase 一致，可以直接在测试函数中使用
+//! - record 可以作为 utils::exti 的回调，配合 hits 检查回调是否被调用了
+//!
+//! 中断处理函数本身的效果（比如设置了某个标识），由测试自己检查
+//!
+//! 注意：
+//! 1. 软件触发的只是 EXTI 的事件，引脚的电平并没有变化，读取引脚电平的逻辑（比如 utils::keypad 的消抖）看到的仍然是真实的电平
+//! 2. RTC 闹钟的中断处理函数通常会检查 RTC_ISR 中的 ALRAF，软件触发时它并没有被置位，处理函数需要能容忍这种情况
+//! 3. 不要在对应的中断处理函数中、或者关闭了中断的临界区中调用 fire_and_wait，中断无法执行，一定会超时
+
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use stm32f4xx_hal::pac;
+
+use super::ticker;
+
+// F413 的 EXTI 共有 24 条线，0 ~ 15 连接 GPIO，其余连接内部的事件
+pub(crate) const LAST_LINE: u8 = 23;
+
+pub(crate) const LINE_PVD: u8 = 16;
+pub(crate) const LINE_RTC_ALARM: u8 = 17;
+pub(crate) const LINE_RTC_WAKEUP: u8 = 22;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum SimError {
+    // 线的编号超过了 LAST_LINE
+    NoSuchLine = 1,
+    // 这条线在 IMR 中被屏蔽了，触发之后 PR 不会被置位，中断也不会发生
+    Masked = 2,
+    // PR 已经被置位，上一次的事件还没有被处理，再次触发不会有任何效果
+    AlreadyPending = 3,
+    // 超时之后 PR 依旧没有被清除，中断没有在 NVIC 中使能，或者处理函数没有清除 PR
+    Timeout = 4,
+}
+
+// 每条线上 record 被调用的次数
+static G_HITS: [AtomicU32; LAST_LINE as usize + 1] =
+    [const { AtomicU32::new(0) }; LAST_LINE as usize + 1];
+
+// 在 line 上产生一个软件事件
+pub(crate) fn fire(line: u8) -> Result<(), SimError> {
+    if line > LAST_LINE {
+        return Err(SimError::NoSuchLine);
+    }
+
+    let exti = unsafe { &*pac::EXTI::ptr() };
+    let mask = 1 << line;
+
+    if exti.imr.read().bits() & mask == 0 {
+        return Err(SimError::Masked);
+    }
+    if exti.pr.read().bits() & mask != 0 {
+        return Err(SimError::AlreadyPending);
+    }
+
+    // 写 0 的位没有影响
+    exti.swier.write(|w| unsafe { w.bits(mask) });
+    Ok(())
+}
+
+// 触发之后等待中断处理函数清除 PR
+// 超时的时候，这里会清除 PR，不让这次事件影响之后的测试
+pub(crate) fn fire_and_wait(line: u8, timeout_ms: u32) -> Result<(), SimError> {
+    fire(line)?;
+
+    let start = ticker::millis();
+    while pending(line) {
+        if ticker::millis().wrapping_sub(start) > timeout_ms {
+            let exti = unsafe { &*pac::EXTI::ptr() };
+            exti.pr.write(|w| unsafe { w.bits(1 << line) });
+            return Err(SimError::Timeout);
+        }
+    }
+    Ok(())
+}
+
+// 用于 TestCase 的运行函数，失败时 detail 为 (线的编号 << 8) | SimError 的值
+pub(crate) fn check(line: u8, timeout_ms: u32) -> Result<(), u32> {
+    fire_and_wait(line, timeout_ms).map_err(|e| (line as u32) << 8 | e as u32)
+}
+
+pub(crate) fn pending(line: u8) -> bool {
+    let exti = unsafe { &*pac::EXTI::ptr() };
+    line <= LAST_LINE && exti.pr.read().bits() & (1 << line) != 0
+}
+
+// 可以直接作为 utils::exti 的回调
+pub(crate) fn record(line: u8) {
+    if let Some(hits) = G_HITS.get(line as usize) {
+        hits.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn hits(line: u8) -> u32 {
+    G_HITS
+        .get(line as usize)
+        .map_or(0, |hits| hits.load(Ordering::Relaxed))
+}
+
+pub(crate) fn reset_hits() {
+    for hits in G_HITS.iter() {
+        hits.store(0, Ordering::Relaxed);
+    }
+}
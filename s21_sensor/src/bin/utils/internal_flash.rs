@@ -0,0 +1,129 @@
+//! 芯片内部的 Flash，作为 utils::datalog / utils::config 的存储
+//!
+//! STM32F413 的 1.5 MB Flash 分为 16 个扇区：0~3 为 16 KB，4 为 64 KB，5~15 为 128 KB
+//! 扇区大小不一致，LogFlash 却只有一个 SECTOR_SIZE，因此这里只开放从 0x2_0000 开始、大小均为 128 KB 的扇区 5~15
+//!
+//! memory.x 只给程序分配了前 512 KB（扇区 0~7），地址从 0x8_0000 开始的扇区 8 及之后，不会被烧录程序覆盖
+//!
+//! 地址为相对于 0x0800_0000 的偏移，读取直接访问映射到内存中的 Flash，写入与擦除的流程为：
+//!
+//! 1. 向 FLASH_KEYR 依次写入 0x45670123 与 0xCDEF89AB，解锁 FLASH_CR
+//! 2. 写入：设置 PSIZE 与 PG，像写 RAM 一样写入，每写一次等待 BSY 为 0
+//!    擦除：设置 SER 与 SNB，置位 STRT
+//! 3. 置位 LOCK，重新上锁
+//!
+//! PSIZE 决定一次写入的宽度，x8 在任何供电电压下都可以使用，虽然最慢，但对于只有几十字节的配置来说足够了
+//!
+//! 注意：擦除一个 128 KB 的扇区需要 1~2 秒，擦除与写入期间读取 Flash（包括取指令）都会被暂停，
+//! 中断处理函数也不例外，因此不要在有严格时间要求的时候写入
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::FLASH;
+
+use super::datalog::LogFlash;
+
+const BASE: u32 = 0x0800_0000;
+
+// 扇区 5 的起始偏移，之后的扇区都是 128 KB
+const FIRST_UNIFORM: u32 = 0x2_0000;
+const FIRST_UNIFORM_SECTOR: u32 = 5;
+
+pub(crate) struct InternalFlash<'a> {
+    flash: &'a FLASH,
+}
+
+impl<'a> InternalFlash<'a> {
+    pub(crate) fn new(flash: &'a FLASH) -> Self {
+        Self { flash }
+    }
+
+    fn unlock(&self) {
+        let flash = self.flash;
+        if flash.cr.read().lock().bit_is_set() {
+            flash.keyr.write(|w| w.key().bits(0x4567_0123));
+            flash.keyr.write(|w| w.key().bits(0xCDEF_89AB));
+        }
+        // 清除上一次操作留下的错误标志，否则新的操作不会开始
+        flash.sr.write(|w| {
+            w.pgserr().set_bit();
+            w.pgperr().set_bit();
+            w.pgaerr().set_bit();
+            w.wrperr().set_bit();
+            w.operr().set_bit();
+            w
+        });
+    }
+
+    fn lock(&self) {
+        self.flash.cr.modify(|_, w| w.lock().set_bit());
+    }
+
+    // 擦除与写入之后，ART 的数据缓存中可能还留着旧的内容
+    fn flush_data_cache(&self) {
+        let acr = &self.flash.acr;
+        acr.modify(|_, w| w.dcen().clear_bit());
+        acr.modify(|_, w| w.dcrst().set_bit());
+        acr.modify(|_, w| w.dcrst().clear_bit());
+        acr.modify(|_, w| w.dcen().set_bit());
+    }
+}
+
+impl LogFlash for InternalFlash<'_> {
+    const SECTOR_SIZE: u32 = 128 * 1024;
+    // 内部 Flash 没有页的概念，这里只是限制一次 program 的长度
+    const PAGE_SIZE: u32 = 256;
+
+    fn wait_idle(&mut self) {
+        while self.flash.sr.read().bsy().bit_is_set() {}
+        if self.flash.cr.read().ser().bit_is_set() {
+            self.flash.cr.modify(|_, w| w.ser().clear_bit());
+            self.lock();
+            self.flush_data_cache();
+        }
+    }
+
+    fn read(&mut self, addr: u32, buf: &mut [u8]) {
+        self.wait_idle();
+        for (offset, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile((BASE + addr + offset as u32) as *const u8) };
+        }
+    }
+
+    fn program(&mut self, addr: u32, data: &[u8]) {
+        self.wait_idle();
+        self.unlock();
+
+        self.flash.cr.modify(|_, w| {
+            w.psize().bits(0b00);
+            w.pg().set_bit();
+            w
+        });
+        for (offset, &byte) in data.iter().enumerate() {
+            unsafe { core::ptr::write_volatile((BASE + addr + offset as u32) as *mut u8, byte) };
+            while self.flash.sr.read().bsy().bit_is_set() {}
+        }
+        self.flash.cr.modify(|_, w| w.pg().clear_bit());
+
+        self.lock();
+        self.flush_data_cache();
+    }
+
+    // 只能擦除扇区 5 及之后的扇区，更靠前的地址会被忽略
+    fn start_erase(&mut self, addr: u32) {
+        if addr < FIRST_UNIFORM {
+            return;
+        }
+        let sector = FIRST_UNIFORM_SECTOR + (addr - FIRST_UNIFORM) / Self::SECTOR_SIZE;
+
+        self.wait_idle();
+        self.unlock();
+
+        self.flash.cr.modify(|_, w| unsafe {
+            w.ser().set_bit();
+            w.snb().bits(sector as u8)
+        });
+        // 擦除完成之后，wait_idle 会清除 SER 并重新上锁
+        self.flash.cr.modify(|_, w| w.strt().set_bit());
+    }
+}
@@ -0,0 +1,189 @@
+//! 四个独立按键，通过 EXTI 检测，在主循环中消抖
+//!
+//! 按键接在 PC0~PC3 上，按下时接地，使用内部上拉，正好对应 EXTI0~EXTI3，各自有独立的中断
+//!
+//! 机械按键按下与松开时都会抖动若干毫秒，如果在中断里直接判定按键，一次按下可能会被当作好几次
+//! 因此中断里只记下“最后一次跳变发生的时刻”，由 Keypad::poll 在跳变之后安静了 DEBOUNCE_MS 毫秒，再去读取引脚的电平
+//! 这样中断本身非常短，按键也不需要一直占用一个定时器
+//!
+//! 按住 Prev/Next 超过 REPEAT_DELAY_MS 后，每隔 REPEAT_PERIOD_MS 产生一次 Repeat 事件，方便快速调整数值
+//!
+//! 接线图：
+//!
+//! PC0 <-> 按键 Prev  <-> GND
+//! PC1 <-> 按键 Next  <-> GND
+//! PC2 <-> 按键 Enter <-> GND
+//! PC3 <-> 按键 Back  <-> GND
+
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+use stm32f4xx_hal::pac::{self, interrupt, NVIC};
+
+use super::ticker;
+
+const DEBOUNCE_MS: u32 = 20;
+const REPEAT_DELAY_MS: u32 = 500;
+const REPEAT_PERIOD_MS: u32 = 150;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Key {
+    Prev = 0,
+    Next = 1,
+    Enter = 2,
+    Back = 3,
+}
+
+const KEYS: [Key; 4] = [Key::Prev, Key::Next, Key::Enter, Key::Back];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum KeyEvent {
+    Press(Key),
+    // 按住不放时自动重复，只有 Prev 和 Next 会产生
+    Repeat(Key),
+}
+
+// 每个按键最后一次跳变的时刻
+static G_EDGE_MS: [AtomicU32; 4] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+// 有跳变、尚未被 poll 处理的按键，每个按键一个 bit
+static G_PENDING: AtomicU8 = AtomicU8::new(0);
+
+pub(crate) fn setup(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpiocen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.syscfgen().enabled());
+
+    let gpioc = &dp.GPIOC;
+    gpioc.pupdr.modify(|_, w| {
+        w.pupdr0().pull_up();
+        w.pupdr1().pull_up();
+        w.pupdr2().pull_up();
+        w.pupdr3().pull_up();
+        w
+    });
+    gpioc.moder.modify(|_, w| {
+        w.moder0().input();
+        w.moder1().input();
+        w.moder2().input();
+        w.moder3().input();
+        w
+    });
+
+    // EXTI0~EXTI3 都选择 Port C
+    dp.SYSCFG.exticr1.modify(|_, w| unsafe {
+        w.exti0().bits(2);
+        w.exti1().bits(2);
+        w.exti2().bits(2);
+        w.exti3().bits(2);
+        w
+    });
+
+    // 按下与松开都要检测，因此上升沿与下降沿都触发
+    const LINES: u32 = 0b1111;
+    let exti = &dp.EXTI;
+    exti.rtsr.modify(|r, w| unsafe { w.bits(r.bits() | LINES) });
+    exti.ftsr.modify(|r, w| unsafe { w.bits(r.bits() | LINES) });
+    exti.pr.write(|w| unsafe { w.bits(LINES) });
+    exti.imr.modify(|r, w| unsafe { w.bits(r.bits() | LINES) });
+
+    unsafe {
+        NVIC::unmask(interrupt::EXTI0);
+        NVIC::unmask(interrupt::EXTI1);
+        NVIC::unmask(interrupt::EXTI2);
+        NVIC::unmask(interrupt::EXTI3);
+    }
+}
+
+fn on_edge(line: usize) {
+    let exti = unsafe { &*pac::EXTI::ptr() };
+    exti.pr.write(|w| unsafe { w.bits(1 << line) });
+
+    G_EDGE_MS[line].store(ticker::millis(), Ordering::Relaxed);
+    G_PENDING.fetch_or(1 << line, Ordering::Release);
+}
+
+#[interrupt]
+fn EXTI0() {
+    on_edge(0);
+}
+
+#[interrupt]
+fn EXTI1() {
+    on_edge(1);
+}
+
+#[interrupt]
+fn EXTI2() {
+    on_edge(2);
+}
+
+#[interrupt]
+fn EXTI3() {
+    on_edge(3);
+}
+
+// 按键按下时引脚为低电平
+fn is_down(key: Key) -> bool {
+    let gpioc = unsafe { &*pac::GPIOC::ptr() };
+    gpioc.idr.read().bits() & (1 << key as u32) == 0
+}
+
+pub(crate) struct Keypad {
+    // 消抖之后的按键状态
+    down: [bool; 4],
+    // 下一次自动重复的时刻
+    next_repeat_ms: [u32; 4],
+}
+
+impl Keypad {
+    pub(crate) fn new() -> Self {
+        Self {
+            down: KEYS.map(is_down),
+            next_repeat_ms: [0; 4],
+        }
+    }
+
+    // 一次最多返回一个事件，主循环应当每一轮都调用
+    pub(crate) fn poll(&mut self, now_ms: u32) -> Option<KeyEvent> {
+        for key in KEYS {
+            let index = key as usize;
+            let mask = 1 << index;
+
+            if G_PENDING.load(Ordering::Acquire) & mask != 0 {
+                let edge_ms = G_EDGE_MS[index].load(Ordering::Relaxed);
+                if now_ms.wrapping_sub(edge_ms) < DEBOUNCE_MS {
+                    continue;
+                }
+
+                // 先清除标志再读电平，若读电平之后又有跳变，中断会重新设置标志，不会漏掉
+                G_PENDING.fetch_and(!mask, Ordering::AcqRel);
+                let down = is_down(key);
+                if down == self.down[index] {
+                    continue;
+                }
+                self.down[index] = down;
+
+                if down {
+                    self.next_repeat_ms[index] = now_ms.wrapping_add(REPEAT_DELAY_MS);
+                    return Some(KeyEvent::Press(key));
+                }
+            } else if self.down[index]
+                && matches!(key, Key::Prev | Key::Next)
+                && (now_ms.wrapping_sub(self.next_repeat_ms[index]) as i32) >= 0
+            {
+                self.next_repeat_ms[index] = now_ms.wrapping_add(REPEAT_PERIOD_MS);
+                return Some(KeyEvent::Repeat(key));
+            }
+        }
+        None
+    }
+}
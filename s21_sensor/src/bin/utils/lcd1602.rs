@@ -0,0 +1,178 @@
+//! 4 bit 模式的 LCD1602，作为 sensor::sink::TextPanel 使用
+//!
+//! 驱动本身就是 s11 的那一份（见工作区中的 lcd1602），这里只是把它包装为 TextPanel，并提供两种接法：
+//!
+//! - DirectBus：直接接在 GPIO 上，也就是 s11 中为 pac::Peripherals 实现的 PinIo，Lcd1602::new 使用的就是它，
+//!   接线图与 s11c02 一致，RW 也接在 GPIO 上，因此与 s11 一样通过 busy flag 等待指令执行完成：
+//!
+//!   A0/A1/A2 RS/RW/E
+//!   B4~B7    D4~D7
+//!
+//! - ShiftBus：通过 74HC595 背板，只需要 SCK、MOSI、RCLK 三根线（见 utils::shift_reg），
+//!   595 的 QA ~ QH 与常见的 PCF8574 I2C 背板的 P0 ~ P7 顺序相同：RS、RW、E、背光、D4 ~ D7
+//!   595 只能输出，读不回 busy flag，PinIo::READABLE 为 false，每条指令之后等待足够长的时间
+
+#![allow(dead_code)]
+
+use lcd1602::{
+    common::DelayUs,
+    pins::{init_with, Bus, DataWidth, Font, LineMode, PinIo, Pins},
+};
+use stm32f4xx_hal::{hal::digital::OutputPin, pac};
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+use super::delay::Delay;
+use super::{
+    sensor::sink::TextPanel,
+    shift_reg::{Link, OutputChain},
+};
+
+// 我们假设 CPU 运行在 12 MHz 的 HSE 上
+const CYCLES_PER_US: u32 = 12;
+
+// 若程序中已经设置了 utils::delay，就使用它，与时钟频率无关；否则按照 CPU 周期估算
+// utils::delay 用的是 TIM6，没有 TIM6 的芯片上总是按照 CPU 周期估算
+// s11 的初始化用 SysTick 延时，而 s21c14 把 SysTick 用作了 1 ms 的中断，因此这里不能使用它
+fn delay_us(us: u32) {
+    #[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+    if let Some(delay) = Delay::get() {
+        delay.us(us);
+        return;
+    }
+    cortex_m::asm::delay(us * CYCLES_PER_US)
+}
+
+struct Wait;
+
+impl DelayUs for Wait {
+    fn delay_us(&self, micro_sec: u32) {
+        delay_us(micro_sec);
+    }
+}
+
+pub(crate) trait LcdBus: PinIo {
+    // 没有背光控制的接法什么都不做
+    fn set_backlight(&self, _on: bool) {}
+}
+
+// RS/RW/E 接在 PA0/PA1/PA2，D4~D7 接在 PB4~PB7
+pub(crate) type DirectBus = pac::Peripherals;
+
+impl LcdBus for DirectBus {}
+
+// 74HC595 背板各个输出的位置
+const SHIFT_RS: u8 = 1 << 0;
+const SHIFT_RW: u8 = 1 << 1;
+const SHIFT_E: u8 = 1 << 2;
+const SHIFT_BACKLIGHT: u8 = 1 << 3;
+// D4 ~ D7 正好在 QE ~ QH 上，与 PinIo 中 PB4 ~ PB7 的位置相同
+const SHIFT_DATA: u8 = 0b1111_0000;
+
+// 背板是输出链上的第 chip 片，链上其他的 595 可以照常使用
+//
+// PinIo 的每次调用都是一次移位，E 的上升沿与数据之间自然有一次移位的间隔
+pub(crate) struct ShiftBus<'c, L, RCLK, const N: usize> {
+    chain: &'c OutputChain<L, RCLK, N>,
+    chip: usize,
+}
+
+impl<'c, L: Link, RCLK: OutputPin, const N: usize> ShiftBus<'c, L, RCLK, N> {
+    pub(crate) fn new(chain: &'c OutputChain<L, RCLK, N>, chip: usize) -> Self {
+        Self { chain, chip }
+    }
+
+    fn modify(&self, mask: u8, value: u8) {
+        self.chain.modify_chip(self.chip, mask, value).ok();
+    }
+}
+
+impl<L: Link, RCLK: OutputPin, const N: usize> PinIo for ShiftBus<'_, L, RCLK, N> {
+    const READABLE: bool = false;
+
+    fn set_rs(&self, high: bool) {
+        self.modify(SHIFT_RS, if high { SHIFT_RS } else { 0 });
+    }
+
+    fn set_rw(&self, high: bool) {
+        self.modify(SHIFT_RW, if high { SHIFT_RW } else { 0 });
+    }
+
+    fn set_e(&self, high: bool) {
+        self.modify(SHIFT_E, if high { SHIFT_E } else { 0 });
+    }
+
+    fn write_dbus(&self, mask: u8, data: u8) {
+        self.modify(mask & SHIFT_DATA, data);
+    }
+
+    fn read_dbus(&self) -> u8 {
+        unreachable!("74HC595 backpack is write only")
+    }
+
+    fn set_dbus_input(&self, _mask: u8, _input: bool) {
+        unreachable!("74HC595 backpack is write only")
+    }
+
+    fn wait_us(&self, micro_sec: u32) {
+        delay_us(micro_sec);
+    }
+}
+
+impl<L: Link, RCLK: OutputPin, const N: usize> LcdBus for ShiftBus<'_, L, RCLK, N> {
+    fn set_backlight(&self, on: bool) {
+        self.modify(SHIFT_BACKLIGHT, if on { SHIFT_BACKLIGHT } else { 0 });
+    }
+}
+
+pub(crate) struct Lcd1602<B: LcdBus = DirectBus> {
+    io: B,
+    bus: Bus<B>,
+}
+
+impl Lcd1602<DirectBus> {
+    pub(crate) fn new(dp: &pac::Peripherals) -> Self {
+        <Pins<4> as DataWidth>::setup_gpio(dp);
+
+        // PinIo 只会用到 GPIOA 与 GPIOB 上 LCD 的那几个引脚，因此另外拿一份 Peripherals 给它，不影响程序中的 dp
+        Self::with_bus(unsafe { pac::Peripherals::steal() })
+    }
+}
+
+impl<B: LcdBus> Lcd1602<B> {
+    pub(crate) fn with_bus(io: B) -> Self {
+        // 初始化流程与 s11c02 相同
+        init_with::<Pins<4>, _>(&io, &Wait, LineMode::TwoLine, Font::Font5x8);
+
+        Self {
+            io,
+            bus: <Pins<4> as DataWidth>::bus(),
+        }
+    }
+
+    pub(crate) fn command(&self, cmd: u8) {
+        self.bus.command(&self.io, cmd);
+    }
+
+    pub(crate) fn set_cursor(&self, row: u8, col: u8) {
+        self.command(0b1000_0000 | (row * 0x40 + col));
+    }
+
+    pub(crate) fn write_byte(&self, data: u8) {
+        self.bus.write_data(&self.io, data);
+    }
+
+    pub(crate) fn set_backlight(&self, on: bool) {
+        self.io.set_backlight(on);
+    }
+}
+
+impl<B: LcdBus> TextPanel for Lcd1602<B> {
+    const COLUMNS: usize = 16;
+
+    fn write_line(&mut self, row: u8, text: &[u8]) {
+        self.set_cursor(row, 0);
+        for col in 0..Self::COLUMNS {
+            self.write_byte(text.get(col).copied().unwrap_or(b' '));
+        }
+    }
+}
@@ -0,0 +1,349 @@
+//! 带缓冲的日志：按模块过滤，运行时调整级别
+//!
+//! rprintln 直接写 RTT，在中断里调用时，格式化与复制都在中断中完成；打印得多了，中断就被拖长了，
+//! 而调试时真正想看的往往只是某一个模块（比如 I2C）的详细输出，其他模块保持安静即可
+//!
+//! 这里的做法类似 ITM 的 printf：
+//!
+//! - 每条日志属于一个模块（Module），每个模块有自己的级别，低于（比级别更详细的）日志直接丢弃
+//! - 判断是否输出只是一次原子读取与比较，不需要的日志连格式化都不会发生，中断中可以放心地写 debug 甚至 trace
+//! - 需要输出的日志先格式化到栈上，再复制进一个环形缓冲，不等待输出；缓冲满了就丢弃新的日志，并计数
+//! - 主循环空闲时调用 drain，把缓冲中的日志写到 RTT（或者任何实现了 fmt::Write 的地方）
+//!
+//! 模块的编号就是 Module 的值，新增模块时在 Module 中加一项，并在 ALL 与 name 中补上即可，
+//! 各模块的级别保存在一个按编号索引的原子数组中
+//!
+//! 级别可以在命令行中修改（见 command），也可以保存到 utils::settings 中，下次开机时由 load_levels 读回：
+//!
+//! log                          列出各模块的级别，以及缓冲丢弃的日志数
+//! log set <module|all> <level> 修改级别，比如 log set i2c debug
+//! log save                     把当前的级别写入 Flash
+//!
+//! 设置的值是 4 个 f32，f32 可以精确表示 24 bit 以内的整数，每个 f32 保存 6 个模块，每个模块 4 bit
+
+#![allow(dead_code)]
+
+use core::{
+    cell::RefCell,
+    fmt::{self, Write},
+    sync::atomic::{AtomicU32, AtomicU8, Ordering},
+};
+
+use cortex_m::interrupt::Mutex;
+
+use super::{
+    datalog::LogFlash,
+    sensor::sink::LineBuf,
+    settings::{SettingsError, SettingsStore, VALUE_COUNT},
+    ticker,
+};
+
+// 一条日志最多保存的字节数，超出的部分被截掉
+pub(crate) const MESSAGE_LEN: usize = 48;
+// 缓冲中最多保存的日志条数
+pub(crate) const CAPACITY: usize = 32;
+
+const SETTINGS_KEY: &str = "log";
+const MODULES_PER_VALUE: usize = 6;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Level {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        [
+            Level::Off,
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ]
+        .into_iter()
+        .find(|level| level.name() == name)
+    }
+
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(Level::Off),
+            1 => Some(Level::Error),
+            2 => Some(Level::Warn),
+            3 => Some(Level::Info),
+            4 => Some(Level::Debug),
+            5 => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+// 日志所属的模块，值即是编号，用作 LEVELS 的下标
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Module {
+    Main = 0,
+    I2c = 1,
+    Spi = 2,
+    Usart = 3,
+    Exti = 4,
+    Timer = 5,
+    Sensor = 6,
+    Flash = 7,
+    Shell = 8,
+}
+
+impl Module {
+    pub(crate) const COUNT: usize = 9;
+
+    pub(crate) const ALL: [Module; Self::COUNT] = [
+        Module::Main,
+        Module::I2c,
+        Module::Spi,
+        Module::Usart,
+        Module::Exti,
+        Module::Timer,
+        Module::Sensor,
+        Module::Flash,
+        Module::Shell,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Module::Main => "main",
+            Module::I2c => "i2c",
+            Module::Spi => "spi",
+            Module::Usart => "usart",
+            Module::Exti => "exti",
+            Module::Timer => "timer",
+            Module::Sensor => "sensor",
+            Module::Flash => "flash",
+            Module::Shell => "shell",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|module| module.name() == name)
+    }
+}
+
+// 设置中最多能保存的模块数
+const _: () = assert!(Module::COUNT <= VALUE_COUNT * MODULES_PER_VALUE);
+
+pub(crate) const DEFAULT_LEVEL: Level = Level::Info;
+
+static LEVELS: [AtomicU8; Module::COUNT] =
+    [const { AtomicU8::new(DEFAULT_LEVEL as u8) }; Module::COUNT];
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Copy)]
+struct Record {
+    millis: u32,
+    module: Module,
+    level: Level,
+    len: u8,
+    text: [u8; MESSAGE_LEN],
+}
+
+struct Ring {
+    records: [Option<Record>; CAPACITY],
+    // 下一条要读出的位置与缓冲中的条数
+    tail: usize,
+    len: usize,
+}
+
+static RING: Mutex<RefCell<Ring>> = Mutex::new(RefCell::new(Ring {
+    records: [None; CAPACITY],
+    tail: 0,
+    len: 0,
+}));
+
+// 是否需要输出，由 log! 在格式化之前调用
+#[inline(always)]
+pub(crate) fn enabled(module: Module, level: Level) -> bool {
+    level as u8 <= LEVELS[module as usize].load(Ordering::Relaxed)
+}
+
+pub(crate) fn level(module: Module) -> Level {
+    Level::from_bits(LEVELS[module as usize].load(Ordering::Relaxed)).unwrap_or(DEFAULT_LEVEL)
+}
+
+pub(crate) fn set_level(module: Module, level: Level) {
+    LEVELS[module as usize].store(level as u8, Ordering::Relaxed);
+}
+
+pub(crate) fn set_all(level: Level) {
+    for module in Module::ALL {
+        set_level(module, level);
+    }
+}
+
+// 缓冲满了被丢弃的日志数
+pub(crate) fn dropped() -> u32 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+// 由 log! 调用，格式化在临界区之外进行，临界区中只复制一条 Record
+pub(crate) fn push(module: Module, level: Level, args: fmt::Arguments) {
+    let mut line = LineBuf::<MESSAGE_LEN>::new();
+    line.write_fmt(args).ok();
+    let bytes = line.as_bytes();
+
+    let mut record = Record {
+        millis: ticker::millis(),
+        module,
+        level,
+        len: bytes.len() as u8,
+        text: [0; MESSAGE_LEN],
+    };
+    record.text[..bytes.len()].copy_from_slice(bytes);
+
+    let pushed = cortex_m::interrupt::free(|cs| {
+        let mut ring = RING.borrow(cs).borrow_mut();
+        if ring.len == CAPACITY {
+            return false;
+        }
+        let index = (ring.tail + ring.len) % CAPACITY;
+        ring.records[index] = Some(record);
+        ring.len += 1;
+        true
+    });
+    if !pushed {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn pop() -> Option<Record> {
+    cortex_m::interrupt::free(|cs| {
+        let mut ring = RING.borrow(cs).borrow_mut();
+        if ring.len == 0 {
+            return None;
+        }
+        let tail = ring.tail;
+        ring.tail = (tail + 1) % CAPACITY;
+        ring.len -= 1;
+        ring.records[tail].take()
+    })
+}
+
+// 在主循环中调用，最多输出 max 条日志，返回实际输出的条数
+// 每次只取出一条，输出时不关中断，输出慢也不影响中断中继续写日志
+pub(crate) fn drain(w: &mut impl Write, max: usize) -> Result<usize, fmt::Error> {
+    let mut count = 0;
+    while count < max {
+        let Some(record) = pop() else {
+            break;
+        };
+        let text = core::str::from_utf8(&record.text[..record.len as usize]).unwrap_or("?");
+        writeln!(
+            w,
+            "{:>8} {:<5} {:<6} {}",
+            record.millis,
+            record.level.name(),
+            record.module.name(),
+            text
+        )?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+// 从设置中读回各模块的级别，没有保存过时保持默认值
+pub(crate) fn load_levels<F: LogFlash>(store: &SettingsStore<F>) {
+    let Some(values) = store.get(SETTINGS_KEY) else {
+        return;
+    };
+    for module in Module::ALL {
+        let index = module as usize;
+        let packed = values[index / MODULES_PER_VALUE] as u32;
+        let bits = (packed >> (index % MODULES_PER_VALUE * 4)) & 0xF;
+        if let Some(level) = Level::from_bits(bits as u8) {
+            set_level(module, level);
+        }
+    }
+}
+
+// 只修改 RAM 中的设置，写入 Flash 需要调用 store.commit
+pub(crate) fn store_levels<F: LogFlash>(store: &mut SettingsStore<F>) -> Result<(), SettingsError> {
+    let mut packed = [0u32; VALUE_COUNT];
+    for module in Module::ALL {
+        let index = module as usize;
+        packed[index / MODULES_PER_VALUE] |=
+            (level(module) as u32) << (index % MODULES_PER_VALUE * 4);
+    }
+    store.set(SETTINGS_KEY, packed.map(|bits| bits as f32))
+}
+
+pub(crate) fn write_levels(w: &mut impl Write) -> fmt::Result {
+    for module in Module::ALL {
+        writeln!(w, "{:<6} {}", module.name(), level(module).name())?;
+    }
+    writeln!(w, "dropped {}", dropped())
+}
+
+// 处理 log 之后的部分，结果写入 w，命令不认识时返回 false，由命令行自己提示
+pub(crate) fn command<'a, F: LogFlash>(
+    mut args: impl Iterator<Item = &'a str>,
+    store: &mut SettingsStore<F>,
+    w: &mut impl Write,
+) -> Result<bool, fmt::Error> {
+    match (args.next(), args.next(), args.next(), args.next()) {
+        (None, _, _, _) => write_levels(w)?,
+        (Some("set"), Some(name), Some(level), None) => {
+            let Some(level) = Level::from_name(level) else {
+                writeln!(w, "levels: off error warn info debug trace")?;
+                return Ok(true);
+            };
+            if name == "all" {
+                set_all(level);
+            } else if let Some(module) = Module::from_name(name) {
+                set_level(module, level);
+            } else {
+                writeln!(w, "no such module")?;
+                return Ok(true);
+            }
+            writeln!(w, "{} = {}", name, level.name())?;
+        }
+        (Some("save"), None, _, _) => match store_levels(store).and_then(|_| store.commit()) {
+            Ok(()) => writeln!(w, "saved, generation {}", store.generation())?,
+            Err(e) => writeln!(w, "save failed: {}", e.name())?,
+        },
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+// 按模块与级别过滤的日志，用法与 write! 相同：
+// log!(Module::I2c, Level::Debug, "read {:#04X}", address);
+// 级别不够时，参数不会被求值，也不会被格式化
+#[allow(unused_macros)]
+macro_rules! log {
+    ($module:expr, $level:expr, $($arg:tt)+) => {{
+        let (module, level) = ($module, $level);
+        if $crate::utils::log::enabled(module, level) {
+            $crate::utils::log::push(module, level, format_args!($($arg)+));
+        }
+    }};
+}
+
+#[allow(unused_imports)]
+pub(crate) use log;
@@ -0,0 +1,501 @@
+//! MAX30102 脉搏血氧传感器（I2C），FIFO 由中断驱动读出
+//!
+//! 芯片内有红光（LED1，660 nm）与红外（LED2，880 nm）两颗 LED，以及一个 18 bit 的光电 ADC：
+//!
+//! - 工作模式：HeartRate 只点亮红光，SpO2 交替点亮红光与红外，MultiLed 由 4 个 slot 决定每个采样周期依次点亮哪颗 LED
+//! - 每颗 LED 的电流为 0 ~ 51 mA，0.2 mA 一档；电流越大信号越强，但也越耗电、越容易让 ADC 饱和
+//! - 采样率（SR）、脉宽（PW，决定 ADC 的分辨率）、ADC 的量程在 SPO2_CONFIG 中设置，
+//!   采样率与脉宽不是任意组合都可以，脉宽越长，能用的采样率越低（见 datasheet 的 Table 11、12）
+//! - 芯片内部可以先把若干个采样平均之后再放入 FIFO（SMP_AVE），FIFO 的实际采样率为 SR / 平均个数
+//!
+//! FIFO 深度为 32 个采样，每个采样按 slot 的顺序（SpO2 模式下为红光、红外）每颗 LED 3 个字节，
+//! 18 bit 的数据左对齐，低分辨率时低位为 0
+//! FIFO_A_FULL 设置“FIFO 还剩多少个空位时”拉低 INT，这就是水位（watermark）；
+//! INT 为开漏输出、低电平有效，通过 utils::exti 注册 on_interrupt 作为回调，中断里只设置一个标识，
+//! 主循环调用 service 时才通过 I2C 读取状态，把 FIFO 中的采样全部读出，放入驱动内部的环形缓冲区，之后用 pop 逐个取出
+//!
+//! 芯片的温度（die temperature）会影响 LED 的波长，从而影响 SpO2 的换算，start_temperature 启动一次测量，
+//! 完成时同样会拉低 INT，service 会顺便读出温度，见 temperature
+//!
+//! I2C 外设需要事先配置好（见 s21c02），地址固定为 0x57，最高 400 kHz
+
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use stm32f4xx_hal::pac::i2c1::RegisterBlock;
+
+use super::{addressing::I2cAddress, blocking_master, sensor::SensorError};
+
+pub(crate) const ADDR: u8 = 0x57;
+pub(crate) const PART_ID: u8 = 0x15;
+
+pub(crate) const FIFO_DEPTH: usize = 32;
+// 18 bit ADC 的满量程
+pub(crate) const ADC_MAX: u32 = (1 << 18) - 1;
+
+// 驱动内部环形缓冲区的长度，比 FIFO 大一倍，主循环偶尔来不及 pop 也不会丢
+const RING_LEN: usize = 64;
+// 一次 I2C 读出的采样个数，FIFO_DATA 不会自动递增地址，分几次读出与一次读出的效果相同
+const CHUNK: usize = 8;
+
+const REG_INT_STATUS_1: u8 = 0x00;
+const REG_INT_STATUS_2: u8 = 0x01;
+const REG_INT_ENABLE_1: u8 = 0x02;
+const REG_INT_ENABLE_2: u8 = 0x03;
+const REG_FIFO_WR_PTR: u8 = 0x04;
+const REG_OVF_COUNTER: u8 = 0x05;
+const REG_FIFO_RD_PTR: u8 = 0x06;
+const REG_FIFO_DATA: u8 = 0x07;
+const REG_FIFO_CONFIG: u8 = 0x08;
+const REG_MODE_CONFIG: u8 = 0x09;
+const REG_SPO2_CONFIG: u8 = 0x0A;
+const REG_LED1_PA: u8 = 0x0C;
+const REG_LED2_PA: u8 = 0x0D;
+const REG_MULTI_LED_1: u8 = 0x11;
+const REG_MULTI_LED_2: u8 = 0x12;
+const REG_TEMP_INT: u8 = 0x1F;
+const REG_TEMP_FRAC: u8 = 0x20;
+const REG_TEMP_CONFIG: u8 = 0x21;
+const REG_PART_ID: u8 = 0xFF;
+
+// INT_STATUS_1 / INT_ENABLE_1
+const INT_A_FULL: u8 = 1 << 7;
+const INT_PPG_RDY: u8 = 1 << 6;
+const INT_ALC_OVF: u8 = 1 << 5;
+// INT_STATUS_2 / INT_ENABLE_2
+const INT_DIE_TEMP_RDY: u8 = 1 << 1;
+
+const MODE_SHDN: u8 = 1 << 7;
+const MODE_RESET: u8 = 1 << 6;
+const FIFO_ROLLOVER_EN: u8 = 1 << 4;
+
+// LED 电流每一档 0.2 mA
+const LED_MA_PER_STEP: f32 = 0.2;
+
+// 复位之后 RESET 位自动清零，一般不超过 1 ms
+const RESET_POLLS: u32 = 100;
+
+// 芯片拉低了 INT，尚未被 service 处理
+static G_INT_PENDING: AtomicBool = AtomicBool::new(false);
+
+// 注册到 utils::exti 的回调
+pub(crate) fn on_interrupt(_line: u8) {
+    G_INT_PENDING.store(true, Ordering::Release);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Mode {
+    // 只有红光
+    HeartRate = 0b010,
+    // 红光 + 红外
+    Spo2 = 0b011,
+    // 由 slot 决定
+    MultiLed = 0b111,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Slot {
+    None = 0,
+    Red = 1,
+    Ir = 2,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum SampleRate {
+    Sps50,
+    Sps100,
+    Sps200,
+    Sps400,
+    Sps800,
+    Sps1000,
+    Sps1600,
+    Sps3200,
+}
+
+impl SampleRate {
+    pub(crate) fn hz(self) -> u32 {
+        match self {
+            SampleRate::Sps50 => 50,
+            SampleRate::Sps100 => 100,
+            SampleRate::Sps200 => 200,
+            SampleRate::Sps400 => 400,
+            SampleRate::Sps800 => 800,
+            SampleRate::Sps1000 => 1000,
+            SampleRate::Sps1600 => 1600,
+            SampleRate::Sps3200 => 3200,
+        }
+    }
+}
+
+// 放入 FIFO 之前，芯片内部平均的采样个数
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Averaging {
+    X1,
+    X2,
+    X4,
+    X8,
+    X16,
+    X32,
+}
+
+impl Averaging {
+    pub(crate) fn count(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+// LED 的脉宽，同时决定 ADC 的分辨率
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum PulseWidth {
+    // 69 us，15 bit
+    Us69,
+    // 118 us，16 bit
+    Us118,
+    // 215 us，17 bit
+    Us215,
+    // 411 us，18 bit
+    Us411,
+}
+
+// ADC 的满量程电流
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum AdcRange {
+    Na2048,
+    Na4096,
+    Na8192,
+    Na16384,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Config {
+    pub(crate) mode: Mode,
+    // MultiLed 模式下使用，其他模式忽略
+    pub(crate) slots: [Slot; 4],
+    pub(crate) sample_rate: SampleRate,
+    pub(crate) averaging: Averaging,
+    pub(crate) pulse_width: PulseWidth,
+    pub(crate) adc_range: AdcRange,
+    // 单位 mA
+    pub(crate) red_ma: f32,
+    pub(crate) ir_ma: f32,
+    // FIFO 中有这么多个采样时拉低 INT，17 ~ 32（FIFO_A_FULL 只有 4 bit，最多设置 15 个空位）
+    pub(crate) watermark: u8,
+}
+
+impl Config {
+    // 指尖测量的常用设置：100 sps，4 个平均，FIFO 的采样率为 25 Hz；18 bit；LED 约 7 mA
+    pub(crate) const FINGER: Config = Config {
+        mode: Mode::Spo2,
+        slots: [Slot::Red, Slot::Ir, Slot::None, Slot::None],
+        sample_rate: SampleRate::Sps100,
+        averaging: Averaging::X4,
+        pulse_width: PulseWidth::Us411,
+        adc_range: AdcRange::Na4096,
+        red_ma: 7.0,
+        ir_ma: 7.0,
+        watermark: 16,
+    };
+
+    // FIFO 中采样的频率
+    pub(crate) fn output_hz(&self) -> f32 {
+        self.sample_rate.hz() as f32 / self.averaging.count() as f32
+    }
+
+    // 每个采样有几颗 LED 的数据
+    fn leds_per_sample(&self) -> usize {
+        match self.mode {
+            Mode::HeartRate => 1,
+            Mode::Spo2 => 2,
+            Mode::MultiLed => self.slots.iter().filter(|&&s| s != Slot::None).count(),
+        }
+    }
+}
+
+// FIFO 中的一个采样；HeartRate 模式下 ir 为 0，MultiLed 模式下按 slot 中第一个红光与第一个红外填入
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Sample {
+    pub(crate) red: u32,
+    pub(crate) ir: u32,
+}
+
+pub(crate) struct Max30102<'a> {
+    i2c: &'a RegisterBlock,
+    addr: I2cAddress,
+    config: Config,
+
+    ring: [Sample; RING_LEN],
+    head: usize,
+    len: usize,
+    // 因为 FIFO 满了（芯片丢弃）或者环形缓冲区满了（驱动丢弃）而丢掉的采样数
+    lost: u32,
+
+    temperature: Option<f32>,
+}
+
+impl<'a> Max30102<'a> {
+    // 软件复位，检查 PART_ID，写入设置，清空 FIFO，并打开水位与温度中断
+    pub(crate) fn new(i2c: &'a RegisterBlock, config: Config) -> Result<Self, SensorError> {
+        let mut dev = Self {
+            i2c,
+            addr: I2cAddress::SevenBit(ADDR),
+            config,
+            ring: [Sample::default(); RING_LEN],
+            head: 0,
+            len: 0,
+            lost: 0,
+            temperature: None,
+        };
+
+        if dev.read_reg(REG_PART_ID)? != PART_ID {
+            return Err(SensorError::Unsupported);
+        }
+
+        dev.write_reg(REG_MODE_CONFIG, MODE_RESET)?;
+        let mut polls = 0;
+        while dev.read_reg(REG_MODE_CONFIG)? & MODE_RESET != 0 {
+            polls += 1;
+            if polls > RESET_POLLS {
+                return Err(SensorError::Timeout);
+            }
+        }
+
+        dev.apply_config()?;
+
+        // 读一次状态，清理复位之后的 PWR_RDY，否则 INT 一直为低
+        dev.read_reg(REG_INT_STATUS_1)?;
+        dev.read_reg(REG_INT_STATUS_2)?;
+        G_INT_PENDING.store(false, Ordering::Release);
+
+        dev.write_reg(REG_INT_ENABLE_1, INT_A_FULL)?;
+        dev.write_reg(REG_INT_ENABLE_2, INT_DIE_TEMP_RDY)?;
+
+        Ok(dev)
+    }
+
+    pub(crate) fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn apply_config(&mut self) -> Result<(), SensorError> {
+        let config = self.config;
+        if !(17..=FIFO_DEPTH as u8).contains(&config.watermark) {
+            return Err(SensorError::OutOfRange);
+        }
+
+        // 写 MODE_CONFIG 会重新开始采样，先写其他寄存器
+        // 关闭 rollover：FIFO 满了之后丢弃新的采样，OVF_COUNTER 记录丢了多少
+        let a_full = FIFO_DEPTH as u8 - config.watermark;
+        self.write_reg(
+            REG_FIFO_CONFIG,
+            (config.averaging as u8) << 5 | (a_full & 0x0F),
+        )?;
+        self.write_reg(
+            REG_SPO2_CONFIG,
+            (config.adc_range as u8) << 5
+                | (config.sample_rate as u8) << 2
+                | config.pulse_width as u8,
+        )?;
+        self.set_led_current(config.red_ma, config.ir_ma)?;
+        self.set_slots(config.slots)?;
+        self.write_reg(REG_MODE_CONFIG, config.mode as u8)?;
+        self.clear_fifo()
+    }
+
+    // 修改 LED 的电流，不影响正在进行的采样，单位 mA，超出 0 ~ 51 mA 的部分截断
+    pub(crate) fn set_led_current(&mut self, red_ma: f32, ir_ma: f32) -> Result<(), SensorError> {
+        let step = |ma: f32| (ma / LED_MA_PER_STEP + 0.5).clamp(0.0, 255.0) as u8;
+        self.write_reg(REG_LED1_PA, step(red_ma))?;
+        self.write_reg(REG_LED2_PA, step(ir_ma))?;
+        self.config.red_ma = red_ma;
+        self.config.ir_ma = ir_ma;
+        Ok(())
+    }
+
+    pub(crate) fn set_slots(&mut self, slots: [Slot; 4]) -> Result<(), SensorError> {
+        self.write_reg(REG_MULTI_LED_1, (slots[1] as u8) << 4 | slots[0] as u8)?;
+        self.write_reg(REG_MULTI_LED_2, (slots[3] as u8) << 4 | slots[2] as u8)?;
+        self.config.slots = slots;
+        Ok(())
+    }
+
+    // 关断时 LED 与 ADC 停止工作，寄存器保持不变，约 0.7 uA
+    pub(crate) fn shutdown(&mut self, shutdown: bool) -> Result<(), SensorError> {
+        let mode = self.config.mode as u8 | if shutdown { MODE_SHDN } else { 0 };
+        self.write_reg(REG_MODE_CONFIG, mode)
+    }
+
+    // 清空芯片的 FIFO 与驱动的环形缓冲区
+    pub(crate) fn clear_fifo(&mut self) -> Result<(), SensorError> {
+        self.write_reg(REG_FIFO_WR_PTR, 0)?;
+        self.write_reg(REG_OVF_COUNTER, 0)?;
+        self.write_reg(REG_FIFO_RD_PTR, 0)?;
+        self.head = 0;
+        self.len = 0;
+        Ok(())
+    }
+
+    // 启动一次芯片温度的测量，约 29 ms，完成时拉低 INT
+    pub(crate) fn start_temperature(&mut self) -> Result<(), SensorError> {
+        self.write_reg(REG_TEMP_CONFIG, 1)
+    }
+
+    // 最近一次测得的芯片温度，单位 ℃
+    pub(crate) fn temperature(&self) -> Option<f32> {
+        self.temperature
+    }
+
+    // INT 被拉低过时，读取状态（同时释放 INT），读出 FIFO 与温度，返回读出的采样数
+    //
+    // 没有中断时不访问 I2C，可以在主循环中随意调用
+    pub(crate) fn service(&mut self) -> Result<usize, SensorError> {
+        if !G_INT_PENDING.swap(false, Ordering::AcqRel) {
+            return Ok(0);
+        }
+
+        let status_1 = self.read_reg(REG_INT_STATUS_1)?;
+        let status_2 = self.read_reg(REG_INT_STATUS_2)?;
+
+        if status_2 & INT_DIE_TEMP_RDY != 0 {
+            let int = self.read_reg(REG_TEMP_INT)? as i8;
+            let frac = self.read_reg(REG_TEMP_FRAC)? & 0x0F;
+            self.temperature = Some(int as f32 + frac as f32 * 0.0625);
+        }
+
+        // 只有温度中断时也读一次 FIFO，中断之间可能已经积累了一些采样
+        if status_1 & INT_ALC_OVF != 0 {
+            // 环境光太强，ADC 的环境光抵消已经到了极限，采样不可信
+            return Err(SensorError::OutOfRange);
+        }
+        self.drain()
+    }
+
+    // 读出 FIFO 中所有的采样，返回读出的个数
+    pub(crate) fn drain(&mut self) -> Result<usize, SensorError> {
+        let write_ptr = self.read_reg(REG_FIFO_WR_PTR)? & 0x1F;
+        let overflow = self.read_reg(REG_OVF_COUNTER)? & 0x1F;
+        let read_ptr = self.read_reg(REG_FIFO_RD_PTR)? & 0x1F;
+
+        // 指针相等时，FIFO 可能是空的，也可能是满的，OVF_COUNTER 不为 0 说明是满的
+        let available = if overflow > 0 {
+            self.lost += overflow as u32;
+            FIFO_DEPTH
+        } else {
+            (write_ptr.wrapping_sub(read_ptr) & 0x1F) as usize
+        };
+
+        let leds = self.config.leds_per_sample();
+        if leds == 0 {
+            return Ok(0);
+        }
+        let bytes_per_sample = leds * 3;
+
+        let mut remaining = available;
+        let mut buf = [0u8; CHUNK * 4 * 3];
+        while remaining > 0 {
+            let count = remaining.min(CHUNK);
+            let chunk = &mut buf[..count * bytes_per_sample];
+            blocking_master::write_read(self.i2c, self.addr, &[REG_FIFO_DATA], chunk)?;
+            for raw in chunk.chunks_exact(bytes_per_sample) {
+                let sample = self.decode(raw);
+                self.push(sample);
+            }
+            remaining -= count;
+        }
+
+        Ok(available)
+    }
+
+    fn decode(&self, raw: &[u8]) -> Sample {
+        let value = |i: usize| {
+            let b = &raw[i * 3..i * 3 + 3];
+            ((b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32) & ADC_MAX
+        };
+        match self.config.mode {
+            Mode::HeartRate => Sample {
+                red: value(0),
+                ir: 0,
+            },
+            Mode::Spo2 => Sample {
+                red: value(0),
+                ir: value(1),
+            },
+            Mode::MultiLed => {
+                let mut sample = Sample::default();
+                let active = self.config.slots.iter().filter(|&&s| s != Slot::None);
+                let mut red_done = false;
+                let mut ir_done = false;
+                for (i, slot) in active.enumerate() {
+                    match slot {
+                        Slot::Red if !red_done => {
+                            sample.red = value(i);
+                            red_done = true;
+                        }
+                        Slot::Ir if !ir_done => {
+                            sample.ir = value(i);
+                            ir_done = true;
+                        }
+                        _ => {}
+                    }
+                }
+                sample
+            }
+        }
+    }
+
+    fn push(&mut self, sample: Sample) {
+        if self.len == RING_LEN {
+            // 丢掉最旧的采样
+            self.head = (self.head + 1) % RING_LEN;
+            self.len -= 1;
+            self.lost += 1;
+        }
+        self.ring[(self.head + self.len) % RING_LEN] = sample;
+        self.len += 1;
+    }
+
+    // 取出最旧的一个采样
+    pub(crate) fn pop(&mut self) -> Option<Sample> {
+        if self.len == 0 {
+            return None;
+        }
+        let sample = self.ring[self.head];
+        self.head = (self.head + 1) % RING_LEN;
+        self.len -= 1;
+        Some(sample)
+    }
+
+    pub(crate) fn available(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn lost(&self) -> u32 {
+        self.lost
+    }
+
+    fn read_reg(&self, reg: u8) -> Result<u8, SensorError> {
+        let mut buf = [0u8; 1];
+        blocking_master::write_read(self.i2c, self.addr, &[reg], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn write_reg(&self, reg: u8, value: u8) -> Result<(), SensorError> {
+        blocking_master::write(self.i2c, self.addr, &[reg, value])?;
+        Ok(())
+    }
+}
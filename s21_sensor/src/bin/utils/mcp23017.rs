@@ -0,0 +1,299 @@
+//! MCP23017 16 位 GPIO 扩展芯片（I2C）
+//!
+//! 芯片有 GPA0~7 与 GPB0~7 两个 8 位的端口，这里把它们拼成一个 u16：GPA 为低 8 位，GPB 为高 8 位，引脚编号为 0 ~ 15
+//! 上电后 IOCON.BANK = 0，A、B 两个端口的同名寄存器地址相邻（A 在前），读写时地址又会自动递增，
+//! 因此一次读写 2 个字节，正好就是一个小端序的 u16
+//!
+//! 每个引脚都可以单独设置方向、上拉，以及电平变化中断（interrupt-on-change）：
+//! INTA 与 INTB 通过 IOCON.MIRROR 合并，并设置为开漏输出（IOCON.ODR），低电平有效，只需要一根线接到 STM32 上
+//! 这根线通过 utils::exti 注册 on_interrupt 作为回调，下降沿触发，之后在主循环中调用 Mcp23017::poll_interrupt 读取变化
+//! 读取 INTCAP 之前，芯片会一直拉低 INT，因此不会因为中断来得太快而丢失变化
+//!
+//! input_pin、output_pin 返回的 ExpanderPin 实现了 embedded-hal 的 InputPin、OutputPin 与 StatefulOutputPin，
+//! 其他需要 GPIO 的驱动（比如 utils::mcp41xx 的片选）可以直接使用扩展出来的引脚，不需要知道每次翻转背后其实是一次 I2C 传输
+//! （stm32f4xx_hal::hal 就是 embedded-hal，见 s03c02）
+//!
+//! 驱动内部保存了 IODIR、GPPU、GPINTEN、OLAT 的副本，修改单个引脚时不需要先读取芯片
+//! 副本放在 Cell 中，所以各个方法都只需要 &self，多个 ExpanderPin 可以同时借用同一个 Mcp23017
+//!
+//! I2C 外设需要事先配置好（见 s21c02），这里只通过 utils::blocking_master 收发数据
+//! 地址为 0x20 + A2A1A0
+
+#![allow(dead_code)]
+
+use core::{
+    cell::Cell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use stm32f4xx_hal::{
+    hal::digital::{self, ErrorKind, ErrorType, InputPin, OutputPin, StatefulOutputPin},
+    pac::i2c1::RegisterBlock,
+};
+
+use super::{
+    addressing::I2cAddress,
+    blocking_master::{self, MasterError},
+};
+
+pub(crate) const ADDR_BASE: u8 = 0x20;
+
+pub(crate) const PIN_COUNT: u8 = 16;
+
+// IOCON.BANK = 0 时各个寄存器的地址，B 端口的寄存器紧随其后
+const REG_IODIR: u8 = 0x00;
+const REG_IPOL: u8 = 0x02;
+const REG_GPINTEN: u8 = 0x04;
+const REG_DEFVAL: u8 = 0x06;
+const REG_INTCON: u8 = 0x08;
+const REG_IOCON: u8 = 0x0A;
+const REG_GPPU: u8 = 0x0C;
+const REG_INTF: u8 = 0x0E;
+const REG_INTCAP: u8 = 0x10;
+const REG_GPIO: u8 = 0x12;
+const REG_OLAT: u8 = 0x14;
+
+// MIRROR：INTA 与 INTB 合并为一个中断
+const IOCON_MIRROR: u8 = 1 << 6;
+// ODR：INT 为开漏输出
+const IOCON_ODR: u8 = 1 << 2;
+
+// 芯片拉低了 INT，尚未被 poll_interrupt 处理
+// 这里假设只有一片 MCP23017 使用中断，多片时可以把它们的 INT 并联（开漏输出），然后逐个 poll_interrupt
+static G_INT_PENDING: AtomicBool = AtomicBool::new(false);
+
+// 注册到 utils::exti 的回调
+pub(crate) fn on_interrupt(_line: u8) {
+    G_INT_PENDING.store(true, Ordering::Release);
+}
+
+// embedded-hal 的 Error 要求实现 Debug，不受 fmt 特性控制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum ExpanderError {
+    // I2C 通信出错，比如地址没有被 ACK
+    Bus,
+    // 引脚编号大于 15
+    InvalidPin,
+}
+
+impl From<MasterError> for ExpanderError {
+    fn from(_: MasterError) -> Self {
+        ExpanderError::Bus
+    }
+}
+
+impl digital::Error for ExpanderError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Direction {
+    Input,
+    Output,
+}
+
+// 一次中断所对应的变化
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Change {
+    // INTF：哪些引脚触发了中断
+    pub(crate) pins: u16,
+    // INTCAP：中断发生时各个引脚的电平
+    pub(crate) levels: u16,
+}
+
+pub(crate) struct Mcp23017<'a> {
+    i2c: &'a RegisterBlock,
+    addr: I2cAddress,
+    iodir: Cell<u16>,
+    gppu: Cell<u16>,
+    gpinten: Cell<u16>,
+    olat: Cell<u16>,
+}
+
+impl<'a> Mcp23017<'a> {
+    // 把芯片恢复为上电时的状态（全部为输入、没有上拉、没有中断），并设置 INT 输出
+    //
+    // 这里假设 IOCON.BANK 为上电时的 0，STM32 复位而芯片没有复位时也是如此，因为我们从不修改 BANK
+    pub(crate) fn new(i2c: &'a RegisterBlock, addr: u8) -> Result<Self, ExpanderError> {
+        let dev = Self {
+            i2c,
+            addr: I2cAddress::SevenBit(addr),
+            iodir: Cell::new(0xFFFF),
+            gppu: Cell::new(0),
+            gpinten: Cell::new(0),
+            olat: Cell::new(0),
+        };
+
+        dev.write_u8(REG_IOCON, IOCON_MIRROR | IOCON_ODR)?;
+        dev.write_u16(REG_IODIR, 0xFFFF)?;
+        dev.write_u16(REG_IPOL, 0)?;
+        dev.write_u16(REG_GPINTEN, 0)?;
+        // INTCON 为 0 时，与引脚之前的电平比较，也就是任何变化都会触发中断，此时 DEFVAL 没有作用
+        dev.write_u16(REG_INTCON, 0)?;
+        dev.write_u16(REG_DEFVAL, 0)?;
+        dev.write_u16(REG_GPPU, 0)?;
+        dev.write_u16(REG_OLAT, 0)?;
+
+        // 读一次 INTCAP，清理复位之前可能残留的中断，否则 INT 一直为低，EXTI 永远等不到下一个下降沿
+        dev.read_u16(REG_INTCAP)?;
+        G_INT_PENDING.store(false, Ordering::Release);
+
+        Ok(dev)
+    }
+
+    // 读取全部 16 个引脚的电平
+    pub(crate) fn read_port(&self) -> Result<u16, ExpanderError> {
+        self.read_u16(REG_GPIO)
+    }
+
+    // 设置全部 16 个引脚的输出电平，输入引脚不受影响
+    pub(crate) fn write_port(&self, value: u16) -> Result<(), ExpanderError> {
+        self.write_u16(REG_OLAT, value)?;
+        self.olat.set(value);
+        Ok(())
+    }
+
+    // 只修改 mask 中为 1 的引脚
+    pub(crate) fn modify_port(&self, mask: u16, value: u16) -> Result<(), ExpanderError> {
+        self.write_port((self.olat.get() & !mask) | (value & mask))
+    }
+
+    pub(crate) fn set_direction(&self, pin: u8, direction: Direction) -> Result<(), ExpanderError> {
+        let iodir = set_bit(self.iodir.get(), pin, direction == Direction::Input)?;
+        self.write_u16(REG_IODIR, iodir)?;
+        self.iodir.set(iodir);
+        Ok(())
+    }
+
+    // 内部的上拉约为 100 kΩ
+    pub(crate) fn set_pull_up(&self, pin: u8, enable: bool) -> Result<(), ExpanderError> {
+        let gppu = set_bit(self.gppu.get(), pin, enable)?;
+        self.write_u16(REG_GPPU, gppu)?;
+        self.gppu.set(gppu);
+        Ok(())
+    }
+
+    // 引脚电平发生任何变化时触发中断
+    pub(crate) fn set_interrupt(&self, pin: u8, enable: bool) -> Result<(), ExpanderError> {
+        let gpinten = set_bit(self.gpinten.get(), pin, enable)?;
+        self.write_u16(REG_GPINTEN, gpinten)?;
+        self.gpinten.set(gpinten);
+        Ok(())
+    }
+
+    // 把引脚设置为输入
+    pub(crate) fn input_pin(
+        &self,
+        pin: u8,
+        pull_up: bool,
+    ) -> Result<ExpanderPin<'_>, ExpanderError> {
+        self.set_pull_up(pin, pull_up)?;
+        self.set_direction(pin, Direction::Input)?;
+        Ok(ExpanderPin {
+            dev: self,
+            mask: 1 << pin,
+        })
+    }
+
+    // 把引脚设置为输出，先设置好电平再切换方向，避免切换的瞬间输出错误的电平
+    pub(crate) fn output_pin(&self, pin: u8, high: bool) -> Result<ExpanderPin<'_>, ExpanderError> {
+        let olat = set_bit(self.olat.get(), pin, high)?;
+        self.write_port(olat)?;
+        self.set_direction(pin, Direction::Output)?;
+        Ok(ExpanderPin {
+            dev: self,
+            mask: 1 << pin,
+        })
+    }
+
+    // 如果 INT 被拉低过，读取 INTF 与 INTCAP，读取 INTCAP 之后芯片会释放 INT
+    pub(crate) fn poll_interrupt(&self) -> Result<Option<Change>, ExpanderError> {
+        if !G_INT_PENDING.swap(false, Ordering::AcqRel) {
+            return Ok(None);
+        }
+
+        // INTF 必须在 INTCAP 之前读取，读取 INTCAP 会把 INTF 一并清零
+        let pins = self.read_u16(REG_INTF)?;
+        let levels = self.read_u16(REG_INTCAP)?;
+        if pins == 0 {
+            return Ok(None);
+        }
+        Ok(Some(Change { pins, levels }))
+    }
+
+    fn write_u8(&self, reg: u8, value: u8) -> Result<(), ExpanderError> {
+        blocking_master::write(self.i2c, self.addr, &[reg, value])?;
+        Ok(())
+    }
+
+    fn write_u16(&self, reg: u8, value: u16) -> Result<(), ExpanderError> {
+        let [a, b] = value.to_le_bytes();
+        blocking_master::write(self.i2c, self.addr, &[reg, a, b])?;
+        Ok(())
+    }
+
+    fn read_u16(&self, reg: u8) -> Result<u16, ExpanderError> {
+        let mut buf = [0u8; 2];
+        blocking_master::write_read(self.i2c, self.addr, &[reg], &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+}
+
+fn set_bit(bits: u16, pin: u8, value: bool) -> Result<u16, ExpanderError> {
+    if pin >= PIN_COUNT {
+        return Err(ExpanderError::InvalidPin);
+    }
+    Ok(if value {
+        bits | (1 << pin)
+    } else {
+        bits & !(1 << pin)
+    })
+}
+
+// 扩展出来的单个引脚
+pub(crate) struct ExpanderPin<'d> {
+    dev: &'d Mcp23017<'d>,
+    mask: u16,
+}
+
+impl ErrorType for ExpanderPin<'_> {
+    type Error = ExpanderError;
+}
+
+impl OutputPin for ExpanderPin<'_> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.dev.modify_port(self.mask, 0)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.dev.modify_port(self.mask, self.mask)
+    }
+}
+
+impl StatefulOutputPin for ExpanderPin<'_> {
+    // 输出的电平就是驱动中 OLAT 的副本，不需要读取芯片
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.dev.olat.get() & self.mask != 0)
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.dev.olat.get() & self.mask == 0)
+    }
+}
+
+impl InputPin for ExpanderPin<'_> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.dev.read_port()? & self.mask != 0)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.dev.read_port()? & self.mask == 0)
+    }
+}
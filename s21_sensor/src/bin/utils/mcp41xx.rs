@@ -0,0 +1,202 @@
+//! MCP41x1/MCP42x1 数字电位器（SPI）
+//!
+//! 包括单路的 MCP4131/4151、双路的 MCP4231/4251 等型号，它们的命令格式完全相同：
+//! 每条命令的第一个字节为 AD3~AD0（寄存器地址）、C1 C0（命令）、D9 D8（数据的最高两位），
+//! 读写命令之后再跟一个字节 D7~D0，增减命令则只有这一个字节
+//!
+//! 7 位的型号有 129 档（0x00 ~ 0x80），8 位的型号有 257 档（0x000 ~ 0x100），满量程时滑片直接连到 A 端
+//!
+//! 芯片在发送命令的同时就会从 SDO 输出数据：第一个字节中 D9 所在的位置输出的是 CMDERR，
+//! 为 1 表示命令有效，为 0 表示地址或命令无效（比如对单路的型号访问滑片 1），这也可以用来判断芯片是否接好
+//! 单路型号的 SDI 与 SDO 共用一个引脚，需要在 MOSI 与这个引脚之间串一个几 kΩ 的电阻，MISO 则直接接到引脚上，
+//! 此时 SCK 不能超过 250 kHz，否则读到的数据不可靠
+//!
+//! 关断（shutdown）通过 TCON 寄存器中的 RxHW 位实现：清零时 A 端断开，滑片与 B 端相连，几乎不消耗电流，
+//! 滑片的设置会被保留，恢复之后依旧有效
+//!
+//! SPI 外设需要事先配置为 Mode 0，8 位（见 s21c07），这里只负责收发数据
+//! 片选是泛型参数，任何实现了 embedded-hal OutputPin 的引脚都可以，包括 utils::mcp23017 扩展出来的引脚
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::{hal::digital::OutputPin, pac::spi1::RegisterBlock};
+
+const REG_WIPER0: u8 = 0x00;
+const REG_WIPER1: u8 = 0x01;
+const REG_TCON: u8 = 0x04;
+const REG_STATUS: u8 = 0x05;
+
+const CMD_WRITE: u8 = 0b00;
+const CMD_INCREMENT: u8 = 0b01;
+const CMD_DECREMENT: u8 = 0b10;
+const CMD_READ: u8 = 0b11;
+
+// 第一个字节中 CMDERR 所在的位
+const CMDERR: u8 = 1 << 1;
+
+// 寄存器都是 9 位的
+const DATA_MASK: u16 = 0x1FF;
+// 读取时 SDI 应该保持为高电平，单路型号上这样才不会和芯片输出的数据冲突
+const READ_FILL: u16 = 0x3FF;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Resolution {
+    // MCP4131/4231 等，129 档
+    Bits7,
+    // MCP4151/4251 等，257 档
+    Bits8,
+}
+
+impl Resolution {
+    pub(crate) fn full_scale(self) -> u16 {
+        match self {
+            Resolution::Bits7 => 0x80,
+            Resolution::Bits8 => 0x100,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Wiper {
+    W0,
+    // 只有双路的型号才有
+    W1,
+}
+
+impl Wiper {
+    fn reg(self) -> u8 {
+        match self {
+            Wiper::W0 => REG_WIPER0,
+            Wiper::W1 => REG_WIPER1,
+        }
+    }
+
+    // TCON 中对应的 RxHW 位
+    fn tcon_hw(self) -> u16 {
+        match self {
+            Wiper::W0 => 1 << 3,
+            Wiper::W1 => 1 << 7,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum PotError {
+    // CMDERR 为 0：命令无效，或者芯片没有接好
+    Command,
+    // 片选引脚出错，比如片选接在 MCP23017 上，而 I2C 通信失败
+    ChipSelect,
+    // 滑片的位置超过了满量程
+    OutOfRange,
+}
+
+pub(crate) struct Mcp41xx<'a, CS> {
+    spi: &'a RegisterBlock,
+    cs: CS,
+    resolution: Resolution,
+}
+
+impl<'a, CS: OutputPin> Mcp41xx<'a, CS> {
+    pub(crate) fn new(
+        spi: &'a RegisterBlock,
+        mut cs: CS,
+        resolution: Resolution,
+    ) -> Result<Self, PotError> {
+        cs.set_high().map_err(|_| PotError::ChipSelect)?;
+        Ok(Self {
+            spi,
+            cs,
+            resolution,
+        })
+    }
+
+    pub(crate) fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    pub(crate) fn set_wiper(&mut self, wiper: Wiper, value: u16) -> Result<(), PotError> {
+        if value > self.resolution.full_scale() {
+            return Err(PotError::OutOfRange);
+        }
+        self.command(wiper.reg(), CMD_WRITE, value)?;
+        Ok(())
+    }
+
+    pub(crate) fn wiper(&mut self, wiper: Wiper) -> Result<u16, PotError> {
+        self.command(wiper.reg(), CMD_READ, READ_FILL)
+    }
+
+    // 向 A 端移动一档，已经在满量程时不会再变化
+    pub(crate) fn increment(&mut self, wiper: Wiper) -> Result<(), PotError> {
+        self.short_command(wiper.reg(), CMD_INCREMENT)
+    }
+
+    // 向 B 端移动一档，已经在 0 时不会再变化
+    pub(crate) fn decrement(&mut self, wiper: Wiper) -> Result<(), PotError> {
+        self.short_command(wiper.reg(), CMD_DECREMENT)
+    }
+
+    pub(crate) fn shutdown(&mut self, wiper: Wiper, shutdown: bool) -> Result<(), PotError> {
+        let tcon = self.command(REG_TCON, CMD_READ, READ_FILL)?;
+        let tcon = if shutdown {
+            tcon & !wiper.tcon_hw()
+        } else {
+            tcon | wiper.tcon_hw()
+        };
+        self.command(REG_TCON, CMD_WRITE, tcon)?;
+        Ok(())
+    }
+
+    pub(crate) fn is_shutdown(&mut self, wiper: Wiper) -> Result<bool, PotError> {
+        let tcon = self.command(REG_TCON, CMD_READ, READ_FILL)?;
+        Ok(tcon & wiper.tcon_hw() == 0)
+    }
+
+    // 归还片选引脚
+    pub(crate) fn release(self) -> CS {
+        self.cs
+    }
+
+    // 16 位的命令，返回芯片在 SDO 上输出的 9 位数据
+    fn command(&mut self, reg: u8, cmd: u8, data: u16) -> Result<u16, PotError> {
+        let first = (reg << 4) | (cmd << 2) | ((data >> 8) as u8 & 0b11);
+
+        self.cs.set_low().map_err(|_| PotError::ChipSelect)?;
+        let high = self.transfer(first);
+        let low = self.transfer(data as u8);
+        self.cs.set_high().map_err(|_| PotError::ChipSelect)?;
+
+        if high & CMDERR == 0 {
+            return Err(PotError::Command);
+        }
+        Ok(u16::from_be_bytes([high, low]) & DATA_MASK)
+    }
+
+    // 8 位的命令，只有增减使用
+    fn short_command(&mut self, reg: u8, cmd: u8) -> Result<(), PotError> {
+        let byte = (reg << 4) | (cmd << 2);
+
+        self.cs.set_low().map_err(|_| PotError::ChipSelect)?;
+        let reply = self.transfer(byte);
+        self.cs.set_high().map_err(|_| PotError::ChipSelect)?;
+
+        if reply & CMDERR == 0 {
+            return Err(PotError::Command);
+        }
+        Ok(())
+    }
+
+    fn transfer(&self, byte: u8) -> u8 {
+        let spi = self.spi;
+        while spi.sr.read().txe().is_not_empty() {}
+        spi.dr.write(|w| w.dr().bits(byte as u16));
+        while spi.sr.read().rxne().is_empty() {}
+        spi.dr.read().dr().bits() as u8
+    }
+}
@@ -0,0 +1,597 @@
+//! MFRC522 13.56 MHz RFID 读卡芯片（SPI）
+//!
+//! 这里只实现读取 ISO 14443A 卡片（MIFARE Classic、Ultralight、NTAG 等）的 UID，不涉及扇区的认证与读写
+//!
+//! SPI 的地址字节：bit 7 为 1 表示读，bit 6 ~ bit 1 为寄存器地址，bit 0 固定为 0
+//!
+//! 芯片通过 FIFO 与卡片交换数据：把要发送的字节写进 FIFO，执行 Transceive 命令，发送完毕后芯片自动转为接收，
+//! 卡片的应答再从 FIFO 中读出。芯片内部的定时器在发送结束时自动启动（TAuto），卡片在 25 ms 内没有应答就触发 TimerIRq
+//!
+//! 读取 UID 的流程（ISO 14443-3）：
+//! 1. REQA（7 bit 的短帧 0x26）：处于 IDLE 状态的卡片回应 2 字节的 ATQA，进入 READY 状态
+//! 2. 防冲突（ANTICOLLISION）：发送 SEL + NVB，卡片回应 UID 的 4 个字节与 BCC（4 个字节的异或）
+//!    多张卡片同时回应时，芯片会报告第一个冲突的位置，把冲突位置之前的位（再加上冲突位，这里取 1）发回去，
+//!    只有 UID 与之相符的卡片才会继续回应，直到剩下一张卡片
+//! 3. 选择（SELECT）：发送 SEL + 0x70 + UID 4 字节 + BCC + CRC_A，卡片回应 SAK
+//!    SAK 的 bit 2 为 1 表示 UID 还没有读完，此时这一级的第一个字节是级联标记 0x88，需要进入下一级（SEL 为 0x93/0x95/0x97）
+//!    4 字节的 UID 只有一级，7 字节的两级，10 字节的三级
+//! 4. HLTA：让卡片进入 HALT 状态，不再回应 REQA，这样放在天线上的卡片不会被反复读到，拿开再放上去才会再次被读到
+//!
+//! CRC_A 由芯片的 CRC 协处理器计算（ModeReg 中预置值为 0x6363）
+//!
+//! 检测卡片的两种方式：
+//! - 轮询：周期性地调用 read_uid，没有卡片时要等到定时器超时（约 25 ms）才返回
+//! - IRQ：调用 enable_irq 之后，周期性地调用 arm 发出 REQA，但不等待应答，
+//!   卡片回应 ATQA 时芯片拉低 IRQ 引脚，EXTI 回调 on_interrupt 记下标识，主循环调用 service 完成后续的防冲突与选择
+//!   没有卡片时 CPU 不需要等待，适合主循环还要做其他事情的场合
+//!
+//! SPI 外设需要事先配置为 Mode 0，8 位，SCK 不超过 10 MHz（见 s21c19）
+
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use stm32f4xx_hal::{hal::digital::OutputPin, pac::spi1::RegisterBlock};
+
+use super::ticker;
+
+// 寄存器地址，只列出用到的
+const REG_COMMAND: u8 = 0x01;
+const REG_COM_IEN: u8 = 0x02;
+const REG_DIV_IEN: u8 = 0x03;
+const REG_COM_IRQ: u8 = 0x04;
+const REG_DIV_IRQ: u8 = 0x05;
+const REG_ERROR: u8 = 0x06;
+const REG_FIFO_DATA: u8 = 0x09;
+const REG_FIFO_LEVEL: u8 = 0x0A;
+const REG_CONTROL: u8 = 0x0C;
+const REG_BIT_FRAMING: u8 = 0x0D;
+const REG_COLL: u8 = 0x0E;
+const REG_MODE: u8 = 0x11;
+const REG_TX_CONTROL: u8 = 0x14;
+const REG_TX_ASK: u8 = 0x15;
+const REG_CRC_RESULT_H: u8 = 0x21;
+const REG_CRC_RESULT_L: u8 = 0x22;
+const REG_RF_CFG: u8 = 0x26;
+const REG_T_MODE: u8 = 0x2A;
+const REG_T_PRESCALER: u8 = 0x2B;
+const REG_T_RELOAD_H: u8 = 0x2C;
+const REG_T_RELOAD_L: u8 = 0x2D;
+const REG_VERSION: u8 = 0x37;
+
+// 写入 CommandReg 的命令
+const CMD_IDLE: u8 = 0x00;
+const CMD_CALC_CRC: u8 = 0x03;
+const CMD_TRANSCEIVE: u8 = 0x0C;
+const CMD_SOFT_RESET: u8 = 0x0F;
+// CommandReg 中的 PowerDown 位，软复位完成之前保持为 1
+const POWER_DOWN: u8 = 1 << 4;
+
+// ComIrqReg / ComIEnReg
+const IRQ_SET1: u8 = 1 << 7;
+const IRQ_RX: u8 = 1 << 5;
+const IRQ_IDLE: u8 = 1 << 4;
+const IRQ_ERR: u8 = 1 << 1;
+const IRQ_TIMER: u8 = 1 << 0;
+// ComIEnReg 的 bit 7：IRQ 引脚取反，有中断时为低电平
+const IRQ_INV: u8 = 1 << 7;
+// DivIEnReg 的 bit 7：IRQ 引脚为推挽输出，否则为开漏
+const IRQ_PUSH_PULL: u8 = 1 << 7;
+// DivIrqReg
+const DIV_IRQ_CRC: u8 = 1 << 2;
+
+// ErrorReg
+const ERR_BUFFER_OVFL: u8 = 1 << 4;
+const ERR_COLL: u8 = 1 << 3;
+const ERR_CRC: u8 = 1 << 2;
+const ERR_PARITY: u8 = 1 << 1;
+const ERR_PROTOCOL: u8 = 1 << 0;
+
+// FIFOLevelReg 的 bit 7：清空 FIFO
+const FLUSH_BUFFER: u8 = 1 << 7;
+// BitFramingReg 的 bit 7：开始发送，只在 Transceive 时有效
+const START_SEND: u8 = 1 << 7;
+// CollReg 的 bit 7：冲突之后收到的位是否保留，清零时冲突之后的位都为 0
+const VALUES_AFTER_COLL: u8 = 1 << 7;
+// CollReg 的 bit 5：CollPos 无效
+const COLL_POS_NOT_VALID: u8 = 1 << 5;
+
+// ISO 14443-3 的命令
+const PICC_REQA: u8 = 0x26;
+const PICC_SEL_CL: [u8; 3] = [0x93, 0x95, 0x97];
+const PICC_HLTA: u8 = 0x50;
+const CASCADE_TAG: u8 = 0x88;
+// NVB：高 4 位为已知的字节数（包括 SEL 与 NVB），低 4 位为剩下的位数
+const NVB_SELECT: u8 = 0x70;
+// SAK 的 bit 2：UID 还没有读完
+const SAK_CASCADE: u8 = 1 << 2;
+
+const FIFO_SIZE: usize = 64;
+// 芯片的定时器已经限制了等待时间，这里再加一层保护，防止芯片没有接好时卡死
+const SOFT_TIMEOUT_MS: u32 = 40;
+
+// 卡片回应了 ATQA，芯片拉低了 IRQ，尚未被 service 处理
+static G_IRQ_PENDING: AtomicBool = AtomicBool::new(false);
+
+// 注册到 utils::exti 的回调，IRQ 引脚为下降沿触发
+pub(crate) fn on_interrupt(_line: u8) {
+    G_IRQ_PENDING.store(true, Ordering::Release);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum RfidError {
+    // 片选引脚出错
+    ChipSelect,
+    // VersionReg 不是 MFRC522（或者芯片没有接好）
+    NotFound,
+    // REQA 没有应答
+    NoTag,
+    // 定时器超时，或者芯片没有完成命令
+    Timeout,
+    // 多张卡片的冲突无法解决
+    Collision,
+    // CRC_A 或 BCC 错误
+    Crc,
+    // 奇偶校验错、FIFO 溢出、应答的长度不对等
+    Protocol,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Uid {
+    // 4、7 或者 10
+    len: u8,
+    bytes: [u8; 10],
+    sak: u8,
+}
+
+impl Uid {
+    pub(crate) fn new(bytes: &[u8], sak: u8) -> Option<Self> {
+        if !matches!(bytes.len(), 4 | 7 | 10) {
+            return None;
+        }
+        let mut uid = Self {
+            len: bytes.len() as u8,
+            bytes: [0; 10],
+            sak,
+        };
+        uid.bytes[..bytes.len()].copy_from_slice(bytes);
+        Some(uid)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    // 最后一级的 SAK，可以据此粗略地判断卡片类型，比如 0x08 为 MIFARE Classic 1K，0x00 为 Ultralight/NTAG
+    pub(crate) fn sak(&self) -> u8 {
+        self.sak
+    }
+}
+
+// Transceive 收到的数据
+struct Response {
+    buf: [u8; FIFO_SIZE],
+    len: usize,
+    // 最后一个字节中有效的位数，0 表示 8 位都有效
+    last_bits: u8,
+}
+
+pub(crate) struct Mfrc522<'a, CS> {
+    spi: &'a RegisterBlock,
+    cs: CS,
+    irq_enabled: bool,
+}
+
+impl<'a, CS: OutputPin> Mfrc522<'a, CS> {
+    // 软复位并完成初始化，之后天线是打开的
+    pub(crate) fn new(spi: &'a RegisterBlock, mut cs: CS) -> Result<Self, RfidError> {
+        cs.set_high().map_err(|_| RfidError::ChipSelect)?;
+        let mut dev = Self {
+            spi,
+            cs,
+            irq_enabled: false,
+        };
+
+        dev.write_reg(REG_COMMAND, CMD_SOFT_RESET)?;
+        // 复位需要等待晶振起振，通常不到 1 ms
+        let start = ticker::millis();
+        while dev.read_reg(REG_COMMAND)? & POWER_DOWN != 0 {
+            if ticker::millis().wrapping_sub(start) > SOFT_TIMEOUT_MS {
+                return Err(RfidError::NotFound);
+            }
+        }
+
+        // 0x91 为 v1.0，0x92 为 v2.0，常见的兼容芯片为 0x88（FM17522）、0xB2
+        // 芯片没有接好时通常读到 0x00 或 0xFF
+        if matches!(dev.version()?, 0x00 | 0xFF) {
+            return Err(RfidError::NotFound);
+        }
+
+        // 定时器在发送结束时自动启动
+        // f_timer = 13.56 MHz / (2 * 169 + 1) = 40 kHz，每个计数 25 us，重装值 1000 就是 25 ms
+        dev.write_reg(REG_T_MODE, 0x80)?;
+        dev.write_reg(REG_T_PRESCALER, 169)?;
+        dev.write_reg(REG_T_RELOAD_H, (1000u16 >> 8) as u8)?;
+        dev.write_reg(REG_T_RELOAD_L, 1000u16 as u8)?;
+        // 100% ASK 调制
+        dev.write_reg(REG_TX_ASK, 0x40)?;
+        // CRC 协处理器的预置值为 0x6363（ISO 14443-3 的 CRC_A）
+        dev.write_reg(REG_MODE, 0x3D)?;
+
+        dev.antenna_on()?;
+        Ok(dev)
+    }
+
+    pub(crate) fn version(&mut self) -> Result<u8, RfidError> {
+        self.read_reg(REG_VERSION)
+    }
+
+    // TX1、TX2 输出 13.56 MHz 载波
+    pub(crate) fn antenna_on(&mut self) -> Result<(), RfidError> {
+        self.set_bits(REG_TX_CONTROL, 0b11)
+    }
+
+    // 关闭载波，天线上的卡片随之断电，省电的同时，下次打开时卡片都会回到 IDLE 状态
+    pub(crate) fn antenna_off(&mut self) -> Result<(), RfidError> {
+        self.clear_bits(REG_TX_CONTROL, 0b11)
+    }
+
+    pub(crate) fn is_antenna_on(&mut self) -> Result<bool, RfidError> {
+        Ok(self.read_reg(REG_TX_CONTROL)? & 0b11 == 0b11)
+    }
+
+    // 接收增益，0 ~ 7 对应 18 dB ~ 48 dB，默认为 4（33 dB），读卡距离不够时可以调高
+    pub(crate) fn set_gain(&mut self, gain: u8) -> Result<(), RfidError> {
+        let value = self.read_reg(REG_RF_CFG)?;
+        self.write_reg(REG_RF_CFG, (value & !0x70) | ((gain.min(7)) << 4))
+    }
+
+    // 轮询方式：REQA、防冲突、选择，最后让卡片进入 HALT
+    pub(crate) fn read_uid(&mut self) -> Result<Uid, RfidError> {
+        self.request_a()?;
+        let uid = self.select()?;
+        self.halt()?;
+        Ok(uid)
+    }
+
+    // 发送 REQA，返回 ATQA
+    pub(crate) fn request_a(&mut self) -> Result<u16, RfidError> {
+        self.write_reg(REG_COLL, 0)?;
+        let response = match self.transceive(&[PICC_REQA], 7, 0) {
+            Ok(response) => response,
+            Err(RfidError::Timeout) => return Err(RfidError::NoTag),
+            Err(e) => return Err(e),
+        };
+        if response.len != 2 || response.last_bits != 0 {
+            return Err(RfidError::Protocol);
+        }
+        Ok(u16::from_le_bytes([response.buf[0], response.buf[1]]))
+    }
+
+    // 卡片处于 READY 状态（刚刚回应了 ATQA）时，逐级完成防冲突与选择
+    pub(crate) fn select(&mut self) -> Result<Uid, RfidError> {
+        let mut uid = [0u8; 10];
+        let mut len = 0;
+
+        for sel in PICC_SEL_CL {
+            let cl = self.anticollision(sel)?;
+            let sak = self.select_level(sel, &cl)?;
+
+            if sak & SAK_CASCADE != 0 {
+                if cl[0] != CASCADE_TAG || len == 6 {
+                    return Err(RfidError::Protocol);
+                }
+                uid[len..len + 3].copy_from_slice(&cl[1..4]);
+                len += 3;
+            } else {
+                uid[len..len + 4].copy_from_slice(&cl[..4]);
+                len += 4;
+                return Uid::new(&uid[..len], sak).ok_or(RfidError::Protocol);
+            }
+        }
+        Err(RfidError::Protocol)
+    }
+
+    // HLTA，卡片不会回应，超时才是正常的结果
+    pub(crate) fn halt(&mut self) -> Result<(), RfidError> {
+        let crc = self.calc_crc(&[PICC_HLTA, 0])?;
+        match self.transceive(&[PICC_HLTA, 0, crc[0], crc[1]], 0, 0) {
+            Err(RfidError::Timeout) => Ok(()),
+            Ok(_) => Err(RfidError::Protocol),
+            Err(e) => Err(e),
+        }
+    }
+
+    // IRQ 引脚设为推挽输出、低电平有效，只在收到数据（RxIRq）时触发
+    pub(crate) fn enable_irq(&mut self) -> Result<(), RfidError> {
+        self.write_reg(REG_DIV_IEN, IRQ_PUSH_PULL)?;
+        self.write_reg(REG_COM_IEN, IRQ_INV | IRQ_RX)?;
+        self.irq_enabled = true;
+        Ok(())
+    }
+
+    pub(crate) fn disable_irq(&mut self) -> Result<(), RfidError> {
+        self.write_reg(REG_COM_IEN, IRQ_INV)?;
+        self.write_reg(REG_DIV_IEN, 0)?;
+        self.irq_enabled = false;
+        G_IRQ_PENDING.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    // IRQ 方式：发出 REQA，不等待应答，一般每 100 ms 左右调用一次
+    // 上一次的 REQA 还没有应答时，重新发出也没有关系
+    pub(crate) fn arm(&mut self) -> Result<(), RfidError> {
+        self.write_reg(REG_COLL, 0)?;
+        self.start_transceive(&[PICC_REQA], 7, 0)
+    }
+
+    // IRQ 方式：没有中断时不访问 SPI，可以在主循环中随意调用
+    // 卡片回应了 ATQA 时，完成防冲突与选择，并让卡片进入 HALT，返回 UID
+    pub(crate) fn service(&mut self) -> Result<Option<Uid>, RfidError> {
+        if !self.irq_enabled || !G_IRQ_PENDING.swap(false, Ordering::AcqRel) {
+            return Ok(None);
+        }
+
+        let response = self.finish_transceive()?;
+        if response.len != 2 || response.last_bits != 0 {
+            return Err(RfidError::Protocol);
+        }
+        let result = self.select().and_then(|uid| self.halt().map(|_| uid));
+        // 防冲突与选择时收到的应答同样会拉低 IRQ，这些都不是新的卡片
+        G_IRQ_PENDING.store(false, Ordering::Release);
+        result.map(Some)
+    }
+
+    // 用 CRC 协处理器计算 CRC_A，返回低字节在前
+    pub(crate) fn calc_crc(&mut self, data: &[u8]) -> Result<[u8; 2], RfidError> {
+        self.write_reg(REG_COMMAND, CMD_IDLE)?;
+        self.write_reg(REG_DIV_IRQ, DIV_IRQ_CRC)?;
+        self.write_reg(REG_FIFO_LEVEL, FLUSH_BUFFER)?;
+        self.write_fifo(data)?;
+        self.write_reg(REG_COMMAND, CMD_CALC_CRC)?;
+
+        let start = ticker::millis();
+        while self.read_reg(REG_DIV_IRQ)? & DIV_IRQ_CRC == 0 {
+            if ticker::millis().wrapping_sub(start) > SOFT_TIMEOUT_MS {
+                return Err(RfidError::Timeout);
+            }
+        }
+        self.write_reg(REG_COMMAND, CMD_IDLE)?;
+
+        Ok([
+            self.read_reg(REG_CRC_RESULT_L)?,
+            self.read_reg(REG_CRC_RESULT_H)?,
+        ])
+    }
+
+    pub(crate) fn release(self) -> CS {
+        self.cs
+    }
+
+    // 某一级的防冲突，返回这一级的 4 个字节（不含 BCC）
+    fn anticollision(&mut self, sel: u8) -> Result<[u8; 4], RfidError> {
+        // SEL NVB UID0 UID1 UID2 UID3 BCC
+        let mut frame = [0u8; 7];
+        frame[0] = sel;
+        // 已经确定的 UID 位数，0 ~ 32
+        let mut known_bits = 0usize;
+
+        // 冲突位置每次至少前进 1 位，最多 32 次
+        for _ in 0..=32 {
+            let tx_last_bits = (known_bits % 8) as u8;
+            let tx_len = 2 + known_bits / 8 + (tx_last_bits != 0) as usize;
+            frame[1] = (((2 + known_bits / 8) as u8) << 4) | tx_last_bits;
+            // 应答的第一个字节与发出的部分字节重叠
+            let first = if tx_last_bits != 0 {
+                tx_len - 1
+            } else {
+                tx_len
+            };
+
+            // 冲突之后的位清零，冲突位才能由我们决定
+            self.clear_bits(REG_COLL, VALUES_AFTER_COLL)?;
+            // 应答从未发完的那一位开始，接收时对齐到同一个位置，第一个字节与发出的部分字节拼起来
+            let result = self.transceive(&frame[..tx_len], tx_last_bits, tx_last_bits);
+            let response = match result {
+                Ok(response) => response,
+                Err(RfidError::Collision) => {
+                    let coll = self.read_reg(REG_COLL)?;
+                    if coll & COLL_POS_NOT_VALID != 0 {
+                        return Err(RfidError::Collision);
+                    }
+                    // CollPos 为 1 ~ 32，0 表示第 32 位
+                    let pos = match (coll & 0x1F) as usize {
+                        0 => 32,
+                        pos => pos,
+                    };
+                    if pos <= known_bits {
+                        return Err(RfidError::Collision);
+                    }
+                    // 冲突位之前的位已经在 FIFO 中，读出来拼到 frame 里
+                    let response = self.read_response()?;
+                    merge(&mut frame, first, tx_last_bits, &response);
+                    // 冲突的那一位取 1，选择 UID 中这一位为 1 的卡片
+                    let bit = pos - 1;
+                    frame[2 + bit / 8] |= 1 << (bit % 8);
+                    known_bits = pos;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if first + response.len != frame.len() {
+                return Err(RfidError::Protocol);
+            }
+            merge(&mut frame, first, tx_last_bits, &response);
+
+            let bcc = frame[2] ^ frame[3] ^ frame[4] ^ frame[5];
+            if bcc != frame[6] {
+                return Err(RfidError::Crc);
+            }
+            return Ok([frame[2], frame[3], frame[4], frame[5]]);
+        }
+        Err(RfidError::Collision)
+    }
+
+    // 选中这一级的 UID，返回 SAK
+    fn select_level(&mut self, sel: u8, cl: &[u8; 4]) -> Result<u8, RfidError> {
+        let bcc = cl[0] ^ cl[1] ^ cl[2] ^ cl[3];
+        let mut frame = [sel, NVB_SELECT, cl[0], cl[1], cl[2], cl[3], bcc, 0, 0];
+        let crc = self.calc_crc(&frame[..7])?;
+        frame[7..].copy_from_slice(&crc);
+
+        let response = self.transceive(&frame, 0, 0)?;
+        // SAK 加上 2 字节的 CRC_A
+        if response.len != 3 || response.last_bits != 0 {
+            return Err(RfidError::Protocol);
+        }
+        if self.calc_crc(&response.buf[..1])? != [response.buf[1], response.buf[2]] {
+            return Err(RfidError::Crc);
+        }
+        Ok(response.buf[0])
+    }
+
+    // tx_last_bits：最后一个字节发送的位数，0 表示 8 位
+    // rx_align：收到的第一个位存放在第一个字节的哪一位
+    fn transceive(
+        &mut self,
+        data: &[u8],
+        tx_last_bits: u8,
+        rx_align: u8,
+    ) -> Result<Response, RfidError> {
+        self.start_transceive(data, tx_last_bits, rx_align)?;
+
+        let start = ticker::millis();
+        loop {
+            let irq = self.read_reg(REG_COM_IRQ)?;
+            if irq & (IRQ_RX | IRQ_IDLE) != 0 {
+                break;
+            }
+            if irq & IRQ_TIMER != 0 {
+                return Err(RfidError::Timeout);
+            }
+            if ticker::millis().wrapping_sub(start) > SOFT_TIMEOUT_MS {
+                return Err(RfidError::Timeout);
+            }
+        }
+
+        self.finish_transceive()
+    }
+
+    fn start_transceive(
+        &mut self,
+        data: &[u8],
+        tx_last_bits: u8,
+        rx_align: u8,
+    ) -> Result<(), RfidError> {
+        self.write_reg(REG_COMMAND, CMD_IDLE)?;
+        // 写 1 清零所有中断标识
+        self.write_reg(REG_COM_IRQ, !IRQ_SET1)?;
+        self.write_reg(REG_FIFO_LEVEL, FLUSH_BUFFER)?;
+        self.write_fifo(data)?;
+        self.write_reg(REG_BIT_FRAMING, (rx_align << 4) | tx_last_bits)?;
+        self.write_reg(REG_COMMAND, CMD_TRANSCEIVE)?;
+        self.set_bits(REG_BIT_FRAMING, START_SEND)
+    }
+
+    // 收到应答之后检查错误，并读出 FIFO
+    fn finish_transceive(&mut self) -> Result<Response, RfidError> {
+        self.clear_bits(REG_BIT_FRAMING, START_SEND)?;
+
+        let error = self.read_reg(REG_ERROR)?;
+        if error & (ERR_BUFFER_OVFL | ERR_PARITY | ERR_PROTOCOL) != 0 {
+            return Err(RfidError::Protocol);
+        }
+        if error & ERR_COLL != 0 {
+            return Err(RfidError::Collision);
+        }
+        // 芯片自己检查 CRC 的功能（RxCRCEn）没有打开，这一位正常情况下不会置位
+        if error & ERR_CRC != 0 {
+            return Err(RfidError::Crc);
+        }
+
+        let response = self.read_response()?;
+        if response.len == 0 {
+            return Err(RfidError::Protocol);
+        }
+        Ok(response)
+    }
+
+    fn read_response(&mut self) -> Result<Response, RfidError> {
+        let len = (self.read_reg(REG_FIFO_LEVEL)? & 0x7F) as usize;
+        let mut response = Response {
+            buf: [0; FIFO_SIZE],
+            len: len.min(FIFO_SIZE),
+            last_bits: self.read_reg(REG_CONTROL)? & 0b111,
+        };
+        for index in 0..response.len {
+            response.buf[index] = self.read_reg(REG_FIFO_DATA)?;
+        }
+        Ok(response)
+    }
+
+    fn write_fifo(&mut self, data: &[u8]) -> Result<(), RfidError> {
+        self.cs.set_low().map_err(|_| RfidError::ChipSelect)?;
+        self.transfer(address(REG_FIFO_DATA, false));
+        for &byte in data {
+            self.transfer(byte);
+        }
+        self.cs.set_high().map_err(|_| RfidError::ChipSelect)
+    }
+
+    fn write_reg(&mut self, reg: u8, value: u8) -> Result<(), RfidError> {
+        self.cs.set_low().map_err(|_| RfidError::ChipSelect)?;
+        self.transfer(address(reg, false));
+        self.transfer(value);
+        self.cs.set_high().map_err(|_| RfidError::ChipSelect)
+    }
+
+    fn read_reg(&mut self, reg: u8) -> Result<u8, RfidError> {
+        self.cs.set_low().map_err(|_| RfidError::ChipSelect)?;
+        self.transfer(address(reg, true));
+        let value = self.transfer(0);
+        self.cs.set_high().map_err(|_| RfidError::ChipSelect)?;
+        Ok(value)
+    }
+
+    fn set_bits(&mut self, reg: u8, mask: u8) -> Result<(), RfidError> {
+        let value = self.read_reg(reg)?;
+        self.write_reg(reg, value | mask)
+    }
+
+    fn clear_bits(&mut self, reg: u8, mask: u8) -> Result<(), RfidError> {
+        let value = self.read_reg(reg)?;
+        self.write_reg(reg, value & !mask)
+    }
+
+    fn transfer(&self, byte: u8) -> u8 {
+        let spi = self.spi;
+        while spi.sr.read().txe().is_not_empty() {}
+        spi.dr.write(|w| w.dr().bits(byte as u16));
+        while spi.sr.read().rxne().is_empty() {}
+        spi.dr.read().dr().bits() as u8
+    }
+}
+
+fn address(reg: u8, read: bool) -> u8 {
+    ((read as u8) << 7) | ((reg << 1) & 0x7E)
+}
+
+// 把收到的数据拼到 frame[first..] 上，第一个字节的低 rx_align 位保留 frame 中原有的（已经发出的）位
+fn merge(frame: &mut [u8], first: usize, rx_align: u8, response: &Response) {
+    let keep = (1u8 << rx_align) - 1;
+    for (index, &byte) in response.buf[..response.len].iter().enumerate() {
+        let Some(slot) = frame.get_mut(first + index) else {
+            break;
+        };
+        *slot = if index == 0 {
+            (*slot & keep) | (byte & !keep)
+        } else {
+            byte
+        };
+    }
+}
@@ -0,0 +1,65 @@
+// 带 #[cfg] 的模块用到了部分芯片上没有的外设，用到它们的程序在开头写有 chip_caps::require!，见 chip_caps
+
+pub(crate) mod analog;
+pub(crate) mod as5600;
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub(crate) mod auto_dim;
+pub(crate) mod bh1750;
+pub(crate) mod bme280;
+pub(crate) mod bus_trace;
+pub(crate) mod calibration;
+pub(crate) mod config;
+pub(crate) mod datalog;
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub(crate) mod delay;
+pub(crate) mod dfu;
+pub(crate) mod dsp;
+pub(crate) mod encoder;
+pub(crate) mod event_bus;
+pub(crate) mod executor;
+pub(crate) mod exti;
+pub(crate) mod exti_sim;
+pub(crate) mod internal_flash;
+pub(crate) mod keypad;
+pub(crate) mod lcd1602;
+pub(crate) mod log;
+pub(crate) mod max30102;
+pub(crate) mod mcp23017;
+pub(crate) mod mcp41xx;
+pub(crate) mod mfrc522;
+pub(crate) mod ms5611;
+pub(crate) mod pca9685;
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub(crate) mod qspi_flash;
+pub(crate) mod selftest;
+pub(crate) mod sensor;
+pub(crate) mod servo;
+pub(crate) mod settings;
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub(crate) mod shell_auth;
+pub(crate) mod shift_reg;
+pub(crate) mod sht;
+pub(crate) mod spo2;
+pub(crate) mod tft_panel;
+pub(crate) mod thermocouple;
+pub(crate) mod ticker;
+pub(crate) mod tsl2561;
+pub(crate) mod ui;
+pub(crate) mod wait_cell;
+pub(crate) mod watch;
+pub(crate) mod ws2812_spi;
+pub(crate) mod xpt2046;
+
+// 与其它章节共用的模块，代码在工作区中单独的 crate 里
+// 与上面的模块一样，不是每个程序都会用到，因此允许未使用
+#[allow(unused_imports)]
+pub(crate) use i2c_master::{addressing, blocking_master};
+#[allow(unused_imports)]
+pub(crate) use mcu_common::{io, pid, ws2812};
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+#[allow(unused_imports)]
+pub(crate) use quadspi_core::{auto_poll, command};
+#[allow(unused_imports)]
+pub(crate) use telemetry_core::crc16;
+#[allow(unused_imports)]
+pub(crate) use tft_display::{font5x7, framebuffer, st7789};
@@ -0,0 +1,397 @@
+//! MS5611 气压传感器（I2C 或 SPI），以及由气压换算海拔高度
+//!
+//! MS5611 只有几条命令，没有寄存器：
+//!
+//! | 命令        | 内容                                               |
+//! | ----------- | -------------------------------------------------- |
+//! | 0x1E        | 复位，之后芯片重新载入 PROM，约 3 ms                |
+//! | 0x40 + osr  | 启动 D1（气压）的转换                               |
+//! | 0x50 + osr  | 启动 D2（温度）的转换                               |
+//! | 0x00        | 读出上一次转换的结果，24 位；没有完成时读到 0       |
+//! | 0xA0 + 2*n  | 读出 PROM 的第 n 个字（16 位），n 为 0 ~ 7         |
+//!
+//! PROM 的第 1 ~ 6 个字是出厂校准参数 C1 ~ C6，第 7 个字的低 4 位是整个 PROM 的 CRC4（算法见 AN520），
+//! 读出之后先检查 CRC，不一致就说明读错了，或者根本不是 MS5611
+//!
+//! 过采样（OSR）越高，噪声越小，转换时间越长：256 时约 0.6 ms、噪声约 0.065 mbar，4096 时约 9 ms、噪声约 0.012 mbar
+//! 气压和温度的 OSR 可以分别设置，温度变化慢，一般用较低的 OSR 即可
+//!
+//! 补偿公式见 datasheet 的 PRESSURE AND TEMPERATURE CALCULATION，全部是整数运算，
+//! 低于 20 ℃ 时还要按 SECOND ORDER TEMPERATURE COMPENSATION 再修正一次
+//!
+//! 海拔高度使用国际标准大气的气压高度公式：h = 44330 * (1 - (p / p0)^(1 / 5.255))，p0 默认为 101325 Pa，
+//! 想要相对高度（比如相对起飞点）时，用 zero_altitude 把当前气压设为 p0
+//!
+//! 总线由 Bus trait 抽象：I2c 使用 utils::blocking_master，I2C 外设需要事先配置好（见 s21c02）；
+//! Spi 需要 SPI 外设事先配置为 Mode 0，8 位，SCK 不超过 20 MHz，片选为任何实现了 OutputPin 的引脚
+//! CSB 接地时 I2C 地址为 0x77，接 VDD 时为 0x76；PS 接 VDD 选择 I2C，接地选择 SPI
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::{
+    hal::digital::OutputPin,
+    pac::{i2c1, spi1},
+};
+
+use super::{
+    addressing::I2cAddress,
+    analog::ln,
+    blocking_master,
+    sensor::{Celsius, Measurement, Pascal, Reading, Sensor, SensorError},
+    ticker,
+};
+
+pub(crate) const ADDR_CSB_LOW: u8 = 0x77;
+pub(crate) const ADDR_CSB_HIGH: u8 = 0x76;
+
+pub(crate) const SEA_LEVEL_PA: f32 = 101_325.0;
+
+const CMD_RESET: u8 = 0x1E;
+const CMD_CONVERT_D1: u8 = 0x40;
+const CMD_CONVERT_D2: u8 = 0x50;
+const CMD_ADC_READ: u8 = 0x00;
+const CMD_PROM_READ: u8 = 0xA0;
+
+// 复位之后载入 PROM 需要 2.8 ms
+const RESET_MS: u32 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Osr {
+    X256,
+    X512,
+    X1024,
+    X2048,
+    X4096,
+}
+
+impl Osr {
+    // 加在转换命令上的值
+    fn cmd_offset(self) -> u8 {
+        (self as u8) * 2
+    }
+
+    // datasheet 给出的最长转换时间，向上取整到毫秒
+    pub(crate) fn conversion_ms(self) -> u32 {
+        match self {
+            Osr::X256 => 1,
+            Osr::X512 => 2,
+            Osr::X1024 => 3,
+            Osr::X2048 => 5,
+            Osr::X4096 => 10,
+        }
+    }
+}
+
+// MS5611 的命令都很简单：发送一个字节，或者发送一个字节之后读出若干字节
+pub(crate) trait Bus {
+    fn command(&mut self, cmd: u8) -> Result<(), SensorError>;
+    fn read(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), SensorError>;
+}
+
+pub(crate) struct I2c<'a> {
+    i2c: &'a i2c1::RegisterBlock,
+    addr: I2cAddress,
+}
+
+impl<'a> I2c<'a> {
+    pub(crate) fn new(i2c: &'a i2c1::RegisterBlock, addr: u8) -> Self {
+        Self {
+            i2c,
+            addr: I2cAddress::SevenBit(addr),
+        }
+    }
+}
+
+impl Bus for I2c<'_> {
+    fn command(&mut self, cmd: u8) -> Result<(), SensorError> {
+        blocking_master::write(self.i2c, self.addr, &[cmd])?;
+        Ok(())
+    }
+
+    fn read(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), SensorError> {
+        blocking_master::write_read(self.i2c, self.addr, &[cmd], buf)?;
+        Ok(())
+    }
+}
+
+pub(crate) struct Spi<'a, CS> {
+    spi: &'a spi1::RegisterBlock,
+    cs: CS,
+}
+
+impl<'a, CS: OutputPin> Spi<'a, CS> {
+    pub(crate) fn new(spi: &'a spi1::RegisterBlock, mut cs: CS) -> Result<Self, SensorError> {
+        cs.set_high().map_err(|_| SensorError::Bus)?;
+        Ok(Self { spi, cs })
+    }
+
+    // 归还片选引脚
+    pub(crate) fn release(self) -> CS {
+        self.cs
+    }
+
+    fn transfer(&self, byte: u8) -> u8 {
+        let spi = self.spi;
+        while spi.sr.read().txe().is_not_empty() {}
+        spi.dr.write(|w| w.dr().bits(byte as u16));
+        while spi.sr.read().rxne().is_empty() {}
+        spi.dr.read().dr().bits() as u8
+    }
+}
+
+impl<CS: OutputPin> Bus for Spi<'_, CS> {
+    fn command(&mut self, cmd: u8) -> Result<(), SensorError> {
+        self.read(cmd, &mut [])
+    }
+
+    fn read(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), SensorError> {
+        self.cs.set_low().map_err(|_| SensorError::Bus)?;
+        self.transfer(cmd);
+        for byte in buf.iter_mut() {
+            *byte = self.transfer(0);
+        }
+        self.cs.set_high().map_err(|_| SensorError::Bus)?;
+        Ok(())
+    }
+}
+
+// AN520 中的 CRC4，prom[7] 的低 4 位是 CRC 本身，计算时当作 0
+fn crc4(prom: &[u16; 8]) -> u8 {
+    let mut words = *prom;
+    words[7] &= 0xFF00;
+
+    let mut rem: u16 = 0;
+    for cnt in 0..16 {
+        let word = words[cnt / 2];
+        rem ^= if cnt % 2 == 1 {
+            word & 0x00FF
+        } else {
+            word >> 8
+        };
+        for _ in 0..8 {
+            rem = if rem & 0x8000 != 0 {
+                (rem << 1) ^ 0x3000
+            } else {
+                rem << 1
+            };
+        }
+    }
+    ((rem >> 12) & 0xF) as u8
+}
+
+// 出厂校准参数 C1 ~ C6
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+struct Calibration {
+    c: [i64; 7],
+}
+
+impl Calibration {
+    fn parse(prom: &[u16; 8]) -> Result<Self, SensorError> {
+        if crc4(prom) != (prom[7] & 0xF) as u8 {
+            return Err(SensorError::Checksum);
+        }
+        // 全 0 或者全 1 的 PROM 也能凑巧通过 CRC，多半是总线没有接好
+        if prom.iter().all(|&w| w == 0) || prom.iter().all(|&w| w == 0xFFFF) {
+            return Err(SensorError::Bus);
+        }
+
+        let mut c = [0i64; 7];
+        for (n, word) in prom.iter().enumerate().take(7).skip(1) {
+            c[n] = *word as i64;
+        }
+        Ok(Self { c })
+    }
+
+    // 返回温度（0.01 ℃）与气压（Pa），已经包含二阶补偿
+    fn compensate(&self, d1: u32, d2: u32) -> (i32, i32) {
+        let c = &self.c;
+        let (d1, d2) = (d1 as i64, d2 as i64);
+
+        let dt = d2 - (c[5] << 8);
+        let mut temp = 2000 + ((dt * c[6]) >> 23);
+        let mut off = (c[2] << 16) + ((c[4] * dt) >> 7);
+        let mut sens = (c[1] << 15) + ((c[3] * dt) >> 8);
+
+        // 二阶补偿，只在 20 ℃ 以下进行
+        if temp < 2000 {
+            let t2 = (dt * dt) >> 31;
+            let low = (temp - 2000) * (temp - 2000);
+            let mut off2 = 5 * low / 2;
+            let mut sens2 = 5 * low / 4;
+            if temp < -1500 {
+                let very_low = (temp + 1500) * (temp + 1500);
+                off2 += 7 * very_low;
+                sens2 += 11 * very_low / 2;
+            }
+            temp -= t2;
+            off -= off2;
+            sens -= sens2;
+        }
+
+        let press = (((d1 * sens) >> 21) - off) >> 15;
+        (temp as i32, press as i32)
+    }
+}
+
+// 由气压计算海拔高度，单位 m
+pub(crate) fn altitude(press: f32, sea_level: f32) -> f32 {
+    44330.0 * (1.0 - pow(press / sea_level, 1.0 / 5.255))
+}
+
+// 由已知海拔高度处的气压，反推海平面气压
+pub(crate) fn sea_level_pressure(press: f32, altitude: f32) -> f32 {
+    press / pow(1.0 - altitude / 44330.0, 5.255)
+}
+
+// no_std 下没有 f32::powf，x^y = e^(y * ln x)，ln 见 utils::analog
+fn pow(x: f32, y: f32) -> f32 {
+    exp(y * ln(x))
+}
+
+// 把 x 拆成 k * ln(2) + r，|r| 不超过 ln(2) / 2，e^x = 2^k * e^r，e^r 用泰勒级数计算，取到 r^8 已经足够
+fn exp(x: f32) -> f32 {
+    let k = (x / core::f32::consts::LN_2 + if x < 0.0 { -0.5 } else { 0.5 }) as i32;
+    if !(-126..=127).contains(&k) {
+        return if k < 0 { 0.0 } else { f32::INFINITY };
+    }
+    let r = x - k as f32 * core::f32::consts::LN_2;
+
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for n in 1..=8 {
+        term *= r / n as f32;
+        sum += term;
+    }
+
+    sum * f32::from_bits(((k + 127) as u32) << 23)
+}
+
+// 一次采样的结果
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Ms5611Measurement {
+    pub(crate) temp: Celsius,
+    pub(crate) press: Pascal,
+    // 相对于 p0 的高度，单位 m
+    pub(crate) altitude: f32,
+}
+
+impl Measurement for Ms5611Measurement {
+    fn for_each_reading(&self, f: &mut dyn FnMut(Reading)) {
+        self.temp.for_each_reading(f);
+        self.press.for_each_reading(f);
+        f(Reading {
+            quantity: "alt",
+            value: self.altitude,
+            unit: "m",
+        });
+    }
+}
+
+pub(crate) struct Ms5611<B> {
+    bus: B,
+    calib: Calibration,
+    press_osr: Osr,
+    temp_osr: Osr,
+    sea_level: f32,
+}
+
+impl<B: Bus> Ms5611<B> {
+    // 复位芯片，读出 PROM 并检查 CRC
+    pub(crate) fn new(mut bus: B) -> Result<Self, SensorError> {
+        bus.command(CMD_RESET)?;
+        ticker::delay_ms(RESET_MS);
+
+        let mut prom = [0u16; 8];
+        for (n, word) in prom.iter_mut().enumerate() {
+            let mut buf = [0u8; 2];
+            bus.read(CMD_PROM_READ + 2 * n as u8, &mut buf)?;
+            *word = u16::from_be_bytes(buf);
+        }
+
+        Ok(Self {
+            bus,
+            calib: Calibration::parse(&prom)?,
+            press_osr: Osr::X4096,
+            temp_osr: Osr::X1024,
+            sea_level: SEA_LEVEL_PA,
+        })
+    }
+
+    pub(crate) fn set_osr(&mut self, press: Osr, temp: Osr) {
+        self.press_osr = press;
+        self.temp_osr = temp;
+    }
+
+    // 一次采样需要的时间，调度的间隔不应该比这更短
+    pub(crate) fn sample_ms(&self) -> u32 {
+        self.press_osr.conversion_ms() + self.temp_osr.conversion_ms()
+    }
+
+    pub(crate) fn set_sea_level(&mut self, press: f32) {
+        self.sea_level = press;
+    }
+
+    pub(crate) fn sea_level(&self) -> f32 {
+        self.sea_level
+    }
+
+    // 把当前的气压作为 p0，之后的高度都是相对于此处的高度
+    pub(crate) fn zero_altitude(&mut self) -> Result<(), SensorError> {
+        let (_, press) = self.read()?;
+        self.sea_level = press as f32;
+        Ok(())
+    }
+
+    // 依次转换 D2 与 D1，返回温度（0.01 ℃）与气压（Pa）
+    pub(crate) fn read(&mut self) -> Result<(i32, i32), SensorError> {
+        let d2 = self.convert(CMD_CONVERT_D2, self.temp_osr)?;
+        let d1 = self.convert(CMD_CONVERT_D1, self.press_osr)?;
+        Ok(self.calib.compensate(d1, d2))
+    }
+
+    pub(crate) fn release(self) -> B {
+        self.bus
+    }
+
+    fn convert(&mut self, cmd: u8, osr: Osr) -> Result<u32, SensorError> {
+        self.bus.command(cmd + osr.cmd_offset())?;
+        ticker::delay_ms(osr.conversion_ms());
+
+        let mut buf = [0u8; 3];
+        self.bus.read(CMD_ADC_READ, &mut buf)?;
+        let value = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]);
+        // 转换没有完成就读取，或者读取之前又发送了别的命令，结果都是 0
+        if value == 0 {
+            return Err(SensorError::NotReady);
+        }
+        Ok(value)
+    }
+}
+
+impl<B: Bus> Sensor for Ms5611<B> {
+    type Output = Ms5611Measurement;
+
+    fn name(&self) -> &'static str {
+        "baro"
+    }
+
+    fn sample(&mut self) -> Result<Ms5611Measurement, SensorError> {
+        let (temp, press) = self.read()?;
+
+        // datasheet 给出的工作范围为 -40 ~ 85 ℃，10 ~ 1200 mbar
+        if !(-4000..=8500).contains(&temp) || !(1_000..=120_000).contains(&press) {
+            return Err(SensorError::OutOfRange);
+        }
+
+        let press = press as f32;
+        Ok(Ms5611Measurement {
+            temp: Celsius(temp as f32 / 100.0),
+            press: Pascal(press),
+            altitude: altitude(press, self.sea_level),
+        })
+    }
+}
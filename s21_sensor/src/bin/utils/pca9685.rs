@@ -0,0 +1,338 @@
+//! PCA9685 16 通道 12 位 PWM 芯片（I2C）
+//!
+//! 芯片内部有一个 25 MHz 的振荡器，经过 PRE_SCALE 分频之后驱动一个 12 位的计数器，16 个通道共用这个计数器，因此频率相同
+//! 输出频率 = 25 MHz / (4096 * (PRE_SCALE + 1))，PRE_SCALE 最小为 3，于是频率的范围约为 24 Hz ~ 1526 Hz，
+//! 驱动舵机用 50 Hz，驱动 LED 可以用 1 kHz 以上，避免闪烁
+//! PRE_SCALE 只能在 SLEEP 时写入，set_frequency 会自动处理
+//!
+//! 每个通道有 ON 与 OFF 两个 12 位的值：计数器等于 ON 时输出变为高电平，等于 OFF 时变为低电平
+//! 因此除了占空比，还可以设置每个通道的相位（ON），让各个通道错开上升沿，减小电源的瞬时电流
+//! ON_H/OFF_H 的第 4 位为 full on/full off，用来输出 100% 与 0%，两者同时设置时 full off 优先
+//!
+//! 所有通道寄存器之后还有一组 ALL_LED 寄存器，写入它等于写入全部 16 个通道
+//!
+//! 地址：
+//! - 0x40 + A5~A0，A5~A0 由芯片的 6 个引脚决定
+//! - all-call：MODE1.ALLCALL 打开时，芯片同时响应 ALLCALLADR（默认 0x70），
+//!   总线上有多片 PCA9685 时，可以通过这个地址同时操作全部的芯片
+//!
+//! SLEEP：振荡器停止，所有输出停止，PWM 的设置保留
+//! 唤醒之后需要等待振荡器稳定（500 us），如果进入 SLEEP 之前有通道在输出，MODE1.RESTART 会变为 1，
+//! 此时向 RESTART 写 1 可以恢复之前的输出，wake 会自动处理
+//!
+//! channel 返回的 Pca9685Channel 实现了 embedded-hal 的 SetDutyCycle，与 utils::servo 中 TIM 的通道一样，
+//! 可以直接交给 utils::servo::Servo 使用
+//!
+//! 驱动内部保存了 MODE1 的副本与当前的频率，放在 Cell 中，各个方法都只需要 &self，多个 Pca9685Channel 可以同时借用同一个 Pca9685
+//!
+//! I2C 外设需要事先配置好（见 s21c02），这里只通过 utils::blocking_master 收发数据
+
+#![allow(dead_code)]
+
+use core::cell::Cell;
+
+use stm32f4xx_hal::{
+    hal::pwm::{self, ErrorKind, ErrorType, SetDutyCycle},
+    pac::i2c1::RegisterBlock,
+};
+
+use super::{
+    addressing::I2cAddress,
+    blocking_master::{self, MasterError},
+    ticker,
+};
+
+pub(crate) const ADDR_BASE: u8 = 0x40;
+pub(crate) const ADDR_ALL_CALL_DEFAULT: u8 = 0x70;
+
+pub(crate) const CHANNEL_COUNT: u8 = 16;
+
+// 计数器的一个周期
+pub(crate) const TICKS: u16 = 4096;
+
+const OSC_HZ: u32 = 25_000_000;
+const PRESCALE_MIN: u32 = 3;
+const PRESCALE_MAX: u32 = 255;
+
+pub(crate) const FREQ_MIN_HZ: u32 = OSC_HZ / (TICKS as u32 * (PRESCALE_MAX + 1));
+pub(crate) const FREQ_MAX_HZ: u32 = OSC_HZ / (TICKS as u32 * (PRESCALE_MIN + 1));
+
+const REG_MODE1: u8 = 0x00;
+const REG_MODE2: u8 = 0x01;
+const REG_ALLCALLADR: u8 = 0x05;
+const REG_LED0_ON_L: u8 = 0x06;
+const REG_ALL_LED_ON_L: u8 = 0xFA;
+const REG_PRE_SCALE: u8 = 0xFE;
+
+const MODE1_RESTART: u8 = 1 << 7;
+// 读写时寄存器地址自动递增，一次就能写完一个通道的 4 个寄存器
+const MODE1_AI: u8 = 1 << 5;
+const MODE1_SLEEP: u8 = 1 << 4;
+const MODE1_ALLCALL: u8 = 1 << 0;
+
+// 推挽输出，驱动舵机的信号线或者 LED 都需要
+const MODE2_OUTDRV: u8 = 1 << 2;
+
+// ON_H/OFF_H 的第 4 位
+const FULL: u16 = 1 << 12;
+
+// 通用广播地址上的软件复位命令
+const GENERAL_CALL_ADDR: u8 = 0x00;
+const SWRST: u8 = 0x06;
+
+// embedded-hal 的 Error 要求实现 Debug，不受 fmt 特性控制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum PwmError {
+    // I2C 通信出错，比如地址没有被 ACK
+    Bus,
+    // 通道编号大于 15
+    InvalidChannel,
+    // 频率超出了 FREQ_MIN_HZ ~ FREQ_MAX_HZ，或者 ON/OFF 超过了 4095
+    OutOfRange,
+}
+
+impl From<MasterError> for PwmError {
+    fn from(_: MasterError) -> Self {
+        PwmError::Bus
+    }
+}
+
+impl pwm::Error for PwmError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+// 根据频率计算 PRE_SCALE，四舍五入
+pub(crate) const fn prescale(freq_hz: u32) -> Result<u8, PwmError> {
+    if freq_hz < FREQ_MIN_HZ || freq_hz > FREQ_MAX_HZ {
+        return Err(PwmError::OutOfRange);
+    }
+    let div = TICKS as u32 * freq_hz;
+    let prescale = (OSC_HZ + div / 2) / div - 1;
+    if prescale < PRESCALE_MIN || prescale > PRESCALE_MAX {
+        return Err(PwmError::OutOfRange);
+    }
+    Ok(prescale as u8)
+}
+
+// 舵机常用的 50 Hz 对应的 PRE_SCALE 为 121
+const _: () = assert!(matches!(prescale(50), Ok(121)));
+
+pub(crate) struct Pca9685<'a> {
+    i2c: &'a RegisterBlock,
+    addr: I2cAddress,
+    mode1: Cell<u8>,
+    prescale: Cell<u8>,
+}
+
+impl<'a> Pca9685<'a> {
+    // 复位芯片，设置频率，并唤醒
+    //
+    // 软件复位使用的是通用广播地址，总线上所有的 PCA9685 都会被复位
+    pub(crate) fn new(i2c: &'a RegisterBlock, addr: u8, freq_hz: u32) -> Result<Self, PwmError> {
+        let prescale = prescale(freq_hz)?;
+
+        blocking_master::write(i2c, I2cAddress::SevenBit(GENERAL_CALL_ADDR), &[SWRST])?;
+
+        // 复位之后 MODE1 为 SLEEP | ALLCALL
+        let dev = Self {
+            i2c,
+            addr: I2cAddress::SevenBit(addr),
+            mode1: Cell::new(MODE1_SLEEP | MODE1_ALLCALL),
+            prescale: Cell::new(prescale),
+        };
+
+        dev.write_mode1(MODE1_SLEEP | MODE1_AI | MODE1_ALLCALL)?;
+        dev.write_u8(REG_MODE2, MODE2_OUTDRV)?;
+        dev.write_u8(REG_PRE_SCALE, prescale)?;
+        dev.set_all(0, 0)?;
+        dev.wake()?;
+
+        Ok(dev)
+    }
+
+    // 实际的频率，与设置的频率会有一些误差
+    pub(crate) fn frequency(&self) -> u32 {
+        OSC_HZ / (TICKS as u32 * (self.prescale.get() as u32 + 1))
+    }
+
+    // 计数器一个 tick 对应的纳秒数，50 Hz 时约为 4.9 us
+    pub(crate) fn tick_ns(&self) -> u32 {
+        1_000_000_000 / (self.frequency() * TICKS as u32)
+    }
+
+    pub(crate) fn set_frequency(&self, freq_hz: u32) -> Result<(), PwmError> {
+        let prescale = prescale(freq_hz)?;
+        let sleeping = self.is_sleeping();
+
+        self.sleep()?;
+        self.write_u8(REG_PRE_SCALE, prescale)?;
+        self.prescale.set(prescale);
+        if !sleeping {
+            self.wake()?;
+        }
+        Ok(())
+    }
+
+    // 计数器等于 on 时输出高电平，等于 off 时输出低电平，两者都是 0 ~ 4095
+    pub(crate) fn set_channel(&self, channel: u8, on: u16, off: u16) -> Result<(), PwmError> {
+        check_channel(channel)?;
+        if on >= TICKS || off >= TICKS {
+            return Err(PwmError::OutOfRange);
+        }
+        self.write_on_off(channel_reg(channel), on, off)
+    }
+
+    // 持续输出高电平
+    pub(crate) fn set_full_on(&self, channel: u8) -> Result<(), PwmError> {
+        check_channel(channel)?;
+        self.write_on_off(channel_reg(channel), FULL, 0)
+    }
+
+    // 持续输出低电平
+    pub(crate) fn set_full_off(&self, channel: u8) -> Result<(), PwmError> {
+        check_channel(channel)?;
+        self.write_on_off(channel_reg(channel), 0, FULL)
+    }
+
+    // 以 phase 为上升沿的位置，输出 duty / 4096 的占空比
+    // duty 为 0 与 4096 时分别使用 full off 与 full on
+    pub(crate) fn set_duty(&self, channel: u8, phase: u16, duty: u16) -> Result<(), PwmError> {
+        check_channel(channel)?;
+        if phase >= TICKS || duty > TICKS {
+            return Err(PwmError::OutOfRange);
+        }
+        let (on, off) = match duty {
+            0 => (0, FULL),
+            TICKS => (FULL, 0),
+            _ => (phase, (phase + duty) % TICKS),
+        };
+        self.write_on_off(channel_reg(channel), on, off)
+    }
+
+    // 同时设置全部 16 个通道
+    pub(crate) fn set_all(&self, on: u16, off: u16) -> Result<(), PwmError> {
+        if on >= TICKS || off >= TICKS {
+            return Err(PwmError::OutOfRange);
+        }
+        self.write_on_off(REG_ALL_LED_ON_L, on, off)
+    }
+
+    pub(crate) fn set_all_off(&self) -> Result<(), PwmError> {
+        self.write_on_off(REG_ALL_LED_ON_L, 0, FULL)
+    }
+
+    // 打开/关闭 all-call，addr 为 7 位地址
+    pub(crate) fn set_all_call(&self, enable: bool, addr: u8) -> Result<(), PwmError> {
+        if enable {
+            // ALLCALLADR 中存放的是 8 位的写地址
+            self.write_u8(REG_ALLCALLADR, addr << 1)?;
+            self.write_mode1(self.mode1.get() | MODE1_ALLCALL)
+        } else {
+            self.write_mode1(self.mode1.get() & !MODE1_ALLCALL)
+        }
+    }
+
+    pub(crate) fn is_sleeping(&self) -> bool {
+        self.mode1.get() & MODE1_SLEEP != 0
+    }
+
+    // 停止振荡器，所有通道停止输出，功耗降到几 uA
+    pub(crate) fn sleep(&self) -> Result<(), PwmError> {
+        self.write_mode1(self.mode1.get() | MODE1_SLEEP)
+    }
+
+    pub(crate) fn wake(&self) -> Result<(), PwmError> {
+        // RESTART 只能读取芯片得到，副本中并不记录
+        let mode1 = self.read_u8(REG_MODE1)?;
+        self.write_mode1(self.mode1.get() & !MODE1_SLEEP)?;
+        // 等待振荡器稳定
+        ticker::delay_ms(1);
+        if mode1 & MODE1_RESTART != 0 {
+            // 写 1 清除 RESTART，同时恢复进入 SLEEP 之前的输出
+            self.write_u8(REG_MODE1, self.mode1.get() | MODE1_RESTART)?;
+        }
+        Ok(())
+    }
+
+    // 单个通道，占空比的分辨率为 4096
+    pub(crate) fn channel(&self, channel: u8) -> Result<Pca9685Channel<'_>, PwmError> {
+        check_channel(channel)?;
+        Ok(Pca9685Channel {
+            dev: self,
+            channel,
+            phase: 0,
+        })
+    }
+
+    fn write_mode1(&self, mode1: u8) -> Result<(), PwmError> {
+        // RESTART 是写 1 清除的，副本中不能带上它
+        let mode1 = mode1 & !MODE1_RESTART;
+        self.write_u8(REG_MODE1, mode1)?;
+        self.mode1.set(mode1);
+        Ok(())
+    }
+
+    fn write_on_off(&self, reg: u8, on: u16, off: u16) -> Result<(), PwmError> {
+        let [on_l, on_h] = on.to_le_bytes();
+        let [off_l, off_h] = off.to_le_bytes();
+        blocking_master::write(self.i2c, self.addr, &[reg, on_l, on_h, off_l, off_h])?;
+        Ok(())
+    }
+
+    fn write_u8(&self, reg: u8, value: u8) -> Result<(), PwmError> {
+        blocking_master::write(self.i2c, self.addr, &[reg, value])?;
+        Ok(())
+    }
+
+    fn read_u8(&self, reg: u8) -> Result<u8, PwmError> {
+        let mut buf = [0u8];
+        blocking_master::write_read(self.i2c, self.addr, &[reg], &mut buf)?;
+        Ok(buf[0])
+    }
+}
+
+fn check_channel(channel: u8) -> Result<(), PwmError> {
+    if channel >= CHANNEL_COUNT {
+        return Err(PwmError::InvalidChannel);
+    }
+    Ok(())
+}
+
+fn channel_reg(channel: u8) -> u8 {
+    REG_LED0_ON_L + 4 * channel
+}
+
+// PCA9685 的单个通道
+pub(crate) struct Pca9685Channel<'d> {
+    dev: &'d Pca9685<'d>,
+    channel: u8,
+    phase: u16,
+}
+
+impl Pca9685Channel<'_> {
+    // 上升沿的位置，同一块芯片上的多个舵机可以各自错开一些，避免同时启动
+    pub(crate) fn set_phase(&mut self, phase: u16) {
+        self.phase = phase % TICKS;
+    }
+
+    pub(crate) fn channel(&self) -> u8 {
+        self.channel
+    }
+}
+
+impl ErrorType for Pca9685Channel<'_> {
+    type Error = PwmError;
+}
+
+impl SetDutyCycle for Pca9685Channel<'_> {
+    fn max_duty_cycle(&self) -> u16 {
+        TICKS
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        self.dev.set_duty(self.channel, self.phase, duty)
+    }
+}
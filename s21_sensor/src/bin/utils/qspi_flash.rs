@@ -0,0 +1,91 @@
+//! 通过 QUADSPI 访问 W25Q 系列 SPI Flash，作为 utils::datalog 的存储
+//!
+//! 命令的格式与等待方式都来自 s19（见 utils::command 与 utils::auto_poll），这里全部使用单线命令：
+//! 记录只有 32 字节，四线模式省下的那点时间不值得为此去设置 Quad Enable 位
+//!
+//! 擦除只发出命令，并不等待完成，下一次访问 Flash 之前才会等待 BUSY 位清零，
+//! 这样 datalog 可以提前擦除下一个扇区，擦除期间 CPU 可以继续采样
+//!
+//! QUADSPI 的时钟、引脚与 DCR 需要事先配置好，见 s21c03
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::QUADSPI;
+
+use super::{
+    auto_poll,
+    command::{run_blocking, w25q, write_ccr, write_dr_u8, Command, FunctionalMode, Lines},
+    datalog::LogFlash,
+};
+
+// Read Data，单线，没有空指令周期
+const READ_DATA: Command = Command::instruction_only(0x03)
+    .with_address(Lines::Single)
+    .with_data(Lines::Single);
+// Page Program，单线，一次最多写入 256 字节，且不能跨页
+const PAGE_PROGRAM: Command = Command::instruction_only(0x02)
+    .with_address(Lines::Single)
+    .with_data(Lines::Single);
+
+// QUADSPI 的 FIFO 深度为 32 字节
+const FIFO_DEPTH: u8 = 32;
+
+pub(crate) struct QspiFlash<'a> {
+    qspi: &'a QUADSPI,
+}
+
+impl<'a> QspiFlash<'a> {
+    pub(crate) fn new(qspi: &'a QUADSPI) -> Self {
+        Self { qspi }
+    }
+
+    fn write_enable(&self) {
+        run_blocking(self.qspi, &w25q::WRITE_ENABLE, 0, None);
+        auto_poll::run_blocking(
+            self.qspi,
+            &w25q::READ_STATUS_1,
+            &auto_poll::W25Q_WRITE_ENABLED,
+        );
+    }
+}
+
+impl LogFlash for QspiFlash<'_> {
+    const SECTOR_SIZE: u32 = 4096;
+    const PAGE_SIZE: u32 = w25q::PAGE_SIZE as u32;
+
+    fn wait_idle(&mut self) {
+        auto_poll::run_blocking(self.qspi, &w25q::READ_STATUS_1, &auto_poll::W25Q_NOT_BUSY);
+    }
+
+    fn read(&mut self, addr: u32, buf: &mut [u8]) {
+        self.wait_idle();
+        run_blocking(self.qspi, &READ_DATA, addr, Some(buf));
+    }
+
+    fn program(&mut self, addr: u32, data: &[u8]) {
+        self.wait_idle();
+        self.write_enable();
+
+        let qspi = self.qspi;
+        write_ccr(
+            qspi,
+            &PAGE_PROGRAM,
+            FunctionalMode::IndirectWrite,
+            addr,
+            data.len(),
+        );
+        for &byte in data {
+            while qspi.sr.read().flevel().bits() >= FIFO_DEPTH {}
+            write_dr_u8(qspi, byte);
+        }
+        while qspi.sr.read().tcf().bit_is_clear() {}
+        qspi.fcr.write(|w| w.ctcf().set_bit());
+        while qspi.sr.read().busy().bit_is_set() {}
+    }
+
+    fn start_erase(&mut self, addr: u32) {
+        self.wait_idle();
+        self.write_enable();
+        run_blocking(self.qspi, &w25q::SECTOR_ERASE_4K, addr, None);
+    }
+}
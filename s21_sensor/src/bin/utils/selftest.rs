@@ -0,0 +1,169 @@
+//! 上电自检，用于板子的调试（bring-up）以及产线测试
+//!
+//! 每一项测试都是一个 TestCase：名称、错误码，以及一个运行函数
+//! 运行函数返回 Err(detail) 表示失败，detail 的含义由各项测试自己决定，比如出错的地址、读到的数值
+//!
+//! run 依次运行各项测试，每一项的结果同时输出到 RTT 与串口（任意实现了 core::fmt::Write 的对象），
+//! 最后得到的 Summary 还可以驱动一个 LED，这样没有接调试器和串口的时候，也能看出结果：
+//!
+//! - 全部通过：LED 以 1 Hz 均匀闪烁
+//! - 有失败：LED 快闪 N 次，然后熄灭 2 s，如此循环，N 为第一个失败项的错误码
+//!
+//! 因此错误码应该在 1 ~ 9 之间，太多次的快闪数不清楚
+
+#![allow(dead_code)]
+
+use core::fmt::{self, Write};
+
+use rtt_target::rprintln;
+
+use super::ticker;
+
+pub(crate) struct TestCase<C> {
+    pub(crate) name: &'static str,
+    // 失败时串口报告中的错误码，也是 LED 快闪的次数
+    pub(crate) code: u8,
+    pub(crate) run: fn(&C) -> Result<(), u32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Summary {
+    pub(crate) passed: u8,
+    pub(crate) failed: u8,
+    // 第一个失败项的错误码
+    pub(crate) first_failure: Option<u8>,
+}
+
+// 快闪时每一次亮、灭的时长
+const BLINK_MS: u32 = 200;
+// 两组快闪之间的停顿
+const PAUSE_MS: u32 = 2000;
+
+impl Summary {
+    pub(crate) fn all_passed(&self) -> bool {
+        self.failed == 0
+    }
+
+    // 按照当前的时间，LED 是否应该点亮
+    pub(crate) fn led_on(&self, now_ms: u32) -> bool {
+        match self.first_failure {
+            None => now_ms % 1000 < 500,
+            Some(code) => {
+                let blinks = code as u32 * 2 * BLINK_MS;
+                let t = now_ms % (blinks + PAUSE_MS);
+                t < blinks && t % (2 * BLINK_MS) < BLINK_MS
+            }
+        }
+    }
+}
+
+// 同时输出到 RTT 与串口
+fn emit(out: &mut impl Write, args: fmt::Arguments) {
+    rprintln!("{}", args);
+    writeln!(out, "{}\r", args).ok();
+}
+
+// 运行名称与 filter 相同的测试，filter 为 None 时运行全部测试
+pub(crate) fn run<C>(
+    cases: &[TestCase<C>],
+    ctx: &C,
+    filter: Option<&str>,
+    out: &mut impl Write,
+) -> Summary {
+    let mut summary = Summary::default();
+
+    for case in cases
+        .iter()
+        .filter(|case| filter.is_none_or(|name| name == case.name))
+    {
+        let start = ticker::millis();
+        let result = (case.run)(ctx);
+        let elapsed = ticker::millis().wrapping_sub(start);
+
+        match result {
+            Ok(()) => {
+                summary.passed += 1;
+                emit(out, format_args!("[PASS] {} ({} ms)", case.name, elapsed));
+            }
+            Err(detail) => {
+                summary.failed += 1;
+                summary.first_failure.get_or_insert(case.code);
+                emit(
+                    out,
+                    format_args!(
+                        "[FAIL] {} E{} detail 0x{:08X} ({} ms)",
+                        case.name, case.code, detail, elapsed
+                    ),
+                );
+            }
+        }
+    }
+
+    if summary == Summary::default() {
+        emit(out, format_args!("selftest: no such test"));
+        return summary;
+    }
+
+    match summary.first_failure {
+        None => emit(
+            out,
+            format_args!("selftest: {} passed, PASS", summary.passed),
+        ),
+        Some(code) => emit(
+            out,
+            format_args!(
+                "selftest: {} passed, {} failed, FAIL E{}",
+                summary.passed, summary.failed, code
+            ),
+        ),
+    }
+
+    summary
+}
+
+// March C- 内存测试，可以发现 stuck-at、相邻单元的耦合以及地址译码错误
+//
+// {⇑(w0); ⇑(r0,w1); ⇑(r1,w0); ⇓(r0,w1); ⇓(r1,w0); ⇓(r0)}
+//
+// 这里以字为单位，0 与 1 分别为 0x0000_0000 与 0xFFFF_FFFF，失败时返回出错的地址
+// 测试会覆盖 area 中原有的内容
+pub(crate) fn march_c(area: &mut [u32]) -> Result<(), u32> {
+    const ZERO: u32 = 0;
+    const ONE: u32 = !0;
+
+    let len = area.len();
+    let base = area.as_mut_ptr();
+
+    // 必须使用 volatile 读写，否则编译器会直接把写入的值当作读到的值
+    let read = |i: usize| unsafe { base.add(i).read_volatile() };
+    let write = |i: usize, value: u32| unsafe { base.add(i).write_volatile(value) };
+    let check = |i: usize, expected: u32| {
+        if read(i) == expected {
+            Ok(())
+        } else {
+            Err(base.wrapping_add(i) as u32)
+        }
+    };
+
+    for i in 0..len {
+        write(i, ZERO);
+    }
+    for (expected, value) in [(ZERO, ONE), (ONE, ZERO)] {
+        for i in 0..len {
+            check(i, expected)?;
+            write(i, value);
+        }
+    }
+    for (expected, value) in [(ZERO, ONE), (ONE, ZERO)] {
+        for i in (0..len).rev() {
+            check(i, expected)?;
+            write(i, value);
+        }
+    }
+    for i in (0..len).rev() {
+        check(i, ZERO)?;
+    }
+
+    Ok(())
+}
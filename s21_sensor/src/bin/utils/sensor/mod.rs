@@ -0,0 +1,159 @@
+//! 通用的传感器接口
+//!
+//! 每个传感器驱动只需要实现 Sensor trait，说明自己叫什么、一次采样会得到什么样的数据，
+//! 之后就可以把它注册到 Scheduler 里，由 Scheduler 按照各自的采样间隔调用，并把结果分发给各个 Sink
+
+#![allow(dead_code)]
+
+pub(crate) mod scheduler;
+pub(crate) mod sink;
+pub(crate) mod sonar_array;
+pub(crate) mod ultrasonic;
+
+// 传感器采样可能出现的错误
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum SensorError {
+    // 传感器还没有准备好数据，比如还在转换中
+    NotReady,
+    // 等待传感器响应超时
+    Timeout,
+    // 读到的数据超出了传感器的有效范围
+    OutOfRange,
+    // 总线通信出错，比如 I2C 没有收到 ACK
+    Bus,
+    // 数据校验失败
+    Checksum,
+    // 传感器不支持所请求的功能，比如 SHT4x 没有周期测量模式
+    Unsupported,
+    // 传感器自己报告了故障，比如热电偶开路或者短路
+    Fault,
+}
+
+impl SensorError {
+    // 用于日志与 CSV 记录，与 Debug 的输出相同，但不依赖 fmt 特性
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            SensorError::NotReady => "NotReady",
+            SensorError::Timeout => "Timeout",
+            SensorError::OutOfRange => "OutOfRange",
+            SensorError::Bus => "Bus",
+            SensorError::Checksum => "Checksum",
+            SensorError::Unsupported => "Unsupported",
+            SensorError::Fault => "Fault",
+        }
+    }
+}
+
+// 单个物理量的读数，这是 Sink 实际接收的数据
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Reading {
+    // 物理量的名称，比如 "temp"
+    pub(crate) quantity: &'static str,
+    pub(crate) value: f32,
+    // 单位，比如 "C"
+    pub(crate) unit: &'static str,
+}
+
+// 一次采样的结果，可能会包含多个物理量（比如温湿度传感器一次就返回温度和湿度）
+// 因此这里要求采样结果能逐个吐出其中包含的读数
+pub(crate) trait Measurement {
+    fn for_each_reading(&self, f: &mut dyn FnMut(Reading));
+}
+
+pub(crate) trait Sensor {
+    // 一次采样的结果类型
+    type Output: Measurement;
+
+    // 传感器的名字，会出现在日志和 LCD 上，最好短一点
+    fn name(&self) -> &'static str;
+
+    // 执行一次采样
+    fn sample(&mut self) -> Result<Self::Output, SensorError>;
+}
+
+// Scheduler 需要把各种不同 Output 的传感器放在同一个数组里，
+// 因此我们需要一个不带关联类型的 trait，来抹去 Output 的具体类型
+pub(crate) trait DynSensor {
+    fn name(&self) -> &'static str;
+    fn sample_readings(&mut self, f: &mut dyn FnMut(Reading)) -> Result<(), SensorError>;
+}
+
+impl<S: Sensor> DynSensor for S {
+    fn name(&self) -> &'static str {
+        Sensor::name(self)
+    }
+
+    fn sample_readings(&mut self, f: &mut dyn FnMut(Reading)) -> Result<(), SensorError> {
+        self.sample()?.for_each_reading(f);
+        Ok(())
+    }
+}
+
+// 下面是几个常用的带单位的物理量，传感器驱动可以直接拿来当作 Output
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Celsius(pub(crate) f32);
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Volt(pub(crate) f32);
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Millimeter(pub(crate) f32);
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct RelativeHumidity(pub(crate) f32);
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Pascal(pub(crate) f32);
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Lux(pub(crate) f32);
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Degree(pub(crate) f32);
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct DegreePerSecond(pub(crate) f32);
+
+macro_rules! impl_single_measurement {
+    ($t:ty, $quantity:literal, $unit:literal) => {
+        impl Measurement for $t {
+            fn for_each_reading(&self, f: &mut dyn FnMut(Reading)) {
+                f(Reading {
+                    quantity: $quantity,
+                    value: self.0,
+                    unit: $unit,
+                })
+            }
+        }
+    };
+}
+
+impl_single_measurement!(Celsius, "temp", "C");
+impl_single_measurement!(Volt, "volt", "V");
+impl_single_measurement!(Millimeter, "dist", "mm");
+impl_single_measurement!(RelativeHumidity, "rh", "%");
+impl_single_measurement!(Pascal, "press", "Pa");
+impl_single_measurement!(Lux, "light", "lx");
+impl_single_measurement!(Degree, "angle", "deg");
+impl_single_measurement!(DegreePerSecond, "speed", "deg/s");
@@ -0,0 +1,111 @@
+//! 按固定间隔轮询传感器的调度器
+//!
+//! Scheduler 本身并不持有时钟，每次调用 poll() 时由调用者给出当前的毫秒数（一般来自 utils::ticker::millis()）
+//! 这样 Scheduler 可以放在主循环里，也可以放在 TIM 的中断里运行
+
+#![allow(dead_code)]
+
+use super::{sink::Sink, DynSensor, SensorError};
+
+struct Slot<'a> {
+    sensor: &'a mut dyn DynSensor,
+    period_ms: u32,
+    next_due_ms: u32,
+    // 连续出错的次数，Sink 可以据此决定是否要显示错误
+    error_cnt: u32,
+}
+
+pub(crate) struct Scheduler<'a, const N: usize> {
+    slots: [Option<Slot<'a>>; N],
+}
+
+impl<'a, const N: usize> Scheduler<'a, N> {
+    pub(crate) fn new() -> Self {
+        Self {
+            slots: core::array::from_fn(|_| None),
+        }
+    }
+
+    // 注册一个传感器，让它每 period_ms 毫秒采样一次，第一次采样发生在 first_due_ms
+    // 如果所有的位置都已经被占用，则把传感器原样返回给调用者
+    pub(crate) fn register(
+        &mut self,
+        sensor: &'a mut dyn DynSensor,
+        period_ms: u32,
+        first_due_ms: u32,
+    ) -> Result<(), &'a mut dyn DynSensor> {
+        match self.slots.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                slot.replace(Slot {
+                    sensor,
+                    period_ms,
+                    next_due_ms: first_due_ms,
+                    error_cnt: 0,
+                });
+                Ok(())
+            }
+            None => Err(sensor),
+        }
+    }
+
+    // 修改指定传感器的采样间隔，从下一次采样之后开始生效，返回是否找到了该传感器
+    pub(crate) fn set_period(&mut self, sensor_name: &str, period_ms: u32) -> bool {
+        match self
+            .slots
+            .iter_mut()
+            .flatten()
+            .find(|slot| slot.sensor.name() == sensor_name)
+        {
+            Some(slot) => {
+                slot.period_ms = period_ms;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 检查所有的传感器，对到期的传感器执行采样，并把读数分发给所有的 Sink
+    // 最后调用一次每个 Sink 的 flush()，让 Sink 有机会做一些周期性的工作（比如 LCD 翻页）
+    pub(crate) fn poll(&mut self, now_ms: u32, sinks: &mut [&mut dyn Sink]) {
+        for slot in self.slots.iter_mut().flatten() {
+            // 计时器是会回绕的，因此这里不能直接比较大小，而是看两者的差值的符号
+            if (now_ms.wrapping_sub(slot.next_due_ms) as i32) < 0 {
+                continue;
+            }
+
+            // 以“上一次应该采样的时刻”为基准计算下一次的时刻，而不是以 now_ms 为基准，防止采样间隔逐渐漂移
+            // 不过如果已经落后了一整个周期以上（比如主循环被阻塞了很久），就直接从现在开始重新计算
+            slot.next_due_ms = slot.next_due_ms.wrapping_add(slot.period_ms);
+            if (now_ms.wrapping_sub(slot.next_due_ms) as i32) >= 0 {
+                slot.next_due_ms = now_ms.wrapping_add(slot.period_ms);
+            }
+
+            let name = slot.sensor.name();
+            let result = slot.sensor.sample_readings(&mut |reading| {
+                for sink in sinks.iter_mut() {
+                    sink.publish(now_ms, name, &reading);
+                }
+            });
+
+            match result {
+                Ok(()) => slot.error_cnt = 0,
+                Err(e) => {
+                    slot.error_cnt += 1;
+                    for sink in sinks.iter_mut() {
+                        sink.error(now_ms, name, e, slot.error_cnt);
+                    }
+                }
+            }
+        }
+
+        for sink in sinks.iter_mut() {
+            sink.flush(now_ms);
+        }
+    }
+}
+
+// 方便 Sink 使用的一个辅助函数，判定错误是否值得报告
+// 偶尔出现一次 NotReady 是正常的，连续出现才需要报告
+pub(crate) fn worth_reporting(error: SensorError, error_cnt: u32) -> bool {
+    error != SensorError::NotReady || error_cnt >= 3
+}
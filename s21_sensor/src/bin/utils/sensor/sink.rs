@@ -0,0 +1,276 @@
+//! 传感器读数的去向
+//!
+//! Scheduler 得到读数之后，会依次交给每一个 Sink，Sink 自己决定如何处理这些读数
+//!
+//! 这里提供了三种 Sink：
+//! RttSink：直接通过 RTT 打印到调试器
+//! LcdPageSink：把最新的读数记下来，按页轮流显示在 2x16 的屏幕上
+//! TelemetrySink：把读数整理成一行一行的文本，通过任意可以逐字节发送的接口发送出去（USART、USB CDC 等）
+
+#![allow(dead_code)]
+
+use core::fmt::Write;
+
+use rtt_target::rprintln;
+
+use super::{scheduler::worth_reporting, Reading, SensorError};
+
+pub(crate) trait Sink {
+    // 接收一个读数
+    fn publish(&mut self, now_ms: u32, sensor_name: &'static str, reading: &Reading);
+
+    // 接收一次采样失败的通知，error_cnt 为连续失败的次数
+    fn error(
+        &mut self,
+        _now_ms: u32,
+        _sensor_name: &'static str,
+        _error: SensorError,
+        _error_cnt: u32,
+    ) {
+    }
+
+    // 每次 Scheduler::poll() 的末尾都会调用一次
+    fn flush(&mut self, _now_ms: u32) {}
+}
+
+pub(crate) struct RttSink;
+
+impl Sink for RttSink {
+    fn publish(&mut self, now_ms: u32, sensor_name: &'static str, reading: &Reading) {
+        rprintln!(
+            "[{:>8}] {}.{} = {:.2} {}",
+            now_ms,
+            sensor_name,
+            reading.quantity,
+            reading.value,
+            reading.unit
+        );
+    }
+
+    fn error(
+        &mut self,
+        now_ms: u32,
+        sensor_name: &'static str,
+        error: SensorError,
+        error_cnt: u32,
+    ) {
+        if worth_reporting(error, error_cnt) {
+            rprintln!(
+                "[{:>8}] {} error: {} x{}",
+                now_ms,
+                sensor_name,
+                error.name(),
+                error_cnt
+            );
+        }
+    }
+}
+
+// 一个固定长度的行缓冲，实现了 fmt::Write，这样我们可以在没有堆的情况下使用 write!() 来格式化文本
+// 超出长度的部分会被直接丢弃
+pub(crate) struct LineBuf<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> LineBuf<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl<const N: usize> Write for LineBuf<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let copy_len = s.len().min(N - self.len);
+        self.buf[self.len..self.len + copy_len].copy_from_slice(&s.as_bytes()[..copy_len]);
+        self.len += copy_len;
+        Ok(())
+    }
+}
+
+// 能够显示两行文本的屏幕，比如 LCD1602
+pub(crate) trait TextPanel {
+    const COLUMNS: usize;
+
+    // 在指定的行上显示文本，不足一行的部分应该用空格补齐
+    fn write_line(&mut self, row: u8, text: &[u8]);
+}
+
+#[derive(Clone, Copy)]
+struct PageEntry {
+    sensor_name: &'static str,
+    reading: Reading,
+    // 该条目是否处于错误状态
+    failed: bool,
+}
+
+// 每一页显示一个传感器的一个物理量
+// 第一行为 传感器名.物理量名，第二行为 数值 单位
+// N 为最多能记住的条目数量
+pub(crate) struct LcdPageSink<P: TextPanel, const N: usize> {
+    panel: P,
+    entries: [Option<PageEntry>; N],
+    page_period_ms: u32,
+    next_page_ms: u32,
+    current_page: usize,
+}
+
+impl<P: TextPanel, const N: usize> LcdPageSink<P, N> {
+    pub(crate) fn new(panel: P, page_period_ms: u32) -> Self {
+        Self {
+            panel,
+            entries: [None; N],
+            page_period_ms,
+            next_page_ms: 0,
+            current_page: 0,
+        }
+    }
+
+    // 找到已有的条目，如果没有，就占用一个空位
+    fn entry_mut(
+        &mut self,
+        sensor_name: &'static str,
+        quantity: &'static str,
+    ) -> Option<&mut Option<PageEntry>> {
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| {
+                entry.is_some_and(|entry| {
+                    entry.sensor_name == sensor_name && entry.reading.quantity == quantity
+                })
+            })
+            .or_else(|| self.entries.iter().position(|entry| entry.is_none()))?;
+        Some(&mut self.entries[index])
+    }
+
+    fn draw_page(&mut self, index: usize) {
+        let Some(entry) = self.entries[index] else {
+            return;
+        };
+
+        let mut line = LineBuf::<32>::new();
+
+        write!(line, "{}.{}", entry.sensor_name, entry.reading.quantity).ok();
+        self.panel.write_line(0, line.as_bytes());
+
+        line.clear();
+        if entry.failed {
+            write!(line, "--- {}", entry.reading.unit).ok();
+        } else {
+            write!(line, "{:.2} {}", entry.reading.value, entry.reading.unit).ok();
+        }
+        self.panel.write_line(1, line.as_bytes());
+    }
+}
+
+impl<P: TextPanel, const N: usize> Sink for LcdPageSink<P, N> {
+    fn publish(&mut self, _now_ms: u32, sensor_name: &'static str, reading: &Reading) {
+        if let Some(slot) = self.entry_mut(sensor_name, reading.quantity) {
+            slot.replace(PageEntry {
+                sensor_name,
+                reading: *reading,
+                failed: false,
+            });
+        }
+    }
+
+    fn error(
+        &mut self,
+        _now_ms: u32,
+        sensor_name: &'static str,
+        error: SensorError,
+        error_cnt: u32,
+    ) {
+        if !worth_reporting(error, error_cnt) {
+            return;
+        }
+        // 只标记已有的条目，一个从来没有成功过的传感器，我们也不知道它有哪些物理量
+        for entry in self.entries.iter_mut().flatten() {
+            if entry.sensor_name == sensor_name {
+                entry.failed = true;
+            }
+        }
+    }
+
+    // 翻页放在 flush 里处理，而不是每收到一个读数就刷新一次屏幕，LCD1602 的刷新是比较慢的
+    fn flush(&mut self, now_ms: u32) {
+        if (now_ms.wrapping_sub(self.next_page_ms) as i32) < 0 {
+            return;
+        }
+        self.next_page_ms = now_ms.wrapping_add(self.page_period_ms);
+
+        let page_cnt = self.entries.iter().flatten().count();
+        if page_cnt == 0 {
+            return;
+        }
+
+        // 条目总是从前往后占用的，因此有效的页就是前 page_cnt 个
+        self.current_page = (self.current_page + 1) % page_cnt;
+        self.draw_page(self.current_page);
+    }
+}
+
+// 可以逐字节发送数据的接口
+pub(crate) trait ByteWrite {
+    fn write_bytes(&mut self, bytes: &[u8]);
+}
+
+// 每个读数输出一行文本，格式为
+// T,<毫秒>,<传感器名>,<物理量名>,<数值>,<单位>\n
+// 采样失败时输出
+// E,<毫秒>,<传感器名>,<错误>,<连续失败次数>\n
+// 这样的格式可以直接被 Host 端的串口工具保存为 CSV
+pub(crate) struct TelemetrySink<W: ByteWrite> {
+    writer: W,
+}
+
+impl<W: ByteWrite> TelemetrySink<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: ByteWrite> Sink for TelemetrySink<W> {
+    fn publish(&mut self, now_ms: u32, sensor_name: &'static str, reading: &Reading) {
+        let mut line = LineBuf::<64>::new();
+        writeln!(
+            line,
+            "T,{},{},{},{:.3},{}",
+            now_ms, sensor_name, reading.quantity, reading.value, reading.unit
+        )
+        .ok();
+        self.writer.write_bytes(line.as_bytes());
+    }
+
+    fn error(
+        &mut self,
+        now_ms: u32,
+        sensor_name: &'static str,
+        error: SensorError,
+        error_cnt: u32,
+    ) {
+        let mut line = LineBuf::<64>::new();
+        writeln!(
+            line,
+            "E,{},{},{},{}",
+            now_ms,
+            sensor_name,
+            error.name(),
+            error_cnt
+        )
+        .ok();
+        self.writer.write_bytes(line.as_bytes());
+    }
+}
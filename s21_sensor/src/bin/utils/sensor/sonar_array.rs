@@ -0,0 +1,385 @@
+//! 多个超声波模块（US-100 脉冲模式 / HC-SR04）轮流测距，合成为一个“最近障碍物”
+//!
+//! 机器人的保险杠上往往要装好几个超声波模块，分别朝向左前、正前、右前，
+//! 如果它们同时触发，A 发出的声波被障碍物反射之后，可能先到达 B，B 就把别人的回波当成了自己的，量出一个偏近的距离（串扰）
+//! 同一个模块连续测量时也有类似的问题：上一次的声波在房间里来回反射，还没有散去，就被下一次当成回波
+//!
+//! 因此这里的 SonarArray 让模块按顺序轮流测量，同一时刻只有一个模块在发声：
+//!
+//! 1. 触发当前模块，等待它的 Echo 结束，或者超过由它的量程决定的时间
+//! 2. 之后保持安静 quiet_ms，让这一次的声波散去
+//! 3. 轮到下一个模块
+//!
+//! 每个模块的引脚、朝向、量程、安静时间都写在一张 SonarConfig 的表中，由使用者给出（见 s21c27）
+//! 量程越小，等待回波的时间越短，一轮也就越快；安静时间则与环境有关，空旷的房间混响长，需要更长一点
+//!
+//! 与 utils::sensor::ultrasonic 中忙等待 Echo 不同，这里 Echo 的两个边沿由 EXTI 中断记录时间戳（见 utils::exti），
+//! poll 只是检查状态，不会阻塞，可以和别的工作一起放在主循环中；时间戳来自 utils::ticker，所有模块共用这一个定时器，
+//! 而不是每个模块占用一个 TIM 的输入捕获通道，这样 Echo 可以接在任意的引脚上
+//!
+//! 每个模块最近一次的结果保存在 SonarArray 中，fused 把它们合成为一个输出：
+//! 未过期的读数中距离最近的那个，以及有多少个模块“看不见”（出错或者读数过期），
+//! 对于保险杠来说，看不见的方向不能当作没有障碍物
+//!
+//! 限制：
+//! 1. Echo 使用的 EXTI 线为 4 ~ 15，且引脚编号不能相同（见 utils::exti）
+//! 2. EXTI 回调使用的状态是 static，同一时刻只能有一个 SonarArray
+
+#![allow(dead_code)]
+
+use core::{
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{AtomicU32, AtomicU8, Ordering},
+};
+
+use stm32f4xx_hal::pac;
+
+use super::{
+    ultrasonic::{Air, NOMINAL_SPEED_MM_PER_US},
+    Measurement, Millimeter, Reading, SensorError,
+};
+use crate::utils::{
+    exti::{self, ExtiError, Port, Trigger},
+    ticker,
+};
+
+// GPIO 端口寄存器的地址，见 Reference Manual 的 Memory map
+const GPIO_BASE: u32 = 0x4002_0000;
+const GPIO_STRIDE: u32 = 0x400;
+const GPIO_MODER: u32 = 0x00;
+const GPIO_PUPDR: u32 = 0x0C;
+const GPIO_IDR: u32 = 0x10;
+const GPIO_BSRR: u32 = 0x18;
+
+// 从触发到 Echo 拉高之前的准备时间（发出 8 个 40 kHz 的脉冲等），两种模块都在 0.5 ms 左右，这里留一些余量
+const ECHO_START_US: u64 = 1_000;
+// Trig 高电平的时长，至少 10 us
+const TRIG_US: u64 = 12;
+
+// 正在测量的模块的 Echo 所在的 EXTI 线，NO_LINE 表示没有
+const NO_LINE: u8 = 0xFF;
+// Echo 还没有结束
+const NO_WIDTH: u32 = u32::MAX;
+
+static ACTIVE_LINE: AtomicU8 = AtomicU8::new(NO_LINE);
+// 每个 EXTI 线对应的端口，回调中据此读取 Echo 的电平
+static ECHO_PORTS: [AtomicU8; 16] = [const { AtomicU8::new(0) }; 16];
+// ticker::micros 的低 32 位，0 表示还没有看到上升沿
+static RISE_US: AtomicU32 = AtomicU32::new(0);
+static WIDTH_US: AtomicU32 = AtomicU32::new(NO_WIDTH);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Pin {
+    pub(crate) port: Port,
+    pub(crate) num: u8,
+}
+
+impl Pin {
+    pub(crate) const fn new(port: Port, num: u8) -> Self {
+        Self { port, num }
+    }
+
+    fn reg(self, offset: u32) -> *mut u32 {
+        (GPIO_BASE + GPIO_STRIDE * self.port as u32 + offset) as *mut u32
+    }
+
+    fn is_high(self) -> bool {
+        unsafe { read_volatile(self.reg(GPIO_IDR)) & (1 << self.num) != 0 }
+    }
+
+    fn set(self, high: bool) {
+        let bit = if high { self.num } else { self.num + 16 };
+        unsafe { write_volatile(self.reg(GPIO_BSRR), 1 << bit) };
+    }
+
+    // 两位一组的寄存器（MODER、PUPDR）
+    fn modify2(self, offset: u32, value: u32) {
+        let shift = self.num * 2;
+        unsafe {
+            let bits = read_volatile(self.reg(offset)) & !(0b11 << shift);
+            write_volatile(self.reg(offset), bits | (value << shift));
+        }
+    }
+}
+
+// 一个模块的配置
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct SonarConfig {
+    // 出现在日志中，最好短一点
+    pub(crate) name: &'static str,
+    pub(crate) trig: Pin,
+    // 编号为 4 ~ 15
+    pub(crate) echo: Pin,
+    // 安装的朝向，单位度，0 为正前方，左负右正
+    pub(crate) bearing_deg: i16,
+    // 只关心这个距离以内的障碍物，超过它就不再等待回波
+    pub(crate) max_range_mm: u16,
+    // 这个模块测量结束之后，下一个模块触发之前保持安静的时间
+    pub(crate) quiet_ms: u16,
+}
+
+impl SonarConfig {
+    // 等待回波的最长时间，按 0 ℃ 左右的声速（最慢）计算，保证量程之内的回波都能等到
+    fn echo_timeout_us(&self) -> u64 {
+        ECHO_START_US + (2.0 * self.max_range_mm as f32 / NOMINAL_SPEED_MM_PER_US) as u64
+    }
+}
+
+// 一个模块最近一次的结果
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+struct Track {
+    // None 表示还没有测量过
+    last: Option<Result<Millimeter, SensorError>>,
+    updated_us: u64,
+    ok_cnt: u32,
+    error_cnt: u32,
+}
+
+// 合成的输出中，最近的障碍物
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Obstacle {
+    // 模块在配置表中的下标
+    pub(crate) index: usize,
+    pub(crate) name: &'static str,
+    pub(crate) bearing_deg: i16,
+    pub(crate) distance: Millimeter,
+}
+
+impl Measurement for Obstacle {
+    fn for_each_reading(&self, f: &mut dyn FnMut(Reading)) {
+        self.distance.for_each_reading(f);
+        f(Reading {
+            quantity: "bearing",
+            value: self.bearing_deg as f32,
+            unit: "deg",
+        });
+    }
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Fused {
+    // 量程之内最近的障碍物，None 表示所有看得见的方向都没有障碍物
+    pub(crate) nearest: Option<Obstacle>,
+    // 出错或者读数过期的模块数
+    pub(crate) blind: usize,
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+enum Phase {
+    // 等待安静时间结束
+    Quiet { until_us: u64 },
+    // 已经触发了 index，等待它的回波
+    Listening { index: usize, triggered_us: u64 },
+}
+
+pub(crate) struct SonarArray<const N: usize> {
+    configs: &'static [SonarConfig; N],
+    tracks: [Track; N],
+    // 下一个要触发的模块
+    next: usize,
+    phase: Phase,
+    // 当前的声速，单位 mm/us
+    speed: f32,
+}
+
+impl<const N: usize> SonarArray<N> {
+    // 配置所有模块的引脚，并为每个 Echo 注册 EXTI 回调，需要事先调用 ticker::setup
+    pub(crate) fn new(
+        dp: &pac::Peripherals,
+        configs: &'static [SonarConfig; N],
+    ) -> Result<Self, ExtiError> {
+        for config in configs {
+            dp.RCC.ahb1enr.modify(|r, w| unsafe {
+                w.bits(r.bits() | 1 << config.trig.port as u32 | 1 << config.echo.port as u32)
+            });
+
+            config.trig.set(false);
+            // 01: 通用输出
+            config.trig.modify2(GPIO_MODER, 0b01);
+            // 10: 下拉，00: 输入，模块没有接上时 Echo 保持低电平
+            config.echo.modify2(GPIO_PUPDR, 0b10);
+            config.echo.modify2(GPIO_MODER, 0b00);
+
+            exti::register(
+                dp,
+                config.echo.port,
+                config.echo.num,
+                Trigger::Both,
+                on_echo,
+            )?;
+            ECHO_PORTS[config.echo.num as usize].store(config.echo.port as u8, Ordering::Relaxed);
+        }
+
+        Ok(Self {
+            configs,
+            tracks: [Track {
+                last: None,
+                updated_us: 0,
+                ok_cnt: 0,
+                error_cnt: 0,
+            }; N],
+            next: 0,
+            phase: Phase::Quiet { until_us: 0 },
+            speed: NOMINAL_SPEED_MM_PER_US,
+        })
+    }
+
+    // 按照气温（和湿度）修正声速，见 utils::sensor::ultrasonic
+    pub(crate) fn set_air(&mut self, air: Air) {
+        self.speed = air.speed_of_sound();
+    }
+
+    pub(crate) fn config(&self, index: usize) -> &SonarConfig {
+        &self.configs[index]
+    }
+
+    // 在主循环中反复调用，有模块测量结束时，返回它的下标与结果
+    pub(crate) fn poll(&mut self) -> Option<(usize, Result<Millimeter, SensorError>)> {
+        let now = ticker::micros();
+        match self.phase {
+            Phase::Quiet { until_us } => {
+                if now >= until_us {
+                    self.trigger(now);
+                }
+                None
+            }
+            Phase::Listening {
+                index,
+                triggered_us,
+            } => {
+                let width = WIDTH_US.load(Ordering::Acquire);
+                let result = if width != NO_WIDTH {
+                    // 回波走了一个来回，因此要除以 2
+                    let distance = width as f32 / 2.0 * self.speed;
+                    if distance > self.configs[index].max_range_mm as f32 {
+                        Err(SensorError::OutOfRange)
+                    } else {
+                        Ok(Millimeter(distance))
+                    }
+                } else if now - triggered_us > self.configs[index].echo_timeout_us() {
+                    if RISE_US.load(Ordering::Acquire) == 0 {
+                        // 连 Echo 的上升沿都没有，模块可能没有接好
+                        Err(SensorError::Timeout)
+                    } else {
+                        // 量程之内没有回波
+                        Err(SensorError::OutOfRange)
+                    }
+                } else {
+                    return None;
+                };
+
+                self.finish(index, result, now);
+                Some((index, result))
+            }
+        }
+    }
+
+    fn trigger(&mut self, now: u64) {
+        let index = self.next;
+        self.next = (index + 1) % N;
+        let config = &self.configs[index];
+
+        // 上一次测量的 Echo 还没有结束（比如 US-100 没有收到回波时，要 66 ms 左右才会拉低 Echo）
+        if config.echo.is_high() {
+            self.finish(index, Err(SensorError::NotReady), now);
+            return;
+        }
+
+        RISE_US.store(0, Ordering::Relaxed);
+        WIDTH_US.store(NO_WIDTH, Ordering::Relaxed);
+        ACTIVE_LINE.store(config.echo.num, Ordering::Release);
+
+        config.trig.set(true);
+        let start = ticker::micros();
+        while ticker::micros() - start < TRIG_US {}
+        config.trig.set(false);
+
+        self.phase = Phase::Listening {
+            index,
+            triggered_us: now,
+        };
+    }
+
+    fn finish(&mut self, index: usize, result: Result<Millimeter, SensorError>, now: u64) {
+        ACTIVE_LINE.store(NO_LINE, Ordering::Release);
+
+        let track = &mut self.tracks[index];
+        track.last = Some(result);
+        track.updated_us = now;
+        // 量程之内没有障碍物也是一个有效的结果
+        match result {
+            Ok(_) | Err(SensorError::OutOfRange) => track.ok_cnt += 1,
+            Err(_) => track.error_cnt += 1,
+        }
+
+        self.phase = Phase::Quiet {
+            until_us: now + self.configs[index].quiet_ms as u64 * 1000,
+        };
+    }
+
+    // 合成所有模块的最近一次结果，早于 max_age_ms 的读数视为过期
+    pub(crate) fn fused(&self, max_age_ms: u32) -> Fused {
+        let now = ticker::micros();
+        let mut fused = Fused {
+            nearest: None,
+            blind: 0,
+        };
+        for (index, track) in self.tracks.iter().enumerate() {
+            let fresh = now - track.updated_us <= max_age_ms as u64 * 1000;
+            match track.last {
+                Some(Ok(distance)) if fresh => {
+                    if fused.nearest.is_none_or(|o| distance.0 < o.distance.0) {
+                        let config = &self.configs[index];
+                        fused.nearest = Some(Obstacle {
+                            index,
+                            name: config.name,
+                            bearing_deg: config.bearing_deg,
+                            distance,
+                        });
+                    }
+                }
+                Some(Err(SensorError::OutOfRange)) if fresh => {}
+                _ => fused.blind += 1,
+            }
+        }
+        fused
+    }
+
+    // 每个模块的统计：名字、最近一次的结果、成功与出错的次数
+    pub(crate) fn for_each_stats(
+        &self,
+        mut f: impl FnMut(&SonarConfig, Option<Result<Millimeter, SensorError>>, u32, u32),
+    ) {
+        for (config, track) in self.configs.iter().zip(self.tracks.iter()) {
+            f(config, track.last, track.ok_cnt, track.error_cnt);
+        }
+    }
+}
+
+// Echo 的两个边沿都会调用，在中断中执行
+// 只记录正在测量的模块，其他模块的 Echo（比如上一次测量迟迟没有结束的那个）被忽略
+fn on_echo(line: u8) {
+    if line != ACTIVE_LINE.load(Ordering::Acquire) {
+        return;
+    }
+
+    let now = (ticker::micros() as u32).max(1);
+    let port = ECHO_PORTS[line as usize].load(Ordering::Relaxed);
+    let idr = (GPIO_BASE + GPIO_STRIDE * port as u32 + GPIO_IDR) as *const u32;
+    let high = unsafe { read_volatile(idr) } & (1 << line) != 0;
+
+    if high {
+        RISE_US.store(now, Ordering::Release);
+    } else {
+        let rise = RISE_US.load(Ordering::Acquire);
+        if rise != 0 && WIDTH_US.load(Ordering::Relaxed) == NO_WIDTH {
+            WIDTH_US.store(now.wrapping_sub(rise), Ordering::Release);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! 带温度补偿的超声波测距（US-100 的脉冲模式，或者 HC-SR04）
+//!
+//! s06c04 中，我们直接用 0.3314 mm/us 作为声速，但声速其实随着气温变化：
+//!
+//! c ≈ 331.3 + 0.606 * T (m/s)
+//!
+//! 0.3314 mm/us 只对应 0 ℃ 左右的空气，到了 25 ℃，声速约为 346.4 m/s，同样的回波时间，实际距离要远 4.5% 左右，
+//! 1 米处就差了 4 厘米多；空气的湿度也有一点影响，相对湿度每增加 1%，声速大约增加 0.0124 m/s（简化的经验公式）
+//!
+//! 因此这里的 Ultrasonic 在每次测距时，会先从一个 EnvironmentSource 读取当前的气温（和湿度），据此计算声速，
+//! 并同时给出“未补偿”（按 0.3314 mm/us）与“补偿后”两个距离，方便对比
+//!
+//! EnvironmentSource 是可以替换的：
+//! - utils::bme280::Bme280 同时提供气温与湿度
+//! - TemperatureSensor 可以把任何 Output 为 Celsius 的 Sensor（比如 DS18B20）包装为只提供气温的 EnvironmentSource
+//! - FixedAir 使用固定的气温，用于没有温度传感器的场合
+//!
+//! 环境读数失败时，使用上一次成功的读数；从来没有成功过的话，就按 0.3314 mm/us 计算
+//!
+//! 注意：测距时会忙等待回波结束，最长约 30 ms，超出量程时更长（见 ECHO_TIMEOUT_US）
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac;
+
+use super::{Celsius, Measurement, Millimeter, Reading, RelativeHumidity, Sensor, SensorError};
+use crate::utils::ticker;
+
+// s06c04 中使用的声速，单位 mm/us
+pub(crate) const NOMINAL_SPEED_MM_PER_US: f32 = 0.3314;
+
+// 从触发到 Echo 拉高的最长等待时间
+const ECHO_START_TIMEOUT_US: u64 = 5_000;
+// Echo 高电平的最长时间，US-100 测不到回波时，会在 66 ms 左右自行拉低 Echo，见 s06c04_us100_driver_02periodic
+const ECHO_TIMEOUT_US: u64 = 70_000;
+// 模块的有效量程
+const MAX_DISTANCE_MM: f32 = 4500.0;
+
+// 一次环境读数
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Air {
+    pub(crate) temp: Celsius,
+    // 不能测量湿度的传感器，给 None 即可
+    pub(crate) humidity: Option<RelativeHumidity>,
+}
+
+impl Air {
+    // 声速，单位 mm/us（数值上等于 km/s）
+    pub(crate) fn speed_of_sound(&self) -> f32 {
+        let mut speed = 331.3 + 0.606 * self.temp.0;
+        if let Some(rh) = self.humidity {
+            speed += 0.0124 * rh.0;
+        }
+        speed / 1000.0
+    }
+}
+
+// 为超声波测距提供环境读数
+pub(crate) trait EnvironmentSource {
+    fn air(&mut self) -> Result<Air, SensorError>;
+}
+
+impl<E: EnvironmentSource + ?Sized> EnvironmentSource for &mut E {
+    fn air(&mut self) -> Result<Air, SensorError> {
+        (**self).air()
+    }
+}
+
+// 固定的气温
+pub(crate) struct FixedAir(pub(crate) Celsius);
+
+impl EnvironmentSource for FixedAir {
+    fn air(&mut self) -> Result<Air, SensorError> {
+        Ok(Air {
+            temp: self.0,
+            humidity: None,
+        })
+    }
+}
+
+// 把一个温度传感器当作 EnvironmentSource 使用
+pub(crate) struct TemperatureSensor<S>(pub(crate) S);
+
+impl<S: Sensor<Output = Celsius>> EnvironmentSource for TemperatureSensor<S> {
+    fn air(&mut self) -> Result<Air, SensorError> {
+        Ok(Air {
+            temp: self.0.sample()?,
+            humidity: None,
+        })
+    }
+}
+
+// 一次测距的结果
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Range {
+    // 按 0.3314 mm/us 计算的距离
+    pub(crate) raw: Millimeter,
+    // 按实际声速计算的距离
+    pub(crate) compensated: Millimeter,
+    // 计算时实际使用的环境读数，None 表示从来没有得到过环境读数
+    pub(crate) air: Option<Air>,
+    // 单位 m/s
+    pub(crate) speed_of_sound: f32,
+}
+
+impl Measurement for Range {
+    fn for_each_reading(&self, f: &mut dyn FnMut(Reading)) {
+        f(Reading {
+            quantity: "dist",
+            value: self.compensated.0,
+            unit: "mm",
+        });
+        f(Reading {
+            quantity: "raw",
+            value: self.raw.0,
+            unit: "mm",
+        });
+        f(Reading {
+            quantity: "sound",
+            value: self.speed_of_sound,
+            unit: "m/s",
+        });
+        if let Some(air) = self.air {
+            air.temp.for_each_reading(f);
+            if let Some(rh) = air.humidity {
+                rh.for_each_reading(f);
+            }
+        }
+    }
+}
+
+// Trig 接 PA5，Echo 接 PB10，与 s06c04 相同
+pub(crate) struct Ultrasonic<'a, E: EnvironmentSource> {
+    gpioa: &'a pac::GPIOA,
+    gpiob: &'a pac::GPIOB,
+    env: E,
+    // 最近一次成功的环境读数
+    last_air: Option<Air>,
+}
+
+impl<'a, E: EnvironmentSource> Ultrasonic<'a, E> {
+    pub(crate) fn new(dp: &'a pac::Peripherals, env: E) -> Self {
+        dp.RCC.ahb1enr.modify(|_, w| {
+            w.gpioaen().enabled();
+            w.gpioben().enabled();
+            w
+        });
+
+        let gpioa = &dp.GPIOA;
+        gpioa.odr.modify(|_, w| w.odr5().low());
+        gpioa.moder.modify(|_, w| w.moder5().output());
+
+        let gpiob = &dp.GPIOB;
+        gpiob.pupdr.modify(|_, w| w.pupdr10().pull_down());
+        gpiob.moder.modify(|_, w| w.moder10().input());
+
+        Self {
+            gpioa,
+            gpiob,
+            env,
+            last_air: None,
+        }
+    }
+
+    // 取回 EnvironmentSource
+    pub(crate) fn release(self) -> E {
+        self.env
+    }
+
+    fn echo_is_high(&self) -> bool {
+        self.gpiob.idr.read().idr10().is_high()
+    }
+
+    // 触发一次测量，返回 Echo 高电平的时长，单位 us
+    fn measure_echo(&mut self) -> Result<u32, SensorError> {
+        if self.echo_is_high() {
+            // 上一次测量还没有结束
+            return Err(SensorError::NotReady);
+        }
+
+        // Trig 拉高至少 10 us
+        self.gpioa.odr.modify(|_, w| w.odr5().high());
+        let start = ticker::micros();
+        while ticker::micros() - start < 10 {}
+        self.gpioa.odr.modify(|_, w| w.odr5().low());
+
+        let start = ticker::micros();
+        while !self.echo_is_high() {
+            if ticker::micros() - start > ECHO_START_TIMEOUT_US {
+                return Err(SensorError::Timeout);
+            }
+        }
+
+        let rise = ticker::micros();
+        while self.echo_is_high() {
+            if ticker::micros() - rise > ECHO_TIMEOUT_US {
+                return Err(SensorError::Timeout);
+            }
+        }
+
+        Ok((ticker::micros() - rise) as u32)
+    }
+}
+
+impl<E: EnvironmentSource> Sensor for Ultrasonic<'_, E> {
+    type Output = Range;
+
+    fn name(&self) -> &'static str {
+        "sonar"
+    }
+
+    fn sample(&mut self) -> Result<Range, SensorError> {
+        // 先读取环境，BME280 这类传感器的转换本身就要花上几毫秒，放在测距之前，不影响回波的计时
+        if let Ok(air) = self.env.air() {
+            self.last_air = Some(air);
+        }
+
+        let echo_us = self.measure_echo()?;
+
+        let speed = self
+            .last_air
+            .map(|air| air.speed_of_sound())
+            .unwrap_or(NOMINAL_SPEED_MM_PER_US);
+
+        // 回波走了一个来回，因此要除以 2
+        let half = echo_us as f32 / 2.0;
+        let raw = half * NOMINAL_SPEED_MM_PER_US;
+        let compensated = half * speed;
+
+        // 超出量程，一般是 US-100 没有收到回波，由自身的看门狗拉低了 Echo
+        if compensated > MAX_DISTANCE_MM {
+            return Err(SensorError::OutOfRange);
+        }
+
+        Ok(Range {
+            raw: Millimeter(raw),
+            compensated: Millimeter(compensated),
+            air: self.last_air,
+            speed_of_sound: speed * 1000.0,
+        })
+    }
+}
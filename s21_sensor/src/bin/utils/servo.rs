@@ -0,0 +1,240 @@
+//! 舵机
+//!
+//! 常见的舵机（比如 SG90、MG996R）使用周期 20 ms（50 Hz）的 PWM 控制，高电平的宽度决定了舵机转到的角度，
+//! 一般 500 us ~ 2500 us 对应 0° ~ 180°，不同型号、甚至同一型号的不同个体都会有差异，见 ServoConfig
+//!
+//! Servo 只要求 PWM 输出实现 embedded-hal 的 SetDutyCycle（stm32f4xx_hal::hal 就是 embedded-hal，见 s03c02），
+//! 它根据 PWM 的周期，把脉宽换算为占空比，因此同一套 API 既可以使用：
+//! - 这里的 Tim4Channel：STM32 自己的 TIM4 的 4 个通道，分辨率为 1 us
+//! - utils::pca9685::Pca9685Channel：PCA9685 的 16 个通道，50 Hz 时分辨率约为 4.9 us
+//!
+//! TIM4 的 PWM 原理见 s06c03，这里 TIM4 的计数频率为 1 MHz，ARR + 1 就是以微秒为单位的周期
+//!
+//! 接线图：
+//!
+//! PD12 (TIM4_CH1) -> 舵机 1 信号线
+//! PD13 (TIM4_CH2) -> 舵机 2 信号线
+//! PD14 (TIM4_CH3) -> 舵机 3 信号线
+//! PD15 (TIM4_CH4) -> 舵机 4 信号线
+//!
+//! 舵机的电源需要单独提供（一般为 5V），只与 STM32 共地，启动与堵转时的电流可能超过 1 A
+
+#![allow(dead_code)]
+
+use core::convert::Infallible;
+
+use stm32f4xx_hal::{
+    hal::pwm::{ErrorType, SetDutyCycle},
+    pac,
+};
+
+// 舵机的 PWM 周期
+pub(crate) const PERIOD_US: u32 = 20_000;
+
+// TIM4 的计数时钟，与 APB1 相同，切换到 HSE 之后为 12 MHz
+const TIM_CLK_HZ: u32 = 12_000_000;
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct ServoConfig {
+    // 转到 0° 时的脉宽
+    pub(crate) min_pulse_us: u32,
+    // 转到 range_deg 时的脉宽
+    pub(crate) max_pulse_us: u32,
+    pub(crate) range_deg: f32,
+}
+
+impl ServoConfig {
+    pub(crate) const SG90: Self = Self {
+        min_pulse_us: 500,
+        max_pulse_us: 2500,
+        range_deg: 180.0,
+    };
+
+    pub(crate) fn center_pulse_us(&self) -> u32 {
+        (self.min_pulse_us + self.max_pulse_us) / 2
+    }
+}
+
+pub(crate) struct Servo<P: SetDutyCycle> {
+    pwm: P,
+    period_us: u32,
+    config: ServoConfig,
+    // 最后一次设置的脉宽，detach 之后为 None
+    pulse_us: Option<u32>,
+}
+
+impl<P: SetDutyCycle> Servo<P> {
+    // period_us 为 PWM 的周期，需要与 PWM 输出实际的频率一致
+    pub(crate) fn new(pwm: P, period_us: u32, config: ServoConfig) -> Self {
+        Self {
+            pwm,
+            period_us,
+            config,
+            pulse_us: None,
+        }
+    }
+
+    pub(crate) fn config(&self) -> &ServoConfig {
+        &self.config
+    }
+
+    // 超出 config 范围的脉宽会被限制在范围之内，以免舵机撞到机械限位
+    pub(crate) fn set_pulse_us(&mut self, pulse_us: u32) -> Result<(), P::Error> {
+        let pulse_us = pulse_us.clamp(self.config.min_pulse_us, self.config.max_pulse_us);
+        let max = self.pwm.max_duty_cycle() as u32;
+        // 四舍五入，PCA9685 的一个 tick 有好几微秒
+        let duty = (pulse_us * max + self.period_us / 2) / self.period_us;
+        self.pwm.set_duty_cycle(duty.min(max) as u16)?;
+        self.pulse_us = Some(pulse_us);
+        Ok(())
+    }
+
+    // 0° ~ range_deg
+    pub(crate) fn set_angle(&mut self, angle_deg: f32) -> Result<(), P::Error> {
+        let ServoConfig {
+            min_pulse_us,
+            max_pulse_us,
+            range_deg,
+        } = self.config;
+        let ratio = (angle_deg / range_deg).clamp(0.0, 1.0);
+        let pulse_us = min_pulse_us as f32 + ratio * (max_pulse_us - min_pulse_us) as f32;
+        self.set_pulse_us((pulse_us + 0.5) as u32)
+    }
+
+    pub(crate) fn center(&mut self) -> Result<(), P::Error> {
+        self.set_pulse_us(self.config.center_pulse_us())
+    }
+
+    pub(crate) fn pulse_us(&self) -> Option<u32> {
+        self.pulse_us
+    }
+
+    // 根据最后一次设置的脉宽换算回来，舵机实际的位置并不能读到
+    pub(crate) fn angle(&self) -> Option<f32> {
+        let ServoConfig {
+            min_pulse_us,
+            max_pulse_us,
+            range_deg,
+        } = self.config;
+        self.pulse_us.map(|pulse_us| {
+            (pulse_us - min_pulse_us) as f32 / (max_pulse_us - min_pulse_us) as f32 * range_deg
+        })
+    }
+
+    // 停止输出脉冲，大多数舵机此时不再保持位置，可以用手转动，也不再耗电
+    pub(crate) fn detach(&mut self) -> Result<(), P::Error> {
+        self.pwm.set_duty_cycle(0)?;
+        self.pulse_us = None;
+        Ok(())
+    }
+
+    pub(crate) fn release(self) -> P {
+        self.pwm
+    }
+}
+
+// 把 TIM4 的 4 个通道配置为 PWM 输出，周期为 period_us，初始时没有输出
+pub(crate) fn setup_tim4(dp: &pac::Peripherals, period_us: u32) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioden().enabled());
+    dp.RCC.apb1enr.modify(|_, w| w.tim4en().enabled());
+
+    let gpiod = &dp.GPIOD;
+    gpiod.afrh.modify(|_, w| {
+        w.afrh12().af2();
+        w.afrh13().af2();
+        w.afrh14().af2();
+        w.afrh15().af2();
+        w
+    });
+    gpiod.moder.modify(|_, w| {
+        w.moder12().alternate();
+        w.moder13().alternate();
+        w.moder14().alternate();
+        w.moder15().alternate();
+        w
+    });
+
+    let tim4 = &dp.TIM4;
+    tim4.psc
+        .write(|w| w.psc().bits((TIM_CLK_HZ / 1_000_000 - 1) as u16));
+    tim4.arr.write(|w| w.arr().bits((period_us - 1) as u16));
+    tim4.cr1.modify(|_, w| w.arpe().enabled());
+
+    // PWM Mode 1：CNT < CCR 时输出高电平，CCR 就是以微秒为单位的脉宽
+    tim4.ccmr1_output().write(|w| {
+        w.cc1s().output();
+        w.oc1m().pwm_mode1();
+        w.oc1pe().enabled();
+        w.cc2s().output();
+        w.oc2m().pwm_mode1();
+        w.oc2pe().enabled();
+        w
+    });
+    tim4.ccmr2_output().write(|w| {
+        w.cc3s().output();
+        w.oc3m().pwm_mode1();
+        w.oc3pe().enabled();
+        w.cc4s().output();
+        w.oc4m().pwm_mode1();
+        w.oc4pe().enabled();
+        w
+    });
+    tim4.ccr1().write(|w| w.ccr().bits(0));
+    tim4.ccr2().write(|w| w.ccr().bits(0));
+    tim4.ccr3().write(|w| w.ccr().bits(0));
+    tim4.ccr4().write(|w| w.ccr().bits(0));
+    tim4.ccer.modify(|_, w| {
+        w.cc1e().set_bit();
+        w.cc2e().set_bit();
+        w.cc3e().set_bit();
+        w.cc4e().set_bit();
+        w
+    });
+
+    // 把 PSC、ARR 从预装载寄存器搬到影子寄存器
+    tim4.egr.write(|w| w.ug().update());
+    tim4.cr1.modify(|_, w| w.cen().enabled());
+}
+
+// TIM4 的单个通道，需要先调用 setup_tim4
+pub(crate) struct Tim4Channel {
+    // 1 ~ 4
+    channel: u8,
+}
+
+impl Tim4Channel {
+    // channel 为 1 ~ 4
+    pub(crate) fn new(channel: u8) -> Option<Self> {
+        match channel {
+            1..=4 => Some(Self { channel }),
+            _ => None,
+        }
+    }
+}
+
+impl ErrorType for Tim4Channel {
+    type Error = Infallible;
+}
+
+impl SetDutyCycle for Tim4Channel {
+    // ARR + 1，也就是以微秒为单位的周期，因此 PERIOD_US 不能超过 65535
+    fn max_duty_cycle(&self) -> u16 {
+        let tim4 = unsafe { &*pac::TIM4::ptr() };
+        (tim4.arr.read().arr().bits() as u32 + 1).min(u16::MAX as u32) as u16
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        // 各个通道只修改自己的 CCR，不需要互斥
+        let tim4 = unsafe { &*pac::TIM4::ptr() };
+        let ccr = match self.channel {
+            1 => tim4.ccr1(),
+            2 => tim4.ccr2(),
+            3 => tim4.ccr3(),
+            _ => tim4.ccr4(),
+        };
+        ccr.write(|w| w.ccr().bits(duty));
+        Ok(())
+    }
+}
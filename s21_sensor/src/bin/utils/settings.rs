@@ -0,0 +1,269 @@
+//! 保存在外部 Flash 中的设置，比如各个传感器的校准参数
+//!
+//! 设置是一组 键 -> 值 的条目，键为不超过 8 字节的名字，值固定为 4 个 f32，
+//! 读写都在 RAM 中进行，调用 commit 之后才会整体写入 Flash
+//!
+//! Flash 中使用两个扇区（A/B 两个 bank），轮流写入：
+//!
+//! | 偏移 | 长度      | 内容                                          |
+//! | 0    | 4         | 魔数 "SET1"                                   |
+//! | 4    | 4         | 代数 generation，每次 commit 加 1             |
+//! | 8    | 2         | 条目数                                        |
+//! | 10   | 2         | 所有条目的 CRC-16                             |
+//! | 12   | 2         | 前 12 个字节的 CRC-16                         |
+//! | 14   | 2         | 保留，0xFFFF                                  |
+//! | 16   | 24 * 条目 | 每个条目为 键（8 字节，不足补 0）+ 4 个 f32   |
+//!
+//! 启动时（open）两个 bank 中校验都正确、代数较大的那个有效
+//! commit 总是写入另一个 bank：先擦除，再写条目，最后才写头，因此无论在哪一步掉电，原来的 bank 都完好无损
+//!
+//! 存储介质与 utils::datalog 相同，只要实现了 LogFlash 就可以
+
+#![allow(dead_code)]
+
+use super::{
+    crc16::crc16,
+    datalog::{pack, unpack, LogFlash},
+};
+
+pub(crate) const KEY_LEN: usize = 8;
+pub(crate) const VALUE_COUNT: usize = 4;
+pub(crate) const MAX_ENTRIES: usize = 16;
+
+pub(crate) type Values = [f32; VALUE_COUNT];
+
+const MAGIC: u32 = u32::from_le_bytes(*b"SET1");
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = KEY_LEN + VALUE_COUNT * 4;
+const BODY_MAX: usize = MAX_ENTRIES * ENTRY_SIZE;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum SettingsError {
+    // 起始地址没有对齐到扇区，或者一个扇区放不下所有条目
+    Geometry,
+    // 条目已满
+    Full,
+    // 写入之后读回的内容不一致
+    Verify,
+}
+
+impl SettingsError {
+    // 用于命令行的输出，与 Debug 的输出相同，但不依赖 fmt 特性
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            SettingsError::Geometry => "Geometry",
+            SettingsError::Full => "Full",
+            SettingsError::Verify => "Verify",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    key: [u8; KEY_LEN],
+    values: Values,
+}
+
+pub(crate) struct SettingsStore<F: LogFlash> {
+    flash: F,
+    start: u32,
+    entries: [Option<Entry>; MAX_ENTRIES],
+    generation: u32,
+    // 当前有效的 bank，全新的 Flash 上为 None
+    active: Option<u32>,
+}
+
+impl<F: LogFlash> SettingsStore<F> {
+    // 使用 [start, start + 2 * SECTOR_SIZE) 两个扇区，读取其中有效的设置
+    pub(crate) fn open(flash: F, start: u32) -> Result<Self, SettingsError> {
+        if !start.is_multiple_of(F::SECTOR_SIZE) || F::SECTOR_SIZE < (HEADER_SIZE + BODY_MAX) as u32
+        {
+            return Err(SettingsError::Geometry);
+        }
+
+        let mut store = Self {
+            flash,
+            start,
+            entries: [None; MAX_ENTRIES],
+            generation: 0,
+            active: None,
+        };
+
+        let mut newest: Option<(u32, u32)> = None;
+        for bank in 0..2 {
+            if let Some(generation) = store.check_bank(bank) {
+                if newest.is_none_or(|(newest_gen, _)| generation > newest_gen) {
+                    newest = Some((generation, bank));
+                }
+            }
+        }
+
+        if let Some((generation, bank)) = newest {
+            store.load_bank(bank);
+            store.generation = generation;
+            store.active = Some(bank);
+        }
+
+        Ok(store)
+    }
+
+    fn bank_addr(&self, bank: u32) -> u32 {
+        self.start + bank * F::SECTOR_SIZE
+    }
+
+    // 读取并校验一个 bank 的头，返回条目数与条目的 CRC
+    fn read_header(&mut self, bank: u32) -> Option<(u32, usize, u16)> {
+        let mut header = [0u8; HEADER_SIZE];
+        self.flash.read(self.bank_addr(bank), &mut header);
+
+        let field = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+        let half = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+
+        if field(0) != MAGIC || half(12) != crc16(&header[..12]) {
+            return None;
+        }
+        let count = half(8) as usize;
+        if count > MAX_ENTRIES {
+            return None;
+        }
+        Some((field(4), count, half(10)))
+    }
+
+    fn read_body(&mut self, bank: u32, count: usize, body: &mut [u8; BODY_MAX]) {
+        let addr = self.bank_addr(bank) + HEADER_SIZE as u32;
+        self.flash.read(addr, &mut body[..count * ENTRY_SIZE]);
+    }
+
+    // 头与条目的校验都正确时，返回这个 bank 的代数
+    fn check_bank(&mut self, bank: u32) -> Option<u32> {
+        let (generation, count, body_crc) = self.read_header(bank)?;
+        let mut body = [0u8; BODY_MAX];
+        self.read_body(bank, count, &mut body);
+        (crc16(&body[..count * ENTRY_SIZE]) == body_crc).then_some(generation)
+    }
+
+    fn load_bank(&mut self, bank: u32) {
+        let Some((_, count, _)) = self.read_header(bank) else {
+            return;
+        };
+        let mut body = [0u8; BODY_MAX];
+        self.read_body(bank, count, &mut body);
+
+        self.entries = [None; MAX_ENTRIES];
+        for (slot, raw) in self
+            .entries
+            .iter_mut()
+            .zip(body.chunks_exact(ENTRY_SIZE).take(count))
+        {
+            let mut values = [0.0; VALUE_COUNT];
+            for (value, bytes) in values.iter_mut().zip(raw[KEY_LEN..].chunks_exact(4)) {
+                *value = f32::from_le_bytes(bytes.try_into().unwrap());
+            }
+            slot.replace(Entry {
+                key: raw[..KEY_LEN].try_into().unwrap(),
+                values,
+            });
+        }
+    }
+
+    fn find(&self, key: &str) -> Option<usize> {
+        let key: [u8; KEY_LEN] = pack(key);
+        self.entries
+            .iter()
+            .position(|entry| entry.is_some_and(|entry| entry.key == key))
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<Values> {
+        self.find(key)
+            .and_then(|index| self.entries[index])
+            .map(|entry| entry.values)
+    }
+
+    // 只修改 RAM 中的内容，需要 commit 才会写入 Flash
+    pub(crate) fn set(&mut self, key: &str, values: Values) -> Result<(), SettingsError> {
+        let index = self
+            .find(key)
+            .or_else(|| self.entries.iter().position(Option::is_none))
+            .ok_or(SettingsError::Full)?;
+        self.entries[index] = Some(Entry {
+            key: pack(key),
+            values,
+        });
+        Ok(())
+    }
+
+    // 删除一个条目，返回它是否存在，同样需要 commit
+    pub(crate) fn remove(&mut self, key: &str) -> bool {
+        match self.find(key) {
+            Some(index) => {
+                self.entries[index] = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn for_each(&self, mut f: impl FnMut(&str, &Values)) {
+        for entry in self.entries.iter().flatten() {
+            f(unpack(&entry.key), &entry.values);
+        }
+    }
+
+    pub(crate) fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    // 把 RAM 中的设置写入另一个 bank，写入并校验成功之后，它才成为有效的 bank
+    pub(crate) fn commit(&mut self) -> Result<(), SettingsError> {
+        let bank = self.active.map_or(0, |active| 1 - active);
+        let generation = self.generation.wrapping_add(1);
+
+        let mut body = [0u8; BODY_MAX];
+        let mut count = 0;
+        for entry in self.entries.iter().flatten() {
+            let raw = &mut body[count * ENTRY_SIZE..(count + 1) * ENTRY_SIZE];
+            raw[..KEY_LEN].copy_from_slice(&entry.key);
+            for (bytes, value) in raw[KEY_LEN..].chunks_exact_mut(4).zip(entry.values) {
+                bytes.copy_from_slice(&value.to_le_bytes());
+            }
+            count += 1;
+        }
+        let body = &body[..count * ENTRY_SIZE];
+
+        let mut header = [0xFFu8; HEADER_SIZE];
+        header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&generation.to_le_bytes());
+        header[8..10].copy_from_slice(&(count as u16).to_le_bytes());
+        header[10..12].copy_from_slice(&crc16(body).to_le_bytes());
+        let header_crc = crc16(&header[..12]);
+        header[12..14].copy_from_slice(&header_crc.to_le_bytes());
+
+        let addr = self.bank_addr(bank);
+        self.flash.start_erase(addr);
+        self.flash.wait_idle();
+        self.program_span(addr + HEADER_SIZE as u32, body);
+        // 头最后写入，写入头之前掉电的话，这个 bank 不会被认为有效
+        self.program_span(addr, &header);
+
+        if self.check_bank(bank) != Some(generation) {
+            return Err(SettingsError::Verify);
+        }
+
+        self.generation = generation;
+        self.active = Some(bank);
+        Ok(())
+    }
+
+    // LogFlash::program 不能跨页，这里按页拆开写入
+    fn program_span(&mut self, mut addr: u32, mut data: &[u8]) {
+        while !data.is_empty() {
+            let room = (F::PAGE_SIZE - addr % F::PAGE_SIZE) as usize;
+            let (chunk, rest) = data.split_at(room.min(data.len()));
+            self.flash.program(addr, chunk);
+            addr += chunk.len() as u32;
+            data = rest;
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! 串口/USB 命令行的挑战-应答认证
+//!
+//! 擦除 Flash、修改 Option Bytes、重启进入 DFU 这类命令，一旦被误触发或者被别人触发，后果很严重，
+//! 因此执行之前要求对方证明自己持有一个共享的密钥，而密钥本身从不在线路上传输：
+//!
+//! 1. 对方发送 auth，设备生成一个 NONCE_LEN 字节的随机数（nonce）作为挑战，以十六进制回复
+//! 2. 对方用密钥计算 HMAC-SHA256(key, nonce)，以十六进制发回（auth <mac>）
+//! 3. 设备用同样的方法计算并比较，一致则开启一个 SESSION_MS 的会话，会话内的危险命令不再需要认证
+//!
+//! 每个 nonce 只能应答一次，无论对错都会作废，超过 CHALLENGE_MS 没有应答也会作废，
+//! 因此线路上录下来的应答无法被重放，每猜错一次都要重新申请挑战
+//! 连续 MAX_FAILURES 次应答错误之后，LOCKOUT_MS 内拒绝生成新的挑战
+//!
+//! 注意：失败计数与锁定只保存在 RAM 中，复位之后就清零了，需要更强的保护时，可以把它们保存到 RTC_BKPxR 中
+//!
+//! 密钥为 KEY_LEN 字节，保存在 utils::settings 中：settings 的值是 4 个 f32，
+//! 这里把密钥拆成 8 个 u32，按位原样存放在 KEY_SETTINGS 两个条目中（f32::from_bits 与 to_bits 不会改动任何一位）
+//!
+//! Host 端可以用 openssl 计算应答：
+//!
+//! ```shell
+//! echo -n <nonce> | xxd -r -p | openssl dgst -sha256 -mac HMAC -macopt hexkey:<key>
+//! ```
+
+#![allow(dead_code)]
+
+use crypto_core::{hmac, sha256::DIGEST_LEN};
+
+use super::{
+    datalog::LogFlash,
+    settings::{SettingsError, SettingsStore, Values, VALUE_COUNT},
+};
+
+pub(crate) const KEY_LEN: usize = 32;
+pub(crate) const NONCE_LEN: usize = 16;
+
+pub(crate) const CHALLENGE_MS: u32 = 30_000;
+pub(crate) const SESSION_MS: u32 = 5 * 60_000;
+pub(crate) const MAX_FAILURES: u8 = 3;
+pub(crate) const LOCKOUT_MS: u32 = 60_000;
+
+// 每个条目保存 VALUE_COUNT 个 u32
+const KEY_SETTINGS: [&str; 2] = ["authkey0", "authkey1"];
+
+pub(crate) type Key = [u8; KEY_LEN];
+pub(crate) type Nonce = [u8; NONCE_LEN];
+pub(crate) type Mac = [u8; DIGEST_LEN];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum AuthError {
+    // 还没有设置密钥，危险命令一律拒绝
+    NoKey,
+    // 没有待应答的挑战，或者挑战已经过期
+    NoChallenge,
+    // 应答错误
+    BadResponse,
+    // 失败次数过多，还需要等待的毫秒数
+    Locked(u32),
+    // 没有认证，或者会话已经过期
+    NotAuthenticated,
+}
+
+// 从 settings 中读出密钥，两个条目必须都存在
+pub(crate) fn load_key<F: LogFlash>(store: &SettingsStore<F>) -> Option<Key> {
+    let mut key = [0u8; KEY_LEN];
+    for (chunk, name) in key.chunks_exact_mut(VALUE_COUNT * 4).zip(KEY_SETTINGS) {
+        let values = store.get(name)?;
+        for (bytes, value) in chunk.chunks_exact_mut(4).zip(values) {
+            bytes.copy_from_slice(&value.to_bits().to_le_bytes());
+        }
+    }
+    Some(key)
+}
+
+// 把密钥写入 settings 并 commit
+pub(crate) fn save_key<F: LogFlash>(
+    store: &mut SettingsStore<F>,
+    key: &Key,
+) -> Result<(), SettingsError> {
+    for (chunk, name) in key.chunks_exact(VALUE_COUNT * 4).zip(KEY_SETTINGS) {
+        let mut values: Values = [0.0; VALUE_COUNT];
+        for (value, bytes) in values.iter_mut().zip(chunk.chunks_exact(4)) {
+            *value = f32::from_bits(u32::from_le_bytes(bytes.try_into().unwrap()));
+        }
+        store.set(name, values)?;
+    }
+    store.commit()
+}
+
+// 认证的状态，所有的时间都是 utils::ticker::millis 的毫秒数，由调用者传入
+pub(crate) struct ShellAuth {
+    key: Option<Key>,
+    // 待应答的挑战，以及生成它的时间
+    challenge: Option<(Nonce, u32)>,
+    // 会话开始的时间
+    session: Option<u32>,
+    failures: u8,
+    // 锁定开始的时间
+    locked: Option<u32>,
+}
+
+impl ShellAuth {
+    pub(crate) fn new(key: Option<Key>) -> Self {
+        Self {
+            key,
+            challenge: None,
+            session: None,
+            failures: 0,
+            locked: None,
+        }
+    }
+
+    pub(crate) fn has_key(&self) -> bool {
+        self.key.is_some()
+    }
+
+    // 更换密钥，旧密钥下的挑战与会话一并作废
+    pub(crate) fn set_key(&mut self, key: Key) {
+        self.key = Some(key);
+        self.challenge = None;
+        self.session = None;
+    }
+
+    // 生成一个新的挑战，random 由调用者从 RNG 中取得，之前未应答的挑战作废
+    pub(crate) fn challenge(&mut self, now: u32, random: Nonce) -> Result<Nonce, AuthError> {
+        if self.key.is_none() {
+            return Err(AuthError::NoKey);
+        }
+        self.check_lockout(now)?;
+
+        self.challenge = Some((random, now));
+        Ok(random)
+    }
+
+    // 检查对最近一次挑战的应答，无论对错，这个挑战都会作废
+    pub(crate) fn respond(&mut self, now: u32, mac: &Mac) -> Result<(), AuthError> {
+        let key = self.key.ok_or(AuthError::NoKey)?;
+        self.check_lockout(now)?;
+
+        let (nonce, issued) = self.challenge.take().ok_or(AuthError::NoChallenge)?;
+        if now.wrapping_sub(issued) > CHALLENGE_MS {
+            return Err(AuthError::NoChallenge);
+        }
+
+        if hmac::verify(&key, &nonce, mac) {
+            self.failures = 0;
+            self.session = Some(now);
+            return Ok(());
+        }
+
+        self.failures += 1;
+        if self.failures >= MAX_FAILURES {
+            self.failures = 0;
+            self.locked = Some(now);
+            self.session = None;
+        }
+        Err(AuthError::BadResponse)
+    }
+
+    // 危险命令执行之前调用，会话有效时返回 Ok
+    pub(crate) fn authorize(&mut self, now: u32) -> Result<(), AuthError> {
+        if self.key.is_none() {
+            return Err(AuthError::NoKey);
+        }
+        match self.session {
+            Some(start) if now.wrapping_sub(start) <= SESSION_MS => Ok(()),
+            _ => {
+                self.session = None;
+                Err(AuthError::NotAuthenticated)
+            }
+        }
+    }
+
+    pub(crate) fn logout(&mut self) {
+        self.challenge = None;
+        self.session = None;
+    }
+
+    // 会话剩余的毫秒数
+    pub(crate) fn session_remaining(&self, now: u32) -> Option<u32> {
+        self.session
+            .map(|start| now.wrapping_sub(start))
+            .filter(|&elapsed| elapsed <= SESSION_MS)
+            .map(|elapsed| SESSION_MS - elapsed)
+    }
+
+    fn check_lockout(&mut self, now: u32) -> Result<(), AuthError> {
+        if let Some(start) = self.locked {
+            let elapsed = now.wrapping_sub(start);
+            if elapsed < LOCKOUT_MS {
+                return Err(AuthError::Locked(LOCKOUT_MS - elapsed));
+            }
+            self.locked = None;
+        }
+        Ok(())
+    }
+}
+
+// 把十六进制字符串解析到 buf 中，长度必须正好是 buf 的两倍
+pub(crate) fn parse_hex(text: &str, buf: &mut [u8]) -> Option<()> {
+    if text.len() != buf.len() * 2 {
+        return None;
+    }
+    let digit = |c: u8| (c as char).to_digit(16);
+    for (byte, pair) in buf.iter_mut().zip(text.as_bytes().chunks_exact(2)) {
+        *byte = (digit(pair[0])? << 4 | digit(pair[1])?) as u8;
+    }
+    Some(())
+}
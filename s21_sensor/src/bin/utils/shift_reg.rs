@@ -0,0 +1,358 @@
+//! 74HC595 / 74HC165 移位寄存器级联，用很少的几根线扩展出任意多的输出与输入
+//!
+//! 74HC595（串入并出）：
+//!
+//! - SER 上的数据在 SRCLK 的上升沿移入，同时整个移位寄存器往后挪一位，QH' 输出被挤出去的那一位，接到下一片的 SER 上
+//! - RCLK 的上升沿把移位寄存器锁存到 QA ~ QH，移位的过程中输出保持不变，不会闪烁
+//! - 因此每次更新都要把整条链重新移一遍，先移出的字节最终停在离 MCU 最远的那一片里
+//!
+//! 74HC165（并入串出）：
+//!
+//! - SH/LD 为低电平时，把 A ~ H 的电平装入移位寄存器，QH 立刻输出 H；SH/LD 回到高电平之后，CLK 的上升沿让数据往 QH 移动一位
+//! - 下一片的 QH 接到这一片的 SER 上，这样离 MCU 最近的那一片先被读出
+//!
+//! 链上的引脚编号为 chip * 8 + n，chip 0 为离 MCU 最近的那一片，n 为 QA ~ QH（595）或者 A ~ H（165）
+//!
+//! 收发的方式由 Link 决定：
+//!
+//! - SpiLink：SCK 接 SRCLK / CLK，MOSI 接 SER，MISO 接 QH，一个字节一次传输，SPI 需要事先配置为 Mode 0、高位在前
+//!   74HC165 的 QH 在 CLK 上升沿之后约 20 ns 才变化，而 SPI 恰好在上升沿采样，SCK 在几 MHz 以内都能读到变化之前的值
+//! - BitBangLink：任意三个实现了 embedded-hal 的 OutputPin / InputPin 的引脚，不需要的一端用 NoPin 代替
+//!   不额外延时，74HC 系列在 3.3V 下能跟上 10 MHz 以上的时钟，GPIO 翻转远没有这么快
+//!
+//! 两条链可以共用同一组 SCK/MOSI/MISO，只要锁存线分开即可：读 165 时移进 595 的数据不会被锁存，下一次 commit 又会整条重写
+//!
+//! 与 utils::mcp23017 一样，驱动内部保存了输出的副本，各个方法都只需要 &self，
+//! output_pin、input_pin 返回的单个引脚实现了 embedded-hal 的 OutputPin / StatefulOutputPin / InputPin，可以交给其他驱动使用
+//!
+//! 输出链每修改一次就移一整条链，需要同时改动很多个引脚时，放进 batch 中，结束时只移一次
+
+#![allow(dead_code)]
+
+use core::{
+    cell::{Cell, RefCell},
+    convert::Infallible,
+};
+
+use stm32f4xx_hal::{
+    hal::digital::{self, ErrorKind, ErrorType, InputPin, OutputPin, PinState, StatefulOutputPin},
+    pac::spi1::RegisterBlock,
+};
+
+// embedded-hal 的 Error 要求实现 Debug，不受 fmt 特性控制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum ShiftError {
+    // 锁存线或者 BitBangLink 的引脚操作失败
+    Pin,
+    // 引脚编号超出了链的长度
+    InvalidPin,
+}
+
+impl digital::Error for ShiftError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+// 同时移出一个字节、移入一个字节，高位在前
+pub(crate) trait Link {
+    fn shift(&mut self, out: u8) -> Result<u8, ShiftError>;
+}
+
+pub(crate) struct SpiLink<'a> {
+    spi: &'a RegisterBlock,
+}
+
+impl<'a> SpiLink<'a> {
+    pub(crate) fn new(spi: &'a RegisterBlock) -> Self {
+        Self { spi }
+    }
+}
+
+impl Link for SpiLink<'_> {
+    fn shift(&mut self, out: u8) -> Result<u8, ShiftError> {
+        let spi = self.spi;
+        while spi.sr.read().txe().is_not_empty() {}
+        spi.dr.write(|w| w.dr().bits(out as u16));
+        while spi.sr.read().rxne().is_empty() {}
+        Ok(spi.dr.read().dr().bits() as u8)
+    }
+}
+
+pub(crate) struct BitBangLink<SCK, SER, QH> {
+    sck: SCK,
+    ser: SER,
+    qh: QH,
+}
+
+impl<SCK: OutputPin, SER: OutputPin, QH: InputPin> BitBangLink<SCK, SER, QH> {
+    pub(crate) fn new(mut sck: SCK, ser: SER, qh: QH) -> Result<Self, ShiftError> {
+        sck.set_low().map_err(|_| ShiftError::Pin)?;
+        Ok(Self { sck, ser, qh })
+    }
+}
+
+impl<SCK: OutputPin, SER: OutputPin, QH: InputPin> Link for BitBangLink<SCK, SER, QH> {
+    fn shift(&mut self, out: u8) -> Result<u8, ShiftError> {
+        let mut input = 0;
+        for bit in (0..8).rev() {
+            // QH 在上升沿之后才变为下一位，所以先读取，再产生上升沿
+            if self.qh.is_high().map_err(|_| ShiftError::Pin)? {
+                input |= 1 << bit;
+            }
+            self.ser
+                .set_state(PinState::from(out & (1 << bit) != 0))
+                .map_err(|_| ShiftError::Pin)?;
+            self.sck.set_high().map_err(|_| ShiftError::Pin)?;
+            self.sck.set_low().map_err(|_| ShiftError::Pin)?;
+        }
+        Ok(input)
+    }
+}
+
+// 占位用的引脚，比如只有 595 的链不需要 QH，写入什么都不做，读取永远为低电平
+pub(crate) struct NoPin;
+
+impl ErrorType for NoPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for NoPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl InputPin for NoPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+fn split_pin(pin: u16, chips: usize) -> Result<(usize, u8), ShiftError> {
+    let chip = (pin / 8) as usize;
+    if chip >= chips {
+        return Err(ShiftError::InvalidPin);
+    }
+    Ok((chip, 1 << (pin % 8)))
+}
+
+// N 片 74HC595 组成的输出链
+pub(crate) struct OutputChain<L, RCLK, const N: usize> {
+    link: RefCell<L>,
+    rclk: RefCell<RCLK>,
+    state: Cell<[u8; N]>,
+    // batch 中的修改还没有移出
+    dirty: Cell<bool>,
+    batching: Cell<bool>,
+}
+
+impl<L: Link, RCLK: OutputPin, const N: usize> OutputChain<L, RCLK, N> {
+    pub(crate) const PIN_COUNT: u16 = N as u16 * 8;
+
+    // 把全部输出清零
+    //
+    // 上电时 595 的输出是随机的，OE 由 MCU 控制的话，应该在 new 之后再拉低 OE
+    pub(crate) fn new(link: L, mut rclk: RCLK) -> Result<Self, ShiftError> {
+        rclk.set_low().map_err(|_| ShiftError::Pin)?;
+        let chain = Self {
+            link: RefCell::new(link),
+            rclk: RefCell::new(rclk),
+            state: Cell::new([0; N]),
+            dirty: Cell::new(false),
+            batching: Cell::new(false),
+        };
+        chain.commit()?;
+        Ok(chain)
+    }
+
+    // 输出的副本，不需要读取芯片
+    pub(crate) fn state(&self) -> [u8; N] {
+        self.state.get()
+    }
+
+    pub(crate) fn write_all(&self, state: [u8; N]) -> Result<(), ShiftError> {
+        self.state.set(state);
+        self.changed()
+    }
+
+    // 只修改第 chip 片中 mask 为 1 的引脚
+    pub(crate) fn modify_chip(&self, chip: usize, mask: u8, value: u8) -> Result<(), ShiftError> {
+        let mut state = self.state.get();
+        let byte = state.get_mut(chip).ok_or(ShiftError::InvalidPin)?;
+        *byte = (*byte & !mask) | (value & mask);
+        self.state.set(state);
+        self.changed()
+    }
+
+    pub(crate) fn set(&self, pin: u16, high: bool) -> Result<(), ShiftError> {
+        let (chip, mask) = split_pin(pin, N)?;
+        self.modify_chip(chip, mask, if high { mask } else { 0 })
+    }
+
+    pub(crate) fn is_set(&self, pin: u16) -> Result<bool, ShiftError> {
+        let (chip, mask) = split_pin(pin, N)?;
+        Ok(self.state.get()[chip] & mask != 0)
+    }
+
+    // f 中的修改只改动副本，f 结束之后一次性移出；batch 可以嵌套，只有最外层结束时才移出
+    pub(crate) fn batch<R>(&self, f: impl FnOnce(&Self) -> R) -> Result<R, ShiftError> {
+        let outer = !self.batching.replace(true);
+        let result = f(self);
+        if outer {
+            self.batching.set(false);
+            if self.dirty.get() {
+                self.commit()?;
+            }
+        }
+        Ok(result)
+    }
+
+    // 把副本移出到整条链，并锁存到输出
+    pub(crate) fn commit(&self) -> Result<(), ShiftError> {
+        let mut link = self.link.borrow_mut();
+        // 最远的那一片先移出
+        for &byte in self.state.get().iter().rev() {
+            link.shift(byte)?;
+        }
+
+        let mut rclk = self.rclk.borrow_mut();
+        rclk.set_high().map_err(|_| ShiftError::Pin)?;
+        rclk.set_low().map_err(|_| ShiftError::Pin)?;
+
+        self.dirty.set(false);
+        Ok(())
+    }
+
+    pub(crate) fn output_pin(
+        &self,
+        pin: u16,
+    ) -> Result<ChainOutputPin<'_, L, RCLK, N>, ShiftError> {
+        split_pin(pin, N)?;
+        Ok(ChainOutputPin { chain: self, pin })
+    }
+
+    fn changed(&self) -> Result<(), ShiftError> {
+        self.dirty.set(true);
+        if self.batching.get() {
+            return Ok(());
+        }
+        self.commit()
+    }
+}
+
+// 输出链上的单个引脚
+pub(crate) struct ChainOutputPin<'c, L, RCLK, const N: usize> {
+    chain: &'c OutputChain<L, RCLK, N>,
+    pin: u16,
+}
+
+impl<L, RCLK, const N: usize> ErrorType for ChainOutputPin<'_, L, RCLK, N> {
+    type Error = ShiftError;
+}
+
+impl<L: Link, RCLK: OutputPin, const N: usize> OutputPin for ChainOutputPin<'_, L, RCLK, N> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.chain.set(self.pin, false)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.chain.set(self.pin, true)
+    }
+}
+
+impl<L: Link, RCLK: OutputPin, const N: usize> StatefulOutputPin
+    for ChainOutputPin<'_, L, RCLK, N>
+{
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        self.chain.is_set(self.pin)
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.chain.is_set(self.pin)?)
+    }
+}
+
+// N 片 74HC165 组成的输入链
+pub(crate) struct InputChain<L, SHLD, const N: usize> {
+    link: RefCell<L>,
+    shld: RefCell<SHLD>,
+    last: Cell<[u8; N]>,
+}
+
+impl<L: Link, SHLD: OutputPin, const N: usize> InputChain<L, SHLD, N> {
+    pub(crate) const PIN_COUNT: u16 = N as u16 * 8;
+
+    pub(crate) fn new(link: L, mut shld: SHLD) -> Result<Self, ShiftError> {
+        shld.set_high().map_err(|_| ShiftError::Pin)?;
+        Ok(Self {
+            link: RefCell::new(link),
+            shld: RefCell::new(shld),
+            last: Cell::new([0; N]),
+        })
+    }
+
+    // 装入所有引脚此刻的电平，并读出整条链
+    pub(crate) fn read(&self) -> Result<[u8; N], ShiftError> {
+        {
+            // SH/LD 的低电平至少 20 ns，两次 GPIO 写入之间的间隔已经足够
+            let mut shld = self.shld.borrow_mut();
+            shld.set_low().map_err(|_| ShiftError::Pin)?;
+            shld.set_high().map_err(|_| ShiftError::Pin)?;
+        }
+
+        let mut link = self.link.borrow_mut();
+        let mut state = [0; N];
+        // 最近的那一片先读出
+        for byte in state.iter_mut() {
+            *byte = link.shift(0)?;
+        }
+        self.last.set(state);
+        Ok(state)
+    }
+
+    // 最近一次 read 的结果
+    pub(crate) fn last(&self) -> [u8; N] {
+        self.last.get()
+    }
+
+    // 重新读取整条链，返回其中一个引脚
+    pub(crate) fn is_high(&self, pin: u16) -> Result<bool, ShiftError> {
+        let (chip, mask) = split_pin(pin, N)?;
+        Ok(self.read()?[chip] & mask != 0)
+    }
+
+    pub(crate) fn input_pin(&self, pin: u16) -> Result<ChainInputPin<'_, L, SHLD, N>, ShiftError> {
+        split_pin(pin, N)?;
+        Ok(ChainInputPin { chain: self, pin })
+    }
+}
+
+// 输入链上的单个引脚，每次读取都会重新读取整条链，需要同时读取多个引脚时，直接使用 InputChain::read
+pub(crate) struct ChainInputPin<'c, L, SHLD, const N: usize> {
+    chain: &'c InputChain<L, SHLD, N>,
+    pin: u16,
+}
+
+impl<L, SHLD, const N: usize> ErrorType for ChainInputPin<'_, L, SHLD, N> {
+    type Error = ShiftError;
+}
+
+impl<L: Link, SHLD: OutputPin, const N: usize> InputPin for ChainInputPin<'_, L, SHLD, N> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        self.chain.is_high(self.pin)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.chain.is_high(self.pin)?)
+    }
+}
@@ -0,0 +1,328 @@
+//! Sensirion SHT3x/SHT4x 温湿度传感器（I2C）
+//!
+//! 两个系列的读数格式相同：温度与湿度各一个 16 bit 的字，每个字后面跟一个 CRC-8，一共 6 个字节
+//! 主要的区别在于命令：SHT3x 的命令为 16 bit，SHT4x 的命令为 8 bit，换算湿度的公式也略有不同
+//!
+//! 测量模式：
+//! - SingleShot：每次采样时发出一次测量命令，等待转换完成后读取结果，两次采样之间芯片处于休眠，功耗最低
+//! - Periodic（仅 SHT3x）：芯片按照固定的频率自行测量，采样时只需要读取最新的结果（fetch data）
+//!   还没有新数据时芯片会 NACK 读方向的地址，此时 sample 返回 NotReady
+//!
+//! 加热器用于驱散传感器表面的凝露，或者检查传感器是否正常（加热时温度应该上升、湿度应该下降）：
+//! - SHT3x 的加热器可以一直开着，开启之后照常采样即可
+//! - SHT4x 的加热器只能以脉冲的方式工作，开启之后每次采样都改为“以 20 mW 加热 0.1 s，然后测量”，
+//!   加热器的占空比不应超过 10%，因此此时的采样间隔至少应为 1 s
+//!
+//! 无论哪种情况，加热时读到的温湿度都不代表环境的真实值
+//!
+//! 每一个字都会检查 CRC，不一致时返回 Checksum
+//! Sht 同时实现了 EnvironmentSource，可以替代 BME280 为超声波测距提供温湿度补偿（见 s21c02）
+//!
+//! I2C 外设需要事先配置好（见 s21c02），这里只通过 utils::blocking_master 收发数据
+//! 为了不占用总线，这里不使用 clock stretching，而是在发出测量命令之后等待足够的时间再读取
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::i2c1::RegisterBlock;
+
+use super::{
+    addressing::I2cAddress,
+    blocking_master::{self, MasterError},
+    sensor::{
+        ultrasonic::{Air, EnvironmentSource},
+        Celsius, Measurement, Reading, RelativeHumidity, Sensor, SensorError,
+    },
+    ticker,
+};
+
+// SHT3x 的 ADDR 引脚接 GND 时为 0x44，接 VDD 时为 0x45
+// SHT4x 的地址由型号决定，SHT40-AD1B 为 0x44，SHT40-BD1B 为 0x45，SHT40-CD1B 为 0x46
+pub(crate) const ADDR_DEFAULT: u8 = 0x44;
+pub(crate) const ADDR_ALT: u8 = 0x45;
+
+// SHT3x 的命令，测量均为 high repeatability，不使用 clock stretching
+const SHT3X_SINGLE_SHOT: u16 = 0x2400;
+const SHT3X_FETCH_DATA: u16 = 0xE000;
+const SHT3X_BREAK: u16 = 0x3093;
+const SHT3X_SOFT_RESET: u16 = 0x30A2;
+const SHT3X_HEATER_ON: u16 = 0x306D;
+const SHT3X_HEATER_OFF: u16 = 0x3066;
+const SHT3X_READ_SERIAL: u16 = 0x3780;
+// high repeatability 下最长 15.5 ms
+const SHT3X_MEASURE_MS: u32 = 16;
+
+// SHT4x 的命令
+const SHT4X_MEASURE_HIGH: u8 = 0xFD;
+const SHT4X_HEAT_20MW_100MS: u8 = 0x15;
+const SHT4X_SOFT_RESET: u8 = 0x94;
+const SHT4X_READ_SERIAL: u8 = 0x89;
+// high precision 下最长 8.3 ms
+const SHT4X_MEASURE_MS: u32 = 9;
+// 加热 0.1 s 之后还要再测量一次，最长约 110 ms
+const SHT4X_HEAT_MS: u32 = 111;
+
+// 软复位、读取序列号等命令最长需要 1.5 ms
+const COMMAND_MS: u32 = 2;
+
+// CRC-8，多项式 0x31，初始值 0xFF，不反转输入输出，结果不异或，见 datasheet 的 Checksum Calculation 一节
+pub(crate) const fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0xFFu8;
+    let mut index = 0;
+    while index < data.len() {
+        crc ^= data[index];
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        index += 1;
+    }
+    crc
+}
+
+// datasheet 给出的例子，0xBEEF 的 CRC 应为 0x92
+const _: () = assert!(crc8(&[0xBE, 0xEF]) == 0x92);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Model {
+    Sht3x,
+    Sht4x,
+}
+
+// 周期测量模式下，每秒测量的次数
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Rate {
+    Mps0_5,
+    Mps1,
+    Mps2,
+    Mps4,
+    Mps10,
+}
+
+impl Rate {
+    // 对应的 high repeatability 命令
+    fn command(self) -> u16 {
+        match self {
+            Rate::Mps0_5 => 0x2032,
+            Rate::Mps1 => 0x2130,
+            Rate::Mps2 => 0x2236,
+            Rate::Mps4 => 0x2334,
+            Rate::Mps10 => 0x2737,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Mode {
+    SingleShot,
+    Periodic(Rate),
+}
+
+// 一次采样的结果
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct ShtMeasurement {
+    pub(crate) temp: Celsius,
+    pub(crate) rh: RelativeHumidity,
+}
+
+impl Measurement for ShtMeasurement {
+    fn for_each_reading(&self, f: &mut dyn FnMut(Reading)) {
+        self.temp.for_each_reading(f);
+        self.rh.for_each_reading(f);
+    }
+}
+
+pub(crate) struct Sht<'a> {
+    i2c: &'a RegisterBlock,
+    addr: I2cAddress,
+    model: Model,
+    mode: Mode,
+    heater: bool,
+    serial: u32,
+}
+
+impl<'a> Sht<'a> {
+    // 软复位芯片，并读取序列号，之后处于 SingleShot 模式，加热器关闭
+    pub(crate) fn new(i2c: &'a RegisterBlock, model: Model, addr: u8) -> Result<Self, SensorError> {
+        let mut sht = Self {
+            i2c,
+            addr: I2cAddress::SevenBit(addr),
+            model,
+            mode: Mode::SingleShot,
+            heater: false,
+            serial: 0,
+        };
+
+        match model {
+            Model::Sht3x => {
+                // 芯片可能还处于上一次运行时开启的周期测量模式，此时它只接受 break 与软复位
+                sht.command16(SHT3X_BREAK)?;
+                ticker::delay_ms(COMMAND_MS);
+                sht.command16(SHT3X_SOFT_RESET)?;
+                ticker::delay_ms(COMMAND_MS);
+                sht.command16(SHT3X_READ_SERIAL)?;
+            }
+            Model::Sht4x => {
+                sht.command8(SHT4X_SOFT_RESET)?;
+                ticker::delay_ms(COMMAND_MS);
+                sht.command8(SHT4X_READ_SERIAL)?;
+            }
+        }
+        ticker::delay_ms(COMMAND_MS);
+        let [high, low] = sht.read_words()?;
+        sht.serial = ((high as u32) << 16) | low as u32;
+
+        Ok(sht)
+    }
+
+    pub(crate) fn model(&self) -> Model {
+        self.model
+    }
+
+    // 芯片出厂时写入的 32 bit 序列号，可以用来区分同一条总线上的多个传感器，或者记录在日志里
+    pub(crate) fn serial_number(&self) -> u32 {
+        self.serial
+    }
+
+    pub(crate) fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // SHT4x 不支持周期测量，返回 Unsupported
+    pub(crate) fn set_mode(&mut self, mode: Mode) -> Result<(), SensorError> {
+        if self.model == Model::Sht4x && mode != Mode::SingleShot {
+            return Err(SensorError::Unsupported);
+        }
+        if self.model == Model::Sht3x {
+            self.stop_periodic()?;
+            if let Mode::Periodic(rate) = mode {
+                self.command16(rate.command())?;
+            }
+        }
+        self.mode = mode;
+        Ok(())
+    }
+
+    pub(crate) fn heater(&self) -> bool {
+        self.heater
+    }
+
+    pub(crate) fn set_heater(&mut self, on: bool) -> Result<(), SensorError> {
+        if self.model == Model::Sht3x {
+            // 周期测量时芯片不接受加热器的命令，需要先停下来，设置完再重新开始
+            self.stop_periodic()?;
+            self.command16(if on {
+                SHT3X_HEATER_ON
+            } else {
+                SHT3X_HEATER_OFF
+            })?;
+            if let Mode::Periodic(rate) = self.mode {
+                ticker::delay_ms(COMMAND_MS);
+                self.command16(rate.command())?;
+            }
+        }
+        // SHT4x 的加热器在每次采样时才会使用，这里只需要记下来
+        self.heater = on;
+        Ok(())
+    }
+
+    fn stop_periodic(&self) -> Result<(), SensorError> {
+        if let Mode::Periodic(_) = self.mode {
+            self.command16(SHT3X_BREAK)?;
+            ticker::delay_ms(COMMAND_MS);
+        }
+        Ok(())
+    }
+
+    fn command16(&self, command: u16) -> Result<(), SensorError> {
+        blocking_master::write(self.i2c, self.addr, &command.to_be_bytes())?;
+        Ok(())
+    }
+
+    fn command8(&self, command: u8) -> Result<(), SensorError> {
+        blocking_master::write(self.i2c, self.addr, &[command])?;
+        Ok(())
+    }
+
+    // 读取两个字，并检查每个字的 CRC
+    fn read_words(&self) -> Result<[u16; 2], SensorError> {
+        let mut buf = [0u8; 6];
+        blocking_master::read(self.i2c, self.addr, &mut buf).map_err(|e| match e {
+            // 转换尚未完成，或者周期测量模式下还没有新的数据
+            MasterError::Nack => SensorError::NotReady,
+            e => SensorError::from(e),
+        })?;
+
+        let mut words = [0u16; 2];
+        for (word, chunk) in words.iter_mut().zip(buf.chunks_exact(3)) {
+            if crc8(&chunk[..2]) != chunk[2] {
+                return Err(SensorError::Checksum);
+            }
+            *word = u16::from_be_bytes([chunk[0], chunk[1]]);
+        }
+        Ok(words)
+    }
+
+    fn convert(&self, raw_t: u16, raw_rh: u16) -> ShtMeasurement {
+        let temp = -45.0 + 175.0 * raw_t as f32 / 65535.0;
+        let rh = match self.model {
+            Model::Sht3x => 100.0 * raw_rh as f32 / 65535.0,
+            // SHT4x 的公式允许结果略微超出 0 ~ 100 %，需要自己截断
+            Model::Sht4x => (-6.0 + 125.0 * raw_rh as f32 / 65535.0).clamp(0.0, 100.0),
+        };
+        ShtMeasurement {
+            temp: Celsius(temp),
+            rh: RelativeHumidity(rh),
+        }
+    }
+}
+
+impl Sensor for Sht<'_> {
+    type Output = ShtMeasurement;
+
+    fn name(&self) -> &'static str {
+        "sht"
+    }
+
+    fn sample(&mut self) -> Result<ShtMeasurement, SensorError> {
+        match (self.model, self.mode) {
+            (Model::Sht3x, Mode::SingleShot) => {
+                self.command16(SHT3X_SINGLE_SHOT)?;
+                ticker::delay_ms(SHT3X_MEASURE_MS);
+            }
+            (Model::Sht3x, Mode::Periodic(_)) => self.command16(SHT3X_FETCH_DATA)?,
+            (Model::Sht4x, _) if self.heater => {
+                self.command8(SHT4X_HEAT_20MW_100MS)?;
+                ticker::delay_ms(SHT4X_HEAT_MS);
+            }
+            (Model::Sht4x, _) => {
+                self.command8(SHT4X_MEASURE_HIGH)?;
+                ticker::delay_ms(SHT4X_MEASURE_MS);
+            }
+        }
+
+        let [raw_t, raw_rh] = self.read_words()?;
+        Ok(self.convert(raw_t, raw_rh))
+    }
+}
+
+impl EnvironmentSource for Sht<'_> {
+    fn air(&mut self) -> Result<Air, SensorError> {
+        let m = self.sample()?;
+        Ok(Air {
+            temp: m.temp,
+            humidity: Some(m.rh),
+        })
+    }
+}
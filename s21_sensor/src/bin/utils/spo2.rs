@@ -0,0 +1,230 @@
+//! 由红光、红外两路 PPG（光电容积脉搏波）估计心率与血氧饱和度
+//!
+//! 手指中的血液对光的吸收随心跳变化，光电 ADC 读到的信号 = 很大的直流（组织、静脉血）+ 很小的交流（动脉血的搏动），
+//! 交流部分通常只有直流的 0.5% ~ 2%
+//!
+//! 流水线（每一路相同，滤波器见 utils::dsp）：
+//!
+//! 1. Ema 跟踪直流分量 DC，红外的 DC 同时用来判断手指是否放好
+//! 2. DcBlocker 去掉直流，得到交流分量 AC，再用 MovingAverage 平滑掉高频噪声
+//! 3. 心跳检测：心脏收缩时吸收增加、信号下降，因此把红外的 AC 取反，每次由负变正的过零点算作一次心跳，
+//!    两次心跳之间的采样数换算为心率；两次心跳的间隔必须在 30 ~ 220 bpm 之间，太近的过零点视为噪声忽略
+//! 4. 每次心跳时，用上一个心跳周期内 AC 的峰峰值与 DC 计算比值的比值：
+//!    R = (AC_red / DC_red) / (AC_ir / DC_ir)
+//!    再用 Maxim 给出的经验公式换算为 SpO2：SpO2 = -45.060 R^2 + 30.354 R + 94.845
+//!
+//! 芯片温度会让红光 LED 的波长漂移（约 0.1 ~ 0.2 nm/℃），血红蛋白在 660 nm 附近的吸收曲线很陡，
+//! 因此 R 会随温度变化，这里用一个线性系数把 R 修正到 25 ℃ 时的值，TEMP_COEFF 需要按实际的模块标定
+//!
+//! 经验公式只适用于 Maxim 的参考设计，这里的结果只能作为演示，不能用于医疗用途
+
+#![allow(dead_code)]
+
+use super::{
+    dsp::{DcBlocker, Ema, MovingAverage},
+    max30102::Sample,
+};
+
+// 手指放好时，红外的直流分量至少有这么大（18 bit ADC 的读数），否则认为没有手指
+const FINGER_THRESHOLD: f32 = 50_000.0;
+
+const DC_ALPHA: f32 = 0.05;
+const AC_ALPHA: f32 = 0.95;
+
+const MIN_BPM: f32 = 30.0;
+const MAX_BPM: f32 = 220.0;
+
+// 每 ℃ R 的相对变化，需要标定
+const TEMP_COEFF: f32 = 0.002;
+const TEMP_REF: f32 = 25.0;
+
+// 至少检测到这么多次心跳之后才给出结果
+const MIN_BEATS: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Estimate {
+    pub(crate) bpm: f32,
+    // 百分比
+    pub(crate) spo2: f32,
+    // 修正温度之后的 R
+    pub(crate) ratio: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Status {
+    NoFinger,
+    // 手指已经放好，还在等待足够多的心跳
+    Acquiring,
+    Tracking,
+}
+
+// 一路信号的滤波器，以及一个心跳周期内 AC 的最大、最小值
+struct Channel {
+    dc: Ema,
+    ac: DcBlocker,
+    smooth: MovingAverage<4>,
+    max: f32,
+    min: f32,
+}
+
+impl Channel {
+    const fn new() -> Self {
+        Self {
+            dc: Ema::new(DC_ALPHA),
+            ac: DcBlocker::new(AC_ALPHA),
+            smooth: MovingAverage::new(),
+            max: f32::MIN,
+            min: f32::MAX,
+        }
+    }
+
+    // 返回平滑之后的 AC
+    fn process(&mut self, x: f32) -> f32 {
+        self.dc.process(x);
+        let ac = self.smooth.process(self.ac.process(x));
+        self.max = self.max.max(ac);
+        self.min = self.min.min(ac);
+        ac
+    }
+
+    // 一个心跳周期内 AC 的峰峰值与 DC 之比，并开始下一个周期
+    fn take_perfusion(&mut self) -> Option<f32> {
+        let pp = self.max - self.min;
+        self.max = f32::MIN;
+        self.min = f32::MAX;
+        let dc = self.dc.value()?;
+        if pp <= 0.0 || dc <= 0.0 {
+            return None;
+        }
+        Some(pp / dc)
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+pub(crate) struct Spo2Estimator {
+    // FIFO 中采样的频率
+    sample_hz: f32,
+    red: Channel,
+    ir: Channel,
+
+    prev_ir: f32,
+    since_beat: u32,
+    // 第一次过零点之前为 false，此时的间隔没有意义
+    seen_beat: bool,
+    beats: u32,
+    intervals: MovingAverage<4>,
+    ratios: MovingAverage<4>,
+
+    temperature: Option<f32>,
+    status: Status,
+}
+
+impl Spo2Estimator {
+    pub(crate) const fn new(sample_hz: f32) -> Self {
+        Self {
+            sample_hz,
+            red: Channel::new(),
+            ir: Channel::new(),
+            prev_ir: 0.0,
+            since_beat: 0,
+            seen_beat: false,
+            beats: 0,
+            intervals: MovingAverage::new(),
+            ratios: MovingAverage::new(),
+            temperature: None,
+            status: Status::NoFinger,
+        }
+    }
+
+    // 芯片温度，单位 ℃，没有设置时不做温度修正
+    pub(crate) fn set_temperature(&mut self, celsius: f32) {
+        self.temperature = Some(celsius);
+    }
+
+    pub(crate) fn status(&self) -> Status {
+        self.status
+    }
+
+    // 每个采样调用一次，检测到心跳时返回 true
+    pub(crate) fn process(&mut self, sample: Sample) -> bool {
+        let ir_raw = sample.ir as f32;
+        if ir_raw < FINGER_THRESHOLD {
+            if self.status != Status::NoFinger {
+                self.reset();
+            }
+            return false;
+        }
+        if self.status == Status::NoFinger {
+            self.status = Status::Acquiring;
+        }
+
+        self.red.process(sample.red as f32);
+        let ir = -self.ir.process(ir_raw);
+
+        self.since_beat += 1;
+        let min_interval = (self.sample_hz * 60.0 / MAX_BPM) as u32;
+        let max_interval = (self.sample_hz * 60.0 / MIN_BPM) as u32;
+
+        // 太久没有心跳，可能是手指动了，重新开始
+        if self.seen_beat && self.since_beat > max_interval {
+            self.seen_beat = false;
+            self.beats = 0;
+            self.intervals.reset();
+            self.ratios.reset();
+            self.status = Status::Acquiring;
+        }
+
+        let crossed = self.prev_ir <= 0.0 && ir > 0.0;
+        self.prev_ir = ir;
+        if !crossed || (self.seen_beat && self.since_beat < min_interval) {
+            return false;
+        }
+
+        let red_perfusion = self.red.take_perfusion();
+        let ir_perfusion = self.ir.take_perfusion();
+        if self.seen_beat {
+            self.intervals.process(self.since_beat as f32);
+            if let (Some(red), Some(ir)) = (red_perfusion, ir_perfusion) {
+                self.ratios.process(red / ir);
+            }
+            self.beats += 1;
+            if self.beats >= MIN_BEATS {
+                self.status = Status::Tracking;
+            }
+        }
+        self.seen_beat = true;
+        self.since_beat = 0;
+        true
+    }
+
+    // 心跳次数足够时，给出最近几次心跳的平均结果
+    pub(crate) fn estimate(&self) -> Option<Estimate> {
+        if self.status != Status::Tracking {
+            return None;
+        }
+        let interval = self.intervals.average()?;
+        let mut ratio = self.ratios.average()?;
+        if let Some(t) = self.temperature {
+            ratio *= 1.0 + TEMP_COEFF * (t - TEMP_REF);
+        }
+        let spo2 = -45.060 * ratio * ratio + 30.354 * ratio + 94.845;
+        Some(Estimate {
+            bpm: 60.0 * self.sample_hz / interval,
+            spo2: spo2.clamp(0.0, 100.0),
+            ratio,
+        })
+    }
+
+    pub(crate) fn reset(&mut self) {
+        let temperature = self.temperature;
+        *self = Self::new(self.sample_hz);
+        self.temperature = temperature;
+    }
+}
@@ -0,0 +1,145 @@
+//! 在 TFT 屏幕上显示 utils::ui 的菜单，并用触摸屏操作
+//!
+//! TftPanel 实现了 TextPanel，Ui 可以像使用 LCD1602 一样使用它：两行文字，每行 COLUMNS 个字符，
+//! 文字先画在帧缓冲中，每次 write_line 之后只发送这一行所在的区域（见 utils::framebuffer 与 utils::st7789）
+//!
+//! 屏幕底部是一排四个按钮：Back、Prev、Next、Enter，与 utils::keypad 的四个按键一一对应，
+//! button_at 把触摸的坐标转换为 ui::Event，通常在 TouchEvent::Up 时调用，也就是“点击”之后才触发，
+//! 这样手指在屏幕上滑过时不会误触
+//!
+//! 布局按 240x240 的屏幕设计，更大的屏幕也可以使用，只是右侧与下方会空出来
+
+#![allow(dead_code)]
+
+use super::{
+    font5x7,
+    framebuffer::{self, Color, FrameBuffer, Rect},
+    sensor::sink::TextPanel,
+    st7789::{PanelBus, St7789},
+    ui::Event,
+    xpt2046::Point,
+};
+
+// 文字放大 2 倍，每个字符 12 个像素宽
+const SCALE: u16 = 2;
+const CHAR_WIDTH: u16 = (font5x7::WIDTH as u16 + 1) * SCALE;
+const LINE_HEIGHT: u16 = 32;
+const TEXT_TOP: u16 = 24;
+const MARGIN: u16 = 6;
+
+const BUTTON_TOP: u16 = 180;
+const BUTTON_WIDTH: u16 = 60;
+const BUTTON_HEIGHT: u16 = 60;
+const BUTTONS: [(Event, &str); 4] = [
+    (Event::Back, "BACK"),
+    (Event::Prev, "<"),
+    (Event::Next, ">"),
+    (Event::Enter, "OK"),
+];
+
+const BACKGROUND: Color = framebuffer::BLACK;
+const FOREGROUND: Color = framebuffer::WHITE;
+const BUTTON_FACE: Color = framebuffer::rgb565(40, 40, 96);
+const BUTTON_PRESSED: Color = framebuffer::rgb565(96, 96, 200);
+
+pub(crate) struct TftPanel<'a, B: PanelBus> {
+    fb: FrameBuffer<'a>,
+    lcd: St7789<B>,
+}
+
+impl<'a, B: PanelBus> TftPanel<'a, B> {
+    // 屏幕需要已经 init 过，这里清屏并画出按钮
+    pub(crate) fn new(fb: FrameBuffer<'a>, lcd: St7789<B>) -> Self {
+        let mut panel = Self { fb, lcd };
+        panel.fb.clear(BACKGROUND);
+        for index in 0..BUTTONS.len() {
+            panel.draw_button(index, false);
+        }
+        panel.lcd.flush(&mut panel.fb);
+        panel
+    }
+
+    // 触摸的坐标落在哪个按钮上
+    pub(crate) fn button_at(&self, point: Point) -> Option<Event> {
+        let index = button_index(point)?;
+        Some(BUTTONS[index].0)
+    }
+
+    // 按下时高亮对应的按钮，抬起时恢复，给用户一个反馈
+    pub(crate) fn highlight(&mut self, point: Option<Point>) {
+        let pressed = point.and_then(button_index);
+        for index in 0..BUTTONS.len() {
+            self.draw_button(index, pressed == Some(index));
+        }
+        self.lcd.flush(&mut self.fb);
+    }
+
+    // 直接在帧缓冲上绘制，比如校准时的十字，之后需要调用 flush
+    pub(crate) fn frame_buffer(&mut self) -> &mut FrameBuffer<'a> {
+        &mut self.fb
+    }
+
+    pub(crate) fn flush(&mut self) {
+        self.lcd.flush(&mut self.fb);
+    }
+
+    // 清除 frame_buffer 上画的内容，恢复按钮，文字需要 Ui 重新绘制
+    pub(crate) fn redraw(&mut self) {
+        self.fb.clear(BACKGROUND);
+        for index in 0..BUTTONS.len() {
+            self.draw_button(index, false);
+        }
+        self.lcd.flush(&mut self.fb);
+    }
+
+    // 每次绘制都会产生脏矩形，因此只在按下、抬起时调用
+    fn draw_button(&mut self, index: usize, pressed: bool) {
+        let (_, label) = BUTTONS[index];
+        let x = index as u16 * BUTTON_WIDTH;
+        let face = if pressed { BUTTON_PRESSED } else { BUTTON_FACE };
+
+        // 留 2 个像素的缝隙
+        self.fb.fill_rect(
+            Rect::new(x, BUTTON_TOP, BUTTON_WIDTH, BUTTON_HEIGHT),
+            BACKGROUND,
+        );
+        self.fb.fill_rect(
+            Rect::new(x + 2, BUTTON_TOP + 2, BUTTON_WIDTH - 4, BUTTON_HEIGHT - 4),
+            face,
+        );
+
+        let text_width = label.len() as u16 * CHAR_WIDTH;
+        let text_x = x + (BUTTON_WIDTH - text_width) / 2;
+        let text_y = BUTTON_TOP + (BUTTON_HEIGHT - font5x7::HEIGHT as u16 * SCALE) / 2;
+        self.fb
+            .draw_text(text_x, text_y, label, FOREGROUND, u8::MAX, SCALE);
+    }
+}
+
+impl<B: PanelBus> TextPanel for TftPanel<'_, B> {
+    const COLUMNS: usize = ((240 - 2 * MARGIN) / CHAR_WIDTH) as usize;
+
+    fn write_line(&mut self, row: u8, text: &[u8]) {
+        let y = TEXT_TOP + row as u16 * LINE_HEIGHT;
+        self.fb.fill_rect(
+            Rect::new(0, y, self.fb.width(), font5x7::HEIGHT as u16 * SCALE),
+            BACKGROUND,
+        );
+
+        let len = text.len().min(Self::COLUMNS);
+        let text = core::str::from_utf8(&text[..len]).unwrap_or("?");
+        self.fb
+            .draw_text(MARGIN, y, text, FOREGROUND, u8::MAX, SCALE);
+
+        self.lcd.flush(&mut self.fb);
+    }
+}
+
+fn button_index(point: Point) -> Option<usize> {
+    if point.y < BUTTON_TOP as i16 || point.y >= (BUTTON_TOP + BUTTON_HEIGHT) as i16 || point.x < 0
+    {
+        return None;
+    }
+    let index = point.x as usize / BUTTON_WIDTH as usize;
+    (index < BUTTONS.len()).then_some(index)
+}
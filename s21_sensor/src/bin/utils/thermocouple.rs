@@ -0,0 +1,276 @@
+//! MAX6675/MAX31855 K 型热电偶转换芯片（SPI）
+//!
+//! 两者都是只读的：拉低片选之后直接读出数据，不需要发送任何命令，拉低片选的同时会中止正在进行的转换，
+//! 拉高片选之后重新开始转换，因此两次读取之间需要间隔一次转换的时间，否则读到的永远是旧的数据
+//!
+//! MAX6675，16 位，转换时间约 220 ms：
+//!
+//! | 位      | 内容                                 |
+//! | ------- | ------------------------------------ |
+//! | D15     | 固定为 0                             |
+//! | D14~D3  | 12 位的温度，0.25 °C/LSB，0 ~ 1023.75 °C |
+//! | D2      | 热电偶开路                           |
+//! | D1      | 固定为 0，可以用来判断芯片是否接好   |
+//!
+//! MAX31855，32 位，转换时间约 100 ms：
+//!
+//! | 位      | 内容                                         |
+//! | ------- | -------------------------------------------- |
+//! | D31~D18 | 14 位有符号的热电偶温度，0.25 °C/LSB          |
+//! | D16     | 出现了下面任意一种故障                       |
+//! | D15~D4  | 12 位有符号的冷端（芯片自身）温度，0.0625 °C/LSB |
+//! | D2      | SCV：热电偶对 VCC 短路                       |
+//! | D1      | SCG：热电偶对 GND 短路                       |
+//! | D0      | OC：热电偶开路                               |
+//!
+//! 故障会以 ThermocoupleError 返回，而不是给出一个错误的温度；通过 Sensor 使用时，统一转换为 SensorError::Fault
+//!
+//! 热电偶的读数噪声不小，N 大于 1 时，Sensor 输出最近 N 次读数的滑动平均，出现故障时清空
+//!
+//! SPI 外设需要事先配置为 Mode 0，8 位（见 s21c11），SCK 不能超过 4.3 MHz，这里只负责接收数据
+//! 片选是泛型参数，任何实现了 embedded-hal OutputPin 的引脚都可以
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::{hal::digital::OutputPin, pac::spi1::RegisterBlock};
+
+use super::{
+    sensor::{Celsius, Measurement, Reading, Sensor, SensorError},
+    ticker,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Model {
+    Max6675,
+    Max31855,
+}
+
+impl Model {
+    // 一次转换所需的时间，留了一些余量
+    pub(crate) fn conversion_ms(self) -> u32 {
+        match self {
+            Model::Max6675 => 250,
+            Model::Max31855 => 110,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum ThermocoupleError {
+    // 热电偶开路，或者没有接
+    Open,
+    // 热电偶对 GND 短路，仅 MAX31855
+    ShortToGnd,
+    // 热电偶对 VCC 短路，仅 MAX31855
+    ShortToVcc,
+    // 读到的数据不符合格式，比如芯片没有接好时 MISO 读到全 0 或者全 1
+    NoDevice,
+    // 上一次转换还没有完成
+    NotReady,
+    // 片选引脚出错
+    ChipSelect,
+}
+
+impl From<ThermocoupleError> for SensorError {
+    fn from(e: ThermocoupleError) -> Self {
+        match e {
+            ThermocoupleError::Open
+            | ThermocoupleError::ShortToGnd
+            | ThermocoupleError::ShortToVcc => SensorError::Fault,
+            ThermocoupleError::NoDevice | ThermocoupleError::ChipSelect => SensorError::Bus,
+            ThermocoupleError::NotReady => SensorError::NotReady,
+        }
+    }
+}
+
+// 一次读取的结果
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct ThermocoupleMeasurement {
+    // 热端，也就是热电偶测到的温度
+    pub(crate) hot: Celsius,
+    // 冷端，也就是芯片自身的温度，仅 MAX31855
+    pub(crate) cold: Option<Celsius>,
+}
+
+impl Measurement for ThermocoupleMeasurement {
+    fn for_each_reading(&self, f: &mut dyn FnMut(Reading)) {
+        self.hot.for_each_reading(f);
+        if let Some(cold) = self.cold {
+            f(Reading {
+                quantity: "cj",
+                value: cold.0,
+                unit: "C",
+            });
+        }
+    }
+}
+
+pub(crate) struct Thermocouple<'a, CS, const N: usize = 1> {
+    spi: &'a RegisterBlock,
+    cs: CS,
+    model: Model,
+    last_read_ms: Option<u32>,
+    average: MovingAverage<N>,
+}
+
+impl<'a, CS: OutputPin, const N: usize> Thermocouple<'a, CS, N> {
+    pub(crate) fn new(
+        spi: &'a RegisterBlock,
+        mut cs: CS,
+        model: Model,
+    ) -> Result<Self, ThermocoupleError> {
+        cs.set_high().map_err(|_| ThermocoupleError::ChipSelect)?;
+        Ok(Self {
+            spi,
+            cs,
+            model,
+            // 上电之后的第一次转换也需要时间
+            last_read_ms: Some(ticker::millis()),
+            average: MovingAverage::new(),
+        })
+    }
+
+    pub(crate) fn model(&self) -> Model {
+        self.model
+    }
+
+    // 读取一次，不经过滑动平均
+    pub(crate) fn read(&mut self) -> Result<ThermocoupleMeasurement, ThermocoupleError> {
+        let now = ticker::millis();
+        if let Some(last) = self.last_read_ms {
+            if now.wrapping_sub(last) < self.model.conversion_ms() {
+                return Err(ThermocoupleError::NotReady);
+            }
+        }
+
+        let mut buf = [0u8; 4];
+        let len = match self.model {
+            Model::Max6675 => 2,
+            Model::Max31855 => 4,
+        };
+        self.cs
+            .set_low()
+            .map_err(|_| ThermocoupleError::ChipSelect)?;
+        for byte in buf.iter_mut().take(len) {
+            *byte = self.transfer(0);
+        }
+        self.cs
+            .set_high()
+            .map_err(|_| ThermocoupleError::ChipSelect)?;
+        self.last_read_ms = Some(now);
+
+        match self.model {
+            Model::Max6675 => decode_max6675(u16::from_be_bytes([buf[0], buf[1]])),
+            Model::Max31855 => decode_max31855(u32::from_be_bytes(buf)),
+        }
+    }
+
+    pub(crate) fn release(self) -> CS {
+        self.cs
+    }
+
+    fn transfer(&self, byte: u8) -> u8 {
+        let spi = self.spi;
+        while spi.sr.read().txe().is_not_empty() {}
+        spi.dr.write(|w| w.dr().bits(byte as u16));
+        while spi.sr.read().rxne().is_empty() {}
+        spi.dr.read().dr().bits() as u8
+    }
+}
+
+impl<CS: OutputPin, const N: usize> Sensor for Thermocouple<'_, CS, N> {
+    type Output = ThermocoupleMeasurement;
+
+    fn name(&self) -> &'static str {
+        "tc"
+    }
+
+    fn sample(&mut self) -> Result<ThermocoupleMeasurement, SensorError> {
+        match self.read() {
+            Ok(measurement) => Ok(ThermocoupleMeasurement {
+                hot: Celsius(self.average.push(measurement.hot.0)),
+                cold: measurement.cold,
+            }),
+            Err(e) => {
+                // 故障恢复之后，不应该再和故障之前的读数混在一起
+                if e != ThermocoupleError::NotReady {
+                    self.average.clear();
+                }
+                Err(e.into())
+            }
+        }
+    }
+}
+
+fn decode_max6675(raw: u16) -> Result<ThermocoupleMeasurement, ThermocoupleError> {
+    // D15 与 D1 固定为 0
+    if raw & 0x8002 != 0 {
+        return Err(ThermocoupleError::NoDevice);
+    }
+    if raw & (1 << 2) != 0 {
+        return Err(ThermocoupleError::Open);
+    }
+    Ok(ThermocoupleMeasurement {
+        hot: Celsius((raw >> 3) as f32 * 0.25),
+        cold: None,
+    })
+}
+
+fn decode_max31855(raw: u32) -> Result<ThermocoupleMeasurement, ThermocoupleError> {
+    // D17 与 D3 固定为 0，MISO 悬空读到全 1 时也会在这里被发现
+    if raw & ((1 << 17) | (1 << 3)) != 0 {
+        return Err(ThermocoupleError::NoDevice);
+    }
+    if raw & (1 << 16) != 0 {
+        return Err(if raw & (1 << 0) != 0 {
+            ThermocoupleError::Open
+        } else if raw & (1 << 1) != 0 {
+            ThermocoupleError::ShortToGnd
+        } else {
+            ThermocoupleError::ShortToVcc
+        });
+    }
+    // 把有符号数移到最高位，再算术右移回来，完成符号扩展
+    let hot = (raw as i32) >> 18;
+    let cold = ((raw << 16) as i32) >> 20;
+    Ok(ThermocoupleMeasurement {
+        hot: Celsius(hot as f32 * 0.25),
+        cold: Some(Celsius(cold as f32 * 0.0625)),
+    })
+}
+
+// 最近 N 个数的滑动平均，N 为 1 时就是原样输出
+pub(crate) struct MovingAverage<const N: usize> {
+    buf: [f32; N],
+    len: usize,
+    next: usize,
+}
+
+impl<const N: usize> MovingAverage<N> {
+    pub(crate) const fn new() -> Self {
+        assert!(N > 0);
+        Self {
+            buf: [0.0; N],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    // 加入一个数，返回当前的平均值，还没有攒够 N 个数时，按已有的个数平均
+    pub(crate) fn push(&mut self, value: f32) -> f32 {
+        self.buf[self.next] = value;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+        self.buf[..self.len].iter().sum::<f32>() / self.len as f32
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+}
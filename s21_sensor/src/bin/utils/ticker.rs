@@ -0,0 +1,78 @@
+//! 以 TIM2 + TIM5 级联作为系统的单调时钟
+//!
+//! TIM2 以 1 MHz 的频率自由计数，每次溢出时通过 TRGO 让 TIM5 计数一次，两者拼起来就是一个 64 bit 的微秒计数器，
+//! 级联的原理见 s06 的 utils::chain，这里不需要任何中断，也可以认为永远不会回绕
+//!
+//! millis() 依旧返回 u32 的毫秒数，大约 49.7 天后会回绕，因此比较时间时，应该使用 wrapping_sub 的结果来判断先后
+//! 需要更长时间跨度（几小时到几天）的超时或者时间戳时，请使用 micros()
+//!
+//! 读取时间只是读一下 CNT 寄存器，在中断里和主循环里都可以随意调用
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac;
+
+// 假设 APB1 的 TIM 时钟为 12 MHz（直接使用 HSE，且不分频）
+const TIM_CLK_HZ: u32 = 12_000_000;
+
+pub(crate) fn setup(dp: &pac::Peripherals) {
+    dp.RCC.apb1enr.modify(|_, w| {
+        w.tim2en().enabled();
+        w.tim5en().enabled();
+        w
+    });
+
+    let tim2 = &dp.TIM2;
+    let tim5 = &dp.TIM5;
+
+    // 12 MHz / 12 = 1 MHz
+    tim2.psc
+        .write(|w| w.psc().bits((TIM_CLK_HZ / 1_000_000 - 1) as u16));
+    tim2.arr.write(|w| w.arr().bits(u32::MAX));
+    // TIM2 溢出时，在 TRGO 上输出一个脉冲
+    tim2.cr2.modify(|_, w| w.mms().update());
+
+    // TIM5 的 ITR0 连接到 TIM2 的 TRGO，使用 External Clock Mode 1，每个脉冲计数一次
+    tim5.psc.write(|w| w.psc().bits(0));
+    tim5.arr.write(|w| w.arr().bits(u32::MAX));
+    tim5.smcr.modify(|_, w| {
+        w.ts().itr0();
+        w.sms().ext_clock_mode();
+        w
+    });
+    tim5.egr.write(|w| w.ug().update());
+    tim5.cr1.modify(|_, w| w.cen().enabled());
+
+    // 手动产生一次更新事件，让 PSC 的值立刻生效，并清零 CNT
+    // 这次更新事件同样会经过 TRGO 让 TIM5 加 1，因此之后还要再把 TIM5 清零
+    tim2.egr.write(|w| w.ug().update());
+    tim5.cnt.reset();
+
+    tim2.cr1.modify(|_, w| w.cen().enabled());
+}
+
+// 开机以来的微秒数
+pub(crate) fn micros() -> u64 {
+    // 这里只读取 CNT，不会与其他持有 TIM2/TIM5 的代码冲突
+    let (tim2, tim5) = unsafe { (&*pac::TIM2::ptr(), &*pac::TIM5::ptr()) };
+
+    // 按照“高 - 低 - 高”的顺序读取，若两次读到的高位不同，说明中间 TIM2 溢出了，重新读取
+    loop {
+        let high = tim5.cnt.read().bits();
+        let low = tim2.cnt.read().bits();
+        if tim5.cnt.read().bits() == high {
+            return (high as u64) << 32 | low as u64;
+        }
+    }
+}
+
+// 开机以来的毫秒数
+pub(crate) fn millis() -> u32 {
+    (micros() / 1000) as u32
+}
+
+// 忙等待指定的毫秒数
+pub(crate) fn delay_ms(ms: u32) {
+    let start = millis();
+    while millis().wrapping_sub(start) < ms {}
+}
@@ -0,0 +1,337 @@
+//! TSL2561 环境光传感器（I2C）
+//!
+//! 与 BH1750 不同，TSL2561 有两个光电二极管：CH0 对可见光与红外都敏感，CH1 主要对红外敏感，
+//! 芯片只给出两个通道的原始计数，lux 需要自己按照 datasheet 的经验公式计算：
+//! 先算出 CH1/CH0 的比值（光源中红外的占比，白炽灯远高于 LED 与日光），再按比值所在的区间选择一组系数
+//!
+//! 这里使用 datasheet 中 Calculating Lux 一节给出的整数算法（系数已经放大了 2^14），
+//! 避免了经验公式中的 ratio^1.4（core 中没有 powf），只是最后不再舍入为整数，保留了小于 1 lx 的部分
+//! T/FN/CL 封装与 CS 封装的系数不同，见 Package
+//!
+//! 积分时间与增益：
+//! - 积分时间 13.7 ms / 101 ms / 402 ms，越长越灵敏，但 CH0 的满量程分别只有 5047 / 37177 / 65535
+//! - 增益 1x / 16x
+//!
+//! 计算时会把计数换算到 402 ms、16x 的标称条件下，所以换了积分时间或者增益，lux 的结果不变
+//! 开启 auto_gain 时，CH0 饱和就切换到 1x，1x 下计数太小就切换到 16x，
+//! 切换之后的这次采样返回 NotReady，等下一次采样用新的增益积分完成之后再给出结果
+//!
+//! 测量模式：
+//! - Continuous：芯片一直保持上电，不停地积分，采样时只读取最新一次积分的结果
+//! - OneShot：每次采样时上电，等待一个积分时间后读取结果，再断电，功耗最低
+//!
+//! I2C 外设需要事先配置好（见 s21c02），这里只通过 utils::blocking_master 收发数据
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::i2c1::RegisterBlock;
+
+use super::{
+    addressing::I2cAddress,
+    blocking_master,
+    sensor::{Lux, Sensor, SensorError},
+    ticker,
+};
+
+// ADDR SEL 引脚接 GND、悬空、接 VDD 时，地址分别为 0x29、0x39、0x49
+pub(crate) const ADDR_GND: u8 = 0x29;
+pub(crate) const ADDR_FLOAT: u8 = 0x39;
+pub(crate) const ADDR_VDD: u8 = 0x49;
+
+// 命令字节：CMD 位必须为 1，WORD 位表示读写 16 bit
+const CMD: u8 = 0x80;
+const WORD: u8 = 0x20;
+
+const REG_CONTROL: u8 = 0x00;
+const REG_TIMING: u8 = 0x01;
+const REG_ID: u8 = 0x0A;
+const REG_DATA0: u8 = 0x0C;
+const REG_DATA1: u8 = 0x0E;
+
+const POWER_ON: u8 = 0x03;
+const POWER_OFF: u8 = 0x00;
+const TIMING_GAIN_16X: u8 = 1 << 4;
+
+// ID 寄存器的高 4 位，0b0001 为 TSL2561CS，0b0101 为 TSL2561T/FN/CL（0b0000 与 0b0100 为没有 I2C 的 TSL2560）
+const PARTNO_CS: u8 = 0b0001;
+const PARTNO_T: u8 = 0b0101;
+
+// 1x 增益下 CH0 小于这个值时切换到 16x，16x 下只有 1/16 的余量也不会饱和
+const AUTO_GAIN_LOW: u16 = 1000;
+
+// 整数算法中的放大倍数
+const LUX_SCALE: u32 = 14;
+const RATIO_SCALE: u32 = 9;
+const CH_SCALE: u32 = 10;
+// 322/11 * 2^10 与 322/81 * 2^10，把 13.7 ms 与 101 ms 的计数换算到 402 ms
+const CHSCALE_TINT0: u32 = 0x7517;
+const CHSCALE_TINT1: u32 = 0x0FE7;
+
+// (K, B, M)：比值不超过 K 时，lux = CH0 * B - CH1 * M
+const COEFF_T: [(u32, u32, u32); 8] = [
+    (0x0040, 0x01F2, 0x01BE),
+    (0x0080, 0x0214, 0x02D1),
+    (0x00C0, 0x023F, 0x037B),
+    (0x0100, 0x0270, 0x03FE),
+    (0x0138, 0x016F, 0x01FC),
+    (0x019A, 0x00D2, 0x00FB),
+    (0x029A, 0x0018, 0x0012),
+    (u32::MAX, 0x0000, 0x0000),
+];
+const COEFF_CS: [(u32, u32, u32); 8] = [
+    (0x0043, 0x0204, 0x01AD),
+    (0x0085, 0x0228, 0x02C1),
+    (0x00C8, 0x0253, 0x0363),
+    (0x010A, 0x0282, 0x03DF),
+    (0x014D, 0x0177, 0x01DD),
+    (0x019A, 0x0101, 0x0127),
+    (0x029A, 0x0037, 0x002B),
+    (u32::MAX, 0x0000, 0x0000),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Package {
+    // TSL2561T、TSL2561FN、TSL2561CL
+    T,
+    // TSL2561CS，芯片级封装
+    Cs,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Integration {
+    Ms13,
+    Ms101,
+    Ms402,
+}
+
+impl Integration {
+    fn bits(self) -> u8 {
+        self as u8
+    }
+
+    // 多等 1 ms，内部振荡器有一定的误差
+    fn wait_ms(self) -> u32 {
+        match self {
+            Integration::Ms13 => 15,
+            Integration::Ms101 => 102,
+            Integration::Ms402 => 403,
+        }
+    }
+
+    // CH0 的满量程，达到这个值说明已经饱和
+    fn full_scale(self) -> u16 {
+        match self {
+            Integration::Ms13 => 5047,
+            Integration::Ms101 => 37177,
+            Integration::Ms402 => 65535,
+        }
+    }
+
+    fn ch_scale(self) -> u32 {
+        match self {
+            Integration::Ms13 => CHSCALE_TINT0,
+            Integration::Ms101 => CHSCALE_TINT1,
+            Integration::Ms402 => 1 << CH_SCALE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Gain {
+    X1,
+    X16,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Mode {
+    Continuous,
+    OneShot,
+}
+
+pub(crate) struct Tsl2561<'a> {
+    i2c: &'a RegisterBlock,
+    addr: I2cAddress,
+    package: Package,
+    integration: Integration,
+    gain: Gain,
+    auto_gain: bool,
+    mode: Mode,
+    // 最近一次的原始计数，方便调试与标定
+    raw: (u16, u16),
+}
+
+impl<'a> Tsl2561<'a> {
+    // 读取 ID 确认芯片型号，之后处于 OneShot 模式、402 ms、16x、开启 auto_gain
+    pub(crate) fn new(i2c: &'a RegisterBlock, addr: u8) -> Result<Self, SensorError> {
+        let mut tsl = Self {
+            i2c,
+            addr: I2cAddress::SevenBit(addr),
+            package: Package::T,
+            integration: Integration::Ms402,
+            gain: Gain::X16,
+            auto_gain: true,
+            mode: Mode::OneShot,
+            raw: (0, 0),
+        };
+
+        // 上电之后才能读写其它寄存器
+        tsl.write_reg(REG_CONTROL, POWER_ON)?;
+        tsl.package = match tsl.read_reg(REG_ID)? >> 4 {
+            PARTNO_T => Package::T,
+            PARTNO_CS => Package::Cs,
+            _ => return Err(SensorError::Unsupported),
+        };
+        tsl.write_timing()?;
+        tsl.write_reg(REG_CONTROL, POWER_OFF)?;
+
+        Ok(tsl)
+    }
+
+    pub(crate) fn package(&self) -> Package {
+        self.package
+    }
+
+    pub(crate) fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    pub(crate) fn integration(&self) -> Integration {
+        self.integration
+    }
+
+    pub(crate) fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // 最近一次采样的 CH0（可见光 + 红外）与 CH1（红外）的原始计数
+    pub(crate) fn raw(&self) -> (u16, u16) {
+        self.raw
+    }
+
+    pub(crate) fn set_timing(
+        &mut self,
+        integration: Integration,
+        gain: Gain,
+        auto_gain: bool,
+    ) -> Result<(), SensorError> {
+        self.integration = integration;
+        self.gain = gain;
+        self.auto_gain = auto_gain;
+        self.write_timing()
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: Mode) -> Result<(), SensorError> {
+        self.mode = mode;
+        match mode {
+            // 第一个结果要等一个积分时间之后才有
+            Mode::Continuous => self.write_reg(REG_CONTROL, POWER_ON),
+            Mode::OneShot => self.write_reg(REG_CONTROL, POWER_OFF),
+        }
+    }
+
+    fn write_timing(&self) -> Result<(), SensorError> {
+        let gain = match self.gain {
+            Gain::X1 => 0,
+            Gain::X16 => TIMING_GAIN_16X,
+        };
+        self.write_reg(REG_TIMING, gain | self.integration.bits())
+    }
+
+    fn write_reg(&self, reg: u8, value: u8) -> Result<(), SensorError> {
+        blocking_master::write(self.i2c, self.addr, &[CMD | reg, value])?;
+        Ok(())
+    }
+
+    fn read_reg(&self, reg: u8) -> Result<u8, SensorError> {
+        let mut buf = [0u8; 1];
+        blocking_master::write_read(self.i2c, self.addr, &[CMD | reg], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    // 16 bit 的数据寄存器低字节在前
+    fn read_word(&self, reg: u8) -> Result<u16, SensorError> {
+        let mut buf = [0u8; 2];
+        blocking_master::write_read(self.i2c, self.addr, &[CMD | WORD | reg], &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    // 根据这次的计数调整增益，返回是否修改了增益
+    fn adjust_gain(&mut self, ch0: u16) -> Result<bool, SensorError> {
+        let gain = match self.gain {
+            Gain::X16 if ch0 >= self.integration.full_scale() => Gain::X1,
+            Gain::X1 if ch0 < AUTO_GAIN_LOW => Gain::X16,
+            _ => return Ok(false),
+        };
+        self.gain = gain;
+        self.write_timing()?;
+        Ok(true)
+    }
+
+    fn convert(&self, ch0: u16, ch1: u16) -> Lux {
+        // 换算到 402 ms、16x
+        let mut scale = self.integration.ch_scale();
+        if self.gain == Gain::X1 {
+            scale <<= 4;
+        }
+        let channel0 = (ch0 as u32 * scale) >> CH_SCALE;
+        let channel1 = (ch1 as u32 * scale) >> CH_SCALE;
+
+        // 比值放大了 2^9
+        let ratio = (channel1 << (RATIO_SCALE + 1))
+            .checked_div(channel0)
+            .map_or(0, |ratio| (ratio + 1) >> 1);
+
+        let coeff = match self.package {
+            Package::T => &COEFF_T,
+            Package::Cs => &COEFF_CS,
+        };
+        let (_, b, m) = coeff
+            .iter()
+            .copied()
+            .find(|&(k, _, _)| ratio <= k)
+            .unwrap_or_default();
+
+        let lux = (channel0 * b).saturating_sub(channel1 * m);
+        Lux(lux as f32 / (1 << LUX_SCALE) as f32)
+    }
+}
+
+impl Sensor for Tsl2561<'_> {
+    type Output = Lux;
+
+    fn name(&self) -> &'static str {
+        "tsl2561"
+    }
+
+    fn sample(&mut self) -> Result<Lux, SensorError> {
+        if self.mode == Mode::OneShot {
+            self.write_reg(REG_CONTROL, POWER_ON)?;
+            ticker::delay_ms(self.integration.wait_ms());
+        }
+
+        // 先读 CH0 再读 CH1，两者来自同一次积分
+        let ch0 = self.read_word(REG_DATA0)?;
+        let ch1 = self.read_word(REG_DATA1)?;
+        self.raw = (ch0, ch1);
+
+        if self.mode == Mode::OneShot {
+            self.write_reg(REG_CONTROL, POWER_OFF)?;
+        }
+
+        if self.auto_gain && self.adjust_gain(ch0)? {
+            return Err(SensorError::NotReady);
+        }
+        if ch0 >= self.integration.full_scale() {
+            return Err(SensorError::OutOfRange);
+        }
+        Ok(self.convert(ch0, ch1))
+    }
+}
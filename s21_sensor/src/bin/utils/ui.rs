@@ -0,0 +1,377 @@
+//! 显示在 TextPanel（LCD1602）上的简易菜单
+//!
+//! LcdPageSink 只能按固定的顺序轮流显示读数，想看某一个读数，或者想调整一下采样间隔之类的参数，就只能改代码重新烧录
+//! 这里提供一个菜单树，用按键或者编码器（见 utils::keypad 与 utils::encoder）在其中导航：
+//!
+//! Menu：一级菜单，包含若干个 Item
+//! Item::Value：只读的数值，每次刷新屏幕时调用 get 闭包取值
+//! Item::Number：可以修改的数值，按下 Enter 进入编辑，Prev/Next 按 step 调整，且限制在 min~max 之间，
+//!               再次按下 Enter 时调用 set 闭包写回，按下 Back 放弃修改
+//! Item::Menu：子菜单
+//! Item::Readings：逐条浏览 ReadingSource 中的读数，ReadingCache 作为 Sink 注册到 Scheduler 上，就会记下所有传感器的最新读数
+//!
+//! 闭包都是 Fn 而不是 FnMut，这样菜单树可以只持有共享引用，需要修改的数据请放在 Cell 里
+//!
+//! 屏幕的第一行为 菜单名 序号/条目数，第二行为当前选中的条目
+//! 由于 LCD1602 的刷新比较慢，Ui 只在收到事件之后，或者每隔 refresh_ms 毫秒才重新绘制一次
+
+#![allow(dead_code)]
+
+use core::{cell::Cell, fmt::Write};
+
+use super::sensor::{
+    scheduler::worth_reporting,
+    sink::{LineBuf, Sink, TextPanel},
+    Reading, SensorError,
+};
+
+// 菜单的导航事件
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Event {
+    // 上一项，编辑时减小数值
+    Prev,
+    // 下一项，编辑时增大数值
+    Next,
+    Enter,
+    Back,
+}
+
+pub(crate) struct Number<'a> {
+    pub(crate) get: &'a dyn Fn() -> f32,
+    pub(crate) set: &'a dyn Fn(f32),
+    pub(crate) min: f32,
+    pub(crate) max: f32,
+    pub(crate) step: f32,
+    // 显示的小数位数
+    pub(crate) decimals: usize,
+}
+
+impl Number<'_> {
+    fn clamp(&self, value: f32) -> f32 {
+        value.clamp(self.min, self.max)
+    }
+}
+
+pub(crate) enum Item<'a> {
+    // get 返回 None 时显示 ---，比如传感器还没有读数
+    Value {
+        label: &'static str,
+        unit: &'static str,
+        get: &'a dyn Fn() -> Option<f32>,
+    },
+    Number {
+        label: &'static str,
+        unit: &'static str,
+        number: Number<'a>,
+    },
+    Menu(Menu<'a>),
+    Readings {
+        label: &'static str,
+        source: &'a dyn ReadingSource,
+    },
+}
+
+pub(crate) struct Menu<'a> {
+    pub(crate) title: &'static str,
+    pub(crate) items: &'a [Item<'a>],
+}
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Browse,
+    // 正在编辑的数值，确认之前不会写回
+    Edit(f32),
+    // 正在浏览的读数序号
+    Readings(usize),
+}
+
+// DEPTH 为菜单的最大层数，包括根菜单，超出的子菜单无法进入
+pub(crate) struct Ui<'a, P: TextPanel, const DEPTH: usize> {
+    panel: P,
+    // 从根菜单到当前菜单的路径，以及每一层选中的条目
+    menus: [&'a Menu<'a>; DEPTH],
+    cursors: [usize; DEPTH],
+    depth: usize,
+    mode: Mode,
+    refresh_ms: u32,
+    next_refresh_ms: u32,
+    dirty: bool,
+}
+
+impl<'a, P: TextPanel, const DEPTH: usize> Ui<'a, P, DEPTH> {
+    pub(crate) fn new(panel: P, root: &'a Menu<'a>, refresh_ms: u32) -> Self {
+        Self {
+            panel,
+            menus: [root; DEPTH],
+            cursors: [0; DEPTH],
+            depth: 0,
+            mode: Mode::Browse,
+            refresh_ms,
+            next_refresh_ms: 0,
+            dirty: true,
+        }
+    }
+
+    fn selected(&self) -> Option<&'a Item<'a>> {
+        self.menus[self.depth].items.get(self.cursors[self.depth])
+    }
+
+    pub(crate) fn handle(&mut self, event: Event) {
+        self.dirty = true;
+        match self.mode {
+            Mode::Browse => self.browse(event),
+            Mode::Edit(value) => self.edit(value, event),
+            Mode::Readings(index) => self.browse_readings(index, event),
+        }
+    }
+
+    fn browse(&mut self, event: Event) {
+        let count = self.menus[self.depth].items.len();
+        let cursor = self.cursors[self.depth];
+
+        match event {
+            Event::Back => self.depth = self.depth.saturating_sub(1),
+            _ if count == 0 => {}
+            Event::Prev => self.cursors[self.depth] = (cursor + count - 1) % count,
+            Event::Next => self.cursors[self.depth] = (cursor + 1) % count,
+            Event::Enter => match self.selected() {
+                Some(Item::Number { number, .. }) => {
+                    self.mode = Mode::Edit(number.clamp((number.get)()))
+                }
+                Some(Item::Menu(sub)) if self.depth + 1 < DEPTH => {
+                    self.depth += 1;
+                    self.menus[self.depth] = sub;
+                    self.cursors[self.depth] = 0;
+                }
+                Some(Item::Readings { .. }) => self.mode = Mode::Readings(0),
+                _ => {}
+            },
+        }
+    }
+
+    fn edit(&mut self, value: f32, event: Event) {
+        let Some(Item::Number { number, .. }) = self.selected() else {
+            self.mode = Mode::Browse;
+            return;
+        };
+
+        match event {
+            Event::Prev => self.mode = Mode::Edit(number.clamp(value - number.step)),
+            Event::Next => self.mode = Mode::Edit(number.clamp(value + number.step)),
+            Event::Enter => {
+                (number.set)(value);
+                self.mode = Mode::Browse;
+            }
+            Event::Back => self.mode = Mode::Browse,
+        }
+    }
+
+    fn browse_readings(&mut self, index: usize, event: Event) {
+        let Some(Item::Readings { source, .. }) = self.selected() else {
+            self.mode = Mode::Browse;
+            return;
+        };
+        let count = source.count().max(1);
+
+        match event {
+            Event::Prev => self.mode = Mode::Readings((index + count - 1) % count),
+            Event::Next => self.mode = Mode::Readings((index + 1) % count),
+            Event::Enter => {}
+            Event::Back => self.mode = Mode::Browse,
+        }
+    }
+
+    // 直接访问屏幕，比如在 TFT 上绘制菜单以外的内容，菜单会在下一次 refresh 时重新绘制
+    pub(crate) fn panel(&mut self) -> &mut P {
+        self.dirty = true;
+        &mut self.panel
+    }
+
+    // 在主循环中调用，需要时重新绘制屏幕
+    pub(crate) fn refresh(&mut self, now_ms: u32) {
+        if !self.dirty && (now_ms.wrapping_sub(self.next_refresh_ms) as i32) < 0 {
+            return;
+        }
+        self.dirty = false;
+        self.next_refresh_ms = now_ms.wrapping_add(self.refresh_ms);
+        self.draw();
+    }
+
+    fn draw(&mut self) {
+        let mut line0 = LineBuf::<32>::new();
+        let mut line1 = LineBuf::<32>::new();
+
+        let menu = self.menus[self.depth];
+        let cursor = self.cursors[self.depth];
+
+        match (self.mode, self.selected()) {
+            (_, None) => {
+                write!(line0, "{}", menu.title).ok();
+                write!(line1, "(empty)").ok();
+            }
+            (Mode::Browse, Some(item)) => {
+                write!(line0, "{} {}/{}", menu.title, cursor + 1, menu.items.len()).ok();
+                match item {
+                    Item::Value { label, unit, get } => {
+                        write!(line1, "{} ", label).ok();
+                        write_value(&mut line1, get(), 2, unit);
+                    }
+                    Item::Number {
+                        label,
+                        unit,
+                        number,
+                    } => {
+                        write!(line1, "{} ", label).ok();
+                        write_value(&mut line1, Some((number.get)()), number.decimals, unit);
+                    }
+                    Item::Menu(sub) => {
+                        write!(line1, "{} >", sub.title).ok();
+                    }
+                    Item::Readings { label, .. } => {
+                        write!(line1, "{} >", label).ok();
+                    }
+                }
+            }
+            (
+                Mode::Edit(value),
+                Some(Item::Number {
+                    label,
+                    unit,
+                    number,
+                }),
+            ) => {
+                write!(line0, "Set {}", label).ok();
+                write!(line1, "> ").ok();
+                write_value(&mut line1, Some(value), number.decimals, unit);
+            }
+            (Mode::Readings(index), Some(Item::Readings { label, source })) => {
+                match source.entry(index) {
+                    Some(entry) => {
+                        write!(line0, "{}.{}", entry.sensor_name, entry.reading.quantity).ok();
+                        let value = (!entry.failed).then_some(entry.reading.value);
+                        write_value(&mut line1, value, 2, entry.reading.unit);
+                    }
+                    None => {
+                        write!(line0, "{}", label).ok();
+                        write!(line1, "no data").ok();
+                    }
+                }
+            }
+            // mode 与选中的条目不匹配，handle 会在下一个事件时回到 Browse
+            _ => return,
+        }
+
+        self.panel.write_line(0, line0.as_bytes());
+        self.panel.write_line(1, line1.as_bytes());
+    }
+}
+
+fn write_value<const N: usize>(
+    line: &mut LineBuf<N>,
+    value: Option<f32>,
+    decimals: usize,
+    unit: &str,
+) {
+    match value {
+        Some(value) => write!(line, "{:.*} {}", decimals, value, unit).ok(),
+        None => write!(line, "--- {}", unit).ok(),
+    };
+}
+
+// 一条缓存下来的读数
+#[derive(Clone, Copy)]
+pub(crate) struct CachedReading {
+    pub(crate) sensor_name: &'static str,
+    pub(crate) reading: Reading,
+    // 该传感器最近一次采样是否失败
+    pub(crate) failed: bool,
+}
+
+// 可以逐条浏览的读数
+pub(crate) trait ReadingSource {
+    fn count(&self) -> usize;
+    fn entry(&self, index: usize) -> Option<CachedReading>;
+}
+
+// 记下每个传感器、每个物理量的最新读数，N 为最多能记住的条目数量
+//
+// 菜单的闭包与 Scheduler 都要访问它，因此这里用 Cell 实现内部可变性，Sink 则实现在 &ReadingCache 上
+pub(crate) struct ReadingCache<const N: usize> {
+    entries: [Cell<Option<CachedReading>>; N],
+}
+
+impl<const N: usize> ReadingCache<N> {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: core::array::from_fn(|_| Cell::new(None)),
+        }
+    }
+
+    // 某个读数的最新值，没有读数或者最近一次采样失败时返回 None
+    pub(crate) fn value(&self, sensor_name: &str, quantity: &str) -> Option<f32> {
+        self.entries
+            .iter()
+            .filter_map(Cell::get)
+            .find(|entry| entry.sensor_name == sensor_name && entry.reading.quantity == quantity)
+            .filter(|entry| !entry.failed)
+            .map(|entry| entry.reading.value)
+    }
+}
+
+impl<const N: usize> ReadingSource for ReadingCache<N> {
+    fn count(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.get().is_some())
+            .count()
+    }
+
+    // 条目总是从前往后占用的
+    fn entry(&self, index: usize) -> Option<CachedReading> {
+        self.entries.get(index)?.get()
+    }
+}
+
+impl<const N: usize> Sink for &ReadingCache<N> {
+    fn publish(&mut self, _now_ms: u32, sensor_name: &'static str, reading: &Reading) {
+        let slot = self
+            .entries
+            .iter()
+            .find(|entry| {
+                entry.get().is_some_and(|entry| {
+                    entry.sensor_name == sensor_name && entry.reading.quantity == reading.quantity
+                })
+            })
+            .or_else(|| self.entries.iter().find(|entry| entry.get().is_none()));
+
+        if let Some(slot) = slot {
+            slot.set(Some(CachedReading {
+                sensor_name,
+                reading: *reading,
+                failed: false,
+            }));
+        }
+    }
+
+    fn error(
+        &mut self,
+        _now_ms: u32,
+        sensor_name: &'static str,
+        error: SensorError,
+        error_cnt: u32,
+    ) {
+        if !worth_reporting(error, error_cnt) {
+            return;
+        }
+        for slot in self.entries.iter() {
+            if let Some(mut entry) = slot.get() {
+                if entry.sensor_name == sensor_name {
+                    entry.failed = true;
+                    slot.set(Some(entry));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! 让中断处理函数唤醒 core::future 的任务
+//!
+//! 现有的驱动都是“中断里设置一个标志，主循环里轮询这个标志”，换成 async 时，轮询的那一侧变成了 Future：
+//! poll 时条件还不满足，就把 Context 中的 Waker 存起来并返回 Pending；中断处理函数在条件满足时调用 Waker::wake，
+//! 执行器（见 utils::executor）收到通知之后再 poll 一次
+//!
+//! 这里提供三种存放 Waker 的容器，都可以直接放在 static 中，在中断与任务之间共享：
+//!
+//! - WaitCell<T>：一次性的完成通知，比如 I2C 的一个事件、DMA 的一次传输完成，中断调用 signal 放入结果，任务 await wait() 取出结果
+//! - WakerSet<N>：多个任务等待同一种事件，比如 SysTick 的每一个 tick，中断调用 wake_all
+//! - ByteChannel<N>：连续到达的字节，比如 USART 的 RXNE，中断调用 push，任务 await recv() 逐个取出
+//!
+//! 内部都用 cortex_m::interrupt::free 保护，临界区只有几条指令，不会明显推迟其他中断
+//! 它们只负责“通知”，不负责打开或关闭外设的中断，这些仍然由驱动自己决定，已有的中断代码只需要把“设置标志”换成 signal/push
+
+#![allow(dead_code)]
+
+use core::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use cortex_m::interrupt::Mutex;
+
+// 把 cx 中的 Waker 存入 slot，已经存着同一个任务的 Waker 时不需要重新 clone
+fn register(slot: &mut Option<Waker>, cx: &Context) {
+    match slot {
+        Some(waker) if waker.will_wake(cx.waker()) => (),
+        _ => *slot = Some(cx.waker().clone()),
+    }
+}
+
+struct CellState<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+}
+
+// 只能有一个任务在等待，第二个任务 wait 时会顶替掉第一个任务的 Waker
+pub(crate) struct WaitCell<T> {
+    state: Mutex<RefCell<CellState<T>>>,
+}
+
+impl<T> WaitCell<T> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(CellState {
+                value: None,
+                waker: None,
+            })),
+        }
+    }
+
+    // 在中断中调用，放入结果并唤醒等待的任务，上一个结果还没有被取走时会被覆盖
+    pub(crate) fn signal(&self, value: T) {
+        cortex_m::interrupt::free(|cs| {
+            let mut state = self.state.borrow(cs).borrow_mut();
+            state.value = Some(value);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+    }
+
+    // 丢弃还没有被取走的结果，在启动一次新的操作之前调用，防止取到上一次遗留的结果
+    pub(crate) fn reset(&self) {
+        cortex_m::interrupt::free(|cs| self.state.borrow(cs).borrow_mut().value = None);
+    }
+
+    // 等待下一个结果
+    pub(crate) fn wait(&self) -> Wait<'_, T> {
+        Wait { cell: self }
+    }
+}
+
+pub(crate) struct Wait<'a, T> {
+    cell: &'a WaitCell<T>,
+}
+
+impl<T> Future for Wait<'_, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        cortex_m::interrupt::free(|cs| {
+            let mut state = self.cell.state.borrow(cs).borrow_mut();
+            match state.value.take() {
+                Some(value) => Poll::Ready(value),
+                None => {
+                    register(&mut state.waker, cx);
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+// 最多 N 个任务同时等待
+pub(crate) struct WakerSet<const N: usize> {
+    wakers: Mutex<RefCell<[Option<Waker>; N]>>,
+}
+
+impl<const N: usize> WakerSet<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            wakers: Mutex::new(RefCell::new([const { None }; N])),
+        }
+    }
+
+    // 在 Future::poll 中调用
+    // 已经满了的时候直接唤醒这个任务，让它下一轮再试，结果是多 poll 几次，而不是永远不被唤醒
+    pub(crate) fn register(&self, cx: &Context) {
+        cortex_m::interrupt::free(|cs| {
+            let mut wakers = self.wakers.borrow(cs).borrow_mut();
+            if wakers
+                .iter()
+                .flatten()
+                .any(|waker| waker.will_wake(cx.waker()))
+            {
+                return;
+            }
+            match wakers.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => *slot = Some(cx.waker().clone()),
+                None => cx.waker().wake_by_ref(),
+            }
+        });
+    }
+
+    // 在中断中调用，唤醒所有等待的任务，被唤醒的任务需要重新 register
+    pub(crate) fn wake_all(&self) {
+        cortex_m::interrupt::free(|cs| {
+            for waker in self.wakers.borrow(cs).borrow_mut().iter_mut() {
+                if let Some(waker) = waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+    }
+}
+
+struct ChannelState<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+    // 缓冲区满了之后丢弃的字节数
+    dropped: u32,
+    waker: Option<Waker>,
+}
+
+// 中断写入、一个任务读出的字节队列
+pub(crate) struct ByteChannel<const N: usize> {
+    state: Mutex<RefCell<ChannelState<N>>>,
+}
+
+impl<const N: usize> ByteChannel<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(ChannelState {
+                buf: [0; N],
+                head: 0,
+                len: 0,
+                dropped: 0,
+                waker: None,
+            })),
+        }
+    }
+
+    // 在中断中调用，缓冲区满时丢弃这个字节并返回 false
+    pub(crate) fn push(&self, byte: u8) -> bool {
+        cortex_m::interrupt::free(|cs| {
+            let mut state = self.state.borrow(cs).borrow_mut();
+            if state.len == N {
+                state.dropped += 1;
+                return false;
+            }
+            let tail = (state.head + state.len) % N;
+            state.buf[tail] = byte;
+            state.len += 1;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            true
+        })
+    }
+
+    pub(crate) fn dropped(&self) -> u32 {
+        cortex_m::interrupt::free(|cs| self.state.borrow(cs).borrow().dropped)
+    }
+
+    // 等待并取出下一个字节
+    pub(crate) fn recv(&self) -> Recv<'_, N> {
+        Recv { channel: self }
+    }
+}
+
+pub(crate) struct Recv<'a, const N: usize> {
+    channel: &'a ByteChannel<N>,
+}
+
+impl<const N: usize> Future for Recv<'_, N> {
+    type Output = u8;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u8> {
+        cortex_m::interrupt::free(|cs| {
+            let mut state = self.channel.state.borrow(cs).borrow_mut();
+            if state.len == 0 {
+                register(&mut state.waker, cx);
+                return Poll::Pending;
+            }
+            let byte = state.buf[state.head];
+            state.head = (state.head + 1) % N;
+            state.len -= 1;
+            Poll::Ready(byte)
+        })
+    }
+}
@@ -0,0 +1,365 @@
+//! 观察变量：运行时通过命令行查看、修改程序中的变量
+//!
+//! 装好的设备上没有调试器，想知道某个计数器现在是多少、某个参数调成多少合适，只能加打印、重新编译、重新烧录
+//! 这里提供一个简陋的替代：程序（或者各个模块）把想观察的变量登记到一张静态的表中，每一项记录
+//!
+//! - 名字
+//! - 变量的地址与类型标记（Kind），读取时按照类型标记解释地址上的数据
+//! - 是否允许修改
+//!
+//! 命令行（见 s21c25）把 watch 开头的命令交给 command 处理：
+//!
+//! watch                    列出全部变量：名字、类型、读写权限、地址、当前值
+//! watch get <name>         读取一个变量
+//! watch set <name> <value> 修改一个允许修改的变量，value 按照变量的类型解析，整数可以用 0x 开头的十六进制
+//!
+//! 能登记的变量只有原子类型（AtomicU32、AtomicBool 等，以及这里的 AtomicF32），
+//! 变量总是在主循环、中断之间共享，原子类型保证命令行读写时不会读到一半，也不会与中断的修改冲突
+//! 登记时要求 &'static，变量本身就是 static，地址不会失效
+//!
+//! 修改只对 watch_mut 登记的变量开放，程序自己决定哪些变量可以改（比如参数），哪些只能看（比如计数器）
+
+#![allow(dead_code)]
+
+use core::{
+    cell::RefCell,
+    fmt::{self, Write},
+    sync::atomic::{
+        AtomicBool, AtomicI16, AtomicI32, AtomicI8, AtomicU16, AtomicU32, AtomicU8, Ordering,
+    },
+};
+
+use cortex_m::interrupt::Mutex;
+
+pub(crate) const MAX_WATCHES: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Kind {
+    Bool,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl Kind {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Kind::Bool => "bool",
+            Kind::U8 => "u8",
+            Kind::I8 => "i8",
+            Kind::U16 => "u16",
+            Kind::I16 => "i16",
+            Kind::U32 => "u32",
+            Kind::I32 => "i32",
+            Kind::F32 => "f32",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) enum Value {
+    Bool(bool),
+    Unsigned(u32),
+    Signed(i32),
+    Float(f32),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Unsigned(v) => write!(f, "{} ({:#X})", v, v),
+            Value::Signed(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum WatchError {
+    Full,
+    // 同名的变量已经登记过了
+    Duplicate,
+    NotFound,
+    ReadOnly,
+    // 值无法按照变量的类型解析，或者超出了类型的范围
+    BadValue,
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            WatchError::Full => "registry full",
+            WatchError::Duplicate => "name already registered",
+            WatchError::NotFound => "no such variable",
+            WatchError::ReadOnly => "read only",
+            WatchError::BadValue => "bad value",
+        };
+        f.write_str(text)
+    }
+}
+
+// f32 没有对应的原子类型，用 AtomicU32 保存它的位
+pub(crate) struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    pub(crate) const fn new(value: f32) -> Self {
+        Self(AtomicU32::new(value.to_bits()))
+    }
+
+    pub(crate) fn load(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn store(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed)
+    }
+}
+
+// 可以登记的类型，由类型决定 Kind，使用者不需要自己写类型标记
+pub(crate) trait Watchable: Sync {
+    const KIND: Kind;
+}
+
+macro_rules! watchable {
+    ($($ty:ty => $kind:ident),+ $(,)?) => {
+        $(impl Watchable for $ty {
+            const KIND: Kind = Kind::$kind;
+        })+
+    };
+}
+
+watchable!(
+    AtomicBool => Bool,
+    AtomicU8 => U8,
+    AtomicI8 => I8,
+    AtomicU16 => U16,
+    AtomicI16 => I16,
+    AtomicU32 => U32,
+    AtomicI32 => I32,
+    AtomicF32 => F32,
+);
+
+#[derive(Clone, Copy)]
+struct Entry {
+    name: &'static str,
+    // 保存为 usize，这样 Entry 才能放进 Mutex 中；它总是指向一个 'static 的、与 kind 对应的原子类型
+    addr: usize,
+    kind: Kind,
+    writable: bool,
+}
+
+impl Entry {
+    // 安全性：addr 来自 register 的 &'static T，kind 来自 T::KIND，二者一定对应
+    fn read(&self) -> Value {
+        unsafe {
+            match self.kind {
+                Kind::Bool => {
+                    Value::Bool((*(self.addr as *const AtomicBool)).load(Ordering::Relaxed))
+                }
+                Kind::U8 => {
+                    Value::Unsigned((*(self.addr as *const AtomicU8)).load(Ordering::Relaxed) as u32)
+                }
+                Kind::I8 => {
+                    Value::Signed((*(self.addr as *const AtomicI8)).load(Ordering::Relaxed) as i32)
+                }
+                Kind::U16 => Value::Unsigned(
+                    (*(self.addr as *const AtomicU16)).load(Ordering::Relaxed) as u32,
+                ),
+                Kind::I16 => {
+                    Value::Signed((*(self.addr as *const AtomicI16)).load(Ordering::Relaxed) as i32)
+                }
+                Kind::U32 => {
+                    Value::Unsigned((*(self.addr as *const AtomicU32)).load(Ordering::Relaxed))
+                }
+                Kind::I32 => {
+                    Value::Signed((*(self.addr as *const AtomicI32)).load(Ordering::Relaxed))
+                }
+                Kind::F32 => Value::Float((*(self.addr as *const AtomicF32)).load()),
+            }
+        }
+    }
+
+    // 先按照类型解析，范围检查通过之后才写入
+    fn write(&self, text: &str) -> Result<Value, WatchError> {
+        if !self.writable {
+            return Err(WatchError::ReadOnly);
+        }
+        let bad = |_| WatchError::BadValue;
+        unsafe {
+            match self.kind {
+                Kind::Bool => {
+                    let value = match text {
+                        "1" | "true" | "on" => true,
+                        "0" | "false" | "off" => false,
+                        _ => return Err(WatchError::BadValue),
+                    };
+                    (*(self.addr as *const AtomicBool)).store(value, Ordering::Relaxed);
+                }
+                Kind::U8 => {
+                    let value = u8::try_from(parse_u32(text)?).map_err(bad)?;
+                    (*(self.addr as *const AtomicU8)).store(value, Ordering::Relaxed);
+                }
+                Kind::I8 => {
+                    let value = text.parse::<i8>().map_err(|_| WatchError::BadValue)?;
+                    (*(self.addr as *const AtomicI8)).store(value, Ordering::Relaxed);
+                }
+                Kind::U16 => {
+                    let value = u16::try_from(parse_u32(text)?).map_err(bad)?;
+                    (*(self.addr as *const AtomicU16)).store(value, Ordering::Relaxed);
+                }
+                Kind::I16 => {
+                    let value = text.parse::<i16>().map_err(|_| WatchError::BadValue)?;
+                    (*(self.addr as *const AtomicI16)).store(value, Ordering::Relaxed);
+                }
+                Kind::U32 => {
+                    (*(self.addr as *const AtomicU32)).store(parse_u32(text)?, Ordering::Relaxed);
+                }
+                Kind::I32 => {
+                    let value = text.parse::<i32>().map_err(|_| WatchError::BadValue)?;
+                    (*(self.addr as *const AtomicI32)).store(value, Ordering::Relaxed);
+                }
+                Kind::F32 => {
+                    let value = text.parse::<f32>().map_err(|_| WatchError::BadValue)?;
+                    if !value.is_finite() {
+                        return Err(WatchError::BadValue);
+                    }
+                    (*(self.addr as *const AtomicF32)).store(value);
+                }
+            }
+        }
+        Ok(self.read())
+    }
+}
+
+// 十进制，或者 0x 开头的十六进制
+fn parse_u32(text: &str) -> Result<u32, WatchError> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse::<u32>(),
+    }
+    .map_err(|_| WatchError::BadValue)
+}
+
+struct Registry {
+    entries: [Option<Entry>; MAX_WATCHES],
+}
+
+static REGISTRY: Mutex<RefCell<Registry>> = Mutex::new(RefCell::new(Registry {
+    entries: [None; MAX_WATCHES],
+}));
+
+fn register<T: Watchable>(
+    name: &'static str,
+    var: &'static T,
+    writable: bool,
+) -> Result<(), WatchError> {
+    let entry = Entry {
+        name,
+        addr: var as *const T as usize,
+        kind: T::KIND,
+        writable,
+    };
+    cortex_m::interrupt::free(|cs| {
+        let mut registry = REGISTRY.borrow(cs).borrow_mut();
+        if registry.entries.iter().flatten().any(|e| e.name == name) {
+            return Err(WatchError::Duplicate);
+        }
+        let slot = registry
+            .entries
+            .iter_mut()
+            .find(|e| e.is_none())
+            .ok_or(WatchError::Full)?;
+        *slot = Some(entry);
+        Ok(())
+    })
+}
+
+// 登记一个只读的变量
+pub(crate) fn watch<T: Watchable>(name: &'static str, var: &'static T) -> Result<(), WatchError> {
+    register(name, var, false)
+}
+
+// 登记一个可以通过命令行修改的变量
+pub(crate) fn watch_mut<T: Watchable>(
+    name: &'static str,
+    var: &'static T,
+) -> Result<(), WatchError> {
+    register(name, var, true)
+}
+
+fn find(name: &str) -> Result<Entry, WatchError> {
+    cortex_m::interrupt::free(|cs| {
+        REGISTRY
+            .borrow(cs)
+            .borrow()
+            .entries
+            .iter()
+            .flatten()
+            .find(|e| e.name == name)
+            .copied()
+            .ok_or(WatchError::NotFound)
+    })
+}
+
+pub(crate) fn get(name: &str) -> Result<Value, WatchError> {
+    find(name).map(|entry| entry.read())
+}
+
+// 登记时的名字，需要长期保存名字时（比如定时打印）使用它，而不是命令行中的临时字符串
+pub(crate) fn registered_name(name: &str) -> Result<&'static str, WatchError> {
+    find(name).map(|entry| entry.name)
+}
+
+// 返回写入之后读回的值
+pub(crate) fn set(name: &str, text: &str) -> Result<Value, WatchError> {
+    find(name)?.write(text)
+}
+
+// 每个变量一行，读取在临界区之外进行，不会因为输出慢而推迟中断
+pub(crate) fn write_list(w: &mut impl Write) -> fmt::Result {
+    for index in 0..MAX_WATCHES {
+        let entry = cortex_m::interrupt::free(|cs| REGISTRY.borrow(cs).borrow().entries[index]);
+        if let Some(entry) = entry {
+            writeln!(
+                w,
+                "{:<12} {:<4} {} {:#010X} {}",
+                entry.name,
+                entry.kind.name(),
+                if entry.writable { "rw" } else { "r " },
+                entry.addr,
+                entry.read()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// 处理 watch 之后的部分，结果写入 w，命令不认识时返回 false，由命令行自己提示
+pub(crate) fn command<'a>(
+    mut args: impl Iterator<Item = &'a str>,
+    w: &mut impl Write,
+) -> Result<bool, fmt::Error> {
+    match (args.next(), args.next(), args.next(), args.next()) {
+        (None, _, _, _) | (Some("list"), None, _, _) => write_list(w)?,
+        (Some("get"), Some(name), None, _) => match get(name) {
+            Ok(value) => writeln!(w, "{} = {}", name, value)?,
+            Err(e) => writeln!(w, "{}: {}", name, e)?,
+        },
+        (Some("set"), Some(name), Some(text), None) => match set(name, text) {
+            Ok(value) => writeln!(w, "{} = {}", name, value)?,
+            Err(e) => writeln!(w, "{}: {}", name, e)?,
+        },
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
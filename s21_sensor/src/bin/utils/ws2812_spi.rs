@@ -0,0 +1,91 @@
+//! 用 SPI 的 MOSI 驱动 ws2812
+//!
+//! SPI2 只使用 MOSI（PB15，AF5），SCK 不需要接：APB1 为 12 MHz，4 分频之后每个 SPI bit 为 333 ns，
+//! ws2812 的一个 bit 用 4 个 SPI bit 表示：
+//!
+//! - bit 0：1000，高电平 333 ns
+//! - bit 1：1100，高电平 667 ns
+//!
+//! 周期为 1.33 us，在 ws2812 允许的范围之内，一个 SPI 字节刚好是两个 ws2812 bit，每颗灯 12 个字节
+//!
+//! 每个 4 bit 都以 0 结尾，SPI 发完最后一个字节之后 MOSI 保持低电平，所以字节之间的间隔只会拉长某个 bit 的低电平，
+//! 但间隔太长又会被当作复位，因此与 s06 的 GPIO 翻转后端相同，每颗灯发送时屏蔽中断（约 32 us）
+
+#![allow(dead_code)]
+
+use stm32f4xx_hal::pac::{self, spi1::RegisterBlock};
+
+use super::{
+    ticker,
+    ws2812::{FrameBuffer, Ws2812Out, BITS_PER_LED},
+};
+
+// 新款 ws2812b 的复位时间为 280 us
+const RESET_US: u64 = 300;
+
+pub(crate) struct SpiWs2812 {
+    spi: &'static RegisterBlock,
+}
+
+impl SpiWs2812 {
+    // 假设 APB1 为 12 MHz
+    pub(crate) fn new(dp: &pac::Peripherals) -> Self {
+        dp.RCC.ahb1enr.modify(|_, w| w.gpioben().enabled());
+        dp.RCC.apb1enr.modify(|_, w| w.spi2en().enabled());
+
+        let gpiob = &dp.GPIOB;
+        gpiob.afrh.modify(|_, w| w.afrh15().af5());
+        gpiob.ospeedr.modify(|_, w| w.ospeedr15().high_speed());
+        gpiob.moder.modify(|_, w| w.moder15().alternate());
+
+        let spi = unsafe { &*pac::SPI2::ptr() };
+        spi.cr1.write(|w| {
+            // 只发不收，不会产生 OVR
+            w.bidimode().bidirectional();
+            w.bidioe().output_enabled();
+            w.dff().eight_bit();
+            w.lsbfirst().msbfirst();
+            w.br().div4();
+            w.mstr().master();
+            w.ssm().enabled();
+            w.ssi().slave_not_selected();
+            w
+        });
+        spi.cr1.modify(|_, w| w.spe().enabled());
+
+        Self { spi }
+    }
+
+    // 高 2 bit 依次展开为 SPI 的一个字节
+    fn encode_pair(bits: u32) -> u8 {
+        let nibble = |bit: u32| if bit != 0 { 0b1100 } else { 0b1000 };
+        (nibble(bits & 0b10) << 4) | nibble(bits & 0b01)
+    }
+
+    fn send_led(&self, grb: u32) {
+        let spi = self.spi;
+        cortex_m::interrupt::free(|_| {
+            for pair in (0..BITS_PER_LED / 2).rev() {
+                let byte = Self::encode_pair(grb >> (pair * 2));
+                while spi.sr.read().txe().is_not_empty() {}
+                spi.dr.write(|w| w.dr().bits(byte as u16));
+            }
+        });
+    }
+}
+
+impl Ws2812Out for SpiWs2812 {
+    type Error = core::convert::Infallible;
+
+    fn show<const N: usize>(&mut self, frame: &FrameBuffer<N>) -> Result<(), Self::Error> {
+        for grb in frame.grb_words() {
+            self.send_led(grb);
+        }
+
+        // 等最后一个字节移出，再保持低电平直到灯带锁存
+        while self.spi.sr.read().bsy().is_busy() {}
+        let start = ticker::micros();
+        while ticker::micros() - start < RESET_US {}
+        Ok(())
+    }
+}
@@ -0,0 +1,482 @@
+//! XPT2046（ADS7843 兼容）电阻触摸屏控制器（SPI）
+//!
+//! 电阻屏由两层带电阻的薄膜组成，按下时两层在触点处接通，芯片轮流在一层上加电压、用另一层测量触点的电压，
+//! 就得到 X、Y 两个 12 位的读数，这个读数与屏幕坐标之间只是近似线性，还要经过校准才能使用
+//!
+//! 每次转换先发送一个控制字节：S A2 A1 A0 MODE SER/DFR PD1 PD0，之后的两个字节中，芯片输出 12 位的结果（高位对齐，低 3 位补 0）
+//! 这里总是使用差分（DFR）模式、12 位，PD = 00：两次转换之间芯片掉电，并打开 PENIRQ
+//!
+//! 读数的处理：
+//!
+//! 1. 压力：Z1、Z2 两次测量可以求出触点的电阻，R = R_X-plate * X / 4096 * (Z2 / Z1 - 1)，按得越重电阻越小，
+//!    电阻超过 max_resistance 的触摸（轻轻擦过、抬起的过程中）直接丢弃，这些时候的 X、Y 读数很不可靠
+//! 2. 中值滤波：X、Y 各采样 SAMPLES 次，取中值，并且要求中间一半读数的跨度不超过 max_spread，否则认为读数还不稳定
+//! 3. 采样结束后再测一次压力，防止笔在采样的过程中抬起
+//!
+//! 校准使用三点仿射变换：屏幕坐标 = A * (raw_x, raw_y, 1)，A 为 2x3 的矩阵，可以同时修正缩放、偏移、旋转以及 X/Y 的对调，
+//! 在屏幕上依次显示三个十字，用户点击后由 solve 解出矩阵（见 calibrate）
+//! 矩阵有 6 个参数，而 utils::settings 中每个条目只有 4 个 f32，因此分成 touch_x、touch_y 两个条目保存（见 Affine::save）
+//! 校准需要在屏幕上显示目标，不适合 utils::calibration 那种“输入一个数值”的 Prompt，因此没有实现 Calibrate
+//!
+//! PENIRQ：没有触摸时，PENIRQ 为高电平，按下时被拉低，把 pen_irq 注册为这个引脚下降沿的 EXTI 回调（见 utils::exti），
+//! 空闲时 poll 只检查一个标识，不会访问 SPI；转换的过程中 PENIRQ 也会跳动，因此每次读取之后都会清除这个标识
+//! 不接 PENIRQ 时，Config::use_irq 设为 false，poll 每次都会真正采样一次
+//!
+//! XPT2046 的 DCLK 最高约 2 MHz，通常与屏幕共用一个 SPI，而屏幕需要快得多的时钟，
+//! 因此每次访问时临时把 SPI 切换为 Mode 0、br 指定的分频，访问结束后恢复原来的 CR1
+//! 片选是泛型参数，任何实现了 embedded-hal OutputPin 的引脚都可以
+
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use stm32f4xx_hal::{hal::digital::OutputPin, pac::spi1::RegisterBlock};
+
+use super::{
+    datalog::LogFlash,
+    settings::{SettingsError, SettingsStore},
+    ticker,
+};
+
+// 控制字节：S = 1，12 位，差分模式，PD = 00
+const CMD_X: u8 = 0b1101_0000;
+const CMD_Y: u8 = 0b1001_0000;
+const CMD_Z1: u8 = 0b1011_0000;
+const CMD_Z2: u8 = 0b1100_0000;
+
+// CR1 中需要临时修改的位
+const CR1_CPHA: u32 = 1 << 0;
+const CR1_CPOL: u32 = 1 << 1;
+const CR1_BR: u32 = 0b111 << 3;
+const CR1_SPE: u32 = 1 << 6;
+const CR1_DFF: u32 = 1 << 11;
+
+const FULL_SCALE: f32 = 4096.0;
+
+// 每个坐标的采样次数，取中值
+const SAMPLES: usize = 7;
+
+// 连续这么多次采样失败，才认为笔已经抬起，避免一次不稳定的读数就产生 Up、Down
+const RELEASE_MISSES: u8 = 3;
+
+// 校准时，每个点需要连续得到这么多个有效采样，取平均
+const CALIBRATION_SAMPLES: u32 = 8;
+const CALIBRATION_TIMEOUT_MS: u32 = 30_000;
+
+// 保存在 utils::settings 中的键
+const KEY_X: &str = "touch_x";
+const KEY_Y: &str = "touch_y";
+
+static PEN_IRQ: AtomicBool = AtomicBool::new(false);
+
+// PENIRQ 下降沿的 EXTI 回调
+pub(crate) fn pen_irq(_line: u8) {
+    PEN_IRQ.store(true, Ordering::Relaxed);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum TouchError {
+    ChipSelect,
+    // 校准时用户一直没有点击
+    Timeout,
+    // 三个校准点几乎在一条直线上，或者离得太近，解不出变换矩阵
+    Degenerate,
+    Settings(SettingsError),
+}
+
+impl From<SettingsError> for TouchError {
+    fn from(e: SettingsError) -> Self {
+        TouchError::Settings(e)
+    }
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub(crate) struct Config {
+    // CR1 的 BR 位，SCK = f_PCLK / 2^(br + 1)
+    pub(crate) br: u8,
+    // X 方向薄膜的电阻，模块的说明里没有的话，用万用表量一下 X+ 与 X- 之间的电阻
+    pub(crate) x_plate_ohms: f32,
+    // 触点电阻超过这个值的采样被丢弃
+    pub(crate) max_resistance: f32,
+    // SAMPLES 次读数中，中间一半的最大跨度
+    pub(crate) max_spread: u16,
+    // 坐标变化超过这么多像素才产生 Move
+    pub(crate) move_threshold: i16,
+    // 是否接了 PENIRQ，并注册了 pen_irq
+    pub(crate) use_irq: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            br: 0b010,
+            x_plate_ohms: 400.0,
+            max_resistance: 1500.0,
+            max_spread: 40,
+            move_threshold: 3,
+            use_irq: true,
+        }
+    }
+}
+
+// 未经校准的读数
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct RawPoint {
+    pub(crate) x: u16,
+    pub(crate) y: u16,
+    // 触点的电阻，单位为 Ω
+    pub(crate) resistance: f32,
+}
+
+// 屏幕坐标，校准不完美时，屏幕边缘处可能略微超出屏幕
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Point {
+    pub(crate) x: i16,
+    pub(crate) y: i16,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum TouchEvent {
+    Down(Point),
+    Move(Point),
+    // 坐标为抬起之前最后一个有效的位置
+    Up(Point),
+}
+
+// screen_x = a * raw_x + b * raw_y + c
+// screen_y = d * raw_x + e * raw_y + f
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct Affine {
+    pub(crate) a: f32,
+    pub(crate) b: f32,
+    pub(crate) c: f32,
+    pub(crate) d: f32,
+    pub(crate) e: f32,
+    pub(crate) f: f32,
+}
+
+impl Affine {
+    // 还没有校准时使用：假设 X、Y 与屏幕方向一致，读数的 0 ~ 4095 线性对应整个屏幕
+    pub(crate) fn uncalibrated(width: u16, height: u16) -> Self {
+        Self {
+            a: width as f32 / FULL_SCALE,
+            b: 0.0,
+            c: 0.0,
+            d: 0.0,
+            e: height as f32 / FULL_SCALE,
+            f: 0.0,
+        }
+    }
+
+    // 由三对 原始读数 -> 屏幕坐标 解出变换矩阵
+    //
+    // 以第三个点为原点，两个坐标方向各自是一个二元一次方程组，用克莱姆法则求解
+    pub(crate) fn solve(raw: &[(f32, f32); 3], screen: &[(f32, f32); 3]) -> Option<Self> {
+        let (x1, y1) = (raw[0].0 - raw[2].0, raw[0].1 - raw[2].1);
+        let (x2, y2) = (raw[1].0 - raw[2].0, raw[1].1 - raw[2].1);
+
+        // 三个点围成的三角形面积的两倍，太小时噪声会被大大放大
+        let det = x1 * y2 - x2 * y1;
+        if det.abs() < 1000.0 {
+            return None;
+        }
+
+        let axis = |t1: f32, t2: f32, t3: f32, raw3: (f32, f32)| {
+            let (t1, t2) = (t1 - t3, t2 - t3);
+            let p = (t1 * y2 - t2 * y1) / det;
+            let q = (x1 * t2 - x2 * t1) / det;
+            (p, q, t3 - p * raw3.0 - q * raw3.1)
+        };
+        let (a, b, c) = axis(screen[0].0, screen[1].0, screen[2].0, raw[2]);
+        let (d, e, f) = axis(screen[0].1, screen[1].1, screen[2].1, raw[2]);
+        Some(Self { a, b, c, d, e, f })
+    }
+
+    pub(crate) fn map(&self, raw: &RawPoint) -> Point {
+        let (x, y) = (raw.x as f32, raw.y as f32);
+        Point {
+            x: (self.a * x + self.b * y + self.c) as i16,
+            y: (self.d * x + self.e * y + self.f) as i16,
+        }
+    }
+
+    // 两个条目，需要 commit 才会写入 Flash，这里一起完成
+    pub(crate) fn save<F: LogFlash>(
+        &self,
+        store: &mut SettingsStore<F>,
+    ) -> Result<(), SettingsError> {
+        store.set(KEY_X, [self.a, self.b, self.c, 0.0])?;
+        store.set(KEY_Y, [self.d, self.e, self.f, 0.0])?;
+        store.commit()
+    }
+
+    // 两个条目都存在时才返回
+    pub(crate) fn load<F: LogFlash>(store: &SettingsStore<F>) -> Option<Self> {
+        let [a, b, c, _] = store.get(KEY_X)?;
+        let [d, e, f, _] = store.get(KEY_Y)?;
+        Some(Self { a, b, c, d, e, f })
+    }
+
+    // 删除保存的校准，同样立刻 commit
+    pub(crate) fn forget<F: LogFlash>(store: &mut SettingsStore<F>) -> Result<(), SettingsError> {
+        store.remove(KEY_X);
+        store.remove(KEY_Y);
+        store.commit()
+    }
+}
+
+pub(crate) struct Xpt2046<'a, CS> {
+    spi: &'a RegisterBlock,
+    cs: CS,
+    config: Config,
+    cal: Affine,
+    // 正在触摸时，最后一个有效的位置
+    pressed: Option<Point>,
+    misses: u8,
+}
+
+impl<'a, CS: OutputPin> Xpt2046<'a, CS> {
+    pub(crate) fn new(
+        spi: &'a RegisterBlock,
+        mut cs: CS,
+        config: Config,
+        cal: Affine,
+    ) -> Result<Self, TouchError> {
+        cs.set_high().map_err(|_| TouchError::ChipSelect)?;
+        Ok(Self {
+            spi,
+            cs,
+            config,
+            cal,
+            pressed: None,
+            misses: 0,
+        })
+    }
+
+    pub(crate) fn calibration(&self) -> Affine {
+        self.cal
+    }
+
+    pub(crate) fn set_calibration(&mut self, cal: Affine) {
+        self.cal = cal;
+    }
+
+    pub(crate) fn is_pressed(&self) -> bool {
+        self.pressed.is_some()
+    }
+
+    // 归还片选引脚
+    pub(crate) fn release(self) -> CS {
+        self.cs
+    }
+
+    // 采样一次，没有按下、压力不够或者读数不稳定时返回 None
+    pub(crate) fn sample(&mut self) -> Result<Option<RawPoint>, TouchError> {
+        let config = self.config;
+        let result = self.with_spi(|touch| {
+            let resistance = touch.pressure(touch.convert(CMD_X))?;
+
+            let mut xs = [0u16; SAMPLES];
+            let mut ys = [0u16; SAMPLES];
+            for (x, y) in xs.iter_mut().zip(ys.iter_mut()) {
+                *x = touch.convert(CMD_X);
+                *y = touch.convert(CMD_Y);
+            }
+            let x = median(&mut xs, config.max_spread)?;
+            let y = median(&mut ys, config.max_spread)?;
+
+            // 笔可能在采样的过程中抬起，最后再确认一次
+            touch.pressure(x)?;
+            Some(RawPoint { x, y, resistance })
+        });
+
+        // 转换的过程中 PENIRQ 也会跳动，这些下降沿不是新的触摸
+        PEN_IRQ.store(false, Ordering::Relaxed);
+        result
+    }
+
+    // 在主循环中调用，把采样转换为按下、移动、抬起的事件
+    pub(crate) fn poll(&mut self) -> Result<Option<TouchEvent>, TouchError> {
+        if self.pressed.is_none() && self.config.use_irq && !PEN_IRQ.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+
+        let sample = self.sample()?;
+        let event = match (self.pressed, sample) {
+            (None, None) => None,
+            (None, Some(raw)) => {
+                let point = self.cal.map(&raw);
+                self.pressed = Some(point);
+                Some(TouchEvent::Down(point))
+            }
+            (Some(last), Some(raw)) => {
+                self.misses = 0;
+                let point = self.cal.map(&raw);
+                let threshold = self.config.move_threshold;
+                if (point.x - last.x).abs() >= threshold || (point.y - last.y).abs() >= threshold {
+                    self.pressed = Some(point);
+                    Some(TouchEvent::Move(point))
+                } else {
+                    None
+                }
+            }
+            (Some(last), None) => {
+                self.misses += 1;
+                if self.misses >= RELEASE_MISSES {
+                    self.misses = 0;
+                    self.pressed = None;
+                    Some(TouchEvent::Up(last))
+                } else {
+                    None
+                }
+            }
+        };
+        Ok(event)
+    }
+
+    // 三点校准，targets 为屏幕上的三个目标点，最好分布在屏幕的三个角附近，且不在一条直线上
+    //
+    // 每个点开始之前调用 show(序号, 目标点)，由调用者在屏幕上画出目标
+    // 用户按住目标点，取 CALIBRATION_SAMPLES 个有效采样的平均值，之后要等到抬起才进行下一个点
+    // 成功后立刻使用新的校准，是否保存由调用者决定（见 Affine::save）
+    pub(crate) fn calibrate(
+        &mut self,
+        targets: &[(u16, u16); 3],
+        mut show: impl FnMut(usize, (u16, u16)),
+    ) -> Result<Affine, TouchError> {
+        let mut raw = [(0.0, 0.0); 3];
+        let mut screen = [(0.0, 0.0); 3];
+
+        for (index, &target) in targets.iter().enumerate() {
+            show(index, target);
+            raw[index] = self.hold_point()?;
+            screen[index] = (target.0 as f32, target.1 as f32);
+            self.wait_release()?;
+        }
+
+        let cal = Affine::solve(&raw, &screen).ok_or(TouchError::Degenerate)?;
+        self.cal = cal;
+        self.pressed = None;
+        Ok(cal)
+    }
+
+    // 连续得到 CALIBRATION_SAMPLES 个有效采样，中途抬起则重新开始
+    fn hold_point(&mut self) -> Result<(f32, f32), TouchError> {
+        let start = ticker::millis();
+        let (mut sum_x, mut sum_y, mut count) = (0.0, 0.0, 0);
+
+        while count < CALIBRATION_SAMPLES {
+            if ticker::millis().wrapping_sub(start) > CALIBRATION_TIMEOUT_MS {
+                return Err(TouchError::Timeout);
+            }
+            match self.sample()? {
+                Some(raw) => {
+                    sum_x += raw.x as f32;
+                    sum_y += raw.y as f32;
+                    count += 1;
+                }
+                None => (sum_x, sum_y, count) = (0.0, 0.0, 0),
+            }
+            ticker::delay_ms(10);
+        }
+
+        Ok((sum_x / count as f32, sum_y / count as f32))
+    }
+
+    // 连续 RELEASE_MISSES 次没有触摸，才算抬起
+    fn wait_release(&mut self) -> Result<(), TouchError> {
+        let start = ticker::millis();
+        let mut misses = 0;
+        while misses < RELEASE_MISSES {
+            if ticker::millis().wrapping_sub(start) > CALIBRATION_TIMEOUT_MS {
+                return Err(TouchError::Timeout);
+            }
+            misses = match self.sample()? {
+                Some(_) => 0,
+                None => misses + 1,
+            };
+            ticker::delay_ms(10);
+        }
+        Ok(())
+    }
+
+    // 由 X 读数与 Z1、Z2 计算触点电阻，压力不够时返回 None
+    fn pressure(&self, x: u16) -> Option<f32> {
+        let z1 = self.convert(CMD_Z1);
+        let z2 = self.convert(CMD_Z2);
+        // Z1 太小时基本就是没有按下，也避免了下面除以 0
+        if z1 < 32 || z2 <= z1 {
+            return None;
+        }
+
+        let resistance =
+            self.config.x_plate_ohms * x as f32 / FULL_SCALE * (z2 as f32 / z1 as f32 - 1.0);
+        (resistance <= self.config.max_resistance).then_some(resistance)
+    }
+
+    // 临时把 SPI 切换为 XPT2046 需要的设置，并选中芯片
+    fn with_spi<R>(&mut self, f: impl FnOnce(&Self) -> R) -> Result<R, TouchError> {
+        let spi = self.spi;
+        // 等待其他设备的传输完全结束
+        while spi.sr.read().txe().is_not_empty() {}
+        while spi.sr.read().bsy().is_busy() {}
+
+        // 修改 CPOL、CPHA、BR、DFF 时 SPE 必须为 0
+        let saved = spi.cr1.read().bits();
+        let ours = (saved & !(CR1_CPHA | CR1_CPOL | CR1_BR | CR1_DFF | CR1_SPE))
+            | ((self.config.br as u32) << 3);
+        spi.cr1.write(|w| unsafe { w.bits(saved & !CR1_SPE) });
+        spi.cr1.write(|w| unsafe { w.bits(ours) });
+        spi.cr1.write(|w| unsafe { w.bits(ours | CR1_SPE) });
+
+        // 其他设备只发不收，这里先把残留的数据与 OVR 清掉
+        spi.dr.read();
+        spi.sr.read();
+
+        self.cs.set_low().map_err(|_| TouchError::ChipSelect)?;
+        let result = f(self);
+        self.cs.set_high().map_err(|_| TouchError::ChipSelect)?;
+
+        while spi.sr.read().bsy().is_busy() {}
+        spi.cr1.write(|w| unsafe { w.bits(ours) });
+        spi.cr1.write(|w| unsafe { w.bits(saved & !CR1_SPE) });
+        spi.cr1.write(|w| unsafe { w.bits(saved) });
+
+        Ok(result)
+    }
+
+    // 一次转换：控制字节，之后的两个字节中是 12 位的结果
+    fn convert(&self, cmd: u8) -> u16 {
+        self.transfer(cmd);
+        let high = self.transfer(0);
+        let low = self.transfer(0);
+        u16::from_be_bytes([high, low]) >> 3
+    }
+
+    fn transfer(&self, byte: u8) -> u8 {
+        let spi = self.spi;
+        while spi.sr.read().txe().is_not_empty() {}
+        spi.dr.write(|w| w.dr().bits(byte as u16));
+        while spi.sr.read().rxne().is_empty() {}
+        spi.dr.read().dr().bits() as u8
+    }
+}
+
+// 排序后取中值，中间一半的跨度超过 max_spread 时返回 None
+fn median(values: &mut [u16; SAMPLES], max_spread: u16) -> Option<u16> {
+    values.sort_unstable();
+    let quarter = SAMPLES / 4;
+    let spread = values[SAMPLES - 1 - quarter] - values[quarter];
+    (spread <= max_spread).then_some(values[SAMPLES / 2])
+}
@@ -0,0 +1,7 @@
+/* 说明见 s01_rcc 的 memory.x */
+
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 512K
+  RAM : ORIGIN = 0x20000000, LENGTH = 320K
+}
@@ -0,0 +1,390 @@
+//! 基于 COBS + CRC16 的二进制帧协议
+//!
+//! s21c01 中，我们是用一行一行的文本输出遥测数据的，这样做很直观，但有几个问题：
+//! 1. 文本格式比较浪费带宽，一个 f32 要占用 6~10 个字节
+//! 2. 线路上出现干扰时，我们无法知道收到的数据是不是错的
+//! 3. 想要从 Host 发送结构化的命令到 MCU，还得自己写一个解析器
+//!
+//! 因此这里我们换用二进制的帧（见 telemetry_core::framing 的说明）：
+//! 消息用 serde + postcard 序列化，加上类型 tag 和 CRC16，再用 COBS 编码，最后以 0x00 作为帧的结尾
+//!
+//! MCU 每隔一段时间发送一帧 Telemetry，同时在 USART1 的中断中接收 Host 发来的 Command 并回复 Response
+//! 当 USART1 报告溢出、噪声或帧错误时，通知 FrameDecoder 重新同步
+//!
+//! 可选的 payload 加密：把 PAYLOAD_KEY 设置为一个 16 字节的密钥后，发出的帧都会用 AES-128-CTR 加密（见 telemetry_core::framing），
+//! Host 端用 telemetry_host frames --key <同一个密钥的十六进制> 解析；此时 MCU 只接受加密的 Command，明文的 Command 会被忽略
+//! 每一帧的 nonce 由两部分组成：高 32 位是上电时从 RNG 取得的随机数，低 32 位是发送的帧数，这样复位之后也不会重复使用之前的 nonce
+//!
+//! 接线图：
+//!
+//! USB-TTL 模块
+//! PA9  (USART1 Tx) <-> Rx
+//! PA10 (USART1 Rx) <-> Tx
+//! GND              <-> GND
+
+#![no_std]
+#![no_main]
+
+use core::cell::{Cell, RefCell};
+
+use cortex_m::{
+    interrupt::{CriticalSection, Mutex},
+    peripheral::NVIC,
+};
+use cortex_m_rt::exception;
+use crypto_core::aes::{Aes128, KEY_LEN};
+use embedded_io::Write;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+use serde::Serialize;
+use stm32f4xx_hal::pac::{self, interrupt};
+
+mod utils;
+
+use utils::{
+    framing::{encode_frame, encode_sealed_frame, FrameDecoder, MsgTag, MAX_ENCODED_LEN},
+    io,
+    message::{Command, ErrorCode, Response, Telemetry},
+};
+
+chip_caps::require!(RNG);
+
+// 出厂校准值，见 s21c01
+const TS_CAL1: *const u16 = 0x1FFF_7A2C as *const u16;
+const TS_CAL2: *const u16 = 0x1FFF_7A2E as *const u16;
+const VREFINT_CAL: *const u16 = 0x1FFF_7A2A as *const u16;
+// 96 bit 的芯片唯一 ID
+const UID_BASE: *const [u8; 12] = 0x1FFF_7A10 as *const [u8; 12];
+
+// 为 None 时收发明文的帧，设置为 Some 时启用加密，比如
+// Some([0x2B, 0x7E, 0x15, 0x16, 0x28, 0xAE, 0xD2, 0xA6, 0xAB, 0xF7, 0x15, 0x88, 0x09, 0xCF, 0x4F, 0x3C])
+const PAYLOAD_KEY: Option<[u8; KEY_LEN]> = None;
+
+// 密钥扩展在编译期完成
+static CIPHER: Option<Aes128> = match PAYLOAD_KEY {
+    Some(key) => Some(Aes128::new(&key)),
+    None => None,
+};
+
+static G_DP: Mutex<RefCell<Option<pac::Peripherals>>> = Mutex::new(RefCell::new(None));
+static G_DECODER: Mutex<RefCell<FrameDecoder>> = Mutex::new(RefCell::new(FrameDecoder::new()));
+static G_MILLIS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+static G_PERIOD_MS: Mutex<Cell<u32>> = Mutex::new(Cell::new(1000));
+// 下一帧加密时使用的 nonce
+static G_NONCE: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let dp = pac::Peripherals::take().unwrap();
+
+    setup_hse(&dp);
+    setup_systick(&dp);
+    setup_adc(&dp);
+    setup_usart1(&dp);
+
+    if PAYLOAD_KEY.is_some() {
+        setup_rng(&dp);
+        let boot_id = random_u32(&dp.RNG);
+        cortex_m::interrupt::free(|cs| {
+            G_NONCE.borrow(cs).set((boot_id as u64) << 32);
+            G_DECODER
+                .borrow(cs)
+                .borrow_mut()
+                .set_key(PAYLOAD_KEY.as_ref());
+        });
+    }
+
+    cortex_m::interrupt::free(|cs| G_DP.borrow(cs).replace(Some(dp)));
+
+    unsafe { NVIC::unmask(interrupt::USART1) };
+
+    rprintln!("framed telemetry started");
+
+    let mut seq = 0u32;
+    let mut next_due_ms = 0u32;
+
+    loop {
+        let (now_ms, period_ms) = cortex_m::interrupt::free(|cs| {
+            (G_MILLIS.borrow(cs).get(), G_PERIOD_MS.borrow(cs).get())
+        });
+
+        if (now_ms.wrapping_sub(next_due_ms) as i32) < 0 {
+            continue;
+        }
+        next_due_ms = now_ms.wrapping_add(period_ms);
+
+        cortex_m::interrupt::free(|cs| {
+            let dp_ref = G_DP.borrow(cs).borrow();
+            let dp = dp_ref.as_ref().unwrap();
+
+            let (mcu_temp_centi, vdda_mv) = sample_internal(&dp.ADC1);
+
+            let telemetry = Telemetry {
+                seq,
+                uptime_ms: now_ms,
+                mcu_temp_centi,
+                vdda_mv,
+            };
+
+            // 整个发送过程都在临界区内，防止中断中发送的 Response 插入到这一帧的中间
+            send_frame(
+                cs,
+                &mut io::Usart::new(&dp.USART1),
+                MsgTag::Telemetry,
+                &telemetry,
+            );
+        });
+
+        seq = seq.wrapping_add(1);
+    }
+}
+
+// 帧可以写到任意一个实现了 embedded_io::Write 的传输上（见 utils::io），这里用的是 USART1
+fn send_frame<W: Write, T: Serialize>(cs: &CriticalSection, out: &mut W, tag: MsgTag, msg: &T) {
+    let mut buf = [0u8; MAX_ENCODED_LEN];
+    let encoded = match &CIPHER {
+        Some(cipher) => {
+            // 低 32 位是帧数，不能进位到高 32 位的随机数中
+            // 按最快 10 ms 一帧计算，低 32 位回绕也需要一年以上，这里不做处理
+            let nonce = G_NONCE.borrow(cs);
+            let current = nonce.get();
+            let count = (current as u32).wrapping_add(1);
+            nonce.set((current & 0xFFFF_FFFF_0000_0000) | count as u64);
+            encode_sealed_frame(tag, msg, cipher, current, &mut buf)
+        }
+        None => encode_frame(tag, msg, &mut buf),
+    };
+    match encoded {
+        Ok(frame) => {
+            if out.write_all(frame).is_err() {
+                rprintln!("send error");
+            }
+        }
+        Err(e) => rprintln!("encode error: {:?}", e),
+    }
+}
+
+fn handle_command(command: Command) -> Response {
+    match command {
+        Command::Ping(value) => Response::Pong(value),
+        Command::SetPeriod { ms } => {
+            if !(10..=60_000).contains(&ms) {
+                return Response::Err(ErrorCode::BadArgument);
+            }
+            cortex_m::interrupt::free(|cs| G_PERIOD_MS.borrow(cs).set(ms));
+            Response::Ok
+        }
+        Command::ReadUid => Response::Uid(unsafe { UID_BASE.read_volatile() }),
+    }
+}
+
+#[interrupt]
+fn USART1() {
+    cortex_m::interrupt::free(|cs| {
+        let dp_ref = G_DP.borrow(cs).borrow();
+        let dp = dp_ref.as_ref().unwrap();
+        let usart = &dp.USART1;
+
+        let mut decoder = G_DECODER.borrow(cs).borrow_mut();
+
+        // 先读 SR 再读 DR，可以同时清除 RXNE 以及 ORE/NE/FE 这些错误标志
+        let sr = usart.sr.read();
+        let byte = usart.dr.read().dr().bits() as u8;
+
+        if sr.ore().bit_is_set() || sr.nf().bit_is_set() || sr.fe().bit_is_set() {
+            // 出错时，至少有一个字节丢失或者损坏了，当前的帧已经不可信了
+            decoder.resync();
+            return;
+        }
+
+        let response = match decoder.push(byte) {
+            None => return,
+            // 设置了密钥时，不执行明文的命令
+            Some(Ok(frame)) if CIPHER.is_some() && !frame.sealed => {
+                rprintln!("plaintext frame ignored");
+                return;
+            }
+            Some(Ok(frame)) if frame.tag == MsgTag::Command => match frame.parse::<Command>() {
+                Ok(command) => handle_command(command),
+                Err(_) => Response::Err(ErrorCode::BadCommand),
+            },
+            // 其他类型的帧，MCU 不需要处理
+            Some(Ok(_)) => return,
+            Some(Err(e)) => {
+                rprintln!("bad frame: {:?}, total {}", e, decoder.error_cnt());
+                return;
+            }
+        };
+
+        send_frame(cs, &mut io::Usart::new(usart), MsgTag::Response, &response);
+    });
+}
+
+#[exception]
+fn SysTick() {
+    cortex_m::interrupt::free(|cs| {
+        let millis = G_MILLIS.borrow(cs);
+        millis.set(millis.get().wrapping_add(1));
+    });
+}
+
+// 采样内部温度传感器和 V_{REFINT}，返回 0.01 ℃ 为单位的温度，以及 mV 为单位的 V_{DDA}
+// 计算方法见 s21c01
+fn sample_internal(adc: &pac::ADC1) -> (i16, u16) {
+    let vrefint_raw = read_adc_channel(adc, 17).max(1) as f32;
+    let temp_raw = read_adc_channel(adc, 18) as f32;
+
+    let (cal1, cal2, vrefint_cal) = unsafe {
+        (
+            TS_CAL1.read_volatile() as f32,
+            TS_CAL2.read_volatile() as f32,
+            VREFINT_CAL.read_volatile() as f32,
+        )
+    };
+
+    let temp_raw = temp_raw * vrefint_cal / vrefint_raw;
+    let temp = 30.0 + (temp_raw - cal1) * (110.0 - 30.0) / (cal2 - cal1);
+    let vdda = 3.3 * vrefint_cal / vrefint_raw;
+
+    ((temp * 100.0) as i16, (vdda * 1000.0) as u16)
+}
+
+fn read_adc_channel(adc: &pac::ADC1, channel: u8) -> u16 {
+    adc.sqr3.modify(|_, w| unsafe { w.sq1().bits(channel) });
+    adc.cr2.modify(|_, w| w.swstart().start());
+    while adc.sr.read().eoc().bit_is_clear() {}
+    adc.dr.read().data().bits()
+}
+
+// 从 RNG 取出一个随机数，RNG 报告错误时丢弃这个数，重新等待
+fn random_u32(rng: &pac::RNG) -> u32 {
+    loop {
+        let sr = rng.sr.read();
+        if sr.seis().bit_is_set() || sr.ceis().bit_is_set() {
+            // 清除错误标志并重新启动 RNG
+            rng.sr.modify(|_, w| {
+                w.seis().clear_bit();
+                w.ceis().clear_bit();
+                w
+            });
+            rng.cr.modify(|_, w| w.rngen().clear_bit());
+            rng.cr.modify(|_, w| w.rngen().set_bit());
+            continue;
+        }
+        if sr.drdy().bit_is_set() {
+            return rng.dr.read().bits();
+        }
+    }
+}
+
+// 切换到 HSE 时钟源
+fn setup_hse(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while !rcc.cfgr.read().sws().is_hse() {}
+}
+
+// SysTick 使用 HCLK / 8 = 1.5 MHz 的时钟，每 1500 个时钟触发一次，也就是 1 kHz
+fn setup_systick(dp: &pac::Peripherals) {
+    let systick = &dp.STK;
+
+    systick
+        .load
+        .modify(|_, w| unsafe { w.reload().bits(1500 - 1) });
+    systick.val.reset();
+    systick.ctrl.modify(|_, w| {
+        w.clksource().bit(false);
+        w.tickint().bit(true);
+        w.enable().set_bit();
+        w
+    });
+}
+
+// PLL 只用来产生 RNG 的 48 MHz 时钟，见 s18c01
+// 12 MHz / 6 * 96 = 192 MHz，Q 输出 192 MHz / 4 = 48 MHz，P 输出没有使用
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+fn setup_rng(dp: &pac::Peripherals) {
+    let rcc = &dp.RCC;
+    rcc.pllcfgr.modify(|_, w| {
+        w.pllsrc().hse();
+        unsafe {
+            w.pllm().bits(6);
+            w.plln().bits(96);
+            w.pllq().bits(4);
+        }
+        w.pllp().div2();
+        w
+    });
+    rcc.cr.modify(|_, w| w.pllon().on());
+    while rcc.cr.read().pllrdy().is_not_ready() {}
+
+    rcc.dckcfgr2.modify(|_, w| w.ck48msel().pll());
+    rcc.ahb2enr.modify(|_, w| w.rngen().enabled());
+
+    dp.RNG.cr.modify(|_, w| w.rngen().set_bit());
+}
+
+fn setup_adc(dp: &pac::Peripherals) {
+    dp.RCC.apb2enr.modify(|_, w| w.adc1en().enabled());
+
+    dp.ADC_COMMON.ccr.modify(|_, w| {
+        w.adcpre().div2();
+        w.tsvrefe().enabled();
+        w
+    });
+
+    let adc = &dp.ADC1;
+    // 通道 17、18 位于 SMPR1，每个通道 3 位，0b111 为 480 个周期
+    // 不用 smp17().cycles480()，F401/F411 的 pac 没有生成这些方法
+    adc.smpr1.modify(|r, w| unsafe {
+        w.bits(r.bits() | 0b111 << (3 * (17 - 10)) | 0b111 << (3 * (18 - 10)))
+    });
+    adc.sqr1.modify(|_, w| w.l().bits(0));
+    adc.cr2.modify(|_, w| w.adon().enabled());
+}
+
+// USART1，115200 8N1，启用接收中断
+fn setup_usart1(dp: &pac::Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| w.gpioaen().enabled());
+    dp.RCC.apb2enr.modify(|_, w| w.usart1en().enabled());
+
+    let gpioa = &dp.GPIOA;
+    gpioa.afrh.modify(|_, w| {
+        w.afrh9().af7(); // Tx
+        w.afrh10().af7(); // Rx
+        w
+    });
+    gpioa.pupdr.modify(|_, w| {
+        w.pupdr9().pull_up();
+        w.pupdr10().pull_up();
+        w
+    });
+    gpioa.moder.modify(|_, w| {
+        w.moder9().alternate();
+        w.moder10().alternate();
+        w
+    });
+
+    let serial1 = &dp.USART1;
+
+    serial1.cr1.modify(|_, w| w.ue().enabled());
+
+    // 12 MHz / (16 * 115200) = 6.51，整数部分为 6，小数部分为 0.51 * 16 ≈ 8
+    serial1.brr.write(|w| {
+        w.div_mantissa().bits(6);
+        w.div_fraction().bits(8);
+        w
+    });
+
+    serial1.cr1.modify(|_, w| {
+        w.rxneie().enabled();
+        w.re().enabled();
+        w.te().enabled();
+        w
+    });
+}
@@ -0,0 +1,5 @@
+// 帧格式与消息的定义在 telemetry_core 中，与 Host 端的 telemetry_host 共用
+pub(crate) use telemetry_core::{framing, message};
+
+// 与 s21 共用的模块，代码在工作区中单独的 crate 里
+pub(crate) use mcu_common::io;
@@ -0,0 +1,132 @@
+//! COBS: Consistent Overhead Byte Stuffing
+//!
+//! 在字节流上传输数据帧，首先要解决的问题是“帧从哪里开始，到哪里结束”
+//! COBS 的做法是：把数据中所有的 0x00 都替换掉，这样 0x00 就可以专门用来作为帧的分隔符
+//!
+//! 编码方式：把数据按 0x00 切成若干段，每一段前面加上一个字节，表示“到下一个 0x00 的距离”
+//! 比如 [0x11, 0x22, 0x00, 0x33] 会被编码为 [0x03, 0x11, 0x22, 0x02, 0x33]
+//! 若一段连续 254 个字节都没有 0x00，则插入一个 0xFF，表示这一段后面并没有 0x00
+//!
+//! 因此 n 个字节的数据，编码后最多为 n + n / 254 + 1 个字节，开销是固定且可预期的
+
+// 编码 n 个字节最多需要的空间
+pub const fn max_encoded_len(n: usize) -> usize {
+    n + n / 254 + 1
+}
+
+// 将 src 编码到 dst 中，返回编码后的长度，dst 的空间不够时返回 None
+// 编码结果不包含末尾的 0x00 分隔符
+pub fn encode(src: &[u8], dst: &mut [u8]) -> Option<usize> {
+    if dst.len() < max_encoded_len(src.len()) {
+        return None;
+    }
+
+    // code_index 指向当前这一段的长度字节
+    let mut code_index = 0;
+    let mut code = 1u8;
+    let mut out_index = 1;
+
+    for &byte in src {
+        if byte == 0 {
+            dst[code_index] = code;
+            code_index = out_index;
+            out_index += 1;
+            code = 1;
+        } else {
+            dst[out_index] = byte;
+            out_index += 1;
+            code += 1;
+            if code == 0xFF {
+                dst[code_index] = code;
+                code_index = out_index;
+                out_index += 1;
+                code = 1;
+            }
+        }
+    }
+
+    dst[code_index] = code;
+
+    Some(out_index)
+}
+
+// 就地解码，返回解码后的长度，数据不是合法的 COBS 编码时返回 None
+// 输入不应包含 0x00 分隔符
+pub fn decode_in_place(buf: &mut [u8]) -> Option<usize> {
+    let mut read_index = 0;
+    let mut write_index = 0;
+
+    while read_index < buf.len() {
+        let code = buf[read_index];
+        if code == 0 {
+            return None;
+        }
+        read_index += 1;
+
+        let seg_end = read_index + code as usize - 1;
+        if seg_end > buf.len() {
+            return None;
+        }
+
+        // 解码后的数据总是比编码前短，因此就地向前搬移是安全的
+        buf.copy_within(read_index..seg_end, write_index);
+        write_index += seg_end - read_index;
+        read_index = seg_end;
+
+        // 0xFF 表示这一段后面没有 0x00，另外最后一段后面也没有 0x00
+        if code != 0xFF && read_index < buf.len() {
+            buf[write_index] = 0;
+            write_index += 1;
+        }
+    }
+
+    Some(write_index)
+}
+
+// cargo test -p telemetry_core --target x86_64-unknown-linux-gnu
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(src: &[u8]) {
+        let mut buf = [0u8; 600];
+        let len = encode(src, &mut buf).unwrap();
+        assert!(len <= max_encoded_len(src.len()));
+        assert!(!buf[..len].contains(&0));
+        assert_eq!(decode_in_place(&mut buf[..len]), Some(src.len()));
+        assert_eq!(&buf[..src.len()], src);
+    }
+
+    #[test]
+    fn example_from_module_doc() {
+        let mut buf = [0u8; 8];
+        let len = encode(&[0x11, 0x22, 0x00, 0x33], &mut buf).unwrap();
+        assert_eq!(buf[..len], [0x03, 0x11, 0x22, 0x02, 0x33]);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(&[]);
+        round_trip(&[0x00]);
+        round_trip(&[0x00, 0x00]);
+        round_trip(&[0x11, 0x00, 0x00, 0x22]);
+
+        // 连续 254 个非 0 字节前后，是插入 0xFF 的边界
+        let mut long = [0u8; 520];
+        for (index, byte) in long.iter_mut().enumerate() {
+            *byte = (index % 255) as u8 + 1;
+        }
+        for len in [253, 254, 255, 508, 520] {
+            round_trip(&long[..len]);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let mut buf = [0u8; 4];
+        assert_eq!(encode(&[1, 2, 3, 4], &mut buf), None);
+        // 长度字节指向了数据之外
+        assert_eq!(decode_in_place(&mut [0x05, 0x11, 0x22]), None);
+        assert_eq!(decode_in_place(&mut [0x02, 0x11, 0x00]), None);
+    }
+}
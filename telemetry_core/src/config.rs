@@ -0,0 +1,369 @@
+//! 带版本号的配置，存两份，启动时自动迁移旧版本
+//!
+//! 配置是一个固定的结构体，各个字段由 postcard 序列化（与 message 中的消息一样），因此增删字段时不需要手动计算偏移
+//! 结构体本身由使用者定义（比如 s21 的 utils::config），这里只负责存储的格式，以及两份副本之间的修复与版本的迁移
+//!
+//! Flash 中的每一份副本占用一个扇区：
+//!
+//! | 偏移 | 长度 | 内容                                        |
+//! | 0    | 2    | 魔数 "CF"                                   |
+//! | 2    | 2    | 结构体的版本号 version                      |
+//! | 4    | 2    | payload 的长度                              |
+//! | 6    | 2    | 前 6 个字节与 payload 的 CRC-16             |
+//! | 8    | ...  | payload，postcard 序列化的结构体            |
+//!
+//! 两份副本的内容相同，保存时先写第一份，校验无误后再写第二份，任何时刻都至少有一份是完整的
+//! 读取时优先使用第一份，它损坏了才使用第二份，并把第二份的内容写回第一份
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    crc16::{crc16, crc16_update},
+    flash::LogFlash,
+};
+
+const MAGIC: u16 = u16::from_le_bytes(*b"CF");
+const HEADER_SIZE: usize = 8;
+pub const PAYLOAD_MAX: usize = 64;
+
+// 可以保存在 ConfigStore 中的配置结构体
+//
+// 修改结构体的定义时：
+// 1. 把旧的定义改名（比如 ConfigV1）留下来，VERSION 加 1
+// 2. 在 migrate 中把旧版本的 payload 反序列化为旧的结构体，再转换为新的结构体
+//
+// 这样已经烧录过旧版本固件的板子，升级后依旧保留原来的配置
+pub trait Versioned: Serialize + DeserializeOwned + Default {
+    const VERSION: u16;
+
+    // 把旧版本的 payload 转换为当前版本，不认识的版本返回 None
+    fn migrate(version: u16, payload: &[u8]) -> Option<Self>;
+}
+
+fn decode<C: Versioned>(version: u16, payload: &[u8]) -> Option<C> {
+    if version == C::VERSION {
+        return postcard::from_bytes(payload).ok();
+    }
+    C::migrate(version, payload)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    // 起始地址没有对齐到扇区
+    Geometry,
+    // 序列化之后超过了 PAYLOAD_MAX
+    TooLarge,
+    // 写入之后读回的内容不一致
+    Verify,
+}
+
+// 配置是从哪里读到的
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Origin {
+    // 两份副本都无效，使用默认值
+    Default,
+    Primary,
+    // 第一份损坏，使用了第二份
+    Backup,
+    // 从旧版本迁移而来，参数为旧的版本号
+    Migrated(u16),
+}
+
+pub struct ConfigStore<F: LogFlash, C: Versioned> {
+    flash: F,
+    start: u32,
+    config: core::marker::PhantomData<C>,
+}
+
+impl<F: LogFlash, C: Versioned> ConfigStore<F, C> {
+    // 使用 [start, start + 2 * SECTOR_SIZE) 两个扇区
+    pub fn new(flash: F, start: u32) -> Result<Self, ConfigError> {
+        if !start.is_multiple_of(F::SECTOR_SIZE) {
+            return Err(ConfigError::Geometry);
+        }
+        Ok(Self {
+            flash,
+            start,
+            config: core::marker::PhantomData,
+        })
+    }
+
+    fn copy_addr(&self, copy: u32) -> u32 {
+        self.start + copy * F::SECTOR_SIZE
+    }
+
+    // 读取一份副本，校验通过时返回版本号与 payload 的长度
+    fn read_copy(&mut self, copy: u32, payload: &mut [u8; PAYLOAD_MAX]) -> Option<(u16, usize)> {
+        let addr = self.copy_addr(copy);
+        let mut header = [0u8; HEADER_SIZE];
+        self.flash.read(addr, &mut header);
+
+        let half = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+        let len = half(4) as usize;
+        if half(0) != MAGIC || len > PAYLOAD_MAX {
+            return None;
+        }
+
+        self.flash
+            .read(addr + HEADER_SIZE as u32, &mut payload[..len]);
+        let crc = crc16_update(crc16(&header[..6]), &payload[..len]);
+        (crc == half(6)).then_some((half(2), len))
+    }
+
+    // 读取配置，失败时返回默认值，不会返回错误
+    //
+    // 使用了第二份副本，或者从旧版本迁移而来时，会立刻把结果写回 Flash
+    pub fn load(&mut self) -> (C, Origin) {
+        let mut payload = [0u8; PAYLOAD_MAX];
+
+        for (copy, origin) in [(0, Origin::Primary), (1, Origin::Backup)] {
+            let Some((version, len)) = self.read_copy(copy, &mut payload) else {
+                continue;
+            };
+            let Some(config) = decode::<C>(version, &payload[..len]) else {
+                continue;
+            };
+
+            let origin = if version != C::VERSION {
+                Origin::Migrated(version)
+            } else {
+                origin
+            };
+            if origin != Origin::Primary {
+                self.save(&config).ok();
+            }
+            return (config, origin);
+        }
+
+        (C::default(), Origin::Default)
+    }
+
+    // 依次写入两份副本
+    pub fn save(&mut self, config: &C) -> Result<(), ConfigError> {
+        let mut raw = [0xFFu8; HEADER_SIZE + PAYLOAD_MAX];
+        let len = postcard::to_slice(config, &mut raw[HEADER_SIZE..])
+            .map_err(|_| ConfigError::TooLarge)?
+            .len();
+
+        raw[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+        raw[2..4].copy_from_slice(&C::VERSION.to_le_bytes());
+        raw[4..6].copy_from_slice(&(len as u16).to_le_bytes());
+        let crc = crc16_update(crc16(&raw[..6]), &raw[HEADER_SIZE..HEADER_SIZE + len]);
+        raw[6..8].copy_from_slice(&crc.to_le_bytes());
+        let raw = &raw[..HEADER_SIZE + len];
+
+        for copy in 0..2 {
+            self.write_copy(copy, raw)?;
+        }
+        Ok(())
+    }
+
+    fn write_copy(&mut self, copy: u32, raw: &[u8]) -> Result<(), ConfigError> {
+        let addr = self.copy_addr(copy);
+        self.flash.start_erase(addr);
+        self.flash.wait_idle();
+
+        // 一份副本最多 72 字节，只有在跨页时才需要拆成两次写入
+        let first_len = ((F::PAGE_SIZE - addr % F::PAGE_SIZE) as usize).min(raw.len());
+        self.flash.program(addr, &raw[..first_len]);
+        if first_len < raw.len() {
+            self.flash
+                .program(addr + first_len as u32, &raw[first_len..]);
+        }
+
+        let mut readback = [0u8; HEADER_SIZE + PAYLOAD_MAX];
+        self.flash.read(addr, &mut readback[..raw.len()]);
+        if &readback[..raw.len()] != raw {
+            return Err(ConfigError::Verify);
+        }
+        Ok(())
+    }
+}
+
+// cargo test -p telemetry_core --target x86_64-unknown-linux-gnu
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    const SECTOR: usize = 256;
+
+    // 用 RAM 模拟的 NOR Flash：擦除后为 0xFF，写入只能把 1 改为 0，写入不能跨页
+    struct RamFlash {
+        mem: [u8; 2 * SECTOR],
+    }
+
+    impl RamFlash {
+        fn new() -> Self {
+            Self {
+                mem: [0xFF; 2 * SECTOR],
+            }
+        }
+    }
+
+    impl LogFlash for &mut RamFlash {
+        const SECTOR_SIZE: u32 = SECTOR as u32;
+        const PAGE_SIZE: u32 = 64;
+
+        fn wait_idle(&mut self) {}
+
+        fn read(&mut self, addr: u32, buf: &mut [u8]) {
+            let addr = addr as usize;
+            buf.copy_from_slice(&self.mem[addr..addr + buf.len()]);
+        }
+
+        fn program(&mut self, addr: u32, data: &[u8]) {
+            assert_eq!(
+                addr / Self::PAGE_SIZE,
+                (addr + data.len() as u32 - 1) / Self::PAGE_SIZE
+            );
+            let addr = addr as usize;
+            for (cell, byte) in self.mem[addr..addr + data.len()].iter_mut().zip(data) {
+                *cell &= byte;
+            }
+        }
+
+        fn start_erase(&mut self, addr: u32) {
+            let addr = addr as usize / SECTOR * SECTOR;
+            self.mem[addr..addr + SECTOR].fill(0xFF);
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    struct ConfigV1 {
+        period_ms: u32,
+    }
+
+    impl Default for ConfigV1 {
+        fn default() -> Self {
+            Self { period_ms: 1000 }
+        }
+    }
+
+    impl Versioned for ConfigV1 {
+        const VERSION: u16 = 1;
+
+        fn migrate(_version: u16, _payload: &[u8]) -> Option<Self> {
+            None
+        }
+    }
+
+    // 版本 2 增加了 contrast
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    struct ConfigV2 {
+        period_ms: u32,
+        contrast: u8,
+    }
+
+    impl Default for ConfigV2 {
+        fn default() -> Self {
+            Self {
+                period_ms: 1000,
+                contrast: 40,
+            }
+        }
+    }
+
+    impl Versioned for ConfigV2 {
+        const VERSION: u16 = 2;
+
+        fn migrate(version: u16, payload: &[u8]) -> Option<Self> {
+            match version {
+                1 => postcard::from_bytes::<ConfigV1>(payload)
+                    .ok()
+                    .map(|v1| Self {
+                        period_ms: v1.period_ms,
+                        ..Self::default()
+                    }),
+                _ => None,
+            }
+        }
+    }
+
+    const SAVED: ConfigV2 = ConfigV2 {
+        period_ms: 250,
+        contrast: 70,
+    };
+
+    fn store(flash: &mut RamFlash) -> ConfigStore<&mut RamFlash, ConfigV2> {
+        ConfigStore::new(flash, 0).unwrap()
+    }
+
+    #[test]
+    fn blank_flash_gives_default() {
+        let mut flash = RamFlash::new();
+        assert_eq!(
+            store(&mut flash).load(),
+            (ConfigV2::default(), Origin::Default)
+        );
+        assert!(ConfigStore::<_, ConfigV2>::new(&mut flash, 16).is_err());
+    }
+
+    #[test]
+    fn save_then_load() {
+        let mut flash = RamFlash::new();
+        store(&mut flash).save(&SAVED).unwrap();
+        assert_eq!(store(&mut flash).load(), (SAVED, Origin::Primary));
+        // 两份副本完全相同
+        assert_eq!(flash.mem[..SECTOR], flash.mem[SECTOR..]);
+    }
+
+    // 第一份写到一半掉电，或者其中有 bit 翻转：使用第二份，并把它写回第一份
+    #[test]
+    fn falls_back_to_backup_and_repairs_primary() {
+        let mut flash = RamFlash::new();
+        store(&mut flash).save(&SAVED).unwrap();
+        flash.mem[HEADER_SIZE] ^= 0x01;
+
+        assert_eq!(store(&mut flash).load(), (SAVED, Origin::Backup));
+        assert_eq!(flash.mem[..SECTOR], flash.mem[SECTOR..]);
+        assert_eq!(store(&mut flash).load(), (SAVED, Origin::Primary));
+
+        // 第一份被擦除之后掉电，同样可以恢复
+        flash.mem[..SECTOR].fill(0xFF);
+        assert_eq!(store(&mut flash).load(), (SAVED, Origin::Backup));
+        assert_eq!(store(&mut flash).load(), (SAVED, Origin::Primary));
+    }
+
+    #[test]
+    fn both_copies_corrupt_gives_default() {
+        let mut flash = RamFlash::new();
+        store(&mut flash).save(&SAVED).unwrap();
+        flash.mem[HEADER_SIZE] ^= 0x01;
+        flash.mem[SECTOR + 6] ^= 0x01;
+        assert_eq!(
+            store(&mut flash).load(),
+            (ConfigV2::default(), Origin::Default)
+        );
+    }
+
+    // 旧固件保存的版本 1，升级之后迁移为版本 2，并立刻以版本 2 写回
+    #[test]
+    fn migrates_old_version() {
+        let mut flash = RamFlash::new();
+        ConfigStore::<_, ConfigV1>::new(&mut flash, 0)
+            .unwrap()
+            .save(&ConfigV1 { period_ms: 250 })
+            .unwrap();
+
+        let migrated = ConfigV2 {
+            period_ms: 250,
+            ..ConfigV2::default()
+        };
+        assert_eq!(store(&mut flash).load(), (migrated, Origin::Migrated(1)));
+        assert_eq!(flash.mem[2..4], 2u16.to_le_bytes());
+        assert_eq!(flash.mem[SECTOR + 2..SECTOR + 4], 2u16.to_le_bytes());
+        assert_eq!(store(&mut flash).load(), (migrated, Origin::Primary));
+
+        // 降级后不认识新版本，使用默认值，而不是把新版本的 payload 当作旧版本解析
+        assert_eq!(
+            ConfigStore::<_, ConfigV1>::new(&mut flash, 0)
+                .unwrap()
+                .load(),
+            (ConfigV1::default(), Origin::Default)
+        );
+    }
+}
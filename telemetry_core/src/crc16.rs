@@ -0,0 +1,56 @@
+//! CRC-16/CCITT-FALSE
+//!
+//! 多项式 0x1021，初始值 0xFFFF，不反转输入输出，结果不异或
+//!
+//! STM32F413 上的硬件 CRC 单元（见 s15_crc）只能计算 CRC-32，对于几十个字节的帧来说，4 个字节的校验值有些浪费，
+//! 因此这里用软件逐 bit 计算 CRC-16，帧很短，这点计算量可以忽略
+
+pub const fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0xFFFF, data)
+}
+
+// 允许分段计算，把上一段的结果作为下一段的 crc 传入即可
+pub const fn crc16_update(mut crc: u16, data: &[u8]) -> u16 {
+    let mut index = 0;
+    while index < data.len() {
+        crc ^= (data[index] as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        index += 1;
+    }
+    crc
+}
+
+// 标准的校验值，"123456789" 的 CRC-16/CCITT-FALSE 应为 0x29B1
+const _: () = assert!(crc16(b"123456789") == 0x29B1);
+
+// cargo test -p telemetry_core --target x86_64-unknown-linux-gnu
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_update_matches_one_shot() {
+        let data = b"123456789";
+        for split in 0..=data.len() {
+            let (head, tail) = data.split_at(split);
+            assert_eq!(crc16_update(crc16(head), tail), 0x29B1);
+        }
+    }
+
+    // 把 crc16 以大端序接在数据后面，再算一次 CRC 结果为 0，这是 CRC 的基本性质
+    #[test]
+    fn residue_is_zero() {
+        let data = b"telemetry";
+        let crc = crc16(data);
+        assert_eq!(crc16_update(crc, &crc.to_be_bytes()), 0);
+        assert_ne!(crc16(b"telemetrz"), crc);
+    }
+}
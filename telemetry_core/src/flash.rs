@@ -0,0 +1,21 @@
+//! 数据记录器与配置所用的存储介质
+//!
+//! s21 的 utils::datalog、utils::settings 与 utils::config 都只依赖这个 trait，
+//! MCU 端由 QSPI Flash 或芯片内部的 Flash 实现，Host 端的测试可以用一块 RAM 代替
+
+// 存储介质，需要能按页写入，按扇区擦除，擦除后为 0xFF
+pub trait LogFlash {
+    const SECTOR_SIZE: u32;
+    const PAGE_SIZE: u32;
+
+    // 等待上一次写入或擦除完成
+    fn wait_idle(&mut self);
+
+    fn read(&mut self, addr: u32, buf: &mut [u8]);
+
+    // 写入不会跨页
+    fn program(&mut self, addr: u32, data: &[u8]);
+
+    // 只发出擦除命令，不必等待擦除完成
+    fn start_erase(&mut self, addr: u32);
+}
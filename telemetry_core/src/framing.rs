@@ -0,0 +1,440 @@
+//! 二进制帧协议
+//!
+//! 一帧数据在线路上的样子是
+//!
+//! COBS( tag | payload | crc16 ) | 0x00
+//!
+//! tag：1 个字节，表示 payload 的类型，见 MsgTag
+//! payload：由 postcard 序列化的消息
+//! crc16：对 tag 和 payload 计算的 CRC-16/CCITT-FALSE，小端序
+//! 0x00：帧分隔符，由于 COBS 编码后的数据中不会出现 0x00，因此看到 0x00 就说明一帧结束了
+//!
+//! 接收端只需要把收到的字节逐个交给 FrameDecoder，它会在每次收到完整、校验正确的帧时返回这一帧
+//! 对于有干扰的线路（比如没有接好的 UART），出错的帧会被整个丢弃，并从下一个 0x00 之后重新开始接收
+//!
+//! 可选的 payload 加密：
+//!
+//! COBS( tag | SEALED_FLAG | nonce | AES-128-CTR(payload) | crc16 ) | 0x00
+//!
+//! nonce：8 个字节，大端序，与块序号组成 CTR 的计数器（见 crypto_core::aes::counter_block）
+//! 同一个密钥下 nonce 不能重复，由发送端保证，比如用上电时从 RNG 取得的随机数加上一个递增的序号
+//! crc16 是对密文计算的，接收端先校验再解密，解密之后的 payload 原地替换掉密文
+//!
+//! 注意：加密只能防止线路上的数据被读懂，不能防止被篡改或者重放，攻击者仍然可以修改密文再重新计算 crc16
+//! 需要完整性时应该在 payload 之外再加上 MAC（见 crypto_core::hmac）
+
+use crypto_core::aes::{counter_block, Aes128, KEY_LEN};
+use serde::{Deserialize, Serialize};
+
+use crate::{cobs, crc16::crc16};
+
+// 未编码的帧（tag + payload + crc16）的最大长度
+pub const MAX_FRAME_LEN: usize = 128;
+// 编码后的帧，加上末尾的 0x00 的最大长度
+pub const MAX_ENCODED_LEN: usize = cobs::max_encoded_len(MAX_FRAME_LEN) + 1;
+
+// tag 的最高位，表示 payload 是加密的，MsgTag 中的值都不会用到这一位
+pub const SEALED_FLAG: u8 = 0x80;
+pub const NONCE_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MsgTag {
+    // MCU -> Host：传感器读数等周期性数据
+    Telemetry = 0x01,
+    // MCU -> Host：日志文本
+    Log = 0x02,
+    // Host -> MCU：命令
+    Command = 0x10,
+    // MCU -> Host：对命令的应答
+    Response = 0x11,
+}
+
+impl MsgTag {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(Self::Telemetry),
+            0x02 => Some(Self::Log),
+            0x10 => Some(Self::Command),
+            0x11 => Some(Self::Response),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    // 消息序列化后超出了 MAX_FRAME_LEN
+    TooLong,
+    // 序列化或反序列化失败
+    Serde,
+    // COBS 解码失败
+    Cobs,
+    // 帧太短，连 tag 和 crc16 都放不下
+    Truncated,
+    // 校验失败
+    Crc,
+    // 未知的 tag
+    UnknownTag(u8),
+    // 收到了加密的帧，但接收端没有设置密钥
+    NoKey,
+}
+
+// 将一条消息编码为一个完整的帧（包括末尾的 0x00），返回 out 中实际使用的部分
+pub fn encode_frame<'a, T: Serialize>(
+    tag: MsgTag,
+    msg: &T,
+    out: &'a mut [u8; MAX_ENCODED_LEN],
+) -> Result<&'a [u8], FrameError> {
+    encode(tag, msg, None, out)
+}
+
+// 与 encode_frame 相同，但 payload 用 cipher 加密，nonce 在同一个密钥下不能重复
+pub fn encode_sealed_frame<'a, T: Serialize>(
+    tag: MsgTag,
+    msg: &T,
+    cipher: &Aes128,
+    nonce: u64,
+    out: &'a mut [u8; MAX_ENCODED_LEN],
+) -> Result<&'a [u8], FrameError> {
+    encode(tag, msg, Some((cipher, nonce)), out)
+}
+
+fn encode<'a, T: Serialize>(
+    tag: MsgTag,
+    msg: &T,
+    seal: Option<(&Aes128, u64)>,
+    out: &'a mut [u8; MAX_ENCODED_LEN],
+) -> Result<&'a [u8], FrameError> {
+    let mut raw = [0u8; MAX_FRAME_LEN];
+    raw[0] = tag as u8;
+
+    let mut payload_index = 1;
+    if let Some((_, nonce)) = seal {
+        raw[0] |= SEALED_FLAG;
+        raw[1..1 + NONCE_LEN].copy_from_slice(&nonce.to_be_bytes());
+        payload_index += NONCE_LEN;
+    }
+
+    // 为末尾的 crc16 预留 2 个字节
+    let payload_len = postcard::to_slice(msg, &mut raw[payload_index..MAX_FRAME_LEN - 2])
+        .map_err(|_| FrameError::TooLong)?
+        .len();
+
+    let crc_index = payload_index + payload_len;
+    if let Some((cipher, nonce)) = seal {
+        cipher.apply_ctr(&counter_block(nonce), &mut raw[payload_index..crc_index]);
+    }
+
+    let crc = crc16(&raw[..crc_index]);
+    raw[crc_index..crc_index + 2].copy_from_slice(&crc.to_le_bytes());
+
+    let encoded_len = cobs::encode(&raw[..crc_index + 2], out).ok_or(FrameError::TooLong)?;
+    out[encoded_len] = 0x00;
+
+    Ok(&out[..=encoded_len])
+}
+
+// 一个校验通过的帧，payload 借用自 FrameDecoder 的内部缓冲
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'a> {
+    pub tag: MsgTag,
+    // payload 在线路上是否是加密的，设置了密钥的接收端可以借此拒绝明文的命令
+    pub sealed: bool,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    // 反序列化出的消息可以直接借用 payload 中的数据，比如 &str
+    pub fn parse<T: Deserialize<'a>>(&self) -> Result<T, FrameError> {
+        postcard::from_bytes(self.payload).map_err(|_| FrameError::Serde)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecoderState {
+    // 线路上出现过错误（比如 UART 的溢出、帧错误），我们不知道现在是不是处在一帧的中间，需要等待一个 0x00
+    Hunting,
+    // 正在接收一帧
+    Receiving,
+    // 当前帧已经超长，丢弃剩下的字节直到 0x00
+    Discarding,
+}
+
+pub struct FrameDecoder {
+    state: DecoderState,
+    buf: [u8; MAX_ENCODED_LEN],
+    len: usize,
+    // 统计出错的帧数，方便判断线路的质量
+    error_cnt: u32,
+    // 用来解密加密的帧，没有设置时，加密的帧会被当作错误丢弃
+    cipher: Option<Aes128>,
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            // 刚上电时缓冲区是空的，直接当作一帧的开头即可
+            // 就算上电时正好处在一帧的中间，这一帧也会因为校验失败而被丢弃，并在下一个 0x00 之后自然同步
+            state: DecoderState::Receiving,
+            buf: [0; MAX_ENCODED_LEN],
+            len: 0,
+            error_cnt: 0,
+            cipher: None,
+        }
+    }
+
+    pub fn error_cnt(&self) -> u32 {
+        self.error_cnt
+    }
+
+    // 设置或者清除解密用的密钥，明文的帧不受影响
+    pub fn set_key(&mut self, key: Option<&[u8; KEY_LEN]>) {
+        self.cipher = key.map(Aes128::new);
+    }
+
+    // 底层的字节流出错时调用，丢弃当前正在接收的帧，等待下一个 0x00
+    pub fn resync(&mut self) {
+        if self.state == DecoderState::Receiving && self.len > 0 {
+            self.error_cnt += 1;
+        }
+        self.state = DecoderState::Hunting;
+        self.len = 0;
+    }
+
+    // 送入一个字节
+    // 返回 None 表示帧还没有结束，返回 Some 表示一帧结束了，结果可能是一个完整的帧，也可能是错误
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame<'_>, FrameError>> {
+        match (self.state, byte) {
+            (DecoderState::Hunting, 0x00) => {
+                self.state = DecoderState::Receiving;
+                self.len = 0;
+                None
+            }
+            (DecoderState::Hunting, _) => None,
+            (DecoderState::Discarding, 0x00) => {
+                self.state = DecoderState::Receiving;
+                self.len = 0;
+                self.error_cnt += 1;
+                Some(Err(FrameError::TooLong))
+            }
+            (DecoderState::Discarding, _) => None,
+            // 连续的 0x00 是空帧，直接忽略，发送端可以借此主动触发接收端的重新同步
+            (DecoderState::Receiving, 0x00) if self.len == 0 => None,
+            (DecoderState::Receiving, 0x00) => {
+                let encoded_len = self.len;
+                self.len = 0;
+                let result = Self::check(&mut self.buf[..encoded_len], self.cipher.as_ref());
+                if result.is_err() {
+                    self.error_cnt += 1;
+                }
+                Some(result)
+            }
+            (DecoderState::Receiving, _) => {
+                if self.len == self.buf.len() {
+                    self.state = DecoderState::Discarding;
+                } else {
+                    self.buf[self.len] = byte;
+                    self.len += 1;
+                }
+                None
+            }
+        }
+    }
+
+    fn check<'a>(buf: &'a mut [u8], cipher: Option<&Aes128>) -> Result<Frame<'a>, FrameError> {
+        let raw_len = cobs::decode_in_place(buf).ok_or(FrameError::Cobs)?;
+        if raw_len < 3 {
+            return Err(FrameError::Truncated);
+        }
+
+        let crc_index = raw_len - 2;
+        let expected = u16::from_le_bytes([buf[crc_index], buf[crc_index + 1]]);
+        if crc16(&buf[..crc_index]) != expected {
+            return Err(FrameError::Crc);
+        }
+
+        let sealed = buf[0] & SEALED_FLAG != 0;
+        let tag_byte = buf[0] & !SEALED_FLAG;
+        let tag = MsgTag::from_u8(tag_byte).ok_or(FrameError::UnknownTag(tag_byte))?;
+
+        let mut payload_index = 1;
+        if sealed {
+            let cipher = cipher.ok_or(FrameError::NoKey)?;
+            if crc_index < 1 + NONCE_LEN {
+                return Err(FrameError::Truncated);
+            }
+            let nonce = u64::from_be_bytes(buf[1..1 + NONCE_LEN].try_into().unwrap());
+            payload_index += NONCE_LEN;
+            cipher.apply_ctr(&counter_block(nonce), &mut buf[payload_index..crc_index]);
+        }
+
+        Ok(Frame {
+            tag,
+            sealed,
+            payload: &buf[payload_index..crc_index],
+        })
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// cargo test -p telemetry_core --target x86_64-unknown-linux-gnu
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Command, Telemetry};
+
+    const TELEMETRY: Telemetry = Telemetry {
+        seq: 7,
+        uptime_ms: 123_456,
+        mcu_temp_centi: -1250,
+        vdda_mv: 3300,
+    };
+
+    fn assert_telemetry(frame: Frame) {
+        assert_eq!(frame.tag, MsgTag::Telemetry);
+        let t: Telemetry = frame.parse().unwrap();
+        assert_eq!(t.seq, TELEMETRY.seq);
+        assert_eq!(t.uptime_ms, TELEMETRY.uptime_ms);
+        assert_eq!(t.mcu_temp_centi, TELEMETRY.mcu_temp_centi);
+        assert_eq!(t.vdda_mv, TELEMETRY.vdda_mv);
+    }
+
+    // 把 bytes 逐个送入，只接受最后一个字节结束一帧
+    fn push_all<'d>(decoder: &'d mut FrameDecoder, bytes: &[u8]) -> Result<Frame<'d>, FrameError> {
+        let (last, rest) = bytes.split_last().unwrap();
+        for &byte in rest {
+            assert!(decoder.push(byte).is_none());
+        }
+        decoder.push(*last).unwrap()
+    }
+
+    #[test]
+    fn frame_round_trips() {
+        let mut out = [0; MAX_ENCODED_LEN];
+        let frame = encode_frame(MsgTag::Telemetry, &TELEMETRY, &mut out).unwrap();
+        assert_eq!(frame.last(), Some(&0x00));
+        assert!(!frame[..frame.len() - 1].contains(&0x00));
+
+        let mut decoder = FrameDecoder::new();
+        let decoded = push_all(&mut decoder, frame).unwrap();
+        assert!(!decoded.sealed);
+        assert_telemetry(decoded);
+
+        let mut out = [0; MAX_ENCODED_LEN];
+        let frame = encode_frame(MsgTag::Log, &"boot ok", &mut out).unwrap();
+        let decoded = push_all(&mut decoder, frame).unwrap();
+        assert_eq!(decoded.tag, MsgTag::Log);
+        assert_eq!(decoded.parse::<&str>(), Ok("boot ok"));
+        assert_eq!(decoder.error_cnt(), 0);
+    }
+
+    #[test]
+    fn sealed_frame_round_trips() {
+        let key = [0x2B; KEY_LEN];
+        let cipher = Aes128::new(&key);
+        let mut out = [0; MAX_ENCODED_LEN];
+        let frame =
+            encode_sealed_frame(MsgTag::Telemetry, &TELEMETRY, &cipher, 42, &mut out).unwrap();
+
+        let mut decoder = FrameDecoder::new();
+        assert_eq!(
+            push_all(&mut decoder, frame).unwrap_err(),
+            FrameError::NoKey
+        );
+
+        decoder.set_key(Some(&key));
+        let decoded = push_all(&mut decoder, frame).unwrap();
+        assert!(decoded.sealed);
+        assert_telemetry(decoded);
+    }
+
+    #[test]
+    fn message_too_long_is_rejected() {
+        let mut out = [0; MAX_ENCODED_LEN];
+        let text = core::str::from_utf8(&[b'x'; MAX_FRAME_LEN]).unwrap();
+        assert_eq!(
+            encode_frame(MsgTag::Log, &text, &mut out).unwrap_err(),
+            FrameError::TooLong
+        );
+    }
+
+    // 任何一个字节出错，都应该被 crc16 发现，而且不影响下一帧
+    #[test]
+    fn corrupted_frame_fails_crc() {
+        let mut out = [0; MAX_ENCODED_LEN];
+        let frame =
+            encode_frame(MsgTag::Command, &Command::SetPeriod { ms: 500 }, &mut out).unwrap();
+        let mut decoder = FrameDecoder::new();
+
+        let mut bad = [0; MAX_ENCODED_LEN];
+        bad[..frame.len()].copy_from_slice(frame);
+        // 跳过第一个字节，它是 COBS 的长度字节，改了通常是 Cobs 错误
+        bad[2] ^= 0x10;
+        assert_eq!(
+            push_all(&mut decoder, &bad[..frame.len()]).unwrap_err(),
+            FrameError::Crc
+        );
+
+        let decoded = push_all(&mut decoder, frame).unwrap();
+        assert_eq!(decoded.tag, MsgTag::Command);
+        assert!(matches!(
+            decoded.parse(),
+            Ok(Command::SetPeriod { ms: 500 })
+        ));
+        assert_eq!(decoder.error_cnt(), 1);
+    }
+
+    // 上电时正好处在一帧的中间：前面的半帧被当作一个错误的帧丢弃，之后的帧正常接收
+    #[test]
+    fn resyncs_after_garbage() {
+        let mut out = [0; MAX_ENCODED_LEN];
+        let frame = encode_frame(MsgTag::Telemetry, &TELEMETRY, &mut out).unwrap();
+        let mut decoder = FrameDecoder::new();
+
+        for &byte in &frame[frame.len() / 2..frame.len() - 1] {
+            assert!(decoder.push(byte).is_none());
+        }
+        assert!(decoder.push(0x00).unwrap().is_err());
+        assert_telemetry(push_all(&mut decoder, frame).unwrap());
+        assert_eq!(decoder.error_cnt(), 1);
+
+        // 线路出错后调用 resync，在下一个 0x00 之前的字节都被忽略，连续的 0x00 也不算错误
+        for &byte in &frame[..3] {
+            assert!(decoder.push(byte).is_none());
+        }
+        decoder.resync();
+        for &byte in &[0x12, 0x34, 0x56] {
+            assert!(decoder.push(byte).is_none());
+        }
+        assert!(decoder.push(0x00).is_none());
+        assert!(decoder.push(0x00).is_none());
+        assert_telemetry(push_all(&mut decoder, frame).unwrap());
+        assert_eq!(decoder.error_cnt(), 2);
+    }
+
+    // 没有 0x00 的长串字节不会让缓冲区溢出，等到 0x00 时报告一次 TooLong
+    #[test]
+    fn overlong_frame_is_discarded() {
+        let mut decoder = FrameDecoder::new();
+        for _ in 0..MAX_ENCODED_LEN * 2 {
+            assert!(decoder.push(0x55).is_none());
+        }
+        assert_eq!(
+            decoder.push(0x00).unwrap().unwrap_err(),
+            FrameError::TooLong
+        );
+
+        let mut out = [0; MAX_ENCODED_LEN];
+        let frame = encode_frame(MsgTag::Telemetry, &TELEMETRY, &mut out).unwrap();
+        assert_telemetry(push_all(&mut decoder, frame).unwrap());
+        assert_eq!(decoder.error_cnt(), 1);
+    }
+}
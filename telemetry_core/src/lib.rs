@@ -0,0 +1,27 @@
+//! MCU 与 Host 共用的数据格式
+//!
+//! s22 的遥测帧与 s21 的数据记录，原本都定义在各自的 utils 中，Host 端想要解析它们，就只能照着代码再写一遍，
+//! 字段顺序或者长度一旦改了，两边就悄悄地对不上了
+//!
+//! 因此把这些格式单独放在这个 no_std 的 crate 里：
+//!
+//! - MCU 端（s21、s22）通过 path 依赖直接使用
+//! - Host 端（telemetry_host）也使用同一份定义，把帧与 Flash 的转储文件解析为结构体或 JSON
+//!
+//! 模块：
+//!
+//! - cobs / crc16：帧的编码与校验
+//! - framing：COBS( tag | payload | crc16 ) | 0x00 的帧格式，以及逐字节接收的 FrameDecoder，payload 可以选择用 AES-128-CTR 加密
+//! - message：帧中承载的消息
+//! - record：数据记录器在 Flash 中的 32 字节定长记录
+//! - flash / config：记录与配置所用的存储介质，以及带版本号、存两份的配置的存储格式
+
+#![no_std]
+
+pub mod cobs;
+pub mod config;
+pub mod crc16;
+pub mod flash;
+pub mod framing;
+pub mod message;
+pub mod record;
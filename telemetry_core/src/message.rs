@@ -0,0 +1,54 @@
+//! 帧中承载的消息
+//!
+//! 这些结构体只需要 derive Serialize/Deserialize，具体的字节布局由 postcard 决定：
+//! 整数使用变长编码（varint），小的数字只占 1 个字节；枚举先写一个 varint 的变体编号，再写变体的内容
+//!
+//! MsgTag::Log 没有专门的结构体，payload 就是一个 postcard 序列化的 &str
+//!
+//! 注意：调整字段或变体的顺序会改变编码结果，MCU 与 Host 端（telemetry_host）都直接使用这里的定义，
+//! 修改之后两边都要重新编译
+
+use serde::{Deserialize, Serialize};
+
+// MsgTag::Telemetry
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Telemetry {
+    pub seq: u32,
+    pub uptime_ms: u32,
+    // 单位为 0.01 ℃，用整数传输可以省下不少字节
+    pub mcu_temp_centi: i16,
+    // 单位为 mV
+    pub vdda_mv: u16,
+}
+
+// MsgTag::Command
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    // 原样返回参数
+    Ping(u32),
+    // 修改遥测的发送间隔
+    SetPeriod { ms: u32 },
+    // 读取芯片的 96 bit 唯一 ID
+    ReadUid,
+}
+
+// MsgTag::Response
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Response {
+    Pong(u32),
+    Ok,
+    Uid([u8; 12]),
+    Err(ErrorCode),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ErrorCode {
+    // 帧是完整的，但内容无法解析为 Command
+    BadCommand,
+    // 参数超出范围
+    BadArgument,
+}
@@ -0,0 +1,126 @@
+//! 数据记录器（见 s21 的 utils::datalog）写入 Flash 的记录格式
+//!
+//! 每个读数是一条 32 字节的定长记录：
+//!
+//! | 偏移 | 长度 | 内容                                           |
+//! | 0    | 4    | 序号 seq，单调递增，重启之后接着上一次继续      |
+//! | 4    | 4    | 时间戳，开机以来的毫秒数                        |
+//! | 8    | 4    | 数值，f32                                       |
+//! | 12   | 8    | 传感器名，不足的部分补 0                        |
+//! | 20   | 6    | 物理量名                                        |
+//! | 26   | 4    | 单位                                            |
+//! | 30   | 2    | 前 30 个字节的 CRC-16/CCITT-FALSE（见 crc16）   |
+//!
+//! 所有多字节的字段均为小端序；时间戳在每次重启后从 0 开始，跨越重启时，以 seq 判断先后
+//!
+//! Flash 擦除后为 0xFF，因此全为 0xFF 的位置是空的，CRC 不对的位置是写到一半或擦除到一半时掉电留下的
+
+use crate::crc16::crc16;
+
+pub const RECORD_SIZE: usize = 32;
+
+pub const SENSOR_LEN: usize = 8;
+pub const QUANTITY_LEN: usize = 6;
+pub const UNIT_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Record {
+    pub seq: u32,
+    pub time_ms: u32,
+    pub value: f32,
+    sensor: [u8; SENSOR_LEN],
+    quantity: [u8; QUANTITY_LEN],
+    unit: [u8; UNIT_LEN],
+}
+
+// 过长的名字会被截断
+pub fn pack<const N: usize>(text: &str) -> [u8; N] {
+    let mut field = [0u8; N];
+    let len = text.len().min(N);
+    field[..len].copy_from_slice(&text.as_bytes()[..len]);
+    field
+}
+
+pub fn unpack(field: &[u8]) -> &str {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).unwrap_or("?")
+}
+
+impl Record {
+    pub fn new(
+        seq: u32,
+        time_ms: u32,
+        value: f32,
+        sensor: &str,
+        quantity: &str,
+        unit: &str,
+    ) -> Self {
+        Self {
+            seq,
+            time_ms,
+            value,
+            sensor: pack(sensor),
+            quantity: pack(quantity),
+            unit: pack(unit),
+        }
+    }
+
+    pub fn sensor(&self) -> &str {
+        unpack(&self.sensor)
+    }
+
+    pub fn quantity(&self) -> &str {
+        unpack(&self.quantity)
+    }
+
+    pub fn unit(&self) -> &str {
+        unpack(&self.unit)
+    }
+
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut raw = [0u8; RECORD_SIZE];
+        raw[0..4].copy_from_slice(&self.seq.to_le_bytes());
+        raw[4..8].copy_from_slice(&self.time_ms.to_le_bytes());
+        raw[8..12].copy_from_slice(&self.value.to_le_bytes());
+        raw[12..20].copy_from_slice(&self.sensor);
+        raw[20..26].copy_from_slice(&self.quantity);
+        raw[26..30].copy_from_slice(&self.unit);
+        let crc = crc16(&raw[..30]);
+        raw[30..32].copy_from_slice(&crc.to_le_bytes());
+        raw
+    }
+
+    pub fn decode(raw: &[u8; RECORD_SIZE]) -> Slot {
+        if raw.iter().all(|&b| b == 0xFF) {
+            return Slot::Erased;
+        }
+        if crc16(&raw[..30]) != u16::from_le_bytes([raw[30], raw[31]]) {
+            return Slot::Corrupt;
+        }
+
+        let word = |i: usize| [raw[i], raw[i + 1], raw[i + 2], raw[i + 3]];
+        let mut record = Record {
+            seq: u32::from_le_bytes(word(0)),
+            time_ms: u32::from_le_bytes(word(4)),
+            value: f32::from_le_bytes(word(8)),
+            sensor: [0; SENSOR_LEN],
+            quantity: [0; QUANTITY_LEN],
+            unit: [0; UNIT_LEN],
+        };
+        record.sensor.copy_from_slice(&raw[12..20]);
+        record.quantity.copy_from_slice(&raw[20..26]);
+        record.unit.copy_from_slice(&raw[26..30]);
+        Slot::Valid(record)
+    }
+}
+
+// Flash 中一个记录位置的状态
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Slot {
+    Erased,
+    // 写到一半掉电，或者擦除到一半掉电
+    Corrupt,
+    Valid(Record),
+}
@@ -0,0 +1,188 @@
+//! 解析数据记录器（s21 的 utils::datalog）保存的记录
+//!
+//! 两种来源：
+//!
+//! - parse_image：Flash 日志区域的原始镜像，比如用编程器读出的 W25Q32 从 1 MB 处开始的 256 KB，
+//!   按 32 字节一条记录逐个检查，与 MCU 端 mount 时的做法相同
+//! - parse_export：s21c03 的 dump 命令导出的文本，每行为 L,<seq>,<毫秒>,<传感器名>,<物理量名>,<数值>,<单位>，
+//!   最后一行为 END,<记录数>
+//!
+//! 两者得到的都是按 seq 从旧到新排列的 Entry
+
+use std::fmt;
+
+use serde::Serialize;
+use telemetry_core::record::{Record, Slot, RECORD_SIZE};
+
+// 一条有效的记录，字符串字段已经去掉了末尾补的 0
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Entry {
+    pub seq: u32,
+    pub time_ms: u32,
+    pub sensor: String,
+    pub quantity: String,
+    pub value: f32,
+    pub unit: String,
+}
+
+impl From<&Record> for Entry {
+    fn from(record: &Record) -> Self {
+        Self {
+            seq: record.seq,
+            time_ms: record.time_ms,
+            sensor: record.sensor().to_owned(),
+            quantity: record.quantity().to_owned(),
+            value: record.value,
+            unit: record.unit().to_owned(),
+        }
+    }
+}
+
+// 一个 Flash 镜像的解析结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Image {
+    pub entries: Vec<Entry>,
+    // 空的位置（全为 0xFF）
+    pub erased: usize,
+    // CRC 不对的位置，写入或擦除时掉电留下的
+    pub corrupt: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DumpError {
+    // 镜像的长度不是记录长度的整数倍，多半是读取的范围不对
+    ImageLength(usize),
+    // 导出文本中无法解析的一行，line 从 1 开始
+    BadLine { line: usize, reason: &'static str },
+    // 没有 END 行，导出被中断了
+    MissingEnd,
+    // END 行中的记录数与实际的行数不一致
+    CountMismatch { expected: usize, actual: usize },
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DumpError::ImageLength(len) => write!(
+                f,
+                "image length {} is not a multiple of {} bytes",
+                len, RECORD_SIZE
+            ),
+            DumpError::BadLine { line, reason } => write!(f, "line {}: {}", line, reason),
+            DumpError::MissingEnd => write!(f, "export is truncated, END line is missing"),
+            DumpError::CountMismatch { expected, actual } => write!(
+                f,
+                "END reports {} records, but {} were exported",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DumpError {}
+
+pub fn parse_image(bytes: &[u8]) -> Result<Image, DumpError> {
+    if !bytes.len().is_multiple_of(RECORD_SIZE) {
+        return Err(DumpError::ImageLength(bytes.len()));
+    }
+
+    let mut image = Image::default();
+    for chunk in bytes.chunks_exact(RECORD_SIZE) {
+        match Record::decode(chunk.try_into().unwrap()) {
+            Slot::Valid(record) => image.entries.push(Entry::from(&record)),
+            Slot::Erased => image.erased += 1,
+            Slot::Corrupt => image.corrupt += 1,
+        }
+    }
+    // 环形缓冲区中，最旧的记录不一定在镜像的开头
+    image.entries.sort_by_key(|entry| entry.seq);
+    Ok(image)
+}
+
+pub fn parse_export(text: &str) -> Result<Vec<Entry>, DumpError> {
+    let mut entries = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let bad = |reason| DumpError::BadLine {
+            line: line_no,
+            reason,
+        };
+
+        let line = line.trim();
+        // 串口上可能还混有命令的回显与其它的输出，只看 L 与 END 开头的行
+        if let Some(count) = line.strip_prefix("END,") {
+            let expected = count.parse().map_err(|_| bad("bad record count"))?;
+            if expected != entries.len() {
+                return Err(DumpError::CountMismatch {
+                    expected,
+                    actual: entries.len(),
+                });
+            }
+            return Ok(entries);
+        }
+        let Some(fields) = line.strip_prefix("L,") else {
+            continue;
+        };
+
+        let fields: Vec<&str> = fields.split(',').collect();
+        let [seq, time_ms, sensor, quantity, value, unit] = fields[..] else {
+            return Err(bad("expected 6 fields after L"));
+        };
+        entries.push(Entry {
+            seq: seq.parse().map_err(|_| bad("bad seq"))?,
+            time_ms: time_ms.parse().map_err(|_| bad("bad timestamp"))?,
+            sensor: sensor.to_owned(),
+            quantity: quantity.to_owned(),
+            value: value.parse().map_err(|_| bad("bad value"))?,
+            unit: unit.to_owned(),
+        });
+    }
+
+    Err(DumpError::MissingEnd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(seq: u32) -> Record {
+        Record::new(seq, seq * 1000, 21.5, "sht31", "temp", "C")
+    }
+
+    // 镜像中混有空位与写坏的记录，而且环形缓冲区已经绕回，最旧的记录不在开头
+    #[test]
+    fn image_round_trips() {
+        let mut corrupt = record(9).encode();
+        corrupt[8] ^= 0x01;
+
+        let mut bytes = Vec::new();
+        bytes.extend(record(3).encode());
+        bytes.extend([0xFF; RECORD_SIZE]);
+        bytes.extend(corrupt);
+        bytes.extend(record(2).encode());
+
+        let image = parse_image(&bytes).unwrap();
+        assert_eq!(image.erased, 1);
+        assert_eq!(image.corrupt, 1);
+        assert_eq!(
+            image.entries,
+            [Entry::from(&record(2)), Entry::from(&record(3))]
+        );
+        assert_eq!(image.entries[0].sensor, "sht31");
+
+        assert_eq!(
+            parse_image(&bytes[1..]).unwrap_err(),
+            DumpError::ImageLength(bytes.len() - 1)
+        );
+    }
+
+    #[test]
+    fn export_requires_end_line() {
+        let text = "> dump\r\nL,2,2000,sht31,temp,21.5,C\r\nL,3,3000,sht31,temp,21.5,C\r\n";
+        assert_eq!(parse_export(text).unwrap_err(), DumpError::MissingEnd);
+
+        let entries = parse_export(&format!("{}END,2\r\n", text)).unwrap();
+        assert_eq!(entries, [Entry::from(&record(2)), Entry::from(&record(3))]);
+    }
+}
@@ -0,0 +1,289 @@
+//! 把串口上收到的字节流解析为消息
+//!
+//! 与 MCU 端一样，字节逐个交给 telemetry_core 的 FrameDecoder，得到校验通过的帧之后，再按 tag 反序列化为对应的消息
+//! 字节流可以分多次送入，比如每次从串口读到多少就送入多少，一帧跨越两次送入也没有关系
+
+use std::fmt;
+
+use crypto_core::aes::KEY_LEN;
+use serde::Serialize;
+use telemetry_core::{
+    framing::{Frame, FrameDecoder, FrameError, MsgTag},
+    message::{Command, Response, Telemetry},
+};
+
+// 一帧解析出的消息，转为 JSON 时以 "type" 字段区分类型，比如
+// {"type":"telemetry","seq":1,"uptime_ms":1000,"mcu_temp_centi":3012,"vdda_mv":3300}
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Telemetry(Telemetry),
+    Log { text: String },
+    Command { command: Command },
+    Response { response: Response },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    // 帧本身有问题：COBS、CRC、长度、未知的 tag
+    Frame(FrameError),
+    // 帧是完整的，但 payload 不能反序列化为 tag 对应的消息，通常是 MCU 与 Host 使用的 telemetry_core 版本不同
+    Payload(MsgTag),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Frame(FrameError::UnknownTag(tag)) => {
+                write!(f, "unknown tag {:#04x}", tag)
+            }
+            DecodeError::Frame(FrameError::NoKey) => write!(f, "sealed frame, use --key"),
+            DecodeError::Frame(e) => write!(f, "bad frame: {:?}", e),
+            DecodeError::Payload(tag) => write!(f, "payload does not match tag {:?}", tag),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// 按 tag 反序列化一个校验通过的帧
+pub fn parse_frame(frame: &Frame) -> Result<Message, DecodeError> {
+    let payload_error = |_| DecodeError::Payload(frame.tag);
+    let message = match frame.tag {
+        MsgTag::Telemetry => Message::Telemetry(frame.parse().map_err(payload_error)?),
+        MsgTag::Log => Message::Log {
+            text: frame.parse::<&str>().map_err(payload_error)?.to_owned(),
+        },
+        MsgTag::Command => Message::Command {
+            command: frame.parse().map_err(payload_error)?,
+        },
+        MsgTag::Response => Message::Response {
+            response: frame.parse().map_err(payload_error)?,
+        },
+    };
+    Ok(message)
+}
+
+#[derive(Default)]
+pub struct StreamDecoder {
+    decoder: FrameDecoder,
+    // 成功解析的消息数
+    message_cnt: u32,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 用 key 解密加密的帧，明文的帧照常解析
+    pub fn with_key(key: &[u8; KEY_LEN]) -> Self {
+        let mut decoder = Self::new();
+        decoder.decoder.set_key(Some(key));
+        decoder
+    }
+
+    // 送入一段字节，每结束一帧调用一次 on_frame
+    pub fn feed(&mut self, bytes: &[u8], mut on_frame: impl FnMut(Result<Message, DecodeError>)) {
+        for &byte in bytes {
+            let Some(result) = self.decoder.push(byte) else {
+                continue;
+            };
+            let result = result
+                .map_err(DecodeError::Frame)
+                .and_then(|frame| parse_frame(&frame));
+            if result.is_ok() {
+                self.message_cnt += 1;
+            }
+            on_frame(result);
+        }
+    }
+
+    // 一次性解析一段完整的数据，比如保存下来的串口抓包文件
+    pub fn decode_all(bytes: &[u8]) -> Vec<Result<Message, DecodeError>> {
+        let mut decoder = Self::new();
+        let mut results = Vec::new();
+        decoder.feed(bytes, |result| results.push(result));
+        results
+    }
+
+    // 串口出错（溢出、帧错误等）时调用，丢弃当前的帧
+    pub fn resync(&mut self) {
+        self.decoder.resync();
+    }
+
+    pub fn message_cnt(&self) -> u32 {
+        self.message_cnt
+    }
+
+    // 出错的帧数，包括 FrameDecoder 丢弃的帧，不包括 payload 无法解析的帧
+    pub fn error_cnt(&self) -> u32 {
+        self.decoder.error_cnt()
+    }
+}
+
+// MCU 端用 telemetry_core 编码，这里用 StreamDecoder 解码，两边对格式的理解必须一致
+// cargo test -p telemetry_host --target x86_64-unknown-linux-gnu
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telemetry_core::{
+        cobs,
+        crc16::crc16,
+        framing::{encode_frame, encode_sealed_frame, MAX_ENCODED_LEN},
+    };
+
+    const TELEMETRY: Telemetry = Telemetry {
+        seq: 7,
+        uptime_ms: 123_456,
+        mcu_temp_centi: -1250,
+        vdda_mv: 3300,
+    };
+
+    fn encode<T: serde::Serialize>(tag: MsgTag, msg: &T) -> Vec<u8> {
+        let mut out = [0; MAX_ENCODED_LEN];
+        encode_frame(tag, msg, &mut out).unwrap().to_vec()
+    }
+
+    // 不经过 encode_frame，直接拼出 tag | payload | crc16 再做 COBS 编码，用来构造错误的帧
+    fn encode_raw(raw: &[u8]) -> Vec<u8> {
+        let mut out = vec![0; cobs::max_encoded_len(raw.len()) + 1];
+        let len = cobs::encode(raw, &mut out).unwrap();
+        out.truncate(len);
+        out.push(0x00);
+        out
+    }
+
+    fn assert_telemetry(result: &Result<Message, DecodeError>) {
+        match result {
+            Ok(Message::Telemetry(t)) => {
+                assert_eq!(t.seq, TELEMETRY.seq);
+                assert_eq!(t.uptime_ms, TELEMETRY.uptime_ms);
+                assert_eq!(t.mcu_temp_centi, TELEMETRY.mcu_temp_centi);
+                assert_eq!(t.vdda_mv, TELEMETRY.vdda_mv);
+            }
+            other => panic!("expected telemetry, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        let mut stream = encode(MsgTag::Telemetry, &TELEMETRY);
+        stream.extend(encode(MsgTag::Log, &"boot ok"));
+        stream.extend(encode(MsgTag::Command, &Command::SetPeriod { ms: 500 }));
+        stream.extend(encode(MsgTag::Response, &Response::Uid([0xA5; 12])));
+
+        let results = StreamDecoder::decode_all(&stream);
+        assert_eq!(results.len(), 4);
+        assert_telemetry(&results[0]);
+        assert!(matches!(&results[1], Ok(Message::Log { text }) if text == "boot ok"));
+        assert!(matches!(
+            results[2],
+            Ok(Message::Command {
+                command: Command::SetPeriod { ms: 500 }
+            })
+        ));
+        assert!(matches!(
+            results[3],
+            Ok(Message::Response {
+                response: Response::Uid(uid)
+            }) if uid == [0xA5; 12]
+        ));
+    }
+
+    // 串口每次读到的字节数是任意的，逐字节送入的结果应该与一次送入相同
+    #[test]
+    fn frame_split_across_feeds() {
+        let frame = encode(MsgTag::Telemetry, &TELEMETRY);
+        let mut decoder = StreamDecoder::new();
+        let mut results = Vec::new();
+        for byte in &frame {
+            decoder.feed(core::slice::from_ref(byte), |result| results.push(result));
+        }
+        assert_eq!(results.len(), 1);
+        assert_telemetry(&results[0]);
+        assert_eq!(decoder.message_cnt(), 1);
+    }
+
+    #[test]
+    fn sealed_frame_round_trips() {
+        let key = [0x2B; 16];
+        let mut out = [0; MAX_ENCODED_LEN];
+        let cipher = crypto_core::aes::Aes128::new(&key);
+        let frame = encode_sealed_frame(MsgTag::Telemetry, &TELEMETRY, &cipher, 42, &mut out)
+            .unwrap()
+            .to_vec();
+
+        let mut decoder = StreamDecoder::with_key(&key);
+        let mut results = Vec::new();
+        decoder.feed(&frame, |result| results.push(result));
+        assert_eq!(results.len(), 1);
+        assert_telemetry(&results[0]);
+
+        // 没有密钥的接收端不能解析，而不是把密文当作 payload
+        let results = StreamDecoder::decode_all(&frame);
+        assert!(matches!(
+            results[..],
+            [Err(DecodeError::Frame(FrameError::NoKey))]
+        ));
+    }
+
+    // 帧的末尾在传输中丢失，最后一段 COBS 的长度字节指向了帧外，之后的帧不受影响
+    #[test]
+    fn truncated_cobs_frame_is_rejected() {
+        let frame = encode(MsgTag::Telemetry, &TELEMETRY);
+        let mut stream = frame[..frame.len() - 2].to_vec();
+        stream.push(0x00);
+        stream.extend(&frame);
+
+        let mut decoder = StreamDecoder::new();
+        let mut results = Vec::new();
+        decoder.feed(&stream, |result| results.push(result));
+        assert_eq!(results.len(), 2);
+        assert!(matches!(
+            results[0],
+            Err(DecodeError::Frame(FrameError::Cobs))
+        ));
+        assert_telemetry(&results[1]);
+        assert_eq!(decoder.error_cnt(), 1);
+        assert_eq!(decoder.message_cnt(), 1);
+    }
+
+    // COBS 本身是完整的，但连 tag 和 crc16 都放不下
+    #[test]
+    fn frame_shorter_than_header_is_rejected() {
+        let results = StreamDecoder::decode_all(&encode_raw(&[MsgTag::Log as u8, 0x12]));
+        assert!(matches!(
+            results[..],
+            [Err(DecodeError::Frame(FrameError::Truncated))]
+        ));
+    }
+
+    #[test]
+    fn crc_mismatch_is_rejected() {
+        // 把正常的帧解开，改掉 crc16 的一位，再重新编码
+        let mut raw = encode(MsgTag::Log, &"boot ok");
+        raw.pop();
+        let len = cobs::decode_in_place(&mut raw).unwrap();
+        raw.truncate(len);
+        assert_eq!(crc16(&raw[..len - 2]).to_le_bytes(), raw[len - 2..]);
+        raw[len - 2] ^= 0x01;
+
+        let results = StreamDecoder::decode_all(&encode_raw(&raw));
+        assert!(matches!(
+            results[..],
+            [Err(DecodeError::Frame(FrameError::Crc))]
+        ));
+    }
+
+    // CRC 正确但 payload 与 tag 对不上，比如两端的 telemetry_core 版本不同
+    #[test]
+    fn payload_not_matching_tag_is_rejected() {
+        let frame = encode(MsgTag::Response, &"not a response");
+        let results = StreamDecoder::decode_all(&frame);
+        assert!(matches!(
+            results[..],
+            [Err(DecodeError::Payload(MsgTag::Response))]
+        ));
+    }
+}
@@ -0,0 +1,15 @@
+//! Host 端的解析库
+//!
+//! MCU 发出的数据有两种：
+//!
+//! - s22 在串口上发送的二进制帧，格式见 telemetry_core::framing，由 frames 解析为 Message
+//! - s21 的数据记录器保存在 Flash 中的记录，由 dump 解析，支持两种来源：
+//!   用调试器或编程器读出的 Flash 原始镜像，以及 s21c03 的 dump 命令导出的文本
+//!
+//! 解析的结果都实现了 serde::Serialize，可以直接用 serde_json 转为 JSON，交给其它的程序（比如网页上的仪表盘）使用，
+//! 本 crate 的 main.rs 就是这样一个命令行工具
+//!
+//! 所有的字节布局都来自 telemetry_core，与 MCU 端是同一份定义，这里不再重复描述
+
+pub mod dump;
+pub mod frames;
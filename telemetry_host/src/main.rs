@@ -0,0 +1,157 @@
+//! 把 MCU 的数据转为 JSON Lines（每行一个 JSON 对象）的命令行工具
+//!
+//! 用法：
+//!
+//! telemetry_host frames [文件]   解析二进制帧，每帧输出一行，省略文件时从 stdin 读取，
+//!                                 可以直接接在串口后面，比如 cat /dev/ttyUSB0 | telemetry_host frames
+//! telemetry_host image <文件>    解析 Flash 日志区域的原始镜像，每条记录输出一行，最后一行为统计
+//! telemetry_host export [文件]   解析 s21c03 的 dump 命令导出的文本，每条记录输出一行
+//! telemetry_host selftest        运行 crypto_core 的测试向量，与 MCU 端 s21c06 的 crypto 自检相同
+//!
+//! frames 前面可以加上 --key <32 个十六进制字符>，用来解密 payload 加密的帧（见 s22c01 的 PAYLOAD_KEY）
+//!
+//! 出错的帧不会中断解析，会以 {"error": "..."} 的形式输出，方便查看线路的质量
+
+use std::{
+    env,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    process::ExitCode,
+};
+
+use crypto_core::{aes::KEY_LEN, vectors};
+use serde::Serialize;
+use serde_json::json;
+use telemetry_host::{dump, frames::StreamDecoder};
+
+const USAGE: &str =
+    "usage: telemetry_host <frames [--key HEX] [FILE] | image FILE | export [FILE] | selftest>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args[..] {
+        ["frames"] => frames(io::stdin().lock(), StreamDecoder::new()),
+        ["frames", "--key", key] => {
+            parse_key(key).and_then(|decoder| frames(io::stdin().lock(), decoder))
+        }
+        ["frames", "--key", key, path] => {
+            parse_key(key).and_then(|decoder| frames(File::open(path)?, decoder))
+        }
+        ["frames", path] => File::open(path)
+            .map_err(Into::into)
+            .and_then(|file| frames(file, StreamDecoder::new())),
+        ["image", path] => image(path),
+        ["export"] => export(io::stdin().lock()),
+        ["export", path] => File::open(path).map_err(Into::into).and_then(export),
+        ["selftest"] => selftest(),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn print_line(out: &mut impl Write, value: &impl Serialize) -> Result<()> {
+    serde_json::to_writer(&mut *out, value)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn parse_key(text: &str) -> Result<StreamDecoder> {
+    if text.len() != KEY_LEN * 2 {
+        return Err(format!("key must be {} hex digits", KEY_LEN * 2).into());
+    }
+    let mut key = [0u8; KEY_LEN];
+    for (byte, index) in key.iter_mut().zip((0..text.len()).step_by(2)) {
+        *byte = u8::from_str_radix(&text[index..index + 2], 16)
+            .map_err(|_| format!("invalid hex in key: {}", text))?;
+    }
+    Ok(StreamDecoder::with_key(&key))
+}
+
+// 边读边解析，从串口读取时不必等到结束才有输出
+fn frames(mut input: impl Read, mut decoder: StreamDecoder) -> Result<()> {
+    let mut out = io::stdout().lock();
+    let mut buf = [0u8; 256];
+    let mut results = Vec::new();
+
+    loop {
+        let len = input.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        decoder.feed(&buf[..len], |result| results.push(result));
+        for result in results.drain(..) {
+            match result {
+                Ok(message) => print_line(&mut out, &message)?,
+                Err(e) => print_line(&mut out, &json!({ "error": e.to_string() }))?,
+            }
+        }
+        out.flush()?;
+    }
+
+    eprintln!(
+        "{} messages, {} bad frames",
+        decoder.message_cnt(),
+        decoder.error_cnt()
+    );
+    Ok(())
+}
+
+fn image(path: &str) -> Result<()> {
+    let bytes = std::fs::read(path)?;
+    let image = dump::parse_image(&bytes)?;
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for entry in &image.entries {
+        print_line(&mut out, entry)?;
+    }
+    print_line(
+        &mut out,
+        &json!({
+            "records": image.entries.len(),
+            "erased": image.erased,
+            "corrupt": image.corrupt,
+        }),
+    )?;
+    Ok(())
+}
+
+fn export(mut input: impl Read) -> Result<()> {
+    let mut text = String::new();
+    input.read_to_string(&mut text)?;
+    let entries = dump::parse_export(&text)?;
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for entry in &entries {
+        print_line(&mut out, entry)?;
+    }
+    Ok(())
+}
+
+fn selftest() -> Result<()> {
+    let mut failed = 0;
+    for check in &vectors::CHECKS {
+        let pass = (check.run)();
+        println!("{:<24} {}", check.name, if pass { "PASS" } else { "FAIL" });
+        if !pass {
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} checks failed", failed, vectors::CHECKS.len()).into());
+    }
+    Ok(())
+}
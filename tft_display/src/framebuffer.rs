@@ -0,0 +1,336 @@
+//! SRAM 中的 RGB565 帧缓冲，以及只刷新改动区域的脏矩形记录
+//!
+//! F411 这样没有 Chrom-ART（DMA2D）的芯片，所有的绘制都只能由 CPU 完成，因此这里：
+//!
+//! - 所有的绘制都先在 SRAM 中的帧缓冲里完成，不直接写屏幕
+//! - 填充时尽量按 32 bit 写入（一次写两个像素），复制时使用 copy_from_slice（编译为 memcpy，同样是按字复制）
+//! - 每次绘制都会记录一个脏矩形，flush 时只把这些区域发送给屏幕（见 utils::st7789）
+//!
+//! 对于 SPI 接口的屏幕，瓶颈通常不在绘制，而在发送：8 MHz 的 SPI 发送一整屏 240x240 的 RGB565 需要 115 ms，
+//! 如果每一帧只改动了一个数字，只发送这个数字所在的区域，刷新的时间可以缩短到 1 ms 以内
+//!
+//! 像素以 u16 保存，SPI 以 16 bit 的帧发送，高位先发，正好是 ST7789 要求的 RGB565 的字节顺序，发送时不需要交换字节
+//!
+//! 脏矩形的数量有上限（DIRTY_SLOTS），新的矩形与已有的矩形重叠或相邻时会被合并，
+//! 放不下时与“合并之后面积增加最少”的那个矩形合并，最坏的情况也只是多发送一些没有改动的像素
+
+use super::font5x7;
+
+pub type Color = u16;
+
+pub const BLACK: Color = 0x0000;
+pub const WHITE: Color = 0xFFFF;
+pub const RED: Color = rgb565(255, 0, 0);
+pub const GREEN: Color = rgb565(0, 255, 0);
+pub const BLUE: Color = rgb565(0, 0, 255);
+
+pub const fn rgb565(r: u8, g: u8, b: u8) -> Color {
+    ((r as u16 & 0xF8) << 8) | ((g as u16 & 0xFC) << 3) | (b as u16 >> 3)
+}
+
+// 以 alpha（0 ~ 255）把 fg 叠加到 bg 上
+//
+// 把 RGB565 展开为 0000_0GGG_GGG0_0000_RRRR_R000_000B_BBBB 这样的 32 bit，三个分量之间留出空位，
+// 这样一次乘法就可以同时算出三个分量，alpha 只保留 5 bit 的精度，对于 RGB565 已经足够
+pub fn blend(bg: Color, fg: Color, alpha: u8) -> Color {
+    const MASK: u32 = 0x07E0_F81F;
+    let alpha = (alpha as u32 + 4) >> 3;
+    let bg = (bg as u32 | (bg as u32) << 16) & MASK;
+    let fg = (fg as u32 | (fg as u32) << 16) & MASK;
+    let mixed = (fg.wrapping_sub(bg).wrapping_mul(alpha) >> 5).wrapping_add(bg) & MASK;
+    (mixed | mixed >> 16) as u16
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub w: u16,
+    pub h: u16,
+}
+
+impl Rect {
+    pub const fn new(x: u16, y: u16, w: u16, h: u16) -> Self {
+        Self { x, y, w, h }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.w == 0 || self.h == 0
+    }
+
+    pub fn right(&self) -> u16 {
+        self.x + self.w
+    }
+
+    pub fn bottom(&self) -> u16 {
+        self.y + self.h
+    }
+
+    pub fn area(&self) -> u32 {
+        self.w as u32 * self.h as u32
+    }
+
+    // 同时包含两个矩形的最小矩形
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            w: self.right().max(other.right()) - x,
+            h: self.bottom().max(other.bottom()) - y,
+        }
+    }
+
+    // 两个矩形重叠，或者紧挨着（合并之后不会多出没有改动的像素）
+    pub fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right()
+            && other.x <= self.right()
+            && self.y <= other.bottom()
+            && other.y <= self.bottom()
+    }
+
+    // 裁剪到 width x height 的屏幕之内
+    pub fn clip(&self, width: u16, height: u16) -> Rect {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Rect {
+            x,
+            y,
+            w: self.right().min(width) - x,
+            h: self.bottom().min(height) - y,
+        }
+    }
+}
+
+// 脏矩形的最大数量
+pub const DIRTY_SLOTS: usize = 8;
+
+// 合并之后，面积的增加不超过这个值时，即使两个矩形没有挨着也直接合并，
+// 每个矩形在发送时都有设置窗口的开销（约 11 个字节），太小的矩形分开发送反而更慢
+const MERGE_SLACK: u32 = 64;
+
+#[derive(Default)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub struct DirtyRects {
+    rects: [Rect; DIRTY_SLOTS],
+    len: usize,
+}
+
+impl DirtyRects {
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+
+        let mut rect = rect;
+        // 与已有的矩形合并之后，新的矩形可能又能与其他矩形合并，因此一直重复到没有可以合并的为止
+        while let Some(index) = self.rects[..self.len].iter().position(|other| {
+            other.touches(&rect)
+                || other.union(&rect).area() <= other.area() + rect.area() + MERGE_SLACK
+        }) {
+            rect = rect.union(&self.rects[index]);
+            self.len -= 1;
+            self.rects[index] = self.rects[self.len];
+        }
+
+        if self.len < DIRTY_SLOTS {
+            self.rects[self.len] = rect;
+            self.len += 1;
+            return;
+        }
+
+        // 已经满了，与合并之后面积增加最少的那个矩形合并
+        let (index, _) = self
+            .rects
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, other)| other.union(&rect).area() - other.area())
+            .unwrap();
+        self.rects[index] = self.rects[index].union(&rect);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rect> {
+        self.rects[..self.len].iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 所有脏矩形的像素总数，矩形之间不会重叠
+    pub fn pixels(&self) -> u32 {
+        self.iter().map(Rect::area).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+pub struct FrameBuffer<'a> {
+    pixels: &'a mut [Color],
+    width: u16,
+    height: u16,
+    dirty: DirtyRects,
+}
+
+impl<'a> FrameBuffer<'a> {
+    // pixels 的长度必须为 width * height，通常是一个 static 的数组
+    // 刚创建时整个屏幕都是脏的，第一次 flush 会发送完整的一帧
+    pub fn new(pixels: &'a mut [Color], width: u16, height: u16) -> Self {
+        assert_eq!(pixels.len(), width as usize * height as usize);
+        let mut fb = Self {
+            pixels,
+            width,
+            height,
+            dirty: DirtyRects::default(),
+        };
+        fb.mark_all_dirty();
+        fb
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn dirty(&self) -> &DirtyRects {
+        &self.dirty
+    }
+
+    // 由 flush 在发送之后调用
+    pub fn clear_dirty(&mut self) {
+        self.dirty.clear();
+    }
+
+    // 下一次 flush 发送整个屏幕，比如屏幕复位之后
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty.clear();
+        self.dirty.add(Rect::new(0, 0, self.width, self.height));
+    }
+
+    // rect 的第 row 行，rect 需要已经裁剪过
+    pub fn row(&self, rect: &Rect, row: u16) -> &[Color] {
+        let start = (rect.y + row) as usize * self.width as usize + rect.x as usize;
+        &self.pixels[start..start + rect.w as usize]
+    }
+
+    fn row_mut(&mut self, rect: &Rect, row: u16) -> &mut [Color] {
+        let start = (rect.y + row) as usize * self.width as usize + rect.x as usize;
+        &mut self.pixels[start..start + rect.w as usize]
+    }
+
+    // 逐个像素绘制时使用，不要用它来填充大面积的区域，每个像素都要记录一次脏矩形
+    pub fn set_pixel(&mut self, x: u16, y: u16, color: Color) {
+        if x < self.width && y < self.height {
+            self.pixels[y as usize * self.width as usize + x as usize] = color;
+            self.dirty.add(Rect::new(x, y, 1, 1));
+        }
+    }
+
+    // 按 32 bit 填充：每一行先处理开头未对齐的半个字，中间一次写两个像素，最后处理剩下的半个字
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let rect = rect.clip(self.width, self.height);
+        let word = (color as u32) << 16 | color as u32;
+        for row in 0..rect.h {
+            let line = self.row_mut(&rect, row);
+            // 安全性：u16 与 u32 都是没有无效值的整数类型，align_to_mut 保证中间部分是对齐的
+            let (head, body, tail) = unsafe { line.align_to_mut::<u32>() };
+            head.fill(color);
+            body.fill(word);
+            tail.fill(color);
+        }
+        self.dirty.add(rect);
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        self.fill_rect(Rect::new(0, 0, self.width, self.height), color);
+    }
+
+    // 把 w x h 的图像（按行排列）复制到 (x, y)，超出屏幕的部分被裁掉
+    pub fn blit(&mut self, x: u16, y: u16, w: u16, h: u16, image: &[Color]) {
+        let rect = Rect::new(x, y, w, h).clip(self.width, self.height);
+        for row in 0..rect.h {
+            let src_start = row as usize * w as usize;
+            let src = &image[src_start..src_start + rect.w as usize];
+            self.row_mut(&rect, row).copy_from_slice(src);
+        }
+        self.dirty.add(rect);
+    }
+
+    // 在帧缓冲内部复制一块区域，比如滚动，源与目标可以重叠
+    pub fn copy_rect(&mut self, src: Rect, dst_x: u16, dst_y: u16) {
+        let src = src.clip(self.width, self.height);
+        let dst = Rect::new(dst_x, dst_y, src.w, src.h).clip(self.width, self.height);
+        let width = self.width as usize;
+
+        // 目标在源的下方时从最后一行开始复制，否则会覆盖还没有复制的源
+        let copy_row = |pixels: &mut [Color], row: u16| {
+            let from = (src.y + row) as usize * width + src.x as usize;
+            let to = (dst.y + row) as usize * width + dst.x as usize;
+            pixels.copy_within(from..from + dst.w as usize, to);
+        };
+        if dst.y > src.y {
+            (0..dst.h).rev().for_each(|row| copy_row(self.pixels, row));
+        } else {
+            (0..dst.h).for_each(|row| copy_row(self.pixels, row));
+        }
+        self.dirty.add(dst);
+    }
+
+    // 用 5x7 的字体绘制文字，每个点放大为 scale x scale 的方块，以 alpha 叠加到原有的内容上
+    // alpha 为 255 时直接覆盖，不做混合，这是最快的情况
+    // 返回文字所占的矩形
+    pub fn draw_text(
+        &mut self,
+        x: u16,
+        y: u16,
+        text: &str,
+        color: Color,
+        alpha: u8,
+        scale: u16,
+    ) -> Rect {
+        let advance = (font5x7::WIDTH as u16 + 1) * scale;
+        let mut cursor = x;
+
+        for ch in text.chars() {
+            let glyph = font5x7::glyph(ch);
+            for (col, &bits) in glyph.iter().enumerate() {
+                for line in 0..font5x7::HEIGHT as u16 {
+                    if bits & (1 << line) == 0 {
+                        continue;
+                    }
+                    let dot =
+                        Rect::new(cursor + col as u16 * scale, y + line * scale, scale, scale)
+                            .clip(self.width, self.height);
+                    for row in 0..dot.h {
+                        let pixels = self.row_mut(&dot, row);
+                        if alpha == u8::MAX {
+                            pixels.fill(color);
+                        } else {
+                            for pixel in pixels {
+                                *pixel = blend(*pixel, color, alpha);
+                            }
+                        }
+                    }
+                }
+            }
+            cursor += advance;
+        }
+
+        let rect = Rect::new(x, y, cursor - x, font5x7::HEIGHT as u16 * scale)
+            .clip(self.width, self.height);
+        // 整段文字只记录一个脏矩形
+        self.dirty.add(rect);
+        rect
+    }
+}
@@ -0,0 +1,14 @@
+//! ST7789 TFT 屏幕：5x7 字库、带脏矩形的帧缓冲，以及 SPI、FSMC 两种接法的驱动
+//!
+//! 最早写在 s03c05 中，s21 的触摸菜单也用同一块屏幕，原来是把三个文件原样复制了一份，
+//! 现在只保留这里的一份，两章在 utils/mod.rs 中用 pub(crate) use 引入，原来的 utils::st7789 这样的路径保持不变
+//!
+//! 与 chip_caps 一样，芯片由各章转发过来的 stm32f401 / stm32f411 / stm32f412 / stm32f413 特性选择，
+//! 只有 F412/F413 有 FSMC，其它芯片上 st7789 中没有 FsmcPanelBus 与 setup_fsmc，
+//! 用到它们的程序需要写上 chip_caps::require!(FSMC)
+
+#![no_std]
+
+pub mod font5x7;
+pub mod framebuffer;
+pub mod st7789;
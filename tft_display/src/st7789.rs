@@ -0,0 +1,320 @@
+//! ST7789 TFT 屏幕，以及把帧缓冲中的脏矩形发送过去的 flush
+//!
+//! 屏幕的接口有两种，都实现了 PanelBus：
+//!
+//! - SpiPanelBus：4 线 SPI（SCK、MOSI、CS、DC），像素以 16 bit 的 SPI 帧发送
+//! - FsmcPanelBus：16 bit 的 8080 并口，接在 FSMC 的 Bank1 NE1 上，DC 接 A16，
+//!   对 CPU 来说，命令和数据就是两个内存地址，写一个像素只需要一条 strh 指令
+//!
+//! ST7789 的窗口机制：CASET、RASET 设置一个矩形窗口，RAMWR 之后写入的像素按行依次填满这个窗口，
+//! 因此一个脏矩形只需要发送 3 个命令（11 个字节），之后是矩形中所有的像素
+
+use stm32f4xx_hal::pac::{spi1::RegisterBlock, Peripherals};
+
+use super::framebuffer::{FrameBuffer, Rect};
+
+const SWRESET: u8 = 0x01;
+const SLPOUT: u8 = 0x11;
+const NORON: u8 = 0x13;
+const INVON: u8 = 0x21;
+const DISPON: u8 = 0x29;
+const CASET: u8 = 0x2A;
+const RASET: u8 = 0x2B;
+const RAMWR: u8 = 0x2C;
+const MADCTL: u8 = 0x36;
+const COLMOD: u8 = 0x3A;
+
+pub trait PanelBus {
+    // 发送一个命令以及它的参数
+    fn command(&mut self, cmd: u8, params: &[u8]);
+    // 在 RAMWR 之后，连续写入若干行像素
+    fn write_pixels<'p>(&mut self, rows: impl Iterator<Item = &'p [u16]>);
+}
+
+// 接线与 s03c03 相同：SPI1，PB1 为 CS，PB2 为 DC
+// SPI1 与 GPIO 的时钟需要提前打开，引脚也需要提前设置好
+pub struct SpiPanelBus<'a> {
+    dp: &'a Peripherals,
+    spi: &'a RegisterBlock,
+}
+
+impl<'a> SpiPanelBus<'a> {
+    // br 为 CR1 的 BR 位，SCK = f_PCLK / 2^(br + 1)，ST7789 的写周期最短为 66 ns，也就是最高约 15 MHz
+    pub fn new(dp: &'a Peripherals, spi: &'a RegisterBlock, br: u8) -> Self {
+        dp.GPIOB.bsrr.write(|w| w.bs1().set());
+
+        spi.cr1.modify(|_, w| {
+            w.mstr().master();
+            w.ssm().enabled();
+            w.ssi().slave_not_selected();
+            // ST7789 使用 Mode 3
+            w.cpol().set_bit();
+            w.cpha().set_bit();
+            w.br().bits(br);
+            w.dff().eight_bit();
+            w
+        });
+        spi.cr1.modify(|_, w| w.spe().enabled());
+
+        Self { dp, spi }
+    }
+
+    fn wait_idle(&self) {
+        while self.spi.sr.read().txe().is_not_empty() {}
+        while self.spi.sr.read().bsy().is_busy() {}
+    }
+
+    fn send(&self, word: u16) {
+        while self.spi.sr.read().txe().is_not_empty() {}
+        self.spi.dr.write(|w| w.dr().bits(word));
+    }
+
+    // 只发送不接收，RX 一直没有被读取，OVR 会被置位，这里先读 DR 再读 SR 把它清除
+    fn clear_overrun(&self) {
+        self.spi.dr.read();
+        self.spi.sr.read();
+    }
+
+    // 修改 DFF 时 SPE 必须为 0
+    fn set_16bit(&self, on: bool) {
+        self.spi.cr1.modify(|_, w| w.spe().disabled());
+        self.spi.cr1.modify(|_, w| w.dff().bit(on));
+        self.spi.cr1.modify(|_, w| w.spe().enabled());
+    }
+
+    fn select(&self, on: bool) {
+        self.dp
+            .GPIOB
+            .bsrr
+            .write(|w| if on { w.br1().reset() } else { w.bs1().set() });
+    }
+
+    // DC 为低表示命令，为高表示数据
+    fn data_mode(&self, data: bool) {
+        self.dp
+            .GPIOB
+            .bsrr
+            .write(|w| if data { w.bs2().set() } else { w.br2().reset() });
+    }
+}
+
+impl PanelBus for SpiPanelBus<'_> {
+    fn command(&mut self, cmd: u8, params: &[u8]) {
+        self.wait_idle();
+        self.select(true);
+
+        self.data_mode(false);
+        self.send(cmd as u16);
+        // DC 必须在命令的最后一位发送完之后才能改变
+        self.wait_idle();
+
+        self.data_mode(true);
+        for &param in params {
+            self.send(param as u16);
+        }
+        self.wait_idle();
+
+        self.select(false);
+        self.clear_overrun();
+    }
+
+    // 16 bit 的 SPI 帧高位先发，正好是 RGB565 的字节顺序
+    fn write_pixels<'p>(&mut self, rows: impl Iterator<Item = &'p [u16]>) {
+        self.wait_idle();
+        self.set_16bit(true);
+        self.select(true);
+        self.data_mode(true);
+
+        for row in rows {
+            for &pixel in row {
+                self.send(pixel);
+            }
+        }
+
+        self.wait_idle();
+        self.select(false);
+        self.set_16bit(false);
+        self.clear_overrun();
+    }
+}
+
+// FSMC Bank1 NE1 的地址
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+const FSMC_BANK1: usize = 0x6000_0000;
+// 16 bit 的总线上，FSMC_A16 对应的是地址的第 17 位
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+const FSMC_A16: usize = 1 << 17;
+
+// FSMC 需要先用 setup_fsmc 设置好
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub struct FsmcPanelBus {
+    cmd: *mut u16,
+    data: *mut u16,
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+impl FsmcPanelBus {
+    pub const fn new() -> Self {
+        Self {
+            cmd: FSMC_BANK1 as *mut u16,
+            data: (FSMC_BANK1 | FSMC_A16) as *mut u16,
+        }
+    }
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+impl Default for FsmcPanelBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+impl PanelBus for FsmcPanelBus {
+    // 安全性：两个地址都位于 setup_fsmc 打开的 Bank1 中
+    fn command(&mut self, cmd: u8, params: &[u8]) {
+        unsafe {
+            self.cmd.write_volatile(cmd as u16);
+            for &param in params {
+                self.data.write_volatile(param as u16);
+            }
+        }
+    }
+
+    fn write_pixels<'p>(&mut self, rows: impl Iterator<Item = &'p [u16]>) {
+        for row in rows {
+            for &pixel in row {
+                unsafe { self.data.write_volatile(pixel) };
+            }
+        }
+    }
+}
+
+// 把一组引脚设置为 AF12（FSMC）、高速输出
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+macro_rules! fsmc_pins {
+    ($gpio:expr, $pins:expr) => {{
+        let pins: &[u32] = $pins;
+        let two_bits = |value: u32, bits: u32| {
+            pins.iter()
+                .fold(value, |v, &p| (v & !(0b11 << (p * 2))) | (bits << (p * 2)))
+        };
+        let af = |value: u32, range: core::ops::Range<u32>| {
+            pins.iter()
+                .filter(|p| range.contains(p))
+                .fold(value, |v, &p| {
+                    let shift = (p % 8) * 4;
+                    (v & !(0xF << shift)) | (12 << shift)
+                })
+        };
+        $gpio
+            .ospeedr
+            .modify(|r, w| unsafe { w.bits(two_bits(r.bits(), 0b11)) });
+        $gpio
+            .afrl
+            .modify(|r, w| unsafe { w.bits(af(r.bits(), 0..8)) });
+        $gpio
+            .afrh
+            .modify(|r, w| unsafe { w.bits(af(r.bits(), 8..16)) });
+        $gpio
+            .moder
+            .modify(|r, w| unsafe { w.bits(two_bits(r.bits(), 0b10)) });
+    }};
+}
+
+// FSMC Bank1 NE1，SRAM 模式，16 bit 总线
+//
+// D0 ~ D15：PD14 PD15 PD0 PD1 PE7 ~ PE15 PD8 PD9 PD10
+// NOE PD4（接屏幕的 RD），NWE PD5（WR），NE1 PD7（CS），A16 PD11（DC）
+//
+// 时序按 HCLK 100 MHz 计算：地址建立 2 个周期，数据保持 4 个周期，一次写入约 70 ns，满足 ST7789 最短 66 ns 的写周期
+#[cfg(any(feature = "stm32f412", feature = "stm32f413"))]
+pub fn setup_fsmc(dp: &Peripherals) {
+    dp.RCC.ahb1enr.modify(|_, w| {
+        w.gpioden().enabled();
+        w.gpioeen().enabled();
+        w
+    });
+    dp.RCC.ahb3enr.modify(|_, w| w.fsmcen().enabled());
+
+    fsmc_pins!(dp.GPIOD, &[0, 1, 4, 5, 7, 8, 9, 10, 11, 14, 15]);
+    fsmc_pins!(dp.GPIOE, &[7, 8, 9, 10, 11, 12, 13, 14, 15]);
+
+    // ADDSET = 2，DATAST = 4
+    dp.FSMC.btr1.write(|w| unsafe { w.bits((4 << 8) | 2) });
+    // MBKEN、MWID = 01（16 bit）、WREN，存储器类型为 SRAM
+    dp.FSMC
+        .bcr1
+        .write(|w| unsafe { w.bits((1 << 12) | (0b01 << 4) | 1) });
+}
+
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "fmt", derive(Debug))]
+pub struct FlushStats {
+    pub rects: u32,
+    pub pixels: u32,
+}
+
+pub struct St7789<B: PanelBus> {
+    bus: B,
+    // 屏幕的可视区域在 ST7789 的 240x320 显存中的偏移，常见的 240x240 的屏幕为 0
+    x_offset: u16,
+    y_offset: u16,
+}
+
+impl<B: PanelBus> St7789<B> {
+    pub fn new(bus: B, x_offset: u16, y_offset: u16) -> Self {
+        Self {
+            bus,
+            x_offset,
+            y_offset,
+        }
+    }
+
+    // 复位并打开显示，需要知道 SYSCLK 才能延时
+    pub fn init(&mut self, sysclk_hz: u32) {
+        let ms = |n: u32| cortex_m::asm::delay(sysclk_hz / 1000 * n);
+
+        self.bus.command(SWRESET, &[]);
+        ms(150);
+        self.bus.command(SLPOUT, &[]);
+        ms(120);
+        // 16 bit 的 RGB565
+        self.bus.command(COLMOD, &[0x55]);
+        // 从左上角开始，按行扫描
+        self.bus.command(MADCTL, &[0x00]);
+        // 常见的 IPS 屏需要打开反色，否则颜色是反的
+        self.bus.command(INVON, &[]);
+        self.bus.command(NORON, &[]);
+        self.bus.command(DISPON, &[]);
+        ms(10);
+    }
+
+    fn set_window(&mut self, rect: &Rect) {
+        let x0 = rect.x + self.x_offset;
+        let x1 = x0 + rect.w - 1;
+        let y0 = rect.y + self.y_offset;
+        let y1 = y0 + rect.h - 1;
+        let [x0h, x0l] = x0.to_be_bytes();
+        let [x1h, x1l] = x1.to_be_bytes();
+        let [y0h, y0l] = y0.to_be_bytes();
+        let [y1h, y1l] = y1.to_be_bytes();
+        self.bus.command(CASET, &[x0h, x0l, x1h, x1l]);
+        self.bus.command(RASET, &[y0h, y0l, y1h, y1l]);
+        self.bus.command(RAMWR, &[]);
+    }
+
+    // 发送所有的脏矩形，之后清空它们
+    pub fn flush(&mut self, fb: &mut FrameBuffer) -> FlushStats {
+        let mut stats = FlushStats::default();
+        for rect in fb.dirty().iter() {
+            self.set_window(rect);
+            self.bus
+                .write_pixels((0..rect.h).map(|row| fb.row(rect, row)));
+            stats.rects += 1;
+            stats.pixels += rect.area();
+        }
+        fb.clear_dirty();
+        stats
+    }
+}